@@ -0,0 +1,98 @@
+#ifndef MERCURY_CLIENT_H
+#define MERCURY_CLIENT_H
+
+/* Generated with cbindgen from client/src/ffi.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Opaque handle to an open wallet
+typedef struct MercuryWallet MercuryWallet;
+
+// Open the wallet described by the JSON request, creating it if it does not exist. Returns
+// NULL on failure and, if error is not NULL, sets *error to a response object describing the
+// failure, to be released with mercury_string_free().
+//
+// # Safety
+//
+// error must be NULL or point to writable storage for a string pointer.
+MercuryWallet *mercury_wallet_open(const char *request, char **error);
+
+// Release a wallet opened with mercury_wallet_open(). NULL is ignored.
+//
+// # Safety
+//
+// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+// released. It must not be used once released.
+void mercury_wallet_free(MercuryWallet *wallet);
+
+// Release a string returned by the library. NULL is ignored.
+//
+// # Safety
+//
+// s must be NULL or a string returned by the library that has not been released.
+void mercury_string_free(char *s);
+
+// New bech32 encoded SCE address to receive a transfer to
+//
+// # Safety
+//
+// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+// released.
+char *mercury_new_address(const MercuryWallet *wallet);
+
+// Statecoins owned by the wallet
+//
+// # Safety
+//
+// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+// released.
+char *mercury_statecoins(const MercuryWallet *wallet);
+
+// Deposit {"amount": <satoshis>}. Returns the new statecoin.
+//
+// # Safety
+//
+// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+// released. request must be NULL or a NUL terminated string.
+char *mercury_deposit(const MercuryWallet *wallet, const char *request);
+
+// Send a statecoin {"statechain_id": <uuid>, "address": <bech32 SCE address>}. Returns the
+// bech32 encoded transfer message to pass to the receiver.
+//
+// # Safety
+//
+// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+// released. request must be NULL or a NUL terminated string.
+char *mercury_transfer_send(const MercuryWallet *wallet, const char *request);
+
+// Receive a statecoin {"transfer_msg": <bech32 transfer message>}. Returns the received
+// statecoin.
+//
+// # Safety
+//
+// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+// released. request must be NULL or a NUL terminated string.
+char *mercury_transfer_receive(const MercuryWallet *wallet, const char *request);
+
+// Withdraw a statecoin {"statechain_id": <uuid>, "address": <bitcoin address>,
+// "tx_fee": <satoshis>}. Returns the withdraw txid.
+//
+// # Safety
+//
+// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+// released. request must be NULL or a NUL terminated string.
+char *mercury_withdraw(const MercuryWallet *wallet, const char *request);
+
+// Swap a statecoin {"statechain_id": <uuid>, "swap_size": <statecoins in the swap group>}.
+// Blocks until the swap completes and returns the statecoin received in the swap.
+//
+// # Safety
+//
+// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+// released. request must be NULL or a NUL terminated string.
+char *mercury_swap(const MercuryWallet *wallet, const char *request);
+
+#endif /* MERCURY_CLIENT_H */
@@ -4,12 +4,15 @@
 //! which is itself exposed to JavaScript via Neon-binding.
 
 use super::Result;
+use crate::endpoint::Endpoints;
+use crate::lightning::ClnRestNode;
 use crate::wallet;
 use crate::{
     state_entity,
     {error::CError, get_config, state_entity::api::get_statechain_fee_info, ClientShim, Tor}, error::WalletErrorType,
 };
 
+use bitcoin::Address;
 use daemon_engine::{JsonCodec, UnixConnection, UnixServer};
 use serde::{Deserialize, Serialize};
 use tokio::prelude::*;
@@ -17,9 +20,11 @@ use tokio::{run, spawn};
 use electrumx_client::response::GetBalanceResponse;
 
 use rand::Rng;
+use std::str::FromStr;
 use state_entity::api::{get_statechain, get_recovery_data, get_swaps_group_info, get_coins_info};
 use uuid::Uuid;
-use wallet::wallet::{DEFAULT_TEST_WALLET_LOC, ElectrumxBox, DEFAULT_WALLET_LOC};
+use wallet::handle::WalletHandle;
+use wallet::wallet::{to_bitcoin_public_key, DEFAULT_TEST_WALLET_LOC, ElectrumxBox, DEFAULT_WALLET_LOC};
 use crate::utilities::encoding;
 use shared_lib::util::FEE;
 
@@ -32,19 +37,33 @@ pub enum DaemonRequest {
     GenAddressSE,
     GetWalletBalance,
     GetStateChainsInfo,
+    GetExpiryReport,
     GetListUnspent,
     // State Entity fns
     GetBackup(Uuid),
     GetFeeInfo,
+    GetParams,
     GetSwapGroups,
     GetCoinsInfo,
     GetStateChain(Uuid),
     GetRecoveryData(String),
     Deposit(u64),
+    DepositTo(u64, String),
+    DepositClaim(String),
+    DepositResume,
+    DepositRefund(Uuid),
     Withdraw(Uuid),
+    WithdrawAll(String, u64),
+    Exit,
+    FundChannel(Uuid, String),
     TransferSender(Uuid, String),
     TransferAny(String),
     TransferReceiver(String),
+    TransferReceipt(Uuid),
+    TransferPending,
+    TransferAccept(Uuid),
+    TransferReject(Uuid),
+    TransferReclaim(Uuid),
     Swap(Uuid, u64, bool),
 }
 
@@ -94,6 +113,10 @@ pub fn run_wallet_daemon(force_testing_mode: bool) -> Result<()> {
             testing_mode = true;
         }
         let network: String = conf_rs.get("network").unwrap();
+        let lightning_node = conf_rs
+            .get::<String>("lightning_node")
+            .ok()
+            .map(|url| ClnRestNode::new(url, conf_rs.get("lightning_rune").ok()));
         let daemon_address: String = conf_rs.get("daemon_address").unwrap();
 
         let mut tor = Tor::from_config(&conf_rs);
@@ -108,8 +131,12 @@ pub fn run_wallet_daemon(force_testing_mode: bool) -> Result<()> {
         };
         println!("config tor: {:?}", tor);
 
-        let client_shim = ClientShim::new(endpoint, None, tor.clone());
-        let conductor_shim = ClientShim::new(conductor_endpoint, None, tor);
+        let mut urls = vec![endpoint];
+        urls.extend(conf_rs.get::<Vec<String>>("fallback_endpoints").unwrap());
+        let client_shim = ClientShim::with_endpoints(Endpoints::from_urls(&urls)?, None, tor.clone())?;
+        let mut urls = vec![conductor_endpoint];
+        urls.extend(conf_rs.get::<Vec<String>>("conductor_fallback_endpoints").unwrap());
+        let conductor_shim = ClientShim::with_endpoints(Endpoints::from_urls(&urls)?, None, tor)?;
 
         let wallet_data_loc = if testing_mode {
             println!("Testing mode enabled.");
@@ -152,6 +179,21 @@ pub fn run_wallet_daemon(force_testing_mode: bool) -> Result<()> {
                 println!("No Electrum server address provided. Defaulted to Mock Electrum server.")
             }
         }
+        // Warn of statecoins that must be transferred or withdrawn before their backup tx
+        // locktime expires
+        wallet.set_expiry_alert_blocks(conf_rs.get("expiry_alert_blocks").unwrap());
+        wallet.set_expiry_alert_hook(Box::new(|expiry| {
+            warn!(
+                "Statecoin {} backup tx locktime expires in {} blocks. Transfer or withdraw it.",
+                expiry.shared_key_id, expiry.blocks_remaining
+            )
+        }));
+        // Respond to competing spends of statecoin funding outputs found on sync
+        wallet.set_spend_response(conf_rs.get("spend_response").unwrap());
+        wallet.set_transfer_concurrency(conf_rs.get("transfer_concurrency").unwrap());
+        wallet.set_funding_conf_max_polls(conf_rs.get("funding_conf_max_polls").unwrap());
+        wallet.set_receive_key_pool_size(conf_rs.get("receive_key_pool_size").unwrap());
+        let wallet = WalletHandle::new(wallet);
 
     let server = future::lazy(move || {
         let mut s = UnixServer::<JsonCodec<DaemonResponse, DaemonRequest>>::new(
@@ -170,105 +212,180 @@ pub fn run_wallet_daemon(force_testing_mode: bool) -> Result<()> {
                     DaemonRequest::LifeCheck => r.send(DaemonResponse::None),
                     DaemonRequest::GenAddressBTC => {
                         debug!("Daemon: GenAddressBTC");
-                        let address = wallet.keys.get_new_address();
-                        wallet.save();
+                        let address = wallet.update(|w| w.keys.get_new_address());
                         r.send(DaemonResponse::value_to_deamon_response(address))
                     }
                     DaemonRequest::GenAddressSE => {
                         debug!("Daemon: GenAddressSE");
-                        let address = wallet.get_new_state_entity_address();
-                        let bech32 = encoding::encode_address(address.unwrap());
-                        wallet.save();
+                        let bech32 = wallet
+                            .update(|w| w.get_new_state_entity_address())
+                            .and_then(encoding::encode_address);
                         r.send(DaemonResponse::value_to_deamon_response(bech32))
                     }
                     DaemonRequest::GetWalletBalance => {
                         debug!("Daemon: GetWalletBalance");
-                        let balance = wallet.get_all_addresses_balance();
+                        let balance = wallet.write().and_then(|mut w| w.get_all_addresses_balance());
                         r.send(DaemonResponse::value_to_deamon_response(balance))
                     }
                     DaemonRequest::GetBackup(statechain_id) => {
                         debug!("Daemon: GetBackup");
-                        let backup_tx = wallet.get_backup_tx(&statechain_id);
+                        let backup_tx = wallet.with(|w| w.get_backup_tx(&statechain_id));
                         r.send(DaemonResponse::value_to_deamon_response(backup_tx))
                     }                    
+                    DaemonRequest::GetExpiryReport => {
+                        debug!("Daemon: GetExpiryReport");
+                        let report = wallet.update(|w| w.get_expiry_report());
+                        r.send(DaemonResponse::value_to_deamon_response(report))
+                    }
                     DaemonRequest::GetStateChainsInfo => {
                         debug!("Daemon: GetStateChainsInfo");
-                        let balance = wallet.get_state_chains_info();
+                        let balance = wallet.with(|w| w.get_state_chains_info());
                         r.send(DaemonResponse::value_to_deamon_response(balance))
                     }
                     DaemonRequest::GetListUnspent => {
                         debug!("Daemon: GetListUnspent");
-                        let list_unspent = wallet.list_unspent();
+                        let list_unspent = wallet.write().and_then(|mut w| w.list_unspent());
                         r.send(DaemonResponse::value_to_deamon_response(list_unspent))
                     }
                     DaemonRequest::GetFeeInfo => {
                         debug!("Daemon: GetFeeInfo");
-                        let fee_info_res = get_statechain_fee_info(&wallet.client_shim);
+                        let fee_info_res = get_statechain_fee_info(wallet.client_shim());
                         r.send(DaemonResponse::value_to_deamon_response(fee_info_res))
                     }
+                    DaemonRequest::GetParams => {
+                        debug!("Daemon: GetParams");
+                        let params_res = state_entity::api::get_statechain_params(wallet.client_shim());
+                        r.send(DaemonResponse::value_to_deamon_response(params_res))
+                    }
                     DaemonRequest::GetSwapGroups => {
                         debug!("Daemon: GetSwapGroups");
-                        let swap_groups_res = get_swaps_group_info(&wallet.conductor_shim);
+                        let swap_groups_res = get_swaps_group_info(wallet.conductor_shim());
                         r.send(DaemonResponse::value_to_deamon_response(swap_groups_res))
                     }
                     DaemonRequest::GetCoinsInfo => {
                         debug!("Daemon: GetCoinsInfo");
-                        let coins_info_res = get_coins_info(&wallet.client_shim);
+                        let coins_info_res = get_coins_info(wallet.client_shim());
                         r.send(DaemonResponse::value_to_deamon_response(coins_info_res))
                     }
                     DaemonRequest::GetStateChain(statechain_id) => {
                         debug!("Daemon: GetStateChain");
-                        let fee_info_res = get_statechain(&wallet.client_shim, &statechain_id);
+                        let fee_info_res = get_statechain(wallet.client_shim(), &statechain_id);
                         r.send(DaemonResponse::value_to_deamon_response(fee_info_res))
                     }
                     DaemonRequest::GetRecoveryData(pubkey_hex) => {
                         debug!("Daemon: GetRecoveryData");
-                        let recovery_data = get_recovery_data(&wallet.client_shim, &pubkey_hex);
+                        let recovery_data = get_recovery_data(wallet.client_shim(), &pubkey_hex);
                         r.send(DaemonResponse::value_to_deamon_response(recovery_data))
                     }
                     DaemonRequest::Deposit(amount) => {
                         debug!("Daemon: Deposit");
-                        let deposit_res = state_entity::deposit::deposit(&mut wallet, &amount);
-                        wallet.save();
+                        let deposit_res =
+                            wallet.update(|w| state_entity::deposit::deposit(w, &amount));
+                        r.send(DaemonResponse::value_to_deamon_response(deposit_res))
+                    }
+                    DaemonRequest::DepositTo(amount, receiver_addr) => {
+                        debug!("Daemon: DepositTo");
+                        let deposit_res = encoding::decode_address(receiver_addr, &network)
+                            .and_then(|sce_address| {
+                                wallet.update(|w| {
+                                    state_entity::deposit::deposit_to(w, &amount, &sce_address)
+                                })
+                            });
                         r.send(DaemonResponse::value_to_deamon_response(deposit_res))
                     }
+                    DaemonRequest::DepositClaim(addr) => {
+                        debug!("Daemon: DepositClaim");
+                        let claim_res = encoding::decode_address(addr, &network)
+                            .and_then(|sce_address| {
+                                wallet.update(|w| {
+                                    state_entity::deposit::deposit_claim(
+                                        w,
+                                        &to_bitcoin_public_key(sce_address.proof_key),
+                                    )
+                                })
+                            });
+                        r.send(DaemonResponse::value_to_deamon_response(claim_res))
+                    }
+                    DaemonRequest::DepositResume => {
+                        debug!("Daemon: DepositResume");
+                        let resume_res = wallet.update(|w| w.resume_pending_deposits());
+                        r.send(DaemonResponse::value_to_deamon_response(resume_res))
+                    }
+                    DaemonRequest::DepositRefund(shared_key_id) => {
+                        debug!("Daemon: DepositRefund");
+                        let refund_res = wallet.update(|w| {
+                            state_entity::deposit::recover_failed_deposit(w, &shared_key_id, None)
+                        });
+                        r.send(DaemonResponse::value_to_deamon_response(refund_res))
+                    }
                     DaemonRequest::Withdraw(statechain_id) => {
                         debug!("Daemon: Withdraw");
-                        let deposit_res =
-                            state_entity::withdraw::withdraw(&mut wallet, &statechain_id, &FEE);
-                        wallet.save();
+                        let deposit_res = wallet.update(|w| {
+                            state_entity::withdraw::withdraw(w, &statechain_id, &FEE)
+                        });
                         r.send(DaemonResponse::value_to_deamon_response(deposit_res))
                     }
+                    DaemonRequest::WithdrawAll(destination, fee_rate) => {
+                        debug!("Daemon: WithdrawAll");
+                        let withdraw_res = match Address::from_str(&destination) {
+                            Ok(destination) => wallet.update(|w| w.withdraw_all(&destination, &fee_rate)),
+                            Err(e) => Err(CError::Generic(e.to_string())),
+                        };
+                        r.send(DaemonResponse::value_to_deamon_response(withdraw_res))
+                    }
+                    DaemonRequest::Exit => {
+                        debug!("Daemon: Exit");
+                        let exit_res = wallet.update(|w| w.exit_all());
+                        r.send(DaemonResponse::value_to_deamon_response(exit_res))
+                    }
+                    DaemonRequest::FundChannel(statechain_id, peer_id) => {
+                        debug!("Daemon: FundChannel");
+                        let fund_res = match &lightning_node {
+                            Some(node) => wallet.update(|w| state_entity::channel::fund_channel(
+                                w, &vec![statechain_id], &FEE, node, &peer_id, true)),
+                            None => Err(CError::Generic(String::from("No Lightning node configured."))),
+                        };
+                        r.send(DaemonResponse::value_to_deamon_response(fund_res))
+                    }
                     DaemonRequest::TransferSender(statechain_id, receiver_addr) => {
                         debug!("Daemon: TransferSender");
                         let sce_address = encoding::decode_address(receiver_addr,&network).unwrap();
-                        let transfer_sender_resp = state_entity::transfer::transfer_sender(
-                            &mut wallet,
-                            &statechain_id,
-                            sce_address,
-                            None
-                        );
+                        let transfer_sender_resp = wallet.update(|w| {
+                            state_entity::transfer::transfer_sender(
+                                w,
+                                &statechain_id,
+                                sce_address,
+                                None
+                            )
+                        });
                         let encoded_message = encoding::encode_message(transfer_sender_resp.unwrap());
-                        wallet.save();
                         r.send(DaemonResponse::value_to_deamon_response(
                             encoded_message,
                         ))
                     }
+                    DaemonRequest::TransferReceipt(statechain_id) => {
+                        debug!("Daemon: TransferReceipt");
+                        let receipt_res = wallet.update(|w| {
+                            state_entity::transfer::transfer_get_receipts(w, &statechain_id)
+                        });
+                        r.send(DaemonResponse::value_to_deamon_response(receipt_res))
+                    }
                     DaemonRequest::TransferAny(receiver_addr) => {
                         debug!("Daemon: TransferAny");
                         // get list of statecoins
                         let encoded_message: String;
-                        let (_, statechain_ids, _, _): (Vec<Uuid>, Vec<Uuid>, Vec<GetBalanceResponse>, Vec<u32>) = wallet.get_state_chains_info().unwrap();
+                        let (_, statechain_ids, _, _): (Vec<Uuid>, Vec<Uuid>, Vec<GetBalanceResponse>, Vec<u32>) = wallet.with(|w| w.get_state_chains_info()).unwrap();
                         if statechain_ids.len() > 0 {                        
                             let sce_address = encoding::decode_address(receiver_addr,&network).unwrap();
-                            let transfer_sender_resp = state_entity::transfer::transfer_sender(
-                                &mut wallet,
-                                &statechain_ids[0],
-                                sce_address,
-                                None
-                            );
+                            let transfer_sender_resp = wallet.update(|w| {
+                                state_entity::transfer::transfer_sender(
+                                    w,
+                                    &statechain_ids[0],
+                                    sce_address,
+                                    None
+                                )
+                            });
                             encoded_message = encoding::encode_message(transfer_sender_resp.unwrap()).unwrap();
-                            wallet.save();
                         } else {
                             encoded_message = "No statecoins available".to_string();
                         }
@@ -278,29 +395,53 @@ pub fn run_wallet_daemon(force_testing_mode: bool) -> Result<()> {
                     DaemonRequest::TransferReceiver(transfer_msg_bech32) => {
                         debug!("Daemon: TransferReceiver");
                         let mut transfer_msg = encoding::decode_message(transfer_msg_bech32,&network).unwrap();
-                        let transfer_receiver_resp = state_entity::transfer::transfer_receiver(
-                            &mut wallet,
-                            &mut transfer_msg,
-                            &None,
-                        );
-                        wallet.save();
+                        let transfer_receiver_resp = wallet.update(|w| {
+                            state_entity::transfer::transfer_receiver(w, &mut transfer_msg, &None)
+                        });
                         r.send(DaemonResponse::value_to_deamon_response(
                             transfer_receiver_resp,
                         ))
                     }
+                    DaemonRequest::TransferPending => {
+                        debug!("Daemon: TransferPending");
+                        let pending_res = wallet.update(|w| {
+                            w.fetch_pending_transfers()?;
+                            Ok(w.list_pending_transfers())
+                        });
+                        r.send(DaemonResponse::value_to_deamon_response(pending_res))
+                    }
+                    DaemonRequest::TransferAccept(statechain_id) => {
+                        debug!("Daemon: TransferAccept");
+                        let accept_res = wallet.update(|w| w.accept_pending_transfer(&statechain_id));
+                        r.send(DaemonResponse::value_to_deamon_response(accept_res))
+                    }
+                    DaemonRequest::TransferReject(statechain_id) => {
+                        debug!("Daemon: TransferReject");
+                        let reject_res = wallet.update(|w| w.reject_pending_transfer(&statechain_id));
+                        r.send(DaemonResponse::value_to_deamon_response(reject_res))
+                    }
+                    DaemonRequest::TransferReclaim(statechain_id) => {
+                        debug!("Daemon: TransferReclaim");
+                        let reclaim_res = wallet.update(|w| {
+                            state_entity::transfer::transfer_sender_reclaim(w, &statechain_id)
+                        });
+                        r.send(DaemonResponse::value_to_deamon_response(reclaim_res))
+                    }
                     DaemonRequest::Swap(statechain_id, swap_size, force_no_tor) => {
                         debug!(
                             "Daemon: Swapping {} with swap size {}",
                             statechain_id, swap_size
                         );
-                        state_entity::conductor::do_swap(
-                            &mut wallet,
-                            &statechain_id,
-                            &swap_size,
-                            force_no_tor,
-                        )
-                        .unwrap();
-                        wallet.save();
+                        wallet
+                            .update(|w| {
+                                state_entity::conductor::do_swap(
+                                    w,
+                                    &statechain_id,
+                                    &swap_size,
+                                    force_no_tor,
+                                )
+                            })
+                            .unwrap();
                         r.send(DaemonResponse::None)
                     }
                 }
@@ -1,15 +1,21 @@
+use bitcoin::PublicKey;
+use curv::elliptic::curves::traits::ECPoint;
 use curv::{BigInt, FE};
 use kms::ecdsa::two_party::*;
 
-use super::super::utilities::requests;
+use super::noise::{ecdsa_post, unexpected_reply};
 use super::super::ClientShim;
 use super::super::Result;
+use crate::error::CError;
 use crate::wallet::shared_key::SharedKey;
-use shared_lib::structs::{KeyGenMsg1, KeyGenMsg2, Protocol, KeyGenReply1, KeyGenReply2};
+use shared_lib::commitment::p2c_tweak_fe;
+use shared_lib::structs::{
+    EcdsaReply, EcdsaRequest, KeyGenMsg1, KeyGenMsg2, KeyGenReply1, KeyGenReply2, PayToContract,
+    Protocol,
+};
+use serde_json::json;
 use uuid::Uuid;
 
-const KG_PATH_PRE: &str = "ecdsa/keygen";
-
 pub fn get_master_key(
     shared_key_id: &Uuid,
     client_shim: &ClientShim,
@@ -19,9 +25,11 @@ pub fn get_master_key(
     solution: String
 ) -> Result<SharedKey> {
     get_master_key_repeat_keygen(shared_key_id, 
-        client_shim, secret_key, value, protocol, solution, 0)
+        client_shim, secret_key, value, protocol, solution, 0, None)
 }
 
+/// Keygen, committing to p2c_commitment in the shared public key if set (deposit only). The key
+/// share of the returned master key is tweaked, so that its public key is the funding output key.
 pub fn get_master_key_repeat_keygen(
     shared_key_id: &Uuid,
     client_shim: &ClientShim,
@@ -29,21 +37,25 @@ pub fn get_master_key_repeat_keygen(
     value: &u64,
     protocol: Protocol,
     solution: String,
-    kg_reps: u32
+    kg_reps: u32,
+    p2c_commitment: Option<&String>,
 ) -> Result<SharedKey> {
     let mut key_gen_reply_1: KeyGenReply1;
     let mut master_key: MasterKey2;
     let mut n_reps = 0;
     loop {      
-        key_gen_reply_1 = requests::postb(
+        key_gen_reply_1 = match ecdsa_post(
             client_shim,
-            &format!("{}/first", KG_PATH_PRE),
-            KeyGenMsg1 {
+            shared_key_id,
+            EcdsaRequest::KeyGenFirst(KeyGenMsg1 {
                 shared_key_id: *shared_key_id,
                 protocol, 
                 solution: Some(solution.clone())
-            },
-        )?;
+            }),
+        )? {
+            EcdsaReply::KeyGenFirst(reply) => reply,
+            reply => return Err(unexpected_reply(reply)),
+        };
 
         let (kg_party_two_first_message, kg_ec_key_pair_party2) =
             MasterKey2::key_gen_first_message_predefined(secret_key);
@@ -51,21 +63,29 @@ pub fn get_master_key_repeat_keygen(
         let key_gen_msg2 = KeyGenMsg2 {
             shared_key_id: *shared_key_id,
             dlog_proof: kg_party_two_first_message.d_log_proof,
+            p2c_commitment: p2c_commitment.cloned(),
         };
 
-        let kg_party_one_second_message: KeyGenReply2 = requests::postb(
+        let kg_party_one_second_message: KeyGenReply2 = match ecdsa_post(
             client_shim,
-            &format!("{}/second", KG_PATH_PRE),
-            key_gen_msg2,
-        )
-        .unwrap();
+            shared_key_id,
+            EcdsaRequest::KeyGenSecond(key_gen_msg2),
+        )? {
+            EcdsaReply::KeyGenSecond(reply) => reply,
+            reply => return Err(unexpected_reply(reply)),
+        };
 
         let key_gen_second_message = MasterKey2::key_gen_second_message(
             &key_gen_reply_1.msg,
             &kg_party_one_second_message.msg,
         );
 
-        let (_, party_two_paillier) = key_gen_second_message.unwrap();
+        // Verify Paillier key correctness and range proofs from the server
+        let (_, party_two_paillier) = key_gen_second_message.map_err(|_| {
+            CError::StateEntityError(String::from(
+                "Keygen Paillier correctness proof verification failed.",
+            ))
+        })?;
 
 
         master_key = MasterKey2::set_master_key(
@@ -84,6 +104,15 @@ pub fn get_master_key_repeat_keygen(
         }
     }
 
+    let p2c = match p2c_commitment {
+        Some(commitment) => {
+            let (tweaked, p2c) = p2c_tweak_master_key(&master_key, commitment)?;
+            master_key = tweaked;
+            Some(p2c)
+        }
+        None => None,
+    };
+
     Ok(SharedKey {
         id: key_gen_reply_1.user_id,
         share: master_key,
@@ -94,5 +123,45 @@ pub fn get_master_key_repeat_keygen(
         smt_proof: None,
         unspent: true,
         funding_txid: String::default(),
+        backup_addr: None,
+        exit: None,
+        fee_quote: None,
+        transfer_receipt: None,
+        watchtower: None,
+        share_epoch: 0,
+        share_refreshed: None,
+        inheritance: None,
+        rotating_proof_key: None,
+        p2c,
     })
 }
+
+/// Tweak a master key by a pay-to-contract commitment: the private share and the public keys of
+/// the owner are multiplied by the tweak of the shared public key.
+pub fn p2c_tweak_master_key(
+    master_key: &MasterKey2,
+    commitment: &String,
+) -> Result<(MasterKey2, PayToContract)> {
+    let shared_pubkey = PublicKey {
+        compressed: true,
+        key: master_key.public.q.get_element(),
+    };
+    let tweak: FE = p2c_tweak_fe(&shared_pubkey, commitment)?;
+    let x2: FE = master_key.private.get_private_key() * tweak;
+
+    let mut public = serde_json::to_value(&master_key.public)?;
+    public["q"] = serde_json::to_value(master_key.public.q * tweak)?;
+    public["p2"] = serde_json::to_value(master_key.public.p2 * tweak)?;
+    let tweaked: MasterKey2 = serde_json::from_value(json!({
+        "public": public,
+        "private": { "x2": x2 },
+        "chain_code": master_key.chain_code,
+    }))?;
+    Ok((
+        tweaked,
+        PayToContract {
+            shared_pubkey: shared_pubkey.to_string(),
+            commitment: commitment.clone(),
+        },
+    ))
+}
@@ -1,4 +1,5 @@
 pub mod keygen;
+pub mod noise;
 pub mod sign;
 pub mod types;
 
@@ -0,0 +1,199 @@
+//! Noise
+//!
+//! Encrypted channel for the ECDSA protocol messages of a user session, opened with a Noise KK
+//! handshake keyed by the user session proof key and the server identity key.
+
+use super::super::utilities::requests;
+use super::super::ClientShim;
+use super::super::Result;
+use crate::error::CError;
+use shared_lib::noise::{ecdsa_prologue, kk_initiate};
+use shared_lib::structs::{
+    EcdsaReply, EcdsaRequest, IdentityPubKey, NoiseHandshakeMsg, NoiseMsg,
+};
+
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Get the server identity key, from the config if set otherwise from the server
+fn server_identity_key(client_shim: &ClientShim) -> Result<PublicKey> {
+    let key = match client_shim.server_identity_key.is_empty() {
+        true => requests::get::<IdentityPubKey>(client_shim, "ecdsa/noise/identity")?.key,
+        false => client_shim.server_identity_key.clone(),
+    };
+    PublicKey::from_str(&key)
+        .map_err(|e| CError::Generic(format!("Invalid server identity key: {}", e)))
+}
+
+/// Open the encrypted channel of a user session with the private key of its proof key.
+/// Replaces any channel previously opened for the session.
+pub fn noise_connect(
+    client_shim: &ClientShim,
+    shared_key_id: &Uuid,
+    proof_key_priv: &SecretKey,
+) -> Result<()> {
+    let identity_key = server_identity_key(client_shim)?;
+    let (initiator, message) = kk_initiate(
+        &ecdsa_prologue(shared_key_id),
+        proof_key_priv,
+        &identity_key,
+        &[],
+    )?;
+    let reply: NoiseMsg = requests::postb(
+        client_shim,
+        "ecdsa/noise/handshake",
+        NoiseHandshakeMsg {
+            shared_key_id: *shared_key_id,
+            message: hex::encode(message),
+        },
+    )?;
+    let ciphertext = hex::decode(&reply.ciphertext)
+        .map_err(|e| CError::Generic(format!("Invalid handshake reply: {}", e)))?;
+    let (_, channel) = initiator.finish(&ciphertext)?;
+    client_shim.noise.lock()?.insert(*shared_key_id, channel);
+    Ok(())
+}
+
+/// Close the encrypted channel of a user session
+pub fn noise_close(client_shim: &ClientShim, shared_key_id: &Uuid) -> Result<()> {
+    client_shim.noise.lock()?.remove(shared_key_id);
+    Ok(())
+}
+
+fn request_path(request: &EcdsaRequest) -> &'static str {
+    match request {
+        EcdsaRequest::KeyGenFirst(_) => "ecdsa/keygen/first",
+        EcdsaRequest::KeyGenSecond(_) => "ecdsa/keygen/second",
+        EcdsaRequest::SignFirst(_) => "ecdsa/sign/first",
+        EcdsaRequest::SignSecond(_) => "ecdsa/sign/second",
+    }
+}
+
+/// Send an ECDSA protocol message over the encrypted channel of its user session if one is
+/// open, otherwise in the clear to the protocol endpoint
+pub fn ecdsa_post(
+    client_shim: &ClientShim,
+    shared_key_id: &Uuid,
+    request: EcdsaRequest,
+) -> Result<EcdsaReply> {
+    // The channel is taken for the duration of the request so that messages of a session
+    // are sent in order
+    let channel = client_shim.noise.lock()?.remove(shared_key_id);
+    let mut channel = match channel {
+        Some(c) => c,
+        None => {
+            let path = request_path(&request);
+            return Ok(match request {
+                EcdsaRequest::KeyGenFirst(msg) => {
+                    EcdsaReply::KeyGenFirst(requests::postb(client_shim, path, msg)?)
+                }
+                EcdsaRequest::KeyGenSecond(msg) => {
+                    EcdsaReply::KeyGenSecond(requests::postb(client_shim, path, msg)?)
+                }
+                EcdsaRequest::SignFirst(msg) => {
+                    EcdsaReply::SignFirst(requests::postb(client_shim, path, msg)?)
+                }
+                EcdsaRequest::SignSecond(msg) => {
+                    EcdsaReply::SignSecond(requests::postb(client_shim, path, msg)?)
+                }
+            });
+        }
+    };
+
+    let plaintext = serde_json::to_vec(&request)?;
+    let ciphertext = channel.encrypt(&plaintext)?;
+    // If the request fails the channel is out of step with the server and is dropped
+    let reply: NoiseMsg = requests::postb(
+        client_shim,
+        "ecdsa/noise",
+        NoiseMsg {
+            shared_key_id: *shared_key_id,
+            ciphertext: hex::encode(ciphertext),
+        },
+    )?;
+    let ciphertext = hex::decode(&reply.ciphertext)
+        .map_err(|e| CError::Generic(format!("Invalid encrypted reply: {}", e)))?;
+    let reply: EcdsaReply = serde_json::from_slice(&channel.decrypt(&ciphertext)?)?;
+    client_shim.noise.lock()?.insert(*shared_key_id, channel);
+
+    match reply {
+        EcdsaReply::Error(e) => Err(CError::StateEntityError(e)),
+        reply => Ok(reply),
+    }
+}
+
+/// Error for an ECDSA reply of the wrong kind
+pub fn unexpected_reply(reply: EcdsaReply) -> CError {
+    CError::StateEntityError(format!("Unexpected ECDSA reply: {:?}", reply))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::Secp256k1;
+    use mockito::mock;
+    use shared_lib::noise::kk_respond;
+    use shared_lib::structs::{KeyGenMsg1, KeyGenReply1, Protocol};
+
+    fn keygen_first(shared_key_id: Uuid) -> EcdsaRequest {
+        EcdsaRequest::KeyGenFirst(KeyGenMsg1 {
+            shared_key_id,
+            protocol: Protocol::Deposit,
+            solution: None,
+        })
+    }
+
+    #[test]
+    fn test_ecdsa_post() {
+        let secp = Secp256k1::new();
+        let identity_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let identity_key = PublicKey::from_secret_key(&secp, &identity_priv);
+        let proof_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &proof_priv);
+        let shared_key_id = Uuid::new_v4();
+        let client_shim = ClientShim::new(mockito::server_url(), None, None);
+
+        // No channel open: sent in the clear
+        let m = mock("POST", "/ecdsa/keygen/first")
+            .with_body(serde_json::to_string(&KeyGenReply1::example()).unwrap())
+            .expect(1)
+            .create();
+        match ecdsa_post(&client_shim, &shared_key_id, keygen_first(shared_key_id)) {
+            Ok(EcdsaReply::KeyGenFirst(_)) => (),
+            r => assert!(false, "Expected KeyGenFirst reply, got {:?}", r),
+        }
+        m.assert();
+
+        let prologue = ecdsa_prologue(&shared_key_id);
+        let (initiator, msg1) = kk_initiate(&prologue, &proof_priv, &identity_key, &[]).unwrap();
+        let (_, msg2, mut server) =
+            kk_respond(&prologue, &identity_priv, &proof_key, &msg1, &[]).unwrap();
+        let (_, channel) = initiator.finish(&msg2).unwrap();
+        client_shim.noise.lock().unwrap().insert(shared_key_id, channel);
+
+        // Encrypted error replies are returned as errors and the channel is kept
+        let reply = EcdsaReply::Error(String::from("keygen failed"));
+        let ciphertext = server.encrypt(&serde_json::to_vec(&reply).unwrap()).unwrap();
+        let m = mock("POST", "/ecdsa/noise")
+            .with_body(
+                serde_json::to_string(&NoiseMsg {
+                    shared_key_id,
+                    ciphertext: hex::encode(ciphertext),
+                })
+                .unwrap(),
+            )
+            .expect(1)
+            .create();
+        match ecdsa_post(&client_shim, &shared_key_id, keygen_first(shared_key_id)) {
+            Err(CError::StateEntityError(e)) => assert_eq!(e, "keygen failed"),
+            r => assert!(false, "Expected StateEntityError, got {:?}", r),
+        }
+        m.assert();
+        assert!(client_shim.noise.lock().unwrap().contains_key(&shared_key_id));
+
+        // A reply that does not decrypt drops the channel
+        assert!(ecdsa_post(&client_shim, &shared_key_id, keygen_first(shared_key_id)).is_err());
+        assert!(!client_shim.noise.lock().unwrap().contains_key(&shared_key_id));
+    }
+}
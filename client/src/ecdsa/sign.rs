@@ -1,7 +1,9 @@
-use super::super::utilities::requests;
+use super::noise::{ecdsa_post, unexpected_reply};
 use super::super::ClientShim;
 use super::super::Result;
-use shared_lib::structs::{Protocol, SignMsg1, SignMsg2, SignSecondMsgRequest, SignReply1};
+use shared_lib::structs::{
+    EcdsaReply, EcdsaRequest, Protocol, SignMsg1, SignMsg2, SignReply1, SignSecondMsgRequest,
+};
 
 use curv::BigInt;
 use kms::ecdsa::two_party::MasterKey2;
@@ -23,7 +25,10 @@ pub fn sign(
         eph_key_gen_first_message_party_two,
     };
     let sign_party_one_first_message: SignReply1 =
-        requests::postb(client_shim, &format!("ecdsa/sign/first"), &sign_msg1)?;
+        match ecdsa_post(client_shim, shared_key_id, EcdsaRequest::SignFirst(sign_msg1))? {
+            EcdsaReply::SignFirst(reply) => reply,
+            reply => return Err(unexpected_reply(reply)),
+        };
 
     let party_two_sign_message = mk.sign_second_message(
         &eph_ec_key_pair_party2,
@@ -41,11 +46,11 @@ pub fn sign(
         },
     };
 
-    let signature = requests::postb::<&SignMsg2, Vec<Vec<u8>>>(
-        client_shim,
-        &format!("ecdsa/sign/second",),
-        &sign_msg2,
-    )?;
+    let signature =
+        match ecdsa_post(client_shim, shared_key_id, EcdsaRequest::SignSecond(sign_msg2))? {
+            EcdsaReply::SignSecond(signature) => signature,
+            reply => return Err(unexpected_reply(reply)),
+        };
 
     Ok(signature)
 }
@@ -28,6 +28,8 @@ pub enum CError {
     TorError(String),
     /// Swap error
     SwapError(String),
+    /// Shared key must be refreshed before transfer
+    KeyRefreshRequired(String),
 }
 
 impl From<String> for CError {
@@ -78,6 +80,12 @@ impl From<std::io::Error> for CError {
     }
 }
 
+impl<T> From<std::sync::PoisonError<T>> for CError {
+    fn from(e: std::sync::PoisonError<T>) -> CError {
+        CError::Generic(e.to_string())
+    }
+}
+
 impl From<serde_json::Error> for CError {
     fn from(e: serde_json::Error) -> CError {
         CError::Generic(e.to_string())
@@ -124,6 +132,9 @@ pub enum WalletErrorType {
     StateChainNotFound,
     WalletFileNotFound,
     WalletFileInvalid,
+    WalletFileVersionUnsupported,
+    WalletLockPoisoned,
+    PendingTransferNotFound,
 }
 
 impl WalletErrorType {
@@ -136,6 +147,9 @@ impl WalletErrorType {
             WalletErrorType::StateChainNotFound => "StateChain not found in wallet derivation path",
             WalletErrorType::WalletFileNotFound => "Wallet data file not found",
             WalletErrorType::WalletFileInvalid => "Wallet data file invalid format",
+            WalletErrorType::WalletFileVersionUnsupported => "Wallet data file version not supported by this client",
+            WalletErrorType::WalletLockPoisoned => "Wallet lock poisoned by a failed operation",
+            WalletErrorType::PendingTransferNotFound => "Pending transfer not found in wallet",
         }
     }
 }
@@ -150,6 +164,7 @@ impl fmt::Display for CError {
             CError::SharedLibError(ref e) => write!(f, "SharedLib Error: {}", e),
             CError::TorError(ref e) => write!(f, "Tor Error: {}", e),
             CError::SwapError(ref e) => write!(f, "Swap Error: {}", e),
+            CError::KeyRefreshRequired(ref e) => write!(f, "State Entity Error: {}", e),
         }
     }
 }
@@ -0,0 +1,479 @@
+//! FFI
+//!
+//! C ABI for the client library, for wallets written in other languages (e.g. Swift or Kotlin
+//! on mobile). The header is include/mercury_client.h, generated with cbindgen (see
+//! cbindgen.toml).
+//!
+//! Requests and responses are NUL terminated UTF-8 JSON strings. Every call other than
+//! mercury_wallet_open() returns a response object of the form {"ok": <value>} or
+//! {"error": "<message>"}.
+//!
+//! Memory ownership:
+//! - Strings passed to the library are borrowed for the duration of the call only.
+//! - Strings returned by the library are owned by the caller and must be released with
+//!   mercury_string_free(). They must not be freed with the platform free().
+//! - A wallet opened with mercury_wallet_open() is owned by the caller and must be released
+//!   with mercury_wallet_free(). Calls on a wallet may be made from any thread: operations on
+//!   the wallet are serialized and the wallet is saved after each operation.
+
+use super::Result;
+use crate::endpoint::Endpoints;
+use crate::error::{CError, WalletErrorType};
+use crate::state_entity::{deposit, transfer};
+use crate::utilities::encoding;
+use crate::wallet::handle::WalletHandle;
+use crate::wallet::statecoin::{StateCoin, StateCoinInfo};
+use crate::wallet::wallet::{ElectrumxBox, Wallet};
+use crate::ClientShim;
+
+use bitcoin::{Address, Network};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Opaque handle to an open wallet
+pub struct MercuryWallet {
+    handle: WalletHandle,
+}
+
+/// Response to a call
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FfiResponse<T> {
+    Ok(T),
+    Error(String),
+}
+
+/// mercury_wallet_open() request. A new wallet is created at wallet_path if there is none,
+/// from seed (hex) if supplied or else from a random seed.
+#[derive(Deserialize)]
+struct OpenRequest {
+    wallet_path: String,
+    network: String,
+    endpoint: String,
+    conductor_endpoint: String,
+    #[serde(default)]
+    fallback_endpoints: Vec<String>,
+    #[serde(default)]
+    conductor_fallback_endpoints: Vec<String>,
+    #[serde(default)]
+    electrum_server: String, // mock Electrum server if empty
+    #[serde(default)]
+    seed: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DepositRequest {
+    amount: u64,
+}
+
+#[derive(Deserialize)]
+struct TransferSendRequest {
+    statechain_id: Uuid,
+    address: String, // bech32 encoded SCE address of the receiver
+}
+
+#[derive(Deserialize)]
+struct TransferReceiveRequest {
+    transfer_msg: String, // bech32 encoded transfer message
+}
+
+#[derive(Deserialize)]
+struct WithdrawRequest {
+    statechain_id: Uuid,
+    address: String,
+    tx_fee: u64,
+}
+
+#[derive(Deserialize)]
+struct SwapRequest {
+    statechain_id: Uuid,
+    swap_size: u64,
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    // JSON output escapes NUL characters
+    CString::new(s)
+        .expect("JSON string contains no NUL")
+        .into_raw()
+}
+
+/// # Safety
+///
+/// request must be NULL or a NUL terminated string.
+unsafe fn parse<T: DeserializeOwned>(request: *const c_char) -> Result<T> {
+    if request.is_null() {
+        return Err(CError::Generic(String::from("Null request")));
+    }
+    let request = CStr::from_ptr(request)
+        .to_str()
+        .map_err(|e| CError::Generic(format!("Request is not UTF-8: {}", e)))?;
+    serde_json::from_str(request).map_err(|e| CError::Generic(format!("Invalid request: {}", e)))
+}
+
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that is not released
+/// for the lifetime 'a.
+unsafe fn wallet_ref<'a>(wallet: *const MercuryWallet) -> Result<&'a WalletHandle> {
+    match wallet.as_ref() {
+        Some(wallet) => Ok(&wallet.handle),
+        None => Err(CError::Generic(String::from("Null wallet"))),
+    }
+}
+
+/// Run f, catching panics so that they do not unwind across the C ABI, and return its
+/// response object
+fn respond<T, F>(f: F) -> *mut c_char
+where
+    T: Serialize,
+    F: FnOnce() -> Result<T>,
+{
+    let response = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => FfiResponse::Ok(value),
+        Ok(Err(e)) => FfiResponse::Error(e.to_string()),
+        Err(_) => FfiResponse::Error(String::from("Client library panicked")),
+    };
+    let json = serde_json::to_string(&response).unwrap_or_else(|e| {
+        serde_json::to_string(&FfiResponse::<()>::Error(e.to_string())).unwrap()
+    });
+    into_c_string(json)
+}
+
+fn open_wallet(request: OpenRequest) -> Result<Wallet> {
+    let network = Network::from_str(&request.network)
+        .map_err(|e| CError::Generic(format!("Invalid network: {}", e)))?;
+    let mut urls = vec![request.endpoint];
+    urls.extend(request.fallback_endpoints);
+    let client_shim = ClientShim::with_endpoints(Endpoints::from_urls(&urls)?, None, None)?;
+    let mut urls = vec![request.conductor_endpoint];
+    urls.extend(request.conductor_fallback_endpoints);
+    let conductor_shim = ClientShim::with_endpoints(Endpoints::from_urls(&urls)?, None, None)?;
+    let mut wallet = match Wallet::load(&request.wallet_path, client_shim.clone(), conductor_shim.clone()) {
+        Ok(wallet) => wallet,
+        Err(CError::WalletError(WalletErrorType::WalletFileNotFound)) => {
+            let seed = match request.seed {
+                Some(seed) => hex::decode(seed)
+                    .map_err(|e| CError::Generic(format!("Invalid seed: {}", e)))?,
+                None => rand::thread_rng().gen::<[u8; 32]>().to_vec(),
+            };
+            let wallet = Wallet::new(
+                &seed,
+                &network.to_string(),
+                &request.wallet_path,
+                client_shim,
+                conductor_shim,
+            );
+            wallet.save();
+            wallet
+        }
+        Err(e) => return Err(e),
+    };
+    if wallet.get_bitcoin_network() != network {
+        return Err(CError::Generic(format!(
+            "Wallet network is {}, requested {}",
+            wallet.network, network
+        )));
+    }
+    if !request.electrum_server.is_empty() {
+        wallet.set_electrumx_client(ElectrumxBox::new(request.electrum_server)?);
+    }
+    Ok(wallet)
+}
+
+/// Open the wallet described by the JSON request, creating it if it does not exist. Returns
+/// NULL on failure and, if error is not NULL, sets *error to a response object describing the
+/// failure, to be released with mercury_string_free().
+///
+/// # Safety
+///
+/// error must be NULL or point to writable storage for a string pointer.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_wallet_open(
+    request: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut MercuryWallet {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        parse::<OpenRequest>(request).and_then(open_wallet)
+    }));
+    let err = match res {
+        Ok(Ok(wallet)) => {
+            return Box::into_raw(Box::new(MercuryWallet {
+                handle: WalletHandle::new(wallet),
+            }))
+        }
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => String::from("Client library panicked"),
+    };
+    if !error.is_null() {
+        let json = serde_json::to_string(&FfiResponse::<()>::Error(err)).unwrap();
+        *error = into_c_string(json);
+    }
+    ptr::null_mut()
+}
+
+/// Release a wallet opened with mercury_wallet_open(). NULL is ignored.
+///
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+/// released. It must not be used once released.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_wallet_free(wallet: *mut MercuryWallet) {
+    if !wallet.is_null() {
+        drop(Box::from_raw(wallet));
+    }
+}
+
+/// Release a string returned by the library. NULL is ignored.
+///
+/// # Safety
+///
+/// s must be NULL or a string returned by the library that has not been released.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// New bech32 encoded SCE address to receive a transfer to
+///
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+/// released.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_new_address(wallet: *const MercuryWallet) -> *mut c_char {
+    respond(|| {
+        let handle = wallet_ref(wallet)?;
+        let address = handle.update(|w| w.get_new_state_entity_address())?;
+        encoding::encode_address(address)
+    })
+}
+
+/// Statecoins owned by the wallet
+///
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+/// released.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_statecoins(wallet: *const MercuryWallet) -> *mut c_char {
+    respond(|| {
+        let handle = wallet_ref(wallet)?;
+        StateCoin::list(handle)?
+            .iter()
+            .map(|coin| coin.status())
+            .collect::<Result<Vec<StateCoinInfo>>>()
+    })
+}
+
+/// Deposit {"amount": <satoshis>}. Returns the new statecoin.
+///
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+/// released. request must be NULL or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_deposit(
+    wallet: *const MercuryWallet,
+    request: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let handle = wallet_ref(wallet)?;
+        let request: DepositRequest = parse(request)?;
+        let (shared_key_id, ..) = handle.update(|w| deposit::deposit(w, &request.amount))?;
+        StateCoin::new(handle, &shared_key_id)?.status()
+    })
+}
+
+/// Send a statecoin {"statechain_id": <uuid>, "address": <bech32 SCE address>}. Returns the
+/// bech32 encoded transfer message to pass to the receiver.
+///
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+/// released. request must be NULL or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_transfer_send(
+    wallet: *const MercuryWallet,
+    request: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let handle = wallet_ref(wallet)?;
+        let request: TransferSendRequest = parse(request)?;
+        StateCoin::from_statechain_id(handle, &request.statechain_id)?.transfer_to(&request.address)
+    })
+}
+
+/// Receive a statecoin {"transfer_msg": <bech32 transfer message>}. Returns the received
+/// statecoin.
+///
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+/// released. request must be NULL or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_transfer_receive(
+    wallet: *const MercuryWallet,
+    request: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let handle = wallet_ref(wallet)?;
+        let request: TransferReceiveRequest = parse(request)?;
+        let network = handle.with(|w| Ok(w.network.clone()))?;
+        let mut transfer_msg3 = encoding::decode_message(request.transfer_msg, &network)?;
+        let finalize_data =
+            handle.update(|w| transfer::transfer_receiver(w, &mut transfer_msg3, &None))?;
+        StateCoin::new(handle, &finalize_data.new_shared_key_id)?.status()
+    })
+}
+
+/// Withdraw a statecoin {"statechain_id": <uuid>, "address": <bitcoin address>,
+/// "tx_fee": <satoshis>}. Returns the withdraw txid.
+///
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+/// released. request must be NULL or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_withdraw(
+    wallet: *const MercuryWallet,
+    request: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let handle = wallet_ref(wallet)?;
+        let request: WithdrawRequest = parse(request)?;
+        let address = Address::from_str(&request.address)?;
+        StateCoin::from_statechain_id(handle, &request.statechain_id)?
+            .withdraw(&address, request.tx_fee)
+    })
+}
+
+/// Swap a statecoin {"statechain_id": <uuid>, "swap_size": <statecoins in the swap group>}.
+/// Blocks until the swap completes and returns the statecoin received in the swap.
+///
+/// # Safety
+///
+/// wallet must be NULL or a wallet returned by mercury_wallet_open() that has not been
+/// released. request must be NULL or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mercury_swap(
+    wallet: *const MercuryWallet,
+    request: *const c_char,
+) -> *mut c_char {
+    respond(|| {
+        let handle = wallet_ref(wallet)?;
+        let request: SwapRequest = parse(request)?;
+        StateCoin::from_statechain_id(handle, &request.statechain_id)?
+            .join_swap(request.swap_size)?
+            .status()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::wallet::DEFAULT_TEST_WALLET_LOC;
+    use serde_json::{json, Value};
+    use std::fs;
+
+    fn take_response(s: *mut c_char) -> Value {
+        assert!(!s.is_null());
+        let json = unsafe { CStr::from_ptr(s) }.to_str().unwrap().to_string();
+        unsafe { mercury_string_free(s) };
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn open_request() -> CString {
+        CString::new(
+            json!({
+                "wallet_path": DEFAULT_TEST_WALLET_LOC,
+                "network": "regtest",
+                "endpoint": "http://localhost:8000",
+                "conductor_endpoint": "http://localhost:8000",
+                "seed": hex::encode([0xcd; 32]),
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    #[serial]
+    fn test_ffi_wallet() {
+        let _ = fs::remove_file(DEFAULT_TEST_WALLET_LOC);
+        let wallet = unsafe { mercury_wallet_open(open_request().as_ptr(), ptr::null_mut()) };
+        assert!(!wallet.is_null());
+
+        let address = take_response(unsafe { mercury_new_address(wallet) });
+        let address = address["ok"].as_str().unwrap().to_string();
+        assert!(encoding::decode_address(address, &String::from("regtest")).is_ok());
+        assert_eq!(
+            take_response(unsafe { mercury_statecoins(wallet) }),
+            json!({ "ok": [] })
+        );
+
+        // Invalid requests
+        let request = CString::new("{\"amount\": \"all\"}").unwrap();
+        let response = take_response(unsafe { mercury_deposit(wallet, request.as_ptr()) });
+        assert!(response["error"].as_str().unwrap().contains("Invalid request"));
+        let response = take_response(unsafe { mercury_deposit(wallet, ptr::null()) });
+        assert!(response["error"].is_string());
+        let request = CString::new(
+            json!({ "statechain_id": Uuid::new_v4(), "address": "sc1", "tx_fee": 300 }).to_string(),
+        )
+        .unwrap();
+        let response = take_response(unsafe { mercury_withdraw(wallet, request.as_ptr()) });
+        assert!(response["error"].is_string());
+        unsafe { mercury_wallet_free(wallet) };
+
+        // The wallet is saved and reopened
+        let wallet = unsafe { mercury_wallet_open(open_request().as_ptr(), ptr::null_mut()) };
+        assert!(!wallet.is_null());
+        unsafe { mercury_wallet_free(wallet) };
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        let mut error: *mut c_char = ptr::null_mut();
+        let request = CString::new("{\"network\": \"regtest\"}").unwrap();
+        let wallet = unsafe { mercury_wallet_open(request.as_ptr(), &mut error) };
+        assert!(wallet.is_null());
+        assert!(take_response(error)["error"].is_string());
+
+        let response = take_response(unsafe { mercury_new_address(ptr::null()) });
+        assert_eq!(response, json!({ "error": "Error: Null wallet" }));
+        // NULL is ignored
+        unsafe {
+            mercury_wallet_free(ptr::null_mut());
+            mercury_string_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_ffi_header() {
+        let header = include_str!("../include/mercury_client.h");
+        for f in &[
+            "mercury_wallet_open",
+            "mercury_wallet_free",
+            "mercury_string_free",
+            "mercury_new_address",
+            "mercury_statecoins",
+            "mercury_deposit",
+            "mercury_transfer_send",
+            "mercury_transfer_receive",
+            "mercury_withdraw",
+            "mercury_swap",
+        ] {
+            assert!(header.contains(&format!("{}(", f)), "{} missing from header", f);
+        }
+    }
+}
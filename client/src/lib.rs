@@ -15,6 +15,7 @@ extern crate mockito;
 extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
+extern crate serde_cbor;
 
 extern crate daemon_engine;
 extern crate tokio;
@@ -41,7 +42,10 @@ extern crate uuid;
 extern crate sha3;
 pub mod daemon;
 pub mod ecdsa;
+pub mod endpoint;
 pub mod error;
+pub mod ffi;
+pub mod lightning;
 pub mod state_entity;
 pub mod wallet;
 
@@ -50,10 +54,16 @@ mod utilities;
 use serde::{Deserialize, Serialize};
 
 use pyo3::prelude::*;
+use rand::Rng;
 use pyo3::{py_run, PyCell, PyObjectProtocol};
 
 use config::Config as ConfigRs;
+use endpoint::Endpoints;
 use error::CError;
+use shared_lib::noise::TransportState;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
 
 pub type Result<T> = std::result::Result<T, CError>;
 
@@ -66,9 +76,35 @@ pub mod tor {
 pub struct Config {
     pub endpoint: String,
     pub conductor_endpoint: String,
+    /// Endpoints of the state entity used when the endpoint is down, in priority order
+    pub fallback_endpoints: Vec<String>,
+    /// Endpoints of the conductor used when the conductor endpoint is down, in priority order
+    pub conductor_fallback_endpoints: Vec<String>,
     pub electrum_server: String,
     pub testing_mode: bool,
+    /// Encode protocol messages with CBOR instead of JSON
+    pub cbor: bool,
     pub tor: Tor,
+    pub retry: RetryPolicy,
+    pub timeouts: Timeouts,
+    /// Blocks remaining before a statecoin backup tx locktime expires at which to alert
+    pub expiry_alert_blocks: u32,
+    /// Response to spends of statecoin funding outputs by txs other than their backup tx
+    pub spend_response: wallet::shared_key::SpendResponse,
+    /// Maximum number of concurrent sender flows when transferring many statecoins
+    pub transfer_concurrency: usize,
+    /// Funding tx confirmation status polls before a deposit stops waiting and can be resumed
+    pub funding_conf_max_polls: u32,
+    /// Unissued one-time receive keys kept in the wallet's pool
+    pub receive_key_pool_size: usize,
+    /// Send ECDSA protocol messages over a Noise channel encrypted to the server identity key
+    pub ecdsa_encryption: bool,
+    /// Server identity public key (hex). Server signed fee quotes, receipts and reserves are
+    /// verified against this pinned key. Fetched from the server for encrypted channels if empty.
+    pub server_identity_key: String,
+    /// Send a nonce with each request and require the response to be signed by the server
+    /// identity key. Requires server_identity_key.
+    pub verify_responses: bool,
 }
 
 impl Config {
@@ -78,9 +114,22 @@ impl Config {
         Ok(Config {
             endpoint: cfg.get("endpoint")?,
             conductor_endpoint: cfg.get("conductor_endpoint")?,
+            fallback_endpoints: cfg.get("fallback_endpoints")?,
+            conductor_fallback_endpoints: cfg.get("conductor_fallback_endpoints")?,
             electrum_server: cfg.get("electrum_server")?,
             testing_mode: cfg.get("testing_mode")?,
+            cbor: cfg.get("cbor")?,
             tor,
+            retry: cfg.get("retry")?,
+            timeouts: cfg.get("timeouts")?,
+            expiry_alert_blocks: cfg.get("expiry_alert_blocks")?,
+            spend_response: cfg.get("spend_response")?,
+            transfer_concurrency: cfg.get("transfer_concurrency")?,
+            funding_conf_max_polls: cfg.get("funding_conf_max_polls")?,
+            receive_key_pool_size: cfg.get("receive_key_pool_size")?,
+            ecdsa_encryption: cfg.get("ecdsa_encryption")?,
+            server_identity_key: cfg.get("server_identity_key")?,
+            verify_responses: cfg.get("verify_responses")?,
         })
     }
 }
@@ -90,9 +139,22 @@ impl Default for Config {
         Config {
             endpoint: "http://localhost:8000".to_string(),
             conductor_endpoint: "http://localhost:8001".to_string(),
+            fallback_endpoints: vec![],
+            conductor_fallback_endpoints: vec![],
             electrum_server: "127.0.0.1:60401".to_string(),
             testing_mode: true,
+            cbor: false,
             tor: Tor::default(),
+            retry: RetryPolicy::default(),
+            timeouts: Timeouts::default(),
+            expiry_alert_blocks: wallet::wallet::DEFAULT_EXPIRY_ALERT_BLOCKS,
+            spend_response: wallet::shared_key::SpendResponse::default(),
+            transfer_concurrency: wallet::wallet::DEFAULT_TRANSFER_CONCURRENCY,
+            funding_conf_max_polls: wallet::wallet::DEFAULT_FUNDING_CONF_MAX_POLLS,
+            receive_key_pool_size: wallet::receive_keys::DEFAULT_RECEIVE_KEY_POOL_SIZE,
+            ecdsa_encryption: false,
+            server_identity_key: String::default(),
+            verify_responses: false,
         }
     }
 }
@@ -219,31 +281,187 @@ impl Tor {
     }
 }
 
+/// Retry policy of requests to the state entity that fail with a transient error
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts of a request. 1 disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry (milliseconds). Doubled for each further retry.
+    pub backoff_ms: u64,
+    /// Maximum delay between attempts (milliseconds)
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff_ms: 500,
+            max_backoff_ms: 8000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// No retries
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retry number 'retry' (from 1): exponential backoff with jitter, uniformly
+    /// distributed between half and all of the backoff so that clients failing together do not
+    /// retry together
+    pub fn backoff(&self, retry: u32) -> std::time::Duration {
+        let exp = self
+            .backoff_ms
+            .saturating_mul(1u64.checked_shl(retry.saturating_sub(1)).unwrap_or(u64::MAX));
+        let max = std::cmp::min(exp, self.max_backoff_ms);
+        let jittered = max / 2 + rand::thread_rng().gen_range(0, max / 2 + 1);
+        std::time::Duration::from_millis(jittered)
+    }
+}
+
+/// Class of a request to the state entity, determining its timeout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestClass {
+    /// Info and status polling
+    Poll,
+    /// Protocol messages, including key generation and signing
+    Sign,
+    /// Requests that wait for an on-chain or protocol confirmation
+    Confirm,
+}
+
+/// Request timeouts (seconds) by request class
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    pub poll: u64,
+    pub sign: u64,
+    pub confirm: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            poll: 10,
+            sign: 60,
+            confirm: 600,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn get(&self, class: RequestClass) -> std::time::Duration {
+        std::time::Duration::from_secs(match class {
+            RequestClass::Poll => self.poll,
+            RequestClass::Sign => self.sign,
+            RequestClass::Confirm => self.confirm,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ClientShim {
     pub client: reqwest::blocking::Client,
     pub tor: Option<Tor>,
     pub auth_token: Option<String>,
-    pub endpoint: String,
+    /// State entity endpoint and fallbacks
+    pub endpoints: Endpoints,
+    /// Send requests and receive responses CBOR encoded
+    pub cbor: bool,
+    /// Retry policy for transient request failures
+    pub retry: RetryPolicy,
+    /// Request timeouts by request class
+    pub timeouts: Timeouts,
+    /// Open ECDSA protocol message channels before key generation and signing
+    pub ecdsa_encryption: bool,
+    /// Server identity public key for the ECDSA channels. Fetched from the server if empty.
+    pub server_identity_key: String,
+    /// Encrypted ECDSA channels by user session
+    pub noise: Arc<Mutex<HashMap<Uuid, TransportState>>>,
+    /// Require responses to be signed by the server identity key for a request nonce
+    pub verify_responses: bool,
 }
 
 impl ClientShim {
-    pub fn from_config(config: &Config) -> ClientShim {
-        match config.tor.enable {
-            true => Self::new(config.endpoint.to_owned(), None, Some(config.tor.clone())),
-            false => Self::new(config.endpoint.to_owned(), None, None),
+    pub fn from_config(config: &Config) -> Result<ClientShim> {
+        let mut urls = vec![config.endpoint.clone()];
+        urls.extend(config.fallback_endpoints.iter().cloned());
+        let tor = match config.tor.enable {
+            true => Some(config.tor.clone()),
+            false => None,
+        };
+        let mut cs = Self::with_endpoints(Endpoints::from_urls(&urls)?, None, tor)?;
+        cs.cbor = config.cbor;
+        cs.retry = config.retry;
+        cs.timeouts = config.timeouts;
+        cs.ecdsa_encryption = config.ecdsa_encryption;
+        cs.server_identity_key = config.server_identity_key.clone();
+        if config.verify_responses && config.server_identity_key.is_empty() {
+            return Err(CError::Generic(String::from(
+                "verify_responses requires server_identity_key to be set",
+            )));
         }
+        cs.verify_responses = config.verify_responses;
+        Ok(cs)
     }
 
+    /// Client shim for a single endpoint. Panics if the endpoint is invalid.
     pub fn new(endpoint: String, auth_token: Option<String>, tor: Option<Tor>) -> ClientShim {
+        Endpoints::from_urls(&[endpoint])
+            .and_then(|endpoints| Self::with_endpoints(endpoints, auth_token, tor))
+            .expect("invalid endpoint")
+    }
+
+    /// Client shim for an endpoint and fallbacks. Onion endpoints require Tor.
+    pub fn with_endpoints(
+        endpoints: Endpoints,
+        auth_token: Option<String>,
+        tor: Option<Tor>,
+    ) -> Result<ClientShim> {
+        if endpoints.has_onion() && !tor.as_ref().map_or(false, |t| t.enable) {
+            return Err(CError::TorError(String::from(
+                "onion endpoints require Tor to be enabled",
+            )));
+        }
         let client = Self::new_client(tor.as_ref());
         let cs = ClientShim {
             client,
             tor,
             auth_token,
-            endpoint,
+            endpoints,
+            cbor: false,
+            retry: RetryPolicy::default(),
+            timeouts: Timeouts::default(),
+            ecdsa_encryption: false,
+            server_identity_key: String::default(),
+            noise: Arc::new(Mutex::new(HashMap::new())),
+            verify_responses: false,
         };
-        cs
+        Ok(cs)
+    }
+
+    /// Server identity key pinned in the config, to verify statements signed by the server
+    /// against. Statements must not be verified against a key they carry themselves.
+    pub fn pinned_identity_key(&self) -> Result<&String> {
+        match self.server_identity_key.is_empty() {
+            true => Err(CError::Generic(String::from(
+                "server_identity_key must be set to verify server signed statements",
+            ))),
+            false => Ok(&self.server_identity_key),
+        }
+    }
+
+    /// Base URL of the endpoint requests are sent to: the first endpoint that is up
+    pub fn endpoint(&self) -> String {
+        let endpoints = self.endpoints.get();
+        let i = (0..endpoints.len())
+            .find(|i| self.endpoints.is_up(*i))
+            .unwrap_or(0);
+        endpoints[i].url()
     }
 
     pub fn new_client(tor: Option<&Tor>) -> reqwest::blocking::Client {
@@ -300,7 +518,7 @@ pub mod tests {
     #[ignore]
     fn test_client_shim_tor_control() {
         let config = Config::get().expect("failed to get config");
-        let mut cs = ClientShim::from_config(&config);
+        let mut cs = ClientShim::from_config(&config).expect("invalid config");
         let _ = cs.new_tor_id().expect("failed to get new tor id");
     }
 
@@ -308,7 +526,7 @@ pub mod tests {
     #[ignore]
     fn test_tor_stats() {
         let config = Config::get().expect("failed to get config");
-        let mut cs = ClientShim::from_config(&config);
+        let mut cs = ClientShim::from_config(&config).expect("invalid config");
         let mut buffer = Vec::new();
         let mut sum: f32 = 0.0;
         let mut max: f32 = 0.0;
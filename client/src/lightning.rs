@@ -0,0 +1,250 @@
+//! Lightning
+//!
+//! Integration with a Lightning node for opening channels funded by statecoins.
+//!
+//! Channel funding follows the external funding flow of the node: the node negotiates the
+//! channel with the peer and returns the 2-of-2 funding output, the wallet builds and co-signs
+//! the funding transaction with the state entity, and the node is given the funding
+//! transaction to exchange the first commitment signatures before the transaction is broadcast.
+
+use super::Result;
+use crate::error::CError;
+
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{consensus, Address, Script, Transaction};
+use serde_json::json;
+use std::str::FromStr;
+
+/// Parameters of a channel to be opened with a peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelParams {
+    /// Node id of the peer
+    pub peer_id: String,
+    /// Channel capacity (satoshis)
+    pub capacity: u64,
+    /// Whether the channel is announced to the network
+    pub announce: bool,
+}
+
+/// Channel funding output negotiated by the node
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelFundingOutput {
+    pub funding_address: Address,
+    pub script_pubkey: Script,
+}
+
+impl ChannelFundingOutput {
+    /// Check the funding output is a P2WSH output paying to the funding address
+    pub fn verify(&self) -> Result<()> {
+        if self.funding_address.script_pubkey() != self.script_pubkey {
+            return Err(CError::Generic(String::from(
+                "Channel funding address does not match funding script.",
+            )));
+        }
+        if !self.script_pubkey.is_v0_p2wsh() {
+            return Err(CError::Generic(String::from(
+                "Channel funding output is not a P2WSH output.",
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Lightning node that opens channels funded by an external transaction
+pub trait LightningNode {
+    /// Negotiate a channel with a peer and return its funding output
+    fn fund_channel_start(&self, params: &ChannelParams) -> Result<ChannelFundingOutput>;
+
+    /// Hand the (unsigned) funding transaction to the node. Returns the channel id once the
+    /// peer has signed the first commitment transaction. The funding transaction must not be
+    /// broadcast before this returns.
+    fn fund_channel_complete(&self, peer_id: &String, funding_tx: &Transaction) -> Result<String>;
+
+    /// Abandon a channel negotiation
+    fn fund_channel_cancel(&self, peer_id: &String) -> Result<()>;
+}
+
+/// Core Lightning node accessed with the clnrest plugin
+pub struct ClnRestNode {
+    client: reqwest::blocking::Client,
+    endpoint: String,
+    rune: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FundChannelStartResponse {
+    funding_address: String,
+    scriptpubkey: String,
+}
+
+#[derive(Deserialize)]
+struct FundChannelCompleteResponse {
+    channel_id: String,
+    commitments_secured: bool,
+}
+
+impl ClnRestNode {
+    pub fn new(endpoint: String, rune: Option<String>) -> Self {
+        Self {
+            client: reqwest::blocking::Client::new(),
+            endpoint,
+            rune,
+        }
+    }
+
+    fn rpc<V>(&self, method: &str, params: serde_json::Value) -> Result<V>
+    where
+        V: serde::de::DeserializeOwned,
+    {
+        let mut b = self
+            .client
+            .post(&format!("{}/v1/{}", self.endpoint, method))
+            .json(&params);
+        if let Some(rune) = &self.rune {
+            b = b.header("Rune", rune.as_str());
+        }
+        let response = b.send()?;
+        if !response.status().is_success() {
+            return Err(CError::Generic(format!(
+                "Lightning node {} failed: {}",
+                method,
+                response.text()?
+            )));
+        }
+        Ok(response.json()?)
+    }
+}
+
+impl LightningNode for ClnRestNode {
+    fn fund_channel_start(&self, params: &ChannelParams) -> Result<ChannelFundingOutput> {
+        let res: FundChannelStartResponse = self.rpc(
+            "fundchannel_start",
+            json!({
+                "id": params.peer_id,
+                "amount": params.capacity,
+                "announce": params.announce,
+            }),
+        )?;
+        let script_pubkey = Script::from(
+            hex::decode(&res.scriptpubkey)
+                .map_err(|e| CError::Generic(format!("Invalid funding script: {}", e)))?,
+        );
+        Ok(ChannelFundingOutput {
+            funding_address: Address::from_str(&res.funding_address)?,
+            script_pubkey,
+        })
+    }
+
+    fn fund_channel_complete(&self, peer_id: &String, funding_tx: &Transaction) -> Result<String> {
+        let mut unsigned_tx = funding_tx.clone();
+        for input in unsigned_tx.input.iter_mut() {
+            input.witness = vec![];
+            input.script_sig = Script::default();
+        }
+        let psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx)
+            .map_err(|e| CError::Generic(format!("Failed to build funding PSBT: {}", e)))?;
+        let res: FundChannelCompleteResponse = self.rpc(
+            "fundchannel_complete",
+            json!({
+                "id": peer_id,
+                "psbt": base64::encode(&consensus::serialize(&psbt)),
+            }),
+        )?;
+        if !res.commitments_secured {
+            return Err(CError::Generic(String::from(
+                "Channel commitment transactions not secured.",
+            )));
+        }
+        Ok(res.channel_id)
+    }
+
+    fn fund_channel_cancel(&self, peer_id: &String) -> Result<()> {
+        let _: serde_json::Value = self.rpc("fundchannel_cancel", json!({ "id": peer_id }))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{mock, Matcher};
+
+    static FUNDING_ADDRESS: &str = "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7";
+    static FUNDING_SCRIPT: &str = "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262";
+    static PEER_ID: &str = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+
+    #[test]
+    fn test_funding_output_verify() {
+        let script_pubkey = Script::from(hex::decode(FUNDING_SCRIPT).unwrap());
+        let output = ChannelFundingOutput {
+            funding_address: Address::from_str(FUNDING_ADDRESS).unwrap(),
+            script_pubkey: script_pubkey.clone(),
+        };
+        assert!(output.verify().is_ok());
+
+        // address of another script
+        let output = ChannelFundingOutput {
+            funding_address: Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx")
+                .unwrap(),
+            script_pubkey,
+        };
+        assert!(output.verify().is_err());
+    }
+
+    #[test]
+    fn test_fund_channel_start() {
+        let _m = mock("POST", "/v1/fundchannel_start")
+            .match_header("Rune", "rune")
+            .match_body(Matcher::PartialJson(json!({
+                "id": PEER_ID,
+                "amount": 100000,
+            })))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "funding_address": FUNDING_ADDRESS,
+                    "scriptpubkey": FUNDING_SCRIPT,
+                    "mindepth": 1,
+                })
+                .to_string(),
+            )
+            .create();
+
+        let node = ClnRestNode::new(mockito::server_url(), Some(String::from("rune")));
+        let output = node
+            .fund_channel_start(&ChannelParams {
+                peer_id: String::from(PEER_ID),
+                capacity: 100000,
+                announce: false,
+            })
+            .unwrap();
+        assert_eq!(output.funding_address.to_string(), FUNDING_ADDRESS);
+        assert!(output.verify().is_ok());
+    }
+
+    #[test]
+    fn test_fund_channel_complete_not_secured() {
+        let _m = mock("POST", "/v1/fundchannel_complete")
+            .with_status(200)
+            .with_body(
+                json!({
+                    "channel_id": "00",
+                    "commitments_secured": false,
+                })
+                .to_string(),
+            )
+            .create();
+
+        let node = ClnRestNode::new(mockito::server_url(), None);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        };
+        match node.fund_channel_complete(&String::from(PEER_ID), &tx) {
+            Err(e) => assert!(e.to_string().contains("not secured")),
+            Ok(_) => assert!(false, "expected error"),
+        }
+    }
+}
@@ -7,10 +7,12 @@ extern crate uuid;
 
 use client_lib::{
     daemon::{query_wallet_daemon, DaemonRequest, DaemonResponse},
-    state_entity::transfer::TransferFinalizeData,
+    state_entity::{transfer::TransferFinalizeData, withdraw::WithdrawAllReport},
+    wallet::shared_key::{BackupExit, CoinExpiry},
 };
-use shared_lib::{util::transaction_deserialise, structs::{
-    PrepareSignTxMsg, StateChainDataAPI, StateEntityFeeInfoAPI, CoinValueInfo, RecoveryDataMsg
+use shared_lib::{util::{transaction_deserialise, backup_tx_timelock}, structs::{
+    PrepareSignTxMsg, StateChainDataAPI, StateEntityFeeInfoAPI, CoinValueInfo, RecoveryDataMsg,
+    SignedTransferReceipt, StateEntityParamsAPI, TransferMsg3,
 }};
 
 use bitcoin::util::key::PublicKey;
@@ -88,6 +90,24 @@ fn main() {
 
                 println!("\nBackup Tx: {}\n", txhex);
             }
+        } else if matches.is_present("expiry-report") {
+            let report: Vec<CoinExpiry> =
+                match query_wallet_daemon(DaemonRequest::GetExpiryReport).unwrap() {
+                    DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                    DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                    DaemonResponse::None => panic!("None value returned."),
+                };
+            println!("\nShared Key ID:\t\t\t\tLocktime:\tHeight:\tBlocks Remaining:");
+            for expiry in &report {
+                println!(
+                    "{}\t{}\t\t{}\t{}{}",
+                    expiry.shared_key_id,
+                    expiry.locktime,
+                    expiry.height,
+                    expiry.blocks_remaining,
+                    if expiry.alert { "\tTRANSFER OR WITHDRAW" } else { "" }
+                );
+            }
         } else if matches.is_present("list-unspent") {
             let (_, unspent_list): (Vec<bitcoin::Address>, Vec<Vec<GetListUnspentResponse>>) =
                 match query_wallet_daemon(DaemonRequest::GetListUnspent).unwrap() {
@@ -127,6 +147,61 @@ fn main() {
                     hex::encode(consensus::serialize(&tx_b))
                 );
             }
+        } else if matches.is_present("deposit-to") {
+            if let Some(matches) = matches.subcommand_matches("deposit-to") {
+                let amount = u64::from_str(matches.value_of("amount").unwrap()).unwrap();
+                let receiver_addr: String = matches.value_of("addr").unwrap().to_string();
+                let (_, statechain_id, funding_txid): (Uuid, Uuid, String) =
+                    match query_wallet_daemon(DaemonRequest::DepositTo(amount, receiver_addr))
+                        .unwrap()
+                    {
+                        DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                        DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                        DaemonResponse::None => panic!("None value returned."),
+                    };
+                println!(
+                    "\nDeposited {} satoshi's on behalf of recipient. \nState Chain ID: {}",
+                    amount, statechain_id
+                );
+                println!("\nFunding Txid: {}", funding_txid);
+            }
+        } else if matches.is_present("deposit-claim") {
+            if let Some(matches) = matches.subcommand_matches("deposit-claim") {
+                let addr: String = matches.value_of("addr").unwrap().to_string();
+                let statechain_ids: Vec<Uuid> =
+                    match query_wallet_daemon(DaemonRequest::DepositClaim(addr)).unwrap() {
+                        DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                        DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                        DaemonResponse::None => panic!("None value returned."),
+                    };
+                println!("\nClaimed {} StateChains.", statechain_ids.len());
+                for statechain_id in statechain_ids {
+                    println!("State Chain ID: {}", statechain_id);
+                }
+            }
+        } else if matches.is_present("deposit-resume") {
+            let resumed: Vec<(Uuid, Uuid)> =
+                match query_wallet_daemon(DaemonRequest::DepositResume).unwrap() {
+                    DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                    DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                    DaemonResponse::None => panic!("None value returned."),
+                };
+            println!("\nResumed {} deposits.", resumed.len());
+            for (shared_key_id, statechain_id) in resumed {
+                println!("Shared Key ID: {}	State Chain ID: {}", shared_key_id, statechain_id);
+            }
+        } else if matches.is_present("deposit-refund") {
+            if let Some(matches) = matches.subcommand_matches("deposit-refund") {
+                let shared_key_id = Uuid::from_str(matches.value_of("id").unwrap()).unwrap();
+                let refund_txid: String =
+                    match query_wallet_daemon(DaemonRequest::DepositRefund(shared_key_id)).unwrap() {
+                        DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                        DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                        DaemonResponse::None => panic!("None value returned."),
+                    };
+                println!("\nRefunded deposit. Shared Key ID: {}", shared_key_id);
+                println!("\nRefund Txid: {}", refund_txid);
+            }
         } else if matches.is_present("withdraw") {
             if let Some(matches) = matches.subcommand_matches("withdraw") {
                 let statechain_id = Uuid::from_str(matches.value_of("id").unwrap()).unwrap();
@@ -142,6 +217,61 @@ fn main() {
                 );
                 println!("\nWithdraw Txid: {}", txid);
             }
+        } else if matches.is_present("withdraw-all") {
+            if let Some(matches) = matches.subcommand_matches("withdraw-all") {
+                let address = matches.value_of("address").unwrap().to_string();
+                let fee_rate = u64::from_str(matches.value_of("fee-rate").unwrap()).unwrap();
+                let report: WithdrawAllReport =
+                    match query_wallet_daemon(DaemonRequest::WithdrawAll(address, fee_rate)).unwrap() {
+                        DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                        DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                        DaemonResponse::None => panic!("None value returned."),
+                    };
+                println!("\nWithdrawn {} satoshi's in {} transactions.", report.total_amount(), report.txs.len());
+                for tx in report.txs.iter() {
+                    println!("\nWithdraw Txid: {} ({} satoshi's)", tx.txid, tx.amount);
+                    for statechain_id in tx.statechain_ids.iter() {
+                        println!("\tStateChain ID: {}", statechain_id);
+                    }
+                }
+                for (statechain_id, e) in report.failed.iter() {
+                    println!("\nFailed to withdraw StateChain ID: {}: {}", statechain_id, e);
+                }
+            }
+        } else if matches.is_present("exit") {
+            let exits: Vec<(Uuid, BackupExit)> =
+                match query_wallet_daemon(DaemonRequest::Exit).unwrap() {
+                    DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                    DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                    DaemonResponse::None => panic!("None value returned."),
+                };
+            println!("
+Exiting {} StateChains. 
+
+Shared Key ID:				Backup Txid:								Locktime:	Broadcast:", exits.len());
+            for (shared_key_id, exit) in &exits {
+                println!("{}	{}	{}		{}", shared_key_id, exit.txid, exit.locktime, exit.broadcast);
+            }
+            for (_, exit) in exits.iter().filter(|(_, exit)| !exit.broadcast) {
+                println!("
+Backup Tx {} (broadcast once locktime {} expires): {}", exit.txid, exit.locktime, exit.tx_hex);
+            }
+        } else if matches.is_present("fund-channel") {
+            if let Some(matches) = matches.subcommand_matches("fund-channel") {
+                let statechain_id = Uuid::from_str(matches.value_of("id").unwrap()).unwrap();
+                let peer_id: String = matches.value_of("peer").unwrap().to_string();
+                let (txid, channel_id, capacity): (String, String, u64) =
+                    match query_wallet_daemon(DaemonRequest::FundChannel(statechain_id, peer_id)).unwrap() {
+                        DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                        DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                        DaemonResponse::None => panic!("None value returned."),
+                    };
+                println!(
+                    "\nOpened channel {} with capacity {} satoshi's. \nFrom StateChain ID: {}",
+                    channel_id, capacity, statechain_id
+                );
+                println!("\nFunding Txid: {}", txid);
+            }
         } else if matches.is_present("transfer-sender") {
             if let Some(matches) = matches.subcommand_matches("transfer-sender") {
                 let statechain_id = Uuid::from_str(matches.value_of("id").unwrap()).unwrap();
@@ -164,6 +294,26 @@ fn main() {
                     transfer_msg.to_string()
                 );
             }
+        } else if matches.is_present("transfer-receipt") {
+            if let Some(matches) = matches.subcommand_matches("transfer-receipt") {
+                let statechain_id = Uuid::from_str(matches.value_of("id").unwrap()).unwrap();
+                let receipts: Vec<SignedTransferReceipt> =
+                    match query_wallet_daemon(DaemonRequest::TransferReceipt(statechain_id)).unwrap() {
+                        DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                        DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                        DaemonResponse::None => panic!("None value returned."),
+                    };
+                for receipt in &receipts {
+                    println!(
+                        "\nTransferred StateChain ID: {} from proof key {} to proof key {} at root id {}.",
+                        statechain_id,
+                        receipt.receipt.prev_proof_key,
+                        receipt.receipt.new_proof_key,
+                        receipt.receipt.root_id
+                    );
+                    println!("\nReceipt: {}", serde_json::to_string(receipt).unwrap());
+                }
+            }
         } else if matches.is_present("transfer-receiver") {
             if let Some(matches) = matches.subcommand_matches("transfer-receiver") {
                 let transfer_msg: String = matches.value_of("message").unwrap().to_string();
@@ -189,7 +339,7 @@ fn main() {
 
                 println!(
                     "\nLocktime: {}",
-                    tx.lock_time
+                    backup_tx_timelock(&tx)
                 );
 
                 println!(
@@ -197,6 +347,62 @@ fn main() {
                     finalized_data.tx_backup_psm.tx_hex
                 );
             }
+        } else if matches.is_present("transfer-pending") {
+            let pending: Vec<TransferMsg3> =
+                match query_wallet_daemon(DaemonRequest::TransferPending).unwrap() {
+                    DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                    DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                    DaemonResponse::None => panic!("None value returned."),
+                };
+            println!("\n{} pending transfers.", pending.len());
+            for transfer_msg3 in pending {
+                println!(
+                    "State Chain ID: {}	Proof key: {}",
+                    transfer_msg3.statechain_id, transfer_msg3.rec_se_addr.proof_key
+                );
+            }
+        } else if matches.is_present("transfer-accept") {
+            if let Some(matches) = matches.subcommand_matches("transfer-accept") {
+                let statechain_id = Uuid::from_str(matches.value_of("id").unwrap()).unwrap();
+                let finalized_data: TransferFinalizeData =
+                    match query_wallet_daemon(DaemonRequest::TransferAccept(statechain_id)).unwrap() {
+                        DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                        DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                        DaemonResponse::None => panic!("None value returned."),
+                    };
+                println!(
+                    "\nTransfer complete for StateChain ID: {}.",
+                    finalized_data.statechain_id
+                );
+            }
+        } else if matches.is_present("transfer-reject") {
+            if let Some(matches) = matches.subcommand_matches("transfer-reject") {
+                let statechain_id = Uuid::from_str(matches.value_of("id").unwrap()).unwrap();
+                match query_wallet_daemon(DaemonRequest::TransferReject(statechain_id)).unwrap() {
+                    DaemonResponse::Value(_) => (),
+                    DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                    DaemonResponse::None => panic!("None value returned."),
+                };
+                println!("\nRejected transfer of StateChain ID: {}.", statechain_id);
+            }
+        } else if matches.is_present("transfer-reclaim") {
+            if let Some(matches) = matches.subcommand_matches("transfer-reclaim") {
+                let statechain_id = Uuid::from_str(matches.value_of("id").unwrap()).unwrap();
+                let reclaimed: bool =
+                    match query_wallet_daemon(DaemonRequest::TransferReclaim(statechain_id)).unwrap() {
+                        DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                        DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                        DaemonResponse::None => panic!("None value returned."),
+                    };
+                if reclaimed {
+                    println!(
+                        "\nTransfer of StateChain ID: {} was rejected. Transfer it to a new address of this wallet to replace its backup transaction.",
+                        statechain_id
+                    );
+                } else {
+                    println!("\nTransfer of StateChain ID: {} was not rejected.", statechain_id);
+                }
+            }
         } else if matches.is_present("transfer-any") {
             if let Some(matches) = matches.subcommand_matches("transfer-any") {
                 let receiver_addr: String = matches.value_of("addr").unwrap().to_string();
@@ -291,6 +497,17 @@ fn main() {
                     DaemonResponse::None => panic!("None value returned."),
                 };
             println!("State Entity fee info: \n\n{}", fee_info);
+        } else if matches.is_present("params-info") {
+            let params: StateEntityParamsAPI =
+                match query_wallet_daemon(DaemonRequest::GetParams).unwrap() {
+                    DaemonResponse::Value(val) => serde_json::from_str(&val).unwrap(),
+                    DaemonResponse::Error(e) => panic!("{}", e.to_string()),
+                    DaemonResponse::None => panic!("None value returned."),
+                };
+            println!(
+                "State Entity protocol parameters: \n\n{}",
+                serde_json::to_string_pretty(&params).unwrap()
+            );
         } else if matches.is_present("recover-statecoin") {
             if let Some(matches) = matches.subcommand_matches("recover-statecoin") {
                 let publickey_hex = matches.value_of("pk").unwrap();
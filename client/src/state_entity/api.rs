@@ -3,25 +3,44 @@
 //! API calls availble for Client to State Entity
 
 use super::super::Result;
+use crate::error::CError;
 use shared_lib::structs::{
-    SmtProofMsgAPI, StateChainDataAPI, StateEntityFeeInfoAPI, 
+    SmtProofMsgAPI, SmtProofsMsgAPI, SmtProofsAPI, StateChainDataAPI, StateChainUpdatesAPI, StateEntityFeeInfoAPI,
+    StateEntityParamsAPI,
     TransferBatchDataAPI, RecoveryDataMsg, RecoveryRequest, 
-    CoinValueInfo, StateCoinDataAPI, TransferFinalizeData
+    CoinValueInfo, StateCoinDataAPI, TransferFinalizeData, BackupCPFPMsg,
+    ReserveProofMsg, SignedReserveProofReport, SignedStateChainExport, SignedDeletionReceipt,
+    SignedTransferReceipt, PunishmentStandingAPI, UserSessionsChallenge, UserSessionsRequest,
+    UserSessionsAPI, OwnershipChallenge, OwnershipProofMsg, SignedOwnershipConfirmation,
+    SaleAPI, SaleInitMsg, SaleRevealMsg, InheritanceAPI, InheritanceMsg, InheritanceOwnerMsg,
+    SecondFactorAPI, SecondFactorMsg, TransferRejectAPI, VaultAPI, VaultCancelMsg, VaultMsg,
+    WithdrawAccelerateAPI, WithdrawAccelerateMsg, RotateMsg
 };
+use shared_lib::smt_proof::{EncodedProof, ProofFormat};
+use shared_lib::state_chain::StateChainSig;
 use shared_lib::Root;
 
-use super::super::utilities::requests;
-use crate::ClientShim;
+use super::super::utilities::requests::{self, Retry};
+use super::util::verify_statechain_smt;
+use crate::{ClientShim, RequestClass};
 
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 use monotree::Proof;
 use uuid::Uuid;
 use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Get state chain fee
 pub fn get_statechain_fee_info(client_shim: &ClientShim) -> Result<StateEntityFeeInfoAPI> {
     requests::get(client_shim, &format!("info/fee"))
 }
 
+/// Get state entity protocol parameters
+pub fn get_statechain_params(client_shim: &ClientShim) -> Result<StateEntityParamsAPI> {
+    requests::get(client_shim, &format!("info/params"))
+}
+
 /// Get state chain fee
 pub fn get_swaps_group_info(client_shim: &ClientShim) -> Result<HashMap<String,u64>> {
     requests::get(client_shim, &format!("/swap/groupinfo"))
@@ -40,6 +59,38 @@ pub fn get_statechain(
     requests::get(client_shim, &format!("info/statechain/{}", statechain_id))
 }
 
+/// Get state entity signed export of the full statechain history by ID
+pub fn get_statechain_export(
+    client_shim: &ClientShim,
+    statechain_id: &Uuid,
+) -> Result<SignedStateChainExport> {
+    requests::get(client_shim, &format!("info/statechain/{}/export", statechain_id))
+}
+
+/// Get state entity signed receipts for the key shares deleted after each transfer of a statechain
+pub fn get_deletion_receipts(
+    client_shim: &ClientShim,
+    statechain_id: &Uuid,
+) -> Result<Vec<SignedDeletionReceipt>> {
+    requests::get(client_shim, &format!("info/statechain/{}/deletion-proofs", statechain_id))
+}
+
+/// Get state entity signed receipts of the completed transfers of a statechain
+pub fn get_transfer_receipts(
+    client_shim: &ClientShim,
+    statechain_id: &Uuid,
+) -> Result<Vec<SignedTransferReceipt>> {
+    requests::get(client_shim, &format!("info/statechain/{}/transfer-receipts", statechain_id))
+}
+
+/// Get the punishment standing of a statecoin and its owner proof key
+pub fn get_punishment_standing(
+    client_shim: &ClientShim,
+    statechain_id: &Uuid,
+) -> Result<PunishmentStandingAPI> {
+    requests::get(client_shim, &format!("info/standing/{}", statechain_id))
+}
+
 /// Get statecoin (statechain tip) by statechain ID
 pub fn get_statecoin(
     client_shim: &ClientShim,
@@ -57,7 +108,7 @@ pub fn get_recovery_data(
             key: pubkey_hex.to_string(),
             sig: "".to_string(),
         });
-    requests::postb(client_shim, &format!("info/recover/"), recovery_request)
+    requests::postb_with(client_shim, &format!("info/recover/"), recovery_request, Retry::Safe, RequestClass::Poll)
 }
 
 /// Get recovery data by vec of pubkeys
@@ -74,7 +125,7 @@ pub fn get_recovery_data_vec(
         });
     }
 
-    requests::postb(client_shim, &format!("info/recover/"), recovery_request)
+    requests::postb_with(client_shim, &format!("info/recover/"), recovery_request, Retry::Safe, RequestClass::Poll)
 }
 
 pub fn get_sc_transfer_finalize_data(
@@ -94,7 +145,7 @@ pub fn get_confirmed_smt_root(client_shim: &ClientShim) -> Result<Option<Root>>
     requests::get(&client_shim, &format!("info/confirmed_root"))
 }
 
-/// Get state chain inclusion proof
+/// Get state chain inclusion proof. The proof is requested in the compact encoding.
 pub fn get_smt_proof(
     client_shim: &ClientShim,
     root: &Root,
@@ -103,8 +154,80 @@ pub fn get_smt_proof(
     let smt_proof_msg = SmtProofMsgAPI {
         root: root.clone(),
         funding_txid: funding_txid.clone(),
+        proof_format: ProofFormat::Compact,
     };
-    requests::postb(&client_shim, &format!("info/proof"), smt_proof_msg)
+    let proof: Option<EncodedProof> =
+        requests::postb_with(&client_shim, &format!("info/proof"), smt_proof_msg, Retry::Safe, RequestClass::Poll)?;
+    match proof {
+        Some(proof) => Ok(Some(proof.decode()?)),
+        None => Ok(None),
+    }
+}
+
+/// Get state chain inclusion proofs for a list of funding txids against the current root. The
+/// proofs are requested in the compact encoding: decode them with SmtProofsAPI::decode_proofs.
+pub fn get_smt_proofs(
+    client_shim: &ClientShim,
+    funding_txids: &[String],
+) -> Result<SmtProofsAPI> {
+    let smt_proofs_msg = SmtProofsMsgAPI {
+        funding_txids: funding_txids.to_vec(),
+        proof_format: ProofFormat::Compact,
+    };
+    requests::postb_with(&client_shim, &format!("info/proofs"), smt_proofs_msg, Retry::Safe, RequestClass::Poll)
+}
+
+/// Time in seconds to wait for an SMT update to be published in a root
+const SMT_PUBLISH_TIMEOUT: u64 = 600;
+/// Interval in seconds between requests for an SMT update that is not yet published
+const SMT_PUBLISH_POLL_INTERVAL: u64 = 2;
+
+/// Get the current root and an inclusion proof of proof_key and amount for funding_txid
+/// against it. State entities may queue SMT updates between roots, so the proof is requested
+/// until the update is published.
+pub fn get_smt_inclusion_proof(
+    client_shim: &ClientShim,
+    funding_txid: &String,
+    proof_key: &String,
+    amount: u64,
+) -> Result<(Root, Option<Proof>)> {
+    let start = Instant::now();
+    loop {
+        match get_smt_proofs(client_shim, &[funding_txid.clone()]) {
+            Ok(smt_proofs) => {
+                let proof = smt_proofs.decode_proofs()?.into_iter().next().and_then(|(_, p)| p);
+                if !verify_statechain_smt(
+                    &Some(smt_proofs.root.hash()),
+                    funding_txid,
+                    proof_key,
+                    amount,
+                    &proof,
+                ) {
+                    return Err(CError::StateEntityError(format!(
+                        "Proof key {} not included in SMT root for funding txid {}",
+                        proof_key, funding_txid
+                    )));
+                }
+                return Ok((smt_proofs.root, proof));
+            }
+            Err(CError::StateEntityError(e)) if e.contains("SMT update pending") => {
+                if start.elapsed() >= Duration::from_secs(SMT_PUBLISH_TIMEOUT) {
+                    return Err(CError::StateEntityError(e));
+                }
+                debug!("SMT update of funding txid {} not yet published", funding_txid);
+            }
+            Err(e) => return Err(e),
+        }
+        thread::sleep(Duration::from_secs(SMT_PUBLISH_POLL_INTERVAL));
+    }
+}
+
+/// Get ids of statechains modified since a checkpoint (root id or timestamp)
+pub fn get_statechain_updates(
+    client_shim: &ClientShim,
+    since: &String,
+) -> Result<StateChainUpdatesAPI> {
+    requests::get(client_shim, &format!("info/updates?since={}", since))
 }
 
 /// Get transaction batch session status
@@ -115,6 +238,163 @@ pub fn get_transfer_batch_status(
     requests::get(client_shim, &format!("info/transfer-batch/{}", batch_id))
 }
 
+/// Submit signed backup tx CPFP child for broadcast by the state entity watcher
+pub fn submit_backup_cpfp(client_shim: &ClientShim, cpfp_msg: &BackupCPFPMsg) -> Result<()> {
+    requests::postb(client_shim, &format!("info/backup/cpfp"), cpfp_msg)
+}
+
+/// Request a proof of reserves report for a set of signed statecoins
+pub fn get_reserve_proof(
+    client_shim: &ClientShim,
+    reserve_proof_msg: &ReserveProofMsg,
+) -> Result<SignedReserveProofReport> {
+    requests::postb(client_shim, &format!("info/reserves"), reserve_proof_msg)
+}
+
+/// Get a single-use challenge for a user sessions request
+pub fn get_user_sessions_challenge(client_shim: &ClientShim) -> Result<UserSessionsChallenge> {
+    requests::get(client_shim, "info/usersessions/challenge")
+}
+
+/// Get a page of the user sessions the state entity holds for a set of proof keys, optionally
+/// filtered by session state. The request is signed with each proof key over a new challenge.
+pub fn get_user_sessions(
+    client_shim: &ClientShim,
+    proof_key_privs: &[SecretKey],
+    state: Option<String>,
+    offset: u64,
+    limit: Option<u64>,
+) -> Result<UserSessionsAPI> {
+    let challenge = get_user_sessions_challenge(client_shim)?.challenge;
+    let secp = Secp256k1::new();
+    let mut sigs = vec![];
+    for proof_key_priv in proof_key_privs {
+        let proof_key = PublicKey::from_secret_key(&secp, proof_key_priv).to_string();
+        sigs.push(StateChainSig::new_user_sessions_sig(proof_key_priv, &challenge, &proof_key)?);
+    }
+    let user_sessions_msg = UserSessionsRequest {
+        challenge,
+        sigs,
+        state,
+        offset,
+        limit,
+    };
+    requests::postb(client_shim, "info/usersessions", user_sessions_msg)
+}
+
+/// Get a single-use nonce for the owner of a statecoin to prove ownership over
+pub fn get_ownership_challenge(client_shim: &ClientShim) -> Result<OwnershipChallenge> {
+    requests::get(client_shim, "info/ownership-challenge")
+}
+
+/// Submit a proof key signature over an ownership challenge nonce for a signed confirmation
+/// of current ownership of the statecoin
+pub fn prove_ownership(
+    client_shim: &ClientShim,
+    ownership_proof_msg: &OwnershipProofMsg,
+) -> Result<SignedOwnershipConfirmation> {
+    requests::postb(client_shim, "info/ownership-challenge", ownership_proof_msg)
+}
+
+/// Get the build manifest of the state entity server, signed by its identity key. Use
+/// util::verify_server_build to check it against the pinned server identity key.
+pub fn get_build_manifest(client_shim: &ClientShim) -> Result<SignedBuildManifest> {
+    requests::get(client_shim, "info/build")
+}
+
+/// Get the last transfer of a statechain rejected by its receiver
+pub fn get_transfer_reject(
+    client_shim: &ClientShim,
+    statechain_id: &Uuid,
+) -> Result<Option<TransferRejectAPI>> {
+    requests::get(client_shim, &format!("transfer/reject/{}", statechain_id))
+}
+
+/// Open an escrowed sale of a statecoin. Its next transfer is held until the preimage of the
+/// payment hash is revealed.
+pub fn sale_init(client_shim: &ClientShim, sale_init_msg: &SaleInitMsg) -> Result<SaleAPI> {
+    requests::postb(client_shim, "transfer/sale/init", sale_init_msg)
+}
+
+/// Get the escrowed sale of a statechain
+pub fn sale_get(client_shim: &ClientShim, statechain_id: &Uuid) -> Result<SaleAPI> {
+    requests::get(client_shim, &format!("transfer/sale/{}", statechain_id))
+}
+
+/// Reveal the preimage of the payment hash of a sale, finalizing the held transfer
+pub fn sale_reveal(client_shim: &ClientShim, sale_reveal_msg: &SaleRevealMsg) -> Result<SaleAPI> {
+    requests::postb(client_shim, "transfer/sale/reveal", sale_reveal_msg)
+}
+
+/// Designate the receiver of the pending transfer of a statecoin as its beneficiary after a
+/// period of owner inactivity
+pub fn inheritance_designate(
+    client_shim: &ClientShim,
+    inheritance_msg: &InheritanceMsg,
+) -> Result<InheritanceAPI> {
+    requests::postb(client_shim, "transfer/inheritance/designate", inheritance_msg)
+}
+
+/// Owner check in, restarting the inactivity period of a beneficiary designation
+pub fn inheritance_checkin(
+    client_shim: &ClientShim,
+    owner_msg: &InheritanceOwnerMsg,
+) -> Result<InheritanceAPI> {
+    requests::postb(client_shim, "transfer/inheritance/checkin", owner_msg)
+}
+
+/// Revoke the beneficiary designation of a statecoin
+pub fn inheritance_revoke(client_shim: &ClientShim, owner_msg: &InheritanceOwnerMsg) -> Result<()> {
+    requests::postb(client_shim, "transfer/inheritance/revoke", owner_msg)
+}
+
+/// Get the beneficiary designation of a statechain
+pub fn inheritance_get(client_shim: &ClientShim, statechain_id: &Uuid) -> Result<InheritanceAPI> {
+    requests::get(client_shim, &format!("transfer/inheritance/{}", statechain_id))
+}
+
+/// Register, replace or remove the second factor key of a statecoin
+pub fn second_factor_register(
+    client_shim: &ClientShim,
+    second_factor_msg: &SecondFactorMsg,
+) -> Result<SecondFactorAPI> {
+    requests::postb(client_shim, "second-factor/register", second_factor_msg)
+}
+
+/// Get the second factor key registration of a statechain
+pub fn second_factor_get(client_shim: &ClientShim, statechain_id: &Uuid) -> Result<SecondFactorAPI> {
+    requests::get(client_shim, &format!("second-factor/{}", statechain_id))
+}
+
+/// Set the vault delay of a statecoin, or disable vault mode with a delay of 0
+pub fn vault_set(client_shim: &ClientShim, vault_msg: &VaultMsg) -> Result<VaultAPI> {
+    requests::postb(client_shim, "vault/set", vault_msg)
+}
+
+/// Cancel the transfer, withdrawal or migration in progress of a statecoin in vault mode
+pub fn vault_cancel(client_shim: &ClientShim, cancel_msg: &VaultCancelMsg) -> Result<VaultAPI> {
+    requests::postb(client_shim, "vault/cancel", cancel_msg)
+}
+
+/// Get the vault policy of a statechain
+pub fn vault_get(client_shim: &ClientShim, statechain_id: &Uuid) -> Result<VaultAPI> {
+    requests::get(client_shim, &format!("vault/{}", statechain_id))
+}
+
+/// Rotate the proof key of a statecoin to a new proof key of the owner
+pub fn rotate_proof_key(client_shim: &ClientShim, rotate_msg: &RotateMsg) -> Result<()> {
+    requests::postb(client_shim, "rotate", rotate_msg)
+}
+
+/// Request that the state entity accelerate a withdraw tx stuck in the mempool by CPFP of its
+/// fee output
+pub fn withdraw_accelerate(
+    client_shim: &ClientShim,
+    accelerate_msg: &WithdrawAccelerateMsg,
+) -> Result<WithdrawAccelerateAPI> {
+    requests::postb(client_shim, "withdraw/accelerate", accelerate_msg)
+}
+
 /// Reset the state entity's database and in-memory data
 pub fn reset_data(client_shim: &ClientShim) -> Result<()> {
     requests::get(client_shim, "test/reset-db")
@@ -129,6 +409,7 @@ pub fn reset_inram_data(client_shim: &ClientShim) -> Result<()> {
 mod tests {
     use super::*;
     use mockito::mock;
+    use shared_lib::structs::SaleStatus;
 
     fn mock_url() -> String {
         String::from(&mockito::server_url())
@@ -148,5 +429,107 @@ mod tests {
         //let test_string: String = requests::get(&client_shim, &format!("/")).expect("failed to get test string via tor");
         //assert_eq!(test_string, "test string".to_string());
     }
+
+    #[test]
+    fn test_get_user_sessions() {
+        let client_shim = ClientShim::new(mock_url(), None, None);
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&Secp256k1::new(), &proof_key_priv).to_string();
+        let shared_key_id = Uuid::new_v4();
+
+        let _m_challenge = mock("GET", "/info/usersessions/challenge")
+            .with_header("Content-Type", "application/json")
+            .with_body("{\"challenge\":\"abcd\",\"expires\":\"2021-01-01T00:00:00\"}")
+            .create();
+        let _m_sessions = mock("POST", "/info/usersessions")
+            .match_body(mockito::Matcher::PartialJsonString(String::from(
+                "{\"challenge\":\"abcd\",\"offset\":10}",
+            )))
+            .with_header("Content-Type", "application/json")
+            .with_body(format!(
+                "{{\"sessions\":[{{\"proof_key\":\"{}\",\"shared_key_id\":\"{}\",\"statechain_id\":null,\"state\":\"DepositInit\"}}],\"total\":11,\"offset\":10}}",
+                proof_key, shared_key_id
+            ))
+            .create();
+
+        let sessions = get_user_sessions(&client_shim, &[proof_key_priv], None, 10, None).unwrap();
+        assert_eq!(sessions.total, 11);
+        assert_eq!(sessions.sessions[0].shared_key_id, shared_key_id);
+        assert_eq!(sessions.sessions[0].state, Some(String::from("DepositInit")));
+    }
+
+    #[test]
+    fn test_prove_ownership() {
+        let client_shim = ClientShim::new(mock_url(), None, None);
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let statechain_id = Uuid::new_v4();
+
+        let _m_challenge = mock("GET", "/info/ownership-challenge")
+            .with_header("Content-Type", "application/json")
+            .with_body("{\"nonce\":\"abcd\",\"expires\":\"2021-01-01T00:00:00\"}")
+            .create();
+        let nonce = get_ownership_challenge(&client_shim).unwrap().nonce;
+        assert_eq!(nonce, "abcd");
+
+        let _m_proof = mock("POST", "/info/ownership-challenge")
+            .match_body(mockito::Matcher::PartialJsonString(format!(
+                "{{\"statechain_id\":\"{}\",\"nonce\":\"abcd\"}}",
+                statechain_id
+            )))
+            .with_header("Content-Type", "application/json")
+            .with_body(format!(
+                "{{\"confirmation\":{{\"statechain_id\":\"{}\",\"nonce\":\"abcd\",\"proof_key\":\"pk\",\"amount\":1000,\"confirmed_at\":\"2021-01-01T00:00:00\",\"server_pubkey\":\"spk\"}},\"sig\":\"sig\"}}",
+                statechain_id
+            ))
+            .create();
+        let msg = OwnershipProofMsg {
+            statechain_id,
+            nonce: nonce.clone(),
+            statechain_sig: StateChainSig::new_ownership_sig(&proof_key_priv, &nonce, &statechain_id).unwrap(),
+        };
+        let signed = prove_ownership(&client_shim, &msg).unwrap();
+        assert_eq!(signed.confirmation.statechain_id, statechain_id);
+        assert_eq!(signed.confirmation.amount, 1000);
+    }
+
+    #[test]
+    fn test_sale() {
+        let client_shim = ClientShim::new(mock_url(), None, None);
+        let statechain_id = Uuid::new_v4();
+        let payment_hash = "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925";
+        let sale_body = |status: &str| format!(
+            "{{\"statechain_id\":\"{}\",\"payment_hash\":\"{}\",\"expires\":\"2021-01-01T00:00:00\",\"status\":\"{}\",\"buyer_proof_key\":null,\"preimage\":null}}",
+            statechain_id, payment_hash, status
+        );
+
+        let _m_init = mock("POST", "/transfer/sale/init")
+            .match_body(mockito::Matcher::PartialJsonString(format!(
+                "{{\"payment_hash\":\"{}\",\"lifetime\":3600}}",
+                payment_hash
+            )))
+            .with_header("Content-Type", "application/json")
+            .with_body(sale_body("Open"))
+            .create();
+        let sale = sale_init(
+            &client_shim,
+            &SaleInitMsg {
+                shared_key_id: Uuid::new_v4(),
+                payment_hash: payment_hash.to_string(),
+                lifetime: 3600,
+            },
+        )
+        .unwrap();
+        assert_eq!(sale.statechain_id, statechain_id);
+        assert_eq!(sale.status, SaleStatus::Open);
+
+        let _m_get = mock("GET", format!("/transfer/sale/{}", statechain_id).as_str())
+            .with_header("Content-Type", "application/json")
+            .with_body(sale_body("Held"))
+            .create();
+        assert_eq!(
+            sale_get(&client_shim, &statechain_id).unwrap().status,
+            SaleStatus::Held
+        );
+    }
 }
 
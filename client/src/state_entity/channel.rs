@@ -0,0 +1,98 @@
+//! Channel
+//!
+//! Open a Lightning channel funded by the withdrawal of statecoins
+
+// fund_channel() messages:
+// 0. calculate the channel capacity: the withdraw output value of the statecoins
+// 1. Lightning node negotiates the channel with the peer and returns the 2-of-2 funding output
+// 2. Sign state chain to the funding address, request withdrawal and co-sign the funding tx
+// 3. Lightning node receives the funding tx and secures the first commitment txs
+// 4. Confirm withdrawal and broadcast the funding tx
+
+use super::super::Result;
+extern crate shared_lib;
+use shared_lib::util::tx_withdraw_amounts;
+
+use super::api::{get_statechain, get_statechain_fee_info};
+use super::withdraw::{batch_withdraw_confirm, batch_withdraw_init_to_address};
+use crate::error::CError;
+use crate::lightning::{ChannelFundingOutput, ChannelParams, LightningNode};
+use crate::wallet::wallet::Wallet;
+
+use uuid::Uuid;
+
+/// Open a channel with a peer funded by withdrawing statecoins.
+/// Returns the funding txid, the channel id and the channel capacity.
+pub fn fund_channel<N: LightningNode>(
+    wallet: &mut Wallet,
+    statechain_ids: &Vec<Uuid>,
+    tx_fee: &u64,
+    node: &N,
+    peer_id: &String,
+    announce: bool,
+) -> Result<(String, String, u64)> {
+    let mut sc_infos = vec![];
+    for statechain_id in statechain_ids {
+        sc_infos.push(get_statechain(&wallet.client_shim, statechain_id)?);
+    }
+    let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
+    let (capacity, _) = tx_withdraw_amounts(&sc_infos, &se_fee_info, tx_fee)?;
+
+    let params = ChannelParams {
+        peer_id: peer_id.clone(),
+        capacity,
+        announce,
+    };
+    let funding_output = node.fund_channel_start(&params)?;
+
+    let (shared_key_ids, tx_funding, channel_id) =
+        match co_sign_funding_tx(wallet, statechain_ids, tx_fee, node, &params, &funding_output) {
+            Ok(res) => res,
+            Err(e) => {
+                if let Err(cancel_err) = node.fund_channel_cancel(peer_id) {
+                    warn!("Channel: failed to cancel channel with {}: {}", peer_id, cancel_err);
+                }
+                return Err(e);
+            }
+        };
+
+    let txid = batch_withdraw_confirm(
+        wallet,
+        &shared_key_ids,
+        &funding_output.funding_address,
+        &tx_funding,
+    )?;
+    debug!("Channel: funding tx broadcast. txid: {} channel id: {}", txid, channel_id);
+
+    Ok((txid, channel_id, capacity))
+}
+
+fn co_sign_funding_tx<N: LightningNode>(
+    wallet: &mut Wallet,
+    statechain_ids: &Vec<Uuid>,
+    tx_fee: &u64,
+    node: &N,
+    params: &ChannelParams,
+    funding_output: &ChannelFundingOutput,
+) -> Result<(Vec<Uuid>, bitcoin::Transaction, String)> {
+    funding_output.verify()?;
+
+    let (shared_key_ids, tx_funding, _) = batch_withdraw_init_to_address(
+        wallet,
+        statechain_ids,
+        tx_fee,
+        &funding_output.funding_address,
+    )?;
+
+    // The statecoin values may have changed since the capacity was calculated
+    if tx_funding.output[0].script_pubkey != funding_output.script_pubkey
+        || tx_funding.output[0].value != params.capacity
+    {
+        return Err(CError::Generic(String::from(
+            "Channel funding output does not match channel parameters.",
+        )));
+    }
+
+    let channel_id = node.fund_channel_complete(&params.peer_id, &tx_funding)?;
+    Ok((shared_key_ids, tx_funding, channel_id))
+}
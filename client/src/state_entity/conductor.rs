@@ -10,8 +10,8 @@ use crate::state_entity::{
     transfer,
 };
 use crate::wallet::wallet::Wallet;
-use crate::{utilities::requests, ClientShim};
-use shared_lib::{state_chain::StateChainSig, structs::*};
+use crate::{utilities::requests::{self, Retry}, ClientShim, RequestClass};
+use shared_lib::{state_chain::{verify_swap_report, StateChainSig}, structs::*};
 
 use shared_lib::blinded_token::{
     BSTRequestorData, BlindedSpendSignature, BlindedSpentTokenMessage,
@@ -60,11 +60,59 @@ pub fn swap_poll_utxo(client_shim: &ClientShim, statechain_id: &Uuid) -> Result<
 }
 
 pub fn swap_poll_swap(client_shim: &ClientShim, swap_id: &Uuid) -> Result<Option<SwapStatus>> {
-    requests::postb(&client_shim, &String::from("swap/poll/swap"), &SwapID{id: Some(*swap_id)})
+    requests::postb_with(&client_shim, &String::from("swap/poll/swap"), &SwapID{id: Some(*swap_id)}, Retry::Safe, RequestClass::Poll)
 }
 
 pub fn swap_info(client_shim: &ClientShim, swap_id: &Uuid) -> Result<Option<SwapInfo>> {
-    requests::postb(&client_shim, &String::from("swap/info"), &SwapID{id: Some(*swap_id)})
+    requests::postb_with(&client_shim, &String::from("swap/info"), &SwapID{id: Some(*swap_id)}, Retry::Safe, RequestClass::Poll)
+}
+
+/// Get the swap fee and the number of coins registered for and taking part in swaps
+pub fn swap_stats(client_shim: &ClientShim) -> Result<SwapStatsAPI> {
+    requests::get(client_shim, "swap/stats")
+}
+
+/// Get the report of a completed swap. The conductor signature is checked if its identity key
+/// is known.
+pub fn swap_report(client_shim: &ClientShim, swap_id: &Uuid) -> Result<SignedSwapReport> {
+    let signed: SignedSwapReport = requests::get(client_shim, &format!("swap/report/{}", swap_id))?;
+    if &signed.report.swap_id != swap_id {
+        return Err(CError::SwapError(format!(
+            "swap report is for swap {}, expected {}",
+            signed.report.swap_id, swap_id
+        )));
+    }
+    if !client_shim.server_identity_key.is_empty() {
+        verify_swap_report(&signed, &client_shim.server_identity_key)?;
+    }
+    Ok(signed)
+}
+
+/// Check the fee in a swap token against the fee advertised by the conductor before
+/// signing it. In deposit mode the fee deposit txid must be given.
+pub fn swap_check_fee(
+    client_shim: &ClientShim,
+    swap_token: &SwapToken,
+    fee_txid: &Option<String>,
+) -> Result<()> {
+    if swap_token.fee == 0 {
+        return Ok(());
+    }
+    let stats = swap_stats(client_shim)?;
+    if swap_token.fee > stats.fee {
+        return Err(CError::SwapError(format!(
+            "swap token fee {} exceeds the advertised swap fee {}",
+            swap_token.fee, stats.fee
+        )));
+    }
+    if stats.fee_payment == SwapFeePayment::Deposit && fee_txid.is_none() {
+        return Err(CError::SwapError(format!(
+            "swap fee of {} must be paid by a fee deposit to {}",
+            swap_token.fee,
+            stats.fee_address
+        )));
+    }
+    Ok(())
 }
 
 pub fn swap_first_message(
@@ -73,8 +121,10 @@ pub fn swap_first_message(
     statechain_id: &Uuid,
     transfer_batch_sig: &StateChainSig,
     new_address: &SCEAddress,
+    fee_txid: &Option<String>,
 ) -> Result<BSTRequestorData> {
     let swap_token = swap_info.swap_token.clone();
+    swap_check_fee(&wallet.conductor_shim, &swap_token, fee_txid)?;
 
     let statecoin_data: StateCoinDataAPI = get_statecoin(&wallet.client_shim, &statechain_id)?;
     
@@ -107,6 +157,7 @@ pub fn swap_first_message(
             transfer_batch_sig: transfer_batch_sig.to_owned(),
             address: new_address.to_owned(),
             bst_e_prime: my_bst_data.get_e_prime().clone(),
+            fee_txid: fee_txid.clone(),
         },
     )?;
     Ok(my_bst_data)
@@ -186,10 +237,22 @@ fn do_transfer_receiver(
 }
 
 pub fn do_swap(
+    wallet: &mut Wallet,
+    statechain_id: &Uuid,
+    swap_size: &u64,
+    with_tor: bool,
+) -> Result<SCEAddress> {
+    do_swap_with_fee(wallet, statechain_id, swap_size, with_tor, None)
+}
+
+/// Swap a statecoin, with the txid of the fee deposit if the conductor takes the swap fee
+/// by deposit
+pub fn do_swap_with_fee(
     mut wallet: &mut Wallet,
     statechain_id: &Uuid,
     swap_size: &u64,
     with_tor: bool,
+    fee_txid: Option<String>,
 ) -> Result<SCEAddress> {
     if with_tor & (!wallet.client_shim.has_tor()  |! wallet.conductor_shim.has_tor()){
         return Err(CError::SwapError("tor not enabled".to_string()));
@@ -224,12 +287,16 @@ pub fn do_swap(
     }
 
     let proof_key = wallet.se_proof_keys.get_new_key()?;
+    let enc_key = wallet.issue_receive_key(&proof_key);
+    // The swap transfer to this address must be decryptable if the wallet restarts
+    wallet.save();
 
     let proof_key = bitcoin::secp256k1::PublicKey::from_slice(&proof_key.to_bytes().as_slice())?;
 
     let address = SCEAddress {
         tx_backup_addr: None,
         proof_key,
+        enc_key: Some(enc_key.key),
     };
 
     let transfer_batch_sig = transfer::transfer_batch_sign(wallet, &statechain_id, &swap_id)?;
@@ -240,6 +307,7 @@ pub fn do_swap(
         &statechain_id,
         &transfer_batch_sig,
         &address,
+        &fee_txid,
     )?;
 
     //Wait until swap is in phase4 then transfer sender
@@ -325,5 +393,14 @@ pub fn do_swap(
     }
     transfer::transfer_receiver_finalize(&mut wallet, transfer_finalized_data)?;
 
+    // The swap is complete without the report, which the conductor may not serve yet
+    match swap_report(&wallet.conductor_shim, &swap_id) {
+        Ok(report) => {
+            wallet.add_swap_report(report);
+            wallet.save();
+        }
+        Err(e) => warn!("Swap ID {}: swap report not stored: {}", swap_id, e),
+    }
+
     Ok(address)
 }
@@ -4,7 +4,8 @@
 
 // update_proofs():
 // 0. Initiate session - generate ID and perform authorisation
-// 1. For each shared key in the wallet, update the proof if previously unconfirmed and a mainstay-confirmed proof is available
+// 1. For each shared key in the wallet, update the proof if previously unconfirmed and a mainstay-confirmed proof is available,
+//    taking it from the wallet proof cache if already verified against the confirmed root
 // 2. Return the number of successful/failed update attempts
 
 use super::super::Result;
@@ -40,6 +41,7 @@ pub fn confirm_proofs(wallet: &mut Wallet) -> Result<Vec<Uuid>> {
                     None => failed.push(key.id),
                 };
             }
+            wallet.proof_cache.record_root(&root)?;
             root
         }
         None => {
@@ -51,18 +53,29 @@ pub fn confirm_proofs(wallet: &mut Wallet) -> Result<Vec<Uuid>> {
     };
 
     for key in &mut keys_to_update {
-        match get_smt_proof(&shim, &root, &key.funding_txid) {
+        let proof = match wallet.proof_cache.get_proof(&root, &key.funding_txid) {
+            Some(proof) => Ok(Some(proof.clone())),
+            None => get_smt_proof(&shim, &root, &key.funding_txid),
+        };
+        match proof {
             Ok(proof) => {
                 match &key.proof_key {
                     Some(proof_key) => match verify_statechain_smt(
                         &Some(root.hash()),
+                        &key.funding_txid,
                         &proof_key.to_string(),
+                        key.value,
                         &proof,
                     ) {
                         false => failed.push(key.id),
                         true => {
                             // Update proof in Shared key
                             key.update_proof(&root, &proof);
+                            if let Some(proof) = &proof {
+                                wallet
+                                    .proof_cache
+                                    .insert_proof(&root, &key.funding_txid, proof)?;
+                            }
                         }
                     },
                     None => failed.push(key.id),
@@ -6,35 +6,90 @@
 // 0. Initiate session - generate ID and perform authorisation
 // 1. Generate shared wallet
 // 2. Co-op sign back-up tx
-// 3. Broadcast funding tx and wait for SE verification
+// 3. Broadcast funding tx, wait for its confirmations and for SE verification
 // 4. Verify funding txid and proof key in SM
+// Progress is saved in the wallet before each network step so that an interrupted deposit can
+// be continued with deposit_resume()
+//
+// deposit_with_p2c(): deposit as above with the shared key tweaked at keygen so that the
+// funding output key commits to the proof key, or to other data, by pay-to-contract
+//
+// deposit_to(): deposit from this wallet on behalf of the owner of a SCEAddress
+// 0-4. Deposit as above with the owner proof key and backup address
+// 5. Hand off the shared key to the owner, encrypted to the owner proof key
+//
+// recover_failed_deposit(): refund a deposit whose funding tx was broadcast but never confirmed
+// 0. Authorise the refund address with the proof key that paid the deposit
+// 1. Co-sign and broadcast the refund tx spending the funding output
+//
+// deposit_claim(): owner receives the shared keys handed off to its proof key
+// 0. Decrypt the handoff and verify the statechain is owned by the proof key
+// 1. Transfer to a new address of the wallet to invalidate the key share known to the payer
 
 use super::super::Result;
 extern crate shared_lib;
-use shared_lib::structs::{DepositMsg1, DepositMsg2, PrepareSignTxMsg, Protocol, UserID, StatechainID};
-use shared_lib::util::{tx_backup_build, tx_funding_build, FEE, transaction_serialise};
+use shared_lib::commitment::proof_key_commitment;
+use shared_lib::ecies::WalletDecryptable;
+use shared_lib::state_chain::{fee_quote_hash, verify_fee_quote, StateChainSig};
+use shared_lib::structs::{
+    DepositHandoffMsg, DepositMsg1, DepositMsg2, DepositRefundMsg, PrepareSignTxMsg, Protocol, SCEAddress, StatechainID,
+    TimelockMode, UserID,
+};
+use shared_lib::util::{
+    transaction_deserialise, transaction_serialise, tx_backup_build, tx_funding_build,
+    tx_refund_build, validate_address_network, FEE,
+};
 
-use super::api::{get_smt_proof, get_smt_root, get_statechain_fee_info};
+use super::api::{
+    get_smt_inclusion_proof, get_statechain_fee_info, get_statechain_params, get_statecoin,
+};
+use super::transfer::{transfer_receiver, transfer_sender};
 use crate::error::{CError, WalletErrorType};
-use crate::state_entity::util::{cosign_tx_input, verify_statechain_smt};
-use crate::utilities::requests;
+use crate::state_entity::util::cosign_tx_input;
+use crate::utilities::requests::{self, Retry};
+use crate::RequestClass;
+use crate::wallet::history::Activity;
+use crate::wallet::shared_key::{PendingDeposit, SharedKey};
 use crate::wallet::wallet::{to_bitcoin_public_key, Wallet};
 
-use bitcoin::{consensus, PublicKey, Transaction};
+use bitcoin::hashes::{hex::FromHex, sha256};
+use bitcoin::{consensus, Address, PublicKey, Transaction};
+use chrono::Utc;
 use curv::elliptic::curves::traits::ECPoint;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 use uuid::Uuid;
 use sha3::Sha3_256;
 use digest::Digest;
 
+/// Interval (seconds) between polls of the funding tx confirmation status
+const FUNDING_CONF_POLL_INTERVAL: u64 = 30;
+
 /// Message to server initiating state entity protocol.
 /// Shared wallet ID returned
 pub fn session_init(wallet: &mut Wallet, proof_key: &String) -> Result<UserID> {
+    session_init_with_payer(wallet, proof_key, None, None)
+}
+
+/// Message to server initiating state entity protocol. If 'payer_proof_key' is supplied the
+/// deposit is made on behalf of the owner of 'proof_key'. 'fee_quote_hash' acknowledges the
+/// fee quote the deposit is made under.
+/// Shared wallet ID returned
+pub fn session_init_with_payer(
+    wallet: &mut Wallet,
+    proof_key: &String,
+    payer_proof_key: Option<String>,
+    fee_quote_hash: Option<String>,
+) -> Result<UserID> {
     requests::postb(
         &wallet.client_shim,
         &format!("deposit/init"),
         &DepositMsg1 {
             auth: "auth".to_string(),
             proof_key: proof_key.to_owned(),
+            payer_proof_key,
+            fee_quote_hash,
         },
     )
 }
@@ -45,154 +100,772 @@ pub fn deposit(
     wallet: &mut Wallet,
     amount: &u64,
 ) -> Result<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)> {
+    deposit_with_backup_addr(wallet, amount, None)
+}
+
+/// Deposit coins into state entity. 'progress' is called with the funding txid, its number of
+/// confirmations and the number required by the state entity each time the funding tx
+/// confirmation status is polled.
+pub fn deposit_with_progress(
+    wallet: &mut Wallet,
+    amount: &u64,
+    progress: &mut dyn FnMut(&str, u32, u32),
+) -> Result<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)> {
+    let proof_key = wallet.se_proof_keys.get_new_key()?;
+
+    deposit_to_proof_key(wallet, amount, None, proof_key, None, None, progress)
+}
+
+/// Deposit coins into state entity with backup tx paying to 'backup_addr' if supplied,
+/// otherwise to a new key from the wallet's se_backup_keys.
+pub fn deposit_with_backup_addr(
+    wallet: &mut Wallet,
+    amount: &u64,
+    backup_addr: Option<Address>,
+) -> Result<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)> {
+    // Generate proof key
+    let proof_key = wallet.se_proof_keys.get_new_key()?;
+
+    deposit_to_proof_key(wallet, amount, backup_addr, proof_key, None, None, &mut log_funding_progress)
+}
+
+/// Deposit coins into state entity with the funding output key committing to 'commitment' (hex
+/// sha256 hash) by pay-to-contract, or to the hash of the proof key if None. The commitment can
+/// be verified from the funding output key with the untweaked shared public key in the shared
+/// key p2c data.
+pub fn deposit_with_p2c(
+    wallet: &mut Wallet,
+    amount: &u64,
+    commitment: Option<String>,
+) -> Result<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)> {
+    if let Some(c) = &commitment {
+        sha256::Hash::from_hex(c).map_err(|e| {
+            CError::Generic(format!("Invalid pay-to-contract commitment: {}", e))
+        })?;
+    }
+    let proof_key = wallet.se_proof_keys.get_new_key()?;
+    let commitment = commitment.unwrap_or(proof_key_commitment(&proof_key));
+
+    deposit_to_proof_key(
+        wallet,
+        amount,
+        None,
+        proof_key,
+        None,
+        Some(commitment),
+        &mut log_funding_progress,
+    )
+}
+
+/// Deposit coins into state entity on behalf of the owner of 'receiver_addr'. The funding tx is
+/// paid by this wallet and the statechain is owned by the receiver proof key from creation.
+/// The shared key is handed off to the receiver, encrypted to its proof key.
+/// Returns shared_key_id, statechain_id and funding txid.
+pub fn deposit_to(
+    wallet: &mut Wallet,
+    amount: &u64,
+    receiver_addr: &SCEAddress,
+) -> Result<(Uuid, Uuid, String)> {
+    // Backup tx must pay the receiver
+    let backup_addr = match &receiver_addr.tx_backup_addr {
+        Some(addr) => addr.clone(),
+        None => {
+            return Err(CError::Generic(String::from(
+                "Receiver address has no backup address.",
+            )))
+        }
+    };
+
+    let payer_proof_key = wallet.se_proof_keys.get_new_key()?;
+    let (shared_key_id, statechain_id, funding_txid, _, _, _) = deposit_to_proof_key(
+        wallet,
+        amount,
+        Some(backup_addr),
+        to_bitcoin_public_key(receiver_addr.proof_key),
+        Some(payer_proof_key),
+        None,
+        &mut log_funding_progress,
+    )?;
+
+    deposit_handoff(wallet, &shared_key_id)?;
+
+    Ok((shared_key_id, statechain_id, funding_txid))
+}
+
+/// Hand off the shared key of a deposit made on behalf of another proof key. The shared key
+/// is encrypted to the owner proof key and stored by the state entity for the owner to claim.
+pub fn deposit_handoff(wallet: &mut Wallet, shared_key_id: &Uuid) -> Result<DepositHandoffMsg> {
+    let mut handoff_msg = {
+        let shared_key = wallet.get_shared_key(shared_key_id)?;
+        DepositHandoffMsg {
+            shared_key_id: shared_key.id,
+            statechain_id: shared_key
+                .statechain_id
+                .ok_or(CError::WalletError(WalletErrorType::KeyMissingData))?,
+            proof_key: shared_key
+                .proof_key
+                .clone()
+                .ok_or(CError::WalletError(WalletErrorType::KeyMissingData))?,
+            payload: serde_json::to_string(shared_key)?,
+        }
+    };
+
+    //encrypt then make immutable
+    handoff_msg.encrypt()?;
+    let handoff_msg = handoff_msg;
+
+    // Overwrites the stored handoff: safe to repeat
+    requests::postb_with(
+        &wallet.client_shim,
+        &format!("deposit/handoff"),
+        &handoff_msg,
+        Retry::Safe,
+        RequestClass::Sign,
+    )?;
+
+    // Coin is owned by the receiver
+    {
+        let shared_key = wallet.get_shared_key_mut(shared_key_id)?;
+        shared_key.unspent = false;
+    }
+
+    Ok(handoff_msg)
+}
+
+/// Claim the coins deposited on behalf of 'proof_key' by other wallets. Each coin is
+/// transferred to a new address of this wallet so that the key share known to the payer is
+/// no longer valid. Returns the statechain ids of the claimed coins.
+pub fn deposit_claim(wallet: &mut Wallet, proof_key: &PublicKey) -> Result<Vec<Uuid>> {
+    let handoffs: Vec<DepositHandoffMsg> = requests::get(
+        &wallet.client_shim,
+        &format!("deposit/handoff/{}", proof_key),
+    )?;
+
+    let mut statechain_ids = vec![];
+    for mut handoff_msg in handoffs {
+        // Skip coins already claimed
+        if wallet
+            .shared_keys
+            .iter()
+            .any(|k| k.statechain_id == Some(handoff_msg.statechain_id))
+        {
+            continue;
+        }
+
+        wallet.decrypt(&mut handoff_msg)?;
+        let mut shared_key: SharedKey = serde_json::from_str(&handoff_msg.payload)?;
+        if shared_key.id != handoff_msg.shared_key_id
+            || shared_key.statechain_id != Some(handoff_msg.statechain_id)
+            || shared_key.proof_key != Some(proof_key.to_string())
+        {
+            return Err(CError::Generic(String::from(
+                "Deposit handoff does not match shared key.",
+            )));
+        }
+
+        // Statechain must be owned by this proof key
+        let statecoin_data = get_statecoin(&wallet.client_shim, &handoff_msg.statechain_id)?;
+        if statecoin_data.statecoin.data != proof_key.to_string() {
+            return Err(CError::Generic(String::from(
+                "Deposited statechain not owned by proof key.",
+            )));
+        }
+
+        shared_key.unspent = true;
+        wallet.shared_keys.push(shared_key);
+
+        // Transfer to a new address of this wallet
+        let rec_se_addr = wallet.get_new_state_entity_address()?;
+        let mut transfer_msg3 =
+            transfer_sender(wallet, &handoff_msg.statechain_id, rec_se_addr, None)?;
+        transfer_receiver(wallet, &mut transfer_msg3, &None)?;
+
+        statechain_ids.push(handoff_msg.statechain_id);
+    }
+
+    Ok(statechain_ids)
+}
+
+/// Deposit coins into state entity owned by 'proof_key'. 'payer_proof_key' is supplied when
+/// the deposit is made on behalf of another wallet. 'p2c_commitment' is committed to in the
+/// funding output key if supplied.
+fn deposit_to_proof_key(
+    wallet: &mut Wallet,
+    amount: &u64,
+    backup_addr: Option<Address>,
+    proof_key: PublicKey,
+    payer_proof_key: Option<PublicKey>,
+    p2c_commitment: Option<String>,
+    progress: &mut dyn FnMut(&str, u32, u32),
+) -> Result<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)> {
+    // Check backup address before initiating protocol
+    if let Some(addr) = &backup_addr {
+        validate_address_network(addr, &wallet.get_bitcoin_network())?;
+    }
+
     // Get state entity fee info
     let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
 
+    // The fee quote acknowledged below must be signed by the pinned server identity key and
+    // not expired, so that the deposit cannot be charged fees the State Entity did not commit to
+    verify_fee_quote(&se_fee_info, wallet.client_shim.pinned_identity_key()?)?;
+    match se_fee_info.valid_until {
+        Some(valid_until) if valid_until > Utc::now().naive_utc() => (),
+        Some(_) => {
+            return Err(CError::Generic(String::from(
+                "State Entity fee quote has expired. Retry the deposit for a new quote.",
+            )))
+        }
+        None => {
+            return Err(CError::Generic(String::from(
+                "State Entity fee quote has no expiry",
+            )))
+        }
+    }
+
     // Ensure funds cover fees before initiating protocol
     if FEE + se_fee_info.deposit as u64 >= *amount {
         return Err(CError::WalletError(WalletErrorType::NotEnoughFunds));
     }
 
+    // Ensure the statecoin is not uneconomical to withdraw
+    if *amount < se_fee_info.min_deposit {
+        return Err(CError::Generic(format!(
+            "Deposit amount {} is below the State Entity minimum deposit {}",
+            amount, se_fee_info.min_deposit
+        )));
+    }
+
     //calculate SE fee amount from rate
     let deposit_fee = (amount * se_fee_info.deposit as u64) / 10000 as u64;
-    let withdraw_fee = (amount * se_fee_info.withdraw as u64) / 10000 as u64;
 
     // Greedy coin selection.
     let (inputs, addrs, amounts) =
         wallet.coin_selection_greedy(&(amount + deposit_fee + FEE))?;
 
-    // Generate proof key
-    let proof_key = wallet.se_proof_keys.get_new_key()?;
-
-    // Init. session - Receive shared wallet ID
-    let shared_key_id: UserID = session_init(wallet, &proof_key.to_string())?;
+    // Init. session acknowledging the fee quote - Receive shared wallet ID
+    let shared_key_id: UserID = session_init_with_payer(
+        wallet,
+        &proof_key.to_string(),
+        payer_proof_key.map(|k| k.to_string()),
+        Some(fee_quote_hash(&se_fee_info)?),
+    )?;
 
-    // generate solution for the PoW challenge
     let challenge = match shared_key_id.challenge {
         Some(c) => c,
         None => return Err(CError::Generic(String::from("missing pow challenge from server"))),
     };
 
-    let difficulty = 4 as usize;
-    let mut counter = 0;
-    let zeros = String::from_utf8(vec![b'0'; difficulty]).unwrap();
-    let mut hasher = Sha3_256::new();
-    loop {
-        hasher.input(&format!("{}:{:x}", challenge, counter).as_bytes());
-        let result = hex::encode(hasher.result_reset());
-        if result[..difficulty] == zeros {
-            break;
-        };
-        counter += 1
-    }
+    // Save the deposit so that it can be resumed from here on
+    let pending = PendingDeposit {
+        shared_key_id: shared_key_id.id,
+        challenge,
+        proof_key: proof_key.to_string(),
+        payer_proof_key: payer_proof_key.map(|k| k.to_string()),
+        amount: *amount,
+        fee_quote: se_fee_info,
+        inputs,
+        input_addrs: addrs,
+        input_amounts: amounts,
+        backup_addr: backup_addr.map(|addr| addr.to_string()),
+        tx_funding_hex: None,
+        tx_backup_psm: None,
+        funding_txid: None,
+        p2c_commitment,
+    };
+    wallet.save_pending_deposit(&pending);
+
+    deposit_resume(wallet, pending, progress)
+}
+
+/// Continue a deposit from the last step saved in 'pending'. Progress is saved in the wallet
+/// before each network step and the pending deposit is removed once confirmed. 'progress' is
+/// called each time the funding tx confirmation status is polled.
+/// Returns shared_key_id, statechain_id, funding txid, signed backup tx, back up transacion
+/// data and proof_key
+pub fn deposit_resume(
+    wallet: &mut Wallet,
+    mut pending: PendingDeposit,
+    progress: &mut dyn FnMut(&str, u32, u32),
+) -> Result<(Uuid, Uuid, String, Transaction, PrepareSignTxMsg, PublicKey)> {
+    let shared_key_id = pending.shared_key_id;
+    let amount = pending.amount;
+    let proof_key = PublicKey::from_str(&pending.proof_key)
+        .map_err(|e| CError::Generic(format!("Invalid pending deposit proof key: {}", e)))?;
+    let se_fee_info = pending.fee_quote.clone();
 
-    let solution = format!("{:x}", counter);
+    //calculate SE fee amount from rate
+    let deposit_fee = (amount * se_fee_info.deposit as u64) / 10000 as u64;
+    let withdraw_fee = (amount * se_fee_info.withdraw as u64) / 10000 as u64;
 
     // 2P-ECDSA with state entity to create a Shared key
-    let shared_key = wallet.gen_shared_key(&shared_key_id.id, amount, solution)?;
+    if wallet.get_shared_key(&shared_key_id).is_err() {
+        let solution = solve_pow_challenge(&pending.challenge);
+        wallet.open_ecdsa_channel(&shared_key_id, &pending.proof_key)?;
+        match &pending.p2c_commitment {
+            Some(commitment) => {
+                wallet.gen_shared_key_p2c(&shared_key_id, &amount, solution, commitment)?
+            }
+            None => wallet.gen_shared_key(&shared_key_id, &amount, solution)?,
+        };
 
-    // Create funding tx
-    let pk = shared_key.share.public.q.get_element(); // co-owned key address to send funds to (P_addr)
-    let p_addr =
-        bitcoin::Address::p2wpkh(&to_bitcoin_public_key(pk), wallet.get_bitcoin_network())?;
-    let change_addr = wallet.keys.get_new_address()?.to_string();
-    let change_amount = amounts.iter().sum::<u64>() - amount - deposit_fee - FEE;
-    
-    let tx_0 = tx_funding_build(
-        &inputs,
-        &p_addr.to_string(),
-        amount,
-        &deposit_fee,
-        &se_fee_info.address,
-        &change_addr,
-        &change_amount,
-    )?;
+        // Keep the signed fee quote as the record of the fee agreed for the deposit
+        wallet.get_shared_key_mut(&shared_key_id)?.fee_quote = Some(se_fee_info.clone());
+        wallet.save_pending_deposit(&pending);
+    }
 
-    
-    let tx_funding_signed = wallet.sign_tx(
-        &tx_0,
-        &(0..inputs.len()).collect(), // inputs to sign are all inputs is this case
-        &addrs,
-        &amounts,
-    );
+    // co-owned key address to send funds to (P_addr)
+    let pk = wallet.get_shared_key(&shared_key_id)?.share.public.q.get_element();
 
-    //get initial locktime
-    let chaintip = wallet
-        .electrumx_client
-        .instance
-        .get_tip_header()?;
-    debug!("Deposit: Got current best block height: {}", chaintip.height.to_string());
-    let init_locktime: u32 = (chaintip.height as u32) + (se_fee_info.initlock as u32);
-    debug!("Deposit: Set initial locktime: {}", init_locktime.to_string());
+    // Create funding tx
+    let tx_funding_signed = match pending.tx_funding_hex.clone() {
+        Some(tx_hex) => transaction_deserialise(&tx_hex)?,
+        None => {
+            let p_addr =
+                bitcoin::Address::p2wpkh(&to_bitcoin_public_key(pk), wallet.get_bitcoin_network())?;
+            let change_addr = wallet.keys.get_new_address()?.to_string();
+            let change_amount =
+                pending.input_amounts.iter().sum::<u64>() - amount - deposit_fee - FEE;
 
-    // Make unsigned backup tx
-    let backup_receive_addr = wallet.se_backup_keys.get_new_address()?;
-    
-    let tx_backup_unsigned =
-        tx_backup_build(&tx_funding_signed.txid(), &backup_receive_addr, &amount, &init_locktime, &withdraw_fee, &se_fee_info.address)?;
-
-    // Co-sign tx backup tx
-    let tx_backup_psm = PrepareSignTxMsg {
-        shared_key_ids: vec![shared_key_id.id],
-        protocol: Protocol::Deposit,
-        tx_hex: transaction_serialise(&tx_backup_unsigned),
-        input_addrs: vec![pk],
-        input_amounts: vec![*amount],
-        proof_key: Some(proof_key.to_string()),
+            let tx_0 = tx_funding_build(
+                &pending.inputs,
+                &p_addr.to_string(),
+                &amount,
+                &deposit_fee,
+                &se_fee_info.address,
+                &change_addr,
+                &change_amount,
+            )?;
+
+            let tx_funding_signed = wallet.sign_tx(
+                &tx_0,
+                &(0..pending.inputs.len()).collect(), // inputs to sign are all inputs is this case
+                &pending.input_addrs,
+                &pending.input_amounts,
+            );
+            pending.tx_funding_hex = Some(transaction_serialise(&tx_funding_signed));
+            tx_funding_signed
+        }
     };
 
-    let witness = {
-        let tmp = cosign_tx_input(wallet, &tx_backup_psm)?;
-        if tmp.len() != 1 {
-            return Err(CError::Generic(String::from("expected 1 witness from cosign_tx_input")));
-        } else {
-            tmp[0].to_owned()
+    // Co-sign backup tx
+    let tx_backup_psm = match &pending.tx_backup_psm {
+        Some(tx_backup_psm) => tx_backup_psm.clone(),
+        None => {
+            //get initial locktime
+            let chaintip = wallet
+                .electrumx_client
+                .instance
+                .get_tip_header()?;
+            debug!("Deposit: Got current best block height: {}", chaintip.height.to_string());
+            // relative timelocks count from funding tx confirmation rather than the current height
+            let init_locktime: u32 = match se_fee_info.timelock_mode {
+                TimelockMode::Absolute => (chaintip.height as u32) + (se_fee_info.initlock as u32),
+                TimelockMode::Relative => se_fee_info.initlock as u32,
+            };
+            debug!("Deposit: Set initial locktime: {}", init_locktime.to_string());
+
+            // Make unsigned backup tx
+            let backup_receive_addr = match &pending.backup_addr {
+                Some(addr) => Address::from_str(addr)?,
+                None => wallet.se_backup_keys.get_new_address()?,
+            };
+            pending.backup_addr = Some(backup_receive_addr.to_string());
+
+            let tx_backup_unsigned =
+                tx_backup_build(&tx_funding_signed.txid(), &backup_receive_addr, &amount, &init_locktime, &withdraw_fee, &se_fee_info.address, &se_fee_info.timelock_mode)?;
+
+            let tx_backup_psm = PrepareSignTxMsg {
+                shared_key_ids: vec![shared_key_id],
+                protocol: Protocol::Deposit,
+                tx_hex: transaction_serialise(&tx_backup_unsigned),
+                input_addrs: vec![pk],
+                input_amounts: vec![amount],
+                proof_key: Some(proof_key.to_string()),
+            };
+            wallet.save_pending_deposit(&pending);
+
+            let witness = {
+                let tmp = cosign_tx_input(wallet, &tx_backup_psm)?;
+                if tmp.len() != 1 {
+                    return Err(CError::Generic(String::from("expected 1 witness from cosign_tx_input")));
+                } else {
+                    tmp[0].to_owned()
+                }
+            };
+
+            // Add witness to back up tx
+            let mut tx_backup_signed = tx_backup_unsigned.clone();
+            tx_backup_signed.input[0].witness = witness;
+            // TODO: check signature is valid?
+
+            PrepareSignTxMsg {
+                tx_hex: transaction_serialise(&tx_backup_signed),
+                ..tx_backup_psm
+            }
         }
     };
+    pending.tx_backup_psm = Some(tx_backup_psm.clone());
+    let tx_backup_signed = transaction_deserialise(&tx_backup_psm.tx_hex)?;
 
-    // Add witness to back up tx
-    let mut tx_backup_signed = tx_backup_unsigned.clone();
-    tx_backup_signed.input[0].witness = witness;
-    // TODO: check signature is valid?
+    // Broadcast funding transcation. Resumed deposits rebroadcast it in case it was dropped
+    // from the mempool.
+    wallet.save_pending_deposit(&pending);
+    let funding_txid = broadcast_funding_tx(wallet, &tx_funding_signed)?;
+    pending.funding_txid = Some(funding_txid.clone());
+    wallet.save_pending_deposit(&pending);
 
-    // Broadcast funding transcation
-    let funding_txid = wallet
-        .electrumx_client
-        .instance
-        .broadcast_transaction(hex::encode(consensus::serialize(&tx_funding_signed)))?;
+    // Wait for the confirmations required by the state entity before confirming the deposit
+    let required_confirmation = get_statechain_params(&wallet.client_shim)?.required_confirmation;
+    wait_funding_confirmations(wallet, &funding_txid, required_confirmation, progress)?;
 
     // Wait for server confirmation of funding tx and receive new StateChain's id
-    let statechain_id: StatechainID = requests::postb(
+    let statechain_id: StatechainID = requests::postb_with(
         &wallet.client_shim,
         &format!("deposit/confirm"),
         &DepositMsg2 {
-            shared_key_id: shared_key_id.id,
+            shared_key_id,
         },
+        Retry::Unsafe,
+        RequestClass::Confirm,
     )?;
     
     // Verify proof key inclusion in SE sparse merkle tree
-    let root = get_smt_root(&wallet.client_shim)?.unwrap();
-    let proof = get_smt_proof(&wallet.client_shim, &root, &funding_txid)?;
-    assert!(verify_statechain_smt(
-        &Some(root.hash()),
+    let (root, proof) = get_smt_inclusion_proof(
+        &wallet.client_shim,
+        &funding_txid,
         &proof_key.to_string(),
-        &proof
-    ));
+        amount,
+    )?;
+    match &proof {
+        Some(proof) => wallet.proof_cache.insert_proof(&root, &funding_txid, proof)?,
+        None => wallet.proof_cache.record_root(&root)?,
+    }
 
     // Add proof and state chain id to Shared key
     {
-        let shared_key = wallet.get_shared_key_mut(&shared_key_id.id)?;
+        let shared_key = wallet.get_shared_key_mut(&shared_key_id)?;
         shared_key.statechain_id = Some(statechain_id.id);
-        shared_key.tx_backup_psm = Some(tx_backup_psm.to_owned());
+        // Store the signed backup tx so that it can be broadcast by the wallet
+        shared_key.tx_backup_psm = Some(tx_backup_psm.clone());
+        shared_key.backup_addr = pending.backup_addr.clone();
         shared_key.add_proof_data(&proof_key.to_string(), &root, &proof, &funding_txid);
     }
+    wallet.record_activity(Activity::Deposit, &shared_key_id, Some(funding_txid.clone()))?;
+    wallet.pending_deposits.retain(|d| d.shared_key_id != shared_key_id);
+
+    // Back up transaction data is returned unsigned
+    let mut tx_backup_unsigned = tx_backup_signed.clone();
+    tx_backup_unsigned.input[0].witness = vec![];
 
     Ok((
-        shared_key_id.id,
+        shared_key_id,
         statechain_id.id,
         funding_txid,
         tx_backup_signed,
-        tx_backup_psm,
+        PrepareSignTxMsg {
+            tx_hex: transaction_serialise(&tx_backup_unsigned),
+            ..tx_backup_psm
+        },
         proof_key,
     ))
 }
+
+/// Recover the funds of a pending deposit whose funding tx was broadcast but which was never
+/// confirmed by the state entity, e.g. after deposit/confirm timed out. The state entity
+/// co-signs a refund tx paying the deposit amount, less the tx fee, to 'refund_addr' if
+/// supplied, otherwise to a new address of the wallet. The refund tx is broadcast and the
+/// pending deposit removed. Returns the refund txid.
+pub fn recover_failed_deposit(
+    wallet: &mut Wallet,
+    shared_key_id: &Uuid,
+    refund_addr: Option<Address>,
+) -> Result<String> {
+    let pending = match wallet
+        .pending_deposits
+        .iter()
+        .find(|d| d.shared_key_id == *shared_key_id)
+    {
+        Some(pending) => pending.clone(),
+        None => {
+            return Err(CError::Generic(format!(
+                "No pending deposit for shared key ID {}.",
+                shared_key_id
+            )))
+        }
+    };
+    // The funding tx is only broadcast once the backup tx is co-signed
+    let tx_backup_psm = match &pending.tx_backup_psm {
+        Some(tx_backup_psm) => tx_backup_psm.clone(),
+        None => {
+            return Err(CError::Generic(String::from(
+                "Deposit funding tx was not broadcast: there are no funds to recover.",
+            )))
+        }
+    };
+    let tx_backup = transaction_deserialise(&tx_backup_psm.tx_hex)?;
+
+    let refund_addr = match refund_addr {
+        Some(addr) => {
+            validate_address_network(&addr, &wallet.get_bitcoin_network())?;
+            addr
+        }
+        None => wallet.keys.get_new_address()?,
+    };
+
+    // Authorise the refund with the proof key that paid the deposit
+    let proof_key = PublicKey::from_str(pending.payer_proof_key.as_ref().unwrap_or(&pending.proof_key))
+        .map_err(|e| CError::Generic(format!("Invalid pending deposit proof key: {}", e)))?;
+    let proof_key_derivation = wallet
+        .se_proof_keys
+        .get_key_derivation(&proof_key)
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+    let refund_sig = StateChainSig::new(
+        &proof_key_derivation.private_key.key,
+        &String::from("REFUND"),
+        &refund_addr.to_string(),
+    )?;
+
+    // deposit/refund can be repeated for the same address, so is safe to retry
+    requests::postb_with(
+        &wallet.client_shim,
+        &format!("deposit/refund"),
+        &DepositRefundMsg {
+            shared_key_id: *shared_key_id,
+            refund_sig,
+        },
+        Retry::Safe,
+        RequestClass::Sign,
+    )?;
+
+    // Co-sign refund tx
+    let tx_refund_unsigned = tx_refund_build(
+        &tx_backup.input[0].previous_output,
+        &refund_addr,
+        &pending.amount,
+        &FEE,
+    )?;
+    let tx_refund_psm = PrepareSignTxMsg {
+        shared_key_ids: vec![*shared_key_id],
+        protocol: Protocol::Refund,
+        tx_hex: transaction_serialise(&tx_refund_unsigned),
+        input_addrs: tx_backup_psm.input_addrs.clone(),
+        input_amounts: vec![pending.amount],
+        proof_key: None,
+    };
+    let witness = {
+        let tmp = cosign_tx_input(wallet, &tx_refund_psm)?;
+        if tmp.len() != 1 {
+            return Err(CError::Generic(String::from("expected 1 witness from cosign_tx_input")));
+        } else {
+            tmp[0].to_owned()
+        }
+    };
+    let mut tx_refund_signed = tx_refund_unsigned.clone();
+    tx_refund_signed.input[0].witness = witness;
+
+    let refund_txid = wallet
+        .electrumx_client
+        .instance
+        .broadcast_transaction(hex::encode(consensus::serialize(&tx_refund_signed)))?;
+
+    // The deposit is abandoned: the shared key holds no coin
+    wallet.pending_deposits.retain(|d| d.shared_key_id != *shared_key_id);
+    if let Ok(shared_key) = wallet.get_shared_key_mut(shared_key_id) {
+        shared_key.unspent = false;
+    }
+
+    Ok(refund_txid)
+}
+
+/// Generate solution for the PoW challenge
+fn solve_pow_challenge(challenge: &String) -> String {
+    let difficulty = 4 as usize;
+    let mut counter = 0;
+    let zeros = String::from_utf8(vec![b'0'; difficulty]).unwrap();
+    let mut hasher = Sha3_256::new();
+    loop {
+        hasher.input(&format!("{}:{:x}", challenge, counter).as_bytes());
+        let result = hex::encode(hasher.result_reset());
+        if result[..difficulty] == zeros {
+            break;
+        };
+        counter += 1
+    }
+
+    format!("{:x}", counter)
+}
+
+/// Broadcast the funding tx. A resumed deposit may have broadcast the tx before the wallet was
+/// saved, so the tx being rejected is not an error if it is already known to the network.
+/// Other rejections are returned with the action required to complete the deposit.
+fn broadcast_funding_tx(wallet: &mut Wallet, tx_funding_signed: &Transaction) -> Result<String> {
+    let funding_txid = tx_funding_signed.txid().to_string();
+    match wallet
+        .electrumx_client
+        .instance
+        .broadcast_transaction(hex::encode(consensus::serialize(tx_funding_signed)))
+    {
+        Ok(txid) => Ok(txid),
+        Err(e) => match wallet
+            .electrumx_client
+            .instance
+            .get_transaction_conf_status(funding_txid.clone(), false)
+        {
+            Ok(_) => Ok(funding_txid),
+            Err(_) => Err(funding_broadcast_error(&funding_txid, &e.to_string())),
+        },
+    }
+}
+
+/// Error for a funding tx rejected by the mempool with reason 'reason'
+fn funding_broadcast_error(funding_txid: &str, reason: &str) -> CError {
+    let lower = reason.to_lowercase();
+    let action = if lower.contains("fee not met")
+        || lower.contains("insufficient fee")
+        || lower.contains("min relay fee")
+    {
+        "The fee is too low for the current mempool. Resume the deposit once mempool fees have dropped."
+    } else if lower.contains("missing") || lower.contains("spent") {
+        "The funding inputs are missing or already spent. Check the wallet balance has confirmed and make a new deposit."
+    } else if lower.contains("mempool-conflict") || lower.contains("conflict") {
+        "The funding inputs are spent by another unconfirmed transaction. Make a new deposit once it has confirmed."
+    } else {
+        "Resume the deposit to retry the broadcast."
+    };
+    CError::Generic(format!(
+        "Funding transaction {} rejected: {}. {}",
+        funding_txid, reason, action
+    ))
+}
+
+/// Wait until the funding tx has 'required' confirmations, polling its status every
+/// FUNDING_CONF_POLL_INTERVAL seconds. 'progress' is called with the funding txid, its number
+/// of confirmations and 'required' after each poll. Gives up after the wallet's
+/// funding_conf_max_polls polls, leaving the deposit pending to be resumed later.
+pub fn wait_funding_confirmations(
+    wallet: &mut Wallet,
+    funding_txid: &String,
+    required: u32,
+    progress: &mut dyn FnMut(&str, u32, u32),
+) -> Result<()> {
+    let max_polls = wallet.funding_conf_max_polls();
+    for poll in 1..=max_polls {
+        let confirmations = match wallet
+            .electrumx_client
+            .instance
+            .get_transaction_conf_status(funding_txid.clone(), false)
+        {
+            Ok(status) => status.confirmations.unwrap_or(0),
+            Err(e) => {
+                return Err(CError::Generic(format!(
+                    "Funding transaction {} not found: {}. It may have been dropped from the mempool. Resume the deposit to rebroadcast it.",
+                    funding_txid, e
+                )))
+            }
+        };
+        progress(funding_txid, confirmations, required);
+        if confirmations >= required {
+            return Ok(());
+        }
+        if poll == max_polls {
+            return Err(CError::Generic(format!(
+                "Funding transaction {} has {} of {} required confirmations after {} status polls. The deposit is saved: resume it later to continue waiting.",
+                funding_txid, confirmations, required, max_polls
+            )));
+        }
+        thread::sleep(Duration::from_secs(FUNDING_CONF_POLL_INTERVAL));
+    }
+    Ok(())
+}
+
+/// Log funding tx confirmation progress
+pub fn log_funding_progress(funding_txid: &str, confirmations: u32, required: u32) {
+    info!(
+        "Deposit: funding tx {} has {} of {} required confirmations",
+        funding_txid, confirmations, required
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::wallet::DEFAULT_TEST_WALLET_LOC;
+    use crate::ClientShim;
+    use shared_lib::mocks::mock_electrum::{MockElectrum, TxScenario};
+
+    #[test]
+    fn test_funding_broadcast_error() {
+        let err = funding_broadcast_error("txid", "min relay fee not met, 100 < 141 (code 66)");
+        assert!(format!("{}", err).contains("fee is too low"));
+        let err = funding_broadcast_error("txid", "bad-txns-inputs-missingorspent");
+        assert!(format!("{}", err).contains("missing or already spent"));
+        let err = funding_broadcast_error("txid", "txn-mempool-conflict (code 18)");
+        assert!(format!("{}", err).contains("another unconfirmed transaction"));
+        let err = funding_broadcast_error("txid", "unknown");
+        assert!(format!("{}", err).contains("retry the broadcast"));
+    }
+
+    #[test]
+    fn test_wait_funding_confirmations() {
+        let mut wallet = Wallet::new(
+            &[0xcd; 32],
+            &"regtest".to_string(),
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        );
+        let mut polls = vec![];
+        // Mock Electrum reports 3 confirmations
+        wait_funding_confirmations(&mut wallet, &String::from("txid"), 2, &mut |_, confs, required| {
+            polls.push((confs, required))
+        })
+        .unwrap();
+        assert_eq!(polls, vec![(3, 2)]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_funding_tx_failure_scenarios() {
+        let mut wallet = Wallet::new(
+            &[0xcd; 32],
+            &"regtest".to_string(),
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        );
+        let tx_funding = transaction_deserialise(&String::from("020000000001014e3e3b35c39ac305aaa3dc364c7378fceaf3cd124101e4f234672a51e74c17d10000000000ffffffff011fae01000000000016001451e57b299625a0c3755f18050c684a6adfdc54c102483045022100de6849daa364f55bdbff15a24250dad308110fbf5c32e02259349ca23c41e1e702201efcee6590fac368585172a9ac31281055e3590e9478ba954500e49cd51be012012102992a0ce40f87d9bf333dbbf60b726b5023fc10c2838179b66c577cb843bf2355a5080000")).unwrap();
+        let funding_txid = tx_funding.txid().to_string();
+
+        // Rejected for a too low fee and not already known to the network
+        MockElectrum::set_scenario(&funding_txid, TxScenario::FeeSpike { fee_rate: 0.001 });
+        let err = broadcast_funding_tx(&mut wallet, &tx_funding).unwrap_err();
+        assert!(format!("{}", err).contains("fee is too low"));
+
+        // Broadcast but dropped from the mempool
+        MockElectrum::set_scenario(&funding_txid, TxScenario::NeverBroadcast);
+        assert_eq!(broadcast_funding_tx(&mut wallet, &tx_funding).unwrap(), funding_txid);
+        let err = wait_funding_confirmations(&mut wallet, &funding_txid, 1, &mut |_, _, _| ())
+            .unwrap_err();
+        assert!(format!("{}", err).contains("Resume the deposit to rebroadcast"));
+
+        // Confirmed once the funding tx has the required confirmations
+        MockElectrum::set_scenario(&funding_txid, TxScenario::Confirmed(1));
+        let mut polls = vec![];
+        wait_funding_confirmations(&mut wallet, &funding_txid, 1, &mut |_, confs, _| polls.push(confs))
+            .unwrap();
+        assert_eq!(polls, vec![1]);
+
+        // Gives up once the maximum number of polls is reached
+        MockElectrum::set_scenario(&funding_txid, TxScenario::Confirmed(1));
+        wallet.set_funding_conf_max_polls(1);
+        let mut polls = vec![];
+        let err = wait_funding_confirmations(&mut wallet, &funding_txid, 2, &mut |_, confs, _| {
+            polls.push(confs)
+        })
+        .unwrap_err();
+        assert_eq!(polls, vec![1]);
+        assert!(format!("{}", err).contains("resume it later"));
+        MockElectrum::clear_scenarios();
+    }
+}
@@ -0,0 +1,125 @@
+//! Inheritance
+//!
+//! Time-locked transfer of a statecoin to a beneficiary, who can only receive it once the
+//! owner has not checked in with the State Entity for the inactivity period.
+
+// designate_beneficiary():
+// 0. Transfer the statecoin to the beneficiary as usual (transfer_sender). The transfer message
+//    is left with the State Entity for the beneficiary to fetch.
+// 1. Designate the receiver of the pending transfer as beneficiary, signing the transfer
+//    statechain sig. The statecoin stays spendable by the owner.
+
+// checkin():
+// 0. Restart the inactivity period. Called on wallet sync for designated statecoins.
+
+// inheritance_claim():
+// 0. Check the designation is for the pending transfer and the inactivity period has passed
+// 1. Receive the statecoin as usual (transfer_receiver)
+
+use super::super::Result;
+extern crate shared_lib;
+
+use super::api::{inheritance_checkin, inheritance_designate, inheritance_get, inheritance_revoke};
+use super::transfer::{transfer_receiver, transfer_sender, TransferFinalizeData};
+use crate::error::{CError, WalletErrorType};
+use crate::wallet::wallet::Wallet;
+use shared_lib::state_chain::StateChainSig;
+use shared_lib::structs::{
+    InheritanceAPI, InheritanceMsg, InheritanceOwnerMsg, SCEAddress, TransferMsg3,
+};
+
+use bitcoin::PublicKey;
+use chrono::Utc;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Owner: transfer a statecoin to the beneficiary at receiver_addr and designate them as its
+/// beneficiary. The beneficiary can only complete the transfer once the owner has not checked
+/// in for inactivity_period seconds. Until then the statecoin remains the owner's.
+pub fn designate_beneficiary(
+    wallet: &mut Wallet,
+    statechain_id: &Uuid,
+    receiver_addr: SCEAddress,
+    inactivity_period: u64,
+) -> Result<(TransferMsg3, InheritanceAPI)> {
+    let shared_key_id = wallet.get_shared_key_by_statechain_id(statechain_id)?.id;
+    let proof_key = wallet
+        .get_shared_key(&shared_key_id)?
+        .proof_key
+        .clone()
+        .ok_or(CError::WalletError(WalletErrorType::KeyMissingData))?;
+
+    let transfer_msg3 = transfer_sender(wallet, statechain_id, receiver_addr, None)?;
+
+    let proof_key = PublicKey::from_str(&proof_key)
+        .map_err(|e| CError::Generic(format!("Invalid proof key: {}", e)))?;
+    let proof_key_derivation = wallet
+        .se_proof_keys
+        .get_key_derivation(&proof_key)
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+    let statechain_sig = StateChainSig::new_inheritance_sig(
+        &proof_key_derivation.private_key.key,
+        &transfer_msg3.statechain_sig,
+        inactivity_period,
+        statechain_id,
+    )?;
+    let inheritance = inheritance_designate(
+        &wallet.client_shim,
+        &InheritanceMsg {
+            shared_key_id,
+            statechain_sig,
+            inactivity_period,
+        },
+    )?;
+
+    // The statecoin is only spent once the beneficiary claims it
+    {
+        let shared_key = wallet.get_shared_key_mut(&shared_key_id)?;
+        shared_key.unspent = true;
+        shared_key.inheritance = Some(inheritance.clone());
+    }
+    wallet.save();
+    Ok((transfer_msg3, inheritance))
+}
+
+/// Owner: check in, restarting the inactivity period of the designation of a statecoin
+pub fn checkin(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<InheritanceAPI> {
+    let shared_key_id = wallet.get_shared_key_by_statechain_id(statechain_id)?.id;
+    let inheritance =
+        inheritance_checkin(&wallet.client_shim, &InheritanceOwnerMsg { shared_key_id })?;
+    wallet.get_shared_key_mut(&shared_key_id)?.inheritance = Some(inheritance.clone());
+    Ok(inheritance)
+}
+
+/// Owner: revoke the designation of a statecoin. The pending transfer to the beneficiary can
+/// then be completed at any time, so the statecoin should be transferred again, e.g. to the
+/// owner's own address.
+pub fn revoke(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<()> {
+    let shared_key_id = wallet.get_shared_key_by_statechain_id(statechain_id)?.id;
+    inheritance_revoke(&wallet.client_shim, &InheritanceOwnerMsg { shared_key_id })?;
+    wallet.get_shared_key_mut(&shared_key_id)?.inheritance = None;
+    wallet.save();
+    Ok(())
+}
+
+/// Beneficiary: receive a statecoin once the owner has been inactive for the designated period
+pub fn inheritance_claim(
+    wallet: &mut Wallet,
+    transfer_msg3: &mut TransferMsg3,
+) -> Result<TransferFinalizeData> {
+    let statechain_id = transfer_msg3.statechain_id;
+    let inheritance = inheritance_get(&wallet.client_shim, &statechain_id)?;
+    if inheritance.transfer_sig != transfer_msg3.statechain_sig {
+        return Err(CError::Generic(format!(
+            "Designation of statechain {} is for another transfer",
+            statechain_id
+        )));
+    }
+    if inheritance.claimable_from > Utc::now().naive_utc() {
+        return Err(CError::Generic(format!(
+            "Statechain {} can only be claimed from {}",
+            statechain_id, inheritance.claimable_from
+        )));
+    }
+    transfer_receiver(wallet, transfer_msg3, &None)
+}
@@ -0,0 +1,181 @@
+//! Migrate
+//!
+//! Move a statecoin from the current state entity to another state entity
+
+// migrate() messages:
+// 1. Owner gets the migration public key M of the new State Entity
+// 2. Owner initialises migration with the old State Entity
+//      a. sign statechain over to M (purpose MIGRATE)
+//      b. old State Entity generates x1 and sends it to the Owner
+// 3. Owner re-keys with the old State Entity
+//      a. calculate t1 = o1x1, t2 = t1*o2_inv
+//      b. send t2, O2 to the old State Entity
+//      c. old State Entity returns the statechain export and new key share s2 encrypted to M,
+//          signed with its export key
+// 4. Owner imports the statecoin to the new State Entity
+//      a. new State Entity verifies the package and returns the new shared key id and S2
+//      b. keygen with fixed secret o2
+//      c. Verify o2*S2 = P
+// The shared public key is unchanged, so the current backup tx remains valid.
+
+use super::super::Result;
+
+use crate::error::{CError, WalletErrorType};
+use crate::state_entity::api::{get_smt_inclusion_proof, get_statechain};
+use crate::wallet::{key_paths::funding_txid_to_int, shared_key::SharedKey, wallet::Wallet};
+use crate::{utilities::requests, ClientShim};
+use shared_lib::{ecies::SelfEncryptable, state_chain::StateChainSig, structs::*};
+use bitcoin::PublicKey;
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::{FE, GE};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Get the migration public key of a state entity
+pub fn migrate_get_pubkey(client_shim: &ClientShim) -> Result<MigratePubKey> {
+    requests::get(client_shim, &format!("migrate/pubkey"))
+}
+
+/// Migrate a statecoin from the wallet's state entity to the state entity at new_client_shim.
+/// Returns the ID of the new shared key, which is held with the new state entity.
+pub fn migrate(
+    wallet: &mut Wallet,
+    statechain_id: &Uuid,
+    new_client_shim: &ClientShim,
+) -> Result<Uuid> {
+    let (shared_key_id, o1, value, funding_txid, mut tx_backup_psm, backup_addr) = {
+        let shared_key = wallet.get_shared_key_by_statechain_id(statechain_id)?;
+        (
+            shared_key.id,
+            shared_key.share.private.get_private_key(),
+            shared_key.value,
+            shared_key.funding_txid.clone(),
+            shared_key
+                .tx_backup_psm
+                .clone()
+                .ok_or(CError::WalletError(WalletErrorType::KeyMissingData))?,
+            shared_key.backup_addr.clone(),
+        )
+    };
+
+    // Sign statechain over to the new state entity
+    let migration_pubkey = migrate_get_pubkey(new_client_shim)?;
+    let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, statechain_id)?;
+    let proof_key = statechain_data.get_tip()?.data.clone();
+    let proof_key_derivation = wallet
+        .se_proof_keys
+        .get_key_derivation(&PublicKey::from_str(&proof_key).unwrap());
+    // Bind the signature to the statechain and the current tip position
+    let statechain_sig = StateChainSig::new_bound(
+        &proof_key_derivation
+            .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?
+            .private_key
+            .key,
+        &String::from("MIGRATE"),
+        &migration_pubkey.key,
+        statechain_id,
+        (statechain_data.chain.len() - 1) as u64,
+    )?;
+    let auth_sig = wallet.second_factor_sig(statechain_id, &statechain_sig)?;
+
+    let mut transfer_msg2: TransferMsg2 = requests::postb(
+        &wallet.client_shim,
+        &format!("migrate/init"),
+        &MigrateMsg1 {
+            shared_key_id,
+            statechain_sig,
+            auth_sig,
+        },
+    )?;
+    wallet.decrypt(&mut transfer_msg2)?;
+
+    // t1 = o1x1, t2 = t1*o2_inv
+    let x1 = transfer_msg2.x1.get_fe()?;
+    let t1 = o1 * x1;
+    let funding_txid_int = funding_txid_to_int(&funding_txid).map_err(|e| {
+        CError::Generic(format!(
+            "Failed to get funding txid int from funding_txid: {:?} error: {}",
+            funding_txid, e
+        ))
+    })?;
+    let mut o2: FE = ECScalar::zero();
+    wallet
+        .se_key_shares
+        .get_new_key_encoded_id(funding_txid_int, Some(&mut o2))
+        .map_err(|e| {
+            CError::Generic(format!(
+                "Failed to get new key encoded id from funding_txid_int: {} error: {}",
+                funding_txid_int, e
+            ))
+        })?;
+    let g: GE = ECPoint::generator();
+    let o2_pub: GE = g * o2;
+    let t2 = t1 * (o2.invert());
+
+    // get old SE public key share for t2 encryption
+    let s1_pub: S1PubKey = requests::postb(
+        &wallet.client_shim,
+        &format!("transfer/pubkey"),
+        UserID {
+            id: shared_key_id,
+            challenge: None,
+        },
+    )?;
+
+    let mut migrate_msg2 = MigrateMsg2 {
+        shared_key_id,
+        statechain_id: *statechain_id,
+        t2: FESer::from_fe(&t2),
+        o2_pub,
+    };
+    migrate_msg2.encrypt_with_pubkey(&PublicKey::from_str(&s1_pub.key).unwrap())?;
+
+    let package: SignedMigrationPackage =
+        requests::postb(&wallet.client_shim, &format!("migrate/export"), &migrate_msg2)?;
+
+    // The statecoin is closed on the old state entity
+    wallet.get_shared_key_mut(&shared_key_id)?.unspent = false;
+
+    let transfer_msg5: TransferMsg5 =
+        requests::postb(new_client_shim, &format!("migrate/import"), &package)?;
+
+    // Make shared key with new private share
+    let shared_key = SharedKey::new_repeat_keygen(
+        &transfer_msg5.new_shared_key_id,
+        new_client_shim,
+        &o2.get_element(),
+        &value,
+        Protocol::Transfer,
+        "".to_string(),
+        0,
+    )?;
+
+    // Check shared key master public key == private share * SE public share
+    let pk = shared_key.share.public.q.get_element();
+    if (transfer_msg5.s2_pub * o2).get_element() != pk {
+        return Err(CError::StateEntityError(String::from(
+            "Migrate failed. Incorrect master public key generated.",
+        )));
+    }
+    wallet.shared_keys.push(shared_key);
+
+    // Verify proof key inclusion in the new SE sparse merkle tree
+    let (root, proof) = get_smt_inclusion_proof(new_client_shim, &funding_txid, &proof_key, value)
+        .map_err(|e| {
+            CError::StateEntityError(format!(
+                "Migrate failed. Proof key not included in new state entity SMT: {}",
+                e
+            ))
+        })?;
+
+    tx_backup_psm.shared_key_ids = vec![transfer_msg5.new_shared_key_id];
+    {
+        let shared_key = wallet.get_shared_key_mut(&transfer_msg5.new_shared_key_id)?;
+        shared_key.statechain_id = Some(*statechain_id);
+        shared_key.tx_backup_psm = Some(tx_backup_psm);
+        shared_key.backup_addr = backup_addr;
+        shared_key.add_proof_data(&proof_key, &root, &proof, &funding_txid);
+    }
+
+    Ok(transfer_msg5.new_shared_key_id)
+}
@@ -1,9 +1,18 @@
 // state entity client side interface
 
 pub mod api;
+pub mod channel;
 pub mod conductor;
 pub mod confirm_proofs;
 pub mod deposit;
+pub mod inheritance;
+pub mod migrate;
+pub mod reserves;
+pub mod rotate;
+pub mod sale;
+pub mod second_factor;
 pub mod transfer;
 pub mod util;
+pub mod vault;
+pub mod watchtower;
 pub mod withdraw;
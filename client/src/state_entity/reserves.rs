@@ -0,0 +1,128 @@
+//! Reserves
+//!
+//! Prove ownership of the statecoins held in the wallet to a third party verifier
+
+// prove_reserves():
+// 0. Sign the verifier-supplied nonce with the proof key of each unspent statecoin
+// 1. Request an aggregated report from the State Entity with SMT proofs against the latest attested root
+// 2. Verify the report before returning it
+
+// prove_ownership():
+// 0. Sign the verifier-supplied ownership challenge nonce with the proof key of the statecoin
+// 1. Request a confirmation of current ownership signed by the State Entity
+// 2. Verify the confirmation before returning it
+
+use super::super::Result;
+extern crate shared_lib;
+
+use super::api::{get_reserve_proof, prove_ownership as prove_ownership_api};
+use crate::error::{CError, WalletErrorType};
+use crate::wallet::wallet::Wallet;
+use shared_lib::state_chain::{verify_ownership_confirmation, verify_reserve_proof, StateChainSig};
+use shared_lib::structs::{
+    OwnershipProofMsg, ReserveCoinSig, ReserveProofMsg, SignedOwnershipConfirmation,
+    SignedReserveProofReport,
+};
+use uuid::Uuid;
+
+use bitcoin::PublicKey;
+use std::str::FromStr;
+
+/// Produce a proof of reserves report for all unspent statecoins in the wallet over
+/// the verifier-supplied nonce. The report is verified against the pinned server identity key
+/// before it is returned. The verifier checks it against the State Entity identity public key
+/// with verify_reserve_proof.
+pub fn prove_reserves(wallet: &Wallet, nonce: &String) -> Result<SignedReserveProofReport> {
+    let mut statecoins = vec![];
+    for shared_key in &wallet.shared_keys {
+        if !shared_key.unspent {
+            continue;
+        }
+        let (statechain_id, proof_key) = match (&shared_key.statechain_id, &shared_key.proof_key) {
+            (Some(id), Some(pk)) => (id, pk),
+            _ => continue,
+        };
+        let proof_key_derivation = wallet
+            .se_proof_keys
+            .get_key_derivation(
+                &PublicKey::from_str(proof_key).map_err(|e| CError::Generic(e.to_string()))?,
+            )
+            .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+        statecoins.push(ReserveCoinSig {
+            statechain_id: *statechain_id,
+            statechain_sig: StateChainSig::new_reserves_sig(
+                &proof_key_derivation.private_key.key,
+                nonce,
+                statechain_id,
+            )?,
+        });
+    }
+
+    let reserve_proof_msg = ReserveProofMsg {
+        nonce: nonce.clone(),
+        statecoins,
+    };
+    let server_pubkey = wallet.client_shim.pinned_identity_key()?;
+    let signed = get_reserve_proof(&wallet.client_shim, &reserve_proof_msg)?;
+
+    verify_reserve_proof(&signed, nonce, server_pubkey)?;
+    let report = &signed.report;
+    if report.entries.len() != reserve_proof_msg.statecoins.len()
+        || report
+            .entries
+            .iter()
+            .zip(reserve_proof_msg.statecoins.iter())
+            .any(|(entry, coin)| entry.statechain_id != coin.statechain_id)
+    {
+        return Err(CError::Generic(String::from(
+            "Reserve proof report does not match requested statecoins",
+        )));
+    }
+    Ok(signed)
+}
+
+/// Prove current ownership of a statecoin in the wallet over a nonce issued by the State Entity
+/// (/info/ownership-challenge) to a third party verifier. The verifier checks the returned
+/// confirmation against the State Entity identity public key with verify_ownership_confirmation,
+/// as this wallet does with its pinned server_identity_key.
+pub fn prove_ownership(
+    wallet: &Wallet,
+    statechain_id: &Uuid,
+    nonce: &String,
+) -> Result<SignedOwnershipConfirmation> {
+    let shared_key = wallet
+        .shared_keys
+        .iter()
+        .find(|k| k.unspent && k.statechain_id.as_ref() == Some(statechain_id))
+        .ok_or(CError::WalletError(WalletErrorType::StateChainNotFound))?;
+    let proof_key = shared_key
+        .proof_key
+        .as_ref()
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+    let proof_key_derivation = wallet
+        .se_proof_keys
+        .get_key_derivation(
+            &PublicKey::from_str(proof_key).map_err(|e| CError::Generic(e.to_string()))?,
+        )
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+
+    let ownership_proof_msg = OwnershipProofMsg {
+        statechain_id: *statechain_id,
+        nonce: nonce.clone(),
+        statechain_sig: StateChainSig::new_ownership_sig(
+            &proof_key_derivation.private_key.key,
+            nonce,
+            statechain_id,
+        )?,
+    };
+    let server_pubkey = wallet.client_shim.pinned_identity_key()?;
+    let signed = prove_ownership_api(&wallet.client_shim, &ownership_proof_msg)?;
+
+    verify_ownership_confirmation(&signed, server_pubkey, statechain_id, nonce)?;
+    if &signed.confirmation.proof_key != proof_key {
+        return Err(CError::Generic(String::from(
+            "Ownership confirmation does not match statecoin proof key",
+        )));
+    }
+    Ok(signed)
+}
@@ -0,0 +1,105 @@
+//! Rotate
+//!
+//! Proof key rotation: the owner of a statecoin whose proof key may be compromised replaces it
+//! with a new proof key of the wallet, without transferring the statecoin. The shared key and
+//! backup tx are unchanged.
+//!
+//! The new proof key is saved in the wallet before it is sent, so that the statecoin can still be
+//! spent if the wallet stops before the rotation is confirmed. The State Entity only rotates a
+//! statecoin in vault mode once the vault delay has passed: call rotate_proof_key() again after
+//! the delay. The same new proof key is then signed for, so the delay is not restarted.
+
+// rotate_proof_key():
+// 0. Derive a new proof key, or reuse that of the rotation in progress, and save the wallet
+// 1. Sign the new proof key with the current proof key, bound to the statechain tip
+// 2. Co-sign with the second factor, if the hook provides it
+// 3. Once the State Entity has appended the new proof key, set it as the coin's proof key
+
+use super::super::Result;
+extern crate shared_lib;
+
+use super::api::{get_statechain, rotate_proof_key as rotate_proof_key_api};
+use crate::error::{CError, WalletErrorType};
+use crate::wallet::wallet::Wallet;
+use shared_lib::state_chain::StateChainSig;
+use shared_lib::structs::{RotateMsg, StateChainDataAPI};
+
+use bitcoin::PublicKey;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Rotate the proof key of a statecoin to a new proof key of the wallet. Returns the new proof
+/// key.
+pub fn rotate_proof_key(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<PublicKey> {
+    let (shared_key_id, rotating_proof_key) = {
+        let shared_key = wallet.get_shared_key_by_statechain_id(statechain_id)?;
+        (shared_key.id, shared_key.rotating_proof_key.clone())
+    };
+    let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, statechain_id)?;
+    let tip = statechain_data.get_tip()?.data;
+    let proof_key = PublicKey::from_str(&tip)
+        .map_err(|e| CError::Generic(format!("Invalid proof key: {}", e)))?;
+
+    // Rotation completed by the State Entity but not recorded in the wallet
+    if rotating_proof_key.as_ref() == Some(&tip) {
+        complete_rotation(wallet, &shared_key_id, &proof_key)?;
+        return Ok(proof_key);
+    }
+
+    let proof_key_priv = wallet
+        .se_proof_keys
+        .get_key_derivation(&proof_key)
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?
+        .private_key;
+
+    let new_proof_key = match rotating_proof_key {
+        Some(key) => PublicKey::from_str(&key)
+            .map_err(|e| CError::Generic(format!("Invalid proof key: {}", e)))?,
+        None => {
+            let key = wallet.se_proof_keys.get_new_key()?;
+            wallet
+                .get_shared_key_mut(&shared_key_id)?
+                .rotating_proof_key = Some(key.to_string());
+            wallet.save();
+            key
+        }
+    };
+
+    let statechain_sig = StateChainSig::new_rotate_sig(
+        &proof_key_priv.key,
+        &new_proof_key.to_string(),
+        statechain_id,
+        (statechain_data.chain.len() - 1) as u64,
+    )?;
+    let auth_sig = wallet.second_factor_sig(statechain_id, &statechain_sig)?;
+    rotate_proof_key_api(
+        &wallet.client_shim,
+        &RotateMsg {
+            shared_key_id,
+            statechain_sig,
+            auth_sig,
+        },
+    )?;
+
+    complete_rotation(wallet, &shared_key_id, &new_proof_key)?;
+    info!(
+        "ROTATE: Proof key of statechain {} rotated to {}",
+        statechain_id, new_proof_key
+    );
+    Ok(new_proof_key)
+}
+
+/// Set the rotated proof key as the proof key of a shared key
+fn complete_rotation(
+    wallet: &mut Wallet,
+    shared_key_id: &Uuid,
+    new_proof_key: &PublicKey,
+) -> Result<()> {
+    {
+        let shared_key = wallet.get_shared_key_mut(shared_key_id)?;
+        shared_key.proof_key = Some(new_proof_key.to_string());
+        shared_key.rotating_proof_key = None;
+    }
+    wallet.save();
+    Ok(())
+}
@@ -0,0 +1,166 @@
+//! Sale
+//!
+//! Escrowed sale of a statecoin in exchange for the preimage of a payment hash, e.g. the
+//! payment hash of a lightning invoice from the seller to the buyer.
+
+// sell():
+// 0. Open a sale of the statecoin with the payment hash
+// 1. Transfer the statecoin to the buyer as usual (transfer_sender)
+
+// sale_receive():
+// 0. Check the sale is open for the agreed payment hash and lifetime
+// 1. Perform transfer_receiver up to the key share update. The State Entity holds the transfer.
+// 2. Check the sale is held for the buyer's proof key. Only then should the buyer pay.
+
+// sale_complete():
+// 0. Reveal the preimage, or wait for the seller to reveal it
+// 1. Finalize the transfer in the wallet once the State Entity has finalized it
+
+use super::super::Result;
+extern crate shared_lib;
+
+use super::api::{sale_get, sale_init, sale_reveal};
+use super::transfer::{
+    transfer_receiver_finalize, transfer_receiver_key_update, transfer_sender, TransferFinalizeData,
+};
+use crate::error::CError;
+use crate::wallet::wallet::Wallet;
+use shared_lib::state_chain::verify_sale_preimage;
+use shared_lib::structs::{
+    SCEAddress, SaleAPI, SaleInitMsg, SaleRevealMsg, SaleStatus, TransferMsg3,
+};
+
+use chrono::{Duration, Utc};
+use std::thread;
+use std::time;
+use uuid::Uuid;
+
+/// Interval in seconds between requests for the status of a held sale
+const SALE_POLL_INTERVAL: u64 = 3;
+
+/// Seller: open an escrowed sale of a statecoin and transfer it to the buyer. The transfer is
+/// held by the State Entity until the preimage of payment_hash is revealed or the sale
+/// expires after lifetime seconds.
+pub fn sell(
+    wallet: &mut Wallet,
+    statechain_id: &Uuid,
+    receiver_addr: SCEAddress,
+    payment_hash: &String,
+    lifetime: u64,
+) -> Result<TransferMsg3> {
+    let shared_key_id = wallet.get_shared_key_by_statechain_id(statechain_id)?.id;
+    let sale = sale_init(
+        &wallet.client_shim,
+        &SaleInitMsg {
+            shared_key_id,
+            payment_hash: payment_hash.clone(),
+            lifetime,
+        },
+    )?;
+    if sale.status != SaleStatus::Open {
+        return Err(CError::Generic(format!(
+            "Sale of statechain {} is not open: {:?}",
+            statechain_id, sale.status
+        )));
+    }
+
+    transfer_sender(wallet, statechain_id, receiver_addr, None)
+}
+
+/// Buyer: receive a statecoin sold for the preimage of payment_hash. The sale must remain open
+/// for at least min_lifetime seconds. Returns the data to finalize the transfer in the wallet
+/// with once the sale is complete.
+///
+/// The buyer should only pay, e.g. the lightning invoice, once this returns successfully, and
+/// the payment must expire before the sale does.
+pub fn sale_receive(
+    wallet: &mut Wallet,
+    transfer_msg3: &mut TransferMsg3,
+    payment_hash: &String,
+    min_lifetime: u64,
+) -> Result<TransferFinalizeData> {
+    let statechain_id = transfer_msg3.statechain_id;
+    let sale = sale_get(&wallet.client_shim, &statechain_id)?;
+    if sale.status != SaleStatus::Open {
+        return Err(CError::Generic(format!(
+            "Sale of statechain {} is not open: {:?}",
+            statechain_id, sale.status
+        )));
+    }
+    if sale.payment_hash.to_lowercase() != payment_hash.to_lowercase() {
+        return Err(CError::Generic(format!(
+            "Sale of statechain {} is for a different payment hash",
+            statechain_id
+        )));
+    }
+    if sale.expires < Utc::now().naive_utc() + Duration::seconds(min_lifetime as i64) {
+        return Err(CError::Generic(format!(
+            "Sale of statechain {} expires too soon: {}",
+            statechain_id, sale.expires
+        )));
+    }
+
+    let finalize_data = transfer_receiver_key_update(wallet, transfer_msg3, &None)?;
+
+    let sale = sale_get(&wallet.client_shim, &statechain_id)?;
+    let buyer_proof_key = transfer_msg3.rec_se_addr.proof_key.to_string();
+    if sale.status != SaleStatus::Held || sale.buyer_proof_key != Some(buyer_proof_key) {
+        return Err(CError::Generic(format!(
+            "Transfer of statechain {} is not held for sale to this wallet",
+            statechain_id
+        )));
+    }
+
+    Ok(finalize_data)
+}
+
+/// Buyer: complete a sale by revealing the preimage, or if None waiting for the seller to
+/// reveal it, then finalize the transfer in the wallet.
+pub fn sale_complete(
+    wallet: &mut Wallet,
+    finalize_data: TransferFinalizeData,
+    preimage: Option<String>,
+) -> Result<()> {
+    let statechain_id = finalize_data.statechain_id;
+    let mut sale = match preimage {
+        Some(preimage) => sale_reveal(
+            &wallet.client_shim,
+            &SaleRevealMsg {
+                statechain_id,
+                preimage,
+            },
+        )?,
+        None => sale_get(&wallet.client_shim, &statechain_id)?,
+    };
+
+    while sale.status == SaleStatus::Held {
+        thread::sleep(time::Duration::from_secs(SALE_POLL_INTERVAL));
+        sale = sale_get(&wallet.client_shim, &statechain_id)?;
+    }
+    if sale.status != SaleStatus::Finalized {
+        return Err(CError::Generic(format!(
+            "Sale of statechain {} was not finalized: {:?}",
+            statechain_id, sale.status
+        )));
+    }
+
+    transfer_receiver_finalize(wallet, finalize_data)
+}
+
+/// Seller: reveal the preimage of a sale paid other than through the payment hash, e.g.
+/// on-chain, finalizing the held transfer to the buyer.
+pub fn sale_reveal_preimage(
+    wallet: &Wallet,
+    statechain_id: &Uuid,
+    preimage: &String,
+) -> Result<SaleAPI> {
+    let sale = sale_get(&wallet.client_shim, statechain_id)?;
+    verify_sale_preimage(preimage, &sale.payment_hash)?;
+    sale_reveal(
+        &wallet.client_shim,
+        &SaleRevealMsg {
+            statechain_id: *statechain_id,
+            preimage: preimage.clone(),
+        },
+    )
+}
@@ -0,0 +1,65 @@
+//! Second Factor
+//!
+//! Registration of a second factor key, e.g. held on a phone, whose signature the State Entity
+//! then requires for transfers, withdrawals and migrations of a statecoin. Signatures are
+//! requested through the wallet's second factor hook.
+
+// set_second_factor():
+// 0. Sign the new key (or its removal) with the owner proof key
+// 1. Co-sign with the current second factor, if the hook provides it, for the change to take
+//    effect immediately. Otherwise the State Entity applies it after the recovery delay.
+
+use super::super::Result;
+extern crate shared_lib;
+
+use super::api::{get_statechain, second_factor_get, second_factor_register};
+use crate::error::{CError, WalletErrorType};
+use crate::wallet::wallet::Wallet;
+use shared_lib::state_chain::StateChainSig;
+use shared_lib::structs::{SecondFactorAPI, SecondFactorMsg, StateChainDataAPI};
+
+use bitcoin::PublicKey;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Register auth_key as the second factor key of a statecoin, or remove the second factor if
+/// None. Check pending_effective of the result for a change delayed by the State Entity.
+pub fn set_second_factor(
+    wallet: &mut Wallet,
+    statechain_id: &Uuid,
+    auth_key: Option<String>,
+) -> Result<SecondFactorAPI> {
+    if let Some(key) = &auth_key {
+        PublicKey::from_str(key)
+            .map_err(|e| CError::Generic(format!("Invalid second factor key: {}", e)))?;
+    }
+    let shared_key_id = wallet.get_shared_key_by_statechain_id(statechain_id)?.id;
+    let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, statechain_id)?;
+    let proof_key = PublicKey::from_str(&statechain_data.get_tip()?.data)
+        .map_err(|e| CError::Generic(format!("Invalid proof key: {}", e)))?;
+    let proof_key_derivation = wallet
+        .se_proof_keys
+        .get_key_derivation(&proof_key)
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+    let statechain_sig = StateChainSig::new_second_factor_key_sig(
+        &proof_key_derivation.private_key.key,
+        &auth_key,
+        statechain_id,
+    )?;
+    let auth_sig = wallet.second_factor_sig(statechain_id, &statechain_sig)?;
+
+    second_factor_register(
+        &wallet.client_shim,
+        &SecondFactorMsg {
+            shared_key_id,
+            auth_key,
+            statechain_sig,
+            auth_sig,
+        },
+    )
+}
+
+/// Get the second factor key registration of a statecoin
+pub fn get_second_factor(wallet: &Wallet, statechain_id: &Uuid) -> Result<SecondFactorAPI> {
+    second_factor_get(&wallet.client_shim, statechain_id)
+}
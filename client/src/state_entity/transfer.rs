@@ -20,16 +20,23 @@ use super::super::Result;
 
 use crate::error::{CError, WalletErrorType};
 use crate::state_entity::{
-    api::{get_smt_proof, get_smt_root, get_statecoin, get_statechain, get_statechain_fee_info},
-    util::{cosign_tx_input, verify_statechain_smt},
+    api::{
+        get_deletion_receipts, get_smt_inclusion_proof, get_statecoin, get_statechain,
+        get_statechain_fee_info, get_transfer_receipts, get_transfer_reject,
+    },
+    util::cosign_tx_input_with_shares,
 };
-use crate::wallet::{key_paths::funding_txid_to_int, wallet::Wallet};
-use crate::{utilities::requests, ClientShim};
-use shared_lib::{ecies::WalletDecryptable, ecies::SelfEncryptable, state_chain::StateChainSig, structs::*, util::{transaction_serialise, transaction_deserialise}};
-use bitcoin::{Address, PublicKey};
+use crate::wallet::{history::Activity, key_paths::funding_txid_to_int, wallet::Wallet};
+use crate::{utilities::requests::{self, Retry}, ClientShim, RequestClass};
+use shared_lib::{ecies::WalletDecryptable, ecies::SelfEncryptable, state_chain::{StateChainSig, verify_deletion_receipts, verify_transfer_receipt, verify_transfer_reject}, structs::*, util::{transaction_serialise, transaction_deserialise, validate_address_network, backup_tx_timelock, backup_tx_timelock_mode, set_backup_tx_timelock}};
+use bitcoin::{Address, PrivateKey, PublicKey};
 use curv::elliptic::curves::traits::{ECPoint, ECScalar};
 use curv::{FE, GE};
+use kms::ecdsa::two_party::MasterKey2;
+use std::collections::{HashSet, VecDeque};
 use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use uuid::Uuid;
 use shared_lib::structs::TransferFinalizeData as TransferFinalizeDataAPI;
 
@@ -42,6 +49,8 @@ pub struct TransferFinalizeData {
     pub proof_key: String,
     pub statechain_id: Uuid,
     pub tx_backup_psm: PrepareSignTxMsg,
+    #[serde(default)]
+    pub batch_id: Option<Uuid>, // set for transfers of a batch, e.g. a swap
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -146,126 +155,422 @@ pub fn transfer_sender(
     receiver_addr: SCEAddress,
     batch_id: Option<Uuid>
 ) -> Result<TransferMsg3> {
-    // Get required shared key data
-    let shared_key_id;
-    let mut prepare_sign_msg;
-    {
-        let shared_key = wallet.get_shared_key_by_statechain_id(statechain_id)?;
-        shared_key_id = shared_key.id.clone();
-        prepare_sign_msg = shared_key
-            .tx_backup_psm
-            .clone()
-            .ok_or(CError::WalletError(WalletErrorType::KeyMissingData))?;
-    }
-
     // Get state entity fee and locktime info
     let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
 
-    // First sign state chain
-    let statecoin_data: StateCoinDataAPI = get_statecoin(&wallet.client_shim, &statechain_id)?;
-    
-    // Get proof key for signing
-    let proof_key_derivation = wallet
-        .se_proof_keys
-        .get_key_derivation(&PublicKey::from_str(&statecoin_data.statecoin.data).unwrap());
-    let statechain_sig = StateChainSig::new(
-        &proof_key_derivation
-            .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?
-            .private_key
-            .key,
-        &String::from("TRANSFER"),
-        &receiver_addr.proof_key.clone().to_string(),
-    )?;
+    let mut sender = TransferSender::prepare(wallet, statechain_id, receiver_addr, batch_id, &se_fee_info)?;
 
     // Init transfer: Send statechain signature or batch data
-    let mut transfer_msg2: TransferMsg2 = requests::postb(
-        &wallet.client_shim,
-        &format!("transfer/sender"),
-        &TransferMsg1 {
-            shared_key_id: shared_key_id.to_owned(),
-            statechain_sig: statechain_sig.clone(),
-            batch_id: batch_id,
-        },
-    )?;
+    let transfer_msg2 = match sender.init() {
+        Ok(m) => m,
+        // Shared key is from an earlier SE key epoch: refresh then retry
+        Err(CError::KeyRefreshRequired(_)) => {
+            sender.refresh_shared_key(wallet)?;
+            sender.init()?
+        }
+        Err(e) => return Err(e),
+    };
 
-    wallet.decrypt(&mut transfer_msg2)?;
+    let transfer_msg3 = sender.sign(transfer_msg2)?;
 
-    let mut tx = transaction_deserialise(&prepare_sign_msg.tx_hex)?;
+    // Mark funds as spent in wallet
+    {
+        let mut shared_key = wallet.get_shared_key_mut(&sender.shared_key_id)?;
+        shared_key.unspent = false;
+    }
+    let activity = match batch_id {
+        Some(_) => Activity::SwapOut,
+        None => Activity::TransferOut,
+    };
+    wallet.record_activity(activity, &sender.shared_key_id, None)?;
+
+    sender.update_msg(&transfer_msg3)?;
+
+    Ok(transfer_msg3)
+}
+
+/// Progress of the sender side of a transfer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TransferStage {
+    /// Not started, or failed before the state chain signature was made
+    Pending,
+    /// New back up tx built and state chain signed
+    Prepared,
+    /// Transfer initiated with the state entity (transfer/sender)
+    Initiated,
+    /// New back up tx co-signed. The coin is treated as spent from here on.
+    Signed,
+    /// Transfer message 3 stored with the state entity for the receiver
+    Complete,
+}
+
+/// Sender side of a transfer after the wallet dependent steps, holding the wallet data needed
+/// to complete the transfer so that the remaining network bound steps can run on another
+/// thread without access to the wallet
+pub struct TransferSender {
+    pub statechain_id: Uuid,
+    pub shared_key_id: Uuid,
+    pub receiver_addr: SCEAddress,
+    pub stage: TransferStage,
+    client_shim: ClientShim,
+    network: String,
+    share: MasterKey2,
+    proof_key: PublicKey,
+    proof_key_priv: PrivateKey,
+    prepare_sign_msg: PrepareSignTxMsg,
+    statechain_sig: StateChainSig,
+    auth_sig: Option<StateChainSig>,
+    batch_id: Option<Uuid>,
+}
 
-    // Update prepare_sign_msg with new owners address, proof key
-    prepare_sign_msg.protocol = Protocol::Transfer;
-    match tx.output.get_mut(0) {
-        Some(v) => match receiver_addr.tx_backup_addr.clone() {
-            Some(v2) => v.script_pubkey = v2.script_pubkey(),
+impl TransferSender {
+    /// Build the new back up tx paying to the receiver and sign the state chain over to the
+    /// receiver proof key
+    pub fn prepare(
+        wallet: &mut Wallet,
+        statechain_id: &Uuid,
+        receiver_addr: SCEAddress,
+        batch_id: Option<Uuid>,
+        se_fee_info: &StateEntityFeeInfoAPI,
+    ) -> Result<Self> {
+        // Receiver backup address must be valid for this network
+        if let Some(addr) = &receiver_addr.tx_backup_addr {
+            validate_address_network(addr, &wallet.get_bitcoin_network())?;
+        }
+
+        // Get required shared key data
+        let (shared_key_id, share, mut prepare_sign_msg) = {
+            let shared_key = wallet.get_shared_key_by_statechain_id(statechain_id)?;
+            if shared_key.exit.is_some() {
+                return Err(CError::Generic(format!(
+                    "Statecoin {} is exiting via its backup tx", statechain_id
+                )));
+            }
+            (
+                shared_key.id.clone(),
+                shared_key.share.clone(),
+                shared_key
+                    .tx_backup_psm
+                    .clone()
+                    .ok_or(CError::WalletError(WalletErrorType::KeyMissingData))?,
+            )
+        };
+
+        let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, &statechain_id)?;
+
+        // Build new back up tx paying to the new owner, which the state chain signature commits to
+        let mut tx = transaction_deserialise(&prepare_sign_msg.tx_hex)?;
+
+        // Update prepare_sign_msg with new owners address, proof key
+        prepare_sign_msg.protocol = Protocol::Transfer;
+        match tx.output.get_mut(0) {
+            Some(v) => match receiver_addr.tx_backup_addr.clone() {
+                Some(v2) => v.script_pubkey = v2.script_pubkey(),
+                None => (),
+            },
             None => (),
-        },
-        None => (),
-    };
-    prepare_sign_msg.proof_key = Some(receiver_addr.proof_key.clone().to_string());
-    //set updated decremented locktime
-    tx.lock_time = statecoin_data.locktime - se_fee_info.interval;
-    prepare_sign_msg.tx_hex = transaction_serialise(&tx);
-
-    // Sign new back up tx
-    let new_backup_witness = {
-        let tmp = cosign_tx_input(wallet, &prepare_sign_msg)?;
-        if tmp.len() != 1 {return Err(CError::Generic(String::from("expected one tx input witness")));}
-        tmp[0].to_owned()
-    };
+        };
+        prepare_sign_msg.proof_key = Some(receiver_addr.proof_key.clone().to_string());
+        //set updated decremented locktime
+        set_backup_tx_timelock(&mut tx, &(statechain_data.locktime - se_fee_info.interval))?;
+        prepare_sign_msg.tx_hex = transaction_serialise(&tx);
+
+        // Sign state chain
+        // Get proof key for signing. Transfer message 2 is encrypted to the same key.
+        let proof_key = PublicKey::from_str(&statechain_data.get_tip()?.data).unwrap();
+        let proof_key_priv = wallet
+            .se_proof_keys
+            .get_key_derivation(&proof_key)
+            .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?
+            .private_key;
+        // Bind the signature to the statechain and the current tip position, and commit to the
+        // new back up tx
+        let statechain_sig = StateChainSig::new_bound_with_backup(
+            &proof_key_priv.key,
+            &String::from("TRANSFER"),
+            &receiver_addr.proof_key.clone().to_string(),
+            statechain_id,
+            (statechain_data.chain.len() - 1) as u64,
+            &tx,
+        )?;
+        let auth_sig = wallet.second_factor_sig(statechain_id, &statechain_sig)?;
+
+        Ok(TransferSender {
+            statechain_id: statechain_id.to_owned(),
+            shared_key_id,
+            receiver_addr,
+            stage: TransferStage::Prepared,
+            client_shim: wallet.client_shim.clone(),
+            network: wallet.network.clone(),
+            share,
+            proof_key,
+            proof_key_priv,
+            prepare_sign_msg,
+            statechain_sig,
+            auth_sig,
+            batch_id,
+        })
+    }
 
-    let mut tx = transaction_deserialise(&prepare_sign_msg.tx_hex)?;
-    // Update back up tx with new witness
-    tx.input[0].witness = new_backup_witness;
-    prepare_sign_msg.tx_hex = transaction_serialise(&tx);
-
-    // Get o1 priv key
-    let shared_key = wallet.get_shared_key(&shared_key_id)?;
-    let o1 = shared_key.share.private.get_private_key();
-
-    // t1 = o1x1
-    let x1 = transfer_msg2.x1.get_fe()?;
-    let t1 = o1 * x1;
-    let t1_encryptable = FESer::from_fe(&t1);
-
-    let mut transfer_msg3 = TransferMsg3 {
-        shared_key_id: shared_key_id.to_owned(),
-        t1: t1_encryptable,
-        statechain_sig,
-        statechain_id: statechain_id.to_owned(),
-        tx_backup_psm: prepare_sign_msg.to_owned(),
-        rec_se_addr: receiver_addr,
-    };
+    /// Init transfer: Send statechain signature or batch data. Returns decrypted transfer
+    /// message 2.
+    pub fn init(&mut self) -> Result<TransferMsg2> {
+        let transfer_msg1 = TransferMsg1 {
+            shared_key_id: self.shared_key_id.to_owned(),
+            statechain_sig: self.statechain_sig.clone(),
+            batch_id: self.batch_id,
+            auth_sig: self.auth_sig.clone(),
+        };
+        let mut transfer_msg2: TransferMsg2 = requests::postb_with(
+            &self.client_shim,
+            &format!("transfer/sender"),
+            &transfer_msg1,
+            Retry::Safe,
+            RequestClass::Sign,
+        )?;
+        if transfer_msg2.proof_key != self.proof_key {
+            return Err(CError::Generic(String::from(
+                "Transfer message 2 encrypted to unexpected proof key",
+            )));
+        }
+        transfer_msg2.decrypt(&self.proof_key_priv)?;
+        self.stage = TransferStage::Initiated;
+        Ok(transfer_msg2)
+    }
 
-    //encrypt then make immutable
-    transfer_msg3.encrypt()?;
-    let transfer_msg3 = transfer_msg3;
+    /// Reload the shared key share after it has been refreshed to the current SE key epoch
+    pub fn refresh_shared_key(&mut self, wallet: &mut Wallet) -> Result<()> {
+        wallet.refresh_shared_key(&self.shared_key_id)?;
+        self.share = wallet.get_shared_key(&self.shared_key_id)?.share.clone();
+        Ok(())
+    }
 
-    // Mark funds as spent in wallet
-    {
-        let mut shared_key = wallet.get_shared_key_mut(&shared_key_id)?;
-        shared_key.unspent = false;
+    /// Co-sign the new back up tx and create transfer message 3 for the receiver
+    pub fn sign(&mut self, transfer_msg2: TransferMsg2) -> Result<TransferMsg3> {
+        // Sign new back up tx
+        let new_backup_witness = {
+            let tmp = cosign_tx_input_with_shares(
+                &self.client_shim,
+                &self.network,
+                &[self.share.clone()],
+                &self.prepare_sign_msg,
+            )?;
+            if tmp.len() != 1 {return Err(CError::Generic(String::from("expected one tx input witness")));}
+            tmp[0].to_owned()
+        };
+
+        let mut tx = transaction_deserialise(&self.prepare_sign_msg.tx_hex)?;
+        // Update back up tx with new witness
+        tx.input[0].witness = new_backup_witness;
+        let mut prepare_sign_msg = self.prepare_sign_msg.clone();
+        prepare_sign_msg.tx_hex = transaction_serialise(&tx);
+        self.stage = TransferStage::Signed;
+
+        // Get o1 priv key
+        let o1 = self.share.private.get_private_key();
+
+        // t1 = o1x1
+        let x1 = transfer_msg2.x1.get_fe()?;
+        let t1 = o1 * x1;
+        let t1_encryptable = FESer::from_fe(&t1);
+
+        let mut transfer_msg3 = TransferMsg3 {
+            shared_key_id: self.shared_key_id.to_owned(),
+            t1: t1_encryptable,
+            statechain_sig: self.statechain_sig.clone(),
+            statechain_id: self.statechain_id.to_owned(),
+            tx_backup_psm: prepare_sign_msg,
+            rec_se_addr: self.receiver_addr.clone(),
+        };
+
+        //encrypt then make immutable
+        transfer_msg3.encrypt()?;
+        Ok(transfer_msg3)
+    }
+
+    /// Update server database with transfer message 3 so that the receiver can get the message
+    pub fn update_msg(&mut self, transfer_msg3: &TransferMsg3) -> Result<()> {
+        requests::postb_with(
+            &self.client_shim,
+            &format!("transfer/update_msg"),
+            transfer_msg3,
+            Retry::Safe,
+            RequestClass::Sign,
+        )?;
+        self.stage = TransferStage::Complete;
+        Ok(())
     }
 
-    //store transfer_msg_3 in db
+    /// Run the network bound steps: init, sign and update_msg
+    pub fn run(&mut self) -> Result<TransferMsg3> {
+        let transfer_msg2 = self.init()?;
+        let transfer_msg3 = self.sign(transfer_msg2)?;
+        self.update_msg(&transfer_msg3)?;
+        Ok(transfer_msg3)
+    }
+}
 
-    // Update server database with transfer message 3 so that
-    // the receiver can get the message
-    requests::postb(
-        &wallet.client_shim,
-        &format!("transfer/update_msg"),
-        &transfer_msg3,
-    )?;
+/// Outcome of one statecoin of a transfer_sender_many batch
+#[derive(Clone, Debug)]
+pub struct TransferManyStatus {
+    pub statechain_id: Uuid,
+    pub receiver_addr: SCEAddress,
+    /// Last stage reached
+    pub stage: TransferStage,
+    /// Transfer message 3 to pass to the receiver, if complete
+    pub transfer_msg3: Option<TransferMsg3>,
+    pub error: Option<String>,
+}
 
-    Ok(transfer_msg3)
+impl TransferManyStatus {
+    pub fn is_complete(&self) -> bool {
+        self.stage == TransferStage::Complete && self.error.is_none()
+    }
+}
+
+/// Per statecoin outcomes of a transfer_sender_many batch, in request order
+#[derive(Clone, Debug)]
+pub struct TransferManyReport {
+    pub statuses: Vec<TransferManyStatus>,
+}
+
+impl TransferManyReport {
+    pub fn completed(&self) -> Vec<&TransferManyStatus> {
+        self.statuses.iter().filter(|s| s.is_complete()).collect()
+    }
+
+    pub fn failed(&self) -> Vec<&TransferManyStatus> {
+        self.statuses.iter().filter(|s| !s.is_complete()).collect()
+    }
+}
+
+fn is_key_refresh_required(e: &CError) -> bool {
+    match e {
+        CError::KeyRefreshRequired(_) => true,
+        _ => false,
+    }
+}
+
+/// Transfer many statecoins, each to its own receiver, running up to max_concurrency sender
+/// flows at once. The wallet dependent steps of each transfer run in turn on the calling
+/// thread and the network bound steps run on worker threads sharing the state entity client
+/// connection pool. A failed transfer does not stop the others: the outcome of each statecoin
+/// is reported. Statecoins whose new back up tx was co-signed are marked spent.
+pub fn transfer_sender_many(
+    wallet: &mut Wallet,
+    transfers: &[(Uuid, SCEAddress)],
+    max_concurrency: usize,
+) -> Result<TransferManyReport> {
+    let mut statuses: Vec<TransferManyStatus> = transfers
+        .iter()
+        .map(|(statechain_id, receiver_addr)| TransferManyStatus {
+            statechain_id: *statechain_id,
+            receiver_addr: receiver_addr.clone(),
+            stage: TransferStage::Pending,
+            transfer_msg3: None,
+            error: None,
+        })
+        .collect();
+
+    // Fee and locktime info is the same for every transfer
+    let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
+
+    let mut queue = VecDeque::new();
+    let mut seen = HashSet::new();
+    for (i, (statechain_id, receiver_addr)) in transfers.iter().enumerate() {
+        if !seen.insert(*statechain_id) {
+            statuses[i].error = Some(String::from("statecoin already in this batch"));
+            continue;
+        }
+        match TransferSender::prepare(wallet, statechain_id, receiver_addr.clone(), None, &se_fee_info) {
+            Ok(sender) => {
+                statuses[i].stage = sender.stage;
+                queue.push_back((i, sender));
+            }
+            Err(e) => statuses[i].error = Some(e.to_string()),
+        }
+    }
+
+    // Bounded worker pool. Workers return each sender with its outcome so that transfers
+    // needing a shared key refresh can be retried with the wallet.
+    let n_workers = max_concurrency.max(1).min(queue.len());
+    let queue = Arc::new(Mutex::new(queue));
+    let (tx, rx) = mpsc::channel();
+    let workers: Vec<_> = (0..n_workers)
+        .map(|_| {
+            let queue = queue.clone();
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                let next = match queue.lock() {
+                    Ok(mut queue) => queue.pop_front(),
+                    Err(_) => None,
+                };
+                let (i, mut sender) = match next {
+                    Some(job) => job,
+                    None => break,
+                };
+                let res = sender.run();
+                if tx.send((i, sender, res)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+
+    let mut refresh = vec![];
+    for (i, mut sender, res) in rx {
+        match res {
+            Err(e) if sender.stage == TransferStage::Prepared && is_key_refresh_required(&e) => {
+                refresh.push((i, sender));
+                continue;
+            }
+            res => record_transfer_outcome(wallet, &mut statuses[i], &mut sender, res)?,
+        }
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    // Shared keys from an earlier SE key epoch: refresh then retry
+    for (i, mut sender) in refresh {
+        let res = sender.refresh_shared_key(wallet).and_then(|_| sender.run());
+        record_transfer_outcome(wallet, &mut statuses[i], &mut sender, res)?;
+    }
+
+    wallet.save();
+    Ok(TransferManyReport { statuses })
+}
+
+fn record_transfer_outcome(
+    wallet: &mut Wallet,
+    status: &mut TransferManyStatus,
+    sender: &mut TransferSender,
+    res: Result<TransferMsg3>,
+) -> Result<()> {
+    status.stage = sender.stage;
+    // Mark funds as spent in wallet once the new back up tx is co-signed
+    if sender.stage >= TransferStage::Signed {
+        let shared_key = wallet.get_shared_key_mut(&sender.shared_key_id)?;
+        shared_key.unspent = false;
+        wallet.record_activity(Activity::TransferOut, &sender.shared_key_id, None)?;
+    }
+    match res {
+        Ok(transfer_msg3) => status.transfer_msg3 = Some(transfer_msg3),
+        Err(e) => status.error = Some(e.to_string()),
+    }
+    Ok(())
 }
 
 // Get the transfer message 3
 // created by the sender and stored in the SE database
 pub fn transfer_get_msg(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<TransferMsg3> {
-    requests::postb(
+    requests::postb_with(
         &wallet.client_shim,
         &format!("transfer/get_msg"),
         &StatechainID {id: *statechain_id},
+        Retry::Safe,
+        RequestClass::Poll,
     )
 }
 
@@ -290,6 +595,25 @@ pub fn transfer_receiver_repeat_keygen(
     transfer_msg3: &mut TransferMsg3,
     batch_data: &Option<BatchData>,
     keygen1_reps: u32
+) -> Result<TransferFinalizeData> {
+    let mut finalize_data = transfer_receiver_key_update(wallet, transfer_msg3, batch_data)?;
+
+    // In batch case this step is performed once all other transfers in the batch are complete.
+    if batch_data.is_none() {
+        // Finalize protocol run by generating new shared key and updating wallet.
+        transfer_receiver_finalize_repeat_keygen(wallet, &mut finalize_data, keygen1_reps)?;
+    }
+
+    Ok(finalize_data)
+}
+
+/// Receiver side of Transfer protocol up to the key share update with the State Entity.
+/// Returns the data to update the wallet with once the State Entity has finalized the
+/// transfer.
+pub fn transfer_receiver_key_update(
+    wallet: &mut Wallet,
+    transfer_msg3: &mut TransferMsg3,
+    batch_data: &Option<BatchData>,
 ) -> Result<TransferFinalizeData> {
     //Decrypt the message on receipt
     match wallet.decrypt(transfer_msg3) {
@@ -316,12 +640,14 @@ pub fn transfer_receiver_repeat_keygen(
     .ok_or(CError::Generic(String::from(
         "Failed to decode ScriptpubKey.",
     )))?;
-    wallet
-        .se_backup_keys
-        .get_address_derivation(&back_up_rec_se_addr.to_string())
-        .ok_or(CError::Generic(String::from(
+    if !wallet.is_own_backup_addr(
+        &back_up_rec_se_addr,
+        &transfer_msg3.rec_se_addr.proof_key.to_string(),
+    ) {
+        return Err(CError::Generic(String::from(
             "Backup Tx receiving address not found in this wallet!",
-        )))?;
+        )));
+    }
 
     // Check locktime of recieved backup transaction
     let chaintip = wallet
@@ -329,10 +655,30 @@ pub fn transfer_receiver_repeat_keygen(
         .instance
         .get_tip_header()?;
     debug!("Transfer receiver: Got current best block height: {}", chaintip.height.to_string());
-    if tx_backup.lock_time <= (chaintip.height as u32) {
-            return Err(CError::Generic(format!(
-                "Error: backup tx locktime ({:?}) expired, blockheight {:?}",tx_backup.lock_time,chaintip.height
-            )));
+    match backup_tx_timelock_mode(&tx_backup) {
+        TimelockMode::Absolute => {
+            if tx_backup.lock_time <= (chaintip.height as u32) {
+                return Err(CError::Generic(format!(
+                    "Error: backup tx locktime ({:?}) expired, blockheight {:?}",tx_backup.lock_time,chaintip.height
+                )));
+            }
+        }
+        TimelockMode::Relative => {
+            // relative timelock expires once the funding tx has enough confirmations
+            let funding_txid = tx_backup.input[0].previous_output.txid.to_string();
+            let confirmations = wallet
+                .electrumx_client
+                .instance
+                .get_transaction_conf_status(funding_txid, false)?
+                .confirmations
+                .unwrap_or(0);
+            if backup_tx_timelock(&tx_backup) <= confirmations {
+                return Err(CError::Generic(format!(
+                    "Error: backup tx relative locktime ({:?}) expired, funding confirmations {:?}",
+                    backup_tx_timelock(&tx_backup), confirmations
+                )));
+            }
+        }
     }
 
     // Check validity of the backup transaction
@@ -345,8 +691,37 @@ pub fn transfer_receiver_repeat_keygen(
     transfer_msg3
         .statechain_sig
         .verify(&prev_owner_proof_key)?;
+    // A bound signature must be for this statechain and its current tip
+    transfer_msg3.statechain_sig.check_binding(
+        &transfer_msg3.statechain_id,
+        (statechain_data.chain.len() - 1) as u64,
+        false,
+    )?;
+    // A signature committing to a backup tx must commit to the received backup tx, which is
+    // recorded in the new state
+    transfer_msg3.statechain_sig.check_backup_tx(&tx_backup)?;
     debug!("State chain signature is valid.");
 
+    // Verify the state entity deleted the key shares of previous owners. Receipts are checked
+    // against the pinned server identity key, which a wallet requiring receipts must have set.
+    if wallet.require_deletion_receipts() || !wallet.client_shim.server_identity_key.is_empty() {
+        let server_pubkey = wallet.client_shim.pinned_identity_key()?;
+        let receipts = get_deletion_receipts(&wallet.client_shim, &transfer_msg3.statechain_id)?;
+        let prev_proof_keys: Vec<String> = if wallet.require_deletion_receipts() {
+            let chain = &statechain_data.chain;
+            chain[..chain.len() - 1].iter().map(|s| s.data.clone()).collect()
+        } else {
+            vec![]
+        };
+        verify_deletion_receipts(
+            &receipts,
+            &transfer_msg3.statechain_id,
+            &prev_proof_keys,
+            server_pubkey,
+        )?;
+        debug!("Key share deletion receipts are valid.");
+    }
+
     // Check signature is for proof key owned by this wallet
     let new_owner_proof_key = transfer_msg3.statechain_sig.data.clone();
     wallet
@@ -402,7 +777,7 @@ pub fn transfer_receiver_repeat_keygen(
 
     // get SE/lockbox public key share
     let s1_pub: S1PubKey =
-        requests::postb(&wallet.client_shim, &format!("transfer/pubkey"), UserID { id: transfer_msg3.shared_key_id, challenge: None })?;
+        requests::postb_with(&wallet.client_shim, &format!("transfer/pubkey"), UserID { id: transfer_msg3.shared_key_id, challenge: None }, Retry::Safe, RequestClass::Sign)?;
 
     let msg4 = &mut TransferMsg4 {
         shared_key_id: transfer_msg3.shared_key_id,
@@ -421,12 +796,17 @@ pub fn transfer_receiver_repeat_keygen(
     let transfer_msg5: TransferMsg5 =
         requests::postb(&wallet.client_shim, &format!("transfer/receiver"), msg4)?;
 
+    // t1 is no longer needed: delete the one-time key it was encrypted to
+    if let Some(enc_key) = transfer_msg3.rec_se_addr.enc_key {
+        wallet.delete_receive_key(&PublicKey { compressed: true, key: enc_key });
+    }
+
     // Update tx_backup_psm shared_key_id with new one
     let mut tx_backup_psm = transfer_msg3.tx_backup_psm.clone();
     tx_backup_psm.shared_key_ids = vec![transfer_msg5.new_shared_key_id.clone()];
 
     // Data to update wallet with transfer. Should only be applied after StateEntity has finalized.
-    let mut finalize_data = TransferFinalizeData {
+    Ok(TransferFinalizeData {
         new_shared_key_id: transfer_msg5.new_shared_key_id,
         o2,
         s2_pub: transfer_msg5.s2_pub,
@@ -434,15 +814,8 @@ pub fn transfer_receiver_repeat_keygen(
         proof_key: transfer_msg3.rec_se_addr.proof_key.clone().to_string(),
         statechain_id: transfer_msg3.statechain_id,
         tx_backup_psm,
-    };
-
-    // In batch case this step is performed once all other transfers in the batch are complete.
-    if batch_data.is_none() {
-        // Finalize protocol run by generating new shared key and updating wallet.
-        transfer_receiver_finalize_repeat_keygen(wallet, &mut finalize_data, keygen1_reps)?;
-    }
-
-    Ok(finalize_data)
+        batch_id: batch_data.as_ref().map(|b| b.id),
+    })
 }
 
 /// Finalize protocol run by generating new shared key and updating wallet.
@@ -462,6 +835,7 @@ pub fn transfer_receiver_finalize_repeat_keygen(
     keygen1_reps: u32
 ) -> Result<()> {
     // Make shared key with new private share
+    wallet.open_ecdsa_channel(&finalize_data.new_shared_key_id, &finalize_data.proof_key)?;
     wallet.gen_shared_key_fixed_secret_key_repeat_keygen(
         &finalize_data.new_shared_key_id,
         &finalize_data.o2.get_element(),
@@ -485,27 +859,42 @@ pub fn transfer_receiver_finalize_repeat_keygen(
     let rec_proof_key = finalize_data.proof_key.clone();
 
     // Verify proof key inclusion in SE sparse merkle tree
-    let root = get_smt_root(&wallet.client_shim)?.unwrap();
     let funding_txid = &finalize_data.statechain_data.utxo.txid.to_string();
-    let proof = get_smt_proof(&wallet.client_shim, &root, funding_txid)?;
-    assert!(verify_statechain_smt(
-        &Some(root.hash()),
+    let (root, proof) = get_smt_inclusion_proof(
+        &wallet.client_shim,
+        funding_txid,
         &rec_proof_key,
-        &proof
-    ));
+        finalize_data.statechain_data.amount,
+    )?;
+    match &proof {
+        Some(proof) => wallet.proof_cache.insert_proof(&root, funding_txid, proof)?,
+        None => wallet.proof_cache.record_root(&root)?,
+    }
 
     let amount = finalize_data.statechain_data.amount.clone();
 
     finalize_data.tx_backup_psm.input_addrs = vec![pk];
     finalize_data.tx_backup_psm.input_amounts = vec![amount];
 
+    let backup_addr = transaction_deserialise(&finalize_data.tx_backup_psm.tx_hex)?
+        .output
+        .get(0)
+        .and_then(|o| Address::from_script(&o.script_pubkey, wallet.get_bitcoin_network()))
+        .map(|a| a.to_string());
+
     // Add state chain id, proof key and SMT inclusion proofs to local SharedKey data
     {
         let shared_key = wallet.get_shared_key_mut(&finalize_data.new_shared_key_id)?;
         shared_key.statechain_id = Some(finalize_data.statechain_id);
         shared_key.tx_backup_psm = Some(finalize_data.tx_backup_psm.clone());
+        shared_key.backup_addr = backup_addr;
         shared_key.add_proof_data(&rec_proof_key, &root, &proof, funding_txid);
     }
+    let activity = match finalize_data.batch_id {
+        Some(_) => Activity::SwapIn,
+        None => Activity::TransferIn,
+    };
+    wallet.record_activity(activity, &finalize_data.new_shared_key_id, None)?;
 
     Ok(())
 }
@@ -571,8 +960,138 @@ pub fn transfer_reveal_nonce(
     )
 }
 
+/// Get the state entity receipts of the transfers of a statechain sent from this wallet. Each
+/// receipt is verified against the pinned server identity key and stored with the sender shared
+/// key as proof of payment. Returns the
+/// receipts of all transfers of the statechain sent from this wallet.
+pub fn transfer_get_receipts(
+    wallet: &mut Wallet,
+    statechain_id: &Uuid,
+) -> Result<Vec<SignedTransferReceipt>> {
+    let server_pubkey = wallet.client_shim.pinned_identity_key()?.clone();
+    let receipts = get_transfer_receipts(&wallet.client_shim, statechain_id)?;
+    let mut sent = vec![];
+    for shared_key in wallet
+        .shared_keys
+        .iter_mut()
+        .filter(|k| k.statechain_id == Some(*statechain_id) && !k.unspent)
+    {
+        let proof_key = match &shared_key.proof_key {
+            Some(proof_key) => proof_key,
+            None => continue,
+        };
+        if let Some(receipt) = receipts.iter().find(|r| &r.receipt.prev_proof_key == proof_key) {
+            verify_transfer_receipt(receipt, &server_pubkey, statechain_id, proof_key)?;
+            shared_key.transfer_receipt = Some(receipt.clone());
+        }
+        if let Some(receipt) = &shared_key.transfer_receipt {
+            sent.push(receipt.clone());
+        }
+    }
+    if sent.is_empty() {
+        return Err(CError::Generic(format!(
+            "No transfer receipt for statechain {} sent from this wallet",
+            statechain_id
+        )));
+    }
+    Ok(sent)
+}
+
+/// Decline an inbound transfer before completing transfer_receiver. The State Entity returns the
+/// statecoin to the sender.
+pub fn transfer_reject(wallet: &Wallet, transfer_msg3: &TransferMsg3) -> Result<()> {
+    let proof_key_priv = wallet
+        .se_proof_keys
+        .get_key_derivation(
+            &PublicKey::from_str(&transfer_msg3.statechain_sig.data)
+                .map_err(|e| CError::Generic(e.to_string()))?,
+        )
+        .ok_or(CError::Generic(String::from(
+            "Transfer Error: StateChain is signed over to proof key not owned by this wallet!",
+        )))?
+        .private_key;
+    let transfer_reject_msg = TransferRejectMsg {
+        statechain_id: transfer_msg3.statechain_id,
+        statechain_sig: StateChainSig::new_transfer_reject_sig(
+            &proof_key_priv.key,
+            &transfer_msg3.statechain_sig,
+            &transfer_msg3.statechain_id,
+        )?,
+    };
+    requests::postb(&wallet.client_shim, "transfer/reject", transfer_reject_msg)
+}
+
+/// Sender: take back a statecoin whose transfer was rejected by the receiver. Returns false if
+/// the last transfer of the statechain was not rejected.
+///
+/// The receiver was given a backup tx with a lower locktime than the sender's, so the statecoin
+/// should be transferred again, e.g. to a new address of this wallet, to replace it.
+pub fn transfer_sender_reclaim(wallet: &mut Wallet, statechain_id: &Uuid) -> Result<bool> {
+    let reject = match get_transfer_reject(&wallet.client_shim, statechain_id)? {
+        Some(r) => r,
+        None => return Ok(false),
+    };
+    verify_transfer_reject(&reject)?;
+
+    // The statecoin is still owned by this wallet if the rejected transfer was its last
+    let statecoin = get_statecoin(&wallet.client_shim, statechain_id)?;
+    let shared_key = match wallet.shared_keys.iter_mut().find(|k| {
+        k.statechain_id == Some(*statechain_id)
+            && k.proof_key.as_ref() == Some(&statecoin.statecoin.data)
+    }) {
+        Some(k) => k,
+        None => return Ok(false),
+    };
+    shared_key.unspent = true;
+    warn!(
+        "Transfer of statechain {} rejected by receiver {}. Transfer the statecoin again to replace its backup tx.",
+        statechain_id, reject.transfer_sig.data
+    );
+    Ok(true)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::wallet::wallet::DEFAULT_TEST_WALLET_LOC;
+    use mockito::mock;
+
+    #[test]
+    #[serial]
+    fn test_transfer_sender_many_partial_failure() {
+        let _m = mock("GET", "/info/fee")
+            .with_header("Content-Type", "application/json")
+            .with_body(serde_json::to_string(&StateEntityFeeInfoAPI::example()).unwrap())
+            .create();
+        let mut wallet = Wallet::new(
+            &[0xcd; 32],
+            &"regtest".to_string(),
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new(mockito::server_url(), None, None),
+            ClientShim::new(mockito::server_url(), None, None),
+        );
+        let addr = wallet.get_new_state_entity_address().unwrap();
+        let statechain_id = Uuid::new_v4();
+
+        let report = transfer_sender_many(
+            &mut wallet,
+            &[(statechain_id, addr.clone()), (statechain_id, addr)],
+            2,
+        )
+        .unwrap();
+        // Each transfer is reported in request order
+        assert_eq!(report.statuses.len(), 2);
+        assert!(report.completed().is_empty());
+        assert_eq!(report.failed().len(), 2);
+        // Statecoin not owned by the wallet fails to prepare
+        assert_eq!(report.statuses[0].stage, TransferStage::Pending);
+        assert!(report.statuses[0].error.is_some());
+        // Duplicate statecoin is rejected
+        assert_eq!(
+            report.statuses[1].error,
+            Some(String::from("statecoin already in this batch"))
+        );
+    }
 
     // use curv::elliptic::curves::traits::{ECPoint, ECScalar};
     // use curv::{FE, GE};
@@ -6,30 +6,50 @@ extern crate shared_lib;
 
 use super::super::utilities::requests;
 use super::super::Result;
+use super::api;
 use crate::ecdsa;
 use crate::wallet::wallet::Wallet;
+use crate::ClientShim;
 
-use shared_lib::structs::PrepareSignTxMsg;
-use shared_lib::util::{transaction_deserialise, get_sighash};
+use shared_lib::state_chain::verify_build_manifest;
+use shared_lib::structs::{BackupCPFPMsg, PrepareSignTxMsg, SignedBuildManifest};
+use shared_lib::util::{transaction_deserialise, transaction_serialise, get_sighash};
+
+use bitcoin::Transaction;
+use kms::ecdsa::two_party::MasterKey2;
+use uuid::Uuid;
 
 use curv::arithmetic::traits::Converter;
 use curv::BigInt;
-use monotree::{
-    hasher::{Blake3, Hasher},
-    tree::verify_proof,
-    {Hash, Proof},
-};
-
-use std::convert::TryInto;
+use monotree::{Hash, Proof};
 
 /// Sign a transaction input with state entity shared wallet. Return signature witness.
 pub fn cosign_tx_input(
     wallet: &mut Wallet,
     prepare_sign_msg: &PrepareSignTxMsg,
+) -> Result<Vec<Vec<Vec<u8>>>> {
+    let mut shares = vec![];
+    for shared_key_id in &prepare_sign_msg.shared_key_ids {
+        let shared_key = wallet.get_shared_key(shared_key_id)?;
+        shares.push(shared_key.share.clone());
+        if let Some(proof_key) = shared_key.proof_key.clone() {
+            wallet.open_ecdsa_channel(shared_key_id, &proof_key)?;
+        }
+    }
+    cosign_tx_input_with_shares(&wallet.client_shim, &wallet.network, &shares, prepare_sign_msg)
+}
+
+/// Sign a transaction input with the given shared key shares, one per input in
+/// prepare_sign_msg.shared_key_ids, without access to the wallet. Return signature witness.
+pub fn cosign_tx_input_with_shares(
+    client_shim: &ClientShim,
+    network: &String,
+    shares: &[MasterKey2],
+    prepare_sign_msg: &PrepareSignTxMsg,
 ) -> Result<Vec<Vec<Vec<u8>>>> {
     // message 1 - send tx data for validation.
     requests::postb(
-        &wallet.client_shim,
+        client_shim,
         &format!("prepare-sign/"),
         prepare_sign_msg,
     )?;
@@ -45,19 +65,16 @@ pub fn cosign_tx_input(
             &i,
             &prepare_sign_msg.input_addrs[i],
             &prepare_sign_msg.input_amounts[i],
-            &wallet.network,
+            network,
         );
 
-        let shared_key = wallet.get_shared_key(&shared_key_id)?;
-        let mk = &shared_key.share;
-
         // co-sign transaction
         let witness = ecdsa::sign(
-            &wallet.client_shim,
+            client_shim,
             BigInt::from_hex(&hex::encode(&sig_hash[..])),
-            &mk,
+            &shares[i],
             prepare_sign_msg.protocol,
-            &shared_key.id,
+            shared_key_id,
         )?;
 
         witnesses.push(witness);
@@ -66,12 +83,45 @@ pub fn cosign_tx_input(
     Ok(witnesses)
 }
 
+/// Bump the fee of a statecoin backup tx: build and sign a CPFP child paying at 'fee_rate'
+/// (sat/vbyte) and submit it to the State Entity to be broadcast alongside the backup tx.
+/// Returns the signed child tx, which may also be broadcast directly once the backup tx is valid.
+pub fn backup_tx_cpfp(
+    wallet: &mut Wallet,
+    statechain_id: &Uuid,
+    fee_rate: &u64,
+) -> Result<Transaction> {
+    let tx_cpfp = wallet.backup_tx_cpfp(statechain_id, fee_rate)?;
+    api::submit_backup_cpfp(
+        &wallet.client_shim,
+        &BackupCPFPMsg {
+            statechain_id: *statechain_id,
+            tx_cpfp_hex: transaction_serialise(&tx_cpfp),
+        },
+    )?;
+    Ok(tx_cpfp)
+}
+
+/// Verify that the statecoin of funding_txid with the given amount is owned by proof_key in
+/// the SMT root
 pub fn verify_statechain_smt(
     root: &Option<Hash>,
+    funding_txid: &String,
     proof_key: &String,
+    amount: u64,
     proof: &Option<Proof>,
 ) -> bool {
-    let entry: &[u8; 32] = proof_key[..32].as_bytes().try_into().unwrap();
-    let hasher = Blake3::new();
-    verify_proof(&hasher, root.as_ref(), &entry, proof.as_ref())
+    shared_lib::state_chain::verify_statechain_smt(root, funding_txid, proof_key, amount, proof)
+}
+
+/// Get the build manifest of the state entity server and verify that it is signed by the pinned
+/// server identity key and was built in release build mode from the audited git_commit
+pub fn verify_server_build(
+    client_shim: &ClientShim,
+    git_commit: &String,
+) -> Result<SignedBuildManifest> {
+    let server_pubkey = client_shim.pinned_identity_key()?;
+    let signed = api::get_build_manifest(client_shim)?;
+    verify_build_manifest(&signed, server_pubkey, git_commit)?;
+    Ok(signed)
 }
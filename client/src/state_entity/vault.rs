@@ -0,0 +1,85 @@
+//! Vault
+//!
+//! Vault mode: the State Entity only completes a transfer, withdrawal or migration of the
+//! statecoin once the vault delay has passed since it was started, and the owner can cancel it
+//! until then.
+//!
+//! A transfer is completed by the receiver as usual once the delay has passed. A withdrawal is
+//! refused by the State Entity until then: call batch_withdraw_init_to_address() again with the
+//! same address after the delay. The statechain sig is then unchanged, so the delay is not
+//! restarted.
+
+// cancel():
+// 0. Get the statechain sig of the operation in progress from the State Entity
+// 1. Sign its cancellation with the owner proof key
+
+use super::super::Result;
+extern crate shared_lib;
+
+use super::api::{get_statechain, vault_cancel, vault_get, vault_set};
+use crate::error::{CError, WalletErrorType};
+use crate::wallet::wallet::Wallet;
+use shared_lib::state_chain::StateChainSig;
+use shared_lib::structs::{StateChainDataAPI, VaultAPI, VaultCancelMsg, VaultMsg};
+
+use bitcoin::secp256k1::key::SecretKey;
+use bitcoin::PublicKey;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Get the owner proof private key of a statecoin
+fn proof_key_priv(wallet: &Wallet, statechain_id: &Uuid) -> Result<SecretKey> {
+    let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, statechain_id)?;
+    let proof_key = PublicKey::from_str(&statechain_data.get_tip()?.data)
+        .map_err(|e| CError::Generic(format!("Invalid proof key: {}", e)))?;
+    Ok(wallet
+        .se_proof_keys
+        .get_key_derivation(&proof_key)
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?
+        .private_key
+        .key)
+}
+
+/// Set the vault delay (seconds) of a statecoin, or disable vault mode with a delay of 0.
+/// Check pending_effective of the result for a shorter delay not yet in effect.
+pub fn set_vault_delay(wallet: &Wallet, statechain_id: &Uuid, delay: u64) -> Result<VaultAPI> {
+    let shared_key_id = wallet.get_shared_key_by_statechain_id(statechain_id)?.id;
+    let statechain_sig =
+        StateChainSig::new_vault_sig(&proof_key_priv(wallet, statechain_id)?, delay, statechain_id)?;
+    vault_set(
+        &wallet.client_shim,
+        &VaultMsg {
+            shared_key_id,
+            delay,
+            statechain_sig,
+        },
+    )
+}
+
+/// Cancel the transfer, withdrawal or migration in progress of a statecoin in vault mode
+pub fn cancel(wallet: &Wallet, statechain_id: &Uuid) -> Result<VaultAPI> {
+    let shared_key_id = wallet.get_shared_key_by_statechain_id(statechain_id)?.id;
+    let pending_sig = vault_get(&wallet.client_shim, statechain_id)?
+        .pending_sig
+        .ok_or(CError::Generic(format!(
+            "Statechain {} has no vault operation in progress",
+            statechain_id
+        )))?;
+    let statechain_sig = StateChainSig::new_vault_cancel_sig(
+        &proof_key_priv(wallet, statechain_id)?,
+        &pending_sig,
+        statechain_id,
+    )?;
+    vault_cancel(
+        &wallet.client_shim,
+        &VaultCancelMsg {
+            shared_key_id,
+            statechain_sig,
+        },
+    )
+}
+
+/// Get the vault policy and operation in progress of a statecoin
+pub fn get_vault(wallet: &Wallet, statechain_id: &Uuid) -> Result<VaultAPI> {
+    vault_get(&wallet.client_shim, statechain_id)
+}
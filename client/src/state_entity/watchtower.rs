@@ -0,0 +1,178 @@
+//! Watchtower
+//!
+//! Delegate the broadcast of statecoin backup txs to a watchtower, which may be the State
+//! Entity or another server, so that statecoins are kept safe while the wallet is offline.
+
+// watchtower_register():
+// 0. Encrypt the signed backup tx of the statecoin to the watchtower key
+// 1. Sign the registration with the statecoin proof key, incrementing its version
+// 2. Register with the watchtower and record the registration in the wallet
+
+// watchtower_revoke():
+// 0. Sign the revocation with the statecoin proof key, incrementing the registration version
+// 1. Revoke with the watchtower and record the revocation in the wallet
+
+// watchtower_update():
+// 0. Revoke the registrations of statecoins transferred or withdrawn
+// 1. Update the registrations of statecoins whose backup tx has changed
+// 2. Optionally register owned statecoins not yet registered, e.g. received by transfer
+
+use super::super::Result;
+extern crate shared_lib;
+
+use crate::error::{CError, WalletErrorType};
+use crate::wallet::shared_key::WatchtowerRegistration;
+use crate::wallet::wallet::Wallet;
+use crate::{utilities::requests, ClientShim};
+use shared_lib::ecies::SelfEncryptable;
+use shared_lib::structs::{WatchtowerKeyAPI, WatchtowerRegisterMsg, WatchtowerRevokeMsg};
+use shared_lib::util::transaction_deserialise;
+
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::PublicKey;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Get the public key that backup txs are encrypted to
+pub fn watchtower_get_key(watchtower_shim: &ClientShim) -> Result<PublicKey> {
+    let key = requests::get::<WatchtowerKeyAPI>(watchtower_shim, "watchtower/key")?.key;
+    PublicKey::from_str(&key)
+        .map_err(|e| CError::Generic(format!("Invalid watchtower key: {}", e)))
+}
+
+/// Private key of the proof key of a statecoin, which authorises its registration
+fn auth_key_priv(wallet: &Wallet, shared_key_id: &Uuid) -> Result<SecretKey> {
+    let proof_key = wallet
+        .get_shared_key(shared_key_id)?
+        .proof_key
+        .clone()
+        .ok_or(CError::Generic(String::from("Statecoin has no proof key")))?;
+    let proof_key = PublicKey::from_str(&proof_key)
+        .map_err(|e| CError::Generic(format!("Invalid proof key: {}", e)))?;
+    Ok(wallet
+        .se_proof_keys
+        .get_key_derivation(&proof_key)
+        .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?
+        .private_key
+        .key)
+}
+
+/// Create or update the registration of the backup tx of a statecoin with a watchtower. The
+/// backup tx is broadcast from the height at which its locktime expires.
+pub fn watchtower_register(
+    wallet: &mut Wallet,
+    watchtower_shim: &ClientShim,
+    shared_key_id: &Uuid,
+) -> Result<WatchtowerRegistration> {
+    let shared_key = wallet.get_shared_key(shared_key_id)?;
+    if !shared_key.unspent {
+        return Err(CError::Generic(format!(
+            "Statecoin spent. Shared key ID: {}",
+            shared_key_id
+        )));
+    }
+    let tx_hex = shared_key
+        .tx_backup_psm
+        .as_ref()
+        .map(|psm| psm.tx_hex.clone())
+        .ok_or(CError::Generic(String::from("Statecoin has no backup tx")))?;
+    let version = shared_key.watchtower.as_ref().map_or(0, |r| r.version) + 1;
+
+    let tx = transaction_deserialise(&tx_hex)?;
+    if tx.input.get(0).map_or(true, |txin| txin.witness.is_empty()) {
+        return Err(CError::Generic(String::from("Backup tx is not signed")));
+    }
+    let height = wallet.electrumx_client.instance.get_tip_header()?.height as u32;
+    let broadcast_height = height + wallet.backup_tx_blocks_remaining(&tx, height)?;
+
+    let mut backup_tx = tx_hex;
+    backup_tx.encrypt_with_pubkey(&watchtower_get_key(watchtower_shim)?)?;
+    let auth_key_priv = auth_key_priv(wallet, shared_key_id)?;
+    let mut register_msg = WatchtowerRegisterMsg {
+        id: *shared_key_id,
+        auth_key: wallet.get_shared_key(shared_key_id)?.proof_key.clone().unwrap_or_default(),
+        version,
+        broadcast_height,
+        backup_tx,
+        sig: String::default(),
+    };
+    register_msg.sign(&auth_key_priv)?;
+    requests::postb::<_, ()>(watchtower_shim, "watchtower/register", &register_msg)?;
+
+    let registration = WatchtowerRegistration {
+        version,
+        backup_txid: tx.txid().to_string(),
+        broadcast_height,
+        revoked: false,
+    };
+    wallet.get_shared_key_mut(shared_key_id)?.watchtower = Some(registration.clone());
+    wallet.save();
+    Ok(registration)
+}
+
+/// Revoke the registration of the backup tx of a statecoin with a watchtower
+pub fn watchtower_revoke(
+    wallet: &mut Wallet,
+    watchtower_shim: &ClientShim,
+    shared_key_id: &Uuid,
+) -> Result<()> {
+    let mut registration = wallet
+        .get_shared_key(shared_key_id)?
+        .watchtower
+        .clone()
+        .ok_or(CError::Generic(format!(
+            "Statecoin not registered with a watchtower. Shared key ID: {}",
+            shared_key_id
+        )))?;
+    registration.version += 1;
+
+    let mut revoke_msg = WatchtowerRevokeMsg {
+        id: *shared_key_id,
+        version: registration.version,
+        sig: String::default(),
+    };
+    revoke_msg.sign(&auth_key_priv(wallet, shared_key_id)?)?;
+    requests::postb::<_, ()>(watchtower_shim, "watchtower/revoke", &revoke_msg)?;
+
+    registration.revoked = true;
+    wallet.get_shared_key_mut(shared_key_id)?.watchtower = Some(registration);
+    wallet.save();
+    Ok(())
+}
+
+/// Bring the watchtower registrations of the wallet up to date, e.g. after each transfer:
+/// registrations of statecoins no longer owned are revoked and those whose backup tx has
+/// changed are updated. If register_new is set, owned statecoins not registered are
+/// registered. Returns the shared key IDs of the registrations changed.
+pub fn watchtower_update(
+    wallet: &mut Wallet,
+    watchtower_shim: &ClientShim,
+    register_new: bool,
+) -> Result<Vec<Uuid>> {
+    let mut revoke = vec![];
+    let mut register = vec![];
+    for shared_key in &wallet.shared_keys {
+        let backup_txid = match &shared_key.tx_backup_psm {
+            Some(psm) => transaction_deserialise(&psm.tx_hex)?.txid().to_string(),
+            None => continue,
+        };
+        match &shared_key.watchtower {
+            Some(r) if r.revoked => (),
+            Some(_) if !shared_key.unspent => revoke.push(shared_key.id),
+            Some(r) if r.backup_txid != backup_txid => register.push(shared_key.id),
+            Some(_) => (),
+            None if register_new && shared_key.unspent && shared_key.exit.is_none() => {
+                register.push(shared_key.id)
+            }
+            None => (),
+        }
+    }
+
+    for shared_key_id in &revoke {
+        watchtower_revoke(wallet, watchtower_shim, shared_key_id)?;
+    }
+    for shared_key_id in &register {
+        watchtower_register(wallet, watchtower_shim, shared_key_id)?;
+    }
+    Ok(revoke.into_iter().chain(register.into_iter()).collect())
+}
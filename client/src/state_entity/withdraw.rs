@@ -8,18 +8,34 @@
 // 2. Co-sign withdraw tx
 // 3. Broadcast withdraw tx
 
+// batch_withdraw():
+// The number of statecoins and the value left after fees are checked against the state entity
+// fee info before the withdrawal is requested, so that it is not authorised for a tx that
+// cannot be co-signed.
+
+// withdraw_all():
+// 0. Split the unspent statecoins into batches of up to MAX_WITHDRAW_BATCH_SIZE, or the state
+//    entity limit if lower
+// 1. Withdraw each batch in a single tx paying the tx fee for its size
+// 2. If a batch fails, withdraw its statecoins one by one so that the others are not held back
+
 use super::super::Result;
 extern crate shared_lib;
 use shared_lib::{
     state_chain::StateChainSig,
-    structs::{PrepareSignTxMsg, Protocol, StateCoinDataAPI, WithdrawMsg1, WithdrawMsg2},
-    util::{transaction_serialise, tx_withdraw_build},
+    structs::{PrepareSignTxMsg, Protocol, StateChainDataAPI, WithdrawMsg1, WithdrawMsg2},
+    util::{
+        transaction_serialise, tx_withdraw_amounts, tx_withdraw_build, validate_address_network,
+        withdraw_inputs_verify,
+    },
 };
 
-use super::api::{get_statechain, get_statecoin, get_statechain_fee_info};
+use super::api::{get_statechain, get_statechain_fee_info};
 use crate::error::{CError, WalletErrorType};
 use crate::state_entity::util::cosign_tx_input;
-use crate::utilities::requests;
+use crate::utilities::requests::{self, Retry};
+use crate::RequestClass;
+use crate::wallet::history::Activity;
 use crate::wallet::wallet::Wallet;
 
 use bitcoin::{consensus, PublicKey};
@@ -28,6 +44,46 @@ use curv::elliptic::curves::traits::ECPoint;
 use std::str::FromStr;
 use uuid::Uuid;
 
+/// Maximum number of statecoins withdrawn in a single tx by withdraw_all()
+pub const MAX_WITHDRAW_BATCH_SIZE: usize = 20;
+/// Virtual size of a signed P2WPKH withdraw tx input
+const WITHDRAW_TX_INPUT_VSIZE: u64 = 68;
+/// Virtual size of a withdraw tx excluding inputs: version, locktime, counts, segwit marker, the
+/// withdraw output (up to 43 vbytes for P2WSH or P2TR) and the state entity fee output
+const WITHDRAW_TX_BASE_VSIZE: u64 = 11 + 43 + 31;
+
+/// Tx fee of a withdraw tx spending n statecoins at fee_rate (sat/vbyte)
+pub fn withdraw_tx_fee(n: usize, fee_rate: &u64) -> u64 {
+    fee_rate * (WITHDRAW_TX_BASE_VSIZE + n as u64 * WITHDRAW_TX_INPUT_VSIZE)
+}
+
+/// Withdraw tx of a withdraw_all() batch
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WithdrawAllTx {
+    pub txid: String,
+    pub statechain_ids: Vec<Uuid>,
+    /// Value paid to the destination
+    pub amount: u64,
+}
+
+/// Outcome of withdraw_all(): the withdraw txs broadcast and the statecoins that could not be
+/// withdrawn, with the error
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WithdrawAllReport {
+    pub txs: Vec<WithdrawAllTx>,
+    pub failed: Vec<(Uuid, String)>,
+}
+
+impl WithdrawAllReport {
+    pub fn withdrawn(&self) -> Vec<Uuid> {
+        self.txs.iter().flat_map(|tx| tx.statechain_ids.clone()).collect()
+    }
+
+    pub fn total_amount(&self) -> u64 {
+        self.txs.iter().map(|tx| tx.amount).sum()
+    }
+}
+
 /// Withdraw coins from state entity. Returns signed withdraw transaction, statechain_id and withdrawn amount.
 pub fn withdraw(wallet: &mut Wallet, statechain_id: &Uuid, tx_fee: &u64) 
     -> Result<(String, Uuid, u64)> {
@@ -45,6 +101,71 @@ pub fn batch_withdraw(wallet: &mut Wallet, statechain_ids: &Vec<Uuid>, tx_fee: &
     Ok((tx_id, statechain_ids.clone(), amount))
 }
 
+/// Withdraw statecoins in a single tx to an address that may not belong to the wallet. Returns
+/// the withdraw txid and the value paid to the address.
+pub fn batch_withdraw_to_address(wallet: &mut Wallet, statechain_ids: &Vec<Uuid>, tx_fee: &u64,
+    address: &bitcoin::Address)
+    -> Result<(String, u64)> {
+    let (shared_key_ids, tx_signed, amount) =
+        batch_withdraw_init_to_address(wallet, statechain_ids, tx_fee, address)?;
+    let tx_id = batch_withdraw_confirm(wallet, &shared_key_ids, address, &tx_signed)?;
+    Ok((tx_id, amount))
+}
+
+/// Number of statecoins withdrawn in a single tx by withdraw_all(): MAX_WITHDRAW_BATCH_SIZE, or
+/// the state entity limit if lower
+pub fn withdraw_batch_size(max_withdraw_inputs: u64) -> usize {
+    match max_withdraw_inputs {
+        0 => MAX_WITHDRAW_BATCH_SIZE,
+        max => MAX_WITHDRAW_BATCH_SIZE.min(max as usize),
+    }
+}
+
+/// Withdraw every unspent statecoin in the wallet to destination, batching up to
+/// withdraw_batch_size() statecoins per tx at fee_rate (sat/vbyte). Statecoins of a batch that
+/// fails are withdrawn one by one, and those that still fail are reported rather than stopping
+/// the sweep.
+pub fn withdraw_all(wallet: &mut Wallet, destination: &bitcoin::Address, fee_rate: &u64)
+    -> Result<WithdrawAllReport> {
+    validate_address_network(destination, &wallet.get_bitcoin_network())?;
+    let statechain_ids: Vec<Uuid> = wallet
+        .shared_keys
+        .iter()
+        .filter(|key| key.unspent && key.exit.is_none())
+        .filter_map(|key| key.statechain_id)
+        .collect();
+
+    let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
+    let mut report = WithdrawAllReport::default();
+    for batch in statechain_ids.chunks(withdraw_batch_size(se_fee_info.max_withdraw_inputs)) {
+        let batch = batch.to_vec();
+        let tx_fee = withdraw_tx_fee(batch.len(), fee_rate);
+        match batch_withdraw_to_address(wallet, &batch, &tx_fee, destination) {
+            Ok((txid, amount)) => {
+                report.txs.push(WithdrawAllTx { txid, statechain_ids: batch, amount });
+                continue;
+            }
+            Err(e) if batch.len() == 1 => {
+                report.failed.push((batch[0], e.to_string()));
+                continue;
+            }
+            Err(e) => warn!("Withdraw of {} statecoins failed, withdrawing one by one: {}", batch.len(), e),
+        }
+        let tx_fee = withdraw_tx_fee(1, fee_rate);
+        for statechain_id in batch {
+            match batch_withdraw_to_address(wallet, &vec![statechain_id], &tx_fee, destination) {
+                Ok((txid, amount)) => report.txs.push(WithdrawAllTx {
+                    txid,
+                    statechain_ids: vec![statechain_id],
+                    amount,
+                }),
+                Err(e) => report.failed.push((statechain_id, e.to_string())),
+            }
+        }
+    }
+    Ok(report)
+}
+
 pub fn withdraw_init(wallet: &mut Wallet, statechain_id: &Uuid, tx_fee: &u64) 
     -> Result<(Uuid, bitcoin::Address, bitcoin::Transaction, u64)> {
     let vec_scid = vec![*statechain_id];
@@ -64,10 +185,24 @@ pub fn batch_withdraw_init(wallet: &mut Wallet, statechain_ids: &Vec<Uuid>, tx_f
     -> Result<(Vec<Uuid>, bitcoin::Address, bitcoin::Transaction, u64)> {
     // Generate receiving address of withdrawn funds
     let rec_se_address = wallet.keys.get_new_address()?;
-    
+    let (shared_key_ids, tx_signed, amount) =
+        batch_withdraw_init_to_address(wallet, statechain_ids, tx_fee, &rec_se_address)?;
+    Ok((shared_key_ids, rec_se_address, tx_signed, amount))
+}
+
+/// Withdraw coins from state entity to an address that may not belong to the wallet, e.g. a
+/// channel funding output. Returns shared key ids, signed withdraw transaction and withdrawn amount.
+pub fn batch_withdraw_init_to_address(wallet: &mut Wallet, statechain_ids: &Vec<Uuid>, tx_fee: &u64,
+    rec_se_address: &bitcoin::Address)
+    -> Result<(Vec<Uuid>, bitcoin::Transaction, u64)> {
+    let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
+    withdraw_inputs_verify(statechain_ids.len(), se_fee_info.max_withdraw_inputs)?;
+
     let mut shared_key_ids=vec![];
     let mut pks = vec![];
     let mut statechain_sigs = vec![];
+    let mut auth_sigs = vec![];
+    let mut sc_infos = vec![];
 
     for statechain_id in statechain_ids{
         // first get required shared key data
@@ -79,8 +214,8 @@ pub fn batch_withdraw_init(wallet: &mut Wallet, statechain_ids: &Vec<Uuid>, tx_f
         }
     
         // Sign state chain
-        let statecoin_data: StateCoinDataAPI = get_statecoin(&wallet.client_shim, &statechain_id)?;
-        if statecoin_data.amount == 0 {
+        let statechain_data: StateChainDataAPI = get_statechain(&wallet.client_shim, &statechain_id)?;
+        if statechain_data.amount == 0 {
             return Err(CError::StateEntityError(String::from(
                 "Withdraw: StateChain is already withdrawn.",
             )));
@@ -88,47 +223,45 @@ pub fn batch_withdraw_init(wallet: &mut Wallet, statechain_ids: &Vec<Uuid>, tx_f
         // get proof key for signing
         let proof_key_derivation = wallet
             .se_proof_keys
-            .get_key_derivation(&PublicKey::from_str(&statecoin_data.statecoin.data).unwrap())
+            .get_key_derivation(&PublicKey::from_str(&statechain_data.get_tip()?.data).unwrap())
             .ok_or(CError::WalletError(WalletErrorType::KeyNotFound));
-        let statechain_sig = StateChainSig::new(
+        // Bind the signature to the statechain and the current tip position
+        let statechain_sig = StateChainSig::new_bound(
             &proof_key_derivation.unwrap().private_key.key,
             &String::from("WITHDRAW"),
             &rec_se_address.to_string(),
+            statechain_id,
+            (statechain_data.chain.len() - 1) as u64,
         )?;
+        auth_sigs.push(wallet.second_factor_sig(statechain_id, &statechain_sig)?);
         statechain_sigs.push(statechain_sig);
+        sc_infos.push(statechain_data);
     }
+
+    // Check the statecoins cover the fees before requesting the withdrawal
+    tx_withdraw_amounts(&sc_infos, &se_fee_info, tx_fee)?;
     
-    // Alert SE of desire of withdraw and receive authorisation if state chain signature verifies
-    requests::postb(
+    // Alert SE of desire of withdraw and receive authorisation if state chain signature verifies.
+    // withdraw/init can be repeated (e.g. to replace the withdrawal tx), so is safe to retry.
+    requests::postb_with(
         &wallet.client_shim,
         &format!("withdraw/init"),
         &WithdrawMsg1 {
             shared_key_ids: shared_key_ids.clone(),
             statechain_sigs: statechain_sigs.clone(),
+            auth_sigs,
         },
+        Retry::Safe,
+        RequestClass::Sign,
     )?;
 
-    let mut sc_infos = vec![];
-    let mut amounts = vec![];
-    let mut total_amount = 0;
-    let se_fee_info = get_statechain_fee_info(&wallet.client_shim)?;
-    
-    
-    for statechain_id in statechain_ids{
-        // Get state chain info
-        let sc_info = get_statechain(&wallet.client_shim, &statechain_id)?;
-        // Get state entity withdraw fee info
-        
-
-        total_amount += sc_info.amount;
-        amounts.push(sc_info.amount.clone());
-        sc_infos.push(sc_info);
-    }
+    let amounts: Vec<u64> = sc_infos.iter().map(|sc_info| sc_info.amount).collect();
+    let total_amount: u64 = amounts.iter().sum();
 
     // Construct withdraw tx
     let tx_withdraw_unsigned = tx_withdraw_build(
         &sc_infos,
-        &rec_se_address,
+        rec_se_address,
         &se_fee_info,
         tx_fee
     )?;
@@ -146,24 +279,32 @@ pub fn batch_withdraw_init(wallet: &mut Wallet, statechain_ids: &Vec<Uuid>, tx_f
 
     
     let mut tx_withdraw_signed = tx_withdraw_unsigned.clone();
-    tx_withdraw_signed.input[0].witness = witness[0].clone();
+    for (input, input_witness) in tx_withdraw_signed.input.iter_mut().zip(witness.iter()) {
+        input.witness = input_witness.clone();
+    }
     
-    Ok((shared_key_ids, rec_se_address, tx_withdraw_signed, total_amount - se_fee_info.withdraw))
+    Ok((shared_key_ids, tx_withdraw_signed, total_amount - se_fee_info.withdraw))
 }
  
 pub fn batch_withdraw_confirm(wallet: &mut Wallet, shared_key_ids: &Vec<Uuid>, 
     rec_se_address: &bitcoin::Address, tx_withdraw_signed: &bitcoin::Transaction) 
     -> Result<String> {
-    let witness: Vec<Vec<Vec<u8>>> = requests::postb(
+    let witness: Vec<Vec<Vec<u8>>> = requests::postb_with(
         &wallet.client_shim,
         &format!("/withdraw/confirm"),
         &WithdrawMsg2 {
             shared_key_ids: shared_key_ids.clone(),
             address: rec_se_address.to_string(),
         },
+        Retry::Unsafe,
+        RequestClass::Confirm,
     )?;
     
-    assert!(tx_withdraw_signed.input[0].witness == witness[0]);
+    assert!(tx_withdraw_signed
+        .input
+        .iter()
+        .zip(witness.iter())
+        .all(|(input, input_witness)| &input.witness == input_witness));
 
     // Mark funds as withdrawn in wallet
     for shared_key_id in shared_key_ids
@@ -179,5 +320,9 @@ pub fn batch_withdraw_confirm(wallet: &mut Wallet, shared_key_ids: &Vec<Uuid>,
         .broadcast_transaction(hex::encode(consensus::serialize(&tx_withdraw_signed.to_owned())))?;
     debug!("Withdraw: Withdrawal tx broadcast. txid: {}", withdraw_txid);
 
+    for shared_key_id in shared_key_ids {
+        wallet.record_activity(Activity::Withdraw, shared_key_id, Some(withdraw_txid.clone()))?;
+    }
+
     Ok(withdraw_txid)
 }
@@ -5,6 +5,7 @@
 use bech32::{self, FromBase32, ToBase32};
 use shared_lib::structs::{SCEAddress,TransferMsg3,FESer,PrepareSignTxMsg, Protocol};
 use shared_lib::state_chain::StateChainSig;
+use shared_lib::util::{backup_tx_timelock, transaction_deserialise};
 use bitcoin::secp256k1;
 use bitcoin::{Address, Network};
 use crate::wallet::wallet::to_bitcoin_public_key;
@@ -14,16 +15,19 @@ use hex;
 use super::super::Result;
 use crate::error::CError;
 
-/// Encode a statechain address (proof key) in bech32 format
+/// Encode a statechain address (proof key and optional one-time receive key) in bech32 format
 pub fn encode_address(sce_address: SCEAddress) -> Result<String> {
 
-	let proof_key = sce_address.proof_key;
-	let encoded = bech32::encode("sc", proof_key.serialize().to_base32()).unwrap();
+	let mut key_bytes = sce_address.proof_key.serialize().to_vec();
+	if let Some(enc_key) = sce_address.enc_key {
+		key_bytes.extend_from_slice(&enc_key.serialize());
+	}
+	let encoded = bech32::encode("sc", key_bytes.to_base32()).unwrap();
 
     Ok(encoded)
 }
 
-/// Encode a statechain address (proof key) in bech32 format
+/// Decode a statechain address (proof key and optional one-time receive key) from bech32 format
 pub fn decode_address(bech32_address: String, network: &String) -> Result<SCEAddress> {
 
 	let (prefix, pubkey) = bech32::decode(&bech32_address).unwrap();
@@ -35,11 +39,21 @@ pub fn decode_address(bech32_address: String, network: &String) -> Result<SCEAdd
 	}
 
 	let keyslice = Vec::<u8>::from_base32(&pubkey).unwrap();
-	let proof_key = secp256k1::PublicKey::from_slice(&keyslice).unwrap();
+	//bytes 0..33 compressed proof key, optional bytes 33..66 compressed one-time receive key
+	let (proof_key, enc_key) = match keyslice.len() {
+		33 => (secp256k1::PublicKey::from_slice(&keyslice)?, None),
+		66 => (
+			secp256k1::PublicKey::from_slice(&keyslice[..33])?,
+			Some(secp256k1::PublicKey::from_slice(&keyslice[33..])?),
+		),
+		_ => return Err(CError::Generic(String::from(
+			"Mercury address incorrect length",
+		))),
+	};
 
     let tx_backup_addr = Some(Address::p2wpkh(&to_bitcoin_public_key(proof_key), network.parse::<Network>().unwrap())?);
 
-    Ok(SCEAddress { tx_backup_addr, proof_key })
+    Ok(SCEAddress { tx_backup_addr, proof_key, enc_key })
 }
 
 // Encode a mercury transaction message in bech32 format
@@ -50,6 +64,25 @@ pub fn encode_message(message: TransferMsg3) -> Result<String> {
 	    )));
 	}
 
+	// a bound statechain signature must be bound to the statechain of the message
+	if let Some(id) = message.statechain_sig.statechain_id {
+		if id != message.statechain_id {
+			return Err(CError::Generic(String::from(
+				"Cannot encode transfer message - statechain signature bound to another statechain",
+			)));
+		}
+	}
+
+	// a backup tx commitment must be to the backup tx of the message, which it is recovered from
+	if message.statechain_sig.backup_txid.is_some() {
+		if message.statechain_sig.position.is_none() {
+			return Err(CError::Generic(String::from(
+				"Cannot encode transfer message - backup tx commitment of unbound statechain signature",
+			)));
+		}
+		message.statechain_sig.check_backup_tx(&transaction_deserialise(&message.tx_backup_psm.tx_hex)?)?;
+	}
+
 	let mut sig_bytes = hex::decode(message.statechain_sig.sig.clone()).unwrap();
 	let mut tx_bytes = hex::decode(message.tx_backup_psm.clone().tx_hex).unwrap();
 
@@ -69,8 +102,20 @@ pub fn encode_message(message: TransferMsg3) -> Result<String> {
 	ser_bytes.append(&mut sig_bytes);
 	//byte sig_len is backup tx length (variable)
 	ser_bytes.push(tx_bytes.len() as u8);
-	//remaining bytes backup tx
+	//bytes tx_len..tx_len+tx_len_bytes backup tx
 	ser_bytes.append(&mut tx_bytes);
+	//optional final 8 bytes: chain position of a bound statechain signature
+	if let Some(position) = message.statechain_sig.position {
+		ser_bytes.extend_from_slice(&position.to_be_bytes());
+	}
+	//optional final byte 0x01: bound statechain signature commits to the backup tx
+	if message.statechain_sig.backup_txid.is_some() {
+		ser_bytes.push(1);
+	}
+	//optional final 33 bytes: compressed one-time receive key t1 is encrypted to
+	if let Some(enc_key) = message.rec_se_addr.enc_key {
+		ser_bytes.extend_from_slice(&enc_key.serialize());
+	}
 
 	let bech32_encoded = bech32::encode("mm",ser_bytes.to_base32()).unwrap();
 
@@ -104,8 +149,41 @@ pub fn decode_message(message: String, network: &String) -> Result<TransferMsg3>
 	let sig_bytes = &decoded_bytes[191..sig_len];
 	//byte sig_len is backup tx length (variable)
 	let tx_len = (decoded_bytes[sig_len] as usize) + sig_len.clone() + 1;
-	//remaining bytes backup tx
+	//bytes tx_len..tx_len+tx_len_bytes backup tx
 	let tx_bytes = &decoded_bytes[(sig_len+1)..tx_len];
+	//optional final 33 bytes: compressed one-time receive key t1 is encrypted to
+	let (decoded_bytes, enc_key) = match decoded_bytes.len() - tx_len {
+		33 | 41 | 42 => {
+			let split = decoded_bytes.len() - 33;
+			(&decoded_bytes[..split], Some(secp256k1::PublicKey::from_slice(&decoded_bytes[split..])?))
+		},
+		_ => (&decoded_bytes[..], None),
+	};
+	//optional final 8 bytes: chain position of a bound statechain signature
+	//optional final byte 0x01: bound statechain signature commits to the backup tx
+	let (position, commits_backup) = match decoded_bytes.len() - tx_len {
+		0 => (None, false),
+		8 | 9 => {
+			let mut position_bytes = [0u8; 8];
+			position_bytes.copy_from_slice(&decoded_bytes[tx_len..tx_len + 8]);
+			(Some(u64::from_be_bytes(position_bytes)), decoded_bytes.get(tx_len + 8) == Some(&1))
+		},
+		_ => return Err(CError::Generic(String::from(
+			"Mercury transfer message incorrect length",
+		))),
+	};
+	if decoded_bytes.len() - tx_len == 9 && !commits_backup {
+		return Err(CError::Generic(String::from(
+			"Mercury transfer message incorrect backup tx commitment flag",
+		)));
+	}
+	let (backup_txid, locktime) = if commits_backup {
+		let tx = transaction_deserialise(&hex::encode(tx_bytes))?;
+		(Some(tx.txid().to_string()), Some(backup_tx_timelock(&tx)))
+	} else {
+		(None, None)
+	};
+	let statechain_id = Uuid::from_bytes(&statechain_id_bytes.clone()).unwrap();
 
 	let proof_key = secp256k1::PublicKey::from_slice(&proof_key_bytes.clone()).unwrap();
     let tx_backup_addr = Some(Address::p2wpkh(&to_bitcoin_public_key(proof_key), network.parse::<Network>().unwrap())?);
@@ -127,12 +205,17 @@ pub fn decode_message(message: String, network: &String) -> Result<TransferMsg3>
 		    purpose: "TRANSFER".to_string(),
 		    data: hex::encode(proof_key_bytes.clone()),
 		    sig: hex::encode(sig_bytes),
+		    statechain_id: position.map(|_| statechain_id),
+		    position,
+		    backup_txid,
+		    locktime,
 	    },
-	    statechain_id: Uuid::from_bytes(&statechain_id_bytes.clone()).unwrap(),
+	    statechain_id,
 	    tx_backup_psm: tx_backup_psm,
 	    rec_se_addr: SCEAddress {
 	    	tx_backup_addr,
 	    	proof_key: proof_key,
+	    	enc_key,
 	    },
 	};
 
@@ -144,6 +227,7 @@ pub fn decode_message(message: String, network: &String) -> Result<TransferMsg3>
 mod tests {
 
     use super::*;
+    use std::str::FromStr;
 	static SCEADDR: &str = "{ \"tx_backup_addr\": \"bcrt1q28jhk2vkyksvxa2lrqzsc6z2dt0ac4xpvlhtj5\", \"proof_key\": \"0284cbb3019459e603b5242d8602ba2d14b8d9fb238782048287be32eb00dafa66\" }";
 	static TRANSFER_MSG_3: &str = "{ \"shared_key_id\": \"bec09086-ff5a-4654-984e-4b0722c0dbef\", \"t1\": { \"secret_bytes\": [4, 205, 61, 74, 107, 173, 231, 32, 22, 93, 82, 80, 211, 251, 184, 165, 79, 197, 216, 194, 220, 25, 70, 222, 238, 52, 240, 157, 53, 165, 104, 149, 153, 132, 142, 229, 190, 165, 226, 25, 119, 137, 87, 104, 178, 156, 169, 102, 129, 252, 176, 240, 83, 148, 121, 98, 210, 191, 23, 22, 115, 156, 71, 113, 175, 173, 176, 159, 160, 69, 197, 40, 61, 239, 140, 47, 222, 195, 29, 68, 112, 228, 38, 84, 43, 255, 108, 159, 153, 4, 60, 94, 250, 35, 184, 16, 152, 111, 178, 78, 89, 209, 85, 237, 93, 81, 203, 199, 157, 104, 62, 9, 178, 146, 8, 106, 34, 224, 35, 228, 161, 99, 162, 119, 56 ] }, \"statechain_sig\": { \"purpose\": \"TRANSFER\", \"data\": \"032bba3673baecf8bb9ab9a7d8a56406595325d6ac18cb42ccb9f79c3d775018a4\", \"sig\": \"304402203647888e56952bb15ae9d566a36a6a13cbd19850a3e01c93e81ab03665845a1b02205811701164799f97cf875d5eeac776cab4033196a899168d8332b87bf3793f6f\" }, \"statechain_id\": \"9fd31ccb-e6c1-498c-80be-e8d9deec7c79\", \"tx_backup_psm\": { \"shared_key_ids\": [\"bec09086-ff5a-4654-984e-4b0722c0dbef\"], \"protocol\": \"Transfer\", \"tx_hex\": \"020000000001014e3e3b35c39ac305aaa3dc364c7378fceaf3cd124101e4f234672a51e74c17d10000000000ffffffff011fae01000000000016001451e57b299625a0c3755f18050c684a6adfdc54c102483045022100de6849daa364f55bdbff15a24250dad308110fbf5c32e02259349ca23c41e1e702201efcee6590fac368585172a9ac31281055e3590e9478ba954500e49cd51be012012102992a0ce40f87d9bf333dbbf60b726b5023fc10c2838179b66c577cb843bf2355a5080000\", \"input_addrs\": [\"0347da6a8ec18b6f2d884b295ab7be01163dd28b555b145e2f260975b061e3c689\"], \"input_amounts\": [111111], \"proof_key\": \"032bba3673baecf8bb9ab9a7d8a56406595325d6ac18cb42ccb9f79c3d775018a4\" }, \"rec_se_addr\": { \"tx_backup_addr\": \"bcrt1q28jhk2vkyksvxa2lrqzsc6z2dt0ac4xpvlhtj5\", \"proof_key\": \"0284cbb3019459e603b5242d8602ba2d14b8d9fb238782048287be32eb00dafa66\" } }";
 
@@ -177,5 +261,67 @@ mod tests {
         assert_eq!(transfer_msg_3.shared_key_id,decmsg.shared_key_id);
         assert_eq!(transfer_msg_3.t1,decmsg.t1);
         assert_eq!(transfer_msg_3.statechain_id,decmsg.statechain_id);
+        assert_eq!(transfer_msg_3.statechain_sig,decmsg.statechain_sig);
+    }
+
+    #[test]
+    fn test_message_encoding_bound_sig() {
+        let mut transfer_msg_3 =
+            serde_json::from_str::<TransferMsg3>(&TRANSFER_MSG_3.to_string()).unwrap();
+        transfer_msg_3.statechain_sig.statechain_id = Some(transfer_msg_3.statechain_id);
+        transfer_msg_3.statechain_sig.position = Some(3);
+
+        let b32enc = encode_message(transfer_msg_3.clone()).unwrap();
+        let decmsg = decode_message(b32enc, &"bitcoin".to_string()).unwrap();
+        assert_eq!(transfer_msg_3.statechain_sig,decmsg.statechain_sig);
+
+        // bound to a different statechain
+        transfer_msg_3.statechain_sig.statechain_id = Some(Uuid::new_v4());
+        assert!(encode_message(transfer_msg_3).is_err());
+    }
+
+    #[test]
+    fn test_message_encoding_backup_commitment() {
+        let mut transfer_msg_3 =
+            serde_json::from_str::<TransferMsg3>(&TRANSFER_MSG_3.to_string()).unwrap();
+        let tx = transaction_deserialise(&transfer_msg_3.tx_backup_psm.tx_hex).unwrap();
+        transfer_msg_3.statechain_sig.statechain_id = Some(transfer_msg_3.statechain_id);
+        transfer_msg_3.statechain_sig.position = Some(3);
+        transfer_msg_3.statechain_sig.backup_txid = Some(tx.txid().to_string());
+        transfer_msg_3.statechain_sig.locktime = Some(backup_tx_timelock(&tx));
+
+        // the commitment is recovered from the backup tx in the message
+        let b32enc = encode_message(transfer_msg_3.clone()).unwrap();
+        let decmsg = decode_message(b32enc, &"bitcoin".to_string()).unwrap();
+        assert_eq!(transfer_msg_3.statechain_sig,decmsg.statechain_sig);
+
+        // committed to a different backup tx
+        transfer_msg_3.statechain_sig.locktime = Some(backup_tx_timelock(&tx) - 1);
+        assert!(encode_message(transfer_msg_3).is_err());
+    }
+
+    #[test]
+    fn test_encoding_receive_key() {
+        let mut sce_address = serde_json::from_str::<SCEAddress>(&SCEADDR.to_string()).unwrap();
+        sce_address.enc_key = Some(secp256k1::PublicKey::from_str(
+            "032bba3673baecf8bb9ab9a7d8a56406595325d6ac18cb42ccb9f79c3d775018a4",
+        ).unwrap());
+
+        let bech32_encoded = encode_address(sce_address.clone()).unwrap();
+        assert_eq!(decode_address(bech32_encoded, &"regtest".to_string()).unwrap(), sce_address);
+
+        // with and without a bound statechain signature
+        let mut transfer_msg_3 =
+            serde_json::from_str::<TransferMsg3>(&TRANSFER_MSG_3.to_string()).unwrap();
+        transfer_msg_3.rec_se_addr.enc_key = sce_address.enc_key;
+        let decmsg = decode_message(encode_message(transfer_msg_3.clone()).unwrap(), &"bitcoin".to_string()).unwrap();
+        assert_eq!(decmsg.rec_se_addr.enc_key, sce_address.enc_key);
+        assert_eq!(transfer_msg_3.statechain_sig,decmsg.statechain_sig);
+
+        transfer_msg_3.statechain_sig.statechain_id = Some(transfer_msg_3.statechain_id);
+        transfer_msg_3.statechain_sig.position = Some(3);
+        let decmsg = decode_message(encode_message(transfer_msg_3.clone()).unwrap(), &"bitcoin".to_string()).unwrap();
+        assert_eq!(decmsg.rec_se_addr.enc_key, sce_address.enc_key);
+        assert_eq!(transfer_msg_3.statechain_sig,decmsg.statechain_sig);
     }
 }
@@ -1,24 +1,231 @@
 //! Requests
 //!
-//! Send requests and decode responses
+//! Send requests and decode responses. Each attempt of a request is sent to the first endpoint
+//! of the client shim that is up (see endpoint.rs), so that retries fail over to the fallback
+//! endpoints.
 
 use floating_duration::TimeFormat;
 use serde;
 use std::time::Instant;
 
-use super::super::ClientShim;
-use super::super::Result;
+use super::super::{ClientShim, RequestClass, Result};
+use crate::endpoint::Endpoint;
 use crate::error::CError;
+use rand::Rng;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use shared_lib::state_chain::{
+    verify_response, ErrorCode, ERROR_CODE_HEADER, NONCE_HEADER, RESPONSE_SIG_HEADER,
+};
+use std::error::Error;
 
+const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// True if the server responded with a CBOR encoded body. Errors are always
+/// returned as text, so a CBOR response is never a state entity error.
+fn is_cbor(response: &reqwest::blocking::Response) -> bool {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map_or(false, |ct| ct.starts_with(CBOR_CONTENT_TYPE))
+}
+
+fn decode_cbor<V>(bytes: &[u8]) -> Result<V>
+where
+    V: serde::de::DeserializeOwned,
+{
+    serde_cbor::from_slice(bytes)
+        .map_err(|e| CError::Generic(format!("failed to decode CBOR response: {}", e)))
+}
+
+/// Random nonce the response to a request must be signed for. None if responses are not
+/// verified.
+fn request_nonce(client_shim: &ClientShim) -> Option<String> {
+    match client_shim.verify_responses {
+        true => Some(hex::encode(rand::thread_rng().gen::<[u8; 16]>())),
+        false => None,
+    }
+}
+
+fn response_sig(response: &reqwest::blocking::Response) -> Option<String> {
+    response
+        .headers()
+        .get(RESPONSE_SIG_HEADER)
+        .and_then(|sig| sig.to_str().ok())
+        .map(String::from)
+}
+
+/// Check that a response body is signed by the server identity key for the request nonce and
+/// path. Errors are not signed, so only successful responses are checked.
+fn check_response_sig(
+    client_shim: &ClientShim,
+    nonce: &Option<String>,
+    path: &str,
+    sig: &Option<String>,
+    body: &[u8],
+) -> Result<()> {
+    let nonce = match nonce {
+        Some(n) => n,
+        None => return Ok(()),
+    };
+    let sig = sig.as_ref().ok_or(CError::Generic(format!(
+        "(req {}) response is not signed by the state entity",
+        path
+    )))?;
+    verify_response(&client_shim.server_identity_key, sig, nonce, path, body).map_err(|e| {
+        CError::Generic(format!(
+            "(req {}) invalid state entity response signature: {}",
+            path, e
+        ))
+    })
+}
+
+/// Whether a request may be repeated after a transient failure
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Retry {
+    /// Not safe to repeat. Only retried if the server cannot have processed the request: the
+    /// connection was refused or the request was rejected by the rate limiter.
+    Unsafe,
+    /// Safe to repeat (reads and idempotent protocol steps). Also retried after timeouts,
+    /// dropped connections and state entity "try again" errors.
+    Safe,
+}
+
+/// Failed request attempt
+enum Failure {
+    /// The server has not processed the request
+    NotProcessed(CError),
+    /// The server may have processed the request
+    Uncertain(CError),
+    /// Not retried
+    Permanent(CError),
+}
+
+impl From<CError> for Failure {
+    fn from(e: CError) -> Self {
+        Failure::Permanent(e)
+    }
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Self {
+        if connection_refused(&e) {
+            Failure::NotProcessed(CError::from(e))
+        } else {
+            Failure::Uncertain(CError::from(e))
+        }
+    }
+}
+
+fn connection_refused(e: &reqwest::Error) -> bool {
+    let mut source = e.source();
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            return io_err.kind() == std::io::ErrorKind::ConnectionRefused;
+        }
+        source = err.source();
+    }
+    false
+}
+
+fn error_code(response: &reqwest::blocking::Response) -> Option<ErrorCode> {
+    response
+        .headers()
+        .get(ERROR_CODE_HEADER)
+        .and_then(|code| code.to_str().ok())
+        .and_then(ErrorCode::from_str)
+}
+
+/// Classify a state entity error response
+fn state_entity_error(text: String, code: Option<ErrorCode>) -> Failure {
+    if code == Some(ErrorCode::KeyRefreshRequired) {
+        Failure::Permanent(CError::KeyRefreshRequired(text))
+    } else if text.contains("due to rate limit") {
+        Failure::NotProcessed(CError::StateEntityError(text))
+    } else if text.contains("Error: try again") {
+        Failure::Uncertain(CError::StateEntityError(text))
+    } else {
+        Failure::Permanent(CError::StateEntityError(text))
+    }
+}
+
+/// Check that an endpoint is reachable and serving requests
+pub fn health_check(client_shim: &ClientShim, endpoint: &Endpoint) -> bool {
+    client_shim
+        .client
+        .get(&format!("{}/ping", endpoint.url()))
+        .timeout(client_shim.timeouts.get(RequestClass::Poll))
+        .send()
+        .map_or(false, |r| r.status().is_success())
+}
+
+/// Make attempts of a request according to the client shim retry policy. Each attempt is
+/// given the index of the endpoint to send it to.
+fn with_retry<V, F>(client_shim: &ClientShim, path: &str, retry: Retry, attempt: F) -> Result<V>
+where
+    F: Fn(usize) -> std::result::Result<V, Failure>,
+{
+    let mut n = 1;
+    loop {
+        let endpoint = client_shim
+            .endpoints
+            .select(|e| health_check(client_shim, e));
+        let e = match attempt(endpoint) {
+            Ok(v) => return Ok(v),
+            Err(Failure::Permanent(e)) => return Err(e),
+            Err(Failure::Uncertain(e)) if retry == Retry::Unsafe => return Err(e),
+            Err(Failure::NotProcessed(e)) | Err(Failure::Uncertain(e)) => e,
+        };
+        if n >= client_shim.retry.max_attempts {
+            return Err(e);
+        }
+        let delay = client_shim.retry.backoff(n);
+        warn!(
+            "(req {}) attempt {} failed: {}. Retrying in {} ms",
+            path,
+            n,
+            e,
+            delay.as_millis()
+        );
+        std::thread::sleep(delay);
+        n += 1;
+    }
+}
+
+/// POST a protocol message with the signing timeout. The request is only retried if it was not
+/// processed by the server.
 pub fn postb<T, V>(client_shim: &ClientShim, path: &str, body: T) -> Result<V>
 where
     T: serde::ser::Serialize,
     V: serde::de::DeserializeOwned,
 {
-    _postb(client_shim, path, body)
+    postb_with(client_shim, path, body, Retry::Unsafe, RequestClass::Sign)
 }
 
-fn _postb<T, V>(client_shim: &ClientShim, path: &str, body: T) -> Result<V>
+/// POST with a per-call retry behaviour and timeout
+pub fn postb_with<T, V>(
+    client_shim: &ClientShim,
+    path: &str,
+    body: T,
+    retry: Retry,
+    class: RequestClass,
+) -> Result<V>
+where
+    T: serde::ser::Serialize,
+    V: serde::de::DeserializeOwned,
+{
+    with_retry(client_shim, path, retry, |endpoint| {
+        _postb(client_shim, endpoint, path, &body, class)
+    })
+}
+
+fn _postb<T, V>(
+    client_shim: &ClientShim,
+    endpoint: usize,
+    path: &str,
+    body: &T,
+    class: RequestClass,
+) -> std::result::Result<V, Failure>
 where
     T: serde::ser::Serialize,
     V: serde::de::DeserializeOwned,
@@ -28,46 +235,101 @@ where
 
     let mut b = client_shim
         .client
-        .post(&format!("{}/{}", client_shim.endpoint, path));
+        .post(&format!("{}/{}", client_shim.endpoints.get()[endpoint].url(), path))
+        .timeout(client_shim.timeouts.get(class));
 
     if client_shim.auth_token.is_some() {
         b = b.bearer_auth(client_shim.auth_token.clone().unwrap());
     }
 
+    let nonce = request_nonce(client_shim);
+    if let Some(n) = &nonce {
+        b = b.header(NONCE_HEADER, n.as_str());
+    }
+
+    if client_shim.cbor {
+        let bytes = serde_cbor::to_vec(&body)
+            .map_err(|e| CError::Generic(format!("failed to CBOR encode request: {}", e)))?;
+        b = b
+            .header(CONTENT_TYPE, CBOR_CONTENT_TYPE)
+            .header(ACCEPT, CBOR_CONTENT_TYPE)
+            .body(bytes);
+    } else {
+        b = b.json(body);
+    }
+
     // catch reqwest errors
-    let value = match b.json(&body).send() {
+    let value = match b.send() {
         Ok(v) => {
+            client_shim.endpoints.report_success(endpoint);
             //Reject responses that are too long
             match v.content_length() {
                 Some(l) => {
                     if l > 1000000 {
                         info!("POST value ignored because of size: {}", l);
-                        return Err(CError::Generic(format!(
+                        return Err(Failure::Permanent(CError::Generic(format!(
                             "POST value ignored because of size: {}",
                             l
-                        )));
+                        ))));
                     }
                 }
                 None => (),
             };
 
+            let sig = response_sig(&v);
+            if is_cbor(&v) {
+                let bytes = v.bytes()?;
+                info!("(req {}, took: {})", path, TimeFormat(start.elapsed()));
+                check_response_sig(client_shim, &nonce, path, &sig, &bytes)?;
+                return Ok(decode_cbor(&bytes)?);
+            }
+
+            let code = error_code(&v);
             let text = v.text()?;
 
             if text.contains(&String::from("Error: ")) {
-                return Err(CError::StateEntityError(text));
+                return Err(state_entity_error(text, code));
             }
 
+            check_response_sig(client_shim, &nonce, path, &sig, text.as_bytes())?;
             text
         }
 
-        Err(e) => return Err(CError::from(e)),
+        Err(e) => {
+            client_shim.endpoints.report_failure(endpoint);
+            return Err(Failure::from(e));
+        }
     };
 
     info!("(req {}, took: {})", path, TimeFormat(start.elapsed()));
     Ok(serde_json::from_str(value.as_str()).expect(&format!("failed to parse: {}", value.as_str())))
 }
 
+/// GET with the poll timeout. Reads are safe to repeat, so the request is retried on any
+/// transient failure.
 pub fn get<V>(client_shim: &ClientShim, path: &str) -> Result<V>
+where
+    V: serde::de::DeserializeOwned,
+{
+    get_with(client_shim, path, RequestClass::Poll)
+}
+
+/// GET with a per-call timeout
+pub fn get_with<V>(client_shim: &ClientShim, path: &str, class: RequestClass) -> Result<V>
+where
+    V: serde::de::DeserializeOwned,
+{
+    with_retry(client_shim, path, Retry::Safe, |endpoint| {
+        _get(client_shim, endpoint, path, class)
+    })
+}
+
+fn _get<V>(
+    client_shim: &ClientShim,
+    endpoint: usize,
+    path: &str,
+    class: RequestClass,
+) -> std::result::Result<V, Failure>
 where
     V: serde::de::DeserializeOwned,
 {
@@ -76,16 +338,40 @@ where
 
     let mut b = client_shim
         .client
-        .get(&format!("{}/{}", client_shim.endpoint, path));
+        .get(&format!("{}/{}", client_shim.endpoints.get()[endpoint].url(), path))
+        .timeout(client_shim.timeouts.get(class));
 
     if client_shim.auth_token.is_some() {
         b = b.bearer_auth(client_shim.auth_token.clone().unwrap());
     }
 
+    if client_shim.cbor {
+        b = b.header(ACCEPT, CBOR_CONTENT_TYPE);
+    }
+
+    let nonce = request_nonce(client_shim);
+    if let Some(n) = &nonce {
+        b = b.header(NONCE_HEADER, n.as_str());
+    }
+
     // catch reqwest errors
-    let value = match b.send() {
-        Ok(v) => v.text().unwrap(),
-        Err(e) => return Err(CError::from(e)),
+    let (value, sig, code) = match b.send() {
+        Ok(v) => {
+            client_shim.endpoints.report_success(endpoint);
+            let sig = response_sig(&v);
+            if is_cbor(&v) {
+                let bytes = v.bytes()?;
+                info!("(req {}, took: {})", path, TimeFormat(start.elapsed()));
+                check_response_sig(client_shim, &nonce, path, &sig, &bytes)?;
+                return Ok(decode_cbor(&bytes)?);
+            }
+            let code = error_code(&v);
+            (v.text()?, sig, code)
+        }
+        Err(e) => {
+            client_shim.endpoints.report_failure(endpoint);
+            return Err(Failure::from(e));
+        }
     };
 
     info!("GET return value: {:?}", value);
@@ -94,8 +380,221 @@ where
 
     // catch State entity errors
     if value.contains(&String::from("Error: ")) {
-        return Err(CError::StateEntityError(value));
+        return Err(state_entity_error(value, code));
     }
+    check_response_sig(client_shim, &nonce, path, &sig, value.as_bytes())?;
 
     Ok(serde_json::from_str(value.as_str()).unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::Endpoints;
+    use crate::RetryPolicy;
+    use mockito::mock;
+
+    fn test_client_shim() -> ClientShim {
+        let mut client_shim = ClientShim::new(mockito::server_url(), None, None);
+        client_shim.retry = RetryPolicy {
+            max_attempts: 3,
+            backoff_ms: 1,
+            max_backoff_ms: 2,
+        };
+        client_shim
+    }
+
+    #[test]
+    fn test_failover_to_fallback_endpoint() {
+        // Nothing listens on the primary endpoint
+        let endpoints = Endpoints::from_urls(&[
+            String::from("http://127.0.0.1:1"),
+            mockito::server_url(),
+        ])
+        .unwrap();
+        let mut client_shim = ClientShim::with_endpoints(endpoints, None, None).unwrap();
+        client_shim.retry = test_client_shim().retry;
+
+        let m = mock("GET", "/failover/get")
+            .with_body("\"fallback\"")
+            .expect(2)
+            .create();
+        assert_eq!(get::<String>(&client_shim, "failover/get").unwrap(), "fallback");
+        assert!(!client_shim.endpoints.is_up(0));
+        assert_eq!(client_shim.endpoint(), mockito::server_url());
+        // The primary is not retried during its cooldown
+        assert_eq!(get::<String>(&client_shim, "failover/get").unwrap(), "fallback");
+        m.assert();
+
+        // Connection refused: unsafe steps fail over too
+        let client_shim = ClientShim::with_endpoints(
+            Endpoints::from_urls(&[String::from("http://127.0.0.1:1"), mockito::server_url()])
+                .unwrap(),
+            None,
+            None,
+        )
+        .unwrap();
+        let m = mock("POST", "/failover/post")
+            .with_body("\"fallback\"")
+            .expect(1)
+            .create();
+        assert_eq!(
+            postb::<_, String>(&client_shim, "failover/post", "msg").unwrap(),
+            "fallback"
+        );
+        m.assert();
+    }
+
+    #[test]
+    fn test_verify_responses() {
+        let mut client_shim = test_client_shim();
+        client_shim.verify_responses = true;
+        client_shim.server_identity_key = String::from(
+            "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+        );
+
+        // Unsigned, or signed for another nonce, path or body
+        let m = mock("GET", "/verify/get")
+            .with_body("\"forged\"")
+            .expect(1)
+            .create();
+        assert!(get::<String>(&client_shim, "verify/get").is_err());
+        m.assert();
+        let m = mock("POST", "/verify/post")
+            .with_header(RESPONSE_SIG_HEADER, "3044022000")
+            .with_body("\"forged\"")
+            .expect(1)
+            .create();
+        assert!(postb::<_, String>(&client_shim, "verify/post", "msg").is_err());
+        m.assert();
+
+        // Not verified if disabled
+        client_shim.verify_responses = false;
+        let m = mock("GET", "/verify/get")
+            .with_body("\"unsigned\"")
+            .expect(1)
+            .create();
+        assert_eq!(get::<String>(&client_shim, "verify/get").unwrap(), "unsigned");
+        m.assert();
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            backoff_ms: 100,
+            max_backoff_ms: 1000,
+        };
+        for retry in 1..10 {
+            let max = std::cmp::min(100 * 2u64.pow(retry - 1), 1000);
+            let delay = policy.backoff(retry).as_millis() as u64;
+            assert!(delay >= max / 2 && delay <= max, "retry {}: {}", retry, delay);
+        }
+        // No overflow for large retry counts
+        assert!(policy.backoff(100).as_millis() <= 1000);
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let timeouts = crate::Timeouts {
+            poll: 1,
+            sign: 2,
+            confirm: 3,
+        };
+        assert_eq!(timeouts.get(RequestClass::Poll).as_secs(), 1);
+        assert_eq!(timeouts.get(RequestClass::Sign).as_secs(), 2);
+        assert_eq!(timeouts.get(RequestClass::Confirm).as_secs(), 3);
+        let default = crate::Timeouts::default();
+        assert!(default.poll < default.sign && default.sign < default.confirm);
+    }
+
+    #[test]
+    fn test_get_retried_on_try_again() {
+        let m = mock("GET", "/retry/get")
+            .with_body("Error: try again: conflicting update")
+            .expect(3)
+            .create();
+        match get::<String>(&test_client_shim(), "retry/get") {
+            Err(CError::StateEntityError(e)) => assert!(e.contains("try again")),
+            _ => assert!(false, "expected StateEntityError"),
+        }
+        m.assert();
+    }
+
+    #[test]
+    fn test_error_code() {
+        let m = mock("POST", "/transfer/sender")
+            .with_header(ERROR_CODE_HEADER, ErrorCode::KeyRefreshRequired.as_str())
+            .with_body("Error: key refresh required: transfer_sender - shared key id: 1")
+            .expect(1)
+            .create();
+        match postb::<_, String>(&test_client_shim(), "transfer/sender", "msg") {
+            Err(CError::KeyRefreshRequired(_)) => (),
+            r => assert!(false, "expected KeyRefreshRequired, got {:?}", r),
+        }
+        m.assert();
+
+        // Same text without the code is not typed
+        let m = mock("POST", "/transfer/sender2")
+            .with_body("Error: key refresh required: transfer_sender - shared key id: 1")
+            .create();
+        match postb::<_, String>(&test_client_shim(), "transfer/sender2", "msg") {
+            Err(CError::StateEntityError(_)) => (),
+            r => assert!(false, "expected StateEntityError, got {:?}", r),
+        }
+        m.assert();
+    }
+
+    #[test]
+    fn test_postb_retry() {
+        // Unsafe step: not retried if the server may have processed the request
+        let m = mock("POST", "/retry/unsafe")
+            .with_body("Error: try again: conflicting update")
+            .expect(1)
+            .create();
+        assert!(postb::<_, String>(&test_client_shim(), "retry/unsafe", "msg").is_err());
+        m.assert();
+
+        // Safe step
+        let m = mock("POST", "/retry/safe")
+            .with_body("Error: try again: conflicting update")
+            .expect(3)
+            .create();
+        assert!(
+            postb_with::<_, String>(
+                &test_client_shim(),
+                "retry/safe",
+                "msg",
+                Retry::Safe,
+                RequestClass::Sign
+            )
+            .is_err()
+        );
+        m.assert();
+
+        // Rejected by the rate limiter: retried for unsafe steps
+        let m = mock("POST", "/retry/ratelimit")
+            .with_body("Error: Not available until 1s due to rate limit")
+            .expect(3)
+            .create();
+        assert!(postb::<_, String>(&test_client_shim(), "retry/ratelimit", "msg").is_err());
+        m.assert();
+
+        // Other errors are not retried
+        let m = mock("POST", "/retry/error")
+            .with_body("Error: Authentication Error: User authorisation failed")
+            .expect(1)
+            .create();
+        assert!(
+            postb_with::<_, String>(
+                &test_client_shim(),
+                "retry/error",
+                "msg",
+                Retry::Safe,
+                RequestClass::Sign
+            )
+            .is_err()
+        );
+        m.assert();
+    }
+}
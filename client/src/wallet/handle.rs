@@ -0,0 +1,140 @@
+//! Wallet Handle
+//!
+//! Cheap cloneable handle to a Wallet shared between threads.
+//!
+//! Read operations take a shared lock and run concurrently. Mutations take an exclusive lock,
+//! are serialized and the wallet is saved once the mutation completes. The state entity and
+//! conductor client shims are held outside the lock, so requests that do not touch wallet
+//! data (e.g. swap and info polls) are never blocked by a running protocol such as a withdrawal.
+
+use super::super::Result;
+use super::wallet::Wallet;
+use crate::error::{CError, WalletErrorType};
+use crate::ClientShim;
+
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[derive(Clone)]
+pub struct WalletHandle {
+    wallet: Arc<RwLock<Wallet>>,
+    client_shim: ClientShim,
+    conductor_shim: ClientShim,
+}
+
+impl WalletHandle {
+    pub fn new(wallet: Wallet) -> Self {
+        WalletHandle {
+            client_shim: wallet.client_shim.clone(),
+            conductor_shim: wallet.conductor_shim.clone(),
+            wallet: Arc::new(RwLock::new(wallet)),
+        }
+    }
+
+    /// State entity client shim. Does not lock the wallet.
+    pub fn client_shim(&self) -> &ClientShim {
+        &self.client_shim
+    }
+
+    /// Conductor client shim. Does not lock the wallet.
+    pub fn conductor_shim(&self) -> &ClientShim {
+        &self.conductor_shim
+    }
+
+    /// Shared access to the wallet. Blocks while a mutation is in progress.
+    pub fn read(&self) -> Result<RwLockReadGuard<Wallet>> {
+        self.wallet
+            .read()
+            .map_err(|_| CError::WalletError(WalletErrorType::WalletLockPoisoned))
+    }
+
+    /// Exclusive access to the wallet. The caller is responsible for saving the wallet.
+    pub fn write(&self) -> Result<RwLockWriteGuard<Wallet>> {
+        self.wallet
+            .write()
+            .map_err(|_| CError::WalletError(WalletErrorType::WalletLockPoisoned))
+    }
+
+    /// Run a read operation on the wallet
+    pub fn with<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Wallet) -> Result<T>,
+    {
+        f(&*self.read()?)
+    }
+
+    /// Run a mutation on the wallet and save it. The wallet is saved whether or not the
+    /// mutation succeeds, as a failed protocol may have already updated wallet data.
+    pub fn update<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Wallet) -> Result<T>,
+    {
+        let mut wallet = self.write()?;
+        let res = f(&mut *wallet);
+        wallet.save();
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::wallet::DEFAULT_TEST_WALLET_LOC;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn gen_handle() -> WalletHandle {
+        WalletHandle::new(Wallet::new(
+            &[0xcd; 32],
+            &"regtest".to_string(),
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        ))
+    }
+
+    #[test]
+    #[serial]
+    fn test_handle_shared_state() {
+        let handle = gen_handle();
+        let clone = handle.clone();
+        let addr = thread::spawn(move || clone.update(|w| w.keys.get_new_address()))
+            .join()
+            .unwrap()
+            .unwrap();
+        assert_eq!(handle.read().unwrap().keys.last_derived_pos, 1);
+        assert!(handle
+            .with(|w| Ok(w.keys.addresses_derivation_map.contains_key(&addr.to_string())))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_handle_concurrent_reads() {
+        let handle = gen_handle();
+        let guard = handle.read().unwrap();
+        // A second reader is not blocked by the first
+        let clone = handle.clone();
+        let id = thread::spawn(move || clone.with(|w| Ok(w.id.clone())))
+            .join()
+            .unwrap()
+            .unwrap();
+        assert_eq!(id, guard.id);
+    }
+
+    #[test]
+    fn test_handle_shims_not_locked() {
+        let handle = gen_handle();
+        let (tx, rx) = mpsc::channel();
+        let clone = handle.clone();
+        let _guard = handle.write().unwrap();
+        thread::spawn(move || {
+            let endpoint = clone.conductor_shim().endpoint();
+            tx.send(endpoint).unwrap();
+        });
+        // Client shims are available while a mutation holds the wallet lock
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "http://localhost:8000"
+        );
+    }
+}
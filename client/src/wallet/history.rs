@@ -0,0 +1,278 @@
+//! History
+//!
+//! Record of wallet activity (deposits, transfers, swaps and withdrawals) and its export for
+//! accounting, with the fiat value of each event at the time it occurred from a pluggable price
+//! source.
+
+use super::super::Result;
+use crate::error::CError;
+
+use chrono::NaiveDateTime;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Satoshis per bitcoin
+const SATS_PER_BTC: f64 = 100_000_000.0;
+
+/// Kind of wallet activity. Value enters the wallet on deposit, transfer in and swap in, and
+/// leaves it on transfer out, swap out and withdrawal.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Activity {
+    Deposit,
+    TransferIn,
+    TransferOut,
+    SwapIn,
+    SwapOut,
+    Withdraw,
+}
+
+impl Activity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Activity::Deposit => "deposit",
+            Activity::TransferIn => "transfer_in",
+            Activity::TransferOut => "transfer_out",
+            Activity::SwapIn => "swap_in",
+            Activity::SwapOut => "swap_out",
+            Activity::Withdraw => "withdraw",
+        }
+    }
+}
+
+/// A wallet activity event, recorded when it completes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ActivityEvent {
+    pub timestamp: i64, // unix time (seconds)
+    pub activity: Activity,
+    pub amount: u64, // statecoin value (satoshis)
+    pub shared_key_id: Uuid,
+    pub statechain_id: Option<Uuid>,
+    /// On-chain tx of the event: the funding tx of a deposit or the withdraw tx of a
+    /// withdrawal. None for transfers and swaps, which are off-chain.
+    pub txid: Option<String>,
+}
+
+impl ActivityEvent {
+    pub fn new(
+        activity: Activity,
+        amount: u64,
+        shared_key_id: Uuid,
+        statechain_id: Option<Uuid>,
+        txid: Option<String>,
+    ) -> Self {
+        ActivityEvent {
+            timestamp: chrono::Utc::now().timestamp(),
+            activity,
+            amount,
+            shared_key_id,
+            statechain_id,
+            txid,
+        }
+    }
+}
+
+/// Source of the fiat price of bitcoin at the time of an event, e.g. an exchange rate API or
+/// a file of historical prices
+pub trait PriceSource {
+    /// Currency code of the prices, e.g. "USD"
+    fn currency(&self) -> String;
+    /// Price of 1 BTC at unix time timestamp, or None if unknown
+    fn price_at(&self, timestamp: i64) -> Result<Option<f64>>;
+}
+
+/// Format of a history export
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for HistoryFormat {
+    type Err = CError;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(HistoryFormat::Csv),
+            "json" => Ok(HistoryFormat::Json),
+            _ => Err(CError::Generic(format!("Unknown history format: {}", s))),
+        }
+    }
+}
+
+/// An exported activity event. amount is positive for value entering the wallet and negative
+/// for value leaving it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryRecord {
+    pub timestamp: i64,
+    pub date: String, // UTC, ISO 8601
+    pub activity: Activity,
+    pub amount: i64,
+    pub shared_key_id: Uuid,
+    pub statechain_id: Option<Uuid>,
+    pub txid: Option<String>,
+    pub fiat_value: Option<f64>,
+    pub fiat_currency: Option<String>,
+}
+
+const CSV_HEADER: &str =
+    "timestamp,date,activity,amount,shared_key_id,statechain_id,txid,fiat_value,fiat_currency";
+
+impl HistoryRecord {
+    pub fn from_event(
+        event: &ActivityEvent,
+        price_source: Option<&dyn PriceSource>,
+    ) -> Result<Self> {
+        let amount = match event.activity {
+            Activity::Deposit | Activity::TransferIn | Activity::SwapIn => event.amount as i64,
+            Activity::TransferOut | Activity::SwapOut | Activity::Withdraw => {
+                -(event.amount as i64)
+            }
+        };
+        let (fiat_value, fiat_currency) = match price_source {
+            Some(source) => (
+                source
+                    .price_at(event.timestamp)?
+                    .map(|price| amount as f64 / SATS_PER_BTC * price),
+                Some(source.currency()),
+            ),
+            None => (None, None),
+        };
+        Ok(HistoryRecord {
+            timestamp: event.timestamp,
+            date: NaiveDateTime::from_timestamp(event.timestamp, 0)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string(),
+            activity: event.activity,
+            amount,
+            shared_key_id: event.shared_key_id,
+            statechain_id: event.statechain_id,
+            txid: event.txid.clone(),
+            fiat_value,
+            fiat_currency,
+        })
+    }
+
+    fn to_csv(&self) -> String {
+        [
+            self.timestamp.to_string(),
+            self.date.clone(),
+            self.activity.as_str().to_string(),
+            self.amount.to_string(),
+            self.shared_key_id.to_string(),
+            self.statechain_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            self.txid.clone().unwrap_or_default(),
+            self.fiat_value
+                .map(|v| format!("{:.2}", v))
+                .unwrap_or_default(),
+            self.fiat_currency.clone().unwrap_or_default(),
+        ]
+        .join(",")
+    }
+}
+
+/// Export activity events in time order, valued with price_source if supplied
+pub fn export_history(
+    events: &[ActivityEvent],
+    format: HistoryFormat,
+    price_source: Option<&dyn PriceSource>,
+) -> Result<String> {
+    let mut events = events.to_vec();
+    events.sort_by_key(|e| e.timestamp);
+    let records = events
+        .iter()
+        .map(|e| HistoryRecord::from_event(e, price_source))
+        .collect::<Result<Vec<HistoryRecord>>>()?;
+    match format {
+        HistoryFormat::Json => Ok(serde_json::to_string_pretty(&records)?),
+        HistoryFormat::Csv => {
+            let mut csv = String::from(CSV_HEADER);
+            for record in &records {
+                csv.push('\n');
+                csv.push_str(&record.to_csv());
+            }
+            csv.push('\n');
+            Ok(csv)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestPriceSource;
+
+    impl PriceSource for TestPriceSource {
+        fn currency(&self) -> String {
+            String::from("USD")
+        }
+        // 20000 USD until the second event, then unknown
+        fn price_at(&self, timestamp: i64) -> Result<Option<f64>> {
+            Ok(match timestamp < 1609459300 {
+                true => Some(20000.0),
+                false => None,
+            })
+        }
+    }
+
+    fn events() -> Vec<ActivityEvent> {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        vec![
+            ActivityEvent {
+                timestamp: 1609459300,
+                activity: Activity::Withdraw,
+                amount: 100000,
+                shared_key_id,
+                statechain_id: Some(statechain_id),
+                txid: Some(String::from("ab")),
+            },
+            ActivityEvent {
+                timestamp: 1609459200,
+                activity: Activity::Deposit,
+                amount: 100000,
+                shared_key_id,
+                statechain_id: Some(statechain_id),
+                txid: Some(String::from("cd")),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_export_history_json() {
+        let json = export_history(&events(), HistoryFormat::Json, Some(&TestPriceSource)).unwrap();
+        let records: Vec<HistoryRecord> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records.len(), 2);
+        // Sorted by time
+        assert_eq!(records[0].activity, Activity::Deposit);
+        assert_eq!(records[0].date, "2021-01-01T00:00:00Z");
+        assert_eq!(records[0].amount, 100000);
+        assert_eq!(records[0].fiat_value, Some(20.0));
+        assert_eq!(records[0].fiat_currency, Some(String::from("USD")));
+        // Value leaving the wallet is negative
+        assert_eq!(records[1].amount, -100000);
+        assert_eq!(records[1].fiat_value, None);
+    }
+
+    #[test]
+    fn test_export_history_csv() {
+        let events = events();
+        let csv = export_history(&events, HistoryFormat::Csv, None).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            format!(
+                "1609459200,2021-01-01T00:00:00Z,deposit,100000,{},{},cd,,",
+                events[1].shared_key_id,
+                events[1].statechain_id.unwrap()
+            )
+        );
+        assert!(lines[2].starts_with("1609459300,2021-01-01T00:01:40Z,withdraw,-100000,"));
+        assert_eq!(HistoryFormat::from_str("CSV").unwrap(), HistoryFormat::Csv);
+        assert!(HistoryFormat::from_str("xml").is_err());
+    }
+}
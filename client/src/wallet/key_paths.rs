@@ -3,6 +3,27 @@
 //! Structs defining and storing protocol of key generation.
 //! Structs differ in their storage/access of keys:
 //!     Storage by Bitcoin address, Public key and State Entity Address
+//!
+//! Derivation scheme
+//!
+//! Wallet keys are derived from the master key at
+//!
+//!     m / purpose' / coin_type' / account' / chain' / index'
+//!
+//!     purpose'   STATECHAIN_PURPOSE (ASCII "Merc")
+//!     coin_type' 0 for Bitcoin, 1 for testnet, signet and regtest (SLIP-44)
+//!     account'   wallet account, from 0
+//!     chain'     CHAIN_KEYS: general usage keys
+//!                CHAIN_SE_BACKUP_KEYS: backup tx output keys
+//!                CHAIN_SE_PROOF_KEYS: statecoin proof keys
+//!                CHAIN_SE_KEY_SHARES: private shares of shared keys
+//!     index'     sequential from 0, or for backup keys, proof keys and key shares an id
+//!                encoded as an index (funding_txid_to_int, uuid_to_int)
+//!
+//! Wallets created before the scheme derive the chains directly from the master key
+//! (m / chain' / index'), which is the legacy root path "m". Statecoins can be recovered in
+//! any compatible wallet from the master key, the root path and the derived indices, as
+//! exported in a DerivationExport.
 
 use super::super::Result;
 use crate::curv::elliptic::curves::traits::ECScalar;
@@ -10,13 +31,14 @@ use crate::error::CError;
 use crate::wallet::wallet::to_bitcoin_public_key;
 use bitcoin::{
     secp256k1::{All, Secp256k1},
-    util::bip32::{ChildNumber, ExtendedPrivKey, ExtendedPubKey},
-    {PrivateKey, PublicKey},
+    util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey},
+    {Network, PrivateKey, PublicKey},
 };
 use curv::FE;
 
 use std::collections::HashMap;
 use std::str::FromStr;
+use uuid::Uuid;
 
 /// Struct stores key pairs and their derivation path position
 #[derive(Debug, Copy, Clone)]
@@ -338,6 +360,150 @@ pub fn funding_txid_to_int(funding_txid: &String) -> Result<u32> {
     u32::from_str_radix(&funding_txid[0..6], 16).map_err(|e| CError::from(e))
 }
 
+/// Map a shared key or statechain id to a hardened child index for key share derivation
+pub fn uuid_to_int(id: &Uuid) -> u32 {
+    let bytes = id.as_bytes();
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & 0x7FFFFFFF
+}
+
+/// Derivation scheme purpose: ASCII "Merc"
+pub const STATECHAIN_PURPOSE: u32 = 0x4d657263;
+/// Chain of general usage keys
+pub const CHAIN_KEYS: u32 = 0;
+/// Chain of State Entity backup tx output keys
+pub const CHAIN_SE_BACKUP_KEYS: u32 = 1;
+/// Chain of State Entity proof keys
+pub const CHAIN_SE_PROOF_KEYS: u32 = 2;
+/// Chain of private shares of State Entity shared keys
+pub const CHAIN_SE_KEY_SHARES: u32 = 3;
+/// Current version of the DerivationExport format
+pub const DERIVATION_EXPORT_VERSION: u32 = 1;
+
+/// Root path of the key chains of 'account' on 'network': m / purpose' / coin_type' / account'
+pub fn account_path(network: Network, account: u32) -> Result<DerivationPath> {
+    let coin_type = match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+    Ok(DerivationPath::from(vec![
+        ChildNumber::from_hardened_idx(STATECHAIN_PURPOSE)?,
+        ChildNumber::from_hardened_idx(coin_type)?,
+        ChildNumber::from_hardened_idx(account)?,
+    ]))
+}
+
+/// Root path of wallets created before the derivation scheme: m
+pub fn legacy_path() -> DerivationPath {
+    DerivationPath::from(vec![])
+}
+
+/// Derive the extended key of 'chain' under 'root'. The network is set explicitly as extended
+/// key serialization does not distinguish regtest from testnet.
+pub fn derive_chain(
+    secp: &Secp256k1<All>,
+    master_priv_key: &ExtendedPrivKey,
+    root: &DerivationPath,
+    chain: u32,
+) -> Result<ExtendedPrivKey> {
+    let mut ext_priv_key = master_priv_key
+        .derive_priv(secp, root)?
+        .ckd_priv(secp, ChildNumber::from_hardened_idx(chain)?)?;
+    ext_priv_key.network = master_priv_key.network;
+    Ok(ext_priv_key)
+}
+
+/// Derived indices of a key chain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChainExport {
+    pub chain: u32,
+    /// Number of sequential keys derived: indices 0 to next_index - 1
+    pub next_index: u32,
+    /// Indices of keys derived from encoded ids
+    pub encoded_indices: Vec<u32>,
+}
+
+impl ChainExport {
+    pub fn from_key_path(chain: u32, key_path: &KeyPath) -> Self {
+        let mut encoded_indices: Vec<u32> = key_path
+            .key_derivation_map
+            .values()
+            .filter(|d| d.pos > key_path.last_derived_pos)
+            .map(|d| d.pos)
+            .collect();
+        encoded_indices.sort();
+        ChainExport {
+            chain,
+            next_index: key_path.last_derived_pos,
+            encoded_indices,
+        }
+    }
+
+    pub fn from_key_path_with_addresses(chain: u32, key_path: &KeyPathWithAddresses) -> Self {
+        let mut encoded_indices: Vec<u32> = key_path
+            .addresses_derivation_map
+            .values()
+            .filter(|d| d.pos > key_path.last_derived_pos)
+            .map(|d| d.pos)
+            .collect();
+        encoded_indices.sort();
+        ChainExport {
+            chain,
+            next_index: key_path.last_derived_pos,
+            encoded_indices,
+        }
+    }
+
+    /// Re-derive the chain's keys in key_path
+    pub fn derive_key_path(&self, key_path: &mut KeyPath) -> Result<()> {
+        for _ in key_path.last_derived_pos..self.next_index {
+            key_path.get_new_key()?;
+        }
+        for pos in &self.encoded_indices {
+            key_path.get_new_key_encoded_id(*pos, None)?;
+        }
+        Ok(())
+    }
+
+    /// Re-derive the chain's keys in key_path
+    pub fn derive_key_path_with_addresses(&self, key_path: &mut KeyPathWithAddresses) -> Result<()> {
+        for _ in key_path.last_derived_pos..self.next_index {
+            key_path.get_new_address()?;
+        }
+        for pos in &self.encoded_indices {
+            key_path.get_new_address_encoded_id(*pos)?;
+        }
+        Ok(())
+    }
+}
+
+/// Wallet key derivation export, from which the wallet keys and statecoins can be recovered by
+/// any wallet implementing the derivation scheme
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DerivationExport {
+    pub version: u32,
+    pub network: String,
+    /// Extended master private key
+    pub master_priv_key: String,
+    /// Root path of the key chains, e.g. "m" (legacy) or "m/1298494051'/1'/0'"
+    pub root_path: String,
+    pub chains: Vec<ChainExport>,
+}
+
+impl DerivationExport {
+    /// Get the derived indices of 'chain'. A chain missing from the export has no keys derived.
+    pub fn get_chain(&self, chain: u32) -> ChainExport {
+        self.chains
+            .iter()
+            .find(|c| c.chain == chain)
+            .cloned()
+            .unwrap_or(ChainExport {
+                chain,
+                next_index: 0,
+                encoded_indices: vec![],
+            })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
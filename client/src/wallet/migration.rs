@@ -0,0 +1,132 @@
+//! Wallet file migrations
+//!
+//! Wallet files are JSON objects carrying the version of their format. Files saved before the
+//! version was introduced are version 0. Older files are upgraded to WALLET_VERSION one version
+//! at a time by the registered migrations before being read by Wallet::from_json, so that
+//! from_json only has to read the current format. Wallet::load keeps a copy of a file before
+//! upgrading it.
+
+use super::super::Result;
+use super::key_paths::legacy_path;
+use super::proof_cache::ProofCache;
+use super::receive_keys::ReceiveKeyPool;
+use crate::error::{CError, WalletErrorType};
+
+use serde_json::{json, Map, Value};
+
+/// Version of the wallet file format written by this client
+pub const WALLET_VERSION: u64 = 1;
+
+/// Upgrade of the wallet file format from version `from` to `from + 1`
+struct Migration {
+    from: u64,
+    name: &'static str,
+    migrate: fn(&mut Map<String, Value>) -> Result<()>,
+}
+
+/// Migrations in version order
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    name: "versioned",
+    migrate: migrate_v0,
+}];
+
+/// Version 0 wallets were extended with new fields without a version. Fields missing from a
+/// wallet saved before they were added are set to their defaults.
+fn migrate_v0(wallet: &mut Map<String, Value>) -> Result<()> {
+    let defaults = vec![
+        ("derivation_path", json!(legacy_path().to_string())),
+        ("require_deletion_receipts", json!(false)),
+        ("external_backup_addrs", json!("{}")),
+        ("sync_checkpoint", Value::Null),
+        ("pending_deposits", json!("[]")),
+        ("pending_transfers", json!("[]")),
+        ("auto_accept_transfers", json!(true)),
+        ("activity", json!("[]")),
+        ("swap_reports", json!("[]")),
+        (
+            "receive_keys",
+            json!(serde_json::to_string(&ReceiveKeyPool::default())?),
+        ),
+        (
+            "proof_cache",
+            json!(serde_json::to_string(&ProofCache::default())?),
+        ),
+    ];
+    for (field, default) in defaults {
+        wallet.entry(field).or_insert(default);
+    }
+    Ok(())
+}
+
+/// Format version of a wallet file
+pub fn wallet_version(wallet: &Value) -> Result<u64> {
+    match wallet.get("version") {
+        None => Ok(0),
+        Some(v) => v
+            .as_u64()
+            .ok_or(CError::WalletError(WalletErrorType::WalletFileInvalid)),
+    }
+}
+
+/// Upgrade a wallet file to WALLET_VERSION. Files of a later version than this client supports
+/// are rejected.
+pub fn migrate_wallet(mut wallet: Value) -> Result<Value> {
+    let mut version = wallet_version(&wallet)?;
+    if version > WALLET_VERSION {
+        return Err(CError::WalletError(
+            WalletErrorType::WalletFileVersionUnsupported,
+        ));
+    }
+    let fields = wallet
+        .as_object_mut()
+        .ok_or(CError::WalletError(WalletErrorType::WalletFileInvalid))?;
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= version) {
+        debug!(
+            "Migrating wallet file from version {}: {}",
+            migration.from, migration.name
+        );
+        (migration.migrate)(fields)?;
+        version = migration.from + 1;
+        fields.insert(String::from("version"), json!(version));
+    }
+    Ok(wallet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_ordered() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.from, i as u64, "migration versions must be contiguous");
+        }
+        assert_eq!(MIGRATIONS.len() as u64, WALLET_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_wallet() {
+        // Fields set by version 0 wallets are kept
+        let wallet = json!({"id": "a", "activity": "[1]"});
+        assert_eq!(wallet_version(&wallet).unwrap(), 0);
+        let migrated = migrate_wallet(wallet).unwrap();
+        assert_eq!(wallet_version(&migrated).unwrap(), WALLET_VERSION);
+        assert_eq!(migrated["id"], "a");
+        assert_eq!(migrated["activity"], "[1]");
+        assert_eq!(migrated["pending_transfers"], "[]");
+        assert_eq!(migrated["derivation_path"], "m");
+
+        // Current wallets are unchanged
+        assert_eq!(migrate_wallet(migrated.clone()).unwrap(), migrated);
+
+        // Later and invalid versions are rejected
+        let newer = json!({"version": WALLET_VERSION + 1});
+        match migrate_wallet(newer) {
+            Err(CError::WalletError(WalletErrorType::WalletFileVersionUnsupported)) => (),
+            res => assert!(false, "unexpected result: {:?}", res),
+        }
+        assert!(migrate_wallet(json!({"version": "1"})).is_err());
+        assert!(migrate_wallet(json!([])).is_err());
+    }
+}
@@ -1,3 +1,10 @@
+pub mod handle;
+pub mod history;
 pub mod key_paths;
+pub mod migration;
+pub mod proof_cache;
+pub mod receive_keys;
 pub mod shared_key;
+pub mod statecoin;
 pub mod wallet;
+pub mod watch_only;
@@ -0,0 +1,228 @@
+//! Proof cache
+//!
+//! SMT roots and inclusion proofs verified by the wallet, saved with the wallet so that they are
+//! not downloaded and verified again. Proofs are keyed by root id and funding txid and are
+//! dropped once a newer attested root supersedes their root.
+//!
+//! The verified roots are kept as a chain in root id order. Every root served by the state
+//! entity is checked against the chain: a state entity serving two different roots with the
+//! same id (equivocation) is detected, and the chain can be compared with the roots seen by
+//! other wallets to detect a state entity showing different trees to different users.
+
+use super::super::Result;
+use crate::error::CError;
+
+use monotree::Proof;
+use shared_lib::Root;
+use std::collections::{BTreeMap, HashMap};
+
+/// Maximum number of verified roots kept. The oldest roots and their proofs are dropped first.
+pub const MAX_VERIFIED_ROOTS: usize = 1000;
+
+/// An SMT root verified by the wallet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VerifiedRoot {
+    pub id: i64,
+    /// Root hash (hex)
+    pub hash: String,
+    /// True once the root has been seen attested by mainstay
+    pub attested: bool,
+}
+
+/// Verified roots and proofs
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ProofCache {
+    /// Verified roots in increasing id order
+    roots: Vec<VerifiedRoot>,
+    /// Verified inclusion proofs by root id and funding txid
+    proofs: BTreeMap<i64, HashMap<String, Proof>>,
+}
+
+/// Id and hash (hex) of a root
+fn root_id_hash(root: &Root) -> Result<(i64, String)> {
+    let id = root
+        .id()
+        .ok_or(CError::Generic(String::from("SMT root has no id")))?;
+    if root.value.is_none() && root.commitment_info().is_none() {
+        return Err(CError::Generic(format!("SMT root {} has no hash", id)));
+    }
+    Ok((id, hex::encode(root.hash())))
+}
+
+impl ProofCache {
+    /// Verified roots in increasing id order
+    pub fn roots(&self) -> &[VerifiedRoot] {
+        &self.roots
+    }
+
+    fn find_root(&self, id: i64) -> std::result::Result<usize, usize> {
+        self.roots.binary_search_by_key(&id, |r| r.id)
+    }
+
+    /// Latest root seen attested
+    pub fn latest_attested_root(&self) -> Option<&VerifiedRoot> {
+        self.roots.iter().rev().find(|r| r.attested)
+    }
+
+    /// Check that a root served by the state entity does not conflict with a verified root of
+    /// the same id
+    pub fn check_root(&self, root: &Root) -> Result<()> {
+        let (id, hash) = root_id_hash(root)?;
+        match self.find_root(id) {
+            Ok(i) if self.roots[i].hash != hash => Err(equivocation(id, &self.roots[i].hash, &hash)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the verified roots against roots seen by another wallet
+    pub fn check_consistent(&self, other: &[VerifiedRoot]) -> Result<()> {
+        for root in other {
+            if let Ok(i) = self.find_root(root.id) {
+                if self.roots[i].hash != root.hash {
+                    return Err(equivocation(root.id, &self.roots[i].hash, &root.hash));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a root verified by the wallet to the chain. A newer attested root supersedes older
+    /// roots: their cached proofs are dropped.
+    pub fn record_root(&mut self, root: &Root) -> Result<()> {
+        self.check_root(root)?;
+        let (id, hash) = root_id_hash(root)?;
+        let attested = root.is_confirmed();
+        match self.find_root(id) {
+            Ok(i) => self.roots[i].attested |= attested,
+            Err(i) => self.roots.insert(i, VerifiedRoot { id, hash, attested }),
+        }
+        if attested {
+            self.proofs = self.proofs.split_off(&id);
+        }
+        if self.roots.len() > MAX_VERIFIED_ROOTS {
+            let dropped: Vec<VerifiedRoot> = self
+                .roots
+                .drain(..self.roots.len() - MAX_VERIFIED_ROOTS)
+                .collect();
+            for root in dropped {
+                self.proofs.remove(&root.id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Cached proof for funding_txid against root. None if not cached or if root does not
+    /// match the verified root of its id.
+    pub fn get_proof(&self, root: &Root, funding_txid: &String) -> Option<&Proof> {
+        let (id, hash) = root_id_hash(root).ok()?;
+        match self.find_root(id) {
+            Ok(i) if self.roots[i].hash == hash => self.proofs.get(&id)?.get(funding_txid),
+            _ => None,
+        }
+    }
+
+    /// Cache a proof for funding_txid verified against root, recording the root. Proofs against
+    /// roots superseded by a newer attested root are not cached.
+    pub fn insert_proof(&mut self, root: &Root, funding_txid: &String, proof: &Proof) -> Result<()> {
+        self.record_root(root)?;
+        let (id, _) = root_id_hash(root)?;
+        if self.latest_attested_root().map_or(false, |r| r.id > id) {
+            return Ok(());
+        }
+        self.proofs
+            .entry(id)
+            .or_insert_with(HashMap::new)
+            .insert(funding_txid.clone(), proof.clone());
+        Ok(())
+    }
+
+    /// Number of cached proofs
+    pub fn proof_count(&self) -> usize {
+        self.proofs.values().map(|p| p.len()).sum()
+    }
+}
+
+fn equivocation(id: i64, hash: &String, other: &String) -> CError {
+    CError::StateEntityError(format!(
+        "State entity equivocation: SMT root {} seen with hashes {} and {}",
+        id, hash, other
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared_lib::mainstay::CommitmentInfo;
+
+    fn root(id: i64, value: u8) -> Root {
+        Root::from(Some(id), Some([value; 32]), &None).unwrap()
+    }
+
+    #[test]
+    fn test_proof_cache() {
+        let mut cache = ProofCache::default();
+        let proof: Proof = vec![(true, vec![1; 33])];
+        let txid = String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e");
+
+        cache.insert_proof(&root(2, 2), &txid, &proof).unwrap();
+        cache.record_root(&root(1, 1)).unwrap();
+        assert_eq!(cache.roots().iter().map(|r| r.id).collect::<Vec<i64>>(), vec![1, 2]);
+        assert_eq!(cache.get_proof(&root(2, 2), &txid), Some(&proof));
+        assert_eq!(cache.get_proof(&root(1, 1), &txid), None);
+        // A root with the same id but a different hash is not served from the cache
+        assert_eq!(cache.get_proof(&root(2, 3), &txid), None);
+
+        // Equivocation
+        match cache.record_root(&root(2, 3)) {
+            Err(CError::StateEntityError(e)) => assert!(e.contains("equivocation")),
+            r => assert!(false, "expected equivocation error, got {:?}", r),
+        }
+        assert!(cache.check_root(&root(2, 2)).is_ok());
+        assert!(cache.check_root(&root(3, 3)).is_ok());
+        assert!(cache.check_consistent(&[VerifiedRoot { id: 2, hash: hex::encode([2; 32]), attested: false }]).is_ok());
+        assert!(cache.check_consistent(&[VerifiedRoot { id: 1, hash: hex::encode([9; 32]), attested: false }]).is_err());
+        assert!(cache.record_root(&Root::from(None, Some([1; 32]), &None).unwrap()).is_err());
+
+        // Round trip through the wallet file
+        let json = serde_json::to_string(&cache).unwrap();
+        assert_eq!(serde_json::from_str::<ProofCache>(&json).unwrap(), cache);
+    }
+
+    #[test]
+    fn test_proof_cache_attested_root_supersedes() {
+        let mut cache = ProofCache::default();
+        let proof: Proof = vec![(false, vec![2; 33])];
+        let txid = String::from("txid");
+        cache.insert_proof(&root(1, 1), &txid, &proof).unwrap();
+        cache.insert_proof(&root(3, 3), &txid, &proof).unwrap();
+        assert_eq!(cache.proof_count(), 2);
+
+        // Root attested by mainstay
+        let commitment_info: CommitmentInfo = serde_json::from_value(serde_json::json!({
+            "attestation": {
+                "merkle_root": [2u8; 32],
+                "txid": [4u8; 32],
+                "confirmed": true,
+                "inserted_at": "2020-05-02T12:07:54Z"
+            },
+            "merkleproof": {
+                "merkle_root": [2u8; 32],
+                "commitment": [2u8; 32],
+                "ops": [],
+                "append": [],
+                "position": 0
+            }
+        }))
+        .unwrap();
+        let attested = Root::from(Some(2), None, &Some(commitment_info)).unwrap();
+        assert!(attested.is_confirmed());
+        cache.record_root(&attested).unwrap();
+        assert_eq!(cache.latest_attested_root().map(|r| r.id), Some(2));
+        // Proofs against root 1 are superseded
+        assert_eq!(cache.proof_count(), 1);
+        assert_eq!(cache.get_proof(&root(1, 1), &txid), None);
+        cache.insert_proof(&root(1, 1), &txid, &proof).unwrap();
+        assert_eq!(cache.proof_count(), 1);
+        assert!(cache.get_proof(&root(3, 3), &txid).is_some());
+    }
+}
@@ -0,0 +1,116 @@
+//! Receive keys
+//!
+//! One-time encryption keys published alongside SCE addresses. Senders encrypt transfer message
+//! 3 to the receive key of the address instead of the receiver proof key, and the wallet deletes
+//! the key once the transfer has been received, so that transfer messages leaked later from the
+//! mailbox or transport logs cannot be decrypted with the wallet's keys.
+
+use shared_lib::util::keygen::generate_keypair;
+
+use bitcoin::{PrivateKey, PublicKey};
+
+/// Default number of unissued receive keys kept in the pool
+pub const DEFAULT_RECEIVE_KEY_POOL_SIZE: usize = 10;
+
+/// A one-time receive key
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReceiveKey {
+    pub public_key: PublicKey,
+    pub private_key: PrivateKey,
+    pub proof_key: Option<String>, // proof key of the address the key was issued with
+}
+
+/// Pool of receive keys. Keys are generated at random rather than derived from the wallet
+/// seed so that a deleted key cannot be recovered. Unissued keys are generated ahead of use and
+/// issued keys are kept until the transfer to their address is received or rejected.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ReceiveKeyPool {
+    pub keys: Vec<ReceiveKey>,
+}
+
+impl ReceiveKeyPool {
+    /// Generate unissued keys until there are pool_size of them
+    pub fn fill(&mut self, pool_size: usize) {
+        let unissued = self.keys.iter().filter(|k| k.proof_key.is_none()).count();
+        for _ in unissued..pool_size {
+            let (private_key, public_key) = generate_keypair();
+            self.keys.push(ReceiveKey {
+                public_key,
+                private_key,
+                proof_key: None,
+            });
+        }
+    }
+
+    /// Issue a receive key for the address of proof_key and refill the pool
+    pub fn issue(&mut self, proof_key: &String, pool_size: usize) -> PublicKey {
+        self.fill(pool_size.max(1));
+        let key = self
+            .keys
+            .iter_mut()
+            .find(|k| k.proof_key.is_none())
+            .expect("receive key pool filled");
+        key.proof_key = Some(proof_key.clone());
+        let public_key = key.public_key;
+        self.fill(pool_size);
+        public_key
+    }
+
+    /// Private key of an issued receive key
+    pub fn get_private_key(&self, public_key: &PublicKey) -> Option<PrivateKey> {
+        self.keys
+            .iter()
+            .find(|k| k.proof_key.is_some() && &k.public_key == public_key)
+            .map(|k| k.private_key)
+    }
+
+    /// Delete a receive key once used. Returns false if the key is not in the pool.
+    pub fn delete(&mut self, public_key: &PublicKey) -> bool {
+        let len = self.keys.len();
+        self.keys.retain(|k| &k.public_key != public_key);
+        self.keys.len() != len
+    }
+
+    /// Number of issued keys not yet used
+    pub fn issued(&self) -> usize {
+        self.keys.iter().filter(|k| k.proof_key.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receive_key_pool() {
+        let mut pool = ReceiveKeyPool::default();
+        pool.fill(3);
+        assert_eq!(pool.keys.len(), 3);
+        assert_eq!(pool.issued(), 0);
+        // Unissued keys cannot be used
+        assert!(pool.get_private_key(&pool.keys[0].public_key).is_none());
+
+        let proof_key = String::from("proof_key");
+        let key1 = pool.issue(&proof_key, 3);
+        let key2 = pool.issue(&proof_key, 3);
+        assert_ne!(key1, key2);
+        assert_eq!(pool.issued(), 2);
+        assert_eq!(pool.keys.len(), 5);
+        let private_key = pool.get_private_key(&key1).unwrap();
+        assert_eq!(
+            PublicKey::from_private_key(&bitcoin::secp256k1::Secp256k1::new(), &private_key),
+            key1
+        );
+
+        // Keys are used once
+        assert!(pool.delete(&key1));
+        assert!(!pool.delete(&key1));
+        assert!(pool.get_private_key(&key1).is_none());
+        assert_eq!(pool.issued(), 1);
+
+        // A pool of size 0 still issues keys
+        let mut pool = ReceiveKeyPool::default();
+        pool.issue(&proof_key, 0);
+        assert_eq!(pool.keys.len(), 1);
+    }
+}
@@ -4,11 +4,15 @@
 
 use super::super::{ecdsa, ClientShim, Result};
 use shared_lib::{
-    structs::{PrepareSignTxMsg, Protocol},
+    structs::{
+        InheritanceAPI, PayToContract, PrepareSignTxMsg, Protocol, SignedTransferReceipt,
+        StateEntityFeeInfoAPI, TransferMsg3,
+    },
     Root,
 };
 
 use bitcoin::secp256k1::key::SecretKey;
+use bitcoin::{Address, TxIn};
 use curv::elliptic::curves::traits::ECScalar;
 use curv::FE;
 use kms::ecdsa::two_party::MasterKey2;
@@ -20,6 +24,91 @@ pub struct InclusionProofSMT {
     pub root: Root,
     pub proof: Option<Proof>,
 }
+/// Emergency unilateral exit of a statecoin via its backup tx
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BackupExit {
+    pub txid: String,
+    pub tx_hex: String,
+    pub locktime: u32,
+    /// True once the backup tx has been accepted for broadcast
+    pub broadcast: bool,
+}
+
+/// Response of the wallet to a spend of a statecoin funding output by a tx other than its current
+/// backup tx, e.g. an old backup tx broadcast by a previous owner. The outdated backup tx has a
+/// later locktime than the current one, so the coin is recovered by confirming a spend first.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "strategy")]
+pub enum SpendResponse {
+    /// Alert only
+    None,
+    /// Broadcast the current backup tx once its locktime has expired, with a CPFP child so that
+    /// both pay fee_rate (sat/vbyte)
+    Backup { fee_rate: u64 },
+    /// Withdraw the coin cooperatively with the State Entity at fee_rate (sat/vbyte)
+    Withdraw { fee_rate: u64 },
+}
+
+impl Default for SpendResponse {
+    fn default() -> Self {
+        SpendResponse::None
+    }
+}
+
+/// Registration of a statecoin backup tx with a watchtower, which broadcasts it from
+/// broadcast_height if the wallet is offline
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WatchtowerRegistration {
+    pub version: u64,
+    pub backup_txid: String,
+    pub broadcast_height: u32,
+    /// True once revoked, e.g. after the statecoin has been transferred
+    pub revoked: bool,
+}
+
+/// Backup tx locktime countdown of a statecoin. Once the locktime expires the coin can only be
+/// kept safe by broadcasting its backup tx, so it should be transferred or withdrawn before.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CoinExpiry {
+    pub shared_key_id: Uuid,
+    pub statechain_id: Option<Uuid>,
+    pub locktime: u32, // block height, or blocks after funding confirmation for relative locktimes
+    pub height: u32,   // current chain height
+    pub blocks_remaining: u32,
+    /// True if blocks_remaining is at or below the wallet's expiry alert threshold
+    pub alert: bool,
+}
+
+/// Deposit in progress. Saved in the wallet before each network step of the deposit protocol
+/// so that the deposit can be resumed if the wallet is restarted before it completes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingDeposit {
+    pub shared_key_id: Uuid,
+    pub challenge: String, // PoW challenge solved at keygen
+    pub proof_key: String,
+    pub payer_proof_key: Option<String>, // set if deposited on behalf of the owner of proof_key
+    pub amount: u64,
+    pub fee_quote: StateEntityFeeInfoAPI, // fee quote acknowledged at session init
+    pub inputs: Vec<TxIn>,                // funding tx inputs
+    pub input_addrs: Vec<Address>,
+    pub input_amounts: Vec<u64>,
+    pub backup_addr: Option<String>,
+    pub tx_funding_hex: Option<String>, // signed funding tx, once built
+    pub tx_backup_psm: Option<PrepareSignTxMsg>, // co-signed backup tx, once signed
+    pub funding_txid: Option<String>,   // set once the funding tx is broadcast
+    #[serde(default)]
+    pub p2c_commitment: Option<String>, // pay-to-contract commitment in the funding output key
+}
+
+/// Inbound transfer to one of the wallet's proof keys, fetched from the State Entity relay
+/// mailbox or added by the user. Saved in the wallet until it is accepted or rejected so that
+/// several transfers can be received at once.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingTransfer {
+    pub transfer_msg3: TransferMsg3, // as sent: t1 is encrypted to the receiver proof key
+    pub rejected: bool, // rejected transfers are kept so they are not fetched again
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SharedKey {
     pub id: Uuid,
@@ -31,6 +120,26 @@ pub struct SharedKey {
     pub smt_proof: Option<InclusionProofSMT>,
     pub unspent: bool,
     pub funding_txid: String,
+    #[serde(default)]
+    pub backup_addr: Option<String>, // address the backup tx pays to
+    #[serde(default)]
+    pub exit: Option<BackupExit>, // set once the coin is exiting via its backup tx
+    #[serde(default)]
+    pub fee_quote: Option<StateEntityFeeInfoAPI>, // signed fee quote acknowledged at deposit
+    #[serde(default)]
+    pub transfer_receipt: Option<SignedTransferReceipt>, // proof of payment once transferred
+    #[serde(default)]
+    pub watchtower: Option<WatchtowerRegistration>, // backup tx registration with a watchtower
+    #[serde(default)]
+    pub share_epoch: u32, // number of refreshes of the key shares
+    #[serde(default)]
+    pub share_refreshed: Option<i64>, // unix time of the last key share refresh
+    #[serde(default)]
+    pub inheritance: Option<InheritanceAPI>, // beneficiary designation, checked in on sync
+    #[serde(default)]
+    pub rotating_proof_key: Option<String>, // new proof key of a proof key rotation in progress
+    #[serde(default)]
+    pub p2c: Option<PayToContract>, // pay-to-contract commitment in the funding output key
 }
 
 impl SharedKey {
@@ -56,7 +165,30 @@ impl SharedKey {
     ) -> Result<SharedKey> {
         let mut key_share_priv: FE = ECScalar::zero(); // convert to curv lib
         key_share_priv.set_element(*secret_key);
-        ecdsa::get_master_key_repeat_keygen(id, client_shim, &key_share_priv, value, protocol, solution, keygen_reps)
+        ecdsa::get_master_key_repeat_keygen(id, client_shim, &key_share_priv, value, protocol, solution, keygen_reps, None)
+    }
+
+    /// Deposit keygen committing to p2c_commitment in the funding output key
+    pub fn new_p2c(
+        id: &Uuid,
+        client_shim: &ClientShim,
+        secret_key: &SecretKey,
+        value: &u64,
+        solution: String,
+        p2c_commitment: &String,
+    ) -> Result<SharedKey> {
+        let mut key_share_priv: FE = ECScalar::zero(); // convert to curv lib
+        key_share_priv.set_element(*secret_key);
+        ecdsa::get_master_key_repeat_keygen(
+            id,
+            client_shim,
+            &key_share_priv,
+            value,
+            Protocol::Deposit,
+            solution,
+            0,
+            Some(p2c_commitment),
+        )
     }
 
     pub fn add_proof_data(
@@ -0,0 +1,370 @@
+//! StateCoin
+//!
+//! High-level handle to a single statecoin of a wallet, for wallet integrations. A StateCoin
+//! owns the shared key id of the coin and runs the state entity protocols on it (transfer,
+//! withdraw and swap) through a WalletHandle, so that the wallet is locked and saved around each
+//! operation. Listeners registered on the coin are called as operations start, complete or
+//! fail, and each operation can be run on a background thread with its _async variant.
+
+use super::super::Result;
+use super::handle::WalletHandle;
+use super::wallet::Wallet;
+use crate::error::CError;
+use crate::state_entity::{conductor, transfer, withdraw};
+use crate::utilities::encoding;
+
+use shared_lib::util::validate_address_network;
+
+use bitcoin::Address;
+use std::fmt;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use uuid::Uuid;
+
+/// Status of a statecoin in the wallet
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StateCoinStatus {
+    /// Deposit not yet confirmed: the coin has no statechain
+    Unconfirmed,
+    /// Owned by the wallet and available for transfer, swap or withdrawal
+    Owned,
+    /// Exiting via its backup tx
+    Exiting,
+    /// Transferred, swapped or withdrawn
+    Spent,
+}
+
+/// Wallet data of a statecoin
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StateCoinInfo {
+    pub shared_key_id: Uuid,
+    pub statechain_id: Option<Uuid>,
+    pub value: u64, // satoshis
+    pub proof_key: Option<String>,
+    pub funding_txid: String,
+    pub status: StateCoinStatus,
+}
+
+/// Operation run on a statecoin
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateCoinOp {
+    Transfer,
+    Withdraw,
+    Swap,
+}
+
+impl fmt::Display for StateCoinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateCoinOp::Transfer => write!(f, "transfer"),
+            StateCoinOp::Withdraw => write!(f, "withdraw"),
+            StateCoinOp::Swap => write!(f, "swap"),
+        }
+    }
+}
+
+/// Event reported to the listeners of a statecoin
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateCoinEvent {
+    Started {
+        shared_key_id: Uuid,
+        op: StateCoinOp,
+    },
+    /// Transfer message 3 for the receiver, bech32 encoded
+    Transferred {
+        shared_key_id: Uuid,
+        transfer_msg: String,
+    },
+    Withdrawn {
+        shared_key_id: Uuid,
+        txid: String,
+        amount: u64,
+    },
+    /// The coin was swapped for the coin with shared key new_shared_key_id
+    Swapped {
+        shared_key_id: Uuid,
+        new_shared_key_id: Uuid,
+    },
+    Failed {
+        shared_key_id: Uuid,
+        op: StateCoinOp,
+        error: String,
+    },
+}
+
+/// Callback for statecoin events. Called on the thread running the operation.
+pub type StateCoinListener = Arc<dyn Fn(&StateCoinEvent) + Send + Sync>;
+
+/// Handle to a statecoin of a wallet. Cloning the handle clones its listeners.
+#[derive(Clone)]
+pub struct StateCoin {
+    wallet: WalletHandle,
+    shared_key_id: Uuid,
+    listeners: Vec<StateCoinListener>,
+}
+
+impl StateCoin {
+    /// Handle to the statecoin with shared key shared_key_id. Err if not in the wallet.
+    pub fn new(wallet: &WalletHandle, shared_key_id: &Uuid) -> Result<Self> {
+        wallet.with(|w| w.get_shared_key(shared_key_id).map(|_| ()))?;
+        Ok(StateCoin {
+            wallet: wallet.clone(),
+            shared_key_id: *shared_key_id,
+            listeners: vec![],
+        })
+    }
+
+    /// Handle to the statecoin of statechain statechain_id
+    pub fn from_statechain_id(wallet: &WalletHandle, statechain_id: &Uuid) -> Result<Self> {
+        let shared_key_id = wallet.with(|w| Ok(w.get_shared_key_by_statechain_id(statechain_id)?.id))?;
+        Self::new(wallet, &shared_key_id)
+    }
+
+    /// Handles to the statecoins owned by the wallet
+    pub fn list(wallet: &WalletHandle) -> Result<Vec<Self>> {
+        let shared_key_ids: Vec<Uuid> = wallet.with(|w| {
+            Ok(w.shared_keys
+                .iter()
+                .filter(|k| k.unspent && k.statechain_id.is_some() && k.exit.is_none())
+                .map(|k| k.id)
+                .collect())
+        })?;
+        shared_key_ids
+            .iter()
+            .map(|id| Self::new(wallet, id))
+            .collect()
+    }
+
+    pub fn shared_key_id(&self) -> Uuid {
+        self.shared_key_id
+    }
+
+    /// Register a listener for the events of operations on this handle
+    pub fn on_event<F>(&mut self, listener: F)
+    where
+        F: Fn(&StateCoinEvent) + Send + Sync + 'static,
+    {
+        self.listeners.push(Arc::new(listener));
+    }
+
+    /// Wallet data and status of the coin
+    pub fn status(&self) -> Result<StateCoinInfo> {
+        self.wallet.with(|w| {
+            let key = w.get_shared_key(&self.shared_key_id)?;
+            let status = match (key.unspent, key.statechain_id, &key.exit) {
+                (false, _, _) => StateCoinStatus::Spent,
+                (true, _, Some(_)) => StateCoinStatus::Exiting,
+                (true, None, None) => StateCoinStatus::Unconfirmed,
+                (true, Some(_), None) => StateCoinStatus::Owned,
+            };
+            Ok(StateCoinInfo {
+                shared_key_id: key.id,
+                statechain_id: key.statechain_id,
+                value: key.value,
+                proof_key: key.proof_key.clone(),
+                funding_txid: key.funding_txid.clone(),
+                status,
+            })
+        })
+    }
+
+    /// Transfer the coin to the bech32 encoded SCE address receiver_addr. Returns the bech32
+    /// encoded transfer message to send to the receiver.
+    pub fn transfer_to(&self, receiver_addr: &str) -> Result<String> {
+        let shared_key_id = self.shared_key_id;
+        self.run(StateCoinOp::Transfer, |wallet, statechain_id| {
+            let addr = encoding::decode_address(receiver_addr.to_string(), &wallet.network)?;
+            let transfer_msg3 = transfer::transfer_sender(wallet, &statechain_id, addr, None)?;
+            let transfer_msg = encoding::encode_message(transfer_msg3)?;
+            Ok((
+                transfer_msg.clone(),
+                StateCoinEvent::Transferred {
+                    shared_key_id,
+                    transfer_msg,
+                },
+            ))
+        })
+    }
+
+    /// Withdraw the coin to destination, paying tx_fee (satoshis). Returns the withdraw txid.
+    pub fn withdraw(&self, destination: &Address, tx_fee: u64) -> Result<String> {
+        let shared_key_id = self.shared_key_id;
+        self.run(StateCoinOp::Withdraw, |wallet, statechain_id| {
+            validate_address_network(destination, &wallet.get_bitcoin_network())?;
+            let (txid, amount) = withdraw::batch_withdraw_to_address(
+                wallet,
+                &vec![statechain_id],
+                &tx_fee,
+                destination,
+            )?;
+            Ok((
+                txid.clone(),
+                StateCoinEvent::Withdrawn {
+                    shared_key_id,
+                    txid,
+                    amount,
+                },
+            ))
+        })
+    }
+
+    /// Swap the coin in a swap group of swap_size statecoins. Returns a handle to the coin
+    /// received in the swap, with the listeners of this handle. The wallet is locked until the
+    /// swap completes. Swaps use tor if both client shims have it enabled.
+    pub fn join_swap(&self, swap_size: u64) -> Result<StateCoin> {
+        let shared_key_id = self.shared_key_id;
+        let new_shared_key_id = self.run(StateCoinOp::Swap, |wallet, statechain_id| {
+            let with_tor = wallet.client_shim.has_tor() && wallet.conductor_shim.has_tor();
+            let address = conductor::do_swap(wallet, &statechain_id, &swap_size, with_tor)?;
+            let proof_key = address.proof_key.to_string();
+            let new_shared_key_id = wallet
+                .shared_keys
+                .iter()
+                .find(|k| k.unspent && k.proof_key.as_ref() == Some(&proof_key))
+                .map(|k| k.id)
+                .ok_or(CError::SwapError(String::from(
+                    "swapped statecoin not found in wallet",
+                )))?;
+            Ok((
+                new_shared_key_id,
+                StateCoinEvent::Swapped {
+                    shared_key_id,
+                    new_shared_key_id,
+                },
+            ))
+        })?;
+        Ok(StateCoin {
+            wallet: self.wallet.clone(),
+            shared_key_id: new_shared_key_id,
+            listeners: self.listeners.clone(),
+        })
+    }
+
+    /// Run transfer_to() on a background thread
+    pub fn transfer_to_async(&self, receiver_addr: &str) -> JoinHandle<Result<String>> {
+        let coin = self.clone();
+        let receiver_addr = receiver_addr.to_string();
+        thread::spawn(move || coin.transfer_to(&receiver_addr))
+    }
+
+    /// Run withdraw() on a background thread
+    pub fn withdraw_async(&self, destination: &Address, tx_fee: u64) -> JoinHandle<Result<String>> {
+        let coin = self.clone();
+        let destination = destination.clone();
+        thread::spawn(move || coin.withdraw(&destination, tx_fee))
+    }
+
+    /// Run join_swap() on a background thread
+    pub fn join_swap_async(&self, swap_size: u64) -> JoinHandle<Result<StateCoin>> {
+        let coin = self.clone();
+        thread::spawn(move || coin.join_swap(swap_size))
+    }
+
+    fn emit(&self, event: StateCoinEvent) {
+        for listener in &self.listeners {
+            listener(&event);
+        }
+    }
+
+    /// Run operation op on the statechain of the coin, then save the wallet and report the
+    /// event returned by the operation, or its failure
+    fn run<F, T>(&self, op: StateCoinOp, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Wallet, Uuid) -> Result<(T, StateCoinEvent)>,
+    {
+        self.emit(StateCoinEvent::Started {
+            shared_key_id: self.shared_key_id,
+            op,
+        });
+        let shared_key_id = self.shared_key_id;
+        let res = self.wallet.update(|wallet| {
+            let key = wallet.get_shared_key(&shared_key_id)?;
+            let statechain_id = match (key.unspent, key.statechain_id, &key.exit) {
+                (true, Some(statechain_id), None) => statechain_id,
+                _ => {
+                    return Err(CError::Generic(format!(
+                        "Cannot {} statecoin {}: not owned by the wallet",
+                        op, shared_key_id
+                    )))
+                }
+            };
+            f(wallet, statechain_id)
+        });
+        match res {
+            Ok((value, event)) => {
+                self.emit(event);
+                Ok(value)
+            }
+            Err(e) => {
+                self.emit(StateCoinEvent::Failed {
+                    shared_key_id: self.shared_key_id,
+                    op,
+                    error: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::WalletErrorType;
+    use crate::wallet::wallet::DEFAULT_TEST_WALLET_LOC;
+    use crate::ClientShim;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+
+    fn gen_handle() -> WalletHandle {
+        WalletHandle::new(Wallet::new(
+            &[0xcd; 32],
+            &"regtest".to_string(),
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        ))
+    }
+
+    #[test]
+    fn test_statecoin_not_found() {
+        let handle = gen_handle();
+        assert!(StateCoin::list(&handle).unwrap().is_empty());
+        match StateCoin::new(&handle, &Uuid::new_v4()) {
+            Err(CError::WalletError(WalletErrorType::SharedKeyNotFound)) => (),
+            _ => assert!(false, "expected SharedKeyNotFound"),
+        }
+        assert!(StateCoin::from_statechain_id(&handle, &Uuid::new_v4()).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_statecoin_events() {
+        // Handle to a coin missing from the wallet: operations fail before any request
+        let mut coin = StateCoin {
+            wallet: gen_handle(),
+            shared_key_id: Uuid::new_v4(),
+            listeners: vec![],
+        };
+        let events = Arc::new(Mutex::new(vec![]));
+        let events_clone = events.clone();
+        coin.on_event(move |event| events_clone.lock().unwrap().push(event.clone()));
+
+        let destination = Address::from_str("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x").unwrap();
+        assert!(coin.withdraw_async(&destination, 300).join().unwrap().is_err());
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0],
+            StateCoinEvent::Started {
+                shared_key_id: coin.shared_key_id(),
+                op: StateCoinOp::Withdraw,
+            }
+        );
+        match &events[1] {
+            StateCoinEvent::Failed { op, .. } => assert_eq!(op, &StateCoinOp::Withdraw),
+            e => assert!(false, "expected Failed event, got {:?}", e),
+        }
+    }
+}
@@ -4,22 +4,56 @@
 
 use super::super::Result;
 use shared_lib::{
+    commitment::{p2c_tweak_fe, verify_p2c},
     ecies,
     ecies::{SelfEncryptable, WalletDecryptable},
+    electrum_batch::{script_hash, ElectrumBatchClient, ElectrumxBatch},
+    header_chain::{HeaderChain, TxMerkleProof},
     mocks::mock_electrum::MockElectrum,
-    structs::{Protocol, SCEAddress},
-    util::{transaction_deserialise, get_sighash},
+    state_chain::StateChainSig,
+    structs::{
+        FESer, PrepareSignTxMsg, Protocol, RecoveryDataMsg, RefreshMsg1, RefreshMsg2, RefreshReply,
+        S1PubKey, SCEAddress, SpendIncident, TimelockMode, TransferMsg2, TransferMsg3, UserID,
+    },
+    swap_data::SignedSwapReport,
+    util::{transaction_deserialise, transaction_serialise, get_sighash, tx_cpfp_build, validate_address_network, backup_tx_timelock, backup_tx_timelock_mode},
+    Root,
 };
 
-use super::key_paths::{KeyPath, KeyPathWithAddresses};
+use super::history::{export_history, Activity, ActivityEvent, HistoryFormat, PriceSource};
+use super::proof_cache::ProofCache;
+use super::receive_keys::{ReceiveKeyPool, DEFAULT_RECEIVE_KEY_POOL_SIZE};
+use super::migration::{migrate_wallet, wallet_version, WALLET_VERSION};
+use super::key_paths::{
+    account_path, derive_chain, funding_txid_to_int, legacy_path, uuid_to_int, ChainExport,
+    DerivationExport, KeyPath, KeyPathWithAddresses, CHAIN_KEYS, CHAIN_SE_BACKUP_KEYS,
+    CHAIN_SE_KEY_SHARES, CHAIN_SE_PROOF_KEYS, DERIVATION_EXPORT_VERSION,
+};
+use super::watch_only::{WatchOnlyCoin, WatchOnlyWallet};
+use crate::ecdsa;
 use crate::error::{CError, WalletErrorType};
-use crate::wallet::shared_key::SharedKey;
+use crate::state_entity::{
+    api::{get_confirmed_smt_root, get_smt_proofs, get_smt_root, get_statecoin, get_statechain_updates},
+    deposit::{deposit_handoff, deposit_resume, log_funding_progress},
+    inheritance,
+    transfer::{
+        transfer_get_msg_addr, transfer_receiver, transfer_reject, transfer_sender_many,
+        TransferFinalizeData, TransferManyReport,
+    },
+    util::verify_statechain_smt,
+    withdraw::{batch_withdraw_to_address, withdraw_all, withdraw_tx_fee, WithdrawAllReport},
+};
+use crate::utilities::requests;
+use crate::wallet::shared_key::{
+    BackupExit, CoinExpiry, PendingDeposit, PendingTransfer, SharedKey, SpendResponse,
+};
 use crate::ClientShim;
 
 use bitcoin::{
     secp256k1::{key::SecretKey, All, Message, Secp256k1},
-    util::bip32::{ChildNumber, ExtendedPrivKey},
-    {Address, Network, OutPoint, PublicKey, TxIn},
+    util::bip32::{DerivationPath, ExtendedPrivKey},
+    util::uint::Uint256,
+    {Address, Network, OutPoint, PublicKey, TxIn, Txid},
 };
 
 use electrumx_client::{
@@ -28,27 +62,42 @@ use electrumx_client::{
     response::{GetBalanceResponse, GetListUnspentResponse},
 };
 
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use curv::{FE, GE};
+use kms::ecdsa::two_party::MasterKey2;
 use serde_json::json;
+use monotree::Proof;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::str::FromStr;
 use uuid::Uuid;
 
 pub const DEFAULT_WALLET_LOC: &str = "wallet/wallet.data";
 pub const DEFAULT_TEST_WALLET_LOC: &str = "wallet/test_wallet.data";
-
-// Struct wrapper for Electrumx client instance
+/// Default number of blocks before backup tx locktime expiry at which statecoins are alerted (one day)
+pub const DEFAULT_EXPIRY_ALERT_BLOCKS: u32 = 144;
+/// Default maximum number of concurrent sender flows of transfer_many()
+pub const DEFAULT_TRANSFER_CONCURRENCY: usize = 4;
+/// Default maximum number of funding tx confirmation status polls of a deposit (six hours at
+/// the 30 second poll interval)
+pub const DEFAULT_FUNDING_CONF_MAX_POLLS: u32 = 720;
+
+// Struct wrapper for Electrumx client instance, with a client for batched script hash queries
 pub struct ElectrumxBox {
     pub instance: Box<dyn Electrumx>,
+    pub batch: Box<dyn ElectrumxBatch>,
 }
 impl ElectrumxBox {
     pub fn new(electrum_server: String) -> Result<Self> {
         Ok(ElectrumxBox {
-            instance: Box::new(ElectrumxClient::new(electrum_server)?),
+            instance: Box::new(ElectrumxClient::new(electrum_server.clone())?),
+            batch: Box::new(ElectrumBatchClient::new(electrum_server)?),
         })
     }
     pub fn new_mock() -> Self {
         ElectrumxBox {
             instance: Box::new(MockElectrum::new()),
+            batch: Box::new(MockElectrum::new()),
         }
     }
 }
@@ -66,6 +115,7 @@ pub struct Wallet {
     wallet_data_loc: String,
 
     pub master_priv_key: ExtendedPrivKey,
+    pub derivation_path: DerivationPath, // root path of the key chains (see key_paths)
     pub keys: KeyPathWithAddresses,           // Keys for general usage
     pub se_backup_keys: KeyPathWithAddresses, // keys for use in State Entity back up transactions
     pub se_proof_keys: KeyPath,               // for use as State Entity proof keys
@@ -73,35 +123,85 @@ pub struct Wallet {
 
     pub shared_keys: Vec<SharedKey>, // vector of keys co-owned with state entities
     pub require_mainstay: bool,
+    pub header_chain: Option<HeaderChain>, // headers to verify mainstay attestations against, fetched from Electrum if None
+    pub require_deletion_receipts: bool, // require receipts for the key shares of previous owners on transfer (needs a pinned server_identity_key)
+    pub external_backup_addrs: HashMap<String, Address>, // proof key -> user supplied backup tx address
+    pub sync_checkpoint: Option<String>, // checkpoint of the last incremental sync with the state entity
+    pub pending_deposits: Vec<PendingDeposit>, // deposits not yet confirmed by the state entity
+    pub pending_transfers: Vec<PendingTransfer>, // inbound transfers not yet accepted or rejected
+    pub auto_accept_transfers: bool, // accept inbound transfers fetched on sync
+    pub activity: Vec<ActivityEvent>, // deposits, transfers, swaps and withdrawals, for export_history()
+    pub swap_reports: Vec<SignedSwapReport>, // conductor reports of completed swaps, as proof of mixing
+    pub receive_keys: ReceiveKeyPool, // one-time keys transfer messages to this wallet are encrypted to
+    pub proof_cache: ProofCache, // verified SMT roots and inclusion proofs
+    receive_key_pool_size: usize, // unissued receive keys kept in the pool
+    expiry_alert_blocks: u32, // blocks remaining before backup tx locktime expiry at which to alert
+    expiry_alert_hook: Option<Box<dyn Fn(&CoinExpiry) + Send + Sync>>,
+    second_factor_hook: Option<Box<dyn Fn(&Uuid, &StateChainSig) -> Result<Option<StateChainSig>> + Send + Sync>>,
+    spend_alert_hook: Option<Box<dyn Fn(&SpendIncident) + Send + Sync>>,
+    spend_response: SpendResponse, // response to competing spends of statecoin funding outputs
+    transfer_concurrency: usize, // maximum concurrent sender flows of transfer_many()
+    funding_conf_max_polls: u32, // funding tx confirmation polls before a deposit stops waiting
+    share_refresh_interval: Option<i64>, // seconds between scheduled key share refreshes on sync
 }
 impl Wallet {
     pub fn new(seed: &[u8], network: &String, wallet_data_loc: &str, client_shim: ClientShim, conductor_shim: ClientShim) -> Wallet {
-        let secp = Secp256k1::new();
         let master_priv_key =
             ExtendedPrivKey::new_master(network.parse::<Network>().unwrap(), seed).unwrap();
+        Self::new_at_path(
+            master_priv_key,
+            legacy_path(),
+            Uuid::new_v4().to_string(),
+            network,
+            wallet_data_loc,
+            client_shim,
+            conductor_shim,
+        )
+        .unwrap()
+    }
 
-        let keys_master_ext_key = master_priv_key
-            .ckd_priv(&secp, ChildNumber::from_hardened_idx(0).unwrap())
-            .unwrap();
-        let keys = KeyPathWithAddresses::new(keys_master_ext_key);
-
-        let se_backup_keys_master_ext_key = master_priv_key
-            .ckd_priv(&secp, ChildNumber::from_hardened_idx(1).unwrap())
-            .unwrap();
-        let se_backup_keys = KeyPathWithAddresses::new(se_backup_keys_master_ext_key);
-
-        let se_proof_keys_master_ext_key = master_priv_key
-            .ckd_priv(&secp, ChildNumber::from_hardened_idx(2).unwrap())
-            .unwrap();
-        let se_proof_keys = KeyPath::new(se_proof_keys_master_ext_key);
+    /// Create a wallet with keys derived at 'account' of the derivation scheme
+    /// (m / purpose' / coin_type' / account' / chain' / index')
+    pub fn new_account(
+        seed: &[u8],
+        network: &String,
+        account: u32,
+        wallet_data_loc: &str,
+        client_shim: ClientShim,
+        conductor_shim: ClientShim,
+    ) -> Result<Wallet> {
+        let network_type = network.parse::<Network>()?;
+        let master_priv_key = ExtendedPrivKey::new_master(network_type, seed)?;
+        Self::new_at_path(
+            master_priv_key,
+            account_path(network_type, account)?,
+            Uuid::new_v4().to_string(),
+            network,
+            wallet_data_loc,
+            client_shim,
+            conductor_shim,
+        )
+    }
 
-        let se_key_shares_master_ext_key = master_priv_key
-            .ckd_priv(&secp, ChildNumber::from_hardened_idx(3).unwrap())
-            .unwrap();
-        let se_key_shares = KeyPath::new(se_key_shares_master_ext_key);
+    /// Create a wallet with no keys derived whose key chains are derived under 'derivation_path'
+    fn new_at_path(
+        master_priv_key: ExtendedPrivKey,
+        derivation_path: DerivationPath,
+        id: String,
+        network: &String,
+        wallet_data_loc: &str,
+        client_shim: ClientShim,
+        conductor_shim: ClientShim,
+    ) -> Result<Wallet> {
+        let secp = Secp256k1::new();
+        let derive = |chain| derive_chain(&secp, &master_priv_key, &derivation_path, chain);
+        let keys = KeyPathWithAddresses::new(derive(CHAIN_KEYS)?);
+        let se_backup_keys = KeyPathWithAddresses::new(derive(CHAIN_SE_BACKUP_KEYS)?);
+        let se_proof_keys = KeyPath::new(derive(CHAIN_SE_PROOF_KEYS)?);
+        let se_key_shares = KeyPath::new(derive(CHAIN_SE_KEY_SHARES)?);
 
-        Wallet {
-            id: Uuid::new_v4().to_string(),
+        Ok(Wallet {
+            id,
             network: network.to_string(),
             electrumx_client: ElectrumxBox::new_mock(),
             client_shim,
@@ -109,13 +209,95 @@ impl Wallet {
             secp,
             wallet_data_loc: wallet_data_loc.to_string(),
             master_priv_key,
+            derivation_path,
             keys,
             se_backup_keys,
             se_proof_keys,
             se_key_shares,
             shared_keys: vec![],
             require_mainstay: false,
+            header_chain: None,
+            require_deletion_receipts: false,
+            external_backup_addrs: HashMap::new(),
+            sync_checkpoint: None,
+            pending_deposits: vec![],
+            pending_transfers: vec![],
+            auto_accept_transfers: true,
+            activity: vec![],
+            swap_reports: vec![],
+            receive_keys: ReceiveKeyPool::default(),
+            proof_cache: ProofCache::default(),
+            receive_key_pool_size: DEFAULT_RECEIVE_KEY_POOL_SIZE,
+            expiry_alert_blocks: DEFAULT_EXPIRY_ALERT_BLOCKS,
+            expiry_alert_hook: None,
+            second_factor_hook: None,
+            spend_alert_hook: None,
+            spend_response: SpendResponse::default(),
+            transfer_concurrency: DEFAULT_TRANSFER_CONCURRENCY,
+            funding_conf_max_polls: DEFAULT_FUNDING_CONF_MAX_POLLS,
+            share_refresh_interval: None,
+        })
+    }
+
+    /// Export the master key, derivation path and derived key indices in the portable
+    /// derivation format
+    pub fn export_derivation(&self) -> DerivationExport {
+        DerivationExport {
+            version: DERIVATION_EXPORT_VERSION,
+            network: self.network.clone(),
+            master_priv_key: self.master_priv_key.to_string(),
+            root_path: self.derivation_path.to_string(),
+            chains: vec![
+                ChainExport::from_key_path_with_addresses(CHAIN_KEYS, &self.keys),
+                ChainExport::from_key_path_with_addresses(CHAIN_SE_BACKUP_KEYS, &self.se_backup_keys),
+                ChainExport::from_key_path(CHAIN_SE_PROOF_KEYS, &self.se_proof_keys),
+                ChainExport::from_key_path(CHAIN_SE_KEY_SHARES, &self.se_key_shares),
+            ],
+        }
+    }
+
+    /// Create a wallet from a derivation export, re-deriving all exported keys. Statecoins are
+    /// not part of the export and are recovered from the State Entity with recover_shared_keys().
+    pub fn import_derivation(
+        export: &DerivationExport,
+        wallet_data_loc: &str,
+        client_shim: ClientShim,
+        conductor_shim: ClientShim,
+    ) -> Result<Wallet> {
+        if export.version > DERIVATION_EXPORT_VERSION {
+            return Err(CError::Generic(format!(
+                "Unsupported derivation export version: {}",
+                export.version
+            )));
         }
+        let network = export.network.parse::<Network>()?;
+        let mut master_priv_key = ExtendedPrivKey::from_str(&export.master_priv_key)
+            .map_err(|e| CError::Generic(format!("Invalid master private key: {}", e)))?;
+        master_priv_key.network = network;
+        let derivation_path = DerivationPath::from_str(&export.root_path)?;
+
+        let mut wallet = Self::new_at_path(
+            master_priv_key,
+            derivation_path,
+            Uuid::new_v4().to_string(),
+            &export.network,
+            wallet_data_loc,
+            client_shim,
+            conductor_shim,
+        )?;
+        export
+            .get_chain(CHAIN_KEYS)
+            .derive_key_path_with_addresses(&mut wallet.keys)?;
+        export
+            .get_chain(CHAIN_SE_BACKUP_KEYS)
+            .derive_key_path_with_addresses(&mut wallet.se_backup_keys)?;
+        export
+            .get_chain(CHAIN_SE_PROOF_KEYS)
+            .derive_key_path(&mut wallet.se_proof_keys)?;
+        export
+            .get_chain(CHAIN_SE_KEY_SHARES)
+            .derive_key_path(&mut wallet.se_key_shares)?;
+        Ok(wallet)
     }
 
     //pub fn shared_keys_mutable<'a>(&'a mut self) -> &'a mut Vec<SharedKey> {
@@ -134,6 +316,170 @@ impl Wallet {
         self.require_mainstay
     }
 
+    /// Load the header chain to verify mainstay attestations against from a header chain file
+    pub fn load_header_chain(&mut self, path: &str) -> Result<()> {
+        self.header_chain = Some(HeaderChain::from_file(path)?);
+        Ok(())
+    }
+
+    /// Get the SMT root confirmed by mainstay and verify, against Bitcoin block headers rather
+    /// than the mainstay API, that its attestation tx is confirmed under at least min_work.
+    /// Headers are taken from the loaded header chain, or fetched from the Electrum server. The
+    /// pay-to-contract commitment of the attestation tx to the mainstay merkle root is not checked.
+    /// The verified root is recorded in the proof cache.
+    pub fn get_verified_smt_root(&mut self, min_work: &Uint256) -> Result<Root> {
+        let root = get_confirmed_smt_root(&self.client_shim)?
+            .ok_or(CError::Generic(String::from("No confirmed SMT root")))?;
+        let ci = root.commitment_info().as_ref().ok_or(CError::Generic(String::from(
+            "Confirmed SMT root has no mainstay commitment info",
+        )))?;
+        if !ci.verify() {
+            return Err(CError::Generic(String::from(
+                "Invalid mainstay commitment merkle proof",
+            )));
+        }
+        let attestation = ci.attestation().ok_or(CError::Generic(String::from(
+            "Confirmed SMT root has no mainstay attestation",
+        )))?;
+        let txid = Txid::from_str(&attestation.txid().to_string())
+            .map_err(|e| CError::Generic(format!("Invalid attestation txid: {}", e)))?;
+
+        let electrum = &mut *self.electrumx_client.instance;
+        let confirmations = electrum
+            .get_transaction_conf_status(txid.to_string(), false)?
+            .confirmations
+            .unwrap_or(0);
+        let tip = electrum.get_tip_header()?.height as u32;
+        let height = match confirmations {
+            0 => None,
+            c => (tip + 1).checked_sub(c),
+        }
+        .ok_or(CError::Generic(format!("Attestation tx {} not confirmed", txid)))?;
+        let proof = TxMerkleProof::from_electrum(electrum, &txid, height)?;
+
+        let fetched;
+        let headers = match &self.header_chain {
+            Some(headers) => headers,
+            None => {
+                fetched = HeaderChain::from_electrum(electrum, proof.block_height)?;
+                &fetched
+            }
+        };
+        let confirmations = headers.verify_tx(&txid, &proof, min_work)?;
+        debug!(
+            "Verified SMT root {:?} attestation tx {} with {} confirmations",
+            root.id(),
+            txid,
+            confirmations
+        );
+        self.proof_cache.record_root(&root)?;
+        Ok(root)
+    }
+
+    pub fn set_require_deletion_receipts(&mut self, val: bool) {
+        self.require_deletion_receipts = val;
+    }
+
+    pub fn require_deletion_receipts(&self) -> bool {
+        self.require_deletion_receipts
+    }
+
+    pub fn set_auto_accept_transfers(&mut self, val: bool) {
+        self.auto_accept_transfers = val;
+    }
+
+    pub fn auto_accept_transfers(&self) -> bool {
+        self.auto_accept_transfers
+    }
+
+    pub fn set_expiry_alert_blocks(&mut self, val: u32) {
+        self.expiry_alert_blocks = val;
+    }
+
+    pub fn expiry_alert_blocks(&self) -> u32 {
+        self.expiry_alert_blocks
+    }
+
+    pub fn set_transfer_concurrency(&mut self, val: usize) {
+        self.transfer_concurrency = val.max(1);
+    }
+
+    pub fn transfer_concurrency(&self) -> usize {
+        self.transfer_concurrency
+    }
+
+    /// Stop waiting for the funding tx of a deposit to confirm after this many status polls.
+    /// The deposit stays pending and can be resumed later.
+    pub fn set_funding_conf_max_polls(&mut self, val: u32) {
+        self.funding_conf_max_polls = val.max(1);
+    }
+
+    pub fn funding_conf_max_polls(&self) -> u32 {
+        self.funding_conf_max_polls
+    }
+
+    /// Refresh the key shares of each statecoin on sync once this many seconds have passed since
+    /// its last refresh. None to refresh only on demand with refresh_key().
+    pub fn set_share_refresh_interval(&mut self, val: Option<i64>) {
+        self.share_refresh_interval = val;
+    }
+
+    pub fn share_refresh_interval(&self) -> Option<i64> {
+        self.share_refresh_interval
+    }
+
+    pub fn set_receive_key_pool_size(&mut self, val: usize) {
+        self.receive_key_pool_size = val;
+    }
+
+    pub fn receive_key_pool_size(&self) -> usize {
+        self.receive_key_pool_size
+    }
+
+    /// Set the hook called by get_expiry_report() for each statecoin whose backup tx locktime
+    /// expires within the expiry alert threshold
+    pub fn set_expiry_alert_hook(&mut self, hook: Box<dyn Fn(&CoinExpiry) + Send + Sync>) {
+        self.expiry_alert_hook = Some(hook);
+    }
+
+    /// Set the hook asked for the second factor signature over the statechain sig of a
+    /// transfer, withdrawal or migration of a statecoin, e.g. by prompting a phone holding the
+    /// second factor key. Returns None for statecoins without a second factor.
+    pub fn set_second_factor_hook(
+        &mut self,
+        hook: Box<dyn Fn(&Uuid, &StateChainSig) -> Result<Option<StateChainSig>> + Send + Sync>,
+    ) {
+        self.second_factor_hook = Some(hook);
+    }
+
+    /// Set the hook called by sync() for each statecoin whose funding output the State Entity
+    /// reports spent by a tx other than its current backup tx, e.g. an old backup tx broadcast by
+    /// a previous owner
+    pub fn set_spend_alert_hook(&mut self, hook: Box<dyn Fn(&SpendIncident) + Send + Sync>) {
+        self.spend_alert_hook = Some(hook);
+    }
+
+    /// Set the response of sync() to competing spends of statecoin funding outputs
+    pub fn set_spend_response(&mut self, val: SpendResponse) {
+        self.spend_response = val;
+    }
+
+    pub fn spend_response(&self) -> SpendResponse {
+        self.spend_response
+    }
+
+    /// Second factor signature over statechain_sig from the second factor hook, if set
+    pub fn second_factor_sig(
+        &self,
+        statechain_id: &Uuid,
+        statechain_sig: &StateChainSig,
+    ) -> Result<Option<StateChainSig>> {
+        match &self.second_factor_hook {
+            Some(hook) => hook(statechain_id, statechain_sig),
+            None => Ok(None),
+        }
+    }
+
     /// serialize wallet to json
     pub fn to_json(&self) -> serde_json::Value {
         // get all encoded child indices for KeyPaths used in state entity protocols
@@ -156,10 +502,12 @@ impl Wallet {
             }
         }
         json!({
+            "version": WALLET_VERSION,
             "id": self.id,
             "network": self.network,
             "wallet_data_loc": self.wallet_data_loc,
             "master_priv_key": self.master_priv_key.to_string(),
+            "derivation_path": self.derivation_path.to_string(),
             "keys_last_derived_pos": self.keys.last_derived_pos,
             "se_backup_keys_last_derived_pos": self.se_backup_keys.last_derived_pos,
             "se_backup_keys_pos_encoded": serde_json::to_string(&se_backup_keys_pos_encoded).unwrap(),
@@ -168,13 +516,23 @@ impl Wallet {
             "se_key_shares_last_derivation_pos": self.se_key_shares.last_derived_pos,
             "se_key_shares_pos_encoded": serde_json::to_string(&se_key_shares_pos_encoded).unwrap(),
             "shared_keys": serde_json::to_string(&self.shared_keys).unwrap(),
-            "require_mainstay": self.require_mainstay
+            "require_mainstay": self.require_mainstay,
+            "require_deletion_receipts": self.require_deletion_receipts,
+            "external_backup_addrs": serde_json::to_string(&self.external_backup_addrs).unwrap(),
+            "sync_checkpoint": self.sync_checkpoint,
+            "pending_deposits": serde_json::to_string(&self.pending_deposits).unwrap(),
+            "pending_transfers": serde_json::to_string(&self.pending_transfers).unwrap(),
+            "auto_accept_transfers": self.auto_accept_transfers,
+            "activity": serde_json::to_string(&self.activity).unwrap(),
+            "swap_reports": serde_json::to_string(&self.swap_reports).unwrap(),
+            "receive_keys": serde_json::to_string(&self.receive_keys).unwrap(),
+            "proof_cache": serde_json::to_string(&self.proof_cache).unwrap()
         })
     }
 
-    /// load wallet from json
+    /// load wallet from json, upgrading wallets saved in an earlier format
     pub fn from_json(json: serde_json::Value, client_shim: ClientShim, conductor_shim: ClientShim) -> Result<Self> {
-        let secp = Secp256k1::new();
+        let json = migrate_wallet(json)?;
         let network = json["network"].as_str().unwrap().to_string();
 
         // master extended keys
@@ -182,50 +540,25 @@ impl Wallet {
             ExtendedPrivKey::from_str(json["master_priv_key"].as_str().unwrap()).unwrap();
         master_priv_key.network = network.parse::<Network>().unwrap();
 
-        // keys
-        let mut keys_master_ext_key = master_priv_key
-            .ckd_priv(&secp, ChildNumber::from_hardened_idx(0).unwrap())
-            .unwrap();
-        keys_master_ext_key.network = network.parse::<Network>().unwrap();
-        let keys = KeyPathWithAddresses::new(keys_master_ext_key);
-
-        // se_backup_keys
-        let mut se_backup_keys_master_ext_key = master_priv_key
-            .ckd_priv(&secp, ChildNumber::from_hardened_idx(1).unwrap())
-            .unwrap();
-        se_backup_keys_master_ext_key.network = network.parse::<Network>().unwrap();
-        let se_backup_keys = KeyPathWithAddresses::new(se_backup_keys_master_ext_key);
-
-        // se_proof_keys
-        let mut se_proof_keys_master_ext_key = master_priv_key
-            .ckd_priv(&secp, ChildNumber::from_hardened_idx(2).unwrap())
-            .unwrap();
-        se_proof_keys_master_ext_key.network = network.parse::<Network>().unwrap();
-        let se_proof_keys = KeyPath::new(se_proof_keys_master_ext_key);
-
-        // se_key_shares
-        let mut se_key_shares_master_ext_key = master_priv_key
-            .ckd_priv(&secp, ChildNumber::from_hardened_idx(3).unwrap())
-            .unwrap();
-        se_key_shares_master_ext_key.network = network.parse::<Network>().unwrap();
-        let se_key_shares = KeyPath::new(se_key_shares_master_ext_key);
+        // wallets saved before the derivation scheme derive key chains from the master key
+        let derivation_path = match json.get("derivation_path").and_then(|v| v.as_str()) {
+            Some(path) => DerivationPath::from_str(path)?,
+            None => legacy_path(),
+        };
 
-        let mut wallet = Wallet {
-            id: json["id"].as_str().unwrap().to_string(),
-            network,
-            electrumx_client: ElectrumxBox::new_mock(),
+        let mut wallet = Self::new_at_path(
+            master_priv_key,
+            derivation_path,
+            json["id"].as_str().unwrap().to_string(),
+            &network,
+            json["wallet_data_loc"].as_str().unwrap(),
             client_shim,
             conductor_shim,
-            secp,
-            wallet_data_loc: json["wallet_data_loc"].as_str().unwrap().to_string(),
-            master_priv_key,
-            keys,
-            se_backup_keys,
-            se_proof_keys,
-            se_key_shares,
-            shared_keys: vec![],
-            require_mainstay: json.get("require_mainstay").unwrap().as_bool().unwrap(),
-        };
+        )?;
+        wallet.require_mainstay = json.get("require_mainstay").unwrap().as_bool().unwrap();
+        wallet.require_deletion_receipts = json.get("require_deletion_receipts").and_then(|v| v.as_bool()).unwrap_or(false);
+        wallet.sync_checkpoint = json.get("sync_checkpoint").and_then(|v| v.as_str()).map(String::from);
+        wallet.auto_accept_transfers = json.get("auto_accept_transfers").and_then(|v| v.as_bool()).unwrap_or(true);
 
         // re-derive keys which have been previously derived
         for _ in 0..json["keys_last_derived_pos"].as_u64().unwrap() {
@@ -276,6 +609,35 @@ impl Wallet {
             wallet.shared_keys = shared_keys;
         }
 
+        // wallets saved before external backup addresses were supported have no entry
+        if let Some(addrs_str) = json.get("external_backup_addrs").and_then(|v| v.as_str()) {
+            wallet.external_backup_addrs = serde_json::from_str(addrs_str)?;
+        }
+
+        if let Some(pending_str) = json.get("pending_deposits").and_then(|v| v.as_str()) {
+            wallet.pending_deposits = serde_json::from_str(pending_str)?;
+        }
+
+        if let Some(pending_str) = json.get("pending_transfers").and_then(|v| v.as_str()) {
+            wallet.pending_transfers = serde_json::from_str(pending_str)?;
+        }
+
+        if let Some(activity_str) = json.get("activity").and_then(|v| v.as_str()) {
+            wallet.activity = serde_json::from_str(activity_str)?;
+        }
+
+        if let Some(reports_str) = json.get("swap_reports").and_then(|v| v.as_str()) {
+            wallet.swap_reports = serde_json::from_str(reports_str)?;
+        }
+
+        if let Some(receive_keys_str) = json.get("receive_keys").and_then(|v| v.as_str()) {
+            wallet.receive_keys = serde_json::from_str(receive_keys_str)?;
+        }
+
+        if let Some(proof_cache_str) = json.get("proof_cache").and_then(|v| v.as_str()) {
+            wallet.proof_cache = serde_json::from_str(proof_cache_str)?;
+        }
+
         debug!("(wallet id: {}) Loaded wallet to memory", wallet.id);
         Ok(wallet)
     }
@@ -287,24 +649,64 @@ impl Wallet {
         debug!("(wallet id: {}) Saved wallet to disk", self.id);
     }
 
-    /// load wallet from disk
+    /// load wallet from disk. A wallet saved in an earlier format is upgraded, keeping a copy
+    /// of the original file at <wallet_data_loc>.v<version>.bak
     pub fn load(wallet_data_loc: &str, client_shim: ClientShim, conductor_shim: ClientShim) -> Result<Wallet> {
         let data = match fs::read_to_string(wallet_data_loc) {
             Ok(data) => data,
             Err(_) => return Err(CError::WalletError(WalletErrorType::WalletFileNotFound))
         };
-        let serde_json_data = match serde_json::from_str(&data) {
+        let serde_json_data: serde_json::Value = match serde_json::from_str(&data) {
             Ok(data) => data,
             Err(_) => return Err(CError::WalletError(WalletErrorType::WalletFileInvalid))
         };
-        let wallet: Wallet = match Wallet::from_json(serde_json_data, client_shim, conductor_shim) {
+        let version = wallet_version(&serde_json_data)?;
+        if version < WALLET_VERSION {
+            let backup_loc = format!("{}.v{}.bak", wallet_data_loc, version);
+            fs::write(&backup_loc, &data)?;
+            info!("Wallet file version {} backed up to {}", version, backup_loc);
+        }
+        let migrated = migrate_wallet(serde_json_data)?;
+        let wallet: Wallet = match Wallet::from_json(migrated.clone(), client_shim, conductor_shim) {
             Ok(wallet) => wallet,
             Err(_) => return Err(CError::WalletError(WalletErrorType::WalletFileInvalid))
         };
+        if version < WALLET_VERSION {
+            fs::write(wallet_data_loc, migrated.to_string())?;
+            info!("Wallet file upgraded from version {} to {}", version, WALLET_VERSION);
+        }
         debug!("(wallet id: {}) Loaded wallet to memory", wallet.id);
         Ok(wallet)
     }
 
+    /// Export public wallet data (addresses, proof keys and statecoin data) to a watch-only wallet
+    pub fn export_watch_only(&self, wallet_data_loc: &str) -> WatchOnlyWallet {
+        let addresses = self
+            .get_all_wallet_addresses()
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        let proof_keys = self
+            .se_proof_keys
+            .key_derivation_map
+            .keys()
+            .cloned()
+            .collect();
+        let coins = self
+            .shared_keys
+            .iter()
+            .map(|shared_key| WatchOnlyCoin::from_shared_key(shared_key))
+            .collect();
+        WatchOnlyWallet::new(
+            &self.id,
+            &self.network,
+            wallet_data_loc,
+            addresses,
+            proof_keys,
+            coins,
+        )
+    }
+
     /// Select unspent coins greedily. Return TxIns along with corresponding spending addresses and amounts
     pub fn coin_selection_greedy(
         &mut self,
@@ -332,14 +734,54 @@ impl Wallet {
     }
 
     pub fn get_new_state_entity_address(&mut self) -> Result<SCEAddress> {
+        self.get_new_state_entity_address_with_backup(None)
+    }
+
+    /// Generate a new SCEAddress. Backup txs of coins received at this address pay to 'backup_addr'
+    /// if supplied (e.g. cold storage), otherwise to the proof key in se_backup_keys. The address
+    /// carries a one-time receive key from the receive key pool.
+    pub fn get_new_state_entity_address_with_backup(&mut self, backup_addr: Option<Address>) -> Result<SCEAddress> {
 
         let (proof_key, priv_key) = self
             .se_proof_keys
             .get_new_key_priv()?;
-        // add proof key to address map
-        let tx_backup_addr = Some(self.se_backup_keys.add_address(proof_key,priv_key)?);
+        let tx_backup_addr = match backup_addr {
+            Some(addr) => {
+                validate_address_network(&addr, &self.get_bitcoin_network())?;
+                // record address so that incoming transfers paying to it are accepted
+                self.external_backup_addrs.insert(proof_key.to_string(), addr.clone());
+                Some(addr)
+            },
+            // add proof key to address map
+            None => Some(self.se_backup_keys.add_address(proof_key,priv_key)?),
+        };
+
+        let enc_key = self.issue_receive_key(&proof_key);
 
-        Ok(SCEAddress {tx_backup_addr, proof_key: proof_key.key})
+        Ok(SCEAddress {tx_backup_addr, proof_key: proof_key.key, enc_key: Some(enc_key.key)})
+    }
+
+    /// Issue a one-time receive key for an address of proof_key. Transfer messages to the
+    /// address are encrypted to the key, which is deleted by delete_receive_key() once used.
+    /// The wallet must be saved before the address is given out: receive keys are not derived
+    /// from the seed and cannot be recovered.
+    pub fn issue_receive_key(&mut self, proof_key: &PublicKey) -> PublicKey {
+        self.receive_keys
+            .issue(&proof_key.to_string(), self.receive_key_pool_size)
+    }
+
+    /// Delete a one-time receive key once the transfer encrypted to it has been received or
+    /// rejected, so that the transfer message cannot be decrypted again
+    pub fn delete_receive_key(&mut self, enc_key: &PublicKey) {
+        self.receive_keys.delete(enc_key);
+    }
+
+    /// Check that backup tx address is owned by this wallet or was supplied by the user for proof key
+    pub fn is_own_backup_addr(&self, address: &Address, proof_key: &String) -> bool {
+        self.se_backup_keys
+            .get_address_derivation(&address.to_string())
+            .is_some()
+            || self.external_backup_addrs.get(proof_key) == Some(address)
     }
 
     /// Sign inputs with given addresses derived by this wallet. input_indices, addresses and amoumts lists
@@ -406,7 +848,35 @@ impl Wallet {
                 let priv_k = p.private_key;
                 val.decrypt(&priv_k)
             }
-            None => Err(CError::WalletError(WalletErrorType::KeyNotFound).into()),
+            // one-time receive keys are deleted once used
+            None => match self.receive_keys.get_private_key(k) {
+                Some(priv_k) => val.decrypt(&priv_k),
+                None => Err(CError::WalletError(WalletErrorType::KeyNotFound).into()),
+            },
+        }
+    }
+
+    /// Open the encrypted channel for the ECDSA protocol messages of user session 'id' if ECDSA
+    /// encryption is enabled and not already open. The channel is keyed by 'proof_key', which is
+    /// skipped with a warning if its private key is not in the wallet, e.g. for deposits made
+    /// on behalf of another wallet.
+    pub fn open_ecdsa_channel(&self, id: &Uuid, proof_key: &String) -> Result<()> {
+        if !self.client_shim.ecdsa_encryption || self.client_shim.noise.lock()?.contains_key(id) {
+            return Ok(());
+        }
+        let proof_key = PublicKey::from_str(proof_key)
+            .map_err(|e| CError::Generic(format!("Invalid proof key: {}", e)))?;
+        match self.se_proof_keys.get_key_derivation(&proof_key) {
+            Some(derivation) => {
+                ecdsa::noise::noise_connect(&self.client_shim, id, &derivation.private_key.key)
+            }
+            None => {
+                warn!(
+                    "(wallet id: {}) No private key for proof key {}. ECDSA messages not encrypted.",
+                    self.id, proof_key
+                );
+                Ok(())
+            }
         }
     }
 
@@ -415,9 +885,10 @@ impl Wallet {
         self.gen_shared_key_repeat_keygen(id, value, solution, 0)
     }
 
-     /// create new 2P-ECDSA key with state entity
+     /// create new 2P-ECDSA key with state entity. The private key share is derived from the
+     /// shared key id so that it can be regenerated from the wallet seed.
      pub fn gen_shared_key_repeat_keygen(&mut self, id: &Uuid, value: &u64, solution: String, kg1_reps: u32) -> Result<&SharedKey> {
-        let key_share_pub = self.se_key_shares.get_new_key()?;
+        let key_share_pub = self.se_key_shares.get_new_key_encoded_id(uuid_to_int(id), None)?;
         let key_share_priv = self
             .se_key_shares
             .get_key_derivation(&key_share_pub)
@@ -438,6 +909,28 @@ impl Wallet {
         Ok(self.shared_keys.last().unwrap())
     }
 
+    /// create new deposit 2P-ECDSA key committing to p2c_commitment in the funding output key
+    pub fn gen_shared_key_p2c(&mut self, id: &Uuid, value: &u64, solution: String, p2c_commitment: &String) -> Result<&SharedKey> {
+        let key_share_pub = self.se_key_shares.get_new_key_encoded_id(uuid_to_int(id), None)?;
+        let key_share_priv = self
+            .se_key_shares
+            .get_key_derivation(&key_share_pub)
+            .unwrap()
+            .private_key
+            .key;
+
+        let shared_key = SharedKey::new_p2c(
+            id,
+            &self.client_shim,
+            &key_share_priv,
+            value,
+            solution,
+            p2c_commitment,
+        )?;
+        self.shared_keys.push(shared_key);
+        Ok(self.shared_keys.last().unwrap())
+    }
+
     /// create new 2P-ECDSA key with pre-definfed private key
     pub fn gen_shared_key_fixed_secret_key(
         &mut self,
@@ -468,6 +961,415 @@ impl Wallet {
             Ok(())
         }
 
+    /// Refresh a shared key generated in an earlier State Entity key epoch. Keygen is re-run with the
+    /// existing private share so that the shared public key is unchanged.
+    pub fn refresh_shared_key(&mut self, id: &Uuid) -> Result<()> {
+        let (o1, value, shared_pk, proof_key) = {
+            let shared_key = self.get_shared_key(id)?;
+            (
+                shared_key.share.private.get_private_key(),
+                shared_key.value,
+                shared_key.share.public.q,
+                shared_key.proof_key.clone(),
+            )
+        };
+        if let Some(proof_key) = proof_key {
+            self.open_ecdsa_channel(id, &proof_key)?;
+        }
+
+        requests::postb::<_, ()>(
+            &self.client_shim,
+            "ecdsa/keygen/refresh",
+            UserID { id: *id, challenge: None },
+        )?;
+        let refreshed = ecdsa::get_master_key(
+            id,
+            &self.client_shim,
+            &o1,
+            &value,
+            Protocol::Transfer,
+            String::default(),
+        )?;
+
+        if refreshed.share.public.q != shared_pk {
+            return Err(CError::StateEntityError(String::from(
+                "Key refresh failed. Shared public key changed.",
+            )));
+        }
+
+        self.get_shared_key_mut(id)?.share = refreshed.share;
+        debug!("(wallet id: {}) Refreshed shared key {}", self.id, id);
+        Ok(())
+    }
+
+    /// Refresh the key shares of a statecoin with the State Entity (proactive security). The owner
+    /// and State Entity shares are replaced with new shares of the same shared key, so that the
+    /// shared public key and backup tx are unchanged and shares leaked before the refresh are of no
+    /// use. The new owner share is derived from the wallet key share path but cannot be matched by
+    /// recover_shared_keys(): it is saved with the wallet. Return the new share epoch.
+    pub fn refresh_key(&mut self, statechain_id: &Uuid) -> Result<u32> {
+        let (shared_key_id, o1, value, shared_pk, proof_key) = {
+            let shared_key = self.get_shared_key_by_statechain_id(statechain_id)?;
+            if !shared_key.unspent {
+                return Err(CError::StateEntityError(format!(
+                    "Key share refresh failed. State Chain {} is spent.",
+                    statechain_id
+                )));
+            }
+            (
+                shared_key.id,
+                shared_key.share.private.get_private_key(),
+                shared_key.value,
+                shared_key.share.public.q,
+                shared_key.proof_key.clone(),
+            )
+        };
+        if let Some(proof_key) = proof_key {
+            self.open_ecdsa_channel(&shared_key_id, &proof_key)?;
+        }
+
+        let mut transfer_msg2: TransferMsg2 = requests::postb(
+            &self.client_shim,
+            "refresh/first",
+            &RefreshMsg1 { shared_key_id },
+        )?;
+        self.decrypt(&mut transfer_msg2)?;
+
+        // t1 = o1x1, t2 = t1*o2_inv
+        let x1 = transfer_msg2.x1.get_fe()?;
+        let (_, o2_priv) = self.se_key_shares.get_new_key_priv()?;
+        let mut o2: FE = ECScalar::zero();
+        o2.set_element(o2_priv.key);
+        let g: GE = ECPoint::generator();
+        let o2_pub: GE = g * o2;
+        let t2 = o1 * x1 * (o2.invert());
+
+        // get SE public key share for t2 encryption
+        let s1_pub: S1PubKey = requests::postb(
+            &self.client_shim,
+            "transfer/pubkey",
+            UserID {
+                id: shared_key_id,
+                challenge: None,
+            },
+        )?;
+        let s1_pub = PublicKey::from_str(&s1_pub.key)
+            .map_err(|e| CError::Generic(format!("Invalid SE public key share: {}", e)))?;
+
+        let mut refresh_msg2 = RefreshMsg2 {
+            shared_key_id,
+            statechain_id: *statechain_id,
+            t2: FESer::from_fe(&t2),
+            o2_pub,
+        };
+        refresh_msg2.encrypt_with_pubkey(&s1_pub)?;
+        let reply: RefreshReply =
+            requests::postb(&self.client_shim, "refresh/second", &refresh_msg2)?;
+
+        // Keygen with the new private share o2
+        let refreshed = ecdsa::get_master_key(
+            &shared_key_id,
+            &self.client_shim,
+            &o2,
+            &value,
+            Protocol::Transfer,
+            String::default(),
+        )?;
+        if refreshed.share.public.q != shared_pk || reply.s2_pub * o2 != shared_pk {
+            return Err(CError::StateEntityError(String::from(
+                "Key share refresh failed. Shared public key changed.",
+            )));
+        }
+
+        {
+            let shared_key = self.get_shared_key_mut(&shared_key_id)?;
+            shared_key.share = refreshed.share;
+            shared_key.share_epoch = reply.share_epoch;
+            shared_key.share_refreshed = Some(chrono::Utc::now().timestamp());
+        }
+        self.save();
+        debug!(
+            "(wallet id: {}) Refreshed key shares of State Chain {}. Share epoch: {}",
+            self.id, statechain_id, reply.share_epoch
+        );
+        Ok(reply.share_epoch)
+    }
+
+    /// Refresh the key shares of unspent statecoins last refreshed more than share_refresh_interval
+    /// seconds ago, or never refreshed. Failures are logged and the statecoin is refreshed on a
+    /// later call. Return ids of refreshed shared keys.
+    pub fn refresh_due_keys(&mut self) -> Vec<Uuid> {
+        let interval = match self.share_refresh_interval {
+            Some(interval) => interval,
+            None => return vec![],
+        };
+        let now = chrono::Utc::now().timestamp();
+        let due: Vec<(Uuid, Uuid)> = self
+            .shared_keys
+            .iter()
+            .filter(|key| key.unspent && key.exit.is_none())
+            .filter(|key| key.share_refreshed.map_or(true, |t| now - t >= interval))
+            .filter_map(|key| key.statechain_id.map(|statechain_id| (key.id, statechain_id)))
+            .collect();
+
+        let mut refreshed = vec![];
+        for (shared_key_id, statechain_id) in due {
+            match self.refresh_key(&statechain_id) {
+                Ok(_) => refreshed.push(shared_key_id),
+                Err(e) => warn!(
+                    "(wallet id: {}) Key share refresh of State Chain {} failed: {}",
+                    self.id, statechain_id, e
+                ),
+            }
+        }
+        refreshed
+    }
+
+    /// Check in for unspent statecoins with a beneficiary designation, restarting their
+    /// inactivity period. A designation the State Entity no longer holds, e.g. after a new
+    /// transfer of the statecoin, is removed. Failures are logged and the statecoin is checked
+    /// in on a later call. Return ids of checked in shared keys.
+    pub fn inheritance_checkins(&mut self) -> Vec<Uuid> {
+        let designated: Vec<(Uuid, Uuid)> = self
+            .shared_keys
+            .iter()
+            .filter(|key| key.unspent && key.inheritance.is_some())
+            .filter_map(|key| key.statechain_id.map(|statechain_id| (key.id, statechain_id)))
+            .collect();
+
+        let mut checked_in = vec![];
+        for (shared_key_id, statechain_id) in designated {
+            match inheritance::checkin(self, &statechain_id) {
+                Ok(_) => checked_in.push(shared_key_id),
+                Err(CError::StateEntityError(e)) if e.contains("No beneficiary designation") => {
+                    if let Ok(key) = self.get_shared_key_mut(&shared_key_id) {
+                        key.inheritance = None;
+                    }
+                }
+                Err(e) => warn!(
+                    "(wallet id: {}) Inheritance check in of State Chain {} failed: {}",
+                    self.id, statechain_id, e
+                ),
+            }
+        }
+        checked_in
+    }
+
+    /// Regenerate shared keys from State Entity recovery data. Private key shares are re-derived from
+    /// the seed using the shared key id (deposit) or funding txid (transfer) and matched against the
+    /// server's record of the client public share. Return ids of shared keys added to the wallet.
+    pub fn recover_shared_keys(&mut self, recovery_data: &Vec<RecoveryDataMsg>) -> Result<Vec<Uuid>> {
+        let mut recovered = vec![];
+        for data in recovery_data {
+            if self.get_shared_key(&data.shared_key_id).is_ok() {
+                continue;
+            }
+            // Public master key data not stored for this shared key
+            if data.shared_key_data.is_empty() || data.shared_key_data == "None" {
+                continue;
+            }
+            let mut public: serde_json::Value = serde_json::from_str(&data.shared_key_data)?;
+            let p2: GE = serde_json::from_value(public["p2"].clone())?;
+
+            let tx_backup = transaction_deserialise(&data.tx_hex)?;
+            let funding_txid = tx_backup
+                .input
+                .get(0)
+                .ok_or(CError::Generic(String::from("Backup tx has no inputs.")))?
+                .previous_output
+                .txid
+                .to_string();
+            let backup_addr = tx_backup
+                .output
+                .get(0)
+                .and_then(|o| Address::from_script(&o.script_pubkey, self.get_bitcoin_network()))
+                .map(|a| a.to_string());
+
+            let g: GE = ECPoint::generator();
+            let mut key_share: Option<FE> = None;
+            for child_id in vec![uuid_to_int(&data.shared_key_id), funding_txid_to_int(&funding_txid)?] {
+                let mut o: FE = ECScalar::zero();
+                self.se_key_shares.get_new_key_encoded_id(child_id, Some(&mut o))?;
+                if g * o == p2 {
+                    key_share = Some(o);
+                    break;
+                }
+            }
+            let mut key_share = key_share.ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+
+            // Funding output key committing to a pay-to-contract commitment: tweak the key share
+            if let Some(p2c) = &data.p2c {
+                let shared_pubkey = PublicKey::from_str(&p2c.shared_pubkey)
+                    .map_err(|e| CError::Generic(format!("Invalid pay-to-contract key: {}", e)))?;
+                let tweak = p2c_tweak_fe(&shared_pubkey, &p2c.commitment)?;
+                key_share = key_share * tweak;
+                public["p2"] = serde_json::to_value(p2 * tweak)?;
+            }
+
+            let master_key: MasterKey2 = serde_json::from_value(json!({
+                "public": public,
+                "private": { "x2": key_share },
+                "chain_code": "0"
+            }))?;
+            let shared_pk = master_key.public.q.get_element();
+            if let Some(p2c) = &data.p2c {
+                verify_p2c(p2c, &PublicKey { compressed: true, key: shared_pk })?;
+            }
+
+            self.shared_keys.push(SharedKey {
+                id: data.shared_key_id,
+                share: master_key,
+                value: data.amount,
+                statechain_id: Some(data.statechain_id),
+                tx_backup_psm: Some(PrepareSignTxMsg {
+                    shared_key_ids: vec![data.shared_key_id],
+                    protocol: Protocol::Transfer,
+                    tx_hex: data.tx_hex.clone(),
+                    input_addrs: vec![shared_pk],
+                    input_amounts: vec![data.amount],
+                    proof_key: Some(data.proof_key.clone()),
+                }),
+                proof_key: Some(data.proof_key.clone()),
+                smt_proof: None,
+                unspent: true,
+                funding_txid,
+                backup_addr,
+                exit: None,
+                fee_quote: None,
+                transfer_receipt: None,
+                watchtower: None,
+                share_epoch: 0,
+                share_refreshed: None,
+                inheritance: None,
+                rotating_proof_key: None,
+                p2c: data.p2c.clone(),
+            });
+            recovered.push(data.shared_key_id);
+        }
+        Ok(recovered)
+    }
+
+    /// Incrementally sync shared keys with the State Entity. Only statechains modified since the stored
+    /// checkpoint are queried: coins whose statechain tip is no longer their proof key, nor the new proof
+    /// key of a rotation in progress, are marked spent and the SMT inclusion proofs of the other modified
+    /// coins are refreshed. On the first sync all
+    /// unspent coins are treated as modified. Inbound transfers in the relay mailbox are added to the
+    /// pending transfers and, if auto_accept_transfers is set, accepted. Refreshed proofs are added to
+    /// the proof cache. The spend alert hook is called for modified coins whose funding output has been
+    /// spent by a tx other than their backup tx, which are then responded to with the wallet's spend
+    /// response (see respond_to_spend()). Key shares due a scheduled refresh are refreshed and
+    /// beneficiary designations are checked in. The new checkpoint is saved with the wallet. Return ids
+    /// of updated, received, refreshed and checked in shared keys.
+    pub fn sync(&mut self) -> Result<Vec<Uuid>> {
+        let (mut since, mut modified) = match &self.sync_checkpoint {
+            Some(checkpoint) => (checkpoint.clone(), HashSet::new()),
+            None => {
+                let root_id = get_smt_root(&self.client_shim)?
+                    .and_then(|root| root.id())
+                    .unwrap_or(0);
+                let modified = self
+                    .shared_keys
+                    .iter()
+                    .filter(|key| key.unspent)
+                    .filter_map(|key| key.statechain_id)
+                    .collect::<HashSet<Uuid>>();
+                (root_id.to_string(), modified)
+            }
+        };
+        loop {
+            let updates = get_statechain_updates(&self.client_shim, &since)?;
+            modified.extend(updates.statechain_ids);
+            since = updates.checkpoint.format("%Y-%m-%dT%H:%M:%S%.f").to_string();
+            if !updates.more {
+                break;
+            }
+        }
+
+        let mut updated = vec![];
+        let mut to_prove = vec![];
+        let mut incidents = vec![];
+        for key in self.shared_keys.iter_mut().filter(|key| key.unspent) {
+            if let (Some(statechain_id), Some(_)) = (key.statechain_id, &key.proof_key) {
+                if !modified.contains(&statechain_id) {
+                    continue;
+                }
+                let statecoin = get_statecoin(&self.client_shim, &statechain_id)?;
+                // Proof key rotated by the State Entity but not recorded in the wallet
+                if key.rotating_proof_key.as_ref() == Some(&statecoin.statecoin.data) {
+                    key.proof_key = key.rotating_proof_key.take();
+                }
+                let proof_key = key.proof_key.as_ref().unwrap();
+                if let Some(incident) = &statecoin.spend_incident {
+                    warn!(
+                        "(wallet id: {}) Funding output {} of statechain {} spent by a tx other than its backup tx",
+                        self.id, incident.funding, statechain_id
+                    );
+                    if let Some(hook) = &self.spend_alert_hook {
+                        hook(incident);
+                    }
+                    if key.exit.is_none() {
+                        incidents.push(incident.clone());
+                    }
+                }
+                if &statecoin.statecoin.data != proof_key {
+                    key.unspent = false;
+                    updated.push(key.id);
+                } else {
+                    to_prove.push(key.funding_txid.clone());
+                }
+            }
+        }
+
+        if !to_prove.is_empty() {
+            let smt_proofs = get_smt_proofs(&self.client_shim, &to_prove)?;
+            self.proof_cache.record_root(&smt_proofs.root)?;
+            let proofs: HashMap<String, Option<Proof>> =
+                smt_proofs.decode_proofs()?.into_iter().collect();
+            for key in self.shared_keys.iter_mut().filter(|key| key.unspent) {
+                if let (Some(proof), Some(proof_key)) = (proofs.get(&key.funding_txid), &key.proof_key) {
+                    if verify_statechain_smt(
+                        &Some(smt_proofs.root.hash()),
+                        &key.funding_txid,
+                        proof_key,
+                        key.value,
+                        proof,
+                    ) {
+                        key.update_proof(&smt_proofs.root, proof);
+                        if let Some(proof) = proof {
+                            self.proof_cache
+                                .insert_proof(&smt_proofs.root, &key.funding_txid, proof)?;
+                        }
+                        updated.push(key.id);
+                    }
+                }
+            }
+        }
+
+        // Outcomes are audit logged by respond_to_spend
+        for incident in &incidents {
+            let _ = self.respond_to_spend(incident);
+        }
+
+        // Receive transfers waiting in the relay mailbox
+        self.fetch_pending_transfers()?;
+        if self.auto_accept_transfers {
+            for finalize_data in self.accept_pending_transfers() {
+                updated.push(finalize_data.new_shared_key_id);
+            }
+        }
+
+        // Scheduled key share refreshes
+        updated.extend(self.refresh_due_keys());
+
+        // Restart the inactivity period of beneficiary designations
+        updated.extend(self.inheritance_checkins());
+
+        self.sync_checkpoint = Some(since);
+        self.save();
+        debug!("(wallet id: {}) Synced {} shared keys", self.id, updated.len());
+        Ok(updated)
+    }
+
     /// Get shared key by id. Return None if no shared key with given id.
     pub fn get_shared_key(&self, id: &Uuid) -> Result<&SharedKey> {
         for shared in &self.shared_keys {
@@ -529,14 +1431,6 @@ impl Wallet {
         Err(CError::WalletError(WalletErrorType::SharedKeyNotFound))
     }
 
-    /// return balance of address
-    fn get_address_balance(&mut self, address: &bitcoin::Address) -> GetBalanceResponse {
-        self.electrumx_client
-            .instance
-            .get_balance(&address.to_string())
-            .unwrap()
-    }
-
     fn zero_balance(&self, addr: &GetBalanceResponse) -> bool {
         if addr.confirmed == 0 {
             if addr.unconfirmed == 0 {
@@ -553,22 +1447,49 @@ impl Wallet {
         addresses
     }
 
+    /// Addresses derived by this wallet that have transaction history. Their script hashes are
+    /// subscribed to with batched requests.
+    fn get_used_wallet_addresses(&mut self) -> Result<Vec<bitcoin::Address>> {
+        let addresses = self.get_all_wallet_addresses();
+        let script_hashes: Vec<String> = addresses
+            .iter()
+            .map(|a| script_hash(&a.script_pubkey()))
+            .collect();
+        let statuses = self
+            .electrumx_client
+            .batch
+            .batch_script_subscribe(&script_hashes)
+            .map_err(|e| CError::Generic(e.to_string()))?;
+        Ok(addresses
+            .into_iter()
+            .zip(statuses)
+            .filter(|(_, status)| status.is_some())
+            .map(|(addr, _)| addr)
+            .collect())
+    }
+
+    /// Return addresses derived by this wallet with a non-zero balance and their balances. Only
+    /// the balances of addresses with history are queried, in batches.
     pub fn get_all_addresses_balance(
         &mut self,
     ) -> Result<(Vec<bitcoin::Address>, Vec<GetBalanceResponse>)> {
-        let all_addrs = self.get_all_wallet_addresses();
-        let all_bals: Vec<GetBalanceResponse> = all_addrs
-            .clone()
-            .into_iter()
-            .map(|a| self.get_address_balance(&a))
+        let used_addrs = self.get_used_wallet_addresses()?;
+        let script_hashes: Vec<String> = used_addrs
+            .iter()
+            .map(|a| script_hash(&a.script_pubkey()))
             .collect();
+        let all_bals = self
+            .electrumx_client
+            .batch
+            .batch_script_get_balance(&script_hashes)
+            .map_err(|e| CError::Generic(e.to_string()))?;
 
         // return non-0 balances
         let mut addrs: Vec<bitcoin::Address> = vec![];
         let mut bals: Vec<GetBalanceResponse> = vec![];
-        for (i, balance) in all_bals.into_iter().enumerate() {
+        for (addr, balance) in used_addrs.into_iter().zip(all_bals) {
             if !self.zero_balance(&balance) {
-                addrs.push(all_addrs.get(i).unwrap().clone());
+                addrs.push(addr);
                 bals.push(balance);
             }
         }
@@ -601,7 +1522,7 @@ impl Wallet {
                     unconfirmed: 0,
                 });
                 let tx = transaction_deserialise(&shared_key.tx_backup_psm.as_ref().unwrap().tx_hex)?;
-                state_chain_locktimes.push(tx.lock_time);
+                state_chain_locktimes.push(backup_tx_timelock(&tx));
                 shared_key_ids.push(shared_key.id.to_owned());
                 if shared_key.statechain_id.is_some() {
                     statechain_ids.push(shared_key.statechain_id.clone().unwrap());
@@ -622,11 +1543,463 @@ impl Wallet {
         Ok(backup_tx_hex)
     }
 
-    /// List unspent outputs for addresses derived by this wallet.
+    /// Build and sign a CPFP child tx spending the backup tx output of the statecoin with given ID.
+    /// The child pays to a new wallet address with fee such that backup tx and child together pay
+    /// 'fee_rate' (sat/vbyte).
+    pub fn backup_tx_cpfp(&mut self, statechain_id: &Uuid, fee_rate: &u64) -> Result<bitcoin::Transaction> {
+        let (tx_backup, value) = {
+            let shared_key = self.get_shared_key_by_statechain_id(statechain_id)?;
+            let tx_backup_psm = shared_key.tx_backup_psm.as_ref().ok_or(CError::Generic(
+                format!("No backup tx for statechain {}", statechain_id),
+            ))?;
+            (transaction_deserialise(&tx_backup_psm.tx_hex)?, shared_key.value)
+        };
+        let parent_fee = value.saturating_sub(tx_backup.output.iter().map(|o| o.value).sum());
+
+        // Find backup tx output paying to this wallet's backup keys
+        let network = self.get_bitcoin_network();
+        let (vout, key_derivation) = tx_backup
+            .output
+            .iter()
+            .enumerate()
+            .find_map(|(i, output)| {
+                Address::from_script(&output.script_pubkey, network)
+                    .and_then(|addr| self.se_backup_keys.get_address_derivation(&addr.to_string()))
+                    .map(|derivation| (i, derivation))
+            })
+            .ok_or(CError::WalletError(WalletErrorType::KeyNotFound))?;
+        let amount = tx_backup.output[vout].value;
+
+        let rec_address = self.keys.get_new_address()?;
+        let tx_cpfp = tx_cpfp_build(&tx_backup, &(vout as u32), &parent_fee, fee_rate, &rec_address)?;
+
+        // Sign child input with backup key
+        let pk = key_derivation.public_key.unwrap().key;
+        let sig_hash = get_sighash(&tx_cpfp, &0, &pk, &amount, &self.network);
+        let msg = Message::from_slice(&sig_hash)?;
+        let mut signature = self
+            .secp
+            .sign(&msg, &key_derivation.private_key.key)
+            .serialize_der()
+            .to_vec();
+        signature.push(1);
+
+        let mut tx_cpfp_signed = tx_cpfp.clone();
+        tx_cpfp_signed.input[0].witness = vec![signature, pk.serialize().to_vec()];
+        Ok(tx_cpfp_signed)
+    }
+
+    /// Emergency unilateral exit, for use when the State Entity is unresponsive or malicious.
+    /// Every owned statecoin is marked as exiting with its signed backup tx, and the wallet is
+    /// saved before anything is broadcast. Backup txs whose locktime has expired are then
+    /// broadcast. Exiting coins whose backup tx is not yet broadcast are retried, so the exit can
+    /// be repeated until every backup tx is broadcast. Returns the exits by shared key ID.
+    pub fn exit_all(&mut self) -> Result<Vec<(Uuid, BackupExit)>> {
+        for shared_key in self.shared_keys.iter_mut().filter(|k| k.unspent && k.exit.is_none()) {
+            let tx_hex = match &shared_key.tx_backup_psm {
+                Some(tx_backup_psm) => tx_backup_psm.tx_hex.clone(),
+                None => {
+                    warn!("Exit: no backup tx for shared key {}", shared_key.id);
+                    continue;
+                }
+            };
+            let tx = transaction_deserialise(&tx_hex)?;
+            if tx.input.get(0).map_or(true, |txin| txin.witness.is_empty()) {
+                warn!("Exit: backup tx for shared key {} is not signed", shared_key.id);
+                continue;
+            }
+            shared_key.exit = Some(BackupExit {
+                txid: tx.txid().to_string(),
+                tx_hex,
+                locktime: backup_tx_timelock(&tx),
+                broadcast: false,
+            });
+        }
+        self.save();
+
+        let height = self.electrumx_client.instance.get_tip_header()?.height as u32;
+        let mut exits = vec![];
+        for i in 0..self.shared_keys.len() {
+            let mut exit = match &self.shared_keys[i].exit {
+                Some(exit) => exit.clone(),
+                None => continue,
+            };
+            if !exit.broadcast {
+                let tx = transaction_deserialise(&exit.tx_hex)?;
+                if self.backup_tx_locktime_expired(&tx, height)? {
+                    match self.electrumx_client.instance.broadcast_transaction(exit.tx_hex.clone()) {
+                        Ok(_) => exit.broadcast = true,
+                        Err(e) => warn!("Exit: failed to broadcast backup tx {}: {}", exit.txid, e),
+                    }
+                }
+                self.shared_keys[i].exit = Some(exit.clone());
+            }
+            exits.push((self.shared_keys[i].id, exit));
+        }
+        self.save();
+        Ok(exits)
+    }
+
+    /// Respond to a spend of the funding output of an owned statecoin by a tx other than its
+    /// current backup tx with the wallet's spend response strategy. The current backup tx has an
+    /// earlier locktime than any outdated backup tx, so broadcasting it with a CPFP child as soon as
+    /// its locktime expires, or withdrawing the coin, confirms before the competing spend. If the
+    /// State Entity has already broadcast the backup tx in response, only the CPFP child is added.
+    /// Coins already exiting are left alone. Every outcome is audit logged. Returns the txid of the
+    /// response tx broadcast, if any.
+    pub fn respond_to_spend(&mut self, incident: &SpendIncident) -> Result<Option<String>> {
+        let statechain_id = incident.statechain_id;
+        let (shared_key_id, tx_hex) = match self
+            .shared_keys
+            .iter()
+            .find(|k| k.unspent && k.exit.is_none() && k.statechain_id == Some(statechain_id))
+        {
+            Some(key) => (key.id, key.tx_backup_psm.as_ref().map(|psm| psm.tx_hex.clone())),
+            None => return Ok(None),
+        };
+
+        let res = match self.spend_response {
+            SpendResponse::None => Ok(None),
+            SpendResponse::Backup { fee_rate } => {
+                self.respond_with_backup(incident, &shared_key_id, tx_hex, &fee_rate)
+            }
+            SpendResponse::Withdraw { fee_rate } => self.keys.get_new_address().and_then(|address| {
+                batch_withdraw_to_address(self, &vec![statechain_id], &withdraw_tx_fee(1, &fee_rate), &address)
+                    .map(|(txid, _)| Some(txid))
+            }),
+        };
+        match &res {
+            Ok(Some(txid)) => info!(
+                target: "audit",
+                "(wallet id: {}) Spend response {:?} to competing spend of statechain {} funding output {}: broadcast {}",
+                self.id, self.spend_response, statechain_id, incident.funding, txid
+            ),
+            Ok(None) => info!(
+                target: "audit",
+                "(wallet id: {}) Spend response {:?} to competing spend of statechain {} funding output {}: nothing broadcast",
+                self.id, self.spend_response, statechain_id, incident.funding
+            ),
+            Err(e) => warn!(
+                target: "audit",
+                "(wallet id: {}) Spend response {:?} to competing spend of statechain {} funding output {} failed: {}",
+                self.id, self.spend_response, statechain_id, incident.funding, e
+            ),
+        }
+        res
+    }
+
+    /// Broadcast the backup tx of a shared key, unless already broadcast by the State Entity, and a
+    /// CPFP child at fee_rate, and mark the coin as exiting. Nothing is broadcast before the backup
+    /// tx locktime expires.
+    fn respond_with_backup(
+        &mut self,
+        incident: &SpendIncident,
+        shared_key_id: &Uuid,
+        tx_hex: Option<String>,
+        fee_rate: &u64,
+    ) -> Result<Option<String>> {
+        let tx_hex = tx_hex.ok_or(CError::Generic(format!(
+            "No backup tx for statechain {}",
+            incident.statechain_id
+        )))?;
+        let tx = transaction_deserialise(&tx_hex)?;
+        let txid = tx.txid().to_string();
+        let height = self.electrumx_client.instance.get_tip_header()?.height as u32;
+        if !self.backup_tx_locktime_expired(&tx, height)? {
+            return Ok(None);
+        }
+        if incident.response_txid.as_ref() != Some(&txid) {
+            self.electrumx_client
+                .instance
+                .broadcast_transaction(tx_hex.clone())?;
+        }
+        let exit = BackupExit {
+            txid: txid.clone(),
+            tx_hex,
+            locktime: backup_tx_timelock(&tx),
+            broadcast: true,
+        };
+        self.get_shared_key_mut(shared_key_id)?.exit = Some(exit);
+        self.save();
+
+        let tx_cpfp = self.backup_tx_cpfp(&incident.statechain_id, fee_rate)?;
+        if let Err(e) = self
+            .electrumx_client
+            .instance
+            .broadcast_transaction(transaction_serialise(&tx_cpfp))
+        {
+            warn!("Failed to broadcast CPFP child of backup tx {}: {}", txid, e);
+        }
+        Ok(Some(txid))
+    }
+
+    /// Save the progress of a pending deposit to disk
+    pub fn save_pending_deposit(&mut self, pending: &PendingDeposit) {
+        match self
+            .pending_deposits
+            .iter_mut()
+            .find(|d| d.shared_key_id == pending.shared_key_id)
+        {
+            Some(d) => *d = pending.clone(),
+            None => self.pending_deposits.push(pending.clone()),
+        }
+        self.save();
+    }
+
+    /// Resume deposits interrupted before being confirmed by the state entity, e.g. by a wallet
+    /// restart. Each deposit continues from the last step saved. Deposits made on behalf of
+    /// another proof key are handed off once confirmed. Deposits which fail remain pending and
+    /// can be resumed again. Returns the shared key ID and statechain ID of resumed deposits.
+    pub fn resume_pending_deposits(&mut self) -> Result<Vec<(Uuid, Uuid)>> {
+        let mut resumed = vec![];
+        for pending in self.pending_deposits.clone() {
+            let shared_key_id = pending.shared_key_id;
+            let handoff = pending.payer_proof_key.is_some();
+            match deposit_resume(self, pending, &mut log_funding_progress) {
+                Ok((_, statechain_id, _, _, _, _)) => {
+                    self.save();
+                    if handoff {
+                        deposit_handoff(self, &shared_key_id)?;
+                    }
+                    resumed.push((shared_key_id, statechain_id));
+                }
+                Err(e) => warn!("Failed to resume deposit {}: {}", shared_key_id, e),
+            }
+        }
+        self.save();
+        Ok(resumed)
+    }
+
+    /// Add an inbound transfer to one of the wallet's proof keys to the pending transfers. Returns
+    /// false if the transfer is already pending, has been rejected or has already been received.
+    pub fn add_pending_transfer(&mut self, transfer_msg3: TransferMsg3) -> Result<bool> {
+        let proof_key = transfer_msg3.rec_se_addr.proof_key.to_string();
+        if self
+            .se_proof_keys
+            .get_key_derivation(
+                &PublicKey::from_str(&proof_key).map_err(|e| CError::Generic(e.to_string()))?,
+            )
+            .is_none()
+        {
+            return Err(CError::WalletError(WalletErrorType::KeyNotFound));
+        }
+        if self
+            .pending_transfers
+            .iter()
+            .any(|p| p.transfer_msg3.statechain_sig == transfer_msg3.statechain_sig)
+        {
+            return Ok(false);
+        }
+        if self.shared_keys.iter().any(|key| {
+            key.statechain_id == Some(transfer_msg3.statechain_id)
+                && key.proof_key.as_ref() == Some(&proof_key)
+        }) {
+            return Ok(false);
+        }
+        self.pending_transfers.push(PendingTransfer {
+            transfer_msg3,
+            rejected: false,
+        });
+        self.save();
+        Ok(true)
+    }
+
+    /// Inbound transfers waiting to be accepted or rejected
+    pub fn list_pending_transfers(&self) -> Vec<TransferMsg3> {
+        self.pending_transfers
+            .iter()
+            .filter(|p| !p.rejected)
+            .map(|p| p.transfer_msg3.clone())
+            .collect()
+    }
+
+    fn get_pending_transfer_pos(&self, statechain_id: &Uuid) -> Result<usize> {
+        self.pending_transfers
+            .iter()
+            .position(|p| !p.rejected && p.transfer_msg3.statechain_id == *statechain_id)
+            .ok_or(CError::WalletError(WalletErrorType::PendingTransferNotFound))
+    }
+
+    /// Accept a pending inbound transfer of a statechain by completing the receiver side of the
+    /// transfer protocol. The transfer remains pending if this fails so that it can be retried.
+    pub fn accept_pending_transfer(&mut self, statechain_id: &Uuid) -> Result<TransferFinalizeData> {
+        let pos = self.get_pending_transfer_pos(statechain_id)?;
+        let mut transfer_msg3 = self.pending_transfers[pos].transfer_msg3.clone();
+        let finalize_data = transfer_receiver(self, &mut transfer_msg3, &None)?;
+        self.pending_transfers.remove(pos);
+        self.save();
+        Ok(finalize_data)
+    }
+
+    /// Reject a pending inbound transfer of a statechain. The State Entity returns the statecoin
+    /// to the sender. The transfer is not fetched again on sync.
+    pub fn reject_pending_transfer(&mut self, statechain_id: &Uuid) -> Result<()> {
+        let pos = self.get_pending_transfer_pos(statechain_id)?;
+        transfer_reject(self, &self.pending_transfers[pos].transfer_msg3)?;
+        self.pending_transfers[pos].rejected = true;
+        if let Some(enc_key) = self.pending_transfers[pos].transfer_msg3.rec_se_addr.enc_key {
+            self.delete_receive_key(&PublicKey { compressed: true, key: enc_key });
+        }
+        self.save();
+        Ok(())
+    }
+
+    /// Fetch inbound transfers to the wallet's proof keys from the State Entity relay mailbox and
+    /// add them to the pending transfers. Returns the statechain IDs of transfers added.
+    pub fn fetch_pending_transfers(&mut self) -> Result<Vec<Uuid>> {
+        let proof_keys: Vec<String> = self
+            .se_proof_keys
+            .key_derivation_map
+            .keys()
+            .map(|pk| pk.to_string())
+            .collect();
+        let mut added = vec![];
+        for proof_key in proof_keys {
+            for transfer_msg3 in transfer_get_msg_addr(self, &proof_key)? {
+                let statechain_id = transfer_msg3.statechain_id;
+                if self.add_pending_transfer(transfer_msg3)? {
+                    added.push(statechain_id);
+                }
+            }
+        }
+        Ok(added)
+    }
+
+    /// Accept all pending inbound transfers. Transfers which fail remain pending and can be
+    /// accepted again or rejected. Returns the finalize data of accepted transfers.
+    pub fn accept_pending_transfers(&mut self) -> Vec<TransferFinalizeData> {
+        let mut accepted = vec![];
+        for transfer_msg3 in self.list_pending_transfers() {
+            let statechain_id = transfer_msg3.statechain_id;
+            match self.accept_pending_transfer(&statechain_id) {
+                Ok(finalize_data) => accepted.push(finalize_data),
+                Err(e) => warn!("Failed to accept transfer of statechain {}: {}", statechain_id, e),
+            }
+        }
+        accepted
+    }
+
+    /// Transfer many statecoins, each to its own receiver address, running up to
+    /// transfer_concurrency sender flows at once. Returns the outcome of each transfer: a
+    /// failed transfer does not stop the others. See transfer::transfer_sender_many.
+    pub fn transfer_many(&mut self, pairs: &[(Uuid, SCEAddress)]) -> Result<TransferManyReport> {
+        let max_concurrency = self.transfer_concurrency;
+        transfer_sender_many(self, pairs, max_concurrency)
+    }
+
+    /// Withdraw every unspent statecoin to destination, in as few txs as the withdraw batch
+    /// limit allows, at fee_rate (sat/vbyte). Returns the withdraw txs and the statecoins that
+    /// could not be withdrawn. See withdraw::withdraw_all.
+    pub fn withdraw_all(&mut self, destination: &Address, fee_rate: &u64) -> Result<WithdrawAllReport> {
+        withdraw_all(self, destination, fee_rate)
+    }
+
+    /// Record a completed activity of the statecoin of shared_key_id, valued at the statecoin
+    /// amount. Recorded activity is exported by export_history().
+    pub fn record_activity(&mut self, activity: Activity, shared_key_id: &Uuid, txid: Option<String>) -> Result<()> {
+        let shared_key = self.get_shared_key(shared_key_id)?;
+        let event = ActivityEvent::new(activity, shared_key.value, *shared_key_id, shared_key.statechain_id, txid);
+        self.activity.push(event);
+        Ok(())
+    }
+
+    /// Store the conductor report of a completed swap. A report of a swap already stored is
+    /// ignored.
+    pub fn add_swap_report(&mut self, report: SignedSwapReport) {
+        if self
+            .swap_reports
+            .iter()
+            .all(|r| r.report.swap_id != report.report.swap_id)
+        {
+            self.swap_reports.push(report);
+        }
+    }
+
+    /// Export every deposit, transfer, swap and withdrawal of the wallet in time order, with
+    /// the fiat value of each at the time it occurred if price_source is supplied
+    pub fn export_history(&self, format: HistoryFormat, price_source: Option<&dyn PriceSource>) -> Result<String> {
+        export_history(&self.activity, format, price_source)
+    }
+
+    /// Backup tx locktime countdown of each owned statecoin at the current chain height. Coins
+    /// with at most expiry_alert_blocks blocks remaining are marked as alerts and passed to the
+    /// expiry alert hook, so that the owner can transfer or withdraw them before having to
+    /// broadcast the backup tx. Coins already exiting are not included.
+    pub fn get_expiry_report(&mut self) -> Result<Vec<CoinExpiry>> {
+        let height = self.electrumx_client.instance.get_tip_header()?.height as u32;
+        let mut report = vec![];
+        for i in 0..self.shared_keys.len() {
+            let shared_key = &self.shared_keys[i];
+            if !shared_key.unspent || shared_key.exit.is_some() {
+                continue;
+            }
+            let tx = match &shared_key.tx_backup_psm {
+                Some(tx_backup_psm) => transaction_deserialise(&tx_backup_psm.tx_hex)?,
+                None => continue,
+            };
+            let (shared_key_id, statechain_id) = (shared_key.id, shared_key.statechain_id);
+
+            let blocks_remaining = self.backup_tx_blocks_remaining(&tx, height)?;
+            let expiry = CoinExpiry {
+                shared_key_id,
+                statechain_id,
+                locktime: backup_tx_timelock(&tx),
+                height,
+                blocks_remaining,
+                alert: blocks_remaining <= self.expiry_alert_blocks,
+            };
+            if expiry.alert {
+                if let Some(hook) = &self.expiry_alert_hook {
+                    hook(&expiry);
+                }
+            }
+            report.push(expiry);
+        }
+        Ok(report)
+    }
+
+    /// Blocks remaining at block height 'height' until the backup tx locktime expires. Relative
+    /// locktimes count from the funding tx confirmation.
+    pub fn backup_tx_blocks_remaining(&mut self, tx: &bitcoin::Transaction, height: u32) -> Result<u32> {
+        let locktime = backup_tx_timelock(tx);
+        Ok(match backup_tx_timelock_mode(tx) {
+            TimelockMode::Absolute => locktime.saturating_sub(height),
+            TimelockMode::Relative => {
+                let funding_txid = tx.input[0].previous_output.txid.to_string();
+                let confirmations = self
+                    .electrumx_client
+                    .instance
+                    .get_transaction_conf_status(funding_txid, false)?
+                    .confirmations
+                    .unwrap_or(0);
+                locktime.saturating_sub(confirmations)
+            }
+        })
+    }
+
+    /// True if the backup tx locktime has expired at block height 'height', so that the tx can be
+    /// included in the next block
+    fn backup_tx_locktime_expired(&mut self, tx: &bitcoin::Transaction, height: u32) -> Result<bool> {
+        Ok(match backup_tx_timelock_mode(tx) {
+            TimelockMode::Absolute => tx.lock_time <= height,
+            TimelockMode::Relative => {
+                let funding_txid = tx.input[0].previous_output.txid.to_string();
+                let confirmations = self
+                    .electrumx_client
+                    .instance
+                    .get_transaction_conf_status(funding_txid, false)?
+                    .confirmations
+                    .unwrap_or(0);
+                backup_tx_timelock(tx) <= confirmations
+            }
+        })
+    }
+
+    /// List unspent outputs for addresses derived by this wallet that have transaction history.
     pub fn list_unspent(
         &mut self,
     ) -> Result<(Vec<bitcoin::Address>, Vec<Vec<GetListUnspentResponse>>)> {
-        let addresses = self.get_all_wallet_addresses();
+        let addresses = self.get_used_wallet_addresses()?;
         let mut unspent_list: Vec<Vec<GetListUnspentResponse>> = vec![];
         for addr in &addresses {
             let addr_unspent_list = self.list_unspent_for_address(addr.to_string())?;
@@ -680,6 +2053,8 @@ pub fn to_bitcoin_public_key(pk: curv::PK) -> bitcoin::util::key::PublicKey {
 mod tests {
     use super::*;
     extern crate shared_lib;
+    use shared_lib::mocks::mock_electrum::TxScenario;
+    use shared_lib::swap_data::SwapReport;
 
     fn gen_wallet(conductor_port: Option<u16>) -> Wallet {
         gen_wallet_with_seed(&[0xcd; 32], conductor_port)
@@ -712,6 +2087,58 @@ mod tests {
         assert_eq!(wallet.to_json(), wallet_loaded.to_json());
     }
 
+    /// Wallet files saved in each format version
+    const WALLET_FIXTURES: &[(u64, &str)] = &[
+        (0, include_str!("fixtures/wallet_v0.json")),
+        (1, include_str!("fixtures/wallet_v1.json")),
+    ];
+
+    #[test]
+    #[serial]
+    fn test_wallet_load_fixtures() {
+        assert_eq!(WALLET_FIXTURES.len() as u64, WALLET_VERSION + 1);
+        let shim = || ClientShim::new("http://localhost:8000".to_string(), None, None);
+        for (version, fixture) in WALLET_FIXTURES {
+            let backup_loc = format!("{}.v{}.bak", DEFAULT_TEST_WALLET_LOC, version);
+            let _ = fs::remove_file(&backup_loc);
+            fs::write(DEFAULT_TEST_WALLET_LOC, fixture).unwrap();
+
+            let wallet = Wallet::load(DEFAULT_TEST_WALLET_LOC, shim(), shim()).unwrap();
+            let fixture_json: serde_json::Value = serde_json::from_str(fixture).unwrap();
+            assert_eq!(wallet.id, fixture_json["id"].as_str().unwrap());
+            assert_eq!(wallet.keys.last_derived_pos, 2);
+            assert_eq!(wallet.se_backup_keys.last_derived_pos, 1);
+            assert_eq!(wallet.se_proof_keys.last_derived_pos, 1);
+            match version {
+                0 => {
+                    assert_eq!(wallet.derivation_path, legacy_path());
+                    assert!(wallet.auto_accept_transfers);
+                    assert!(!wallet.require_deletion_receipts);
+                }
+                _ => {
+                    assert_eq!(wallet.derivation_path.to_string(), "m/1298494051'/1'/0'");
+                    assert!(!wallet.auto_accept_transfers);
+                    assert!(wallet.require_deletion_receipts);
+                    assert_eq!(wallet.sync_checkpoint, Some(String::from("42")));
+                }
+            }
+
+            // The file is upgraded, with a copy of earlier versions
+            let saved: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(DEFAULT_TEST_WALLET_LOC).unwrap()).unwrap();
+            assert_eq!(wallet_version(&saved).unwrap(), WALLET_VERSION);
+            if *version < WALLET_VERSION {
+                assert_eq!(fs::read_to_string(&backup_loc).unwrap(), *fixture);
+                fs::remove_file(&backup_loc).unwrap();
+            } else {
+                assert!(fs::metadata(&backup_loc).is_err());
+            }
+
+            let reloaded = Wallet::load(DEFAULT_TEST_WALLET_LOC, shim(), shim()).unwrap();
+            assert_eq!(wallet.to_json(), reloaded.to_json());
+        }
+    }
+
     #[test]
     #[serial]
     fn test_wallet_save_overwrite() {
@@ -739,6 +2166,40 @@ mod tests {
         assert!(wallet1.keys.last_derived_pos != loaded_wall2.keys.last_derived_pos);
     }
 
+    #[test]
+    fn test_external_backup_addr() {
+        let mut wallet = gen_wallet(None);
+        let cold_addr = wallet.keys.get_new_address().unwrap();
+
+        let sce_addr = wallet
+            .get_new_state_entity_address_with_backup(Some(cold_addr.clone()))
+            .unwrap();
+        assert_eq!(sce_addr.tx_backup_addr, Some(cold_addr.clone()));
+        assert!(wallet.is_own_backup_addr(&cold_addr, &sce_addr.proof_key.to_string()));
+
+        // Address only accepted for the proof key it was supplied with
+        let sce_addr2 = wallet.get_new_state_entity_address().unwrap();
+        assert!(!wallet.is_own_backup_addr(&cold_addr, &sce_addr2.proof_key.to_string()));
+        assert!(wallet.is_own_backup_addr(
+            &sce_addr2.tx_backup_addr.unwrap(),
+            &sce_addr2.proof_key.to_string()
+        ));
+
+        // Address for wrong network rejected
+        let mainnet_addr = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap();
+        assert!(wallet
+            .get_new_state_entity_address_with_backup(Some(mainnet_addr))
+            .is_err());
+
+        // Persisted
+        let wallet_rebuilt = super::Wallet::from_json(
+            wallet.to_json(),
+            ClientShim::new("http://localhost:8000".to_string(),None,None), ClientShim::new("http://localhost:8000".to_string(), None, None),
+        )
+        .unwrap();
+        assert!(wallet_rebuilt.is_own_backup_addr(&cold_addr, &sce_addr.proof_key.to_string()));
+    }
+
     #[test]
     fn test_to_and_from_json() {
         let mut wallet = gen_wallet(None);
@@ -754,6 +2215,27 @@ mod tests {
             .get_new_key_encoded_id(9999999, None)
             .unwrap();
         let key_shares2 = wallet.se_key_shares.get_new_key().unwrap();
+        wallet.activity.push(ActivityEvent::new(Activity::Deposit, 1000, Uuid::new_v4(), None, None));
+        let swap_report = SignedSwapReport {
+            report: SwapReport {
+                swap_id: Uuid::new_v4(),
+                participants: 5,
+                denomination: 100000,
+                completed_at: chrono::Utc::now().naive_utc(),
+            },
+            server_pubkey: None,
+            sig: None,
+        };
+        wallet.add_swap_report(swap_report.clone());
+        wallet.add_swap_report(swap_report);
+        assert_eq!(wallet.swap_reports.len(), 1);
+        wallet.issue_receive_key(&proof_key1);
+        let mut root = Root::from_random();
+        root.set_id(&1);
+        wallet
+            .proof_cache
+            .insert_proof(&root, &String::from("funding_txid"), &vec![(true, vec![1; 33])])
+            .unwrap();
 
         let wallet_json = wallet.to_json();
 
@@ -886,6 +2368,370 @@ mod tests {
                 .pos,
             1
         );
+        assert_eq!(wallet.activity, wallet_rebuilt.activity);
+        assert_eq!(wallet.swap_reports, wallet_rebuilt.swap_reports);
+        assert_eq!(wallet.receive_keys, wallet_rebuilt.receive_keys);
+        assert_eq!(wallet.proof_cache, wallet_rebuilt.proof_cache);
+        assert_eq!(wallet_rebuilt.proof_cache.proof_count(), 1);
+    }
+
+    #[test]
+    fn test_derivation_export_import() {
+        use crate::wallet::key_paths::STATECHAIN_PURPOSE;
+
+        let mut wallet = gen_wallet(None);
+        let backup_addr = wallet.se_backup_keys.get_new_address().unwrap();
+        let proof_key = wallet.se_proof_keys.get_new_key().unwrap();
+        let encoded_key_share = wallet
+            .se_key_shares
+            .get_new_key_encoded_id(9999999, None)
+            .unwrap();
+
+        let export = wallet.export_derivation();
+        assert_eq!(export.root_path, "m");
+        let export: DerivationExport =
+            serde_json::from_str(&serde_json::to_string(&export).unwrap()).unwrap();
+
+        let imported = Wallet::import_derivation(
+            &export,
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        )
+        .unwrap();
+        let sorted_addrs = |w: &Wallet| {
+            let mut addrs: Vec<String> =
+                w.get_all_wallet_addresses().iter().map(|a| a.to_string()).collect();
+            addrs.sort();
+            addrs
+        };
+        assert_eq!(sorted_addrs(&imported), sorted_addrs(&wallet));
+        assert!(imported.se_backup_keys.get_address_derivation(&backup_addr.to_string()).is_some());
+        assert!(imported.se_proof_keys.get_key_derivation(&proof_key).is_some());
+        assert_eq!(
+            imported.se_key_shares.get_key_derivation(&encoded_key_share).unwrap().pos,
+            9999999
+        );
+
+        // Accounts of the derivation scheme have distinct keys, kept on save and load
+        let mut account = Wallet::new_account(
+            &[0xcd; 32],
+            &"regtest".to_string(),
+            1,
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        )
+        .unwrap();
+        let account_proof_key = account.se_proof_keys.get_new_key().unwrap();
+        assert!(account_proof_key != proof_key);
+        assert_eq!(
+            account.export_derivation().root_path,
+            format!("m/{}'/1'/1'", STATECHAIN_PURPOSE)
+        );
+        let account_rebuilt = Wallet::from_json(
+            account.to_json(),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        )
+        .unwrap();
+        assert!(account_rebuilt.se_proof_keys.get_key_derivation(&account_proof_key).is_some());
+
+        // Unsupported versions are rejected
+        let mut export = account.export_derivation();
+        export.version = DERIVATION_EXPORT_VERSION + 1;
+        assert!(Wallet::import_derivation(
+            &export,
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_shared_key_share_derivation_deterministic() {
+        let id = Uuid::new_v4();
+        let mut wallet1 = gen_wallet(None);
+        let mut wallet2 = gen_wallet(None);
+        let _ = wallet1.se_key_shares.get_new_key();
+
+        let mut o1: FE = ECScalar::zero();
+        let mut o2: FE = ECScalar::zero();
+        let pk1 = wallet1.se_key_shares.get_new_key_encoded_id(uuid_to_int(&id), Some(&mut o1)).unwrap();
+        let pk2 = wallet2.se_key_shares.get_new_key_encoded_id(uuid_to_int(&id), Some(&mut o2)).unwrap();
+        assert_eq!(pk1, pk2);
+        assert_eq!(o1, o2);
+    }
+
+    #[test]
+    fn test_recover_shared_keys_skips_missing_data() {
+        let mut wallet = gen_wallet(None);
+        let mut recovery_data = RecoveryDataMsg::example();
+        recovery_data.shared_key_data = "None".to_string();
+        assert_eq!(wallet.recover_shared_keys(&vec![recovery_data]).unwrap().len(), 0);
+        assert_eq!(wallet.shared_keys.len(), 0);
+    }
+
+    #[test]
+    fn test_backup_tx_locktime_expired() {
+        // Mock Electrum tip height 12345, funding confirmations 3
+        let mut wallet = gen_wallet(None);
+        let mut tx = transaction_deserialise(&String::from("020000000001014e3e3b35c39ac305aaa3dc364c7378fceaf3cd124101e4f234672a51e74c17d10000000000ffffffff011fae01000000000016001451e57b299625a0c3755f18050c684a6adfdc54c102483045022100de6849daa364f55bdbff15a24250dad308110fbf5c32e02259349ca23c41e1e702201efcee6590fac368585172a9ac31281055e3590e9478ba954500e49cd51be012012102992a0ce40f87d9bf333dbbf60b726b5023fc10c2838179b66c577cb843bf2355a5080000")).unwrap();
+        assert!(wallet.backup_tx_locktime_expired(&tx, 12345).unwrap());
+        tx.lock_time = 12345;
+        assert!(wallet.backup_tx_locktime_expired(&tx, 12345).unwrap());
+        tx.lock_time = 12346;
+        assert!(!wallet.backup_tx_locktime_expired(&tx, 12345).unwrap());
+
+        // Relative timelock
+        tx.lock_time = 0;
+        tx.input[0].sequence = 3;
+        assert_eq!(backup_tx_timelock_mode(&tx), TimelockMode::Relative);
+        assert!(wallet.backup_tx_locktime_expired(&tx, 12345).unwrap());
+        tx.input[0].sequence = 4;
+        assert!(!wallet.backup_tx_locktime_expired(&tx, 12345).unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn test_backup_tx_blocks_remaining() {
+        // Mock Electrum funding confirmations 3
+        let mut wallet = gen_wallet(None);
+        let mut tx = transaction_deserialise(&String::from("020000000001014e3e3b35c39ac305aaa3dc364c7378fceaf3cd124101e4f234672a51e74c17d10000000000ffffffff011fae01000000000016001451e57b299625a0c3755f18050c684a6adfdc54c102483045022100de6849daa364f55bdbff15a24250dad308110fbf5c32e02259349ca23c41e1e702201efcee6590fac368585172a9ac31281055e3590e9478ba954500e49cd51be012012102992a0ce40f87d9bf333dbbf60b726b5023fc10c2838179b66c577cb843bf2355a5080000")).unwrap();
+        tx.lock_time = 12445;
+        assert_eq!(wallet.backup_tx_blocks_remaining(&tx, 12345).unwrap(), 100);
+        assert_eq!(wallet.backup_tx_blocks_remaining(&tx, 12500).unwrap(), 0);
+
+        // Relative timelock
+        tx.lock_time = 0;
+        tx.input[0].sequence = 10;
+        assert_eq!(wallet.backup_tx_blocks_remaining(&tx, 12345).unwrap(), 7);
+    }
+
+    #[test]
+    #[serial]
+    fn test_backup_tx_locktime_funding_reorg() {
+        let mut wallet = gen_wallet(None);
+        let mut tx = transaction_deserialise(&String::from("020000000001014e3e3b35c39ac305aaa3dc364c7378fceaf3cd124101e4f234672a51e74c17d10000000000ffffffff011fae01000000000016001451e57b299625a0c3755f18050c684a6adfdc54c102483045022100de6849daa364f55bdbff15a24250dad308110fbf5c32e02259349ca23c41e1e702201efcee6590fac368585172a9ac31281055e3590e9478ba954500e49cd51be012012102992a0ce40f87d9bf333dbbf60b726b5023fc10c2838179b66c577cb843bf2355a5080000")).unwrap();
+        tx.lock_time = 0;
+        tx.input[0].sequence = 10;
+        let funding_txid = tx.input[0].previous_output.txid.to_string();
+
+        // Relative timelock expires once the funding tx has 10 confirmations. A reorg of the
+        // funding tx restarts the countdown, so the backup tx cannot yet be broadcast.
+        MockElectrum::set_scenario(&funding_txid, TxScenario::Reorged { confirmations: 10, after: 1 });
+        assert!(wallet.backup_tx_locktime_expired(&tx, 12345).unwrap());
+        assert!(!wallet.backup_tx_locktime_expired(&tx, 12345).unwrap());
+        assert_eq!(wallet.backup_tx_blocks_remaining(&tx, 12345).unwrap(), 10);
+
+        MockElectrum::set_scenario(&funding_txid, TxScenario::StuckUnconfirmed);
+        assert_eq!(wallet.backup_tx_blocks_remaining(&tx, 12345).unwrap(), 10);
+
+        // The funding tx is unknown: the countdown cannot be computed
+        MockElectrum::set_scenario(&funding_txid, TxScenario::NeverBroadcast);
+        assert!(wallet.backup_tx_blocks_remaining(&tx, 12345).is_err());
+        MockElectrum::clear_scenarios();
+    }
+
+    #[test]
+    fn test_expiry_report_no_coins() {
+        let mut wallet = gen_wallet(None);
+        assert_eq!(wallet.expiry_alert_blocks(), DEFAULT_EXPIRY_ALERT_BLOCKS);
+        wallet.set_expiry_alert_hook(Box::new(|_| panic!("No coins to alert.")));
+        assert_eq!(wallet.get_expiry_report().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_spend_response_serde() {
+        let responses = vec![
+            (SpendResponse::None, json!({"strategy": "none"})),
+            (SpendResponse::Backup { fee_rate: 20 }, json!({"strategy": "backup", "fee_rate": 20})),
+            (SpendResponse::Withdraw { fee_rate: 5 }, json!({"strategy": "withdraw", "fee_rate": 5})),
+        ];
+        for (response, value) in responses {
+            assert_eq!(serde_json::to_value(&response).unwrap(), value);
+            assert_eq!(serde_json::from_value::<SpendResponse>(value).unwrap(), response);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_respond_to_spend_unknown_coin() {
+        let mut wallet = gen_wallet(None);
+        assert_eq!(wallet.spend_response(), SpendResponse::None);
+        let incident = SpendIncident {
+            statechain_id: Uuid::new_v4(),
+            funding: OutPoint::default(),
+            height: 100,
+            detected_at: chrono::Utc::now().naive_utc(),
+            response_txid: None,
+        };
+        // Incidents of coins not owned by the wallet are ignored whatever the strategy
+        wallet.set_spend_response(SpendResponse::Backup { fee_rate: 20 });
+        assert_eq!(wallet.respond_to_spend(&incident).unwrap(), None);
+        wallet.set_spend_response(SpendResponse::Withdraw { fee_rate: 20 });
+        assert_eq!(wallet.respond_to_spend(&incident).unwrap(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_exit_all_no_coins() {
+        let mut wallet = gen_wallet(None);
+        assert_eq!(wallet.exit_all().unwrap().len(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_withdraw_all_no_coins() {
+        let mut wallet = gen_wallet(None);
+        let destination = wallet.keys.get_new_address().unwrap();
+        let report = wallet.withdraw_all(&destination, &10).unwrap();
+        assert_eq!(report.txs.len(), 0);
+        assert_eq!(report.failed.len(), 0);
+        assert_eq!(report.total_amount(), 0);
+
+        // Destination on another network
+        let destination = Address { network: Network::Bitcoin, ..destination };
+        assert!(wallet.withdraw_all(&destination, &10).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_pending_deposit_save_load() {
+        let mut wallet = gen_wallet(None);
+        assert_eq!(wallet.resume_pending_deposits().unwrap().len(), 0);
+
+        let (inputs, input_addrs, input_amounts) = wallet.coin_selection_greedy(&10000).unwrap();
+        let mut pending = PendingDeposit {
+            shared_key_id: Uuid::new_v4(),
+            challenge: String::from("challenge"),
+            proof_key: wallet.se_proof_keys.get_new_key().unwrap().to_string(),
+            payer_proof_key: None,
+            amount: 10000,
+            fee_quote: shared_lib::structs::StateEntityFeeInfoAPI::example(),
+            inputs,
+            input_addrs,
+            input_amounts,
+            backup_addr: None,
+            tx_funding_hex: None,
+            tx_backup_psm: None,
+            funding_txid: None,
+            p2c_commitment: None,
+        };
+        wallet.save_pending_deposit(&pending);
+
+        // Progress overwrites the saved deposit
+        pending.funding_txid = Some(String::from("txid"));
+        wallet.save_pending_deposit(&pending);
+        assert_eq!(wallet.pending_deposits.len(), 1);
+
+        let wallet_loaded = Wallet::load(DEFAULT_TEST_WALLET_LOC, ClientShim::new("http://localhost:8000".to_string(), None, None), ClientShim::new("http://localhost:8000".to_string(), None, None)).unwrap();
+        assert_eq!(wallet_loaded.pending_deposits.len(), 1);
+        let loaded = &wallet_loaded.pending_deposits[0];
+        assert_eq!(loaded.shared_key_id, pending.shared_key_id);
+        assert_eq!(loaded.inputs, pending.inputs);
+        assert_eq!(loaded.input_addrs, pending.input_addrs);
+        assert_eq!(loaded.funding_txid, pending.funding_txid);
+    }
+
+    #[test]
+    #[serial]
+    fn test_pending_transfers() {
+        let mut wallet = gen_wallet(None);
+        let rec_se_addr = wallet.get_new_state_entity_address().unwrap();
+        let transfer_msg3 = TransferMsg3 {
+            shared_key_id: Uuid::new_v4(),
+            t1: shared_lib::structs::FESer::new_random(),
+            statechain_sig: shared_lib::state_chain::StateChainSig::new(
+                &SecretKey::from_slice(&[1; 32]).unwrap(),
+                &String::from("TRANSFER"),
+                &rec_se_addr.proof_key.to_string(),
+            )
+            .unwrap(),
+            statechain_id: Uuid::new_v4(),
+            tx_backup_psm: PrepareSignTxMsg::default(),
+            rec_se_addr,
+        };
+        let statechain_id = transfer_msg3.statechain_id;
+
+        assert!(wallet.add_pending_transfer(transfer_msg3.clone()).unwrap());
+        // Transfers already pending are not added again
+        assert!(!wallet.add_pending_transfer(transfer_msg3.clone()).unwrap());
+        assert_eq!(wallet.list_pending_transfers(), vec![transfer_msg3.clone()]);
+
+        let mut wallet_loaded = Wallet::load(DEFAULT_TEST_WALLET_LOC, ClientShim::new(mockito::server_url(), None, None), ClientShim::new("http://localhost:8000".to_string(), None, None)).unwrap();
+        assert_eq!(wallet_loaded.list_pending_transfers(), vec![transfer_msg3.clone()]);
+
+        // Rejected transfers are returned to the sender, no longer listed and not added again
+        let _m_reject = mockito::mock("POST", "/transfer/reject")
+            .match_body(mockito::Matcher::PartialJsonString(format!(
+                "{{\"statechain_id\":\"{}\"}}",
+                statechain_id
+            )))
+            .with_header("Content-Type", "application/json")
+            .with_body("null")
+            .create();
+        assert_eq!(wallet_loaded.receive_keys.issued(), 1);
+        wallet_loaded.reject_pending_transfer(&statechain_id).unwrap();
+        assert_eq!(wallet_loaded.list_pending_transfers().len(), 0);
+        // The receive key of the rejected transfer is deleted
+        assert_eq!(wallet_loaded.receive_keys.issued(), 0);
+        assert!(!wallet_loaded.add_pending_transfer(transfer_msg3.clone()).unwrap());
+        match wallet_loaded.accept_pending_transfer(&statechain_id) {
+            Err(CError::WalletError(WalletErrorType::PendingTransferNotFound)) => (),
+            _ => assert!(false, "expected PendingTransferNotFound"),
+        }
+
+        // Transfers to proof keys not in the wallet are not added
+        let mut other = transfer_msg3;
+        other.rec_se_addr = gen_wallet_with_seed(&[0xab; 32], None)
+            .get_new_state_entity_address()
+            .unwrap();
+        assert!(wallet_loaded.add_pending_transfer(other).is_err());
+    }
+
+    #[test]
+    fn test_receive_key_decrypt() {
+        let mut wallet = gen_wallet(None);
+        let rec_se_addr = wallet.get_new_state_entity_address().unwrap();
+        let enc_key = PublicKey {
+            compressed: true,
+            key: rec_se_addr.enc_key.unwrap(),
+        };
+        assert_ne!(enc_key.key, rec_se_addr.proof_key);
+
+        let t1 = shared_lib::structs::FESer::new_random();
+        let mut transfer_msg3 = TransferMsg3 {
+            shared_key_id: Uuid::new_v4(),
+            t1: t1.clone(),
+            statechain_sig: shared_lib::state_chain::StateChainSig::new(
+                &SecretKey::from_slice(&[1; 32]).unwrap(),
+                &String::from("TRANSFER"),
+                &rec_se_addr.proof_key.to_string(),
+            )
+            .unwrap(),
+            statechain_id: Uuid::new_v4(),
+            tx_backup_psm: PrepareSignTxMsg::default(),
+            rec_se_addr,
+        };
+        let encrypted = {
+            transfer_msg3.encrypt().unwrap();
+            transfer_msg3.clone()
+        };
+        // Not decryptable with the proof key alone
+        let proof_key = PublicKey::from_str(&encrypted.statechain_sig.data).unwrap();
+        let proof_key_priv = wallet.se_proof_keys.get_key_derivation(&proof_key).unwrap().private_key;
+        assert!(encrypted.clone().decrypt(&proof_key_priv).is_err());
+        wallet.decrypt(&mut transfer_msg3).unwrap();
+        assert_eq!(transfer_msg3.t1, t1);
+
+        // Once the key is deleted the message can no longer be decrypted
+        wallet.delete_receive_key(&enc_key);
+        match wallet.decrypt(&mut encrypted.clone()) {
+            Err(_) => (),
+            Ok(_) => assert!(false, "expected decryption to fail"),
+        }
     }
 
     #[test]
@@ -905,6 +2751,23 @@ mod tests {
         assert!(selection.is_err());
     }
 
+    #[test]
+    fn test_get_balance() {
+        let mut wallet = gen_wallet(None);
+        // Unused addresses are not queried
+        let _ = wallet.keys.get_new_address();
+        let _ = wallet.keys.get_new_address();
+
+        let (addrs, bals) = wallet.get_all_addresses_balance().unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(bals.len(), 2);
+        assert_eq!(wallet.get_balance().unwrap().confirmed, 10000100);
+
+        let (addrs, unspent) = wallet.list_unspent().unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert!(unspent.iter().all(|u| u.len() == 1));
+    }
+
     #[test]
     fn test_decrypt() {
         use shared_lib::ecies::Encryptable;
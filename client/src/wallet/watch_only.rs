@@ -0,0 +1,316 @@
+//! Watch-only Wallet
+//!
+//! Wallet holding only public data exported from a full wallet. Able to monitor statecoin
+//! balances, verify SMT inclusion proofs against the current root and detect incoming transfers.
+//! Contains no private key material and so cannot sign.
+
+use super::super::Result;
+use crate::error::{CError, WalletErrorType};
+use crate::state_entity::{
+    api::{get_smt_proofs, get_statecoin},
+    util::verify_statechain_smt,
+};
+use crate::utilities::requests;
+use crate::wallet::shared_key::{InclusionProofSMT, SharedKey};
+use crate::wallet::wallet::to_bitcoin_public_key;
+use crate::ClientShim;
+use shared_lib::structs::TransferMsg3;
+
+use bitcoin::{Network, PublicKey};
+use curv::elliptic::curves::traits::ECPoint;
+use monotree::Proof;
+use std::collections::HashMap;
+use std::fs;
+use uuid::Uuid;
+
+pub const DEFAULT_WATCH_ONLY_WALLET_LOC: &str = "wallet/watch_only_wallet.data";
+
+/// Public data of a single statecoin
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WatchOnlyCoin {
+    pub shared_key_id: Uuid,
+    pub statechain_id: Option<Uuid>,
+    pub shared_pubkey: String,
+    pub proof_key: Option<String>,
+    pub value: u64, //Satoshis
+    pub funding_txid: String,
+    pub tx_backup_hex: Option<String>,
+    pub smt_proof: Option<InclusionProofSMT>,
+    pub unspent: bool,
+}
+
+impl WatchOnlyCoin {
+    pub fn from_shared_key(shared_key: &SharedKey) -> Self {
+        WatchOnlyCoin {
+            shared_key_id: shared_key.id,
+            statechain_id: shared_key.statechain_id,
+            shared_pubkey: to_bitcoin_public_key(shared_key.share.public.q.get_element())
+                .to_string(),
+            proof_key: shared_key.proof_key.clone(),
+            value: shared_key.value,
+            funding_txid: shared_key.funding_txid.clone(),
+            tx_backup_hex: shared_key.tx_backup_psm.as_ref().map(|psm| psm.tx_hex.clone()),
+            smt_proof: shared_key.smt_proof.clone(),
+            unspent: shared_key.unspent,
+        }
+    }
+}
+
+/// Watch-only Wallet
+#[derive(Serialize, Deserialize)]
+pub struct WatchOnlyWallet {
+    pub id: String,
+    pub network: String,
+    #[serde(skip)]
+    pub client_shim: Option<ClientShim>,
+    wallet_data_loc: String,
+
+    pub addresses: Vec<String>,     // general and backup tx receive addresses
+    pub proof_keys: Vec<PublicKey>, // State Entity proof keys derived by the full wallet
+    pub coins: Vec<WatchOnlyCoin>,
+}
+
+impl WatchOnlyWallet {
+    pub fn new(
+        id: &String,
+        network: &String,
+        wallet_data_loc: &str,
+        addresses: Vec<String>,
+        proof_keys: Vec<PublicKey>,
+        coins: Vec<WatchOnlyCoin>,
+    ) -> Self {
+        WatchOnlyWallet {
+            id: id.to_owned(),
+            network: network.to_owned(),
+            client_shim: None,
+            wallet_data_loc: wallet_data_loc.to_string(),
+            addresses,
+            proof_keys,
+            coins,
+        }
+    }
+
+    pub fn set_client_shim(&mut self, client_shim: ClientShim) {
+        self.client_shim = Some(client_shim);
+    }
+
+    fn get_client_shim(&self) -> Result<&ClientShim> {
+        self.client_shim.as_ref().ok_or(CError::Generic(String::from(
+            "Watch-only wallet has no State Entity connection set.",
+        )))
+    }
+
+    /// serialize wallet to json
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap()
+    }
+
+    /// load wallet from json
+    pub fn from_json(json: serde_json::Value, client_shim: Option<ClientShim>) -> Result<Self> {
+        let mut wallet: WatchOnlyWallet = serde_json::from_value(json)?;
+        wallet.client_shim = client_shim;
+        Ok(wallet)
+    }
+
+    /// save to disk
+    pub fn save(&self) {
+        let wallet_json = self.to_json().to_string();
+        fs::write(&self.wallet_data_loc, wallet_json).expect("Unable to save wallet!");
+        debug!("(watch-only wallet id: {}) Saved wallet to disk", self.id);
+    }
+
+    /// load wallet from disk
+    pub fn load(wallet_data_loc: &str, client_shim: Option<ClientShim>) -> Result<Self> {
+        let data = match fs::read_to_string(wallet_data_loc) {
+            Ok(data) => data,
+            Err(_) => return Err(CError::WalletError(WalletErrorType::WalletFileNotFound)),
+        };
+        let serde_json_data = match serde_json::from_str(&data) {
+            Ok(data) => data,
+            Err(_) => return Err(CError::WalletError(WalletErrorType::WalletFileInvalid)),
+        };
+        let wallet = match WatchOnlyWallet::from_json(serde_json_data, client_shim) {
+            Ok(wallet) => wallet,
+            Err(_) => return Err(CError::WalletError(WalletErrorType::WalletFileInvalid)),
+        };
+        debug!("(watch-only wallet id: {}) Loaded wallet to memory", wallet.id);
+        Ok(wallet)
+    }
+
+    pub fn get_bitcoin_network(&self) -> Network {
+        self.network.parse::<Network>().unwrap()
+    }
+
+    /// Get coin by statechain id
+    pub fn get_coin_by_statechain_id(&self, statechain_id: &Uuid) -> Result<&WatchOnlyCoin> {
+        for coin in &self.coins {
+            if coin.statechain_id == Some(statechain_id.to_owned()) {
+                return Ok(coin);
+            }
+        }
+        Err(CError::WalletError(WalletErrorType::StateChainNotFound))
+    }
+
+    /// Return total value of unspent statecoins
+    pub fn get_statecoin_balance(&self) -> u64 {
+        self.coins
+            .iter()
+            .filter(|coin| coin.unspent)
+            .map(|coin| coin.value)
+            .sum()
+    }
+
+    /// Verify each coin's stored SMT inclusion proof against the root it was stored with.
+    /// Return statechain ids of coins whose proofs fail.
+    pub fn verify_stored_proofs(&self) -> Vec<Uuid> {
+        let mut failed = vec![];
+        for coin in &self.coins {
+            if let (Some(statechain_id), Some(proof_key), Some(smt_proof)) =
+                (coin.statechain_id, &coin.proof_key, &coin.smt_proof)
+            {
+                if !verify_statechain_smt(
+                    &Some(smt_proof.root.hash()),
+                    &coin.funding_txid,
+                    proof_key,
+                    coin.value,
+                    &smt_proof.proof,
+                ) {
+                    failed.push(statechain_id);
+                }
+            }
+        }
+        failed
+    }
+
+    /// Fetch the current SMT root and fresh inclusion proofs for all unspent coins in a single
+    /// request. Store proofs that verify and return statechain ids of coins whose proof did not
+    /// verify against the new root.
+    pub fn update_proofs(&mut self) -> Result<Vec<Uuid>> {
+        let client_shim = self.get_client_shim()?.clone();
+        let funding_txids: Vec<String> = self
+            .coins
+            .iter()
+            .filter(|coin| coin.unspent && coin.statechain_id.is_some() && coin.proof_key.is_some())
+            .map(|coin| coin.funding_txid.clone())
+            .collect();
+        let smt_proofs = get_smt_proofs(&client_shim, &funding_txids)?;
+        let proofs: HashMap<String, Option<Proof>> =
+            smt_proofs.decode_proofs()?.into_iter().collect();
+        let root = smt_proofs.root;
+
+        let mut failed = vec![];
+        for coin in self.coins.iter_mut().filter(|coin| coin.unspent) {
+            if let (Some(statechain_id), Some(proof_key)) = (coin.statechain_id, &coin.proof_key) {
+                let proof = proofs.get(&coin.funding_txid).cloned().unwrap_or(None);
+                if verify_statechain_smt(
+                    &Some(root.hash()),
+                    &coin.funding_txid,
+                    proof_key,
+                    coin.value,
+                    &proof,
+                ) {
+                    coin.smt_proof = Some(InclusionProofSMT {
+                        root: root.clone(),
+                        proof,
+                    });
+                } else {
+                    failed.push(statechain_id);
+                }
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Check each unspent coin's statechain tip against its proof key and mark coins that have
+    /// been transferred away as spent. Return statechain ids of newly spent coins.
+    pub fn update_spent(&mut self) -> Result<Vec<Uuid>> {
+        let client_shim = self.get_client_shim()?.clone();
+        let mut spent = vec![];
+        for coin in self.coins.iter_mut().filter(|coin| coin.unspent) {
+            if let (Some(statechain_id), Some(proof_key)) = (coin.statechain_id, &coin.proof_key) {
+                let statecoin = get_statecoin(&client_shim, &statechain_id)?;
+                if &statecoin.statecoin.data != proof_key {
+                    coin.unspent = false;
+                    spent.push(statechain_id);
+                }
+            }
+        }
+        Ok(spent)
+    }
+
+    /// Query the State Entity for transfer messages addressed to any of this wallet's proof keys.
+    /// Messages for statechains already held by the wallet are ignored.
+    pub fn get_incoming_transfers(&self) -> Result<Vec<TransferMsg3>> {
+        let client_shim = self.get_client_shim()?;
+        let mut incoming = vec![];
+        for proof_key in &self.proof_keys {
+            let msgs: Vec<TransferMsg3> = requests::get(
+                client_shim,
+                &format!("transfer/get_msg_addr/{}", proof_key.to_string()),
+            )?;
+            for msg in msgs {
+                let known = self.coins.iter().any(|coin| {
+                    coin.unspent
+                        && coin.statechain_id == Some(msg.statechain_id)
+                        && coin.proof_key == Some(msg.rec_se_addr.proof_key.to_string())
+                });
+                if !known {
+                    incoming.push(msg);
+                }
+            }
+        }
+        Ok(incoming)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::wallet::{Wallet, DEFAULT_TEST_WALLET_LOC};
+
+    fn gen_wallet() -> Wallet {
+        let mut wallet = Wallet::new(
+            &[0xcd; 32],
+            &"regtest".to_string(),
+            DEFAULT_TEST_WALLET_LOC,
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+            ClientShim::new("http://localhost:8000".to_string(), None, None),
+        );
+        let _ = wallet.keys.get_new_address();
+        let _ = wallet.se_backup_keys.get_new_address();
+        let _ = wallet.se_proof_keys.get_new_key();
+        let _ = wallet.se_proof_keys.get_new_key();
+        wallet
+    }
+
+    #[test]
+    fn test_export_watch_only() {
+        let wallet = gen_wallet();
+        let watch_only = wallet.export_watch_only(DEFAULT_WATCH_ONLY_WALLET_LOC);
+
+        assert_eq!(watch_only.id, wallet.id);
+        assert_eq!(watch_only.network, wallet.network);
+        assert_eq!(watch_only.addresses.len(), 2);
+        assert_eq!(watch_only.proof_keys.len(), 2);
+        for proof_key in &watch_only.proof_keys {
+            assert!(wallet.se_proof_keys.get_key_derivation(proof_key).is_some());
+        }
+
+        // No private key material exported
+        let json_str = watch_only.to_json().to_string();
+        assert!(!json_str.contains(&wallet.master_priv_key.to_string()));
+        assert!(!json_str.contains("private"));
+    }
+
+    #[test]
+    fn test_to_and_from_json() {
+        let wallet = gen_wallet();
+        let watch_only = wallet.export_watch_only(DEFAULT_WATCH_ONLY_WALLET_LOC);
+
+        let watch_only_rebuilt = WatchOnlyWallet::from_json(watch_only.to_json(), None).unwrap();
+        assert_eq!(watch_only.to_json(), watch_only_rebuilt.to_json());
+        assert_eq!(watch_only_rebuilt.get_statecoin_balance(), 0);
+        assert!(watch_only_rebuilt.verify_stored_proofs().is_empty());
+        assert!(watch_only_rebuilt.get_incoming_transfers().is_err());
+    }
+}
@@ -627,6 +627,7 @@ mod tests {
         let address2 = SCEAddress {
             tx_backup_addr: None,
             proof_key: proof_key2,
+            enc_key: None,
         };
         let transfer_batch_sig2 = state_entity::transfer::transfer_batch_sign(&mut wallet2, &wallet_sers[1].1, &swap_id).unwrap();
 
@@ -636,6 +637,7 @@ mod tests {
             &wallet_sers[1].1,
             &transfer_batch_sig2,
             &address2,
+            &None,
         ).unwrap();
 
         let proof_key3 = wallet3.se_proof_keys.get_new_key().unwrap();
@@ -643,6 +645,7 @@ mod tests {
         let address3 = SCEAddress {
             tx_backup_addr: None,
             proof_key: proof_key3,
+            enc_key: None,
         };
         let transfer_batch_sig3 = state_entity::transfer::transfer_batch_sign(&mut wallet3, &wallet_sers[2].1, &swap_id).unwrap();
 
@@ -652,6 +655,7 @@ mod tests {
             &wallet_sers[2].1,
             &transfer_batch_sig3,
             &address3,
+            &None,
         ).unwrap();
 
         // after swap group timeout (60 s) attempt to register again
@@ -50,6 +50,13 @@ use wallet::wallet::DEFAULT_TEST_WALLET_LOC;
 #[macro_use]
 extern crate serial_test;
 
+/// Server identity secret key used by spawned test servers
+pub const TEST_IDENTITY_KEY: &str =
+    "0303030303030303030303030303030303030303030303030303030303030303";
+/// Public key of TEST_IDENTITY_KEY, pinned by test wallets
+pub const TEST_IDENTITY_PUBKEY: &str =
+    "02531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337";
+
 #[derive(Debug)]
 pub enum SpawnError {
     GetServer,
@@ -112,6 +119,7 @@ impl SpawnServer for PGDatabase {
         // Set enviroment variable to testing_mode=true to override Settings.toml
         env::set_var("MERC_TESTING_MODE", "true");
         env::set_var("MERC_REQUIRED_CONFIRMATION", "0");
+        env::set_var("MERC_IDENTITY_KEY", TEST_IDENTITY_KEY);
         match port {
             Some(p) => env::set_var("MERC_ROCKET_PORT", &p.to_string()[..]),
             None => ()
@@ -154,6 +162,7 @@ impl SpawnServer for MockDatabase {
         // Set enviroment variable to testing_mode=true to override Settings.toml
         env::set_var("MERC_TESTING_MODE", "true");
         env::set_var("MERC_REQUIRED_CONFIRMATION", "0");
+        env::set_var("MERC_IDENTITY_KEY", TEST_IDENTITY_KEY);
 
         // Rocket server is blocking, so we spawn a new thread.
         let handle = thread::spawn(|| {
@@ -190,6 +199,7 @@ fn gen_wallet_with_seed(seed: &[u8], conductor_port: Option<u16>) -> Wallet {
         ClientShim::new("http://localhost:8000".to_string(), None, None),
         ClientShim::new(conductor_endpoint, None, None),
     );
+    wallet.client_shim.server_identity_key = TEST_IDENTITY_PUBKEY.to_string();
     let _ = wallet.keys.get_new_address();
     let _ = wallet.keys.get_new_address();
     wallet
@@ -204,6 +214,7 @@ pub fn gen_wallet_with_deposit(amount: u64) -> Wallet {
         ClientShim::new("http://localhost:8000".to_string(), None, None),
         ClientShim::new("http://localhost:8000".to_string(), None, None),
     );
+    wallet.client_shim.server_identity_key = TEST_IDENTITY_PUBKEY.to_string();
 
     let _ = wallet.keys.get_new_address();
     let _ = wallet.keys.get_new_address();
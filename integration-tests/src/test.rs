@@ -12,6 +12,7 @@ mod tests {
     extern crate hex;
 
     use shared_lib::structs::Protocol;
+    use shared_lib::mocks::mock_electrum::{MockElectrum, TxScenario};
 
     use curv::elliptic::curves::traits::ECScalar;
     use curv::FE;
@@ -160,6 +161,62 @@ mod tests {
         reset_data(&wallet.client_shim).unwrap();
     }
 
+    #[test]
+    #[serial]
+    fn test_deposit_funding_fee_spike() {
+        time_test!();
+        let _handle = start_server(None, None);
+        let mut wallet = gen_wallet(None);
+
+        // The funding tx is rejected by the mempool: the deposit stays pending
+        MockElectrum::set_default_scenario(Some(TxScenario::FeeSpike { fee_rate: 0.001 }));
+        let err = state_entity::deposit::deposit(&mut wallet, &10000).unwrap_err();
+        assert!(format!("{}", err).contains("fee is too low"), "{}", err);
+        assert_eq!(wallet.pending_deposits.len(), 1);
+
+        // Fees drop: the resumed deposit is broadcast and confirmed
+        MockElectrum::clear_scenarios();
+        assert_eq!(wallet.resume_pending_deposits().unwrap().len(), 1);
+        assert_eq!(wallet.pending_deposits.len(), 0);
+        reset_data(&wallet.client_shim).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_deposit_below_minimum() {
+        time_test!();
+        let _handle = start_server(None, None);
+        let mut wallet = gen_wallet(None);
+
+        let min_deposit = state_entity::api::get_statechain_fee_info(&wallet.client_shim)
+            .unwrap()
+            .min_deposit;
+        let err = state_entity::deposit::deposit(&mut wallet, &(min_deposit - 1)).unwrap_err();
+        assert!(format!("{}", err).contains("minimum deposit"), "{}", err);
+        assert_eq!(wallet.pending_deposits.len(), 0);
+        reset_data(&wallet.client_shim).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_deposit_funding_never_broadcast() {
+        time_test!();
+        let _handle = start_server(None, None);
+        let mut wallet = gen_wallet(None);
+
+        // The funding tx is accepted but never reaches the network
+        MockElectrum::set_default_scenario(Some(TxScenario::NeverBroadcast));
+        let err = state_entity::deposit::deposit(&mut wallet, &10000).unwrap_err();
+        assert!(format!("{}", err).contains("not found"), "{}", err);
+        assert_eq!(wallet.pending_deposits.len(), 1);
+        assert!(wallet.pending_deposits[0].funding_txid.is_some());
+
+        // The resumed deposit rebroadcasts the funding tx
+        MockElectrum::clear_scenarios();
+        assert_eq!(wallet.resume_pending_deposits().unwrap().len(), 1);
+        reset_data(&wallet.client_shim).unwrap();
+    }
+
     // #[test]
     // #[serial]
     // fn test_confirm_proofs() {
@@ -884,10 +941,17 @@ mod tests {
                 "".to_string(),
             ))
         });
+        db.expect_get_statechain_info().returning(|_x| {
+            Err(server_lib::error::SEError::DBError(
+                server_lib::error::DBErrorType::NoDataForID,
+                "".to_string(),
+            ))
+        });
         db.expect_create_user_session()
             .returning(|_user_id, _auth, _proof_key, _challenge, _user_ids| Ok(()));
         db.expect_get_user_auth()
             .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state().returning(|_user_id| Ok(None));
         db.expect_init_ecdsa()
             .returning(|_user_id| Ok(0));
         db.expect_update_keygen_first_msg()
@@ -0,0 +1,89 @@
+//! DB serialization benchmarks
+//!
+//! Custom types are serialized to strings before they are written to Postgres. Measures the
+//! serialization round trip of the largest of them: ECDSA master keys and statechains.
+
+#[macro_use]
+extern crate criterion;
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use criterion::{BenchmarkId, Criterion};
+use curv::BigInt;
+use kms::ecdsa::two_party::{MasterKey1, MasterKey2};
+use server_lib::PGDatabase;
+use shared_lib::state_chain::{StateChain, StateChainSig};
+use uuid::Uuid;
+
+/// Server master key from a completed keygen
+fn master_key() -> MasterKey1 {
+    let (_, comm_witness, ec_key_pair_party1) = MasterKey1::key_gen_first_message();
+    let (kg_party_two_first_message, _) = MasterKey2::key_gen_first_message();
+    let (_, paillier_key_pair, party_one_private) = MasterKey1::key_gen_second_message(
+        comm_witness.clone(),
+        &ec_key_pair_party1,
+        &kg_party_two_first_message.d_log_proof,
+    );
+    MasterKey1::set_master_key(
+        &BigInt::from(0),
+        party_one_private,
+        &comm_witness.public_share,
+        &kg_party_two_first_message.public_share,
+        paillier_key_pair,
+    )
+}
+
+/// Statechain transferred length - 1 times
+fn statechain(length: usize) -> StateChain {
+    let secp = Secp256k1::new();
+    let keys: Vec<SecretKey> = (1..=length)
+        .map(|i| SecretKey::from_slice(&[i as u8; 32]).unwrap())
+        .collect();
+    let pks: Vec<String> = keys
+        .iter()
+        .map(|sk| PublicKey::from_secret_key(&secp, sk).to_string())
+        .collect();
+    let statechain_id = Uuid::new_v4();
+    let mut state_chain = StateChain::new(pks[0].clone());
+    for i in 1..length {
+        let sig = StateChainSig::new_bound(
+            &keys[i - 1],
+            &String::from("TRANSFER"),
+            &pks[i],
+            &statechain_id,
+            (i - 1) as u64,
+        )
+        .unwrap();
+        state_chain.add(&sig).unwrap();
+    }
+    state_chain
+}
+
+fn bench_master_key(c: &mut Criterion) {
+    let mk = master_key();
+    let mk_str = PGDatabase::ser(&mk).unwrap();
+
+    c.bench_function("ser master key", |b| {
+        b.iter(|| PGDatabase::ser(&mk).unwrap())
+    });
+    c.bench_function("deser master key", |b| {
+        b.iter(|| PGDatabase::deser::<MasterKey1>(mk_str.clone()).unwrap())
+    });
+}
+
+fn bench_statechain(c: &mut Criterion) {
+    let mut group = c.benchmark_group("statechain");
+    for length in [1, 10, 100].iter() {
+        let state_chain = statechain(*length);
+        let state_chain_str = PGDatabase::ser(&state_chain).unwrap();
+        group.bench_with_input(BenchmarkId::new("ser", length), &state_chain, |b, state_chain| {
+            b.iter(|| PGDatabase::ser(state_chain).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("deser", length), &state_chain_str, |b, state_chain_str| {
+            b.iter(|| PGDatabase::deser::<StateChain>(state_chain_str.clone()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_master_key, bench_statechain);
+criterion_main!(benches);
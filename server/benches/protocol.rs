@@ -0,0 +1,158 @@
+//! Protocol benchmarks
+//!
+//! 2P-ECDSA keygen and signing rounds as run by the server (party 1) and wallet (party 2), and
+//! the transfer receiver key update.
+
+#[macro_use]
+extern crate criterion;
+
+use criterion::Criterion;
+use curv::{
+    arithmetic::traits::Samplable,
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    BigInt, FE, GE,
+};
+use kms::ecdsa::two_party::{MasterKey1, MasterKey2};
+use server_lib::protocol::transfer::transfer_key_update;
+
+/// Run keygen to completion, returning the server and wallet master keys
+fn keygen() -> (MasterKey1, MasterKey2) {
+    let (kg_party_one_first_message, comm_witness, ec_key_pair_party1) =
+        MasterKey1::key_gen_first_message();
+    let (kg_party_two_first_message, ec_key_pair_party2) = MasterKey2::key_gen_first_message();
+    let (kg_party_one_second_message, paillier_key_pair, party_one_private) =
+        MasterKey1::key_gen_second_message(
+            comm_witness.clone(),
+            &ec_key_pair_party1,
+            &kg_party_two_first_message.d_log_proof,
+        );
+    let (_, party_two_paillier) = MasterKey2::key_gen_second_message(
+        &kg_party_one_first_message,
+        &kg_party_one_second_message,
+    )
+    .expect("keygen second message verification failed");
+
+    let mk1 = MasterKey1::set_master_key(
+        &BigInt::from(0),
+        party_one_private,
+        &comm_witness.public_share,
+        &kg_party_two_first_message.public_share,
+        paillier_key_pair,
+    );
+    let mk2 = MasterKey2::set_master_key(
+        &BigInt::from(0),
+        &ec_key_pair_party2,
+        &kg_party_one_second_message
+            .ecdh_second_message
+            .comm_witness
+            .public_share,
+        &party_two_paillier,
+    );
+    (mk1, mk2)
+}
+
+fn bench_keygen(c: &mut Criterion) {
+    c.bench_function("keygen first message", |b| {
+        b.iter(|| MasterKey1::key_gen_first_message())
+    });
+
+    // The server second message generates the Paillier key pair and its correctness proofs
+    c.bench_function("keygen second message", |b| {
+        let (_, comm_witness, ec_key_pair_party1) = MasterKey1::key_gen_first_message();
+        let (kg_party_two_first_message, _) = MasterKey2::key_gen_first_message();
+        b.iter(|| {
+            MasterKey1::key_gen_second_message(
+                comm_witness.clone(),
+                &ec_key_pair_party1,
+                &kg_party_two_first_message.d_log_proof,
+            )
+        })
+    });
+
+    c.bench_function("keygen wallet verification", |b| {
+        let (kg_party_one_first_message, comm_witness, ec_key_pair_party1) =
+            MasterKey1::key_gen_first_message();
+        let (kg_party_two_first_message, _) = MasterKey2::key_gen_first_message();
+        let (kg_party_one_second_message, _, _) = MasterKey1::key_gen_second_message(
+            comm_witness,
+            &ec_key_pair_party1,
+            &kg_party_two_first_message.d_log_proof,
+        );
+        b.iter(|| {
+            MasterKey2::key_gen_second_message(
+                &kg_party_one_first_message,
+                &kg_party_one_second_message,
+            )
+        })
+    });
+}
+
+fn bench_sign(c: &mut Criterion) {
+    let (mk1, mk2) = keygen();
+    let message = BigInt::sample(256);
+
+    c.bench_function("sign first message", |b| {
+        b.iter(|| MasterKey1::sign_first_message())
+    });
+
+    c.bench_function("sign wallet second message", |b| {
+        let (_, eph_comm_witness, eph_ec_key_pair_party2) = MasterKey2::sign_first_message();
+        let (eph_key_gen_first_message_party_one, _) = MasterKey1::sign_first_message();
+        b.iter(|| {
+            mk2.sign_second_message(
+                &eph_ec_key_pair_party2,
+                eph_comm_witness.clone(),
+                &eph_key_gen_first_message_party_one,
+                &message,
+            )
+        })
+    });
+
+    // The server second message verifies the wallet's ephemeral key proofs and completes the
+    // signature
+    c.bench_function("sign second message", |b| {
+        let (eph_key_gen_first_message_party_two, eph_comm_witness, eph_ec_key_pair_party2) =
+            MasterKey2::sign_first_message();
+        let (eph_key_gen_first_message_party_one, eph_ec_key_pair_party1) =
+            MasterKey1::sign_first_message();
+        let party_two_sign_message = mk2.sign_second_message(
+            &eph_ec_key_pair_party2,
+            eph_comm_witness,
+            &eph_key_gen_first_message_party_one,
+            &message,
+        );
+        b.iter(|| {
+            mk1.sign_second_message(
+                &party_two_sign_message,
+                &eph_key_gen_first_message_party_two,
+                &eph_ec_key_pair_party1,
+                &message,
+            )
+            .expect("signature verification failed")
+        })
+    });
+}
+
+fn bench_transfer(c: &mut Criterion) {
+    // Shared key o1*s1 == o2*s2 with s2 = t2 * x1^-1 * s1
+    let g: GE = ECPoint::generator();
+    let s1: FE = ECScalar::new_random();
+    let x1: FE = ECScalar::new_random();
+    let t2: FE = ECScalar::new_random();
+    let o1: FE = ECScalar::new_random();
+    let s2 = t2 * x1.invert() * s1;
+    let o1_pub = g * o1;
+    let o2_pub = g * (o1 * s1 * s2.invert());
+
+    c.bench_function("transfer receiver key update", |b| {
+        b.iter(|| transfer_key_update(&s1, &x1, &t2, &o1_pub, &o2_pub).unwrap())
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_keygen, bench_sign
+}
+criterion_group!(transfer, bench_transfer);
+criterion_main!(benches, transfer);
@@ -0,0 +1,88 @@
+//! Build script
+//!
+//! Embeds the build manifest of the server (git commit, Cargo.lock hash, enabled features and
+//! compiler) in the binary. See src/build_info.rs and doc/reproducible_builds.md.
+//!
+//! The git commit can be supplied with MERCURY_BUILD_GIT_COMMIT when building outside a git
+//! checkout, e.g. in a docker build context without .git.
+
+use bitcoin_hashes::{sha256, Hash};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    match output.status.success() {
+        true => Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => None,
+    }
+}
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let workspace_dir = Path::new(&manifest_dir).parent().unwrap();
+
+    let (git_commit, git_dirty) = match env::var("MERCURY_BUILD_GIT_COMMIT") {
+        Ok(commit) => (commit, false),
+        Err(_) => (
+            command_output("git", &["rev-parse", "HEAD"]).unwrap_or(String::from("unknown")),
+            command_output("git", &["status", "--porcelain", "--untracked-files=no"])
+                .map_or(true, |status| !status.is_empty()),
+        ),
+    };
+
+    let cargo_lock = workspace_dir.join("Cargo.lock");
+    let cargo_lock_hash = match fs::read(&cargo_lock) {
+        Ok(lock) => sha256::Hash::hash(&lock).to_string(),
+        Err(_) => String::from("unknown"),
+    };
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    let rustc = env::var("RUSTC").unwrap_or(String::from("rustc"));
+    let rustc_version = command_output(&rustc, &["--version"]).unwrap_or(String::from("unknown"));
+    let profile = env::var("PROFILE").unwrap_or_default();
+
+    // Release build mode: the manifest must identify the source exactly
+    let release = env::var("CARGO_FEATURE_RELEASE").is_ok();
+    if release {
+        if git_commit == "unknown" || git_dirty {
+            panic!("release build requires a clean git checkout or MERCURY_BUILD_GIT_COMMIT");
+        }
+        if cargo_lock_hash == "unknown" {
+            panic!("release build requires Cargo.lock");
+        }
+        if profile != "release" {
+            panic!("release build requires the release profile (cargo build --release)");
+        }
+    }
+
+    println!("cargo:rustc-env=MERCURY_BUILD_GIT_COMMIT={}", git_commit);
+    println!("cargo:rustc-env=MERCURY_BUILD_GIT_DIRTY={}", git_dirty);
+    println!("cargo:rustc-env=MERCURY_BUILD_CARGO_LOCK_HASH={}", cargo_lock_hash);
+    println!("cargo:rustc-env=MERCURY_BUILD_FEATURES={}", features.join(","));
+    println!("cargo:rustc-env=MERCURY_BUILD_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=MERCURY_BUILD_PROFILE={}", profile);
+    println!("cargo:rustc-env=MERCURY_BUILD_RELEASE={}", release);
+
+    println!("cargo:rerun-if-env-changed=MERCURY_BUILD_GIT_COMMIT");
+    println!("cargo:rerun-if-changed={}", cargo_lock.display());
+    // Sources of the server and its workspace dependencies, for the dirty state
+    for src in &["server/src", "shared/src"] {
+        println!("cargo:rerun-if-changed={}", workspace_dir.join(src).display());
+    }
+    for git_file in &["HEAD", "index"] {
+        let path = workspace_dir.join(".git").join(git_file);
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
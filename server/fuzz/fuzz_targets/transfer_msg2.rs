@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use server_fuzz::*;
+
+fuzz_target!(|data: &[u8]| {
+    // Response to clients: deserialization only
+    fuzz_json::<TransferMsg2, _>(data, |_, _| ());
+});
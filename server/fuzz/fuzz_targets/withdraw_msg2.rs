@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use server_fuzz::*;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz_json::<WithdrawMsg2, _>(data, |sce, msg| sce.withdraw_confirm(msg));
+});
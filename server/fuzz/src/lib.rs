@@ -0,0 +1,504 @@
+//! # Fuzz
+//!
+//! Shared setup for the cargo-fuzz targets in fuzz_targets/. Each target decodes the fuzz
+//! input as one of the JSON request structs accepted by the server and, for structs the
+//! server receives, passes it to the protocol trait method its route calls.
+//!
+//! The StateChainEntity is backed by a MockDatabase holding a stateful fixture: one deposited,
+//! confirmed statecoin owned by a single user session. Every shared key ID and statechain ID
+//! resolves to it, so that inputs are exercised past the first database lookup, and updates
+//! made while handling an input are seen by later queries. Queries for ECDSA key material
+//! return NoDataForID. Any panic is a bug: request handling must return an error for malformed
+//! input.
+//!
+//! The mock has an expectation for every Database method. Methods added to the trait must be
+//! added here, or the targets calling them panic.
+
+use server_lib::config::{Config, Mode};
+use server_lib::error::{DBErrorType, SEError};
+use server_lib::server::StateChainEntity;
+use server_lib::structs::*;
+use server_lib::MockDatabase;
+use shared_lib::state_chain::{StateChain, StateChainSig};
+use shared_lib::Root;
+
+use bitcoin::hashes::sha256d;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::Transaction;
+use chrono::{NaiveDateTime, Utc};
+use curv::FE;
+use monotree::database::MemoryDB;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+pub use server_lib::service::*;
+pub use shared_lib::structs::*;
+pub use shared_lib::swap_data::*;
+
+pub type SCE = StateChainEntity<MockDatabase, MemoryDB>;
+
+/// Backup tx of the fixture statecoin
+static BACKUP_TX: &str = "{\"version\":2,\"lock_time\":0,\"input\":[{\"previous_output\":\"faaaa0920fbaefae9c98a57cdace0deffa96cc64a651851bdd167f397117397c:0\",\"script_sig\":\"\",\"sequence\":4294967295,\"witness\":[]}],\"output\":[{\"value\":9000,\"script_pubkey\":\"00148fc32525487d2cb7323c960bdfb0a5ee6a364738\"}]}";
+
+fn no_data() -> SEError {
+    SEError::DBError(DBErrorType::NoDataForID, String::from("fuzz"))
+}
+
+/// Database state of the fuzz state entity
+#[derive(Clone)]
+pub struct Fixture {
+    pub user_id: Uuid,
+    /// None once the session no longer owns the statechain
+    pub statechain_id: Option<Uuid>,
+    pub user_auth: String,
+    pub proof_key: String,
+    pub challenge: String,
+    pub chain: StateChain,
+    pub amount: i64,
+    pub version: i64,
+    pub confirmed: bool,
+    pub locked_until: NaiveDateTime,
+    pub session_state: Option<SessionState>,
+    pub tx_backup: Transaction,
+    pub backup_tx_history: Vec<Transaction>,
+    pub transfer: Option<(StateChainSig, FE, Option<Uuid>)>,
+    pub transfer_msg: Option<TransferMsg3>,
+    pub withdraw_sc_sig: Option<StateChainSig>,
+    pub tx_withdraw: Option<Transaction>,
+    pub sighash: Option<sha256d::Hash>,
+    pub sighash_records: Vec<SighashRecord>,
+    pub roots: Vec<Root>,
+}
+
+impl Fixture {
+    /// A deposited, confirmed and unlocked statecoin. The proof key is derived from proof_priv.
+    pub fn new(proof_priv: &SecretKey) -> Self {
+        let proof_key = PublicKey::from_secret_key(&Secp256k1::new(), proof_priv).to_string();
+        let tx_backup: Transaction = serde_json::from_str(BACKUP_TX).unwrap();
+        Fixture {
+            user_id: Uuid::parse_str("00000000-0000-4000-8000-000000000001").unwrap(),
+            statechain_id: Some(Uuid::parse_str("00000000-0000-4000-8000-000000000002").unwrap()),
+            user_auth: String::from("user_auth"),
+            proof_key: proof_key.clone(),
+            challenge: String::from("challenge"),
+            chain: StateChain::new(proof_key),
+            amount: 10000,
+            version: 0,
+            confirmed: true,
+            locked_until: Utc::now().naive_utc(),
+            session_state: Some(SessionState::Owner),
+            tx_backup: tx_backup.clone(),
+            backup_tx_history: vec![tx_backup],
+            transfer: None,
+            transfer_msg: None,
+            withdraw_sc_sig: None,
+            tx_withdraw: None,
+            sighash: None,
+            sighash_records: vec![],
+            roots: vec![],
+        }
+    }
+}
+
+/// MockDatabase backed by a fixture. Returns the MockDatabase and the shared fixture.
+pub fn fixture_db(fixture: Fixture) -> (MockDatabase, Arc<Mutex<Fixture>>) {
+    let state = Arc::new(Mutex::new(fixture));
+    let mut db = MockDatabase::new();
+
+    // Fixture accessors
+    macro_rules! get {
+        ($expect:ident, |$f:ident $(, $arg:pat)*| $body:expr) => {{
+            let s = state.clone();
+            db.$expect().returning(move |$($arg),*| {
+                let $f = s.lock().unwrap();
+                $body
+            });
+        }};
+    }
+    macro_rules! set {
+        ($expect:ident, |$f:ident $(, $arg:pat)*| $body:expr) => {{
+            let s = state.clone();
+            db.$expect().returning(move |$($arg),*| {
+                let mut $f = s.lock().unwrap();
+                $body;
+                Ok(())
+            });
+        }};
+    }
+
+    // Connection and initialisation
+    db.expect_set_connection_from_config().returning(|_| Ok(()));
+    db.expect_set_connection().returning(|_| Ok(()));
+    db.expect_init_coins_histo().returning(|_| Ok(()));
+    db.expect_init_user_ids().returning(|_| Ok(()));
+    db.expect_init().returning(|_, _| Ok(()));
+    db.expect_reset().returning(|| Ok(()));
+
+    // User session
+    get!(expect_get_user_auth, |f, _| Ok(f.user_auth.clone()));
+    get!(expect_get_challenge, |f, _| Ok(f.challenge.clone()));
+    get!(expect_get_proof_key, |f, _| Ok(f.proof_key.clone()));
+    set!(expect_update_proof_key, |f, _, proof_key| f.proof_key = proof_key.clone());
+    get!(expect_get_statechain_id, |f, _| f.statechain_id.ok_or(no_data()));
+    set!(expect_update_statechain_id, |f, _, id| f.statechain_id = Some(*id));
+    set!(expect_remove_statechain_id, |f, _| f.statechain_id = None);
+    get!(expect_get_owner_id, |f, _| Ok(f.user_id));
+    set!(expect_create_user_session, |f, user_id, auth, proof_key, challenge, _| {
+        f.user_id = *user_id;
+        f.user_auth = auth.clone();
+        f.proof_key = proof_key.clone();
+        f.challenge = challenge.clone();
+        f.statechain_id = None;
+        f.session_state = Some(SessionState::DepositInit);
+    });
+    set!(expect_transfer_init_user_session, |f, new_user_id, statechain_id, _, _| {
+        f.user_id = *new_user_id;
+        f.statechain_id = Some(*statechain_id);
+        f.session_state = Some(SessionState::TransferInit);
+    });
+    get!(expect_get_session_state, |f, _| Ok(f.session_state));
+    set!(expect_update_session_state, |f, _, session_state| f.session_state = Some(*session_state));
+    db.expect_get_user_sessions().returning(|_, _, _, _| Ok((vec![], 0)));
+    db.expect_remove_orphaned_sessions().returning(|_, _, _| Ok((vec![], 0)));
+    db.expect_insert_fee_acknowledgement().returning(|_, _, _| Ok(()));
+    db.expect_get_fee_acknowledgement().returning(|_| Ok(None));
+    db.expect_create_deposit_handoff().returning(|_, _, _| Ok(()));
+    db.expect_get_deposit_handoff_proof_key().returning(|_| Ok(None));
+    db.expect_get_deposit_payer_proof_key().returning(|_| Ok(None));
+    db.expect_update_deposit_handoff().returning(|_| Ok(()));
+    db.expect_get_deposit_handoffs().returning(|_| Ok(vec![]));
+    db.expect_insert_api_key().returning(|_| Ok(()));
+    db.expect_get_api_key().returning(|_| Err(no_data()));
+    db.expect_revoke_api_key().returning(|_| Ok(()));
+
+    // Statechain
+    get!(expect_get_statechain, |f, _| Ok(f.chain.clone()));
+    get!(expect_get_statechain_version, |f, _| Ok(f.version));
+    get!(expect_get_statechain_amount, |f, _| Ok(StateChainAmount {
+        chain: f.chain.clone(),
+        amount: f.amount,
+    }));
+    get!(expect_get_statechain_info, |f, _| Ok(StateChainInfo {
+        chain: f.chain.clone(),
+        amount: f.amount,
+        tx_backup: Some(f.tx_backup.clone()),
+        confirmed: f.confirmed,
+        archived: false,
+    }));
+    get!(expect_get_statechain_owner, |f, _| Ok(StateChainOwner {
+        locked_until: f.locked_until,
+        owner_id: f.user_id,
+        chain: f.chain.clone(),
+    }));
+    set!(expect_create_statechain, |f, statechain_id, user_id, chain, amount| {
+        f.statechain_id = Some(*statechain_id);
+        f.user_id = *user_id;
+        f.chain = chain.clone();
+        f.amount = *amount;
+        f.version = 0;
+    });
+    {
+        let s = state.clone();
+        db.expect_update_statechain_amount()
+            .returning(move |_, chain, amount, _, version| {
+                let mut f = s.lock().unwrap();
+                if f.version != version {
+                    return Err(SEError::TryAgain(String::from("fuzz: conflicting update")));
+                }
+                f.chain = chain;
+                f.amount = amount as i64;
+                f.version += 1;
+                Ok(())
+            });
+    }
+    {
+        let s = state.clone();
+        db.expect_update_statechain_owner()
+            .returning(move |_, chain, new_user_id, version| {
+                let mut f = s.lock().unwrap();
+                if f.version != version {
+                    return Err(SEError::TryAgain(String::from("fuzz: conflicting update")));
+                }
+                f.chain = chain;
+                f.user_id = *new_user_id;
+                f.version += 1;
+                Ok(())
+            });
+    }
+    get!(expect_is_confirmed, |f, _| Ok(f.confirmed));
+    set!(expect_set_confirmed, |f, _| f.confirmed = true);
+    get!(expect_get_sc_locked_until, |f, _| Ok(f.locked_until));
+    set!(expect_update_locked_until, |f, _, time| f.locked_until = *time);
+    get!(expect_get_proof_key_holdings, |f, _| Ok((1, f.amount as u64)));
+    get!(expect_get_total_value, |f| Ok(f.amount as u64));
+    get!(expect_get_recovery_data, |f, _| Ok(match f.statechain_id {
+        Some(id) => vec![(f.user_id, id, f.tx_backup.clone())],
+        None => vec![],
+    }));
+    db.expect_get_statechains_updated_since().returning(|_, _| Ok(vec![]));
+    db.expect_archive_statechains().returning(|_, _| Ok(vec![]));
+    db.expect_purge_archived_statechains().returning(|_| Ok(0));
+    db.expect_insert_chain_segment().returning(|_, _| Ok(()));
+    db.expect_get_chain_segments().returning(|_| Ok(vec![]));
+    db.expect_set_shared_pubkey().returning(|_, _| Ok(()));
+    db.expect_get_shared_pubkey().returning(|_| Ok(None));
+    db.expect_get_statecoin_pubkey().returning(|_| Ok(None));
+    db.expect_update_shared_pubkey().returning(|_, _| Ok(()));
+    db.expect_get_p2c().returning(|_| Ok(None));
+    db.expect_update_p2c().returning(|_, _| Ok(()));
+
+    // Backup txs
+    get!(expect_get_user_backup_tx, |f, _| Ok(f.tx_backup.clone()));
+    set!(expect_update_user_backup_tx, |f, _, tx| f.tx_backup = tx);
+    get!(expect_get_backup_transaction, |f, _| Ok(f.tx_backup.clone()));
+    get!(expect_get_backup_transaction_and_proof_key, |f, _| Ok((
+        f.tx_backup.clone(),
+        f.proof_key.clone()
+    )));
+    set!(expect_update_backup_tx, |f, _, tx| f.tx_backup = tx);
+    set!(expect_create_backup_transaction, |f, _, tx| f.tx_backup = tx.clone());
+    db.expect_update_backup_cpfp_tx().returning(|_, _| Ok(()));
+    db.expect_remove_backup_tx().returning(|_| Ok(()));
+    db.expect_get_current_backup_txs().returning(|_| Ok(vec![]));
+    get!(expect_get_backup_tx_history, |f, _| Ok(f.backup_tx_history.clone()));
+    set!(expect_insert_backup_tx_history, |f, _, tx| f.backup_tx_history.push(tx.clone()));
+    db.expect_get_backup_addr().returning(|_| Ok(None));
+    db.expect_update_backup_addr().returning(|_, _| Ok(()));
+
+    // Sighashes
+    get!(expect_get_sighash, |f, _| f.sighash.ok_or(no_data()));
+    set!(expect_update_sighash, |f, _, sig_hash| f.sighash = Some(sig_hash));
+    set!(expect_insert_sighash_record, |f, record| f.sighash_records.push(record.clone()));
+    get!(expect_get_sighash_record, |f, user_id, sig_hash| f
+        .sighash_records
+        .iter()
+        .find(|r| r.user_id == *user_id && r.sig_hash == *sig_hash)
+        .cloned()
+        .ok_or(no_data()));
+    get!(expect_get_signed_sighashes, |f, outpoint| Ok(f
+        .sighash_records
+        .iter()
+        .filter(|r| r.signed && r.outpoint == *outpoint)
+        .cloned()
+        .collect()));
+    set!(expect_set_sighash_signed, |f, user_id, sig_hash| f
+        .sighash_records
+        .iter_mut()
+        .filter(|r| r.user_id == *user_id && r.sig_hash == *sig_hash)
+        .for_each(|r| r.signed = true));
+    set!(expect_set_sighash_finalized, |f, user_id, sig_hash| f
+        .sighash_records
+        .iter_mut()
+        .filter(|r| r.user_id == *user_id && r.sig_hash == *sig_hash)
+        .for_each(|r| r.finalized = true));
+
+    // Transfer
+    set!(expect_create_transfer, |f, _, sig, x1, batch_id| f.transfer =
+        Some((sig.clone(), *x1, batch_id)));
+    get!(expect_get_transfer_data, |f, _| match (&f.transfer, f.statechain_id) {
+        (Some((sig, x1, batch_id)), Some(statechain_id)) => Ok(TransferData {
+            statechain_id,
+            statechain_sig: sig.clone(),
+            x1: *x1,
+            batch_id: *batch_id,
+            version: f.version,
+        }),
+        _ => Err(no_data()),
+    });
+    db.expect_claim_transfer().returning(|_, _| Ok(()));
+    set!(expect_remove_transfer_data, |f, _| f.transfer = None);
+    get!(expect_transfer_is_completed, |f, _| f.transfer.is_none()
+        && f.transfer_msg.is_some());
+    set!(expect_update_transfer_msg, |f, _, msg| f.transfer_msg = Some(msg.clone()));
+    get!(expect_get_transfer_msg, |f, _| f.transfer_msg.clone().ok_or(no_data()));
+    get!(expect_get_transfer_msg_addr, |f, _| Ok(f.transfer_msg.clone().into_iter().collect()));
+    db.expect_get_transfer_reject().returning(|_| Ok(None));
+    db.expect_update_transfer_reject().returning(|_| Ok(()));
+    db.expect_insert_transfer_receipt().returning(|_, _| Ok(()));
+    db.expect_get_transfer_receipts().returning(|_| Ok(vec![]));
+    db.expect_insert_deletion_receipt().returning(|_| Ok(()));
+    db.expect_get_deletion_receipts().returning(|_| Ok(vec![]));
+
+    // Batch transfer. No batches exist.
+    db.expect_has_transfer_batch_id().returning(|_| false);
+    db.expect_get_transfer_batch_id().returning(|_| Err(no_data()));
+    db.expect_get_transfer_batch_data().returning(|_| Err(no_data()));
+    db.expect_create_transfer_batch_data().returning(|_, _| Ok(()));
+    db.expect_get_punished_state_chains().returning(|_| Ok(vec![]));
+    db.expect_update_punished().returning(|_, _, _| Ok(()));
+    db.expect_get_transfer_batch_start_time().returning(|_| Err(no_data()));
+    db.expect_update_transfer_batch_start_height().returning(|_, _| Ok(()));
+    db.expect_get_transfer_batch_start_height().returning(|_| Ok(None));
+    db.expect_get_batch_transfer_statechain_ids().returning(|_| Err(no_data()));
+    db.expect_get_finalize_batch_data().returning(|_| Err(no_data()));
+    db.expect_get_sc_transfer_finalize_data().returning(|_| Err(no_data()));
+    db.expect_update_finalize_batch_data().returning(|_, _, _| Ok(()));
+    db.expect_get_transfer_batch_version().returning(|_| Err(no_data()));
+    db.expect_update_transfer_batch_finalized().returning(|_, _, _| Ok(()));
+
+    // Withdraw
+    get!(expect_has_withdraw_sc_sig, |f, _| f
+        .withdraw_sc_sig
+        .as_ref()
+        .map(|_| ())
+        .ok_or(no_data()));
+    get!(expect_get_withdraw_sc_sig, |f, _| f.withdraw_sc_sig.clone().ok_or(no_data()));
+    set!(expect_update_withdraw_sc_sig, |f, _, sig| f.withdraw_sc_sig = Some(sig));
+    set!(expect_update_withdraw_tx_sighash, |f, _, sig_hash, tx| {
+        f.sighash = Some(sig_hash);
+        f.tx_withdraw = Some(tx);
+    });
+    get!(expect_get_tx_withdraw, |f, _| f.tx_withdraw.clone().ok_or(no_data()));
+    set!(expect_update_tx_withdraw, |f, _, tx| f.tx_withdraw = Some(tx));
+    get!(expect_get_withdraw_confirm_data, |f, _| match (
+        &f.tx_withdraw,
+        &f.withdraw_sc_sig,
+        f.statechain_id
+    ) {
+        (Some(tx), Some(sig), Some(statechain_id)) => Ok(WithdrawConfirmData {
+            tx_withdraw: tx.clone(),
+            withdraw_sc_sig: sig.clone(),
+            statechain_id,
+        }),
+        _ => Err(no_data()),
+    });
+
+    // SMT roots
+    {
+        let s = state.clone();
+        db.expect_root_update().returning(move |rt| {
+            let mut f = s.lock().unwrap();
+            let mut root = rt.clone();
+            let id = f.roots.len() as i64 + 1;
+            root.set_id(&id);
+            f.roots.push(root);
+            Ok(id)
+        });
+    }
+    {
+        let s = state.clone();
+        db.expect_root_insert().returning(move |mut root| {
+            let mut f = s.lock().unwrap();
+            let id = f.roots.len() as i64 + 1;
+            root.set_id(&id);
+            f.roots.push(root);
+            Ok(id as u64)
+        });
+    }
+    get!(expect_root_get_current_id, |f| Ok(f.roots.len() as i64));
+    get!(expect_get_root, |f, id| Ok(f.roots.iter().find(|r| r.id() == Some(id)).cloned()));
+    get!(expect_get_root_info, |f, id| Ok(f
+        .roots
+        .iter()
+        .find(|r| r.id() == Some(id))
+        .cloned()));
+    get!(expect_get_confirmed_smt_root, |f| Ok(f
+        .roots
+        .iter()
+        .rev()
+        .find(|r| r.is_confirmed())
+        .cloned()));
+    get!(expect_is_root_confirmed, |f, id| Ok(f
+        .roots
+        .iter()
+        .any(|r| r.id() == Some(id) && r.is_confirmed())));
+    db.expect_insert_root_leaves().returning(|_, _, _| Ok(()));
+    db.expect_get_root_leaves().returning(|_| Ok(vec![]));
+
+    // ECDSA. Key material is not part of the fixture.
+    db.expect_get_lockbox_index().returning(|_| Ok(None));
+    db.expect_update_lockbox_index().returning(|_, _| Ok(()));
+    db.expect_get_s1_pubkey().returning(|_| Err(no_data()));
+    db.expect_update_s1_pubkey().returning(|_, _| Ok(()));
+    db.expect_get_public_master().returning(|_| Ok(None));
+    db.expect_update_public_master().returning(|_, _| Ok(()));
+    db.expect_get_ecdsa_master().returning(|_| Ok(None));
+    db.expect_update_ecdsa_master().returning(|_, _| Ok(()));
+    db.expect_get_ecdsa_master_key_input().returning(|_| Err(no_data()));
+    db.expect_get_ecdsa_witness_keypair().returning(|_| Err(no_data()));
+    db.expect_get_ecdsa_s2().returning(|_| Err(no_data()));
+    db.expect_update_ecdsa_s2().returning(|_, _| Ok(()));
+    db.expect_get_keygen_first_msg().returning(|_| Err(no_data()));
+    db.expect_update_keygen_first_msg().returning(|_, _| Ok(()));
+    db.expect_update_keygen_first_msg_and_witness().returning(|_, _, _, _| Ok(()));
+    db.expect_update_keygen_second_msg().returning(|_, _, _, _| Ok(()));
+    db.expect_update_party_2_public().returning(|_, _| Ok(()));
+    db.expect_init_ecdsa().returning(|_| Ok(0));
+    db.expect_reset_ecdsa().returning(|_| Ok(()));
+    db.expect_remove_ecdsa().returning(|_| Ok(()));
+    db.expect_get_ecdsa_key_epoch().returning(|_| Ok(0));
+    db.expect_update_ecdsa_key_epoch().returning(|_, _| Ok(()));
+    db.expect_get_ecdsa_share_epoch().returning(|_| Ok(0));
+    db.expect_update_ecdsa_share_epoch().returning(|_, _| Ok(()));
+    db.expect_get_ecdsa_refresh_x1().returning(|_| Ok(None));
+    db.expect_update_ecdsa_refresh_x1().returning(|_, _| Ok(()));
+    db.expect_update_ecdsa_proof_verified().returning(|_, _| Ok(()));
+    db.expect_get_unverified_keygen_sessions().returning(|| Ok(vec![]));
+    db.expect_get_ecdsa_party_1_private().returning(|_| Err(no_data()));
+    db.expect_get_ecdsa_keypair().returning(|_| Err(no_data()));
+    db.expect_update_ecdsa_sign_first().returning(|_, _, _| Ok(()));
+    db.expect_get_ecdsa_sign_second_input().returning(|_| Err(no_data()));
+
+    // Statecoin services. None are set up for the fixture statecoin.
+    db.expect_get_sale().returning(|_| Ok(None));
+    db.expect_update_sale().returning(|_| Ok(()));
+    db.expect_get_inheritance().returning(|_| Ok(None));
+    db.expect_update_inheritance().returning(|_| Ok(()));
+    db.expect_remove_inheritance().returning(|_| Ok(()));
+    db.expect_get_second_factor().returning(|_| Ok(None));
+    db.expect_update_second_factor().returning(|_| Ok(()));
+    db.expect_get_vault().returning(|_| Ok(None));
+    db.expect_update_vault().returning(|_| Ok(()));
+    db.expect_get_punishment().returning(|_| Ok(None));
+    db.expect_update_punishment().returning(|_| Ok(()));
+    db.expect_remove_expired_punishments().returning(|_, _| Ok(0));
+    db.expect_get_watchtower_registration().returning(|_| Ok(None));
+    db.expect_update_watchtower_registration().returning(|_| Ok(()));
+    db.expect_count_watchtower_registrations().returning(|_| Ok(0));
+    db.expect_get_due_watchtower_txs().returning(|_| Ok(vec![]));
+    db.expect_remove_watchtower_tx().returning(|_| Ok(()));
+
+    // Swaps and fees
+    db.expect_insert_swap_fee().returning(|_, _, _, _| Ok(()));
+    db.expect_get_swap_fee_by_txid().returning(|_| Ok(None));
+    db.expect_get_swap_fee_owed().returning(|_| Ok(0));
+    db.expect_update_swap_fee_collected().returning(|_| Ok(()));
+    db.expect_insert_swap_report().returning(|_| Ok(()));
+    db.expect_get_swap_report().returning(|_| Ok(None));
+    db.expect_insert_spend_incident().returning(|_| Ok(()));
+    db.expect_update_spend_incident_response().returning(|_, _| Ok(()));
+    db.expect_get_spend_incident().returning(|_| Ok(None));
+    db.expect_insert_fee().returning(|_, _, _, _| Ok(()));
+    db.expect_get_fees().returning(|_, _| Ok(vec![]));
+
+    (db, state)
+}
+
+/// State entity with the fixture database, no rate limits, no lockbox and no mainstay
+pub fn fuzz_sc_entity() -> SCE {
+    let mut config = Config::default();
+    config.mode = Mode::Both;
+    config.testing_mode = true;
+    config.lockbox = None;
+    config.mainstay = None;
+    config.rate_limit_slow = None;
+    config.rate_limit_fast = None;
+    config.rate_limit_id = None;
+    let proof_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+    let (db, _) = fixture_db(Fixture::new(&proof_priv));
+    SCE::load(db, MemoryDB::new("fuzz"), Some(config)).unwrap()
+}
+
+/// Decode data as JSON and, if it is a valid T, pass it to handle
+pub fn fuzz_json<T, R>(data: &[u8], handle: impl FnOnce(&SCE, T) -> R)
+where
+    T: serde::de::DeserializeOwned + serde::Serialize,
+{
+    if let Ok(msg) = serde_json::from_slice::<T>(data) {
+        // decoded messages must round trip
+        let ser = serde_json::to_vec(&msg).unwrap();
+        let _: T = serde_json::from_slice(&ser).unwrap();
+        let sce = fuzz_sc_entity();
+        let _ = handle(&sce, msg);
+    }
+}
@@ -0,0 +1,141 @@
+//! # AML
+//!
+//! Pluggable anti-money-laundering screening of deposits and withdrawals.
+//!
+//! Operators that must screen funds entering or leaving the state entity implement AmlHook
+//! and set it on the StateChainEntity with set_aml_hook(). The hook is called from
+//! deposit_confirm and withdraw/init. Screening may be slow or depend on an external
+//! service: a hook that cannot yet decide returns Delay and the client is asked to try again,
+//! so no request thread is held while screening completes. Every outcome is written to the
+//! audit log (log target "audit").
+
+use crate::error::SEError;
+use crate::Result;
+use uuid::Uuid;
+use std::fmt;
+
+/// Protocol step being screened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmlEventKind {
+    Deposit,
+    Withdraw,
+}
+
+/// Details of a deposit or withdrawal passed to the AML hook
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmlEvent {
+    pub kind: AmlEventKind,
+    pub user_id: Uuid,
+    pub statechain_id: Option<Uuid>,
+    /// Funding txid of the statecoin
+    pub txid: String,
+    /// Statecoin amount (satoshis)
+    pub amount: u64,
+    /// Withdrawal destination address
+    pub destination: Option<String>,
+}
+
+/// Screening decision
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmlOutcome {
+    /// Proceed with the request
+    Allow,
+    /// Screening not complete. The client should retry later.
+    Delay(String),
+    /// Refuse the request
+    Reject(String),
+}
+
+impl fmt::Display for AmlOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmlOutcome::Allow => write!(f, "allow"),
+            AmlOutcome::Delay(reason) => write!(f, "delay ({})", reason),
+            AmlOutcome::Reject(reason) => write!(f, "reject ({})", reason),
+        }
+    }
+}
+
+/// AML screening hook
+pub trait AmlHook: Send + Sync {
+    fn screen(&self, event: &AmlEvent) -> AmlOutcome;
+}
+
+/// Default hook: allows everything
+#[derive(Debug, Default)]
+pub struct NoopAmlHook;
+
+impl AmlHook for NoopAmlHook {
+    fn screen(&self, _event: &AmlEvent) -> AmlOutcome {
+        AmlOutcome::Allow
+    }
+}
+
+/// Run the hook for an event, record the outcome in the audit log and convert
+/// Delay and Reject outcomes into errors.
+pub fn aml_screen(hook: &dyn AmlHook, event: &AmlEvent) -> Result<()> {
+    let outcome = hook.screen(event);
+    info!(
+        target: "audit",
+        "AML: {:?} user_id: {} statechain_id: {:?} txid: {} amount: {} destination: {:?} outcome: {}",
+        event.kind,
+        event.user_id,
+        event.statechain_id,
+        event.txid,
+        event.amount,
+        event.destination,
+        outcome
+    );
+    match outcome {
+        AmlOutcome::Allow => Ok(()),
+        AmlOutcome::Delay(reason) => Err(SEError::TryAgain(format!(
+            "{:?} pending AML screening: {}",
+            event.kind, reason
+        ))),
+        AmlOutcome::Reject(reason) => Err(SEError::Generic(format!(
+            "{:?} rejected by AML screening: {}",
+            event.kind, reason
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct RejectWithdrawals;
+
+    impl AmlHook for RejectWithdrawals {
+        fn screen(&self, event: &AmlEvent) -> AmlOutcome {
+            match event.kind {
+                AmlEventKind::Deposit => AmlOutcome::Delay(String::from("checking")),
+                AmlEventKind::Withdraw => AmlOutcome::Reject(String::from("blocked destination")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_aml_screen() {
+        let mut event = AmlEvent {
+            kind: AmlEventKind::Deposit,
+            user_id: Uuid::new_v4(),
+            statechain_id: None,
+            txid: String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e"),
+            amount: 10000,
+            destination: None,
+        };
+        assert!(aml_screen(&NoopAmlHook, &event).is_ok());
+
+        match aml_screen(&RejectWithdrawals, &event) {
+            Err(SEError::TryAgain(e)) => assert!(e.contains("checking")),
+            _ => assert!(false, "expected TryAgain error"),
+        }
+
+        event.kind = AmlEventKind::Withdraw;
+        event.destination = Some(String::from("bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8"));
+        match aml_screen(&RejectWithdrawals, &event) {
+            Err(SEError::Generic(e)) => assert!(e.contains("blocked destination")),
+            _ => assert!(false, "expected Generic error"),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! # API Key API
+//!
+//! Rocket route handlers for the API key service.
+
+use super::SCE;
+use crate::protocol::api_key::ApiKeys;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Issue an info API key (admin)
+#[post("/admin/api-key", data = "<issue_msg>")]
+pub fn issue_api_key(sc_entity: State<SCE>, issue_msg: Payload<ApiKeyIssueMsg>) -> Result<Payload<ApiKeyAPI>> {
+    sc_entity.check_rate_slow("admin")?;
+    match sc_entity.issue_api_key(issue_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Revoke an info API key (admin)
+#[post("/admin/api-key/revoke", data = "<revoke_msg>")]
+pub fn revoke_api_key(sc_entity: State<SCE>, revoke_msg: Payload<ApiKeyRevokeMsg>) -> Result<Payload<()>> {
+    sc_entity.check_rate_slow("admin")?;
+    match sc_entity.revoke_api_key(revoke_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,180 @@
+//! # Conductor API
+//!
+//! Rocket route handlers for the Conductor service.
+
+use super::SCE;
+use crate::error::SEError;
+use crate::protocol::conductor::Conductor;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::{blinded_token::BlindedSpendSignature, structs::*, swap_data::*};
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[openapi]
+/// # Poll conductor for the status of a specified registered statecoin ID
+#[post("/swap/poll/utxo", data = "<statechain_id>")]
+pub fn poll_utxo(sc_entity: State<SCE>, statechain_id: Payload<StatechainID>) -> Result<Payload<SwapID>> {
+    sc_entity.check_rate_fast("swap")?;
+    sc_entity.update_swap_info()?;
+    match sc_entity.poll_utxo(&statechain_id.id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Poll conductor for the status of a specified swap ID
+#[post("/swap/poll/swap", data = "<swap_id>")]
+pub fn poll_swap(sc_entity: State<SCE>, swap_id: Payload<SwapID>) -> Result<Payload<Option<SwapStatus>>> {
+    sc_entity.check_rate_fast("swap")?;
+    sc_entity.update_swap_info()?;
+    match sc_entity.poll_swap(&swap_id.id.ok_or("poll_swap: swap_id.id is None".to_string())?) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get information a specified swap ID
+#[post("/swap/info", data = "<swap_id>")]
+pub fn get_swap_info(sc_entity: State<SCE>, swap_id: Payload<SwapID>) -> Result<Payload<Option<SwapInfo>>> {
+    sc_entity.check_rate_fast("swap")?;
+    sc_entity.update_swap_info()?;
+    match sc_entity.get_swap_info(
+        &swap_id.id.ok_or("poll_swap: swap_id.id is None".to_string())?) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get blinded spend token required for second message
+#[post("/swap/blinded-spend-signature", data = "<bst_msg>")]
+pub fn get_blinded_spend_signature(
+    sc_entity: State<SCE>,
+    bst_msg: Payload<BSTMsg>,
+) -> Result<Payload<BlindedSpendSignature>> {
+    sc_entity.check_rate_fast("swap")?;
+    let bst_msg = bst_msg.into_inner();
+    let swap_uuid = &Uuid::from_str(&bst_msg.swap_id)?;
+    let statechain_uuid = &Uuid::from_str(&bst_msg.statechain_id)?;
+    let result = sc_entity
+        .get_blinded_spend_signature(swap_uuid, statechain_uuid)
+        .map(|x| Payload(x));
+    let _ = sc_entity.update_swap_info();
+    return result
+}
+
+#[openapi]
+/// # Phase 0 of coinswap: Notify conductor of desire to take part in a swap with signature to prove ownership of statecoin.
+#[post("/swap/register-utxo", data = "<register_utxo_msg>")]
+pub fn register_utxo(
+    sc_entity: State<SCE>,
+    register_utxo_msg: Payload<RegisterUtxo>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("swap")?;
+    match sc_entity.register_utxo(&register_utxo_msg.into_inner()) {
+        Ok(res) => {
+            let _ = sc_entity.update_swap_info();
+            return Ok(Payload(res))
+        },
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// Remove coin from awaiting in swap pool
+#[post("/swap/deregister-utxo", data = "<statechain_id>")]
+pub fn deregister_utxo(
+    sc_entity: State<SCE>,
+    statechain_id: Payload<StatechainID>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("swap")?;
+    match sc_entity.deregister_utxo(&statechain_id.id) {
+        Ok(res) => {
+            let _ = sc_entity.update_swap_info();
+            return Ok(Payload(res))
+        },
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Phase 1 of coinswap: Participants sign SwapToken and provide a statechain address and e_prime for blind spend token.
+#[post("/swap/first", data = "<swap_msg1>")]
+pub fn swap_first_message(sc_entity: State<SCE>, swap_msg1: Payload<SwapMsg1>) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("swap")?;
+    match sc_entity.swap_first_message(&swap_msg1.into_inner()) {
+        Ok(res) => {
+            let _ = sc_entity.update_swap_info();    
+            return Ok(Payload(res))
+        },
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Phase 2 of coinswap: Participants provide blind spend token and recieve address.
+#[post("/swap/second", data = "<swap_msg2>")]
+pub fn swap_second_message(
+    sc_entity: State<SCE>,
+    swap_msg2: Payload<SwapMsg2>,
+) -> Result<Payload<(SCEAddress)>> {
+    sc_entity.check_rate_fast("swap")?;
+    match sc_entity.swap_second_message(&swap_msg2.into_inner()) {
+        Ok(res) => {
+            let _ = sc_entity.update_swap_info();
+            return Ok(Payload(res))
+        },
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get information on current group registrations
+#[get("/swap/groupinfo")]
+pub fn get_group_info(
+    sc_entity: State<SCE>,
+    ) -> Result<Payload<(HashMap<SwapGroup,GroupStatus>)>> {
+    sc_entity.check_rate_fast("swap")?;
+    sc_entity.update_swap_info()?;
+    match sc_entity.get_group_info() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the swap fee terms and the number of registrations and swaps in progress
+#[get("/swap/stats")]
+pub fn get_swap_stats(
+    sc_entity: State<SCE>,
+    ) -> Result<Payload<SwapStatsAPI>> {
+    sc_entity.check_rate_fast("swap")?;
+    match sc_entity.get_swap_stats() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the report of a completed swap, signed by the server identity key
+#[get("/swap/report/<swap_id>")]
+pub fn get_swap_report(
+    sc_entity: State<SCE>,
+    swap_id: String,
+    ) -> Result<Payload<SignedSwapReport>> {
+    sc_entity.check_rate_fast("swap")?;
+    let swap_id = Uuid::from_str(&swap_id)
+        .map_err(|e| SEError::SwapError(format!("Invalid swap id: {}", e)))?;
+    match sc_entity.get_swap_report(&swap_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,94 @@
+//! # Deposit API
+//!
+//! Rocket route handlers for the Deposit service.
+
+use super::SCE;
+use crate::protocol::deposit::Deposit;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Initiate a statechain deposit and generate a shared key ID
+#[post("/deposit/init", data = "<deposit_msg1>")]
+pub fn deposit_init(sc_entity: State<SCE>, deposit_msg1: Payload<DepositMsg1>) -> Result<Payload<UserID>> {
+    sc_entity.check_rate_slow("deposit_init")?;
+    match sc_entity.deposit_init(deposit_msg1.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Confirm the deposit process has completed and retreive the statechain ID
+#[post("/deposit/confirm", data = "<deposit_msg2>")]
+pub fn deposit_confirm(
+    sc_entity: State<SCE>,
+    deposit_msg2: Payload<DepositMsg2>,
+) -> Result<Payload<StatechainID>> {
+    sc_entity.check_rate_fast("deposit_confirm")?;
+    match sc_entity.deposit_confirm(deposit_msg2.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Store the encrypted shared key of a deposit made on behalf of another proof key
+#[post("/deposit/handoff", data = "<handoff_msg>")]
+pub fn deposit_handoff(
+    sc_entity: State<SCE>,
+    handoff_msg: Payload<DepositHandoffMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("deposit_handoff")?;
+    match sc_entity.deposit_handoff(handoff_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the deposit handoffs (DepositHandoffMsg) stored for a proof key
+#[get("/deposit/handoff/<proof_key>")]
+pub fn deposit_get_handoffs(
+    sc_entity: State<SCE>,
+    proof_key: String,
+) -> Result<Payload<Vec<DepositHandoffMsg>>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.deposit_get_handoffs(proof_key) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Authorise the refund of a deposit that was never confirmed
+#[post("/deposit/refund", data = "<refund_msg>")]
+pub fn deposit_refund(
+    sc_entity: State<SCE>,
+    refund_msg: Payload<DepositRefundMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("deposit_refund")?;
+    match sc_entity.deposit_refund(refund_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Pause or resume new deposits (admin)
+#[post("/admin/deposit/pause", data = "<pause_msg>")]
+pub fn set_deposits_paused(
+    sc_entity: State<SCE>,
+    pause_msg: Payload<DepositPauseMsg>,
+) -> Result<Payload<DepositCapacityAPI>> {
+    sc_entity.check_rate_slow("admin")?;
+    match sc_entity.set_deposits_paused(pause_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,128 @@
+//! # Ecdsa API
+//!
+//! Rocket route handlers for the Ecdsa service.
+
+use super::SCE;
+use crate::protocol::ecdsa::Ecdsa;
+use crate::protocol::noise::EcdsaChannel;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # First round of the 2P-ECDSA key generation protocol: get pubkey and ZK proof commitments
+#[post("/ecdsa/keygen/first", data = "<key_gen_msg1>")]
+pub fn first_message(
+    sc_entity: State<SCE>,
+    key_gen_msg1: Payload<KeyGenMsg1>,
+) -> Result<Payload<KeyGenReply1>> {
+    sc_entity.check_rate_slow("ecdsa")?;
+    match sc_entity.first_message(key_gen_msg1.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Second round of the 2P-ECDSA key generation protocol: get Paillier share and proofs
+#[post("/ecdsa/keygen/second", data = "<key_gen_msg2>")]
+pub fn second_message(
+    sc_entity: State<SCE>,
+    key_gen_msg2: Payload<KeyGenMsg2>,
+) -> Result<Payload<KeyGenReply2>> {
+    sc_entity.check_rate_slow("ecdsa")?;
+    match sc_entity.second_message(key_gen_msg2.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Keygen sessions without verified zero-knowledge proofs (admin)
+#[post("/admin/keygen/audit", data = "<audit_msg>")]
+pub fn keygen_audit(
+    sc_entity: State<SCE>,
+    audit_msg: Payload<KeygenAuditMsg>,
+) -> Result<Payload<KeygenAuditAPI>> {
+    sc_entity.check_rate_slow("admin")?;
+    match sc_entity.keygen_audit(audit_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # First round of the 2P-ECDSA signing protocol: shared ephemeral keygen and proofs 
+#[post("/ecdsa/sign/first", data = "<sign_msg1>")]
+pub fn sign_first(
+    sc_entity: State<SCE>,
+    sign_msg1: Payload<SignMsg1>,
+) -> Result<Payload<SignReply1>> {
+    sc_entity.check_rate_slow("ecdsa")?;
+    match sc_entity.sign_first(sign_msg1.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Second round of the 2P-ECDSA signing protocol: signature generation and verification
+#[post("/ecdsa/sign/second", data = "<sign_msg2>")]
+pub fn sign_second(sc_entity: State<SCE>, sign_msg2: Payload<SignMsg2>) -> Result<Payload<Vec<Vec<u8>>>> {
+    sc_entity.check_rate_slow("ecdsa")?;
+    match sc_entity.sign_second(sign_msg2.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Reset 2P-ECDSA keygen for a shared key from an earlier key epoch
+#[post("/ecdsa/keygen/refresh", data = "<user_id>")]
+pub fn key_refresh(sc_entity: State<SCE>, user_id: Payload<UserID>) -> Result<Payload<()>> {
+    sc_entity.check_rate_slow("ecdsa")?;
+    match sc_entity.key_refresh(user_id.id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the server identity public key used to open encrypted ECDSA channels
+#[get("/ecdsa/noise/identity")]
+pub fn noise_get_identity(sc_entity: State<SCE>) -> Result<Payload<IdentityPubKey>> {
+    sc_entity.check_rate_fast("ecdsa")?;
+    match sc_entity.noise_get_identity() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Open the encrypted ECDSA channel of a user session: Noise KK handshake
+#[post("/ecdsa/noise/handshake", data = "<handshake_msg>")]
+pub fn noise_handshake(
+    sc_entity: State<SCE>,
+    handshake_msg: Payload<NoiseHandshakeMsg>,
+) -> Result<Payload<NoiseMsg>> {
+    sc_entity.check_rate_slow("ecdsa")?;
+    match sc_entity.noise_handshake(handshake_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Send a 2P-ECDSA keygen or signing message over the encrypted channel of a user session
+#[post("/ecdsa/noise", data = "<noise_msg>")]
+pub fn noise_ecdsa(sc_entity: State<SCE>, noise_msg: Payload<NoiseMsg>) -> Result<Payload<NoiseMsg>> {
+    sc_entity.check_rate_slow("ecdsa")?;
+    match sc_entity.noise_ecdsa(noise_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
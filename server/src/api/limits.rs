@@ -0,0 +1,126 @@
+//! # Limits
+//!
+//! Request body size limits. A request declaring a Content-Length above the limit for its
+//! route is rerouted to payload_too_large before its body is read and is answered with
+//! 413 Payload Too Large. Bodies without a Content-Length are capped by the Rocket "json"
+//! data limit, which is set to the largest configured limit.
+
+use crate::config::RocketConfig;
+use crate::error::SEError;
+use crate::Result;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{uri::Origin, Method, Status};
+use rocket::{Data, Request};
+use std::collections::HashMap;
+
+/// Route that requests exceeding their body size limit are rerouted to
+pub const PAYLOAD_TOO_LARGE_PATH: &str = "/payload-too-large";
+
+/// Request body size limits fairing
+#[derive(Debug, Clone)]
+pub struct BodyLimits {
+    default: u64,
+    routes: HashMap<String, u64>,
+}
+
+impl BodyLimits {
+    /// Read limits from the rocket config. route_body_limits is a comma separated
+    /// list of path=bytes entries.
+    pub fn from_config(config: &RocketConfig) -> Result<Self> {
+        let mut routes = HashMap::new();
+        let limits_str = config.route_body_limits.replace(" ", "");
+        for entry in limits_str.split(",").filter(|e| !e.is_empty()) {
+            let (path, limit) = match entry.find('=') {
+                Some(i) => (&entry[..i], &entry[i + 1..]),
+                None => {
+                    return Err(SEError::Generic(format!(
+                        "Invalid route body limit: {}",
+                        entry
+                    )))
+                }
+            };
+            let limit = limit.parse::<u64>().map_err(|e| {
+                SEError::Generic(format!("Invalid route body limit {}: {}", entry, e))
+            })?;
+            routes.insert(path.to_string(), limit);
+        }
+        Ok(Self {
+            default: config.max_body_size,
+            routes,
+        })
+    }
+
+    /// Body size limit for a route path
+    pub fn limit(&self, path: &str) -> u64 {
+        *self.routes.get(path).unwrap_or(&self.default)
+    }
+
+    /// Largest limit over all routes
+    pub fn max(&self) -> u64 {
+        self.routes
+            .values()
+            .fold(self.default, |max, l| if *l > max { *l } else { max })
+    }
+}
+
+impl Fairing for BodyLimits {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request body size limits",
+            kind: Kind::Request,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let length = match request
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|l| l.parse::<u64>().ok())
+        {
+            Some(l) => l,
+            None => return,
+        };
+        let path = request.uri().path().to_string();
+        let limit = self.limit(&path);
+        if length > limit {
+            warn!(
+                "Request body of {} bytes to {} exceeds limit of {} bytes",
+                length, path, limit
+            );
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(PAYLOAD_TOO_LARGE_PATH).unwrap());
+        }
+    }
+}
+
+#[get("/payload-too-large")]
+pub fn payload_too_large() -> Status {
+    Status::PayloadTooLarge
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body_limits_from_config() {
+        let mut config = RocketConfig::default();
+        config.max_body_size = 1000;
+        config.route_body_limits = String::from("/info/recover=100, /ecdsa/keygen/second=5000");
+        let limits = BodyLimits::from_config(&config).unwrap();
+        assert_eq!(limits.limit("/info/recover"), 100);
+        assert_eq!(limits.limit("/ecdsa/keygen/second"), 5000);
+        assert_eq!(limits.limit("/info/proof"), 1000);
+        assert_eq!(limits.max(), 5000);
+
+        config.route_body_limits = String::from("");
+        let limits = BodyLimits::from_config(&config).unwrap();
+        assert_eq!(limits.max(), 1000);
+
+        config.route_body_limits = String::from("/info/recover");
+        assert!(BodyLimits::from_config(&config).is_err());
+        config.route_body_limits = String::from("/info/recover=ten");
+        assert!(BodyLimits::from_config(&config).is_err());
+    }
+}
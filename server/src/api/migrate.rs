@@ -0,0 +1,66 @@
+//! # Migrate API
+//!
+//! Rocket route handlers for the Migrate service.
+
+use super::SCE;
+use crate::protocol::migrate::Migrate;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Get the migration public key of this state entity
+#[get("/migrate/pubkey")]
+pub fn migrate_get_pubkey(sc_entity: State<SCE>) -> Result<Payload<MigratePubKey>> {
+    sc_entity.check_rate_fast("migrate")?;
+    match sc_entity.migrate_get_pubkey() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Migration initiation by owner: get x1
+#[post("/migrate/init", data = "<migrate_msg1>")]
+pub fn migrate_init(
+    sc_entity: State<SCE>,
+    migrate_msg1: Payload<MigrateMsg1>,
+) -> Result<Payload<TransferMsg2>> {
+    sc_entity.check_rate_fast("migrate")?;
+    match sc_entity.migrate_init(migrate_msg1.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Migration key share update and export to the new state entity
+#[post("/migrate/export", data = "<migrate_msg2>")]
+pub fn migrate_export(
+    sc_entity: State<SCE>,
+    migrate_msg2: Payload<MigrateMsg2>,
+) -> Result<Payload<SignedMigrationPackage>> {
+    sc_entity.check_rate_fast("migrate")?;
+    match sc_entity.migrate_export(migrate_msg2.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Import of a statecoin migrated from another state entity
+#[post("/migrate/import", data = "<package>")]
+pub fn migrate_import(
+    sc_entity: State<SCE>,
+    package: Payload<SignedMigrationPackage>,
+) -> Result<Payload<TransferMsg5>> {
+    sc_entity.check_rate_fast("migrate")?;
+    match sc_entity.migrate_import(package.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
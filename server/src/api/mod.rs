@@ -0,0 +1,41 @@
+//! # API
+//!
+//! Rocket frontend of the state entity. Each handler applies rate limiting, decodes the
+//! request and calls the corresponding method of the service API (see service.rs). No
+//! protocol logic lives here so that another web framework can be used as a frontend
+//! by reimplementing this module only.
+
+pub mod api_key;
+pub mod conductor;
+pub mod deposit;
+pub mod ecdsa;
+pub mod migrate;
+pub mod limits;
+pub mod payload;
+pub mod ping;
+pub mod refresh;
+pub mod revenue;
+pub mod rotate;
+pub mod second_factor;
+pub mod vault;
+pub mod transfer;
+pub mod transfer_batch;
+pub mod util;
+pub mod watchtower;
+pub mod withdraw;
+
+use crate::server::StateChainEntity;
+use cfg_if::cfg_if;
+
+//Generics cannot be used in Rocket State, therefore we define the concrete
+//type of StateChainEntity here
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        pub type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        pub type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
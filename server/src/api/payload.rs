@@ -0,0 +1,163 @@
+//! # Payload
+//!
+//! Content negotiated request and response bodies. Protocol messages are JSON encoded by
+//! default. Clients may instead send a CBOR encoded body (Content-Type: application/cbor)
+//! and/or request a CBOR encoded response (Accept: application/cbor), which is considerably
+//! smaller and faster to decode for the BigInt-heavy ECDSA keygen and sign messages.
+//!
+//! If the request carries a client nonce (X-Merc-Nonce) and the server has an identity key,
+//! the response body is signed together with the nonce and route (X-Merc-Response-Sig), so
+//! that clients can detect responses forged by a proxy terminating TLS.
+
+use rocket::data::{self, Data, FromDataSimple};
+use rocket::http::{ContentType, MediaType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::{Outcome, Request, State};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::OpenApiFromData;
+use rocket_okapi::response::OpenApiResponder;
+use rocket_okapi::util::add_schema_response;
+use rocket_okapi::JsonSchema;
+use rocket_okapi::Result as OpenApiResult;
+use okapi::openapi3::{MediaType as OpenApiMediaType, RequestBody, Responses};
+use okapi::Map;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use shared_lib::state_chain::{sign_response, MAX_NONCE_LEN, NONCE_HEADER, RESPONSE_SIG_HEADER};
+use std::io::{Cursor, Read};
+
+use super::SCE;
+
+/// Body size limit used if no "json" data limit is configured
+const DEFAULT_LIMIT: u64 = 1 << 20;
+
+/// application/cbor media type
+pub fn cbor_media_type() -> MediaType {
+    MediaType::new("application", "cbor")
+}
+
+fn is_cbor(media_type: &MediaType) -> bool {
+    media_type.top() == "application" && media_type.sub() == "cbor"
+}
+
+/// True if the request body is CBOR encoded
+pub fn request_is_cbor(request: &Request) -> bool {
+    request.content_type().map_or(false, |ct| is_cbor(ct.media_type()))
+}
+
+/// True if the client prefers a CBOR encoded response
+pub fn accepts_cbor(request: &Request) -> bool {
+    request
+        .accept()
+        .map_or(false, |accept| is_cbor(accept.preferred().media_type()))
+}
+
+/// JSON or CBOR encoded protocol message
+#[derive(Debug)]
+pub struct Payload<T>(pub T);
+
+impl<T> Payload<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromDataSimple for Payload<T> {
+    type Error = String;
+
+    fn from_data(request: &Request, data: Data) -> data::Outcome<Self, String> {
+        let limit = request.limits().get("json").unwrap_or(DEFAULT_LIMIT);
+        let mut bytes = Vec::new();
+        if let Err(e) = data.open().take(limit).read_to_end(&mut bytes) {
+            return Outcome::Failure((Status::BadRequest, e.to_string()));
+        }
+        let value = match request_is_cbor(request) {
+            true => serde_cbor::from_slice(&bytes).map_err(|e| e.to_string()),
+            false => serde_json::from_slice(&bytes).map_err(|e| e.to_string()),
+        };
+        match value {
+            Ok(v) => Outcome::Success(Payload(v)),
+            Err(e) => {
+                warn!("Failed to decode request body: {}", e);
+                Outcome::Failure((Status::UnprocessableEntity, e))
+            }
+        }
+    }
+}
+
+/// Signature of the response body to a request carrying a client nonce. None if the request
+/// has no nonce or the server has no identity key.
+fn response_sig(request: &Request, body: &[u8]) -> Option<String> {
+    let nonce = request.headers().get_one(NONCE_HEADER)?;
+    if nonce.len() > MAX_NONCE_LEN {
+        return None;
+    }
+    let sc_entity = request.guard::<State<SCE>>().succeeded()?;
+    let identity_key = sc_entity.identity_key().ok()?;
+    match sign_response(&identity_key, nonce, &request.uri().to_string(), body) {
+        Ok(sig) => Some(sig),
+        Err(e) => {
+            error!("Failed to sign response: {}", e);
+            None
+        }
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r> for Payload<T> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let (content_type, body) = match accepts_cbor(request) {
+            true => (
+                ContentType(cbor_media_type()),
+                serde_cbor::to_vec(&self.0).map_err(|e| e.to_string()),
+            ),
+            false => (
+                ContentType::JSON,
+                serde_json::to_vec(&self.0).map_err(|e| e.to_string()),
+            ),
+        };
+        let body = match body {
+            Ok(b) => b,
+            Err(e) => {
+                error!("Failed to encode response: {}", e);
+                return Err(Status::InternalServerError);
+            }
+        };
+        let mut response = Response::build();
+        response.header(content_type);
+        if let Some(sig) = response_sig(request, &body) {
+            response.raw_header(RESPONSE_SIG_HEADER, sig);
+        }
+        response.sized_body(Cursor::new(body)).ok()
+    }
+}
+
+impl<'r, T: JsonSchema + DeserializeOwned> OpenApiFromData<'r> for Payload<T> {
+    fn request_body(gen: &mut OpenApiGenerator) -> OpenApiResult<RequestBody> {
+        let schema = gen.json_schema::<T>();
+        let mut content = Map::new();
+        for media_type in &["application/json", "application/cbor"] {
+            content.insert(
+                media_type.to_string(),
+                OpenApiMediaType {
+                    schema: Some(schema.clone()),
+                    ..OpenApiMediaType::default()
+                },
+            );
+        }
+        Ok(RequestBody {
+            content,
+            required: true,
+            ..RequestBody::default()
+        })
+    }
+}
+
+impl<T: JsonSchema + Serialize> OpenApiResponder<'static> for Payload<T> {
+    fn responses(gen: &mut OpenApiGenerator) -> OpenApiResult<Responses> {
+        let mut responses = Responses::default();
+        let schema = gen.json_schema::<T>();
+        add_schema_response(&mut responses, 200, "application/json", schema.clone())?;
+        add_schema_response(&mut responses, 200, "application/cbor", schema)?;
+        Ok(responses)
+    }
+}
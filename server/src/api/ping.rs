@@ -0,0 +1,31 @@
+//! # Ping API
+//!
+//! Rocket route handler for the Ping service.
+
+use super::SCE;
+use crate::protocol::ping::{HealthReport, HealthStatus, Ping};
+use crate::Result;
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::State;
+use rocket_contrib::json::Json;
+
+#[get("/ping")]
+pub fn ping(sc_entity: State<SCE>) -> Result<Status> {
+    // TODO: Add logic for health check
+    sc_entity.ping()?;
+    Ok(Status::Ok)
+}
+
+/// Server and dependency health for load balancers: 200 if healthy or degraded, 503 if
+/// unhealthy
+#[get("/health")]
+pub fn health(sc_entity: State<SCE>) -> Result<status::Custom<Json<HealthReport>>> {
+    let report = sc_entity.health()?;
+    let code = match report.status {
+        HealthStatus::Unhealthy => Status::ServiceUnavailable,
+        _ => Status::Ok,
+    };
+    Ok(status::Custom(code, Json(report)))
+}
@@ -0,0 +1,41 @@
+//! # Refresh API
+//!
+//! Rocket route handlers for the key share Refresh service.
+
+use super::SCE;
+use crate::protocol::refresh::Refresh;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Key share refresh initiation by owner: get x1
+#[post("/refresh/first", data = "<refresh_msg1>")]
+pub fn refresh_first(
+    sc_entity: State<SCE>,
+    refresh_msg1: Payload<RefreshMsg1>,
+) -> Result<Payload<TransferMsg2>> {
+    sc_entity.check_rate_fast("refresh")?;
+    match sc_entity.refresh_first(refresh_msg1.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Key share refresh completion by owner: key share update and deletion
+#[post("/refresh/second", data = "<refresh_msg2>")]
+pub fn refresh_second(
+    sc_entity: State<SCE>,
+    refresh_msg2: Payload<RefreshMsg2>,
+) -> Result<Payload<RefreshReply>> {
+    sc_entity.check_rate_fast("refresh")?;
+    match sc_entity.refresh_second(refresh_msg2.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
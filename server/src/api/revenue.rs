@@ -0,0 +1,24 @@
+//! # Revenue API
+//!
+//! Rocket route handlers for the revenue report.
+
+use super::SCE;
+use crate::protocol::revenue::Revenue;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Revenue report (admin)
+#[post("/admin/revenue", data = "<report_msg>")]
+pub fn revenue_report(sc_entity: State<SCE>, report_msg: Payload<RevenueReportMsg>) -> Result<Payload<RevenueReportAPI>> {
+    sc_entity.check_rate_slow("admin")?;
+    match sc_entity.revenue_report(report_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,27 @@
+//! # Rotate API
+//!
+//! Rocket route handlers for the proof key Rotate service.
+
+use super::SCE;
+use crate::protocol::rotate::Rotate;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use super::payload::Payload;
+use rocket::State;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Rotate the proof key of a statecoin to a new proof key of the owner, without a transfer
+#[post("/rotate", data = "<rotate_msg>")]
+pub fn rotate_proof_key(
+    sc_entity: State<SCE>,
+    rotate_msg: Payload<RotateMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_slow("rotate")?;
+    match sc_entity.rotate_proof_key(rotate_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
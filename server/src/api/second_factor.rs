@@ -0,0 +1,46 @@
+//! # Second Factor API
+//!
+//! Rocket route handlers for the second factor authorization of transfers and withdrawals.
+
+use super::SCE;
+use crate::error::SEError;
+use crate::protocol::second_factor::SecondFactor;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[openapi]
+/// # Register, replace or remove the second factor key of a statecoin
+#[post("/second-factor/register", data = "<second_factor_msg>")]
+pub fn second_factor_register(
+    sc_entity: State<SCE>,
+    second_factor_msg: Payload<SecondFactorMsg>,
+) -> Result<Payload<SecondFactorAPI>> {
+    sc_entity.check_rate_slow("second_factor")?;
+    match sc_entity.second_factor_register(second_factor_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the second factor key registration of a statechain
+#[get("/second-factor/<statechain_id>")]
+pub fn second_factor_get(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<SecondFactorAPI>> {
+    sc_entity.check_rate_fast("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.second_factor_get(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
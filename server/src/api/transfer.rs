@@ -0,0 +1,234 @@
+//! # Transfer API
+//!
+//! Rocket route handlers for the Transfer service.
+
+use super::SCE;
+use crate::error::SEError;
+use crate::protocol::inheritance::Inheritance;
+use crate::protocol::sale::Sale;
+use crate::protocol::transfer::Transfer;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[openapi]
+/// # Transfer initiation by sender: get x1 and new backup transaction
+#[post("/transfer/sender", data = "<transfer_msg1>")]
+pub fn transfer_sender(
+    sc_entity: State<SCE>,
+    transfer_msg1: Payload<TransferMsg1>,
+) -> Result<Payload<TransferMsg2>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.transfer_sender(transfer_msg1.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Retreive the current SE public key share for t2 encryption
+#[post("/transfer/pubkey", data = "<user_id>")]
+pub fn transfer_get_pubkey(
+    sc_entity: State<SCE>,
+    user_id: Payload<UserID>,
+) -> Result<Payload<S1PubKey>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.transfer_get_pubkey(user_id.id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Transfer completing by receiver: key share update and deletion
+#[post("/transfer/receiver", data = "<transfer_msg4>")]
+pub fn transfer_receiver(
+    sc_entity: State<SCE>,
+    transfer_msg4: Payload<TransferMsg4>,
+) -> Result<Payload<TransferMsg5>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.transfer_receiver(transfer_msg4.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Update stored transfer message (TransferMsg3)
+#[post("/transfer/update_msg", data = "<transfer_msg3>")]
+pub fn transfer_update_msg(
+    sc_entity: State<SCE>,
+    transfer_msg3: Payload<TransferMsg3>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.transfer_update_msg(transfer_msg3.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get stored transfer message (TransferMsg3)
+#[post("/transfer/get_msg", data = "<statechain_id>")]
+pub fn transfer_get_msg(
+    sc_entity: State<SCE>,
+    statechain_id: Payload<StatechainID>,
+) -> Result<Payload<TransferMsg3>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.transfer_get_msg(statechain_id.id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get stored transfer message (TransferMsg3)
+#[get("/transfer/get_msg_addr/<receive_addr>")]
+pub fn transfer_get_msg_addr(
+    sc_entity: State<SCE>,
+    receive_addr: String,
+) -> Result<Payload<Vec<TransferMsg3>>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.transfer_get_msg_addr(receive_addr) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Transfer rejection by receiver: return the statecoin to the sender
+#[post("/transfer/reject", data = "<transfer_reject_msg>")]
+pub fn transfer_reject(
+    sc_entity: State<SCE>,
+    transfer_reject_msg: Payload<TransferRejectMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.transfer_reject(transfer_reject_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the last transfer of a statechain rejected by its receiver
+#[get("/transfer/reject/<statechain_id>")]
+pub fn transfer_get_reject(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<Option<TransferRejectAPI>>> {
+    sc_entity.check_rate_fast("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.transfer_get_reject(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Open an escrowed sale: hold the next transfer until the preimage of a payment hash is revealed
+#[post("/transfer/sale/init", data = "<sale_init_msg>")]
+pub fn sale_init(
+    sc_entity: State<SCE>,
+    sale_init_msg: Payload<SaleInitMsg>,
+) -> Result<Payload<SaleAPI>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.sale_init(sale_init_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the escrowed sale of a statechain
+#[get("/transfer/sale/<statechain_id>")]
+pub fn sale_get(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<SaleAPI>> {
+    sc_entity.check_rate_fast("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.sale_get(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Reveal the preimage of an escrowed sale payment hash, finalizing the held transfer
+#[post("/transfer/sale/reveal", data = "<sale_reveal_msg>")]
+pub fn sale_reveal(
+    sc_entity: State<SCE>,
+    sale_reveal_msg: Payload<SaleRevealMsg>,
+) -> Result<Payload<SaleAPI>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.sale_reveal(sale_reveal_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Designate the receiver of the pending transfer as beneficiary after a period of owner inactivity
+#[post("/transfer/inheritance/designate", data = "<inheritance_msg>")]
+pub fn inheritance_designate(
+    sc_entity: State<SCE>,
+    inheritance_msg: Payload<InheritanceMsg>,
+) -> Result<Payload<InheritanceAPI>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.inheritance_designate(inheritance_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Owner check in, restarting the inactivity period of a beneficiary designation
+#[post("/transfer/inheritance/checkin", data = "<owner_msg>")]
+pub fn inheritance_checkin(
+    sc_entity: State<SCE>,
+    owner_msg: Payload<InheritanceOwnerMsg>,
+) -> Result<Payload<InheritanceAPI>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.inheritance_checkin(owner_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Revoke the beneficiary designation of a statechain
+#[post("/transfer/inheritance/revoke", data = "<owner_msg>")]
+pub fn inheritance_revoke(
+    sc_entity: State<SCE>,
+    owner_msg: Payload<InheritanceOwnerMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("transfer")?;
+    match sc_entity.inheritance_revoke(owner_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the beneficiary designation of a statechain
+#[get("/transfer/inheritance/<statechain_id>")]
+pub fn inheritance_get(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<InheritanceAPI>> {
+    sc_entity.check_rate_fast("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.inheritance_get(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,47 @@
+//! # Transfer Batch API
+//!
+//! Rocket route handlers for the BatchTransfer service.
+
+use super::SCE;
+use crate::protocol::transfer_batch::BatchTransfer;
+use crate::protocol::util::RateLimiter;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Initiate the batch transfer protocol: provide statechain signatures
+#[post(
+    "/transfer/batch/init",
+    data = "<transfer_batch_init_msg>"
+)]
+pub fn transfer_batch_init(
+    sc_entity: State<SCE>,
+    transfer_batch_init_msg: Payload<TransferBatchInitMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("transfer_batch")?;
+    match sc_entity.transfer_batch_init(transfer_batch_init_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Complete Batch transfer: reveal transfer nonce
+#[post(
+    "/transfer/batch/reveal",
+    data = "<transfer_reveal_nonce>"
+)]
+pub fn transfer_reveal_nonce(
+    sc_entity: State<SCE>,
+    transfer_reveal_nonce: Payload<TransferRevealNonce>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("transfer_batch")?;
+    match sc_entity.transfer_reveal_nonce(transfer_reveal_nonce.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,385 @@
+//! # Util API
+//!
+//! Rocket route handlers for the Utilities and Storage service.
+
+use super::SCE;
+use crate::error::SEError;
+use crate::protocol::api_key::ApiKeys;
+use crate::protocol::punishment::Punishment;
+use crate::protocol::util::{RateLimiter, Utilities};
+use crate::storage::Storage;
+use crate::Result;
+use shared_lib::{smt_proof::EncodedProof, structs::*, Root};
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[openapi]
+/// # Get statechain entity operating information
+#[get("/info/fee")]
+pub fn get_fees(sc_entity: State<SCE>) -> Result<Payload<StateEntityFeeInfoAPI>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.get_fees() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get statechain entity protocol parameters
+#[get("/info/params")]
+pub fn get_params(sc_entity: State<SCE>) -> Result<Payload<StateEntityParamsAPI>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.get_params() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the current statecoin amount histogram
+#[get("/info/coins")]
+pub fn get_coin_info(sc_entity: State<SCE>) -> Result<Payload<CoinValueInfo>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.get_coin_info() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get current statechain information for specified statechain ID
+#[get("/info/statechain/<statechain_id>?<api_key>")]
+pub fn get_statechain(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+    api_key: Option<String>,
+) -> Result<Payload<StateChainDataAPI>> {
+    sc_entity.check_rate_info(&api_key)?;
+    match sc_entity.get_statechain_data_api(Uuid::from_str(&statechain_id).unwrap()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get current statecoin (statechain tip) information for specified statechain ID
+#[get("/info/statecoin/<statechain_id>?<api_key>")]
+pub fn get_statecoin(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+    api_key: Option<String>,
+) -> Result<Payload<StateCoinDataAPI>> {
+    sc_entity.check_rate_info(&api_key)?;
+    match sc_entity.get_statecoin_data_api(Uuid::from_str(&statechain_id).unwrap()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get current statechain information for specified statechain ID
+#[get("/info/owner/<statechain_id>?<api_key>")]
+pub fn get_owner_id(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+    api_key: Option<String>,
+) -> Result<Payload<OwnerID>> {
+    sc_entity.check_rate_info(&api_key)?;
+    match sc_entity.get_owner_id(Uuid::from_str(&statechain_id).unwrap()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the current Sparse Merkle Tree commitment root
+#[get("/info/root?<api_key>")]
+pub fn get_smt_root(sc_entity: State<SCE>, api_key: Option<String>) -> Result<Payload<Option<Root>>> {
+    sc_entity.check_rate_info(&api_key)?;
+    match sc_entity.get_smt_root() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the Merkle path proof for a specified statechain (TxID) and root
+#[post("/info/proof?<api_key>", data = "<smt_proof_msg>")]
+pub fn get_smt_proof(
+    sc_entity: State<SCE>,
+    smt_proof_msg: Payload<SmtProofMsgAPI>,
+    api_key: Option<String>,
+) -> Result<Payload<Option<EncodedProof>>> {
+    sc_entity.check_rate_info(&api_key)?;
+    let smt_proof_msg = smt_proof_msg.into_inner();
+    let format = smt_proof_msg.proof_format;
+    match sc_entity.get_smt_proof(smt_proof_msg) {
+        Ok(res) => return Ok(Payload(res.map(|proof| EncodedProof::new(proof, format)))),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the Merkle path proofs for a list of statechains (TxIDs) against the current root
+#[post("/info/proofs?<api_key>", data = "<smt_proofs_msg>")]
+pub fn get_smt_proofs(
+    sc_entity: State<SCE>,
+    smt_proofs_msg: Payload<SmtProofsMsgAPI>,
+    api_key: Option<String>,
+) -> Result<Payload<SmtProofsAPI>> {
+    sc_entity.check_rate_info(&api_key)?;
+    match sc_entity.get_smt_proofs(smt_proofs_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get statechains modified since a checkpoint (root ID or timestamp)
+#[get("/info/updates?<since>&<api_key>")]
+pub fn get_statechain_updates(
+    sc_entity: State<SCE>,
+    since: String,
+    api_key: Option<String>,
+) -> Result<Payload<StateChainUpdatesAPI>> {
+    sc_entity.check_rate_info(&api_key)?;
+    match sc_entity.get_statechain_updates(&since) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get transfer finalize data for specified statechain ID
+#[get("/info/sc-transfer-finalize-data/<statechain_id>")]
+pub fn get_sc_transfer_finalize_data(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<TransferFinalizeData>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.get_sc_transfer_finalize_data(Uuid::from_str(&statechain_id).unwrap()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get batch transfer status and statecoin IDs for specified batch ID
+#[get("/info/transfer-batch/<batch_id>")]
+pub fn get_transfer_batch_status(
+    sc_entity: State<SCE>,
+    batch_id: String,
+) -> Result<Payload<TransferBatchDataAPI>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.get_transfer_batch_status(Uuid::from_str(&batch_id).unwrap()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Recover statechain and backup transaction for proof key
+#[post("/info/recover", data = "<request_recovery_data>")]
+pub fn get_recovery_data(
+    sc_entity: State<SCE>,
+    request_recovery_data: Payload<Vec<RecoveryRequest>>,
+) -> Result<Payload<Vec<RecoveryDataMsg>>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.get_recovery_data(request_recovery_data.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Submit transaction details to the server in preparation for 2P-ECDSA signing
+#[post("/prepare-sign", data = "<prepare_sign_msg>")]
+pub fn prepare_sign_tx(
+    sc_entity: State<SCE>,
+    prepare_sign_msg: Payload<PrepareSignTxMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.prepare_sign_tx(prepare_sign_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Submit a signed CPFP child tx to bump the fee of a statecoin backup tx
+#[post("/info/backup/cpfp", data = "<cpfp_msg>")]
+pub fn submit_backup_cpfp(
+    sc_entity: State<SCE>,
+    cpfp_msg: Payload<BackupCPFPMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.submit_backup_cpfp(cpfp_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the full history of a statechain as a state entity signed JSON document
+#[get("/info/statechain/<statechain_id>/export")]
+pub fn get_statechain_export(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<SignedStateChainExport>> {
+    sc_entity.check_rate_slow("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.get_statechain_export(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the state entity signed receipts for the key shares deleted after each transfer of a statechain
+#[get("/info/statechain/<statechain_id>/deletion-proofs")]
+pub fn get_deletion_receipts(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<Vec<SignedDeletionReceipt>>> {
+    sc_entity.check_rate_slow("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.get_deletion_receipts(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the state entity signed receipts of the completed transfers of a statechain
+#[get("/info/statechain/<statechain_id>/transfer-receipts")]
+pub fn get_transfer_receipts(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<Vec<SignedTransferReceipt>>> {
+    sc_entity.check_rate_slow("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.get_transfer_receipts(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the punishment standing of a statecoin and its owner proof key
+#[get("/info/standing/<statechain_id>")]
+pub fn get_punishment_standing(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<PunishmentStandingAPI>> {
+    sc_entity.check_rate_fast("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.get_punishment_standing(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get a proof of reserves report for a set of statecoins signed over a verifier nonce
+#[post("/info/reserves", data = "<reserve_proof_msg>")]
+pub fn get_reserve_proof(
+    sc_entity: State<SCE>,
+    reserve_proof_msg: Payload<ReserveProofMsg>,
+) -> Result<Payload<SignedReserveProofReport>> {
+    sc_entity.check_rate_slow("info")?;
+    match sc_entity.get_reserve_proof(reserve_proof_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get a single-use challenge to sign with each proof key for /info/usersessions
+#[get("/info/usersessions/challenge")]
+pub fn get_user_sessions_challenge(sc_entity: State<SCE>) -> Result<Payload<UserSessionsChallenge>> {
+    sc_entity.check_rate_slow("info")?;
+    match sc_entity.get_user_sessions_challenge() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the user sessions held for a set of proof keys, authenticated by proof key signatures
+#[post("/info/usersessions", data = "<user_sessions_msg>")]
+pub fn get_user_sessions(
+    sc_entity: State<SCE>,
+    user_sessions_msg: Payload<UserSessionsRequest>,
+) -> Result<Payload<UserSessionsAPI>> {
+    sc_entity.check_rate_slow("info")?;
+    match sc_entity.get_user_sessions(user_sessions_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get a single-use nonce for the owner of a statecoin to sign with its current proof key
+#[get("/info/ownership-challenge")]
+pub fn get_ownership_challenge(sc_entity: State<SCE>) -> Result<Payload<OwnershipChallenge>> {
+    sc_entity.check_rate_slow("info")?;
+    match sc_entity.get_ownership_challenge() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Prove ownership of a statecoin with a proof key signature over an ownership challenge nonce
+#[post("/info/ownership-challenge", data = "<ownership_proof_msg>")]
+pub fn prove_ownership(
+    sc_entity: State<SCE>,
+    ownership_proof_msg: Payload<OwnershipProofMsg>,
+) -> Result<Payload<SignedOwnershipConfirmation>> {
+    sc_entity.check_rate_slow("info")?;
+    match sc_entity.prove_ownership(ownership_proof_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the build manifest of the server, signed by the server identity key
+#[get("/info/build")]
+pub fn get_build_manifest(sc_entity: State<SCE>) -> Result<Payload<SignedBuildManifest>> {
+    sc_entity.check_rate_fast("info")?;
+    match sc_entity.get_build_manifest() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Reset databases and in-RAM data if in testing mode
+#[get("/test/reset-db")]
+pub fn reset_test_dbs(sc_entity: State<SCE>) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("reset-db")?;
+    match sc_entity.reset_test_dbs() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Reset databases and in-RAM data if in testing mode
+#[get("/test/reset-inram-data")]
+pub fn reset_inram_data(sc_entity: State<SCE>) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("reset-inram-data")?;
+    match sc_entity.reset_inram_data() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
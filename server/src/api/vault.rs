@@ -0,0 +1,60 @@
+//! # Vault API
+//!
+//! Rocket route handlers for the vault mode velocity controls.
+
+use super::SCE;
+use crate::error::SEError;
+use crate::protocol::util::RateLimiter;
+use crate::protocol::vault::Vault;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[openapi]
+/// # Set the vault delay of a statecoin, or disable vault mode with a delay of 0
+#[post("/vault/set", data = "<vault_msg>")]
+pub fn vault_set(
+    sc_entity: State<SCE>,
+    vault_msg: Payload<VaultMsg>,
+) -> Result<Payload<VaultAPI>> {
+    sc_entity.check_rate_slow("vault")?;
+    match sc_entity.vault_set(vault_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Cancel the transfer, withdrawal or migration in progress of a statecoin in vault mode
+#[post("/vault/cancel", data = "<cancel_msg>")]
+pub fn vault_cancel(
+    sc_entity: State<SCE>,
+    cancel_msg: Payload<VaultCancelMsg>,
+) -> Result<Payload<VaultAPI>> {
+    sc_entity.check_rate_fast("vault")?;
+    match sc_entity.vault_cancel(cancel_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Get the vault policy of a statechain
+#[get("/vault/<statechain_id>")]
+pub fn vault_get(
+    sc_entity: State<SCE>,
+    statechain_id: String,
+) -> Result<Payload<VaultAPI>> {
+    sc_entity.check_rate_fast("info")?;
+    let statechain_id = Uuid::from_str(&statechain_id)
+        .map_err(|e| SEError::Generic(format!("Invalid statechain id: {}", e)))?;
+    match sc_entity.vault_get(statechain_id) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,53 @@
+//! # Watchtower API
+//!
+//! Rocket route handlers for the Watchtower service: registration of encrypted backup txs
+//! broadcast on behalf of their owners.
+
+use super::SCE;
+use crate::protocol::util::RateLimiter;
+use crate::protocol::watchtower::Watchtower;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Get the public key that registered backup txs are encrypted to
+#[get("/watchtower/key")]
+pub fn watchtower_get_key(sc_entity: State<SCE>) -> Result<Payload<WatchtowerKeyAPI>> {
+    sc_entity.check_rate_fast("watchtower")?;
+    match sc_entity.watchtower_get_key() {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Create or update the registration of an encrypted backup tx
+#[post("/watchtower/register", data = "<register_msg>")]
+pub fn watchtower_register(
+    sc_entity: State<SCE>,
+    register_msg: Payload<WatchtowerRegisterMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_slow("watchtower")?;
+    match sc_entity.watchtower_register(register_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Revoke the registration of a backup tx
+#[post("/watchtower/revoke", data = "<revoke_msg>")]
+pub fn watchtower_revoke(
+    sc_entity: State<SCE>,
+    revoke_msg: Payload<WatchtowerRevokeMsg>,
+) -> Result<Payload<()>> {
+    sc_entity.check_rate_slow("watchtower")?;
+    match sc_entity.watchtower_revoke(revoke_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,53 @@
+//! # Withdraw API
+//!
+//! Rocket route handlers for the Withdraw service.
+
+use super::SCE;
+use crate::protocol::accelerate::Accelerate;
+use crate::protocol::util::RateLimiter;
+use crate::protocol::withdraw::Withdraw;
+use crate::Result;
+use shared_lib::structs::*;
+
+use rocket::State;
+use super::payload::Payload;
+use rocket_okapi::openapi;
+
+#[openapi]
+/// # Initiate the withdrawal process: provide signed statechain
+#[post("/withdraw/init", data = "<withdraw_msg1>")]
+pub fn withdraw_init(sc_entity: State<SCE>, withdraw_msg1: Payload<WithdrawMsg1>) -> Result<Payload<()>> {
+    sc_entity.check_rate_fast("withdraw")?;
+    match sc_entity.withdraw_init(withdraw_msg1.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Complete the withdrawal process: confirm withdrawal transaction
+#[post("/withdraw/confirm", data = "<withdraw_msg2>")]
+pub fn withdraw_confirm(
+    sc_entity: State<SCE>,
+    withdraw_msg2: Payload<WithdrawMsg2>,
+) -> Result<Payload<Vec<Vec<Vec<u8>>>>> {
+    sc_entity.check_rate_fast("withdraw")?;
+    match sc_entity.withdraw_confirm(withdraw_msg2.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
+
+#[openapi]
+/// # Request acceleration of a withdraw transaction stuck in the mempool by CPFP of its fee output
+#[post("/withdraw/accelerate", data = "<withdraw_accelerate_msg>")]
+pub fn withdraw_accelerate(
+    sc_entity: State<SCE>,
+    withdraw_accelerate_msg: Payload<WithdrawAccelerateMsg>,
+) -> Result<Payload<WithdrawAccelerateAPI>> {
+    sc_entity.check_rate_slow("withdraw")?;
+    match sc_entity.withdraw_accelerate(withdraw_accelerate_msg.into_inner()) {
+        Ok(res) => return Ok(Payload(res)),
+        Err(e) => return Err(e),
+    }
+}
@@ -0,0 +1,41 @@
+//! Build info
+//!
+//! Build manifest of the server binary, embedded at compile time by build.rs. In release build
+//! mode (feature "release") the build fails unless the manifest identifies the source exactly.
+
+use shared_lib::structs::BuildManifest;
+
+/// Build manifest of this binary
+pub fn build_manifest() -> BuildManifest {
+    BuildManifest {
+        version: String::from(env!("CARGO_PKG_VERSION")),
+        git_commit: String::from(env!("MERCURY_BUILD_GIT_COMMIT")),
+        git_dirty: env!("MERCURY_BUILD_GIT_DIRTY") == "true",
+        cargo_lock_hash: String::from(env!("MERCURY_BUILD_CARGO_LOCK_HASH")),
+        features: env!("MERCURY_BUILD_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(String::from)
+            .collect(),
+        rustc_version: String::from(env!("MERCURY_BUILD_RUSTC_VERSION")),
+        profile: String::from(env!("MERCURY_BUILD_PROFILE")),
+        release: env!("MERCURY_BUILD_RELEASE") == "true",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_manifest() {
+        let manifest = build_manifest();
+        assert_eq!(manifest.version, env!("CARGO_PKG_VERSION"));
+        assert!(!manifest.git_commit.is_empty());
+        assert!(!manifest.rustc_version.is_empty());
+        let mut features = manifest.features.clone();
+        features.sort();
+        assert_eq!(features, manifest.features);
+        assert_eq!(manifest.release, manifest.features.contains(&String::from("release")));
+    }
+}
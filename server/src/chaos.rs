@@ -0,0 +1,136 @@
+//! # Chaos
+//!
+//! Fault injection for soak testing the retry and resume logic of clients (deposits, transfers
+//! and swaps) against a misbehaving server.
+//!
+//! When the server is built with the chaos feature, DB writes fail, responses are delayed and
+//! keygen sessions are dropped at the rates set in the chaos config. Without the feature no
+//! fault is ever injected, whatever the config.
+
+use crate::config::ChaosConfig;
+use crate::error::{DBErrorType, SEError};
+use crate::Result;
+
+use rand::Rng;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Request, Response};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Injects faults at the rates of a chaos config. Also the fairing delaying responses.
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjector {
+    config: ChaosConfig,
+}
+
+impl FaultInjector {
+    pub fn new(config: &ChaosConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// True if faults are injected: the chaos feature is enabled and a fault rate is set
+    pub fn enabled(&self) -> bool {
+        cfg!(feature = "chaos") && self.config.is_set()
+    }
+
+    /// True with probability 'rate'
+    fn roll(rate: f64) -> bool {
+        cfg!(feature = "chaos") && rate > 0.0 && rand::thread_rng().gen::<f64>() < rate
+    }
+
+    /// Fail a DB write at the configured rate
+    pub fn db_write(&self) -> Result<()> {
+        if Self::roll(self.config.db_write_failure_rate) {
+            warn!("CHAOS: Injected DB write failure");
+            return Err(SEError::DBError(
+                DBErrorType::ConnectionFailed,
+                String::from("Fault injection: DB write failed"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Drop a keygen session at the configured rate. The client must retry the keygen message.
+    pub fn keygen(&self, user_id: &Uuid) -> Result<()> {
+        if Self::roll(self.config.keygen_drop_rate) {
+            warn!("CHAOS: Injected keygen session drop. Shared Key ID: {}", user_id);
+            return Err(SEError::Generic(format!(
+                "Fault injection: keygen session dropped. Shared Key ID: {}",
+                user_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Delay of a response at the configured rate: uniform up to response_delay_ms
+    pub fn response_delay(&self) -> Option<Duration> {
+        if Self::roll(self.config.response_delay_rate) {
+            let ms = rand::thread_rng().gen_range(0..=self.config.response_delay_ms);
+            return Some(Duration::from_millis(ms));
+        }
+        None
+    }
+}
+
+impl Fairing for FaultInjector {
+    fn info(&self) -> Info {
+        Info {
+            name: "Fault injection",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, _: &mut Response) {
+        if let Some(delay) = self.response_delay() {
+            debug!("CHAOS: Delaying response to {} by {:?}", request.uri(), delay);
+            thread::sleep(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rate: f64) -> ChaosConfig {
+        ChaosConfig {
+            db_write_failure_rate: rate,
+            response_delay_rate: rate,
+            response_delay_ms: 10,
+            keygen_drop_rate: rate,
+        }
+    }
+
+    #[test]
+    fn test_fault_injector_never() {
+        let faults = FaultInjector::new(&config(0.0));
+        assert!(!faults.enabled());
+        for _ in 0..100 {
+            assert!(faults.db_write().is_ok());
+            assert!(faults.keygen(&Uuid::new_v4()).is_ok());
+            assert!(faults.response_delay().is_none());
+        }
+        assert!(FaultInjector::default().db_write().is_ok());
+    }
+
+    #[test]
+    fn test_fault_injector_always() {
+        // Faults are only injected if the server is built with the chaos feature
+        let chaos = cfg!(feature = "chaos");
+        let faults = FaultInjector::new(&config(1.0));
+        assert_eq!(faults.enabled(), chaos);
+        match faults.db_write() {
+            Err(SEError::DBError(DBErrorType::ConnectionFailed, _)) => assert!(chaos),
+            Ok(()) => assert!(!chaos),
+            Err(e) => assert!(false, "unexpected error {}", e),
+        }
+        assert_eq!(faults.keygen(&Uuid::new_v4()).is_err(), chaos);
+        match faults.response_delay() {
+            Some(d) => assert!(chaos && d <= Duration::from_millis(10)),
+            None => assert!(!chaos),
+        }
+    }
+}
@@ -7,6 +7,8 @@ use super::Result;
 use config_rs::{Config as ConfigRs, Environment, File};
 use serde::{Deserialize, Serialize};
 use shared_lib::mainstay::MainstayConfig;
+use shared_lib::structs::TimelockMode;
+use shared_lib::swap_data::SwapFeePayment;
 use std::env;
 use std::str::FromStr;
 use std::vec::Vec;
@@ -18,9 +20,32 @@ use std::num::NonZeroU32;
 pub enum Mode {
     Both,
     Core,
-    Conductor
+    Conductor,
+    Watchtower
 }
 
+/// Unit of the batch transfer lifetime and punishment durations
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DurationUnit {
+    /// Wall-clock seconds
+    Seconds,
+    /// Blocks of the chain the backup txs are timelocked on
+    Blocks,
+}
+
+/// Response of the bitcoind watcher to a spend of the funding output of a live statecoin by a
+/// tx other than its current backup tx. Fee bumping the backup tx beyond its submitted CPFP
+/// child and cooperative responses with the current owner are not supported.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendResponse {
+    /// Flag the statechain only
+    None,
+    /// Also broadcast the current backup tx, and its CPFP child if one was submitted, retried
+    /// each scan until the funding output is spent in a block
+    Backup,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ConductorConfig {
@@ -29,7 +54,7 @@ pub struct ConductorConfig {
     //Time in seconds that a UTXO registered for a swap must be polled for 
     //in order to remain in a swap group
     pub utxo_timeout: u32, 
-    /// Length of punishment for unresponsivve/misbehaving batch-transfer utxo
+    /// Length of punishment for unresponsivve/misbehaving batch-transfer utxo, in duration_unit
     pub punishment_duration: u64,
     /// The time waited after a group is started until the swap begins
     pub daily_epochs: u32,
@@ -39,6 +64,10 @@ pub struct ConductorConfig {
     pub swap_wallet_version: String,
     /// Allowable swap group amounts (in satoshis)
     pub permitted_groups: String,
+    /// Swap fee (satoshis) per participant. 0 for free swaps.
+    pub swap_fee: u64,
+    /// How the swap fee is paid
+    pub swap_fee_payment: SwapFeePayment,
 }
 
 impl Default for ConductorConfig {
@@ -51,6 +80,322 @@ impl Default for ConductorConfig {
             max_swap_size: 5,
             swap_wallet_version: "0.6.0".to_string(),
             permitted_groups: "100000,500000,1000000,5000000,10000000,50000000,100000000".to_string(),
+            swap_fee: 0,
+            swap_fee_payment: SwapFeePayment::Deduction,
+        }
+    }
+}
+
+impl ConductorConfig {
+    /// Allowable swap group amounts (in satoshis)
+    pub fn permitted_groups(&self) -> Vec<u64> {
+        self.permitted_groups
+            .split(",")
+            .map(|group| group.parse().unwrap())
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Punishment policy config
+pub struct PunishmentConfig {
+    /// Comma separated punishment durations (duration_unit) for the first, second, ... batch transfer
+    /// failure. The last duration applies to all further failures. If empty,
+    /// conductor.punishment_duration is used for every failure.
+    pub batch_schedule: String,
+    /// Comma separated punishment durations (duration_unit) for repeat swap failures. If empty,
+    /// conductor.punishment_duration is used for every failure.
+    pub swap_schedule: String,
+    /// Time in seconds after the last failure after which the failure count of a statechain or
+    /// proof key is reset
+    pub decay: u64,
+    /// Interval in seconds between removals of expired punishment records. 0 disables cleanup.
+    pub cleanup_interval: u64,
+}
+
+impl Default for PunishmentConfig {
+    fn default() -> Self {
+        Self {
+            batch_schedule: String::from(""),
+            swap_schedule: String::from(""),
+            decay: 604800,
+            cleanup_interval: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Statechain archival config
+pub struct ArchiveConfig {
+    /// Interval in seconds between archival runs. 0 disables archival.
+    pub interval: u64,
+    /// Time in seconds after its last update after which a finished (withdrawn or expired)
+    /// statechain is moved to the archive
+    pub retention: u64,
+    /// Time in seconds after archival after which archived statechains are deleted.
+    /// 0 keeps archived statechains indefinitely.
+    pub purge_after: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            interval: 3600,
+            retention: 2592000, // 30 days
+            purge_after: 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Orphaned user session cleanup config. Deposit sessions that stay in a state longer than the
+/// time to live of the state are removed with their keygen data.
+pub struct SessionCleanupConfig {
+    /// Interval in seconds between cleanup runs. 0 disables cleanup.
+    pub interval: u64,
+    /// Time to live in seconds of sessions created by deposit/init awaiting key generation.
+    /// 0: never removed.
+    pub deposit_init_ttl: u64,
+    /// Time to live in seconds of deposit sessions awaiting co-signing of the backup tx.
+    /// 0: never removed.
+    pub deposit_keygen_ttl: u64,
+    /// Time to live in seconds of deposit sessions awaiting deposit/confirm. The deposit may
+    /// have been funded: once its session is removed the owner can only recover it with the
+    /// backup tx. 0: never removed.
+    pub deposit_signed_ttl: u64,
+}
+
+impl Default for SessionCleanupConfig {
+    fn default() -> Self {
+        Self {
+            interval: 3600,
+            deposit_init_ttl: 86400,   // 1 day
+            deposit_keygen_ttl: 86400, // 1 day
+            deposit_signed_ttl: 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Health probe config
+pub struct HealthConfig {
+    /// Time in seconds a health report is cached for
+    pub cache_ttl: u64,
+    /// Number of SMT roots not yet attested to mainstay above which the server is degraded
+    pub mainstay_max_lag: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            cache_ttl: 5,
+            mainstay_max_lag: 100,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Withdraw tx acceleration policy config. Withdraw txs are accelerated by spending their fee
+/// output in a child tx signed by the bitcoind wallet, which must hold the fee address keys.
+pub struct AccelerateConfig {
+    /// Accept acceleration requests. Requires bitcoind.
+    pub enabled: bool,
+    /// Time in seconds a withdraw tx must have been in the mempool before it is accelerated
+    pub min_wait: u64,
+    /// Maximum fee rate (sat/vbyte) a withdraw tx is accelerated to
+    pub max_fee_rate: u64,
+    /// Maximum share (percent) of the fee output value spent on the child tx fee
+    pub max_fee_share: u64,
+}
+
+impl Default for AccelerateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_wait: 3600,
+            max_fee_rate: 100,
+            max_fee_share: 50,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Per-user deposit limits. Users are identified by the owner proof key of their statecoins.
+/// Limits of 0 are disabled.
+pub struct LimitsConfig {
+    /// Maximum number of unspent statecoins owned by a proof key
+    pub max_coins_per_proof_key: u64,
+    /// Maximum aggregate value (satoshis) of the unspent statecoins owned by a proof key
+    pub max_value_per_proof_key: u64,
+    /// Maximum value (satoshis) of a single statecoin
+    pub max_coin_value: u64,
+    /// Comma separated list of proof keys exempt from the limits
+    pub exempt: String,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_coins_per_proof_key: 0,
+            max_value_per_proof_key: 0,
+            max_coin_value: 0,
+            exempt: String::from(""),
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// Whether proof_key is on the exempt list
+    pub fn is_exempt(&self, proof_key: &String) -> bool {
+        self.exempt
+            .split(",")
+            .map(|k| k.trim())
+            .any(|k| !k.is_empty() && k == proof_key)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Statechain length and size limits. The oldest states of a chain are pruned into hash-linked
+/// archive segments when the stored chain grows past prune_length states or prune_size bytes.
+/// Limits of 0 are disabled.
+pub struct ChainLimitsConfig {
+    /// Maximum number of states in a chain, including pruned states. A transfer that would
+    /// exceed it is refused.
+    pub max_length: u64,
+    /// Maximum serialized size in bytes of a stored chain after pruning. A transfer that would
+    /// exceed it is refused.
+    pub max_size: u64,
+    /// Number of stored states above which a chain is pruned
+    pub prune_length: u64,
+    /// Serialized size in bytes of a stored chain above which it is pruned
+    pub prune_size: u64,
+    /// Number of most recent states kept in a stored chain when it is pruned
+    pub prune_keep: u64,
+}
+
+impl Default for ChainLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 0,
+            max_size: 0,
+            prune_length: 100,
+            prune_size: 0,
+            prune_keep: 10,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// SMT batch insertion config
+pub struct SmtBatchConfig {
+    /// Time in milliseconds SMT updates are accumulated for before they are inserted together
+    /// and the root is updated. 0 inserts each update and updates the root immediately.
+    pub interval: u64,
+    /// Maximum number of updates accumulated before the batch is inserted early
+    pub max_size: usize,
+}
+
+impl Default for SmtBatchConfig {
+    fn default() -> Self {
+        Self {
+            interval: 0,
+            max_size: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// SMT root update policy config. Unless roots are updated for every SMT update, updates are
+/// queued and published together in a new root.
+pub struct RootUpdateConfig {
+    /// Number of queued SMT updates at which a new root is published. 0: no limit.
+    pub max_updates: usize,
+    /// Time in seconds after which queued SMT updates are published. 0: no limit.
+    pub interval: u64,
+}
+
+impl Default for RootUpdateConfig {
+    fn default() -> Self {
+        Self {
+            max_updates: 1,
+            interval: 0,
+        }
+    }
+}
+
+impl RootUpdateConfig {
+    /// True if a root is published for every SMT update
+    pub fn is_immediate(&self) -> bool {
+        self.max_updates == 1 || (self.max_updates == 0 && self.interval == 0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// Fault injection config. Faults are only injected by servers built with the chaos feature.
+pub struct ChaosConfig {
+    /// Probability that a DB write fails
+    pub db_write_failure_rate: f64,
+    /// Probability that a response is delayed
+    pub response_delay_rate: f64,
+    /// Maximum response delay in milliseconds
+    pub response_delay_ms: u64,
+    /// Probability that a keygen message fails as if the keygen session had been dropped
+    pub keygen_drop_rate: f64,
+}
+
+impl ChaosConfig {
+    /// True if any fault rate is set
+    pub fn is_set(&self) -> bool {
+        self.db_write_failure_rate > 0.0
+            || self.response_delay_rate > 0.0
+            || self.keygen_drop_rate > 0.0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Watchtower config. The watchtower broadcasts backup txs registered by their owners once their
+/// broadcast height is reached. Requires bitcoind.
+pub struct WatchtowerConfig {
+    /// Hex encoded secret key that registered backup txs are encrypted to. Registration is
+    /// disabled if not set.
+    pub key: Option<String>,
+    /// Maximum number of registrations per auth key. 0: no limit.
+    pub max_registrations: u64,
+}
+
+impl Default for WatchtowerConfig {
+    fn default() -> Self {
+        Self {
+            key: None,
+            max_registrations: 100,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// DB column encryption config. Sensitive values (key shares, transfer data and sig hashes) are
+/// encrypted with AES-256-GCM under a master key before they are written to the DB.
+pub struct DbEncryptionConfig {
+    /// Hex encoded 32 byte master key. Column encryption is disabled if neither key nor key_url
+    /// is set.
+    pub key: Option<String>,
+    /// URL of a key management service returning the hex encoded master key. Used if key is
+    /// not set.
+    pub key_url: Option<String>,
+    /// Id of the master key, stored with each value encrypted with it
+    pub key_id: u32,
+    /// Comma separated id:key pairs of previous master keys. Values encrypted with a previous
+    /// key can be read until they are re-encrypted with the current key.
+    pub previous_keys: String,
+}
+
+impl Default for DbEncryptionConfig {
+    fn default() -> Self {
+        Self {
+            key: None,
+            key_url: None,
+            key_id: 1,
+            previous_keys: String::from(""),
         }
     }
 }
@@ -97,6 +442,32 @@ impl Default for StorageConfig {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// DynamoDB storage config. If enabled, user sessions, statechains, ECDSA key shares, transfers,
+/// transfer batches and backup txs are stored in DynamoDB tables instead of Postgres. Only
+/// applies to servers built with the dynamodb feature.
+pub struct DynamoDbConfig {
+    /// Store the core tables in DynamoDB
+    pub enabled: bool,
+    /// AWS region name
+    pub region: String,
+    /// Endpoint URL overriding the region endpoint, e.g. for DynamoDB Local
+    pub endpoint: Option<String>,
+    /// Prefix of the DynamoDB table names
+    pub table_prefix: String,
+}
+
+impl Default for DynamoDbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            region: String::from("us-east-1"),
+            endpoint: None,
+            table_prefix: String::from("mercury_"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 /// Rocket specific config
 pub struct RocketConfig {
@@ -105,7 +476,12 @@ pub struct RocketConfig {
     /// Rocket address
     pub address: String,
     /// Rocket port
-    pub port: u16
+    pub port: u16,
+    /// Maximum request body size (bytes)
+    pub max_body_size: u64,
+    /// Per-route maximum request body sizes overriding max_body_size,
+    /// e.g. "/info/recover=65536,/ecdsa/keygen/second=2097152"
+    pub route_body_limits: String,
 }
 
 impl Default for RocketConfig {
@@ -113,15 +489,17 @@ impl Default for RocketConfig {
         RocketConfig {
             keep_alive: 100,
             address: "0.0.0.0".to_string(),
-            port: 8000
+            port: 8000,
+            max_body_size: 1048576,
+            route_body_limits: String::from(""),
         }
     }
 }
 
 /// Config struct storing all StataChain Entity config
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
-    /// Mode: "core", "conductor" or "both"
+    /// Mode: "core", "conductor", "both" or "watchtower"
     pub mode: Mode, 
     /// Log file location. If not present print to stdout
     pub log_file: String,
@@ -137,6 +515,9 @@ pub struct Config {
     pub lockheight_init: u32,
     /// Transfer nlocktime decrement
     pub lh_decrement: u32,
+    /// Backup tx timelock mode: "absolute" (nLockTime) or "relative" (nSequence).
+    /// In relative mode lockheight_init is the number of blocks after funding confirmation.
+    pub backup_timelock_mode: TimelockMode,
     /// Required confirmations for deposit
     pub required_confirmation: u32,
     /// Receive address for fee payments
@@ -145,12 +526,34 @@ pub struct Config {
     pub fee_deposit: u64,
     /// Withdraw fee (basis points)
     pub fee_withdraw: u64,
-    /// Time to allow batch transfer to take
+    /// Withdraw fee per statecoin withdrawn (satoshis), added to the basis point fee
+    pub fee_withdraw_input: u64,
+    /// Maximum number of statecoins withdrawn in one withdraw tx. 0: no limit.
+    pub max_withdraw_inputs: u64,
+    /// Fee quote validity period (seconds). A quote is valid for one to two periods.
+    pub fee_quote_validity: u64,
+    /// Whether deposits must acknowledge a valid fee quote
+    pub fee_ack_required: bool,
+    /// Maximum transaction fee (satoshis) of a transaction the server will co-sign
+    pub max_tx_fee: u64,
+    /// Minimum statecoin amount (satoshis) that can be deposited
+    pub min_deposit: u64,
+    /// Backup and withdraw tx outputs paying the owner less than this (satoshis) are dust
+    pub dust_limit: u64,
+    /// Maximum total value (satoshis) of unspent statecoins. 0 for no cap.
+    pub max_total_value: u64,
+    /// Whether deposits are paused at startup. Can be changed at runtime with the admin key.
+    pub deposits_paused: bool,
+    /// Time to allow batch transfer to take, in duration_unit
     pub batch_lifetime: u64,
+    /// Unit of batch_lifetime and the punishment durations: "seconds" or "blocks"
+    pub duration_unit: DurationUnit,
     /// Watch-only
     pub watch_only: bool,
     /// bitcoind node connecton
     pub bitcoind: String,
+    /// Response to a spend of a live statecoin funding output by another tx than its backup tx
+    pub spend_response: SpendResponse,
     /// VDF difficulty factor
     pub difficulty: u64,
     /// Storage config
@@ -173,6 +576,80 @@ pub struct Config {
     pub wallet_version: String,
     /// Server message for wallet users
     pub wallet_message: String,
+    /// Whether keygen zero-knowledge proofs (party 2 DLog proof, party 1 Paillier key correctness
+    /// and range proofs) must verify. Verification outcomes are recorded for every keygen session
+    /// regardless.
+    pub enforce_zk_proofs: bool,
+    /// Whether transfer, withdraw and migrate statechain signatures must be bound to the
+    /// statechain id and chain position. Legacy (unbound) signatures are accepted if false.
+    pub require_bound_sigs: bool,
+    /// Current epoch of the server keygen parameters. Shared keys generated in an earlier
+    /// epoch must be refreshed before they can be transferred.
+    pub key_epoch: u32,
+    /// Hex encoded secret key used to sign statechain exports. Exports are disabled if not set.
+    pub export_key: Option<String>,
+    /// Hex encoded server identity secret key, the static key of encrypted ECDSA channels and
+    /// the key responses, fee quotes, proof of reserves reports, ownership confirmations and
+    /// deletion and transfer receipts are signed with. Encrypted channels, response signatures,
+    /// proof of reserves, ownership confirmations and receipts are disabled, and fee quotes
+    /// unsigned, if not set.
+    pub identity_key: Option<String>,
+    /// Secret required to issue and revoke info API keys. API key administration is
+    /// disabled if not set.
+    pub admin_key: Option<String>,
+    /// Whether the info routes require an API key. If false, requests without a key share
+    /// the fast rate limit.
+    pub info_api_key_required: bool,
+    /// Maximum number of SMT inclusion proofs cached. 0 disables the cache.
+    pub proof_cache_size: usize,
+    /// Validity period (seconds) of the single-use challenges signed to list user sessions
+    pub user_sessions_challenge_ttl: u64,
+    /// Validity period (seconds) of the single-use nonces signed to prove statecoin ownership
+    pub ownership_challenge_ttl: u64,
+    /// Maximum lifetime (seconds) of an escrowed sale
+    pub sale_max_lifetime: u64,
+    /// Minimum inactivity period (seconds) of a beneficiary designation
+    pub inheritance_min_inactivity: u64,
+    /// Maximum inactivity period (seconds) of a beneficiary designation
+    pub inheritance_max_inactivity: u64,
+    /// Delay (seconds) before a change of second factor key not signed by the current second
+    /// factor takes effect
+    pub second_factor_delay: u64,
+    /// Maximum delay (seconds) of a vault policy
+    pub vault_max_delay: u64,
+    /// Address (host:port) of the gRPC interface. Requires the grpc feature. Disabled if not set.
+    pub grpc_address: Option<String>,
+    /// Hex encoded secret key used to receive statecoins migrated from other state entities.
+    /// Its public key is the migration public key. Migration import is disabled if not set.
+    pub migration_key: Option<String>,
+    /// Comma separated export public keys of the state entities statecoins may be migrated from
+    pub migration_trusted_keys: String,
+    /// Punishment policy config
+    pub punishment: PunishmentConfig,
+    /// Statechain archival config
+    pub archive: ArchiveConfig,
+    /// Orphaned user session cleanup config
+    pub session_cleanup: SessionCleanupConfig,
+    /// Health probe config
+    pub health: HealthConfig,
+    /// Withdraw tx acceleration policy config
+    pub accelerate: AccelerateConfig,
+    /// Per-user deposit limits config
+    pub limits: LimitsConfig,
+    /// Statechain length and size limits config
+    pub chain_limits: ChainLimitsConfig,
+    /// SMT batch insertion config
+    pub smt_batch: SmtBatchConfig,
+    /// SMT root update policy config
+    pub root_update: RootUpdateConfig,
+    /// Fault injection config
+    pub chaos: ChaosConfig,
+    /// Watchtower config
+    pub watchtower: WatchtowerConfig,
+    /// DB column encryption config
+    pub db_encryption: DbEncryptionConfig,
+    /// DynamoDB storage config
+    pub dynamodb: DynamoDbConfig,
 }
 
 impl Default for Config {
@@ -186,13 +663,25 @@ impl Default for Config {
             testing_mode: false,
             lockheight_init: 10000,
             lh_decrement: 100,
+            backup_timelock_mode: TimelockMode::Absolute,
             required_confirmation: 3,
             fee_address: String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x,bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x"),
             fee_deposit: 40,
             fee_withdraw: 40,
+            fee_withdraw_input: 0,
+            max_withdraw_inputs: 100,
+            fee_quote_validity: 3600, // 1 hour
+            fee_ack_required: false,
+            max_tx_fee: 1000000,
+            min_deposit: 100000,
+            dust_limit: 546,
+            max_total_value: 0,
+            deposits_paused: false,
             batch_lifetime: 3600,     // 1 hour
+            duration_unit: DurationUnit::Seconds,
             watch_only: false,
             bitcoind: String::from(""),
+            spend_response: SpendResponse::Backup,
             difficulty: 4,
             storage: StorageConfig::default(),
             mainstay: Some(MainstayConfig::default()),
@@ -204,6 +693,37 @@ impl Default for Config {
             deposit_pow: true,
             wallet_version: "0.6.0".to_string(),
             wallet_message: "".to_string(),
+            enforce_zk_proofs: true,
+            require_bound_sigs: false,
+            key_epoch: 0,
+            export_key: None,
+            identity_key: None,
+            admin_key: None,
+            info_api_key_required: false,
+            proof_cache_size: 10000,
+            user_sessions_challenge_ttl: 300, // 5 minutes
+            ownership_challenge_ttl: 300, // 5 minutes
+            sale_max_lifetime: 86400, // 1 day
+            inheritance_min_inactivity: 86400, // 1 day
+            inheritance_max_inactivity: 31536000, // 1 year
+            second_factor_delay: 172800, // 2 days
+            vault_max_delay: 2592000, // 30 days
+            grpc_address: None,
+            migration_key: None,
+            migration_trusted_keys: String::from(""),
+            punishment: PunishmentConfig::default(),
+            archive: ArchiveConfig::default(),
+            session_cleanup: SessionCleanupConfig::default(),
+            health: HealthConfig::default(),
+            accelerate: AccelerateConfig::default(),
+            limits: LimitsConfig::default(),
+            chain_limits: ChainLimitsConfig::default(),
+            smt_batch: SmtBatchConfig::default(),
+            root_update: RootUpdateConfig::default(),
+            chaos: ChaosConfig::default(),
+            watchtower: WatchtowerConfig::default(),
+            db_encryption: DbEncryptionConfig::default(),
+            dynamodb: DynamoDbConfig::default(),
         }
     }
 }
@@ -283,6 +803,12 @@ impl Config {
         if let Ok(v) = env::var("MERC_ROCKET_PORT") {
             let _ = conf_rs.set("rocket.port", v)?;
         }
+        if let Ok(v) = env::var("MERC_ROCKET_MAX_BODY_SIZE") {
+            let _ = conf_rs.set("rocket.max_body_size", v)?;
+        }
+        if let Ok(v) = env::var("MERC_ROCKET_ROUTE_BODY_LIMITS") {
+            let _ = conf_rs.set("rocket.route_body_limits", v)?;
+        }
 
         if let Ok(v) = env::var("MERC_UTXO_TIMEOUT") {
             let _ = conf_rs.set("conductor.utxo_timeout", v)?;
@@ -292,6 +818,158 @@ impl Config {
             let _ = conf_rs.set("conductor.group_timeout", v)?;
         }
 
+        if let Ok(v) = env::var("MERC_SWAP_FEE") {
+            let _ = conf_rs.set("conductor.swap_fee", v)?;
+        }
+        if let Ok(v) = env::var("MERC_SWAP_FEE_PAYMENT") {
+            let _ = conf_rs.set("conductor.swap_fee_payment", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_PUNISHMENT_BATCH_SCHEDULE") {
+            let _ = conf_rs.set("punishment.batch_schedule", v)?;
+        }
+        if let Ok(v) = env::var("MERC_PUNISHMENT_SWAP_SCHEDULE") {
+            let _ = conf_rs.set("punishment.swap_schedule", v)?;
+        }
+        if let Ok(v) = env::var("MERC_PUNISHMENT_DECAY") {
+            let _ = conf_rs.set("punishment.decay", v)?;
+        }
+        if let Ok(v) = env::var("MERC_PUNISHMENT_CLEANUP_INTERVAL") {
+            let _ = conf_rs.set("punishment.cleanup_interval", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_ARCHIVE_INTERVAL") {
+            let _ = conf_rs.set("archive.interval", v)?;
+        }
+        if let Ok(v) = env::var("MERC_ARCHIVE_RETENTION") {
+            let _ = conf_rs.set("archive.retention", v)?;
+        }
+        if let Ok(v) = env::var("MERC_ARCHIVE_PURGE_AFTER") {
+            let _ = conf_rs.set("archive.purge_after", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_SESSION_CLEANUP_INTERVAL") {
+            let _ = conf_rs.set("session_cleanup.interval", v)?;
+        }
+        if let Ok(v) = env::var("MERC_SESSION_CLEANUP_DEPOSIT_INIT_TTL") {
+            let _ = conf_rs.set("session_cleanup.deposit_init_ttl", v)?;
+        }
+        if let Ok(v) = env::var("MERC_SESSION_CLEANUP_DEPOSIT_KEYGEN_TTL") {
+            let _ = conf_rs.set("session_cleanup.deposit_keygen_ttl", v)?;
+        }
+        if let Ok(v) = env::var("MERC_SESSION_CLEANUP_DEPOSIT_SIGNED_TTL") {
+            let _ = conf_rs.set("session_cleanup.deposit_signed_ttl", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_HEALTH_CACHE_TTL") {
+            let _ = conf_rs.set("health.cache_ttl", v)?;
+        }
+        if let Ok(v) = env::var("MERC_HEALTH_MAINSTAY_MAX_LAG") {
+            let _ = conf_rs.set("health.mainstay_max_lag", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_ACCELERATE_ENABLED") {
+            let _ = conf_rs.set("accelerate.enabled", v)?;
+        }
+        if let Ok(v) = env::var("MERC_ACCELERATE_MIN_WAIT") {
+            let _ = conf_rs.set("accelerate.min_wait", v)?;
+        }
+        if let Ok(v) = env::var("MERC_ACCELERATE_MAX_FEE_RATE") {
+            let _ = conf_rs.set("accelerate.max_fee_rate", v)?;
+        }
+        if let Ok(v) = env::var("MERC_ACCELERATE_MAX_FEE_SHARE") {
+            let _ = conf_rs.set("accelerate.max_fee_share", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_LIMITS_MAX_COINS_PER_PROOF_KEY") {
+            let _ = conf_rs.set("limits.max_coins_per_proof_key", v)?;
+        }
+        if let Ok(v) = env::var("MERC_LIMITS_MAX_VALUE_PER_PROOF_KEY") {
+            let _ = conf_rs.set("limits.max_value_per_proof_key", v)?;
+        }
+        if let Ok(v) = env::var("MERC_LIMITS_MAX_COIN_VALUE") {
+            let _ = conf_rs.set("limits.max_coin_value", v)?;
+        }
+        if let Ok(v) = env::var("MERC_LIMITS_EXEMPT") {
+            let _ = conf_rs.set("limits.exempt", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_CHAIN_LIMITS_MAX_LENGTH") {
+            let _ = conf_rs.set("chain_limits.max_length", v)?;
+        }
+        if let Ok(v) = env::var("MERC_CHAIN_LIMITS_MAX_SIZE") {
+            let _ = conf_rs.set("chain_limits.max_size", v)?;
+        }
+        if let Ok(v) = env::var("MERC_CHAIN_LIMITS_PRUNE_LENGTH") {
+            let _ = conf_rs.set("chain_limits.prune_length", v)?;
+        }
+        if let Ok(v) = env::var("MERC_CHAIN_LIMITS_PRUNE_SIZE") {
+            let _ = conf_rs.set("chain_limits.prune_size", v)?;
+        }
+        if let Ok(v) = env::var("MERC_CHAIN_LIMITS_PRUNE_KEEP") {
+            let _ = conf_rs.set("chain_limits.prune_keep", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_SMT_BATCH_INTERVAL") {
+            let _ = conf_rs.set("smt_batch.interval", v)?;
+        }
+        if let Ok(v) = env::var("MERC_SMT_BATCH_MAX_SIZE") {
+            let _ = conf_rs.set("smt_batch.max_size", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_ROOT_UPDATE_MAX_UPDATES") {
+            let _ = conf_rs.set("root_update.max_updates", v)?;
+        }
+        if let Ok(v) = env::var("MERC_ROOT_UPDATE_INTERVAL") {
+            let _ = conf_rs.set("root_update.interval", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_CHAOS_DB_WRITE_FAILURE_RATE") {
+            let _ = conf_rs.set("chaos.db_write_failure_rate", v)?;
+        }
+        if let Ok(v) = env::var("MERC_CHAOS_RESPONSE_DELAY_RATE") {
+            let _ = conf_rs.set("chaos.response_delay_rate", v)?;
+        }
+        if let Ok(v) = env::var("MERC_CHAOS_RESPONSE_DELAY_MS") {
+            let _ = conf_rs.set("chaos.response_delay_ms", v)?;
+        }
+        if let Ok(v) = env::var("MERC_CHAOS_KEYGEN_DROP_RATE") {
+            let _ = conf_rs.set("chaos.keygen_drop_rate", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_WATCHTOWER_KEY") {
+            let _ = conf_rs.set("watchtower.key", v)?;
+        }
+        if let Ok(v) = env::var("MERC_WATCHTOWER_MAX_REGISTRATIONS") {
+            let _ = conf_rs.set("watchtower.max_registrations", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_DB_ENCRYPTION_KEY") {
+            let _ = conf_rs.set("db_encryption.key", v)?;
+        }
+        if let Ok(v) = env::var("MERC_DB_ENCRYPTION_KEY_URL") {
+            let _ = conf_rs.set("db_encryption.key_url", v)?;
+        }
+        if let Ok(v) = env::var("MERC_DB_ENCRYPTION_KEY_ID") {
+            let _ = conf_rs.set("db_encryption.key_id", v)?;
+        }
+        if let Ok(v) = env::var("MERC_DB_ENCRYPTION_PREVIOUS_KEYS") {
+            let _ = conf_rs.set("db_encryption.previous_keys", v)?;
+        }
+
+        if let Ok(v) = env::var("MERC_DYNAMODB_ENABLED") {
+            let _ = conf_rs.set("dynamodb.enabled", v)?;
+        }
+        if let Ok(v) = env::var("MERC_DYNAMODB_REGION") {
+            let _ = conf_rs.set("dynamodb.region", v)?;
+        }
+        if let Ok(v) = env::var("MERC_DYNAMODB_ENDPOINT") {
+            let _ = conf_rs.set("dynamodb.endpoint", v)?;
+        }
+        if let Ok(v) = env::var("MERC_DYNAMODB_TABLE_PREFIX") {
+            let _ = conf_rs.set("dynamodb.table_prefix", v)?;
+        }
+
         // Type checks
         let fee_address = conf_rs.get_str("fee_address")?;
         let fee_address_str = fee_address.replace(" ", "");
@@ -2,7 +2,11 @@
 //!
 //! Custom Error types for server
 
-use shared_lib::{error::SharedLibError, structs::CoinValueInfo};
+use shared_lib::{
+    error::SharedLibError,
+    state_chain::{ErrorCode, ERROR_CODE_HEADER},
+    structs::CoinValueInfo,
+};
 
 use crate::storage::db::Column;
 use bitcoin::secp256k1::Error as SecpError;
@@ -53,6 +57,22 @@ pub enum SEError {
     LockboxError(String),
     /// Rate limit error
     RateLimitError(String),
+    /// Protocol message not valid in the current session state
+    SessionStateError(String),
+    /// Deposit exceeds a per-user limit, or transfer exceeds a statechain length or size limit
+    LimitError(String),
+    /// Shared key from an earlier key epoch must be refreshed before transfer
+    KeyRefreshRequired(String),
+}
+
+impl SEError {
+    /// Code of errors the client acts on, sent in the ERROR_CODE_HEADER of the response
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            SEError::KeyRefreshRequired(_) => Some(ErrorCode::KeyRefreshRequired),
+            _ => None,
+        }
+    }
 }
 
 impl From<String> for SEError {
@@ -109,6 +129,13 @@ impl From<uuid::ParseError> for SEError {
     }
 }
 
+#[cfg(feature = "dynamodb")]
+impl<E: error::Error + 'static> From<rusoto_core::RusotoError<E>> for SEError {
+    fn from(e: rusoto_core::RusotoError<E>) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
 
 impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::conductor::Scheduler>>>
     for SEError
@@ -120,6 +147,36 @@ impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::cond
     }
 }
 
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::util::ProofCache>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::util::ProofCache>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, Option<shared_lib::structs::StateEntityFeeInfoAPI>>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, Option<shared_lib::structs::StateEntityFeeInfoAPI>>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, std::collections::HashMap<String, std::sync::Arc<crate::protocol::api_key::ApiKeyLimiter>>>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, std::collections::HashMap<String, std::sync::Arc<crate::protocol::api_key::ApiKeyLimiter>>>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
 impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, CoinValueInfo>>>
     for SEError
 {
@@ -140,6 +197,46 @@ impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::server::UserID
     }
 }
 
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::ping::HealthState>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, crate::protocol::ping::HealthState>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, std::collections::HashMap<String, chrono::NaiveDateTime>>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, std::collections::HashMap<String, chrono::NaiveDateTime>>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, std::collections::HashMap<uuid::Uuid, shared_lib::noise::TransportState>>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, std::collections::HashMap<uuid::Uuid, shared_lib::noise::TransportState>>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
+impl From<std::sync::PoisonError<std::sync::MutexGuard<'_, std::collections::HashMap<String, u64>>>>
+    for SEError
+{
+    fn from(
+        e: std::sync::PoisonError<std::sync::MutexGuard<'_, std::collections::HashMap<String, u64>>>,
+    ) -> SEError {
+        SEError::Generic(e.to_string())
+    }
+}
+
 impl From<Box<dyn std::error::Error>>
     for SEError
 {
@@ -202,6 +299,9 @@ impl fmt::Display for SEError {
             SEError::TransferBatchEnded(ref e) => write!(f, "Error: Transfer batch ended. {}", e),
             SEError::LockboxError(ref e) => write!(f, "Lockbox Error: {}", e),
             SEError::RateLimitError(ref e) => write!(f, "Error: Not available until {} due to rate limit", e),
+            SEError::SessionStateError(ref e) => write!(f, "Session State Error: {}", e),
+            SEError::LimitError(ref e) => write!(f, "Limit Error: {}", e),
+            SEError::KeyRefreshRequired(ref e) => write!(f, "Error: key refresh required: {}", e),
         }
     }
 }
@@ -237,10 +337,12 @@ impl error::Error for SEError {
 
 impl Responder<'static> for SEError {
     fn respond_to(self, _: &Request) -> ::std::result::Result<Response<'static>, Status> {
-        Response::build()
-            .header(ContentType::JSON)
-            .sized_body(Cursor::new(format!("{}", self)))
-            .ok()
+        let mut response = Response::build();
+        response.header(ContentType::JSON);
+        if let Some(code) = self.code() {
+            response.raw_header(ERROR_CODE_HEADER, code.as_str());
+        }
+        response.sized_body(Cursor::new(format!("{}", self))).ok()
     }
 }
 
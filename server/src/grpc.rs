@@ -0,0 +1,462 @@
+//! # gRPC
+//!
+//! tonic frontend of the state entity for server-to-server federation. Serves the service
+//! generated from shared/proto/state_entity.proto on grpc_address, alongside the Rocket
+//! frontend and sharing its state. Requests and replies are the JSON encoded protocol
+//! messages of the REST API carried in a JsonMsg (JSON over gRPC), not protobuf messages. As in the api module, each method applies rate limiting,
+//! decodes the request and calls the corresponding method of the service API (see service.rs).
+//! Service calls block, so they are run on the tokio blocking thread pool.
+//!
+//! Info methods accept an info API key in the "x-api-key" request metadata.
+
+use crate::api::SCE;
+use crate::error::{DBErrorType, SEError};
+use crate::protocol::accelerate::Accelerate;
+use crate::service::*;
+use crate::Result;
+use shared_lib::grpc::{
+    state_entity_server::{StateEntity, StateEntityServer},
+    Empty, JsonMsg,
+};
+use shared_lib::smt_proof::EncodedProof;
+use shared_lib::structs::*;
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use tonic::{transport::Server, Request, Response, Status};
+
+type GrpcResult<T> = std::result::Result<Response<T>, Status>;
+
+/// gRPC service over a state entity
+pub struct GrpcService {
+    sc_entity: Arc<SCE>,
+}
+
+fn to_status(e: SEError) -> Status {
+    match e {
+        SEError::AuthError => Status::unauthenticated(e.to_string()),
+        SEError::RateLimitError(_) => Status::resource_exhausted(e.to_string()),
+        SEError::TryAgain(_) => Status::unavailable(e.to_string()),
+        SEError::DBError(DBErrorType::NoDataForID, _) => Status::not_found(e.to_string()),
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+fn api_key<T>(request: &Request<T>) -> Option<String> {
+    request
+        .metadata()
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+impl GrpcService {
+    pub fn new(sc_entity: SCE) -> Self {
+        GrpcService {
+            sc_entity: Arc::new(sc_entity),
+        }
+    }
+
+    /// Run a service call on the blocking thread pool
+    async fn blocking<U, F>(&self, f: F) -> std::result::Result<U, Status>
+    where
+        U: Send + 'static,
+        F: FnOnce(&SCE) -> Result<U> + Send + 'static,
+    {
+        let sc_entity = self.sc_entity.clone();
+        tokio::task::spawn_blocking(move || f(&sc_entity))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(to_status)
+    }
+
+    /// Decode the request message, make the service call and encode the reply
+    async fn call<T, U, F>(&self, request: Request<JsonMsg>, f: F) -> GrpcResult<JsonMsg>
+    where
+        T: DeserializeOwned + Send + 'static,
+        U: Serialize + Send + 'static,
+        F: FnOnce(&SCE, T) -> Result<U> + Send + 'static,
+    {
+        let msg: T = request.get_ref().decode()?;
+        let reply = self.blocking(move |sc_entity| f(sc_entity, msg)).await?;
+        Ok(Response::new(JsonMsg::encode(&reply)?))
+    }
+
+    /// As call, for service calls with no reply data
+    async fn call_empty<T, F>(&self, request: Request<JsonMsg>, f: F) -> GrpcResult<Empty>
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: FnOnce(&SCE, T) -> Result<()> + Send + 'static,
+    {
+        let msg: T = request.get_ref().decode()?;
+        self.blocking(move |sc_entity| f(sc_entity, msg)).await?;
+        Ok(Response::new(Empty {}))
+    }
+}
+
+#[tonic::async_trait]
+impl StateEntity for GrpcService {
+    async fn get_fees(&self, _request: Request<Empty>) -> GrpcResult<JsonMsg> {
+        let reply = self
+            .blocking(|sc_entity| {
+                sc_entity.check_rate_fast("info")?;
+                sc_entity.get_fees()
+            })
+            .await?;
+        Ok(Response::new(JsonMsg::encode(&reply)?))
+    }
+
+    async fn get_params(&self, _request: Request<Empty>) -> GrpcResult<JsonMsg> {
+        let reply = self
+            .blocking(|sc_entity| {
+                sc_entity.check_rate_fast("info")?;
+                sc_entity.get_params()
+            })
+            .await?;
+        Ok(Response::new(JsonMsg::encode(&reply)?))
+    }
+
+    async fn get_statechain(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        let api_key = api_key(&request);
+        self.call(request, move |sc_entity, msg: StatechainID| {
+            sc_entity.check_rate_info(&api_key)?;
+            sc_entity.get_statechain_data_api(msg.id)
+        })
+        .await
+    }
+
+    async fn get_statecoin(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        let api_key = api_key(&request);
+        self.call(request, move |sc_entity, msg: StatechainID| {
+            sc_entity.check_rate_info(&api_key)?;
+            sc_entity.get_statecoin_data_api(msg.id)
+        })
+        .await
+    }
+
+    async fn get_smt_root(&self, request: Request<Empty>) -> GrpcResult<JsonMsg> {
+        let api_key = api_key(&request);
+        let reply = self
+            .blocking(move |sc_entity| {
+                sc_entity.check_rate_info(&api_key)?;
+                sc_entity.get_smt_root()
+            })
+            .await?;
+        Ok(Response::new(JsonMsg::encode(&reply)?))
+    }
+
+    async fn get_smt_proof(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        let api_key = api_key(&request);
+        self.call(request, move |sc_entity, msg: SmtProofMsgAPI| {
+            sc_entity.check_rate_info(&api_key)?;
+            let format = msg.proof_format;
+            Ok(sc_entity
+                .get_smt_proof(msg)?
+                .map(|proof| EncodedProof::new(proof, format)))
+        })
+        .await
+    }
+
+    async fn get_smt_proofs(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        let api_key = api_key(&request);
+        self.call(request, move |sc_entity, msg: SmtProofsMsgAPI| {
+            sc_entity.check_rate_info(&api_key)?;
+            sc_entity.get_smt_proofs(msg)
+        })
+        .await
+    }
+
+    async fn keygen_first(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: KeyGenMsg1| {
+            sc_entity.check_rate_slow("ecdsa")?;
+            sc_entity.first_message(msg)
+        })
+        .await
+    }
+
+    async fn keygen_second(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: KeyGenMsg2| {
+            sc_entity.check_rate_slow("ecdsa")?;
+            sc_entity.second_message(msg)
+        })
+        .await
+    }
+
+    async fn sign_first(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: SignMsg1| {
+            sc_entity.check_rate_slow("ecdsa")?;
+            sc_entity.sign_first(msg)
+        })
+        .await
+    }
+
+    async fn sign_second(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: SignMsg2| {
+            sc_entity.check_rate_slow("ecdsa")?;
+            sc_entity.sign_second(msg)
+        })
+        .await
+    }
+
+    async fn prepare_sign_tx(&self, request: Request<JsonMsg>) -> GrpcResult<Empty> {
+        self.call_empty(request, |sc_entity, msg: PrepareSignTxMsg| {
+            sc_entity.check_rate_fast("info")?;
+            sc_entity.prepare_sign_tx(msg)
+        })
+        .await
+    }
+
+    async fn deposit_init(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: DepositMsg1| {
+            sc_entity.check_rate_slow("deposit_init")?;
+            sc_entity.deposit_init(msg)
+        })
+        .await
+    }
+
+    async fn deposit_confirm(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: DepositMsg2| {
+            sc_entity.check_rate_fast("deposit_confirm")?;
+            sc_entity.deposit_confirm(msg)
+        })
+        .await
+    }
+
+    async fn deposit_refund(&self, request: Request<JsonMsg>) -> GrpcResult<Empty> {
+        self.call_empty(request, |sc_entity, msg: DepositRefundMsg| {
+            sc_entity.check_rate_fast("deposit_refund")?;
+            sc_entity.deposit_refund(msg)
+        })
+        .await
+    }
+
+    async fn transfer_sender(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: TransferMsg1| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.transfer_sender(msg)
+        })
+        .await
+    }
+
+    async fn transfer_get_pubkey(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: UserID| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.transfer_get_pubkey(msg.id)
+        })
+        .await
+    }
+
+    async fn transfer_receiver(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: TransferMsg4| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.transfer_receiver(msg)
+        })
+        .await
+    }
+
+    async fn transfer_update_msg(&self, request: Request<JsonMsg>) -> GrpcResult<Empty> {
+        self.call_empty(request, |sc_entity, msg: TransferMsg3| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.transfer_update_msg(msg)
+        })
+        .await
+    }
+
+    async fn transfer_get_msg(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: StatechainID| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.transfer_get_msg(msg.id)
+        })
+        .await
+    }
+
+    async fn transfer_get_msg_addr(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, receive_addr: String| {
+            sc_entity.check_rate_fast("info")?;
+            sc_entity.transfer_get_msg_addr(receive_addr)
+        })
+        .await
+    }
+
+    async fn transfer_reject(&self, request: Request<JsonMsg>) -> GrpcResult<Empty> {
+        self.call_empty(request, |sc_entity, msg: TransferRejectMsg| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.transfer_reject(msg)
+        })
+        .await
+    }
+
+    async fn transfer_get_reject(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: StatechainID| {
+            sc_entity.check_rate_fast("info")?;
+            sc_entity.transfer_get_reject(msg.id)
+        })
+        .await
+    }
+
+    async fn sale_init(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: SaleInitMsg| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.sale_init(msg)
+        })
+        .await
+    }
+
+    async fn sale_get(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: StatechainID| {
+            sc_entity.check_rate_fast("info")?;
+            sc_entity.sale_get(msg.id)
+        })
+        .await
+    }
+
+    async fn sale_reveal(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: SaleRevealMsg| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.sale_reveal(msg)
+        })
+        .await
+    }
+
+    async fn inheritance_designate(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: InheritanceMsg| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.inheritance_designate(msg)
+        })
+        .await
+    }
+
+    async fn inheritance_checkin(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: InheritanceOwnerMsg| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.inheritance_checkin(msg)
+        })
+        .await
+    }
+
+    async fn inheritance_revoke(&self, request: Request<JsonMsg>) -> GrpcResult<Empty> {
+        self.call_empty(request, |sc_entity, msg: InheritanceOwnerMsg| {
+            sc_entity.check_rate_fast("transfer")?;
+            sc_entity.inheritance_revoke(msg)
+        })
+        .await
+    }
+
+    async fn inheritance_get(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: StatechainID| {
+            sc_entity.check_rate_fast("info")?;
+            sc_entity.inheritance_get(msg.id)
+        })
+        .await
+    }
+
+    async fn rotate_proof_key(&self, request: Request<JsonMsg>) -> GrpcResult<Empty> {
+        self.call_empty(request, |sc_entity, msg: RotateMsg| {
+            sc_entity.check_rate_slow("rotate")?;
+            sc_entity.rotate_proof_key(msg)
+        })
+        .await
+    }
+
+    async fn vault_set(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: VaultMsg| {
+            sc_entity.check_rate_slow("vault")?;
+            sc_entity.vault_set(msg)
+        })
+        .await
+    }
+
+    async fn vault_cancel(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: VaultCancelMsg| {
+            sc_entity.check_rate_fast("vault")?;
+            sc_entity.vault_cancel(msg)
+        })
+        .await
+    }
+
+    async fn vault_get(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: StatechainID| {
+            sc_entity.check_rate_fast("info")?;
+            sc_entity.vault_get(msg.id)
+        })
+        .await
+    }
+
+    async fn withdraw_init(&self, request: Request<JsonMsg>) -> GrpcResult<Empty> {
+        self.call_empty(request, |sc_entity, msg: WithdrawMsg1| {
+            sc_entity.check_rate_fast("withdraw")?;
+            sc_entity.withdraw_init(msg)
+        })
+        .await
+    }
+
+    async fn withdraw_confirm(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: WithdrawMsg2| {
+            sc_entity.check_rate_fast("withdraw")?;
+            sc_entity.withdraw_confirm(msg)
+        })
+        .await
+    }
+
+    async fn withdraw_accelerate(&self, request: Request<JsonMsg>) -> GrpcResult<JsonMsg> {
+        self.call(request, |sc_entity, msg: WithdrawAccelerateMsg| {
+            sc_entity.check_rate_slow("withdraw")?;
+            sc_entity.withdraw_accelerate(msg)
+        })
+        .await
+    }
+}
+
+/// Serve the gRPC interface on a new thread
+pub fn spawn(sc_entity: SCE, address: &String) -> Result<thread::JoinHandle<()>> {
+    let addr: SocketAddr = address
+        .parse()
+        .map_err(|e| SEError::Generic(format!("Invalid gRPC address {}: {}", address, e)))?;
+    let service = StateEntityServer::new(GrpcService::new(sc_entity));
+    Ok(thread::spawn(move || {
+        let mut runtime = match tokio::runtime::Runtime::new() {
+            Ok(r) => r,
+            Err(e) => {
+                error!("gRPC: failed to start runtime: {}", e);
+                return;
+            }
+        };
+        info!("gRPC: listening on {}", addr);
+        if let Err(e) = runtime.block_on(Server::builder().add_service(service).serve(addr)) {
+            error!("gRPC: server error: {}", e);
+        }
+    }))
+}
+
+/// Serve the gRPC interface on a handle sharing the state entity, alongside the Rocket server,
+/// if grpc_address is set
+pub fn spawn_shared(sc_entity: &SCE) -> Result<Option<thread::JoinHandle<()>>> {
+    match &sc_entity.config.grpc_address {
+        Some(address) => Ok(Some(spawn(sc_entity.share()?, address)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_status() {
+        assert_eq!(to_status(SEError::AuthError).code(), tonic::Code::Unauthenticated);
+        assert_eq!(
+            to_status(SEError::RateLimitError(String::from("1s"))).code(),
+            tonic::Code::ResourceExhausted
+        );
+        assert_eq!(
+            to_status(SEError::DBError(DBErrorType::NoDataForID, String::from("id"))).code(),
+            tonic::Code::NotFound
+        );
+        assert_eq!(
+            to_status(SEError::Generic(String::from("error"))).code(),
+            tonic::Code::Internal
+        );
+    }
+}
@@ -34,6 +34,7 @@ extern crate zk_paillier;
 extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
+extern crate serde_cbor;
 
 #[cfg(test)]
 #[macro_use]
@@ -50,22 +51,40 @@ extern crate shared_lib;
 #[macro_use]
 extern crate time_test;
 
+pub mod aml;
+pub mod api;
+pub mod build_info;
+pub mod chaos;
 pub mod config;
 pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod protocol;
 pub mod server;
+pub mod service;
 pub mod storage;
 pub mod watch;
 
 pub type Result<T> = std::result::Result<T, error::SEError>;
 pub type Hash = bitcoin::hashes::sha256d::Hash;
 
+cfg_if::cfg_if! {
+    if #[cfg(feature = "dynamodb")] {
+        /// Database of the server. The core tables are stored in DynamoDB if enabled in the
+        /// config.
+        pub type ServerDatabase = storage::dynamodb::DynamoDatabase;
+    } else {
+        /// Database of the server
+        pub type ServerDatabase = PGDatabase;
+    }
+}
+
 use rocket_contrib::databases::r2d2;
 use rocket_contrib::databases::r2d2_postgres::PostgresConnectionManager;
 
 use crate::storage::db::Alpha;
 use bitcoin::hashes::sha256d;
-use bitcoin::Transaction;
+use bitcoin::{OutPoint, Transaction};
 use chrono::NaiveDateTime;
 use curv::{FE, GE};
 use kms::ecdsa::two_party::*;
@@ -74,7 +93,7 @@ use mockall::*;
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one::Party1Private;
 use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
 use rocket_contrib::databases::postgres;
-use shared_lib::{state_chain::*, structs::{TransferMsg3,TransferFinalizeData}, Root, structs::CoinValueInfo};
+use shared_lib::{state_chain::*, swap_data::SwapReport, structs::{TransferMsg3,TransferFinalizeData,SpendIncident,SignedDeletionReceipt,DepositHandoffMsg,StateEntityFeeInfoAPI,SignedTransferReceipt,TransferRejectAPI,FeeType,PayToContract}, Root, structs::CoinValueInfo};
 use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 use crate::server::UserIDs;
@@ -94,11 +113,19 @@ pub struct PGDatabaseSmt {
 /// POstgres database struct for Mercury. Contains database connection pool and SMT DB items.
 pub struct PGDatabase {
     pub pool: Option<r2d2::Pool<PostgresConnectionManager>>,
+    /// Read-only replica connection pool used by the info routes
+    pub pool_replica: Option<r2d2::Pool<PostgresConnectionManager>>,
     pub smt: PGDatabaseSmt,
+    /// Injects DB write failures in chaos mode
+    pub faults: chaos::FaultInjector,
+    /// Encrypts sensitive column values
+    pub cipher: storage::encryption::ColumnCipher,
 }
 
 use structs::*;
 
+/// Storage backend of the state entity. Methods added here must also be given an expectation in
+/// the fuzz fixture database (fuzz/src/lib.rs).
 #[automock]
 pub trait Database {
     fn get_new() -> Self;
@@ -109,6 +136,7 @@ pub trait Database {
     fn init_coins_histo(&self, coins_histo: &Mutex<CoinValueInfo>) -> Result<()>;
     fn init_user_ids(&self, user_ids: &Mutex<UserIDs>) -> Result<()>;
     fn update_withdraw_sc_sig(&self, user_id: &Uuid, sig: StateChainSig) -> Result<()>;
+    fn get_withdraw_sc_sig(&self, user_id: Uuid) -> Result<StateChainSig>;
     fn update_withdraw_tx_sighash(
         &self,
         user_id: &Uuid,
@@ -123,6 +151,7 @@ pub trait Database {
     fn update_user_backup_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()>;
     fn get_user_backup_tx(&self, user_id: Uuid) -> Result<Transaction>;
     fn update_backup_tx(&self, statechain_id: &Uuid, tx: Transaction) -> Result<()>;
+    fn update_backup_cpfp_tx(&self, statechain_id: &Uuid, tx: Transaction) -> Result<()>;
     fn get_withdraw_confirm_data(&self, user_id: Uuid) -> Result<WithdrawConfirmData>;
     /// Update root value in DB. Update root with ID or insert new DB item.
     fn root_update(&self, rt: &Root) -> Result<i64>;
@@ -132,6 +161,9 @@ pub trait Database {
     fn root_get_current_id(&self) -> Result<i64>;
     /// Get root with given ID
     fn get_root(&self, id: i64) -> Result<Option<Root>>;
+    /// Get root with given ID from the read replica, or from the primary if the replica does not
+    /// have it yet
+    fn get_root_info(&self, id: i64) -> Result<Option<Root>>;
     /// Find the latest confirmed root
     fn get_confirmed_smt_root(&self) -> Result<Option<Root>>;
     fn get_statechain_id(&self, user_id: Uuid) -> Result<Uuid>;
@@ -142,12 +174,20 @@ pub trait Database {
     fn get_challenge(&self, user_id: &Uuid) -> Result<String>;
     fn update_statechain_id(&self, user_id: &Uuid, statechain_id: &Uuid) -> Result<()>;
     fn get_statechain_amount(&self, statechain_id: Uuid) -> Result<StateChainAmount>;
+    /// Get the statechain, backup tx and confirmation status from the read replica if configured
+    fn get_statechain_info(&self, statechain_id: Uuid) -> Result<StateChainInfo>;
+    /// Get the row version of a StateChain. Read before the statechain when the statechain is
+    /// to be updated with a versioned update.
+    fn get_statechain_version(&self, statechain_id: &Uuid) -> Result<i64>;
+    /// Versioned update: fails with a retryable error if the statechain has been updated since
+    /// `version` was read
     fn update_statechain_amount(
         &self,
         statechain_id: &Uuid,
         state_chain: StateChain,
         amount: u64,
         coins_histo: Arc<Mutex<CoinValueInfo>>,
+        version: i64,
     ) -> Result<()>;
     fn create_statechain(
         &self,
@@ -157,11 +197,14 @@ pub trait Database {
         amount: &i64,
     ) -> Result<()>;
     fn get_statechain(&self, statechain_id: Uuid) -> Result<StateChain>;
+    /// Versioned update: fails with a retryable error if the statechain has been updated since
+    /// `version` was read
     fn update_statechain_owner(
         &self,
         statechain_id: &Uuid,
         state_chain: StateChain,
         new_user_id: &Uuid,
+        version: i64,
     ) -> Result<()>;
     // Remove statechain_id from user session to signal end of session
     fn remove_statechain_id(&self, user_id: &Uuid) -> Result<()>;
@@ -175,6 +218,12 @@ pub trait Database {
     fn get_backup_transaction(&self, statechain_id: Uuid) -> Result<Transaction>;
     fn get_backup_transaction_and_proof_key(&self, user_id: Uuid) -> Result<(Transaction, String)>;
     fn get_proof_key(&self, user_id: Uuid) -> Result<String>;
+    /// Set the proof key of a user session, e.g. after a proof key rotation
+    fn update_proof_key(&self, user_id: &Uuid, proof_key: &String) -> Result<()>;
+    /// Number and aggregate value of the unspent statecoins owned by proof_key
+    fn get_proof_key_holdings(&self, proof_key: &String) -> Result<(u64, u64)>;
+    /// Aggregate value of all unspent statecoins
+    fn get_total_value(&self) -> Result<u64>;
     fn get_sc_locked_until(&self, statechain_id: Uuid) -> Result<NaiveDateTime>;
     fn update_locked_until(&self, statechain_id: &Uuid, time: &NaiveDateTime) -> Result<()>;
     fn get_transfer_batch_data(&self, batch_id: Uuid) -> Result<TransferBatchData>;
@@ -197,9 +246,15 @@ pub trait Database {
         state_chains: Vec<Uuid>,
     ) -> Result<()>;
     fn get_transfer_data(&self, statechain_id: Uuid) -> Result<TransferData>;
+    /// Claim the transfer data for a receiver. Fails with a retryable error if the transfer
+    /// data has been updated or claimed since `version` was read.
+    fn claim_transfer(&self, statechain_id: &Uuid, version: i64) -> Result<()>;
     fn remove_transfer_data(&self, statechain_id: &Uuid) -> Result<()>;
     fn transfer_is_completed(&self, statechain_id: Uuid) -> bool;
     fn get_public_master(&self, user_id: Uuid) -> Result<Option<String>>;    
+    /// Pay-to-contract commitment the deposit shared key of a user session is tweaked with
+    fn get_p2c(&self, user_id: Uuid) -> Result<Option<PayToContract>>;
+    fn update_p2c(&self, user_id: &Uuid, p2c: &PayToContract) -> Result<()>;
     fn get_ecdsa_master(&self, user_id: Uuid) -> Result<Option<String>>;
     fn get_ecdsa_witness_keypair(
         &self,
@@ -225,24 +280,59 @@ pub trait Database {
         paillier_key_pair: party_one::PaillierKeyPair,
         party_one_private: party_one::Party1Private,
     ) -> Result<()>;
+    /// Update the owner public key share of a shared key, when it is tweaked after keygen
+    fn update_party_2_public(&self, user_id: &Uuid, party2_public: GE) -> Result<()>;
     fn init_ecdsa(&self, user_id: &Uuid) -> Result<u64>;
+    /// Clear the keygen data of a shared key. The share epoch is kept.
+    fn reset_ecdsa(&self, user_id: &Uuid) -> Result<()>;
+    fn get_ecdsa_key_epoch(&self, user_id: Uuid) -> Result<u32>;
+    fn update_ecdsa_key_epoch(&self, user_id: &Uuid, epoch: u32) -> Result<()>;
+    fn get_ecdsa_share_epoch(&self, user_id: Uuid) -> Result<u32>;
+    fn update_ecdsa_share_epoch(&self, user_id: &Uuid, epoch: u32) -> Result<()>;
+    fn get_ecdsa_refresh_x1(&self, user_id: Uuid) -> Result<Option<FE>>;
+    fn update_ecdsa_refresh_x1(&self, user_id: &Uuid, x1: &FE) -> Result<()>;
+    fn update_ecdsa_s2(&self, user_id: &Uuid, s2: &FE) -> Result<()>;
+    fn update_ecdsa_proof_verified(&self, user_id: &Uuid, verified: bool) -> Result<()>;
+    fn get_unverified_keygen_sessions(&self) -> Result<Vec<Uuid>>;
     fn get_keygen_first_msg(&self,user_id: &Uuid) -> Result<party_one::KeyGenFirstMsg>;
     fn get_ecdsa_party_1_private(&self, user_id: Uuid) -> Result<party_one::Party1Private>;
     fn get_ecdsa_keypair(&self, user_id: Uuid) -> Result<ECDSAKeypair>;
-    fn update_punished(&self, batch_id: &Uuid, punished_state_chains: Vec<Uuid>) -> Result<()>;
+    /// Versioned update: fails with a retryable error if the batch has been updated since
+    /// `version` was read
+    fn update_punished(
+        &self,
+        batch_id: &Uuid,
+        punished_state_chains: Vec<Uuid>,
+        version: i64,
+    ) -> Result<()>;
     fn get_transfer_batch_start_time(&self, batch_id: &Uuid) -> Result<NaiveDateTime> ;
+    fn update_transfer_batch_start_height(&self, batch_id: &Uuid, height: u64) -> Result<()>;
+    /// Block height a batch transfer started at. None if the batch lifetime is not in blocks.
+    fn get_transfer_batch_start_height(&self, batch_id: &Uuid) -> Result<Option<u64>>;
     fn get_batch_transfer_statechain_ids(&self, batch_id: &Uuid) -> Result<HashSet<Uuid>>;
     fn get_finalize_batch_data(&self, batch_id: Uuid) -> Result<TransferFinalizeBatchData>;
     fn get_sc_transfer_finalize_data(
         &self,
         statechain_id: &Uuid
     ) -> Result<TransferFinalizeData>;
+    /// Versioned update: fails with a retryable error if the statechain has been updated since
+    /// `version` was read
     fn update_finalize_batch_data(
         &self,
         statechain_id: &Uuid,
         finalized_data: &TransferFinalizeData,
+        version: i64,
+    ) -> Result<()>;
+    /// Get the row version of a TransferBatch
+    fn get_transfer_batch_version(&self, batch_id: &Uuid) -> Result<i64>;
+    /// Versioned update: fails with a retryable error if the batch has been updated since
+    /// `version` was read
+    fn update_transfer_batch_finalized(
+        &self,
+        batch_id: &Uuid,
+        b_finalized: &bool,
+        version: i64,
     ) -> Result<()>;
-    fn update_transfer_batch_finalized(&self, batch_id: &Uuid, b_finalized: &bool) -> Result<()>;
     fn get_statechain_owner(&self, statechain_id: Uuid) -> Result<StateChainOwner>;
     fn get_recovery_data(&self, proofkey: String) -> Result<Vec<(Uuid,Uuid,Transaction)>>;
     // Create DB entry for newly generated ID signalling that user has passed some
@@ -277,8 +367,203 @@ pub trait Database {
     fn set_shared_pubkey(&self, statechain_id: Uuid, pubkey: &String) -> Result<()>;
     fn get_shared_pubkey(&self, user_id: Uuid) -> Result<Option<String>>;
     fn get_statecoin_pubkey(&self, statechain_id: Uuid) -> Result<Option<String>>;
+    /// Address the current backup tx of a statechain pays to
+    fn get_backup_addr(&self, statechain_id: &Uuid) -> Result<Option<String>>;
+    fn update_backup_addr(&self, statechain_id: &Uuid, addr: &String) -> Result<()>;
     fn update_ecdsa_master(&self, user_id: &Uuid, master_key: MasterKey1) -> Result<()>;
     fn get_sighash(&self, user_id: Uuid) -> Result<sha256d::Hash>;
+    /// Record a sighash prepared for co-signing a spend of an outpoint
+    fn insert_sighash_record(&self, record: &SighashRecord) -> Result<()>;
+    /// Get the sighash record prepared in a user session
+    fn get_sighash_record(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<SighashRecord>;
+    /// Get all co-signed sighash records spending an outpoint
+    fn get_signed_sighashes(&self, outpoint: &OutPoint) -> Result<Vec<SighashRecord>>;
+    /// Mark a prepared sighash as co-signed
+    fn set_sighash_signed(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<()>;
+    /// Mark a prepared backup tx sighash as finalized by its deposit or transfer
+    fn set_sighash_finalized(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<()>;
+    /// Store a newly issued info API key
+    fn insert_api_key(&self, api_key: &ApiKey) -> Result<()>;
+    /// Get an info API key by the hash of the key
+    fn get_api_key(&self, key_hash: &String) -> Result<ApiKey>;
+    /// Mark an info API key as revoked
+    fn revoke_api_key(&self, key_hash: &String) -> Result<()>;
+    /// Get ids and modification times of statechains modified since a checkpoint, oldest first
+    fn get_statechains_updated_since(
+        &self,
+        since: &UpdatesSince,
+        limit: i64,
+    ) -> Result<Vec<(Uuid, NaiveDateTime)>>;
+    /// Move up to limit finished statechains last updated before updated_before into the
+    /// archive, with their backup txs. A statechain is finished once withdrawn, or once its
+    /// backup tx has been confirmed and removed by the watcher. Returns the ids and amounts of
+    /// the archived statechains.
+    fn archive_statechains(
+        &self,
+        updated_before: &NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(Uuid, i64)>>;
+    /// Delete statechains archived before archived_before. Returns the number deleted.
+    fn purge_archived_statechains(&self, archived_before: &NaiveDateTime) -> Result<u64>;
+    /// Remove up to limit user sessions without a statechain left in state since before
+    /// updated_before, with their Ecdsa, Lockbox, DepositHandoff and FeeAcknowledgement rows.
+    /// Returns the ids of the removed sessions and the number of related rows removed.
+    fn remove_orphaned_sessions(
+        &self,
+        state: &SessionState,
+        updated_before: &NaiveDateTime,
+        limit: i64,
+    ) -> Result<(Vec<Uuid>, u64)>;
+    /// Delete the ecdsa key data of a shared key
+    fn remove_ecdsa(&self, user_id: &Uuid) -> Result<()>;
+    /// Store a signed key share deletion receipt
+    fn insert_deletion_receipt(&self, receipt: &SignedDeletionReceipt) -> Result<()>;
+    /// Get the key share deletion receipts of a statechain, oldest first
+    fn get_deletion_receipts(&self, statechain_id: &Uuid) -> Result<Vec<SignedDeletionReceipt>>;
+    /// Store the receipt of a completed transfer. user_id is the shared key ID of the sender.
+    fn insert_transfer_receipt(&self, user_id: &Uuid, receipt: &SignedTransferReceipt) -> Result<()>;
+    /// Get the transfer receipts of a statechain, oldest first
+    fn get_transfer_receipts(&self, statechain_id: &Uuid) -> Result<Vec<SignedTransferReceipt>>;
+    /// Get the last rejected transfer of a statechain
+    fn get_transfer_reject(&self, statechain_id: &Uuid) -> Result<Option<TransferRejectAPI>>;
+    /// Insert or replace the rejected transfer of a statechain
+    fn update_transfer_reject(&self, reject: &TransferRejectAPI) -> Result<()>;
+    /// Get the escrowed sale of a statechain
+    fn get_sale(&self, statechain_id: &Uuid) -> Result<Option<SaleRecord>>;
+    /// Insert or replace the escrowed sale of a statechain
+    fn update_sale(&self, sale: &SaleRecord) -> Result<()>;
+    /// Get the beneficiary designation of a statechain
+    fn get_inheritance(&self, statechain_id: &Uuid) -> Result<Option<InheritanceRecord>>;
+    /// Insert or replace the beneficiary designation of a statechain
+    fn update_inheritance(&self, inheritance: &InheritanceRecord) -> Result<()>;
+    /// Delete the beneficiary designation of a statechain
+    fn remove_inheritance(&self, statechain_id: &Uuid) -> Result<()>;
+    /// Get the second factor key registration of a statechain
+    fn get_second_factor(&self, statechain_id: &Uuid) -> Result<Option<SecondFactorRecord>>;
+    /// Insert or replace the second factor key registration of a statechain
+    fn update_second_factor(&self, second_factor: &SecondFactorRecord) -> Result<()>;
+    /// Get the vault policy of a statechain
+    fn get_vault(&self, statechain_id: &Uuid) -> Result<Option<VaultRecord>>;
+    /// Insert or replace the vault policy of a statechain
+    fn update_vault(&self, vault: &VaultRecord) -> Result<()>;
+    /// Record the swap fee of a participant of a swap, with the fee deposit txid if paid by
+    /// deposit. A repeated record of the same participant is ignored.
+    fn insert_swap_fee(
+        &self,
+        swap_id: &Uuid,
+        statechain_id: &Uuid,
+        fee: u64,
+        txid: &Option<String>,
+    ) -> Result<()>;
+    /// Get the swap id and statechain id the fee deposit txid was used for
+    fn get_swap_fee_by_txid(&self, txid: &String) -> Result<Option<(Uuid, Uuid)>>;
+    /// Swap fees owed by deduction by a statechain from finalized swaps, not yet collected
+    fn get_swap_fee_owed(&self, statechain_id: &Uuid) -> Result<u64>;
+    /// Mark the swap fees owed by a statechain as collected
+    fn update_swap_fee_collected(&self, statechain_id: &Uuid) -> Result<()>;
+    /// Record the report of a completed swap. A repeated record of the same swap is ignored.
+    fn insert_swap_report(&self, report: &SwapReport) -> Result<()>;
+    /// Get the report of a completed swap
+    fn get_swap_report(&self, swap_id: &Uuid) -> Result<Option<SwapReport>>;
+    /// Record a spend of the funding output of a statechain by a tx other than its backup tx and
+    /// mark the statechain updated. A repeated record for the same statechain is ignored.
+    fn insert_spend_incident(&self, incident: &SpendIncident) -> Result<()>;
+    /// Record the txid of the backup tx broadcast in response to the spend incident of a
+    /// statechain and mark the statechain updated
+    fn update_spend_incident_response(
+        &self,
+        statechain_id: &Uuid,
+        response_txid: &String,
+    ) -> Result<()>;
+    /// Get the spend incident of a statechain, if any
+    fn get_spend_incident(&self, statechain_id: &Uuid) -> Result<Option<SpendIncident>>;
+    /// Record a co-signed backup tx of a statechain. A repeated record of the same tx is ignored.
+    fn insert_backup_tx_history(&self, statechain_id: &Uuid, tx: &Transaction) -> Result<()>;
+    /// Get the recorded backup txs of a statechain, in the order recorded
+    fn get_backup_tx_history(&self, statechain_id: &Uuid) -> Result<Vec<Transaction>>;
+    /// Record the SMT leaves (funding txid, value) updated by a root
+    fn insert_root_leaves(&self, root_id: i64, leaves: &[(String, String)]) -> Result<()>;
+    /// Get the (root id, value) of the SMT leaf of a funding txid updated by each root, in
+    /// root order
+    fn get_root_leaves(&self, funding_txid: &String) -> Result<Vec<(i64, String)>>;
+    /// Whether the root with the given ID, or a later record of the same root, is confirmed in
+    /// a mainstay attestation
+    fn is_root_confirmed(&self, id: i64) -> Result<bool>;
+    /// Get the watchtower registration with the given ID
+    fn get_watchtower_registration(&self, id: &Uuid) -> Result<Option<WatchtowerRecord>>;
+    /// Insert or replace a watchtower registration. A registration is only replaced by one with a
+    /// greater version.
+    fn update_watchtower_registration(&self, record: &WatchtowerRecord) -> Result<()>;
+    /// Number of registrations of an auth key not revoked
+    fn count_watchtower_registrations(&self, auth_key: &String) -> Result<u64>;
+    /// Get the registrations not revoked with a broadcast height less than or equal to height
+    fn get_due_watchtower_txs(&self, height: i64) -> Result<Vec<WatchtowerRecord>>;
+    /// Drop the backup tx of a registration once confirmed, keeping its version
+    fn remove_watchtower_tx(&self, id: &Uuid) -> Result<()>;
+    /// Record a fee charged to a statechain, paid by txid. A repeated record is ignored.
+    fn insert_fee(
+        &self,
+        txid: &String,
+        fee_type: &FeeType,
+        statechain_id: &Uuid,
+        amount: u64,
+    ) -> Result<()>;
+    /// Get the fees recorded from 'from' (inclusive) to 'to' (exclusive), oldest first
+    fn get_fees(&self, from: &NaiveDateTime, to: &NaiveDateTime) -> Result<Vec<FeeRecord>>;
+    /// Store a segment of states pruned from a statechain. A repeated segment is ignored.
+    fn insert_chain_segment(&self, statechain_id: &Uuid, segment: &ArchivedSegment) -> Result<()>;
+    /// Get the pruned segments stored for a statechain
+    fn get_chain_segments(&self, statechain_id: &Uuid) -> Result<Vec<ArchivedSegment>>;
+    /// Get the punishment record of a statechain id or proof key
+    fn get_punishment(&self, id: &String) -> Result<Option<PunishmentRecord>>;
+    /// Insert or replace a punishment record
+    fn update_punishment(&self, record: &PunishmentRecord) -> Result<()>;
+    /// Delete punishment records locked until before locked_before whose last offence was
+    /// before offence_before. Returns the number of records deleted.
+    fn remove_expired_punishments(
+        &self,
+        locked_before: &NaiveDateTime,
+        offence_before: &NaiveDateTime,
+    ) -> Result<u64>;
+    /// Record a deposit made by a payer on behalf of the owner of proof_key
+    fn create_deposit_handoff(
+        &self,
+        user_id: &Uuid,
+        proof_key: &String,
+        payer_proof_key: &String,
+    ) -> Result<()>;
+    /// Get the owner proof key of a deposit made on behalf of another proof key
+    fn get_deposit_handoff_proof_key(&self, user_id: &Uuid) -> Result<Option<String>>;
+    /// Get the payer proof key of a deposit made on behalf of another proof key
+    fn get_deposit_payer_proof_key(&self, user_id: &Uuid) -> Result<Option<String>>;
+    /// Store the encrypted shared key of a deposit made on behalf of another proof key
+    fn update_deposit_handoff(&self, msg: &DepositHandoffMsg) -> Result<()>;
+    /// Get the stored deposit handoffs for a proof key
+    fn get_deposit_handoffs(&self, proof_key: &String) -> Result<Vec<DepositHandoffMsg>>;
+    /// Store the fee quote acknowledged by a deposit
+    fn insert_fee_acknowledgement(
+        &self,
+        user_id: &Uuid,
+        quote_hash: &String,
+        quote: &StateEntityFeeInfoAPI,
+    ) -> Result<()>;
+    /// Get the fee quote acknowledged by a deposit
+    fn get_fee_acknowledgement(&self, user_id: &Uuid) -> Result<Option<StateEntityFeeInfoAPI>>;
+    /// Get the protocol state of a user session. None for sessions created before the state
+    /// was recorded.
+    fn get_session_state(&self, user_id: &Uuid) -> Result<Option<SessionState>>;
+    /// Set the protocol state of a user session
+    fn update_session_state(&self, user_id: &Uuid, state: &SessionState) -> Result<()>;
+    /// Get a page of the user sessions of a set of proof keys, optionally restricted to a
+    /// session state, ordered by proof key then session id. Returns the page and the total
+    /// number of matching sessions.
+    fn get_user_sessions(
+        &self,
+        proof_keys: &Vec<String>,
+        state: Option<SessionState>,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<UserSessionRecord>, i64)>;
 }
 
 pub mod structs {
@@ -290,12 +575,26 @@ pub mod structs {
         pub amount: i64,
     }
 
+    /// Statechain data served by the info routes
+    #[derive(Clone)]
+    pub struct StateChainInfo {
+        pub chain: StateChain,
+        pub amount: i64,
+        /// None once the statecoin has been withdrawn
+        pub tx_backup: Option<Transaction>,
+        pub confirmed: bool,
+        /// Whether the statechain has been moved to the archive
+        pub archived: bool,
+    }
+
     #[derive(Clone, Debug)]
     pub struct TransferBatchData {
         pub state_chains: HashSet<Uuid>,
         pub punished_state_chains: Vec<Uuid>,
         pub start_time: NaiveDateTime,
         pub finalized: bool,
+        /// Row version when read
+        pub version: i64,
     }
 
     #[derive(Clone, Debug)]
@@ -308,6 +607,8 @@ pub mod structs {
     pub struct BackupTxID {
         pub tx: Transaction,
         pub id: Uuid,
+        /// CPFP child tx to broadcast with the backup tx, if one has been submitted
+        pub cpfp: Option<Transaction>,
     }
 
     #[derive(Debug)]
@@ -328,6 +629,8 @@ pub mod structs {
         pub statechain_sig: StateChainSig,
         pub x1: FE,
         pub batch_id: Option<Uuid>,
+        /// Row version when read
+        pub version: i64,
     }
 
     #[derive(Clone)]
@@ -349,6 +652,170 @@ pub mod structs {
         pub eph_key_gen_first_message_party_two: party_two::EphKeyGenFirstMsg,
     }
 
+    /// Checkpoint of an incremental statechain update query
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum UpdatesSince {
+        /// Modified while this root or a later one was the current root
+        RootId(i64),
+        /// Modified at or after this time
+        Time(NaiveDateTime),
+    }
+
+    /// Info API key. Only the sha256 hash of the key is stored.
+    #[derive(Clone, Debug)]
+    pub struct ApiKey {
+        pub key_hash: String,
+        pub label: String,
+        /// Requests per second
+        pub quota: u32,
+        pub revoked: bool,
+        pub created: NaiveDateTime,
+    }
+
+    /// Batch transfer and swap failure record of a statechain or proof key
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct PunishmentRecord {
+        /// Statechain id or proof key
+        pub id: String,
+        /// Failures since the failure count was last reset
+        pub offences: u32,
+        pub last_offence: NaiveDateTime,
+        pub locked_until: NaiveDateTime,
+    }
+
+    /// Escrowed sale of a statechain. The transfer to the buyer is held until the preimage of
+    /// the payment hash is revealed.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct SaleRecord {
+        pub statechain_id: Uuid,
+        pub payment_hash: String,
+        pub expires: NaiveDateTime,
+        /// Held transfer to the buyer
+        pub finalize_data: Option<TransferFinalizeData>,
+        pub preimage: Option<String>,
+        pub finalized: bool,
+    }
+
+    /// Second factor key registration of a statechain. Only applies while the owner proof key
+    /// it was registered with is the statechain tip.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct SecondFactorRecord {
+        pub statechain_id: Uuid,
+        pub proof_key: String,
+        pub auth_key: Option<String>,
+        /// Change waiting for the recovery delay: the new key, or None for removal
+        pub pending_auth_key: Option<String>,
+        /// Time the pending change takes effect. None if no change is pending.
+        pub pending_effective: Option<NaiveDateTime>,
+    }
+
+    /// Vault policy of a statechain: a delay between the start of a transfer, withdrawal or
+    /// migration and its completion. Only applies while the owner proof key it was set with is
+    /// the statechain tip.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct VaultRecord {
+        pub statechain_id: Uuid,
+        pub proof_key: String,
+        /// Delay (seconds). 0 if the vault is disabled.
+        pub delay: u64,
+        /// Shorter delay waiting for the current delay to pass
+        pub pending_delay: Option<u64>,
+        /// Time the pending delay takes effect. None if no change is pending.
+        pub pending_effective: Option<NaiveDateTime>,
+        /// Statechain sig of the operation in progress. None if there is none or it was cancelled.
+        pub pending_sig: Option<StateChainSig>,
+        /// Time the operation in progress was started
+        pub pending_since: Option<NaiveDateTime>,
+    }
+
+    /// Beneficiary designation of a statechain. The receiver of the pending transfer signed by
+    /// transfer_sig can only complete it once the owner has not checked in for
+    /// inactivity_period seconds.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct InheritanceRecord {
+        pub statechain_id: Uuid,
+        /// Pending transfer statechain sig. Its data is the beneficiary proof key.
+        pub transfer_sig: StateChainSig,
+        /// Owner's designation statechain sig
+        pub designation_sig: StateChainSig,
+        pub inactivity_period: u64,
+        pub last_checkin: NaiveDateTime,
+    }
+
+    /// Watchtower registration of an encrypted backup tx
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct WatchtowerRecord {
+        pub id: Uuid,
+        pub auth_key: String,
+        pub version: u64,
+        pub broadcast_height: u32,
+        /// Backup tx encrypted to the watchtower key (hex). None once revoked or confirmed.
+        pub backup_tx: Option<String>,
+        pub revoked: bool,
+    }
+
+    /// Fee ledger entry
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct FeeRecord {
+        pub txid: String,
+        pub fee_type: FeeType,
+        pub statechain_id: Uuid,
+        pub amount: u64,
+        pub collected_at: NaiveDateTime,
+    }
+
+    /// Proof key, statechain and protocol state of a user session
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct UserSessionRecord {
+        pub user_id: Uuid,
+        pub proof_key: String,
+        pub statechain_id: Option<Uuid>,
+        pub state: Option<SessionState>,
+    }
+
+    /// Protocol state of a user session. Transitions are defined in protocol::session.
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+    pub enum SessionState {
+        /// Created by deposit/init. Awaiting key generation.
+        DepositInit,
+        /// Deposit shared key generated. Awaiting co-signing of the backup tx.
+        DepositKeyGen,
+        /// Deposit backup tx co-signed. Awaiting deposit/confirm.
+        DepositSigned,
+        /// Refund of an unconfirmed deposit authorised by deposit/refund. Awaiting co-signing.
+        Refund,
+        /// Created for the receiver of a transfer or migration. Awaiting key generation.
+        TransferInit,
+        /// Shared key reset by ecdsa/keygen/refresh or refresh/second. Awaiting key generation.
+        RefreshInit,
+        /// Session owns a statechain
+        Owner,
+        /// Transfer initiated by transfer/sender. Awaiting co-signing and the receiver.
+        Transfer,
+        /// Withdrawal authorised by withdraw/init. Awaiting co-signing and withdraw/confirm.
+        Withdraw,
+        /// Migration initiated by migrate/init. Awaiting migrate/export.
+        Migrate,
+        /// Statechain transferred to the session of a new owner
+        Transferred,
+        /// Statechain withdrawn
+        Withdrawn,
+        /// Statechain exported to another entity
+        Migrated,
+    }
+
+    /// A sighash prepared for co-signing, with the outpoint and timelock of the spend
+    #[derive(Clone, Debug)]
+    pub struct SighashRecord {
+        pub user_id: Uuid,
+        pub outpoint: OutPoint,
+        pub sig_hash: sha256d::Hash,
+        pub locktime: u32,
+        pub signed: bool,
+        /// False for a backup tx until the deposit or transfer that signed it is finalized
+        pub finalized: bool,
+    }
+
     pub struct ECDSAMasterKeyInput {
         pub party2_public: GE,
         pub paillier_key_pair: party_one::PaillierKeyPair,
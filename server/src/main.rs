@@ -1,16 +1,66 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
 extern crate server_lib;
-use server_lib::{server, Database, PGDatabase};
+use server_lib::{server, storage::{encryption, snapshot}, Database, PGDatabase, ServerDatabase};
 
 fn main() {
+    // Maintenance subcommands: server_exec snapshot <file> | server_exec restore <file> |
+    // server_exec reencrypt
+    let args: Vec<String> = std::env::args().collect();
+    match (args.get(1).map(String::as_str), args.get(2)) {
+        (Some("snapshot"), Some(path)) => {
+            snapshot::run_snapshot(path)
+                .map_err(|e| {dbg!(format!("error writing snapshot: {}", &e)); e})
+                .unwrap();
+            return;
+        },
+        (Some("restore"), Some(path)) => {
+            snapshot::run_restore(path)
+                .map_err(|e| {dbg!(format!("error restoring snapshot: {}", &e)); e})
+                .unwrap();
+            return;
+        },
+        (Some("reencrypt"), _) => {
+            encryption::run_reencrypt()
+                .map_err(|e| {dbg!(format!("error re-encrypting DB: {}", &e)); e})
+                .unwrap();
+            return;
+        },
+        (Some(cmd), _) => panic!("unknown command {}. usage: server_exec [snapshot|restore <file>|reencrypt]", cmd),
+        (None, _) => (),
+    }
 
-    server::get_server::<PGDatabase, PGDatabase>(
+    let rocket = server::get_server::<ServerDatabase, PGDatabase>(
         None,
-        PGDatabase::get_new(),
+        ServerDatabase::get_new(),
         PGDatabase::get_new(),
     )
     .map_err(|e| {dbg!(format!("error initializing server: {}", &e)); e})
-    .unwrap()
-    .launch();
+    .unwrap();
+    let sc_entity = server::state_entity(&rocket)
+        .map_err(|e| {dbg!(format!("error initializing server: {}", &e)); e})
+        .unwrap();
+
+    server_lib::protocol::punishment::spawn_cleanup(sc_entity)
+        .map_err(|e| {dbg!(format!("error starting punishment cleanup: {}", &e)); e})
+        .unwrap();
+
+    server_lib::protocol::archive::spawn_archival(sc_entity)
+        .map_err(|e| {dbg!(format!("error starting statechain archival: {}", &e)); e})
+        .unwrap();
+
+    server_lib::protocol::session_cleanup::spawn_session_cleanup(sc_entity)
+        .map_err(|e| {dbg!(format!("error starting session cleanup: {}", &e)); e})
+        .unwrap();
+
+    server_lib::protocol::root_update::spawn_root_updates(sc_entity)
+        .map_err(|e| {dbg!(format!("error starting root updates: {}", &e)); e})
+        .unwrap();
+
+    #[cfg(feature = "grpc")]
+    server_lib::grpc::spawn_shared(sc_entity)
+        .map_err(|e| {dbg!(format!("error initializing grpc server: {}", &e)); e})
+        .unwrap();
+
+    rocket.launch();
 }
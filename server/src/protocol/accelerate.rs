@@ -0,0 +1,335 @@
+//! StateEntity Accelerate
+//!
+//! Acceleration of withdraw txs stuck in the mempool. The State Entity spends the withdraw tx
+//! fee output, paid to one of its fee addresses, in a child tx (CPFP) so that the withdraw tx
+//! and child together pay the fee rate requested by the owner. The child tx is signed and
+//! broadcast by the bitcoind wallet, which must hold the fee address keys.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::config::AccelerateConfig;
+use crate::error::SEError;
+use crate::protocol::session::SessionEvent;
+use crate::protocol::util::Utilities;
+use crate::server::StateChainEntity;
+use crate::Database;
+use shared_lib::structs::{WithdrawAccelerateAPI, WithdrawAccelerateMsg};
+use shared_lib::util::tx_cpfp_build;
+
+use bitcoin::{consensus, Address, Transaction};
+use cfg_if::cfg_if;
+use chrono::Utc;
+use std::str::FromStr;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Mempool entry of a withdraw tx
+#[derive(Debug, Clone)]
+pub struct MempoolTx {
+    pub vsize: u64,
+    /// Fee paid by the tx itself
+    pub fee: u64,
+    /// Time (unix seconds) the tx entered the mempool
+    pub time: u64,
+}
+
+/// Acceleration policy: check that a withdraw tx in the mempool may be accelerated to fee_rate
+/// at time now. prev_fee_rate is the fee rate of a previous acceleration of the tx, if any.
+pub fn accelerate_policy(
+    config: &AccelerateConfig,
+    parent: &MempoolTx,
+    now: u64,
+    fee_rate: u64,
+    prev_fee_rate: Option<u64>,
+) -> Result<()> {
+    if fee_rate > config.max_fee_rate {
+        return Err(SEError::Generic(format!(
+            "Fee rate {} exceeds the maximum acceleration fee rate {}",
+            fee_rate, config.max_fee_rate
+        )));
+    }
+    if now < parent.time + config.min_wait {
+        return Err(SEError::Generic(format!(
+            "Withdraw tx must be in the mempool for {} seconds before it is accelerated",
+            config.min_wait
+        )));
+    }
+    if parent.fee >= fee_rate * parent.vsize {
+        return Err(SEError::Generic(format!(
+            "Withdraw tx already pays fee rate {}",
+            fee_rate
+        )));
+    }
+    if let Some(prev) = prev_fee_rate {
+        if prev >= fee_rate {
+            return Err(SEError::Generic(format!(
+                "Withdraw tx already accelerated to fee rate {}",
+                prev
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Output of tx paying one of the fee addresses, and the address
+pub fn fee_output(tx: &Transaction, fee_addresses: &[Address]) -> Option<(u32, Address)> {
+    tx.output.iter().enumerate().find_map(|(vout, output)| {
+        fee_addresses
+            .iter()
+            .find(|addr| addr.script_pubkey() == output.script_pubkey)
+            .map(|addr| (vout as u32, addr.clone()))
+    })
+}
+
+/// Build the unsigned child tx accelerating tx_withdraw to fee_rate, spending its fee output
+/// back to the fee address. The child tx fee may not exceed max_fee_share of the fee output.
+pub fn accelerate_tx_build(
+    config: &AccelerateConfig,
+    tx_withdraw: &Transaction,
+    fee_addresses: &[Address],
+    parent: &MempoolTx,
+    now: u64,
+    fee_rate: u64,
+    prev_fee_rate: Option<u64>,
+) -> Result<Transaction> {
+    accelerate_policy(config, parent, now, fee_rate, prev_fee_rate)?;
+
+    let (vout, fee_address) = fee_output(tx_withdraw, fee_addresses).ok_or(SEError::Generic(
+        String::from("Withdraw tx has no fee output"),
+    ))?;
+    let fee_value = tx_withdraw.output[vout as usize].value;
+    let tx_cpfp = tx_cpfp_build(tx_withdraw, &vout, &parent.fee, &fee_rate, &fee_address)?;
+
+    let child_fee = fee_value - tx_cpfp.output[0].value;
+    if child_fee * 100 > fee_value * config.max_fee_share {
+        return Err(SEError::Generic(format!(
+            "Acceleration to fee rate {} costs {} of the {} fee output",
+            fee_rate, child_fee, fee_value
+        )));
+    }
+    Ok(tx_cpfp)
+}
+
+/// StateChain Accelerate protocol trait
+pub trait Accelerate {
+    /// Owner request to accelerate the withdraw tx of a withdrawn statecoin:
+    ///     - Check the withdraw tx is in the mempool and the acceleration policy
+    ///     - Sign and broadcast a child tx spending the withdraw tx fee output
+    ///     - Return the child txid
+    fn withdraw_accelerate(&self, accelerate_msg: WithdrawAccelerateMsg)
+        -> Result<WithdrawAccelerateAPI>;
+}
+
+impl Accelerate for SCE {
+    fn withdraw_accelerate(
+        &self,
+        accelerate_msg: WithdrawAccelerateMsg,
+    ) -> Result<WithdrawAccelerateAPI> {
+        if !self.config.accelerate.enabled || self.config.bitcoind.is_empty() {
+            return Err(SEError::Generic(String::from(
+                "Withdraw tx acceleration is not enabled",
+            )));
+        }
+        let user_id = accelerate_msg.shared_key_id;
+        let fee_rate = accelerate_msg.fee_rate;
+        self.check_user_auth(&user_id)?;
+        let next_state = self.check_session_event(&user_id, SessionEvent::WithdrawAccelerate)?;
+
+        let tx_withdraw = self.database.get_tx_withdraw(user_id)?;
+        let txid = tx_withdraw.txid();
+        let fee_addresses = self
+            .config
+            .fee_address
+            .replace(" ", "")
+            .split(",")
+            .map(|addr| Address::from_str(addr).map_err(|e| SEError::Generic(e.to_string())))
+            .collect::<Result<Vec<Address>>>()?;
+
+        let rpc_path_parts: Vec<&str> = self.config.bitcoind.split('@').collect();
+        let rpc_cred: Vec<&str> = rpc_path_parts[0].split(':').collect();
+        if rpc_path_parts.len() != 2 || rpc_cred.len() != 2 {
+            return Err(SEError::Generic(String::from("Invalid bitcoind RPC path")));
+        }
+        cfg_if! {
+            if #[cfg(any(test,feature="mockbitcoinrpc"))]{
+                use shared_lib::mocks::mock_rpc_client::MockBitcoinClient;
+                let mut rpc = MockBitcoinClient::new();
+            } else {
+                use bitcoincore_rpc::{Auth, Client, RpcApi};
+                let rpc = Client::new(rpc_path_parts[1].to_string(),
+                              Auth::UserPass(rpc_cred[0].to_string(),
+                                             rpc_cred[1].to_string()))
+                    .map_err(|e| SEError::Generic(e.to_string()))?;
+            }
+        }
+
+        // Accelerations of a tx are serialized so that each replaces the previous child tx
+        let mut accelerations = self.accelerations.lock()?;
+        let entry = rpc.get_mempool_entry(&txid).map_err(|e| {
+            SEError::Generic(format!("Withdraw tx {} not in the mempool: {:?}", txid, e))
+        })?;
+        let parent = MempoolTx {
+            vsize: entry.vsize,
+            fee: entry.fees.base.as_sat(),
+            time: entry.time,
+        };
+        let tx_cpfp = accelerate_tx_build(
+            &self.config.accelerate,
+            &tx_withdraw,
+            &fee_addresses,
+            &parent,
+            Utc::now().timestamp() as u64,
+            fee_rate,
+            accelerations.get(&txid.to_string()).cloned(),
+        )?;
+
+        let signed = rpc
+            .sign_raw_transaction_with_wallet(&tx_cpfp, None, None)
+            .map_err(|e| SEError::Generic(format!("Error signing CPFP tx: {:?}", e)))?;
+        if !signed.complete {
+            return Err(SEError::Generic(String::from(
+                "Fee output not held by the bitcoind wallet",
+            )));
+        }
+        let tx_cpfp: Transaction = consensus::deserialize(&signed.hex)
+            .map_err(|e| SEError::Generic(e.to_string()))?;
+        rpc.send_raw_transaction(&consensus::serialize(&tx_cpfp))
+            .map_err(|e| SEError::Generic(format!("Error sending CPFP tx: {:?}", e)))?;
+
+        accelerations.insert(txid.to_string(), fee_rate);
+        self.update_session_state(&user_id, next_state)?;
+
+        info!(
+            "ACCELERATE: Withdraw txid {} accelerated to fee rate {} by CPFP txid {}. Shared Key ID: {}",
+            txid,
+            fee_rate,
+            tx_cpfp.txid(),
+            user_id
+        );
+
+        Ok(WithdrawAccelerateAPI {
+            txid: txid.to_string(),
+            cpfp_txid: tx_cpfp.txid().to_string(),
+            fee_rate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use crate::structs::SessionState;
+    use bitcoin::{OutPoint, TxIn, TxOut};
+    use shared_lib::mocks::mock_rpc_client::{MOCK_MEMPOOL_FEE_RATE, MOCK_MEMPOOL_VSIZE};
+    use uuid::Uuid;
+
+    static FEE_ADDRESS: &str = "bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x";
+    static WITHDRAW_ADDRESS: &str = "bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8";
+
+    fn tx_withdraw(fee_value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: bitcoin::Script::default(),
+                sequence: 0xFFFFFFFF,
+                witness: Vec::new(),
+            }],
+            output: vec![
+                TxOut {
+                    value: 1000000,
+                    script_pubkey: Address::from_str(WITHDRAW_ADDRESS).unwrap().script_pubkey(),
+                },
+                TxOut {
+                    value: fee_value,
+                    script_pubkey: Address::from_str(FEE_ADDRESS).unwrap().script_pubkey(),
+                },
+            ],
+        }
+    }
+
+    fn mempool_tx() -> MempoolTx {
+        MempoolTx {
+            vsize: MOCK_MEMPOOL_VSIZE,
+            fee: MOCK_MEMPOOL_VSIZE * MOCK_MEMPOOL_FEE_RATE,
+            time: 0,
+        }
+    }
+
+    #[test]
+    fn test_accelerate_policy() {
+        let config = AccelerateConfig::default();
+        let now = config.min_wait;
+        assert!(accelerate_policy(&config, &mempool_tx(), now, 10, None).is_ok());
+        // Fee rate above the maximum
+        assert!(accelerate_policy(&config, &mempool_tx(), now, config.max_fee_rate + 1, None).is_err());
+        // Not in the mempool for long enough
+        assert!(accelerate_policy(&config, &mempool_tx(), now - 1, 10, None).is_err());
+        // Withdraw tx already pays the fee rate
+        assert!(accelerate_policy(&config, &mempool_tx(), now, MOCK_MEMPOOL_FEE_RATE, None).is_err());
+        // Already accelerated to the fee rate
+        assert!(accelerate_policy(&config, &mempool_tx(), now, 10, Some(10)).is_err());
+        assert!(accelerate_policy(&config, &mempool_tx(), now, 11, Some(10)).is_ok());
+    }
+
+    #[test]
+    fn test_accelerate_tx_build() {
+        let config = AccelerateConfig::default();
+        let fee_addresses = vec![Address::from_str(FEE_ADDRESS).unwrap()];
+        let tx = tx_withdraw(10000);
+        let tx_cpfp = accelerate_tx_build(&config, &tx, &fee_addresses, &mempool_tx(), config.min_wait, 10, None).unwrap();
+        assert_eq!(tx_cpfp.input[0].previous_output.txid, tx.txid());
+        assert_eq!(tx_cpfp.input[0].previous_output.vout, 1);
+        assert_eq!(tx_cpfp.output[0].script_pubkey, fee_addresses[0].script_pubkey());
+        // Package pays the fee rate
+        let child_fee = 10000 - tx_cpfp.output[0].value;
+        assert_eq!(child_fee + mempool_tx().fee, 10 * (MOCK_MEMPOOL_VSIZE + shared_lib::util::CPFP_CHILD_VSIZE));
+
+        // Child fee exceeds the share of the fee output
+        assert!(accelerate_tx_build(&config, &tx_withdraw(4000), &fee_addresses, &mempool_tx(), config.min_wait, 10, None).is_err());
+        // No fee output
+        let mut tx = tx_withdraw(10000);
+        tx.output.pop();
+        assert!(accelerate_tx_build(&config, &tx, &fee_addresses, &mempool_tx(), config.min_wait, 10, None).is_err());
+    }
+
+    #[test]
+    fn test_withdraw_accelerate() {
+        let user_id = Uuid::new_v4();
+        let tx = tx_withdraw(20000);
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth().returning(|_| Ok(String::default()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Withdrawn)));
+        db.expect_get_tx_withdraw().returning(move |_| Ok(tx.clone()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        let msg = WithdrawAccelerateMsg { shared_key_id: user_id, fee_rate: 10 };
+
+        // Disabled by default
+        assert!(sc_entity.withdraw_accelerate(msg.clone()).is_err());
+
+        sc_entity.config.accelerate.enabled = true;
+        sc_entity.config.bitcoind = String::from("user:pass@localhost:18443");
+        sc_entity.config.fee_address = String::from(FEE_ADDRESS);
+        let res = sc_entity.withdraw_accelerate(msg.clone()).unwrap();
+        assert_eq!(res.txid, tx_withdraw(20000).txid().to_string());
+        assert_eq!(res.fee_rate, 10);
+
+        // A further acceleration must be to a higher fee rate
+        assert!(sc_entity.withdraw_accelerate(msg).is_err());
+        let msg = WithdrawAccelerateMsg { shared_key_id: user_id, fee_rate: 20 };
+        assert!(sc_entity.withdraw_accelerate(msg).is_ok());
+    }
+}
@@ -0,0 +1,211 @@
+//! StateEntity API Keys
+//!
+//! Optional API keys for the read-only info routes. Explorers and other heavy readers are
+//! issued a key with its own request quota so that they do not share (and exhaust) the fast
+//! rate limit used by wallets. Keys are issued and revoked by the operator with the admin key.
+//! Only the sha256 hash of a key is stored.
+
+pub use super::super::Result;
+
+extern crate shared_lib;
+use super::util::RateLimiter;
+use crate::error::{DBErrorType, SEError};
+use crate::structs::ApiKey;
+use crate::{server::StateChainEntity, Database};
+use shared_lib::structs::*;
+
+use bitcoin::hashes::{sha256, Hash};
+use cfg_if::cfg_if;
+use chrono::Utc;
+use governor::{clock::DefaultClock, state::{InMemoryState, NotKeyed}, Quota};
+use rand::Rng;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+//Generics cannot be used in Rocket State, therefore we define the concrete
+//type of StateChainEntity here
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Rate limiter of a single API key
+pub type ApiKeyLimiter = governor::RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+
+/// Hash under which an API key (or the admin key) is stored and compared
+pub fn api_key_hash(key: &str) -> String {
+    sha256::Hash::hash(key.as_bytes()).to_string()
+}
+
+/// StateChain Entity API key management
+pub trait ApiKeys {
+    /// API: Issue a new info API key with a quota (requests per second). Requires the admin key.
+    fn issue_api_key(&self, issue_msg: ApiKeyIssueMsg) -> Result<ApiKeyAPI>;
+
+    /// API: Revoke an info API key. Requires the admin key.
+    fn revoke_api_key(&self, revoke_msg: ApiKeyRevokeMsg) -> Result<()>;
+
+    /// Check the rate limit of an info request. Requests with an API key are limited by the
+    /// key's quota, requests without by the fast rate limit (or rejected if keys are required).
+    fn check_rate_info(&self, api_key: &Option<String>) -> Result<()>;
+}
+
+impl SCE {
+    /// Check admin_key is the state entity admin key
+    pub fn check_admin_key(&self, admin_key: &String) -> Result<()> {
+        match &self.config.admin_key {
+            Some(k) if api_key_hash(k) == api_key_hash(admin_key) => Ok(()),
+            _ => Err(SEError::AuthError),
+        }
+    }
+
+    fn get_api_key_limiter(&self, key_hash: &String) -> Result<Arc<ApiKeyLimiter>> {
+        let mut guard = self.api_key_limiters.lock()?;
+        if let Some(limiter) = guard.get(key_hash) {
+            return Ok(limiter.clone());
+        }
+        let api_key = match self.database.get_api_key(key_hash) {
+            Ok(k) => k,
+            Err(SEError::DBError(DBErrorType::NoDataForID, _)) => return Err(SEError::AuthError),
+            Err(e) => return Err(e),
+        };
+        if api_key.revoked {
+            return Err(SEError::AuthError);
+        }
+        let quota = NonZeroU32::new(api_key.quota)
+            .ok_or(SEError::Generic(format!("API key {} has zero quota", api_key.label)))?;
+        let limiter = Arc::new(governor::RateLimiter::direct(Quota::per_second(quota)));
+        guard.insert(key_hash.clone(), limiter.clone());
+        Ok(limiter)
+    }
+}
+
+impl ApiKeys for SCE {
+    fn issue_api_key(&self, issue_msg: ApiKeyIssueMsg) -> Result<ApiKeyAPI> {
+        self.check_admin_key(&issue_msg.admin_key)?;
+        if issue_msg.quota == 0 {
+            return Err(SEError::Generic(String::from("API key quota must be non-zero")));
+        }
+
+        let mut rng = rand::thread_rng();
+        let key = hex::encode(rng.gen::<[u8; 32]>());
+        self.database.insert_api_key(&ApiKey {
+            key_hash: api_key_hash(&key),
+            label: issue_msg.label.clone(),
+            quota: issue_msg.quota,
+            revoked: false,
+            created: Utc::now().naive_utc(),
+        })?;
+
+        info!("API KEY: issued key for {} with quota {}", issue_msg.label, issue_msg.quota);
+        Ok(ApiKeyAPI {
+            key,
+            label: issue_msg.label,
+            quota: issue_msg.quota,
+        })
+    }
+
+    fn revoke_api_key(&self, revoke_msg: ApiKeyRevokeMsg) -> Result<()> {
+        self.check_admin_key(&revoke_msg.admin_key)?;
+        let key_hash = api_key_hash(&revoke_msg.key);
+        self.database.revoke_api_key(&key_hash)?;
+        self.api_key_limiters.lock()?.remove(&key_hash);
+        info!("API KEY: revoked key {}", key_hash);
+        Ok(())
+    }
+
+    fn check_rate_info(&self, api_key: &Option<String>) -> Result<()> {
+        match api_key {
+            None => {
+                if self.config.info_api_key_required {
+                    return Err(SEError::AuthError);
+                }
+                self.check_rate_fast("info")
+            }
+            Some(key) => {
+                let key_hash = api_key_hash(key);
+                let limiter = self.get_api_key_limiter(&key_hash)?;
+                limiter.check().map_err(|e| {
+                    SEError::RateLimitError(format!("{} for API key {} ", SEError::from(e), key_hash))
+                })?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use mockall::predicate;
+
+    #[test]
+    fn test_issue_and_check_api_key() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_insert_api_key().times(1).returning(|_| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        let issue_msg = ApiKeyIssueMsg {
+            admin_key: String::from("admin"),
+            label: String::from("explorer"),
+            quota: 1,
+        };
+
+        // Admin key not configured
+        match sc_entity.issue_api_key(issue_msg.clone()) {
+            Err(SEError::AuthError) => (),
+            res => assert!(false, "expected AuthError, got {:?}", res),
+        }
+
+        sc_entity.config.admin_key = Some(String::from("admin"));
+        let api_key = sc_entity.issue_api_key(issue_msg).unwrap();
+        assert_eq!(api_key.label, "explorer");
+
+        // Key is looked up once then cached
+        let key_hash = api_key_hash(&api_key.key);
+        let mut db = MockDatabase::new();
+        db.expect_get_api_key()
+            .with(predicate::eq(key_hash.clone()))
+            .times(1)
+            .returning(move |h| {
+                Ok(ApiKey {
+                    key_hash: h.clone(),
+                    label: String::from("explorer"),
+                    quota: 1,
+                    revoked: false,
+                    created: Utc::now().naive_utc(),
+                })
+            });
+        db.expect_get_api_key()
+            .returning(|_| Err(SEError::DBError(DBErrorType::NoDataForID, String::from("API key"))));
+        sc_entity.database = db;
+
+        assert!(sc_entity.check_rate_info(&Some(api_key.key.clone())).is_ok());
+        // Quota of 1 per second exceeded
+        match sc_entity.check_rate_info(&Some(api_key.key.clone())) {
+            Err(SEError::RateLimitError(_)) => (),
+            res => assert!(false, "expected RateLimitError, got {:?}", res),
+        }
+        // Unknown key
+        match sc_entity.check_rate_info(&Some(String::from("unknown"))) {
+            Err(SEError::AuthError) => (),
+            res => assert!(false, "expected AuthError, got {:?}", res),
+        }
+
+        // No key
+        assert!(sc_entity.check_rate_info(&None).is_ok());
+        sc_entity.config.info_api_key_required = true;
+        match sc_entity.check_rate_info(&None) {
+            Err(SEError::AuthError) => (),
+            res => assert!(false, "expected AuthError, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! StateEntity Archive
+//!
+//! Archival of finished statechains.
+//!
+//! Withdrawn statechains, and statechains whose backup tx has been confirmed by the watcher,
+//! are moved from the statechain and backup tx tables into the archive once they have not been
+//! updated for the retention period. Archived statechains are still served by the info routes,
+//! flagged as archived, until they are purged.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::protocol::ping::Ping;
+use crate::server::StateChainEntity;
+use crate::Database;
+
+use cfg_if::cfg_if;
+use chrono::{Duration, Utc};
+use std::{thread, time};
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Maximum number of statechains archived per DB transaction
+const ARCHIVE_BATCH_SIZE: i64 = 1000;
+
+impl SCE {
+    /// Move finished statechains past the retention period into the archive and purge
+    /// archived statechains past purge_after. Returns the number of statechains archived.
+    pub fn archive_statechains(&self) -> Result<usize> {
+        let config = &self.config.archive;
+        let now = Utc::now().naive_utc();
+        let updated_before = now - Duration::seconds(config.retention as i64);
+
+        let mut count = 0;
+        loop {
+            let archived = self
+                .database
+                .archive_statechains(&updated_before, ARCHIVE_BATCH_SIZE)?;
+            // Expired statecoins are no longer in circulation
+            let mut guard = self.coin_value_info.as_ref().lock()?;
+            for (statechain_id, amount) in &archived {
+                if *amount > 0 {
+                    if let Err(e) = guard.decrement(amount) {
+                        warn!("ARCHIVE: State Chain ID {} coin value info: {}", statechain_id, e);
+                    }
+                }
+            }
+            drop(guard);
+            count += archived.len();
+            if (archived.len() as i64) < ARCHIVE_BATCH_SIZE {
+                break;
+            }
+        }
+        if count > 0 {
+            info!("ARCHIVE: Archived {} finished statechains.", count);
+        }
+
+        if config.purge_after > 0 {
+            let archived_before = now - Duration::seconds(config.purge_after as i64);
+            let purged = self.database.purge_archived_statechains(&archived_before)?;
+            if purged > 0 {
+                info!("ARCHIVE: Purged {} archived statechains.", purged);
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Run statechain archival on a handle sharing the state entity, if the archive interval is
+/// non-zero
+pub fn spawn_archival(sc_entity: &SCE) -> Result<Option<thread::JoinHandle<()>>> {
+    let interval = sc_entity.config.archive.interval;
+    if interval == 0 {
+        return Ok(None);
+    }
+    let sc_entity = sc_entity.share()?;
+    Ok(Some(thread::spawn(move || loop {
+        if let Err(e) = sc_entity.archive_statechains() {
+            warn!("ARCHIVE: Archival failed: {}", e);
+        }
+        let _ = sc_entity.heartbeat("statechain_archival", time::Duration::from_secs(interval));
+        thread::sleep(time::Duration::from_secs(interval));
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_archive_statechains() {
+        let expired_id = Uuid::new_v4();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_archive_statechains()
+            .withf(|updated_before, limit| {
+                let age = Utc::now().naive_utc() - *updated_before;
+                age >= Duration::seconds(86400) && *limit == ARCHIVE_BATCH_SIZE
+            })
+            .times(1)
+            .returning(move |_, _| Ok(vec![(Uuid::new_v4(), 0), (expired_id, 10000)]));
+        db.expect_purge_archived_statechains()
+            .times(1)
+            .returning(|_| Ok(1));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.archive.retention = 86400;
+        sc_entity.config.archive.purge_after = 86400;
+        {
+            let mut guard = sc_entity.coin_value_info.lock().unwrap();
+            guard.increment(&10000);
+            guard.increment(&10000);
+        }
+
+        assert_eq!(sc_entity.archive_statechains().unwrap(), 2);
+        // the expired statecoin is removed from the coin value info
+        let guard = sc_entity.coin_value_info.lock().unwrap();
+        assert_eq!(guard.values.get(&10000).unwrap().get(), 1);
+    }
+
+    #[test]
+    fn test_archive_statechains_no_purge() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_archive_statechains().returning(|_, _| Ok(vec![]));
+        db.expect_purge_archived_statechains().times(0);
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.archive.purge_after = 0;
+        assert_eq!(sc_entity.archive_statechains().unwrap(), 0);
+    }
+}
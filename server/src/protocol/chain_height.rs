@@ -0,0 +1,129 @@
+//! StateEntity Chain Height
+//!
+//! Current block height of the chain the backup txs are timelocked on.
+//!
+//! Batch transfer lifetimes and punishment durations can be configured in blocks so that they
+//! do not drift from the backup tx timelocks. The height is tracked by the bitcoind watcher when
+//! it is running and read from the Electrum server otherwise. Statecoin locks are wall-clock
+//! times, so durations in blocks are converted at the target block interval where a time is
+//! required.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::config::DurationUnit;
+use crate::server::StateChainEntity;
+
+use cfg_if::cfg_if;
+use electrumx_client::{electrumx_client::ElectrumxClient, interface::Electrumx};
+use shared_lib::mocks::mock_electrum::MockElectrum;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Target block interval in seconds
+pub const BLOCK_INTERVAL: u64 = 600;
+
+/// Time after which a tracked height is read from Electrum again
+const HEIGHT_MAX_AGE: Duration = Duration::from_secs(120);
+
+impl DurationUnit {
+    /// Wall-clock seconds of a duration in this unit
+    pub fn seconds(&self, duration: u64) -> u64 {
+        match self {
+            DurationUnit::Seconds => duration,
+            DurationUnit::Blocks => duration * BLOCK_INTERVAL,
+        }
+    }
+}
+
+/// Latest known block height, shared by the watcher and the state entity
+#[derive(Debug, Default)]
+pub struct ChainHeight {
+    height: AtomicU64,
+    updated: Mutex<Option<Instant>>,
+}
+
+impl ChainHeight {
+    pub fn set(&self, height: u64) {
+        self.height.store(height, Ordering::SeqCst);
+        *self.updated.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Tracked height if it was updated within max_age
+    pub fn get(&self, max_age: Duration) -> Option<u64> {
+        match *self.updated.lock().unwrap() {
+            Some(t) if t.elapsed() <= max_age => Some(self.height.load(Ordering::SeqCst)),
+            _ => None,
+        }
+    }
+}
+
+impl SCE {
+    /// Current block height: the height tracked by the watcher, or the Electrum server tip if
+    /// the watcher is not running
+    pub fn current_height(&self) -> Result<u64> {
+        if let Some(height) = self.chain_height.get(HEIGHT_MAX_AGE) {
+            return Ok(height);
+        }
+        let mut electrum: Box<dyn Electrumx> = if self.config.testing_mode {
+            Box::new(MockElectrum::new())
+        } else {
+            Box::new(ElectrumxClient::new(self.config.electrum_server.clone())?)
+        };
+        let height = electrum.get_tip_header()?.height as u64;
+        self.chain_height.set(height);
+        Ok(height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+
+    #[test]
+    fn test_duration_unit_seconds() {
+        assert_eq!(DurationUnit::Seconds.seconds(180), 180);
+        assert_eq!(DurationUnit::Blocks.seconds(3), 1800);
+    }
+
+    #[test]
+    fn test_chain_height() {
+        let chain_height = ChainHeight::default();
+        assert_eq!(chain_height.get(HEIGHT_MAX_AGE), None);
+        chain_height.set(700000);
+        assert_eq!(chain_height.get(HEIGHT_MAX_AGE), Some(700000));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(chain_height.get(Duration::from_millis(1)), None);
+    }
+
+    fn test_db() -> MockDatabase {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db
+    }
+
+    #[test]
+    fn test_current_height() {
+        let sc_entity = test_sc_entity(test_db(), None, None, None, None);
+        // tracked height is used while fresh
+        sc_entity.chain_height.set(700000);
+        assert_eq!(sc_entity.current_height().unwrap(), 700000);
+
+        // Electrum tip otherwise
+        let sc_entity = test_sc_entity(test_db(), None, None, None, None);
+        assert_eq!(sc_entity.current_height().unwrap(), 12345);
+        assert_eq!(sc_entity.chain_height.get(HEIGHT_MAX_AGE), Some(12345));
+    }
+}
@@ -9,13 +9,14 @@ use shared_lib::{
     blinded_token::{
         BSTSenderData, BlindedSpendSignature, BlindedSpendToken, BlindedSpentTokenMessage,
     },
-    state_chain::StateChainSig,
+    state_chain::{sign_swap_report, StateChainSig},
     structs::*,
     swap_data::*,
 };
 extern crate shared_lib;
 use crate::server::StateChainEntity;
 use crate::config::ConductorConfig;
+use crate::protocol::punishment::Offence;
 use crate::protocol::transfer_batch::BatchTransfer;
 use crate::protocol::withdraw::Withdraw;
 use crate::storage::Storage;
@@ -25,20 +26,17 @@ use cfg_if::cfg_if;
 use curv::FE;
 use mockall::predicate::*;
 use mockall::*;
-use rocket::State;
-use rocket_contrib::json::Json;
 use std::collections::{HashMap, HashSet, LinkedList};
 use std::iter::FromIterator;
 use std::str::FromStr;
 #[cfg(test)]
 use std::sync::{Arc, Mutex};
 use uuid::Uuid;
-use rocket_okapi::openapi;
 use rocket_okapi::JsonSchema;
 use schemars;
 use bitcoin::secp256k1::Signature;
+use bitcoin::{Address, Transaction, Txid};
 use chrono::{NaiveDateTime, Utc, Duration,Timelike};
-use crate::protocol::util::RateLimiter;
 use versions::Versioning;
 
 const MIN_AMOUNT: u64 = 100000; // bitcoin tx nlocktime cutoff
@@ -56,8 +54,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
     }
 }
 
@@ -132,6 +130,12 @@ pub trait Conductor {
     fn get_group_info(&self) -> Result<HashMap<SwapGroup,GroupStatus>>;
 
     fn update_swap_info(&self) -> Result<bool>;
+
+    /// API: Get the swap fee terms and the number of registrations and swaps in progress
+    fn get_swap_stats(&self) -> Result<SwapStatsAPI>;
+
+    /// API: Get the report of a completed swap, signed by the identity key if set
+    fn get_swap_report(&self, swap_id: &Uuid) -> Result<SignedSwapReport>;
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -150,8 +154,12 @@ pub struct Scheduler {
     punishment_timeout: u32,
     //punished coins with expiry time
     punishment_map: HashMap<Uuid, NaiveDateTime>,
+    //coins that failed a swap since the punishment policy was last applied
+    swap_offenders: Vec<Uuid>,
     //permitted swap size groups
     permitted_groups: Vec<u64>,
+    //swap fee per participant
+    swap_fee: u64,
     //State chain id to requested swap size map
     statechain_swap_size_map: BisetMap<Uuid, u64>,
     //A map of state chain registereds for swap to amount
@@ -179,13 +187,6 @@ pub struct Scheduler {
 
 impl Scheduler {
     pub fn new(config: &ConductorConfig) -> Self {
-        let permitted_groups_vec: Vec<&str> = config.permitted_groups.split(",").collect();
-        let mut permitted_groups_int: Vec<u64> = vec![];
-        for group_string in permitted_groups_vec {
-            let group_int: u64 = group_string.to_string().parse().unwrap();
-            permitted_groups_int.push(group_int);
-        }
-
         Self {
             utxo_timeout: config.utxo_timeout.clone(),
             #[cfg(not(test))]
@@ -200,7 +201,9 @@ impl Scheduler {
             #[cfg(test)]
             punishment_timeout: 24, 
             punishment_map: HashMap::<Uuid, NaiveDateTime>::new(),
-            permitted_groups: permitted_groups_int,
+            swap_offenders: Vec::new(),
+            permitted_groups: config.permitted_groups(),
+            swap_fee: config.swap_fee,
             statechain_swap_size_map: BisetMap::<Uuid, u64>::new(),
             statechain_amount_map: BisetMap::<Uuid, u64>::new(),
             group_info_map: HashMap::<SwapGroup, GroupStatus>::new(),
@@ -240,6 +243,21 @@ impl Scheduler {
         Ok(())
     }
 
+    //Take the coins that failed a swap since the last call
+    pub fn take_swap_offenders(&mut self) -> Vec<Uuid> {
+        std::mem::take(&mut self.swap_offenders)
+    }
+
+    //Set the punishment expiry time of a coin
+    pub fn set_punishment(&mut self, statechain_id: &Uuid, locked_until: NaiveDateTime) {
+        self.punishment_map.insert(*statechain_id, locked_until);
+    }
+
+    pub fn remove_expired_punishments(&mut self) {
+        let now: NaiveDateTime = Utc::now().naive_utc();
+        self.punishment_map.retain(|_, t| *t > now);
+    }
+
     pub fn get_swap_id(&self, statechain_id: &Uuid) -> Option<Uuid> {
         self.swap_id_map.get(statechain_id).cloned()
     }
@@ -513,6 +531,7 @@ impl Scheduler {
                         amount,
                         time_out: self.group_timeout as u64,
                         statechain_ids: ids_for_swap.clone(),
+                        fee: self.swap_fee,
                     };
 
                     let si = SwapInfo {
@@ -589,6 +608,7 @@ impl Scheduler {
                                     let now: NaiveDateTime = Utc::now().naive_utc();
                                     let t = now + Duration::seconds(self.punishment_timeout as i64);
                                     self.punishment_map.insert(*sc_id,t);
+                                    self.swap_offenders.push(*sc_id);
                                 }
                             }
                         }
@@ -727,6 +747,16 @@ impl Scheduler {
        self.shutdown_requested && !self.swaps_ongoing()
     }
 
+    /// Number of statecoins registered for a swap and number of swaps in progress
+    pub fn stats(&self) -> (u64, u64) {
+        let ongoing = self
+            .swap_info_map
+            .values()
+            .filter(|info| info.status != SwapStatus::End)
+            .count();
+        (self.statechain_amount_map.len() as u64, ongoing as u64)
+    }
+
     pub fn swaps_ongoing(&self) -> bool {
         for value in self.swap_info_map.values() {
             if value.status != SwapStatus::End {
@@ -883,6 +913,138 @@ pub fn generate_blind_spend_signatures(
     Ok(scid_bst_sig_map)
 }
 
+/// Check a fee deposit tx pays at least fee to the fee addresses
+pub fn verify_swap_fee_tx(tx: &Transaction, fee_addresses: &[Address], fee: u64) -> Result<()> {
+    let paid: u64 = tx
+        .output
+        .iter()
+        .filter(|output| {
+            fee_addresses
+                .iter()
+                .any(|addr| addr.script_pubkey() == output.script_pubkey)
+        })
+        .map(|output| output.value)
+        .sum();
+    if paid < fee {
+        return Err(SEError::SwapError(format!(
+            "fee deposit {} pays {} to the fee address, swap fee is {}",
+            tx.txid(),
+            paid,
+            fee
+        )));
+    }
+    Ok(())
+}
+
+impl SCE {
+    /// Swap fees owed by deduction by a statechain. Deductions are not collected while swap
+    /// fees are disabled.
+    pub fn swap_fee_owed(&self, statechain_id: &Uuid) -> Result<u64> {
+        if self.config.conductor.swap_fee == 0 {
+            return Ok(0);
+        }
+        self.database.get_swap_fee_owed(statechain_id)
+    }
+
+    /// Record the report of a swap whose batch transfer was finalized
+    fn record_swap(&self, swap_info: &SwapInfo) -> Result<()> {
+        let report = SwapReport {
+            swap_id: swap_info.swap_token.id,
+            participants: swap_info.swap_token.statechain_ids.len() as u64,
+            denomination: swap_info.swap_token.amount,
+            completed_at: Utc::now().naive_utc(),
+        };
+        self.database.insert_swap_report(&report)?;
+        info!("SCHEDULER: Swap ID: {} report recorded", report.swap_id);
+        Ok(())
+    }
+
+    /// Get a fee deposit tx from bitcoind. The tx may be unconfirmed.
+    fn get_fee_deposit_tx(&self, txid: &String) -> Result<Transaction> {
+        let txid = Txid::from_str(txid)
+            .map_err(|e| SEError::SwapError(format!("invalid fee deposit txid: {}", e)))?;
+        let rpc_path_parts: Vec<&str> = self.config.bitcoind.split('@').collect();
+        let rpc_cred: Vec<&str> = rpc_path_parts[0].split(':').collect();
+        if rpc_path_parts.len() != 2 || rpc_cred.len() != 2 {
+            return Err(SEError::Generic(String::from(
+                "Swap fee deposits require a bitcoind RPC path",
+            )));
+        }
+        cfg_if! {
+            if #[cfg(any(test,feature="mockbitcoinrpc"))]{
+                use shared_lib::mocks::mock_rpc_client::MockBitcoinClient;
+                let mut rpc = MockBitcoinClient::new();
+            } else {
+                use bitcoincore_rpc::{Auth, Client, RpcApi};
+                let rpc = Client::new(rpc_path_parts[1].to_string(),
+                              Auth::UserPass(rpc_cred[0].to_string(),
+                                             rpc_cred[1].to_string()))
+                    .map_err(|e| SEError::Generic(e.to_string()))?;
+            }
+        }
+        rpc.get_raw_transaction(&txid, None).map_err(|e| {
+            SEError::SwapError(format!("fee deposit {} not found: {:?}", txid, e))
+        })
+    }
+
+    /// Check the swap fee of a participant is paid, before its blinded spend token can be
+    /// issued, and record it. The fee is agreed by the swap token signature and paid either by
+    /// deduction from the withdrawal of the swapped statecoin or by a fee deposit.
+    fn check_swap_fee(&self, swap_token: &SwapToken, swap_msg1: &SwapMsg1) -> Result<()> {
+        if swap_token.fee == 0 {
+            return Ok(());
+        }
+        let payment = self.config.conductor.swap_fee_payment;
+        let txid = match payment {
+            SwapFeePayment::Deduction => None,
+            SwapFeePayment::Deposit => {
+                let txid = swap_msg1.fee_txid.clone().ok_or(SEError::SwapError(
+                    "swap fee deposit txid required".to_string(),
+                ))?;
+                if let Some(used) = self.database.get_swap_fee_by_txid(&txid)? {
+                    if used != (swap_msg1.swap_id, swap_msg1.statechain_id) {
+                        return Err(SEError::SwapError(format!(
+                            "swap fee deposit {} already used",
+                            txid
+                        )));
+                    }
+                }
+                let fee_addresses = self
+                    .config
+                    .fee_address
+                    .replace(" ", "")
+                    .split(",")
+                    .map(|addr| Address::from_str(addr).map_err(|e| SEError::Generic(e.to_string())))
+                    .collect::<Result<Vec<Address>>>()?;
+                verify_swap_fee_tx(&self.get_fee_deposit_tx(&txid)?, &fee_addresses, swap_token.fee)?;
+                Some(txid)
+            }
+        };
+
+        self.database.insert_swap_fee(
+            &swap_msg1.swap_id,
+            &swap_msg1.statechain_id,
+            swap_token.fee,
+            &txid,
+        )?;
+        // Fees paid by deduction are recorded in the fee ledger when collected by withdrawal
+        if let Some(txid) = &txid {
+            self.database.insert_fee(
+                txid,
+                &FeeType::Swap,
+                &swap_msg1.statechain_id,
+                swap_token.fee,
+            )?;
+        }
+        info!(
+            target: "audit",
+            "SWAP FEE: {:?} Swap ID: {} State Chain ID: {} fee: {} txid: {:?}",
+            payment, swap_msg1.swap_id, swap_msg1.statechain_id, swap_token.fee, txid
+        );
+        Ok(())
+    }
+}
+
 impl Conductor for SCE {
     fn poll_utxo(&self, statechain_id: &Uuid) -> Result<SwapID> {
         let mut guard = self.scheduler.as_ref().expect("scheduler is None").lock()?;
@@ -919,6 +1081,9 @@ impl Conductor for SCE {
                 SwapStatus::Phase4 => match self.get_transfer_batch_status(swap_id.to_owned()) {
                     Ok(res) => {
                         if res.finalized {
+                            if let Some(info) = guard.get_swap_info(swap_id) {
+                                self.record_swap(&info)?;
+                            }
                             let _ = guard.transfer_ended(swap_id)?;
                         }
                     }
@@ -969,7 +1134,14 @@ impl Conductor for SCE {
         }
 
         //Verify the signature
-        let _ = self.verify_statechain_sig(key_id, sig, None)?;
+        let sco = self.verify_statechain_sig(key_id, sig, None)?;
+
+        //Check the coin and its owner proof key are not punished
+        let proof_key = sco.chain.get_tip().data.clone();
+        let seconds_remaining = self.punishment_remaining(key_id, &proof_key)?;
+        if seconds_remaining > 0 {
+            return Err(SEError::SwapError(format!("In punishment list. Seconds remaining: {:?}", &seconds_remaining)));
+        }
 
         let sc_amount = self.database.get_statechain_amount(*key_id)?;
         let amount: u64 = sc_amount.amount.clone() as u64;
@@ -1006,8 +1178,15 @@ impl Conductor for SCE {
 
     fn update_swap_info(&self) -> Result<bool>{
         if self.check_rate_slow("update_swap_info").is_ok() {
-            let mut guard = self.scheduler.as_ref().expect("scheduler is None").lock().unwrap();
+            let mut guard = self.scheduler.as_ref().expect("scheduler is None").lock()?;
             guard.update_swap_info()?;
+            // Escalate punishments of coins that failed a swap according to the punishment policy
+            for statechain_id in guard.take_swap_offenders() {
+                match self.punish(&statechain_id, Offence::Swap) {
+                    Ok(locked_until) => guard.set_punishment(&statechain_id, locked_until),
+                    Err(e) => warn!("SCHEDULER: Failed to apply punishment policy. State Chain ID: {}: {}", statechain_id, e),
+                }
+            }
             drop(guard);
             return Ok(true)
         }
@@ -1053,6 +1232,8 @@ impl Conductor for SCE {
                     None,
                 )?;
 
+                self.check_swap_fee(&i.swap_token, swap_msg1)?;
+
                 guard.register_bst(&swap_msg1)?;                
 
                 info!(
@@ -1150,8 +1331,9 @@ impl Conductor for SCE {
                 ));
             }
             let addr = unclaimed_addr_list.get(0).
-                unwrap().
-                clone();
+                ok_or(SEError::SwapError(
+                "swap_second_message: unclaimed_addr_list is empty".to_string()))?
+                .clone();
             sce_address_bisetmap.insert(addr.clone(), claimed_nonce);
             sce_address_bisetmap.remove(&addr, &None);
 
@@ -1167,6 +1349,35 @@ impl Conductor for SCE {
         }
     }
 
+    fn get_swap_stats(&self) -> Result<SwapStatsAPI> {
+        let guard = self.scheduler.as_ref().expect("scheduler is None").lock()?;
+        let (registered, swaps_ongoing) = guard.stats();
+        drop(guard);
+        let fee_address = self.config.fee_address.replace(" ", "");
+        Ok(SwapStatsAPI {
+            fee: self.config.conductor.swap_fee,
+            fee_payment: self.config.conductor.swap_fee_payment,
+            fee_address: fee_address.split(",").next().unwrap_or("").to_string(),
+            registered,
+            swaps_ongoing,
+        })
+    }
+
+    fn get_swap_report(&self, swap_id: &Uuid) -> Result<SignedSwapReport> {
+        let report = self.database.get_swap_report(swap_id)?.ok_or(SEError::SwapError(format!(
+            "No report for swap ID {}: swap not completed",
+            swap_id
+        )))?;
+        match self.config.identity_key {
+            Some(_) => Ok(sign_swap_report(&self.identity_key()?, report)?),
+            None => Ok(SignedSwapReport {
+                report,
+                server_pubkey: None,
+                sig: None,
+            }),
+        }
+    }
+
     fn get_address_from_blinded_spend_token(&self, bst: &BlindedSpendToken) -> Result<SCEAddress> {
         let bst_msg: BlindedSpentTokenMessage = match serde_json::from_str(&bst.get_msg()) {
             Ok(v) => v,
@@ -1219,141 +1430,7 @@ impl Conductor for SCE {
     }
 }
 
-#[openapi]
-/// # Poll conductor for the status of a specified registered statecoin ID
-#[post("/swap/poll/utxo", format = "json", data = "<statechain_id>")]
-pub fn poll_utxo(sc_entity: State<SCE>, statechain_id: Json<StatechainID>) -> Result<Json<SwapID>> {
-    sc_entity.check_rate_fast("swap")?;
-    sc_entity.update_swap_info()?;
-    match sc_entity.poll_utxo(&statechain_id.id) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
 
-#[openapi]
-/// # Poll conductor for the status of a specified swap ID
-#[post("/swap/poll/swap", format = "json", data = "<swap_id>")]
-pub fn poll_swap(sc_entity: State<SCE>, swap_id: Json<SwapID>) -> Result<Json<Option<SwapStatus>>> {
-    sc_entity.check_rate_fast("swap")?;
-    sc_entity.update_swap_info()?;
-    match sc_entity.poll_swap(&swap_id.id.ok_or("poll_swap: swap_id.id is None".to_string())?) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
-
-#[openapi]
-/// # Get information a specified swap ID
-#[post("/swap/info", format = "json", data = "<swap_id>")]
-pub fn get_swap_info(sc_entity: State<SCE>, swap_id: Json<SwapID>) -> Result<Json<Option<SwapInfo>>> {
-    sc_entity.check_rate_fast("swap")?;
-    sc_entity.update_swap_info()?;
-    match sc_entity.get_swap_info(
-        &swap_id.id.ok_or("poll_swap: swap_id.id is None".to_string())?) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
-
-#[openapi]
-/// # Get blinded spend token required for second message
-#[post("/swap/blinded-spend-signature", format = "json", data = "<bst_msg>")]
-pub fn get_blinded_spend_signature(
-    sc_entity: State<SCE>,
-    bst_msg: Json<BSTMsg>,
-) -> Result<Json<BlindedSpendSignature>> {
-    sc_entity.check_rate_fast("swap")?;
-    let bst_msg = bst_msg.into_inner();
-    let swap_uuid = &Uuid::from_str(&bst_msg.swap_id)?;
-    let statechain_uuid = &Uuid::from_str(&bst_msg.statechain_id)?;
-    let result = sc_entity
-        .get_blinded_spend_signature(swap_uuid, statechain_uuid)
-        .map(|x| Json(x));
-    let _ = sc_entity.update_swap_info();
-    return result
-}
-
-#[openapi]
-/// # Phase 0 of coinswap: Notify conductor of desire to take part in a swap with signature to prove ownership of statecoin.
-#[post("/swap/register-utxo", format = "json", data = "<register_utxo_msg>")]
-pub fn register_utxo(
-    sc_entity: State<SCE>,
-    register_utxo_msg: Json<RegisterUtxo>,
-) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("swap")?;
-    match sc_entity.register_utxo(&register_utxo_msg.into_inner()) {
-        Ok(res) => {
-            let _ = sc_entity.update_swap_info();
-            return Ok(Json(res))
-        },
-        Err(e) => return Err(e),
-    }
-}
-
-#[openapi]
-/// Remove coin from awaiting in swap pool
-#[post("/swap/deregister-utxo", format = "json", data = "<statechain_id>")]
-pub fn deregister_utxo(
-    sc_entity: State<SCE>,
-    statechain_id: Json<StatechainID>,
-) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("swap")?;
-    match sc_entity.deregister_utxo(&statechain_id.id) {
-        Ok(res) => {
-            let _ = sc_entity.update_swap_info();
-            return Ok(Json(res))
-        },
-        Err(e) => return Err(e),
-    }
-}
-
-
-
-#[openapi]
-/// # Phase 1 of coinswap: Participants sign SwapToken and provide a statechain address and e_prime for blind spend token.
-#[post("/swap/first", format = "json", data = "<swap_msg1>")]
-pub fn swap_first_message(sc_entity: State<SCE>, swap_msg1: Json<SwapMsg1>) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("swap")?;
-    match sc_entity.swap_first_message(&swap_msg1.into_inner()) {
-        Ok(res) => {
-            let _ = sc_entity.update_swap_info();    
-            return Ok(Json(res))
-        },
-        Err(e) => return Err(e),
-    }
-}
-
-#[openapi]
-/// # Phase 2 of coinswap: Participants provide blind spend token and recieve address.
-#[post("/swap/second", format = "json", data = "<swap_msg2>")]
-pub fn swap_second_message(
-    sc_entity: State<SCE>,
-    swap_msg2: Json<SwapMsg2>,
-) -> Result<Json<(SCEAddress)>> {
-    sc_entity.check_rate_fast("swap")?;
-    match sc_entity.swap_second_message(&swap_msg2.into_inner()) {
-        Ok(res) => {
-            let _ = sc_entity.update_swap_info();
-            return Ok(Json(res))
-        },
-        Err(e) => return Err(e),
-    }
-}
-
-#[openapi]
-/// # Get information on current group registrations
-#[get("/swap/groupinfo", format = "json")]
-pub fn get_group_info(
-    sc_entity: State<SCE>,
-    ) -> Result<Json<(HashMap<SwapGroup,GroupStatus>)>> {
-    sc_entity.check_rate_fast("swap")?;
-    sc_entity.update_swap_info()?;
-    match sc_entity.get_group_info() {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
 
 #[allow(dead_code)]
 #[cfg(test)]
@@ -1368,7 +1445,7 @@ mod tests {
     use mockall::predicate;
     use shared_lib::{
         blinded_token::{BSTRequestorData, BlindedSpendToken},
-        state_chain::{State as SCState, StateChain, StateChainSig},
+        state_chain::{verify_swap_report, State as SCState, StateChain, StateChainSig},
         util::keygen::Message,
     };
     use std::collections::HashSet;
@@ -1383,12 +1460,22 @@ mod tests {
             amount: 1,
             time_out: 100,
             statechain_ids: vec![Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap()],
+            fee: 0,
         };
         let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap(); // Proof key priv part
         let proof_key = PublicKey::from_secret_key(&Secp256k1::new(), &proof_key_priv); // proof key
 
         assert_eq!(
             swap_token.to_message().unwrap(),
+            Message::from_slice(
+                hex::decode("88a0186a3edd664466fcafcbd04753af24e811d2a64f1cc7d829ff4bc830512f")
+                    .unwrap()
+                    .as_slice()
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            swap_token.to_message_legacy().unwrap(),
             Message::from_slice(
                 hex::decode("6d4574375a0dea62b40f418c0974049f41487fe56f8201c1eca841b80b9e7d1e")
                     .unwrap()
@@ -1413,13 +1500,14 @@ mod tests {
             Uuid::from_str("ac0d59ea-6316-4357-852e-fa63ffa72ccc").unwrap(),
             Uuid::from_str("0143bee3-1273-47b0-bedc-973ef6f477b7").unwrap()
             ],
+            fee: 0,
         };
 
         println!("{:?}", swap_token);
 
 
         let message_expected = Message::from_slice(&hex::decode("932f880741335b0fb6a91fe9c95247e99e682ea748f1f536a4a898bfd13f6e55").unwrap()).unwrap();
-        let message = swap_token.to_message().unwrap();
+        let message = swap_token.to_message_legacy().unwrap();
         assert_eq!(message, message_expected);
 
     }
@@ -1459,7 +1547,9 @@ mod tests {
             wallet_requirement,
             punishment_map,
             punishment_timeout,
+            swap_offenders: vec![],
             permitted_groups,
+            swap_fee: 0,
             statechain_swap_size_map,
             statechain_amount_map,
             group_info_map: HashMap::<SwapGroup,GroupStatus>::new(),
@@ -1644,6 +1734,8 @@ mod tests {
         chain.push(SCState {
             data: proof_key.to_string(),
             next_state: None,
+            backup_txid: None,
+            locktime: None,
         });
 
         let statechain: StateChain = chain.try_into().expect("expected Vec<State> to convert to StateChain");
@@ -1681,6 +1773,8 @@ mod tests {
             .with(predicate::eq(statechain_id_2))
             .returning(move |_| Ok(statechain_amount_2.clone()));            
 
+        db.expect_get_punishment().returning(|_| Ok(None));
+
         let mut sc_entity = test_sc_entity(db, None, None, None, None);
         sc_entity.scheduler = Some(Arc::new(Mutex::new(get_scheduler(vec![(3, 10), (3, 10), (3, 10)]))));
 
@@ -1765,6 +1859,7 @@ mod tests {
             sce_addresses.push(SCEAddress {
                 tx_backup_addr: None,
                 proof_key: proof_key_vec.last().unwrap().clone(),
+                enc_key: None,
             });
 
             //Mock database responses
@@ -1772,6 +1867,8 @@ mod tests {
             chain.push(SCState {
                 data: proof_key_vec.last().unwrap().to_string(),
                 next_state: None,
+                backup_txid: None,
+                locktime: None,
             });
             let statechain: StateChain = chain.try_into().expect("expected Vec<State> to convert to StateChain");
             let statechain2 = statechain.clone();
@@ -1813,6 +1910,7 @@ mod tests {
             transfer_batch_sig,
             address: sce_addresses[0].clone(),
             bst_e_prime: FE::zero(),
+            fee_txid: None,
         };
 
         match sc_entity.swap_first_message(&swap_msg_1) {
@@ -1872,6 +1970,7 @@ mod tests {
                 transfer_batch_sig,
                 address: sce_addresses[i].clone(),
                 bst_e_prime: FE::new_random(),
+                fee_txid: None,
             };
             swap_msgs.push(swap_msg_1.clone());
             // Valid inputs
@@ -1889,6 +1988,8 @@ mod tests {
             chain.push(SCState {
             data: proof_key_vec[0].to_string(),
             next_state: None,
+            backup_txid: None,
+            locktime: None,
         });
         let statechain: StateChain = chain.try_into().expect("expected Vec<State> to convert to StateChain");
         let statechain2 = statechain.clone();
@@ -1944,6 +2045,7 @@ mod tests {
             swap_msg_1_diff_address.address = SCEAddress {
                 tx_backup_addr: None,
                 proof_key: pub_key,
+                enc_key: None,
             };
             assert!(swap_msg_1_diff_address.address != swap_msg_1.address);
 
@@ -1951,6 +2053,8 @@ mod tests {
             chain_diff.push(SCState {
                 data: pub_key.to_string(),
                 next_state: None,
+                backup_txid: None,
+                locktime: None,
              });
             let statechain_diff: StateChain = chain_diff.try_into().expect("expected Vec<State> to convert to StateChain");
             let statechain_diff2 = statechain_diff.clone();
@@ -2175,6 +2279,7 @@ mod tests {
                 "03b97f69f86f42c65787bfcaebc9c717993fec405973f6368b3d158cb79aa27791",
             )
             .unwrap(),
+            enc_key: None,
         };
         sce_addr_biset_map.insert(sce_addr.clone(), None);
         drop(guard);
@@ -2271,6 +2376,7 @@ mod tests {
                 "03b97f69f86f42c65787bfcaebc9c717993fec405973f6368b3d158cb79aa27791",
             )
             .unwrap(),
+            enc_key: None,
         };
         sce_addr_biset_map.insert(
             sce_addr.clone(),
@@ -2368,6 +2474,7 @@ mod tests {
                                 .unwrap(),
                         ),
                         proof_key,
+                        enc_key: None,
                     };
                     println!("SCE-Address: {:?}", sce_address);
                     println!("Sending swap token signature and SCE address.");
@@ -2379,6 +2486,7 @@ mod tests {
                         transfer_batch_sig,
                         address: sce_address,
                         bst_e_prime: FE::new_random(),
+                        fee_txid: None,
                     });
                     println!("Server response: {:?}", first_msg_resp);
                     phase_1_complete = true;
@@ -2434,6 +2542,7 @@ mod tests {
                         amount: 1,
                         time_out: GROUP_TIMEOUT,
                         statechain_ids: vec![statechain_id, statechain_id],
+                        fee: 0,
                     },
                     bst_sender_data: BSTSenderData::setup(),
                 }))
@@ -2451,6 +2560,7 @@ mod tests {
                         amount: 1,
                         time_out: GROUP_TIMEOUT,
                         statechain_ids: vec![statechain_id, statechain_id],
+                        fee: 0,
                     },
                     bst_sender_data: BSTSenderData::setup(),
                 }))
@@ -2467,6 +2577,7 @@ mod tests {
                         amount: 1,
                         time_out: GROUP_TIMEOUT,
                         statechain_ids: vec![statechain_id, statechain_id],
+                        fee: 0,
                     },
                     bst_sender_data: BSTSenderData::setup(),
                 }))
@@ -2481,6 +2592,7 @@ mod tests {
                     "65aab40995d3ed5d03a0567b04819ff12641b84c17f5e9d5dd075571e183469c8f",
                 )
                 .unwrap(),
+                enc_key: None,
             })
         });
         conductor
@@ -2522,4 +2634,199 @@ mod tests {
         assert!(guard.swaps_ongoing() == false);
         assert!(guard.shutdown_ready() == false);
     }
+
+    fn fee_swap_msg1(swap_id: Uuid, statechain_id: Uuid, fee_txid: Option<String>) -> SwapMsg1 {
+        let proof_key =
+            PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[1; 32]).unwrap());
+        SwapMsg1 {
+            swap_id,
+            statechain_id,
+            swap_token_sig: String::default(),
+            transfer_batch_sig: StateChainSig::example(),
+            address: SCEAddress { tx_backup_addr: None, proof_key, enc_key: None },
+            bst_e_prime: FE::zero(),
+            fee_txid,
+        }
+    }
+
+    #[test]
+    fn test_verify_swap_fee_tx() {
+        use shared_lib::mocks::mock_rpc_client::{MockBitcoinClient, MOCK_RAW_TX_ADDRESS, MOCK_RAW_TX_VALUE};
+        let tx = MockBitcoinClient::new()
+            .get_raw_transaction(
+                &Txid::from_str("0000000000000000000000000000000000000000000000000000000000000001").unwrap(),
+                None,
+            )
+            .unwrap();
+        let fee_address = Address::from_str(MOCK_RAW_TX_ADDRESS).unwrap();
+        let other_address = Address::from_str("bcrt1qsuqsurhgfduhqw6ejquw54482sqpkfc22gytyh").unwrap();
+
+        assert!(verify_swap_fee_tx(&tx, &[fee_address.clone()], MOCK_RAW_TX_VALUE).is_ok());
+        assert!(verify_swap_fee_tx(&tx, &[other_address.clone(), fee_address], 1000).is_ok());
+        match verify_swap_fee_tx(&tx, &[other_address], 1000) {
+            Err(SEError::SwapError(e)) => assert!(e.contains("pays 0 to the fee address")),
+            r => assert!(false, "Expected SwapError, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_check_swap_fee() {
+        use shared_lib::mocks::mock_rpc_client::{MOCK_RAW_TX_ADDRESS, MOCK_RAW_TX_VALUE};
+        let swap_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let used_txid = "0000000000000000000000000000000000000000000000000000000000000001";
+        let fee_txid = "0000000000000000000000000000000000000000000000000000000000000002";
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_swap_fee_by_txid()
+            .with(predicate::eq(used_txid.to_string()))
+            .returning(|_| Ok(Some((Uuid::new_v4(), Uuid::new_v4()))));
+        db.expect_get_swap_fee_by_txid()
+            .with(predicate::eq(fee_txid.to_string()))
+            .returning(|_| Ok(None));
+        db.expect_insert_swap_fee()
+            .with(
+                predicate::eq(swap_id),
+                predicate::eq(statechain_id),
+                predicate::always(),
+                predicate::always(),
+            )
+            .times(2)
+            .returning(|_, _, _, _| Ok(()));
+        // Only the fee paid by deposit is recorded in the fee ledger
+        db.expect_insert_fee()
+            .with(
+                predicate::eq(fee_txid.to_string()),
+                predicate::eq(FeeType::Swap),
+                predicate::eq(statechain_id),
+                predicate::eq(MOCK_RAW_TX_VALUE),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.bitcoind = String::from("user:pass@localhost:18443");
+        sc_entity.config.fee_address = String::from(MOCK_RAW_TX_ADDRESS);
+        sc_entity.config.conductor.swap_fee = MOCK_RAW_TX_VALUE;
+
+        let mut swap_token = SwapToken {
+            id: swap_id,
+            amount: 100000,
+            time_out: 100,
+            statechain_ids: vec![statechain_id],
+            fee: 0,
+        };
+
+        // No fee in the token: nothing to pay
+        assert!(sc_entity
+            .check_swap_fee(&swap_token, &fee_swap_msg1(swap_id, statechain_id, None))
+            .is_ok());
+
+        // Deduction: recorded without a deposit
+        swap_token.fee = MOCK_RAW_TX_VALUE;
+        assert!(sc_entity
+            .check_swap_fee(&swap_token, &fee_swap_msg1(swap_id, statechain_id, None))
+            .is_ok());
+
+        sc_entity.config.conductor.swap_fee_payment = SwapFeePayment::Deposit;
+        // Deposit txid required
+        assert!(sc_entity
+            .check_swap_fee(&swap_token, &fee_swap_msg1(swap_id, statechain_id, None))
+            .is_err());
+        // Deposit used by another participant
+        match sc_entity.check_swap_fee(
+            &swap_token,
+            &fee_swap_msg1(swap_id, statechain_id, Some(used_txid.to_string())),
+        ) {
+            Err(SEError::SwapError(e)) => assert!(e.contains("already used")),
+            r => assert!(false, "Expected SwapError, got {:?}", r),
+        }
+        // Deposit too small
+        swap_token.fee = MOCK_RAW_TX_VALUE + 1;
+        assert!(sc_entity
+            .check_swap_fee(
+                &swap_token,
+                &fee_swap_msg1(swap_id, statechain_id, Some(fee_txid.to_string()))
+            )
+            .is_err());
+        // Deposit pays the fee
+        swap_token.fee = MOCK_RAW_TX_VALUE;
+        assert!(sc_entity
+            .check_swap_fee(
+                &swap_token,
+                &fee_swap_msg1(swap_id, statechain_id, Some(fee_txid.to_string()))
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_get_swap_stats() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.scheduler = Some(Arc::new(Mutex::new(get_scheduler(vec![(3, 10), (3, 10), (3, 10)]))));
+        sc_entity.config.conductor.swap_fee = 1000;
+        sc_entity.config.conductor.swap_fee_payment = SwapFeePayment::Deposit;
+
+        let stats = sc_entity.get_swap_stats().unwrap();
+        assert_eq!(stats.fee, 1000);
+        assert_eq!(stats.fee_payment, SwapFeePayment::Deposit);
+        assert_eq!(stats.fee_address, "bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+        assert_eq!(stats.registered, 3);
+        assert_eq!(stats.swaps_ongoing, 0);
+
+        sc_entity.scheduler.as_ref().unwrap().lock().unwrap().update_swap_info().unwrap();
+        assert_eq!(sc_entity.get_swap_stats().unwrap().swaps_ongoing, 1);
+    }
+
+    #[test]
+    fn test_get_swap_report() {
+        let stored: Arc<Mutex<Option<SwapReport>>> = Arc::new(Mutex::new(None));
+        let stored_insert = stored.clone();
+        let stored_get = stored.clone();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_insert_swap_report().returning(move |report| {
+            let mut guard = stored_insert.lock().unwrap();
+            if guard.is_none() {
+                *guard = Some(report.clone());
+            }
+            Ok(())
+        });
+        db.expect_get_swap_report()
+            .returning(move |_| Ok(stored_get.lock().unwrap().clone()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.scheduler = Some(Arc::new(Mutex::new(get_scheduler(vec![(3, 10), (3, 10), (3, 10)]))));
+        let mut guard = sc_entity.scheduler.as_ref().unwrap().lock().unwrap();
+        guard.update_swap_info().unwrap();
+        let swap_id = guard.swap_id_map.iter().next().unwrap().1.to_owned();
+        let swap_info = guard.get_swap_info(&swap_id).unwrap();
+        drop(guard);
+
+        // Not completed
+        match sc_entity.get_swap_report(&swap_id) {
+            Err(SEError::SwapError(e)) => assert!(e.contains("swap not completed")),
+            res => assert!(false, "unexpected result: {:?}", res),
+        }
+
+        sc_entity.record_swap(&swap_info).unwrap();
+        let signed = sc_entity.get_swap_report(&swap_id).unwrap();
+        assert_eq!(signed.report.swap_id, swap_id);
+        assert_eq!(
+            signed.report.participants,
+            swap_info.swap_token.statechain_ids.len() as u64
+        );
+        assert_eq!(signed.report.denomination, swap_info.swap_token.amount);
+        assert!(signed.sig.is_none());
+
+        // Signed with the identity key, with the time of the first record
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        sc_entity.config.identity_key = Some(hex::encode(&identity_key[..]));
+        let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &identity_key).to_string();
+        sc_entity.record_swap(&swap_info).unwrap();
+        let signed_2 = sc_entity.get_swap_report(&swap_id).unwrap();
+        assert_eq!(signed_2.report, signed.report);
+        assert!(verify_swap_report(&signed_2, &server_pubkey).is_ok());
+    }
 }
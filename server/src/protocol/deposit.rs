@@ -5,20 +5,19 @@
 pub use super::super::Result;
 use crate::server::DEPOSITS_COUNT;
 extern crate shared_lib;
+use crate::aml::{aml_screen, AmlEvent, AmlEventKind};
+use crate::protocol::session::SessionEvent;
 use crate::error::SEError;
 use crate::server::{StateChainEntity};
-use crate::protocol::util::RateLimiter;
 use crate::storage::Storage;
 use crate::Database;
-use shared_lib::{state_chain::*, structs::*, util::FEE};
+use shared_lib::{state_chain::*, structs::*, util::{validate_address_network, FEE}};
 
 use bitcoin::PublicKey;
 use cfg_if::cfg_if;
-use rocket::State;
-use rocket_contrib::json::Json;
 use std::str::FromStr;
+use std::sync::atomic::Ordering;
 use uuid::Uuid;
-use rocket_okapi::openapi;
 use rand::Rng;
 use hex;
 
@@ -30,8 +29,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
     }
 }
 
@@ -47,6 +46,104 @@ pub trait Deposit {
     ///     - Create StateChain DB object
     ///     - Update sparse merkle tree with new StateChain entry
     fn deposit_confirm(&self, deposit_msg2: DepositMsg2) -> Result<StatechainID>;
+
+    /// API: Store the encrypted shared key of a deposit made on behalf of another proof key
+    ///     - Only the payer of the deposit may store the handoff
+    ///     - The handoff is stored for the owner of the statechain
+    fn deposit_handoff(&self, handoff_msg: DepositHandoffMsg) -> Result<()>;
+
+    /// API: Get the deposit handoffs stored for a proof key
+    fn deposit_get_handoffs(&self, proof_key: String) -> Result<Vec<DepositHandoffMsg>>;
+
+    /// API: Authorise the refund of a deposit that never reached statechain creation
+    ///     - Only deposits with a co-signed backup tx that were not confirmed may be refunded
+    ///     - The refund address is signed by the proof key that paid the deposit
+    ///     - The refund tx is then co-signed with protocol Refund and the deposit can no longer
+    ///       be confirmed
+    fn deposit_refund(&self, refund_msg: DepositRefundMsg) -> Result<()>;
+
+    /// API: Pause or resume new deposits (circuit breaker). Requires the admin key.
+    ///     - Deposits already co-signed can still be confirmed
+    ///     - Transfers and withdrawals are not affected
+    fn set_deposits_paused(&self, pause_msg: DepositPauseMsg) -> Result<DepositCapacityAPI>;
+}
+
+impl SCE {
+    /// Circuit breaker state and remaining capacity under the total value cap
+    pub fn deposit_capacity(&self) -> Result<DepositCapacityAPI> {
+        let paused = self.deposits_paused.load(Ordering::SeqCst);
+        let total_value = match self.config.max_total_value {
+            0 => 0,
+            _ => self.database.get_total_value()?,
+        };
+        let capacity = match self.config.max_total_value {
+            0 => None,
+            max => Some(max.saturating_sub(total_value)),
+        };
+        Ok(DepositCapacityAPI {
+            paused,
+            total_value,
+            capacity,
+        })
+    }
+
+    /// Check a new deposit of 'amount' (None if not yet known) is allowed by the circuit
+    /// breaker and fits under the cap on the total value of statecoins. Applies to all proof
+    /// keys.
+    pub fn check_deposit_capacity(&self, amount: Option<u64>) -> Result<()> {
+        let status = self.deposit_capacity()?;
+        if status.paused {
+            return Err(SEError::LimitError(String::from(
+                "Deposits are paused. Transfers and withdrawals are not affected.",
+            )));
+        }
+        if let Some(capacity) = status.capacity {
+            let amount = amount.unwrap_or(0);
+            if capacity == 0 || amount > capacity {
+                return Err(SEError::LimitError(format!(
+                    "Deposit of {} exceeds the remaining deposit capacity {}.",
+                    amount, capacity
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a deposit of 'amount' (None if not yet known) owned by proof_key is within the
+    /// per-user limits
+    pub fn check_deposit_limits(&self, proof_key: &String, amount: Option<u64>) -> Result<()> {
+        let limits = &self.config.limits;
+        if limits.is_exempt(proof_key) {
+            return Ok(());
+        }
+        if let Some(amount) = amount {
+            if limits.max_coin_value > 0 && amount > limits.max_coin_value {
+                return Err(SEError::LimitError(format!(
+                    "Statecoin value {} exceeds the maximum coin value {}.",
+                    amount, limits.max_coin_value
+                )));
+            }
+        }
+        if limits.max_coins_per_proof_key == 0 && limits.max_value_per_proof_key == 0 {
+            return Ok(());
+        }
+
+        let (coins, value) = self.database.get_proof_key_holdings(proof_key)?;
+        if limits.max_coins_per_proof_key > 0 && coins + 1 > limits.max_coins_per_proof_key {
+            return Err(SEError::LimitError(format!(
+                "Proof key owns {} statecoins. Maximum statecoins per proof key is {}.",
+                coins, limits.max_coins_per_proof_key
+            )));
+        }
+        let value = value + amount.unwrap_or(0);
+        if limits.max_value_per_proof_key > 0 && value > limits.max_value_per_proof_key {
+            return Err(SEError::LimitError(format!(
+                "Aggregate statecoin value {} exceeds the maximum value per proof key {}.",
+                value, limits.max_value_per_proof_key
+            )));
+        }
+        Ok(())
+    }
 }
 
 impl Deposit for SCE {
@@ -63,6 +160,34 @@ impl Deposit for SCE {
             )));
         };
 
+        // Check payer proof key when depositing on behalf of another proof key
+        if let Some(payer_proof_key) = &deposit_msg1.payer_proof_key {
+            if let Err(_) = PublicKey::from_str(payer_proof_key) {
+                return Err(SEError::Generic(String::from(
+                    "Payer proof key not in correct format.",
+                )));
+            };
+            if payer_proof_key == &deposit_msg1.proof_key {
+                return Err(SEError::Generic(String::from(
+                    "Payer proof key must differ from owner proof key.",
+                )));
+            }
+        }
+
+        self.check_deposit_capacity(None)?;
+        self.check_deposit_limits(&deposit_msg1.proof_key, None)?;
+
+        // Check the acknowledged fee quote is a valid quote issued by the state entity
+        let fee_quote = match &deposit_msg1.fee_quote_hash {
+            Some(quote_hash) => Some((quote_hash, self.get_acknowledged_fee_quote(quote_hash)?)),
+            None if self.config.fee_ack_required => {
+                return Err(SEError::Generic(String::from(
+                    "Fee quote acknowledgement required. Get a fee quote from /info/fee.",
+                )))
+            }
+            None => None,
+        };
+
         // Generate shared wallet ID (user ID)
         let user_id = Uuid::new_v4();
 
@@ -77,6 +202,24 @@ impl Deposit for SCE {
         self.database
             .create_user_session(&user_id, &deposit_msg1.auth, &deposit_msg1.proof_key, &challenge, self.user_ids.clone())?;
 
+        // Store the acknowledged fee quote as the record of the fee agreed for the deposit
+        if let Some((quote_hash, quote)) = &fee_quote {
+            self.database
+                .insert_fee_acknowledgement(&user_id, quote_hash, quote)?;
+            info!("DEPOSIT: Fee quote {} acknowledged. User ID: {}", quote_hash, user_id);
+        }
+
+        // The statechain is owned by proof_key. The payer hands off the shared key once the
+        // deposit is confirmed.
+        if let Some(payer_proof_key) = &deposit_msg1.payer_proof_key {
+            self.database.create_deposit_handoff(
+                &user_id,
+                &deposit_msg1.proof_key,
+                payer_proof_key,
+            )?;
+            info!("DEPOSIT: Deposit on behalf of proof key. User ID: {}", user_id);
+        }
+
         info!(
             "DEPOSIT: Protocol initiated. User ID generated: {}",
             user_id
@@ -94,6 +237,7 @@ impl Deposit for SCE {
         // let shared_key_id = deposit_msg2.shared_key_id.clone();
         self.check_user_auth(&deposit_msg2.shared_key_id)?;
         let user_id = deposit_msg2.shared_key_id;
+        let next_state = self.check_session_event(&user_id, SessionEvent::DepositConfirm)?;
 
         // Get back up tx and proof key
         let (tx_backup, proof_key) = self
@@ -114,6 +258,20 @@ impl Deposit for SCE {
             total += output.value;
         }
         let amount = (total + FEE) as i64;
+        self.check_deposit_limits(&proof_key, Some(amount as u64))?;
+
+        aml_screen(
+            self.aml_hook.as_ref(),
+            &AmlEvent {
+                kind: AmlEventKind::Deposit,
+                user_id,
+                statechain_id: None,
+                txid: tx_backup.input[0].previous_output.txid.to_string(),
+                amount: amount as u64,
+                destination: None,
+            },
+        )?;
+
         let state_chain = StateChain::new(proof_key.clone());
 
         // Insert into StateChain table
@@ -127,6 +285,13 @@ impl Deposit for SCE {
         // Insert into BackupTx table
         self.database
             .create_backup_transaction(&statechain_id, &tx_backup)?;
+        self.record_backup_addr(&statechain_id, &tx_backup)?;
+        self.database
+            .insert_backup_tx_history(&statechain_id, &tx_backup)?;
+
+        // Later spends of the funding outpoint are checked for conflicts with the backup tx
+        let sig_hash = self.database.get_sighash(user_id)?;
+        self.database.set_sighash_finalized(&user_id, &sig_hash)?;
 
         info!(
             "DEPOSIT: State Chain created. ID: {} For user ID: {}",
@@ -136,10 +301,22 @@ impl Deposit for SCE {
         // Update UserSession with StateChain's ID
         self.database
             .update_statechain_id(&user_id, &statechain_id)?;
+        self.update_session_state(&user_id, next_state)?;
 
         //increment fee metric
         DEPOSITS_COUNT.inc();
 
+        // Record the deposit fee paid by the funding tx for the revenue report
+        let deposit_fee = (amount as u64 * self.config.fee_deposit) / 10000;
+        if deposit_fee > 0 {
+            self.database.insert_fee(
+                &tx_backup.input[0].previous_output.txid.to_string(),
+                &FeeType::Deposit,
+                &statechain_id,
+                deposit_fee,
+            )?;
+        }
+
         // Update sparse merkle tree with new StateChain entry
         let (current_root, new_root) = self.update_smt(
             &tx_backup
@@ -150,6 +327,7 @@ impl Deposit for SCE {
                 .txid
                 .to_string(),
             &proof_key,
+            amount as u64,
         )?;
 
         info!(
@@ -163,30 +341,98 @@ impl Deposit for SCE {
 
         Ok(StatechainID {id: statechain_id})
     }
-}
 
-#[openapi]
-/// # Initiate a statechain deposit and generate a shared key ID
-#[post("/deposit/init", format = "json", data = "<deposit_msg1>")]
-pub fn deposit_init(sc_entity: State<SCE>, deposit_msg1: Json<DepositMsg1>) -> Result<Json<UserID>> {
-    sc_entity.check_rate_slow("deposit_init")?;
-    match sc_entity.deposit_init(deposit_msg1.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    fn deposit_handoff(&self, handoff_msg: DepositHandoffMsg) -> Result<()> {
+        self.check_user_auth(&handoff_msg.shared_key_id)?;
+        let user_id = handoff_msg.shared_key_id;
+        self.check_session_event(&user_id, SessionEvent::DepositHandoff)?;
+
+        let proof_key = match self.database.get_deposit_handoff_proof_key(&user_id)? {
+            Some(k) => k,
+            None => {
+                return Err(SEError::Generic(String::from(
+                    "Deposit was not made on behalf of another proof key.",
+                )))
+            }
+        };
+        if handoff_msg.proof_key != proof_key {
+            return Err(SEError::Generic(String::from(
+                "Deposit handoff proof key does not match statechain owner.",
+            )));
+        }
+        // Deposit must be confirmed
+        if self.database.get_statechain_id(user_id)? != handoff_msg.statechain_id {
+            return Err(SEError::Generic(String::from(
+                "Deposit handoff statechain ID does not match deposit.",
+            )));
+        }
+
+        self.database.update_deposit_handoff(&handoff_msg)?;
+
+        info!(
+            "DEPOSIT: Shared key handoff stored. State Chain ID: {}",
+            handoff_msg.statechain_id
+        );
+        Ok(())
     }
-}
 
-#[openapi]
-/// # Confirm the deposit process has completed and retreive the statechain ID
-#[post("/deposit/confirm", format = "json", data = "<deposit_msg2>")]
-pub fn deposit_confirm(
-    sc_entity: State<SCE>,
-    deposit_msg2: Json<DepositMsg2>,
-) -> Result<Json<StatechainID>> {
-    sc_entity.check_rate_fast("deposit_confirm")?;
-    match sc_entity.deposit_confirm(deposit_msg2.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    fn deposit_get_handoffs(&self, proof_key: String) -> Result<Vec<DepositHandoffMsg>> {
+        self.database.get_deposit_handoffs(&proof_key)
+    }
+
+    fn deposit_refund(&self, refund_msg: DepositRefundMsg) -> Result<()> {
+        self.check_user_auth(&refund_msg.shared_key_id)?;
+        let user_id = refund_msg.shared_key_id;
+
+        // Sessions created before the session state was recorded may own a statechain
+        if self.database.get_session_state(&user_id)?.is_none() {
+            return Err(SEError::Generic(String::from(
+                "Deposit refund requires a recorded session state.",
+            )));
+        }
+        let next_state = self.check_session_event(&user_id, SessionEvent::DepositRefund)?;
+
+        let refund_sig = refund_msg.refund_sig;
+        if refund_sig.purpose != "REFUND" {
+            return Err(SEError::Generic(String::from(
+                "Deposit refund signature purpose must be REFUND.",
+            )));
+        }
+        let refund_addr = bitcoin::Address::from_str(&refund_sig.data)
+            .map_err(|e| SEError::Generic(format!("Invalid refund address: {}", e)))?;
+        let network = self
+            .config
+            .network
+            .parse::<bitcoin::Network>()
+            .map_err(|e| SEError::Generic(e.to_string()))?;
+        validate_address_network(&refund_addr, &network)?;
+
+        // The refund is authorised by the proof key that paid the deposit
+        let proof_key = match self.database.get_deposit_payer_proof_key(&user_id)? {
+            Some(k) => k,
+            None => self.database.get_proof_key(user_id)?,
+        };
+        refund_sig.verify(&proof_key)?;
+
+        // Mark UserSession as authorised for refund to the signed address
+        self.database.update_withdraw_sc_sig(&user_id, refund_sig)?;
+        self.update_session_state(&user_id, next_state)?;
+
+        info!(
+            "DEPOSIT: Refund authorised. User ID: {}. Refund address: {}",
+            user_id, refund_addr
+        );
+        Ok(())
+    }
+
+    fn set_deposits_paused(&self, pause_msg: DepositPauseMsg) -> Result<DepositCapacityAPI> {
+        self.check_admin_key(&pause_msg.admin_key)?;
+        self.deposits_paused.store(pause_msg.paused, Ordering::SeqCst);
+        match pause_msg.paused {
+            true => warn!("DEPOSIT: Deposits paused."),
+            false => info!("DEPOSIT: Deposits resumed."),
+        }
+        self.deposit_capacity()
     }
 }
 
@@ -196,7 +442,9 @@ pub mod tests {
     use crate::protocol::util::{
         mocks,
         tests::{test_sc_entity, BACKUP_TX_NOT_SIGNED, BACKUP_TX_SIGNED},
+        Utilities,
     };
+    use crate::structs::SessionState;
     use bitcoin::Transaction;
     use std::str::FromStr;
 
@@ -212,6 +460,8 @@ pub mod tests {
         match sc_entity.deposit_init(DepositMsg1 {
             auth: String::from("auth"),
             proof_key: String::from(""),
+            payer_proof_key: None,
+            fee_quote_hash: None,
         }) {
             Ok(_) => assert!(false, "Expected failure."),
             Err(e) => assert!(e.to_string().contains("Proof key not in correct format.")),
@@ -222,6 +472,8 @@ pub mod tests {
             proof_key: String::from(
                 "65aab40995d3ed5d03a0567b04819ff12641b84c17f5e9d5dd075571e18346",
             ),
+            payer_proof_key: None,
+            fee_quote_hash: None,
         }) {
             Ok(_) => assert!(false, "Expected failure."),
             Err(e) => assert!(e.to_string().contains("Proof key not in correct format.")),
@@ -232,11 +484,307 @@ pub mod tests {
                 auth: String::from("auth"),
                 proof_key: String::from(
                     "026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e"
-                )
+                ),
+                payer_proof_key: None,
+                fee_quote_hash: None,
+            })
+            .is_ok());
+    }
+
+    #[test]
+    fn test_deposit_limits() {
+        let proof_key =
+            String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e");
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_proof_key_holdings()
+            .returning(|_| Ok((2, 300000)));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Disabled by default
+        assert!(sc_entity.check_deposit_limits(&proof_key, Some(u64::MAX / 2)).is_ok());
+
+        sc_entity.config.limits.max_coin_value = 100000;
+        match sc_entity.check_deposit_limits(&proof_key, Some(100001)) {
+            Err(SEError::LimitError(e)) => assert!(e.contains("maximum coin value")),
+            r => assert!(false, "Expected LimitError, got {:?}", r),
+        }
+        assert!(sc_entity.check_deposit_limits(&proof_key, Some(100000)).is_ok());
+
+        sc_entity.config.limits.max_coins_per_proof_key = 2;
+        match sc_entity.check_deposit_limits(&proof_key, None) {
+            Err(SEError::LimitError(e)) => assert!(e.contains("Maximum statecoins per proof key")),
+            r => assert!(false, "Expected LimitError, got {:?}", r),
+        }
+        sc_entity.config.limits.max_coins_per_proof_key = 3;
+        assert!(sc_entity.check_deposit_limits(&proof_key, None).is_ok());
+
+        sc_entity.config.limits.max_value_per_proof_key = 350000;
+        assert!(sc_entity.check_deposit_limits(&proof_key, Some(50000)).is_ok());
+        match sc_entity.check_deposit_limits(&proof_key, Some(50001)) {
+            Err(SEError::LimitError(e)) => assert!(e.contains("maximum value per proof key")),
+            r => assert!(false, "Expected LimitError, got {:?}", r),
+        }
+
+        // Exempt proof keys are not limited
+        sc_entity.config.limits.exempt = format!("02aaaa, {}", proof_key);
+        assert!(sc_entity.check_deposit_limits(&proof_key, Some(100001)).is_ok());
+    }
+
+    #[test]
+    fn test_deposit_capacity() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_total_value().returning(|| Ok(900000));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // No cap by default
+        assert_eq!(sc_entity.deposit_capacity().unwrap().capacity, None);
+        assert!(sc_entity.check_deposit_capacity(Some(u64::MAX / 2)).is_ok());
+
+        sc_entity.config.max_total_value = 1000000;
+        assert_eq!(sc_entity.deposit_capacity().unwrap().capacity, Some(100000));
+        assert!(sc_entity.check_deposit_capacity(None).is_ok());
+        assert!(sc_entity.check_deposit_capacity(Some(100000)).is_ok());
+        match sc_entity.check_deposit_capacity(Some(100001)) {
+            Err(SEError::LimitError(e)) => assert!(e.contains("remaining deposit capacity")),
+            r => assert!(false, "Expected LimitError, got {:?}", r),
+        }
+        assert_eq!(sc_entity.get_fees().unwrap().capacity, Some(100000));
+
+        // Full
+        sc_entity.config.max_total_value = 900000;
+        assert!(sc_entity.check_deposit_capacity(None).is_err());
+
+        // Circuit breaker requires the admin key
+        sc_entity.config.max_total_value = 0;
+        let pause = |admin_key: &str, paused: bool| DepositPauseMsg {
+            admin_key: String::from(admin_key),
+            paused,
+        };
+        assert!(sc_entity.set_deposits_paused(pause("admin", true)).is_err());
+        sc_entity.config.admin_key = Some(String::from("admin"));
+        assert!(sc_entity.set_deposits_paused(pause("other", true)).is_err());
+        assert!(sc_entity.set_deposits_paused(pause("admin", true)).unwrap().paused);
+        match sc_entity.check_deposit_capacity(Some(1)) {
+            Err(SEError::LimitError(e)) => assert!(e.contains("Deposits are paused")),
+            r => assert!(false, "Expected LimitError, got {:?}", r),
+        }
+        assert!(sc_entity.get_fees().unwrap().deposits_paused);
+        assert!(!sc_entity.set_deposits_paused(pause("admin", false)).unwrap().paused);
+        assert!(sc_entity.check_deposit_capacity(Some(1)).is_ok());
+    }
+
+    #[test]
+    fn test_deposit_init_on_behalf() {
+        let proof_key =
+            String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e");
+        let payer_proof_key =
+            String::from("03b2483ab9bea9843bd9bfb941e8c86c1308e77aa95fccd0e63c2874c0e3ead3f5");
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_create_user_session().returning(|_, _, _, _, _| Ok(()));
+        let (pk, ppk) = (proof_key.clone(), payer_proof_key.clone());
+        db.expect_create_deposit_handoff()
+            .times(1)
+            .withf(move |_, proof_key, payer_proof_key| proof_key == &pk && payer_proof_key == &ppk)
+            .returning(|_, _, _| Ok(()));
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Invalid payer proof key
+        match sc_entity.deposit_init(DepositMsg1 {
+            auth: String::from("auth"),
+            proof_key: proof_key.clone(),
+            payer_proof_key: Some(String::from("")),
+            fee_quote_hash: None,
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Payer proof key not in correct format.")),
+        }
+        // Payer is the owner
+        match sc_entity.deposit_init(DepositMsg1 {
+            auth: String::from("auth"),
+            proof_key: proof_key.clone(),
+            payer_proof_key: Some(proof_key.clone()),
+            fee_quote_hash: None,
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("must differ")),
+        }
+
+        assert!(sc_entity
+            .deposit_init(DepositMsg1 {
+                auth: String::from("auth"),
+                proof_key,
+                payer_proof_key: Some(payer_proof_key),
+                fee_quote_hash: None,
             })
             .is_ok());
     }
 
+    #[test]
+    fn test_deposit_init_fee_ack() {
+        let proof_key =
+            String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e");
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_create_user_session().returning(|_, _, _, _, _| Ok(()));
+        db.expect_insert_fee_acknowledgement()
+            .times(1)
+            .withf(|_, quote_hash, quote| &fee_quote_hash(quote).unwrap() == quote_hash)
+            .returning(|_, _, _| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.export_key = Some(hex::encode(&[3; 32]));
+        sc_entity.config.fee_ack_required = true;
+
+        let deposit_msg1 = |fee_quote_hash| DepositMsg1 {
+            auth: String::from("auth"),
+            proof_key: proof_key.clone(),
+            payer_proof_key: None,
+            fee_quote_hash,
+        };
+
+        // No acknowledgement
+        match sc_entity.deposit_init(deposit_msg1(None)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Fee quote acknowledgement required")),
+        }
+
+        // Acknowledgement of a quote with a different fee
+        let quote = sc_entity.get_fees().unwrap();
+        assert!(quote.sig.is_some());
+        let mut other = quote.clone();
+        other.deposit += 1;
+        match sc_entity.deposit_init(deposit_msg1(Some(fee_quote_hash(&other).unwrap()))) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not match a valid fee quote")),
+        }
+
+        assert!(sc_entity
+            .deposit_init(deposit_msg1(Some(fee_quote_hash(&quote).unwrap())))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_deposit_handoff() {
+        let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let statechain_id = Uuid::from_str("2b41ff2f-7a2e-4b5b-bfbc-1d9a5f2f8e0b").unwrap();
+        let proof_key =
+            String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e");
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_user_id| Ok(String::from("user_auth")));
+        let pk = proof_key.clone();
+        db.expect_get_deposit_handoff_proof_key()
+            .returning(move |_| Ok(Some(pk.clone())));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        db.expect_update_deposit_handoff().times(1).returning(|_| Ok(()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Owner)));
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        let handoff_msg = DepositHandoffMsg {
+            shared_key_id: user_id,
+            statechain_id,
+            proof_key: proof_key.clone(),
+            payload: String::from("00"),
+        };
+
+        // Handoff for another proof key
+        let mut msg = handoff_msg.clone();
+        msg.proof_key =
+            String::from("03b2483ab9bea9843bd9bfb941e8c86c1308e77aa95fccd0e63c2874c0e3ead3f5");
+        match sc_entity.deposit_handoff(msg) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not match statechain owner")),
+        }
+
+        // Handoff for another statechain
+        let mut msg = handoff_msg.clone();
+        msg.statechain_id = Uuid::new_v4();
+        match sc_entity.deposit_handoff(msg) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not match deposit")),
+        }
+
+        assert!(sc_entity.deposit_handoff(handoff_msg).is_ok());
+    }
+
+    #[test]
+    fn test_deposit_refund() {
+        let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let proof_key_priv = bitcoin::secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_pubkey = bitcoin::secp256k1::PublicKey::from_secret_key(
+            &bitcoin::secp256k1::Secp256k1::new(),
+            &proof_key_priv,
+        );
+        let proof_key = proof_pubkey.to_string();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .times(1)
+            .returning(|_| Ok(None));
+        db.expect_get_session_state()
+            .times(1)
+            .returning(|_| Ok(Some(SessionState::Owner)));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositSigned)));
+        db.expect_get_deposit_payer_proof_key().returning(|_| Ok(None));
+        db.expect_get_proof_key()
+            .returning(move |_| Ok(proof_key.clone()));
+        db.expect_update_withdraw_sc_sig().times(1).returning(|_, _| Ok(()));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Refund)
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        let network = sc_entity.config.network.parse::<bitcoin::Network>().unwrap();
+        let refund_addr = bitcoin::Address::p2wpkh(
+            &PublicKey { compressed: true, key: proof_pubkey },
+            network,
+        )
+        .unwrap()
+        .to_string();
+        let refund_msg = |purpose: &str, key: &bitcoin::secp256k1::SecretKey| DepositRefundMsg {
+            shared_key_id: user_id,
+            refund_sig: StateChainSig::new(key, &String::from(purpose), &refund_addr).unwrap(),
+        };
+
+        // Session state not recorded
+        match sc_entity.deposit_refund(refund_msg("REFUND", &proof_key_priv)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("requires a recorded session state")),
+        }
+        // Confirmed deposit
+        match sc_entity.deposit_refund(refund_msg("REFUND", &proof_key_priv)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("DepositRefund not valid")),
+        }
+        // Signature for another purpose
+        match sc_entity.deposit_refund(refund_msg("WITHDRAW", &proof_key_priv)) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("purpose must be REFUND")),
+        }
+        // Signature by another key
+        let other_key = bitcoin::secp256k1::SecretKey::from_slice(&[2; 32]).unwrap();
+        assert!(sc_entity.deposit_refund(refund_msg("REFUND", &other_key)).is_err());
+
+        assert!(sc_entity.deposit_refund(refund_msg("REFUND", &proof_key_priv)).is_ok());
+    }
+
     #[test]
     fn test_deposit_confirm() {
         let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
@@ -244,6 +792,7 @@ pub mod tests {
             String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e");
         let tx_backup: Transaction = serde_json::from_str(&BACKUP_TX_NOT_SIGNED).unwrap();
         let tx_backup_signed = serde_json::from_str::<Transaction>(&BACKUP_TX_SIGNED).unwrap();
+        let backup_txid = tx_backup_signed.txid();
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
@@ -252,6 +801,7 @@ pub mod tests {
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
         db.expect_root_update().returning(|_| Ok(1));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         // First return unsigned back up tx
         db.expect_get_backup_transaction_and_proof_key()
             .times(1)
@@ -260,11 +810,38 @@ pub mod tests {
         db.expect_get_backup_transaction_and_proof_key()
             .returning(move |_| Ok((tx_backup_signed.clone(), proof_key.clone())));
         db.expect_create_statechain().returning(|_, _, _, _| Ok(()));
+        db.expect_insert_fee()
+            .withf(|_, fee_type, _, _| *fee_type == FeeType::Deposit)
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
         db.expect_create_backup_transaction()
             .returning(|_, _| Ok(()));
+        // The backup address is recorded with the statechain
+        db.expect_update_backup_addr()
+            .withf(|_, addr| addr == "bcrt1q3lpj2f2g05ktwv3ujc9alv99ae4rv3ecnxkwh8")
+            .times(1)
+            .returning(|_, _| Ok(()));
+        // The co-signed backup tx is recorded in the backup tx history
+        db.expect_insert_backup_tx_history()
+            .withf(move |_, tx| tx.txid() == backup_txid)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        // The co-signed backup tx sighash is finalized
+        let sig_hash = bitcoin::hashes::sha256d::Hash::from_str(&"01".repeat(32)).unwrap();
+        db.expect_get_sighash().returning(move |_| Ok(sig_hash));
+        db.expect_set_sighash_finalized()
+            .withf(move |id, hash| *id == user_id && *hash == sig_hash)
+            .times(1)
+            .returning(|_, _| Ok(()));
         db.expect_update_statechain_id().returning(|_, _| Ok(()));
         db.expect_get_shared_pubkey().returning(|_| Ok(Some("".to_string())));
         db.expect_set_shared_pubkey().returning(|_,_| Ok(()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositSigned)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Owner)
+            .times(1)
+            .returning(|_, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
 
@@ -286,4 +863,28 @@ pub mod tests {
             })
             .is_ok());
     }
+
+    #[test]
+    fn test_deposit_confirm_out_of_order() {
+        let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+           .returning(|_user_id| Ok(String::from("user_auth")));
+        // Key generation complete but backup tx not co-signed
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositKeyGen)));
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        match sc_entity.deposit_confirm(DepositMsg2 {
+            shared_key_id: user_id,
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e
+                .to_string()
+                .contains("DepositConfirm not valid in session state DepositKeyGen")),
+        }
+    }
 }
@@ -1,32 +1,37 @@
 pub use super::super::Result;
 
+use crate::chaos::FaultInjector;
 use crate::error::{DBErrorType, SEError};
 use crate::Database;
 use crate::{server::StateChainEntity, structs::*};
 use shared_lib::{
-    structs::{KeyGenMsg1, KeyGenMsg2, KeyGenReply1, KeyGenReply2, SignReply1, Protocol, SignMsg1, SignMsg2},
+    commitment::p2c_tweak_fe,
+    structs::{KeyGenMsg1, KeyGenMsg2, KeyGenReply1, KeyGenReply2, KeygenAuditAPI, KeygenAuditMsg, PayToContract, SignReply1, Protocol, SignMsg1, SignMsg2, UserID},
     util::reverse_hex_str,
 };
 use super::requests::post_lb;
+use super::session::SessionEvent;
 
-use bitcoin::{hashes::sha256d, secp256k1::Signature, Transaction};
+use bitcoin::{
+    hashes::{hex::FromHex, sha256, sha256d},
+    secp256k1::Signature,
+    PublicKey, Transaction,
+};
 use cfg_if::cfg_if;
 use curv::{
     arithmetic::traits::Converter,
+    cryptographic_primitives::proofs::sigma_dlog::{DLogProof, ProveDLog},
     elliptic::curves::traits::ECPoint,
     {BigInt, FE, GE, PK},
 };
 pub use kms::ecdsa::two_party::*;
 pub use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::*;
-use rocket::State;
-use rocket_contrib::json::Json;
 use std::string::ToString;
 use uuid::Uuid;
-use rocket_okapi::openapi;
 use url::Url;
 use sha3::Sha3_256;
 use digest::Digest;
-use crate::protocol::util::{Utilities, RateLimiter};
+use crate::protocol::util::Utilities;
 
 cfg_if! {
     if #[cfg(any(test,feature="mockdb"))]{
@@ -34,8 +39,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
     }
 }
 
@@ -50,6 +55,10 @@ pub trait Ecdsa {
     fn sign_first(&self, sign_msg1: SignMsg1) -> Result<SignReply1>;
 
     fn sign_second(&self, sign_msg2: SignMsg2) -> Result<Vec<Vec<u8>>>;
+
+    /// Reset keygen data for a shared key generated in an earlier key epoch so that the owner can
+    /// re-run keygen with the same key shares and obtain fresh Paillier parameters.
+    fn key_refresh(&self, user_id: Uuid) -> Result<()>;
 }
 
 impl Ecdsa for SCE {
@@ -74,6 +83,8 @@ impl Ecdsa for SCE {
         self.check_user_auth(&key_gen_msg1.shared_key_id)?;
         
         let user_id = key_gen_msg1.shared_key_id;
+        self.check_session_event(&user_id, SessionEvent::KeyGenFirst)?;
+        FaultInjector::new(&self.config.chaos).keygen(&user_id)?;
         let db = &self.database;
         
         // if deposit, verify VDF
@@ -185,6 +196,37 @@ impl Ecdsa for SCE {
         let kg_party_one_second_msg: party1::KeyGenParty1Message2;
         let db = &self.database;
         let user_id = key_gen_msg2.shared_key_id;
+        let next_state = self.check_session_event(&user_id, SessionEvent::KeyGenSecond)?;
+        FaultInjector::new(&self.config.chaos).keygen(&user_id)?;
+
+        // Verify party 2 discrete log proof. The outcome is recorded for auditing with that of the
+        // Paillier proofs.
+        let dlog_verified = DLogProof::verify(&key_gen_msg2.dlog_proof).is_ok();
+        info!("KEYGEN: DLog proof verified: {}. Shared Key ID: {}", dlog_verified, user_id);
+        if !dlog_verified && self.config.enforce_zk_proofs {
+            self.record_proof_verification(&user_id, false);
+            return Err(SEError::Generic(format!(
+                "Keygen DLog proof verification failed for Shared Key ID: {}",
+                user_id
+            )));
+        }
+
+        // Pay-to-contract commitments are only made in the funding output key of a deposit
+        if let Some(commitment) = &key_gen_msg2.p2c_commitment {
+            if next_state != Some(SessionState::DepositKeyGen) {
+                return Err(SEError::Generic(String::from(
+                    "Pay-to-contract commitment is only accepted at deposit",
+                )));
+            }
+            if self.lockbox.is_some() {
+                return Err(SEError::Generic(String::from(
+                    "Pay-to-contract commitment not supported with lockbox",
+                )));
+            }
+            sha256::Hash::from_hex(commitment).map_err(|e| {
+                SEError::Generic(format!("Invalid pay-to-contract commitment: {}", e))
+            })?;
+        }
 
         // call lockbox
         match &self.lockbox {
@@ -196,6 +238,7 @@ impl Ecdsa for SCE {
                 
                 let path: &str = "ecdsa/keygen/second";
                 let kg_party_one_second_message: party1::KeyGenParty1Message2 = post_lb(&lockbox_url, path, &key_gen_msg2)?;
+                self.verify_paillier_proofs(&user_id, &kg_party_one_second_message, dlog_verified)?;
                 kg_party_one_second_msg = kg_party_one_second_message;
             },
             None => {
@@ -212,6 +255,7 @@ impl Ecdsa for SCE {
                     &ec_key_pair,
                     &key_gen_msg2.dlog_proof,
                 );
+                self.verify_paillier_proofs(&user_id, &kg_party_one_second_message, dlog_verified)?;
 
                 db.update_keygen_second_msg(
                     &user_id,
@@ -221,6 +265,9 @@ impl Ecdsa for SCE {
                 )?;
 
                 self.master_key(user_id)?;
+                if let Some(commitment) = &key_gen_msg2.p2c_commitment {
+                    self.p2c_tweak_master_key(&user_id, commitment)?;
+                }
                 kg_party_one_second_msg = kg_party_one_second_message;
             }
         }
@@ -245,12 +292,20 @@ impl Ecdsa for SCE {
 
         db.update_public_master(&key_gen_msg2.shared_key_id,public_key_data)?;
 
+        // Record the keygen parameter epoch of the new shared key
+        if self.config.key_epoch > 0 {
+            db.update_ecdsa_key_epoch(&user_id, self.config.key_epoch)?;
+        }
+
+        self.update_session_state(&user_id, next_state)?;
+
         Ok(KeyGenReply2 { msg: kg_party_one_second_msg } )
     }
 
     fn sign_first(&self, sign_msg1: SignMsg1) -> Result<SignReply1> {
         self.check_user_auth(&sign_msg1.shared_key_id)?;
         let user_id = sign_msg1.shared_key_id;
+        self.check_session_event(&user_id, SessionEvent::SignFirst)?;
 
         let sign_party_one_first_msg: party_one::EphKeyGenFirstMsg;
         let db = &self.database;
@@ -284,6 +339,10 @@ impl Ecdsa for SCE {
     fn sign_second(&self, sign_msg2: SignMsg2) -> Result<Vec<Vec<u8>>> {
         self.check_user_auth(&sign_msg2.shared_key_id)?;
         let user_id = sign_msg2.shared_key_id;
+        let next_state = self.check_session_event(
+            &user_id,
+            SessionEvent::SignSecond(sign_msg2.sign_second_msg_request.protocol),
+        )?;
         let db = &self.database;
 
         // Get validated sig hash for this user
@@ -309,6 +368,16 @@ impl Ecdsa for SCE {
             )));
         }
 
+        // Refuse to co-sign a spend conflicting with one already co-signed
+        let record = db.get_sighash_record(&user_id, &sig_hash)?;
+        let signed = db.get_signed_sighashes(&record.outpoint)?;
+        if let Some(conflict) = find_conflicting_sighash(&signed, &sig_hash, record.locktime) {
+            return Err(SEError::SigningError(format!(
+                "Spend of {} conflicts with co-signed sighash {} at locktime {}",
+                record.outpoint, conflict.sig_hash, conflict.locktime
+            )));
+        }
+
         let ws: Vec<Vec<u8>>;
 
         match &self.lockbox {
@@ -365,9 +434,11 @@ impl Ecdsa for SCE {
             ws = witness;
         }}
 
+        db.set_sighash_signed(&user_id, &sig_hash)?;
+
         // Get transaction which is being signed.
         let mut tx: Transaction = match sign_msg2.sign_second_msg_request.protocol {
-            Protocol::Withdraw => db.get_tx_withdraw(user_id)?,
+            Protocol::Withdraw | Protocol::Refund => db.get_tx_withdraw(user_id)?,
             _ => db.get_user_backup_tx(user_id)?,
         };
 
@@ -388,6 +459,11 @@ impl Ecdsa for SCE {
                 db.update_tx_withdraw(user_id, tx)?;
                 info!("WITHDRAW: Tx signed and stored. User ID: {}", user_id);
             }
+            Protocol::Refund => {
+                // Store signed refund tx in UserSession DB object
+                db.update_tx_withdraw(user_id, tx)?;
+                info!("DEPOSIT: Refund tx signed and stored. User ID: {}", user_id);
+            }
             _ => {
                 // Store signed backup tx in UserSession DB object
                 db.update_user_backup_tx(&user_id, tx)?;
@@ -398,63 +474,128 @@ impl Ecdsa for SCE {
             }
         };
 
+        self.update_session_state(&user_id, next_state)?;
+
         Ok(ws)
     }
-}
 
-#[openapi]
-/// # First round of the 2P-ECDSA key generation protocol: get pubkey and ZK proof commitments
-#[post("/ecdsa/keygen/first", format = "json", data = "<key_gen_msg1>")]
-pub fn first_message(
-    sc_entity: State<SCE>,
-    key_gen_msg1: Json<KeyGenMsg1>,
-) -> Result<Json<KeyGenReply1>> {
-    sc_entity.check_rate_slow("ecdsa")?;
-    match sc_entity.first_message(key_gen_msg1.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    fn key_refresh(&self, user_id: Uuid) -> Result<()> {
+        self.check_user_auth(&user_id)?;
+        let next_state = self.check_session_event(&user_id, SessionEvent::KeyRefresh)?;
+        if self.lockbox.is_some() {
+            return Err(SEError::Generic(String::from("Key refresh not supported with lockbox")));
+        }
+        let db = &self.database;
+
+        if db.get_ecdsa_key_epoch(user_id)? >= self.config.key_epoch {
+            return Err(SEError::Generic(format!(
+                "Shared key ID {} is already at the current key epoch",
+                user_id
+            )));
+        }
+
+        // Re-use the current server private share so that the shared public key is unchanged
+        let (_, ec_key_pair) = db.get_ecdsa_witness_keypair(user_id)?;
+        let ec_key_pair_json = serde_json::to_value(&ec_key_pair).map_err(|e| e.to_string())?;
+        let s1: FE = serde_json::from_value(ec_key_pair_json["secret_share"].clone())
+            .map_err(|e| e.to_string())?;
+
+        db.update_ecdsa_s2(&user_id, &s1)?;
+        db.reset_ecdsa(&user_id)?;
+        self.update_session_state(&user_id, next_state)?;
+
+        info!("KEY REFRESH: Keygen data reset. Shared Key ID: {}", user_id);
+        Ok(())
     }
 }
 
-#[openapi]
-/// # Second round of the 2P-ECDSA key generation protocol: get Paillier share and proofs
-#[post("/ecdsa/keygen/second", format = "json", data = "<key_gen_msg2>")]
-pub fn second_message(
-    sc_entity: State<SCE>,
-    key_gen_msg2: Json<KeyGenMsg2>,
-) -> Result<Json<KeyGenReply2>> {
-    sc_entity.check_rate_slow("ecdsa")?;
-    match sc_entity.second_message(key_gen_msg2.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+impl SCE {
+    /// Record the outcome of the keygen proof verifications of a shared key for auditing
+    fn record_proof_verification(&self, user_id: &Uuid, verified: bool) {
+        if let Err(e) = self.database.update_ecdsa_proof_verified(user_id, verified) {
+            warn!("KEYGEN: Failed to record proof verification for Shared Key ID {}: {}", user_id, e);
+        }
     }
-}
 
-#[openapi]
-/// # First round of the 2P-ECDSA signing protocol: shared ephemeral keygen and proofs 
-#[post("/ecdsa/sign/first", format = "json", data = "<sign_msg1>")]
-pub fn sign_first(
-    sc_entity: State<SCE>,
-    sign_msg1: Json<SignMsg1>,
-) -> Result<Json<SignReply1>> {
-    sc_entity.check_rate_slow("ecdsa")?;
-    match sc_entity.sign_first(sign_msg1.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Verify the Paillier key correctness and range proofs of the party 1 keygen message, as the
+    /// client does, and record the outcome of the keygen proofs. Fails if a proof is not verified
+    /// and proofs are enforced.
+    fn verify_paillier_proofs(
+        &self,
+        user_id: &Uuid,
+        kg_party_one_second_msg: &party1::KeyGenParty1Message2,
+        dlog_verified: bool,
+    ) -> Result<()> {
+        let kg_first_msg = self.database.get_keygen_first_msg(user_id)?;
+        let paillier_verified =
+            MasterKey2::key_gen_second_message(&kg_first_msg, kg_party_one_second_msg).is_ok();
+        info!("KEYGEN: Paillier proofs verified: {}. Shared Key ID: {}", paillier_verified, user_id);
+        self.record_proof_verification(user_id, dlog_verified && paillier_verified);
+        if !paillier_verified && self.config.enforce_zk_proofs {
+            return Err(SEError::Generic(format!(
+                "Keygen Paillier proof verification failed for Shared Key ID: {}",
+                user_id
+            )));
+        }
+        Ok(())
     }
-}
 
-#[openapi]
-/// # Second round of the 2P-ECDSA signing protocol: signature generation and verification
-#[post("/ecdsa/sign/second", format = "json", data = "<sign_msg2>")]
-pub fn sign_second(sc_entity: State<SCE>, sign_msg2: Json<SignMsg2>) -> Result<Json<Vec<Vec<u8>>>> {
-    sc_entity.check_rate_slow("ecdsa")?;
-    match sc_entity.sign_second(sign_msg2.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Shared keys whose keygen proofs were not all verified
+    pub fn keygen_audit(&self, audit_msg: KeygenAuditMsg) -> Result<KeygenAuditAPI> {
+        self.check_admin_key(&audit_msg.admin_key)?;
+        Ok(KeygenAuditAPI {
+            enforce_zk_proofs: self.config.enforce_zk_proofs,
+            unverified: self.database.get_unverified_keygen_sessions()?,
+        })
+    }
+
+    /// Tweak the shared key of a deposit with a pay-to-contract commitment. The public key of the
+    /// master key is multiplied by the tweak, so that signatures are verified against the funding
+    /// output key. The server key share is unchanged: the owner share carries the tweak.
+    fn p2c_tweak_master_key(&self, user_id: &Uuid, commitment: &String) -> Result<()> {
+        let master_key_str = self
+            .database
+            .get_ecdsa_master(*user_id)?
+            .ok_or(SEError::DBError(DBErrorType::NoDataForID, user_id.to_string()))?;
+        let mut master_key: MasterKey1 =
+            serde_json::from_str(&master_key_str).map_err(|e| e.to_string())?;
+
+        let shared_pubkey = PublicKey {
+            compressed: true,
+            key: master_key.public.q.get_element(),
+        };
+        let tweak = p2c_tweak_fe(&shared_pubkey, commitment)?;
+        master_key.public.q = master_key.public.q * tweak;
+        master_key.public.p2 = master_key.public.p2 * tweak;
+        // The owner public key share is checked against in transfers, and must be the tweaked one
+        self.database.update_party_2_public(user_id, master_key.public.p2)?;
+        self.database.update_ecdsa_master(user_id, master_key)?;
+        self.database.update_p2c(
+            user_id,
+            &PayToContract {
+                shared_pubkey: shared_pubkey.to_string(),
+                commitment: commitment.clone(),
+            },
+        )?;
+        info!("KEYGEN: Pay-to-contract commitment {} in shared key. Shared Key ID: {}", commitment, user_id);
+        Ok(())
     }
 }
 
+/// Find a co-signed sighash that conflicts with a new spend of the same outpoint: a different
+/// finalized sighash at an equal or lower locktime than the new spend. Backup txs of deposits
+/// and transfers that were never finalized are ignored, so that aborted transfers and swaps can
+/// be retried.
+pub fn find_conflicting_sighash<'a>(
+    signed: &'a [SighashRecord],
+    sig_hash: &sha256d::Hash,
+    locktime: u32,
+) -> Option<&'a SighashRecord> {
+    signed
+        .iter()
+        .find(|r| r.finalized && r.sig_hash != *sig_hash && r.locktime <= locktime)
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -466,9 +607,8 @@ pub mod tests {
     use mockito;
     use serde_json;
     use curv::elliptic::curves::traits::ECScalar;
-    use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
-    use crate::curv::cryptographic_primitives::proofs::sigma_dlog::ProveDLog;
     use curv::cryptographic_primitives::proofs::sigma_ec_ddh::ECDDHProof;
+    use crate::protocol::transfer::transfer_key_update;
 
     #[test]
     fn test_keygen_lockbox_client() {
@@ -479,17 +619,28 @@ pub mod tests {
         db.expect_create_user_session().returning(|_, _, _, _, _| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositInit)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::DepositKeyGen)
+            .times(1)
+            .returning(|_, _| Ok(()));
         db.expect_get_lockbox_index().returning(|_| Ok(Some(0)));
         db.expect_init_ecdsa().returning(|_user_id| Ok(0));
         db.expect_update_keygen_first_msg().returning(|_,_| Ok(()));
         db.expect_update_s1_pubkey().returning(|_, _| Ok(()));
         db.expect_update_public_master().returning(|_,_| Ok(()));
+        db.expect_update_ecdsa_proof_verified()
+            .withf(|_, verified| *verified)
+            .times(1)
+            .returning(|_,_| Ok(()));
         db.expect_get_challenge().returning(move |_| Ok(challenge.clone()));
+        let (kg_first_msg, comm_witness, ec_key_pair) = MasterKey1::key_gen_first_message();
+        let kgm1_clone = kg_first_msg.clone();
+        db.expect_get_keygen_first_msg().returning(move |_| Ok(kgm1_clone.clone()));
 
         let sc_entity = test_sc_entity(db, Some(mockito::server_url()), None, None, None);
 
-        let kg_first_msg = party_one::KeyGenFirstMsg { pk_commitment: BigInt::from(0), zk_pok_commitment: BigInt::from(1) };
-
         let serialized_m1 = serde_json::to_string(&(&user_id,&kg_first_msg)).unwrap();
 
         
@@ -512,22 +663,6 @@ pub mod tests {
 
         let secret_share: FE = ECScalar::new_random();
         let d_log_proof = DLogProof::prove(&secret_share);
-        let json = r#"
-                {
-                    "public_share":{"x":"de6822e27f1223c9a8200408fa002c612c3635d801ea6c3315789f8cf3e3fe29","y":"e3231aca5034eb8bd5271b728a516720088a69e124ccbd982003c50b474bb22a"},
-                    "secret_share":"eddb897ad33e4fef8b71bd4b6eab7e6f3c6acfe8d6346989389706e4c2331be6"
-                }
-            "#;
-        
-        let ec_key_pair: party_one::EcKeyPair = serde_json::from_str(&json.to_string()).unwrap();
-
-        let comm_witness = party_one::CommWitness {
-            pk_commitment_blind_factor: BigInt::from(0),
-            zk_pok_blind_factor: BigInt::from(1),
-            public_share: ECPoint::generator(),
-            d_log_proof: d_log_proof.clone(),
-        };
-        
         let (kg_party_one_second_message, _, _): (
             party1::KeyGenParty1Message2,
             party_one::PaillierKeyPair,
@@ -545,7 +680,7 @@ pub mod tests {
           .with_body(serialized_m2)
           .create();
 
-        let kg_msg_2 = KeyGenMsg2 { shared_key_id: user_id, dlog_proof: d_log_proof};
+        let kg_msg_2 = KeyGenMsg2 { shared_key_id: user_id, dlog_proof: d_log_proof, p2c_commitment: None };
         
         let return_msg = sc_entity.second_message(kg_msg_2).unwrap();
 
@@ -562,15 +697,19 @@ pub mod tests {
         db.expect_create_user_session().returning(|_, _, _, _, _| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositInit)));
+        db.expect_update_session_state().returning(|_, _| Ok(()));
         db.expect_get_lockbox_index().returning(|_| Ok(Some(0)));
 
-        let kg_first_msg = party_one::KeyGenFirstMsg { pk_commitment: BigInt::from(0), zk_pok_commitment: BigInt::from(1) };
+        let (kg_first_msg, comm_witness, ec_key_pair) = MasterKey1::key_gen_first_message();
         
         let kgm1_clone = kg_first_msg.clone();
 
         db.expect_get_keygen_first_msg().returning(move |_| Ok(kgm1_clone.clone()));
         db.expect_update_s1_pubkey().returning(|_, _| Ok(()));
         db.expect_update_public_master().returning(|_,_| Ok(()));
+        db.expect_update_ecdsa_proof_verified().returning(|_,_| Ok(()));
         db.expect_get_challenge().returning(move |_| Ok(challenge.clone()));
 
         let sc_entity = test_sc_entity(db, Some(mockito::server_url()), None, None, None);
@@ -598,22 +737,6 @@ pub mod tests {
 
         let secret_share: FE = ECScalar::new_random();
         let d_log_proof = DLogProof::prove(&secret_share);
-        let json = r#"
-                {
-                    "public_share":{"x":"de6822e27f1223c9a8200408fa002c612c3635d801ea6c3315789f8cf3e3fe29","y":"e3231aca5034eb8bd5271b728a516720088a69e124ccbd982003c50b474bb22a"},
-                    "secret_share":"eddb897ad33e4fef8b71bd4b6eab7e6f3c6acfe8d6346989389706e4c2331be6"
-                }
-            "#;
-        
-        let ec_key_pair: party_one::EcKeyPair = serde_json::from_str(&json.to_string()).unwrap();
-
-        let comm_witness = party_one::CommWitness {
-            pk_commitment_blind_factor: BigInt::from(0),
-            zk_pok_blind_factor: BigInt::from(1),
-            public_share: ECPoint::generator(),
-            d_log_proof: d_log_proof.clone(),
-        };
-        
         let (kg_party_one_second_message, _, _): (
             party1::KeyGenParty1Message2,
             party_one::PaillierKeyPair,
@@ -631,7 +754,7 @@ pub mod tests {
           .with_body(serialized_m2)
           .create();
 
-        let kg_msg_2 = KeyGenMsg2 { shared_key_id: user_id, dlog_proof: d_log_proof};
+        let kg_msg_2 = KeyGenMsg2 { shared_key_id: user_id, dlog_proof: d_log_proof, p2c_commitment: None };
         
         let return_msg = sc_entity.second_message(kg_msg_2).unwrap();
 
@@ -648,6 +771,8 @@ pub mod tests {
         db.expect_create_user_session().returning(|_, _, _, _, _| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositInit)));
         db.expect_get_lockbox_index().returning(|_| Ok(Some(0)));
 
         let kg_first_msg_err = SEError::DBError(
@@ -658,6 +783,7 @@ pub mod tests {
         db.expect_get_keygen_first_msg().returning(move |_| Err(kgm1_clone.clone()));
         db.expect_update_s1_pubkey().returning(|_, _| Ok(()));
         db.expect_update_public_master().returning(|_,_| Ok(()));
+        db.expect_update_ecdsa_proof_verified().returning(|_,_| Ok(()));
         db.expect_get_challenge().returning(move |_| Ok(challenge.clone()));
 
         let sc_entity = test_sc_entity(db, Some(mockito::server_url()), None, None, None);
@@ -691,11 +817,18 @@ pub mod tests {
     fn test_sign_lockbox_client() {
         let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
         let tx_backup: Transaction = serde_json::from_str(&BACKUP_TX_NOT_SIGNED).unwrap();
+        let outpoint = tx_backup.input[0].previous_output;
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_create_user_session().returning(|_, _, _, _, _| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositKeyGen)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::DepositSigned)
+            .times(1)
+            .returning(|_, _| Ok(()));
         db.expect_get_lockbox_index().returning(|_| Ok(Some(0)));
         db.expect_get_user_backup_tx().returning(move |_| Ok(tx_backup.clone()));
         db.expect_update_user_backup_tx().returning(|_, _| Ok(()));
@@ -704,6 +837,16 @@ pub mod tests {
             "#;
         let sig_hash: sha256d::Hash = serde_json::from_str(&hexhash.to_string()).unwrap();
         db.expect_get_sighash().returning(move |_| Ok(sig_hash));
+        db.expect_get_sighash_record().returning(move |user_id, sig_hash| Ok(SighashRecord {
+            user_id: *user_id,
+            outpoint,
+            sig_hash: *sig_hash,
+            locktime: 0,
+            signed: false,
+            finalized: true,
+        }));
+        db.expect_get_signed_sighashes().returning(|_| Ok(vec![]));
+        db.expect_set_sighash_signed().times(1).returning(|_, _| Ok(()));
         db.expect_update_shared_pubkey().returning(|_,_| Ok(()));
 
         let sc_entity = test_sc_entity(db, Some(mockito::server_url()), None, None, None);
@@ -770,4 +913,282 @@ pub mod tests {
 
     }
 
+    #[test]
+    fn test_find_conflicting_sighash() {
+        let tx_backup: Transaction = serde_json::from_str(&BACKUP_TX_NOT_SIGNED).unwrap();
+        let hash_1: sha256d::Hash = serde_json::from_str(
+            "\"0000000000000000000000000000000000000000000000000000000000000001\"").unwrap();
+        let hash_2: sha256d::Hash = serde_json::from_str(
+            "\"0000000000000000000000000000000000000000000000000000000000000002\"").unwrap();
+        let mut signed = vec![SighashRecord {
+            user_id: Uuid::new_v4(),
+            outpoint: tx_backup.input[0].previous_output,
+            sig_hash: hash_1,
+            locktime: 1000,
+            signed: true,
+            finalized: true,
+        }];
+
+        // re-signing the same sighash is allowed
+        assert!(find_conflicting_sighash(&signed, &hash_1, 1000).is_none());
+        // a different spend with a lower locktime is allowed
+        assert!(find_conflicting_sighash(&signed, &hash_2, 900).is_none());
+        // a different spend with an equal or higher locktime is refused
+        assert!(find_conflicting_sighash(&signed, &hash_2, 1000).is_some());
+        assert!(find_conflicting_sighash(&signed, &hash_2, 1100).is_some());
+
+        // the backup tx of an aborted transfer does not block a retry at the same locktime
+        signed[0].finalized = false;
+        assert!(find_conflicting_sighash(&signed, &hash_2, 1000).is_none());
+    }
+
+    #[test]
+    fn test_keygen_invalid_dlog_proof() {
+        let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+           .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositInit)));
+        db.expect_update_ecdsa_proof_verified()
+            .withf(|_, verified| !*verified)
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        let secret_share: FE = ECScalar::new_random();
+        let mut d_log_proof = DLogProof::prove(&secret_share);
+        d_log_proof.pk = ECPoint::generator();
+
+        let kg_msg_2 = KeyGenMsg2 { shared_key_id: user_id, dlog_proof: d_log_proof, p2c_commitment: None };
+        match sc_entity.second_message(kg_msg_2) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("DLog proof verification failed")),
+        }
+    }
+
+    #[test]
+    fn test_keygen_invalid_paillier_proofs() {
+        let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let (kg_first_msg, _, _) = MasterKey1::key_gen_first_message();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+           .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::DepositInit)));
+        db.expect_get_lockbox_index().returning(|_| Ok(Some(0)));
+        db.expect_get_keygen_first_msg().returning(move |_| Ok(kg_first_msg.clone()));
+        db.expect_update_ecdsa_proof_verified()
+            .withf(|_, verified| !*verified)
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let sc_entity = test_sc_entity(db, Some(mockito::server_url()), None, None, None);
+
+        // Party 1 message not matching the commitments of the party 1 first message
+        let (_, comm_witness, ec_key_pair) = MasterKey1::key_gen_first_message();
+        let d_log_proof = DLogProof::prove(&ECScalar::new_random());
+        let (kg_party_one_second_message, _, _): (
+            party1::KeyGenParty1Message2,
+            party_one::PaillierKeyPair,
+            party_one::Party1Private,
+        ) = MasterKey1::key_gen_second_message(
+            comm_witness,
+            &ec_key_pair,
+            &d_log_proof,
+        );
+        let _m_2 = mockito::mock("POST", "/ecdsa/keygen/second")
+          .with_header("content-type", "application/json")
+          .with_body(serde_json::to_string(&kg_party_one_second_message).unwrap())
+          .create();
+
+        let kg_msg_2 = KeyGenMsg2 { shared_key_id: user_id, dlog_proof: d_log_proof, p2c_commitment: None };
+        match sc_entity.second_message(kg_msg_2) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Paillier proof verification failed")),
+        }
+    }
+
+    #[test]
+    fn test_keygen_audit() {
+        let unverified = Uuid::new_v4();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_unverified_keygen_sessions()
+            .returning(move || Ok(vec![unverified]));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.admin_key = Some(String::from("admin"));
+
+        let audit_msg = |admin_key: &str| KeygenAuditMsg { admin_key: admin_key.to_string() };
+        match sc_entity.keygen_audit(audit_msg("not admin")) {
+            Err(SEError::AuthError) => (),
+            r => assert!(false, "Expected AuthError, got {:?}", r),
+        }
+        let audit = sc_entity.keygen_audit(audit_msg("admin")).unwrap();
+        assert!(audit.enforce_zk_proofs);
+        assert_eq!(audit.unverified, vec![unverified]);
+    }
+
+    #[test]
+    fn test_keygen_p2c_commitment_rejected() {
+        let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let test_db = || {
+            let mut db = MockDatabase::new();
+            db.expect_set_connection_from_config().returning(|_| Ok(()));
+            db.expect_get_user_auth()
+               .returning(|_user_id| Ok(String::from("user_auth")));
+            db.expect_get_session_state()
+                .returning(|_| Ok(Some(SessionState::DepositInit)));
+            db.expect_update_ecdsa_proof_verified().returning(|_, _| Ok(()));
+            db
+        };
+        let secret_share: FE = ECScalar::new_random();
+        let d_log_proof = DLogProof::prove(&secret_share);
+
+        // Commitment must be a sha256 hash
+        let sc_entity = test_sc_entity(test_db(), None, None, None, None);
+        let kg_msg_2 = KeyGenMsg2 {
+            shared_key_id: user_id,
+            dlog_proof: d_log_proof.clone(),
+            p2c_commitment: Some(String::from("not a hash")),
+        };
+        match sc_entity.second_message(kg_msg_2) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Invalid pay-to-contract commitment")),
+        }
+
+        // Lockbox shares cannot be tweaked
+        let sc_entity = test_sc_entity(test_db(), Some(mockito::server_url()), None, None, None);
+        let kg_msg_2 = KeyGenMsg2 {
+            shared_key_id: user_id,
+            dlog_proof: d_log_proof,
+            p2c_commitment: Some(hex::encode([1u8; 32])),
+        };
+        match sc_entity.second_message(kg_msg_2) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("not supported with lockbox")),
+        }
+    }
+
+    #[test]
+    fn test_p2c_transfer_key_update() {
+        let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let commitment = hex::encode([1u8; 32]);
+
+        // Shared key with server share s1 and owner share o1
+        let (_, comm_witness, ec_key_pair) = MasterKey1::key_gen_first_message();
+        let public_share = comm_witness.public_share.clone();
+        let o1: FE = ECScalar::new_random();
+        let d_log_proof = DLogProof::prove(&o1);
+        let (_, paillier_key_pair, party_one_private): (
+            party1::KeyGenParty1Message2,
+            party_one::PaillierKeyPair,
+            party_one::Party1Private,
+        ) = MasterKey1::key_gen_second_message(comm_witness, &ec_key_pair, &d_log_proof);
+        let s1 = party_one_private.get_private_key();
+        let master_key = MasterKey1::set_master_key(
+            &BigInt::from(0),
+            party_one_private,
+            &public_share,
+            &d_log_proof.pk,
+            paillier_key_pair,
+        );
+        let master_key_str = serde_json::to_string(&master_key).unwrap();
+
+        // Owner share tweaked by the commitment, as done by the client
+        let shared_pubkey = PublicKey {
+            compressed: true,
+            key: master_key.public.q.get_element(),
+        };
+        let tweak = p2c_tweak_fe(&shared_pubkey, &commitment).unwrap();
+        let o1_tweaked = o1 * tweak;
+        let g: GE = ECPoint::generator();
+        let o1_pub_tweaked = g * o1_tweaked;
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_ecdsa_master()
+            .returning(move |_| Ok(Some(master_key_str.clone())));
+        db.expect_update_party_2_public()
+            .withf(move |_, party2_public| *party2_public == o1_pub_tweaked)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_update_ecdsa_master()
+            .withf(move |_, master_key| master_key.public.p2 == o1_pub_tweaked)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_update_p2c().times(1).returning(|_, _| Ok(()));
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.p2c_tweak_master_key(&user_id, &commitment).unwrap();
+
+        // Transfer of the tweaked coin: sender t1 = o1 * x1, receiver t2 = t1 * o2^-1
+        let x1: FE = ECScalar::new_random();
+        let o2: FE = ECScalar::new_random();
+        let t2 = o1_tweaked * x1 * o2.invert();
+        let o2_pub = g * o2;
+        let (s2, _) = transfer_key_update(&s1, &x1, &t2, &o1_pub_tweaked, &o2_pub).unwrap();
+        assert_eq!(
+            master_key.public.q * tweak,
+            o2_pub * s2,
+            "shared public key unchanged by the transfer"
+        );
+
+        // The untweaked owner public key share fails the transfer check
+        match transfer_key_update(&s1, &x1, &t2, &d_log_proof.pk, &o2_pub) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("P1 != P2")),
+        }
+    }
+
+    #[test]
+    fn test_key_refresh() {
+        let user_id = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+           .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Owner)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::RefreshInit)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_get_ecdsa_key_epoch().returning(|_| Ok(0));
+        let json = r#"
+                {
+                    "public_share":{"x":"de6822e27f1223c9a8200408fa002c612c3635d801ea6c3315789f8cf3e3fe29","y":"e3231aca5034eb8bd5271b728a516720088a69e124ccbd982003c50b474bb22a"},
+                    "secret_share":"eddb897ad33e4fef8b71bd4b6eab7e6f3c6acfe8d6346989389706e4c2331be6"
+                }
+            "#;
+        let ec_key_pair: party_one::EcKeyPair = serde_json::from_str(&json.to_string()).unwrap();
+        let comm_witness = party_one::CommWitness {
+            pk_commitment_blind_factor: BigInt::from(0),
+            zk_pok_blind_factor: BigInt::from(1),
+            public_share: ECPoint::generator(),
+            d_log_proof: DLogProof::prove(&ECScalar::new_random()),
+        };
+        db.expect_get_ecdsa_witness_keypair()
+            .returning(move |_| Ok((comm_witness.clone(), ec_key_pair.clone())));
+        let expected_s1: FE = serde_json::from_str("\"eddb897ad33e4fef8b71bd4b6eab7e6f3c6acfe8d6346989389706e4c2331be6\"").unwrap();
+        db.expect_update_ecdsa_s2()
+            .withf(move |_, s2| *s2 == expected_s1)
+            .returning(|_, _| Ok(()));
+        db.expect_reset_ecdsa().times(1).returning(|_| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Shared key already at current epoch
+        match sc_entity.key_refresh(user_id) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("already at the current key epoch")),
+        }
+
+        // Rotate key epoch
+        sc_entity.config.key_epoch = 1;
+        assert!(sc_entity.key_refresh(user_id).is_ok());
+    }
+
 }
@@ -0,0 +1,404 @@
+//! StateEntity Inheritance
+//!
+//! Time-locked transfer of a statecoin to a beneficiary on top of the transfer protocol.
+//!
+//! The owner transfers the statecoin to the beneficiary's proof key as usual but does not pass
+//! the transfer message on, and instead designates the receiver of the pending transfer as
+//! beneficiary with an inactivity period, signing the transfer statechain sig. The beneficiary
+//! cannot complete transfer/receiver until the owner has not checked in for the inactivity
+//! period. Until then the owner can check in, revoke the designation or transfer, withdraw or
+//! migrate the statecoin as usual. A designation only applies to the transfer it signs, so a
+//! new transfer by the owner releases it.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::{DBErrorType, SEError};
+use crate::server::StateChainEntity;
+use crate::structs::{InheritanceRecord, StateChainOwner};
+use crate::Database;
+use shared_lib::state_chain::{is_locked, StateChainSig};
+use shared_lib::structs::{InheritanceAPI, InheritanceMsg, InheritanceOwnerMsg};
+
+use cfg_if::cfg_if;
+use chrono::{Duration, NaiveDateTime, Utc};
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Time from which the beneficiary can complete the transfer
+pub fn claimable_from(inheritance: &InheritanceRecord) -> NaiveDateTime {
+    inheritance.last_checkin + Duration::seconds(inheritance.inactivity_period as i64)
+}
+
+fn inheritance_api(inheritance: &InheritanceRecord) -> InheritanceAPI {
+    InheritanceAPI {
+        statechain_id: inheritance.statechain_id,
+        transfer_sig: inheritance.transfer_sig.clone(),
+        designation_sig: inheritance.designation_sig.clone(),
+        inactivity_period: inheritance.inactivity_period,
+        last_checkin: inheritance.last_checkin,
+        claimable_from: claimable_from(inheritance),
+    }
+}
+
+/// StateChain Entity inheritance API calls
+pub trait Inheritance {
+    /// API: Designate the receiver of the pending transfer of a statecoin owned by the caller as
+    /// its beneficiary. The transfer can only be completed once the owner has not checked in
+    /// for the inactivity period.
+    fn inheritance_designate(&self, inheritance_msg: InheritanceMsg) -> Result<InheritanceAPI>;
+
+    /// API: Owner check in, restarting the inactivity period
+    fn inheritance_checkin(&self, owner_msg: InheritanceOwnerMsg) -> Result<InheritanceAPI>;
+
+    /// API: Owner revokes the beneficiary designation. The pending transfer can then be
+    /// completed by its receiver at any time, so the owner should also transfer the
+    /// statecoin again.
+    fn inheritance_revoke(&self, owner_msg: InheritanceOwnerMsg) -> Result<()>;
+
+    /// API: Get the beneficiary designation of a statechain
+    fn inheritance_get(&self, statechain_id: Uuid) -> Result<InheritanceAPI>;
+}
+
+impl SCE {
+    /// Statechain owned by an authorised user and not locked
+    fn inheritance_owner(&self, user_id: &Uuid) -> Result<(Uuid, StateChainOwner)> {
+        self.check_user_auth(user_id)?;
+        let statechain_id = self.database.get_statechain_id(*user_id)?;
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        is_locked(sco.locked_until)?;
+        if sco.owner_id != *user_id {
+            return Err(SEError::Generic(format!(
+                "State Chain not owned by User ID: {}.",
+                user_id
+            )));
+        }
+        Ok((statechain_id, sco))
+    }
+
+    /// Beneficiary designation of the pending transfer of a statechain. None if there is no
+    /// designation or it signs an earlier transfer.
+    fn get_active_inheritance(&self, statechain_id: &Uuid) -> Result<Option<InheritanceRecord>> {
+        let inheritance = match self.database.get_inheritance(statechain_id)? {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        match self.database.get_transfer_data(*statechain_id) {
+            Ok(td) if td.statechain_sig == inheritance.transfer_sig => Ok(Some(inheritance)),
+            Ok(_) | Err(SEError::DBError(DBErrorType::NoDataForID, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Err if the receiver of a transfer is a beneficiary whose inactivity period has not
+    /// passed. Returns true if the transfer is claimed by a beneficiary.
+    pub fn check_inheritance_claim(
+        &self,
+        statechain_id: &Uuid,
+        transfer_sig: &StateChainSig,
+    ) -> Result<bool> {
+        let inheritance = match self.database.get_inheritance(statechain_id)? {
+            Some(i) if &i.transfer_sig == transfer_sig => i,
+            _ => return Ok(false),
+        };
+        let claimable_from = claimable_from(&inheritance);
+        if Utc::now().naive_utc() < claimable_from {
+            return Err(SEError::Generic(format!(
+                "Statechain {} can only be claimed by the beneficiary from {}",
+                statechain_id, claimable_from
+            )));
+        }
+        info!(
+            "INHERITANCE: Claimed by beneficiary. State Chain ID: {}",
+            statechain_id
+        );
+        Ok(true)
+    }
+}
+
+impl Inheritance for SCE {
+    fn inheritance_designate(&self, inheritance_msg: InheritanceMsg) -> Result<InheritanceAPI> {
+        let inactivity_period = inheritance_msg.inactivity_period;
+        if inactivity_period < self.config.inheritance_min_inactivity
+            || inactivity_period > self.config.inheritance_max_inactivity
+        {
+            return Err(SEError::Generic(format!(
+                "Inactivity period must be between {} and {} seconds",
+                self.config.inheritance_min_inactivity, self.config.inheritance_max_inactivity
+            )));
+        }
+        let (statechain_id, sco) = self.inheritance_owner(&inheritance_msg.shared_key_id)?;
+
+        // The beneficiary is the receiver of the pending transfer
+        let td = self.database.get_transfer_data(statechain_id)?;
+        if td.batch_id.is_some() {
+            return Err(SEError::Generic(format!(
+                "Transfer of statechain {} is part of a batch and cannot be designated",
+                statechain_id
+            )));
+        }
+        if self.has_open_sale(&statechain_id)? {
+            return Err(SEError::Generic(format!(
+                "Statechain {} has an open sale and cannot be designated",
+                statechain_id
+            )));
+        }
+
+        let designation_sig = inheritance_msg.statechain_sig;
+        if !designation_sig.is_inheritance(&td.statechain_sig, inactivity_period, &statechain_id) {
+            return Err(SEError::Generic(format!(
+                "Designation of statechain {} does not sign the pending transfer",
+                statechain_id
+            )));
+        }
+        designation_sig.verify(&sco.chain.get_tip().data)?;
+
+        let inheritance = InheritanceRecord {
+            statechain_id,
+            transfer_sig: td.statechain_sig,
+            designation_sig,
+            inactivity_period,
+            last_checkin: Utc::now().naive_utc(),
+        };
+        self.database.update_inheritance(&inheritance)?;
+        info!(
+            "INHERITANCE: Designated. State Chain ID: {}. Inactivity period: {}",
+            statechain_id, inactivity_period
+        );
+        Ok(inheritance_api(&inheritance))
+    }
+
+    fn inheritance_checkin(&self, owner_msg: InheritanceOwnerMsg) -> Result<InheritanceAPI> {
+        let (statechain_id, _) = self.inheritance_owner(&owner_msg.shared_key_id)?;
+        let mut inheritance = match self.get_active_inheritance(&statechain_id)? {
+            Some(i) => i,
+            None => {
+                return Err(SEError::Generic(format!(
+                    "No beneficiary designation for statechain {}",
+                    statechain_id
+                )))
+            }
+        };
+        inheritance.last_checkin = Utc::now().naive_utc();
+        self.database.update_inheritance(&inheritance)?;
+        debug!("INHERITANCE: Check in. State Chain ID: {}", statechain_id);
+        Ok(inheritance_api(&inheritance))
+    }
+
+    fn inheritance_revoke(&self, owner_msg: InheritanceOwnerMsg) -> Result<()> {
+        let (statechain_id, _) = self.inheritance_owner(&owner_msg.shared_key_id)?;
+        self.database.remove_inheritance(&statechain_id)?;
+        info!("INHERITANCE: Revoked. State Chain ID: {}", statechain_id);
+        Ok(())
+    }
+
+    fn inheritance_get(&self, statechain_id: Uuid) -> Result<InheritanceAPI> {
+        match self.get_active_inheritance(&statechain_id)? {
+            Some(inheritance) => Ok(inheritance_api(&inheritance)),
+            None => Err(SEError::Generic(format!(
+                "No beneficiary designation for statechain {}",
+                statechain_id
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use crate::structs::TransferData;
+    use bitcoin::secp256k1::key::SecretKey;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1};
+    use curv::elliptic::curves::traits::ECScalar;
+    use shared_lib::state_chain::StateChain;
+    use std::sync::{Arc, Mutex};
+
+    /// Mock database holding a single designation of a statechain with a pending transfer
+    fn inheritance_db(
+        shared_key_id: Uuid,
+        statechain_id: Uuid,
+        owner_priv: &SecretKey,
+        transfer_sig: &StateChainSig,
+    ) -> (MockDatabase, Arc<Mutex<Option<InheritanceRecord>>>) {
+        let inheritance = Arc::new(Mutex::new(None));
+        let owner_proof_key =
+            PublicKey::from_secret_key(&Secp256k1::new(), owner_priv).to_string();
+        let transfer_sig = transfer_sig.clone();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_| Ok(String::from("user_auth")));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: shared_key_id,
+                chain: StateChain::new(owner_proof_key.clone()),
+            })
+        });
+        db.expect_get_transfer_data().returning(move |_| {
+            Ok(TransferData {
+                statechain_id,
+                statechain_sig: transfer_sig.clone(),
+                x1: ECScalar::new_random(),
+                batch_id: None,
+                version: 0,
+            })
+        });
+        db.expect_get_sale().returning(|_| Ok(None));
+        let get = inheritance.clone();
+        db.expect_get_inheritance()
+            .returning(move |_| Ok(get.lock().unwrap().clone()));
+        let update = inheritance.clone();
+        db.expect_update_inheritance().returning(move |i| {
+            *update.lock().unwrap() = Some(i.clone());
+            Ok(())
+        });
+        let remove = inheritance.clone();
+        db.expect_remove_inheritance().returning(move |_| {
+            *remove.lock().unwrap() = None;
+            Ok(())
+        });
+        (db, inheritance)
+    }
+
+    fn transfer_sig(owner_priv: &SecretKey, beneficiary_priv: &SecretKey) -> StateChainSig {
+        let beneficiary_proof_key =
+            PublicKey::from_secret_key(&Secp256k1::new(), beneficiary_priv).to_string();
+        StateChainSig::new(owner_priv, &String::from("TRANSFER"), &beneficiary_proof_key).unwrap()
+    }
+
+    #[test]
+    #[serial]
+    fn test_inheritance_designate() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let beneficiary_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let transfer_sig = transfer_sig(&owner_priv, &beneficiary_priv);
+        let (db, _) = inheritance_db(shared_key_id, statechain_id, &owner_priv, &transfer_sig);
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        let period = sc_entity.config.inheritance_min_inactivity;
+
+        let sig = |key: &SecretKey, period: u64| {
+            StateChainSig::new_inheritance_sig(key, &transfer_sig, period, &statechain_id).unwrap()
+        };
+        let msg = |statechain_sig: StateChainSig, inactivity_period: u64| InheritanceMsg {
+            shared_key_id,
+            statechain_sig,
+            inactivity_period,
+        };
+
+        // Inactivity period out of range
+        assert!(sc_entity
+            .inheritance_designate(msg(sig(&owner_priv, period - 1), period - 1))
+            .is_err());
+        let max = sc_entity.config.inheritance_max_inactivity;
+        assert!(sc_entity
+            .inheritance_designate(msg(sig(&owner_priv, max + 1), max + 1))
+            .is_err());
+        // Signed for another period or by another key than the owner proof key
+        assert!(sc_entity
+            .inheritance_designate(msg(sig(&owner_priv, period + 1), period))
+            .is_err());
+        assert!(sc_entity
+            .inheritance_designate(msg(sig(&beneficiary_priv, period), period))
+            .is_err());
+        // Only the owner can designate
+        let mut not_owner = msg(sig(&owner_priv, period), period);
+        not_owner.shared_key_id = Uuid::new_v4();
+        match sc_entity.inheritance_designate(not_owner) {
+            Err(SEError::Generic(e)) => assert!(e.contains("not owned")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        let inheritance = sc_entity
+            .inheritance_designate(msg(sig(&owner_priv, period), period))
+            .unwrap();
+        assert_eq!(inheritance.statechain_id, statechain_id);
+        assert_eq!(inheritance.transfer_sig, transfer_sig);
+        assert_eq!(
+            inheritance.claimable_from,
+            inheritance.last_checkin + Duration::seconds(period as i64)
+        );
+        assert_eq!(sc_entity.inheritance_get(statechain_id).unwrap(), inheritance);
+
+        // Check in restarts the inactivity period
+        let checkin = sc_entity
+            .inheritance_checkin(InheritanceOwnerMsg { shared_key_id })
+            .unwrap();
+        assert!(checkin.last_checkin >= inheritance.last_checkin);
+
+        sc_entity
+            .inheritance_revoke(InheritanceOwnerMsg { shared_key_id })
+            .unwrap();
+        assert!(sc_entity.inheritance_get(statechain_id).is_err());
+        assert!(sc_entity
+            .inheritance_checkin(InheritanceOwnerMsg { shared_key_id })
+            .is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_inheritance_claim() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let beneficiary_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let transfer_sig = transfer_sig(&owner_priv, &beneficiary_priv);
+        let (db, inheritance) =
+            inheritance_db(shared_key_id, statechain_id, &owner_priv, &transfer_sig);
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        let period = 86400;
+        let record = InheritanceRecord {
+            statechain_id,
+            transfer_sig: transfer_sig.clone(),
+            designation_sig: StateChainSig::new_inheritance_sig(
+                &owner_priv,
+                &transfer_sig,
+                period,
+                &statechain_id,
+            )
+            .unwrap(),
+            inactivity_period: period,
+            last_checkin: Utc::now().naive_utc(),
+        };
+
+        // No designation
+        assert!(!sc_entity
+            .check_inheritance_claim(&statechain_id, &transfer_sig)
+            .unwrap());
+
+        // The beneficiary cannot claim before the inactivity period passes
+        *inheritance.lock().unwrap() = Some(record.clone());
+        match sc_entity.check_inheritance_claim(&statechain_id, &transfer_sig) {
+            Err(SEError::Generic(e)) => assert!(e.contains("can only be claimed")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        // Other transfers of the statechain are not affected
+        let other_transfer_sig = self::transfer_sig(&owner_priv, &owner_priv);
+        assert!(!sc_entity
+            .check_inheritance_claim(&statechain_id, &other_transfer_sig)
+            .unwrap());
+
+        // Owner inactive
+        let mut inactive = record;
+        inactive.last_checkin = Utc::now().naive_utc() - Duration::seconds(period as i64 + 1);
+        *inheritance.lock().unwrap() = Some(inactive);
+        assert!(sc_entity
+            .check_inheritance_claim(&statechain_id, &transfer_sig)
+            .unwrap());
+    }
+}
@@ -0,0 +1,566 @@
+//! StateEntity Migrate
+//!
+//! StateEntity Migrate protocol trait and implementation.
+//!
+//! A statecoin is moved from one state entity to another in a three-party re-keying between the
+//! owner, the old state entity and the new state entity. The shared public key, and so the
+//! funding output and the owner's backup transaction, are unchanged:
+//!     1. The owner signs the statechain over to the migration public key of the new entity
+//!        (purpose MIGRATE) and the old entity returns a blinding factor x1 (as in transfer).
+//!     2. The owner sends t2 = o1*x1*o2_inv and O2 to the old entity. The old entity computes the
+//!        new key share s2 = t2*x1_inv*s1, checks O2*s2 = P and returns the statechain export and
+//!        s2, encrypted to the migration public key, in a package signed with its export key.
+//!        The statecoin is then closed on the old entity.
+//!     3. The owner passes the package to the new entity, which verifies it against its trusted
+//!        export keys and opens a user session with key share s2. The owner then runs keygen
+//!        with its new share o2 as in transfer.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::aml::{aml_screen, AmlEvent, AmlEventKind};
+use crate::error::SEError;
+use crate::protocol::{session::SessionEvent, util::Utilities, withdraw::Withdraw};
+use crate::Database;
+use crate::{server::StateChainEntity, storage::Storage};
+use shared_lib::{
+    ecies, ecies::SelfEncryptable, ecies::WalletDecryptable, state_chain::*, structs::*, util::transaction_deserialise,
+};
+
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::{key::SecretKey, PublicKey, Secp256k1};
+use bitcoin::util::key::PrivateKey;
+use cfg_if::cfg_if;
+use curv::{
+    elliptic::curves::traits::{ECPoint, ECScalar},
+    FE, GE,
+};
+use std::{convert::TryInto, str::FromStr};
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// StateChain Migrate protocol trait
+pub trait Migrate {
+    /// API: Get the migration public key of this state entity
+    fn migrate_get_pubkey(&self) -> Result<MigratePubKey>;
+
+    /// API: Initiate migration of a statecoin to another state entity:
+    ///     - Check the statechain signature for the new entity migration public key
+    ///     - Generate x1 and return it encrypted to the owner proof key
+    fn migrate_init(&self, migrate_msg1: MigrateMsg1) -> Result<TransferMsg2>;
+
+    /// API: Re-key and export the statecoin to the new state entity:
+    ///     - Compute the new entity key share s2 and check O2*s2 = P
+    ///     - Return the signed migration package
+    ///     - Close the statecoin on this state entity
+    fn migrate_export(&self, migrate_msg2: MigrateMsg2) -> Result<SignedMigrationPackage>;
+
+    /// API: Import a statecoin migrated from another state entity:
+    ///     - Verify the migration package
+    ///     - Create the statechain and a user session with key share s2 for the owner
+    ///     - Return the new shared key ID and the public key share S2
+    fn migrate_import(&self, package: SignedMigrationPackage) -> Result<TransferMsg5>;
+}
+
+impl SCE {
+    fn migration_key(&self) -> Result<SecretKey> {
+        match &self.config.migration_key {
+            Some(k) => SecretKey::from_str(k)
+                .map_err(|e| SEError::Generic(format!("Invalid migration key: {}", e))),
+            None => Err(SEError::Generic(String::from(
+                "Statechain migration import not enabled",
+            ))),
+        }
+    }
+
+    fn migration_export_key(&self) -> Result<SecretKey> {
+        match &self.config.export_key {
+            Some(k) => SecretKey::from_str(k)
+                .map_err(|e| SEError::Generic(format!("Invalid statechain export key: {}", e))),
+            None => Err(SEError::Generic(String::from(
+                "Statechain migration export not enabled",
+            ))),
+        }
+    }
+}
+
+impl Migrate for SCE {
+    fn migrate_get_pubkey(&self) -> Result<MigratePubKey> {
+        let key = self.migration_key()?;
+        Ok(MigratePubKey {
+            key: PublicKey::from_secret_key(&Secp256k1::new(), &key).to_string(),
+        })
+    }
+
+    fn migrate_init(&self, migrate_msg1: MigrateMsg1) -> Result<TransferMsg2> {
+        self.check_user_auth(&migrate_msg1.shared_key_id)?;
+        let user_id = migrate_msg1.shared_key_id;
+        let next_state = self.check_session_event(&user_id, SessionEvent::MigrateInit)?;
+        debug!("MIGRATE: Init. Shared Key ID: {}", user_id);
+
+        // Fail before any state changes if this entity cannot sign the package
+        self.migration_export_key()?;
+
+        if migrate_msg1.statechain_sig.purpose != "MIGRATE" {
+            return Err(SEError::Generic(String::from(
+                "migrate_init - statechain signature purpose must be MIGRATE",
+            )));
+        }
+        if ecies::PublicKey::from_str(&migrate_msg1.statechain_sig.data).is_err() {
+            return Err(SEError::Generic(String::from(
+                "migrate_init - statechain signature data is not a migration public key",
+            )));
+        }
+
+        if self.get_if_signed_for_withdrawal(&user_id)?.is_some() {
+            return Err(SEError::Generic(format!(
+                "migrate_init - shared key id: {} is signed for withdrawal",
+                &user_id
+            )));
+        }
+
+        // Key shares held by a lockbox cannot be re-keyed for export
+        if self.get_lockbox_url(&user_id)?.is_some() {
+            return Err(SEError::Generic(format!(
+                "migrate_init - shared key id: {} is held by a lockbox",
+                &user_id
+            )));
+        }
+
+        let statechain_id = self.database.get_statechain_id(user_id)?;
+        self.check_sale_not_held(&statechain_id)?;
+
+        // Only confirmed statecoins can be migrated
+        if !self.database.is_confirmed(&statechain_id)? {
+            self.verify_tx_confirmed(&statechain_id)?;
+            self.database.set_confirmed(&statechain_id)?;
+            let sc_amount = self.database.get_statechain_amount(statechain_id.clone())?;
+            let mut guard = self.coin_value_info.as_ref().lock()?;
+            guard.increment(&sc_amount.amount);
+        }
+
+        // Checks ownership, lock and the signature against the current proof key
+        let sco =
+            self.verify_statechain_sig(&statechain_id, &migrate_msg1.statechain_sig, Some(user_id))?;
+        migrate_msg1.statechain_sig.check_binding(
+            &statechain_id,
+            sco.chain.tip_position(),
+            self.config.require_bound_sigs,
+        )?;
+        self.check_second_factor(
+            &statechain_id,
+            &sco.chain.get_tip().data,
+            &migrate_msg1.statechain_sig,
+            &migrate_msg1.auth_sig,
+        )?;
+        self.vault_start(
+            &statechain_id,
+            &sco.chain.get_tip().data,
+            &migrate_msg1.statechain_sig,
+            false,
+        )?;
+
+        // Generate x1
+        let x1: FE = ECScalar::new_random();
+
+        self.database
+            .create_transfer(&statechain_id, &migrate_msg1.statechain_sig, &x1, None)?;
+        self.update_session_state(&user_id, next_state)?;
+
+        info!(
+            "MIGRATE: Init complete. Shared key ID: {}. State Chain ID: {}",
+            user_id, statechain_id
+        );
+
+        // encrypt x1 with owner proof key
+        let proof_key = ecies::PublicKey::from_str(&self.database.get_proof_key(user_id)?)
+            .map_err(|e| SEError::SharedLibError(format!("error deserialising proof key: {}", e)))?;
+
+        let mut msg2 = TransferMsg2 {
+            x1: FESer::from_fe(&x1),
+            proof_key,
+        };
+        msg2.encrypt()
+            .map_err(|e| SEError::SharedLibError(format!("{}", e)))?;
+
+        Ok(msg2)
+    }
+
+    fn migrate_export(&self, mut migrate_msg2: MigrateMsg2) -> Result<SignedMigrationPackage> {
+        self.check_user_auth(&migrate_msg2.shared_key_id)?;
+        let user_id = migrate_msg2.shared_key_id;
+        let next_state = self.check_session_event(&user_id, SessionEvent::MigrateExport)?;
+        let statechain_id = migrate_msg2.statechain_id;
+        let export_key = self.migration_export_key()?;
+
+        if self.database.get_statechain_id(user_id)? != statechain_id {
+            return Err(SEError::Generic(format!(
+                "migrate_export - shared key id: {} does not own State Chain ID: {}",
+                user_id, statechain_id
+            )));
+        }
+
+        let td = self.database.get_transfer_data(statechain_id)?;
+        if td.statechain_sig.purpose != "MIGRATE" {
+            return Err(SEError::Generic(format!(
+                "migrate_export - no migration initiated for State Chain ID: {}",
+                statechain_id
+            )));
+        }
+        self.check_vault_delay(&statechain_id, &td.statechain_sig)?;
+
+        // Compute the new entity key share
+        let kp = self.database.get_ecdsa_keypair(user_id)?;
+        let s1 = kp.party_1_private.get_private_key();
+        let key: SecretKey = kp.clone().try_into()?;
+        let s1_priv = PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key,
+        };
+        migrate_msg2.decrypt(&s1_priv).map_err(|e| {
+            SEError::SharedLibError(format!("Failed to decrypt t2 in migrate_msg2. Error: {}", e))
+        })?;
+        let t2 = migrate_msg2
+            .t2
+            .get_fe()
+            .map_err(|e| SEError::Generic(format!("Failed to get FE from migrate_msg2: {}", e)))?;
+
+        let s2: FE = t2 * (td.x1.invert()) * s1;
+
+        // Check P1 = o1_pub*s1 === P2 = o2_pub*s2
+        if kp.party_2_public * s1 != migrate_msg2.o2_pub * s2 {
+            error!("MIGRATE: Protocol failed. P1 != P2.");
+            return Err(SEError::Generic(String::from(
+                "Migrate protocol error: P1 != P2",
+            )));
+        }
+
+        // Export the statechain before it is closed so that it includes the backup tx
+        let export = self.get_statechain_export(statechain_id)?;
+
+        let migration_pubkey = ecies::PublicKey::from_str(&td.statechain_sig.data)
+            .map_err(|e| SEError::SharedLibError(format!("error deserialising migration key: {}", e)))?;
+        let mut s2_enc = FESer::from_fe(&s2);
+        s2_enc
+            .encrypt_with_pubkey(&migration_pubkey)
+            .map_err(|e| SEError::SharedLibError(format!("{}", e)))?;
+
+        let signed = sign_migration_package(
+            &export_key,
+            MigrationPackage {
+                export,
+                statechain_sig: td.statechain_sig.clone(),
+                s2: s2_enc,
+                o2_pub: migrate_msg2.o2_pub,
+            },
+        )?;
+
+        // Close the statecoin: final state signs for the migration public key
+        let version = self.database.get_statechain_version(&statechain_id)?;
+        let mut state_chain: StateChain = self.database.get_statechain(statechain_id)?;
+        let prev_proof_key = state_chain.get_tip().data.clone();
+        state_chain.add(&td.statechain_sig)?;
+        self.database.update_statechain_amount(
+            &statechain_id,
+            state_chain,
+            0,
+            self.coin_value_info.clone(),
+            version,
+        )?;
+        self.database.remove_statechain_id(&user_id)?;
+        self.database.remove_backup_tx(&statechain_id)?;
+        self.database.remove_transfer_data(&statechain_id)?;
+        self.database.remove_ecdsa(&user_id)?;
+        self.issue_deletion_receipt(&user_id, &statechain_id, &prev_proof_key)?;
+        self.update_session_state(&user_id, next_state)?;
+
+        let (prev_root, new_root) = self.update_smt(
+            &signed.package.export.export.funding_txid,
+            &td.statechain_sig.data,
+            signed.package.export.export.amount,
+        )?;
+
+        info!(
+            "MIGRATE: Exported. Shared Key ID: {}. State Chain ID: {}",
+            user_id, statechain_id
+        );
+        debug!(
+            "MIGRATE: State Chain ID: {}. New root: {:?}. Previous root: {:?}.",
+            statechain_id, &new_root, &prev_root
+        );
+
+        Ok(signed)
+    }
+
+    fn migrate_import(&self, signed: SignedMigrationPackage) -> Result<TransferMsg5> {
+        let migration_key = self.migration_key()?;
+        let migration_pubkey =
+            PublicKey::from_secret_key(&Secp256k1::new(), &migration_key).to_string();
+
+        let server_pubkey = signed.package.export.export.server_pubkey.clone();
+        if !self
+            .config
+            .migration_trusted_keys
+            .split(",")
+            .any(|k| k.trim() == server_pubkey)
+        {
+            return Err(SEError::Generic(format!(
+                "migrate_import - migration package signed by untrusted key: {}",
+                server_pubkey
+            )));
+        }
+        verify_migration_package(&signed, &server_pubkey, &migration_pubkey)?;
+
+        let package = signed.package;
+        let export = package.export.export;
+        let statechain_id = export.statechain_id;
+        debug!("MIGRATE: Import. State Chain ID: {}", statechain_id);
+
+        if self.database.get_statechain(statechain_id).is_ok() {
+            return Err(SEError::Generic(format!(
+                "migrate_import - State Chain ID: {} already exists",
+                statechain_id
+            )));
+        }
+
+        // Recover the key share
+        let mut s2_ser = package.s2;
+        s2_ser
+            .decrypt(&PrivateKey {
+                compressed: true,
+                network: Network::Regtest,
+                key: migration_key,
+            })
+            .map_err(|e| SEError::SharedLibError(format!("Failed to decrypt s2: {}", e)))?;
+        let s2 = s2_ser
+            .get_fe()
+            .map_err(|e| SEError::Generic(format!("Failed to get FE from s2: {}", e)))?;
+        let g: GE = ECPoint::generator();
+        let s2_pub: GE = g * s2;
+        let shared_pubkey: GE = package.o2_pub * s2;
+
+        let new_user_id = Uuid::new_v4();
+        let state_chain: StateChain = (&export.chain).try_into()?;
+        let proof_key = state_chain.get_tip().data.clone();
+        let tx_backup_hex = export.tx_backup_hex.clone().ok_or(SEError::Generic(
+            String::from("migrate_import - backup tx missing"),
+        ))?;
+        let tx_backup = transaction_deserialise(&tx_backup_hex)?;
+
+        aml_screen(
+            self.aml_hook.as_ref(),
+            &AmlEvent {
+                kind: AmlEventKind::Deposit,
+                user_id: new_user_id,
+                statechain_id: Some(statechain_id),
+                txid: export.funding_txid.clone(),
+                amount: export.amount,
+                destination: None,
+            },
+        )?;
+
+        // The user session proof key is taken from the signature data, so the owner keeps
+        // the current proof key
+        let finalized_data = TransferFinalizeData {
+            new_shared_key_id: new_user_id,
+            statechain_id,
+            statechain_sig: StateChainSig {
+                data: proof_key.clone(),
+                ..package.statechain_sig
+            },
+            s2,
+            new_tx_backup_hex: tx_backup_hex,
+            batch_data: None,
+        };
+
+        self.database.create_statechain(
+            &statechain_id,
+            &new_user_id,
+            &state_chain,
+            &(export.amount as i64),
+        )?;
+        self.database.set_shared_pubkey(
+            statechain_id,
+            &serde_json::to_string(&shared_pubkey).map_err(|e| SEError::Generic(e.to_string()))?,
+        )?;
+        self.database
+            .create_backup_transaction(&statechain_id, &tx_backup)?;
+        // Backup tx history recorded by the old state entity. Version 1 exports have none.
+        for tx_hex in &export.backup_txs {
+            self.database
+                .insert_backup_tx_history(&statechain_id, &transaction_deserialise(tx_hex)?)?;
+        }
+        self.database
+            .insert_backup_tx_history(&statechain_id, &tx_backup)?;
+        self.database.transfer_init_user_session(
+            &new_user_id,
+            &statechain_id,
+            finalized_data,
+            self.user_ids.clone(),
+        )?;
+
+        // The old entity only exports confirmed statecoins
+        self.database.set_confirmed(&statechain_id)?;
+        {
+            let mut guard = self.coin_value_info.as_ref().lock()?;
+            guard.increment(&(export.amount as i64));
+        }
+
+        let (prev_root, new_root) =
+            self.update_smt(&export.funding_txid, &proof_key, export.amount)?;
+
+        info!(
+            "MIGRATE: Imported. New shared key ID: {}. State Chain ID: {}",
+            new_user_id, statechain_id
+        );
+        debug!(
+            "MIGRATE: State Chain ID: {}. New root: {:?}. Previous root: {:?}.",
+            statechain_id, &new_root, &prev_root
+        );
+
+        Ok(TransferMsg5 {
+            new_shared_key_id: new_user_id,
+            s2_pub,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use chrono::Utc;
+
+    static EXPORT_KEY: &str = "0303030303030303030303030303030303030303030303030303030303030303";
+    static MIGRATION_KEY: &str = "0404040404040404040404040404040404040404040404040404040404040404";
+
+    fn pubkey(secret: &str) -> String {
+        PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_str(secret).unwrap())
+            .to_string()
+    }
+
+    fn test_package(migration_pubkey: &String) -> SignedMigrationPackage {
+        let export_key = SecretKey::from_str(EXPORT_KEY).unwrap();
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&Secp256k1::new(), &proof_key_priv).to_string();
+        let export = StateChainExport {
+            version: STATECHAIN_EXPORT_VERSION,
+            statechain_id: Uuid::new_v4(),
+            amount: 10000,
+            funding_txid: String::from(
+                "c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e",
+            ),
+            chain: StateChain::new(proof_key).get_chain().clone(),
+            tx_backup_hex: None,
+            backup_txs: vec![],
+            roots: vec![],
+            exported_at: Utc::now().naive_utc(),
+            server_pubkey: pubkey(EXPORT_KEY),
+        };
+        let g: GE = ECPoint::generator();
+        sign_migration_package(
+            &export_key,
+            MigrationPackage {
+                export: sign_statechain_export(&export_key, export).unwrap(),
+                statechain_sig: StateChainSig::new(
+                    &proof_key_priv,
+                    &String::from("MIGRATE"),
+                    migration_pubkey,
+                )
+                .unwrap(),
+                s2: FESer::new_random(),
+                o2_pub: g,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_migrate_get_pubkey() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Import not enabled
+        match sc_entity.migrate_get_pubkey() {
+            Err(SEError::Generic(e)) => assert!(e.contains("not enabled")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        sc_entity.config.migration_key = Some(String::from(MIGRATION_KEY));
+        assert_eq!(
+            sc_entity.migrate_get_pubkey().unwrap(),
+            MigratePubKey { key: pubkey(MIGRATION_KEY) }
+        );
+    }
+
+    #[test]
+    fn test_migrate_init_purpose() {
+        let mut db = MockDatabase::new();
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(crate::structs::SessionState::Owner)));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.export_key = Some(String::from(EXPORT_KEY));
+
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let migrate_msg1 = MigrateMsg1 {
+            shared_key_id: Uuid::new_v4(),
+            statechain_sig: StateChainSig::new(
+                &proof_key_priv,
+                &String::from("TRANSFER"),
+                &pubkey(MIGRATION_KEY),
+            )
+            .unwrap(),
+            auth_sig: None,
+        };
+        match sc_entity.migrate_init(migrate_msg1) {
+            Err(SEError::Generic(e)) => assert!(e.contains("purpose must be MIGRATE")),
+            _ => assert!(false, "expected Generic error"),
+        }
+    }
+
+    #[test]
+    fn test_migrate_import_rejected() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        let package = test_package(&pubkey(MIGRATION_KEY));
+
+        // Import not enabled
+        match sc_entity.migrate_import(package.clone()) {
+            Err(SEError::Generic(e)) => assert!(e.contains("not enabled")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        // Package from an untrusted state entity
+        sc_entity.config.migration_key = Some(String::from(MIGRATION_KEY));
+        match sc_entity.migrate_import(package.clone()) {
+            Err(SEError::Generic(e)) => assert!(e.contains("untrusted key")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        // Trusted, but the statecoin has no backup tx
+        sc_entity.config.migration_trusted_keys = format!("{},{}", pubkey(MIGRATION_KEY), pubkey(EXPORT_KEY));
+        assert!(sc_entity.migrate_import(package).is_err());
+
+        // Signed over to another state entity
+        let package = test_package(&pubkey(EXPORT_KEY));
+        assert!(sc_entity.migrate_import(package).is_err());
+    }
+}
@@ -1,9 +1,26 @@
+pub mod accelerate;
+pub mod api_key;
+pub mod archive;
+pub mod chain_height;
 pub mod conductor;
 pub mod deposit;
 pub mod ecdsa;
+pub mod migrate;
+pub mod noise;
 pub mod ping;
 pub mod transfer;
 pub mod transfer_batch;
 pub mod util;
+pub mod watchtower;
 pub mod withdraw;
-pub mod requests;
\ No newline at end of file
+pub mod requests;
+pub mod root_update;
+pub mod sale;
+pub mod inheritance;
+pub mod second_factor;
+pub mod vault;
+pub mod punishment;pub mod session;
+pub mod session_cleanup;
+pub mod refresh;
+pub mod rotate;
+pub mod revenue;
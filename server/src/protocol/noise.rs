@@ -0,0 +1,225 @@
+//! StateEntity Noise
+//!
+//! Encrypted channel for the ECDSA protocol messages of a user session. The channel is opened
+//! with a Noise KK handshake keyed by the server identity key and the proof key of the user
+//! session, so that transcripts logged by proxies and other intermediaries are not readable.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::protocol::ecdsa::Ecdsa;
+use crate::protocol::util::Utilities;
+use crate::server::StateChainEntity;
+use crate::Database;
+use shared_lib::noise::{ecdsa_prologue, kk_respond};
+use shared_lib::structs::{
+    EcdsaReply, EcdsaRequest, IdentityPubKey, NoiseHandshakeMsg, NoiseMsg,
+};
+
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use cfg_if::cfg_if;
+use std::str::FromStr;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Encrypted ECDSA channel protocol trait
+pub trait EcdsaChannel {
+    /// API: Get the server identity public key
+    fn noise_get_identity(&self) -> Result<IdentityPubKey>;
+
+    /// API: Open the encrypted channel of a user session:
+    ///     - Respond to the Noise KK handshake keyed by the session proof key
+    ///     - Replace any channel previously opened for the session
+    fn noise_handshake(&self, handshake_msg: NoiseHandshakeMsg) -> Result<NoiseMsg>;
+
+    /// API: Process an ECDSA protocol message sent over the channel of a user session and
+    /// return the encrypted reply
+    fn noise_ecdsa(&self, noise_msg: NoiseMsg) -> Result<NoiseMsg>;
+}
+
+impl SCE {
+    pub(crate) fn identity_key(&self) -> Result<SecretKey> {
+        match &self.config.identity_key {
+            Some(k) => SecretKey::from_str(k)
+                .map_err(|e| SEError::Generic(format!("Invalid identity key: {}", e))),
+            None => Err(SEError::Generic(String::from(
+                "Encrypted ECDSA channel not enabled",
+            ))),
+        }
+    }
+
+    fn ecdsa_dispatch(&self, request: EcdsaRequest) -> Result<EcdsaReply> {
+        Ok(match request {
+            EcdsaRequest::KeyGenFirst(msg) => EcdsaReply::KeyGenFirst(self.first_message(msg)?),
+            EcdsaRequest::KeyGenSecond(msg) => EcdsaReply::KeyGenSecond(self.second_message(msg)?),
+            EcdsaRequest::SignFirst(msg) => EcdsaReply::SignFirst(self.sign_first(msg)?),
+            EcdsaRequest::SignSecond(msg) => EcdsaReply::SignSecond(self.sign_second(msg)?),
+        })
+    }
+}
+
+fn request_shared_key_id(request: &EcdsaRequest) -> uuid::Uuid {
+    match request {
+        EcdsaRequest::KeyGenFirst(msg) => msg.shared_key_id,
+        EcdsaRequest::KeyGenSecond(msg) => msg.shared_key_id,
+        EcdsaRequest::SignFirst(msg) => msg.shared_key_id,
+        EcdsaRequest::SignSecond(msg) => msg.shared_key_id,
+    }
+}
+
+impl EcdsaChannel for SCE {
+    fn noise_get_identity(&self) -> Result<IdentityPubKey> {
+        let key = self.identity_key()?;
+        Ok(IdentityPubKey {
+            key: PublicKey::from_secret_key(&Secp256k1::new(), &key).to_string(),
+        })
+    }
+
+    fn noise_handshake(&self, handshake_msg: NoiseHandshakeMsg) -> Result<NoiseMsg> {
+        let identity_key = self.identity_key()?;
+        let user_id = handshake_msg.shared_key_id;
+        self.check_user_auth(&user_id)?;
+
+        let proof_key = PublicKey::from_str(&self.database.get_proof_key(user_id)?)
+            .map_err(|e| SEError::Generic(format!("Invalid proof key: {}", e)))?;
+        let message = hex::decode(&handshake_msg.message)
+            .map_err(|e| SEError::Generic(format!("Invalid handshake message: {}", e)))?;
+        let (_, reply, channel) = kk_respond(
+            &ecdsa_prologue(&user_id),
+            &identity_key,
+            &proof_key,
+            &message,
+            &[],
+        )?;
+        self.noise_channels.lock()?.insert(user_id, channel);
+
+        debug!("NOISE: Channel opened. Shared Key ID: {}", user_id);
+        Ok(NoiseMsg {
+            shared_key_id: user_id,
+            ciphertext: hex::encode(reply),
+        })
+    }
+
+    fn noise_ecdsa(&self, noise_msg: NoiseMsg) -> Result<NoiseMsg> {
+        let user_id = noise_msg.shared_key_id;
+        let ciphertext = hex::decode(&noise_msg.ciphertext)
+            .map_err(|e| SEError::Generic(format!("Invalid ciphertext: {}", e)))?;
+
+        // The channel is taken for the duration of the request so that messages of a session
+        // are processed in order
+        let mut channel = self.noise_channels.lock()?.remove(&user_id).ok_or(
+            SEError::Generic(format!("No encrypted channel open. Shared Key ID: {}", user_id)),
+        )?;
+        let plaintext = match channel.decrypt(&ciphertext) {
+            Ok(p) => p,
+            Err(e) => {
+                self.noise_channels.lock()?.insert(user_id, channel);
+                return Err(e.into());
+            }
+        };
+
+        let reply = match serde_json::from_slice::<EcdsaRequest>(&plaintext) {
+            Ok(request) if request_shared_key_id(&request) != user_id => EcdsaReply::Error(
+                String::from("ECDSA message for another user session"),
+            ),
+            Ok(request) => self
+                .ecdsa_dispatch(request)
+                .unwrap_or_else(|e| EcdsaReply::Error(e.to_string())),
+            Err(e) => EcdsaReply::Error(format!("Invalid ECDSA message: {}", e)),
+        };
+        let reply = serde_json::to_vec(&reply).map_err(|e| SEError::Generic(e.to_string()))?;
+        let ciphertext = channel.encrypt(&reply)?;
+        self.noise_channels.lock()?.insert(user_id, channel);
+
+        Ok(NoiseMsg {
+            shared_key_id: user_id,
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use shared_lib::noise::kk_initiate;
+    use shared_lib::structs::{KeyGenMsg1, Protocol};
+    use uuid::Uuid;
+
+    static IDENTITY_KEY: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
+    #[test]
+    fn test_noise_channel() {
+        let secp = Secp256k1::new();
+        let proof_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &proof_priv);
+        let user_id = Uuid::new_v4();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth().returning(|_| Ok(String::default()));
+        db.expect_get_proof_key()
+            .returning(move |_| Ok(proof_key.to_string()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Disabled without an identity key
+        assert!(sc_entity.noise_get_identity().is_err());
+        sc_entity.config.identity_key = Some(String::from(IDENTITY_KEY));
+        let identity = PublicKey::from_str(&sc_entity.noise_get_identity().unwrap().key).unwrap();
+
+        // Handshake by a client without the proof key fails
+        let other_priv = SecretKey::from_slice(&[3; 32]).unwrap();
+        let (_, msg1) = kk_initiate(&ecdsa_prologue(&user_id), &other_priv, &identity, &[]).unwrap();
+        assert!(sc_entity
+            .noise_handshake(NoiseHandshakeMsg { shared_key_id: user_id, message: hex::encode(msg1) })
+            .is_err());
+
+        let (initiator, msg1) = kk_initiate(&ecdsa_prologue(&user_id), &proof_priv, &identity, &[]).unwrap();
+        let reply = sc_entity
+            .noise_handshake(NoiseHandshakeMsg { shared_key_id: user_id, message: hex::encode(msg1) })
+            .unwrap();
+        let (_, mut channel) = initiator.finish(&hex::decode(reply.ciphertext).unwrap()).unwrap();
+
+        // Messages for another user session are refused, in an encrypted reply
+        let request = EcdsaRequest::KeyGenFirst(KeyGenMsg1 {
+            shared_key_id: Uuid::new_v4(),
+            protocol: Protocol::Deposit,
+            solution: None,
+        });
+        let ciphertext = channel.encrypt(&serde_json::to_vec(&request).unwrap()).unwrap();
+        let reply = sc_entity
+            .noise_ecdsa(NoiseMsg { shared_key_id: user_id, ciphertext: hex::encode(ciphertext) })
+            .unwrap();
+        let reply: EcdsaReply = serde_json::from_slice(
+            &channel.decrypt(&hex::decode(reply.ciphertext).unwrap()).unwrap(),
+        )
+        .unwrap();
+        match reply {
+            EcdsaReply::Error(e) => assert!(e.contains("another user session")),
+            _ => assert!(false, "Expected error reply."),
+        }
+
+        // Replayed messages are rejected
+        let ciphertext = hex::encode(channel.encrypt(b"{}").unwrap());
+        assert!(sc_entity
+            .noise_ecdsa(NoiseMsg { shared_key_id: user_id, ciphertext: ciphertext.clone() })
+            .is_ok());
+        assert!(sc_entity
+            .noise_ecdsa(NoiseMsg { shared_key_id: user_id, ciphertext })
+            .is_err());
+
+        // No channel for the user session
+        assert!(sc_entity
+            .noise_ecdsa(NoiseMsg { shared_key_id: Uuid::new_v4(), ciphertext: String::default() })
+            .is_err());
+    }
+}
@@ -1,9 +1,15 @@
-use rocket::http::Status;
 pub use crate::{error::SEError, Result};
-use rocket::State;
 use cfg_if::cfg_if;
 use crate::server::StateChainEntity;
 use crate::protocol::util::RateLimiter;
+use crate::storage::Storage;
+use crate::Database;
+
+use electrumx_client::{electrumx_client::ElectrumxClient, interface::Electrumx};
+use monotree::database::Database as MonotreeDatabase;
+use shared_lib::mocks::mock_electrum::MockElectrum;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 //Generics cannot be used in Rocket State, therefore we define the concrete
 //type of StateChainEntity here
@@ -13,20 +19,148 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// SMT store key written and deleted by the health probe. SMT nodes are stored under the hash
+/// of their value, so the key cannot collide with a node.
+const SMT_PROBE_KEY: [u8; 32] = [0xff; 32];
+
+/// Time the health probe waits for the conductor scheduler lock
+const SCHEDULER_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Health status, in order of severity
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Healthy,
+    /// Some requests fail, e.g. deposits cannot be confirmed without Electrum
+    Degraded,
+    /// Protocol requests cannot be served
+    Unhealthy,
+}
+
+/// Health of a service the server depends on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: HealthStatus,
+    pub detail: Option<String>,
+}
+
+impl HealthCheck {
+    fn new(name: &str, status: HealthStatus, detail: Option<String>) -> Self {
+        HealthCheck {
+            name: name.to_string(),
+            status,
+            detail,
+        }
+    }
+
+    /// Healthy if 'result' is Ok, otherwise 'status' with the error as detail
+    fn from_result<T>(name: &str, result: Result<T>, status: HealthStatus) -> Self {
+        match result {
+            Ok(_) => Self::new(name, HealthStatus::Healthy, None),
+            Err(e) => Self::new(name, status, Some(e.to_string())),
+        }
+    }
+}
+
+/// Server health report
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    /// Most severe status of the checks
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn new(checks: Vec<HealthCheck>) -> Self {
+        HealthReport {
+            status: checks
+                .iter()
+                .map(|c| c.status)
+                .max()
+                .unwrap_or(HealthStatus::Healthy),
+            checks,
+        }
     }
 }
 
-#[get("/ping")]
-pub fn ping(sc_entity: State<SCE>) -> Result<Status> {
-    // TODO: Add logic for health check
-    sc_entity.ping()?;
-    Ok(Status::Ok)
+/// Health probe state, shared between frontends
+#[derive(Default)]
+pub struct HealthState {
+    /// Last report and when it was made
+    report: Option<(Instant, HealthReport)>,
+    /// Background threads: name -> (time of last run, run interval)
+    heartbeats: HashMap<String, (Instant, Duration)>,
+}
+
+/// Mainstay attestation lag check: number of SMT roots since the latest attested root
+pub fn mainstay_lag_check(current_id: i64, confirmed_id: Option<i64>, max_lag: u64) -> HealthCheck {
+    let lag = (current_id - confirmed_id.unwrap_or(0)).max(0) as u64;
+    let status = if lag > max_lag {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Healthy
+    };
+    HealthCheck::new("mainstay", status, Some(format!("{} roots not attested", lag)))
+}
+
+/// Background thread liveness check. A thread is considered dead if it has not run for
+/// twice its interval.
+fn heartbeat_check(name: &str, last: Instant, interval: Duration) -> HealthCheck {
+    let elapsed = last.elapsed();
+    let status = if elapsed > interval * 2 {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Healthy
+    };
+    HealthCheck::new(
+        name,
+        status,
+        Some(format!("last run {} s ago", elapsed.as_secs())),
+    )
+}
+
+fn check_bitcoind(rpc_path: &str) -> HealthCheck {
+    let block_count = || -> Result<u64> {
+        let rpc_path_parts: Vec<&str> = rpc_path.split('@').collect();
+        let rpc_cred: Vec<&str> = rpc_path_parts[0].split(':').collect();
+        if rpc_path_parts.len() != 2 || rpc_cred.len() != 2 {
+            return Err(SEError::Generic(String::from("Invalid bitcoind RPC path")));
+        }
+        cfg_if! {
+            if #[cfg(any(test,feature="mockbitcoinrpc"))]{
+                use shared_lib::mocks::mock_rpc_client::MockBitcoinClient;
+                let mut rpc = MockBitcoinClient::new();
+            } else {
+                use bitcoincore_rpc::{Auth, Client, RpcApi};
+                let rpc = Client::new(rpc_path_parts[1].to_string(),
+                              Auth::UserPass(rpc_cred[0].to_string(),
+                                             rpc_cred[1].to_string()))
+                    .map_err(|e| SEError::Generic(e.to_string()))?;
+            }
+        }
+        rpc.get_block_count()
+            .map_err(|e| SEError::Generic(format!("{:?}", e)))
+    };
+    HealthCheck::from_result("bitcoind", block_count(), HealthStatus::Degraded)
 }
 
 pub trait Ping {
     fn ping(&self) -> Result<()>;
+
+    /// Health of the server and the services it depends on: DB connectivity, SMT store
+    /// writability, Electrum and bitcoind reachability, mainstay attestation lag, and
+    /// scheduler and background thread liveness. Reports are cached for health.cache_ttl
+    /// seconds.
+    fn health(&self) -> Result<HealthReport>;
+
+    /// Record a run of the background thread 'name', which runs every 'interval'
+    fn heartbeat(&self, name: &str, interval: Duration) -> Result<()>;
 }
 
 impl Ping for SCE {
@@ -34,6 +168,126 @@ impl Ping for SCE {
         self.check_rate_fast("info")?;
         Ok(())
     }
+
+    fn health(&self) -> Result<HealthReport> {
+        if let Some((time, report)) = &self.health.lock()?.report {
+            if time.elapsed() < Duration::from_secs(self.config.health.cache_ttl) {
+                return Ok(report.clone());
+            }
+        }
+
+        let mut checks = vec![
+            HealthCheck::from_result(
+                "database",
+                self.database.root_get_current_id(),
+                HealthStatus::Unhealthy,
+            ),
+            self.check_smt_store(),
+            self.check_electrum(),
+        ];
+        if !self.config.bitcoind.is_empty() {
+            checks.push(check_bitcoind(&self.config.bitcoind));
+        }
+        if self.config.mainstay.is_some() {
+            checks.push(self.check_mainstay());
+        }
+        if self.scheduler.is_some() {
+            checks.push(self.check_scheduler());
+        }
+        for (name, (last, interval)) in &self.health.lock()?.heartbeats {
+            checks.push(heartbeat_check(name, *last, *interval));
+        }
+
+        let report = HealthReport::new(checks);
+        if report.status != HealthStatus::Healthy {
+            warn!("HEALTH: {:?}", report);
+        }
+        self.health.lock()?.report = Some((Instant::now(), report.clone()));
+        Ok(report)
+    }
+
+    fn heartbeat(&self, name: &str, interval: Duration) -> Result<()> {
+        self.health
+            .lock()?
+            .heartbeats
+            .insert(name.to_string(), (Instant::now(), interval));
+        Ok(())
+    }
+}
+
+impl SCE {
+    /// Write and delete a probe key in the SMT store
+    fn check_smt_store(&self) -> HealthCheck {
+        let probe = || -> Result<()> {
+            let mut smt = self
+                .smt
+                .lock()
+                .map_err(|e| SEError::Generic(e.to_string()))?;
+            smt.db.put(&SMT_PROBE_KEY, vec![0])?;
+            smt.db.delete(&SMT_PROBE_KEY)?;
+            Ok(())
+        };
+        HealthCheck::from_result("smt_store", probe(), HealthStatus::Unhealthy)
+    }
+
+    fn check_electrum(&self) -> HealthCheck {
+        let tip = || -> Result<()> {
+            let mut electrum: Box<dyn Electrumx> = if self.config.testing_mode {
+                Box::new(MockElectrum::new())
+            } else {
+                Box::new(ElectrumxClient::new(self.config.electrum_server.clone())?)
+            };
+            electrum.get_tip_header()?;
+            Ok(())
+        };
+        HealthCheck::from_result("electrum", tip(), HealthStatus::Degraded)
+    }
+
+    /// Lag of the latest mainstay attested root behind the current root
+    fn check_mainstay(&self) -> HealthCheck {
+        let lag = || -> Result<HealthCheck> {
+            let current_id = self.database.root_get_current_id()?;
+            let confirmed_id = self.get_confirmed_smt_root()?.and_then(|r| r.id());
+            Ok(mainstay_lag_check(
+                current_id,
+                confirmed_id,
+                self.config.health.mainstay_max_lag,
+            ))
+        };
+        match lag() {
+            Ok(check) => check,
+            Err(e) => HealthCheck::new("mainstay", HealthStatus::Degraded, Some(e.to_string())),
+        }
+    }
+
+    /// The conductor scheduler is updated by swap requests. It is live if its lock can be
+    /// acquired.
+    fn check_scheduler(&self) -> HealthCheck {
+        let scheduler = self.scheduler.as_ref().expect("scheduler is None");
+        let start = Instant::now();
+        loop {
+            match scheduler.try_lock() {
+                Ok(_) => return HealthCheck::new("scheduler", HealthStatus::Healthy, None),
+                Err(std::sync::TryLockError::Poisoned(e)) => {
+                    return HealthCheck::new(
+                        "scheduler",
+                        HealthStatus::Unhealthy,
+                        Some(e.to_string()),
+                    )
+                }
+                Err(std::sync::TryLockError::WouldBlock) => {
+                    if start.elapsed() > SCHEDULER_LOCK_TIMEOUT {
+                        return HealthCheck::new(
+                            "scheduler",
+                            HealthStatus::Degraded,
+                            Some(String::from("scheduler lock not acquired")),
+                        );
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -63,4 +317,70 @@ pub mod tests {
         thread::sleep(Duration::from_millis(1000u64 / (rate_limit.unwrap().get() as u64) + 1u64));
         assert_eq!(sc_entity.ping().unwrap(), ());
     }
+
+    #[test]
+    fn test_health() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_root_get_current_id().times(1).returning(|| Ok(1));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.mainstay = None;
+        sc_entity.config.health.cache_ttl = 60;
+        let report = sc_entity.health().unwrap();
+        assert_eq!(report.status, HealthStatus::Healthy, "{:?}", report);
+        let names: Vec<&str> = report.checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["database", "smt_store", "electrum", "scheduler"]);
+
+        // Cached: the DB is not queried again
+        assert_eq!(sc_entity.health().unwrap(), report);
+    }
+
+    #[test]
+    fn test_health_db_unavailable() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_root_get_current_id()
+            .returning(|| Err(SEError::Generic(String::from("connection refused"))));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.mainstay = None;
+        sc_entity.config.health.cache_ttl = 0;
+        let report = sc_entity.health().unwrap();
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert_eq!(report.checks[0].status, HealthStatus::Unhealthy);
+
+        // Dead background thread
+        sc_entity.heartbeat("cleanup", Duration::from_millis(1)).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        let report = sc_entity.health().unwrap();
+        let cleanup = report.checks.iter().find(|c| c.name == "cleanup").unwrap();
+        assert_eq!(cleanup.status, HealthStatus::Degraded);
+    }
+
+    #[test]
+    fn test_health_scheduler_poisoned() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_root_get_current_id().returning(|| Ok(1));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.mainstay = None;
+        sc_entity.config.health.cache_ttl = 0;
+        let scheduler = sc_entity.scheduler.clone().unwrap();
+        let _ = thread::spawn(move || {
+            let _guard = scheduler.lock().unwrap();
+            panic!("scheduler failure");
+        })
+        .join();
+        assert_eq!(sc_entity.health().unwrap().status, HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn test_mainstay_lag_check() {
+        assert_eq!(mainstay_lag_check(10, Some(10), 5).status, HealthStatus::Healthy);
+        assert_eq!(mainstay_lag_check(15, Some(10), 5).status, HealthStatus::Healthy);
+        assert_eq!(mainstay_lag_check(16, Some(10), 5).status, HealthStatus::Degraded);
+        assert_eq!(mainstay_lag_check(6, None, 5).status, HealthStatus::Degraded);
+    }
 }
\ No newline at end of file
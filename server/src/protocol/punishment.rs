@@ -0,0 +1,339 @@
+//! StateEntity Punishment
+//!
+//! Punishment policy for statecoins that fail to complete a batch transfer or a swap.
+//!
+//! Failures are counted per statechain and per owner proof key, so that an owner cannot escape
+//! escalation by moving to a new statecoin. Each failure locks the statecoin for the duration
+//! given by the operator schedule for the failure kind, indexed by the larger of the two
+//! failure counts. The counts are reset once no failure has occurred for the configured decay
+//! period, after which the records are removed by the cleanup task.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::config::{Config, DurationUnit};
+use crate::error::SEError;
+use crate::structs::PunishmentRecord;
+use crate::Database;
+use crate::server::StateChainEntity;
+use crate::protocol::ping::Ping;
+use shared_lib::structs::PunishmentStandingAPI;
+
+use cfg_if::cfg_if;
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::{thread, time};
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Kind of failure a statecoin is punished for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Offence {
+    /// Failure to complete a batch transfer within the batch lifetime
+    Batch,
+    /// Failure to complete swap phase 1/2 within the group timeout
+    Swap,
+}
+
+/// Escalating punishment schedules
+#[derive(Debug, Clone, PartialEq)]
+pub struct PunishmentPolicy {
+    batch_schedule: Vec<u64>,
+    swap_schedule: Vec<u64>,
+    decay: i64,
+}
+
+/// Schedule durations in seconds. Durations in blocks are converted as statecoins are locked
+/// until a time.
+fn parse_schedule(schedule: &String, default: u64, unit: DurationUnit) -> Result<Vec<u64>> {
+    let schedule_str = schedule.replace(" ", "");
+    if schedule_str.is_empty() {
+        return Ok(vec![unit.seconds(default)]);
+    }
+    schedule_str
+        .split(",")
+        .map(|d| {
+            d.parse::<u64>().map(|d| unit.seconds(d)).map_err(|e| {
+                SEError::Generic(format!("Invalid punishment schedule {}: {}", schedule, e))
+            })
+        })
+        .collect()
+}
+
+impl PunishmentPolicy {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let default = config.conductor.punishment_duration;
+        let unit = config.duration_unit;
+        Ok(Self {
+            batch_schedule: parse_schedule(&config.punishment.batch_schedule, default, unit)?,
+            swap_schedule: parse_schedule(&config.punishment.swap_schedule, default, unit)?,
+            decay: config.punishment.decay as i64,
+        })
+    }
+
+    /// Punishment duration in seconds of the nth failure (n >= 1)
+    pub fn duration(&self, offence: Offence, n: u32) -> u64 {
+        let schedule = match offence {
+            Offence::Batch => &self.batch_schedule,
+            Offence::Swap => &self.swap_schedule,
+        };
+        let i = (n.max(1) as usize - 1).min(schedule.len() - 1);
+        schedule[i]
+    }
+
+    /// Failure count of a record at time now, taking the decay period into account
+    pub fn offences(&self, record: Option<&PunishmentRecord>, now: &NaiveDateTime) -> u32 {
+        match record {
+            Some(r) if r.last_offence + Duration::seconds(self.decay) > *now => r.offences,
+            _ => 0,
+        }
+    }
+
+    /// Latest time of the last offence of a record that can be removed at time now
+    pub fn expiry_cutoff(&self, now: &NaiveDateTime) -> NaiveDateTime {
+        *now - Duration::seconds(self.decay)
+    }
+}
+
+/// StateChain Entity Punishment API calls
+pub trait Punishment {
+    /// API: Return the punishment standing of a statecoin and its owner proof key
+    fn get_punishment_standing(&self, statechain_id: Uuid) -> Result<PunishmentStandingAPI>;
+}
+
+impl Punishment for SCE {
+    fn get_punishment_standing(&self, statechain_id: Uuid) -> Result<PunishmentStandingAPI> {
+        let policy = PunishmentPolicy::from_config(&self.config)?;
+        let proof_key = self.get_owner_proof_key(&statechain_id)?;
+        let sc_record = self.database.get_punishment(&statechain_id.to_string())?;
+        let pk_record = self.database.get_punishment(&proof_key)?;
+
+        let now = Utc::now().naive_utc();
+        let statechain_offences = policy.offences(sc_record.as_ref(), &now);
+        let proof_key_offences = policy.offences(pk_record.as_ref(), &now);
+        let locked_until = sc_record
+            .iter()
+            .chain(pk_record.iter())
+            .map(|r| r.locked_until)
+            .filter(|t| *t > now)
+            .max();
+        let next = statechain_offences.max(proof_key_offences) + 1;
+
+        Ok(PunishmentStandingAPI {
+            statechain_id,
+            proof_key,
+            statechain_offences,
+            proof_key_offences,
+            locked_until,
+            seconds_remaining: locked_until.map_or(0, |t| t.timestamp() - now.timestamp()),
+            next_batch_punishment: policy.duration(Offence::Batch, next),
+            next_swap_punishment: policy.duration(Offence::Swap, next),
+        })
+    }
+}
+
+impl SCE {
+    fn get_owner_proof_key(&self, statechain_id: &Uuid) -> Result<String> {
+        let sco = self.database.get_statechain_owner(*statechain_id)?;
+        Ok(sco.chain.get_tip().data.clone())
+    }
+
+    /// Record a failure of a statecoin and its owner proof key. Returns the time until which
+    /// the statecoin is punished.
+    pub fn punish(&self, statechain_id: &Uuid, offence: Offence) -> Result<NaiveDateTime> {
+        let policy = PunishmentPolicy::from_config(&self.config)?;
+        let proof_key = self.get_owner_proof_key(statechain_id)?;
+        let ids = [statechain_id.to_string(), proof_key];
+
+        let now = Utc::now().naive_utc();
+        let mut records = vec![];
+        for id in &ids {
+            let prev = self.database.get_punishment(id)?;
+            records.push(PunishmentRecord {
+                id: id.clone(),
+                offences: policy.offences(prev.as_ref(), &now) + 1,
+                last_offence: now,
+                locked_until: prev.map_or(now, |r| r.locked_until),
+            });
+        }
+
+        let n = records.iter().map(|r| r.offences).max().unwrap_or(1);
+        let duration = policy.duration(offence, n);
+        let locked_until = now + Duration::seconds(duration as i64);
+        for record in records.iter_mut() {
+            record.locked_until = record.locked_until.max(locked_until);
+            self.database.update_punishment(record)?;
+        }
+
+        info!(
+            "PUNISHMENT: State Chain ID: {} punished for {:?} failure {} for {}s.",
+            statechain_id, offence, n, duration
+        );
+        Ok(locked_until)
+    }
+
+    /// Seconds of punishment remaining for a statecoin or its owner proof key. 0 if not punished.
+    pub fn punishment_remaining(&self, statechain_id: &Uuid, proof_key: &String) -> Result<i64> {
+        let now = Utc::now().naive_utc();
+        let mut remaining = 0;
+        for id in &[statechain_id.to_string(), proof_key.clone()] {
+            if let Some(record) = self.database.get_punishment(id)? {
+                remaining = remaining.max(record.locked_until.timestamp() - now.timestamp());
+            }
+        }
+        Ok(remaining)
+    }
+
+    /// Remove expired punishment records and expired conductor punishments.
+    /// Returns the number of records removed.
+    pub fn cleanup_punishments(&self) -> Result<u64> {
+        let policy = PunishmentPolicy::from_config(&self.config)?;
+        let now = Utc::now().naive_utc();
+        let removed = self
+            .database
+            .remove_expired_punishments(&now, &policy.expiry_cutoff(&now))?;
+        if let Some(scheduler) = &self.scheduler {
+            scheduler.lock()?.remove_expired_punishments();
+        }
+        if removed > 0 {
+            info!("PUNISHMENT: Removed {} expired punishment records.", removed);
+        }
+        Ok(removed)
+    }
+}
+
+/// Run punishment cleanup on a handle sharing the state entity, if cleanup_interval is
+/// non-zero
+pub fn spawn_cleanup(sc_entity: &SCE) -> Result<Option<thread::JoinHandle<()>>> {
+    let interval = sc_entity.config.punishment.cleanup_interval;
+    if interval == 0 {
+        return Ok(None);
+    }
+    let sc_entity = sc_entity.share()?;
+    Ok(Some(thread::spawn(move || loop {
+        if let Err(e) = sc_entity.cleanup_punishments() {
+            warn!("PUNISHMENT: Cleanup failed: {}", e);
+        }
+        let _ = sc_entity.heartbeat("punishment_cleanup", time::Duration::from_secs(interval));
+        thread::sleep(time::Duration::from_secs(interval));
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use crate::structs::StateChainOwner;
+    use crate::MockDatabase;
+    use mockall::predicate;
+    use shared_lib::state_chain::StateChain;
+    use std::str::FromStr;
+
+    fn test_policy(batch_schedule: &str, swap_schedule: &str) -> PunishmentPolicy {
+        let mut config = Config::default();
+        config.conductor.punishment_duration = 60;
+        config.punishment.batch_schedule = String::from(batch_schedule);
+        config.punishment.swap_schedule = String::from(swap_schedule);
+        config.punishment.decay = 3600;
+        PunishmentPolicy::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_policy_schedule() {
+        let policy = test_policy("60, 600,3600", "");
+        assert_eq!(policy.duration(Offence::Batch, 1), 60);
+        assert_eq!(policy.duration(Offence::Batch, 2), 600);
+        assert_eq!(policy.duration(Offence::Batch, 3), 3600);
+        assert_eq!(policy.duration(Offence::Batch, 10), 3600);
+        // empty schedule falls back to punishment_duration
+        assert_eq!(policy.duration(Offence::Swap, 1), 60);
+        assert_eq!(policy.duration(Offence::Swap, 5), 60);
+
+        let mut config = Config::default();
+        config.punishment.batch_schedule = String::from("60,ten");
+        assert!(PunishmentPolicy::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_policy_schedule_blocks() {
+        let mut config = Config::default();
+        config.duration_unit = DurationUnit::Blocks;
+        config.conductor.punishment_duration = 1;
+        config.punishment.batch_schedule = String::from("1,6");
+        let policy = PunishmentPolicy::from_config(&config).unwrap();
+        assert_eq!(policy.duration(Offence::Batch, 1), 600);
+        assert_eq!(policy.duration(Offence::Batch, 2), 3600);
+        assert_eq!(policy.duration(Offence::Swap, 1), 600);
+    }
+
+    #[test]
+    fn test_policy_decay() {
+        let policy = test_policy("", "");
+        let now = Utc::now().naive_utc();
+        let mut record = PunishmentRecord {
+            id: String::from("id"),
+            offences: 3,
+            last_offence: now - Duration::seconds(60),
+            locked_until: now,
+        };
+        assert_eq!(policy.offences(None, &now), 0);
+        assert_eq!(policy.offences(Some(&record), &now), 3);
+        record.last_offence = now - Duration::seconds(3601);
+        assert_eq!(policy.offences(Some(&record), &now), 0);
+        assert_eq!(policy.expiry_cutoff(&now), now - Duration::seconds(3600));
+    }
+
+    #[test]
+    fn test_punish_escalates_by_proof_key() {
+        let statechain_id = Uuid::from_str("00000000-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let proof_key = String::from(
+            "026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e",
+        );
+        let proof_key_record = proof_key.clone();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let chain_proof_key = proof_key.clone();
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: Uuid::new_v4(),
+                chain: StateChain::new(chain_proof_key.clone()),
+            })
+        });
+        // new statechain, proof key failed twice recently
+        db.expect_get_punishment()
+            .with(predicate::eq(statechain_id.to_string()))
+            .returning(|_| Ok(None));
+        db.expect_get_punishment()
+            .with(predicate::eq(proof_key.clone()))
+            .returning(move |_| {
+                Ok(Some(PunishmentRecord {
+                    id: proof_key_record.clone(),
+                    offences: 2,
+                    last_offence: Utc::now().naive_utc() - Duration::seconds(600),
+                    locked_until: Utc::now().naive_utc() - Duration::seconds(60),
+                }))
+            });
+        db.expect_update_punishment()
+            .withf(|r: &PunishmentRecord| r.offences == 1 || r.offences == 3)
+            .times(2)
+            .returning(|_| Ok(()));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.punishment.batch_schedule = String::from("60,600,3600,86400");
+        sc_entity.config.punishment.decay = 86400;
+
+        let locked_until = sc_entity.punish(&statechain_id, Offence::Batch).unwrap();
+        let remaining = locked_until.timestamp() - Utc::now().naive_utc().timestamp();
+        assert!(remaining > 3590 && remaining <= 3600, "{}", remaining);
+    }
+}
@@ -0,0 +1,298 @@
+//! StateEntity Refresh
+//!
+//! StateEntity key share refresh protocol trait and implementation.
+//!
+//! The owner and the state entity replace their key shares of a statecoin with new shares of
+//! the same shared key (proactive security), so that shares leaked before the refresh are of no
+//! use. The shared public key, and so the funding output and the backup tx, are unchanged. The
+//! re-keying is that of transfer with the owner as both sender and receiver:
+//!     1. The state entity generates a blinding factor x1 and returns it encrypted to the owner
+//!        proof key.
+//!     2. The owner sends t2 = o1*x1*o2_inv and O2. The state entity computes its new share
+//!        s2 = t2*x1_inv*s1, checks O2*s2 = P, deletes s1 and increments the share epoch of the
+//!        shared key. The owner then runs keygen with its new share o2 as in transfer.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::protocol::{session::SessionEvent, transfer::transfer_key_update, withdraw::Withdraw};
+use crate::server::StateChainEntity;
+use crate::Database;
+use shared_lib::{
+    ecies, ecies::SelfEncryptable, ecies::WalletDecryptable, state_chain::is_locked, structs::*,
+};
+
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::key::SecretKey;
+use bitcoin::util::key::PrivateKey;
+use cfg_if::cfg_if;
+use curv::{elliptic::curves::traits::ECScalar, FE};
+use std::{convert::TryInto, str::FromStr};
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// StateChain key share refresh protocol trait
+pub trait Refresh {
+    /// API: Initiate a key share refresh:
+    ///     - Check the shared key owns an unlocked statechain
+    ///     - Generate x1 and return it encrypted to the owner proof key
+    fn refresh_first(&self, refresh_msg1: RefreshMsg1) -> Result<TransferMsg2>;
+
+    /// API: Complete a key share refresh:
+    ///     - Compute the new key share s2 and check O2*s2 = P
+    ///     - Reset keygen with s2 and increment the share epoch
+    ///     - Return S2 and the new share epoch
+    fn refresh_second(&self, refresh_msg2: RefreshMsg2) -> Result<RefreshReply>;
+}
+
+impl Refresh for SCE {
+    fn refresh_first(&self, refresh_msg1: RefreshMsg1) -> Result<TransferMsg2> {
+        self.check_user_auth(&refresh_msg1.shared_key_id)?;
+        let user_id = refresh_msg1.shared_key_id;
+        let next_state = self.check_session_event(&user_id, SessionEvent::RefreshFirst)?;
+        debug!("REFRESH: First. Shared Key ID: {}", user_id);
+
+        if self.lockbox.is_some() {
+            return Err(SEError::Generic(String::from(
+                "Key share refresh not supported with lockbox",
+            )));
+        }
+        if self.get_if_signed_for_withdrawal(&user_id)?.is_some() {
+            return Err(SEError::Generic(format!(
+                "refresh_first - shared key id: {} is signed for withdrawal",
+                &user_id
+            )));
+        }
+
+        // Check if state chain is owned by user and not locked
+        let statechain_id = self.database.get_statechain_id(user_id)?;
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        is_locked(sco.locked_until)?;
+        if sco.owner_id != user_id {
+            return Err(SEError::Generic(format!(
+                "State Chain not owned by User ID: {}.",
+                user_id
+            )));
+        }
+
+        // Generate x1
+        let x1: FE = ECScalar::new_random();
+        self.database.update_ecdsa_refresh_x1(&user_id, &x1)?;
+        self.update_session_state(&user_id, next_state)?;
+
+        info!(
+            "REFRESH: First complete. Shared key ID: {}. State Chain ID: {}",
+            user_id, statechain_id
+        );
+
+        // encrypt x1 with owner proof key
+        let proof_key = ecies::PublicKey::from_str(&self.database.get_proof_key(user_id)?)
+            .map_err(|e| SEError::SharedLibError(format!("error deserialising proof key: {}", e)))?;
+
+        let mut msg2 = TransferMsg2 {
+            x1: FESer::from_fe(&x1),
+            proof_key,
+        };
+        msg2.encrypt()
+            .map_err(|e| SEError::SharedLibError(format!("{}", e)))?;
+
+        Ok(msg2)
+    }
+
+    fn refresh_second(&self, mut refresh_msg2: RefreshMsg2) -> Result<RefreshReply> {
+        self.check_user_auth(&refresh_msg2.shared_key_id)?;
+        let user_id = refresh_msg2.shared_key_id;
+        let next_state = self.check_session_event(&user_id, SessionEvent::RefreshSecond)?;
+        let statechain_id = refresh_msg2.statechain_id;
+
+        if self.database.get_statechain_id(user_id)? != statechain_id {
+            return Err(SEError::Generic(format!(
+                "refresh_second - shared key id: {} does not own State Chain ID: {}",
+                user_id, statechain_id
+            )));
+        }
+
+        let x1 = self.database.get_ecdsa_refresh_x1(user_id)?.ok_or(SEError::Generic(format!(
+            "refresh_second - no key share refresh initiated for shared key id: {}",
+            user_id
+        )))?;
+
+        // Compute the new key share
+        let kp = self.database.get_ecdsa_keypair(user_id)?;
+        let s1 = kp.party_1_private.get_private_key();
+        let key: SecretKey = kp.clone().try_into()?;
+        let s1_priv = PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key,
+        };
+        refresh_msg2.decrypt(&s1_priv).map_err(|e| {
+            SEError::SharedLibError(format!("Failed to decrypt t2 in refresh_msg2. Error: {}", e))
+        })?;
+        let t2 = refresh_msg2
+            .t2
+            .get_fe()
+            .map_err(|e| SEError::Generic(format!("Failed to get FE from refresh_msg2: {}", e)))?;
+
+        let (s2, s2_pub) =
+            transfer_key_update(&s1, &x1, &t2, &kp.party_2_public, &refresh_msg2.o2_pub)?;
+
+        // Replace s1 with s2 for keygen. The share epoch is kept by reset_ecdsa.
+        let share_epoch = self.database.get_ecdsa_share_epoch(user_id)? + 1;
+        self.database.update_ecdsa_s2(&user_id, &s2)?;
+        self.database.reset_ecdsa(&user_id)?;
+        self.database.update_ecdsa_share_epoch(&user_id, share_epoch)?;
+        self.update_session_state(&user_id, next_state)?;
+
+        info!(
+            "REFRESH: Second complete. Shared key ID: {}. Share epoch: {}",
+            user_id, share_epoch
+        );
+
+        Ok(RefreshReply {
+            s2_pub,
+            share_epoch,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DBErrorType;
+    use crate::protocol::transfer::tests::{PARTY_1_PRIVATE, PARTY_2_PUBLIC};
+    use crate::protocol::util::tests::test_sc_entity;
+    use crate::structs::{ECDSAKeypair, SessionState, StateChainOwner};
+    use bitcoin::secp256k1::{PublicKey, Secp256k1};
+    use chrono::{Duration, Utc};
+    use curv::{elliptic::curves::traits::ECPoint, GE};
+    use mockall::predicate;
+    use shared_lib::state_chain::StateChain;
+
+    fn keypair() -> ECDSAKeypair {
+        ECDSAKeypair {
+            party_1_private: serde_json::from_str(&PARTY_1_PRIVATE.to_string()).unwrap(),
+            party_2_public: serde_json::from_str(&PARTY_2_PUBLIC.to_string()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_refresh() {
+        let user_id = Uuid::new_v4();
+        let other_user_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let x1: FE = ECScalar::new_random();
+        let proof_key = "026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e";
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Owner)));
+        db.expect_get_withdraw_confirm_data()
+            .returning(|_| Err(SEError::DBError(DBErrorType::NoDataForID, String::default())));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_statechain_owner()
+            .times(1)
+            .returning(move |_| {
+                Ok(StateChainOwner {
+                    locked_until: Utc::now().naive_utc() - Duration::seconds(1),
+                    owner_id: other_user_id,
+                    chain: StateChain::new(proof_key.to_string()),
+                })
+            });
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc() - Duration::seconds(1),
+                owner_id: user_id,
+                chain: StateChain::new(proof_key.to_string()),
+            })
+        });
+        db.expect_update_ecdsa_refresh_x1()
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_get_proof_key()
+            .returning(move |_| Ok(proof_key.to_string()));
+        db.expect_get_ecdsa_refresh_x1()
+            .returning(move |_| Ok(Some(x1)));
+        db.expect_get_ecdsa_keypair()
+            .with(predicate::eq(user_id))
+            .returning(|_| Ok(keypair()));
+        db.expect_get_ecdsa_share_epoch().returning(|_| Ok(2));
+
+        // New key shares: o2_pub*s2 = o1_pub*s1 for a random t2
+        let kp = keypair();
+        let s1 = kp.party_1_private.get_private_key();
+        let t2: FE = ECScalar::new_random();
+        let s2 = t2 * x1.invert() * s1;
+        let o2_pub = kp.party_2_public * (s1 * s2.invert());
+        db.expect_update_ecdsa_s2()
+            .withf(move |_, s| *s == s2)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_reset_ecdsa().times(1).returning(|_| Ok(()));
+        db.expect_update_ecdsa_share_epoch()
+            .withf(|_, epoch| *epoch == 3)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::RefreshInit)
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Statechain not owned by the shared key
+        match sc_entity.refresh_first(RefreshMsg1 { shared_key_id: user_id }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("State Chain not owned by User ID")),
+        }
+        let msg2 = sc_entity
+            .refresh_first(RefreshMsg1 { shared_key_id: user_id })
+            .unwrap();
+        assert_eq!(msg2.proof_key.to_string(), proof_key);
+
+        // t2 is encrypted to the state entity key share S1
+        let s1_key: SecretKey = kp.clone().try_into().unwrap();
+        let s1_pub = ecies::PublicKey {
+            compressed: true,
+            key: PublicKey::from_secret_key(&Secp256k1::new(), &s1_key),
+        };
+        let mut refresh_msg2 = RefreshMsg2 {
+            shared_key_id: user_id,
+            statechain_id,
+            t2: FESer::from_fe(&t2),
+            o2_pub,
+        };
+        refresh_msg2.encrypt_with_pubkey(&s1_pub).unwrap();
+
+        // Wrong O2
+        let mut wrong_o2 = refresh_msg2.clone();
+        wrong_o2.o2_pub = GE::generator();
+        match sc_entity.refresh_second(wrong_o2) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("P1 != P2")),
+        }
+
+        // Wrong statechain
+        let mut wrong_statechain = refresh_msg2.clone();
+        wrong_statechain.statechain_id = Uuid::new_v4();
+        assert!(sc_entity.refresh_second(wrong_statechain).is_err());
+
+        let reply = sc_entity.refresh_second(refresh_msg2).unwrap();
+        assert_eq!(reply.share_epoch, 3);
+        assert_eq!(reply.s2_pub, GE::generator() * s2);
+    }
+}
@@ -0,0 +1,264 @@
+//! StateEntity Revenue
+//!
+//! Revenue report for operator accounting. Fees are recorded in the fee ledger when the
+//! deposit, withdrawal or swap paying them is confirmed. The report sums the fees of a period
+//! by fee type and by day, and reconciles each tx against the value it pays to the fee
+//! addresses on-chain.
+
+pub use super::super::Result;
+
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::structs::FeeRecord;
+use crate::{server::StateChainEntity, Database};
+use shared_lib::structs::*;
+
+use bitcoin::{Address, Transaction, Txid};
+use cfg_if::cfg_if;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+//Generics cannot be used in Rocket State, therefore we define the concrete
+//type of StateChainEntity here
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// StateChain Entity revenue reporting
+pub trait Revenue {
+    /// API: Report the fees recorded over a period, reconciled against the value received by
+    /// the fee addresses. Requires the admin key.
+    fn revenue_report(&self, report_msg: RevenueReportMsg) -> Result<RevenueReportAPI>;
+}
+
+/// Value paid to fee_addresses by tx
+pub fn fee_received(tx: &Transaction, fee_addresses: &[Address]) -> u64 {
+    tx.output
+        .iter()
+        .filter(|o| fee_addresses.iter().any(|a| a.script_pubkey() == o.script_pubkey))
+        .map(|o| o.value)
+        .sum()
+}
+
+/// Sum fees by fee type and by day. Each statecoin charged is counted once per fee type.
+pub fn revenue_summary(fees: &[FeeRecord]) -> (Vec<RevenueItemAPI>, Vec<RevenueDayAPI>) {
+    let mut by_fee_type = BTreeMap::new();
+    let mut by_day = BTreeMap::new();
+    for fee in fees {
+        let item = by_fee_type.entry(fee.fee_type).or_insert((0, 0));
+        item.0 += 1;
+        item.1 += fee.amount;
+        let day = by_day
+            .entry((fee.collected_at.date(), fee.fee_type))
+            .or_insert((0, 0));
+        day.0 += 1;
+        day.1 += fee.amount;
+    }
+    (
+        by_fee_type
+            .into_iter()
+            .map(|(fee_type, (count, amount))| RevenueItemAPI {
+                fee_type,
+                count,
+                amount,
+            })
+            .collect(),
+        by_day
+            .into_iter()
+            .map(|((date, fee_type), (count, amount))| RevenueDayAPI {
+                date,
+                fee_type,
+                count,
+                amount,
+            })
+            .collect(),
+    )
+}
+
+impl SCE {
+    /// Get a tx from bitcoind. The tx may be unconfirmed.
+    fn get_fee_tx(&self, txid: &String) -> Result<Transaction> {
+        let txid = Txid::from_str(txid).map_err(|e| SEError::Generic(format!("invalid txid: {}", e)))?;
+        let rpc_path_parts: Vec<&str> = self.config.bitcoind.split('@').collect();
+        let rpc_cred: Vec<&str> = rpc_path_parts[0].split(':').collect();
+        if rpc_path_parts.len() != 2 || rpc_cred.len() != 2 {
+            return Err(SEError::Generic(String::from(
+                "Revenue reconciliation requires a bitcoind RPC path",
+            )));
+        }
+        cfg_if! {
+            if #[cfg(any(test,feature="mockbitcoinrpc"))]{
+                use shared_lib::mocks::mock_rpc_client::MockBitcoinClient;
+                let mut rpc = MockBitcoinClient::new();
+            } else {
+                use bitcoincore_rpc::{Auth, Client, RpcApi};
+                let rpc = Client::new(rpc_path_parts[1].to_string(),
+                              Auth::UserPass(rpc_cred[0].to_string(),
+                                             rpc_cred[1].to_string()))
+                    .map_err(|e| SEError::Generic(e.to_string()))?;
+            }
+        }
+        rpc.get_raw_transaction(&txid, None)
+            .map_err(|e| SEError::Generic(format!("tx {} not found: {:?}", txid, e)))
+    }
+}
+
+impl Revenue for SCE {
+    fn revenue_report(&self, report_msg: RevenueReportMsg) -> Result<RevenueReportAPI> {
+        self.check_admin_key(&report_msg.admin_key)?;
+        if report_msg.to <= report_msg.from {
+            return Err(SEError::Generic(String::from(
+                "Revenue report end must be after its start",
+            )));
+        }
+        let fee_addresses = self
+            .config
+            .fee_address
+            .replace(" ", "")
+            .split(",")
+            .map(|addr| Address::from_str(addr).map_err(|e| SEError::Generic(e.to_string())))
+            .collect::<Result<Vec<Address>>>()?;
+
+        let fees = self.database.get_fees(&report_msg.from, &report_msg.to)?;
+        let (by_fee_type, by_day) = revenue_summary(&fees);
+
+        // A tx may pay several fees, e.g. a batch withdraw tx or a withdraw tx deducting swap
+        // fees
+        let mut expected = BTreeMap::new();
+        for fee in &fees {
+            *expected.entry(fee.txid.clone()).or_insert(0) += fee.amount;
+        }
+        let mut reconciliation = vec![];
+        for (txid, expected) in expected {
+            let received = match self.get_fee_tx(&txid) {
+                Ok(tx) => Some(fee_received(&tx, &fee_addresses)),
+                Err(e) => {
+                    warn!("REVENUE: {}", e);
+                    None
+                }
+            };
+            reconciliation.push(FeeReconciliationAPI {
+                txid,
+                expected,
+                received,
+                reconciled: received.map_or(false, |r| r >= expected),
+            });
+        }
+        reconciliation.sort_by_key(|r| r.reconciled);
+
+        Ok(RevenueReportAPI {
+            from: report_msg.from,
+            to: report_msg.to,
+            total: fees.iter().map(|f| f.amount).sum(),
+            received: reconciliation.iter().filter_map(|r| r.received).sum(),
+            by_fee_type,
+            by_day,
+            reconciliation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use chrono::NaiveDate;
+    use shared_lib::mocks::mock_rpc_client::{MOCK_RAW_TX_ADDRESS, MOCK_RAW_TX_VALUE};
+    use uuid::Uuid;
+
+    static TXID_1: &str = "e3d514ad83995f5c3407a3a6317355fe22d3b24ab2e89455f9db504e3bfb3c86";
+    static TXID_2: &str = "988d8d8de7a81d859c90d0a6b3b577d622608bbe22ce861f71eb46aa662696fb";
+
+    fn fee(txid: &str, fee_type: FeeType, amount: u64, day: u32) -> FeeRecord {
+        FeeRecord {
+            txid: String::from(txid),
+            fee_type,
+            statechain_id: Uuid::new_v4(),
+            amount,
+            collected_at: NaiveDate::from_ymd(2021, 3, day).and_hms(12, 0, 0),
+        }
+    }
+
+    fn fees() -> Vec<FeeRecord> {
+        vec![
+            fee(TXID_1, FeeType::Deposit, 4000, 1),
+            fee(TXID_2, FeeType::Withdraw, 8000, 1),
+            fee(TXID_2, FeeType::Swap, 3000, 1),
+            fee(TXID_1, FeeType::Withdraw, 2000, 2),
+        ]
+    }
+
+    #[test]
+    fn test_revenue_summary() {
+        let (by_fee_type, by_day) = revenue_summary(&fees());
+        assert_eq!(
+            by_fee_type,
+            vec![
+                RevenueItemAPI { fee_type: FeeType::Deposit, count: 1, amount: 4000 },
+                RevenueItemAPI { fee_type: FeeType::Withdraw, count: 2, amount: 10000 },
+                RevenueItemAPI { fee_type: FeeType::Swap, count: 1, amount: 3000 },
+            ]
+        );
+        assert_eq!(by_day.len(), 4);
+        assert_eq!(by_day[3].date, NaiveDate::from_ymd(2021, 3, 2));
+        assert_eq!(by_day[3].amount, 2000);
+    }
+
+    #[test]
+    fn test_revenue_report() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_fees().returning(|_, _| Ok(fees()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.bitcoind = String::from("user:pass@localhost:18443");
+        sc_entity.config.fee_address = String::from(MOCK_RAW_TX_ADDRESS);
+
+        let report_msg = RevenueReportMsg {
+            admin_key: String::from("admin"),
+            from: NaiveDate::from_ymd(2021, 3, 1).and_hms(0, 0, 0),
+            to: NaiveDate::from_ymd(2021, 4, 1).and_hms(0, 0, 0),
+        };
+
+        // Admin key not configured
+        match sc_entity.revenue_report(report_msg.clone()) {
+            Err(SEError::AuthError) => (),
+            res => assert!(false, "expected AuthError, got {:?}", res),
+        }
+        sc_entity.config.admin_key = Some(String::from("admin"));
+
+        // Empty period
+        let mut empty_msg = report_msg.clone();
+        empty_msg.to = empty_msg.from;
+        assert!(sc_entity.revenue_report(empty_msg).is_err());
+
+        // Every mock tx pays MOCK_RAW_TX_VALUE to the fee address: TXID_2 pays less than the
+        // withdraw and swap fees recorded for it
+        let report = sc_entity.revenue_report(report_msg).unwrap();
+        assert_eq!(report.total, 17000);
+        assert_eq!(report.received, 2 * MOCK_RAW_TX_VALUE);
+        assert_eq!(
+            report.reconciliation,
+            vec![
+                FeeReconciliationAPI {
+                    txid: String::from(TXID_2),
+                    expected: 11000,
+                    received: Some(MOCK_RAW_TX_VALUE),
+                    reconciled: false,
+                },
+                FeeReconciliationAPI {
+                    txid: String::from(TXID_1),
+                    expected: 6000,
+                    received: Some(MOCK_RAW_TX_VALUE),
+                    reconciled: true,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! StateEntity Root Update
+//!
+//! SMT root update policy.
+//!
+//! Unless a root is published for every SMT update, deposit, transfer, withdraw and migrate SMT
+//! updates are queued and inserted together, with a single root update (and mainstay
+//! attestation), once root_update.max_updates updates are queued or root_update.interval
+//! seconds after the oldest was queued. Queued updates are not in the tree, and proofs are not
+//! served for funding txids with a queued update, so clients are only ever proven against
+//! published roots.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::config::RootUpdateConfig;
+use crate::error::SEError;
+use crate::protocol::ping::Ping;
+use crate::server::StateChainEntity;
+use crate::storage::Storage;
+use crate::Database;
+use shared_lib::Root;
+
+use cfg_if::cfg_if;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::thread;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Interval in seconds at which the root update thread checks whether queued updates are due
+const ROOT_UPDATE_CHECK_INTERVAL: u64 = 1;
+
+/// SMT updates queued for the next root
+#[derive(Debug, Default)]
+pub struct RootQueue {
+    state: Mutex<RootQueueState>,
+    /// Serializes publication of queued updates
+    publish_lock: Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct RootQueueState {
+    /// Queued (funding txid, proof key, amount) updates, oldest first
+    updates: Vec<(String, String, u64)>,
+    /// Number of queued or publishing updates of each funding txid
+    pending: HashMap<String, usize>,
+    /// Time the oldest queued update was queued
+    since: Option<Instant>,
+}
+
+impl RootQueue {
+    fn lock_state(&self) -> Result<std::sync::MutexGuard<RootQueueState>> {
+        self.state
+            .lock()
+            .map_err(|e| SEError::Generic(format!("Root queue lock poisoned: {}", e)))
+    }
+
+    /// Queue an update. Returns true if queued updates are due for publication.
+    pub fn push(&self, update: (String, String, u64), config: &RootUpdateConfig) -> Result<bool> {
+        let mut state = self.lock_state()?;
+        *state.pending.entry(update.0.clone()).or_insert(0) += 1;
+        state.updates.push(update);
+        if state.since.is_none() {
+            state.since = Some(Instant::now());
+        }
+        Ok(state.is_due(config))
+    }
+
+    /// True if an update of funding_txid is queued or being published
+    pub fn is_pending(&self, funding_txid: &String) -> Result<bool> {
+        Ok(self.lock_state()?.pending.contains_key(funding_txid))
+    }
+
+    /// True if queued updates are due for publication
+    pub fn is_due(&self, config: &RootUpdateConfig) -> Result<bool> {
+        Ok(self.lock_state()?.is_due(config))
+    }
+
+    /// Number of queued updates
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.lock_state()?.updates.len())
+    }
+
+    /// Insert all queued updates with insert. The updates remain pending until insert returns,
+    /// and are queued again if it fails. Returns None if no updates were queued.
+    pub fn publish<F>(&self, insert: F) -> Result<Option<(Option<Root>, Root)>>
+    where
+        F: FnOnce(&[(String, String, u64)]) -> Result<(Option<Root>, Root)>,
+    {
+        let _guard = self
+            .publish_lock
+            .lock()
+            .map_err(|e| SEError::Generic(format!("Root queue lock poisoned: {}", e)))?;
+        let batch = {
+            let mut state = self.lock_state()?;
+            state.since = None;
+            std::mem::take(&mut state.updates)
+        };
+        if batch.is_empty() {
+            return Ok(None);
+        }
+
+        let result = insert(&batch);
+
+        let mut state = self.lock_state()?;
+        match result {
+            Ok(roots) => {
+                for (funding_txid, _, _) in &batch {
+                    if let Some(n) = state.pending.get_mut(funding_txid) {
+                        *n -= 1;
+                        if *n == 0 {
+                            state.pending.remove(funding_txid);
+                        }
+                    }
+                }
+                Ok(Some(roots))
+            }
+            Err(e) => {
+                // Retry with the next publication, ahead of updates queued since
+                let mut updates = batch;
+                updates.append(&mut state.updates);
+                state.updates = updates;
+                state.since = Some(Instant::now());
+                Err(e)
+            }
+        }
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        let mut state = self.lock_state()?;
+        *state = RootQueueState::default();
+        Ok(())
+    }
+}
+
+impl RootQueueState {
+    fn is_due(&self, config: &RootUpdateConfig) -> bool {
+        if self.updates.is_empty() {
+            return false;
+        }
+        if config.max_updates > 0 && self.updates.len() >= config.max_updates {
+            return true;
+        }
+        match self.since {
+            Some(since) if config.interval > 0 => {
+                since.elapsed() >= Duration::from_secs(config.interval)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl SCE {
+    /// Publish queued SMT updates in a new root if they are due
+    pub fn publish_due_roots(&self) -> Result<Option<Root>> {
+        if !self.root_queue.is_due(&self.config.root_update)? {
+            return Ok(None);
+        }
+        self.publish_queued_roots()
+    }
+
+    /// Publish all queued SMT updates in a new root
+    pub fn publish_queued_roots(&self) -> Result<Option<Root>> {
+        let n = self.root_queue.len()?;
+        match self.root_queue.publish(|batch| self.update_smt_batch(batch))? {
+            Some((prev_root, new_root)) => {
+                info!("ROOT: Published {} queued SMT updates.", n);
+                debug!("ROOT: New root: {:?}. Previous root: {:?}.", &new_root, &prev_root);
+                Ok(Some(new_root))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Publish queued SMT updates on the root update interval on a handle sharing the state
+/// entity, if updates are queued on an interval
+pub fn spawn_root_updates(sc_entity: &SCE) -> Result<Option<thread::JoinHandle<()>>> {
+    let config = &sc_entity.config.root_update;
+    if config.is_immediate() || config.interval == 0 {
+        return Ok(None);
+    }
+    let sc_entity = sc_entity.share()?;
+    let interval = Duration::from_secs(ROOT_UPDATE_CHECK_INTERVAL);
+    Ok(Some(thread::spawn(move || loop {
+        if let Err(e) = sc_entity.publish_due_roots() {
+            warn!("ROOT: Root update failed: {}", e);
+        }
+        let _ = sc_entity.heartbeat("root_updates", interval);
+        thread::sleep(interval);
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::{tests::test_sc_entity, Utilities};
+    use shared_lib::structs::SmtProofMsgAPI;
+
+    fn update(i: u8) -> (String, String, u64) {
+        (
+            format!("{}dcaca3b140dfbfe7e6a2d6d7cafea5cdb905178ee5d377804d8337c2c35f62e", i),
+            String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e"),
+            10000,
+        )
+    }
+
+    #[test]
+    fn test_root_queue() {
+        let queue = RootQueue::default();
+        let config = RootUpdateConfig {
+            max_updates: 2,
+            interval: 0,
+        };
+        assert!(!queue.push(update(1), &config).unwrap());
+        assert!(queue.is_pending(&update(1).0).unwrap());
+        assert!(!queue.is_pending(&update(2).0).unwrap());
+        assert!(queue.push(update(2), &config).unwrap());
+
+        // Failed publication leaves the updates queued and pending
+        assert!(queue
+            .publish(|_| Err(SEError::Generic(String::from("insert failed"))))
+            .is_err());
+        assert_eq!(queue.len().unwrap(), 2);
+        assert!(queue.is_pending(&update(1).0).unwrap());
+
+        let published = queue
+            .publish(|batch| {
+                assert_eq!(batch.to_vec(), vec![update(1), update(2)]);
+                Ok((None, Root::from_random()))
+            })
+            .unwrap();
+        assert!(published.is_some());
+        assert_eq!(queue.len().unwrap(), 0);
+        assert!(!queue.is_pending(&update(1).0).unwrap());
+        assert!(!queue.is_due(&config).unwrap());
+        assert!(queue.publish(|_| Ok((None, Root::from_random()))).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_root_queue_interval() {
+        let queue = RootQueue::default();
+        let config = RootUpdateConfig {
+            max_updates: 0,
+            interval: 1,
+        };
+        assert!(!queue.push(update(1), &config).unwrap());
+        assert!(!queue.is_due(&config).unwrap());
+        thread::sleep(Duration::from_millis(1100));
+        assert!(queue.is_due(&config).unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn test_queued_smt_updates() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        db.expect_get_root().returning(|_| Ok(None));
+        db.expect_get_root_info().returning(|_| Ok(Some(Root::from_random())));
+        db.expect_root_update().times(1).returning(|_| Ok(2 as i64));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.mainstay = None;
+        sc_entity.config.root_update = RootUpdateConfig {
+            max_updates: 2,
+            interval: 3600,
+        };
+
+        // First update is queued
+        let (txid1, proof_key1, amount1) = update(1);
+        let (_, new_root) = sc_entity.update_smt(&txid1, &proof_key1, amount1).unwrap();
+        assert!(new_root.is_none());
+
+        // Proofs are not served for funding txids with a queued update
+        let mut root = Root::from_random();
+        root.set_id(&1);
+        match sc_entity.get_smt_proof(SmtProofMsgAPI {
+            root: root.clone(),
+            funding_txid: txid1.clone(),
+            proof_format: Default::default(),
+        }) {
+            Err(SEError::TryAgain(e)) => assert!(e.contains("SMT update pending")),
+            _ => assert!(false, "expected TryAgain error"),
+        }
+
+        // Second update publishes both
+        let (txid2, proof_key2, amount2) = update(2);
+        let (_, new_root) = sc_entity.update_smt(&txid2, &proof_key2, amount2).unwrap();
+        let new_root = new_root.unwrap();
+        assert_eq!(new_root.id(), Some(2));
+        assert!(!sc_entity.root_queue.is_pending(&txid1).unwrap());
+
+        let proof = sc_entity
+            .get_smt_proof(SmtProofMsgAPI {
+                root: new_root.clone(),
+                funding_txid: txid1.clone(),
+                proof_format: Default::default(),
+            })
+            .unwrap();
+        assert!(shared_lib::state_chain::verify_statechain_smt(
+            &Some(new_root.hash()),
+            &txid1,
+            &proof_key1,
+            amount1,
+            &proof
+        ));
+    }
+}
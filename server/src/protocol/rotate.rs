@@ -0,0 +1,338 @@
+//! StateEntity Rotate
+//!
+//! Proof key rotation for the current owner of a statecoin.
+//!
+//! An owner whose proof key may be compromised replaces it with a new proof key they control,
+//! without transferring the statecoin. The owner signs a "ROTATE" StateChainSig of the new proof
+//! key, bound to the statechain tip, which is appended to the statechain as for a transfer. The
+//! shared key, key shares and backup tx are unchanged: no 2P-ECDSA key rotation is run. The
+//! statechain SMT leaf is updated to the new proof key, and the second factor and vault policies
+//! of the old proof key carry over to the new one. A transfer signed but not completed with the
+//! old proof key can no longer be completed. Statecoins in vault mode are rotated once the vault
+//! delay has passed since the rotation was first requested.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::protocol::session::SessionEvent;
+use crate::Database;
+use crate::{server::StateChainEntity, storage::Storage};
+use shared_lib::{state_chain::is_locked, structs::RotateMsg};
+
+use bitcoin::secp256k1::PublicKey;
+use cfg_if::cfg_if;
+use std::str::FromStr;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// StateChain Entity proof key rotation API calls
+pub trait Rotate {
+    /// API: Rotate the proof key of a statecoin owned by the caller:
+    ///     - Check the statechain is owned by the caller and unlocked
+    ///     - Check the "ROTATE" sig is bound to the statechain tip and second factor signed
+    ///     - Append the new proof key to the statechain and update the SMT
+    fn rotate_proof_key(&self, rotate_msg: RotateMsg) -> Result<()>;
+}
+
+impl Rotate for SCE {
+    fn rotate_proof_key(&self, rotate_msg: RotateMsg) -> Result<()> {
+        self.check_user_auth(&rotate_msg.shared_key_id)?;
+        let user_id = rotate_msg.shared_key_id;
+        let next_state = self.check_session_event(&user_id, SessionEvent::RotateProofKey)?;
+        let statechain_sig = &rotate_msg.statechain_sig;
+        let new_proof_key = &statechain_sig.data;
+        debug!("ROTATE: Shared Key ID: {}", user_id);
+
+        if !statechain_sig.is_rotate() {
+            return Err(SEError::Generic(String::from(
+                "rotate_proof_key - statechain sig is not a proof key rotation",
+            )));
+        }
+        PublicKey::from_str(new_proof_key)
+            .map_err(|e| SEError::Generic(format!("Invalid new proof key: {}", e)))?;
+
+        // Check if state chain is owned by user and not locked. The update fails if the
+        // statechain is updated concurrently.
+        let statechain_id = self.database.get_statechain_id(user_id)?;
+        let version = self.database.get_statechain_version(&statechain_id)?;
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        is_locked(sco.locked_until)?;
+        if sco.owner_id != user_id {
+            return Err(SEError::Generic(format!(
+                "State Chain not owned by User ID: {}.",
+                user_id
+            )));
+        }
+        let prev_proof_key = sco.chain.get_tip().data.clone();
+        if new_proof_key == &prev_proof_key {
+            return Err(SEError::Generic(String::from(
+                "rotate_proof_key - new proof key is the current proof key",
+            )));
+        }
+
+        // Rotation signatures are always bound, so that they cannot be replayed
+        statechain_sig.check_binding(&statechain_id, sco.chain.tip_position(), true)?;
+        self.check_second_factor(
+            &statechain_id,
+            &prev_proof_key,
+            statechain_sig,
+            &rotate_msg.auth_sig,
+        )?;
+        self.vault_start(&statechain_id, &prev_proof_key, statechain_sig, false)?;
+        self.check_vault_delay(&statechain_id, statechain_sig)?;
+
+        let mut state_chain = sco.chain;
+        state_chain.add(statechain_sig)?;
+        self.prune_statechain(&statechain_id, &mut state_chain)?;
+        self.check_chain_limits(&statechain_id, &state_chain)?;
+        self.database
+            .update_statechain_owner(&statechain_id, state_chain, &user_id, version)?;
+        self.database.update_proof_key(&user_id, new_proof_key)?;
+        self.update_session_state(&user_id, next_state)?;
+
+        // Policies of the owner carry over to the new proof key
+        if let Some(mut second_factor) = self.database.get_second_factor(&statechain_id)? {
+            if second_factor.proof_key == prev_proof_key {
+                second_factor.proof_key = new_proof_key.clone();
+                self.database.update_second_factor(&second_factor)?;
+            }
+        }
+        if let Some(mut vault) = self.database.get_vault(&statechain_id)? {
+            if vault.proof_key == prev_proof_key {
+                vault.proof_key = new_proof_key.clone();
+                vault.pending_sig = None;
+                vault.pending_since = None;
+                self.database.update_vault(&vault)?;
+            }
+        }
+
+        info!(
+            "ROTATE: Proof key rotated. Shared key ID: {}. State Chain ID: {}",
+            user_id, statechain_id
+        );
+
+        // Update sparse merkle tree with the new proof key
+        let funding_txid = self
+            .database
+            .get_backup_transaction(statechain_id)?
+            .input
+            .get(0)
+            .ok_or(SEError::Generic(String::from("Backup tx has no input")))?
+            .previous_output
+            .txid
+            .to_string();
+        let amount = self.database.get_statechain_amount(statechain_id)?.amount as u64;
+        let (prev_root, new_root) = self.update_smt(&funding_txid, new_proof_key, amount)?;
+        debug!(
+            "ROTATE: State Chain ID: {}. New root: {:?}. Previous root: {:?}.",
+            statechain_id, &new_root, &prev_root
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::{
+        mocks,
+        tests::{test_sc_entity, BACKUP_TX_SIGNED},
+    };
+    use crate::structs::{SecondFactorRecord, SessionState, StateChainAmount, StateChainOwner};
+    use bitcoin::secp256k1::{key::SecretKey, Secp256k1};
+    use bitcoin::Transaction;
+    use chrono::Utc;
+    use shared_lib::state_chain::{StateChain, StateChainSig};
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    fn pubkey(key: &SecretKey) -> String {
+        PublicKey::from_secret_key(&Secp256k1::new(), key).to_string()
+    }
+
+    /// Mock database holding a single statechain owned by shared_key_id. Returns the database
+    /// and the stored statechain and user session proof key.
+    fn rotate_db(
+        shared_key_id: Uuid,
+        statechain_id: Uuid,
+        proof_key: String,
+        second_factor: Option<SecondFactorRecord>,
+    ) -> (MockDatabase, Arc<Mutex<StateChain>>, Arc<Mutex<String>>) {
+        let chain = Arc::new(Mutex::new(StateChain::new(proof_key.clone())));
+        let session_proof_key = Arc::new(Mutex::new(proof_key));
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Owner)));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_statechain_version().returning(|_| Ok(1));
+        let get = chain.clone();
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: shared_key_id,
+                chain: get.lock().unwrap().clone(),
+            })
+        });
+        let update = chain.clone();
+        db.expect_update_statechain_owner()
+            .returning(move |_, state_chain, owner_id, _| {
+                assert_eq!(owner_id, &shared_key_id);
+                *update.lock().unwrap() = state_chain;
+                Ok(())
+            });
+        let update = session_proof_key.clone();
+        db.expect_update_proof_key().returning(move |_, proof_key| {
+            *update.lock().unwrap() = proof_key.clone();
+            Ok(())
+        });
+        db.expect_get_second_factor()
+            .returning(move |_| Ok(second_factor.clone()));
+        db.expect_update_second_factor().returning(|_| Ok(()));
+        db.expect_get_vault().returning(|_| Ok(None));
+        db.expect_get_backup_transaction().returning(|_| {
+            Ok(serde_json::from_str::<Transaction>(&BACKUP_TX_SIGNED.to_string()).unwrap())
+        });
+        let get = chain.clone();
+        db.expect_get_statechain_amount().returning(move |_| {
+            Ok(StateChainAmount {
+                chain: get.lock().unwrap().clone(),
+                amount: 10000,
+            })
+        });
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        db.expect_get_root().returning(|_| Ok(None));
+        db.expect_root_update().returning(|_| Ok(1 as i64));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
+        (db, chain, session_proof_key)
+    }
+
+    #[test]
+    #[serial]
+    fn test_rotate_proof_key() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let new_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let (db, chain, session_proof_key) =
+            rotate_db(shared_key_id, statechain_id, pubkey(&owner_priv), None);
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        let _m = mocks::ms::post_commitment().create();
+
+        let rotate_msg =
+            |key: &SecretKey, new_proof_key: &String, id: &Uuid, position: u64| RotateMsg {
+                shared_key_id,
+                statechain_sig: StateChainSig::new_rotate_sig(key, new_proof_key, id, position)
+                    .unwrap(),
+                auth_sig: None,
+            };
+
+        // Not signed by the owner, bound to another statechain or position, or not a rotation
+        for msg in vec![
+            rotate_msg(&new_priv, &pubkey(&new_priv), &statechain_id, 0),
+            rotate_msg(&owner_priv, &pubkey(&new_priv), &Uuid::new_v4(), 0),
+            rotate_msg(&owner_priv, &pubkey(&new_priv), &statechain_id, 1),
+            rotate_msg(&owner_priv, &pubkey(&owner_priv), &statechain_id, 0),
+            rotate_msg(&owner_priv, &String::from("not a key"), &statechain_id, 0),
+            RotateMsg {
+                shared_key_id,
+                statechain_sig: StateChainSig::new(
+                    &owner_priv,
+                    &String::from("TRANSFER"),
+                    &pubkey(&new_priv),
+                )
+                .unwrap(),
+                auth_sig: None,
+            },
+        ] {
+            assert!(sc_entity.rotate_proof_key(msg).is_err());
+        }
+        assert_eq!(chain.lock().unwrap().tip_position(), 0);
+
+        sc_entity
+            .rotate_proof_key(rotate_msg(
+                &owner_priv,
+                &pubkey(&new_priv),
+                &statechain_id,
+                0,
+            ))
+            .unwrap();
+        assert_eq!(chain.lock().unwrap().get_tip().data, pubkey(&new_priv));
+        assert_eq!(*session_proof_key.lock().unwrap(), pubkey(&new_priv));
+
+        // The old proof key can no longer rotate
+        assert!(sc_entity
+            .rotate_proof_key(rotate_msg(
+                &owner_priv,
+                &pubkey(&owner_priv),
+                &statechain_id,
+                1
+            ))
+            .is_err());
+        sc_entity
+            .rotate_proof_key(rotate_msg(
+                &new_priv,
+                &pubkey(&owner_priv),
+                &statechain_id,
+                1,
+            ))
+            .unwrap();
+        assert_eq!(chain.lock().unwrap().tip_position(), 2);
+    }
+
+    #[test]
+    #[serial]
+    fn test_rotate_proof_key_second_factor() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let new_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let auth_priv = SecretKey::from_slice(&[3; 32]).unwrap();
+        let second_factor = SecondFactorRecord {
+            statechain_id,
+            proof_key: pubkey(&owner_priv),
+            auth_key: Some(pubkey(&auth_priv)),
+            pending_auth_key: None,
+            pending_effective: None,
+        };
+        let (db, chain, _) = rotate_db(
+            shared_key_id,
+            statechain_id,
+            pubkey(&owner_priv),
+            Some(second_factor),
+        );
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        let _m = mocks::ms::post_commitment().create();
+
+        let statechain_sig =
+            StateChainSig::new_rotate_sig(&owner_priv, &pubkey(&new_priv), &statechain_id, 0)
+                .unwrap();
+        let mut rotate_msg = RotateMsg {
+            shared_key_id,
+            statechain_sig: statechain_sig.clone(),
+            auth_sig: None,
+        };
+        match sc_entity.rotate_proof_key(rotate_msg.clone()) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("requires a second factor signature")),
+        }
+
+        rotate_msg.auth_sig =
+            Some(StateChainSig::new_second_factor_sig(&auth_priv, &statechain_sig).unwrap());
+        sc_entity.rotate_proof_key(rotate_msg).unwrap();
+        assert_eq!(chain.lock().unwrap().get_tip().data, pubkey(&new_priv));
+    }
+}
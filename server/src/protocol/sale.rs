@@ -0,0 +1,435 @@
+//! StateEntity Sale
+//!
+//! Escrowed sale of a statecoin on top of the transfer protocol.
+//!
+//! The seller opens a sale with the hash of a secret, e.g. the payment hash of a lightning
+//! invoice to the buyer, then transfers the statecoin to the buyer as usual. When the buyer
+//! completes transfer/receiver the state entity holds the transfer instead of finalizing it,
+//! and finalizes it as soon as the preimage is revealed: by the buyer, who learns it by paying
+//! the invoice, or by the seller once paid on-chain. While the transfer is held the seller
+//! cannot transfer, withdraw or migrate the statecoin, so the buyer should only pay once the
+//! sale is held for its proof key. A held transfer that is not finalized before the sale
+//! expires is dropped, and the seller can transfer the statecoin again.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::protocol::transfer::Transfer;
+use crate::server::StateChainEntity;
+use crate::structs::SaleRecord;
+use crate::Database;
+use shared_lib::state_chain::{is_locked, verify_sale_preimage};
+use shared_lib::structs::{SaleAPI, SaleInitMsg, SaleRevealMsg, SaleStatus, TransferFinalizeData};
+
+use cfg_if::cfg_if;
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::sync::MutexGuard;
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Stage of a sale at time now
+pub fn sale_status(sale: &SaleRecord, now: &NaiveDateTime) -> SaleStatus {
+    if sale.finalized {
+        SaleStatus::Finalized
+    } else if sale.expires <= *now {
+        SaleStatus::Expired
+    } else if sale.finalize_data.is_some() {
+        SaleStatus::Held
+    } else {
+        SaleStatus::Open
+    }
+}
+
+fn sale_api(sale: &SaleRecord) -> SaleAPI {
+    SaleAPI {
+        statechain_id: sale.statechain_id,
+        payment_hash: sale.payment_hash.clone(),
+        expires: sale.expires,
+        status: sale_status(sale, &Utc::now().naive_utc()),
+        buyer_proof_key: sale
+            .finalize_data
+            .as_ref()
+            .map(|d| d.statechain_sig.data.clone()),
+        preimage: sale.preimage.clone(),
+    }
+}
+
+/// StateChain Entity escrowed sale API calls
+pub trait Sale {
+    /// API: Open an escrowed sale of a statecoin owned by the caller. The next transfer of the
+    /// statecoin is held until the preimage of the payment hash is revealed.
+    fn sale_init(&self, sale_init_msg: SaleInitMsg) -> Result<SaleAPI>;
+
+    /// API: Get the escrowed sale of a statechain
+    fn sale_get(&self, statechain_id: Uuid) -> Result<SaleAPI>;
+
+    /// API: Reveal the preimage of the payment hash of a sale, finalizing the held transfer
+    fn sale_reveal(&self, sale_reveal_msg: SaleRevealMsg) -> Result<SaleAPI>;
+}
+
+impl SCE {
+    fn lock_sales(&self) -> Result<MutexGuard<()>> {
+        self.sale_lock
+            .lock()
+            .map_err(|e| SEError::Generic(format!("Sale lock poisoned: {}", e)))
+    }
+
+    /// Err if a transfer of the statechain is held for sale, in which case the statechain
+    /// cannot be transferred, withdrawn or migrated by its owner until the sale expires
+    pub fn check_sale_not_held(&self, statechain_id: &Uuid) -> Result<()> {
+        if let Some(sale) = self.database.get_sale(statechain_id)? {
+            if sale_status(&sale, &Utc::now().naive_utc()) == SaleStatus::Held {
+                return Err(SEError::Generic(format!(
+                    "Statechain {} transfer is held for sale until {}",
+                    statechain_id, sale.expires
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// True if the statechain has a sale that is open or holding a transfer
+    pub fn has_open_sale(&self, statechain_id: &Uuid) -> Result<bool> {
+        Ok(match self.database.get_sale(statechain_id)? {
+            Some(sale) => match sale_status(&sale, &Utc::now().naive_utc()) {
+                SaleStatus::Open | SaleStatus::Held => true,
+                SaleStatus::Finalized | SaleStatus::Expired => false,
+            },
+            None => false,
+        })
+    }
+
+    /// Hold a transfer of a statechain with an open sale until the preimage is revealed, or
+    /// finalize it if the preimage has already been revealed. Returns false if the statechain
+    /// has no open sale, in which case the transfer is not held.
+    pub fn hold_sale_transfer(&self, finalized_data: &TransferFinalizeData) -> Result<bool> {
+        let statechain_id = finalized_data.statechain_id;
+        let _guard = self.lock_sales()?;
+        let mut sale = match self.database.get_sale(&statechain_id)? {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+        match sale_status(&sale, &Utc::now().naive_utc()) {
+            SaleStatus::Open => (),
+            SaleStatus::Held => {
+                return Err(SEError::Generic(format!(
+                    "Statechain {} transfer already held for sale",
+                    statechain_id
+                )))
+            }
+            SaleStatus::Finalized | SaleStatus::Expired => return Ok(false),
+        }
+
+        sale.finalize_data = Some(finalized_data.clone());
+        if sale.preimage.is_some() {
+            self.transfer_finalize(finalized_data)?;
+            sale.finalized = true;
+            info!("SALE: Transfer finalized on receipt. State Chain ID: {}", statechain_id);
+        } else {
+            info!("SALE: Transfer held. State Chain ID: {}", statechain_id);
+        }
+        self.database.update_sale(&sale)?;
+        Ok(true)
+    }
+}
+
+impl Sale for SCE {
+    fn sale_init(&self, sale_init_msg: SaleInitMsg) -> Result<SaleAPI> {
+        let user_id = sale_init_msg.shared_key_id;
+        self.check_user_auth(&user_id)?;
+
+        let payment_hash = sale_init_msg.payment_hash.to_lowercase();
+        match hex::decode(&payment_hash) {
+            Ok(h) if h.len() == 32 => (),
+            _ => {
+                return Err(SEError::Generic(String::from(
+                    "Sale payment hash must be a hex encoded sha256 hash",
+                )))
+            }
+        }
+        if sale_init_msg.lifetime == 0 || sale_init_msg.lifetime > self.config.sale_max_lifetime {
+            return Err(SEError::Generic(format!(
+                "Sale lifetime must be between 1 and {} seconds",
+                self.config.sale_max_lifetime
+            )));
+        }
+
+        let statechain_id = self.database.get_statechain_id(user_id)?;
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        is_locked(sco.locked_until)?;
+        if sco.owner_id != user_id {
+            return Err(SEError::Generic(format!(
+                "State Chain not owned by User ID: {}.",
+                user_id
+            )));
+        }
+
+        let _guard = self.lock_sales()?;
+        let now = Utc::now().naive_utc();
+        if let Some(sale) = self.database.get_sale(&statechain_id)? {
+            match sale_status(&sale, &now) {
+                SaleStatus::Open | SaleStatus::Held => {
+                    return Err(SEError::Generic(format!(
+                        "Statechain {} already has an open sale",
+                        statechain_id
+                    )))
+                }
+                SaleStatus::Finalized | SaleStatus::Expired => (),
+            }
+        }
+
+        let sale = SaleRecord {
+            statechain_id,
+            payment_hash,
+            expires: now + Duration::seconds(sale_init_msg.lifetime as i64),
+            finalize_data: None,
+            preimage: None,
+            finalized: false,
+        };
+        self.database.update_sale(&sale)?;
+        info!(
+            "SALE: Opened. State Chain ID: {}. Expires: {}",
+            statechain_id, sale.expires
+        );
+        Ok(sale_api(&sale))
+    }
+
+    fn sale_get(&self, statechain_id: Uuid) -> Result<SaleAPI> {
+        match self.database.get_sale(&statechain_id)? {
+            Some(sale) => Ok(sale_api(&sale)),
+            None => Err(SEError::Generic(format!(
+                "No sale for statechain {}",
+                statechain_id
+            ))),
+        }
+    }
+
+    fn sale_reveal(&self, sale_reveal_msg: SaleRevealMsg) -> Result<SaleAPI> {
+        let statechain_id = sale_reveal_msg.statechain_id;
+        let _guard = self.lock_sales()?;
+        let mut sale = match self.database.get_sale(&statechain_id)? {
+            Some(s) => s,
+            None => {
+                return Err(SEError::Generic(format!(
+                    "No sale for statechain {}",
+                    statechain_id
+                )))
+            }
+        };
+        match sale_status(&sale, &Utc::now().naive_utc()) {
+            SaleStatus::Finalized => return Ok(sale_api(&sale)),
+            SaleStatus::Expired => {
+                return Err(SEError::Generic(format!(
+                    "Sale of statechain {} expired at {}",
+                    statechain_id, sale.expires
+                )))
+            }
+            SaleStatus::Open | SaleStatus::Held => (),
+        }
+        verify_sale_preimage(&sale_reveal_msg.preimage, &sale.payment_hash)?;
+
+        // Record the preimage first, so that a transfer received later is finalized on receipt
+        sale.preimage = Some(sale_reveal_msg.preimage.to_lowercase());
+        self.database.update_sale(&sale)?;
+
+        if let Some(finalized_data) = &sale.finalize_data {
+            self.transfer_finalize(finalized_data)?;
+            sale.finalized = true;
+            self.database.update_sale(&sale)?;
+            info!("SALE: Preimage revealed. Transfer finalized. State Chain ID: {}", statechain_id);
+        } else {
+            info!("SALE: Preimage revealed. State Chain ID: {}", statechain_id);
+        }
+        Ok(sale_api(&sale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DBErrorType;
+    use crate::protocol::util::tests::test_sc_entity;
+    use crate::structs::StateChainOwner;
+    use shared_lib::state_chain::{sale_payment_hash, StateChain};
+    use std::sync::{Arc, Mutex};
+
+    /// Mock database holding a single sale
+    fn sale_db(shared_key_id: Uuid, statechain_id: Uuid) -> (MockDatabase, Arc<Mutex<Option<SaleRecord>>>) {
+        let sale = Arc::new(Mutex::new(None));
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_| Ok(String::from("user_auth")));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: shared_key_id,
+                chain: StateChain::new(String::from(
+                    "026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e",
+                )),
+            })
+        });
+        let get = sale.clone();
+        db.expect_get_sale()
+            .returning(move |_| Ok(get.lock().unwrap().clone()));
+        let update = sale.clone();
+        db.expect_update_sale().returning(move |s| {
+            *update.lock().unwrap() = Some(s.clone());
+            Ok(())
+        });
+        (db, sale)
+    }
+
+    fn finalize_data(statechain_id: Uuid) -> TransferFinalizeData {
+        let mut finalize_data = TransferFinalizeData::example();
+        finalize_data.statechain_id = statechain_id;
+        finalize_data
+    }
+
+    #[test]
+    #[serial]
+    fn test_sale_init() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let (db, _) = sale_db(shared_key_id, statechain_id);
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        let payment_hash = sale_payment_hash(&hex::encode(&[7; 32])).unwrap();
+
+        let mut msg = SaleInitMsg {
+            shared_key_id,
+            payment_hash: String::from("abcd"),
+            lifetime: 3600,
+        };
+        assert!(sc_entity.sale_init(msg.clone()).is_err());
+        msg.payment_hash = payment_hash.clone();
+        msg.lifetime = sc_entity.config.sale_max_lifetime + 1;
+        assert!(sc_entity.sale_init(msg.clone()).is_err());
+        msg.lifetime = 3600;
+
+        // Only the owner can open a sale
+        let mut not_owner = msg.clone();
+        not_owner.shared_key_id = Uuid::new_v4();
+        match sc_entity.sale_init(not_owner) {
+            Err(SEError::Generic(e)) => assert!(e.contains("not owned")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        let sale = sc_entity.sale_init(msg.clone()).unwrap();
+        assert_eq!(sale.statechain_id, statechain_id);
+        assert_eq!(sale.status, SaleStatus::Open);
+        assert_eq!(sale.payment_hash, payment_hash);
+        assert_eq!(sc_entity.sale_get(statechain_id).unwrap(), sale);
+        assert!(sc_entity.has_open_sale(&statechain_id).unwrap());
+
+        match sc_entity.sale_init(msg) {
+            Err(SEError::Generic(e)) => assert!(e.contains("already has an open sale")),
+            _ => assert!(false, "expected Generic error"),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_sale_hold_and_reveal() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let (mut db, sale) = sale_db(shared_key_id, statechain_id);
+        // Finalization of the held transfer fails at the first DB call
+        db.expect_get_statechain_version().times(1).returning(move |_| {
+            Err(SEError::DBError(DBErrorType::NoDataForID, String::from("version")))
+        });
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        let preimage = hex::encode(&[7; 32]);
+
+        sc_entity
+            .sale_init(SaleInitMsg {
+                shared_key_id,
+                payment_hash: sale_payment_hash(&preimage).unwrap(),
+                lifetime: 3600,
+            })
+            .unwrap();
+        assert!(sc_entity.check_sale_not_held(&statechain_id).is_ok());
+
+        // The transfer to the buyer is held
+        let finalize_data = finalize_data(statechain_id);
+        assert!(sc_entity.hold_sale_transfer(&finalize_data).unwrap());
+        let held = sc_entity.sale_get(statechain_id).unwrap();
+        assert_eq!(held.status, SaleStatus::Held);
+        assert_eq!(held.buyer_proof_key, Some(finalize_data.statechain_sig.data.clone()));
+        assert!(sc_entity.check_sale_not_held(&statechain_id).is_err());
+        assert!(sc_entity.hold_sale_transfer(&finalize_data).is_err());
+
+        // Wrong preimage
+        assert!(sc_entity
+            .sale_reveal(SaleRevealMsg {
+                statechain_id,
+                preimage: hex::encode(&[8; 32]),
+            })
+            .is_err());
+        assert_eq!(sale.lock().unwrap().as_ref().unwrap().preimage, None);
+
+        // The preimage is recorded even if finalization fails, and the sale stays held
+        assert!(sc_entity
+            .sale_reveal(SaleRevealMsg {
+                statechain_id,
+                preimage: preimage.clone(),
+            })
+            .is_err());
+        let held = sc_entity.sale_get(statechain_id).unwrap();
+        assert_eq!(held.status, SaleStatus::Held);
+        assert_eq!(held.preimage, Some(preimage));
+    }
+
+    #[test]
+    #[serial]
+    fn test_sale_expired() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let (db, sale) = sale_db(shared_key_id, statechain_id);
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        let preimage = hex::encode(&[7; 32]);
+        *sale.lock().unwrap() = Some(SaleRecord {
+            statechain_id,
+            payment_hash: sale_payment_hash(&preimage).unwrap(),
+            expires: Utc::now().naive_utc() - Duration::seconds(1),
+            finalize_data: Some(finalize_data(statechain_id)),
+            preimage: None,
+            finalized: false,
+        });
+
+        // An expired held transfer no longer blocks the seller and is not finalized
+        assert_eq!(sc_entity.sale_get(statechain_id).unwrap().status, SaleStatus::Expired);
+        assert!(sc_entity.check_sale_not_held(&statechain_id).is_ok());
+        assert!(!sc_entity.has_open_sale(&statechain_id).unwrap());
+        assert!(!sc_entity.hold_sale_transfer(&finalize_data(statechain_id)).unwrap());
+        match sc_entity.sale_reveal(SaleRevealMsg {
+            statechain_id,
+            preimage,
+        }) {
+            Err(SEError::Generic(e)) => assert!(e.contains("expired")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        // A new sale can be opened
+        let sale = sc_entity
+            .sale_init(SaleInitMsg {
+                shared_key_id,
+                payment_hash: sale_payment_hash(&hex::encode(&[9; 32])).unwrap(),
+                lifetime: 60,
+            })
+            .unwrap();
+        assert_eq!(sale.status, SaleStatus::Open);
+        assert_eq!(sale.buyer_proof_key, None);
+    }
+}
@@ -0,0 +1,332 @@
+//! StateEntity Second Factor
+//!
+//! Two-factor authorization of transfers, withdrawals and migrations.
+//!
+//! The owner of a statecoin can register a second factor key, e.g. held on a phone. While it
+//! is registered, transfer/sender, withdraw/init and migrate/init require a signature of the
+//! second factor key over the owner's statechain sig, in addition to the statechain sig itself.
+//! The key can be replaced or removed at once with a signature of the current key. Without it,
+//! e.g. after losing the second factor, the change only takes effect after the recovery delay,
+//! so that a stolen proof key alone cannot remove the second factor before the owner can
+//! react. A registration only applies while the proof key it was registered by is the
+//! statechain tip, so the receiver of a transfer starts without a second factor.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::server::StateChainEntity;
+use crate::structs::SecondFactorRecord;
+use crate::Database;
+use shared_lib::state_chain::{is_locked, StateChainSig};
+use shared_lib::structs::{SecondFactorAPI, SecondFactorMsg};
+
+use bitcoin::secp256k1::PublicKey;
+use cfg_if::cfg_if;
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::str::FromStr;
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Registration with its pending change applied if the recovery delay has passed at time now
+pub fn second_factor_at(second_factor: &SecondFactorRecord, now: &NaiveDateTime) -> SecondFactorRecord {
+    let mut second_factor = second_factor.clone();
+    if let Some(effective) = second_factor.pending_effective {
+        if effective <= *now {
+            second_factor.auth_key = second_factor.pending_auth_key.take();
+            second_factor.pending_effective = None;
+        }
+    }
+    second_factor
+}
+
+fn second_factor_api(second_factor: &SecondFactorRecord) -> SecondFactorAPI {
+    SecondFactorAPI {
+        statechain_id: second_factor.statechain_id,
+        auth_key: second_factor.auth_key.clone(),
+        pending_auth_key: second_factor.pending_auth_key.clone(),
+        pending_effective: second_factor.pending_effective,
+    }
+}
+
+/// StateChain Entity second factor API calls
+pub trait SecondFactor {
+    /// API: Register, replace or remove the second factor key of a statecoin owned by the
+    /// caller. Changes not signed by the current second factor take effect after the recovery
+    /// delay.
+    fn second_factor_register(&self, second_factor_msg: SecondFactorMsg) -> Result<SecondFactorAPI>;
+
+    /// API: Get the second factor key registration of a statechain
+    fn second_factor_get(&self, statechain_id: Uuid) -> Result<SecondFactorAPI>;
+}
+
+impl SCE {
+    /// Second factor registration of a statechain at time now. An empty registration if none
+    /// applies to the current owner proof key.
+    fn get_current_second_factor(
+        &self,
+        statechain_id: &Uuid,
+        proof_key: &String,
+        now: &NaiveDateTime,
+    ) -> Result<SecondFactorRecord> {
+        match self.database.get_second_factor(statechain_id)? {
+            Some(s) if &s.proof_key == proof_key => Ok(second_factor_at(&s, now)),
+            _ => Ok(SecondFactorRecord {
+                statechain_id: *statechain_id,
+                proof_key: proof_key.clone(),
+                auth_key: None,
+                pending_auth_key: None,
+                pending_effective: None,
+            }),
+        }
+    }
+
+    /// Err if the statechain has a second factor key and auth_sig is not its signature over
+    /// the owner's statechain_sig. proof_key is the current owner proof key.
+    pub fn check_second_factor(
+        &self,
+        statechain_id: &Uuid,
+        proof_key: &String,
+        statechain_sig: &StateChainSig,
+        auth_sig: &Option<StateChainSig>,
+    ) -> Result<()> {
+        let second_factor =
+            self.get_current_second_factor(statechain_id, proof_key, &Utc::now().naive_utc())?;
+        let auth_key = match &second_factor.auth_key {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+        match auth_sig {
+            Some(sig) if sig.is_second_factor(statechain_sig) => sig.verify(auth_key)?,
+            _ => {
+                return Err(SEError::Generic(format!(
+                    "Statechain {} requires a second factor signature",
+                    statechain_id
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SecondFactor for SCE {
+    fn second_factor_register(&self, second_factor_msg: SecondFactorMsg) -> Result<SecondFactorAPI> {
+        let user_id = second_factor_msg.shared_key_id;
+        self.check_user_auth(&user_id)?;
+        if let Some(auth_key) = &second_factor_msg.auth_key {
+            PublicKey::from_str(auth_key)
+                .map_err(|e| SEError::Generic(format!("Invalid second factor key: {}", e)))?;
+        }
+
+        let statechain_id = self.database.get_statechain_id(user_id)?;
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        is_locked(sco.locked_until)?;
+        if sco.owner_id != user_id {
+            return Err(SEError::Generic(format!(
+                "State Chain not owned by User ID: {}.",
+                user_id
+            )));
+        }
+        let proof_key = sco.chain.get_tip().data.clone();
+        let statechain_sig = &second_factor_msg.statechain_sig;
+        if !statechain_sig.is_second_factor_key(&second_factor_msg.auth_key, &statechain_id) {
+            return Err(SEError::Generic(format!(
+                "Statechain {} sig does not sign the second factor key",
+                statechain_id
+            )));
+        }
+        statechain_sig.verify(&proof_key)?;
+
+        let now = Utc::now().naive_utc();
+        let mut second_factor = self.get_current_second_factor(&statechain_id, &proof_key, &now)?;
+        let immediate = match &second_factor.auth_key {
+            None => true,
+            Some(current) => match &second_factor_msg.auth_sig {
+                Some(sig) if sig.is_second_factor(statechain_sig) => {
+                    sig.verify(current)?;
+                    true
+                }
+                Some(_) => {
+                    return Err(SEError::Generic(format!(
+                        "Statechain {} second factor signature does not sign the change",
+                        statechain_id
+                    )))
+                }
+                None => false,
+            },
+        };
+
+        if immediate {
+            second_factor.auth_key = second_factor_msg.auth_key;
+            second_factor.pending_auth_key = None;
+            second_factor.pending_effective = None;
+            info!(
+                "SECOND FACTOR: Key {}. State Chain ID: {}",
+                if second_factor.auth_key.is_some() { "registered" } else { "removed" },
+                statechain_id
+            );
+        } else {
+            second_factor.pending_auth_key = second_factor_msg.auth_key;
+            second_factor.pending_effective =
+                Some(now + Duration::seconds(self.config.second_factor_delay as i64));
+            warn!(
+                "SECOND FACTOR: Change pending until {:?}. State Chain ID: {}",
+                second_factor.pending_effective, statechain_id
+            );
+        }
+        self.database.update_second_factor(&second_factor)?;
+        Ok(second_factor_api(&second_factor))
+    }
+
+    fn second_factor_get(&self, statechain_id: Uuid) -> Result<SecondFactorAPI> {
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        let second_factor = self.get_current_second_factor(
+            &statechain_id,
+            &sco.chain.get_tip().data,
+            &Utc::now().naive_utc(),
+        )?;
+        Ok(second_factor_api(&second_factor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use crate::structs::StateChainOwner;
+    use bitcoin::secp256k1::key::SecretKey;
+    use bitcoin::secp256k1::Secp256k1;
+    use shared_lib::state_chain::StateChain;
+    use std::sync::{Arc, Mutex};
+
+    /// Mock database holding a single second factor registration
+    fn second_factor_db(
+        shared_key_id: Uuid,
+        statechain_id: Uuid,
+        proof_key: String,
+    ) -> (MockDatabase, Arc<Mutex<Option<SecondFactorRecord>>>) {
+        let second_factor = Arc::new(Mutex::new(None));
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_| Ok(String::from("user_auth")));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: shared_key_id,
+                chain: StateChain::new(proof_key.clone()),
+            })
+        });
+        let get = second_factor.clone();
+        db.expect_get_second_factor()
+            .returning(move |_| Ok(get.lock().unwrap().clone()));
+        let update = second_factor.clone();
+        db.expect_update_second_factor().returning(move |s| {
+            *update.lock().unwrap() = Some(s.clone());
+            Ok(())
+        });
+        (db, second_factor)
+    }
+
+    fn pubkey(key: &SecretKey) -> String {
+        PublicKey::from_secret_key(&Secp256k1::new(), key).to_string()
+    }
+
+    #[test]
+    #[serial]
+    fn test_second_factor() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let auth_priv = SecretKey::from_slice(&[3; 32]).unwrap();
+        let new_auth_priv = SecretKey::from_slice(&[4; 32]).unwrap();
+        let proof_key = pubkey(&owner_priv);
+        let (db, second_factor) = second_factor_db(shared_key_id, statechain_id, proof_key.clone());
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        let msg = |auth_key: Option<String>, auth_sig_key: Option<&SecretKey>| {
+            let statechain_sig =
+                StateChainSig::new_second_factor_key_sig(&owner_priv, &auth_key, &statechain_id)
+                    .unwrap();
+            let auth_sig = auth_sig_key
+                .map(|k| StateChainSig::new_second_factor_sig(k, &statechain_sig).unwrap());
+            SecondFactorMsg {
+                shared_key_id,
+                auth_key,
+                statechain_sig,
+                auth_sig,
+            }
+        };
+        let transfer_sig =
+            StateChainSig::new(&owner_priv, &String::from("TRANSFER"), &proof_key).unwrap();
+
+        // No second factor
+        assert!(sc_entity
+            .check_second_factor(&statechain_id, &proof_key, &transfer_sig, &None)
+            .is_ok());
+
+        // First registration takes effect immediately
+        let registered = sc_entity
+            .second_factor_register(msg(Some(pubkey(&auth_priv)), None))
+            .unwrap();
+        assert_eq!(registered.auth_key, Some(pubkey(&auth_priv)));
+        assert_eq!(registered.pending_effective, None);
+
+        // Operations require the second factor signature over the statechain sig
+        assert!(sc_entity
+            .check_second_factor(&statechain_id, &proof_key, &transfer_sig, &None)
+            .is_err());
+        let auth_sig = StateChainSig::new_second_factor_sig(&auth_priv, &transfer_sig).unwrap();
+        assert!(sc_entity
+            .check_second_factor(&statechain_id, &proof_key, &transfer_sig, &Some(auth_sig))
+            .is_ok());
+        let wrong_key_sig = StateChainSig::new_second_factor_sig(&owner_priv, &transfer_sig).unwrap();
+        assert!(sc_entity
+            .check_second_factor(&statechain_id, &proof_key, &transfer_sig, &Some(wrong_key_sig))
+            .is_err());
+        // A registration by a previous owner does not apply
+        assert!(sc_entity
+            .check_second_factor(&statechain_id, &pubkey(&new_auth_priv), &transfer_sig, &None)
+            .is_ok());
+
+        // Removal without the second factor is delayed
+        let pending = sc_entity.second_factor_register(msg(None, None)).unwrap();
+        assert_eq!(pending.auth_key, Some(pubkey(&auth_priv)));
+        assert_eq!(pending.pending_auth_key, None);
+        assert!(pending.pending_effective.unwrap() > Utc::now().naive_utc());
+        assert!(sc_entity
+            .check_second_factor(&statechain_id, &proof_key, &transfer_sig, &None)
+            .is_err());
+
+        // A change signed by the second factor takes effect immediately, cancelling the
+        // pending removal
+        let replaced = sc_entity
+            .second_factor_register(msg(Some(pubkey(&new_auth_priv)), Some(&auth_priv)))
+            .unwrap();
+        assert_eq!(replaced.auth_key, Some(pubkey(&new_auth_priv)));
+        assert_eq!(replaced.pending_effective, None);
+        assert!(sc_entity
+            .second_factor_register(msg(None, Some(&auth_priv)))
+            .is_err());
+
+        // The pending change applies once the recovery delay has passed
+        sc_entity.second_factor_register(msg(None, None)).unwrap();
+        second_factor.lock().unwrap().as_mut().unwrap().pending_effective =
+            Some(Utc::now().naive_utc() - Duration::seconds(1));
+        assert_eq!(sc_entity.second_factor_get(statechain_id).unwrap().auth_key, None);
+        assert!(sc_entity
+            .check_second_factor(&statechain_id, &proof_key, &transfer_sig, &None)
+            .is_ok());
+    }
+}
@@ -0,0 +1,349 @@
+//! StateEntity Session
+//!
+//! Protocol state machine of user sessions.
+//!
+//! Each route that acts on a user session is an event of the state machine. The event is
+//! validated against the stored session state on route entry and the resulting state is
+//! stored once the route has completed, so that protocol messages are only accepted in the
+//! order of the protocol that created the session.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::server::StateChainEntity;
+use crate::structs::SessionState;
+use crate::Database;
+use shared_lib::structs::Protocol;
+
+use cfg_if::cfg_if;
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Protocol message received for a user session
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionEvent {
+    KeyGenFirst,
+    KeyGenSecond,
+    KeyRefresh,
+    RefreshFirst,
+    RefreshSecond,
+    PrepareSign(Protocol),
+    SignFirst,
+    SignSecond(Protocol),
+    DepositConfirm,
+    DepositHandoff,
+    DepositRefund,
+    TransferSender,
+    TransferUpdateMsg,
+    TransferReceiver,
+    TransferReject,
+    WithdrawInit,
+    WithdrawConfirm,
+    WithdrawAccelerate,
+    MigrateInit,
+    MigrateExport,
+    RotateProofKey,
+}
+
+/// Session state after an event, or an error if the event is not valid in the state
+pub fn transition(state: SessionState, event: SessionEvent) -> Result<SessionState> {
+    use SessionEvent::*;
+    use SessionState::*;
+
+    let next = match (state, event) {
+        // Key generation
+        (DepositInit, KeyGenFirst) | (TransferInit, KeyGenFirst) | (RefreshInit, KeyGenFirst) => {
+            Some(state)
+        }
+        (DepositInit, KeyGenSecond) => Some(DepositKeyGen),
+        (TransferInit, KeyGenSecond) | (RefreshInit, KeyGenSecond) => Some(Owner),
+        (Owner, KeyRefresh) | (Transfer, KeyRefresh) => Some(RefreshInit),
+
+        // Key share refresh
+        (Owner, RefreshFirst) => Some(Owner),
+        (Owner, RefreshSecond) => Some(RefreshInit),
+
+        // Deposit
+        (DepositKeyGen, PrepareSign(Protocol::Deposit)) | (DepositKeyGen, SignFirst) => Some(state),
+        (DepositKeyGen, SignSecond(Protocol::Deposit)) => Some(DepositSigned),
+        // Replacement backup tx before deposit/confirm
+        (DepositSigned, PrepareSign(Protocol::Deposit))
+        | (DepositSigned, SignFirst)
+        | (DepositSigned, SignSecond(Protocol::Deposit)) => Some(state),
+        (DepositSigned, DepositConfirm) => Some(Owner),
+        (Owner, DepositHandoff) => Some(Owner),
+
+        // Refund of a deposit that was never confirmed
+        (DepositSigned, DepositRefund) | (Refund, DepositRefund) => Some(Refund),
+        (Refund, PrepareSign(Protocol::Refund))
+        | (Refund, SignFirst)
+        | (Refund, SignSecond(Protocol::Refund)) => Some(Refund),
+
+        // Transfer
+        (Owner, TransferSender) | (Transfer, TransferSender) => Some(Transfer),
+        (Transfer, PrepareSign(Protocol::Transfer))
+        | (Transfer, SignFirst)
+        | (Transfer, SignSecond(Protocol::Transfer))
+        | (Transfer, TransferUpdateMsg)
+        | (Transfer, TransferReceiver) => Some(Transfer),
+        // Transfer declined by the receiver returns the statechain to the sender
+        (Transfer, TransferReject) => Some(Owner),
+
+        // Withdraw
+        (Owner, WithdrawInit) | (Transfer, WithdrawInit) | (Withdraw, WithdrawInit) => {
+            Some(Withdraw)
+        }
+        (Withdraw, PrepareSign(Protocol::Withdraw))
+        | (Withdraw, SignFirst)
+        | (Withdraw, SignSecond(Protocol::Withdraw)) => Some(Withdraw),
+        (Withdraw, WithdrawConfirm) => Some(Withdrawn),
+        // CPFP of the withdraw tx
+        (Withdrawn, WithdrawAccelerate) => Some(Withdrawn),
+
+        // Migrate
+        (Owner, MigrateInit) | (Transfer, MigrateInit) | (Migrate, MigrateInit) => Some(Migrate),
+        (Migrate, MigrateExport) => Some(Migrated),
+
+        // Proof key rotation
+        (Owner, RotateProofKey) => Some(Owner),
+
+        _ => None,
+    };
+
+    next.ok_or(SEError::SessionStateError(format!(
+        "{:?} not valid in session state {:?}",
+        event, state
+    )))
+}
+
+impl SCE {
+    /// Validate an event against the state of a user session. Returns the state to store
+    /// once the event has been processed, or None if the state is unchanged. Sessions created
+    /// before the session state was recorded are not validated.
+    pub fn check_session_event(
+        &self,
+        user_id: &Uuid,
+        event: SessionEvent,
+    ) -> Result<Option<SessionState>> {
+        let state = match self.database.get_session_state(user_id)? {
+            Some(s) => s,
+            None => {
+                debug!("SESSION: No session state recorded. Shared Key ID: {}", user_id);
+                return Ok(None);
+            }
+        };
+        let next = transition(state, event).map_err(|e| {
+            warn!("SESSION: Shared Key ID: {}. {}", user_id, e);
+            e
+        })?;
+        if next == state {
+            return Ok(None);
+        }
+        Ok(Some(next))
+    }
+
+    /// Store the session state returned by check_session_event
+    pub fn update_session_state(&self, user_id: &Uuid, state: Option<SessionState>) -> Result<()> {
+        if let Some(s) = state {
+            debug!("SESSION: Shared Key ID: {}. State: {:?}", user_id, s);
+            self.database.update_session_state(user_id, &s)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+
+    #[test]
+    fn test_deposit_transitions() {
+        let mut state = SessionState::DepositInit;
+        for event in vec![
+            SessionEvent::KeyGenFirst,
+            SessionEvent::KeyGenSecond,
+            SessionEvent::PrepareSign(Protocol::Deposit),
+            SessionEvent::SignFirst,
+            SessionEvent::SignSecond(Protocol::Deposit),
+            SessionEvent::DepositConfirm,
+        ] {
+            state = transition(state, event).unwrap();
+        }
+        assert_eq!(state, SessionState::Owner);
+    }
+
+    #[test]
+    fn test_transfer_transitions() {
+        let mut state = SessionState::Owner;
+        for event in vec![
+            SessionEvent::TransferSender,
+            SessionEvent::PrepareSign(Protocol::Transfer),
+            SessionEvent::SignFirst,
+            SessionEvent::SignSecond(Protocol::Transfer),
+            SessionEvent::TransferUpdateMsg,
+            SessionEvent::TransferReceiver,
+        ] {
+            state = transition(state, event).unwrap();
+        }
+        assert_eq!(state, SessionState::Transfer);
+
+        // Transfer rejected by the receiver
+        assert_eq!(
+            transition(state, SessionEvent::TransferReject).unwrap(),
+            SessionState::Owner
+        );
+        assert!(transition(SessionState::Owner, SessionEvent::TransferReject).is_err());
+
+        // Receiver session
+        let state = transition(SessionState::TransferInit, SessionEvent::KeyGenSecond).unwrap();
+        assert_eq!(state, SessionState::Owner);
+    }
+
+    #[test]
+    fn test_share_refresh_transitions() {
+        let mut state = SessionState::Owner;
+        for event in vec![
+            SessionEvent::RefreshFirst,
+            SessionEvent::RefreshSecond,
+            SessionEvent::KeyGenFirst,
+            SessionEvent::KeyGenSecond,
+        ] {
+            state = transition(state, event).unwrap();
+        }
+        assert_eq!(state, SessionState::Owner);
+
+        // No refresh during a transfer or withdrawal
+        for state in vec![SessionState::Transfer, SessionState::Withdraw] {
+            assert!(transition(state, SessionEvent::RefreshFirst).is_err());
+            assert!(transition(state, SessionEvent::RefreshSecond).is_err());
+        }
+    }
+
+    #[test]
+    fn test_rotate_transitions() {
+        assert_eq!(
+            transition(SessionState::Owner, SessionEvent::RotateProofKey).unwrap(),
+            SessionState::Owner
+        );
+        // No rotation during a transfer, withdrawal or migration, or once the session is closed
+        for state in vec![
+            SessionState::Transfer,
+            SessionState::Withdraw,
+            SessionState::Migrate,
+            SessionState::Transferred,
+            SessionState::Withdrawn,
+        ] {
+            assert!(transition(state, SessionEvent::RotateProofKey).is_err());
+        }
+    }
+
+    #[test]
+    fn test_refund_transitions() {
+        let mut state = SessionState::DepositSigned;
+        for event in vec![
+            SessionEvent::DepositRefund,
+            SessionEvent::PrepareSign(Protocol::Refund),
+            SessionEvent::SignFirst,
+            SessionEvent::SignSecond(Protocol::Refund),
+        ] {
+            state = transition(state, event).unwrap();
+        }
+        assert_eq!(state, SessionState::Refund);
+
+        // Refunded deposits cannot be confirmed
+        assert!(transition(SessionState::Refund, SessionEvent::DepositConfirm).is_err());
+        // Only deposits with a co-signed backup tx and no statechain can be refunded
+        for state in vec![SessionState::DepositInit, SessionState::DepositKeyGen, SessionState::Owner] {
+            assert!(transition(state, SessionEvent::DepositRefund).is_err());
+        }
+    }
+
+    #[test]
+    fn test_out_of_order_events() {
+        // Deposit confirmed before the backup tx is co-signed
+        for state in vec![SessionState::DepositInit, SessionState::DepositKeyGen] {
+            match transition(state, SessionEvent::DepositConfirm) {
+                Ok(_) => assert!(false, "Expected failure."),
+                Err(e) => assert!(e.to_string().contains("DepositConfirm not valid")),
+            }
+        }
+        // Signing before key generation
+        assert!(transition(SessionState::DepositInit, SessionEvent::SignFirst).is_err());
+        // Withdraw tx prepared without withdraw/init
+        assert!(transition(
+            SessionState::Owner,
+            SessionEvent::PrepareSign(Protocol::Withdraw)
+        )
+        .is_err());
+        // Protocol of the tx to sign must match the session
+        assert!(transition(
+            SessionState::Transfer,
+            SessionEvent::SignSecond(Protocol::Withdraw)
+        )
+        .is_err());
+        // Transfer after withdrawal authorised
+        assert!(transition(SessionState::Withdraw, SessionEvent::TransferSender).is_err());
+        // Withdraw tx accelerated before withdraw/confirm
+        assert!(transition(SessionState::Withdraw, SessionEvent::WithdrawAccelerate).is_err());
+        assert_eq!(
+            transition(SessionState::Withdrawn, SessionEvent::WithdrawAccelerate).unwrap(),
+            SessionState::Withdrawn
+        );
+        // Key generation repeated after key generation completed
+        assert!(transition(SessionState::Owner, SessionEvent::KeyGenFirst).is_err());
+        // Closed sessions
+        for state in vec![
+            SessionState::Transferred,
+            SessionState::Withdrawn,
+            SessionState::Migrated,
+        ] {
+            assert!(transition(state, SessionEvent::TransferSender).is_err());
+            assert!(transition(state, SessionEvent::WithdrawInit).is_err());
+            assert!(transition(state, SessionEvent::SignFirst).is_err());
+        }
+    }
+
+    #[test]
+    fn test_check_session_event() {
+        let user_id = Uuid::new_v4();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_session_state()
+            .times(1)
+            .returning(|_| Ok(Some(SessionState::DepositKeyGen)));
+        db.expect_get_session_state()
+            .times(1)
+            .returning(|_| Ok(Some(SessionState::DepositSigned)));
+        db.expect_get_session_state().returning(|_| Ok(None));
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        match sc_entity.check_session_event(&user_id, SessionEvent::DepositConfirm) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("Session State Error")),
+        }
+        assert_eq!(
+            sc_entity
+                .check_session_event(&user_id, SessionEvent::DepositConfirm)
+                .unwrap(),
+            Some(SessionState::Owner)
+        );
+        // No state recorded
+        assert_eq!(
+            sc_entity
+                .check_session_event(&user_id, SessionEvent::DepositConfirm)
+                .unwrap(),
+            None
+        );
+    }
+}
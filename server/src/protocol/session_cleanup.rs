@@ -0,0 +1,152 @@
+//! StateEntity Session Cleanup
+//!
+//! Removal of orphaned user sessions.
+//!
+//! A user session is created by deposit/init before the deposit is funded. Sessions that never
+//! complete key generation, co-signing of the backup tx or deposit/confirm are removed with
+//! their keygen data once they have stayed in their state for longer than its time to live.
+//! Sessions with a statechain are never removed.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::protocol::ping::Ping;
+use crate::server::{StateChainEntity, ORPHANED_SESSIONS_REMOVED, ORPHANED_SESSION_ROWS_REMOVED};
+use crate::structs::SessionState;
+use crate::Database;
+
+use cfg_if::cfg_if;
+use chrono::{Duration, Utc};
+use std::{thread, time};
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Maximum number of sessions removed per DB transaction
+const CLEANUP_BATCH_SIZE: i64 = 1000;
+
+impl SCE {
+    /// Session states that are cleaned up, with their time to live in seconds
+    fn orphaned_session_ttls(&self) -> Vec<(SessionState, u64)> {
+        let config = &self.config.session_cleanup;
+        vec![
+            (SessionState::DepositInit, config.deposit_init_ttl),
+            (SessionState::DepositKeyGen, config.deposit_keygen_ttl),
+            (SessionState::DepositSigned, config.deposit_signed_ttl),
+        ]
+        .into_iter()
+        .filter(|(_, ttl)| *ttl > 0)
+        .collect()
+    }
+
+    /// Remove deposit sessions left in a state for longer than its time to live, with their
+    /// related rows. Returns the number of sessions removed.
+    pub fn cleanup_orphaned_sessions(&self) -> Result<usize> {
+        let now = Utc::now().naive_utc();
+        let mut count = 0;
+        for (state, ttl) in self.orphaned_session_ttls() {
+            let updated_before = now - Duration::seconds(ttl as i64);
+            loop {
+                let (user_ids, related) = self.database.remove_orphaned_sessions(
+                    &state,
+                    &updated_before,
+                    CLEANUP_BATCH_SIZE,
+                )?;
+                let mut guard = self.user_ids.as_ref().lock()?;
+                for user_id in &user_ids {
+                    guard.remove(user_id);
+                }
+                drop(guard);
+                ORPHANED_SESSIONS_REMOVED
+                    .with_label_values(&[&format!("{:?}", state)])
+                    .inc_by(user_ids.len() as u64);
+                ORPHANED_SESSION_ROWS_REMOVED.inc_by(related);
+                count += user_ids.len();
+                if (user_ids.len() as i64) < CLEANUP_BATCH_SIZE {
+                    break;
+                }
+            }
+        }
+        if count > 0 {
+            info!("SESSION CLEANUP: Removed {} orphaned user sessions.", count);
+        }
+        Ok(count)
+    }
+}
+
+/// Run orphaned session cleanup on a handle sharing the state entity, if the cleanup interval
+/// is non-zero
+pub fn spawn_session_cleanup(sc_entity: &SCE) -> Result<Option<thread::JoinHandle<()>>> {
+    let interval = sc_entity.config.session_cleanup.interval;
+    if interval == 0 {
+        return Ok(None);
+    }
+    let sc_entity = sc_entity.share()?;
+    Ok(Some(thread::spawn(move || loop {
+        if let Err(e) = sc_entity.cleanup_orphaned_sessions() {
+            warn!("SESSION CLEANUP: Cleanup failed: {}", e);
+        }
+        let _ = sc_entity.heartbeat("session_cleanup", time::Duration::from_secs(interval));
+        thread::sleep(time::Duration::from_secs(interval));
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use mockall::predicate;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_cleanup_orphaned_sessions() {
+        let orphaned_id = Uuid::new_v4();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_remove_orphaned_sessions()
+            .with(
+                predicate::eq(SessionState::DepositInit),
+                predicate::function(|updated_before: &chrono::NaiveDateTime| {
+                    let age = Utc::now().naive_utc() - *updated_before;
+                    age >= Duration::seconds(600) && age < Duration::seconds(660)
+                }),
+                predicate::eq(CLEANUP_BATCH_SIZE),
+            )
+            .times(1)
+            .returning(move |_, _, _| Ok((vec![orphaned_id], 3)));
+        db.expect_remove_orphaned_sessions()
+            .with(
+                predicate::eq(SessionState::DepositKeyGen),
+                predicate::always(),
+                predicate::always(),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok((vec![], 0)));
+        // A zero time to live disables the cleanup of a state
+        db.expect_remove_orphaned_sessions()
+            .with(
+                predicate::eq(SessionState::DepositSigned),
+                predicate::always(),
+                predicate::always(),
+            )
+            .times(0);
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.session_cleanup.deposit_init_ttl = 600;
+        sc_entity.config.session_cleanup.deposit_keygen_ttl = 3600;
+        sc_entity.config.session_cleanup.deposit_signed_ttl = 0;
+        sc_entity.user_ids.lock().unwrap().insert(orphaned_id);
+
+        let removed_before = ORPHANED_SESSION_ROWS_REMOVED.get();
+        assert_eq!(sc_entity.cleanup_orphaned_sessions().unwrap(), 1);
+        assert!(!sc_entity.user_ids.lock().unwrap().contains(&orphaned_id));
+        assert!(ORPHANED_SESSION_ROWS_REMOVED.get() >= removed_before + 3);
+    }
+}
@@ -6,33 +6,30 @@ pub use super::super::Result;
 extern crate shared_lib;
 extern crate reqwest;
 use crate::server::TRANSFERS_COUNT;
-use super::transfer_batch::transfer_batch_is_ended;
 use shared_lib::{ecies, ecies::WalletDecryptable, ecies::SelfEncryptable, 
     state_chain::*, structs::*, util::transaction_deserialise};
 use bitcoin::secp256k1::key::SecretKey;
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
 use bitcoin::util::key::PrivateKey;
 use bitcoin::network::constants::Network;
-use crate::structs::ECDSAKeypair;
+use chrono::Utc;
+use crate::structs::{ECDSAKeypair, SessionState};
 
 
 use crate::error::SEError;
 use crate::Database;
 use crate::{server::StateChainEntity, storage::Storage};
 use super::requests::post_lb;
-use rocket_okapi::openapi;
 
 use cfg_if::cfg_if;
 use curv::{
     elliptic::curves::traits::{ECPoint, ECScalar},
     {FE, GE},
 };
-use rocket::State;
-use rocket_contrib::json::Json;
 use std::{str::FromStr, convert::TryInto};
 use uuid::Uuid;
 use url::Url;
-use crate::protocol::{util::{Utilities, RateLimiter}, withdraw::Withdraw};
+use crate::protocol::{session::SessionEvent, util::Utilities, withdraw::Withdraw};
 
 
 cfg_if! {
@@ -41,11 +38,32 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
     }
 }
 
+/// Compute the new server key share s2 = t2 * x1^-1 * s1 and its public key, checking that the
+/// shared public key is unchanged (o1_pub * s1 == o2_pub * s2).
+pub fn transfer_key_update(s1: &FE, x1: &FE, t2: &FE, o1_pub: &GE, o2_pub: &GE) -> Result<(FE, GE)> {
+    let s2 = *t2 * (x1.invert()) * *s1;
+
+    let g: GE = ECPoint::generator();
+    let s2_pub = g * s2;
+
+    let p1_pub = *o1_pub * *s1;
+    let p2_pub = *o2_pub * s2;
+
+    // Check P1 = o1_pub*s1 === p2 = o2_pub*s2
+    if p1_pub != p2_pub {
+        error!("TRANSFER: Protocol failed. P1 != P2.");
+        return Err(SEError::Generic(String::from(
+            "Transfer protocol error: P1 != P2",
+        )));
+    }
+    Ok((s2, s2_pub))
+}
+
 impl TryInto<SecretKey> for ECDSAKeypair{
     type Error = SEError;
     fn try_into(self) -> Result<SecretKey> {
@@ -113,6 +131,127 @@ pub trait Transfer {
 
     /// API: Get the transfer message 3 set by update_transfer_msg from the receiver address
     fn transfer_get_msg_addr(&self, receive_addr: String) -> Result<Vec<TransferMsg3>>;
+
+    /// API: Receiver declines a transfer before completing transfer_receiver:
+    ///     - Authorisation of the receiver proof key
+    ///     - Remove the pending transfer and return the statechain to the sender
+    ///     - Record the rejection for the sender
+    fn transfer_reject(&self, transfer_reject_msg: TransferRejectMsg) -> Result<()>;
+
+    /// API: Get the last transfer of a statechain rejected by its receiver
+    fn transfer_get_reject(&self, statechain_id: Uuid) -> Result<Option<TransferRejectAPI>>;
+}
+
+impl SCE {
+    /// Record that the key share of a transferred shared key has been deleted: a receipt
+    /// committing to the key share public key S1 is signed with the identity key, stored and
+    /// written to the audit log. No receipt is issued if no identity key is set.
+    pub fn issue_deletion_receipt(
+        &self,
+        user_id: &Uuid,
+        statechain_id: &Uuid,
+        proof_key: &String,
+    ) -> Result<()> {
+        let identity_key = match self.config.identity_key {
+            Some(_) => self.identity_key()?,
+            None => return Ok(()),
+        };
+        let s1_pub = self.database.get_s1_pubkey(user_id)?;
+        let receipt = DeletionReceipt {
+            statechain_id: *statechain_id,
+            shared_key_id: *user_id,
+            proof_key: proof_key.clone(),
+            commitment: deletion_commitment(&s1_pub),
+            deleted_at: Utc::now().naive_utc(),
+            server_pubkey: PublicKey::from_secret_key(&Secp256k1::new(), &identity_key).to_string(),
+        };
+        let signed = sign_deletion_receipt(&identity_key, receipt)?;
+        self.database.insert_deletion_receipt(&signed)?;
+        info!(
+            target: "audit",
+            "DELETION: Key share deleted. Shared Key ID: {} State Chain ID: {} commitment: {} sig: {}",
+            user_id, statechain_id, signed.receipt.commitment, signed.sig
+        );
+        Ok(())
+    }
+
+    /// Issue the sender of a completed transfer a receipt binding the statechain, the sender and
+    /// receiver proof keys and the SMT root at finalization, signed with the identity key.
+    /// user_id is the shared key ID of the sender. No receipt is issued if no identity key is set.
+    pub fn issue_transfer_receipt(
+        &self,
+        user_id: &Uuid,
+        statechain_id: &Uuid,
+        prev_proof_key: &String,
+        new_proof_key: &String,
+        root_id: i64,
+    ) -> Result<()> {
+        let identity_key = match self.config.identity_key {
+            Some(_) => self.identity_key()?,
+            None => return Ok(()),
+        };
+        let receipt = TransferReceipt {
+            statechain_id: *statechain_id,
+            prev_proof_key: prev_proof_key.clone(),
+            new_proof_key: new_proof_key.clone(),
+            root_id,
+            finalized_at: Utc::now().naive_utc(),
+            server_pubkey: PublicKey::from_secret_key(&Secp256k1::new(), &identity_key).to_string(),
+        };
+        let signed = sign_transfer_receipt(&identity_key, receipt)?;
+        self.database.insert_transfer_receipt(user_id, &signed)?;
+        info!(
+            target: "audit",
+            "TRANSFER: Receipt issued. Shared Key ID: {} State Chain ID: {} root id: {} sig: {}",
+            user_id, statechain_id, root_id, signed.sig
+        );
+        Ok(())
+    }
+
+    /// Prune the oldest states of a statechain into the archive if the stored chain exceeds
+    /// the prune length or size. The pruned segment is stored before the chain is updated: a
+    /// segment whose chain update fails is not linked from any chain and is ignored.
+    pub fn prune_statechain(&self, statechain_id: &Uuid, state_chain: &mut StateChain) -> Result<()> {
+        let limits = &self.config.chain_limits;
+        let over_length =
+            limits.prune_length > 0 && state_chain.get_chain().len() as u64 > limits.prune_length;
+        let over_size =
+            limits.prune_size > 0 && state_chain.serialized_size()? as u64 > limits.prune_size;
+        if !over_length && !over_size {
+            return Ok(());
+        }
+        if let Some(segment) = state_chain.prune(limits.prune_keep as usize)? {
+            self.database.insert_chain_segment(statechain_id, &segment)?;
+            info!(
+                "TRANSFER: Pruned {} states of State Chain ID: {}. Segment hash: {}",
+                segment.states.len(),
+                statechain_id,
+                segment.hash
+            );
+        }
+        Ok(())
+    }
+
+    /// Check a statechain is within the chain length and size limits
+    pub fn check_chain_limits(&self, statechain_id: &Uuid, state_chain: &StateChain) -> Result<()> {
+        let limits = &self.config.chain_limits;
+        if limits.max_length > 0 && state_chain.length() > limits.max_length {
+            return Err(SEError::LimitError(format!(
+                "State Chain ID {} has reached the maximum length of {} states. Withdraw the statecoin and deposit its value to a new statechain.",
+                statechain_id, limits.max_length
+            )));
+        }
+        if limits.max_size > 0 {
+            let size = state_chain.serialized_size()? as u64;
+            if size > limits.max_size {
+                return Err(SEError::LimitError(format!(
+                    "State Chain ID {} size {} bytes exceeds the maximum statechain size of {} bytes. Withdraw the statecoin and deposit its value to a new statechain.",
+                    statechain_id, size, limits.max_size
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Transfer for SCE {
@@ -120,13 +259,20 @@ impl Transfer for SCE {
         self.check_user_auth(&transfer_msg1.shared_key_id)?;
         let user_id = transfer_msg1.shared_key_id;
         debug!("TRANSFER: Sender Side. Shared Key ID: {}", user_id);
+        let next_state = self.check_session_event(&user_id, SessionEvent::TransferSender)?;
 
         if(self.get_if_signed_for_withdrawal(&user_id)?.is_some()) {
             return Err(SEError::Generic(format!("transfer_sender - shared key id: {} is signed for withdrawal", &user_id)));
         }
 
+        // Shared keys generated in an earlier key epoch must be refreshed before transfer
+        if self.config.key_epoch > 0 && self.database.get_ecdsa_key_epoch(user_id)? < self.config.key_epoch {
+            return Err(SEError::KeyRefreshRequired(format!("transfer_sender - shared key id: {}", &user_id)));
+        }
+
         // Get state_chain id
         let statechain_id = self.database.get_statechain_id(user_id)?;
+        self.check_sale_not_held(&statechain_id)?;
 
         // Check that the funding transaction has the required number of confirmations and is valid
         if !self.database.is_confirmed(&statechain_id)? {
@@ -149,8 +295,34 @@ impl Transfer for SCE {
             )));
         }
 
-        // verify statechain sig
-        // TODO
+        // Refuse a transfer that would take the statechain past its length limit
+        let max_length = self.config.chain_limits.max_length;
+        if max_length > 0 && sco.chain.length() >= max_length {
+            return Err(SEError::LimitError(format!(
+                "State Chain ID {} has reached the maximum length of {} states. Withdraw the statecoin and deposit its value to a new statechain.",
+                statechain_id, max_length
+            )));
+        }
+
+        // Check the statechain sig is bound to this statechain and the current tip. The
+        // signature itself is verified when it is added to the statechain on finalization.
+        transfer_msg1.statechain_sig.check_binding(
+            &statechain_id,
+            sco.chain.tip_position(),
+            self.config.require_bound_sigs,
+        )?;
+        self.check_second_factor(
+            &statechain_id,
+            &sco.chain.get_tip().data,
+            &transfer_msg1.statechain_sig,
+            &transfer_msg1.auth_sig,
+        )?;
+        self.vault_start(
+            &statechain_id,
+            &sco.chain.get_tip().data,
+            &transfer_msg1.statechain_sig,
+            transfer_msg1.batch_id.is_some(),
+        )?;
 
         // Generate x1
         let x1: FE = ECScalar::new_random();
@@ -158,6 +330,7 @@ impl Transfer for SCE {
 
         self.database
             .create_transfer(&statechain_id, &transfer_msg1.statechain_sig, &x1, transfer_msg1.batch_id)?;
+        self.update_session_state(&user_id, next_state)?;
 
         info!(
             "TRANSFER: Sender side complete. Previous shared key ID: {}. State Chain ID: {}",
@@ -201,6 +374,9 @@ impl Transfer for SCE {
         
         let user_id = transfer_msg4.shared_key_id;
         let statechain_id = transfer_msg4.statechain_id;
+        self.check_session_event(&user_id, SessionEvent::TransferReceiver)?;
+
+        let sc_version = self.database.get_statechain_version(&statechain_id)?;
 
         // Get Transfer Data for statechain_id
         let td = self.database.get_transfer_data(statechain_id)?;
@@ -213,6 +389,10 @@ impl Transfer for SCE {
             )));
         }
 
+        // The new backup tx must be the backup tx committed to by the statechain signature
+        td.statechain_sig
+            .check_backup_tx(&transaction_deserialise(&transfer_msg4.tx_backup_hex)?)?;
+
         // Check if batch transfer and batch ID matches
         if td.batch_id.is_some() {
             if transfer_msg4.batch_data.is_some() {
@@ -231,6 +411,28 @@ impl Transfer for SCE {
             }
         }
 
+        // A transfer held for sale cannot be part of a batch
+        if transfer_msg4.batch_data.is_some() && self.has_open_sale(&statechain_id)? {
+            return Err(SEError::Generic(format!(
+                "Statechain {} has an open sale and cannot be transferred in a batch",
+                statechain_id
+            )));
+        }
+
+        // A beneficiary can only complete the transfer once the owner has been inactive for the
+        // designated period, and not in a batch
+        let inheritance_claim = self.check_inheritance_claim(&statechain_id, &td.statechain_sig)?;
+        if inheritance_claim && transfer_msg4.batch_data.is_some() {
+            return Err(SEError::Generic(format!(
+                "Statechain {} is claimed by a beneficiary and cannot be transferred in a batch",
+                statechain_id
+            )));
+        }
+
+        // A transfer of a statecoin in vault mode can only be completed once the vault delay
+        // has passed since transfer_sender
+        self.check_vault_delay(&statechain_id, &td.statechain_sig)?;
+
         let s2: FE;
         let s2_pub: GE;
         match &self.get_lockbox_url(&user_id)? {
@@ -275,23 +477,15 @@ impl Transfer for SCE {
                 }
             };
 
-            s2 = t2 * (td.x1.invert()) * s1;
-
-            let g: GE = ECPoint::generator();
-            s2_pub = g * s2;
-
-            let p1_pub = kp.party_2_public * s1;
-            let p2_pub = transfer_msg4.o2_pub * s2;
-
-            // Check P1 = o1_pub*s1 === p2 = o2_pub*s2
-            if p1_pub != p2_pub {
-                error!("TRANSFER: Protocol failed. P1 != P2.");
-                return Err(SEError::Generic(String::from(
-                    "Transfer protocol error: P1 != P2",
-                )));
-            }
+            let (s2_new, s2_pub_new) =
+                transfer_key_update(&s1, &td.x1, &t2, &kp.party_2_public, &transfer_msg4.o2_pub)?;
+            s2 = s2_new;
+            s2_pub = s2_pub_new;
         }}
 
+        // Only one receiver can complete the transfer. Concurrent receivers get a retryable error.
+        self.database.claim_transfer(&statechain_id, td.version)?;
+
         // Create user ID for new UserSession (receiver of transfer)
         let new_shared_key_id = Uuid::new_v4();
 
@@ -314,8 +508,8 @@ impl Transfer for SCE {
             );
 
             // Ensure batch transfer is still active
-            if transfer_batch_is_ended(self.database.get_transfer_batch_start_time(&batch_id)?,
-                                       self.config.batch_lifetime as i64) {
+            let start_time = self.database.get_transfer_batch_start_time(&batch_id)?;
+            if self.transfer_batch_ended(&batch_id, start_time)? {
                 return Err(SEError::TransferBatchEnded(String::from(
                     "Too late to complete transfer.",
                 )));
@@ -324,8 +518,16 @@ impl Transfer for SCE {
             self.database.update_finalize_batch_data(
                 &statechain_id,
                 &finalized_data,
+                sc_version,
             )?;
 
+        // If sold in escrow then hold the transfer until the preimage is revealed
+        } else if self.hold_sale_transfer(&finalized_data)? {
+            debug!(
+                "TRANSFER: Transfer held for sale. State Chain ID: {}",
+                statechain_id
+            );
+
         // If not batch then finalize transfer now
         } else {
             debug!(
@@ -336,6 +538,10 @@ impl Transfer for SCE {
             self.transfer_finalize(&finalized_data)?;
         }
 
+        if inheritance_claim {
+            self.database.remove_inheritance(&statechain_id)?;
+        }
+
         info!(
             "TRANSFER: Receiver side complete. New shared key ID: {}",
             new_shared_key_id
@@ -357,10 +563,19 @@ impl Transfer for SCE {
 
         info!("TRANSFER_FINALIZE: State Chain ID: {}", statechain_id);
 
-        // Update state chain
+        // Update state chain. The update fails if the statechain is updated concurrently.
+        let version = self.database.get_statechain_version(&statechain_id)?;
         let mut state_chain: StateChain = self.database.get_statechain(statechain_id)?;
+        let prev_proof_key = state_chain.get_tip().data.clone();
+
+        // The new state records the backup tx committed to by the statechain signature, which
+        // must be the new backup tx
+        let new_tx_backup_hex = transaction_deserialise(&finalized_data.new_tx_backup_hex)?;
+        finalized_data.statechain_sig.check_backup_tx(&new_tx_backup_hex)?;
 
         state_chain.add(&finalized_data.statechain_sig)?;
+        self.prune_statechain(&statechain_id, &mut state_chain)?;
+        self.check_chain_limits(&statechain_id, &state_chain)?;
 
         let new_user_id = finalized_data.new_shared_key_id;
 
@@ -371,7 +586,10 @@ impl Transfer for SCE {
             &statechain_id,
             state_chain.clone(),
             &new_user_id,
+            version,
         )?;
+        self.database
+            .update_session_state(&sco.owner_id, &SessionState::Transferred)?;
 
         // Create new UserSession to allow new owner to generate shared wallet
 
@@ -394,13 +612,20 @@ impl Transfer for SCE {
                 let _ku_receive: KUAttest = post_lb(&l.0, path, &ku_send)?;
                 self.database.update_lockbox_index(&new_user_id, &l.1)?;
             },
-            None => ()
+            None => self.database.remove_ecdsa(&sco.owner_id)?
         };
-
-        let new_tx_backup_hex = transaction_deserialise(&finalized_data.new_tx_backup_hex)?;
+        self.issue_deletion_receipt(&sco.owner_id, &statechain_id, &prev_proof_key)?;
 
         self.database
             .update_backup_tx(&statechain_id, new_tx_backup_hex.clone())?;
+        self.record_backup_addr(&statechain_id, &new_tx_backup_hex)?;
+        self.database
+            .insert_backup_tx_history(&statechain_id, &new_tx_backup_hex)?;
+
+        // The new backup tx was co-signed in the sender's session. Later spends are checked for
+        // conflicts with it, while backup txs of aborted transfers are not.
+        let sig_hash = self.database.get_sighash(sco.owner_id)?;
+        self.database.set_sighash_finalized(&sco.owner_id, &sig_hash)?;
 
         info!(
             "TRANSFER: Finalized. New shared key ID: {}. State Chain ID: {}",
@@ -408,6 +633,7 @@ impl Transfer for SCE {
         );
 
         // Update sparse merkle tree with new StateChain entry
+        let amount = self.database.get_statechain_amount(statechain_id)?.amount as u64;
         let (prev_root, new_root) = self.update_smt(
             &new_tx_backup_hex
                 .input
@@ -420,6 +646,7 @@ impl Transfer for SCE {
                 .get_tip()
                 .data
                 .clone(),
+            amount,
         )?;
 
         info!(
@@ -431,6 +658,14 @@ impl Transfer for SCE {
             statechain_id, &new_root, &prev_root
         );
 
+        self.issue_transfer_receipt(
+            &sco.owner_id,
+            &statechain_id,
+            &prev_proof_key,
+            &state_chain.get_tip().data,
+            new_root.id().unwrap_or_default(),
+        )?;
+
         // Remove TransferData for this transfer
         self.database.remove_transfer_data(&statechain_id)?;
 
@@ -442,6 +677,7 @@ impl Transfer for SCE {
 
     /// API: Update the state entity database with transfer message 3
     fn transfer_update_msg(&self, transfer_msg3: TransferMsg3) -> Result<()> {
+        self.check_session_event(&transfer_msg3.shared_key_id, SessionEvent::TransferUpdateMsg)?;
         self.database
             .update_transfer_msg(&transfer_msg3.statechain_id, &transfer_msg3)
     }
@@ -455,94 +691,50 @@ impl Transfer for SCE {
     fn transfer_get_msg_addr(&self, receive_addr: String) -> Result<Vec<TransferMsg3>> {
         self.database.get_transfer_msg_addr(&receive_addr)
     }
-}
 
-#[openapi]
-/// # Transfer initiation by sender: get x1 and new backup transaction
-#[post("/transfer/sender", format = "json", data = "<transfer_msg1>")]
-pub fn transfer_sender(
-    sc_entity: State<SCE>,
-    transfer_msg1: Json<TransferMsg1>,
-) -> Result<Json<TransferMsg2>> {
-    sc_entity.check_rate_fast("transfer")?;
-    match sc_entity.transfer_sender(transfer_msg1.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
+    fn transfer_reject(&self, transfer_reject_msg: TransferRejectMsg) -> Result<()> {
+        let statechain_id = transfer_reject_msg.statechain_id;
+        let td = self.database.get_transfer_data(statechain_id)?;
 
-#[openapi]
-/// # Retreive the current SE public key share for t2 encryption
-#[post("/transfer/pubkey", format = "json", data = "<user_id>")]
-pub fn transfer_get_pubkey(
-    sc_entity: State<SCE>,
-    user_id: Json<UserID>,
-) -> Result<Json<S1PubKey>> {
-    sc_entity.check_rate_fast("transfer")?;
-    match sc_entity.transfer_get_pubkey(user_id.id) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
+        // Batch transfers that do not complete are punished rather than returned
+        if td.batch_id.is_some() {
+            return Err(SEError::Generic(format!(
+                "Transfer of statechain {} is part of a batch and cannot be rejected",
+                statechain_id
+            )));
+        }
+        // Transfers held for sale have been completed by the receiver
+        self.check_sale_not_held(&statechain_id)?;
 
-#[openapi]
-/// # Transfer completing by receiver: key share update and deletion
-#[post("/transfer/receiver", format = "json", data = "<transfer_msg4>")]
-pub fn transfer_receiver(
-    sc_entity: State<SCE>,
-    transfer_msg4: Json<TransferMsg4>,
-) -> Result<Json<TransferMsg5>> {
-    sc_entity.check_rate_fast("transfer")?;
-    match sc_entity.transfer_receiver(transfer_msg4.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
+        // Only the receiver, whose proof key the transfer statechain sig signs, can reject
+        let reject = TransferRejectAPI {
+            statechain_id,
+            transfer_sig: td.statechain_sig,
+            reject_sig: transfer_reject_msg.statechain_sig,
+            rejected_at: Utc::now().naive_utc(),
+        };
+        verify_transfer_reject(&reject)?;
 
-#[openapi]
-/// # Update stored transfer message (TransferMsg3)
-#[post("/transfer/update_msg", format = "json", data = "<transfer_msg3>")]
-pub fn transfer_update_msg(
-    sc_entity: State<SCE>,
-    transfer_msg3: Json<TransferMsg3>,
-) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("transfer")?;
-    match sc_entity.transfer_update_msg(transfer_msg3.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        let next_state = self.check_session_event(&sco.owner_id, SessionEvent::TransferReject)?;
+        self.database.remove_transfer_data(&statechain_id)?;
+        self.database.update_transfer_reject(&reject)?;
+        self.update_session_state(&sco.owner_id, next_state)?;
 
-#[openapi]
-/// # Get stored transfer message (TransferMsg3)
-#[post("/transfer/get_msg", format = "json", data = "<statechain_id>")]
-pub fn transfer_get_msg(
-    sc_entity: State<SCE>,
-    statechain_id: Json<StatechainID>,
-) -> Result<Json<TransferMsg3>> {
-    sc_entity.check_rate_fast("transfer")?;
-    match sc_entity.transfer_get_msg(statechain_id.id) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+        info!(
+            "TRANSFER: Rejected by receiver. Shared Key ID: {}. State Chain ID: {}",
+            sco.owner_id, statechain_id
+        );
+        Ok(())
     }
-}
 
-#[openapi]
-/// # Get stored transfer message (TransferMsg3)
-#[get("/transfer/get_msg_addr/<receive_addr>", format = "json")]
-pub fn transfer_get_msg_addr(
-    sc_entity: State<SCE>,
-    receive_addr: String,
-) -> Result<Json<Vec<TransferMsg3>>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.transfer_get_msg_addr(receive_addr) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    fn transfer_get_reject(&self, statechain_id: Uuid) -> Result<Option<TransferRejectAPI>> {
+        self.database.get_transfer_reject(&statechain_id)
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
     use crate::MockDatabase;
     use crate::{
@@ -551,7 +743,10 @@ mod tests {
             mocks,
             tests::{test_sc_entity, BACKUP_TX_NOT_SIGNED},
         },
-        structs::{ECDSAKeypair, StateChainOwner, TransferData, TransferFinalizeBatchData},
+        structs::{
+            ECDSAKeypair, StateChainAmount, StateChainOwner, TransferData,
+            TransferFinalizeBatchData,
+        },
     };
     use chrono::{Duration, Utc};
     use mockall::predicate;
@@ -560,7 +755,7 @@ mod tests {
     use bitcoin::Transaction;
     use crate::shared_lib::util::transaction_serialise;
     use std::convert::TryInto;
-    use crate::structs::WithdrawConfirmData;
+    use crate::structs::{SessionState, WithdrawConfirmData};
     use time_test::time_test;
 
     // Data from a run of transfer protocol.
@@ -589,10 +784,15 @@ mod tests {
         let transfer_msg_1 = TransferMsg1 {
             shared_key_id,
             statechain_sig,
-            batch_id: None
+            batch_id: None,
+            auth_sig: None,
         };
 
         let mut db = MockDatabase::new();
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
+        db.expect_get_inheritance().returning(|_| Ok(None));
         let (_privkey, pubkey) = shared_lib::util::keygen::generate_keypair();
         db.expect_get_proof_key()
             .returning(move |_| Ok(pubkey.to_string()));
@@ -655,6 +855,12 @@ mod tests {
         db.expect_create_transfer().returning(|_, _, _, _| Ok(()));
         db.expect_update_transfer_msg().returning(|_, _| Ok(()));
         db.expect_set_confirmed().returning(|_| Ok(()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Owner)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Transfer)
+            .times(1)
+            .returning(|_, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
 
@@ -700,6 +906,10 @@ mod tests {
         let x1 = msg2.x1.get_fe().expect("failed to get fe");
 
         let mut db = MockDatabase::new();
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
+        db.expect_get_inheritance().returning(|_| Ok(None));
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
@@ -714,7 +924,8 @@ mod tests {
                     .unwrap()
                     .statechain_sig,
                     x1,
-                    batch_id: None
+                    batch_id: None,
+                    version: 0,
                 })
             });
         db.expect_get_ecdsa_keypair()
@@ -738,15 +949,40 @@ mod tests {
             })
         });
         db.expect_get_lockbox_index().returning(|_| Ok(None));
+        db.expect_get_statechain_version().returning(|_| Ok(0));
+        db.expect_claim_transfer().returning(|_, _| Ok(()));
         db.expect_update_statechain_owner()
-            .returning(|_, _, _| Ok(()));
+            .returning(|_, _, _, _| Ok(()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Transfer)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Transferred)
+            .returning(|_, _| Ok(()));
         db.expect_transfer_init_user_session()
             .returning(|_, _, _, _| Ok(()));
         db.expect_update_backup_tx().returning(|_, _| Ok(()));
+        db.expect_update_backup_addr().returning(|_, _| Ok(()));
+        db.expect_insert_backup_tx_history().returning(|_, _| Ok(()));
+        db.expect_get_sighash()
+            .returning(|_| Ok(bitcoin::hashes::sha256d::Hash::from_str(&"01".repeat(32)).unwrap()));
+        db.expect_set_sighash_finalized().returning(|_, _| Ok(()));
+        db.expect_remove_ecdsa()
+            .with(predicate::eq(shared_key_id))
+            .returning(|_| Ok(()));
         db.expect_remove_transfer_data().returning(|_| Ok(()));
+        db.expect_get_statechain_amount().returning(|_| {
+            Ok(StateChainAmount {
+                chain: serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string())
+                    .unwrap()
+                    .try_into()
+                    .unwrap(),
+                amount: 10000,
+            })
+        });
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
         db.expect_root_update().returning(|_| Ok(1));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         db.expect_get_finalize_batch_data().returning(move |_| {
             Ok(TransferFinalizeBatchData {
                 finalized_data_vec: vec![TransferFinalizeData {
@@ -780,7 +1016,7 @@ mod tests {
         .returning(move |_| Ok(Utc::now().naive_utc() - Duration::seconds(1)));
 
         db.expect_update_finalize_batch_data()
-            .returning(|_, _| Ok(()));
+            .returning(|_, _, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
         let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
@@ -845,6 +1081,10 @@ mod tests {
         let x1 = msg2.x1.get_fe().expect("failed to get fe");
 
         let mut db = MockDatabase::new();
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
+        db.expect_get_inheritance().returning(|_| Ok(None));
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
@@ -859,7 +1099,8 @@ mod tests {
                     .unwrap()
                     .statechain_sig,
                     x1,
-                    batch_id: None
+                    batch_id: None,
+                    version: 0,
                 })
             });
         db.expect_get_ecdsa_keypair()
@@ -885,15 +1126,37 @@ mod tests {
         });
         db.expect_get_lockbox_index().returning(|_| Ok(Some(0)));
         db.expect_update_lockbox_index().returning(|_,_|Ok(()));
+        db.expect_get_statechain_version().returning(|_| Ok(0));
+        db.expect_claim_transfer().returning(|_, _| Ok(()));
         db.expect_update_statechain_owner()
-            .returning(|_, _, _| Ok(()));
+            .returning(|_, _, _, _| Ok(()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Transfer)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Transferred)
+            .returning(|_, _| Ok(()));
         db.expect_transfer_init_user_session()
             .returning(|_, _, _, _| Ok(()));
         db.expect_update_backup_tx().returning(|_, _| Ok(()));
+        db.expect_update_backup_addr().returning(|_, _| Ok(()));
+        db.expect_insert_backup_tx_history().returning(|_, _| Ok(()));
+        db.expect_get_sighash()
+            .returning(|_| Ok(bitcoin::hashes::sha256d::Hash::from_str(&"01".repeat(32)).unwrap()));
+        db.expect_set_sighash_finalized().returning(|_, _| Ok(()));
         db.expect_remove_transfer_data().returning(|_| Ok(()));
+        db.expect_get_statechain_amount().returning(|_| {
+            Ok(StateChainAmount {
+                chain: serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string())
+                    .unwrap()
+                    .try_into()
+                    .unwrap(),
+                amount: 10000,
+            })
+        });
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
         db.expect_root_update().returning(|_| Ok(1));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         db.expect_get_finalize_batch_data().returning(move |_| {
             Ok(TransferFinalizeBatchData {
                 finalized_data_vec: vec![TransferFinalizeData {
@@ -918,7 +1181,7 @@ mod tests {
             })
         });
         db.expect_update_finalize_batch_data()
-            .returning(|_, _| Ok(()));
+            .returning(|_, _, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, Some(mockito::server_url()), None, None, None);
         let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
@@ -991,6 +1254,173 @@ mod tests {
         sc_entity.transfer_receiver(transfer_msg_4.clone()).expect("expected transfer_receiver to return Ok");
     }
 
+    #[test]
+    fn test_transfer_finalize_conflict() {
+        let finalized_data =
+            serde_json::from_str::<TransferFinalizeData>(&FINALIZED_DATA.to_string()).unwrap();
+        let statechain_id = finalized_data.statechain_id;
+        let owner_id = Uuid::new_v4();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_statechain_version().returning(|_| Ok(3));
+        db.expect_get_statechain().returning(move |_| {
+            Ok(serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string()).unwrap().try_into().unwrap())
+        });
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id,
+                chain: serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string()).unwrap().try_into().unwrap(),
+            })
+        });
+        db.expect_get_lockbox_index().returning(|_| Ok(None));
+        // Statechain updated by a concurrent receiver
+        db.expect_update_statechain_owner()
+            .with(predicate::eq(statechain_id), predicate::always(), predicate::always(), predicate::eq(3))
+            .times(1)
+            .returning(|id, _, _, _| Err(SEError::TryAgain(format!("conflicting update of statechain id: {}", id))));
+        db.expect_update_session_state().times(0);
+        db.expect_transfer_init_user_session().times(0);
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        match sc_entity.transfer_finalize(&finalized_data) {
+            Err(SEError::TryAgain(e)) => assert!(e.contains("conflicting update")),
+            _ => assert!(false, "expected TryAgain error"),
+        }
+    }
+
+    #[test]
+    fn test_issue_deletion_receipt() {
+        let user_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let proof_key = String::from("03b971d624567214a2e9a53995ee7d4858d6355eb4e3863d9ac540085c8b2d12b3");
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &identity_key).to_string();
+        let g: GE = ECPoint::generator();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_s1_pubkey()
+            .with(predicate::eq(user_id))
+            .returning(move |_| Ok(g));
+        let expected_pubkey = server_pubkey.clone();
+        let expected_proof_key = proof_key.clone();
+        db.expect_insert_deletion_receipt()
+            .withf(move |r| {
+                verify_deletion_receipt(r, &expected_pubkey).is_ok()
+                    && r.receipt.shared_key_id == user_id
+                    && r.receipt.proof_key == expected_proof_key
+                    && r.receipt.commitment == deletion_commitment(&g)
+            })
+            .times(1)
+            .returning(|_| Ok(()));
+
+        // No receipt without an identity key
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.identity_key = None;
+        assert!(sc_entity.issue_deletion_receipt(&user_id, &statechain_id, &proof_key).is_ok());
+
+        // Signed with the identity key, not the export key
+        sc_entity.config.export_key = Some(hex::encode(&[4; 32]));
+        sc_entity.config.identity_key = Some(hex::encode(&[3; 32]));
+        assert!(sc_entity.issue_deletion_receipt(&user_id, &statechain_id, &proof_key).is_ok());
+    }
+
+    #[test]
+    fn test_issue_transfer_receipt() {
+        let user_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let prev_proof_key = String::from("03b971d624567214a2e9a53995ee7d4858d6355eb4e3863d9ac540085c8b2d12b3");
+        let new_proof_key = String::from("026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e");
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &identity_key).to_string();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let (expected_pubkey, expected_prev, expected_new) =
+            (server_pubkey.clone(), prev_proof_key.clone(), new_proof_key.clone());
+        db.expect_insert_transfer_receipt()
+            .withf(move |id, r| {
+                *id == user_id
+                    && verify_transfer_receipt(r, &expected_pubkey, &statechain_id, &expected_prev).is_ok()
+                    && r.receipt.new_proof_key == expected_new
+                    && r.receipt.root_id == 5
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        // No receipt without an identity key
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.identity_key = None;
+        assert!(sc_entity
+            .issue_transfer_receipt(&user_id, &statechain_id, &prev_proof_key, &new_proof_key, 5)
+            .is_ok());
+
+        // Signed with the identity key, not the export key
+        sc_entity.config.export_key = Some(hex::encode(&[4; 32]));
+        sc_entity.config.identity_key = Some(hex::encode(&[3; 32]));
+        assert!(sc_entity
+            .issue_transfer_receipt(&user_id, &statechain_id, &prev_proof_key, &new_proof_key, 5)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_prune_statechain() {
+        let statechain_id = Uuid::new_v4();
+        let secp = Secp256k1::new();
+        let keys: Vec<SecretKey> = (1..8u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<String> = keys
+            .iter()
+            .map(|sk| PublicKey::from_secret_key(&secp, sk).to_string())
+            .collect();
+        let mut state_chain = StateChain::new(pks[0].clone());
+        for i in 0..5 {
+            let sig = StateChainSig::new_bound(&keys[i], &String::from("TRANSFER"), &pks[i + 1], &statechain_id, i as u64).unwrap();
+            state_chain.add(&sig).unwrap();
+        }
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_insert_chain_segment()
+            .withf(move |id, segment| {
+                *id == statechain_id && segment.start_position == 0 && segment.states.len() == 4
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Within the prune length
+        sc_entity.config.chain_limits.prune_length = 6;
+        sc_entity.config.chain_limits.prune_keep = 2;
+        let mut pruned = state_chain.clone();
+        sc_entity.prune_statechain(&statechain_id, &mut pruned).unwrap();
+        assert_eq!(pruned, state_chain);
+
+        sc_entity.config.chain_limits.prune_length = 5;
+        sc_entity.prune_statechain(&statechain_id, &mut pruned).unwrap();
+        assert_eq!(pruned.get_chain().len(), 2);
+        assert_eq!(pruned.length(), 6);
+        assert_eq!(pruned.tip_position(), state_chain.tip_position());
+
+        // Length limit includes pruned states
+        assert!(sc_entity.check_chain_limits(&statechain_id, &pruned).is_ok());
+        sc_entity.config.chain_limits.max_length = 5;
+        match sc_entity.check_chain_limits(&statechain_id, &pruned) {
+            Err(SEError::LimitError(e)) => assert!(e.contains("maximum length of 5 states")),
+            r => assert!(false, "Expected LimitError, got {:?}", r),
+        }
+        sc_entity.config.chain_limits.max_length = 0;
+        sc_entity.config.chain_limits.max_size = 100;
+        match sc_entity.check_chain_limits(&statechain_id, &pruned) {
+            Err(SEError::LimitError(e)) => assert!(e.contains("maximum statechain size of 100 bytes")),
+            r => assert!(false, "Expected LimitError, got {:?}", r),
+        }
+    }
+
     #[test]
     fn test_convert_ecdsa_keypair_to_secret_key() {
         // simulate lockbox secret operations
@@ -1001,6 +1431,102 @@ mod tests {
         let _key: SecretKey = kp.try_into().expect("expected ECDSAKeypair to convert to SecretKey");
     }
 
+    #[test]
+    fn test_transfer_key_update() {
+        let g: GE = ECPoint::generator();
+        let s1: FE = ECScalar::new_random();
+        let x1: FE = ECScalar::new_random();
+        let t2: FE = ECScalar::new_random();
+        let o1: FE = ECScalar::new_random();
+        let s2 = t2 * x1.invert() * s1;
+        let o1_pub = g * o1;
+        let o2_pub = g * (o1 * s1 * s2.invert());
+
+        let (s2_new, s2_pub) = transfer_key_update(&s1, &x1, &t2, &o1_pub, &o2_pub).unwrap();
+        assert_eq!(s2_new, s2);
+        assert_eq!(s2_pub, g * s2);
+
+        // Shared public key changed
+        let o2_pub_wrong = g * o1;
+        match transfer_key_update(&s1, &x1, &t2, &o1_pub, &o2_pub_wrong) {
+            Err(e) => assert!(e.to_string().contains("P1 != P2")),
+            Ok(_) => assert!(false, "expected P1 != P2 error"),
+        }
+    }
+
+    #[test]
+    fn test_transfer_reject() {
+        let secp = Secp256k1::new();
+        let sender_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let receiver_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let receiver_proof_key = PublicKey::from_secret_key(&secp, &receiver_priv).to_string();
+        let statechain_id = Uuid::new_v4();
+        let sender_id = Uuid::new_v4();
+        let transfer_sig = StateChainSig::new(
+            &sender_priv,
+            &String::from("TRANSFER"),
+            &receiver_proof_key,
+        )
+        .unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
+        db.expect_get_inheritance().returning(|_| Ok(None));
+        let sig = transfer_sig.clone();
+        db.expect_get_transfer_data()
+            .with(predicate::eq(statechain_id))
+            .returning(move |_| {
+                Ok(TransferData {
+                    statechain_id,
+                    statechain_sig: sig.clone(),
+                    x1: ECScalar::new_random(),
+                    batch_id: None,
+                    version: 0,
+                })
+            });
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: sender_id,
+                chain: serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string())
+                    .unwrap()
+                    .try_into()
+                    .unwrap(),
+            })
+        });
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Transfer)));
+        db.expect_remove_transfer_data()
+            .with(predicate::eq(statechain_id))
+            .times(1)
+            .returning(|_| Ok(()));
+        db.expect_update_transfer_reject()
+            .withf(move |r| r.statechain_id == statechain_id)
+            .times(1)
+            .returning(|_| Ok(()));
+        db.expect_update_session_state()
+            .withf(move |id, state| *id == sender_id && *state == SessionState::Owner)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Rejection signed by the sender rather than the receiver
+        let msg = TransferRejectMsg {
+            statechain_id,
+            statechain_sig: StateChainSig::new_transfer_reject_sig(&sender_priv, &transfer_sig, &statechain_id).unwrap(),
+        };
+        assert!(sc_entity.transfer_reject(msg).is_err());
+
+        let msg = TransferRejectMsg {
+            statechain_id,
+            statechain_sig: StateChainSig::new_transfer_reject_sig(&receiver_priv, &transfer_sig, &statechain_id).unwrap(),
+        };
+        assert!(sc_entity.transfer_reject(msg).is_ok());
+    }
+
     #[test]
     fn test_convert_secp256k1_scalar_to_secret_key() {
         let s1: FE = ECScalar::new_random();
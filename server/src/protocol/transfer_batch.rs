@@ -7,16 +7,13 @@ pub use super::super::Result;
 use super::transfer::Transfer;
 
 extern crate shared_lib;
+use crate::config::DurationUnit;
 use crate::error::SEError;
 use crate::{server::StateChainEntity, Database};
-use crate::protocol::util::RateLimiter;
 use shared_lib::{commitment::verify_commitment, state_chain::*, structs::*};
 
-use rocket_okapi::openapi;
 use cfg_if::cfg_if;
 use chrono::{NaiveDateTime, Utc};
-use rocket::State;
-use rocket_contrib::json::Json;
 use std::str::FromStr;
 use uuid::Uuid;
 
@@ -28,8 +25,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
     }
 }
 
@@ -71,13 +68,15 @@ impl BatchTransfer for SCE {
         let mut state_chains = vec![];
         for sig in transfer_batch_init_msg.signatures.clone() {
             // Ensure sig is for same batch as others
-            if &sig.clone().purpose[15..] != batch_id.to_string() {
+            if sig.purpose.get(15..) != Some(batch_id.to_string().as_str()) {
                 return Err(SEError::Generic(String::from(
                     "Batch id is not identical for all signtures.",
                 )));
             }
 
-            let statechain_id = Uuid::from_str(&sig.data).unwrap();
+            let statechain_id = Uuid::from_str(&sig.data).map_err(|e| {
+                SEError::Generic(format!("Invalid State Chain ID in batch transfer signature: {}", e))
+            })?;
             let sco = self.database.get_statechain_owner(statechain_id)?;
 
             // Verify sigs
@@ -87,6 +86,15 @@ impl BatchTransfer for SCE {
             // Ensure state chains are all available
             is_locked(sco.locked_until)?;
 
+            // Ensure owners are not punished for earlier failures with other statechains
+            let seconds_remaining = self.punishment_remaining(&statechain_id, proof_key)?;
+            if seconds_remaining > 0 {
+                return Err(SEError::Generic(format!(
+                    "State Chain ID {} punished. Seconds remaining: {}",
+                    statechain_id, seconds_remaining
+                )));
+            }
+
             // Add to TransferBatchData object
             state_chains.push(statechain_id);
         }
@@ -94,6 +102,10 @@ impl BatchTransfer for SCE {
         // Create new TransferBatchData and add to DB
         self.database
             .create_transfer_batch_data(&batch_id, state_chains)?;
+        if self.config.duration_unit == DurationUnit::Blocks {
+            self.database
+                .update_transfer_batch_start_height(&batch_id, self.current_height()?)?;
+        }
 
         info!("TRANSFER_BATCH_INIT: Batch ID {} initiated.", batch_id);
         debug!(
@@ -107,6 +119,7 @@ impl BatchTransfer for SCE {
     fn finalize_batch(&self, batch_id: Uuid) -> Result<()> {
         debug!("TRANSFER_FINALIZE_BATCH: ID: {}", batch_id);
 
+        let version = self.database.get_transfer_batch_version(&batch_id)?;
         let fbd = self.database.get_finalize_batch_data(batch_id)?;
 
         debug!("TRANSFER_FINALIZE_BATCH: data: {:?}", fbd);
@@ -118,7 +131,7 @@ impl BatchTransfer for SCE {
 
         debug!("TRANSFER_FINALIZE_BATCH: updating database for batch ID: {}", batch_id);
         self.database
-            .update_transfer_batch_finalized(&batch_id, &true)?;
+            .update_transfer_batch_finalized(&batch_id, &true, version)?;
 
         Ok(())
     }
@@ -145,7 +158,7 @@ impl BatchTransfer for SCE {
             )));
         }
 
-        if !transfer_batch_is_ended(tbd.start_time, self.config.batch_lifetime as i64) {
+        if !self.transfer_batch_ended(&batch_id, tbd.start_time)? {
             return Err(SEError::Generic(String::from("Transfer Batch still live.")));
         }
 
@@ -181,7 +194,7 @@ impl BatchTransfer for SCE {
                             // remove from transfer batch punished list
                             let mut new_punished = tbd.punished_state_chains.clone();
                             new_punished.retain(|x| x != &statechain_id);
-                            self.database.update_punished(&batch_id, new_punished)?;
+                            self.database.update_punished(&batch_id, new_punished, tbd.version)?;
                         }
                     },
                     None => (),
@@ -194,6 +207,23 @@ impl BatchTransfer for SCE {
     }
 }
 
+impl SCE {
+    /// Check if Transfer Batch is out of its lifetime. Lifetimes in blocks are counted from the
+    /// batch start height, or converted to a time for batches started without one.
+    pub fn transfer_batch_ended(&self, batch_id: &Uuid, start_time: NaiveDateTime) -> Result<bool> {
+        let lifetime = self.config.batch_lifetime;
+        if self.config.duration_unit == DurationUnit::Blocks {
+            if let Some(start_height) = self.database.get_transfer_batch_start_height(batch_id)? {
+                return Ok(self.current_height()? > start_height + lifetime);
+            }
+        }
+        Ok(transfer_batch_is_ended(
+            start_time,
+            self.config.duration_unit.seconds(lifetime) as i64,
+        ))
+    }
+}
+
 /// Check if Transfer Batch is out of time
 pub fn transfer_batch_is_ended(start_time: NaiveDateTime, batch_lifetime: i64) -> bool {
     let current_time = Utc::now().naive_utc().timestamp();
@@ -203,48 +233,12 @@ pub fn transfer_batch_is_ended(start_time: NaiveDateTime, batch_lifetime: i64) -
     false
 }
 
-#[openapi]
-/// # Initiate the batch transfer protocol: provide statechain signatures
-#[post(
-    "/transfer/batch/init",
-    format = "json",
-    data = "<transfer_batch_init_msg>"
-)]
-pub fn transfer_batch_init(
-    sc_entity: State<SCE>,
-    transfer_batch_init_msg: Json<TransferBatchInitMsg>,
-) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("transfer_batch")?;
-    match sc_entity.transfer_batch_init(transfer_batch_init_msg.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
-
-#[openapi]
-/// # Complete Batch transfer: reveal transfer nonce
-#[post(
-    "/transfer/batch/reveal",
-    format = "json",
-    data = "<transfer_reveal_nonce>"
-)]
-pub fn transfer_reveal_nonce(
-    sc_entity: State<SCE>,
-    transfer_reveal_nonce: Json<TransferRevealNonce>,
-) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("transfer_batch")?;
-    match sc_entity.transfer_reveal_nonce(transfer_reveal_nonce.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::MockDatabase;
     use crate::{
-        protocol::{util::tests::test_sc_entity},
+        protocol::{util::tests::{malformed_sigs, test_sc_entity}},
         structs::{StateChainOwner, TransferBatchData, TransferFinalizeBatchData},
     };
     use chrono::{Duration, Utc};
@@ -306,6 +300,7 @@ mod tests {
         }
         db.expect_create_transfer_batch_data()
             .returning(|_, _| Ok(()));
+        db.expect_get_punishment().returning(|_| Ok(None));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
 
@@ -354,6 +349,61 @@ mod tests {
             .is_ok());
     }
 
+    // Malformed signatures over existing statechains return errors and do not panic
+    #[test]
+    fn test_transfer_batch_init_malformed_sigs() {
+        let transfer_batch_init_msg =
+            serde_json::from_str::<TransferBatchInitMsg>(TRANSFER_BATCH_INIT).unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_has_transfer_batch_id().returning(|_| false);
+        for (id, proof_key) in serde_json::from_str::<HashMap<&str, &str>>(SIG_PROOF_KEYS)
+            .unwrap()
+            .into_iter()
+        {
+            db.expect_get_statechain_owner()
+                .with(predicate::eq(Uuid::from_str(id).unwrap()))
+                .returning(move |_| {
+                    Ok(StateChainOwner {
+                        locked_until: Utc::now().naive_utc(),
+                        owner_id: Uuid::new_v4(),
+                        chain: StateChain::new(proof_key.to_string()),
+                    })
+                });
+        }
+        db.expect_get_punishment().returning(|_| Ok(None));
+        db.expect_create_transfer_batch_data().times(0);
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        for m in malformed_sigs() {
+            let mut msg = transfer_batch_init_msg.clone();
+            msg.signatures[1].sig = m.clone();
+            match sc_entity.transfer_batch_init(msg) {
+                Ok(_) => assert!(false, "Expected failure for sig {:?}", m),
+                Err(e) => assert!(
+                    e.to_string().contains("Signature Error")
+                        || e.to_string().contains("Format Error"),
+                    "unexpected error for sig {:?}: {}",
+                    m,
+                    e
+                ),
+            }
+
+            // Malformed purpose and statechain id
+            let mut msg = transfer_batch_init_msg.clone();
+            msg.signatures[1].purpose = format!("TRANSFER_BATCH{}", m);
+            assert!(sc_entity.transfer_batch_init(msg).is_err());
+            let mut msg = transfer_batch_init_msg.clone();
+            msg.signatures[1].data = m.clone();
+            match sc_entity.transfer_batch_init(msg) {
+                Ok(_) => assert!(false, "Expected failure for statechain id {:?}", m),
+                Err(e) => assert!(e.to_string().contains("Invalid State Chain ID"), "{}", e),
+            }
+        }
+    }
+
     #[test]
     #[ignore]
     fn test_finalize_batch() {
@@ -388,6 +438,8 @@ mod tests {
             chain.push(SCState {
                 data: proof_key.to_string(),
                 next_state: None,
+                backup_txid: None,
+                locktime: None,
             });
 
             let statechain: StateChain = chain.try_into().expect("expected Vec<State> to convert to StateChain"); 
@@ -396,8 +448,12 @@ mod tests {
                 .returning(move |_| (Ok(statechain.clone())));
         }
 
+        db.expect_get_transfer_batch_version().returning(|_| Ok(0));
+        db.expect_get_statechain_version().returning(|_| Ok(0));
         db.expect_update_statechain_owner()
-        .returning(|_, _, _| Ok(()));
+        .returning(|_, _, _, _| Ok(()));
+        db.expect_update_session_state()
+        .returning(|_, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
 
@@ -421,6 +477,7 @@ mod tests {
             punished_state_chains: vec![],
             start_time: Utc::now().naive_utc(),
             finalized: false,
+            version: 0,
         };
 
         let mut db = MockDatabase::new();
@@ -432,6 +489,7 @@ mod tests {
                 punished_state_chains: vec![],
                 start_time: Utc::now().naive_utc(),
                 finalized: false,
+                version: 0,
             })
         });
         let mut state_chains = HashSet::new();
@@ -453,6 +511,7 @@ mod tests {
                     punished_state_chains: vec![],
                     start_time: Utc::now().naive_utc(),
                     finalized: true,
+                    version: 0,
                 })
             });
         transfer_batch_data.finalized = false;
@@ -468,6 +527,7 @@ mod tests {
                     punished_state_chains: vec![],
                     start_time: Utc::now().naive_utc(),
                     finalized: false,
+                    version: 0,
                 })
             });
         db.expect_get_transfer_batch_data().returning(move |_| {
@@ -480,11 +540,12 @@ mod tests {
                 punished_state_chains: vec![],
                 start_time: Utc::now().naive_utc() - Duration::seconds(9999), // ensure batch lifetime has passed,
                 finalized: false,
+                version: 0,
             })
         });
 
         db.expect_update_locked_until().returning(|_, _| Ok(()));
-        db.expect_update_punished().returning(|_, _| Ok(()));
+        db.expect_update_punished().returning(|_, _, _| Ok(()));
         db.expect_get_sc_transfer_finalize_data().returning(|_|
             Err(SEError::DBError(
                 DBErrorType::NoDataForID, "no data".to_string())));
@@ -555,4 +616,40 @@ mod tests {
             false
         );
     }
+
+    #[test]
+    fn test_transfer_batch_ended_blocks() {
+        let batch_live = Uuid::new_v4();
+        let batch_ended = Uuid::new_v4();
+        let batch_no_height = Uuid::new_v4();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_transfer_batch_start_height()
+            .with(predicate::eq(batch_live))
+            .returning(|_| Ok(Some(12342)));
+        db.expect_get_transfer_batch_start_height()
+            .with(predicate::eq(batch_ended))
+            .returning(|_| Ok(Some(12341)));
+        db.expect_get_transfer_batch_start_height()
+            .with(predicate::eq(batch_no_height))
+            .returning(|_| Ok(None));
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.duration_unit = DurationUnit::Blocks;
+        sc_entity.config.batch_lifetime = 3;
+        sc_entity.chain_height.set(12345);
+
+        let now = Utc::now().naive_utc();
+        // start time is ignored if the start height is known
+        let start_time = now - Duration::seconds(3600);
+        assert!(!sc_entity.transfer_batch_ended(&batch_live, start_time).unwrap());
+        assert!(sc_entity.transfer_batch_ended(&batch_ended, now).unwrap());
+
+        // lifetime converted to a time otherwise
+        let start_time = now - Duration::seconds(1700);
+        assert!(!sc_entity.transfer_batch_ended(&batch_no_height, start_time).unwrap());
+        let start_time = now - Duration::seconds(1900);
+        assert!(sc_entity.transfer_batch_ended(&batch_no_height, start_time).unwrap());
+    }
 }
@@ -4,25 +4,30 @@
 //! utility functions.
 
 pub use super::super::Result;
-use super::{transfer_batch::{transfer_batch_is_ended, BatchTransfer}};
+use super::{punishment::Offence, session::SessionEvent, transfer_batch::BatchTransfer};
 use url::Url;
 extern crate shared_lib;
 use shared_lib::{
     mainstay::Attestable,
     mocks::mock_electrum::MockElectrum,
+    smt_proof::{EncodedProof, ProofFormat},
     state_chain::*,
     structs::*,
-    util::{get_sighash, tx_withdraw_verify, transaction_deserialise, transaction_serialise},
+    util::{
+        backup_tx_address, backup_tx_timelock, backup_tx_timelock_mode, get_sighash, tx_withdraw_verify,
+        transaction_deserialise, transaction_serialise, withdraw_inputs_verify, FEE,
+    },
     Root,
 };
 pub use kms::ecdsa::two_party::Party1Public;
 
 use shared_lib::structs::{Protocol, TransferFinalizeData};
 
-use rocket_okapi::openapi;
+use crate::build_info;
+use crate::config::SmtBatchConfig;
 use crate::error::{DBErrorType, SEError};
 use crate::storage::Storage;
-use crate::{server::StateChainEntity, Database};
+use crate::{server::StateChainEntity, structs::{SessionState, SighashRecord, UpdatesSince}, Database};
 use cfg_if::cfg_if;
 
 use bitcoin::consensus;
@@ -31,18 +36,29 @@ use electrumx_client::{electrumx_client::ElectrumxClient, interface::Electrumx};
 #[cfg(test)]
 use mockito::{mock, Matcher, Mock};
 pub use monotree::Proof;
-use rocket::State;
-use rocket_contrib::json::Json;
 use std::str::FromStr;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 use bitcoin::OutPoint;
 use bitcoin::Transaction;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use chrono::{NaiveDateTime, Utc};
+use bitcoin::{hashes::Hash, Script, WPubkeyHash};
 use curv::GE;
 use curv::elliptic::curves::traits::ECPoint;
 use std::ops::Deref;
+use rand::Rng;
 
 
 const MAX_LOCKTIME: u32 = 500000000; // bitcoin tx nlocktime cutoff
+const MAX_PROOFS_PER_REQUEST: usize = 1000;
+const MAX_UPDATES_PER_REQUEST: usize = 1000;
+const MAX_USER_SESSIONS_PER_REQUEST: usize = 1000;
+const MAX_PROOF_KEYS_PER_REQUEST: usize = 100;
+const MAX_USER_SESSIONS_CHALLENGES: usize = 100000;
+const MAX_OWNERSHIP_CHALLENGES: usize = 100000;
 
 //Generics cannot be used in Rocket State, therefore we define the concrete
 //type of StateChainEntity here
@@ -52,8 +68,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
     }
 }
 
@@ -63,9 +79,19 @@ pub trait Utilities {
     /// API: Return StateChain Entity fee information.
     fn get_fees(&self) -> Result<StateEntityFeeInfoAPI>;
 
+    /// API: Return StateChain Entity protocol parameters.
+    fn get_params(&self) -> Result<StateEntityParamsAPI>;
+
     /// API: Generates sparse merkle tree inclusion proof for some key in a tree with some root.
     fn get_smt_proof(&self, smt_proof_msg: SmtProofMsgAPI) -> Result<Option<Proof>>;
 
+    /// API: Generates sparse merkle tree inclusion proofs for a list of keys against the current root.
+    fn get_smt_proofs(&self, smt_proofs_msg: SmtProofsMsgAPI) -> Result<SmtProofsAPI>;
+
+    /// API: Return ids of statechains modified since a checkpoint, given either as a root id or as
+    /// a timestamp (%Y-%m-%dT%H:%M:%S%.f). Clients pass the returned checkpoint in the next request.
+    fn get_statechain_updates(&self, since: &String) -> Result<StateChainUpdatesAPI>;
+
     /// API: Get root of sparse merkle tree. Will be via Mainstay in the future.
     //fn get_smt_root(&self) -> Result<Option<Root>>;
 
@@ -94,19 +120,86 @@ pub trait Utilities {
 
     // get lockbox url
     fn get_lockbox_url(&self, user_id: &Uuid) -> Result<Option<(Url,usize)>>;
+
+    /// API: Store a signed CPFP child tx of a statecoin backup tx. The watcher broadcasts
+    /// the child together with the backup tx so that the backup claim confirms promptly.
+    fn submit_backup_cpfp(&self, cpfp_msg: BackupCPFPMsg) -> Result<()>;
+
+    /// API: Produce a proof of reserves report for a set of statecoins. Each statecoin must be
+    /// signed for by its current owner over the verifier nonce and be included in the latest
+    /// mainstay-attested SMT root under that owner's proof key.
+    fn get_reserve_proof(&self, reserve_proof_msg: ReserveProofMsg)
+        -> Result<SignedReserveProofReport>;
+
+    /// API: Export the full public history of a statechain signed by the state entity export key
+    fn get_statechain_export(&self, statechain_id: Uuid) -> Result<SignedStateChainExport>;
+
+    /// API: Get the receipts for the key shares deleted after each transfer of a statechain
+    fn get_deletion_receipts(&self, statechain_id: Uuid) -> Result<Vec<SignedDeletionReceipt>>;
+
+    /// API: Get the receipts of the completed transfers of a statechain
+    fn get_transfer_receipts(&self, statechain_id: Uuid) -> Result<Vec<SignedTransferReceipt>>;
+
+    /// API: Issue a single-use challenge to be signed with each proof key in a user sessions request
+    fn get_user_sessions_challenge(&self) -> Result<UserSessionsChallenge>;
+
+    /// API: Return the user sessions held for a set of proof keys, authenticated by a signature
+    /// from each proof key over an issued challenge. Sessions can be filtered by state and are
+    /// returned in pages ordered by proof key.
+    fn get_user_sessions(&self, user_sessions_msg: UserSessionsRequest) -> Result<UserSessionsAPI>;
+
+    /// API: Issue a single-use nonce for the owner of a statecoin to sign with its proof key
+    fn get_ownership_challenge(&self) -> Result<OwnershipChallenge>;
+
+    /// API: Confirm that a signature over an issued nonce is by the current owner proof key of a
+    /// statechain. Returns a confirmation of the ownership signed by the state entity identity key.
+    fn prove_ownership(
+        &self,
+        ownership_proof_msg: OwnershipProofMsg,
+    ) -> Result<SignedOwnershipConfirmation>;
+
+    /// API: Get the build manifest of the server binary, signed by the server identity key if
+    /// configured
+    fn get_build_manifest(&self) -> Result<SignedBuildManifest>;
 }
 
 impl Utilities for SCE {
     fn get_fees(&self) -> Result<StateEntityFeeInfoAPI> {
-        let fee_address_vec: Vec<&str> = self.config.fee_address.split(",").collect();
-        Ok(StateEntityFeeInfoAPI {
-            address: fee_address_vec[0].to_string().clone(),
-            deposit: self.config.fee_deposit as i64,
-            withdraw: self.config.fee_withdraw,
-            interval: self.config.lh_decrement,
+        let valid_until = fee_quote_expiry(self.config.fee_quote_validity, Utc::now().naive_utc(), 0);
+        let mut guard = self.fee_info_cache.lock()?;
+        let mut fee_info = match guard.as_ref() {
+            Some(fee_info) if fee_info.valid_until == Some(valid_until) => fee_info.clone(),
+            _ => {
+                let fee_info = self.fee_quote(valid_until)?;
+                *guard = Some(fee_info.clone());
+                fee_info
+            }
+        };
+        drop(guard);
+        // Not part of the quote hash, so not cached with the quote
+        let status = self.deposit_capacity()?;
+        fee_info.capacity = status.capacity;
+        fee_info.deposits_paused = status.paused;
+        Ok(fee_info)
+    }
+
+    fn get_params(&self) -> Result<StateEntityParamsAPI> {
+        Ok(StateEntityParamsAPI {
+            network: self.config.network.clone(),
             initlock: self.config.lockheight_init,
-            wallet_version: self.config.wallet_version.clone(),
-            wallet_message: self.config.wallet_message.clone(),
+            interval: self.config.lh_decrement,
+            timelock_mode: self.config.backup_timelock_mode,
+            required_confirmation: self.config.required_confirmation,
+            deposit_pow: self.config.deposit_pow,
+            pow_difficulty: self.config.difficulty,
+            max_tx_fee: self.config.max_tx_fee,
+            batch_lifetime: self.config.duration_unit.seconds(self.config.batch_lifetime),
+            punishment_duration: self.config.duration_unit.seconds(self.config.conductor.punishment_duration),
+            denominations: self.config.conductor.permitted_groups(),
+            swap_timeout: self.config.conductor.group_timeout,
+            utxo_timeout: self.config.conductor.utxo_timeout,
+            max_swap_size: self.config.conductor.max_swap_size,
+            swap_wallet_version: self.config.conductor.swap_wallet_version.clone(),
         })
     }
 
@@ -114,7 +207,7 @@ impl Utilities for SCE {
         // ensure root exists
         match smt_proof_msg.root.id() {
             Some(id) => {
-                if self.database.get_root(id as i64)?.is_none() {
+                if self.database.get_root_info(id as i64)?.is_none() {
                     return Err(SEError::DBError(
                         DBErrorType::NoDataForID,
                         format!("Root id: {:?}", id),
@@ -129,11 +222,61 @@ impl Utilities for SCE {
             }
         }
 
-        Ok(gen_proof_smt(
-            self.smt.clone(),
-            &Some(smt_proof_msg.root.hash()),
-            &smt_proof_msg.funding_txid,
-        )?)
+        self.check_smt_update_published(&smt_proof_msg.funding_txid)?;
+        self.get_smt_proof_cached(&smt_proof_msg.root, &smt_proof_msg.funding_txid)
+    }
+
+    fn get_smt_proofs(&self, smt_proofs_msg: SmtProofsMsgAPI) -> Result<SmtProofsAPI> {
+        if smt_proofs_msg.funding_txids.len() > MAX_PROOFS_PER_REQUEST {
+            return Err(SEError::Generic(format!(
+                "Too many proofs requested: {} (maximum {})",
+                smt_proofs_msg.funding_txids.len(),
+                MAX_PROOFS_PER_REQUEST
+            )));
+        }
+        // Checked before the root is read, so that the root includes any update published since
+        for funding_txid in &smt_proofs_msg.funding_txids {
+            self.check_smt_update_published(funding_txid)?;
+        }
+        let root = self.get_smt_root()?.ok_or(SEError::DBError(
+            DBErrorType::NoDataForID,
+            String::from("Current SMT root"),
+        ))?;
+        let mut proofs = Vec::with_capacity(smt_proofs_msg.funding_txids.len());
+        let format = smt_proofs_msg.proof_format;
+        for funding_txid in smt_proofs_msg.funding_txids {
+            let proof = self
+                .get_smt_proof_cached(&root, &funding_txid)?
+                .map(|proof| EncodedProof::new(proof, format));
+            proofs.push(SmtProofAPI { funding_txid, proof });
+        }
+        Ok(SmtProofsAPI { root, proofs })
+    }
+
+    fn get_statechain_updates(&self, since: &String) -> Result<StateChainUpdatesAPI> {
+        let since = match since.parse::<i64>() {
+            Ok(root_id) => UpdatesSince::RootId(root_id),
+            Err(_) => UpdatesSince::Time(NaiveDateTime::from_str(since).map_err(|e| {
+                SEError::Generic(format!("Invalid update checkpoint {}: {}", since, e))
+            })?),
+        };
+        // Record the checkpoint before querying so that concurrent modifications are not missed
+        let now = Utc::now().naive_utc();
+        let updates = self
+            .database
+            .get_statechains_updated_since(&since, MAX_UPDATES_PER_REQUEST as i64 + 1)?;
+        let more = updates.len() > MAX_UPDATES_PER_REQUEST;
+        let updates: Vec<(Uuid, NaiveDateTime)> =
+            updates.into_iter().take(MAX_UPDATES_PER_REQUEST).collect();
+        let checkpoint = match (more, updates.last()) {
+            (true, Some((_, updated))) => *updated,
+            _ => now,
+        };
+        Ok(StateChainUpdatesAPI {
+            statechain_ids: updates.into_iter().map(|(id, _)| id).collect(),
+            checkpoint,
+            more,
+        })
     }
 
     // fn get_smt_root(&self, db_read: DatabaseR) -> Result<Option<Root>> {
@@ -230,12 +373,31 @@ impl Utilities for SCE {
     // }
 
     fn prepare_sign_tx(&self, prepare_sign_msg: PrepareSignTxMsg) -> Result<()> {
+        let tx = transaction_deserialise(&prepare_sign_msg.tx_hex)?;
+
+        // Signing data must be supplied for every tx input
+        let n_inputs = prepare_sign_msg.input_amounts.len();
+        if tx.input.len() != n_inputs
+            || prepare_sign_msg.input_addrs.len() != n_inputs
+            || prepare_sign_msg.shared_key_ids.len() != n_inputs
+        {
+            return Err(SEError::Generic(String::from(
+                "Number of tx inputs does not match number of signing addresses, amounts and shared key IDs.",
+            )));
+        }
+
+        tx_fee_verify(&tx, &prepare_sign_msg.input_amounts, &self.config.max_tx_fee)?;
+        if prepare_sign_msg.protocol == Protocol::Withdraw {
+            withdraw_inputs_verify(n_inputs, self.config.max_withdraw_inputs)?;
+        }
+
         // Verify unsigned withdraw tx to ensure co-sign will be signing the correct data
         let mut amount = 0;
 
         for (i, input_amount) in prepare_sign_msg.input_amounts.iter().enumerate(){
             let user_id = &prepare_sign_msg.shared_key_ids[i];
             self.check_user_auth(&user_id)?;
+            self.check_session_event(&user_id, SessionEvent::PrepareSign(prepare_sign_msg.protocol))?;
             amount += input_amount;
 
             if prepare_sign_msg.protocol == Protocol::Withdraw {
@@ -245,13 +407,18 @@ impl Utilities for SCE {
                         "Withdraw has not been authorised. /withdraw/init must be called first.",
                     )));
                 }
+                // A withdrawal of a statecoin in vault mode is only co-signed once the vault
+                // delay has passed since withdraw_init
+                self.check_vault_delay(
+                    &self.database.get_statechain_id(*user_id)?,
+                    &self.database.get_withdraw_sc_sig(*user_id)?,
+                )?;
             }
         }
 
 
         // calculate SE fee amount from rate
         let withdraw_fee = (amount * self.config.fee_withdraw) / 10000 as u64;
-        let tx = transaction_deserialise(&prepare_sign_msg.tx_hex)?;
 
         let fee_address_str = self.config.fee_address.replace(" ", "");
         let fee_address_vec: Vec<&str> = fee_address_str.split(",").collect();
@@ -259,16 +426,54 @@ impl Utilities for SCE {
         // Which protocol are we signing for?
         match prepare_sign_msg.protocol {
             Protocol::Withdraw => {
+                // Swap fees owed by deduction are added to the withdraw fee
+                let mut swap_fee_owed = 0;
+                if self.config.conductor.swap_fee > 0 {
+                    for user_id in &prepare_sign_msg.shared_key_ids {
+                        swap_fee_owed += self.swap_fee_owed(&self.database.get_statechain_id(*user_id)?)?;
+                    }
+                }
+                // The withdraw tx also pays the fee per statecoin
+                let withdraw_fee = shared_lib::util::withdraw_fee(
+                    amount,
+                    n_inputs,
+                    self.config.fee_withdraw,
+                    self.config.fee_withdraw_input,
+                );
                 tx_withdraw_verify(
                     &prepare_sign_msg,
                     &fee_address_vec,
-                    &withdraw_fee,
+                    &(withdraw_fee + swap_fee_owed),
                 )?;
 
+                // Withdraw tx pays the withdrawal address and the SE fee only
+                if tx.output.len() != 2 {
+                    return Err(SEError::Generic(String::from(
+                        "Withdraw tx must have exactly 2 outputs.",
+                    )));
+                }
+                verify_owner_output(&tx, self.config.dust_limit)?;
+
                 for (i, user_id) in prepare_sign_msg.shared_key_ids.iter().enumerate(){
                     let statechain_id = self.database.get_statechain_id(*user_id)?;
                     let tx_backup = self.database.get_backup_transaction(statechain_id)?;
 
+                    // Check withdrawal address is the one signed for in withdraw/init
+                    let withdraw_sc_sig = self.database.get_withdraw_sc_sig(*user_id)?;
+                    let withdraw_addr = bitcoin::Address::from_str(&withdraw_sc_sig.data)
+                        .map_err(|e| SEError::Generic(format!("Invalid withdrawal address: {}", e)))?;
+                    if tx.output[0].script_pubkey != withdraw_addr.script_pubkey() {
+                        return Err(SEError::Generic(format!(
+                            "Withdraw tx does not pay authorised withdrawal address {}", withdraw_addr
+                        )));
+                    }
+
+                    // Check input amount is the statecoin value
+                    verify_input_amount(
+                        &prepare_sign_msg.input_amounts[i],
+                        &self.database.get_statechain_amount(statechain_id)?,
+                    )?;
+
                 // Check funding txid UTXO info
                 let tx_backup_input = tx_backup.input.get(0).unwrap().previous_output.to_owned();
                 if tx
@@ -302,6 +507,15 @@ impl Utilities for SCE {
                         tx.clone(),
                     )?;
 
+                    self.database.insert_sighash_record(&SighashRecord {
+                        user_id: *user_id,
+                        outpoint: tx.input[i].previous_output,
+                        sig_hash,
+                        locktime: tx.lock_time,
+                        signed: false,
+                        finalized: true,
+                    })?;
+
                         info!(
                             "WITHDRAW: Withdraw tx ready for signing. User ID: {:?}.",
                             user_id
@@ -343,6 +557,62 @@ impl Utilities for SCE {
                     prepare_sign_msg.shared_key_ids
                 );
             },
+            Protocol::Refund => {
+                let user_id = prepare_sign_msg.shared_key_ids[0];
+
+                // Refund tx pays the authorised refund address only
+                if tx.input.len() != 1 || tx.output.len() != 1 {
+                    return Err(SEError::Generic(String::from(
+                        "Refund tx must have exactly 1 input and 1 output.",
+                    )));
+                }
+
+                // Refund tx must spend the funding outpoint of the deposit backup tx
+                let tx_backup = self.database.get_user_backup_tx(user_id)?;
+                if tx.input[0].previous_output != tx_backup.input[0].previous_output {
+                    return Err(SEError::Generic(String::from(
+                        "Refund tx input does not spend deposit funding outpoint.",
+                    )));
+                }
+
+                // Check input amount is the deposit value
+                let deposit_amount = tx_backup.output.iter().map(|o| o.value).sum::<u64>() + FEE;
+                if prepare_sign_msg.input_amounts[0] != deposit_amount {
+                    return Err(SEError::Generic(String::from(
+                        "Refund tx input amount does not match deposit amount.",
+                    )));
+                }
+
+                // Check refund address is the one signed for in deposit/refund
+                let refund_sig = self.database.get_withdraw_sc_sig(user_id)?;
+                let refund_addr = bitcoin::Address::from_str(&refund_sig.data)
+                    .map_err(|e| SEError::Generic(format!("Invalid refund address: {}", e)))?;
+                if tx.output[0].script_pubkey != refund_addr.script_pubkey() {
+                    return Err(SEError::Generic(format!(
+                        "Refund tx does not pay authorised refund address {}", refund_addr
+                    )));
+                }
+
+                let sig_hash = get_sighash(
+                    &tx,
+                    &0,
+                    &prepare_sign_msg.input_addrs[0],
+                    &prepare_sign_msg.input_amounts[0],
+                    &self.config.network,
+                );
+
+                self.database.update_withdraw_tx_sighash(&user_id, sig_hash, tx.clone())?;
+                self.database.insert_sighash_record(&SighashRecord {
+                    user_id,
+                    outpoint: tx.input[0].previous_output,
+                    sig_hash,
+                    locktime: tx.lock_time,
+                    signed: false,
+                    finalized: true,
+                })?;
+
+                info!("DEPOSIT: Refund tx ready for signing. User ID: {}.", user_id);
+            },
             _ => {
                 // Verify unsigned backup tx to ensure co-sign will be signing the correct data
                 if prepare_sign_msg.input_addrs.len() != prepare_sign_msg.input_amounts.len() {
@@ -358,13 +628,38 @@ impl Utilities for SCE {
                     )));
                 }
 
+                // Backup tx pays the owner backup address and the SE fee only
+                if tx.output.len() != 2 {
+                    return Err(SEError::Generic(String::from(
+                        "Backup tx must have exactly 2 outputs.",
+                    )));
+                }
+                verify_owner_output(&tx, self.config.dust_limit)?;
+                // The owner output may pay a user-supplied address, which is recorded with the statechain
+                self.backup_addr(&tx)?;
+
+                //check that the backup tx uses the configured timelock mode
+                let timelock_mode = backup_tx_timelock_mode(&tx);
+                if timelock_mode != self.config.backup_timelock_mode {
+                    return Err(SEError::Generic(format!(
+                        "Backup tx timelock mode {:?} does not match required mode {:?}.",
+                        timelock_mode, self.config.backup_timelock_mode
+                    )));
+                }
+
                 //check that the locktime is height and not epoch
-                if (tx.lock_time as u32) >= MAX_LOCKTIME {
+                if timelock_mode == TimelockMode::Absolute && (tx.lock_time as u32) >= MAX_LOCKTIME {
                     return Err(SEError::Generic(String::from(
                         "Backup tx locktime specified as Unix epoch time not block height.",
                     )));
                 }
 
+                if backup_tx_timelock(&tx) == 0 {
+                    return Err(SEError::Generic(String::from(
+                        "Backup tx timelock not set.",
+                    )));
+                }
+
                 //check withdrawal fee is correctly set
 
                 tx_withdraw_verify(
@@ -381,7 +676,25 @@ impl Utilities for SCE {
                     let statechain_id = self.database.get_statechain_id(user_id)?;
                     let current_tx_backup = self.database.get_backup_transaction(statechain_id.clone())?;
 
-                    if (current_tx_backup.lock_time as u32) != (tx.lock_time as u32) + (self.config.lh_decrement as u32) {
+                    // New backup tx must spend the statecoin funding outpoint
+                    if tx.input[0].previous_output != current_tx_backup.input[0].previous_output {
+                        return Err(SEError::Generic(String::from(
+                            "Backup tx input does not spend statecoin funding outpoint.",
+                        )));
+                    }
+
+                    // Check input amount is the statecoin value and backup value is unchanged
+                    verify_input_amount(
+                        &prepare_sign_msg.input_amounts[0],
+                        &self.database.get_statechain_amount(statechain_id)?,
+                    )?;
+                    if tx.output[0].value != current_tx_backup.output[0].value {
+                        return Err(SEError::Generic(String::from(
+                            "Backup tx output value changed.",
+                        )));
+                    }
+
+                    if backup_tx_timelock(&current_tx_backup) != backup_tx_timelock(&tx) + (self.config.lh_decrement as u32) {
                         return Err(SEError::Generic(String::from(
                             "Backup tx locktime not correctly decremented.",
                         )));
@@ -393,10 +706,22 @@ impl Utilities for SCE {
 
                 // Only in deposit case add backup tx to UserSession
                 if prepare_sign_msg.protocol == Protocol::Deposit {
+                    if prepare_sign_msg.input_amounts[0] < self.config.min_deposit {
+                        return Err(SEError::Generic(format!(
+                            "Deposit amount {} is below the minimum deposit {}.",
+                            prepare_sign_msg.input_amounts[0], self.config.min_deposit
+                        )));
+                    }
+                    self.check_deposit_capacity(Some(prepare_sign_msg.input_amounts[0]))?;
+                    self.check_deposit_limits(
+                        &self.database.get_proof_key(user_id)?,
+                        Some(prepare_sign_msg.input_amounts[0]),
+                    )?;
+
                     // check if there is an existing backup transaction (from a previous deposit confirm)
                     // if there is: verify that the locktime of the new tx is the same and the destination address
                     let locktime: Option<u32> = match self.database.get_user_backup_tx(user_id.clone()) {
-                        Ok(old_tx) => Some(old_tx.lock_time as u32),
+                        Ok(old_tx) => Some(backup_tx_timelock(&old_tx)),
                         Err(e) => { 
                         if (e.to_string().contains("No data for identifier")) {
                             None
@@ -406,7 +731,7 @@ impl Utilities for SCE {
                         }
                     };
 
-                    if (locktime.is_none() || locktime == Some(tx.lock_time as u32)) {
+                    if (locktime.is_none() || locktime == Some(backup_tx_timelock(&tx))) {
                         self.database.update_user_backup_tx(&user_id, tx.clone())?;
                     } else {
                         return Err(SEError::Generic(String::from(
@@ -424,6 +749,15 @@ impl Utilities for SCE {
                 );
 
                 self.database.update_sighash(&user_id, sig_hash)?;
+                self.database.insert_sighash_record(&SighashRecord {
+                    user_id,
+                    outpoint: tx.input[0].previous_output,
+                    sig_hash,
+                    locktime: backup_tx_timelock(&tx),
+                    signed: false,
+                    // Finalized by deposit confirm or transfer finalize
+                    finalized: false,
+                })?;
 
                 info!(
                     "DEPOSIT: Backup tx ready for signing. Shared Key ID: {}.",
@@ -466,13 +800,396 @@ impl Utilities for SCE {
                     amount,
                     tx_hex: transaction_serialise(&statecoin.2),
                     proof_key: recovery_request.key.clone(),
-                    shared_key_data: public
+                    shared_key_data: public,
+                    p2c: self.database.get_p2c(statecoin.0)?,
                 })
             }
         }
         return Ok(recovery_data);
     }
 
+    fn submit_backup_cpfp(&self, cpfp_msg: BackupCPFPMsg) -> Result<()> {
+        let tx_backup = self.database.get_backup_transaction(cpfp_msg.statechain_id)?;
+        let tx_cpfp = transaction_deserialise(&cpfp_msg.tx_cpfp_hex)?;
+
+        // Child must spend a backup tx output with a witness for the output key
+        let input = tx_cpfp
+            .input
+            .iter()
+            .find(|txin| txin.previous_output.txid == tx_backup.txid())
+            .ok_or(SEError::Generic(String::from(
+                "CPFP tx does not spend backup tx output",
+            )))?;
+        let output = tx_backup
+            .output
+            .get(input.previous_output.vout as usize)
+            .ok_or(SEError::Generic(String::from(
+                "CPFP tx spends non-existent backup tx output",
+            )))?;
+        let pk_bytes = match input.witness.len() {
+            2 => &input.witness[1],
+            _ => return Err(SEError::Generic(String::from("CPFP tx input is not signed"))),
+        };
+        if Script::new_v0_wpkh(&WPubkeyHash::hash(pk_bytes)) != output.script_pubkey {
+            return Err(SEError::Generic(String::from(
+                "CPFP tx witness does not match backup tx output",
+            )));
+        }
+
+        self.database
+            .update_backup_cpfp_tx(&cpfp_msg.statechain_id, tx_cpfp)?;
+        info!(
+            "BACKUP: CPFP tx stored for statechain {}",
+            cpfp_msg.statechain_id
+        );
+        Ok(())
+    }
+
+    fn get_reserve_proof(
+        &self,
+        reserve_proof_msg: ReserveProofMsg,
+    ) -> Result<SignedReserveProofReport> {
+        let identity_key = match self.config.identity_key {
+            Some(_) => self.identity_key()?,
+            None => {
+                return Err(SEError::Generic(String::from(
+                    "Proof of reserves not enabled",
+                )))
+            }
+        };
+        let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &identity_key).to_string();
+
+        let nonce = reserve_proof_msg.nonce;
+        if nonce.is_empty() {
+            return Err(SEError::Generic(String::from(
+                "Reserve proof nonce must not be empty",
+            )));
+        }
+
+        let root = match self.get_confirmed_smt_root()? {
+            Some(r) => r,
+            None => {
+                return Err(SEError::Generic(String::from(
+                    "No attested SMT root available for reserve proof",
+                )))
+            }
+        };
+
+        let mut statechain_ids = HashSet::new();
+        let mut entries = vec![];
+        let mut total: u64 = 0;
+        for coin in reserve_proof_msg.statecoins {
+            let statechain_id = coin.statechain_id;
+            if !statechain_ids.insert(statechain_id) {
+                return Err(SEError::Generic(format!(
+                    "Statechain {} included more than once in reserve proof",
+                    statechain_id
+                )));
+            }
+
+            let sc_amount = self.database.get_statechain_amount(statechain_id)?;
+            let tip = sc_amount.chain.get_tip();
+            if tip.next_state.is_some() {
+                return Err(SEError::Generic(format!(
+                    "Statechain {} has been withdrawn",
+                    statechain_id
+                )));
+            }
+            if !coin.statechain_sig.is_reserves(&nonce, &statechain_id) {
+                return Err(SEError::Generic(format!(
+                    "Reserve proof signature for statechain {} does not commit to nonce",
+                    statechain_id
+                )));
+            }
+            coin.statechain_sig.verify(&tip.data)?;
+
+            // Proof key of the current owner and the amount must be committed to in the
+            // attested root
+            let tx_backup = self.database.get_backup_transaction(statechain_id)?;
+            let funding_txid = tx_backup.input[0].previous_output.txid.to_string();
+            let proof = gen_proof_smt(self.smt.clone(), &Some(root.hash()), &funding_txid)?;
+            if !verify_statechain_smt(
+                &Some(root.hash()),
+                &funding_txid,
+                &tip.data,
+                sc_amount.amount as u64,
+                &proof,
+            ) {
+                return Err(SEError::Generic(format!(
+                    "Statechain {} current owner not yet included in attested SMT root",
+                    statechain_id
+                )));
+            }
+
+            total += sc_amount.amount as u64;
+            entries.push(ReserveProofEntry {
+                statechain_id,
+                amount: sc_amount.amount as u64,
+                funding_txid,
+                proof_key: tip.data.clone(),
+                statechain_sig: coin.statechain_sig,
+                proof,
+            });
+        }
+
+        info!(
+            "RESERVES: Proof of reserves generated for {} statecoins totalling {}",
+            entries.len(),
+            total
+        );
+        Ok(sign_reserve_report(
+            &identity_key,
+            ReserveProofReport {
+                nonce,
+                root,
+                entries,
+                total,
+                server_pubkey,
+            },
+        )?)
+    }
+
+    fn get_statechain_export(&self, statechain_id: Uuid) -> Result<SignedStateChainExport> {
+        let export_key = match &self.config.export_key {
+            Some(k) => SecretKey::from_str(k)
+                .map_err(|e| SEError::Generic(format!("Invalid statechain export key: {}", e)))?,
+            None => {
+                return Err(SEError::Generic(String::from(
+                    "Statechain export not enabled",
+                )))
+            }
+        };
+        let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &export_key).to_string();
+
+        let sc_amount = self.database.get_statechain_amount(statechain_id)?;
+        let tx_backup = self.database.get_backup_transaction(statechain_id)?;
+        let funding_txid = tx_backup.input[0].previous_output.txid.to_string();
+        let tx_backup_hex = match sc_amount.chain.get_tip().next_state {
+            Some(_) => None,
+            None => Some(transaction_serialise(&tx_backup)),
+        };
+        // Recorded backup tx history, including the current backup tx
+        let mut backup_txs: Vec<String> = self
+            .database
+            .get_backup_tx_history(&statechain_id)?
+            .iter()
+            .map(transaction_serialise)
+            .collect();
+        if let Some(tx_hex) = &tx_backup_hex {
+            if !backup_txs.contains(tx_hex) {
+                backup_txs.push(tx_hex.clone());
+            }
+        }
+
+        // Current root and latest attested root with proofs for the funding txid
+        let mut roots: Vec<StateChainExportRoot> = vec![];
+        for root in vec![self.get_smt_root()?, self.get_confirmed_smt_root()?] {
+            if let Some(root) = root {
+                if roots.iter().any(|r| r.root.id() == root.id()) {
+                    continue;
+                }
+                let proof = gen_proof_smt(self.smt.clone(), &Some(root.hash()), &funding_txid)?;
+                roots.push(StateChainExportRoot { root, proof });
+            }
+        }
+
+        let export = StateChainExport {
+            version: STATECHAIN_EXPORT_VERSION,
+            statechain_id,
+            amount: sc_amount.amount as u64,
+            funding_txid,
+            chain: self.restore_statechain(&statechain_id, &sc_amount.chain)?,
+            tx_backup_hex,
+            backup_txs,
+            roots,
+            exported_at: Utc::now().naive_utc(),
+            server_pubkey,
+        };
+        Ok(sign_statechain_export(&export_key, export)?)
+    }
+
+    fn get_deletion_receipts(&self, statechain_id: Uuid) -> Result<Vec<SignedDeletionReceipt>> {
+        // Err if the statechain does not exist
+        self.database.get_statechain(statechain_id)?;
+        self.database.get_deletion_receipts(&statechain_id)
+    }
+
+    fn get_transfer_receipts(&self, statechain_id: Uuid) -> Result<Vec<SignedTransferReceipt>> {
+        // Err if the statechain does not exist
+        self.database.get_statechain(statechain_id)?;
+        self.database.get_transfer_receipts(&statechain_id)
+    }
+
+    fn get_user_sessions_challenge(&self) -> Result<UserSessionsChallenge> {
+        let now = Utc::now().naive_utc();
+        let expires = now + chrono::Duration::seconds(self.config.user_sessions_challenge_ttl as i64);
+        let challenge = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+
+        let mut guard = self.user_sessions_challenges.lock()?;
+        guard.retain(|_, expiry| *expiry > now);
+        if guard.len() >= MAX_USER_SESSIONS_CHALLENGES {
+            return Err(SEError::Generic(String::from(
+                "Too many outstanding user sessions challenges",
+            )));
+        }
+        guard.insert(challenge.clone(), expires);
+        Ok(UserSessionsChallenge { challenge, expires })
+    }
+
+    fn get_user_sessions(&self, user_sessions_msg: UserSessionsRequest) -> Result<UserSessionsAPI> {
+        let challenge = user_sessions_msg.challenge;
+        // Challenges are single use: consume it whether or not the request succeeds
+        match self.user_sessions_challenges.lock()?.remove(&challenge) {
+            Some(expiry) if expiry > Utc::now().naive_utc() => (),
+            _ => {
+                return Err(SEError::Generic(String::from(
+                    "Unknown or expired user sessions challenge",
+                )))
+            }
+        }
+
+        if user_sessions_msg.sigs.is_empty() {
+            return Err(SEError::Generic(String::from("No proof keys in user sessions request")));
+        }
+        if user_sessions_msg.sigs.len() > MAX_PROOF_KEYS_PER_REQUEST {
+            return Err(SEError::Generic(format!(
+                "Too many proof keys in user sessions request: {} (maximum {})",
+                user_sessions_msg.sigs.len(),
+                MAX_PROOF_KEYS_PER_REQUEST
+            )));
+        }
+        let mut proof_keys = vec![];
+        for sig in &user_sessions_msg.sigs {
+            if !sig.is_user_sessions(&challenge) {
+                return Err(SEError::Generic(format!(
+                    "User sessions signature for proof key {} does not commit to challenge",
+                    sig.data
+                )));
+            }
+            // The signed data is the proof key itself
+            sig.verify(&sig.data)?;
+            if !proof_keys.contains(&sig.data) {
+                proof_keys.push(sig.data.clone());
+            }
+        }
+
+        let state = match &user_sessions_msg.state {
+            Some(s) => Some(
+                serde_json::from_value::<SessionState>(serde_json::Value::String(s.clone()))
+                    .map_err(|_| SEError::Generic(format!("Unknown session state: {}", s)))?,
+            ),
+            None => None,
+        };
+        let limit = user_sessions_msg
+            .limit
+            .unwrap_or(MAX_USER_SESSIONS_PER_REQUEST as u64)
+            .min(MAX_USER_SESSIONS_PER_REQUEST as u64);
+        let offset = user_sessions_msg.offset;
+
+        let (records, total) = self.database.get_user_sessions(
+            &proof_keys,
+            state,
+            offset as i64,
+            limit as i64,
+        )?;
+        let sessions = records
+            .into_iter()
+            .map(|r| UserSessionAPI {
+                proof_key: r.proof_key,
+                shared_key_id: r.user_id,
+                statechain_id: r.statechain_id,
+                state: r.state.map(|s| format!("{:?}", s)),
+            })
+            .collect();
+
+        Ok(UserSessionsAPI {
+            sessions,
+            total: total as u64,
+            offset,
+        })
+    }
+
+    fn get_ownership_challenge(&self) -> Result<OwnershipChallenge> {
+        let now = Utc::now().naive_utc();
+        let expires = now + chrono::Duration::seconds(self.config.ownership_challenge_ttl as i64);
+        let nonce = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+
+        let mut guard = self.ownership_challenges.lock()?;
+        guard.retain(|_, expiry| *expiry > now);
+        if guard.len() >= MAX_OWNERSHIP_CHALLENGES {
+            return Err(SEError::Generic(String::from(
+                "Too many outstanding ownership challenges",
+            )));
+        }
+        guard.insert(nonce.clone(), expires);
+        Ok(OwnershipChallenge { nonce, expires })
+    }
+
+    fn prove_ownership(
+        &self,
+        ownership_proof_msg: OwnershipProofMsg,
+    ) -> Result<SignedOwnershipConfirmation> {
+        let identity_key = match self.config.identity_key {
+            Some(_) => self.identity_key()?,
+            None => {
+                return Err(SEError::Generic(String::from(
+                    "Ownership confirmation not enabled",
+                )))
+            }
+        };
+
+        let nonce = ownership_proof_msg.nonce;
+        let statechain_id = ownership_proof_msg.statechain_id;
+        // Nonces are single use: consume it whether or not the proof succeeds
+        match self.ownership_challenges.lock()?.remove(&nonce) {
+            Some(expiry) if expiry > Utc::now().naive_utc() => (),
+            _ => {
+                return Err(SEError::Generic(String::from(
+                    "Unknown or expired ownership challenge",
+                )))
+            }
+        }
+
+        let sc_amount = self.database.get_statechain_amount(statechain_id)?;
+        let tip = sc_amount.chain.get_tip();
+        if tip.next_state.is_some() {
+            return Err(SEError::Generic(format!(
+                "Statechain {} has been withdrawn",
+                statechain_id
+            )));
+        }
+        if !ownership_proof_msg.statechain_sig.is_ownership(&nonce, &statechain_id) {
+            return Err(SEError::Generic(format!(
+                "Ownership signature for statechain {} does not commit to nonce",
+                statechain_id
+            )));
+        }
+        ownership_proof_msg.statechain_sig.verify(&tip.data)?;
+
+        let confirmation = OwnershipConfirmation {
+            statechain_id,
+            nonce,
+            proof_key: tip.data.clone(),
+            amount: sc_amount.amount as u64,
+            confirmed_at: Utc::now().naive_utc(),
+            server_pubkey: PublicKey::from_secret_key(&Secp256k1::new(), &identity_key).to_string(),
+        };
+        info!("OWNERSHIP: Ownership of statechain {} confirmed", statechain_id);
+        Ok(sign_ownership_confirmation(&identity_key, confirmation)?)
+    }
+
+    fn get_build_manifest(&self) -> Result<SignedBuildManifest> {
+        let manifest = build_info::build_manifest();
+        match self.config.identity_key {
+            Some(_) => Ok(sign_build_manifest(&self.identity_key()?, manifest)?),
+            None => Ok(SignedBuildManifest {
+                manifest,
+                server_pubkey: None,
+                sig: None,
+            }),
+        }
+    }
+
     fn get_lockbox_url(&self, user_id: &Uuid) -> Result<Option<(Url,usize)>> {
         let db = &self.database;
 
@@ -498,6 +1215,186 @@ impl Utilities for SCE {
 
 }
 
+/// Check a tx to be co-signed does not spend more than its inputs and that the
+/// tx fee is within the maximum allowed. Returns the tx fee.
+pub fn tx_fee_verify(tx: &Transaction, input_amounts: &[u64], max_tx_fee: &u64) -> Result<u64> {
+    let input_total: u64 = input_amounts.iter().sum();
+    let output_total: u64 = tx.output.iter().map(|o| o.value).sum();
+    if output_total > input_total {
+        return Err(SEError::Generic(format!(
+            "Tx output value {} exceeds input value {}.",
+            output_total, input_total
+        )));
+    }
+    let tx_fee = input_total - output_total;
+    if tx_fee > *max_tx_fee {
+        return Err(SEError::Generic(format!(
+            "Tx fee {} exceeds maximum {}.",
+            tx_fee, max_tx_fee
+        )));
+    }
+    Ok(tx_fee)
+}
+
+/// Check a tx input amount is the value of the statecoin being spent
+fn verify_input_amount(input_amount: &u64, sc_amount: &crate::structs::StateChainAmount) -> Result<()> {
+    if *input_amount != sc_amount.amount as u64 {
+        return Err(SEError::Generic(format!(
+            "Input amount {} does not match statecoin value {}.",
+            input_amount, sc_amount.amount
+        )));
+    }
+    Ok(())
+}
+
+/// Least recently used cache of SMT inclusion proofs served by get_smt_proof, keyed by
+/// root id and funding txid. The cache is emptied whenever the current root is updated.
+#[derive(Debug, Default)]
+pub struct ProofCache {
+    capacity: usize,
+    tick: u64,
+    proofs: HashMap<(i64, String), (Option<Proof>, u64)>,
+    lru: BTreeMap<u64, (i64, String)>,
+}
+
+impl ProofCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&mut self, root_id: i64, funding_txid: &String) -> Option<Option<Proof>> {
+        let key = (root_id, funding_txid.clone());
+        let tick = self.tick + 1;
+        let (proof, used) = self.proofs.get_mut(&key)?;
+        self.lru.remove(used);
+        *used = tick;
+        self.tick = tick;
+        self.lru.insert(tick, key);
+        Some(proof.clone())
+    }
+
+    pub fn insert(&mut self, root_id: i64, funding_txid: String, proof: Option<Proof>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = (root_id, funding_txid);
+        if let Some((_, used)) = self.proofs.remove(&key) {
+            self.lru.remove(&used);
+        }
+        while self.proofs.len() >= self.capacity {
+            let oldest = match self.lru.keys().next() {
+                Some(t) => *t,
+                None => break,
+            };
+            if let Some(k) = self.lru.remove(&oldest) {
+                self.proofs.remove(&k);
+            }
+        }
+        self.tick += 1;
+        self.lru.insert(self.tick, key.clone());
+        self.proofs.insert(key, (proof, self.tick));
+    }
+
+    pub fn len(&self) -> usize {
+        self.proofs.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.proofs.clear();
+        self.lru.clear();
+    }
+}
+
+/// SMT updates accumulated for batch insertion. The first caller to add an update to a batch
+/// waits for the batch interval (or until the batch is full), inserts the whole batch in one
+/// tree operation and updates the root once. The other callers wait for its result.
+#[derive(Debug, Default)]
+pub struct SmtBatch {
+    state: Mutex<SmtBatchState>,
+    cvar: Condvar,
+    /// Serializes batch insertion and root updates
+    insert_lock: Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct SmtBatchState {
+    /// Id of the batch updates are currently added to
+    id: u64,
+    /// (funding txid, proof key, amount) updates of the current batch
+    pending: Vec<(String, String, u64)>,
+    /// True if a caller is waiting to insert the current batch
+    leader: bool,
+    /// Results of inserted batches, with the number of callers yet to collect them
+    done: HashMap<u64, (Result<(Option<Root>, Root)>, usize)>,
+}
+
+impl SmtBatch {
+    /// Add an update to the current batch and wait for the batch to be inserted by insert.
+    /// Returns the roots before and after the batch.
+    pub fn update<F>(&self, update: (String, String, u64), config: &SmtBatchConfig, insert: F)
+        -> Result<(Option<Root>, Root)>
+    where
+        F: FnOnce(&[(String, String, u64)]) -> Result<(Option<Root>, Root)>,
+    {
+        let lock_err = |e: String| SEError::Generic(format!("SMT batch lock poisoned: {}", e));
+        let mut state = self.state.lock().map_err(|e| lock_err(e.to_string()))?;
+        let id = state.id;
+        state.pending.push(update);
+        if state.pending.len() >= config.max_size {
+            self.cvar.notify_all();
+        }
+
+        if state.leader {
+            // Wait for the leader to insert the batch
+            loop {
+                if let Some((result, remaining)) = state.done.get_mut(&id) {
+                    let result = result.clone();
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        state.done.remove(&id);
+                    }
+                    return result;
+                }
+                state = self.cvar.wait(state).map_err(|e| lock_err(e.to_string()))?;
+            }
+        }
+
+        state.leader = true;
+        let deadline = Instant::now() + Duration::from_millis(config.interval);
+        loop {
+            let now = Instant::now();
+            if state.pending.len() >= config.max_size || now >= deadline {
+                break;
+            }
+            state = self
+                .cvar
+                .wait_timeout(state, deadline - now)
+                .map_err(|e| lock_err(e.to_string()))?
+                .0;
+        }
+        let batch = std::mem::take(&mut state.pending);
+        state.id += 1;
+        state.leader = false;
+        drop(state);
+
+        let result = {
+            let _guard = self.insert_lock.lock().map_err(|e| lock_err(e.to_string()))?;
+            insert(&batch)
+        };
+        debug!("SMT: Inserted batch of {} updates.", batch.len());
+
+        if batch.len() > 1 {
+            let mut state = self.state.lock().map_err(|e| lock_err(e.to_string()))?;
+            state.done.insert(id, (result.clone(), batch.len() - 1));
+            self.cvar.notify_all();
+        }
+        result
+    }
+}
+
 pub trait RateLimiter{
     fn check_rate_slow<T:'static+Into<String>>(&self, key: T) -> Result<()>;
     fn check_rate_fast<T:'static+Into<String>>(&self, key: T) -> Result<()>;
@@ -544,190 +1441,162 @@ impl RateLimiter for SCE {
     }
 }
 
-#[openapi]
-/// # Get statechain entity operating information
-#[get("/info/fee", format = "json")]
-pub fn get_fees(sc_entity: State<SCE>) -> Result<Json<StateEntityFeeInfoAPI>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_fees() {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
+/// Expiry of the fee quote issued periods_ago validity periods before now. Quotes are issued
+/// per period of 'validity' seconds and are valid until the end of the following period, so
+/// a quote is valid for between one and two periods and at most two quotes are valid at once.
+pub fn fee_quote_expiry(validity: u64, now: NaiveDateTime, periods_ago: i64) -> NaiveDateTime {
+    let validity = std::cmp::max(validity, 1) as i64;
+    let period = now.timestamp() / validity - periods_ago;
+    NaiveDateTime::from_timestamp((period + 2) * validity, 0)
 }
 
-#[openapi]
-/// # Get the current statecoin amount histogram
-#[get("/info/coins", format = "json")]
-pub fn get_coin_info(sc_entity: State<SCE>) -> Result<Json<CoinValueInfo>> {
-    sc_entity.check_rate_fast("info")?;
-    let guard = sc_entity.coin_value_info.as_ref().lock()?;
-    Ok(Json(guard.deref().clone()))
-}
 
-#[openapi]
-/// # Get current statechain information for specified statechain ID
-#[get("/info/statechain/<statechain_id>", format = "json")]
-pub fn get_statechain(
-    sc_entity: State<SCE>,
-    statechain_id: String,
-) -> Result<Json<StateChainDataAPI>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_statechain_data_api(Uuid::from_str(&statechain_id).unwrap()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+/// Check the output of a backup or withdraw tx paying the owner (output 0) is not below
+/// the dust limit
+pub fn verify_owner_output(tx: &Transaction, dust_limit: u64) -> Result<()> {
+    if tx.output[0].value < dust_limit {
+        return Err(SEError::Generic(format!(
+            "Tx output value {} is below the dust limit {}.",
+            tx.output[0].value, dust_limit
+        )));
     }
+    Ok(())
 }
 
-#[openapi]
-/// # Get current statecoin (statechain tip) information for specified statechain ID
-#[get("/info/statecoin/<statechain_id>", format = "json")]
-pub fn get_statecoin(
-    sc_entity: State<SCE>,
-    statechain_id: String,
-) -> Result<Json<StateCoinDataAPI>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_statecoin_data_api(Uuid::from_str(&statechain_id).unwrap()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+// Utily functions for StateChainEntity to be used throughout codebase.
+impl SCE {
+    /// Address the owner output of a backup tx pays to, on the configured network
+    pub fn backup_addr(&self, tx_backup: &Transaction) -> Result<bitcoin::Address> {
+        let network = self.config.network.parse::<Network>()
+            .map_err(|e| SEError::Generic(e.to_string()))?;
+        Ok(backup_tx_address(tx_backup, &network)?)
     }
-}
 
-#[openapi]
-/// # Get current statechain information for specified statechain ID
-#[get("/info/owner/<statechain_id>", format = "json")]
-pub fn get_owner_id(
-    sc_entity: State<SCE>,
-    statechain_id: String,
-) -> Result<Json<OwnerID>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_owner_id(Uuid::from_str(&statechain_id).unwrap()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Record the address the current backup tx of a statechain pays to in the statechain data
+    pub fn record_backup_addr(&self, statechain_id: &Uuid, tx_backup: &Transaction) -> Result<()> {
+        let addr = self.backup_addr(tx_backup)?;
+        self.database.update_backup_addr(statechain_id, &addr.to_string())
     }
-}
 
-#[openapi]
-/// # Get the current Sparse Merkle Tree commitment root
-#[get("/info/root", format = "json")]
-pub fn get_smt_root(sc_entity: State<SCE>) -> Result<Json<Option<Root>>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_smt_root() {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Full chain of states of a statechain, restoring pruned states from the archive
+    pub fn restore_statechain(&self, statechain_id: &Uuid, state_chain: &StateChain) -> Result<Vec<State>> {
+        if state_chain.get_pruned().is_none() {
+            return Ok(state_chain.get_chain().clone());
+        }
+        let segments = self.database.get_chain_segments(statechain_id)?;
+        state_chain.restore(&segments).map_err(|e| {
+            SEError::Generic(format!(
+                "State Chain ID {}: failed to restore pruned states: {}",
+                statechain_id, e
+            ))
+        })
     }
-}
 
-#[openapi]
-/// # Get the Merkle path proof for a specified statechain (TxID) and root
-#[post("/info/proof", format = "json", data = "<smt_proof_msg>")]
-pub fn get_smt_proof(
-    sc_entity: State<SCE>,
-    smt_proof_msg: Json<SmtProofMsgAPI>,
-) -> Result<Json<Option<Proof>>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_smt_proof(smt_proof_msg.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Fee quote valid until valid_until, signed with the identity key if set
+    fn fee_quote(&self, valid_until: NaiveDateTime) -> Result<StateEntityFeeInfoAPI> {
+        let fee_address_vec: Vec<&str> = self.config.fee_address.split(",").collect();
+        let quote = StateEntityFeeInfoAPI {
+            address: fee_address_vec[0].to_string().clone(),
+            deposit: self.config.fee_deposit as i64,
+            withdraw: self.config.fee_withdraw,
+            withdraw_input: self.config.fee_withdraw_input,
+            max_withdraw_inputs: self.config.max_withdraw_inputs,
+            interval: self.config.lh_decrement,
+            initlock: self.config.lockheight_init,
+            timelock_mode: self.config.backup_timelock_mode,
+            min_deposit: self.config.min_deposit,
+            dust_limit: self.config.dust_limit,
+            capacity: None,
+            deposits_paused: false,
+            wallet_version: self.config.wallet_version.clone(),
+            wallet_message: self.config.wallet_message.clone(),
+            valid_until: Some(valid_until),
+            sig: None,
+        };
+        match self.config.identity_key {
+            Some(_) => Ok(sign_fee_quote(&self.identity_key()?, quote)?),
+            None => Ok(quote),
+        }
     }
-}
 
-#[openapi]
-/// # Get transfer finalize data for specified statechain ID
-#[get("/info/sc-transfer-finalize-data/<statechain_id>", format = "json")]
-pub fn get_sc_transfer_finalize_data(
-    sc_entity: State<SCE>,
-    statechain_id: String,
-) -> Result<Json<TransferFinalizeData>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_sc_transfer_finalize_data(Uuid::from_str(&statechain_id).unwrap()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Get the currently valid fee quote acknowledged by a deposit. Quotes are not stored:
+    /// the quotes of the current and previous validity period are regenerated and compared.
+    pub fn get_acknowledged_fee_quote(&self, quote_hash: &String) -> Result<StateEntityFeeInfoAPI> {
+        let now = Utc::now().naive_utc();
+        for periods_ago in 0..2 {
+            let valid_until = fee_quote_expiry(self.config.fee_quote_validity, now, periods_ago);
+            if valid_until <= now {
+                continue;
+            }
+            let quote = self.fee_quote(valid_until)?;
+            if &fee_quote_hash(&quote)? == quote_hash {
+                return Ok(quote);
+            }
+        }
+        Err(SEError::Generic(String::from(
+            "Fee quote acknowledgement does not match a valid fee quote. Get a new quote from /info/fee.",
+        )))
     }
-}
 
-#[openapi]
-/// # Get batch transfer status and statecoin IDs for specified batch ID
-#[get("/info/transfer-batch/<batch_id>", format = "json")]
-pub fn get_transfer_batch_status(
-    sc_entity: State<SCE>,
-    batch_id: String,
-) -> Result<Json<TransferBatchDataAPI>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_transfer_batch_status(Uuid::from_str(&batch_id).unwrap()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Check that no SMT update of funding_txid is queued: its update is not in any published
+    /// root yet
+    fn check_smt_update_published(&self, funding_txid: &String) -> Result<()> {
+        if self.root_queue.is_pending(funding_txid)? {
+            return Err(SEError::TryAgain(format!(
+                "SMT update pending for funding txid {}. Retry after the next root update.",
+                funding_txid
+            )));
+        }
+        Ok(())
     }
-}
 
-#[openapi]
-/// # Recover statechain and backup transaction for proof key
-#[post("/info/recover", format = "json", data = "<request_recovery_data>")]
-pub fn get_recovery_data(
-    sc_entity: State<SCE>,
-    request_recovery_data: Json<Vec<RecoveryRequest>>,
-) -> Result<Json<Vec<RecoveryDataMsg>>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.get_recovery_data(request_recovery_data.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Generate an SMT inclusion proof for a stored root, using the proof cache
+    fn get_smt_proof_cached(&self, root: &Root, funding_txid: &String) -> Result<Option<Proof>> {
+        let root_id = root.id().unwrap_or_default();
+        if let Some(proof) = self.proof_cache.lock()?.get(root_id, funding_txid) {
+            return Ok(proof);
+        }
+        let proof = gen_proof_smt(self.smt.clone(), &Some(root.hash()), funding_txid)?;
+        self.proof_cache
+            .lock()?
+            .insert(root_id, funding_txid.clone(), proof.clone());
+        Ok(proof)
     }
-}
 
-#[openapi]
-/// # Submit transaction details to the server in preparation for 2P-ECDSA signing
-#[post("/prepare-sign", format = "json", data = "<prepare_sign_msg>")]
-pub fn prepare_sign_tx(
-    sc_entity: State<SCE>,
-    prepare_sign_msg: Json<PrepareSignTxMsg>,
-) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("info")?;
-    match sc_entity.prepare_sign_tx(prepare_sign_msg.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
+    /// Get the current statecoin amount histogram
+    pub fn get_coin_info(&self) -> Result<CoinValueInfo> {
+        let guard = self.coin_value_info.as_ref().lock()?;
+        Ok(guard.deref().clone())
     }
-}
 
-#[openapi]
-/// # Reset databases and in-RAM data if in testing mode
-#[get("/test/reset-db")]
-pub fn reset_test_dbs(sc_entity: State<SCE>) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("reset-db")?;
-    if sc_entity.config.testing_mode {
-        match sc_entity.database.reset() {
-            Ok(_res) => {
-                sc_entity.reset_data()?;
-                sc_entity.database.init(sc_entity.coin_value_info.as_ref(), 
-                    sc_entity.user_ids.as_ref())?;
-                return Ok(Json(()))
-            },
-            Err(e) => return Err(e),
+    /// Reset databases and in-RAM data. Only permitted in testing mode.
+    pub fn reset_test_dbs(&self) -> Result<()> {
+        if self.config.testing_mode {
+            self.database.reset()?;
+            self.reset_data()?;
+            self.database.init(self.coin_value_info.as_ref(),
+                self.user_ids.as_ref())?;
+            return Ok(());
         }
+        return Err(SEError::Generic(String::from(
+            "Cannot reset Databases when not in testing mode.",
+        )));
     }
-    return Err(SEError::Generic(String::from(
-        "Cannot reset Databases when not in testing mode.",
-    )));
-}
 
-#[openapi]
-/// # Reset databases and in-RAM data if in testing mode
-#[get("/test/reset-inram-data")]
-pub fn reset_inram_data(sc_entity: State<SCE>) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("reset-inram-data")?;
-    if sc_entity.config.testing_mode {
-        sc_entity.reset_data()?;
-        sc_entity.database.init(sc_entity.coin_value_info.as_ref(), 
-                    sc_entity.user_ids.as_ref())?;
-        return Ok(Json(()));
-    };
-
-    return Err(SEError::Generic(String::from(
-        "Cannot reset in-ram data when not in testing mode.",
-    )));
-}
+    /// Reset in-RAM data. Only permitted in testing mode.
+    pub fn reset_inram_data(&self) -> Result<()> {
+        if self.config.testing_mode {
+            self.reset_data()?;
+            self.database.init(self.coin_value_info.as_ref(),
+                self.user_ids.as_ref())?;
+            return Ok(());
+        };
 
+        return Err(SEError::Generic(String::from(
+            "Cannot reset in-ram data when not in testing mode.",
+        )));
+    }
 
-// Utily functions for StateChainEntity to be used throughout codebase.
-impl SCE {
     /// Query an Electrum Server for a transaction's confirmation status and address.
     /// Return Ok() if confirmed or Error if not within configured confirmation number.
     pub fn verify_tx_confirmed(&self, statechain_id: &Uuid) -> Result<()> {
@@ -768,7 +1637,12 @@ impl SCE {
         match electrum.get_transaction_conf_status(txid.clone(), false) {
             Ok(res) => {
                 // Check for tx confs. If none after 10*(block time) then return error.
-                if res.confirmations.is_none() {
+                if res.in_active_chain == Some(false) {
+                    return Err(SEError::Generic(String::from(
+                        "Funding Transaction not in active chain.",
+                    )));
+                }
+                else if res.confirmations.is_none() {
                     return Err(SEError::Generic(String::from(
                         "Funding Transaction not confirmed.",
                     )));
@@ -821,14 +1695,12 @@ impl SCE {
             )));
         }
 
-        self.database.update_locked_until(
-            &statechain_id,
-            &get_locked_until(self.config.conductor.punishment_duration as i64)?,
-        )?;
+        let locked_until = self.punish(&statechain_id, Offence::Batch)?;
+        self.database.update_locked_until(&statechain_id, &locked_until)?;
 
         info!(
-            "PUNISHMENT: State Chain ID: {} locked for {}s.",
-            statechain_id, self.config.conductor.punishment_duration
+            "PUNISHMENT: State Chain ID: {} locked until {}.",
+            statechain_id, locked_until
         );
         Ok(())
     }
@@ -872,7 +1744,7 @@ impl SCE {
             }
             // Check batch is still within lifetime
             debug!("TRANSFER_BATCH: checking if batch transfer has ended");
-            if transfer_batch_is_ended(tbd.start_time, self.config.batch_lifetime as i64) {
+            if self.transfer_batch_ended(&batch_id, tbd.start_time)? {
                 let mut punished_state_chains: Vec<Uuid> =
                     self.database.get_punished_state_chains(batch_id)?;
 
@@ -894,7 +1766,7 @@ impl SCE {
                     }
 
                     self.database
-                        .update_punished(&batch_id, punished_state_chains)?;
+                        .update_punished(&batch_id, punished_state_chains, tbd.version)?;
 
                     info!(
                         "TRANSFER_BATCH: Punished all state chains in failed batch. ID: {}.",
@@ -913,6 +1785,67 @@ impl SCE {
             finalized,
         })
     }
+
+    /// The SMT root each state of a statechain was first included in, with the attestation
+    /// status of the root. The funding txid is taken from the backup tx, or from the withdraw
+    /// tx once the backup tx has been removed.
+    fn state_roots(
+        &self,
+        statechain_id: &Uuid,
+        chain: &[State],
+        tx_backup: &Option<Transaction>,
+    ) -> Result<Vec<Option<StateRootAPI>>> {
+        let funding_txid = match tx_backup {
+            Some(tx) => Some(tx.input[0].previous_output.txid),
+            None => self
+                .database
+                .get_owner_id(*statechain_id)
+                .and_then(|user_id| self.database.get_withdraw_confirm_data(user_id))
+                .ok()
+                .map(|wcd| wcd.tx_withdraw.input[0].previous_output.txid),
+        };
+        let leaves = match funding_txid {
+            Some(txid) => self.database.get_root_leaves(&txid.to_string())?,
+            None => vec![],
+        };
+        attribute_state_roots(chain, &leaves)
+            .into_iter()
+            .map(|root_id| match root_id {
+                Some(id) => {
+                    let root = self.database.get_root_info(id)?.ok_or(SEError::DBError(
+                        DBErrorType::NoDataForID,
+                        format!("Root id: {}", id),
+                    ))?;
+                    Ok(Some(StateRootAPI {
+                        root_id: id,
+                        root: hex::encode(root.hash()),
+                        confirmed: self.database.is_root_confirmed(id)?,
+                    }))
+                }
+                None => Ok(None),
+            })
+            .collect()
+    }
+}
+
+/// Attribute each state of a chain to the first root, after the root of the previous state,
+/// that set the SMT leaf of the statecoin to the state data. leaves are the (root id, value)
+/// of the leaf in root order.
+pub fn attribute_state_roots(chain: &[State], leaves: &[(i64, String)]) -> Vec<Option<i64>> {
+    let mut after = 0;
+    chain
+        .iter()
+        .map(|state| {
+            let root_id = leaves
+                .iter()
+                .find(|(id, value)| *id > after && value == &state.data)
+                .map(|(id, _)| *id);
+            if let Some(id) = root_id {
+                after = id;
+            }
+            root_id
+        })
+        .collect()
 }
 
 impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync + 'static> Storage
@@ -923,6 +1856,11 @@ impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync +
         let mut guard_ids_mutex = self.user_ids.as_ref().lock()?;
         guard_coins_mutex.clear();
         guard_ids_mutex.clear();
+        self.proof_cache.lock()?.clear();
+        *self.fee_info_cache.lock()? = None;
+        self.user_sessions_challenges.lock()?.clear();
+        self.ownership_challenges.lock()?.clear();
+        self.root_queue.clear()?;
         Ok(())
     }
 
@@ -939,6 +1877,8 @@ impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync +
         };
 
         let id = db.root_update(root)?;
+        // Proofs are served against the current root only
+        self.proof_cache.lock()?.clear();
         Ok(id)
     }
 
@@ -947,7 +1887,40 @@ impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync +
         &self,
         funding_txid: &String,
         proof_key: &String,
-    ) -> Result<(Option<Root>, Root)> {
+        amount: u64,
+    ) -> Result<(Option<Root>, Option<Root>)> {
+        if !self.config.root_update.is_immediate() {
+            let due = self.root_queue.push(
+                (funding_txid.clone(), proof_key.clone(), amount),
+                &self.config.root_update,
+            )?;
+            let current_root = self.get_smt_root()?;
+            if !due {
+                return Ok((current_root, None));
+            }
+            // The update stays queued if publication fails, and is retried with the next
+            return match self.root_queue.publish(|batch| self.update_smt_batch(batch)) {
+                Ok(Some((prev_root, new_root))) => Ok((prev_root, Some(new_root))),
+                // Published by another caller
+                Ok(None) => Ok((current_root, None)),
+                Err(e) => {
+                    warn!("ROOT: Root update failed: {}", e);
+                    Ok((current_root, None))
+                }
+            };
+        }
+
+        if self.config.smt_batch.interval > 0 {
+            return self
+                .smt_batch
+                .update(
+                    (funding_txid.clone(), proof_key.clone(), amount),
+                    &self.config.smt_batch,
+                    |batch| self.update_smt_batch(batch),
+                )
+                .map(|(current_root, new_root)| (current_root, Some(new_root)));
+        }
+
         let db = &self.database;
 
         //If mocked out current_root will be randomly chosen
@@ -959,10 +1932,38 @@ impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync +
             &current_root.clone().map(|r| r.hash()),
             funding_txid,
             proof_key,
+            amount,
+        )?;
+
+        let mut new_root = Root::from_hash(&new_root_hash.unwrap());
+        let new_root_id = self.update_root(&new_root)?; // Update current root
+        new_root.set_id(&new_root_id);
+        db.insert_root_leaves(new_root_id, &[(funding_txid.clone(), proof_key.clone())])?;
+
+        Ok((current_root, Some(new_root)))
+    }
+
+    // Update SMT with a batch of (key: value) pairs and update current root value once
+    fn update_smt_batch(&self, batch: &[(String, String, u64)]) -> Result<(Option<Root>, Root)> {
+        let db = &self.database;
+
+        let current_root_id = db.root_get_current_id()?;
+        let current_root = db.get_root(current_root_id)?;
+
+        let new_root_hash = update_statechain_smt_batch(
+            self.smt.clone(),
+            &current_root.clone().map(|r| r.hash()),
+            batch,
         )?;
 
-        let new_root = Root::from_hash(&new_root_hash.unwrap());
-        self.update_root(&new_root)?; // Update current root
+        let mut new_root = Root::from_hash(&new_root_hash.unwrap());
+        let new_root_id = self.update_root(&new_root)?; // Update current root
+        new_root.set_id(&new_root_id);
+        let leaves: Vec<(String, String)> = batch
+            .iter()
+            .map(|(funding_txid, proof_key, _)| (funding_txid.clone(), proof_key.clone()))
+            .collect();
+        db.insert_root_leaves(new_root_id, &leaves)?;
 
         Ok((current_root, new_root))
     }
@@ -1129,38 +2130,68 @@ impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync +
     fn get_statechain_data_api(&self, statechain_id: Uuid) -> Result<StateChainDataAPI> {
         //let statechain_id = Uuid::from_str(&statechain_id).unwrap();
 
-        let state_chain = self.database.get_statechain_amount(statechain_id)?;
+        let state_chain = self.database.get_statechain_info(statechain_id)?;
+
+        let chain = self.restore_statechain(&statechain_id, &state_chain.chain)?;
 
-        let state = state_chain.chain.get_first().next_state.clone();
+        let state = chain[0].next_state.clone();
 
         if state.is_some() {
                 if state.unwrap().purpose == String::from("WITHDRAW") {
                     return Ok({StateChainDataAPI {
                         amount: state_chain.amount as u64,
                         utxo: OutPoint::null(),
-                        chain: state_chain.chain.get_chain().clone(),
+                        chain: chain.clone(),
                         locktime: 0 as u32,
                         confirmed: true,
+                        archived: state_chain.archived,
+                        swap_fee_owed: 0,
+                        state_roots: self.state_roots(
+                            &statechain_id,
+                            &chain,
+                            &state_chain.tx_backup,
+                        )?,
+                        spend_incident: None,
+                        backup_addr: None,
                     }});
                 }
             }
 
-        let tx_backup = self.database.get_backup_transaction(statechain_id.clone())?;
+        let tx_backup = state_chain.tx_backup.clone().ok_or(SEError::DBError(
+            DBErrorType::NoDataForID,
+            statechain_id.to_string(),
+        ))?;
 
-        let confirmed = self.database.is_confirmed(&statechain_id)?;
+        // Archived statechains keep only the last backup tx
+        let backup_addr = if state_chain.archived {
+            self.config.network.parse::<Network>().ok()
+                .and_then(|network| backup_tx_address(&tx_backup, &network).ok())
+                .map(|addr| addr.to_string())
+        } else {
+            self.database.get_backup_addr(&statechain_id)?
+        };
 
         return Ok({StateChainDataAPI {
             amount: state_chain.amount as u64,
             utxo: tx_backup.input.get(0).unwrap().previous_output,
-            chain: state_chain.chain.get_chain().clone(),
-            locktime: tx_backup.lock_time,
-            confirmed
+            chain: chain.clone(),
+            locktime: backup_tx_timelock(&tx_backup),
+            confirmed: state_chain.confirmed,
+            archived: state_chain.archived,
+            swap_fee_owed: self.swap_fee_owed(&statechain_id)?,
+            state_roots: self.state_roots(
+                &statechain_id,
+                &chain,
+                &state_chain.tx_backup,
+            )?,
+            spend_incident: self.database.get_spend_incident(&statechain_id)?,
+            backup_addr,
         }});
     }
 
     fn get_statecoin_data_api(&self, statechain_id: Uuid) -> Result<StateCoinDataAPI> {
 
-        let state_chain = self.database.get_statechain_amount(statechain_id)?;
+        let state_chain = self.database.get_statechain_info(statechain_id)?;
 
         let statecoin = state_chain.chain.get_tip();
 
@@ -1173,22 +2204,27 @@ impl<T: Database + Send + Sync + 'static, D: monotree::Database + Send + Sync +
                         statecoin: statecoin.to_owned(),
                         locktime: 0 as u32,
                         confirmed: true,
+                        archived: state_chain.archived,
+                        spend_incident: None,
                     }});
                 }
             },
             None => ()
         };
         
-        let tx_backup = self.database.get_backup_transaction(statechain_id)?;
-
-        let confirmed = self.database.is_confirmed(&statechain_id)?;
+        let tx_backup = state_chain.tx_backup.as_ref().ok_or(SEError::DBError(
+            DBErrorType::NoDataForID,
+            statechain_id.to_string(),
+        ))?;
 
         return Ok({StateCoinDataAPI {
             amount: state_chain.amount as u64,
             utxo: tx_backup.input.get(0).unwrap().previous_output,
             statecoin: statecoin.to_owned(),
-            locktime: tx_backup.lock_time,
-            confirmed,
+            locktime: backup_tx_timelock(tx_backup),
+            confirmed: state_chain.confirmed,
+            archived: state_chain.archived,
+            spend_incident: self.database.get_spend_incident(&statechain_id)?,
         }});
     }
 
@@ -1303,7 +2339,8 @@ pub mod mocks {
 pub mod tests {
     use super::*;
     use crate::shared_lib::mainstay;
-    use crate::{structs::StateChainAmount, MockDatabase};
+    use shared_lib::mocks::mock_electrum::TxScenario;
+    use crate::{structs::{StateChainAmount, StateChainInfo, UserSessionRecord}, MockDatabase};
     use monotree::database::{Database as monotreeDatabase, MemoryDB};
     use std::convert::TryInto;
     use std::str::FromStr;
@@ -1322,6 +2359,17 @@ pub mod tests {
     pub static SHAREDPUBLIC: &str = "{\"x\":\"f8308498a5b5996eb7c410fb7ada7f3524d604b45b247cc4d13e5a32c3763908\",\"y\":\"7e41091fd5ab1138d1a3cdf41b43c82a064839a6b82b251be2be70099b642d1a\"}";
     pub static SHAREDPUBLIC2: &str = "{\"x\":\"5922d902bc02956aac861f25cb3bc5d839879fbeed2818984143bff3a4eddc7\",\"y\":\"da7baea75521527df867f7ff3c996fc18732200f2445e879fbbcb662f7c835d4\"}";
 
+    /// Malformed signatures as might be sent in requests
+    pub fn malformed_sigs() -> Vec<String> {
+        let valid_sig = StateChainSig::example().sig;
+        vec![
+            String::from(""),
+            String::from("zz"),
+            "ab".repeat(200),
+            valid_sig[..valid_sig.len() - 2].to_string(),
+            format!("{}{}", "a".repeat(31), "\u{e9}"),
+        ]
+    }
 
     pub fn test_sc_entity(db: MockDatabase, 
             lockbox_url: Option<String>, 
@@ -1351,12 +2399,14 @@ pub mod tests {
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_root_update().returning(|_| Ok(1 as i64));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root()
             .returning(|_x| Ok(Some(Root::from_random())));
         db.expect_root_update().returning(|_x| Ok(1));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         db.expect_get_confirmed_smt_root()
             .returning(|| Ok(Some(Root::from_random())));
 
@@ -1415,18 +2465,36 @@ pub mod tests {
         assert!(rootc.is_confirmed(), "expected root to be confirmed");
     }
 
+    /// Root of a tree holding only the leaf inserted by the update_smt tests
+    fn smt_root_exp() -> [u8; 32] {
+        use monotree::{hasher::Blake3, Monotree};
+        use std::sync::Arc;
+        let tree = Arc::new(Mutex::new(Monotree::<MemoryDB, Blake3>::new("")));
+        update_statechain_smt(
+            tree,
+            &None,
+            &"1dcaca3b140dfbfe7e6a2d6d7cafea5cdb905178ee5d377804d8337c2c35f62e".to_string(),
+            &"026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e".to_string(),
+            10000,
+        )
+        .unwrap()
+        .unwrap()
+    }
+
     #[test]
     #[serial]
     fn test_update_root_smt() {
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_root_update().returning(|_| Ok(1 as i64));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root()
             .returning(|_x| Ok(Some(Root::from_random())));
         db.expect_root_update().returning(|_x| Ok(1));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         db.expect_get_confirmed_smt_root()
             .returning(|| Ok(Some(Root::from_random())));
         let sc_entity = test_sc_entity(db, None, None, None, None);
@@ -1438,16 +2506,520 @@ pub mod tests {
             .update_smt(
                 &"1dcaca3b140dfbfe7e6a2d6d7cafea5cdb905178ee5d377804d8337c2c35f62e".to_string(),
                 &"026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e".to_string(),
+                10000,
             )
             .unwrap();
+        let new_root = new_root.unwrap();
+
+        let hash_exp = smt_root_exp();
+
+        assert_eq!(new_root.hash(), hash_exp, "new root incorrect");
+    }
+
+    #[test]
+    #[serial]
+    fn test_update_smt_batched() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        db.expect_get_root().returning(|_| Ok(None));
+        db.expect_root_update().times(1).returning(|_| Ok(2 as i64));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.mainstay = None;
+        sc_entity.config.smt_batch.interval = 10;
 
-        let hash_exp: [u8; 32] =
-            hex::decode("cfeecaedcbaa90b750637ad2044b2e4b6425bd1430fc7250dceb28053a7e2733")
-                .unwrap()[..]
-                .try_into()
-                .unwrap();
+        let (current_root, new_root) = sc_entity
+            .update_smt(
+                &"1dcaca3b140dfbfe7e6a2d6d7cafea5cdb905178ee5d377804d8337c2c35f62e".to_string(),
+                &"026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e".to_string(),
+                10000,
+            )
+            .unwrap();
+        let new_root = new_root.unwrap();
 
+        // Same root as an unbatched update
+        let hash_exp = smt_root_exp();
+        assert_eq!(current_root, None);
         assert_eq!(new_root.hash(), hash_exp, "new root incorrect");
+        assert_eq!(new_root.id(), Some(2));
+    }
+
+    #[test]
+    fn test_smt_batch() {
+        use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+        use std::thread;
+
+        let n = 8;
+        let batch = Arc::new(SmtBatch::default());
+        let inserts = Arc::new(AtomicUsize::new(0));
+        // The batch is inserted once it is full, well before the interval
+        let config = SmtBatchConfig {
+            interval: 60000,
+            max_size: n,
+        };
+
+        let handles: Vec<_> = (0..n)
+            .map(|i| {
+                let batch = batch.clone();
+                let inserts = inserts.clone();
+                let config = config.clone();
+                thread::spawn(move || {
+                    batch.update((format!("txid{}", i), format!("key{}", i)), &config, |updates| {
+                        inserts.fetch_add(1, Ordering::SeqCst);
+                        assert_eq!(updates.len(), n);
+                        Ok((None, Root::from_random()))
+                    })
+                })
+            })
+            .collect();
+        let results: Vec<(Option<Root>, Root)> =
+            handles.into_iter().map(|h| h.join().unwrap().unwrap()).collect();
+
+        assert_eq!(inserts.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|r| r == &results[0]));
+        assert!(batch.state.lock().unwrap().done.is_empty());
+
+        // Insert errors are returned to the caller
+        let config = SmtBatchConfig {
+            interval: 0,
+            max_size: 1,
+        };
+        assert!(batch
+            .update((String::from("txid"), String::from("key")), &config, |_| {
+                Err(SEError::Generic(String::from("insert failed")))
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_proof_cache() {
+        let mut cache = ProofCache::new(2);
+        let txid1 = String::from("txid1");
+        let txid2 = String::from("txid2");
+        let txid3 = String::from("txid3");
+        cache.insert(1, txid1.clone(), None);
+        cache.insert(1, txid2.clone(), None);
+        assert_eq!(cache.get(1, &txid1), Some(None));
+        assert_eq!(cache.get(2, &txid1), None);
+
+        // txid2 is least recently used and is evicted
+        cache.insert(1, txid3.clone(), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(1, &txid2), None);
+        assert_eq!(cache.get(1, &txid1), Some(None));
+        assert_eq!(cache.get(1, &txid3), Some(None));
+
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+
+        let mut cache = ProofCache::new(0);
+        cache.insert(1, txid1.clone(), None);
+        assert_eq!(cache.get(1, &txid1), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_smt_proofs() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_root_update().returning(|_| Ok(1 as i64));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        db.expect_get_root().times(1).returning(|_| Ok(None));
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+        let _m = mocks::ms::post_commitment().create();
+
+        let funding_txid =
+            "1dcaca3b140dfbfe7e6a2d6d7cafea5cdb905178ee5d377804d8337c2c35f62e".to_string();
+        let (_, root) = sc_entity
+            .update_smt(
+                &funding_txid,
+                &"026ff25fd651cd921fc490a6691f0dd1dcbf725510f1fbd80d7bf7abdfef7fea0e".to_string(),
+                10000,
+            )
+            .unwrap();
+        let root = root.unwrap();
+
+        // Too many txids
+        let smt_proofs_msg = SmtProofsMsgAPI {
+            funding_txids: vec![funding_txid.clone(); MAX_PROOFS_PER_REQUEST + 1],
+            proof_format: ProofFormat::Json,
+        };
+        match sc_entity.get_smt_proofs(smt_proofs_msg) {
+            Err(SEError::Generic(e)) => assert!(e.contains("Too many proofs")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        let mut db = MockDatabase::new();
+        db.expect_root_get_current_id().returning(|| Ok(1 as i64));
+        let root_clone = root.clone();
+        db.expect_get_root().returning(move |_| Ok(Some(root_clone.clone())));
+        let mut sc_entity = sc_entity;
+        sc_entity.database = db;
+
+        let unknown_txid =
+            "c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e".to_string();
+        let smt_proofs = sc_entity
+            .get_smt_proofs(SmtProofsMsgAPI {
+                funding_txids: vec![funding_txid.clone(), unknown_txid.clone()],
+                proof_format: ProofFormat::Json,
+            })
+            .unwrap();
+        assert_eq!(smt_proofs.root.hash(), root.hash());
+        assert_eq!(smt_proofs.proofs.len(), 2);
+        assert_eq!(smt_proofs.proofs[0].funding_txid, funding_txid);
+        assert!(smt_proofs.proofs[0].proof.is_some());
+        assert_eq!(smt_proofs.proofs[1].funding_txid, unknown_txid);
+
+        // Compact encoding decodes to the same proofs
+        let compact = sc_entity
+            .get_smt_proofs(SmtProofsMsgAPI {
+                funding_txids: vec![funding_txid.clone(), unknown_txid.clone()],
+                proof_format: ProofFormat::Compact,
+            })
+            .unwrap();
+        match &compact.proofs[0].proof {
+            Some(EncodedProof::Compact(_)) => (),
+            p => assert!(false, "expected compact proof, got {:?}", p),
+        }
+        assert_eq!(compact.decode_proofs().unwrap(), smt_proofs.decode_proofs().unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_statechain_updates() {
+        use mockall::predicate;
+        let time = NaiveDateTime::from_str("2021-03-01T12:00:00.5").unwrap();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_statechains_updated_since()
+            .with(predicate::eq(UpdatesSince::RootId(5)), predicate::always())
+            .returning(|_, limit| {
+                Ok((0..limit)
+                    .map(|i| (Uuid::new_v4(), NaiveDateTime::from_timestamp(i, 0)))
+                    .collect())
+            });
+        db.expect_get_statechains_updated_since()
+            .with(predicate::eq(UpdatesSince::Time(time)), predicate::always())
+            .returning(|_, _| Ok(vec![(Uuid::new_v4(), NaiveDateTime::from_timestamp(1, 0))]));
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Limit reached: the checkpoint is the last returned modification time
+        let updates = sc_entity.get_statechain_updates(&String::from("5")).unwrap();
+        assert!(updates.more);
+        assert_eq!(updates.statechain_ids.len(), MAX_UPDATES_PER_REQUEST);
+        assert_eq!(
+            updates.checkpoint,
+            NaiveDateTime::from_timestamp(MAX_UPDATES_PER_REQUEST as i64 - 1, 0)
+        );
+
+        let updates = sc_entity
+            .get_statechain_updates(&String::from("2021-03-01T12:00:00.5"))
+            .unwrap();
+        assert!(!updates.more);
+        assert_eq!(updates.statechain_ids.len(), 1);
+        assert!(updates.checkpoint > time);
+
+        match sc_entity.get_statechain_updates(&String::from("yesterday")) {
+            Err(SEError::Generic(e)) => assert!(e.contains("Invalid update checkpoint")),
+            _ => assert!(false, "expected Generic error"),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_fees_cached() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        let fee_info = sc_entity.get_fees().unwrap();
+        sc_entity.config.fee_withdraw = fee_info.withdraw + 1;
+        assert_eq!(sc_entity.get_fees().unwrap().withdraw, fee_info.withdraw);
+        sc_entity.reset_data().unwrap();
+        assert_eq!(sc_entity.get_fees().unwrap().withdraw, fee_info.withdraw + 1);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_params() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.conductor.permitted_groups = String::from("100000,500000");
+        sc_entity.config.conductor.group_timeout = 30;
+        let params = sc_entity.get_params().unwrap();
+        assert_eq!(params.denominations, vec![100000, 500000]);
+        assert_eq!(params.swap_timeout, 30);
+        assert_eq!(params.interval, sc_entity.config.lh_decrement);
+        assert_eq!(params.batch_lifetime, sc_entity.config.batch_lifetime);
+        assert_eq!(params.punishment_duration, sc_entity.config.conductor.punishment_duration);
+    }
+
+    #[test]
+    fn test_fee_quote_expiry() {
+        let now = NaiveDateTime::from_timestamp(10_050, 0);
+        assert_eq!(fee_quote_expiry(100, now, 0), NaiveDateTime::from_timestamp(10_200, 0));
+        assert_eq!(fee_quote_expiry(100, now, 1), NaiveDateTime::from_timestamp(10_100, 0));
+        // A quote is valid for between one and two periods
+        for t in 10_000..10_100 {
+            let remaining = fee_quote_expiry(100, NaiveDateTime::from_timestamp(t, 0), 0).timestamp() - t;
+            assert!(remaining > 100 && remaining <= 200);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_fees_signed() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.identity_key = None;
+        sc_entity.config.export_key = Some(hex::encode(&[4; 32]));
+        let fee_info = sc_entity.get_fees().unwrap();
+        assert!(fee_info.sig.is_none());
+        assert!(fee_info.valid_until.unwrap() > Utc::now().naive_utc());
+
+        sc_entity.reset_data().unwrap();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        sc_entity.config.identity_key = Some(hex::encode(&[3; 32]));
+        let fee_info = sc_entity.get_fees().unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &identity_key).to_string();
+        assert!(verify_fee_quote(&fee_info, &server_pubkey).is_ok());
+
+        let quote_hash = fee_quote_hash(&fee_info).unwrap();
+        let quote = sc_entity.get_acknowledged_fee_quote(&quote_hash).unwrap();
+        assert_eq!(quote.sig, fee_info.sig);
+        assert!(sc_entity.get_acknowledged_fee_quote(&String::from("00")).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_user_sessions() {
+        let secp = Secp256k1::new();
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &proof_key_priv).to_string();
+        let user_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let expected_keys = vec![proof_key.clone()];
+        let record = UserSessionRecord {
+            user_id,
+            proof_key: proof_key.clone(),
+            statechain_id: Some(statechain_id),
+            state: Some(SessionState::Owner),
+        };
+        db.expect_get_user_sessions()
+            .withf(move |keys, state, offset, limit| {
+                *keys == expected_keys
+                    && *state == Some(SessionState::Owner)
+                    && *offset == 5
+                    && *limit == MAX_USER_SESSIONS_PER_REQUEST as i64
+            })
+            .times(1)
+            .returning(move |_, _, _, _| Ok((vec![record.clone()], 6)));
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        let challenge = sc_entity.get_user_sessions_challenge().unwrap().challenge;
+        let sig = StateChainSig::new_user_sessions_sig(&proof_key_priv, &challenge, &proof_key).unwrap();
+        let request = UserSessionsRequest {
+            challenge: challenge.clone(),
+            sigs: vec![sig.clone(), sig.clone()],
+            state: Some(String::from("Owner")),
+            offset: 5,
+            limit: Some(1_000_000),
+        };
+        let sessions = sc_entity.get_user_sessions(request.clone()).unwrap();
+        assert_eq!(sessions.total, 6);
+        assert_eq!(sessions.offset, 5);
+        assert_eq!(
+            sessions.sessions,
+            vec![UserSessionAPI {
+                proof_key: proof_key.clone(),
+                shared_key_id: user_id,
+                statechain_id: Some(statechain_id),
+                state: Some(String::from("Owner")),
+            }]
+        );
+
+        // challenges are single use
+        match sc_entity.get_user_sessions(request) {
+            Err(SEError::Generic(e)) => assert!(e.contains("Unknown or expired")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        // signature over another challenge
+        let challenge = sc_entity.get_user_sessions_challenge().unwrap().challenge;
+        let request = UserSessionsRequest {
+            challenge,
+            sigs: vec![sig],
+            state: None,
+            offset: 0,
+            limit: None,
+        };
+        match sc_entity.get_user_sessions(request) {
+            Err(SEError::Generic(e)) => assert!(e.contains("does not commit to challenge")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        // signature by another key
+        let challenge = sc_entity.get_user_sessions_challenge().unwrap().challenge;
+        let other_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let request = UserSessionsRequest {
+            challenge: challenge.clone(),
+            sigs: vec![StateChainSig::new_user_sessions_sig(&other_priv, &challenge, &proof_key).unwrap()],
+            state: None,
+            offset: 0,
+            limit: None,
+        };
+        assert!(sc_entity.get_user_sessions(request).is_err());
+
+        // unknown state filter
+        let challenge = sc_entity.get_user_sessions_challenge().unwrap().challenge;
+        let request = UserSessionsRequest {
+            challenge: challenge.clone(),
+            sigs: vec![StateChainSig::new_user_sessions_sig(&proof_key_priv, &challenge, &proof_key).unwrap()],
+            state: Some(String::from("Spent")),
+            offset: 0,
+            limit: None,
+        };
+        match sc_entity.get_user_sessions(request) {
+            Err(SEError::Generic(e)) => assert!(e.contains("Unknown session state")),
+            _ => assert!(false, "expected Generic error"),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_user_sessions_challenge_expired() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_sessions().times(0);
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.user_sessions_challenge_ttl = 0;
+
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&Secp256k1::new(), &proof_key_priv).to_string();
+        sc_entity.get_user_sessions_challenge().unwrap();
+        let challenge = sc_entity.get_user_sessions_challenge().unwrap().challenge;
+        // expired challenges are pruned when new challenges are issued
+        assert_eq!(sc_entity.user_sessions_challenges.lock().unwrap().len(), 1);
+
+        let request = UserSessionsRequest {
+            challenge: challenge.clone(),
+            sigs: vec![StateChainSig::new_user_sessions_sig(&proof_key_priv, &challenge, &proof_key).unwrap()],
+            state: None,
+            offset: 0,
+            limit: None,
+        };
+        match sc_entity.get_user_sessions(request) {
+            Err(SEError::Generic(e)) => assert!(e.contains("Unknown or expired")),
+            _ => assert!(false, "expected Generic error"),
+        }
+        assert_eq!(sc_entity.user_sessions_challenges.lock().unwrap().len(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_prove_ownership() {
+        let secp = Secp256k1::new();
+        let statechain_id = Uuid::new_v4();
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &proof_key_priv).to_string();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let chain_proof_key = proof_key.clone();
+        db.expect_get_statechain_amount().returning(move |_| {
+            Ok(StateChainAmount {
+                chain: StateChain::new(chain_proof_key.clone()),
+                amount: 100000,
+            })
+        });
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // disabled without an identity key, even with an export key
+        sc_entity.config.identity_key = None;
+        sc_entity.config.export_key = Some(hex::encode(&[4; 32]));
+        let nonce = sc_entity.get_ownership_challenge().unwrap().nonce;
+        let msg = OwnershipProofMsg {
+            statechain_id,
+            nonce: nonce.clone(),
+            statechain_sig: StateChainSig::new_ownership_sig(&proof_key_priv, &nonce, &statechain_id).unwrap(),
+        };
+        match sc_entity.prove_ownership(msg) {
+            Err(SEError::Generic(e)) => assert!(e.contains("not enabled")),
+            _ => assert!(false, "expected Generic error"),
+        }
+        sc_entity.config.identity_key = Some(hex::encode(&[3; 32]));
+
+        let nonce = sc_entity.get_ownership_challenge().unwrap().nonce;
+        let msg = OwnershipProofMsg {
+            statechain_id,
+            nonce: nonce.clone(),
+            statechain_sig: StateChainSig::new_ownership_sig(&proof_key_priv, &nonce, &statechain_id).unwrap(),
+        };
+        let signed = sc_entity.prove_ownership(msg.clone()).unwrap();
+        assert_eq!(signed.confirmation.proof_key, proof_key);
+        assert_eq!(signed.confirmation.amount, 100000);
+        assert!(verify_ownership_confirmation(&signed, &server_pubkey, &statechain_id, &nonce).is_ok());
+
+        // nonces are single use
+        match sc_entity.prove_ownership(msg) {
+            Err(SEError::Generic(e)) => assert!(e.contains("Unknown or expired")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        // signature over another nonce
+        let nonce = sc_entity.get_ownership_challenge().unwrap().nonce;
+        let msg = OwnershipProofMsg {
+            statechain_id,
+            nonce: nonce.clone(),
+            statechain_sig: StateChainSig::new_ownership_sig(&proof_key_priv, &String::from("other"), &statechain_id).unwrap(),
+        };
+        match sc_entity.prove_ownership(msg) {
+            Err(SEError::Generic(e)) => assert!(e.contains("does not commit to nonce")),
+            _ => assert!(false, "expected Generic error"),
+        }
+
+        // signature by a key other than the current owner
+        let nonce = sc_entity.get_ownership_challenge().unwrap().nonce;
+        let other_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let msg = OwnershipProofMsg {
+            statechain_id,
+            nonce: nonce.clone(),
+            statechain_sig: StateChainSig::new_ownership_sig(&other_priv, &nonce, &statechain_id).unwrap(),
+        };
+        assert!(sc_entity.prove_ownership(msg).is_err());
+    }
+
+    #[test]
+    fn test_get_build_manifest() {
+        let secp = Secp256k1::new();
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // unsigned without an identity key
+        let unsigned = sc_entity.get_build_manifest().unwrap();
+        assert_eq!(unsigned.manifest, build_info::build_manifest());
+        assert!(unsigned.server_pubkey.is_none() && unsigned.sig.is_none());
+
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        sc_entity.config.identity_key = Some(hex::encode(&identity_key[..]));
+        let signed = sc_entity.get_build_manifest().unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+        assert_eq!(signed.server_pubkey, Some(server_pubkey.clone()));
+        let git_commit = signed.manifest.git_commit.clone();
+        // verifies only in release build mode
+        assert_eq!(
+            verify_build_manifest(&signed, &server_pubkey, &git_commit).is_ok(),
+            signed.manifest.release
+        );
     }
 
     #[test]
@@ -1467,11 +3039,13 @@ pub mod tests {
             tx_hex: transaction_serialise(&tx_backup),
             proof_key: "03b2483ab9bea9843bd9bfb941e8c86c1308e77aa95fccd0e63c2874c0e3ead3f5".to_string(),
             shared_key_data: "".to_string(),
+            p2c: None,
         };
 
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_p2c().returning(|_| Ok(None));
         db.expect_get_recovery_data().returning(move |key| {
             // return error to simulate no statecoin for key
             if key.len() == 0 {
@@ -1481,26 +3055,26 @@ pub mod tests {
                 &BACKUP_TX_SIGNED.to_string(),
             ).unwrap())])
         });
-        db.expect_get_statechain_amount().returning(move |_| {
-            Ok(StateChainAmount {
+        db.expect_get_root_leaves().returning(|_| Ok(vec![]));
+        db.expect_get_spend_incident().returning(|_| Ok(None));
+        db.expect_get_backup_addr().returning(|_| Ok(None));
+        db.expect_get_statechain_info().returning(move |_| {
+            Ok(StateChainInfo {
                 chain: serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string()).unwrap().try_into().unwrap(),
                 amount: 10000,
+                tx_backup: Some(serde_json::from_str::<Transaction>(
+                    &BACKUP_TX_SIGNED.to_string(),
+                ).unwrap()),
+                confirmed: true,
+                archived: false,
             })
         });
-        db.expect_get_backup_transaction().returning(move |_| {
-            Ok(serde_json::from_str::<Transaction>(
-                &BACKUP_TX_SIGNED.to_string(),
-            ).unwrap())
-        });
         db.expect_get_public_master().returning(move |_| {
             Ok(Some(PARTY2PUBLIC.to_string()))
         });
         db.expect_get_statecoin_pubkey().returning(move |_| {
             Ok(Some(SHAREDPUBLIC.to_string()))
         });
-        db.expect_is_confirmed().returning(move |_| {
-            Ok(true)
-        });        
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
 
@@ -1546,6 +3120,56 @@ pub mod tests {
         assert!(sc_entity.verify_tx_confirmed(&statechain_id).is_ok());
     }
 
+    #[test]
+    #[serial]
+    fn test_verify_confirmed_scenarios() {
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_statechain_amount().returning(move |_| {
+            Ok(StateChainAmount {
+                chain: serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string()).unwrap().try_into().unwrap(),
+                amount: 100000,
+            })
+        });
+        db.expect_get_backup_transaction().returning(move |_| {
+            Ok(serde_json::from_str::<Transaction>(
+                &BACKUP_TX_SIGNED2.to_string(),
+            ).unwrap())
+        });
+        db.expect_get_statecoin_pubkey().returning(move |_| {
+            Ok(Some(SHAREDPUBLIC2.to_string()))
+        });
+
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.required_confirmation = 3;
+        let statechain_id = Uuid::new_v4();
+        let funding_txid = "73c7099ce462a699a4e589e370bebac139b2c1bcedb8cd8eb9c67c03aa03f05b";
+
+        let expect_err = |scenario: TxScenario, msg: &str| {
+            MockElectrum::set_scenario(funding_txid, scenario);
+            match sc_entity.verify_tx_confirmed(&statechain_id) {
+                Err(SEError::Generic(e)) => assert_eq!(e, msg),
+                r => assert!(false, "expected Generic error, got {:?}", r),
+            }
+        };
+        expect_err(TxScenario::NeverBroadcast, "Funding Transaction not found.");
+        expect_err(TxScenario::StuckUnconfirmed, "Funding Transaction not confirmed.");
+        expect_err(TxScenario::Confirmed(2), "Funding Transaction insufficient confirmations.");
+        expect_err(TxScenario::FeeSpike { fee_rate: 0.001 }, "Funding Transaction not found.");
+
+        // confirmed, then reorged out of the active chain
+        MockElectrum::set_scenario(funding_txid, TxScenario::Reorged { confirmations: 3, after: 1 });
+        assert!(sc_entity.verify_tx_confirmed(&statechain_id).is_ok());
+        match sc_entity.verify_tx_confirmed(&statechain_id) {
+            Err(SEError::Generic(e)) => assert_eq!(e, "Funding Transaction not in active chain."),
+            r => assert!(false, "expected Generic error, got {:?}", r),
+        }
+
+        MockElectrum::set_scenario(funding_txid, TxScenario::Confirmed(3));
+        assert!(sc_entity.verify_tx_confirmed(&statechain_id).is_ok());
+        MockElectrum::clear_scenarios();
+    }
+
     #[test]
     #[serial]
     fn test_get_recovery_data_no_shared_key_data() {
@@ -1563,11 +3187,13 @@ pub mod tests {
             tx_hex: transaction_serialise(&tx_backup),
             proof_key: "03b2483ab9bea9843bd9bfb941e8c86c1308e77aa95fccd0e63c2874c0e3ead3f5".to_string(),
             shared_key_data: "None".to_string(),
+            p2c: None,
         };
 
 
         let mut db = MockDatabase::new();
         db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_p2c().returning(|_| Ok(None));
         db.expect_get_recovery_data().returning(move |key| {
             // return error to simulate no statecoin for key
             if key.len() == 0 {
@@ -1577,26 +3203,26 @@ pub mod tests {
                 &BACKUP_TX_SIGNED.to_string(),
             ).unwrap())])
         });
-        db.expect_get_statechain_amount().returning(move |_| {
-            Ok(StateChainAmount {
+        db.expect_get_root_leaves().returning(|_| Ok(vec![]));
+        db.expect_get_spend_incident().returning(|_| Ok(None));
+        db.expect_get_backup_addr().returning(|_| Ok(None));
+        db.expect_get_statechain_info().returning(move |_| {
+            Ok(StateChainInfo {
                 chain: serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string()).unwrap().try_into().unwrap(),
                 amount: 10000,
+                tx_backup: Some(serde_json::from_str::<Transaction>(
+                    &BACKUP_TX_SIGNED.to_string(),
+                ).unwrap()),
+                confirmed: true,
+                archived: false,
             })
         });
-        db.expect_get_backup_transaction().returning(move |_| {
-            Ok(serde_json::from_str::<Transaction>(
-                &BACKUP_TX_SIGNED.to_string(),
-            ).unwrap())
-        });
         db.expect_get_public_master().returning(move |_| {
             Ok(None)
         });
         db.expect_get_statecoin_pubkey().returning(move |_| {
             Ok(Some(SHAREDPUBLIC.to_string()))
         });
-        db.expect_is_confirmed().returning(move |_| {
-            Ok(true)
-        });        
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
 
@@ -1616,6 +3242,81 @@ pub mod tests {
         assert_eq!(recovery_data.tx_hex,recovery_return[0].tx_hex);
     }
 
+    #[test]
+    fn test_submit_backup_cpfp() {
+        let statechain_id = Uuid::new_v4();
+        let witness = serde_json::from_str::<Transaction>(&BACKUP_TX_SIGNED).unwrap().input[0]
+            .witness
+            .clone();
+
+        // Backup tx paying to the witness key
+        let mut tx_backup: Transaction = serde_json::from_str(&BACKUP_TX_NOT_SIGNED).unwrap();
+        tx_backup.output[0].script_pubkey = Script::new_v0_wpkh(&WPubkeyHash::hash(&witness[1]));
+        let tx_backup_clone = tx_backup.clone();
+
+        // CPFP child spending backup tx output
+        let mut tx_cpfp: Transaction = serde_json::from_str(&BACKUP_TX_SIGNED).unwrap();
+        tx_cpfp.input[0].previous_output = OutPoint {
+            txid: tx_backup.txid(),
+            vout: 0,
+        };
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_backup_transaction()
+            .returning(move |_| Ok(tx_backup_clone.clone()));
+        db.expect_update_backup_cpfp_tx().times(1).returning(|_, _| Ok(()));
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Child does not spend backup tx
+        let tx_unrelated: Transaction = serde_json::from_str(&BACKUP_TX_SIGNED).unwrap();
+        match sc_entity.submit_backup_cpfp(BackupCPFPMsg {
+            statechain_id,
+            tx_cpfp_hex: transaction_serialise(&tx_unrelated),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not spend backup tx output")),
+        }
+
+        // Child unsigned
+        let mut tx_unsigned = tx_cpfp.clone();
+        tx_unsigned.input[0].witness = vec![];
+        match sc_entity.submit_backup_cpfp(BackupCPFPMsg {
+            statechain_id,
+            tx_cpfp_hex: transaction_serialise(&tx_unsigned),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("not signed")),
+        }
+
+        // Child signed by wrong key
+        let mut tx_wrong_key = tx_cpfp.clone();
+        tx_wrong_key.input[0].witness[1][1] ^= 1;
+        match sc_entity.submit_backup_cpfp(BackupCPFPMsg {
+            statechain_id,
+            tx_cpfp_hex: transaction_serialise(&tx_wrong_key),
+        }) {
+            Ok(_) => assert!(false, "Expected failure."),
+            Err(e) => assert!(e.to_string().contains("does not match backup tx output")),
+        }
+
+        assert!(sc_entity
+            .submit_backup_cpfp(BackupCPFPMsg {
+                statechain_id,
+                tx_cpfp_hex: transaction_serialise(&tx_cpfp),
+            })
+            .is_ok());
+    }
+
+    #[test]
+    fn test_tx_fee_verify() {
+        let tx: Transaction = serde_json::from_str(&BACKUP_TX_NOT_SIGNED).unwrap();
+        // output value is 9000
+        assert_eq!(tx_fee_verify(&tx, &[10000], &1000).unwrap(), 1000);
+        assert!(tx_fee_verify(&tx, &[10000], &999).is_err());
+        assert!(tx_fee_verify(&tx, &[8999], &1000).is_err());
+    }
+
     #[test]
     #[serial]
     fn test_get_sc_transfer_finalize_data() {
@@ -1634,4 +3335,86 @@ pub mod tests {
 
         assert_eq!(sc_entity.get_sc_transfer_finalize_data(statechain_id).unwrap(), data);
     }
+
+    #[test]
+    fn test_attribute_state_roots() {
+        let state = |data: &str| State {
+            data: data.to_string(),
+            next_state: None,
+            backup_txid: None,
+            locktime: None,
+        };
+        let chain = vec![state("a"), state("b"), state("a"), state("c")];
+        let leaves = vec![
+            (3, "a".to_string()),
+            (5, "b".to_string()),
+            (5, "x".to_string()),
+            (8, "a".to_string()),
+        ];
+        // The second "a" state is attributed to the root after the "b" state; "c" is not
+        // yet included
+        assert_eq!(
+            attribute_state_roots(&chain, &leaves),
+            vec![Some(3), Some(5), Some(8), None]
+        );
+        assert_eq!(attribute_state_roots(&chain, &[]), vec![None; 4]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_get_statechain_data_api_state_roots() {
+        use mockall::predicate;
+        let statechain_id = Uuid::new_v4();
+        let tx_backup = serde_json::from_str::<Transaction>(&BACKUP_TX_SIGNED.to_string()).unwrap();
+        let funding_txid = tx_backup.input[0].previous_output.txid.to_string();
+        let chain: StateChain = serde_json::from_str::<StateChainUnchecked>(&STATE_CHAIN.to_string())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let first_state = chain.get_chain()[0].data.clone();
+        let root = Root::from_random();
+        let root_hash = hex::encode(root.hash());
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_spend_incident().returning(|_| Ok(None));
+        db.expect_get_backup_addr()
+            .with(predicate::eq(statechain_id))
+            .returning(|_| Ok(Some(String::from("bcrt1q3lpj2f2g05ktwv3ujc9alv99ae4rv3ecnxkwh8"))));
+        db.expect_get_statechain_info().returning(move |_| {
+            Ok(StateChainInfo {
+                chain: chain.clone(),
+                amount: 10000,
+                tx_backup: Some(tx_backup.clone()),
+                confirmed: true,
+                archived: false,
+            })
+        });
+        db.expect_get_root_leaves()
+            .with(predicate::eq(funding_txid))
+            .returning(move |_| Ok(vec![(4, first_state.clone())]));
+        db.expect_get_root_info()
+            .with(predicate::eq(4))
+            .returning(move |_| Ok(Some(root.clone())));
+        db.expect_is_root_confirmed()
+            .with(predicate::eq(4))
+            .returning(|_| Ok(true));
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        let data = sc_entity.get_statechain_data_api(statechain_id).unwrap();
+        assert_eq!(data.state_roots.len(), data.chain.len());
+        assert_eq!(
+            data.state_roots[0],
+            Some(StateRootAPI {
+                root_id: 4,
+                root: root_hash,
+                confirmed: true,
+            })
+        );
+        assert!(data.state_roots[1..].iter().all(|r| r.is_none()));
+        assert_eq!(
+            data.backup_addr,
+            Some(String::from("bcrt1q3lpj2f2g05ktwv3ujc9alv99ae4rv3ecnxkwh8"))
+        );
+    }
 }
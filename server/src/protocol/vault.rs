@@ -0,0 +1,396 @@
+//! StateEntity Vault
+//!
+//! Velocity controls for statecoins in vault mode.
+//!
+//! The owner of a statecoin can opt it into vault mode with a delay, e.g. 24 hours. The State
+//! Entity then only completes a transfer (transfer/receiver), withdrawal (signing of the
+//! withdraw tx) or migration (migrate/export) once the delay has passed since it was started
+//! (transfer/sender, withdraw/init or migrate/init), and the owner can cancel it with their proof
+//! key until then. A stolen proof key therefore gives the owner the delay to react. Starting
+//! another operation restarts the delay. A longer delay takes effect immediately, a shorter one
+//! (including disabling vault mode) only once the current delay has passed. A policy only
+//! applies while the proof key it was set by is the statechain tip, so the receiver of a
+//! transfer starts without one.
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::error::SEError;
+use crate::server::StateChainEntity;
+use crate::structs::VaultRecord;
+use crate::Database;
+use shared_lib::state_chain::{is_locked, StateChainSig};
+use shared_lib::structs::{VaultAPI, VaultCancelMsg, VaultMsg};
+
+use cfg_if::cfg_if;
+use chrono::{Duration, NaiveDateTime, Utc};
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Policy with its pending delay applied if the current delay has passed at time now
+pub fn vault_at(vault: &VaultRecord, now: &NaiveDateTime) -> VaultRecord {
+    let mut vault = vault.clone();
+    if let Some(effective) = vault.pending_effective {
+        if effective <= *now {
+            vault.delay = vault.pending_delay.take().unwrap_or(0);
+            vault.pending_effective = None;
+        }
+    }
+    vault
+}
+
+/// Time from which the operation in progress can be completed, if any
+fn completable_from(vault: &VaultRecord) -> Option<NaiveDateTime> {
+    match (&vault.pending_sig, vault.pending_since) {
+        (Some(_), Some(since)) => Some(since + Duration::seconds(vault.delay as i64)),
+        _ => None,
+    }
+}
+
+fn vault_api(vault: &VaultRecord) -> VaultAPI {
+    VaultAPI {
+        statechain_id: vault.statechain_id,
+        delay: vault.delay,
+        pending_delay: vault.pending_delay,
+        pending_effective: vault.pending_effective,
+        pending_sig: vault.pending_sig.clone(),
+        completable_from: completable_from(vault),
+    }
+}
+
+/// StateChain Entity vault API calls
+pub trait Vault {
+    /// API: Set the vault delay of a statecoin owned by the caller, or disable vault mode with a
+    /// delay of 0. A shorter delay takes effect once the current delay has passed.
+    fn vault_set(&self, vault_msg: VaultMsg) -> Result<VaultAPI>;
+
+    /// API: Cancel the transfer, withdrawal or migration in progress of a statecoin in vault
+    /// mode owned by the caller
+    fn vault_cancel(&self, cancel_msg: VaultCancelMsg) -> Result<VaultAPI>;
+
+    /// API: Get the vault policy of a statechain
+    fn vault_get(&self, statechain_id: Uuid) -> Result<VaultAPI>;
+}
+
+impl SCE {
+    /// Check the statecoin of user_id is owned by the caller and unlocked. Returns its
+    /// statechain ID and current owner proof key.
+    fn vault_owner(&self, user_id: &Uuid) -> Result<(Uuid, String)> {
+        self.check_user_auth(user_id)?;
+        let statechain_id = self.database.get_statechain_id(*user_id)?;
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        is_locked(sco.locked_until)?;
+        if &sco.owner_id != user_id {
+            return Err(SEError::Generic(format!(
+                "State Chain not owned by User ID: {}.",
+                user_id
+            )));
+        }
+        Ok((statechain_id, sco.chain.get_tip().data.clone()))
+    }
+
+    /// Vault policy of a statechain at time now. Disabled if none applies to the current owner
+    /// proof key.
+    fn get_current_vault(
+        &self,
+        statechain_id: &Uuid,
+        proof_key: &String,
+        now: &NaiveDateTime,
+    ) -> Result<VaultRecord> {
+        match self.database.get_vault(statechain_id)? {
+            Some(v) if &v.proof_key == proof_key => Ok(vault_at(&v, now)),
+            _ => Ok(VaultRecord {
+                statechain_id: *statechain_id,
+                proof_key: proof_key.clone(),
+                delay: 0,
+                pending_delay: None,
+                pending_effective: None,
+                pending_sig: None,
+                pending_since: None,
+            }),
+        }
+    }
+
+    /// Start the vault delay of the operation signed by statechain_sig if the statechain is in
+    /// vault mode. Restarted if statechain_sig differs from the operation in progress. Statecoins
+    /// in vault mode cannot be transferred in a batch.
+    pub fn vault_start(
+        &self,
+        statechain_id: &Uuid,
+        proof_key: &String,
+        statechain_sig: &StateChainSig,
+        batch: bool,
+    ) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let mut vault = match self.database.get_vault(statechain_id)? {
+            Some(v) if &v.proof_key == proof_key => vault_at(&v, &now),
+            _ => return Ok(()),
+        };
+        if vault.delay == 0 {
+            return Ok(());
+        }
+        if batch {
+            return Err(SEError::Generic(format!(
+                "Statechain {} is in vault mode and cannot be transferred in a batch",
+                statechain_id
+            )));
+        }
+        if vault.pending_sig.as_ref() != Some(statechain_sig) {
+            vault.pending_sig = Some(statechain_sig.clone());
+            vault.pending_since = Some(now);
+            self.database.update_vault(&vault)?;
+            info!(
+                "VAULT: {} started. Completable from {:?}. State Chain ID: {}",
+                statechain_sig.purpose,
+                completable_from(&vault),
+                statechain_id
+            );
+        }
+        Ok(())
+    }
+
+    /// Err if the statechain is in vault mode and the operation signed by statechain_sig was not
+    /// started at least the vault delay ago, or was cancelled
+    pub fn check_vault_delay(&self, statechain_id: &Uuid, statechain_sig: &StateChainSig) -> Result<()> {
+        let vault = match self.database.get_vault(statechain_id)? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let sco = self.database.get_statechain_owner(*statechain_id)?;
+        if vault.proof_key != sco.chain.get_tip().data {
+            return Ok(());
+        }
+        let now = Utc::now().naive_utc();
+        let vault = vault_at(&vault, &now);
+        if vault.delay == 0 {
+            return Ok(());
+        }
+        if vault.pending_sig.as_ref() != Some(statechain_sig) {
+            return Err(SEError::Generic(format!(
+                "Statechain {} is in vault mode and the {} was not started or was cancelled",
+                statechain_id, statechain_sig.purpose
+            )));
+        }
+        match completable_from(&vault) {
+            Some(from) if from <= now => Ok(()),
+            from => Err(SEError::Generic(format!(
+                "Statechain {} is in vault mode. The {} can be completed from {:?}",
+                statechain_id, statechain_sig.purpose, from
+            ))),
+        }
+    }
+}
+
+impl Vault for SCE {
+    fn vault_set(&self, vault_msg: VaultMsg) -> Result<VaultAPI> {
+        let (statechain_id, proof_key) = self.vault_owner(&vault_msg.shared_key_id)?;
+        if vault_msg.delay > self.config.vault_max_delay {
+            return Err(SEError::Generic(format!(
+                "Vault delay must be at most {} seconds",
+                self.config.vault_max_delay
+            )));
+        }
+        if !vault_msg.statechain_sig.is_vault(vault_msg.delay, &statechain_id) {
+            return Err(SEError::Generic(format!(
+                "Statechain {} sig does not sign the vault delay",
+                statechain_id
+            )));
+        }
+        vault_msg.statechain_sig.verify(&proof_key)?;
+
+        let now = Utc::now().naive_utc();
+        let mut vault = self.get_current_vault(&statechain_id, &proof_key, &now)?;
+        if vault_msg.delay >= vault.delay {
+            vault.delay = vault_msg.delay;
+            vault.pending_delay = None;
+            vault.pending_effective = None;
+            info!(
+                "VAULT: Delay set to {}s. State Chain ID: {}",
+                vault.delay, statechain_id
+            );
+        } else {
+            vault.pending_delay = Some(vault_msg.delay);
+            vault.pending_effective = Some(now + Duration::seconds(vault.delay as i64));
+            warn!(
+                "VAULT: Delay {}s pending until {:?}. State Chain ID: {}",
+                vault_msg.delay, vault.pending_effective, statechain_id
+            );
+        }
+        self.database.update_vault(&vault)?;
+        Ok(vault_api(&vault))
+    }
+
+    fn vault_cancel(&self, cancel_msg: VaultCancelMsg) -> Result<VaultAPI> {
+        let (statechain_id, proof_key) = self.vault_owner(&cancel_msg.shared_key_id)?;
+        let now = Utc::now().naive_utc();
+        let mut vault = self.get_current_vault(&statechain_id, &proof_key, &now)?;
+        let pending_sig = match &vault.pending_sig {
+            Some(sig) if vault.delay > 0 => sig.clone(),
+            _ => {
+                return Err(SEError::Generic(format!(
+                    "Statechain {} has no vault operation in progress",
+                    statechain_id
+                )))
+            }
+        };
+        if !cancel_msg.statechain_sig.is_vault_cancel(&pending_sig, &statechain_id) {
+            return Err(SEError::Generic(format!(
+                "Statechain {} sig does not cancel the operation in progress",
+                statechain_id
+            )));
+        }
+        cancel_msg.statechain_sig.verify(&proof_key)?;
+
+        vault.pending_sig = None;
+        vault.pending_since = None;
+        self.database.update_vault(&vault)?;
+        warn!(
+            "VAULT: {} cancelled. State Chain ID: {}",
+            pending_sig.purpose, statechain_id
+        );
+        Ok(vault_api(&vault))
+    }
+
+    fn vault_get(&self, statechain_id: Uuid) -> Result<VaultAPI> {
+        let sco = self.database.get_statechain_owner(statechain_id)?;
+        let vault = self.get_current_vault(
+            &statechain_id,
+            &sco.chain.get_tip().data,
+            &Utc::now().naive_utc(),
+        )?;
+        Ok(vault_api(&vault))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use crate::structs::StateChainOwner;
+    use bitcoin::secp256k1::key::SecretKey;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1};
+    use shared_lib::state_chain::StateChain;
+    use std::sync::{Arc, Mutex};
+
+    /// Mock database holding a single vault policy
+    fn vault_db(
+        shared_key_id: Uuid,
+        statechain_id: Uuid,
+        proof_key: String,
+    ) -> (MockDatabase, Arc<Mutex<Option<VaultRecord>>>) {
+        let vault = Arc::new(Mutex::new(None));
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_| Ok(String::from("user_auth")));
+        db.expect_get_statechain_id()
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_statechain_owner().returning(move |_| {
+            Ok(StateChainOwner {
+                locked_until: Utc::now().naive_utc(),
+                owner_id: shared_key_id,
+                chain: StateChain::new(proof_key.clone()),
+            })
+        });
+        let get = vault.clone();
+        db.expect_get_vault()
+            .returning(move |_| Ok(get.lock().unwrap().clone()));
+        let update = vault.clone();
+        db.expect_update_vault().returning(move |v| {
+            *update.lock().unwrap() = Some(v.clone());
+            Ok(())
+        });
+        (db, vault)
+    }
+
+    #[test]
+    #[serial]
+    fn test_vault() {
+        let shared_key_id = Uuid::new_v4();
+        let statechain_id = Uuid::new_v4();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&Secp256k1::new(), &owner_priv).to_string();
+        let (db, vault) = vault_db(shared_key_id, statechain_id, proof_key.clone());
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        let set_msg = |delay: u64| VaultMsg {
+            shared_key_id,
+            delay,
+            statechain_sig: StateChainSig::new_vault_sig(&owner_priv, delay, &statechain_id)
+                .unwrap(),
+        };
+        let transfer_sig =
+            StateChainSig::new(&owner_priv, &String::from("TRANSFER"), &proof_key).unwrap();
+        let withdraw_sig =
+            StateChainSig::new(&owner_priv, &String::from("WITHDRAW"), &proof_key).unwrap();
+
+        // Not in vault mode
+        sc_entity
+            .vault_start(&statechain_id, &proof_key, &transfer_sig, true)
+            .unwrap();
+        assert!(sc_entity.check_vault_delay(&statechain_id, &transfer_sig).is_ok());
+
+        // Enabling takes effect immediately
+        let enabled = sc_entity.vault_set(set_msg(86400)).unwrap();
+        assert_eq!(enabled.delay, 86400);
+        assert!(sc_entity
+            .vault_set(set_msg(sc_entity.config.vault_max_delay + 1))
+            .is_err());
+
+        // The operation can only be completed once the delay has passed since it was started
+        assert!(sc_entity.check_vault_delay(&statechain_id, &transfer_sig).is_err());
+        assert!(sc_entity
+            .vault_start(&statechain_id, &proof_key, &transfer_sig, true)
+            .is_err());
+        sc_entity
+            .vault_start(&statechain_id, &proof_key, &transfer_sig, false)
+            .unwrap();
+        assert!(sc_entity.check_vault_delay(&statechain_id, &transfer_sig).is_err());
+        vault.lock().unwrap().as_mut().unwrap().pending_since =
+            Some(Utc::now().naive_utc() - Duration::seconds(86401));
+        assert!(sc_entity.check_vault_delay(&statechain_id, &transfer_sig).is_ok());
+        // A registration by a previous owner does not apply
+        vault.lock().unwrap().as_mut().unwrap().proof_key = String::from("previous");
+        assert!(sc_entity.check_vault_delay(&statechain_id, &withdraw_sig).is_ok());
+        vault.lock().unwrap().as_mut().unwrap().proof_key = proof_key.clone();
+
+        // Another operation restarts the delay
+        sc_entity
+            .vault_start(&statechain_id, &proof_key, &withdraw_sig, false)
+            .unwrap();
+        assert!(sc_entity.check_vault_delay(&statechain_id, &transfer_sig).is_err());
+        assert!(sc_entity.check_vault_delay(&statechain_id, &withdraw_sig).is_err());
+
+        // The owner can cancel the operation in progress only
+        let cancel_msg = |sig: &StateChainSig| VaultCancelMsg {
+            shared_key_id,
+            statechain_sig: StateChainSig::new_vault_cancel_sig(&owner_priv, sig, &statechain_id)
+                .unwrap(),
+        };
+        assert!(sc_entity.vault_cancel(cancel_msg(&transfer_sig)).is_err());
+        let cancelled = sc_entity.vault_cancel(cancel_msg(&withdraw_sig)).unwrap();
+        assert_eq!(cancelled.pending_sig, None);
+        vault.lock().unwrap().as_mut().unwrap().pending_since =
+            Some(Utc::now().naive_utc() - Duration::seconds(86401));
+        assert!(sc_entity.check_vault_delay(&statechain_id, &withdraw_sig).is_err());
+
+        // Disabling only takes effect once the current delay has passed
+        let pending = sc_entity.vault_set(set_msg(0)).unwrap();
+        assert_eq!(pending.delay, 86400);
+        assert_eq!(pending.pending_delay, Some(0));
+        assert!(pending.pending_effective.unwrap() > Utc::now().naive_utc());
+        vault.lock().unwrap().as_mut().unwrap().pending_effective =
+            Some(Utc::now().naive_utc() - Duration::seconds(1));
+        assert_eq!(sc_entity.vault_get(statechain_id).unwrap().delay, 0);
+        assert!(sc_entity.check_vault_delay(&statechain_id, &withdraw_sig).is_ok());
+    }
+}
@@ -0,0 +1,310 @@
+//! StateEntity Watchtower
+//!
+//! Broadcasting of backup txs on behalf of owners who may be offline when their backup tx
+//! becomes valid. Owners register their backup tx encrypted to the watchtower key with the
+//! block height from which it is broadcast, update the registration when the backup tx
+//! changes and revoke it once the statecoin has been transferred or withdrawn. Due backup txs
+//! are decrypted and broadcast by the watch loop (see watch.rs). The watchtower can run in the
+//! state entity or as a separate server (mode = "watchtower").
+
+pub use super::super::Result;
+extern crate shared_lib;
+use crate::config::WatchtowerConfig;
+use crate::error::SEError;
+use crate::server::StateChainEntity;
+use crate::structs::WatchtowerRecord;
+use crate::Database;
+use shared_lib::ecies::SelfEncryptable;
+use shared_lib::structs::{WatchtowerKeyAPI, WatchtowerRegisterMsg, WatchtowerRevokeMsg};
+use shared_lib::util::transaction_deserialise;
+
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::util::key::PrivateKey;
+use bitcoin::Transaction;
+use cfg_if::cfg_if;
+use std::str::FromStr;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockdb"))]{
+        use crate::MockDatabase;
+        use monotree::database::MemoryDB;
+        type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
+    } else {
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
+    }
+}
+
+/// Watchtower protocol trait
+pub trait Watchtower {
+    /// API: Get the public key that registered backup txs are encrypted to
+    fn watchtower_get_key(&self) -> Result<WatchtowerKeyAPI>;
+
+    /// API: Create or update the registration of a backup tx:
+    ///     - Check the signature by the auth key, which must be the auth key of an existing
+    ///       registration
+    ///     - Check the version is greater than the registered version
+    ///     - Check the backup tx decrypts with the watchtower key
+    fn watchtower_register(&self, register_msg: WatchtowerRegisterMsg) -> Result<()>;
+
+    /// API: Revoke a registration. The backup tx is dropped and the version kept, so that the
+    /// registration cannot be replayed.
+    fn watchtower_revoke(&self, revoke_msg: WatchtowerRevokeMsg) -> Result<()>;
+}
+
+/// Watchtower secret key from the config
+pub fn watchtower_key(config: &WatchtowerConfig) -> Result<SecretKey> {
+    match &config.key {
+        Some(k) => SecretKey::from_str(k)
+            .map_err(|e| SEError::Generic(format!("Invalid watchtower key: {}", e))),
+        None => Err(SEError::Generic(String::from("Watchtower not enabled"))),
+    }
+}
+
+/// Decrypt a registered backup tx with the watchtower key
+pub fn decrypt_backup_tx(key: &SecretKey, backup_tx: &String) -> Result<Transaction> {
+    let mut tx_hex = backup_tx.clone();
+    tx_hex.decrypt(&PrivateKey {
+        compressed: true,
+        network: Network::Regtest,
+        key: *key,
+    })?;
+    Ok(transaction_deserialise(&tx_hex)?)
+}
+
+impl Watchtower for SCE {
+    fn watchtower_get_key(&self) -> Result<WatchtowerKeyAPI> {
+        let key = watchtower_key(&self.config.watchtower)?;
+        Ok(WatchtowerKeyAPI {
+            key: PublicKey::from_secret_key(&Secp256k1::new(), &key).to_string(),
+        })
+    }
+
+    fn watchtower_register(&self, register_msg: WatchtowerRegisterMsg) -> Result<()> {
+        let key = watchtower_key(&self.config.watchtower)?;
+        register_msg.verify()?;
+
+        match self.database.get_watchtower_registration(&register_msg.id)? {
+            Some(record) => {
+                if record.auth_key != register_msg.auth_key {
+                    return Err(SEError::AuthError);
+                }
+                if register_msg.version <= record.version {
+                    return Err(SEError::Generic(format!(
+                        "Watchtower registration version {} not greater than registered version {}",
+                        register_msg.version, record.version
+                    )));
+                }
+            }
+            None => {
+                let max = self.config.watchtower.max_registrations;
+                if max > 0
+                    && self
+                        .database
+                        .count_watchtower_registrations(&register_msg.auth_key)?
+                        >= max
+                {
+                    return Err(SEError::Generic(format!(
+                        "Maximum number of watchtower registrations ({}) reached",
+                        max
+                    )));
+                }
+            }
+        }
+
+        let tx = decrypt_backup_tx(&key, &register_msg.backup_tx)
+            .map_err(|e| SEError::Generic(format!("Invalid encrypted backup tx: {}", e)))?;
+
+        self.database.update_watchtower_registration(&WatchtowerRecord {
+            id: register_msg.id,
+            auth_key: register_msg.auth_key,
+            version: register_msg.version,
+            broadcast_height: register_msg.broadcast_height,
+            backup_tx: Some(register_msg.backup_tx),
+            revoked: false,
+        })?;
+
+        info!(
+            "WATCHTOWER: Registered backup tx {} from height {}. ID: {} version {}",
+            tx.txid(),
+            register_msg.broadcast_height,
+            register_msg.id,
+            register_msg.version
+        );
+        Ok(())
+    }
+
+    fn watchtower_revoke(&self, revoke_msg: WatchtowerRevokeMsg) -> Result<()> {
+        watchtower_key(&self.config.watchtower)?;
+        let record = self
+            .database
+            .get_watchtower_registration(&revoke_msg.id)?
+            .ok_or(SEError::Generic(format!(
+                "No watchtower registration. ID: {}",
+                revoke_msg.id
+            )))?;
+        revoke_msg
+            .verify(&record.auth_key)
+            .map_err(|_| SEError::AuthError)?;
+        if revoke_msg.version <= record.version {
+            return Err(SEError::Generic(format!(
+                "Watchtower revocation version {} not greater than registered version {}",
+                revoke_msg.version, record.version
+            )));
+        }
+
+        self.database.update_watchtower_registration(&WatchtowerRecord {
+            version: revoke_msg.version,
+            backup_tx: None,
+            revoked: true,
+            ..record
+        })?;
+
+        info!(
+            "WATCHTOWER: Revoked registration. ID: {} version {}",
+            revoke_msg.id, revoke_msg.version
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::util::tests::test_sc_entity;
+    use bitcoin::consensus;
+    use shared_lib::util::transaction_serialise;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    static WATCHTOWER_KEY: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+    static BACKUP_TX: &str = "02000000000101b91e2b8e26ae7f93cea773c5d74f7722982134ebbf32ca9b627981a5546ef4c7000000001716001472d64fcb0be3dff555fc87b3d054a1ccb48ac059feffffff0200c2eb0b0000000017a9141040c0c1b81e2e00aec47ef01c2d3a6116ca513d8748b723180100000017a914d5dd335a7721cf03b1f5df5bdf22c63c0e1e472887024730440220167f84b7e579153ff83a480eadc4225ad1c67322ad0e8d5f32d317ce61a6c26802206fff7f176b6780f00cf9d63ea759658e4ca0302dc2204c02bf3ee52e032e051001210297fd944ebb0de31b629a99a14d53fb8c83e5791f714892f72b74751cfd097c1765000000";
+
+    fn register_msg(
+        id: Uuid,
+        auth_priv: &SecretKey,
+        version: u64,
+        watchtower_pub: &str,
+    ) -> WatchtowerRegisterMsg {
+        let tx: Transaction = consensus::deserialize(&hex::decode(BACKUP_TX).unwrap()).unwrap();
+        let mut backup_tx = transaction_serialise(&tx);
+        backup_tx
+            .encrypt_with_pubkey(&shared_lib::ecies::PublicKey::from_str(watchtower_pub).unwrap())
+            .unwrap();
+        let mut msg = WatchtowerRegisterMsg {
+            id,
+            auth_key: PublicKey::from_secret_key(&Secp256k1::new(), auth_priv).to_string(),
+            version,
+            broadcast_height: 147,
+            backup_tx,
+            sig: String::default(),
+        };
+        msg.sign(auth_priv).unwrap();
+        msg
+    }
+
+    #[test]
+    fn test_watchtower_register_revoke() {
+        let auth_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let id = Uuid::new_v4();
+        let registrations: Arc<Mutex<Option<WatchtowerRecord>>> = Arc::new(Mutex::new(None));
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let r = registrations.clone();
+        db.expect_get_watchtower_registration()
+            .returning(move |_| Ok(r.lock().unwrap().clone()));
+        let r = registrations.clone();
+        db.expect_update_watchtower_registration().returning(move |record| {
+            *r.lock().unwrap() = Some(record.clone());
+            Ok(())
+        });
+        db.expect_count_watchtower_registrations().returning(|_| Ok(0));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+
+        // Disabled without a watchtower key
+        assert!(sc_entity.watchtower_get_key().is_err());
+        sc_entity.config.watchtower.key = Some(String::from(WATCHTOWER_KEY));
+        let watchtower_pub = sc_entity.watchtower_get_key().unwrap().key;
+
+        // Register
+        let msg = register_msg(id, &auth_priv, 1, &watchtower_pub);
+        sc_entity.watchtower_register(msg.clone()).unwrap();
+        let record = registrations.lock().unwrap().clone().unwrap();
+        assert_eq!(record.version, 1);
+        assert_eq!(
+            transaction_serialise(&decrypt_backup_tx(
+                &watchtower_key(&sc_entity.config.watchtower).unwrap(),
+                &record.backup_tx.unwrap()
+            )
+            .unwrap()),
+            BACKUP_TX
+        );
+
+        // Replayed registration
+        assert!(sc_entity.watchtower_register(msg).is_err());
+        // Update by another auth key
+        let other_priv = SecretKey::from_slice(&[3; 32]).unwrap();
+        match sc_entity.watchtower_register(register_msg(id, &other_priv, 2, &watchtower_pub)) {
+            Err(SEError::AuthError) => (),
+            r => assert!(false, "Expected AuthError, got {:?}", r),
+        }
+        // Backup tx not encrypted to the watchtower key
+        let other_pub = PublicKey::from_secret_key(&Secp256k1::new(), &other_priv).to_string();
+        assert!(sc_entity
+            .watchtower_register(register_msg(id, &auth_priv, 2, &other_pub))
+            .is_err());
+        // Update
+        sc_entity
+            .watchtower_register(register_msg(id, &auth_priv, 2, &watchtower_pub))
+            .unwrap();
+        assert_eq!(registrations.lock().unwrap().as_ref().unwrap().version, 2);
+
+        // Revoke
+        let mut revoke_msg = WatchtowerRevokeMsg {
+            id,
+            version: 2,
+            sig: String::default(),
+        };
+        revoke_msg.sign(&auth_priv).unwrap();
+        assert!(sc_entity.watchtower_revoke(revoke_msg.clone()).is_err());
+        revoke_msg.version = 3;
+        revoke_msg.sign(&other_priv).unwrap();
+        assert!(sc_entity.watchtower_revoke(revoke_msg.clone()).is_err());
+        revoke_msg.sign(&auth_priv).unwrap();
+        sc_entity.watchtower_revoke(revoke_msg).unwrap();
+        let record = registrations.lock().unwrap().clone().unwrap();
+        assert!(record.revoked);
+        assert_eq!(record.backup_tx, None);
+        assert_eq!(record.version, 3);
+
+        // An older registration cannot be replayed after revocation
+        assert!(sc_entity
+            .watchtower_register(register_msg(id, &auth_priv, 2, &watchtower_pub))
+            .is_err());
+    }
+
+    #[test]
+    fn test_watchtower_max_registrations() {
+        let auth_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_watchtower_registration().returning(|_| Ok(None));
+        db.expect_count_watchtower_registrations().returning(|_| Ok(2));
+        db.expect_update_watchtower_registration().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.watchtower.key = Some(String::from(WATCHTOWER_KEY));
+        let watchtower_pub = sc_entity.watchtower_get_key().unwrap().key;
+
+        sc_entity.config.watchtower.max_registrations = 2;
+        assert!(sc_entity
+            .watchtower_register(register_msg(Uuid::new_v4(), &auth_priv, 1, &watchtower_pub))
+            .is_err());
+        sc_entity.config.watchtower.max_registrations = 0;
+        sc_entity
+            .watchtower_register(register_msg(Uuid::new_v4(), &auth_priv, 1, &watchtower_pub))
+            .unwrap();
+    }
+}
@@ -4,12 +4,11 @@
 
 pub use super::super::Result;
 extern crate shared_lib;
+use crate::aml::{aml_screen, AmlEvent, AmlEventKind};
+use crate::protocol::session::SessionEvent;
 use crate::structs::StateChainOwner;
-use crate::{protocol::util::RateLimiter, server::WITHDRAWALS_COUNT};
-use shared_lib::{state_chain::*, structs::*};
-
-use rocket::State;
-use rocket_contrib::json::Json;
+use crate::server::WITHDRAWALS_COUNT;
+use shared_lib::{state_chain::*, structs::*, util::withdraw_inputs_verify};
 
 use crate::error::SEError;
 use crate::Database;
@@ -17,7 +16,6 @@ use crate::{server::StateChainEntity, storage::Storage};
 use crate::structs::WithdrawConfirmData;
 use cfg_if::cfg_if;
 use uuid::Uuid;
-use rocket_okapi::openapi;
 
 cfg_if! {
     if #[cfg(any(test,feature="mockdb"))]{
@@ -25,8 +23,8 @@ cfg_if! {
         use monotree::database::MemoryDB;
         type SCE = StateChainEntity::<MockDatabase, MemoryDB>;
     } else {
-        use crate::PGDatabase;
-        type SCE = StateChainEntity::<PGDatabase, PGDatabase>;
+        use crate::{PGDatabase, ServerDatabase};
+        type SCE = StateChainEntity::<ServerDatabase, PGDatabase>;
     }
 }
 
@@ -99,29 +97,64 @@ impl Withdraw for SCE {
         if (withdraw_msg1.statechain_sigs.len() != withdraw_msg1.shared_key_ids.len()){
             return Err(SEError::Generic("incorrect number of statechain signatures in withdraw/init request".to_string()));
         }
+        withdraw_inputs_verify(withdraw_msg1.shared_key_ids.len(), self.config.max_withdraw_inputs)?;
        
+        let mut next_states = vec![];
         for user_id in withdraw_msg1.shared_key_ids.iter()
         {
             self.check_user_auth(&user_id)?;
+            next_states.push(self.check_session_event(&user_id, SessionEvent::WithdrawInit)?);
         }
 
-        for (user_id, statechain_sig) in 
-            withdraw_msg1.shared_key_ids.iter().zip(withdraw_msg1.statechain_sigs.iter())
+        for (i, ((user_id, statechain_sig), next_state)) in withdraw_msg1
+            .shared_key_ids
+            .iter()
+            .zip(withdraw_msg1.statechain_sigs.iter())
+            .zip(next_states.into_iter())
+            .enumerate()
         {
             info!("WITHDRAW: Init. Shared Key ID: {}", user_id);
 
             let statechain_id = self.database.get_statechain_id(*user_id)?;
+            self.check_sale_not_held(&statechain_id)?;
 
-            self.verify_statechain_sig(
+            let sco = self.verify_statechain_sig(
                 &statechain_id,
                 &statechain_sig,
                 Some(*user_id),
             )?;
+            statechain_sig.check_binding(
+                &statechain_id,
+                sco.chain.tip_position(),
+                self.config.require_bound_sigs,
+            )?;
+            self.check_second_factor(
+                &statechain_id,
+                &sco.chain.get_tip().data,
+                &statechain_sig,
+                &withdraw_msg1.auth_sigs.get(i).cloned().flatten(),
+            )?;
+            self.vault_start(&statechain_id, &sco.chain.get_tip().data, &statechain_sig, false)?;
+
+            let sc_amount = self.database.get_statechain_amount(statechain_id)?;
+            let tx_backup = self.database.get_backup_transaction(statechain_id)?;
+            aml_screen(
+                self.aml_hook.as_ref(),
+                &AmlEvent {
+                    kind: AmlEventKind::Withdraw,
+                    user_id: *user_id,
+                    statechain_id: Some(statechain_id),
+                    txid: tx_backup.input[0].previous_output.txid.to_string(),
+                    amount: sc_amount.amount as u64,
+                    destination: Some(statechain_sig.data.clone()),
+                },
+            )?;
 
             // Mark UserSession as authorised for withdrawal
 
             self.database
                 .update_withdraw_sc_sig(&user_id, statechain_sig.clone())?;
+            self.update_session_state(&user_id, next_state)?;
 
 
             info!(
@@ -154,11 +187,14 @@ impl Withdraw for SCE {
 
     fn withdraw_confirm(&self, withdraw_msg2: WithdrawMsg2) -> Result<Vec<Vec<Vec<u8>>>> {
         let mut result = Vec::<Vec::<Vec::<u8>>>::new();
+        withdraw_inputs_verify(withdraw_msg2.shared_key_ids.len(), self.config.max_withdraw_inputs)?;
         
         for (i, user_id) in withdraw_msg2.shared_key_ids.iter().enumerate() {
 
             info!("WITHDRAW: Confirm. Shared Key ID: {}", user_id.to_string());
 
+            let next_state = self.check_session_event(&user_id, SessionEvent::WithdrawConfirm)?;
+
             // Get withdraw data - Checking that withdraw tx and statechain signature exists
             let wcd = match self.get_if_signed_for_withdrawal(user_id).map_err(|e| SEError::Generic(
                 format!("{} in withdraw_confirm {}", e, i)))? {
@@ -167,15 +203,18 @@ impl Withdraw for SCE {
             };
 
             // Get statechain and update with final StateChainSig
+            let sc_amount = self.database.get_statechain_amount(wcd.statechain_id)?;
+            let version = self.database.get_statechain_version(&wcd.statechain_id)?;
             let mut state_chain: StateChain = self.database.get_statechain(wcd.statechain_id)?;
 
             state_chain.add(&wcd.withdraw_sc_sig)?;
 
             self.database
-                .update_statechain_amount(&wcd.statechain_id, state_chain, 0, self.coin_value_info.clone())?;
+                .update_statechain_amount(&wcd.statechain_id, state_chain, 0, self.coin_value_info.clone(), version)?;
 
             // Remove statechain_id from user session to signal end of session
             self.database.remove_statechain_id(&user_id)?;
+            self.update_session_state(&user_id, next_state)?;
 
             //increment withdrawals metric
             WITHDRAWALS_COUNT.inc();
@@ -190,11 +229,45 @@ impl Withdraw for SCE {
                     .txid
                     .to_string(),
                 &withdraw_msg2.address,
+                sc_amount.amount as u64,
             )?;
 
             //remove backup tx from the backup db
             self.database.remove_backup_tx(&wcd.statechain_id)?;
 
+            // Record the withdrawal fee paid by the withdraw tx for the revenue report. The fee
+            // output of a batch withdraw tx pays the fees of all of its statecoins.
+            let withdraw_txid = wcd.tx_withdraw.txid().to_string();
+            let withdraw_fee = (sc_amount.amount as u64 * self.config.fee_withdraw) / 10000
+                + self.config.fee_withdraw_input;
+            if withdraw_fee > 0 {
+                self.database.insert_fee(
+                    &withdraw_txid,
+                    &FeeType::Withdraw,
+                    &wcd.statechain_id,
+                    withdraw_fee,
+                )?;
+            }
+
+            // The withdraw tx pays the swap fees owed by deduction
+            let swap_fee_owed = self.swap_fee_owed(&wcd.statechain_id)?;
+            if swap_fee_owed > 0 {
+                self.database.update_swap_fee_collected(&wcd.statechain_id)?;
+                self.database.insert_fee(
+                    &withdraw_txid,
+                    &FeeType::Swap,
+                    &wcd.statechain_id,
+                    swap_fee_owed,
+                )?;
+                info!(
+                    target: "audit",
+                    "SWAP FEE: Collected {} by withdraw txid {}. State Chain ID: {}",
+                    swap_fee_owed,
+                    wcd.tx_withdraw.txid(),
+                    wcd.statechain_id
+                );
+            }
+
             info!(
                 "WITHDRAW: Address included in sparse merkle tree. State Chain ID: {}",
                 wcd.statechain_id
@@ -219,31 +292,6 @@ impl Withdraw for SCE {
     }
 }
 
-#[openapi]
-/// # Initiate the withdrawal process: provide signed statechain
-#[post("/withdraw/init", format = "json", data = "<withdraw_msg1>")]
-pub fn withdraw_init(sc_entity: State<SCE>, withdraw_msg1: Json<WithdrawMsg1>) -> Result<Json<()>> {
-    sc_entity.check_rate_fast("withdraw")?;
-    match sc_entity.withdraw_init(withdraw_msg1.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
-
-#[openapi]
-/// # Complete the withdrawal process: confirm withdrawal transaction
-#[post("/withdraw/confirm", format = "json", data = "<withdraw_msg2>")]
-pub fn withdraw_confirm(
-    sc_entity: State<SCE>,
-    withdraw_msg2: Json<WithdrawMsg2>,
-) -> Result<Json<Vec<Vec<Vec<u8>>>>> {
-    sc_entity.check_rate_fast("withdraw")?;
-    match sc_entity.withdraw_confirm(withdraw_msg2.into_inner()) {
-        Ok(res) => return Ok(Json(res)),
-        Err(e) => return Err(e),
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -252,9 +300,9 @@ mod tests {
         error::DBErrorType,
         protocol::util::{
             mocks,
-            tests::{test_sc_entity, BACKUP_TX_NOT_SIGNED, BACKUP_TX_SIGNED},
+            tests::{malformed_sigs, test_sc_entity, BACKUP_TX_NOT_SIGNED, BACKUP_TX_SIGNED},
         },
-        structs::{StateChainOwner, WithdrawConfirmData},
+        structs::{SessionState, StateChainAmount, StateChainOwner, WithdrawConfirmData},
     };
     use chrono::{Duration, Utc};
     use mockall::predicate;
@@ -275,6 +323,9 @@ mod tests {
         let statechain_id = Uuid::from_str(STATE_CHAIN_ID).unwrap();
 
         let mut db = MockDatabase::new();
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
@@ -309,7 +360,21 @@ mod tests {
                     chain: serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap(),
                 })
             });
+        db.expect_get_statechain_amount().returning(move |_| {
+            Ok(StateChainAmount {
+                chain: serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap(),
+                amount: 10000,
+            })
+        });
+        db.expect_get_backup_transaction()
+            .returning(|_| Ok(serde_json::from_str(&BACKUP_TX_SIGNED).unwrap()));
         db.expect_update_withdraw_sc_sig().returning(|_, _| Ok(()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Owner)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Withdraw)
+            .times(1)
+            .returning(|_, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
 
@@ -332,6 +397,80 @@ mod tests {
         assert!(sc_entity.withdraw_init(withdraw_msg_1.clone()).is_ok());
     }
 
+    // Malformed signatures over an existing statechain return errors and do not panic
+    #[test]
+    fn test_withdraw_init_malformed_sigs() {
+        let withdraw_msg_1 = serde_json::from_str::<WithdrawMsg1>(WITHDRAW_MSG_1).unwrap();
+        let shared_key_id = withdraw_msg_1.shared_key_ids[0];
+        let statechain_id = Uuid::from_str(STATE_CHAIN_ID).unwrap();
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        db.expect_get_user_auth()
+            .returning(|_user_id| Ok(String::from("user_auth")));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Owner)));
+        db.expect_get_statechain_id()
+            .with(predicate::eq(shared_key_id))
+            .returning(move |_| Ok(statechain_id));
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_statechain_owner()
+            .with(predicate::eq(statechain_id))
+            .returning(move |_| {
+                Ok(StateChainOwner {
+                    locked_until: Utc::now().naive_utc(),
+                    owner_id: shared_key_id,
+                    chain: serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap(),
+                })
+            });
+        db.expect_update_session_state().times(0);
+
+        let sc_entity = test_sc_entity(db, None, None, None, None);
+
+        for m in malformed_sigs() {
+            let mut msg = withdraw_msg_1.clone();
+            msg.statechain_sigs[0].sig = m.clone();
+            match sc_entity.withdraw_init(msg) {
+                Ok(_) => assert!(false, "Expected failure for sig {:?}", m),
+                Err(e) => assert!(
+                    e.to_string().contains("Signature Error")
+                        || e.to_string().contains("Format Error"),
+                    "unexpected error for sig {:?}: {}",
+                    m,
+                    e
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_withdraw_max_inputs() {
+        let mut withdraw_msg_1 = serde_json::from_str::<WithdrawMsg1>(WITHDRAW_MSG_1).unwrap();
+        withdraw_msg_1.shared_key_ids.push(Uuid::new_v4());
+        withdraw_msg_1
+            .statechain_sigs
+            .push(withdraw_msg_1.statechain_sigs[0].clone());
+        let withdraw_msg_2 = WithdrawMsg2 {
+            shared_key_ids: withdraw_msg_1.shared_key_ids.clone(),
+            address: "bcrt1qt3jh638mmuzmh92jz8c4wj392p9gj2erf2zut8".to_string(),
+        };
+
+        let mut db = MockDatabase::new();
+        db.expect_set_connection_from_config().returning(|_| Ok(()));
+        let mut sc_entity = test_sc_entity(db, None, None, None, None);
+        sc_entity.config.max_withdraw_inputs = 1;
+
+        // Rejected before any statecoin is authorised or withdrawn
+        match sc_entity.withdraw_init(withdraw_msg_1) {
+            Err(e) => assert!(e.to_string().contains("exceeds the maximum of 1"), "{}", e),
+            Ok(_) => assert!(false, "Expected failure."),
+        }
+        match sc_entity.withdraw_confirm(withdraw_msg_2) {
+            Err(e) => assert!(e.to_string().contains("exceeds the maximum of 1"), "{}", e),
+            Ok(_) => assert!(false, "Expected failure."),
+        }
+    }
+
     #[test]
     fn integration_test_withdraw_confirm() {
         let withdraw_msg_1 = serde_json::from_str::<WithdrawMsg1>(WITHDRAW_MSG_1).unwrap();
@@ -343,6 +482,9 @@ mod tests {
         let statechain_id = Uuid::from_str(STATE_CHAIN_ID).unwrap();
 
         let mut db = MockDatabase::new();
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
@@ -368,15 +510,33 @@ mod tests {
                 statechain_id,
             })
         });
+        db.expect_get_statechain_amount().returning(move |_| {
+            Ok(StateChainAmount {
+                chain: serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap(),
+                amount: 10000,
+            })
+        });
         db.expect_get_statechain()
             .returning(move |_| Ok(serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap()));
+        db.expect_get_statechain_version().returning(|_| Ok(0));
         db.expect_update_statechain_amount()
-            .returning(|_, _, _, _| Ok(()));
+            .returning(|_, _, _, _, _| Ok(()));
         db.expect_remove_statechain_id().returning(|_| Ok(()));
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
         db.expect_root_update().returning(|_| Ok(1));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         db.expect_remove_backup_tx().returning(|_| Ok(()));
+        db.expect_insert_fee()
+            .withf(|_, fee_type, _, amount| *fee_type == FeeType::Withdraw && *amount == 40)
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Withdraw)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Withdrawn)
+            .times(1)
+            .returning(|_, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
         let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
@@ -400,6 +560,9 @@ mod tests {
         let statechain_id = Uuid::from_str(STATE_CHAIN_ID).unwrap();
 
         let mut db = MockDatabase::new();
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
@@ -415,6 +578,14 @@ mod tests {
                     chain: serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap(),
                 })
             });
+        db.expect_get_statechain_amount().returning(move |_| {
+            Ok(StateChainAmount {
+                chain: serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap(),
+                amount: 10000,
+            })
+        });
+        db.expect_get_backup_transaction()
+            .returning(|_| Ok(serde_json::from_str(&BACKUP_TX_SIGNED).unwrap()));
         db.expect_update_withdraw_sc_sig().returning(|_, _| Ok(()));
         //Repeat init (RBF)
         db.expect_get_user_auth()
@@ -431,7 +602,24 @@ mod tests {
                     chain: serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap(),
                 })
             });
+        db.expect_get_statechain_amount().returning(move |_| {
+            Ok(StateChainAmount {
+                chain: serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap(),
+                amount: 10000,
+            })
+        });
+        db.expect_get_backup_transaction()
+            .returning(|_| Ok(serde_json::from_str(&BACKUP_TX_SIGNED).unwrap()));
         db.expect_update_withdraw_sc_sig().returning(|_, _| Ok(()));
+        db.expect_get_session_state()
+            .times(1)
+            .returning(|_| Ok(Some(SessionState::Owner)));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Withdraw)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Withdraw)
+            .times(1)
+            .returning(|_, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
 
@@ -450,6 +638,9 @@ mod tests {
         let statechain_id = Uuid::from_str(STATE_CHAIN_ID).unwrap();
 
         let mut db = MockDatabase::new();
+        db.expect_get_sale().returning(|_| Ok(None));
+        db.expect_get_second_factor().returning(|_| Ok(None));
+        db.expect_get_vault().returning(|_| Ok(None));
         db.expect_set_connection_from_config().returning(|_| Ok(()));
         db.expect_get_user_auth()
            .returning(|_user_id| Ok(String::from("user_auth")));
@@ -477,13 +668,25 @@ mod tests {
         });
         db.expect_get_statechain()
             .returning(move |_| Ok(serde_json::from_str::<StateChainUnchecked>(STATE_CHAIN).unwrap().try_into().unwrap()));
+        db.expect_get_statechain_version().returning(|_| Ok(0));
         db.expect_update_statechain_amount()
-            .returning(|_, _, _, _| Ok(()));
+            .returning(|_, _, _, _, _| Ok(()));
         db.expect_remove_statechain_id().returning(|_| Ok(()));
         db.expect_root_get_current_id().returning(|| Ok(1 as i64));
         db.expect_get_root().returning(|_| Ok(None));
         db.expect_root_update().returning(|_| Ok(1));
+        db.expect_insert_root_leaves().returning(|_, _| Ok(()));
         db.expect_remove_backup_tx().returning(|_| Ok(()));
+        db.expect_insert_fee()
+            .withf(|_, fee_type, _, amount| *fee_type == FeeType::Withdraw && *amount == 40)
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        db.expect_get_session_state()
+            .returning(|_| Ok(Some(SessionState::Withdraw)));
+        db.expect_update_session_state()
+            .withf(|_, state| *state == SessionState::Withdrawn)
+            .times(1)
+            .returning(|_, _| Ok(()));
 
         let sc_entity = test_sc_entity(db, None, None, None, None);
         let _m = mocks::ms::post_commitment().create(); //Mainstay post commitment mock
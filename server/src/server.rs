@@ -1,9 +1,16 @@
 use super::protocol::conductor::Scheduler;
+use super::protocol::api_key::ApiKeyLimiter;
+use super::protocol::root_update::RootQueue;
+use super::protocol::util::{ProofCache, SmtBatch};
+use super::protocol::ping::HealthState;
+use super::protocol::chain_height::ChainHeight;
+use super::api;
 use super::protocol::*;
+use crate::aml::{AmlHook, NoopAmlHook};
 use crate::config::{Config, Mode};
 use crate::structs::{StateChainOwner, WithdrawConfirmData};
 use crate::Database;
-use shared_lib::{mainstay, state_chain::StateChainSig, swap_data::*};
+use shared_lib::{mainstay, noise::TransportState, state_chain::StateChainSig, structs::StateEntityFeeInfoAPI, swap_data::*};
 
 use log::LevelFilter;
 use log4rs::append::file::FileAppender;
@@ -19,9 +26,12 @@ use rocket;
 use rocket_okapi::routes_with_openapi;
 use rocket_okapi::swagger_ui::{make_swagger_ui, SwaggerUIConfig};
 use rocket::{
-    config::{Config as RocketConfig, Environment},
+    config::{Config as RocketConfig, Environment, Limits},
     Request, Rocket, Route
 };
+use rocket_contrib::compression::Compression;
+use crate::api::limits::BodyLimits;
+use crate::chaos::FaultInjector;
 use rocket_prometheus::{
     prometheus::{opts, IntCounter, IntCounterVec},
     PrometheusMetrics,
@@ -29,7 +39,9 @@ use rocket_prometheus::{
 use reqwest;
 use once_cell::sync::Lazy;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
 use uuid::Uuid;
+use chrono::NaiveDateTime;
 use std::collections::HashMap;
 use url::Url;
 use std::collections::HashSet;
@@ -54,6 +66,15 @@ pub static REG_SWAP_UTXOS: Lazy<IntCounterVec> = Lazy::new(|| {
         .expect("Could not create lazy IntGaugeVec")
 });
 
+pub static ORPHANED_SESSIONS_REMOVED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(opts!("orphaned_sessions_removed", "Orphaned user sessions removed by session state"), &["state"])
+        .expect("Could not create lazy IntCounterVec")
+});
+pub static ORPHANED_SESSION_ROWS_REMOVED: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("orphaned_session_rows_removed", "Ecdsa, lockbox, deposit handoff and fee acknowledgement rows removed with orphaned user sessions")
+        .expect("Could not create lazy IntCounter")
+});
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 pub type UserIDs = HashSet::<Uuid>;
@@ -110,7 +131,30 @@ pub struct StateChainEntity<
     pub lockbox: Option<Lockbox>,
     pub rate_limiter_slow: Option<Arc<governor::RateLimiter<String, DashMapStateStore<String> , DefaultClock> >>,
     pub rate_limiter_fast: Option<Arc<governor::RateLimiter<String, DashMapStateStore<String> , DefaultClock> >>,
-    pub rate_limiter_id: Option<Arc<governor::RateLimiter<Uuid, DashMapStateStore<Uuid> , DefaultClock> >>
+    pub rate_limiter_id: Option<Arc<governor::RateLimiter<Uuid, DashMapStateStore<Uuid> , DefaultClock> >>,
+    pub aml_hook: Arc<dyn AmlHook>,
+    pub api_key_limiters: Arc<Mutex<HashMap<String, Arc<ApiKeyLimiter>>>>,
+    pub proof_cache: Arc<Mutex<ProofCache>>,
+    pub fee_info_cache: Arc<Mutex<Option<StateEntityFeeInfoAPI>>>,
+    /// Outstanding user sessions challenges and their expiry times
+    pub user_sessions_challenges: Arc<Mutex<HashMap<String, NaiveDateTime>>>,
+    /// Outstanding ownership challenge nonces and their expiry times
+    pub ownership_challenges: Arc<Mutex<HashMap<String, NaiveDateTime>>>,
+    /// Accelerated withdraw txids and the fee rate of their CPFP child tx
+    pub accelerations: Arc<Mutex<HashMap<String, u64>>>,
+    /// Open encrypted ECDSA channels by user session
+    pub noise_channels: Arc<Mutex<HashMap<Uuid, TransportState>>>,
+    /// SMT updates waiting for batch insertion
+    pub smt_batch: Arc<SmtBatch>,
+    /// SMT updates queued for the next root
+    pub root_queue: Arc<RootQueue>,
+    /// Serializes escrowed sale updates
+    pub sale_lock: Arc<Mutex<()>>,
+    pub health: Arc<Mutex<HealthState>>,
+    /// Deposit circuit breaker. New deposits are refused while set.
+    pub deposits_paused: Arc<AtomicBool>,
+    /// Latest block height, updated by the watcher
+    pub chain_height: Arc<ChainHeight>,
 }
 
 impl<
@@ -130,7 +174,11 @@ impl<
             hasher: Blake3::new(),
         };
 
-        let conductor_config = config_rs.conductor.clone();
+        // The conductor locks statecoins for wall-clock times
+        let mut conductor_config = config_rs.conductor.clone();
+        conductor_config.punishment_duration = config_rs
+            .duration_unit
+            .seconds(conductor_config.punishment_duration);
 
         pub fn init_lb(conf: &Config) -> Option<Lockbox>{
 
@@ -151,13 +199,17 @@ impl<
         let (lockbox, scheduler) = match config_rs.mode {
             Mode::Both => (init_lb(&config_rs), Some(Arc::new(Mutex::new(Scheduler::new(&conductor_config))))),
             Mode::Conductor => (None, Some(Arc::new(Mutex::new(Scheduler::new(&conductor_config))))),
-            Mode::Core => (init_lb(&config_rs), None)
+            Mode::Core => (init_lb(&config_rs), None),
+            Mode::Watchtower => (None, None)
         };
 
         let rate_limiter_slow = config_rs.rate_limit_slow.map(|r| Arc::new(governor::RateLimiter::dashmap(Quota::per_second(r))));
         let rate_limiter_fast = config_rs.rate_limit_fast.map(|r| Arc::new(governor::RateLimiter::dashmap(Quota::per_second(r))));
         let rate_limiter_id = config_rs.rate_limit_id.map(|r| Arc::new(governor::RateLimiter::dashmap(Quota::per_second(r))));
 
+        let proof_cache = ProofCache::new(config_rs.proof_cache_size);
+        let deposits_paused = Arc::new(AtomicBool::new(config_rs.deposits_paused));
+
         let sce = Self {
             config: config_rs,
             database: db,
@@ -168,11 +220,64 @@ impl<
             lockbox,
             rate_limiter_slow,
             rate_limiter_fast,
-            rate_limiter_id
+            rate_limiter_id,
+            aml_hook: Arc::new(NoopAmlHook),
+            api_key_limiters: Arc::new(Mutex::new(HashMap::new())),
+            proof_cache: Arc::new(Mutex::new(proof_cache)),
+            fee_info_cache: Arc::new(Mutex::new(None)),
+            user_sessions_challenges: Arc::new(Mutex::new(HashMap::new())),
+            ownership_challenges: Arc::new(Mutex::new(HashMap::new())),
+            accelerations: Arc::new(Mutex::new(HashMap::new())),
+            noise_channels: Arc::new(Mutex::new(HashMap::new())),
+            smt_batch: Arc::new(SmtBatch::default()),
+            root_queue: Arc::new(RootQueue::default()),
+            sale_lock: Arc::new(Mutex::new(())),
+            health: Arc::new(Mutex::new(HealthState::default())),
+            deposits_paused,
+            chain_height: Arc::new(ChainHeight::default()),
         };
 
         Ok(sce)
     }
+
+    /// Another handle to the same state entity, sharing its in-memory state, SMT, scheduler,
+    /// rate limiters and caches, with its own database connection. Used to serve the entity
+    /// from more than one frontend.
+    pub fn share(&self) -> Result<Self> {
+        let mut database = T::get_new();
+        database.set_connection_from_config(&self.config)?;
+        Ok(Self {
+            config: self.config.clone(),
+            database,
+            coin_value_info: self.coin_value_info.clone(),
+            user_ids: self.user_ids.clone(),
+            smt: self.smt.clone(),
+            scheduler: self.scheduler.clone(),
+            lockbox: self.lockbox.clone(),
+            rate_limiter_slow: self.rate_limiter_slow.clone(),
+            rate_limiter_fast: self.rate_limiter_fast.clone(),
+            rate_limiter_id: self.rate_limiter_id.clone(),
+            aml_hook: self.aml_hook.clone(),
+            api_key_limiters: self.api_key_limiters.clone(),
+            proof_cache: self.proof_cache.clone(),
+            fee_info_cache: self.fee_info_cache.clone(),
+            user_sessions_challenges: self.user_sessions_challenges.clone(),
+            ownership_challenges: self.ownership_challenges.clone(),
+            accelerations: self.accelerations.clone(),
+            noise_channels: self.noise_channels.clone(),
+            smt_batch: self.smt_batch.clone(),
+            root_queue: self.root_queue.clone(),
+            sale_lock: self.sale_lock.clone(),
+            health: self.health.clone(),
+            deposits_paused: self.deposits_paused.clone(),
+            chain_height: self.chain_height.clone(),
+        })
+    }
+
+    /// Set the hook used to screen deposits and withdrawals
+    pub fn set_aml_hook(&mut self, hook: Arc<dyn AmlHook>) {
+        self.aml_hook = hook;
+    }
 }
 
 #[catch(500)]
@@ -185,6 +290,11 @@ fn bad_request() -> &'static str {
     "Bad request"
 }
 
+#[catch(413)]
+fn payload_too_large() -> &'static str {
+    "Payload too large"
+}
+
 #[catch(404)]
 fn not_found(req: &Request) -> String {
     format!("Unknown route '{}'.", req.uri())
@@ -203,86 +313,197 @@ fn get_docs() -> SwaggerUIConfig {
 fn get_routes(mode: &Mode) -> std::vec::Vec<Route>{
     match mode {
         Mode::Both => routes_with_openapi![
-            util::get_statechain,
-            util::get_statecoin,
-            util::get_owner_id,
-            util::get_smt_root,
-            util::get_smt_proof,
-            util::get_fees,
-            util::prepare_sign_tx,
-            util::get_recovery_data,
-            util::get_transfer_batch_status,
-            util::get_coin_info,
-            util::reset_test_dbs,
-            util::reset_inram_data,
-            util::get_sc_transfer_finalize_data,
-            ecdsa::first_message,
-            ecdsa::second_message,
-            ecdsa::sign_first,
-            ecdsa::sign_second,
-            deposit::deposit_init,
-            deposit::deposit_confirm,
-            transfer::transfer_sender,
-            transfer::transfer_receiver,
-            transfer::transfer_update_msg,
-            transfer::transfer_get_msg,
-            transfer::transfer_get_msg_addr,
-            transfer::transfer_get_pubkey,
-            transfer_batch::transfer_batch_init,
-            transfer_batch::transfer_reveal_nonce,
-            withdraw::withdraw_init,
-            withdraw::withdraw_confirm,
-            conductor::poll_utxo,
-            conductor::poll_swap,
-            conductor::get_swap_info,
-            conductor::get_blinded_spend_signature,
-            conductor::register_utxo,
-            conductor::deregister_utxo,
-            conductor::swap_first_message,
-            conductor::swap_second_message,
-            conductor::get_group_info],
+            api::util::get_statechain,
+            api::util::get_statecoin,
+            api::util::get_owner_id,
+            api::util::get_smt_root,
+            api::util::get_smt_proof,
+            api::util::get_smt_proofs,
+            api::util::get_statechain_updates,
+            api::util::get_fees,
+            api::util::get_params,
+            api::util::prepare_sign_tx,
+            api::util::submit_backup_cpfp,
+            api::util::get_reserve_proof,
+            api::util::get_user_sessions_challenge,
+            api::util::get_user_sessions,
+            api::util::get_ownership_challenge,
+            api::util::prove_ownership,
+            api::util::get_build_manifest,
+            api::util::get_statechain_export,
+            api::util::get_deletion_receipts,
+            api::util::get_transfer_receipts,
+            api::util::get_punishment_standing,
+            api::api_key::issue_api_key,
+            api::api_key::revoke_api_key,
+            api::revenue::revenue_report,
+            api::util::get_recovery_data,
+            api::util::get_transfer_batch_status,
+            api::util::get_coin_info,
+            api::util::reset_test_dbs,
+            api::util::reset_inram_data,
+            api::util::get_sc_transfer_finalize_data,
+            api::ecdsa::first_message,
+            api::ecdsa::second_message,
+            api::ecdsa::keygen_audit,
+            api::ecdsa::sign_first,
+            api::ecdsa::sign_second,
+            api::ecdsa::noise_get_identity,
+            api::ecdsa::noise_handshake,
+            api::ecdsa::noise_ecdsa,
+            api::ecdsa::key_refresh,
+            api::deposit::deposit_init,
+            api::deposit::deposit_confirm,
+            api::deposit::deposit_handoff,
+            api::deposit::deposit_get_handoffs,
+            api::deposit::deposit_refund,
+            api::deposit::set_deposits_paused,
+            api::transfer::transfer_sender,
+            api::transfer::transfer_receiver,
+            api::transfer::transfer_update_msg,
+            api::transfer::transfer_get_msg,
+            api::transfer::transfer_get_msg_addr,
+            api::transfer::transfer_reject,
+            api::transfer::transfer_get_reject,
+            api::transfer::transfer_get_pubkey,
+            api::transfer::sale_init,
+            api::transfer::sale_get,
+            api::transfer::sale_reveal,
+            api::transfer::inheritance_designate,
+            api::transfer::inheritance_checkin,
+            api::transfer::inheritance_revoke,
+            api::transfer::inheritance_get,
+            api::second_factor::second_factor_register,
+            api::second_factor::second_factor_get,
+            api::vault::vault_set,
+            api::vault::vault_cancel,
+            api::vault::vault_get,
+            api::migrate::migrate_get_pubkey,
+            api::migrate::migrate_init,
+            api::migrate::migrate_export,
+            api::migrate::migrate_import,
+            api::refresh::refresh_first,
+            api::refresh::refresh_second,
+            api::rotate::rotate_proof_key,
+            api::transfer_batch::transfer_batch_init,
+            api::transfer_batch::transfer_reveal_nonce,
+            api::withdraw::withdraw_init,
+            api::withdraw::withdraw_confirm,
+            api::withdraw::withdraw_accelerate,
+            api::conductor::poll_utxo,
+            api::conductor::poll_swap,
+            api::conductor::get_swap_info,
+            api::conductor::get_blinded_spend_signature,
+            api::conductor::register_utxo,
+            api::conductor::deregister_utxo,
+            api::conductor::swap_first_message,
+            api::conductor::swap_second_message,
+            api::conductor::get_group_info,
+            api::conductor::get_swap_stats,
+            api::conductor::get_swap_report,
+            api::watchtower::watchtower_get_key,
+            api::watchtower::watchtower_register,
+            api::watchtower::watchtower_revoke],
         Mode::Core => routes_with_openapi![
-            util::get_statechain,
-            util::get_statecoin,
-            util::get_owner_id,
-            util::get_smt_root,
-            util::get_smt_proof,
-            util::get_fees,
-            util::prepare_sign_tx,
-            util::get_recovery_data,
-            util::get_transfer_batch_status,
-            util::get_coin_info,
-            util::reset_test_dbs,
-            util::reset_inram_data,
-            util::get_sc_transfer_finalize_data,
-            ecdsa::first_message,
-            ecdsa::second_message,
-            ecdsa::sign_first,
-            ecdsa::sign_second,
-            deposit::deposit_init,
-            deposit::deposit_confirm,
-            transfer::transfer_sender,
-            transfer::transfer_receiver,
-            transfer::transfer_update_msg,
-            transfer::transfer_get_msg,
-            transfer::transfer_get_msg_addr,
-            transfer::transfer_get_pubkey,
-            transfer_batch::transfer_batch_init,
-            transfer_batch::transfer_reveal_nonce,
-            withdraw::withdraw_init,
-            withdraw::withdraw_confirm],
+            api::util::get_statechain,
+            api::util::get_statecoin,
+            api::util::get_owner_id,
+            api::util::get_smt_root,
+            api::util::get_smt_proof,
+            api::util::get_smt_proofs,
+            api::util::get_statechain_updates,
+            api::util::get_fees,
+            api::util::get_params,
+            api::util::prepare_sign_tx,
+            api::util::submit_backup_cpfp,
+            api::util::get_reserve_proof,
+            api::util::get_user_sessions_challenge,
+            api::util::get_user_sessions,
+            api::util::get_ownership_challenge,
+            api::util::prove_ownership,
+            api::util::get_build_manifest,
+            api::util::get_statechain_export,
+            api::util::get_deletion_receipts,
+            api::util::get_transfer_receipts,
+            api::util::get_punishment_standing,
+            api::api_key::issue_api_key,
+            api::api_key::revoke_api_key,
+            api::revenue::revenue_report,
+            api::util::get_recovery_data,
+            api::util::get_transfer_batch_status,
+            api::util::get_coin_info,
+            api::util::reset_test_dbs,
+            api::util::reset_inram_data,
+            api::util::get_sc_transfer_finalize_data,
+            api::ecdsa::first_message,
+            api::ecdsa::second_message,
+            api::ecdsa::keygen_audit,
+            api::ecdsa::sign_first,
+            api::ecdsa::sign_second,
+            api::ecdsa::noise_get_identity,
+            api::ecdsa::noise_handshake,
+            api::ecdsa::noise_ecdsa,
+            api::ecdsa::key_refresh,
+            api::deposit::deposit_init,
+            api::deposit::deposit_confirm,
+            api::deposit::deposit_handoff,
+            api::deposit::deposit_get_handoffs,
+            api::deposit::deposit_refund,
+            api::deposit::set_deposits_paused,
+            api::transfer::transfer_sender,
+            api::transfer::transfer_receiver,
+            api::transfer::transfer_update_msg,
+            api::transfer::transfer_get_msg,
+            api::transfer::transfer_get_msg_addr,
+            api::transfer::transfer_reject,
+            api::transfer::transfer_get_reject,
+            api::transfer::transfer_get_pubkey,
+            api::transfer::sale_init,
+            api::transfer::sale_get,
+            api::transfer::sale_reveal,
+            api::transfer::inheritance_designate,
+            api::transfer::inheritance_checkin,
+            api::transfer::inheritance_revoke,
+            api::transfer::inheritance_get,
+            api::second_factor::second_factor_register,
+            api::second_factor::second_factor_get,
+            api::vault::vault_set,
+            api::vault::vault_cancel,
+            api::vault::vault_get,
+            api::migrate::migrate_get_pubkey,
+            api::migrate::migrate_init,
+            api::migrate::migrate_export,
+            api::migrate::migrate_import,
+            api::refresh::refresh_first,
+            api::refresh::refresh_second,
+            api::rotate::rotate_proof_key,
+            api::transfer_batch::transfer_batch_init,
+            api::transfer_batch::transfer_reveal_nonce,
+            api::withdraw::withdraw_init,
+            api::withdraw::withdraw_confirm,
+            api::withdraw::withdraw_accelerate,
+            api::watchtower::watchtower_get_key,
+            api::watchtower::watchtower_register,
+            api::watchtower::watchtower_revoke],
         Mode::Conductor => routes_with_openapi![
-            util::reset_test_dbs,
-            util::reset_inram_data,
-            conductor::poll_utxo,
-            conductor::poll_swap,
-            conductor::get_swap_info,
-            conductor::get_blinded_spend_signature,
-            conductor::register_utxo,
-            conductor::deregister_utxo,
-            conductor::swap_first_message,
-            conductor::swap_second_message,
-            conductor::get_group_info],
+            api::util::get_punishment_standing,
+            api::util::reset_test_dbs,
+            api::util::reset_inram_data,
+            api::conductor::poll_utxo,
+            api::conductor::poll_swap,
+            api::conductor::get_swap_info,
+            api::conductor::get_blinded_spend_signature,
+            api::conductor::register_utxo,
+            api::conductor::deregister_utxo,
+            api::conductor::swap_first_message,
+            api::conductor::swap_second_message,
+            api::conductor::get_group_info,
+            api::conductor::get_swap_stats,
+            api::conductor::get_swap_report],
+        Mode::Watchtower => routes_with_openapi![
+            api::watchtower::watchtower_get_key,
+            api::watchtower::watchtower_register,
+            api::watchtower::watchtower_revoke],
     }
 }
 
@@ -320,7 +541,7 @@ pub fn get_server<
 
     //At this point the mainstay config should be set,
     //either in testing mode or specified in the settings file
-    if sc_entity.config.mainstay.is_none() {
+    if sc_entity.config.mainstay.is_none() && !matches!(sc_entity.config.mode, Mode::Watchtower) {
         panic!("expected mainstay config");
     }
 
@@ -329,35 +550,54 @@ pub fn get_server<
     prometheus.registry().register(Box::new(WITHDRAWALS_COUNT.clone())).unwrap();
     prometheus.registry().register(Box::new(TRANSFERS_COUNT.clone())).unwrap();
     prometheus.registry().register(Box::new(REG_SWAP_UTXOS.clone())).unwrap();
+    prometheus.registry().register(Box::new(ORPHANED_SESSIONS_REMOVED.clone())).unwrap();
+    prometheus.registry().register(Box::new(ORPHANED_SESSION_ROWS_REMOVED.clone())).unwrap();
+
+    let faults = FaultInjector::new(&sc_entity.config.chaos);
+    if faults.enabled() {
+        warn!("CHAOS: Fault injection enabled: {:?}", sc_entity.config.chaos);
+    } else if sc_entity.config.chaos.is_set() {
+        warn!("CHAOS: Fault injection config ignored: server not built with the chaos feature");
+    }
+    if sc_entity.config.dynamodb.enabled && cfg!(not(feature = "dynamodb")) {
+        warn!("DynamoDB: Storage config ignored: server not built with the dynamodb feature");
+    }
 
-    let rocket_config = get_rocket_config(&sc_entity.config);
+    let body_limits = BodyLimits::from_config(&sc_entity.config.rocket)?;
+    let rocket_config = get_rocket_config(&sc_entity.config, &body_limits);
     let bitcoind = sc_entity.config.bitcoind.clone();
+    let chain_height = sc_entity.chain_height.clone();
 
     if sc_entity.config.watch_only {
         info!("Server running in watch-only mode.");
-        thread::spawn(|| watch_node(bitcoind));
+        thread::spawn(|| watch_node(bitcoind, chain_height));
         let rock = rocket::custom(rocket_config)
-            .register(catchers![internal_error, not_found, bad_request])
+            .register(catchers![internal_error, not_found, bad_request, payload_too_large])
             .mount(
                 "/",
                 routes![
-                    ping::ping
+                    api::ping::ping
                 ],
             );
         Ok(rock)
     } else {
         // if bitcoind path supplied, run watching
         if sc_entity.config.bitcoind.is_empty() == false {
-            thread::spawn(|| watch_node(bitcoind));
+            thread::spawn(|| watch_node(bitcoind, chain_height));
         }
         
         let rock = rocket::custom(rocket_config)
-            .register(catchers![internal_error, not_found, bad_request])
+            .register(catchers![internal_error, not_found, bad_request, payload_too_large])
             .attach(prometheus.clone())
+            .attach(body_limits)
+            .attach(Compression::fairing())
+            .attach(faults)
             .mount(
                 "/",
                 routes![
-                    ping::ping,
+                    api::ping::ping,
+                    api::ping::health,
+                    api::limits::payload_too_large,
                 ],
             )
             .mount(
@@ -372,6 +612,14 @@ pub fn get_server<
     }
 }
 
+/// State entity managed by a Rocket server returned by get_server, to share with the
+/// background tasks and other frontends
+pub fn state_entity(rocket: &Rocket) -> Result<&api::SCE> {
+    rocket
+        .state::<api::SCE>()
+        .ok_or_else(|| "Rocket is not managing a state entity".into())
+}
+
 fn set_logging_config(log_file: &String) {
     if log_file.len() == 0 {
         let _ = env_logger::try_init();
@@ -393,11 +641,12 @@ fn set_logging_config(log_file: &String) {
     }
 }
 
-fn get_rocket_config(config: &Config) -> RocketConfig {
+fn get_rocket_config(config: &Config, body_limits: &BodyLimits) -> RocketConfig {
     RocketConfig::build(Environment::Staging)
         .keep_alive(config.rocket.keep_alive.clone())
         .address(config.rocket.address.clone())
         .port(config.rocket.port.clone())
+        .limits(Limits::new().limit("json", body_limits.max()))
         .finalize()
         .unwrap()
 }
@@ -418,12 +667,24 @@ pub fn get_postgres_url(
 
 //Mock all the traits implemented by StateChainEntity so that they can
 //be called from MockStateChainEntity
+use crate::protocol::accelerate::Accelerate;
+use crate::protocol::api_key::ApiKeys;
 use crate::protocol::conductor::Conductor;
 use crate::protocol::deposit::Deposit;
 use crate::protocol::ecdsa::Ecdsa;
+use crate::protocol::migrate::Migrate;
+use crate::protocol::noise::EcdsaChannel;
+use crate::protocol::refresh::Refresh;
+use crate::protocol::revenue::Revenue;
+use crate::protocol::rotate::Rotate;
+use crate::protocol::inheritance::Inheritance;
+use crate::protocol::sale::Sale;
+use crate::protocol::second_factor::SecondFactor;
 use crate::protocol::transfer::Transfer;
 use crate::protocol::transfer_batch::BatchTransfer;
 use crate::protocol::util::{Proof, Utilities, RateLimiter};
+use crate::protocol::vault::Vault;
+use crate::protocol::watchtower::Watchtower;
 use crate::protocol::withdraw::Withdraw;
 use crate::storage;
 use crate::storage::Storage;
@@ -439,6 +700,13 @@ mock! {
             &self,
             deposit_msg2: DepositMsg2,
         ) -> deposit::Result<StatechainID>;
+        fn deposit_handoff(&self, handoff_msg: DepositHandoffMsg) -> deposit::Result<()>;
+        fn deposit_get_handoffs(
+            &self,
+            proof_key: String,
+        ) -> deposit::Result<Vec<DepositHandoffMsg>>;
+        fn deposit_refund(&self, refund_msg: DepositRefundMsg) -> deposit::Result<()>;
+        fn set_deposits_paused(&self, pause_msg: DepositPauseMsg) -> deposit::Result<DepositCapacityAPI>;
     }
     trait Ecdsa {
         fn master_key(&self, user_id: Uuid) -> ecdsa::Result<()>;
@@ -462,6 +730,13 @@ mock! {
             &self,
             sign_msg2: SignMsg2,
         ) -> ecdsa::Result<Vec<Vec<u8>>>;
+
+        fn key_refresh(&self, user_id: Uuid) -> ecdsa::Result<()>;
+    }
+    trait EcdsaChannel {
+        fn noise_get_identity(&self) -> noise::Result<IdentityPubKey>;
+        fn noise_handshake(&self, handshake_msg: NoiseHandshakeMsg) -> noise::Result<NoiseMsg>;
+        fn noise_ecdsa(&self, noise_msg: NoiseMsg) -> noise::Result<NoiseMsg>;
     }
     trait Conductor {
         fn poll_utxo(&self, statechain_id: &Uuid) -> conductor::Result<SwapID>;
@@ -472,10 +747,20 @@ mock! {
         fn swap_first_message(&self, swap_msg1: &SwapMsg1) -> conductor::Result<()>;
         fn swap_second_message(&self, swap_msg2: &SwapMsg2) -> conductor::Result<SCEAddress>;
         fn get_group_info(&self) -> conductor::Result<HashMap<SwapGroup,GroupStatus>>;
+        fn get_swap_stats(&self) -> conductor::Result<SwapStatsAPI>;
+        fn get_swap_report(&self, swap_id: &Uuid) -> conductor::Result<SignedSwapReport>;
         fn get_blinded_spend_signature(&self, swap_id: &Uuid, statechain_id: &Uuid) -> conductor::Result<BlindedSpendSignature>;
         fn get_address_from_blinded_spend_token(&self, bst: &BlindedSpendToken) -> conductor::Result<SCEAddress>;
         fn update_swap_info(&self) -> conductor::Result<bool>;
     }
+    trait ApiKeys {
+        fn issue_api_key(&self, issue_msg: ApiKeyIssueMsg) -> api_key::Result<ApiKeyAPI>;
+        fn revoke_api_key(&self, revoke_msg: ApiKeyRevokeMsg) -> api_key::Result<()>;
+        fn check_rate_info(&self, api_key: &Option<String>) -> api_key::Result<()>;
+    }
+    trait Revenue {
+        fn revenue_report(&self, report_msg: RevenueReportMsg) -> revenue::Result<RevenueReportAPI>;
+    }
 
     trait Transfer {
         fn transfer_sender(
@@ -497,6 +782,50 @@ mock! {
         fn transfer_update_msg(&self, transfer_msg3: TransferMsg3) -> transfer::Result<()>;
         fn transfer_get_msg(&self, statechain_id: Uuid) -> transfer::Result<TransferMsg3>;
         fn transfer_get_msg_addr(&self, receive_addr: String) -> transfer::Result<Vec<TransferMsg3>>;
+        fn transfer_reject(&self, transfer_reject_msg: TransferRejectMsg) -> transfer::Result<()>;
+        fn transfer_get_reject(&self, statechain_id: Uuid) -> transfer::Result<Option<TransferRejectAPI>>;
+    }
+    trait Sale {
+        fn sale_init(&self, sale_init_msg: SaleInitMsg) -> sale::Result<SaleAPI>;
+        fn sale_get(&self, statechain_id: Uuid) -> sale::Result<SaleAPI>;
+        fn sale_reveal(&self, sale_reveal_msg: SaleRevealMsg) -> sale::Result<SaleAPI>;
+    }
+    trait Inheritance {
+        fn inheritance_designate(&self, inheritance_msg: InheritanceMsg) -> inheritance::Result<InheritanceAPI>;
+        fn inheritance_checkin(&self, owner_msg: InheritanceOwnerMsg) -> inheritance::Result<InheritanceAPI>;
+        fn inheritance_revoke(&self, owner_msg: InheritanceOwnerMsg) -> inheritance::Result<()>;
+        fn inheritance_get(&self, statechain_id: Uuid) -> inheritance::Result<InheritanceAPI>;
+    }
+    trait SecondFactor {
+        fn second_factor_register(&self, second_factor_msg: SecondFactorMsg) -> second_factor::Result<SecondFactorAPI>;
+        fn second_factor_get(&self, statechain_id: Uuid) -> second_factor::Result<SecondFactorAPI>;
+    }
+    trait Vault {
+        fn vault_set(&self, vault_msg: VaultMsg) -> vault::Result<VaultAPI>;
+        fn vault_cancel(&self, cancel_msg: VaultCancelMsg) -> vault::Result<VaultAPI>;
+        fn vault_get(&self, statechain_id: Uuid) -> vault::Result<VaultAPI>;
+    }
+    trait Migrate {
+        fn migrate_get_pubkey(&self) -> migrate::Result<MigratePubKey>;
+        fn migrate_init(&self, migrate_msg1: MigrateMsg1) -> migrate::Result<TransferMsg2>;
+        fn migrate_export(
+            &self,
+            migrate_msg2: MigrateMsg2,
+        ) -> migrate::Result<SignedMigrationPackage>;
+        fn migrate_import(&self, package: SignedMigrationPackage) -> migrate::Result<TransferMsg5>;
+    }
+    trait Refresh {
+        fn refresh_first(&self, refresh_msg1: RefreshMsg1) -> refresh::Result<TransferMsg2>;
+        fn refresh_second(&self, refresh_msg2: RefreshMsg2) -> refresh::Result<RefreshReply>;
+    }
+    trait Rotate {
+        fn rotate_proof_key(&self, rotate_msg: RotateMsg) -> rotate::Result<()>;
+    }
+    trait Punishment {
+        fn get_punishment_standing(
+            &self,
+            statechain_id: Uuid,
+        ) -> punishment::Result<PunishmentStandingAPI>;
     }
     trait BatchTransfer {
         fn transfer_batch_init(
@@ -514,11 +843,20 @@ mock! {
     }
     trait Utilities {
         fn get_fees(&self) -> util::Result<StateEntityFeeInfoAPI>;
+        fn get_params(&self) -> util::Result<StateEntityParamsAPI>;
         /// API: Generates sparse merkle tree inclusion proof for some key in a tree with some root.
         fn get_smt_proof(
             &self,
             smt_proof_msg: SmtProofMsgAPI,
         ) -> util::Result<Option<Proof>>;
+        fn get_smt_proofs(
+            &self,
+            smt_proofs_msg: SmtProofsMsgAPI,
+        ) -> util::Result<SmtProofsAPI>;
+        fn get_statechain_updates(
+            &self,
+            since: &String,
+        ) -> util::Result<StateChainUpdatesAPI>;
         fn prepare_sign_tx(
             &self,
             prepare_sign_msg: PrepareSignTxMsg,
@@ -529,6 +867,37 @@ mock! {
         ) -> util::Result<Vec<RecoveryDataMsg>>;
         fn get_lockbox_url(&self, user_id: &Uuid
         ) -> util::Result<Option<(Url, usize)>>;
+        fn submit_backup_cpfp(
+            &self,
+            cpfp_msg: BackupCPFPMsg,
+        ) -> util::Result<()>;
+        fn get_reserve_proof(
+            &self,
+            reserve_proof_msg: ReserveProofMsg,
+        ) -> util::Result<SignedReserveProofReport>;
+        fn get_statechain_export(
+            &self,
+            statechain_id: Uuid,
+        ) -> util::Result<SignedStateChainExport>;
+        fn get_deletion_receipts(
+            &self,
+            statechain_id: Uuid,
+        ) -> util::Result<Vec<SignedDeletionReceipt>>;
+        fn get_transfer_receipts(
+            &self,
+            statechain_id: Uuid,
+        ) -> util::Result<Vec<SignedTransferReceipt>>;
+        fn get_user_sessions_challenge(&self) -> util::Result<UserSessionsChallenge>;
+        fn get_user_sessions(
+            &self,
+            user_sessions_msg: UserSessionsRequest,
+        ) -> util::Result<UserSessionsAPI>;
+        fn get_ownership_challenge(&self) -> util::Result<OwnershipChallenge>;
+        fn prove_ownership(
+            &self,
+            ownership_proof_msg: OwnershipProofMsg,
+        ) -> util::Result<SignedOwnershipConfirmation>;
+        fn get_build_manifest(&self) -> util::Result<SignedBuildManifest>;
     }
     trait RateLimiter{
         fn check_rate_slow<T:'static+Into<String>>(&self, key: T) -> storage::Result<()>;
@@ -553,9 +922,20 @@ mock! {
         fn get_if_signed_for_withdrawal(&self, user_id: &Uuid) 
             -> withdraw::Result<Option<WithdrawConfirmData>>;
     }
+    trait Watchtower {
+        fn watchtower_get_key(&self) -> watchtower::Result<WatchtowerKeyAPI>;
+        fn watchtower_register(&self, register_msg: WatchtowerRegisterMsg) -> watchtower::Result<()>;
+        fn watchtower_revoke(&self, revoke_msg: WatchtowerRevokeMsg) -> watchtower::Result<()>;
+    }
+    trait Accelerate {
+        fn withdraw_accelerate(&self, accelerate_msg: WithdrawAccelerateMsg)
+            -> accelerate::Result<WithdrawAccelerateAPI>;
+    }
     trait Storage{
         fn reset_data(&self) -> storage::Result<()>;
-        fn update_smt(&self, funding_txid: &String, proof_key: &String)
+        fn update_smt(&self, funding_txid: &String, proof_key: &String, amount: u64)
+            -> storage::Result<(Option<storage::Root>, Option<storage::Root>)>;
+        fn update_smt_batch(&self, batch: &[(String, String, u64)])
             -> storage::Result<(Option<storage::Root>, storage::Root)>;
         fn get_confirmed_smt_root(&self) -> storage::Result<Option<storage::Root>>;
         fn get_smt_root(&self) -> storage::Result<Option<storage::Root>>;
@@ -0,0 +1,42 @@
+//! # Service
+//!
+//! Transport-agnostic service API of the state entity.
+//!
+//! The protocol traits (Deposit, Ecdsa, Transfer, BatchTransfer, Withdraw, Conductor,
+//! Utilities, Storage, Ping, ApiKeys, Migrate, Refresh, Rotate, Punishment, Sale,
+//! Inheritance, SecondFactor and Vault) are implemented by StateChainEntity and take and return
+//! plain structs from shared_lib::structs. They have no dependency on the web framework and
+//! can be called directly, e.g. when embedding the state entity in another process or in
+//! tests. The Rocket frontend in the api module is a thin adapter over this API.
+
+pub use crate::protocol::api_key::ApiKeys;
+pub use crate::protocol::conductor::Conductor;
+pub use crate::protocol::deposit::Deposit;
+pub use crate::protocol::ecdsa::Ecdsa;
+pub use crate::protocol::inheritance::Inheritance;
+pub use crate::protocol::migrate::Migrate;
+pub use crate::protocol::ping::Ping;
+pub use crate::protocol::punishment::Punishment;
+pub use crate::protocol::refresh::Refresh;
+pub use crate::protocol::rotate::Rotate;
+pub use crate::protocol::sale::Sale;
+pub use crate::protocol::second_factor::SecondFactor;
+pub use crate::protocol::transfer::Transfer;
+pub use crate::protocol::transfer_batch::BatchTransfer;
+pub use crate::protocol::util::{RateLimiter, Utilities};
+pub use crate::protocol::vault::Vault;
+pub use crate::protocol::withdraw::Withdraw;
+pub use crate::storage::Storage;
+
+/// The complete state entity service API
+pub trait StateEntityService:
+    Deposit + Ecdsa + Transfer + BatchTransfer + Withdraw + Conductor + Utilities + Storage + Ping
+    + ApiKeys + Migrate + Refresh + Rotate + Punishment + Sale + Inheritance + SecondFactor + Vault
+{
+}
+
+impl<T> StateEntityService for T where
+    T: Deposit + Ecdsa + Transfer + BatchTransfer + Withdraw + Conductor + Utilities + Storage + Ping
+        + ApiKeys + Migrate + Refresh + Rotate + Punishment + Sale + Inheritance + SecondFactor + Vault
+{
+}
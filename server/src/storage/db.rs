@@ -3,9 +3,11 @@
 //! Postgres DB access and update tools.
 
 use super::super::Result;
-use bitcoin::Transaction;
+use bitcoin::{OutPoint, Transaction};
 pub type Hash = bitcoin::hashes::sha256d::Hash;
 
+use crate::chaos::FaultInjector;
+use super::encryption::{column_aad, ColumnCipher, ENCRYPTED_COLUMNS};
 use crate::server::{get_postgres_url, UserIDs};
 use crate::{
     error::{
@@ -26,7 +28,8 @@ use rocket_contrib::databases::r2d2;
 use rocket_contrib::databases::r2d2_postgres::{PostgresConnectionManager, TlsMode};
 use shared_lib::mainstay::CommitmentInfo;
 use shared_lib::state_chain::*;
-use shared_lib::structs::{TransferMsg3,CoinValueInfo,TransferFinalizeData};
+use shared_lib::structs::{TransferMsg3,CoinValueInfo,TransferFinalizeData,SignedDeletionReceipt,DepositHandoffMsg,StateEntityFeeInfoAPI,SignedTransferReceipt,TransferRejectAPI,FeeType,SpendIncident,PayToContract};
+use shared_lib::swap_data::SwapReport;
 use shared_lib::Root;
 use shared_lib::util::transaction_deserialise;
 use rocket_okapi::JsonSchema;
@@ -34,6 +37,7 @@ use rocket_okapi::JsonSchema;
 use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 use std::convert::TryInto;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
 use monotree::database::MemCache;
@@ -70,16 +74,78 @@ pub enum Table {
     Root,
     BackupTxs,
     Smt,
-    Lockbox
+    Lockbox,
+    SignedSighash,
+    ApiKey,
+    DeletionReceipt,
+    Punishment,
+    DepositHandoff,
+    FeeAcknowledgement,
+    TransferReceipt,
+    StateChainArchive,
+    Sale,
+    TransferReject,
+    SwapFee,
+    RootLeaf,
+    Watchtower,
+    FeeLedger,
+    ChainSegment,
+    Inheritance,
+    SecondFactor,
+    Vault,
+    SwapReport,
+    SpendIncident,
+    BackupTxHistory,
+    SchemaVersion,
 }
 impl Table {
+    /// All tables holding server state (excludes the schema version table)
+    pub fn data_tables() -> Vec<Table> {
+        vec![
+            Table::UserSession,
+            Table::Ecdsa,
+            Table::StateChain,
+            Table::Transfer,
+            Table::TransferBatch,
+            Table::Root,
+            Table::BackupTxs,
+            Table::Smt,
+            Table::Lockbox,
+            Table::SignedSighash,
+            Table::ApiKey,
+            Table::DeletionReceipt,
+            Table::Punishment,
+            Table::DepositHandoff,
+            Table::FeeAcknowledgement,
+            Table::TransferReceipt,
+            Table::StateChainArchive,
+            Table::Sale,
+            Table::TransferReject,
+            Table::SwapFee,
+            Table::RootLeaf,
+            Table::Watchtower,
+            Table::FeeLedger,
+            Table::ChainSegment,
+            Table::Inheritance,
+            Table::SecondFactor,
+            Table::Vault,
+            Table::SwapReport,
+            Table::SpendIncident,
+            Table::BackupTxHistory,
+        ]
+    }
+
     pub fn to_string(&self) -> String {
         match self {
-            Table::BackupTxs => format!(
+            Table::BackupTxs | Table::Watchtower => format!(
                 "{:?}.{:?}",
                 Schema::Watcher.to_string().to_lowercase(),
                 self
             ),
+            Table::SchemaVersion => format!(
+                "{:?}.schema_version",
+                Schema::StateChainEntity.to_string().to_lowercase()
+            ),
             _ => format!(
                 "{:?}.{:?}",
                 Schema::StateChainEntity.to_string().to_lowercase(),
@@ -108,6 +174,9 @@ pub enum Column {
     WithdrawScSig,
     MasterPublic,
     Challenge,
+    SessionState,
+    StateUpdated,
+    P2c,
 
     // StateChain,
     // Id,
@@ -118,11 +187,17 @@ pub enum Column {
     TransferFinalizeData,
     TransferReady,
     SharedPublic,
+    BackupAddr,
     Confirmed,
+    Updated,
+    UpdatedRoot,
+    // Row version of StateChain, Transfer and TransferBatch
+    Version,
 
     // BackupTxs
     //Id,
     // TxBackup,
+    TxCpfp,
 
     // Transfer
     // Id,
@@ -134,6 +209,7 @@ pub enum Column {
     // TransferBatch
     // Id,
     StartTime,
+    StartHeight,
     StateChains,
     PunishedStateChains,
     Finalized,
@@ -150,6 +226,10 @@ pub enum Column {
     EphEcKeyPair,
     EphKeyGenFirstMsg,
     POS,
+    KeyEpoch,
+    ProofVerified,
+    ShareEpoch,
+    RefreshX1,
 
     // Root
     // Id,
@@ -160,6 +240,19 @@ pub enum Column {
     Key,
     // Value
     Lockbox,
+
+    // SignedSighash
+    // Id,
+    UserId,
+    OutPoint,
+    // SigHash,
+    // LockTime,
+    Signed,
+    // Finalized,
+
+    // SpendIncident
+    // Id,
+    ResponseTxId,
 }
 
 
@@ -204,6 +297,7 @@ impl PGDatabase {
     }
 
     pub fn database_w(&self) -> Result<DatabaseW> {
+        self.faults.db_write()?;
         match &self.pool {
             Some(p) => match p.get() {
                 Ok(c) => Ok(DatabaseW(c)),
@@ -219,185 +313,106 @@ impl PGDatabase {
         }
     }
 
-    /// Build DB tables and Schemas
-    pub fn make_tables(&self) -> Result<()> {
-        // Create Schemas if they do not already exist
-        let _ = self.database_w()?.execute(
-            &format!(
-                "
-            CREATE SCHEMA IF NOT EXISTS statechainentity;
-            "
-            ),
-            &[],
-        )?;
-        let _ = self.database_w()?.execute(
-            &format!(
-                "
-            CREATE SCHEMA IF NOT EXISTS watcher;
-            "
-            ),
-            &[],
-        )?;
-
-        // Create tables if they do not already exist
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                id uuid NOT NULL,
-                statechainid uuid,
-                authentication varchar,
-                s2 varchar,
-                s1pubkey varchar,
-                sighash varchar,
-                withdrawscsig varchar,
-                txwithdraw varchar,
-                proofkey varchar,
-                txbackup varchar,
-                masterpublic varchar,
-                sharedpublic varchar,
-                challenge varchar,
-                PRIMARY KEY (id)
-            );",
-                Table::UserSession.to_string(),
-            ),
-            &[],
-        )?;
-
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                id uuid NOT NULL,
-                lockbox int8,
-                PRIMARY KEY (id)
-            );",
-                Table::Lockbox.to_string(),
-            ),
-            &[],
-        )?;
-
-
-
-
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                id uuid NOT NULL,
-                keygenfirstmsg varchar,
-                commwitness varchar,
-                eckeypair varchar,
-                party2public varchar,
-                paillierkeypair varchar,
-                party1private varchar,
-                party1masterkey varchar,
-                pos varchar,
-                epheckeypair varchar,
-                ephkeygenfirstmsg varchar,
-                complete bool NOT NULL DEFAULT false,
-                PRIMARY KEY (id)
-            );",
-                Table::Ecdsa.to_string(),
-            ),
-            &[],
-        )?;
-
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                id uuid NOT NULL,
-                chain varchar,
-                amount int8,
-                ownerid uuid,
-                lockeduntil timestamp,
-                transferfinalizedata varchar,
-                transferready bool,
-                sharedpublic varchar,
-                confirmed bool NOT NULL DEFAULT false,
-                PRIMARY KEY (id)
-            );",
-                Table::StateChain.to_string(),
-            ),
-            &[],
-        )?;
-
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                id uuid NOT NULL,
-                statechainsig varchar,
-                x1 varchar,
-                transfermsg varchar,
-                proofkey varchar,
-                batchid uuid,
-                PRIMARY KEY (id)
-            );",
-                Table::Transfer.to_string(),
-            ),
-            &[],
-        )?;
-
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                id uuid NOT NULL,
-                starttime timestamp,
-                statechains varchar,
-                punishedstatechains varchar,
-                finalized bool,
-                PRIMARY KEY (id)
-            );",
-                Table::TransferBatch.to_string(),
-            ),
-            &[],
-        )?;
+    /// Pooled connection to the read replica for the info routes. Falls back to the primary if
+    /// no replica is configured or the replica is unavailable.
+    pub fn database_replica(&self) -> Result<DatabaseR> {
+        if let Some(p) = &self.pool_replica {
+            match p.get() {
+                Ok(c) => return Ok(DatabaseR(c)),
+                Err(e) => warn!(
+                    "Failed to get pooled connection for replica read, using primary: {}",
+                    e
+                ),
+            }
+        }
+        self.database_r()
+    }
 
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                id BIGSERIAL,
-                value varchar,
-                commitmentinfo varchar,
-                PRIMARY KEY (id)
-            );",
-                Table::Root.to_string(),
-            ),
-            &[],
-        )?;
+    fn get_root_from(&self, dbr: &DatabaseR, id: i64) -> Result<Option<Root>> {
+        if id == 0 {
+            return Ok(None);
+        }
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::Root.to_string(),
+        ))?;
+        let rows = statement.query(&[&id])?;
+        if rows.is_empty() {
+            return Err(SEError::DBError(NoDataForID, format!("Root id: {}", id)));
+        };
+        let row = rows.get(0);
 
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                id uuid NOT NULL,
-                txbackup varchar,
-                locktime int8,
-                PRIMARY KEY (id)
-            );",
-                Table::BackupTxs.to_string(),
-            ),
-            &[],
+        let id = match self.get_item_from_row::<i64>(&row, 0, &id.to_string(), Column::Id) {
+            Ok(v) => v,
+            Err(_) => {
+                // No root in table yet. Return None
+                return Ok(None);
+            }
+        };
+        let root = Root::from(
+            Some(id),
+            Self::deser(self.get_item_from_row::<String>(
+                &row,
+                1,
+                &id.to_string(),
+                Column::Value,
+            )?)?,
+            &Self::deser::<Option<CommitmentInfo>>(self.get_item_from_row::<String>(
+                &row,
+                2,
+                &id.to_string(),
+                Column::CommitmentInfo,
+            )?)?,
         )?;
+        Ok(Some(root))
+    }
 
-        self.database_w()?.execute(
-            &format!(
-                "
-            CREATE TABLE IF NOT EXISTS {} (
-                key varchar,
-                value varchar,
-                PRIMARY KEY (key)
-            );",
-                Table::Smt.to_string(),
-            ),
-            &[],
-        )?;
+    /// Statechain info from the statechain table, or from the archive if the statechain has
+    /// been archived
+    fn get_statechain_info_from(&self, dbr: &DatabaseR, statechain_id: &Uuid) -> Result<StateChainInfo> {
+        let statement = dbr.prepare(&format!(
+            "SELECT sc.amount, sc.chain, sc.confirmed, b.txbackup FROM {} sc
+            LEFT JOIN {} b ON b.id = sc.id WHERE sc.id = $1",
+            Table::StateChain.to_string(),
+            Table::BackupTxs.to_string(),
+        ))?;
+        let mut rows = statement.query(&[statechain_id])?;
+        let mut archived = false;
+        if rows.is_empty() {
+            let statement = dbr.prepare(&format!(
+                "SELECT amount, chain, confirmed, txbackup FROM {} WHERE id = $1",
+                Table::StateChainArchive.to_string(),
+            ))?;
+            rows = statement.query(&[statechain_id])?;
+            archived = true;
+        }
+        if rows.is_empty() {
+            return Err(SEError::DBError(NoDataForID, statechain_id.to_string()));
+        };
+        let row = rows.get(0);
+        let id = statechain_id.to_string();
+        let amount = self.get_item_from_row::<i64>(&row, 0, &id, Column::Amount)?;
+        let chain: StateChain = Self::deser::<StateChainUnchecked>(
+            self.get_item_from_row::<String>(&row, 1, &id, Column::Chain)?,
+        )?
+        .try_into()?;
+        let confirmed = self.get_item_from_row::<bool>(&row, 2, &id, Column::Confirmed)?;
+        let tx_backup = match self.get_item_from_row::<Option<String>>(&row, 3, &id, Column::TxBackup)? {
+            Some(tx_backup_str) => Some(Self::deser::<Transaction>(tx_backup_str)?),
+            None => None,
+        };
+        Ok(StateChainInfo {
+            chain,
+            amount,
+            tx_backup,
+            confirmed,
+            archived,
+        })
+    }
 
-        Ok(())
+    /// Build DB tables and Schemas by applying any pending schema migrations
+    pub fn make_tables(&self) -> Result<()> {
+        self.run_migrations()
     }
 
     #[allow(dead_code)]
@@ -435,20 +450,9 @@ impl PGDatabase {
 
     /// Drop all DB tables and schemas.
     fn truncate_tables(&self) -> Result<()> {
+        let tables: Vec<String> = Table::data_tables().iter().map(|t| t.to_string()).collect();
         self.database_w()?.execute(
-            &format!(
-                "
-            TRUNCATE {},{},{},{},{},{},{},{},{} RESTART IDENTITY;",
-                Table::UserSession.to_string(),
-                Table::Ecdsa.to_string(),
-                Table::StateChain.to_string(),
-                Table::Transfer.to_string(),
-                Table::TransferBatch.to_string(),
-                Table::Root.to_string(),
-                Table::BackupTxs.to_string(),
-                Table::Smt.to_string(),
-                Table::Lockbox.to_string(),
-            ),
+            &format!("TRUNCATE {} RESTART IDENTITY;", tables.join(",")),
             &[],
         )?;
         Ok(())
@@ -478,6 +482,89 @@ impl PGDatabase {
         }
     }
 
+    /// Serialize a sensitive value and encrypt it for a column of row id. Reverse of
+    /// deser_enc().
+    pub fn ser_enc<T>(&self, id: &Uuid, table: &Table, column: Column, data: T) -> Result<String>
+    where
+        T: serde::ser::Serialize,
+    {
+        self.cipher.encrypt(&column_aad(table, column, id), Self::ser(data)?)
+    }
+
+    /// Decrypt and deserialize the value of a sensitive column of row id. Values written
+    /// before encryption was enabled are deserialized as they are.
+    pub fn deser_enc<T>(&self, id: &Uuid, table: &Table, column: Column, data: String) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        Self::deser(self.cipher.decrypt(&column_aad(table, column, id), data)?)
+    }
+
+    /// Re-encrypt the values of the encrypted columns with the current master key. A value
+    /// updated concurrently is left as written by the update. Returns the number of values
+    /// re-encrypted.
+    pub fn reencrypt_columns(&self) -> Result<u64> {
+        let mut count = 0;
+        for (table, column) in ENCRYPTED_COLUMNS.iter() {
+            let dbr = self.database_r()?;
+            let statement = dbr.prepare(&format!(
+                "SELECT id, {} FROM {} WHERE {} IS NOT NULL",
+                column.to_string(),
+                table.to_string(),
+                column.to_string(),
+            ))?;
+            let rows = statement.query(&[])?;
+            let dbw = self.database_w()?;
+            let statement = dbw.prepare(&format!(
+                "UPDATE {} SET {} = $1 WHERE id = $2 AND {} = $3",
+                table.to_string(),
+                column.to_string(),
+                column.to_string(),
+            ))?;
+            for row in &rows {
+                let id: Uuid = row.get(0);
+                let data: String = row.get(1);
+                if let Some(reencrypted) = self
+                    .cipher
+                    .reencrypt(&column_aad(table, *column, &id), data.clone())?
+                {
+                    count += statement.execute(&[&reencrypted, &id, &data])?;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Build a WatchtowerRecord from a Watchtower table row
+    fn watchtower_record_from_row(row: &Row) -> WatchtowerRecord {
+        let version: i64 = row.get("version");
+        let broadcast_height: i64 = row.get("broadcastheight");
+        WatchtowerRecord {
+            id: row.get("id"),
+            auth_key: row.get("authkey"),
+            version: version as u64,
+            broadcast_height: broadcast_height as u32,
+            backup_tx: row.get("txbackup"),
+            revoked: row.get("revoked"),
+        }
+    }
+
+    /// Build a SighashRecord from a SignedSighash table row
+    fn sighash_record_from_row(row: &Row) -> Result<SighashRecord> {
+        let outpoint_str: String = row.get("outpoint");
+        let outpoint = OutPoint::from_str(&outpoint_str)
+            .map_err(|e| SEError::Generic(format!("Failed to parse outpoint: {}", e)))?;
+        let locktime: i64 = row.get("locktime");
+        Ok(SighashRecord {
+            user_id: row.get("userid"),
+            outpoint,
+            sig_hash: Self::deser(row.get("sighash"))?,
+            locktime: locktime as u32,
+            signed: row.get("signed"),
+            finalized: row.get("finalized"),
+        })
+    }
+
     /// Create new item in table
     pub fn insert(&self, id: &Uuid, table: Table) -> Result<u64> {
         let dbw = self.database_w()?;
@@ -542,6 +629,61 @@ impl PGDatabase {
         Ok(())
     }
 
+    /// Get the row version of an item in a versioned table
+    pub fn get_version(&self, id: &Uuid, table: Table) -> Result<i64> {
+        self.get_1::<i64>(*id, table, vec![Column::Version])
+    }
+
+    /// Update items in a versioned table for some ID if the row version is unchanged since it was
+    /// read, and increment the version. Returns a retryable error if the row has been updated
+    /// by another request.
+    pub fn update_versioned<'a>(
+        &self,
+        id: &Uuid,
+        table: Table,
+        column: Vec<Column>,
+        data: Vec<&'a dyn ToSql>,
+        version: i64,
+    ) -> Result<()> {
+        let num_items = column.len();
+        let table_str = table.to_string();
+        let mut set_str = self.update_columns_str(column);
+        if num_items > 0 {
+            set_str.push_str(",");
+        }
+        set_str.push_str("version=version+1");
+
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "UPDATE {} SET {} WHERE id = ${} AND version = ${}",
+            table_str,
+            set_str,
+            num_items + 1,
+            num_items + 2
+        ))?;
+
+        let mut owned_data = data.clone();
+        owned_data.push(id);
+        owned_data.push(&version);
+
+        if statement.execute(&owned_data)? == 0 {
+            // Distinguish a missing row from a concurrent update
+            self.get_version(id, table)?;
+            warn!("DB: Conflicting update of {} id: {}", table_str, id);
+            return Err(SEError::TryAgain(format!(
+                "conflicting update of {} id: {}",
+                table_str, id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Time and current root id recorded in a StateChain row when it is modified
+    fn statechain_updated(&self) -> Result<(NaiveDateTime, i64)> {
+        Ok((get_time_now(), self.root_get_current_id()?))
+    }
+
     /// Get items from table for some ID with PostgreSql data types (String, int, Uuid, bool, Uuid, chrono::NaiveDateTime).
     /// Err if ID not found. Return None if data item empty.
     fn get<T, U, V, W>(
@@ -689,24 +831,30 @@ impl Database for PGDatabase {
     fn from_pool(pool: r2d2::Pool<PostgresConnectionManager>) -> Self {
         Self {
             pool: Some(pool),
+            pool_replica: None,
             smt: PGDatabaseSmt {
                 table_name: Table::Smt.to_string(),
                 cache: MemCache::new(),
                 batch_on: false,
                 batch: HashMap::new(),
             },
+            faults: FaultInjector::default(),
+            cipher: ColumnCipher::default(),
         }
     }
 
     fn get_new() -> Self {
         Self {
             pool: None,
+            pool_replica: None,
             smt: PGDatabaseSmt {
                 table_name: Table::Smt.to_string(),
                 cache: MemCache::new(),
                 batch_on: false,
                 batch: HashMap::new(),
             },
+            faults: FaultInjector::default(),
+            cipher: ColumnCipher::default(),
         }
     }
 
@@ -718,7 +866,25 @@ impl Database for PGDatabase {
             config.storage.db_pass_w.clone(),
             config.storage.db_database_w.clone(),
         );
-        self.set_connection(&rocket_url)
+        self.set_connection(&rocket_url)?;
+        self.faults = FaultInjector::new(&config.chaos);
+        self.cipher = ColumnCipher::new(&config.db_encryption)?;
+
+        // Optional read replica for the info routes
+        if !config.storage.db_host_r.is_empty() {
+            let replica_url = get_postgres_url(
+                config.storage.db_host_r.clone(),
+                config.storage.db_port_r.clone(),
+                config.storage.db_user_r.clone(),
+                config.storage.db_pass_r.clone(),
+                config.storage.db_database_r.clone(),
+            );
+            match Self::get_postgres_connection_pool(&replica_url) {
+                Ok(p) => self.pool_replica = Some(p),
+                Err(e) => warn!("Read replica unavailable, using primary for info routes: {}", e),
+            }
+        }
+        Ok(())
     }
 
     fn set_connection(&mut self, url: &String) -> Result<()> {
@@ -792,6 +958,10 @@ impl Database for PGDatabase {
         )
     }
 
+    fn get_withdraw_sc_sig(&self, user_id: Uuid) -> Result<StateChainSig> {
+        Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::WithdrawScSig])?)
+    }
+
     fn update_s1_pubkey(&self, user_id: &Uuid, pubkey: &GE) -> Result<()> {
         self.update(
             user_id,
@@ -841,7 +1011,10 @@ impl Database for PGDatabase {
             user_id,
             Table::UserSession,
             vec![Column::SigHash, Column::TxWithdraw],
-            vec![&Self::ser(sig_hash)?, &Self::ser(tx)?],
+            vec![
+                &self.ser_enc(user_id, &Table::UserSession, Column::SigHash, sig_hash)?,
+                &Self::ser(tx)?,
+            ],
         )
     }
 
@@ -850,13 +1023,17 @@ impl Database for PGDatabase {
             user_id,
             Table::UserSession,
             vec![Column::SigHash],
-            vec![&Self::ser(sig_hash)?],
+            vec![&self.ser_enc(user_id, &Table::UserSession, Column::SigHash, sig_hash)?],
         )
     }
 
     fn get_sighash(&self, user_id: Uuid) -> Result<sha256d::Hash> {
-        let sig_hash: sha256d::Hash =
-            Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::SigHash])?)?;
+        let sig_hash: sha256d::Hash = self.deser_enc(
+            &user_id,
+            &Table::UserSession,
+            Column::SigHash,
+            self.get_1(user_id, Table::UserSession, vec![Column::SigHash])?,
+        )?;
         Ok(sig_hash)
     }
 
@@ -875,11 +1052,22 @@ impl Database for PGDatabase {
 
     fn update_backup_tx(&self,statechain_id: &Uuid, tx: Transaction) -> Result<()> {
         let locktime = tx.lock_time;
+        // Any CPFP child of the previous backup tx is no longer valid
+        let no_cpfp: Option<String> = None;
         self.update(
             statechain_id,
             Table::BackupTxs,
-            vec![Column::TxBackup,Column::LockTime],
-            vec![&Self::ser(tx)?,&(locktime as i64)],
+            vec![Column::TxBackup,Column::LockTime,Column::TxCpfp],
+            vec![&Self::ser(tx)?,&(locktime as i64),&no_cpfp],
+        )
+    }
+
+    fn update_backup_cpfp_tx(&self, statechain_id: &Uuid, tx: Transaction) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxCpfp],
+            vec![&Self::ser(tx)?],
         )
     }
 
@@ -986,7 +1174,11 @@ impl Database for PGDatabase {
         for row in &rows {
             let tx_backup: Transaction = Self::deser(row.get("txbackup"))?;
             let id: Uuid = row.get("id");
-            let backup_obj = BackupTxID { tx: tx_backup, id };
+            let cpfp = match row.get::<_, Option<String>>("txcpfp") {
+                Some(tx_str) => Some(Self::deser(tx_str)?),
+                None => None,
+            };
+            let backup_obj = BackupTxID { tx: tx_backup, id, cpfp };
             txs.push(backup_obj);
         }
         Ok(txs)
@@ -999,43 +1191,18 @@ impl Database for PGDatabase {
 
     /// Get root with given ID
     fn get_root(&self, id: i64) -> Result<Option<Root>> {
-        if id == 0 {
-            return Ok(None);
-        }
-        let dbr = self.database_r()?;
-        let statement = dbr.prepare(&format!(
-            "SELECT * FROM {} WHERE id = $1",
-            Table::Root.to_string(),
-        ))?;
-        let rows = statement.query(&[&id])?;
-        if rows.is_empty() {
-            return Err(SEError::DBError(NoDataForID, format!("Root id: {}", id)));
-        };
-        let row = rows.get(0);
+        self.get_root_from(&self.database_r()?, id)
+    }
 
-        let id = match self.get_item_from_row::<i64>(&row, 0, &id.to_string(), Column::Id) {
-            Ok(v) => v,
-            Err(_) => {
-                // No root in table yet. Return None
-                return Ok(None);
-            }
-        };
-        let root = Root::from(
-            Some(id),
-            Self::deser(self.get_item_from_row::<String>(
-                &row,
-                1,
-                &id.to_string(),
-                Column::Value,
-            )?)?,
-            &Self::deser::<Option<CommitmentInfo>>(self.get_item_from_row::<String>(
-                &row,
-                2,
-                &id.to_string(),
-                Column::CommitmentInfo,
-            )?)?,
-        )?;
-        Ok(Some(root))
+    fn get_root_info(&self, id: i64) -> Result<Option<Root>> {
+        if self.pool_replica.is_none() {
+            return self.get_root(id);
+        }
+        // The replica may not have the root yet
+        match self.get_root_from(&self.database_replica()?, id) {
+            Err(SEError::DBError(NoDataForID, _)) => self.get_root(id),
+            res => res,
+        }
     }
 
     /// Find the latest confirmed root
@@ -1074,11 +1241,12 @@ impl Database for PGDatabase {
     }
 
     fn set_confirmed(&self, statechain_id: &Uuid) -> Result<()> {
+        let (updated, updated_root) = self.statechain_updated()?;
         self.update(
             statechain_id,
             Table::StateChain,
-            vec![Column::Confirmed],
-            vec![&true],
+            vec![Column::Confirmed, Column::Updated, Column::UpdatedRoot],
+            vec![&true, &updated, &updated_root],
         )
     }    
 
@@ -1111,19 +1279,39 @@ impl Database for PGDatabase {
         })
     }
 
-    fn update_statechain_amount(
-        &self,
-        statechain_id: &Uuid,
-        state_chain: StateChain,
-        amount: u64,
-        coins_histo: Arc<Mutex<CoinValueInfo>>
-    ) -> Result<()> {
+    fn get_statechain_info(&self, statechain_id: Uuid) -> Result<StateChainInfo> {
+        if self.pool_replica.is_none() {
+            return self.get_statechain_info_from(&self.database_r()?, &statechain_id);
+        }
+        // The replica may not have the statechain yet
+        match self.get_statechain_info_from(&self.database_replica()?, &statechain_id) {
+            Err(SEError::DBError(NoDataForID, _)) => {
+                self.get_statechain_info_from(&self.database_r()?, &statechain_id)
+            }
+            res => res,
+        }
+    }
+
+    fn get_statechain_version(&self, statechain_id: &Uuid) -> Result<i64> {
+        self.get_version(statechain_id, Table::StateChain)
+    }
+
+    fn update_statechain_amount(
+        &self,
+        statechain_id: &Uuid,
+        state_chain: StateChain,
+        amount: u64,
+        coins_histo: Arc<Mutex<CoinValueInfo>>,
+        version: i64,
+    ) -> Result<()> {
         let prev_statechain_amount = &self.get_statechain_amount(*statechain_id)?.amount;
-        match self.update(
+        let (updated, updated_root) = self.statechain_updated()?;
+        match self.update_versioned(
             statechain_id,
             Table::StateChain,
-            vec![Column::Chain, Column::Amount],
-            vec![&Self::ser(state_chain)?, &(amount as i64)], // signals withdrawn funds
+            vec![Column::Chain, Column::Amount, Column::Updated, Column::UpdatedRoot],
+            vec![&Self::ser(state_chain)?, &(amount as i64), &updated, &updated_root], // signals withdrawn funds
+            version,
         )
         {
             Ok(_) => {
@@ -1147,6 +1335,7 @@ impl Database for PGDatabase {
         amount: &i64,
     ) -> Result<()> {
         self.insert(statechain_id, Table::StateChain)?;
+        let (updated, updated_root) = self.statechain_updated()?;
         self.update(
             statechain_id,
             Table::StateChain,
@@ -1155,12 +1344,16 @@ impl Database for PGDatabase {
                 Column::Amount,
                 Column::LockedUntil,
                 Column::OwnerId,
+                Column::Updated,
+                Column::UpdatedRoot,
             ],
             vec![
                 &Self::ser(state_chain.to_owned())?,
                 amount,
                 &get_time_now(),
                 &user_id.to_owned(),
+                &updated,
+                &updated_root,
             ],
         )?;
         Ok(())
@@ -1182,12 +1375,15 @@ impl Database for PGDatabase {
         statechain_id: &Uuid,
         state_chain: StateChain,
         new_user_id: &Uuid,
+        version: i64,
     ) -> Result<()> {
-        self.update(
+        let (updated, updated_root) = self.statechain_updated()?;
+        self.update_versioned(
             statechain_id,
             Table::StateChain,
-            vec![Column::Chain, Column::OwnerId],
-            vec![&Self::ser(state_chain)?, &new_user_id],
+            vec![Column::Chain, Column::OwnerId, Column::Updated, Column::UpdatedRoot],
+            vec![&Self::ser(state_chain)?, &new_user_id, &updated, &updated_root],
+            version,
         )
     }
 
@@ -1229,6 +1425,50 @@ impl Database for PGDatabase {
         Ok(proof_key)
     }
 
+    fn update_proof_key(&self, user_id: &Uuid, proof_key: &String) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::ProofKey],
+            vec![proof_key],
+        )
+    }
+
+    fn get_proof_key_holdings(&self, proof_key: &String) -> Result<(u64, u64)> {
+        let dbr = self.database_r()?;
+        // Withdrawn statechains have amount 0
+        let statement = dbr.prepare(&format!(
+            "SELECT COUNT(*), COALESCE(SUM(sc.amount), 0)::int8 FROM {} sc
+            JOIN {} us ON us.id = sc.ownerid
+            WHERE us.proofkey = $1 AND sc.amount > 0",
+            Table::StateChain.to_string(),
+            Table::UserSession.to_string(),
+        ))?;
+        let rows = statement.query(&[proof_key])?;
+        if rows.is_empty() {
+            return Ok((0, 0));
+        }
+        let row = rows.get(0);
+        let count: i64 = row.get(0);
+        let value: i64 = row.get(1);
+        Ok((count as u64, value as u64))
+    }
+
+    fn get_total_value(&self) -> Result<u64> {
+        let dbr = self.database_r()?;
+        // Withdrawn statechains have amount 0
+        let statement = dbr.prepare(&format!(
+            "SELECT COALESCE(SUM(amount), 0)::int8 FROM {} WHERE amount > 0",
+            Table::StateChain.to_string(),
+        ))?;
+        let rows = statement.query(&[])?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let value: i64 = rows.get(0).get(0);
+        Ok(value as u64)
+    }
+
     fn get_backup_transaction_and_proof_key(&self, user_id: Uuid) -> Result<(Transaction, String)> {
         let (tx_backup_str, proof_key) = self.get_2::<String, String>(
             user_id,
@@ -1253,6 +1493,7 @@ impl Database for PGDatabase {
     }
 
     fn get_transfer_batch_data(&self, batch_id: Uuid) -> Result<TransferBatchData> {
+        let version = self.get_transfer_batch_version(&batch_id)?;
         let (state_chains_str, start_time, finalized, punished_state_chains_str) = self
             .get_4::<String, NaiveDateTime, bool, String>(
             batch_id,
@@ -1271,6 +1512,7 @@ impl Database for PGDatabase {
             start_time,
             finalized,
             punished_state_chains,
+            version,
         })
     }
 
@@ -1308,7 +1550,7 @@ impl Database for PGDatabase {
                 vec![Column::StateChainSig, Column::X1, Column::BatchId],
                 vec![
                     &Self::ser(statechain_sig.to_owned())?,
-                    &Self::ser(x1.to_owned())?,
+                    &self.ser_enc(statechain_id, &Table::Transfer, Column::X1, x1)?,
                     &batch_id.unwrap().to_owned(),
                 ],
             )
@@ -1319,7 +1561,7 @@ impl Database for PGDatabase {
                 vec![Column::StateChainSig, Column::X1, Column::BatchId],
                 vec![
                     &Self::ser(statechain_sig.to_owned())?,
-                    &Self::ser(x1.to_owned())?,
+                    &self.ser_enc(statechain_id, &Table::Transfer, Column::X1, x1)?,
                     &None::<Uuid>
                 ],
             )
@@ -1386,6 +1628,7 @@ impl Database for PGDatabase {
     }
 
     fn get_transfer_data(&self, statechain_id: Uuid) -> Result<TransferData> {
+        let version = self.get_version(&statechain_id, Table::Transfer)?;
         let (statechain_id, statechain_sig_str, x1_str, batch_id) = self.get_4::<Uuid, String, String, Option<Uuid>>(
             statechain_id,
             Table::Transfer,
@@ -1393,16 +1636,21 @@ impl Database for PGDatabase {
         )?;
 
         let statechain_sig: StateChainSig = Self::deser(statechain_sig_str)?;
-        let x1: FE = Self::deser(x1_str)?;
+        let x1: FE = self.deser_enc(&statechain_id, &Table::Transfer, Column::X1, x1_str)?;
 
         return Ok(TransferData {
             statechain_id,
             statechain_sig,
             x1,
             batch_id,
+            version,
         });
     }
 
+    fn claim_transfer(&self, statechain_id: &Uuid, version: i64) -> Result<()> {
+        self.update_versioned(statechain_id, Table::Transfer, vec![], vec![], version)
+    }
+
     fn remove_transfer_data(&self, statechain_id: &Uuid) -> Result<()> {
         self.remove(statechain_id, Table::Transfer)
     }
@@ -1412,6 +1660,70 @@ impl Database for PGDatabase {
             .is_ok()
     }
 
+    fn get_session_state(&self, user_id: &Uuid) -> Result<Option<SessionState>> {
+        match self.get_1::<Option<String>>(*user_id, Table::UserSession, vec![Column::SessionState])? {
+            Some(state) => Ok(Some(Self::deser(state)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update_session_state(&self, user_id: &Uuid, state: &SessionState) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::SessionState, Column::StateUpdated],
+            vec![&Self::ser(state)?, &get_time_now()],
+        )
+    }
+
+    fn get_user_sessions(
+        &self,
+        proof_keys: &Vec<String>,
+        state: Option<SessionState>,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<UserSessionRecord>, i64)> {
+        let dbr = self.database_r()?;
+        let state = match state {
+            Some(s) => Some(Self::ser(s)?),
+            None => None,
+        };
+        // A NULL state parameter matches sessions in any state
+        let filter = "proofkey = ANY($1) AND ($2::varchar IS NULL OR sessionstate = $2)";
+
+        let statement = dbr.prepare(&format!(
+            "SELECT COUNT(*) FROM {} WHERE {}",
+            Table::UserSession.to_string(),
+            filter,
+        ))?;
+        let rows = statement.query(&[proof_keys, &state])?;
+        let total: i64 = match rows.iter().next() {
+            Some(row) => row.get(0),
+            None => 0,
+        };
+
+        let statement = dbr.prepare(&format!(
+            "SELECT id, proofkey, statechainid, sessionstate FROM {} WHERE {} \
+                ORDER BY proofkey, id OFFSET $3 LIMIT $4",
+            Table::UserSession.to_string(),
+            filter,
+        ))?;
+        let mut sessions = vec![];
+        for row in &statement.query(&[proof_keys, &state, &offset, &limit])? {
+            let session_state: Option<String> = row.get("sessionstate");
+            sessions.push(UserSessionRecord {
+                user_id: row.get("id"),
+                proof_key: row.get("proofkey"),
+                statechain_id: row.get("statechainid"),
+                state: match session_state {
+                    Some(s) => Some(Self::deser(s)?),
+                    None => None,
+                },
+            });
+        }
+        Ok((sessions, total))
+    }
+
     fn get_public_master(&self, user_id: Uuid) -> Result<Option<String>> {
         self.get_1::<Option<String>>(user_id, Table::UserSession, vec![Column::MasterPublic])
     }
@@ -1425,10 +1737,39 @@ impl Database for PGDatabase {
         )
     }
 
+    fn get_p2c(&self, user_id: Uuid) -> Result<Option<PayToContract>> {
+        match self.get_1::<Option<String>>(user_id, Table::UserSession, vec![Column::P2c])? {
+            Some(p2c) => Ok(Some(Self::deser(p2c)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update_p2c(&self, user_id: &Uuid, p2c: &PayToContract) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::P2c],
+            vec![&Self::ser(p2c)?],
+        )
+    }
+
     fn get_statecoin_pubkey(&self, statechain_id: Uuid) -> Result<Option<String>> {
         self.get_1::<Option<String>>(statechain_id, Table::StateChain, vec![Column::SharedPublic])
     }
 
+    fn get_backup_addr(&self, statechain_id: &Uuid) -> Result<Option<String>> {
+        self.get_1::<Option<String>>(*statechain_id, Table::StateChain, vec![Column::BackupAddr])
+    }
+
+    fn update_backup_addr(&self, statechain_id: &Uuid, addr: &String) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::BackupAddr],
+            vec![addr],
+        )
+    }
+
     fn get_shared_pubkey(&self, user_id: Uuid) -> Result<Option<String>> {
         self.get_1::<Option<String>>(user_id, Table::UserSession, vec![Column::SharedPublic])
     }
@@ -1452,7 +1793,13 @@ impl Database for PGDatabase {
     }
 
     fn get_ecdsa_master(&self, user_id: Uuid) -> Result<Option<String>> {
-        self.get_1::<Option<String>>(user_id, Table::Ecdsa, vec![Column::Party1MasterKey])
+        match self.get_1::<Option<String>>(user_id, Table::Ecdsa, vec![Column::Party1MasterKey])? {
+            Some(master_key) => Ok(Some(self.cipher.decrypt(
+                &column_aad(&Table::Ecdsa, Column::Party1MasterKey, &user_id),
+                master_key,
+            )?)),
+            None => Ok(None),
+        }
     }
 
     //kms::ecdsa::two_party::MasterKey1
@@ -1461,7 +1808,7 @@ impl Database for PGDatabase {
             user_id,
             Table::Ecdsa,
             vec![Column::Party1MasterKey],
-            vec![&Self::ser(master_key)?],
+            vec![&self.ser_enc(user_id, &Table::Ecdsa, Column::Party1MasterKey, master_key)?],
         )
     }
 
@@ -1479,9 +1826,20 @@ impl Database for PGDatabase {
             )?;
 
         let party2_public: GE = Self::deser(party2_public_str)?;
-        let paillier_key_pair: party_one::PaillierKeyPair = Self::deser(paillier_key_pair_str)?;
-        let party_one_private: party_one::Party1Private = Self::deser(party_one_private_str)?;
-        let comm_witness: party_one::CommWitness = Self::deser(comm_witness_str)?;
+        let paillier_key_pair: party_one::PaillierKeyPair = self.deser_enc(
+            &user_id,
+            &Table::Ecdsa,
+            Column::PaillierKeyPair,
+            paillier_key_pair_str,
+        )?;
+        let party_one_private: party_one::Party1Private = self.deser_enc(
+            &user_id,
+            &Table::Ecdsa,
+            Column::Party1Private,
+            party_one_private_str,
+        )?;
+        let comm_witness: party_one::CommWitness =
+            self.deser_enc(&user_id, &Table::Ecdsa, Column::CommWitness, comm_witness_str)?;
 
         Ok(ECDSAMasterKeyInput {
             party2_public,
@@ -1500,14 +1858,16 @@ impl Database for PGDatabase {
             Table::Ecdsa,
             vec![Column::CommWitness, Column::EcKeyPair],
         )?;
-        let comm_witness: party_one::CommWitness = Self::deser(comm_witness_str)?;
-        let ec_key_pair: party_one::EcKeyPair = Self::deser(ec_key_pair_str)?;
+        let comm_witness: party_one::CommWitness =
+            self.deser_enc(&user_id, &Table::Ecdsa, Column::CommWitness, comm_witness_str)?;
+        let ec_key_pair: party_one::EcKeyPair =
+            self.deser_enc(&user_id, &Table::Ecdsa, Column::EcKeyPair, ec_key_pair_str)?;
         Ok((comm_witness, ec_key_pair))
     }
 
     fn get_ecdsa_s2(&self, user_id: Uuid) -> Result<FE> {
         let s2_str = self.get_1(user_id, Table::UserSession, vec![Column::S2])?;
-        let s2: FE = Self::deser(s2_str)?;
+        let s2: FE = self.deser_enc(&user_id, &Table::UserSession, Column::S2, s2_str)?;
         Ok(s2)
     }
 
@@ -1530,8 +1890,8 @@ impl Database for PGDatabase {
             vec![
                 &Self::ser(HDPos { pos: 0u32 })?,
                 &Self::ser(key_gen_first_msg.to_owned())?,
-                &Self::ser(comm_witness)?,
-                &Self::ser(ec_key_pair)?,
+                &self.ser_enc(user_id, &Table::Ecdsa, Column::CommWitness, comm_witness)?,
+                &self.ser_enc(user_id, &Table::Ecdsa, Column::EcKeyPair, ec_key_pair)?,
             ],
         )?;
 
@@ -1581,19 +1941,124 @@ impl Database for PGDatabase {
             ],
             vec![
                 &Self::ser(party2_public)?,
-                &Self::ser(paillier_key_pair)?,
-                &Self::ser(party_one_private)?,
+                &self.ser_enc(user_id, &Table::Ecdsa, Column::PaillierKeyPair, paillier_key_pair)?,
+                &self.ser_enc(user_id, &Table::Ecdsa, Column::Party1Private, party_one_private)?,
             ],
         )?;
         Ok(())
     }
 
+    fn update_party_2_public(&self, user_id: &Uuid, party2_public: GE) -> Result<()> {
+        self.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::Party2Public],
+            vec![&Self::ser(party2_public)?],
+        )
+    }
+
     fn init_ecdsa(&self, user_id: &Uuid) -> Result<u64> {
         self.insert(user_id, Table::Ecdsa)
     }
 
+    fn reset_ecdsa(&self, user_id: &Uuid) -> Result<()> {
+        let share_epoch = self.get_ecdsa_share_epoch(*user_id)?;
+        self.remove(user_id, Table::Ecdsa)?;
+        self.insert(user_id, Table::Ecdsa)?;
+        self.update_ecdsa_share_epoch(user_id, share_epoch)
+    }
+
+    fn get_ecdsa_key_epoch(&self, user_id: Uuid) -> Result<u32> {
+        let epoch = self.get_1::<i64>(user_id, Table::Ecdsa, vec![Column::KeyEpoch])?;
+        Ok(epoch as u32)
+    }
+
+    fn update_ecdsa_key_epoch(&self, user_id: &Uuid, epoch: u32) -> Result<()> {
+        self.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::KeyEpoch],
+            vec![&(epoch as i64)],
+        )
+    }
+
+    fn get_ecdsa_share_epoch(&self, user_id: Uuid) -> Result<u32> {
+        let epoch = self.get_1::<i64>(user_id, Table::Ecdsa, vec![Column::ShareEpoch])?;
+        Ok(epoch as u32)
+    }
+
+    fn update_ecdsa_share_epoch(&self, user_id: &Uuid, epoch: u32) -> Result<()> {
+        self.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::ShareEpoch],
+            vec![&(epoch as i64)],
+        )
+    }
+
+    fn get_ecdsa_refresh_x1(&self, user_id: Uuid) -> Result<Option<FE>> {
+        match self.get_1::<Option<String>>(user_id, Table::Ecdsa, vec![Column::RefreshX1])? {
+            Some(x1_str) => Ok(Some(self.deser_enc(
+                &user_id,
+                &Table::Ecdsa,
+                Column::RefreshX1,
+                x1_str,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update_ecdsa_refresh_x1(&self, user_id: &Uuid, x1: &FE) -> Result<()> {
+        self.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::RefreshX1],
+            vec![&self.ser_enc(user_id, &Table::Ecdsa, Column::RefreshX1, x1)?],
+        )
+    }
+
+    fn update_ecdsa_proof_verified(&self, user_id: &Uuid, verified: bool) -> Result<()> {
+        self.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::ProofVerified],
+            vec![&verified],
+        )
+    }
+
+    /// Get IDs of completed keygen sessions without a successful proof verification record
+    fn get_unverified_keygen_sessions(&self) -> Result<Vec<Uuid>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT e.id FROM {} e JOIN {} u ON e.id = u.id WHERE u.masterpublic IS NOT NULL AND e.proofverified IS NOT TRUE",
+            Table::Ecdsa.to_string(),
+            Table::UserSession.to_string(),
+        ))?;
+        let rows = statement.query(&[])?;
+        let mut ids = vec![];
+        for row in &rows {
+            let id: Uuid = row.get("id");
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    fn update_ecdsa_s2(&self, user_id: &Uuid, s2: &FE) -> Result<()> {
+        self.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::S2],
+            vec![&self.ser_enc(user_id, &Table::UserSession, Column::S2, s2)?],
+        )
+    }
+
     fn get_ecdsa_party_1_private(&self, user_id: Uuid) -> Result<party_one::Party1Private> {
-        Self::deser(self.get_1(user_id, Table::Ecdsa, vec![Column::Party1Private])?)
+        self.deser_enc(
+            &user_id,
+            &Table::Ecdsa,
+            Column::Party1Private,
+            self.get_1(user_id, Table::Ecdsa, vec![Column::Party1Private])?,
+        )
     }
 
     fn get_ecdsa_keypair(&self, user_id: Uuid) -> Result<ECDSAKeypair> {
@@ -1603,7 +2068,12 @@ impl Database for PGDatabase {
             vec![Column::Party1Private, Column::Party2Public],
         )?;
 
-        let party_1_private: Party1Private = Self::deser(party_1_private_str)?;
+        let party_1_private: Party1Private = self.deser_enc(
+            &user_id,
+            &Table::Ecdsa,
+            Column::Party1Private,
+            party_1_private_str,
+        )?;
         let party_2_public: GE = Self::deser(party_2_public_str)?;
         Ok(ECDSAKeypair {
             party_1_private,
@@ -1611,12 +2081,18 @@ impl Database for PGDatabase {
         })
     }
 
-    fn update_punished(&self, batch_id: &Uuid, punished_state_chains: Vec<Uuid>) -> Result<()> {
-        self.update(
+    fn update_punished(
+        &self,
+        batch_id: &Uuid,
+        punished_state_chains: Vec<Uuid>,
+        version: i64,
+    ) -> Result<()> {
+        self.update_versioned(
             batch_id,
             Table::TransferBatch,
             vec![Column::PunishedStateChains],
             vec![&Self::ser(punished_state_chains)?],
+            version,
         )
     }
 
@@ -1628,6 +2104,24 @@ impl Database for PGDatabase {
         )
     }
 
+    fn update_transfer_batch_start_height(&self, batch_id: &Uuid, height: u64) -> Result<()> {
+        self.update(
+            batch_id,
+            Table::TransferBatch,
+            vec![Column::StartHeight],
+            vec![&(height as i64)],
+        )
+    }
+
+    fn get_transfer_batch_start_height(&self, batch_id: &Uuid) -> Result<Option<u64>> {
+        let height = self.get_1::<Option<i64>>(
+            batch_id.to_owned(),
+            Table::TransferBatch,
+            vec![Column::StartHeight],
+        )?;
+        Ok(height.map(|h| h as u64))
+    }
+
     fn get_batch_transfer_statechain_ids(&self, batch_id: &Uuid) -> Result<HashSet<Uuid>>{
         let statechain_ids = self.get_1(
             batch_id.to_owned(),
@@ -1673,12 +2167,19 @@ impl Database for PGDatabase {
         &self,
         statechain_id: &Uuid,
         finalized_data: &TransferFinalizeData,
+        version: i64,
     ) -> Result<()> {
-        self.update(
+        self.update_versioned(
             statechain_id,
             Table::StateChain,
             vec![Column::TransferFinalizeData],
-            vec![&Self::ser(finalized_data)?],
+            vec![&self.ser_enc(
+                statechain_id,
+                &Table::StateChain,
+                Column::TransferFinalizeData,
+                finalized_data,
+            )?],
+            version,
         )
     }
 
@@ -1688,15 +2189,25 @@ impl Database for PGDatabase {
     ) -> Result<TransferFinalizeData> {
         let tfd = self.get_1(statechain_id.to_owned(),
             Table::StateChain, vec![Column::TransferFinalizeData])?;
-        Self::deser(tfd)
+        self.deser_enc(statechain_id, &Table::StateChain, Column::TransferFinalizeData, tfd)
     }
 
-    fn update_transfer_batch_finalized(&self, batch_id: &Uuid, b_finalized: &bool) -> Result<()> {
-        self.update(
+    fn get_transfer_batch_version(&self, batch_id: &Uuid) -> Result<i64> {
+        self.get_version(batch_id, Table::TransferBatch)
+    }
+
+    fn update_transfer_batch_finalized(
+        &self,
+        batch_id: &Uuid,
+        b_finalized: &bool,
+        version: i64,
+    ) -> Result<()> {
+        self.update_versioned(
             batch_id,
             Table::TransferBatch,
             vec![Column::Finalized],
             vec![b_finalized],
+            version,
         )
     }
 
@@ -1760,8 +2271,8 @@ impl Database for PGDatabase {
         self.update(
             user_id,
             Table::UserSession,
-            vec![Column::Authentication, Column::ProofKey, Column::Challenge],
-            vec![&auth.clone(), &proof_key.to_owned(), &challenge.clone()],
+            vec![Column::Authentication, Column::ProofKey, Column::Challenge, Column::SessionState],
+            vec![&auth.clone(), &proof_key.to_owned(), &challenge.clone(), &Self::ser(SessionState::DepositInit)?],
         ).map_err(|e| { 
             guard.remove(user_id); 
             let _ = self.remove(user_id, Table::UserSession);
@@ -1799,13 +2310,15 @@ impl Database for PGDatabase {
                 Column::TxBackup,
                 Column::StateChainId,
                 Column::S2,
+                Column::SessionState,
             ],
             vec![
                 &String::from("auth"),
                 &finalized_data.statechain_sig.data.to_owned(),
                 &Self::ser(transaction_deserialise(&finalized_data.new_tx_backup_hex)?)?,
                 &statechain_id,
-                &Self::ser(finalized_data.s2)?,
+                &self.ser_enc(new_user_id, &Table::UserSession, Column::S2, finalized_data.s2)?,
+                &Self::ser(SessionState::TransferInit)?,
             ],
         ).map_err(|e| { 
             guard.remove(new_user_id); 
@@ -1828,7 +2341,7 @@ impl Database for PGDatabase {
             vec![Column::EphKeyGenFirstMsg, Column::EphEcKeyPair],
             vec![
                 &Self::ser(eph_key_gen_first_message_party_two)?,
-                &Self::ser(eph_ec_key_pair_party1)?,
+                &self.ser_enc(&user_id, &Table::Ecdsa, Column::EphEcKeyPair, eph_ec_key_pair_party1)?,
             ],
         )?;
         Ok(())
@@ -1846,9 +2359,14 @@ impl Database for PGDatabase {
                 ],
             )?;
 
-        let shared_key: MasterKey1 = Self::deser(shared_key_str)?;
-        let eph_ec_key_pair_party1: party_one::EphEcKeyPair =
-            Self::deser(eph_ec_key_pair_party1_str)?;
+        let shared_key: MasterKey1 =
+            self.deser_enc(&user_id, &Table::Ecdsa, Column::Party1MasterKey, shared_key_str)?;
+        let eph_ec_key_pair_party1: party_one::EphEcKeyPair = self.deser_enc(
+            &user_id,
+            &Table::Ecdsa,
+            Column::EphEcKeyPair,
+            eph_ec_key_pair_party1_str,
+        )?;
         let eph_key_gen_first_message_party_two: party_two::EphKeyGenFirstMsg =
             Self::deser(eph_key_gen_first_message_party_two_str)?;
 
@@ -1865,6 +2383,1107 @@ impl Database for PGDatabase {
         })
     }
 
+    fn remove_ecdsa(&self, user_id: &Uuid) -> Result<()> {
+        self.remove(user_id, Table::Ecdsa)
+    }
+
+    fn insert_deletion_receipt(&self, receipt: &SignedDeletionReceipt) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, statechainid, receipt, deletedat) VALUES ($1,$2,$3,$4)",
+            Table::DeletionReceipt.to_string()
+        ))?;
+        statement.execute(&[
+            &receipt.receipt.shared_key_id,
+            &receipt.receipt.statechain_id,
+            &Self::ser(receipt)?,
+            &receipt.receipt.deleted_at,
+        ])?;
+        Ok(())
+    }
+
+    fn get_deletion_receipts(&self, statechain_id: &Uuid) -> Result<Vec<SignedDeletionReceipt>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT receipt FROM {} WHERE statechainid = $1 ORDER BY deletedat",
+            Table::DeletionReceipt.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        let mut receipts = vec![];
+        for row in &rows {
+            let receipt: String = row.get("receipt");
+            receipts.push(Self::deser(receipt)?);
+        }
+        Ok(receipts)
+    }
+
+    fn insert_transfer_receipt(&self, user_id: &Uuid, receipt: &SignedTransferReceipt) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, statechainid, receipt, finalizedat) VALUES ($1,$2,$3,$4)",
+            Table::TransferReceipt.to_string()
+        ))?;
+        statement.execute(&[
+            user_id,
+            &receipt.receipt.statechain_id,
+            &Self::ser(receipt)?,
+            &receipt.receipt.finalized_at,
+        ])?;
+        Ok(())
+    }
+
+    fn get_transfer_receipts(&self, statechain_id: &Uuid) -> Result<Vec<SignedTransferReceipt>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT receipt FROM {} WHERE statechainid = $1 ORDER BY finalizedat",
+            Table::TransferReceipt.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        let mut receipts = vec![];
+        for row in &rows {
+            let receipt: String = row.get("receipt");
+            receipts.push(Self::deser(receipt)?);
+        }
+        Ok(receipts)
+    }
+
+    fn get_transfer_reject(&self, statechain_id: &Uuid) -> Result<Option<TransferRejectAPI>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::TransferReject.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        Ok(Some(TransferRejectAPI {
+            statechain_id: row.get("id"),
+            transfer_sig: Self::deser(row.get("transfersig"))?,
+            reject_sig: Self::deser(row.get("rejectsig"))?,
+            rejected_at: row.get("rejectedat"),
+        }))
+    }
+
+    fn update_transfer_reject(&self, reject: &TransferRejectAPI) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, transfersig, rejectsig, rejectedat)
+            VALUES ($1,$2,$3,$4)
+            ON CONFLICT (id) DO UPDATE
+            SET transfersig = EXCLUDED.transfersig, rejectsig = EXCLUDED.rejectsig,
+            rejectedat = EXCLUDED.rejectedat",
+            Table::TransferReject.to_string()
+        ))?;
+        statement.execute(&[
+            &reject.statechain_id,
+            &Self::ser(reject.transfer_sig.clone())?,
+            &Self::ser(reject.reject_sig.clone())?,
+            &reject.rejected_at,
+        ])?;
+        Ok(())
+    }
+
+    fn insert_swap_fee(
+        &self,
+        swap_id: &Uuid,
+        statechain_id: &Uuid,
+        fee: u64,
+        txid: &Option<String>,
+    ) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (swapid, statechainid, fee, txid, createdat)
+            VALUES ($1,$2,$3,$4,$5)
+            ON CONFLICT (swapid, statechainid) DO NOTHING",
+            Table::SwapFee.to_string()
+        ))?;
+        statement.execute(&[
+            swap_id,
+            statechain_id,
+            &(fee as i64),
+            txid,
+            &get_time_now(),
+        ])?;
+        Ok(())
+    }
+
+    fn get_swap_fee_by_txid(&self, txid: &String) -> Result<Option<(Uuid, Uuid)>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT swapid, statechainid FROM {} WHERE txid = $1",
+            Table::SwapFee.to_string(),
+        ))?;
+        let rows = statement.query(&[txid])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        Ok(Some((row.get(0), row.get(1))))
+    }
+
+    fn get_swap_fee_owed(&self, statechain_id: &Uuid) -> Result<u64> {
+        let dbr = self.database_r()?;
+        // Deductions are owed once the swap batch transfer is finalized
+        let statement = dbr.prepare(&format!(
+            "SELECT COALESCE(SUM(sf.fee), 0)::int8 FROM {} sf
+            JOIN {} tb ON tb.id = sf.swapid
+            WHERE sf.statechainid = $1 AND sf.txid IS NULL AND NOT sf.collected AND tb.finalized",
+            Table::SwapFee.to_string(),
+            Table::TransferBatch.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let owed: i64 = rows.get(0).get(0);
+        Ok(owed as u64)
+    }
+
+    fn update_swap_fee_collected(&self, statechain_id: &Uuid) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "UPDATE {} sf SET collected = true
+            FROM {} tb
+            WHERE tb.id = sf.swapid AND sf.statechainid = $1 AND sf.txid IS NULL
+            AND NOT sf.collected AND tb.finalized",
+            Table::SwapFee.to_string(),
+            Table::TransferBatch.to_string(),
+        ))?;
+        statement.execute(&[statechain_id])?;
+        Ok(())
+    }
+
+    fn insert_swap_report(&self, report: &SwapReport) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, participants, denomination, completedat)
+            VALUES ($1,$2,$3,$4)
+            ON CONFLICT (id) DO NOTHING",
+            Table::SwapReport.to_string()
+        ))?;
+        statement.execute(&[
+            &report.swap_id,
+            &(report.participants as i64),
+            &(report.denomination as i64),
+            &report.completed_at,
+        ])?;
+        Ok(())
+    }
+
+    fn get_swap_report(&self, swap_id: &Uuid) -> Result<Option<SwapReport>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::SwapReport.to_string(),
+        ))?;
+        let rows = statement.query(&[swap_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        let participants: i64 = row.get("participants");
+        let denomination: i64 = row.get("denomination");
+        Ok(Some(SwapReport {
+            swap_id: row.get("id"),
+            participants: participants as u64,
+            denomination: denomination as u64,
+            completed_at: row.get("completedat"),
+        }))
+    }
+
+    fn insert_spend_incident(&self, incident: &SpendIncident) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, funding, height, detectedat, responsetxid)
+            VALUES ($1,$2,$3,$4,$5)
+            ON CONFLICT (id) DO NOTHING",
+            Table::SpendIncident.to_string()
+        ))?;
+        let inserted = statement.execute(&[
+            &incident.statechain_id,
+            &incident.funding.to_string(),
+            &(incident.height as i64),
+            &incident.detected_at,
+            &incident.response_txid,
+        ])?;
+        if inserted == 0 {
+            return Ok(());
+        }
+        // Reported to clients with the statechain updates
+        let (updated, updated_root) = self.statechain_updated()?;
+        self.update(
+            &incident.statechain_id,
+            Table::StateChain,
+            vec![Column::Updated, Column::UpdatedRoot],
+            vec![&updated, &updated_root],
+        )
+    }
+
+    fn update_spend_incident_response(
+        &self,
+        statechain_id: &Uuid,
+        response_txid: &String,
+    ) -> Result<()> {
+        self.update(
+            statechain_id,
+            Table::SpendIncident,
+            vec![Column::ResponseTxId],
+            vec![response_txid],
+        )?;
+        // Reported to clients with the statechain updates
+        let (updated, updated_root) = self.statechain_updated()?;
+        self.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Updated, Column::UpdatedRoot],
+            vec![&updated, &updated_root],
+        )
+    }
+
+    fn get_spend_incident(&self, statechain_id: &Uuid) -> Result<Option<SpendIncident>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::SpendIncident.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        let funding: String = row.get("funding");
+        let height: i64 = row.get("height");
+        Ok(Some(SpendIncident {
+            statechain_id: row.get("id"),
+            funding: OutPoint::from_str(&funding)
+                .map_err(|e| SEError::Generic(format!("Invalid spend incident outpoint: {}", e)))?,
+            height: height as u64,
+            detected_at: row.get("detectedat"),
+            response_txid: row.get("responsetxid"),
+        }))
+    }
+
+    fn insert_backup_tx_history(&self, statechain_id: &Uuid, tx: &Transaction) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (statechainid, txid, txbackup, recorded) VALUES ($1,$2,$3,$4)
+            ON CONFLICT (statechainid, txid) DO NOTHING",
+            Table::BackupTxHistory.to_string()
+        ))?;
+        statement.execute(&[
+            statechain_id,
+            &tx.txid().to_string(),
+            &Self::ser(tx.clone())?,
+            &get_time_now(),
+        ])?;
+        Ok(())
+    }
+
+    fn get_backup_tx_history(&self, statechain_id: &Uuid) -> Result<Vec<Transaction>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT txbackup FROM {} WHERE statechainid = $1 ORDER BY id",
+            Table::BackupTxHistory.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        rows.iter().map(|row| Self::deser(row.get(0))).collect()
+    }
+
+    fn insert_root_leaves(&self, root_id: i64, leaves: &[(String, String)]) -> Result<()> {
+        let dbw = self.database_w()?;
+        // A leaf updated twice by a batch holds the last value
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (rootid, fundingtxid, value) VALUES ($1,$2,$3)
+            ON CONFLICT (rootid, fundingtxid) DO UPDATE SET value = EXCLUDED.value",
+            Table::RootLeaf.to_string()
+        ))?;
+        for (funding_txid, value) in leaves {
+            statement.execute(&[&root_id, funding_txid, value])?;
+        }
+        Ok(())
+    }
+
+    fn get_root_leaves(&self, funding_txid: &String) -> Result<Vec<(i64, String)>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT rootid, value FROM {} WHERE fundingtxid = $1 ORDER BY rootid",
+            Table::RootLeaf.to_string(),
+        ))?;
+        let rows = statement.query(&[funding_txid])?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn is_root_confirmed(&self, id: i64) -> Result<bool> {
+        let dbr = self.database_r()?;
+        // Attestation info is recorded as a new root with the same value
+        let statement = dbr.prepare(&format!(
+            "SELECT r2.commitmentinfo FROM {0} r1 JOIN {0} r2 ON r2.value = r1.value
+            WHERE r1.id = $1 AND r2.id >= r1.id",
+            Table::Root.to_string(),
+        ))?;
+        let rows = statement.query(&[&id])?;
+        for row in &rows {
+            let ci = Self::deser::<Option<CommitmentInfo>>(row.get(0))?;
+            if ci.map(|c| c.is_confirmed()).unwrap_or(false) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn get_watchtower_registration(&self, id: &Uuid) -> Result<Option<WatchtowerRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::Watchtower.to_string(),
+        ))?;
+        let rows = statement.query(&[id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        Ok(Some(Self::watchtower_record_from_row(&rows.get(0))))
+    }
+
+    fn update_watchtower_registration(&self, record: &WatchtowerRecord) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {0} (id, authkey, version, broadcastheight, txbackup, revoked, updatedat)
+            VALUES ($1,$2,$3,$4,$5,$6,$7)
+            ON CONFLICT (id) DO UPDATE
+            SET version = EXCLUDED.version, broadcastheight = EXCLUDED.broadcastheight,
+            txbackup = EXCLUDED.txbackup, revoked = EXCLUDED.revoked, updatedat = EXCLUDED.updatedat
+            WHERE {0}.version < EXCLUDED.version",
+            Table::Watchtower.to_string()
+        ))?;
+        let updated = statement.execute(&[
+            &record.id,
+            &record.auth_key,
+            &(record.version as i64),
+            &(record.broadcast_height as i64),
+            &record.backup_tx,
+            &record.revoked,
+            &get_time_now(),
+        ])?;
+        if updated == 0 {
+            return Err(SEError::DBError(UpdateFailed, record.id.to_string()));
+        }
+        Ok(())
+    }
+
+    fn count_watchtower_registrations(&self, auth_key: &String) -> Result<u64> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT COUNT(*) FROM {} WHERE authkey = $1 AND NOT revoked",
+            Table::Watchtower.to_string(),
+        ))?;
+        let rows = statement.query(&[auth_key])?;
+        let count: i64 = rows.get(0).get(0);
+        Ok(count as u64)
+    }
+
+    fn get_due_watchtower_txs(&self, height: i64) -> Result<Vec<WatchtowerRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE broadcastheight <= $1 AND NOT revoked",
+            Table::Watchtower.to_string(),
+        ))?;
+        let rows = statement.query(&[&height])?;
+        Ok(rows.iter().map(|row| Self::watchtower_record_from_row(&row)).collect())
+    }
+
+    fn remove_watchtower_tx(&self, id: &Uuid) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "UPDATE {} SET txbackup = NULL, revoked = true, updatedat = $2 WHERE id = $1",
+            Table::Watchtower.to_string(),
+        ))?;
+        statement.execute(&[id, &get_time_now()])?;
+        Ok(())
+    }
+
+    fn insert_fee(
+        &self,
+        txid: &String,
+        fee_type: &FeeType,
+        statechain_id: &Uuid,
+        amount: u64,
+    ) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (txid, feetype, statechainid, amount, collectedat)
+            VALUES ($1,$2,$3,$4,$5)
+            ON CONFLICT (txid, feetype, statechainid) DO NOTHING",
+            Table::FeeLedger.to_string()
+        ))?;
+        statement.execute(&[
+            txid,
+            &Self::ser(fee_type)?,
+            statechain_id,
+            &(amount as i64),
+            &get_time_now(),
+        ])?;
+        Ok(())
+    }
+
+    fn get_fees(&self, from: &NaiveDateTime, to: &NaiveDateTime) -> Result<Vec<FeeRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE collectedat >= $1 AND collectedat < $2 ORDER BY collectedat",
+            Table::FeeLedger.to_string(),
+        ))?;
+        let rows = statement.query(&[from, to])?;
+        let mut fees = vec![];
+        for row in rows.iter() {
+            let amount: i64 = row.get("amount");
+            fees.push(FeeRecord {
+                txid: row.get("txid"),
+                fee_type: Self::deser(row.get("feetype"))?,
+                statechain_id: row.get("statechainid"),
+                amount: amount as u64,
+                collected_at: row.get("collectedat"),
+            });
+        }
+        Ok(fees)
+    }
+
+    fn insert_chain_segment(&self, statechain_id: &Uuid, segment: &ArchivedSegment) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (statechainid, hash, prevhash, startposition, states)
+            VALUES ($1,$2,$3,$4,$5)
+            ON CONFLICT (statechainid, hash) DO NOTHING",
+            Table::ChainSegment.to_string()
+        ))?;
+        statement.execute(&[
+            statechain_id,
+            &segment.hash,
+            &segment.prev_hash,
+            &(segment.start_position as i64),
+            &Self::ser(&segment.states)?,
+        ])?;
+        Ok(())
+    }
+
+    fn get_chain_segments(&self, statechain_id: &Uuid) -> Result<Vec<ArchivedSegment>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE statechainid = $1 ORDER BY startposition",
+            Table::ChainSegment.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        let mut segments = vec![];
+        for row in rows.iter() {
+            let start_position: i64 = row.get("startposition");
+            segments.push(ArchivedSegment {
+                start_position: start_position as u64,
+                states: Self::deser(row.get("states"))?,
+                prev_hash: row.get("prevhash"),
+                hash: row.get("hash"),
+            });
+        }
+        Ok(segments)
+    }
+
+    fn get_sale(&self, statechain_id: &Uuid) -> Result<Option<SaleRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::Sale.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        let finalize_data: Option<String> = row.get("transferfinalizedata");
+        Ok(Some(SaleRecord {
+            statechain_id: row.get("id"),
+            payment_hash: row.get("paymenthash"),
+            expires: row.get("expires"),
+            finalize_data: match finalize_data {
+                Some(d) => Some(self.deser_enc(
+                statechain_id,
+                &Table::Sale,
+                Column::TransferFinalizeData,
+                d,
+            )?),
+                None => None,
+            },
+            preimage: row.get("preimage"),
+            finalized: row.get("finalized"),
+        }))
+    }
+
+    fn update_sale(&self, sale: &SaleRecord) -> Result<()> {
+        let finalize_data = match &sale.finalize_data {
+            Some(d) => Some(self.ser_enc(
+                &sale.statechain_id,
+                &Table::Sale,
+                Column::TransferFinalizeData,
+                d,
+            )?),
+            None => None,
+        };
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, paymenthash, expires, transferfinalizedata, preimage, finalized)
+            VALUES ($1,$2,$3,$4,$5,$6)
+            ON CONFLICT (id) DO UPDATE
+            SET paymenthash = EXCLUDED.paymenthash, expires = EXCLUDED.expires,
+            transferfinalizedata = EXCLUDED.transferfinalizedata,
+            preimage = EXCLUDED.preimage, finalized = EXCLUDED.finalized",
+            Table::Sale.to_string()
+        ))?;
+        statement.execute(&[
+            &sale.statechain_id,
+            &sale.payment_hash,
+            &sale.expires,
+            &finalize_data,
+            &sale.preimage,
+            &sale.finalized,
+        ])?;
+        Ok(())
+    }
+
+    fn get_inheritance(&self, statechain_id: &Uuid) -> Result<Option<InheritanceRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::Inheritance.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        let inactivity_period: i64 = row.get("inactivityperiod");
+        Ok(Some(InheritanceRecord {
+            statechain_id: row.get("id"),
+            transfer_sig: Self::deser(row.get("transfersig"))?,
+            designation_sig: Self::deser(row.get("designationsig"))?,
+            inactivity_period: inactivity_period as u64,
+            last_checkin: row.get("lastcheckin"),
+        }))
+    }
+
+    fn update_inheritance(&self, inheritance: &InheritanceRecord) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, transfersig, designationsig, inactivityperiod, lastcheckin)
+            VALUES ($1,$2,$3,$4,$5)
+            ON CONFLICT (id) DO UPDATE
+            SET transfersig = EXCLUDED.transfersig, designationsig = EXCLUDED.designationsig,
+            inactivityperiod = EXCLUDED.inactivityperiod, lastcheckin = EXCLUDED.lastcheckin",
+            Table::Inheritance.to_string()
+        ))?;
+        statement.execute(&[
+            &inheritance.statechain_id,
+            &Self::ser(inheritance.transfer_sig.clone())?,
+            &Self::ser(inheritance.designation_sig.clone())?,
+            &(inheritance.inactivity_period as i64),
+            &inheritance.last_checkin,
+        ])?;
+        Ok(())
+    }
+
+    fn remove_inheritance(&self, statechain_id: &Uuid) -> Result<()> {
+        let dbw = self.database_w()?;
+        dbw.execute(
+            &format!(
+                "DELETE FROM {} WHERE id = $1",
+                Table::Inheritance.to_string()
+            ),
+            &[statechain_id],
+        )?;
+        Ok(())
+    }
+
+    fn get_second_factor(&self, statechain_id: &Uuid) -> Result<Option<SecondFactorRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::SecondFactor.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        Ok(Some(SecondFactorRecord {
+            statechain_id: row.get("id"),
+            proof_key: row.get("proofkey"),
+            auth_key: row.get("authkey"),
+            pending_auth_key: row.get("pendingauthkey"),
+            pending_effective: row.get("pendingeffective"),
+        }))
+    }
+
+    fn update_second_factor(&self, second_factor: &SecondFactorRecord) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, proofkey, authkey, pendingauthkey, pendingeffective)
+            VALUES ($1,$2,$3,$4,$5)
+            ON CONFLICT (id) DO UPDATE
+            SET proofkey = EXCLUDED.proofkey, authkey = EXCLUDED.authkey,
+            pendingauthkey = EXCLUDED.pendingauthkey, pendingeffective = EXCLUDED.pendingeffective",
+            Table::SecondFactor.to_string()
+        ))?;
+        statement.execute(&[
+            &second_factor.statechain_id,
+            &second_factor.proof_key,
+            &second_factor.auth_key,
+            &second_factor.pending_auth_key,
+            &second_factor.pending_effective,
+        ])?;
+        Ok(())
+    }
+
+    fn get_vault(&self, statechain_id: &Uuid) -> Result<Option<VaultRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::Vault.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        let delay: i64 = row.get("delay");
+        let pending_delay: Option<i64> = row.get("pendingdelay");
+        Ok(Some(VaultRecord {
+            statechain_id: row.get("id"),
+            proof_key: row.get("proofkey"),
+            delay: delay as u64,
+            pending_delay: pending_delay.map(|d| d as u64),
+            pending_effective: row.get("pendingeffective"),
+            pending_sig: Self::deser(row.get("pendingsig"))?,
+            pending_since: row.get("pendingsince"),
+        }))
+    }
+
+    fn update_vault(&self, vault: &VaultRecord) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, proofkey, delay, pendingdelay, pendingeffective, pendingsig, pendingsince)
+            VALUES ($1,$2,$3,$4,$5,$6,$7)
+            ON CONFLICT (id) DO UPDATE
+            SET proofkey = EXCLUDED.proofkey, delay = EXCLUDED.delay,
+            pendingdelay = EXCLUDED.pendingdelay, pendingeffective = EXCLUDED.pendingeffective,
+            pendingsig = EXCLUDED.pendingsig, pendingsince = EXCLUDED.pendingsince",
+            Table::Vault.to_string()
+        ))?;
+        statement.execute(&[
+            &vault.statechain_id,
+            &vault.proof_key,
+            &(vault.delay as i64),
+            &vault.pending_delay.map(|d| d as i64),
+            &vault.pending_effective,
+            &Self::ser(vault.pending_sig.clone())?,
+            &vault.pending_since,
+        ])?;
+        Ok(())
+    }
+
+    fn get_punishment(&self, id: &String) -> Result<Option<PunishmentRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE id = $1",
+            Table::Punishment.to_string(),
+        ))?;
+        let rows = statement.query(&[id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let row = rows.get(0);
+        let offences: i64 = row.get("offences");
+        Ok(Some(PunishmentRecord {
+            id: row.get("id"),
+            offences: offences as u32,
+            last_offence: row.get("lastoffence"),
+            locked_until: row.get("lockeduntil"),
+        }))
+    }
+
+    fn update_punishment(&self, record: &PunishmentRecord) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, offences, lastoffence, lockeduntil) VALUES ($1,$2,$3,$4)
+            ON CONFLICT (id) DO UPDATE
+            SET offences = EXCLUDED.offences, lastoffence = EXCLUDED.lastoffence,
+            lockeduntil = EXCLUDED.lockeduntil",
+            Table::Punishment.to_string()
+        ))?;
+        statement.execute(&[
+            &record.id,
+            &(record.offences as i64),
+            &record.last_offence,
+            &record.locked_until,
+        ])?;
+        Ok(())
+    }
+
+    fn remove_expired_punishments(
+        &self,
+        locked_before: &NaiveDateTime,
+        offence_before: &NaiveDateTime,
+    ) -> Result<u64> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "DELETE FROM {} WHERE lockeduntil < $1 AND lastoffence < $2",
+            Table::Punishment.to_string(),
+        ))?;
+        Ok(statement.execute(&[locked_before, offence_before])?)
+    }
+
+    fn create_deposit_handoff(
+        &self,
+        user_id: &Uuid,
+        proof_key: &String,
+        payer_proof_key: &String,
+    ) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, proofkey, payerproofkey) VALUES ($1,$2,$3)",
+            Table::DepositHandoff.to_string()
+        ))?;
+        statement.execute(&[user_id, proof_key, payer_proof_key])?;
+        Ok(())
+    }
+
+    fn get_deposit_handoff_proof_key(&self, user_id: &Uuid) -> Result<Option<String>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT proofkey FROM {} WHERE id = $1",
+            Table::DepositHandoff.to_string(),
+        ))?;
+        let rows = statement.query(&[user_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        Ok(Some(rows.get(0).get("proofkey")))
+    }
+
+    fn get_deposit_payer_proof_key(&self, user_id: &Uuid) -> Result<Option<String>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT payerproofkey FROM {} WHERE id = $1",
+            Table::DepositHandoff.to_string(),
+        ))?;
+        let rows = statement.query(&[user_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        Ok(Some(rows.get(0).get("payerproofkey")))
+    }
+
+    fn update_deposit_handoff(&self, msg: &DepositHandoffMsg) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "UPDATE {} SET statechainid = $1, msg = $2 WHERE id = $3",
+            Table::DepositHandoff.to_string()
+        ))?;
+        if statement.execute(&[&msg.statechain_id, &Self::ser(msg)?, &msg.shared_key_id])? == 0 {
+            return Err(SEError::DBError(UpdateFailed, msg.shared_key_id.to_string()));
+        }
+        Ok(())
+    }
+
+    fn get_deposit_handoffs(&self, proof_key: &String) -> Result<Vec<DepositHandoffMsg>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT msg FROM {} WHERE proofkey = $1 AND msg IS NOT NULL",
+            Table::DepositHandoff.to_string(),
+        ))?;
+        let rows = statement.query(&[proof_key])?;
+        let mut msgs = vec![];
+        for row in &rows {
+            let msg: String = row.get("msg");
+            msgs.push(Self::deser(msg)?);
+        }
+        Ok(msgs)
+    }
+
+    fn insert_fee_acknowledgement(
+        &self,
+        user_id: &Uuid,
+        quote_hash: &String,
+        quote: &StateEntityFeeInfoAPI,
+    ) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, quotehash, quote) VALUES ($1,$2,$3)",
+            Table::FeeAcknowledgement.to_string()
+        ))?;
+        statement.execute(&[user_id, quote_hash, &Self::ser(quote)?])?;
+        Ok(())
+    }
+
+    fn get_fee_acknowledgement(&self, user_id: &Uuid) -> Result<Option<StateEntityFeeInfoAPI>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT quote FROM {} WHERE id = $1",
+            Table::FeeAcknowledgement.to_string(),
+        ))?;
+        let rows = statement.query(&[user_id])?;
+        if rows.is_empty() {
+            return Ok(None);
+        };
+        let quote: String = rows.get(0).get("quote");
+        Ok(Some(Self::deser(quote)?))
+    }
+
+    fn insert_sighash_record(&self, record: &SighashRecord) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (userid, outpoint, sighash, locktime, signed, finalized) VALUES ($1,$2,$3,$4,$5,$6)",
+            Table::SignedSighash.to_string()
+        ))?;
+        statement.execute(&[
+            &record.user_id,
+            &record.outpoint.to_string(),
+            &Self::ser(record.sig_hash)?,
+            &(record.locktime as i64),
+            &record.signed,
+            &record.finalized,
+        ])?;
+        Ok(())
+    }
+
+    fn get_sighash_record(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<SighashRecord> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE userid = $1 AND sighash = $2 ORDER BY id DESC LIMIT 1",
+            Table::SignedSighash.to_string(),
+        ))?;
+        let rows = statement.query(&[user_id, &Self::ser(sig_hash)?])?;
+        if rows.is_empty() {
+            return Err(SEError::DBError(NoDataForID, user_id.to_string()));
+        };
+        Self::sighash_record_from_row(&rows.get(0))
+    }
+
+    fn get_signed_sighashes(&self, outpoint: &OutPoint) -> Result<Vec<SighashRecord>> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE outpoint = $1 AND signed = true",
+            Table::SignedSighash.to_string(),
+        ))?;
+        let rows = statement.query(&[&outpoint.to_string()])?;
+        let mut records = Vec::new();
+        for row in &rows {
+            records.push(Self::sighash_record_from_row(&row)?);
+        }
+        Ok(records)
+    }
+
+    fn set_sighash_signed(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "UPDATE {} SET signed = true WHERE userid = $1 AND sighash = $2",
+            Table::SignedSighash.to_string(),
+        ))?;
+        if statement.execute(&[user_id, &Self::ser(sig_hash)?])? == 0 {
+            return Err(SEError::DBError(UpdateFailed, user_id.to_string()));
+        }
+        Ok(())
+    }
+
+    fn set_sighash_finalized(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "UPDATE {} SET finalized = true WHERE userid = $1 AND sighash = $2",
+            Table::SignedSighash.to_string(),
+        ))?;
+        // Sessions prepared before sighash records were kept have none to finalize
+        statement.execute(&[user_id, &Self::ser(sig_hash)?])?;
+        Ok(())
+    }
+
+    fn insert_api_key(&self, api_key: &ApiKey) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (keyhash, label, quota, revoked, created) VALUES ($1,$2,$3,$4,$5)",
+            Table::ApiKey.to_string()
+        ))?;
+        statement.execute(&[
+            &api_key.key_hash,
+            &api_key.label,
+            &(api_key.quota as i64),
+            &api_key.revoked,
+            &api_key.created,
+        ])?;
+        Ok(())
+    }
+
+    fn get_api_key(&self, key_hash: &String) -> Result<ApiKey> {
+        let dbr = self.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT * FROM {} WHERE keyhash = $1",
+            Table::ApiKey.to_string(),
+        ))?;
+        let rows = statement.query(&[key_hash])?;
+        if rows.is_empty() {
+            return Err(SEError::DBError(NoDataForID, String::from("API key")));
+        };
+        let row = rows.get(0);
+        let quota: i64 = row.get("quota");
+        Ok(ApiKey {
+            key_hash: row.get("keyhash"),
+            label: row.get("label"),
+            quota: quota as u32,
+            revoked: row.get("revoked"),
+            created: row.get("created"),
+        })
+    }
+
+    fn revoke_api_key(&self, key_hash: &String) -> Result<()> {
+        let dbw = self.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "UPDATE {} SET revoked = true WHERE keyhash = $1",
+            Table::ApiKey.to_string(),
+        ))?;
+        if statement.execute(&[key_hash])? == 0 {
+            return Err(SEError::DBError(NoDataForID, String::from("API key")));
+        }
+        Ok(())
+    }
+
+    fn get_statechains_updated_since(
+        &self,
+        since: &UpdatesSince,
+        limit: i64,
+    ) -> Result<Vec<(Uuid, NaiveDateTime)>> {
+        let dbr = self.database_r()?;
+        let (column, value): (&str, &dyn ToSql) = match since {
+            UpdatesSince::RootId(root_id) => ("updatedroot", root_id),
+            UpdatesSince::Time(time) => ("updated", time),
+        };
+        let statement = dbr.prepare(&format!(
+            "SELECT id, updated FROM {} WHERE {} >= $1 ORDER BY updated LIMIT $2",
+            Table::StateChain.to_string(),
+            column,
+        ))?;
+        let mut result = vec![];
+        for row in &statement.query(&[value, &limit])? {
+            result.push((row.get("id"), row.get("updated")));
+        }
+        Ok(result)
+    }
+
+    fn archive_statechains(
+        &self,
+        updated_before: &NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(Uuid, i64)>> {
+        let dbw = self.database_w()?;
+        let trans = dbw.transaction()?;
+        // Withdrawn statechains have amount 0. Confirmed statechains without a backup tx have
+        // had their backup tx confirmed and removed by the watcher.
+        let rows = trans.query(
+            &format!(
+                "SELECT sc.id, sc.amount FROM {} sc LEFT JOIN {} b ON b.id = sc.id
+                WHERE sc.updated < $1 AND (sc.amount = 0 OR (sc.confirmed AND b.id IS NULL))
+                ORDER BY sc.updated LIMIT $2 FOR UPDATE OF sc SKIP LOCKED",
+                Table::StateChain.to_string(),
+                Table::BackupTxs.to_string(),
+            ),
+            &[updated_before, &limit],
+        )?;
+        let mut archived = vec![];
+        for row in &rows {
+            let id: Uuid = row.get("id");
+            let amount: i64 = row.get("amount");
+            let reason = if amount == 0 { "withdrawn" } else { "expired" };
+            trans.execute(
+                &format!(
+                    "INSERT INTO {} (id, chain, amount, ownerid, sharedpublic, confirmed, txbackup, updated, reason)
+                    SELECT sc.id, sc.chain, sc.amount, sc.ownerid, sc.sharedpublic, sc.confirmed, b.txbackup, sc.updated, $2
+                    FROM {} sc LEFT JOIN {} b ON b.id = sc.id WHERE sc.id = $1",
+                    Table::StateChainArchive.to_string(),
+                    Table::StateChain.to_string(),
+                    Table::BackupTxs.to_string(),
+                ),
+                &[&id, &reason],
+            )?;
+            for table in &[Table::StateChain, Table::BackupTxs] {
+                trans.execute(
+                    &format!("DELETE FROM {} WHERE id = $1", table.to_string()),
+                    &[&id],
+                )?;
+            }
+            archived.push((id, amount));
+        }
+        trans.commit()?;
+        Ok(archived)
+    }
+
+    fn remove_orphaned_sessions(
+        &self,
+        state: &SessionState,
+        updated_before: &NaiveDateTime,
+        limit: i64,
+    ) -> Result<(Vec<Uuid>, u64)> {
+        let dbw = self.database_w()?;
+        let trans = dbw.transaction()?;
+        // Sessions with a statechain are never orphaned
+        let rows = trans.query(
+            &format!(
+                "SELECT id FROM {} WHERE sessionstate = $1 AND stateupdated < $2
+                AND statechainid IS NULL ORDER BY stateupdated LIMIT $3 FOR UPDATE SKIP LOCKED",
+                Table::UserSession.to_string(),
+            ),
+            &[&Self::ser(state)?, updated_before, &limit],
+        )?;
+        let user_ids: Vec<Uuid> = rows.iter().map(|row| row.get("id")).collect();
+        let mut related = 0;
+        for table in &[
+            Table::Ecdsa,
+            Table::Lockbox,
+            Table::DepositHandoff,
+            Table::FeeAcknowledgement,
+        ] {
+            related += trans.execute(
+                &format!("DELETE FROM {} WHERE id = ANY($1)", table.to_string()),
+                &[&user_ids],
+            )?;
+        }
+        trans.execute(
+            &format!(
+                "DELETE FROM {} WHERE id = ANY($1)",
+                Table::UserSession.to_string()
+            ),
+            &[&user_ids],
+        )?;
+        trans.commit()?;
+        Ok((user_ids, related))
+    }
+
+    fn purge_archived_statechains(&self, archived_before: &NaiveDateTime) -> Result<u64> {
+        let dbw = self.database_w()?;
+        let trans = dbw.transaction()?;
+        // Pruned segments of the purged statechains
+        trans.execute(
+            &format!(
+                "DELETE FROM {} WHERE statechainid IN (SELECT id FROM {} WHERE archived < $1)",
+                Table::ChainSegment.to_string(),
+                Table::StateChainArchive.to_string(),
+            ),
+            &[archived_before],
+        )?;
+        let purged = trans.execute(
+            &format!(
+                "DELETE FROM {} WHERE archived < $1",
+                Table::StateChainArchive.to_string(),
+            ),
+            &[archived_before],
+        )?;
+        trans.commit()?;
+        Ok(purged)
+    }
+
     fn get_tx_withdraw(&self, user_id: Uuid) -> Result<Transaction> {
         Self::deser(self.get_1(user_id, Table::UserSession, vec![Column::TxWithdraw])?)
     }
@@ -0,0 +1,3041 @@
+//! DynamoDB
+//!
+//! DynamoDB storage of the core tables: user sessions, statechains, ECDSA key shares, transfers,
+//! transfer batches and backup txs. The other tables, including the SMT and roots, remain in
+//! Postgres.
+//!
+//! Each table is a DynamoDB table keyed by id, with one attribute per Postgres column. NULL values
+//! are missing attributes. Inserts, updates and removals are conditional on the existence of the
+//! item, and updates of the versioned tables (StateChain, Transfer and TransferBatch) are
+//! conditional on the row version, so concurrent requests behave as they do with Postgres.
+//! Sensitive values are encrypted as they are in Postgres. The UserSession and Transfer tables
+//! have a global secondary index on the proof key.
+
+use super::super::Result;
+use bitcoin::Transaction;
+pub type Hash = bitcoin::hashes::sha256d::Hash;
+
+use super::db::{Column, HDPos, Table};
+use crate::config::{Config, DynamoDbConfig};
+use crate::server::UserIDs;
+use crate::{
+    error::{
+        DBErrorType::{NoDataForID, UpdateFailed},
+        SEError,
+    },
+    structs::*,
+    Database, PGDatabase,
+};
+use bitcoin::hashes::sha256d;
+use bitcoin::OutPoint;
+use chrono::NaiveDateTime;
+use curv::{FE, GE};
+use kms::ecdsa::two_party::*;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::party_one::Party1Private;
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
+use rocket_contrib::databases::r2d2;
+use rocket_contrib::databases::r2d2_postgres::PostgresConnectionManager;
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{
+    AttributeDefinition, AttributeValue, CreateTableError, CreateTableInput, DeleteItemError,
+    DeleteItemInput, DescribeTableInput, DynamoDb, DynamoDbClient, GetItemInput,
+    GlobalSecondaryIndex, KeySchemaElement, Projection, PutItemError, PutItemInput, QueryInput,
+    ScanInput, UpdateItemError, UpdateItemInput,
+};
+use shared_lib::state_chain::*;
+use shared_lib::structs::{
+    CoinValueInfo, DepositHandoffMsg, FeeType, PayToContract, SignedDeletionReceipt,
+    SignedTransferReceipt, SpendIncident, StateEntityFeeInfoAPI, TransferFinalizeData,
+    TransferMsg3, TransferRejectAPI,
+};
+use shared_lib::swap_data::SwapReport;
+use shared_lib::util::transaction_deserialise;
+use shared_lib::Root;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::{thread, time};
+use tokio::runtime::{Builder, Handle, Runtime};
+use uuid::Uuid;
+
+/// Tables stored in DynamoDB
+pub const DYNAMO_TABLES: [Table; 6] = [
+    Table::UserSession,
+    Table::Ecdsa,
+    Table::StateChain,
+    Table::Transfer,
+    Table::TransferBatch,
+    Table::BackupTxs,
+];
+
+/// Name of the proof key index of the UserSession and Transfer tables
+const PROOF_KEY_INDEX: &str = "proofkey-index";
+
+/// Timestamps are stored in a fixed width format so that they sort as strings
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f";
+
+/// Polls of a new table's status before table creation is abandoned
+const TABLE_ACTIVE_POLLS: u32 = 60;
+
+/// DynamoDB item: attribute values by attribute name
+pub type Item = HashMap<String, AttributeValue>;
+
+/// Column value that can be written to a DynamoDB attribute. None is written as a missing
+/// attribute.
+pub trait ToAttribute {
+    fn to_attribute(&self) -> Option<AttributeValue>;
+}
+
+/// Column value that can be read from a DynamoDB attribute. None if the attribute is missing or
+/// has the wrong type.
+pub trait FromAttribute: Sized {
+    fn from_attribute(attr: Option<&AttributeValue>) -> Option<Self>;
+}
+
+impl ToAttribute for String {
+    fn to_attribute(&self) -> Option<AttributeValue> {
+        Some(AttributeValue {
+            s: Some(self.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+impl ToAttribute for i64 {
+    fn to_attribute(&self) -> Option<AttributeValue> {
+        Some(AttributeValue {
+            n: Some(self.to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+impl ToAttribute for bool {
+    fn to_attribute(&self) -> Option<AttributeValue> {
+        Some(AttributeValue {
+            bool: Some(*self),
+            ..Default::default()
+        })
+    }
+}
+
+impl ToAttribute for Uuid {
+    fn to_attribute(&self) -> Option<AttributeValue> {
+        self.to_string().to_attribute()
+    }
+}
+
+impl ToAttribute for NaiveDateTime {
+    fn to_attribute(&self) -> Option<AttributeValue> {
+        self.format(TIME_FORMAT).to_string().to_attribute()
+    }
+}
+
+impl<T: ToAttribute> ToAttribute for Option<T> {
+    fn to_attribute(&self) -> Option<AttributeValue> {
+        self.as_ref().and_then(|v| v.to_attribute())
+    }
+}
+
+impl<T: ToAttribute + ?Sized> ToAttribute for &T {
+    fn to_attribute(&self) -> Option<AttributeValue> {
+        (**self).to_attribute()
+    }
+}
+
+impl FromAttribute for String {
+    fn from_attribute(attr: Option<&AttributeValue>) -> Option<Self> {
+        attr?.s.clone()
+    }
+}
+
+impl FromAttribute for i64 {
+    fn from_attribute(attr: Option<&AttributeValue>) -> Option<Self> {
+        attr?.n.as_ref()?.parse().ok()
+    }
+}
+
+impl FromAttribute for bool {
+    fn from_attribute(attr: Option<&AttributeValue>) -> Option<Self> {
+        attr?.bool
+    }
+}
+
+impl FromAttribute for Uuid {
+    fn from_attribute(attr: Option<&AttributeValue>) -> Option<Self> {
+        Uuid::parse_str(attr?.s.as_ref()?).ok()
+    }
+}
+
+impl FromAttribute for NaiveDateTime {
+    fn from_attribute(attr: Option<&AttributeValue>) -> Option<Self> {
+        NaiveDateTime::parse_from_str(attr?.s.as_ref()?, TIME_FORMAT).ok()
+    }
+}
+
+impl<T: FromAttribute> FromAttribute for Option<T> {
+    fn from_attribute(attr: Option<&AttributeValue>) -> Option<Self> {
+        match attr {
+            None => Some(None),
+            Some(a) => T::from_attribute(Some(a)).map(Some),
+        }
+    }
+}
+
+/// Attribute name of a column. Matches the Postgres column name.
+fn attribute_name(column: &Column) -> String {
+    column.to_string().to_lowercase()
+}
+
+/// Get a column value from an item. Err if the value is missing for a non-Option type.
+pub fn item_value<T: FromAttribute>(item: &Item, id: &Uuid, column: Column) -> Result<T> {
+    T::from_attribute(item.get(&attribute_name(&column)))
+        .ok_or(SEError::DBErrorWC(NoDataForID, id.to_string(), column))
+}
+
+/// Get the id of an item
+pub fn item_id(item: &Item) -> Result<Uuid> {
+    Uuid::from_attribute(item.get("id"))
+        .ok_or(SEError::DBError(NoDataForID, String::from("item without id")))
+}
+
+fn key(id: &Uuid) -> Item {
+    let mut key = HashMap::new();
+    key.insert(String::from("id"), id.to_attribute().unwrap());
+    key
+}
+
+/// Projection expression and attribute names of the id and some columns
+fn projection(column: &Vec<Column>) -> (String, HashMap<String, String>) {
+    let mut names = HashMap::new();
+    names.insert(String::from("#id"), String::from("id"));
+    let mut expr = vec![String::from("#id")];
+    // The id is always projected
+    for (i, col) in column.iter().enumerate().filter(|(_, c)| attribute_name(c) != "id") {
+        names.insert(format!("#c{}", i), attribute_name(col));
+        expr.push(format!("#c{}", i));
+    }
+    (expr.join(","), names)
+}
+
+/// Update expression setting or removing column values
+struct UpdateExpression {
+    set: Vec<String>,
+    remove: Vec<String>,
+    names: HashMap<String, String>,
+    values: Item,
+}
+
+impl UpdateExpression {
+    fn new<'a>(column: Vec<Column>, data: Vec<&'a dyn ToAttribute>) -> Self {
+        let mut expr = Self {
+            set: vec![],
+            remove: vec![],
+            names: HashMap::new(),
+            values: HashMap::new(),
+        };
+        expr.names.insert(String::from("#id"), String::from("id"));
+        for (i, (col, value)) in column.iter().zip(data.iter()).enumerate() {
+            let name = format!("#c{}", i);
+            expr.names.insert(name.clone(), attribute_name(col));
+            match value.to_attribute() {
+                Some(v) => {
+                    expr.values.insert(format!(":v{}", i), v);
+                    expr.set.push(format!("{} = :v{}", name, i));
+                }
+                None => expr.remove.push(name),
+            }
+        }
+        expr
+    }
+
+    fn input(self, table_name: String, id: &Uuid, condition: &str) -> UpdateItemInput {
+        let mut update = vec![];
+        if !self.set.is_empty() {
+            update.push(format!("SET {}", self.set.join(",")));
+        }
+        if !self.remove.is_empty() {
+            update.push(format!("REMOVE {}", self.remove.join(",")));
+        }
+        UpdateItemInput {
+            table_name,
+            key: key(id),
+            update_expression: Some(update.join(" ")),
+            condition_expression: Some(condition.to_string()),
+            expression_attribute_names: Some(self.names),
+            expression_attribute_values: match self.values.is_empty() {
+                true => None,
+                false => Some(self.values),
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// DynamoDB client and table names
+pub struct DynamoStore {
+    client: DynamoDbClient,
+    handle: Handle,
+    /// Runtime driving the client. Held in a Mutex so that the store is Sync.
+    _runtime: Mutex<Runtime>,
+    table_prefix: String,
+}
+
+impl DynamoStore {
+    pub fn new(config: &DynamoDbConfig) -> Result<Self> {
+        let region = match config.endpoint.as_ref().filter(|e| !e.is_empty()) {
+            Some(endpoint) => Region::Custom {
+                name: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => Region::from_str(&config.region).map_err(|e| {
+                SEError::Generic(format!("Invalid DynamoDB region {}: {}", config.region, e))
+            })?,
+        };
+        let runtime = Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .map_err(|e| SEError::Generic(format!("Failed to start DynamoDB runtime: {}", e)))?;
+        Ok(Self {
+            client: DynamoDbClient::new(region),
+            handle: runtime.handle().clone(),
+            _runtime: Mutex::new(runtime),
+            table_prefix: config.table_prefix.clone(),
+        })
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.handle.block_on(future)
+    }
+
+    pub fn table_name(&self, table: &Table) -> String {
+        format!("{}{}", self.table_prefix, format!("{:?}", table).to_lowercase())
+    }
+
+    /// Create any missing tables and wait until they are active
+    pub fn make_tables(&self) -> Result<()> {
+        for table in DYNAMO_TABLES.iter() {
+            let mut attribute_definitions = vec![AttributeDefinition {
+                attribute_name: String::from("id"),
+                attribute_type: String::from("S"),
+            }];
+            let mut global_secondary_indexes = None;
+            if let Table::UserSession | Table::Transfer = table {
+                attribute_definitions.push(AttributeDefinition {
+                    attribute_name: attribute_name(&Column::ProofKey),
+                    attribute_type: String::from("S"),
+                });
+                global_secondary_indexes = Some(vec![GlobalSecondaryIndex {
+                    index_name: String::from(PROOF_KEY_INDEX),
+                    key_schema: vec![KeySchemaElement {
+                        attribute_name: attribute_name(&Column::ProofKey),
+                        key_type: String::from("HASH"),
+                    }],
+                    projection: Projection {
+                        projection_type: Some(String::from("ALL")),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }]);
+            }
+            let input = CreateTableInput {
+                table_name: self.table_name(table),
+                attribute_definitions,
+                key_schema: vec![KeySchemaElement {
+                    attribute_name: String::from("id"),
+                    key_type: String::from("HASH"),
+                }],
+                billing_mode: Some(String::from("PAY_PER_REQUEST")),
+                global_secondary_indexes,
+                ..Default::default()
+            };
+            match self.block_on(self.client.create_table(input)) {
+                Ok(_) => info!("DynamoDB: Created table {}", self.table_name(table)),
+                Err(RusotoError::Service(CreateTableError::ResourceInUse(_))) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        for table in DYNAMO_TABLES.iter() {
+            self.wait_table_active(table)?;
+        }
+        Ok(())
+    }
+
+    fn wait_table_active(&self, table: &Table) -> Result<()> {
+        let table_name = self.table_name(table);
+        for _ in 0..TABLE_ACTIVE_POLLS {
+            let output = self.block_on(self.client.describe_table(DescribeTableInput {
+                table_name: table_name.clone(),
+            }))?;
+            let status = output.table.and_then(|t| t.table_status);
+            if status.as_deref() == Some("ACTIVE") {
+                return Ok(());
+            }
+            thread::sleep(time::Duration::from_secs(1));
+        }
+        Err(SEError::Generic(format!(
+            "DynamoDB table {} is not active",
+            table_name
+        )))
+    }
+
+    /// Remove all items from the tables
+    pub fn truncate_tables(&self) -> Result<()> {
+        for table in DYNAMO_TABLES.iter() {
+            for item in self.scan(table, vec![])? {
+                let id = item_id(&item)?;
+                self.block_on(self.client.delete_item(DeleteItemInput {
+                    table_name: self.table_name(table),
+                    key: key(&id),
+                    ..Default::default()
+                }))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create new item in table with the column defaults of the Postgres table
+    pub fn insert(&self, id: &Uuid, table: Table) -> Result<u64> {
+        let mut item = key(id);
+        let mut default = |column: Column, value: &dyn ToAttribute| {
+            if let Some(v) = value.to_attribute() {
+                item.insert(attribute_name(&column), v);
+            }
+        };
+        match table {
+            Table::UserSession => default(Column::StateUpdated, &get_time_now()),
+            Table::Ecdsa => {
+                default(Column::Complete, &false);
+                default(Column::KeyEpoch, &0i64);
+                default(Column::ShareEpoch, &0i64);
+            }
+            Table::StateChain => {
+                default(Column::Confirmed, &false);
+                default(Column::Version, &0i64);
+            }
+            Table::Transfer | Table::TransferBatch => default(Column::Version, &0i64),
+            _ => (),
+        }
+        let mut names = HashMap::new();
+        names.insert(String::from("#id"), String::from("id"));
+        let input = PutItemInput {
+            table_name: self.table_name(&table),
+            item,
+            condition_expression: Some(String::from("attribute_not_exists(#id)")),
+            expression_attribute_names: Some(names),
+            ..Default::default()
+        };
+        match self.block_on(self.client.put_item(input)) {
+            Ok(_) => Ok(1),
+            Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => Err(
+                SEError::Generic(format!("{} id {} already exists", self.table_name(&table), id)),
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove item in table
+    pub fn remove(&self, id: &Uuid, table: Table) -> Result<()> {
+        match self.remove_where(id, table, vec![], vec![])? {
+            true => Ok(()),
+            false => Err(SEError::DBError(UpdateFailed, id.to_string())),
+        }
+    }
+
+    /// Remove item in table if its column values are unchanged. A None value matches a NULL
+    /// column. Returns false if the item is missing or has changed.
+    pub fn remove_where<'a>(
+        &self,
+        id: &Uuid,
+        table: Table,
+        column: Vec<Column>,
+        data: Vec<&'a dyn ToAttribute>,
+    ) -> Result<bool> {
+        let mut names = HashMap::new();
+        names.insert(String::from("#id"), String::from("id"));
+        let mut values = HashMap::new();
+        let mut condition = vec![String::from("attribute_exists(#id)")];
+        for (i, (col, value)) in column.iter().zip(data.iter()).enumerate() {
+            names.insert(format!("#c{}", i), attribute_name(col));
+            match value.to_attribute() {
+                Some(v) => {
+                    values.insert(format!(":v{}", i), v);
+                    condition.push(format!("#c{} = :v{}", i, i));
+                }
+                None => condition.push(format!("attribute_not_exists(#c{})", i)),
+            }
+        }
+        let input = DeleteItemInput {
+            table_name: self.table_name(&table),
+            key: key(id),
+            condition_expression: Some(condition.join(" AND ")),
+            expression_attribute_names: Some(names),
+            expression_attribute_values: match values.is_empty() {
+                true => None,
+                false => Some(values),
+            },
+            ..Default::default()
+        };
+        match self.block_on(self.client.delete_item(input)) {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Update items in table for some ID. None values are removed.
+    pub fn update<'a>(
+        &self,
+        id: &Uuid,
+        table: Table,
+        column: Vec<Column>,
+        data: Vec<&'a dyn ToAttribute>,
+    ) -> Result<()> {
+        let input = UpdateExpression::new(column, data).input(
+            self.table_name(&table),
+            id,
+            "attribute_exists(#id)",
+        );
+        match self.block_on(self.client.update_item(input)) {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+                Err(SEError::DBError(UpdateFailed, id.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the row version of an item in a versioned table
+    pub fn get_version(&self, id: &Uuid, table: Table) -> Result<i64> {
+        self.get_1::<i64>(*id, table, vec![Column::Version])
+    }
+
+    /// Update items in a versioned table for some ID if the row version is unchanged since it was
+    /// read, and increment the version. Returns a retryable error if the item has been updated
+    /// by another request.
+    pub fn update_versioned<'a>(
+        &self,
+        id: &Uuid,
+        table: Table,
+        column: Vec<Column>,
+        data: Vec<&'a dyn ToAttribute>,
+        version: i64,
+    ) -> Result<()> {
+        let table_name = self.table_name(&table);
+        let mut expr = UpdateExpression::new(column, data);
+        expr.names
+            .insert(String::from("#version"), attribute_name(&Column::Version));
+        expr.values
+            .insert(String::from(":one"), 1i64.to_attribute().unwrap());
+        expr.values
+            .insert(String::from(":version"), version.to_attribute().unwrap());
+        expr.set.push(String::from("#version = #version + :one"));
+        let input = expr.input(table_name.clone(), id, "#version = :version");
+        match self.block_on(self.client.update_item(input)) {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+                // Distinguish a missing item from a concurrent update
+                self.get_version(id, table)?;
+                warn!("DB: Conflicting update of {} id: {}", table_name, id);
+                Err(SEError::TryAgain(format!(
+                    "conflicting update of {} id: {}",
+                    table_name, id
+                )))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the id and some columns of an item. Err if ID not found.
+    pub fn get_item(&self, id: &Uuid, table: &Table, column: &Vec<Column>) -> Result<Item> {
+        let (projection_expression, names) = projection(column);
+        let output = self.block_on(self.client.get_item(GetItemInput {
+            table_name: self.table_name(table),
+            key: key(id),
+            consistent_read: Some(true),
+            projection_expression: Some(projection_expression),
+            expression_attribute_names: Some(names),
+            ..Default::default()
+        }))?;
+        match output.item {
+            Some(item) if !item.is_empty() => Ok(item),
+            _ => Err(SEError::DBError(NoDataForID, id.to_string())),
+        }
+    }
+
+    /// Get 1 item from row in table. Err if ID not found or the item is empty for a non-Option
+    /// type.
+    pub fn get_1<T>(&self, id: Uuid, table: Table, column: Vec<Column>) -> Result<T>
+    where
+        T: FromAttribute,
+    {
+        let item = self.get_item(&id, &table, &column)?;
+        item_value(&item, &id, column[0])
+    }
+    /// Get 2 items from row in table. Err if ID not found or an item is empty for a non-Option
+    /// type.
+    pub fn get_2<T, U>(&self, id: Uuid, table: Table, column: Vec<Column>) -> Result<(T, U)>
+    where
+        T: FromAttribute,
+        U: FromAttribute,
+    {
+        let item = self.get_item(&id, &table, &column)?;
+        Ok((
+            item_value(&item, &id, column[0])?,
+            item_value(&item, &id, column[1])?,
+        ))
+    }
+    /// Get 3 items from row in table. Err if ID not found or an item is empty for a non-Option
+    /// type.
+    pub fn get_3<T, U, V>(&self, id: Uuid, table: Table, column: Vec<Column>) -> Result<(T, U, V)>
+    where
+        T: FromAttribute,
+        U: FromAttribute,
+        V: FromAttribute,
+    {
+        let item = self.get_item(&id, &table, &column)?;
+        Ok((
+            item_value(&item, &id, column[0])?,
+            item_value(&item, &id, column[1])?,
+            item_value(&item, &id, column[2])?,
+        ))
+    }
+    /// Get 4 items from row in table. Err if ID not found or an item is empty for a non-Option
+    /// type.
+    pub fn get_4<T, U, V, W>(
+        &self,
+        id: Uuid,
+        table: Table,
+        column: Vec<Column>,
+    ) -> Result<(T, U, V, W)>
+    where
+        T: FromAttribute,
+        U: FromAttribute,
+        V: FromAttribute,
+        W: FromAttribute,
+    {
+        let item = self.get_item(&id, &table, &column)?;
+        Ok((
+            item_value(&item, &id, column[0])?,
+            item_value(&item, &id, column[1])?,
+            item_value(&item, &id, column[2])?,
+            item_value(&item, &id, column[3])?,
+        ))
+    }
+
+    /// Get the id and some columns of every item in a table. Used by start up and maintenance
+    /// tasks only: reads the whole table.
+    pub fn scan(&self, table: &Table, column: Vec<Column>) -> Result<Vec<Item>> {
+        let (projection_expression, names) = projection(&column);
+        let mut items = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let output = self.block_on(self.client.scan(ScanInput {
+                table_name: self.table_name(table),
+                consistent_read: Some(true),
+                projection_expression: Some(projection_expression.clone()),
+                expression_attribute_names: Some(names.clone()),
+                exclusive_start_key,
+                ..Default::default()
+            }))?;
+            items.extend(output.items.unwrap_or_default());
+            match output.last_evaluated_key {
+                Some(k) if !k.is_empty() => exclusive_start_key = Some(k),
+                _ => return Ok(items),
+            }
+        }
+    }
+
+    /// Get the id and some columns of the items of a proof key in the UserSession or Transfer
+    /// table. The index is eventually consistent.
+    pub fn query_proof_key(
+        &self,
+        table: Table,
+        proof_key: &String,
+        column: Vec<Column>,
+    ) -> Result<Vec<Item>> {
+        let (projection_expression, mut names) = projection(&column);
+        names.insert(String::from("#pk"), attribute_name(&Column::ProofKey));
+        let mut values = HashMap::new();
+        values.insert(String::from(":pk"), proof_key.to_attribute().unwrap());
+        let mut items = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let output = self.block_on(self.client.query(QueryInput {
+                table_name: self.table_name(&table),
+                index_name: Some(String::from(PROOF_KEY_INDEX)),
+                key_condition_expression: Some(String::from("#pk = :pk")),
+                projection_expression: Some(projection_expression.clone()),
+                expression_attribute_names: Some(names.clone()),
+                expression_attribute_values: Some(values.clone()),
+                exclusive_start_key,
+                ..Default::default()
+            }))?;
+            items.extend(output.items.unwrap_or_default());
+            match output.last_evaluated_key {
+                Some(k) if !k.is_empty() => exclusive_start_key = Some(k),
+                _ => return Ok(items),
+            }
+        }
+    }
+}
+
+/// The DynamoDB store, or return the result of the PGDatabase method if DynamoDB storage is not
+/// enabled
+macro_rules! store_or_pg {
+    ($self:ident, $method:ident($($arg:expr),* $(,)?)) => {
+        match &$self.dynamo {
+            Some(store) => store,
+            None => return $self.pg.$method($($arg),*),
+        }
+    };
+}
+
+/// Postgres database with the core tables optionally stored in DynamoDB. Requests spanning
+/// DynamoDB and Postgres tables are not atomic.
+pub struct DynamoDatabase {
+    pub pg: PGDatabase,
+    /// Store of the core tables. None if DynamoDB storage is not enabled.
+    pub dynamo: Option<DynamoStore>,
+}
+
+impl DynamoDatabase {
+    /// Time and current root id recorded in a StateChain item when it is modified
+    fn statechain_updated(&self) -> Result<(NaiveDateTime, i64)> {
+        Ok((get_time_now(), self.pg.root_get_current_id()?))
+    }
+
+    /// Statechain info from the archive in Postgres
+    fn get_archived_statechain_info(&self, statechain_id: &Uuid) -> Result<StateChainInfo> {
+        let dbr = self.pg.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT amount, chain, confirmed, txbackup FROM {} WHERE id = $1",
+            Table::StateChainArchive.to_string(),
+        ))?;
+        let rows = statement.query(&[statechain_id])?;
+        if rows.is_empty() {
+            return Err(SEError::DBError(NoDataForID, statechain_id.to_string()));
+        };
+        let row = rows.get(0);
+        let chain_str: String = row.get("chain");
+        let tx_backup = match row.get::<_, Option<String>>("txbackup") {
+            Some(tx_backup_str) => Some(PGDatabase::deser::<Transaction>(tx_backup_str)?),
+            None => None,
+        };
+        Ok(StateChainInfo {
+            chain: PGDatabase::deser::<StateChainUnchecked>(chain_str)?.try_into()?,
+            amount: row.get("amount"),
+            tx_backup,
+            confirmed: row.get("confirmed"),
+            archived: true,
+        })
+    }
+
+    /// Swap IDs and fees of the deduction fees of a statechain not yet collected
+    fn get_swap_fees_uncollected(&self, statechain_id: &Uuid) -> Result<Vec<(Uuid, i64)>> {
+        let dbr = self.pg.database_r()?;
+        let statement = dbr.prepare(&format!(
+            "SELECT swapid, fee FROM {} WHERE statechainid = $1 AND txid IS NULL AND NOT collected",
+            Table::SwapFee.to_string(),
+        ))?;
+        let mut fees = vec![];
+        for row in &statement.query(&[statechain_id])? {
+            fees.push((row.get("swapid"), row.get("fee")));
+        }
+        Ok(fees)
+    }
+
+    /// Whether a transfer batch is finalized. False if there is no such batch.
+    fn is_transfer_batch_finalized(&self, store: &DynamoStore, batch_id: &Uuid) -> Result<bool> {
+        match store.get_1::<Option<bool>>(*batch_id, Table::TransferBatch, vec![Column::Finalized]) {
+            Ok(finalized) => Ok(finalized == Some(true)),
+            Err(SEError::DBError(NoDataForID, _)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Database for DynamoDatabase {
+    fn init(&self, coins_histo: &Mutex<CoinValueInfo>, user_ids: &Mutex<UserIDs>) -> Result<()> {
+        self.pg.make_tables()?;
+        if let Some(store) = &self.dynamo {
+            store.make_tables()?;
+        }
+        self.init_coins_histo(coins_histo)?;
+        self.init_user_ids(user_ids)
+    }
+
+    fn from_pool(pool: r2d2::Pool<PostgresConnectionManager>) -> Self {
+        Self {
+            pg: PGDatabase::from_pool(pool),
+            dynamo: None,
+        }
+    }
+
+    fn get_new() -> Self {
+        Self {
+            pg: PGDatabase::get_new(),
+            dynamo: None,
+        }
+    }
+
+    fn set_connection_from_config(&mut self, config: &Config) -> Result<()> {
+        self.pg.set_connection_from_config(config)?;
+        if config.dynamodb.enabled {
+            info!("DynamoDB: Core tables stored in DynamoDB");
+            self.dynamo = Some(DynamoStore::new(&config.dynamodb)?);
+        }
+        Ok(())
+    }
+
+    fn set_connection(&mut self, url: &String) -> Result<()> {
+        self.pg.set_connection(url)
+    }
+
+    fn reset(&self) -> Result<()> {
+        if let Some(store) = &self.dynamo {
+            let _ = store.truncate_tables();
+        }
+        self.pg.reset()
+    }
+
+    fn init_coins_histo(&self, coins_histo: &Mutex<CoinValueInfo>) -> Result<()> {
+        let store = store_or_pg!(self, init_coins_histo(coins_histo));
+        let mut guard = coins_histo.lock()?;
+        let mut counts: HashMap<i64, u64> = HashMap::new();
+        for item in store.scan(&Table::StateChain, vec![Column::Amount, Column::Confirmed])? {
+            let id = item_id(&item)?;
+            let amount = item_value::<Option<i64>>(&item, &id, Column::Amount)?;
+            let confirmed = item_value::<bool>(&item, &id, Column::Confirmed)?;
+            if let (Some(amount), true) = (amount, confirmed) {
+                *counts.entry(amount).or_insert(0) += 1;
+            }
+        }
+        for (amount, count) in counts {
+            if let Some(count) = NonZeroU64::new(count) {
+                guard.values.insert(amount, count);
+            }
+        }
+        Ok(())
+    }
+
+    fn init_user_ids(&self, user_ids: &Mutex<UserIDs>) -> Result<()> {
+        let store = store_or_pg!(self, init_user_ids(user_ids));
+        let mut guard = user_ids.lock()?;
+        for item in store.scan(&Table::UserSession, vec![])? {
+            guard.insert(item_id(&item)?);
+        }
+        Ok(())
+    }
+
+    fn has_withdraw_sc_sig(&self, user_id: Uuid) -> Result<()> {
+        let store = store_or_pg!(self, has_withdraw_sc_sig(user_id));
+        store.get_1::<String>(user_id, Table::UserSession, vec![Column::WithdrawScSig])?;
+        Ok(())
+    }
+
+    fn update_withdraw_sc_sig(&self, user_id: &Uuid, sig: StateChainSig) -> Result<()> {
+        let store = store_or_pg!(self, update_withdraw_sc_sig(user_id, sig));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::WithdrawScSig],
+            vec![&PGDatabase::ser(sig)?],
+        )
+    }
+
+    fn get_withdraw_sc_sig(&self, user_id: Uuid) -> Result<StateChainSig> {
+        let store = store_or_pg!(self, get_withdraw_sc_sig(user_id));
+        PGDatabase::deser(store.get_1(user_id, Table::UserSession, vec![Column::WithdrawScSig])?)
+    }
+
+    fn update_s1_pubkey(&self, user_id: &Uuid, pubkey: &GE) -> Result<()> {
+        let store = store_or_pg!(self, update_s1_pubkey(user_id, pubkey));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::S1PubKey],
+            vec![&PGDatabase::ser(pubkey)?],
+        )
+    }
+
+    fn get_s1_pubkey(&self, user_id: &Uuid) -> Result<GE> {
+        let store = store_or_pg!(self, get_s1_pubkey(user_id));
+        PGDatabase::deser(store.get_1(*user_id, Table::UserSession, vec![Column::S1PubKey])?)
+    }
+
+    fn update_withdraw_tx_sighash(
+        &self,
+        user_id: &Uuid,
+        sig_hash: Hash,
+        tx: Transaction,
+    ) -> Result<()> {
+        let store = store_or_pg!(self, update_withdraw_tx_sighash(user_id, sig_hash, tx));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::SigHash, Column::TxWithdraw],
+            vec![
+                &self.pg.ser_enc(user_id, &Table::UserSession, Column::SigHash, sig_hash)?,
+                &PGDatabase::ser(tx)?,
+            ],
+        )
+    }
+
+    fn update_sighash(&self, user_id: &Uuid, sig_hash: Hash) -> Result<()> {
+        let store = store_or_pg!(self, update_sighash(user_id, sig_hash));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::SigHash],
+            vec![&self.pg.ser_enc(user_id, &Table::UserSession, Column::SigHash, sig_hash)?],
+        )
+    }
+
+    fn get_sighash(&self, user_id: Uuid) -> Result<sha256d::Hash> {
+        let store = store_or_pg!(self, get_sighash(user_id));
+        self.pg.deser_enc(
+            &user_id,
+            &Table::UserSession,
+            Column::SigHash,
+            store.get_1(user_id, Table::UserSession, vec![Column::SigHash])?,
+        )
+    }
+
+    fn update_user_backup_tx(&self, user_id: &Uuid, tx: Transaction) -> Result<()> {
+        let store = store_or_pg!(self, update_user_backup_tx(user_id, tx));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxBackup],
+            vec![&PGDatabase::ser(tx)?],
+        )
+    }
+
+    fn get_user_backup_tx(&self, user_id: Uuid) -> Result<Transaction> {
+        let store = store_or_pg!(self, get_user_backup_tx(user_id));
+        PGDatabase::deser(store.get_1(user_id, Table::UserSession, vec![Column::TxBackup])?)
+    }
+
+    fn update_backup_tx(&self, statechain_id: &Uuid, tx: Transaction) -> Result<()> {
+        let store = store_or_pg!(self, update_backup_tx(statechain_id, tx));
+        let locktime = tx.lock_time;
+        // Any CPFP child of the previous backup tx is no longer valid
+        let no_cpfp: Option<String> = None;
+        store.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxBackup, Column::LockTime, Column::TxCpfp],
+            vec![&PGDatabase::ser(tx)?, &(locktime as i64), &no_cpfp],
+        )
+    }
+
+    fn update_backup_cpfp_tx(&self, statechain_id: &Uuid, tx: Transaction) -> Result<()> {
+        let store = store_or_pg!(self, update_backup_cpfp_tx(statechain_id, tx));
+        store.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxCpfp],
+            vec![&PGDatabase::ser(tx)?],
+        )
+    }
+
+    fn get_withdraw_confirm_data(&self, user_id: Uuid) -> Result<WithdrawConfirmData> {
+        let store = store_or_pg!(self, get_withdraw_confirm_data(user_id));
+        let (tx_withdraw_str, withdraw_sc_sig_str, statechain_id) = store
+            .get_3::<String, String, Uuid>(
+                user_id,
+                Table::UserSession,
+                vec![
+                    Column::TxWithdraw,
+                    Column::WithdrawScSig,
+                    Column::StateChainId,
+                ],
+            )?;
+        Ok(WithdrawConfirmData {
+            tx_withdraw: PGDatabase::deser(tx_withdraw_str)?,
+            withdraw_sc_sig: PGDatabase::deser(withdraw_sc_sig_str)?,
+            statechain_id,
+        })
+    }
+
+    /// Get vector of backup transactions that have nlocktimes less than or equal to the supplied
+    /// locktime (lockheight)
+    fn get_current_backup_txs(&self, locktime: i64) -> Result<Vec<BackupTxID>> {
+        let store = store_or_pg!(self, get_current_backup_txs(locktime));
+        let mut txs = vec![];
+        let column = vec![Column::TxBackup, Column::LockTime, Column::TxCpfp];
+        for item in store.scan(&Table::BackupTxs, column)? {
+            let id = item_id(&item)?;
+            match item_value::<Option<i64>>(&item, &id, Column::LockTime)? {
+                Some(tx_locktime) if tx_locktime <= locktime => (),
+                _ => continue,
+            }
+            let cpfp = match item_value::<Option<String>>(&item, &id, Column::TxCpfp)? {
+                Some(tx_str) => Some(PGDatabase::deser(tx_str)?),
+                None => None,
+            };
+            txs.push(BackupTxID {
+                tx: PGDatabase::deser(item_value::<String>(&item, &id, Column::TxBackup)?)?,
+                id,
+                cpfp,
+            });
+        }
+        Ok(txs)
+    }
+
+    // remove confirmed backup transaction from db
+    fn remove_backup_tx(&self, statechain_id: &Uuid) -> Result<()> {
+        let store = store_or_pg!(self, remove_backup_tx(statechain_id));
+        store.remove(statechain_id, Table::BackupTxs)
+    }
+
+    fn get_statechain_id(&self, user_id: Uuid) -> Result<Uuid> {
+        let store = store_or_pg!(self, get_statechain_id(user_id));
+        store.get_1::<Uuid>(user_id, Table::UserSession, vec![Column::StateChainId])
+    }
+
+    fn get_owner_id(&self, statechain_id: Uuid) -> Result<Uuid> {
+        let store = store_or_pg!(self, get_owner_id(statechain_id));
+        store.get_1::<Uuid>(statechain_id, Table::StateChain, vec![Column::OwnerId])
+    }
+
+    fn get_user_auth(&self, user_id: &Uuid) -> Result<String> {
+        let store = store_or_pg!(self, get_user_auth(user_id));
+        store.get_1::<String>(*user_id, Table::UserSession, vec![Column::Authentication])
+    }
+
+    fn is_confirmed(&self, statechain_id: &Uuid) -> Result<bool> {
+        let store = store_or_pg!(self, is_confirmed(statechain_id));
+        store.get_1::<bool>(*statechain_id, Table::StateChain, vec![Column::Confirmed])
+    }
+
+    fn set_confirmed(&self, statechain_id: &Uuid) -> Result<()> {
+        let store = store_or_pg!(self, set_confirmed(statechain_id));
+        let (updated, updated_root) = self.statechain_updated()?;
+        store.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Confirmed, Column::Updated, Column::UpdatedRoot],
+            vec![&true, &updated, &updated_root],
+        )
+    }
+
+    fn get_challenge(&self, user_id: &Uuid) -> Result<String> {
+        let store = store_or_pg!(self, get_challenge(user_id));
+        store.get_1::<String>(*user_id, Table::UserSession, vec![Column::Challenge])
+    }
+
+    fn update_statechain_id(&self, user_id: &Uuid, statechain_id: &Uuid) -> Result<()> {
+        let store = store_or_pg!(self, update_statechain_id(user_id, statechain_id));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::StateChainId],
+            vec![statechain_id],
+        )
+    }
+
+    fn get_statechain_amount(&self, statechain_id: Uuid) -> Result<StateChainAmount> {
+        let store = store_or_pg!(self, get_statechain_amount(statechain_id));
+        let (amount, state_chain_str) = store.get_2::<i64, String>(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Amount, Column::Chain],
+        )?;
+        Ok(StateChainAmount {
+            chain: PGDatabase::deser::<StateChainUnchecked>(state_chain_str)?.try_into()?,
+            amount,
+        })
+    }
+
+    fn get_statechain_info(&self, statechain_id: Uuid) -> Result<StateChainInfo> {
+        let store = store_or_pg!(self, get_statechain_info(statechain_id));
+        let (amount, chain_str, confirmed) = match store.get_3::<i64, String, bool>(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Amount, Column::Chain, Column::Confirmed],
+        ) {
+            Ok(v) => v,
+            Err(SEError::DBError(NoDataForID, _)) => {
+                return self.get_archived_statechain_info(&statechain_id)
+            }
+            Err(e) => return Err(e),
+        };
+        let tx_backup = match store.get_1::<Option<String>>(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxBackup],
+        ) {
+            Ok(Some(tx_backup_str)) => Some(PGDatabase::deser::<Transaction>(tx_backup_str)?),
+            Ok(None) | Err(SEError::DBError(NoDataForID, _)) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(StateChainInfo {
+            chain: PGDatabase::deser::<StateChainUnchecked>(chain_str)?.try_into()?,
+            amount,
+            tx_backup,
+            confirmed,
+            archived: false,
+        })
+    }
+
+    fn get_statechain_version(&self, statechain_id: &Uuid) -> Result<i64> {
+        let store = store_or_pg!(self, get_statechain_version(statechain_id));
+        store.get_version(statechain_id, Table::StateChain)
+    }
+
+    fn update_statechain_amount(
+        &self,
+        statechain_id: &Uuid,
+        state_chain: StateChain,
+        amount: u64,
+        coins_histo: Arc<Mutex<CoinValueInfo>>,
+        version: i64,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            update_statechain_amount(statechain_id, state_chain, amount, coins_histo, version)
+        );
+        let prev_statechain_amount = self.get_statechain_amount(*statechain_id)?.amount;
+        let (updated, updated_root) = self.statechain_updated()?;
+        store.update_versioned(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Chain, Column::Amount, Column::Updated, Column::UpdatedRoot],
+            vec![&PGDatabase::ser(state_chain)?, &(amount as i64), &updated, &updated_root],
+            version,
+        )?;
+        let mut guard = coins_histo.as_ref().lock()?;
+        if self.is_confirmed(statechain_id)? {
+            guard.update(&(amount as i64), &prev_statechain_amount)?;
+        }
+        Ok(())
+    }
+
+    fn create_statechain(
+        &self,
+        statechain_id: &Uuid,
+        user_id: &Uuid,
+        state_chain: &StateChain,
+        amount: &i64,
+    ) -> Result<()> {
+        let store = store_or_pg!(self, create_statechain(statechain_id, user_id, state_chain, amount));
+        store.insert(statechain_id, Table::StateChain)?;
+        let (updated, updated_root) = self.statechain_updated()?;
+        store.update(
+            statechain_id,
+            Table::StateChain,
+            vec![
+                Column::Chain,
+                Column::Amount,
+                Column::LockedUntil,
+                Column::OwnerId,
+                Column::Updated,
+                Column::UpdatedRoot,
+            ],
+            vec![
+                &PGDatabase::ser(state_chain.to_owned())?,
+                amount,
+                &get_time_now(),
+                user_id,
+                &updated,
+                &updated_root,
+            ],
+        )
+    }
+
+    fn get_statechain(&self, statechain_id: Uuid) -> Result<StateChain> {
+        let store = store_or_pg!(self, get_statechain(statechain_id));
+        let state_chain_str =
+            store.get_1::<String>(statechain_id, Table::StateChain, vec![Column::Chain])?;
+        Ok(PGDatabase::deser::<StateChainUnchecked>(state_chain_str)?.try_into()?)
+    }
+
+    fn update_statechain_owner(
+        &self,
+        statechain_id: &Uuid,
+        state_chain: StateChain,
+        new_user_id: &Uuid,
+        version: i64,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            update_statechain_owner(statechain_id, state_chain, new_user_id, version)
+        );
+        let (updated, updated_root) = self.statechain_updated()?;
+        store.update_versioned(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Chain, Column::OwnerId, Column::Updated, Column::UpdatedRoot],
+            vec![&PGDatabase::ser(state_chain)?, new_user_id, &updated, &updated_root],
+            version,
+        )
+    }
+
+    // Remove statechain_id from user session to signal end of session
+    fn remove_statechain_id(&self, user_id: &Uuid) -> Result<()> {
+        let store = store_or_pg!(self, remove_statechain_id(user_id));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::StateChainId],
+            vec![&Uuid::nil()],
+        )
+    }
+
+    fn create_backup_transaction(
+        &self,
+        statechain_id: &Uuid,
+        tx_backup: &Transaction,
+    ) -> Result<()> {
+        let store = store_or_pg!(self, create_backup_transaction(statechain_id, tx_backup));
+        let locktime = tx_backup.lock_time;
+        store.insert(statechain_id, Table::BackupTxs)?;
+        store.update(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxBackup, Column::LockTime],
+            vec![&PGDatabase::ser(tx_backup.clone())?, &(locktime as i64)],
+        )
+    }
+
+    fn get_backup_transaction(&self, statechain_id: Uuid) -> Result<Transaction> {
+        let store = store_or_pg!(self, get_backup_transaction(statechain_id));
+        PGDatabase::deser(store.get_1::<String>(
+            statechain_id,
+            Table::BackupTxs,
+            vec![Column::TxBackup],
+        )?)
+    }
+
+    fn get_proof_key(&self, user_id: Uuid) -> Result<String> {
+        let store = store_or_pg!(self, get_proof_key(user_id));
+        store.get_1::<String>(user_id, Table::UserSession, vec![Column::ProofKey])
+    }
+
+    fn update_proof_key(&self, user_id: &Uuid, proof_key: &String) -> Result<()> {
+        let store = store_or_pg!(self, update_proof_key(user_id, proof_key));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::ProofKey],
+            vec![proof_key],
+        )
+    }
+
+    fn get_proof_key_holdings(&self, proof_key: &String) -> Result<(u64, u64)> {
+        let store = store_or_pg!(self, get_proof_key_holdings(proof_key));
+        let (mut count, mut value) = (0, 0);
+        let sessions =
+            store.query_proof_key(Table::UserSession, proof_key, vec![Column::StateChainId])?;
+        for item in sessions {
+            let user_id = item_id(&item)?;
+            let statechain_id =
+                match item_value::<Option<Uuid>>(&item, &user_id, Column::StateChainId)? {
+                    Some(id) => id,
+                    None => continue,
+                };
+            let (amount, owner_id) = match store.get_2::<i64, Uuid>(
+                statechain_id,
+                Table::StateChain,
+                vec![Column::Amount, Column::OwnerId],
+            ) {
+                Ok(v) => v,
+                Err(SEError::DBError(NoDataForID, _)) => continue,
+                Err(e) => return Err(e),
+            };
+            // Withdrawn statechains have amount 0
+            if owner_id == user_id && amount > 0 {
+                count += 1;
+                value += amount as u64;
+            }
+        }
+        Ok((count, value))
+    }
+
+    fn get_total_value(&self) -> Result<u64> {
+        let store = store_or_pg!(self, get_total_value());
+        let mut value = 0;
+        for item in store.scan(&Table::StateChain, vec![Column::Amount])? {
+            let id = item_id(&item)?;
+            // Withdrawn statechains have amount 0
+            if let Some(amount) = item_value::<Option<i64>>(&item, &id, Column::Amount)? {
+                if amount > 0 {
+                    value += amount as u64;
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    fn get_backup_transaction_and_proof_key(&self, user_id: Uuid) -> Result<(Transaction, String)> {
+        let store = store_or_pg!(self, get_backup_transaction_and_proof_key(user_id));
+        let (tx_backup_str, proof_key) = store.get_2::<String, String>(
+            user_id,
+            Table::UserSession,
+            vec![Column::TxBackup, Column::ProofKey],
+        )?;
+        Ok((PGDatabase::deser(tx_backup_str)?, proof_key))
+    }
+
+    fn get_sc_locked_until(&self, statechain_id: Uuid) -> Result<NaiveDateTime> {
+        let store = store_or_pg!(self, get_sc_locked_until(statechain_id));
+        store.get_1::<NaiveDateTime>(statechain_id, Table::StateChain, vec![Column::LockedUntil])
+    }
+
+    fn update_locked_until(&self, statechain_id: &Uuid, time: &NaiveDateTime) -> Result<()> {
+        let store = store_or_pg!(self, update_locked_until(statechain_id, time));
+        store.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::LockedUntil],
+            vec![time],
+        )
+    }
+
+    fn get_transfer_batch_data(&self, batch_id: Uuid) -> Result<TransferBatchData> {
+        let store = store_or_pg!(self, get_transfer_batch_data(batch_id));
+        let version = store.get_version(&batch_id, Table::TransferBatch)?;
+        let (state_chains_str, start_time, finalized, punished_state_chains_str) = store
+            .get_4::<String, NaiveDateTime, bool, String>(
+                batch_id,
+                Table::TransferBatch,
+                vec![
+                    Column::StateChains,
+                    Column::StartTime,
+                    Column::Finalized,
+                    Column::PunishedStateChains,
+                ],
+            )?;
+        Ok(TransferBatchData {
+            state_chains: PGDatabase::deser(state_chains_str)?,
+            start_time,
+            finalized,
+            punished_state_chains: PGDatabase::deser(punished_state_chains_str)?,
+            version,
+        })
+    }
+
+    fn has_transfer_batch_id(&self, batch_id: Uuid) -> bool {
+        self.get_transfer_batch_id(batch_id).is_ok()
+    }
+
+    fn get_transfer_batch_id(&self, batch_id: Uuid) -> Result<Uuid> {
+        let store = store_or_pg!(self, get_transfer_batch_id(batch_id));
+        store.get_1::<Uuid>(batch_id, Table::TransferBatch, vec![Column::Id])
+    }
+
+    fn get_punished_state_chains(&self, batch_id: Uuid) -> Result<Vec<Uuid>> {
+        let store = store_or_pg!(self, get_punished_state_chains(batch_id));
+        PGDatabase::deser(store.get_1(
+            batch_id,
+            Table::TransferBatch,
+            vec![Column::PunishedStateChains],
+        )?)
+    }
+
+    fn create_transfer(
+        &self,
+        statechain_id: &Uuid,
+        statechain_sig: &StateChainSig,
+        x1: &FE,
+        batch_id: Option<Uuid>,
+    ) -> Result<()> {
+        let store = store_or_pg!(self, create_transfer(statechain_id, statechain_sig, x1, batch_id));
+        // Create Transfer table entry
+        if !self.transfer_is_completed(*statechain_id) {
+            store.insert(statechain_id, Table::Transfer)?;
+        }
+        store.update(
+            statechain_id,
+            Table::Transfer,
+            vec![Column::StateChainSig, Column::X1, Column::BatchId],
+            vec![
+                &PGDatabase::ser(statechain_sig.to_owned())?,
+                &self.pg.ser_enc(statechain_id, &Table::Transfer, Column::X1, x1)?,
+                &batch_id,
+            ],
+        )
+    }
+
+    fn update_transfer_msg(&self, statechain_id: &Uuid, msg: &TransferMsg3) -> Result<()> {
+        let store = store_or_pg!(self, update_transfer_msg(statechain_id, msg));
+        store.update(
+            statechain_id,
+            Table::Transfer,
+            vec![Column::TransferMsg, Column::ProofKey],
+            vec![&PGDatabase::ser(msg.to_owned())?, &msg.statechain_sig.data],
+        )
+    }
+
+    fn get_transfer_msg(&self, statechain_id: &Uuid) -> Result<TransferMsg3> {
+        let store = store_or_pg!(self, get_transfer_msg(statechain_id));
+        PGDatabase::deser(store.get_1(*statechain_id, Table::Transfer, vec![Column::TransferMsg])?)
+    }
+
+    fn get_transfer_msg_addr(&self, receive_addr: &str) -> Result<Vec<TransferMsg3>> {
+        let store = store_or_pg!(self, get_transfer_msg_addr(receive_addr));
+        let mut msg_vec = vec![];
+        let items = store.query_proof_key(
+            Table::Transfer,
+            &receive_addr.to_string(),
+            vec![Column::TransferMsg],
+        )?;
+        for item in items {
+            let id = item_id(&item)?;
+            msg_vec.push(PGDatabase::deser(item_value::<String>(&item, &id, Column::TransferMsg)?)?);
+        }
+        Ok(msg_vec)
+    }
+
+    fn create_transfer_batch_data(&self, batch_id: &Uuid, state_chains: Vec<Uuid>) -> Result<()> {
+        let store = store_or_pg!(self, create_transfer_batch_data(batch_id, state_chains));
+        store.insert(batch_id, Table::TransferBatch)?;
+        store.update(
+            batch_id,
+            Table::TransferBatch,
+            vec![
+                Column::StartTime,
+                Column::StateChains,
+                Column::PunishedStateChains,
+                Column::Finalized,
+            ],
+            vec![
+                &get_time_now(),
+                &PGDatabase::ser(state_chains)?,
+                &PGDatabase::ser(Vec::<String>::new())?,
+                &false,
+            ],
+        )
+    }
+
+    fn get_transfer_data(&self, statechain_id: Uuid) -> Result<TransferData> {
+        let store = store_or_pg!(self, get_transfer_data(statechain_id));
+        let version = store.get_version(&statechain_id, Table::Transfer)?;
+        let (statechain_sig_str, x1_str, batch_id) = store.get_3::<String, String, Option<Uuid>>(
+            statechain_id,
+            Table::Transfer,
+            vec![Column::StateChainSig, Column::X1, Column::BatchId],
+        )?;
+        Ok(TransferData {
+            statechain_id,
+            statechain_sig: PGDatabase::deser(statechain_sig_str)?,
+            x1: self.pg.deser_enc(&statechain_id, &Table::Transfer, Column::X1, x1_str)?,
+            batch_id,
+            version,
+        })
+    }
+
+    fn claim_transfer(&self, statechain_id: &Uuid, version: i64) -> Result<()> {
+        let store = store_or_pg!(self, claim_transfer(statechain_id, version));
+        store.update_versioned(statechain_id, Table::Transfer, vec![], vec![], version)
+    }
+
+    fn remove_transfer_data(&self, statechain_id: &Uuid) -> Result<()> {
+        let store = store_or_pg!(self, remove_transfer_data(statechain_id));
+        store.remove(statechain_id, Table::Transfer)
+    }
+
+    fn transfer_is_completed(&self, statechain_id: Uuid) -> bool {
+        let store = store_or_pg!(self, transfer_is_completed(statechain_id));
+        store
+            .get_1::<Uuid>(statechain_id, Table::Transfer, vec![Column::Id])
+            .is_ok()
+    }
+
+    fn get_session_state(&self, user_id: &Uuid) -> Result<Option<SessionState>> {
+        let store = store_or_pg!(self, get_session_state(user_id));
+        match store.get_1::<Option<String>>(*user_id, Table::UserSession, vec![Column::SessionState])? {
+            Some(state) => Ok(Some(PGDatabase::deser(state)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update_session_state(&self, user_id: &Uuid, state: &SessionState) -> Result<()> {
+        let store = store_or_pg!(self, update_session_state(user_id, state));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::SessionState, Column::StateUpdated],
+            vec![&PGDatabase::ser(state)?, &get_time_now()],
+        )
+    }
+
+    fn get_user_sessions(
+        &self,
+        proof_keys: &Vec<String>,
+        state: Option<SessionState>,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<UserSessionRecord>, i64)> {
+        let store = store_or_pg!(self, get_user_sessions(proof_keys, state, offset, limit));
+        let state = match state {
+            Some(s) => Some(PGDatabase::ser(s)?),
+            None => None,
+        };
+        let mut sessions = vec![];
+        // Ordered by proof key then session id
+        let proof_keys: BTreeSet<&String> = proof_keys.iter().collect();
+        for proof_key in proof_keys {
+            let items = store.query_proof_key(
+                Table::UserSession,
+                proof_key,
+                vec![Column::StateChainId, Column::SessionState],
+            )?;
+            let mut key_sessions = vec![];
+            for item in items {
+                let user_id = item_id(&item)?;
+                let session_state =
+                    item_value::<Option<String>>(&item, &user_id, Column::SessionState)?;
+                // A None state matches sessions in any state
+                if state.is_some() && session_state != state {
+                    continue;
+                }
+                key_sessions.push(UserSessionRecord {
+                    user_id,
+                    proof_key: proof_key.clone(),
+                    statechain_id: item_value(&item, &user_id, Column::StateChainId)?,
+                    state: match session_state {
+                        Some(s) => Some(PGDatabase::deser(s)?),
+                        None => None,
+                    },
+                });
+            }
+            key_sessions.sort_by_key(|s| s.user_id);
+            sessions.extend(key_sessions);
+        }
+        let total = sessions.len() as i64;
+        let page = sessions
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .collect();
+        Ok((page, total))
+    }
+
+    fn get_public_master(&self, user_id: Uuid) -> Result<Option<String>> {
+        let store = store_or_pg!(self, get_public_master(user_id));
+        store.get_1::<Option<String>>(user_id, Table::UserSession, vec![Column::MasterPublic])
+    }
+
+    fn update_public_master(&self, user_id: &Uuid, master_public: Party1Public) -> Result<()> {
+        let store = store_or_pg!(self, update_public_master(user_id, master_public));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::MasterPublic],
+            vec![&PGDatabase::ser(master_public)?],
+        )
+    }
+
+    fn get_p2c(&self, user_id: Uuid) -> Result<Option<PayToContract>> {
+        let store = store_or_pg!(self, get_p2c(user_id));
+        match store.get_1::<Option<String>>(user_id, Table::UserSession, vec![Column::P2c])? {
+            Some(p2c) => Ok(Some(PGDatabase::deser(p2c)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update_p2c(&self, user_id: &Uuid, p2c: &PayToContract) -> Result<()> {
+        let store = store_or_pg!(self, update_p2c(user_id, p2c));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::P2c],
+            vec![&PGDatabase::ser(p2c)?],
+        )
+    }
+
+    fn get_statecoin_pubkey(&self, statechain_id: Uuid) -> Result<Option<String>> {
+        let store = store_or_pg!(self, get_statecoin_pubkey(statechain_id));
+        store.get_1::<Option<String>>(statechain_id, Table::StateChain, vec![Column::SharedPublic])
+    }
+
+    fn get_backup_addr(&self, statechain_id: &Uuid) -> Result<Option<String>> {
+        let store = store_or_pg!(self, get_backup_addr(statechain_id));
+        store.get_1::<Option<String>>(*statechain_id, Table::StateChain, vec![Column::BackupAddr])
+    }
+
+    fn update_backup_addr(&self, statechain_id: &Uuid, addr: &String) -> Result<()> {
+        let store = store_or_pg!(self, update_backup_addr(statechain_id, addr));
+        store.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::BackupAddr],
+            vec![addr],
+        )
+    }
+
+    fn get_shared_pubkey(&self, user_id: Uuid) -> Result<Option<String>> {
+        let store = store_or_pg!(self, get_shared_pubkey(user_id));
+        store.get_1::<Option<String>>(user_id, Table::UserSession, vec![Column::SharedPublic])
+    }
+
+    fn update_shared_pubkey(&self, user_id: Uuid, pubkey: GE) -> Result<()> {
+        let store = store_or_pg!(self, update_shared_pubkey(user_id, pubkey));
+        store.update(
+            &user_id,
+            Table::UserSession,
+            vec![Column::SharedPublic],
+            vec![&PGDatabase::ser(pubkey)?],
+        )
+    }
+
+    fn set_shared_pubkey(&self, statechain_id: Uuid, pubkey: &String) -> Result<()> {
+        let store = store_or_pg!(self, set_shared_pubkey(statechain_id, pubkey));
+        store.update(
+            &statechain_id,
+            Table::StateChain,
+            vec![Column::SharedPublic],
+            vec![pubkey],
+        )
+    }
+
+    fn get_ecdsa_master(&self, user_id: Uuid) -> Result<Option<String>> {
+        let store = store_or_pg!(self, get_ecdsa_master(user_id));
+        match store.get_1::<Option<String>>(user_id, Table::Ecdsa, vec![Column::Party1MasterKey])? {
+            Some(master_key) => Ok(Some(self.pg.cipher.decrypt(
+                &super::encryption::column_aad(&Table::Ecdsa, Column::Party1MasterKey, &user_id),
+                master_key,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update_ecdsa_master(&self, user_id: &Uuid, master_key: MasterKey1) -> Result<()> {
+        let store = store_or_pg!(self, update_ecdsa_master(user_id, master_key));
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::Party1MasterKey],
+            vec![&self.pg.ser_enc(user_id, &Table::Ecdsa, Column::Party1MasterKey, master_key)?],
+        )
+    }
+
+    fn get_ecdsa_master_key_input(&self, user_id: Uuid) -> Result<ECDSAMasterKeyInput> {
+        let store = store_or_pg!(self, get_ecdsa_master_key_input(user_id));
+        let (party2_public_str, paillier_key_pair_str, party_one_private_str, comm_witness_str) =
+            store.get_4::<String, String, String, String>(
+                user_id,
+                Table::Ecdsa,
+                vec![
+                    Column::Party2Public,
+                    Column::PaillierKeyPair,
+                    Column::Party1Private,
+                    Column::CommWitness,
+                ],
+            )?;
+        Ok(ECDSAMasterKeyInput {
+            party2_public: PGDatabase::deser(party2_public_str)?,
+            paillier_key_pair: self.pg.deser_enc(
+                &user_id,
+                &Table::Ecdsa,
+                Column::PaillierKeyPair,
+                paillier_key_pair_str,
+            )?,
+            party_one_private: self.pg.deser_enc(
+                &user_id,
+                &Table::Ecdsa,
+                Column::Party1Private,
+                party_one_private_str,
+            )?,
+            comm_witness: self.pg.deser_enc(
+                &user_id,
+                &Table::Ecdsa,
+                Column::CommWitness,
+                comm_witness_str,
+            )?,
+        })
+    }
+
+    fn get_ecdsa_witness_keypair(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(party_one::CommWitness, party_one::EcKeyPair)> {
+        let store = store_or_pg!(self, get_ecdsa_witness_keypair(user_id));
+        let (comm_witness_str, ec_key_pair_str) = store.get_2::<String, String>(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::CommWitness, Column::EcKeyPair],
+        )?;
+        Ok((
+            self.pg.deser_enc(&user_id, &Table::Ecdsa, Column::CommWitness, comm_witness_str)?,
+            self.pg.deser_enc(&user_id, &Table::Ecdsa, Column::EcKeyPair, ec_key_pair_str)?,
+        ))
+    }
+
+    fn get_ecdsa_s2(&self, user_id: Uuid) -> Result<FE> {
+        let store = store_or_pg!(self, get_ecdsa_s2(user_id));
+        let s2_str = store.get_1(user_id, Table::UserSession, vec![Column::S2])?;
+        self.pg.deser_enc(&user_id, &Table::UserSession, Column::S2, s2_str)
+    }
+
+    fn update_keygen_first_msg_and_witness(
+        &self,
+        user_id: &Uuid,
+        key_gen_first_msg: &party_one::KeyGenFirstMsg,
+        comm_witness: party_one::CommWitness,
+        ec_key_pair: party_one::EcKeyPair,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            update_keygen_first_msg_and_witness(user_id, key_gen_first_msg, comm_witness, ec_key_pair)
+        );
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![
+                Column::POS,
+                Column::KeyGenFirstMsg,
+                Column::CommWitness,
+                Column::EcKeyPair,
+            ],
+            vec![
+                &PGDatabase::ser(HDPos { pos: 0u32 })?,
+                &PGDatabase::ser(key_gen_first_msg.to_owned())?,
+                &self.pg.ser_enc(user_id, &Table::Ecdsa, Column::CommWitness, comm_witness)?,
+                &self.pg.ser_enc(user_id, &Table::Ecdsa, Column::EcKeyPair, ec_key_pair)?,
+            ],
+        )
+    }
+
+    fn update_keygen_first_msg(
+        &self,
+        user_id: &Uuid,
+        key_gen_first_msg: &party_one::KeyGenFirstMsg,
+    ) -> Result<()> {
+        let store = store_or_pg!(self, update_keygen_first_msg(user_id, key_gen_first_msg));
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::KeyGenFirstMsg],
+            vec![&PGDatabase::ser(key_gen_first_msg.to_owned())?],
+        )
+    }
+
+    fn get_keygen_first_msg(&self, user_id: &Uuid) -> Result<party_one::KeyGenFirstMsg> {
+        let store = store_or_pg!(self, get_keygen_first_msg(user_id));
+        PGDatabase::deser(store.get_1(*user_id, Table::Ecdsa, vec![Column::KeyGenFirstMsg])?)
+    }
+
+    fn update_keygen_second_msg(
+        &self,
+        user_id: &Uuid,
+        party2_public: GE,
+        paillier_key_pair: party_one::PaillierKeyPair,
+        party_one_private: party_one::Party1Private,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            update_keygen_second_msg(user_id, party2_public, paillier_key_pair, party_one_private)
+        );
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![
+                Column::Party2Public,
+                Column::PaillierKeyPair,
+                Column::Party1Private,
+            ],
+            vec![
+                &PGDatabase::ser(party2_public)?,
+                &self.pg.ser_enc(user_id, &Table::Ecdsa, Column::PaillierKeyPair, paillier_key_pair)?,
+                &self.pg.ser_enc(user_id, &Table::Ecdsa, Column::Party1Private, party_one_private)?,
+            ],
+        )
+    }
+
+    fn update_party_2_public(&self, user_id: &Uuid, party2_public: GE) -> Result<()> {
+        let store = store_or_pg!(self, update_party_2_public(user_id, party2_public));
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::Party2Public],
+            vec![&PGDatabase::ser(party2_public)?],
+        )
+    }
+
+    fn init_ecdsa(&self, user_id: &Uuid) -> Result<u64> {
+        let store = store_or_pg!(self, init_ecdsa(user_id));
+        store.insert(user_id, Table::Ecdsa)
+    }
+
+    fn reset_ecdsa(&self, user_id: &Uuid) -> Result<()> {
+        let store = store_or_pg!(self, reset_ecdsa(user_id));
+        let share_epoch = self.get_ecdsa_share_epoch(*user_id)?;
+        store.remove(user_id, Table::Ecdsa)?;
+        store.insert(user_id, Table::Ecdsa)?;
+        self.update_ecdsa_share_epoch(user_id, share_epoch)
+    }
+
+    fn get_ecdsa_key_epoch(&self, user_id: Uuid) -> Result<u32> {
+        let store = store_or_pg!(self, get_ecdsa_key_epoch(user_id));
+        let epoch = store.get_1::<i64>(user_id, Table::Ecdsa, vec![Column::KeyEpoch])?;
+        Ok(epoch as u32)
+    }
+
+    fn update_ecdsa_key_epoch(&self, user_id: &Uuid, epoch: u32) -> Result<()> {
+        let store = store_or_pg!(self, update_ecdsa_key_epoch(user_id, epoch));
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::KeyEpoch],
+            vec![&(epoch as i64)],
+        )
+    }
+
+    fn get_ecdsa_share_epoch(&self, user_id: Uuid) -> Result<u32> {
+        let store = store_or_pg!(self, get_ecdsa_share_epoch(user_id));
+        let epoch = store.get_1::<i64>(user_id, Table::Ecdsa, vec![Column::ShareEpoch])?;
+        Ok(epoch as u32)
+    }
+
+    fn update_ecdsa_share_epoch(&self, user_id: &Uuid, epoch: u32) -> Result<()> {
+        let store = store_or_pg!(self, update_ecdsa_share_epoch(user_id, epoch));
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::ShareEpoch],
+            vec![&(epoch as i64)],
+        )
+    }
+
+    fn get_ecdsa_refresh_x1(&self, user_id: Uuid) -> Result<Option<FE>> {
+        let store = store_or_pg!(self, get_ecdsa_refresh_x1(user_id));
+        match store.get_1::<Option<String>>(user_id, Table::Ecdsa, vec![Column::RefreshX1])? {
+            Some(x1_str) => Ok(Some(self.pg.deser_enc(
+                &user_id,
+                &Table::Ecdsa,
+                Column::RefreshX1,
+                x1_str,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update_ecdsa_refresh_x1(&self, user_id: &Uuid, x1: &FE) -> Result<()> {
+        let store = store_or_pg!(self, update_ecdsa_refresh_x1(user_id, x1));
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::RefreshX1],
+            vec![&self.pg.ser_enc(user_id, &Table::Ecdsa, Column::RefreshX1, x1)?],
+        )
+    }
+
+    fn update_ecdsa_proof_verified(&self, user_id: &Uuid, verified: bool) -> Result<()> {
+        let store = store_or_pg!(self, update_ecdsa_proof_verified(user_id, verified));
+        store.update(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::ProofVerified],
+            vec![&verified],
+        )
+    }
+
+    /// Get IDs of completed keygen sessions without a successful proof verification record
+    fn get_unverified_keygen_sessions(&self) -> Result<Vec<Uuid>> {
+        let store = store_or_pg!(self, get_unverified_keygen_sessions());
+        let mut ids = vec![];
+        for item in store.scan(&Table::Ecdsa, vec![Column::ProofVerified])? {
+            let id = item_id(&item)?;
+            if item_value::<Option<bool>>(&item, &id, Column::ProofVerified)? == Some(true) {
+                continue;
+            }
+            match store.get_1::<Option<String>>(id, Table::UserSession, vec![Column::MasterPublic]) {
+                Ok(Some(_)) => ids.push(id),
+                Ok(None) | Err(SEError::DBError(NoDataForID, _)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(ids)
+    }
+
+    fn update_ecdsa_s2(&self, user_id: &Uuid, s2: &FE) -> Result<()> {
+        let store = store_or_pg!(self, update_ecdsa_s2(user_id, s2));
+        store.update(
+            user_id,
+            Table::UserSession,
+            vec![Column::S2],
+            vec![&self.pg.ser_enc(user_id, &Table::UserSession, Column::S2, s2)?],
+        )
+    }
+
+    fn get_ecdsa_party_1_private(&self, user_id: Uuid) -> Result<party_one::Party1Private> {
+        let store = store_or_pg!(self, get_ecdsa_party_1_private(user_id));
+        self.pg.deser_enc(
+            &user_id,
+            &Table::Ecdsa,
+            Column::Party1Private,
+            store.get_1(user_id, Table::Ecdsa, vec![Column::Party1Private])?,
+        )
+    }
+
+    fn get_ecdsa_keypair(&self, user_id: Uuid) -> Result<ECDSAKeypair> {
+        let store = store_or_pg!(self, get_ecdsa_keypair(user_id));
+        let (party_1_private_str, party_2_public_str) = store.get_2::<String, String>(
+            user_id,
+            Table::Ecdsa,
+            vec![Column::Party1Private, Column::Party2Public],
+        )?;
+        let party_1_private: Party1Private = self.pg.deser_enc(
+            &user_id,
+            &Table::Ecdsa,
+            Column::Party1Private,
+            party_1_private_str,
+        )?;
+        Ok(ECDSAKeypair {
+            party_1_private,
+            party_2_public: PGDatabase::deser(party_2_public_str)?,
+        })
+    }
+
+    fn update_punished(
+        &self,
+        batch_id: &Uuid,
+        punished_state_chains: Vec<Uuid>,
+        version: i64,
+    ) -> Result<()> {
+        let store = store_or_pg!(self, update_punished(batch_id, punished_state_chains, version));
+        store.update_versioned(
+            batch_id,
+            Table::TransferBatch,
+            vec![Column::PunishedStateChains],
+            vec![&PGDatabase::ser(punished_state_chains)?],
+            version,
+        )
+    }
+
+    fn get_transfer_batch_start_time(&self, batch_id: &Uuid) -> Result<NaiveDateTime> {
+        let store = store_or_pg!(self, get_transfer_batch_start_time(batch_id));
+        store.get_1::<NaiveDateTime>(*batch_id, Table::TransferBatch, vec![Column::StartTime])
+    }
+
+    fn update_transfer_batch_start_height(&self, batch_id: &Uuid, height: u64) -> Result<()> {
+        let store = store_or_pg!(self, update_transfer_batch_start_height(batch_id, height));
+        store.update(
+            batch_id,
+            Table::TransferBatch,
+            vec![Column::StartHeight],
+            vec![&(height as i64)],
+        )
+    }
+
+    fn get_transfer_batch_start_height(&self, batch_id: &Uuid) -> Result<Option<u64>> {
+        let store = store_or_pg!(self, get_transfer_batch_start_height(batch_id));
+        let height = store.get_1::<Option<i64>>(
+            *batch_id,
+            Table::TransferBatch,
+            vec![Column::StartHeight],
+        )?;
+        Ok(height.map(|h| h as u64))
+    }
+
+    fn get_batch_transfer_statechain_ids(&self, batch_id: &Uuid) -> Result<HashSet<Uuid>> {
+        let store = store_or_pg!(self, get_batch_transfer_statechain_ids(batch_id));
+        PGDatabase::deser(store.get_1(*batch_id, Table::TransferBatch, vec![Column::StateChains])?)
+    }
+
+    fn get_finalize_batch_data(&self, batch_id: Uuid) -> Result<TransferFinalizeBatchData> {
+        let mut finalized_data_vec = vec![];
+        for id in self.get_batch_transfer_statechain_ids(&batch_id)? {
+            let finalized_data = self.get_sc_transfer_finalize_data(&id)?;
+            match finalized_data.batch_data {
+                Some(ref bd) if bd.id == batch_id => finalized_data_vec.push(finalized_data),
+                Some(ref bd) => {
+                    return Err(SEError::DBError(
+                        NoDataForID,
+                        format!("batch_id required:{}, found:{}", batch_id, bd.id),
+                    ))
+                }
+                None => {
+                    return Err(SEError::DBError(NoDataForID, format!("no batch data")))
+                }
+            }
+        }
+        Ok(TransferFinalizeBatchData {
+            finalized_data_vec,
+            start_time: self.get_transfer_batch_start_time(&batch_id)?,
+        })
+    }
+
+    fn update_finalize_batch_data(
+        &self,
+        statechain_id: &Uuid,
+        finalized_data: &TransferFinalizeData,
+        version: i64,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            update_finalize_batch_data(statechain_id, finalized_data, version)
+        );
+        store.update_versioned(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::TransferFinalizeData],
+            vec![&self.pg.ser_enc(
+                statechain_id,
+                &Table::StateChain,
+                Column::TransferFinalizeData,
+                finalized_data,
+            )?],
+            version,
+        )
+    }
+
+    fn get_sc_transfer_finalize_data(&self, statechain_id: &Uuid) -> Result<TransferFinalizeData> {
+        let store = store_or_pg!(self, get_sc_transfer_finalize_data(statechain_id));
+        let tfd = store.get_1(*statechain_id, Table::StateChain, vec![Column::TransferFinalizeData])?;
+        self.pg
+            .deser_enc(statechain_id, &Table::StateChain, Column::TransferFinalizeData, tfd)
+    }
+
+    fn get_transfer_batch_version(&self, batch_id: &Uuid) -> Result<i64> {
+        let store = store_or_pg!(self, get_transfer_batch_version(batch_id));
+        store.get_version(batch_id, Table::TransferBatch)
+    }
+
+    fn update_transfer_batch_finalized(
+        &self,
+        batch_id: &Uuid,
+        b_finalized: &bool,
+        version: i64,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            update_transfer_batch_finalized(batch_id, b_finalized, version)
+        );
+        store.update_versioned(
+            batch_id,
+            Table::TransferBatch,
+            vec![Column::Finalized],
+            vec![b_finalized],
+            version,
+        )
+    }
+
+    fn get_statechain_owner(&self, statechain_id: Uuid) -> Result<StateChainOwner> {
+        let store = store_or_pg!(self, get_statechain_owner(statechain_id));
+        let (locked_until, owner_id, state_chain_str) = store
+            .get_3::<NaiveDateTime, Uuid, String>(
+                statechain_id,
+                Table::StateChain,
+                vec![Column::LockedUntil, Column::OwnerId, Column::Chain],
+            )?;
+        Ok(StateChainOwner {
+            locked_until,
+            owner_id,
+            chain: PGDatabase::deser::<StateChainUnchecked>(state_chain_str)?.try_into()?,
+        })
+    }
+
+    // find statecoin and user information from supplied proof key to enable wallet recovery
+    fn get_recovery_data(&self, proofkey: String) -> Result<Vec<(Uuid, Uuid, Transaction)>> {
+        let store = store_or_pg!(self, get_recovery_data(proofkey));
+        let items =
+            store.query_proof_key(Table::UserSession, &proofkey, vec![Column::StateChainId])?;
+        if items.is_empty() {
+            return Err(SEError::DBError(NoDataForID, String::from("Proof key")));
+        };
+        let mut rc_vec = vec![];
+        for item in items {
+            let user_id = item_id(&item)?;
+            let sid = match item_value::<Option<Uuid>>(&item, &user_id, Column::StateChainId)? {
+                Some(sid) => sid,
+                None => return Err(SEError::DBError(NoDataForID, String::from("Proof key"))),
+            };
+            let owner_id = store.get_1::<Uuid>(sid, Table::StateChain, vec![Column::OwnerId])?;
+            if owner_id == user_id {
+                let tx_backup_str =
+                    store.get_1::<String>(sid, Table::BackupTxs, vec![Column::TxBackup])?;
+                rc_vec.push((user_id, sid, PGDatabase::deser(tx_backup_str)?))
+            }
+        }
+        Ok(rc_vec)
+    }
+
+    // Create DB entry for newly generated ID signalling that user has passed some
+    // verification. For now use ID as 'password' to interact with state entity
+    fn create_user_session(
+        &self,
+        user_id: &Uuid,
+        auth: &String,
+        proof_key: &String,
+        challenge: &String,
+        user_ids: Arc<Mutex<UserIDs>>,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            create_user_session(user_id, auth, proof_key, challenge, user_ids)
+        );
+        let mut guard = user_ids.as_ref().lock()?;
+        guard.insert(user_id.to_owned());
+        store.insert(user_id, Table::UserSession).map_err(|e| {
+            guard.remove(user_id);
+            e
+        })?;
+        self.pg.insert(user_id, Table::Lockbox).map_err(|e| {
+            guard.remove(user_id);
+            let _ = store.remove(user_id, Table::UserSession);
+            e
+        })?;
+        store
+            .update(
+                user_id,
+                Table::UserSession,
+                vec![
+                    Column::Authentication,
+                    Column::ProofKey,
+                    Column::Challenge,
+                    Column::SessionState,
+                ],
+                vec![
+                    auth,
+                    proof_key,
+                    challenge,
+                    &PGDatabase::ser(SessionState::DepositInit)?,
+                ],
+            )
+            .map_err(|e| {
+                guard.remove(user_id);
+                let _ = store.remove(user_id, Table::UserSession);
+                let _ = self.pg.remove(user_id, Table::Lockbox);
+                e
+            })
+    }
+
+    // Create new UserSession to allow new owner to generate shared wallet
+    fn transfer_init_user_session(
+        &self,
+        new_user_id: &Uuid,
+        statechain_id: &Uuid,
+        finalized_data: TransferFinalizeData,
+        user_ids: Arc<Mutex<UserIDs>>,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            transfer_init_user_session(new_user_id, statechain_id, finalized_data, user_ids)
+        );
+        let mut guard = user_ids.as_ref().lock()?;
+        guard.insert(new_user_id.clone());
+        store.insert(new_user_id, Table::UserSession).map_err(|e| {
+            guard.remove(new_user_id);
+            e
+        })?;
+        self.pg.insert(new_user_id, Table::Lockbox).map_err(|e| {
+            guard.remove(new_user_id);
+            let _ = store.remove(new_user_id, Table::UserSession);
+            e
+        })?;
+        store
+            .update(
+                new_user_id,
+                Table::UserSession,
+                vec![
+                    Column::Authentication,
+                    Column::ProofKey,
+                    Column::TxBackup,
+                    Column::StateChainId,
+                    Column::S2,
+                    Column::SessionState,
+                ],
+                vec![
+                    &String::from("auth"),
+                    &finalized_data.statechain_sig.data,
+                    &PGDatabase::ser(transaction_deserialise(&finalized_data.new_tx_backup_hex)?)?,
+                    statechain_id,
+                    &self.pg.ser_enc(new_user_id, &Table::UserSession, Column::S2, finalized_data.s2)?,
+                    &PGDatabase::ser(SessionState::TransferInit)?,
+                ],
+            )
+            .map_err(|e| {
+                guard.remove(new_user_id);
+                let _ = store.remove(new_user_id, Table::UserSession);
+                let _ = self.pg.remove(new_user_id, Table::Lockbox);
+                e
+            })
+    }
+
+    fn update_ecdsa_sign_first(
+        &self,
+        user_id: Uuid,
+        eph_key_gen_first_message_party_two: party_two::EphKeyGenFirstMsg,
+        eph_ec_key_pair_party1: party_one::EphEcKeyPair,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            update_ecdsa_sign_first(
+                user_id,
+                eph_key_gen_first_message_party_two,
+                eph_ec_key_pair_party1
+            )
+        );
+        store.update(
+            &user_id,
+            Table::Ecdsa,
+            vec![Column::EphKeyGenFirstMsg, Column::EphEcKeyPair],
+            vec![
+                &PGDatabase::ser(eph_key_gen_first_message_party_two)?,
+                &self.pg.ser_enc(&user_id, &Table::Ecdsa, Column::EphEcKeyPair, eph_ec_key_pair_party1)?,
+            ],
+        )
+    }
+
+    fn get_ecdsa_sign_second_input(&self, user_id: Uuid) -> Result<ECDSASignSecondInput> {
+        let store = store_or_pg!(self, get_ecdsa_sign_second_input(user_id));
+        let (shared_key_str, eph_ec_key_pair_party1_str, eph_key_gen_first_message_party_two_str) =
+            store.get_3::<String, String, String>(
+                user_id,
+                Table::Ecdsa,
+                vec![
+                    Column::Party1MasterKey,
+                    Column::EphEcKeyPair,
+                    Column::EphKeyGenFirstMsg,
+                ],
+            )?;
+        Ok(ECDSASignSecondInput {
+            shared_key: self.pg.deser_enc(
+                &user_id,
+                &Table::Ecdsa,
+                Column::Party1MasterKey,
+                shared_key_str,
+            )?,
+            eph_ec_key_pair_party1: self.pg.deser_enc(
+                &user_id,
+                &Table::Ecdsa,
+                Column::EphEcKeyPair,
+                eph_ec_key_pair_party1_str,
+            )?,
+            eph_key_gen_first_message_party_two: PGDatabase::deser(
+                eph_key_gen_first_message_party_two_str,
+            )?,
+        })
+    }
+
+    fn remove_ecdsa(&self, user_id: &Uuid) -> Result<()> {
+        let store = store_or_pg!(self, remove_ecdsa(user_id));
+        store.remove(user_id, Table::Ecdsa)
+    }
+
+    fn get_swap_fee_owed(&self, statechain_id: &Uuid) -> Result<u64> {
+        let store = store_or_pg!(self, get_swap_fee_owed(statechain_id));
+        // Deductions are owed once the swap batch transfer is finalized
+        let mut owed = 0;
+        for (swap_id, fee) in self.get_swap_fees_uncollected(statechain_id)? {
+            if self.is_transfer_batch_finalized(store, &swap_id)? {
+                owed += fee as u64;
+            }
+        }
+        Ok(owed)
+    }
+
+    fn update_swap_fee_collected(&self, statechain_id: &Uuid) -> Result<()> {
+        let store = store_or_pg!(self, update_swap_fee_collected(statechain_id));
+        let mut swap_ids = vec![];
+        for (swap_id, _) in self.get_swap_fees_uncollected(statechain_id)? {
+            if self.is_transfer_batch_finalized(store, &swap_id)? {
+                swap_ids.push(swap_id);
+            }
+        }
+        let dbw = self.pg.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "UPDATE {} SET collected = true
+            WHERE statechainid = $1 AND swapid = ANY($2) AND txid IS NULL AND NOT collected",
+            Table::SwapFee.to_string(),
+        ))?;
+        statement.execute(&[statechain_id, &swap_ids])?;
+        Ok(())
+    }
+
+    fn insert_spend_incident(&self, incident: &SpendIncident) -> Result<()> {
+        let store = store_or_pg!(self, insert_spend_incident(incident));
+        let dbw = self.pg.database_w()?;
+        let statement = dbw.prepare(&format!(
+            "INSERT INTO {} (id, funding, height, detectedat, responsetxid)
+            VALUES ($1,$2,$3,$4,$5)
+            ON CONFLICT (id) DO NOTHING",
+            Table::SpendIncident.to_string()
+        ))?;
+        let inserted = statement.execute(&[
+            &incident.statechain_id,
+            &incident.funding.to_string(),
+            &(incident.height as i64),
+            &incident.detected_at,
+            &incident.response_txid,
+        ])?;
+        if inserted == 0 {
+            return Ok(());
+        }
+        // Reported to clients with the statechain updates
+        let (updated, updated_root) = self.statechain_updated()?;
+        store.update(
+            &incident.statechain_id,
+            Table::StateChain,
+            vec![Column::Updated, Column::UpdatedRoot],
+            vec![&updated, &updated_root],
+        )
+    }
+
+    fn get_statechains_updated_since(
+        &self,
+        since: &UpdatesSince,
+        limit: i64,
+    ) -> Result<Vec<(Uuid, NaiveDateTime)>> {
+        let store = store_or_pg!(self, get_statechains_updated_since(since, limit));
+        let mut result = vec![];
+        let items = store.scan(&Table::StateChain, vec![Column::Updated, Column::UpdatedRoot])?;
+        for item in items {
+            let id = item_id(&item)?;
+            let updated = match item_value::<Option<NaiveDateTime>>(&item, &id, Column::Updated)? {
+                Some(updated) => updated,
+                None => continue,
+            };
+            let include = match since {
+                UpdatesSince::RootId(root_id) => {
+                    item_value::<Option<i64>>(&item, &id, Column::UpdatedRoot)?
+                        .map_or(false, |updated_root| updated_root >= *root_id)
+                }
+                UpdatesSince::Time(time) => updated >= *time,
+            };
+            if include {
+                result.push((id, updated));
+            }
+        }
+        result.sort_by_key(|(_, updated)| *updated);
+        result.truncate(limit.max(0) as usize);
+        Ok(result)
+    }
+
+    fn archive_statechains(
+        &self,
+        updated_before: &NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<(Uuid, i64)>> {
+        let store = store_or_pg!(self, archive_statechains(updated_before, limit));
+        let column = vec![
+            Column::Amount,
+            Column::Confirmed,
+            Column::Updated,
+            Column::Version,
+        ];
+        let mut candidates = vec![];
+        for item in store.scan(&Table::StateChain, column)? {
+            let id = item_id(&item)?;
+            match item_value::<Option<NaiveDateTime>>(&item, &id, Column::Updated)? {
+                Some(updated) if updated < *updated_before => candidates.push((updated, item)),
+                _ => (),
+            }
+        }
+        candidates.sort_by_key(|(updated, _)| *updated);
+
+        let mut archived = vec![];
+        for (_, item) in candidates {
+            if archived.len() as i64 >= limit {
+                break;
+            }
+            let id = item_id(&item)?;
+            let amount = item_value::<i64>(&item, &id, Column::Amount)?;
+            let confirmed = item_value::<bool>(&item, &id, Column::Confirmed)?;
+            let version = item_value::<i64>(&item, &id, Column::Version)?;
+            let tx_backup = match store.get_1::<Option<String>>(id, Table::BackupTxs, vec![Column::TxBackup]) {
+                Ok(tx_backup) => Some(tx_backup),
+                Err(SEError::DBError(NoDataForID, _)) => None,
+                Err(e) => return Err(e),
+            };
+            // Withdrawn statechains have amount 0. Confirmed statechains without a backup tx
+            // have had their backup tx confirmed and removed by the watcher.
+            let reason = match (amount, confirmed && tx_backup.is_none()) {
+                (0, _) => "withdrawn",
+                (_, true) => "expired",
+                _ => continue,
+            };
+            let (chain, owner_id, shared_public, updated) = store
+                .get_4::<String, Option<Uuid>, Option<String>, Option<NaiveDateTime>>(
+                    id,
+                    Table::StateChain,
+                    vec![
+                        Column::Chain,
+                        Column::OwnerId,
+                        Column::SharedPublic,
+                        Column::Updated,
+                    ],
+                )?;
+            // Written before the statechain is removed, and replaced if the statechain is
+            // archived again after a concurrent update
+            let dbw = self.pg.database_w()?;
+            let statement = dbw.prepare(&format!(
+                "INSERT INTO {} (id, chain, amount, ownerid, sharedpublic, confirmed, txbackup, updated, reason)
+                VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9)
+                ON CONFLICT (id) DO UPDATE
+                SET chain = EXCLUDED.chain, amount = EXCLUDED.amount, ownerid = EXCLUDED.ownerid,
+                sharedpublic = EXCLUDED.sharedpublic, confirmed = EXCLUDED.confirmed,
+                txbackup = EXCLUDED.txbackup, updated = EXCLUDED.updated, reason = EXCLUDED.reason",
+                Table::StateChainArchive.to_string(),
+            ))?;
+            statement.execute(&[
+                &id,
+                &chain,
+                &amount,
+                &owner_id,
+                &shared_public,
+                &confirmed,
+                &tx_backup.clone().flatten(),
+                &updated,
+                &reason,
+            ])?;
+            // Skipped if updated since it was read
+            if !store.remove_where(&id, Table::StateChain, vec![Column::Version], vec![&version])? {
+                let statement = dbw.prepare(&format!(
+                    "DELETE FROM {} WHERE id = $1",
+                    Table::StateChainArchive.to_string(),
+                ))?;
+                statement.execute(&[&id])?;
+                continue;
+            }
+            if tx_backup.is_some() {
+                let _ = store.remove_where(&id, Table::BackupTxs, vec![], vec![])?;
+            }
+            archived.push((id, amount));
+        }
+        Ok(archived)
+    }
+
+    fn remove_orphaned_sessions(
+        &self,
+        state: &SessionState,
+        updated_before: &NaiveDateTime,
+        limit: i64,
+    ) -> Result<(Vec<Uuid>, u64)> {
+        let store = store_or_pg!(self, remove_orphaned_sessions(state, updated_before, limit));
+        let state_str = PGDatabase::ser(state)?;
+        let column = vec![
+            Column::SessionState,
+            Column::StateUpdated,
+            Column::StateChainId,
+        ];
+        let mut candidates = vec![];
+        for item in store.scan(&Table::UserSession, column)? {
+            let id = item_id(&item)?;
+            // Sessions with a statechain are never orphaned
+            if item_value::<Option<Uuid>>(&item, &id, Column::StateChainId)?.is_some()
+                || item_value::<Option<String>>(&item, &id, Column::SessionState)?.as_ref()
+                    != Some(&state_str)
+            {
+                continue;
+            }
+            match item_value::<Option<NaiveDateTime>>(&item, &id, Column::StateUpdated)? {
+                Some(state_updated) if state_updated < *updated_before => {
+                    candidates.push((state_updated, id))
+                }
+                _ => (),
+            }
+        }
+        candidates.sort();
+        candidates.truncate(limit.max(0) as usize);
+
+        let mut user_ids = vec![];
+        let mut related = 0;
+        for (state_updated, user_id) in candidates {
+            // Skipped if the session has moved on since it was read
+            let no_statechain: Option<Uuid> = None;
+            if !store.remove_where(
+                &user_id,
+                Table::UserSession,
+                vec![Column::SessionState, Column::StateUpdated, Column::StateChainId],
+                vec![&state_str, &state_updated, &no_statechain],
+            )? {
+                continue;
+            }
+            if store.remove_where(&user_id, Table::Ecdsa, vec![], vec![])? {
+                related += 1;
+            }
+            user_ids.push(user_id);
+        }
+        let dbw = self.pg.database_w()?;
+        for table in &[
+            Table::Lockbox,
+            Table::DepositHandoff,
+            Table::FeeAcknowledgement,
+        ] {
+            related += dbw.execute(
+                &format!("DELETE FROM {} WHERE id = ANY($1)", table.to_string()),
+                &[&user_ids],
+            )?;
+        }
+        Ok((user_ids, related))
+    }
+
+    fn get_tx_withdraw(&self, user_id: Uuid) -> Result<Transaction> {
+        let store = store_or_pg!(self, get_tx_withdraw(user_id));
+        PGDatabase::deser(store.get_1(user_id, Table::UserSession, vec![Column::TxWithdraw])?)
+    }
+
+    fn update_tx_withdraw(&self, user_id: Uuid, tx: Transaction) -> Result<()> {
+        let store = store_or_pg!(self, update_tx_withdraw(user_id, tx));
+        store.update(
+            &user_id,
+            Table::UserSession,
+            vec![Column::TxWithdraw],
+            vec![&PGDatabase::ser(tx)?],
+        )
+    }
+
+    // Tables stored in Postgres
+
+    fn get_lockbox_index(&self, user_id: &Uuid) -> Result<Option<usize>> {
+        self.pg.get_lockbox_index(user_id)
+    }
+
+    fn update_lockbox_index(&self, user_id: &Uuid, lockbox_index: &usize) -> Result<()> {
+        self.pg.update_lockbox_index(user_id, lockbox_index)
+    }
+
+    fn root_update(&self, rt: &Root) -> Result<i64> {
+        self.pg.root_update(rt)
+    }
+
+    fn root_insert(&self, root: Root) -> Result<u64> {
+        self.pg.root_insert(root)
+    }
+
+    fn root_get_current_id(&self) -> Result<i64> {
+        self.pg.root_get_current_id()
+    }
+
+    fn get_root(&self, id: i64) -> Result<Option<Root>> {
+        self.pg.get_root(id)
+    }
+
+    fn get_root_info(&self, id: i64) -> Result<Option<Root>> {
+        self.pg.get_root_info(id)
+    }
+
+    fn get_confirmed_smt_root(&self) -> Result<Option<Root>> {
+        self.pg.get_confirmed_smt_root()
+    }
+
+    fn insert_sighash_record(&self, record: &SighashRecord) -> Result<()> {
+        self.pg.insert_sighash_record(record)
+    }
+
+    fn get_sighash_record(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<SighashRecord> {
+        self.pg.get_sighash_record(user_id, sig_hash)
+    }
+
+    fn get_signed_sighashes(&self, outpoint: &OutPoint) -> Result<Vec<SighashRecord>> {
+        self.pg.get_signed_sighashes(outpoint)
+    }
+
+    fn set_sighash_signed(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<()> {
+        self.pg.set_sighash_signed(user_id, sig_hash)
+    }
+
+    fn set_sighash_finalized(&self, user_id: &Uuid, sig_hash: &sha256d::Hash) -> Result<()> {
+        self.pg.set_sighash_finalized(user_id, sig_hash)
+    }
+
+    fn insert_api_key(&self, api_key: &ApiKey) -> Result<()> {
+        self.pg.insert_api_key(api_key)
+    }
+
+    fn get_api_key(&self, key_hash: &String) -> Result<ApiKey> {
+        self.pg.get_api_key(key_hash)
+    }
+
+    fn revoke_api_key(&self, key_hash: &String) -> Result<()> {
+        self.pg.revoke_api_key(key_hash)
+    }
+
+    fn purge_archived_statechains(&self, archived_before: &NaiveDateTime) -> Result<u64> {
+        self.pg.purge_archived_statechains(archived_before)
+    }
+
+    fn insert_deletion_receipt(&self, receipt: &SignedDeletionReceipt) -> Result<()> {
+        self.pg.insert_deletion_receipt(receipt)
+    }
+
+    fn get_deletion_receipts(&self, statechain_id: &Uuid) -> Result<Vec<SignedDeletionReceipt>> {
+        self.pg.get_deletion_receipts(statechain_id)
+    }
+
+    fn insert_transfer_receipt(
+        &self,
+        user_id: &Uuid,
+        receipt: &SignedTransferReceipt,
+    ) -> Result<()> {
+        self.pg.insert_transfer_receipt(user_id, receipt)
+    }
+
+    fn get_transfer_receipts(&self, statechain_id: &Uuid) -> Result<Vec<SignedTransferReceipt>> {
+        self.pg.get_transfer_receipts(statechain_id)
+    }
+
+    fn get_transfer_reject(&self, statechain_id: &Uuid) -> Result<Option<TransferRejectAPI>> {
+        self.pg.get_transfer_reject(statechain_id)
+    }
+
+    fn update_transfer_reject(&self, reject: &TransferRejectAPI) -> Result<()> {
+        self.pg.update_transfer_reject(reject)
+    }
+
+    fn get_sale(&self, statechain_id: &Uuid) -> Result<Option<SaleRecord>> {
+        self.pg.get_sale(statechain_id)
+    }
+
+    fn update_sale(&self, sale: &SaleRecord) -> Result<()> {
+        self.pg.update_sale(sale)
+    }
+
+    fn get_inheritance(&self, statechain_id: &Uuid) -> Result<Option<InheritanceRecord>> {
+        self.pg.get_inheritance(statechain_id)
+    }
+
+    fn update_inheritance(&self, inheritance: &InheritanceRecord) -> Result<()> {
+        self.pg.update_inheritance(inheritance)
+    }
+
+    fn remove_inheritance(&self, statechain_id: &Uuid) -> Result<()> {
+        self.pg.remove_inheritance(statechain_id)
+    }
+
+    fn get_second_factor(&self, statechain_id: &Uuid) -> Result<Option<SecondFactorRecord>> {
+        self.pg.get_second_factor(statechain_id)
+    }
+
+    fn update_second_factor(&self, second_factor: &SecondFactorRecord) -> Result<()> {
+        self.pg.update_second_factor(second_factor)
+    }
+
+    fn get_vault(&self, statechain_id: &Uuid) -> Result<Option<VaultRecord>> {
+        self.pg.get_vault(statechain_id)
+    }
+
+    fn update_vault(&self, vault: &VaultRecord) -> Result<()> {
+        self.pg.update_vault(vault)
+    }
+
+    fn insert_swap_fee(
+        &self,
+        swap_id: &Uuid,
+        statechain_id: &Uuid,
+        fee: u64,
+        txid: &Option<String>,
+    ) -> Result<()> {
+        self.pg.insert_swap_fee(swap_id, statechain_id, fee, txid)
+    }
+
+    fn get_swap_fee_by_txid(&self, txid: &String) -> Result<Option<(Uuid, Uuid)>> {
+        self.pg.get_swap_fee_by_txid(txid)
+    }
+
+    fn insert_swap_report(&self, report: &SwapReport) -> Result<()> {
+        self.pg.insert_swap_report(report)
+    }
+
+    fn get_swap_report(&self, swap_id: &Uuid) -> Result<Option<SwapReport>> {
+        self.pg.get_swap_report(swap_id)
+    }
+
+    fn update_spend_incident_response(
+        &self,
+        statechain_id: &Uuid,
+        response_txid: &String,
+    ) -> Result<()> {
+        let store = store_or_pg!(
+            self,
+            update_spend_incident_response(statechain_id, response_txid)
+        );
+        self.pg.update(
+            statechain_id,
+            Table::SpendIncident,
+            vec![Column::ResponseTxId],
+            vec![response_txid],
+        )?;
+        // Reported to clients with the statechain updates
+        let (updated, updated_root) = self.statechain_updated()?;
+        store.update(
+            statechain_id,
+            Table::StateChain,
+            vec![Column::Updated, Column::UpdatedRoot],
+            vec![&updated, &updated_root],
+        )
+    }
+
+    fn get_spend_incident(&self, statechain_id: &Uuid) -> Result<Option<SpendIncident>> {
+        self.pg.get_spend_incident(statechain_id)
+    }
+
+    fn insert_backup_tx_history(&self, statechain_id: &Uuid, tx: &Transaction) -> Result<()> {
+        self.pg.insert_backup_tx_history(statechain_id, tx)
+    }
+
+    fn get_backup_tx_history(&self, statechain_id: &Uuid) -> Result<Vec<Transaction>> {
+        self.pg.get_backup_tx_history(statechain_id)
+    }
+
+    fn insert_root_leaves(&self, root_id: i64, leaves: &[(String, String)]) -> Result<()> {
+        self.pg.insert_root_leaves(root_id, leaves)
+    }
+
+    fn get_root_leaves(&self, funding_txid: &String) -> Result<Vec<(i64, String)>> {
+        self.pg.get_root_leaves(funding_txid)
+    }
+
+    fn is_root_confirmed(&self, id: i64) -> Result<bool> {
+        self.pg.is_root_confirmed(id)
+    }
+
+    fn get_watchtower_registration(&self, id: &Uuid) -> Result<Option<WatchtowerRecord>> {
+        self.pg.get_watchtower_registration(id)
+    }
+
+    fn update_watchtower_registration(&self, record: &WatchtowerRecord) -> Result<()> {
+        self.pg.update_watchtower_registration(record)
+    }
+
+    fn count_watchtower_registrations(&self, auth_key: &String) -> Result<u64> {
+        self.pg.count_watchtower_registrations(auth_key)
+    }
+
+    fn get_due_watchtower_txs(&self, height: i64) -> Result<Vec<WatchtowerRecord>> {
+        self.pg.get_due_watchtower_txs(height)
+    }
+
+    fn remove_watchtower_tx(&self, id: &Uuid) -> Result<()> {
+        self.pg.remove_watchtower_tx(id)
+    }
+
+    fn insert_fee(
+        &self,
+        txid: &String,
+        fee_type: &FeeType,
+        statechain_id: &Uuid,
+        amount: u64,
+    ) -> Result<()> {
+        self.pg.insert_fee(txid, fee_type, statechain_id, amount)
+    }
+
+    fn get_fees(&self, from: &NaiveDateTime, to: &NaiveDateTime) -> Result<Vec<FeeRecord>> {
+        self.pg.get_fees(from, to)
+    }
+
+    fn insert_chain_segment(&self, statechain_id: &Uuid, segment: &ArchivedSegment) -> Result<()> {
+        self.pg.insert_chain_segment(statechain_id, segment)
+    }
+
+    fn get_chain_segments(&self, statechain_id: &Uuid) -> Result<Vec<ArchivedSegment>> {
+        self.pg.get_chain_segments(statechain_id)
+    }
+
+    fn get_punishment(&self, id: &String) -> Result<Option<PunishmentRecord>> {
+        self.pg.get_punishment(id)
+    }
+
+    fn update_punishment(&self, record: &PunishmentRecord) -> Result<()> {
+        self.pg.update_punishment(record)
+    }
+
+    fn remove_expired_punishments(
+        &self,
+        locked_before: &NaiveDateTime,
+        offence_before: &NaiveDateTime,
+    ) -> Result<u64> {
+        self.pg.remove_expired_punishments(locked_before, offence_before)
+    }
+
+    fn create_deposit_handoff(
+        &self,
+        user_id: &Uuid,
+        proof_key: &String,
+        payer_proof_key: &String,
+    ) -> Result<()> {
+        self.pg.create_deposit_handoff(user_id, proof_key, payer_proof_key)
+    }
+
+    fn get_deposit_handoff_proof_key(&self, user_id: &Uuid) -> Result<Option<String>> {
+        self.pg.get_deposit_handoff_proof_key(user_id)
+    }
+
+    fn get_deposit_payer_proof_key(&self, user_id: &Uuid) -> Result<Option<String>> {
+        self.pg.get_deposit_payer_proof_key(user_id)
+    }
+
+    fn update_deposit_handoff(&self, msg: &DepositHandoffMsg) -> Result<()> {
+        self.pg.update_deposit_handoff(msg)
+    }
+
+    fn get_deposit_handoffs(&self, proof_key: &String) -> Result<Vec<DepositHandoffMsg>> {
+        self.pg.get_deposit_handoffs(proof_key)
+    }
+
+    fn insert_fee_acknowledgement(
+        &self,
+        user_id: &Uuid,
+        quote_hash: &String,
+        quote: &StateEntityFeeInfoAPI,
+    ) -> Result<()> {
+        self.pg.insert_fee_acknowledgement(user_id, quote_hash, quote)
+    }
+
+    fn get_fee_acknowledgement(&self, user_id: &Uuid) -> Result<Option<StateEntityFeeInfoAPI>> {
+        self.pg.get_fee_acknowledgement(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DBErrorType::ConnectionFailed;
+    use mockito::{Matcher, Mock};
+    use serde_json::json;
+    use shared_lib::structs::{FESer, PrepareSignTxMsg, SCEAddress};
+
+    /// Store using the mock server as its DynamoDB endpoint
+    fn mock_store() -> DynamoStore {
+        std::env::set_var("AWS_ACCESS_KEY_ID", "test");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "test");
+        DynamoStore::new(&DynamoDbConfig {
+            enabled: true,
+            region: String::from("us-east-1"),
+            endpoint: Some(mockito::server_url()),
+            table_prefix: String::from("mercury_"),
+        })
+        .unwrap()
+    }
+
+    /// Mock response to a DynamoDB operation
+    fn mock_op(op: &str, status: usize, body: serde_json::Value) -> Mock {
+        let target = format!("DynamoDB_20120810.{}", op);
+        mockito::mock("POST", "/")
+            .match_header("x-amz-target", target.as_str())
+            .with_status(status)
+            .with_header("content-type", "application/x-amz-json-1.0")
+            .with_body(body.to_string())
+    }
+
+    /// Mock failure of the condition of a DynamoDB operation
+    fn mock_conditional_check_failed(op: &str) -> Mock {
+        mock_op(
+            op,
+            400,
+            json!({
+                "__type": "com.amazonaws.dynamodb.v20120810#ConditionalCheckFailedException",
+                "message": "The conditional request failed"
+            }),
+        )
+    }
+
+    const RECEIVE_ADDR: &str =
+        "026cc37050561379a66a863f8ca273c2b29e935cad06bc7f5e6b83a03e0bbff1e6";
+
+    fn partial_json(value: serde_json::Value) -> Matcher {
+        Matcher::PartialJsonString(value.to_string())
+    }
+
+    fn transfer_msg(receive_addr: &str) -> TransferMsg3 {
+        TransferMsg3 {
+            shared_key_id: Uuid::new_v4(),
+            t1: FESer {
+                secret_bytes: vec![1; 32],
+            },
+            statechain_sig: StateChainSig::default(),
+            statechain_id: Uuid::new_v4(),
+            tx_backup_psm: PrepareSignTxMsg::default(),
+            rec_se_addr: SCEAddress {
+                tx_backup_addr: None,
+                proof_key: bitcoin::secp256k1::PublicKey::from_str(receive_addr).unwrap(),
+                enc_key: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_insert_conflict() {
+        let store = mock_store();
+        let id = Uuid::new_v4();
+        // New items have the Postgres column defaults
+        let put = mock_op("PutItem", 200, json!({}))
+            .match_body(partial_json(json!({
+                "TableName": "mercury_statechain",
+                "ConditionExpression": "attribute_not_exists(#id)",
+                "Item": {
+                    "id": {"S": id.to_string()},
+                    "confirmed": {"BOOL": false},
+                    "version": {"N": "0"}
+                }
+            })))
+            .create();
+        assert_eq!(store.insert(&id, Table::StateChain).unwrap(), 1);
+        put.assert();
+        drop(put);
+
+        let _put = mock_conditional_check_failed("PutItem").create();
+        match store.insert(&id, Table::StateChain) {
+            Err(SEError::Generic(e)) => assert!(e.contains("already exists")),
+            res => panic!("expected already exists error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_update_conflicts() {
+        let store = mock_store();
+        let id = Uuid::new_v4();
+
+        // Update of a missing item
+        let update = mock_conditional_check_failed("UpdateItem")
+            .match_body(partial_json(json!({
+                "ConditionExpression": "attribute_exists(#id)"
+            })))
+            .create();
+        match store.update(&id, Table::StateChain, vec![Column::Amount], vec![&10i64]) {
+            Err(SEError::DBError(UpdateFailed, e)) => assert_eq!(e, id.to_string()),
+            res => panic!("expected UpdateFailed, got {:?}", res),
+        }
+        drop(update);
+
+        // Versioned update of an item updated by another request
+        let update = mock_conditional_check_failed("UpdateItem")
+            .match_body(partial_json(json!({
+                "ConditionExpression": "#version = :version",
+                "ExpressionAttributeValues": {":version": {"N": "1"}}
+            })))
+            .create();
+        let get = mock_op(
+            "GetItem",
+            200,
+            json!({"Item": {"id": {"S": id.to_string()}, "version": {"N": "2"}}}),
+        )
+        .create();
+        match store.update_versioned(&id, Table::StateChain, vec![Column::Amount], vec![&10i64], 1)
+        {
+            Err(SEError::TryAgain(_)) => (),
+            res => panic!("expected TryAgain, got {:?}", res),
+        }
+        drop(get);
+
+        // Versioned update of a missing item
+        let _get = mock_op("GetItem", 200, json!({})).create();
+        match store.update_versioned(&id, Table::StateChain, vec![Column::Amount], vec![&10i64], 1)
+        {
+            Err(SEError::DBError(NoDataForID, e)) => assert_eq!(e, id.to_string()),
+            res => panic!("expected NoDataForID, got {:?}", res),
+        }
+        drop(update);
+
+        // Versioned update at the current version
+        let _update = mock_op("UpdateItem", 200, json!({})).create();
+        assert!(store
+            .update_versioned(&id, Table::StateChain, vec![Column::Amount], vec![&10i64], 2)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_get_transfer_msg_addr() {
+        let receive_addr = RECEIVE_ADDR;
+        let msgs = vec![transfer_msg(receive_addr), transfer_msg(receive_addr)];
+        let item = |msg: &TransferMsg3| {
+            json!({
+                "id": {"S": msg.statechain_id.to_string()},
+                "transfermsg": {"S": PGDatabase::ser(msg).unwrap()}
+            })
+        };
+        let last_key = json!({"id": {"S": msgs[0].statechain_id.to_string()}});
+
+        // Two pages of a query of the proof key index. The second continues from the first.
+        let page_1 = mock_op(
+            "Query",
+            200,
+            json!({"Items": [item(&msgs[0])], "LastEvaluatedKey": last_key.clone()}),
+        )
+        .match_body(partial_json(json!({
+            "TableName": "mercury_transfer",
+            "IndexName": PROOF_KEY_INDEX,
+            "ExpressionAttributeValues": {":pk": {"S": receive_addr}}
+        })))
+        .expect(1)
+        .create();
+        let page_2 = mock_op("Query", 200, json!({"Items": [item(&msgs[1])]}))
+            .match_body(partial_json(json!({"ExclusiveStartKey": last_key})))
+            .expect(1)
+            .create();
+
+        // Postgres is not connected: the transfers are read from DynamoDB only
+        let db = DynamoDatabase {
+            pg: PGDatabase::get_new(),
+            dynamo: Some(mock_store()),
+        };
+        assert_eq!(db.get_transfer_msg_addr(receive_addr).unwrap(), msgs);
+        page_1.assert();
+        page_2.assert();
+
+        // Tables not stored in DynamoDB are read from Postgres
+        match db.get_spend_incident(&msgs[0].statechain_id) {
+            Err(SEError::DBError(ConnectionFailed, _)) => (),
+            res => panic!("expected ConnectionFailed, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_store_or_pg_fallback() {
+        // Without a DynamoDB store the core tables are read from Postgres
+        let db = DynamoDatabase::get_new();
+        match db.get_transfer_msg_addr(RECEIVE_ADDR) {
+            Err(SEError::DBError(ConnectionFailed, _)) => (),
+            res => panic!("expected ConnectionFailed, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_attribute_round_trip() {
+        let id = Uuid::new_v4();
+        let time = get_time_now();
+        let mut item = key(&id);
+        item.insert(String::from("amount"), 10i64.to_attribute().unwrap());
+        item.insert(String::from("confirmed"), true.to_attribute().unwrap());
+        item.insert(String::from("updated"), time.to_attribute().unwrap());
+
+        assert_eq!(item_id(&item).unwrap(), id);
+        assert_eq!(item_value::<i64>(&item, &id, Column::Amount).unwrap(), 10);
+        assert_eq!(item_value::<bool>(&item, &id, Column::Confirmed).unwrap(), true);
+        assert_eq!(item_value::<NaiveDateTime>(&item, &id, Column::Updated).unwrap(), time);
+        // Missing attributes are NULL columns
+        assert_eq!(item_value::<Option<String>>(&item, &id, Column::Chain).unwrap(), None);
+        match item_value::<String>(&item, &id, Column::Chain) {
+            Err(SEError::DBErrorWC(NoDataForID, _, _)) => (),
+            res => panic!("expected NoDataForID, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_time_attribute_order() {
+        let earlier = NaiveDateTime::from_timestamp(1_600_000_000, 5_000);
+        let later = NaiveDateTime::from_timestamp(1_600_000_000, 50_000);
+        let s = |t: &NaiveDateTime| t.to_attribute().unwrap().s.unwrap();
+        assert!(s(&earlier) < s(&later));
+    }
+
+    #[test]
+    fn test_update_expression() {
+        let id = Uuid::new_v4();
+        let none: Option<String> = None;
+        let input = UpdateExpression::new(
+            vec![Column::Chain, Column::TxCpfp],
+            vec![&String::from("chain"), &none],
+        )
+        .input(String::from("mercury_backuptxs"), &id, "attribute_exists(#id)");
+        assert_eq!(
+            input.update_expression,
+            Some(String::from("SET #c0 = :v0 REMOVE #c1"))
+        );
+        let names = input.expression_attribute_names.unwrap();
+        assert_eq!(names.get("#c1"), Some(&String::from("txcpfp")));
+        let values = input.expression_attribute_values.unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values.get(":v0").unwrap().s, Some(String::from("chain")));
+    }
+}
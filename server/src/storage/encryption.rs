@@ -0,0 +1,309 @@
+//! Encryption
+//!
+//! Column level encryption of sensitive values: key shares and keygen secrets, transfer data
+//! and sig hashes. Values are serialized as usual by PGDatabase::ser() and then encrypted with
+//! AES-256-GCM under the master key of the db_encryption config. The table, column and row id
+//! of a value are its associated data, so that an encrypted value copied to another row or
+//! column fails to decrypt.
+//!
+//! An encrypted value is stored as "enc1:<key id>:<hex nonce and ciphertext>". Values that do
+//! not start with the prefix were written before encryption was enabled and are read as they
+//! are. After a change of master key, run_reencrypt() re-encrypts every value with the current
+//! key (server_exec reencrypt).
+
+use super::super::Result;
+use super::db::{Column, Table};
+use crate::config::{Config, DbEncryptionConfig};
+use crate::error::SEError;
+use crate::{Database, PGDatabase};
+
+use aes_gcm::aead::{generic_array::GenericArray, Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use rand::Rng;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Prefix of encrypted values
+const ENCRYPTED_PREFIX: &str = "enc1:";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Encrypted columns
+pub const ENCRYPTED_COLUMNS: [(Table, Column); 12] = [
+    (Table::UserSession, Column::S2),
+    (Table::UserSession, Column::SigHash),
+    (Table::Transfer, Column::X1),
+    (Table::StateChain, Column::TransferFinalizeData),
+    (Table::Sale, Column::TransferFinalizeData),
+    (Table::Ecdsa, Column::CommWitness),
+    (Table::Ecdsa, Column::EcKeyPair),
+    (Table::Ecdsa, Column::PaillierKeyPair),
+    (Table::Ecdsa, Column::Party1Private),
+    (Table::Ecdsa, Column::Party1MasterKey),
+    (Table::Ecdsa, Column::EphEcKeyPair),
+    (Table::Ecdsa, Column::RefreshX1),
+];
+
+fn parse_key(key_hex: &str) -> Result<[u8; KEY_LEN]> {
+    let bytes = hex::decode(key_hex.trim())
+        .map_err(|e| SEError::Generic(format!("Invalid DB encryption key: {}", e)))?;
+    if bytes.len() != KEY_LEN {
+        return Err(SEError::Generic(format!(
+            "Invalid DB encryption key: expected {} bytes, got {}",
+            KEY_LEN,
+            bytes.len()
+        )));
+    }
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&bytes);
+    Ok(key)
+}
+
+/// Associated data of the value of a column
+pub fn column_aad(table: &Table, column: Column, id: &Uuid) -> String {
+    format!("{}.{}:{}", table.to_string(), column.to_string(), id)
+}
+
+/// Master keys by id and the id of the current key. Encryption is disabled if there is no
+/// current key.
+#[derive(Clone, Default)]
+pub struct ColumnCipher {
+    keys: HashMap<u32, [u8; KEY_LEN]>,
+    current: Option<u32>,
+}
+
+impl ColumnCipher {
+    /// Master keys of the config. The current key is fetched from the key management service
+    /// if only key_url is set.
+    pub fn new(config: &DbEncryptionConfig) -> Result<Self> {
+        let mut keys = HashMap::new();
+        for entry in config
+            .previous_keys
+            .split(',')
+            .filter(|e| !e.trim().is_empty())
+        {
+            let mut parts = entry.trim().splitn(2, ':');
+            let id = parts.next().unwrap_or("").parse::<u32>().map_err(|e| {
+                SEError::Generic(format!("Invalid DB encryption previous key id: {}", e))
+            })?;
+            let key = parse_key(parts.next().unwrap_or(""))?;
+            keys.insert(id, key);
+        }
+        let key_hex = match (&config.key, &config.key_url) {
+            (Some(key), _) if !key.is_empty() => Some(key.clone()),
+            (_, Some(url)) if !url.is_empty() => {
+                Some(reqwest::blocking::get(url)?.error_for_status()?.text()?)
+            }
+            _ => None,
+        };
+        let current = match key_hex {
+            Some(key_hex) => {
+                keys.insert(config.key_id, parse_key(&key_hex)?);
+                Some(config.key_id)
+            }
+            None => None,
+        };
+        Ok(Self { keys, current })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Encrypt a serialized value with the current key. Returned as it is if encryption is
+    /// disabled.
+    pub fn encrypt(&self, aad: &str, plaintext: String) -> Result<String> {
+        let id = match self.current {
+            Some(id) => id,
+            None => return Ok(plaintext),
+        };
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(&self.keys[&id]));
+        let ciphertext = cipher
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| SEError::Generic(format!("Failed to encrypt {}", aad)))?;
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Ok(format!("{}{}:{}", ENCRYPTED_PREFIX, id, hex::encode(data)))
+    }
+
+    /// Decrypt a value written by encrypt(). Values written before encryption was enabled are
+    /// returned as they are.
+    pub fn decrypt(&self, aad: &str, data: String) -> Result<String> {
+        let (id, data) = match Self::key_id(&data) {
+            Some(id) => (id, &data[data.rfind(':').unwrap_or(0) + 1..]),
+            None => return Ok(data),
+        };
+        let key = self.keys.get(&id).ok_or(SEError::Generic(format!(
+            "Failed to decrypt {}: unknown DB encryption key id {}",
+            aad, id
+        )))?;
+        let bytes = hex::decode(data)
+            .map_err(|e| SEError::Generic(format!("Failed to decrypt {}: {}", aad, e)))?;
+        if bytes.len() < NONCE_LEN {
+            return Err(SEError::Generic(format!(
+                "Failed to decrypt {}: truncated",
+                aad
+            )));
+        }
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
+        let plaintext = cipher
+            .decrypt(
+                GenericArray::from_slice(&bytes[..NONCE_LEN]),
+                Payload {
+                    msg: &bytes[NONCE_LEN..],
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| SEError::Generic(format!("Failed to decrypt {}: not authentic", aad)))?;
+        String::from_utf8(plaintext)
+            .map_err(|e| SEError::Generic(format!("Failed to decrypt {}: {}", aad, e)))
+    }
+
+    /// Id of the key a value is encrypted with. None for a value that is not encrypted.
+    fn key_id(data: &str) -> Option<u32> {
+        if !data.starts_with(ENCRYPTED_PREFIX) {
+            return None;
+        }
+        data[ENCRYPTED_PREFIX.len()..]
+            .split(':')
+            .next()?
+            .parse()
+            .ok()
+    }
+
+    /// True if a value is stored as it would be written now: encrypted with the current key,
+    /// or not encrypted if encryption is disabled
+    pub fn is_current(&self, data: &str) -> bool {
+        Self::key_id(data) == self.current
+    }
+
+    /// Value re-encrypted with the current key, or None if it already is
+    pub fn reencrypt(&self, aad: &str, data: String) -> Result<Option<String>> {
+        if self.is_current(&data) {
+            return Ok(None);
+        }
+        Ok(Some(self.encrypt(aad, self.decrypt(aad, data)?)?))
+    }
+}
+
+/// Maintenance: re-encrypt the sensitive values in the DB configured in Settings.toml with the
+/// current master key. Values written without encryption are encrypted, and values are
+/// decrypted if encryption has been disabled. Can be run while the server is running.
+pub fn run_reencrypt() -> Result<()> {
+    let config = Config::load()?;
+    let mut db = PGDatabase::get_new();
+    db.set_connection_from_config(&config)?;
+    let count = db.reencrypt_columns()?;
+    info!("REENCRYPT: re-encrypted {} values", count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static KEY_1: &str = "6a8c1f2fa8a2b0d2c53ba6b6c2d8f5e4a4c0e3d1b2a3f4e5d6c7b8a9f0e1d2c3";
+    static KEY_2: &str = "11c1f2fa8a2b0d2c53ba6b6c2d8f5e4a4c0e3d1b2a3f4e5d6c7b8a9f0e1d2c3b";
+
+    fn cipher(key_id: u32, key: &str, previous_keys: &str) -> ColumnCipher {
+        ColumnCipher::new(&DbEncryptionConfig {
+            key: Some(String::from(key)),
+            key_url: None,
+            key_id,
+            previous_keys: String::from(previous_keys),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_column_cipher() {
+        let id = Uuid::new_v4();
+        let aad = column_aad(&Table::UserSession, Column::S2, &id);
+        let plaintext =
+            String::from("\"3d0c7b47bd2dd3b1bd5bd1d3e2b2fd0c0cf8ac4b8d3f5e9ab7c6a1d0e2f3a4b5\"");
+
+        // Disabled
+        let disabled = ColumnCipher::default();
+        assert!(!disabled.enabled());
+        assert_eq!(
+            disabled.encrypt(&aad, plaintext.clone()).unwrap(),
+            plaintext
+        );
+        assert!(disabled.is_current(&plaintext));
+
+        let cipher_1 = cipher(1, KEY_1, "");
+        let encrypted = cipher_1.encrypt(&aad, plaintext.clone()).unwrap();
+        assert!(encrypted.starts_with("enc1:1:"));
+        assert!(!encrypted.contains(&plaintext[1..20]));
+        assert_ne!(
+            cipher_1.encrypt(&aad, plaintext.clone()).unwrap(),
+            encrypted
+        );
+        assert_eq!(
+            cipher_1.decrypt(&aad, encrypted.clone()).unwrap(),
+            plaintext
+        );
+        // Values written before encryption was enabled are read as they are
+        assert_eq!(
+            cipher_1.decrypt(&aad, plaintext.clone()).unwrap(),
+            plaintext
+        );
+
+        // Bound to its table, column and row
+        let other_row = column_aad(&Table::UserSession, Column::S2, &Uuid::new_v4());
+        assert!(cipher_1.decrypt(&other_row, encrypted.clone()).is_err());
+        let other_column = column_aad(&Table::Transfer, Column::X1, &id);
+        assert!(cipher_1.decrypt(&other_column, encrypted.clone()).is_err());
+        let mut tampered = encrypted.clone();
+        tampered.pop();
+        tampered.push('0');
+        assert!(cipher_1.decrypt(&aad, tampered).is_err());
+        // Unknown key
+        assert!(cipher(2, KEY_2, "")
+            .decrypt(&aad, encrypted.clone())
+            .is_err());
+    }
+
+    #[test]
+    fn test_column_cipher_rotation() {
+        let id = Uuid::new_v4();
+        let aad = column_aad(&Table::Ecdsa, Column::Party1Private, &id);
+        let plaintext = String::from("{\"x1\":\"1\"}");
+        let encrypted_1 = cipher(1, KEY_1, "")
+            .encrypt(&aad, plaintext.clone())
+            .unwrap();
+
+        let cipher_2 = cipher(2, KEY_2, &format!("1:{}", KEY_1));
+        assert!(!cipher_2.is_current(&encrypted_1));
+        assert_eq!(
+            cipher_2.decrypt(&aad, encrypted_1.clone()).unwrap(),
+            plaintext
+        );
+        let encrypted_2 = cipher_2.reencrypt(&aad, encrypted_1).unwrap().unwrap();
+        assert!(encrypted_2.starts_with("enc1:2:"));
+        assert!(cipher_2.is_current(&encrypted_2));
+        assert_eq!(cipher_2.reencrypt(&aad, encrypted_2.clone()).unwrap(), None);
+        assert_eq!(cipher_2.decrypt(&aad, encrypted_2).unwrap(), plaintext);
+        // Plaintext values are encrypted
+        assert!(cipher_2.reencrypt(&aad, plaintext).unwrap().is_some());
+
+        assert!(ColumnCipher::new(&DbEncryptionConfig {
+            key: Some(String::from("00")),
+            ..DbEncryptionConfig::default()
+        })
+        .is_err());
+        assert!(ColumnCipher::new(&DbEncryptionConfig {
+            previous_keys: String::from("x:00"),
+            ..DbEncryptionConfig::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Migrations
+//!
+//! Embedded schema migrations for the Postgres DB.
+//!
+//! Migrations are SQL scripts in the migrations directory, compiled into the server and
+//! applied in version order at startup. The applied versions are recorded in the
+//! schema_version table, so each migration is run once per DB. A new schema change is added
+//! as a new migration with the next version: applied migrations must not be edited.
+
+use super::super::Result;
+use super::db::Table;
+use crate::error::SEError;
+use crate::PGDatabase;
+
+/// Schema migration
+#[derive(Debug)]
+pub struct Migration {
+    /// Schema version after the migration is applied
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// All migrations, in version order
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("migrations/V1__initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "fee_acknowledgement",
+        sql: include_str!("migrations/V2__fee_acknowledgement.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "transfer_receipt",
+        sql: include_str!("migrations/V3__transfer_receipt.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "statechain_archive",
+        sql: include_str!("migrations/V4__statechain_archive.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "usersession_proofkey_index",
+        sql: include_str!("migrations/V5__usersession_proofkey_index.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "sale",
+        sql: include_str!("migrations/V6__sale.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "transferreject",
+        sql: include_str!("migrations/V7__transferreject.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "swap_fee",
+        sql: include_str!("migrations/V8__swap_fee.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "root_leaf",
+        sql: include_str!("migrations/V9__root_leaf.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "watchtower",
+        sql: include_str!("migrations/V10__watchtower.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "fee_ledger",
+        sql: include_str!("migrations/V11__fee_ledger.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "chain_segment",
+        sql: include_str!("migrations/V12__chain_segment.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "share_refresh",
+        sql: include_str!("migrations/V13__share_refresh.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "session_state_updated",
+        sql: include_str!("migrations/V14__session_state_updated.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "inheritance",
+        sql: include_str!("migrations/V15__inheritance.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "second_factor",
+        sql: include_str!("migrations/V16__second_factor.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "vault",
+        sql: include_str!("migrations/V17__vault.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "transfer_batch_start_height",
+        sql: include_str!("migrations/V18__transfer_batch_start_height.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "swap_report",
+        sql: include_str!("migrations/V19__swap_report.sql"),
+    },
+    Migration {
+        version: 20,
+        name: "spend_incident",
+        sql: include_str!("migrations/V20__spend_incident.sql"),
+    },
+    Migration {
+        version: 21,
+        name: "spend_response",
+        sql: include_str!("migrations/V21__spend_response.sql"),
+    },
+    Migration {
+        version: 22,
+        name: "pay_to_contract",
+        sql: include_str!("migrations/V22__pay_to_contract.sql"),
+    },
+    Migration {
+        version: 23,
+        name: "backup_addr",
+        sql: include_str!("migrations/V23__backup_addr.sql"),
+    },
+    Migration {
+        version: 24,
+        name: "sighash_finalized",
+        sql: include_str!("migrations/V24__sighash_finalized.sql"),
+    },
+    Migration {
+        version: 25,
+        name: "backup_tx_history",
+        sql: include_str!("migrations/V25__backup_tx_history.sql"),
+    },
+];
+
+/// Advisory lock key held while migrations are applied, so that servers starting
+/// concurrently against the same DB do not apply the same migration twice
+const MIGRATION_LOCK_KEY: i64 = 0x6d65_7263_7572_7901;
+
+/// Latest schema version
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+impl PGDatabase {
+    /// Apply pending migrations. Each migration is applied in its own transaction together
+    /// with its schema_version record.
+    pub fn run_migrations(&self) -> Result<()> {
+        let conn = self.database_w()?;
+        conn.execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK_KEY])?;
+        let result = self.apply_migrations(&conn);
+        conn.execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK_KEY])?;
+        result
+    }
+
+    fn apply_migrations(&self, conn: &postgres::Connection) -> Result<()> {
+        conn.batch_execute(&format!(
+            "
+            CREATE SCHEMA IF NOT EXISTS statechainentity;
+            CREATE TABLE IF NOT EXISTS {} (
+                version int4 NOT NULL,
+                name varchar NOT NULL,
+                applied timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+                PRIMARY KEY (version)
+            );",
+            Table::SchemaVersion.to_string(),
+        ))?;
+
+        let current = self.get_schema_version(conn)?;
+        if current > latest_version() {
+            return Err(SEError::Generic(format!(
+                "DB schema version {} is newer than the latest known version {}",
+                current,
+                latest_version()
+            )));
+        }
+
+        for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+            info!(
+                "DB: applying migration V{}__{}",
+                migration.version, migration.name
+            );
+            let trans = conn.transaction()?;
+            trans.batch_execute(migration.sql)?;
+            trans.execute(
+                &format!(
+                    "INSERT INTO {} (version, name) VALUES ($1, $2)",
+                    Table::SchemaVersion.to_string(),
+                ),
+                &[&migration.version, &migration.name],
+            )?;
+            trans.commit()?;
+        }
+
+        info!("DB: schema version {}", latest_version());
+        Ok(())
+    }
+
+    /// Latest applied schema version, or 0 if no migrations have been applied
+    fn get_schema_version(&self, conn: &postgres::Connection) -> Result<i32> {
+        let rows = conn.query(
+            &format!(
+                "SELECT max(version) FROM {}",
+                Table::SchemaVersion.to_string()
+            ),
+            &[],
+        )?;
+        let version: Option<i32> = rows.get(0).get(0);
+        Ok(version.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_migrations_ordered() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version, i as i32 + 1, "migration versions must be contiguous");
+            assert!(!m.sql.trim().is_empty());
+        }
+        let names: HashSet<&str> = MIGRATIONS.iter().map(|m| m.name).collect();
+        assert_eq!(names.len(), MIGRATIONS.len());
+        assert_eq!(latest_version(), MIGRATIONS.len() as i32);
+    }
+
+    #[test]
+    fn test_initial_schema_idempotent() {
+        // The initial migration baselines DBs created before migrations were introduced
+        for line in MIGRATIONS[0].sql.lines() {
+            let line = line.trim_start();
+            if line.starts_with("CREATE TABLE") || line.starts_with("CREATE SCHEMA") {
+                assert!(line.contains("IF NOT EXISTS"), "{}", line);
+            }
+            if line.starts_with("ALTER TABLE") {
+                assert!(line.contains("ADD COLUMN IF NOT EXISTS"), "{}", line);
+            }
+        }
+    }
+}
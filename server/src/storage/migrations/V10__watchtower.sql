@@ -0,0 +1,19 @@
+-- Watchtower registrations.
+--
+-- Backup txs registered by their owners, encrypted to the watchtower key, to be broadcast from
+-- the broadcast height. Revoked registrations are kept without their backup tx so that the
+-- version of an id only increases and older registrations cannot be replayed.
+
+CREATE TABLE watcher.watchtower (
+    id uuid NOT NULL,
+    authkey varchar NOT NULL,
+    version int8 NOT NULL,
+    broadcastheight int8 NOT NULL,
+    txbackup varchar,
+    revoked bool NOT NULL DEFAULT false,
+    updatedat timestamp NOT NULL,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX watchtower_authkey ON watcher.watchtower (authkey);
+CREATE INDEX watchtower_broadcastheight ON watcher.watchtower (broadcastheight) WHERE NOT revoked;
@@ -0,0 +1,17 @@
+-- Fee ledger.
+--
+-- Fees charged by the state entity, recorded when the deposit, withdrawal or swap is
+-- confirmed, with the tx paying the fee to the fee address so that the ledger can be
+-- reconciled against on-chain receipts. A withdraw tx of a batch pays the fees of all of its
+-- statecoins in one output.
+
+CREATE TABLE statechainentity.feeledger (
+    txid varchar NOT NULL,
+    feetype varchar NOT NULL,
+    statechainid uuid NOT NULL,
+    amount int8 NOT NULL,
+    collectedat timestamp NOT NULL,
+    PRIMARY KEY (txid, feetype, statechainid)
+);
+
+CREATE INDEX feeledger_collectedat ON statechainentity.feeledger (collectedat);
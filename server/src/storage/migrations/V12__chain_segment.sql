@@ -0,0 +1,16 @@
+-- Pruned statechain segments.
+--
+-- States pruned from the start of long statechains. Segments are hash-linked: the hash of a
+-- segment commits to the previous segment hash and its states, and the chain records the hash
+-- of the last segment, so that the full chain can be restored and checked. Segments written by
+-- a transfer that failed to commit are not linked from any chain and are ignored.
+
+CREATE TABLE statechainentity.chainsegment (
+    statechainid uuid NOT NULL,
+    hash varchar NOT NULL,
+    prevhash varchar NOT NULL,
+    startposition int8 NOT NULL,
+    states varchar NOT NULL,
+    archived timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    PRIMARY KEY (statechainid, hash)
+);
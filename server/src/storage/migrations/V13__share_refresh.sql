@@ -0,0 +1,7 @@
+-- Key share refresh.
+--
+-- The number of refreshes of the key shares of each shared key (share epoch), and the blinding
+-- factor x1 of a refresh initiated by refresh/first and not yet completed by refresh/second.
+
+ALTER TABLE statechainentity.ecdsa ADD COLUMN IF NOT EXISTS shareepoch int8 NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS refreshx1 varchar;
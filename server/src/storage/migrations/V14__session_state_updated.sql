@@ -0,0 +1,8 @@
+-- Time of the last session state change of each user session, used to remove deposit sessions
+-- that never complete. Existing sessions are timed from the migration.
+
+ALTER TABLE statechainentity.usersession ADD COLUMN IF NOT EXISTS stateupdated timestamp NOT NULL
+    DEFAULT (now() at time zone 'utc');
+
+CREATE INDEX IF NOT EXISTS usersession_sessionstate_stateupdated
+    ON statechainentity.usersession (sessionstate, stateupdated);
@@ -0,0 +1,14 @@
+-- Beneficiary designations.
+--
+-- The receiver of the pending transfer of a statechain, designated as its beneficiary by the
+-- owner, can only complete the transfer once the owner has not checked in for the inactivity
+-- period. Keyed by statechain ID. A new designation replaces the previous one.
+
+CREATE TABLE statechainentity.inheritance (
+    id uuid NOT NULL,
+    transfersig varchar NOT NULL,
+    designationsig varchar NOT NULL,
+    inactivityperiod int8 NOT NULL,
+    lastcheckin timestamp NOT NULL,
+    PRIMARY KEY (id)
+);
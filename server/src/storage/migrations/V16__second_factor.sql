@@ -0,0 +1,15 @@
+-- Second factor keys.
+--
+-- Public keys whose signature is required in addition to the owner's to transfer, withdraw or
+-- migrate a statecoin, keyed by statechain ID. A registration only applies while the proof key
+-- it was registered by is the statechain tip. Changes not signed by the current key are
+-- pending until the recovery delay has passed.
+
+CREATE TABLE statechainentity.secondfactor (
+    id uuid NOT NULL,
+    proofkey varchar NOT NULL,
+    authkey varchar,
+    pendingauthkey varchar,
+    pendingeffective timestamp,
+    PRIMARY KEY (id)
+);
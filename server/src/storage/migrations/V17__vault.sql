@@ -0,0 +1,17 @@
+-- Vault policies.
+--
+-- Delay between the start of a transfer, withdrawal or migration of a statecoin and its
+-- completion, during which the owner can cancel it. Keyed by statechain ID. A policy only
+-- applies while the proof key it was set by is the statechain tip. Shorter delays are pending
+-- until the current delay has passed.
+
+CREATE TABLE statechainentity.vault (
+    id uuid NOT NULL,
+    proofkey varchar NOT NULL,
+    delay int8 NOT NULL,
+    pendingdelay int8,
+    pendingeffective timestamp,
+    pendingsig varchar NOT NULL,
+    pendingsince timestamp,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,5 @@
+-- Block height at which each batch transfer started, set when batch lifetimes are configured
+-- in blocks (duration_unit = "blocks"). Batches started before have no start height and are
+-- timed from their start time.
+
+ALTER TABLE statechainentity.transferbatch ADD COLUMN IF NOT EXISTS startheight int8;
@@ -0,0 +1,12 @@
+-- Swap reports.
+--
+-- Number of participants, denomination and completion time of each completed swap, keyed by
+-- swap ID. The statechains and addresses of the participants are not recorded.
+
+CREATE TABLE statechainentity.swapreport (
+    id uuid NOT NULL,
+    participants int8 NOT NULL,
+    denomination int8 NOT NULL,
+    completedat timestamp NOT NULL,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,171 @@
+-- Initial schema.
+--
+-- Databases created before schema migrations were introduced already contain some or all of
+-- these tables, so every statement is idempotent and brings such a database up to the same
+-- schema as a new one.
+
+CREATE SCHEMA IF NOT EXISTS statechainentity;
+CREATE SCHEMA IF NOT EXISTS watcher;
+
+CREATE TABLE IF NOT EXISTS statechainentity.usersession (
+    id uuid NOT NULL,
+    statechainid uuid,
+    authentication varchar,
+    s2 varchar,
+    s1pubkey varchar,
+    sighash varchar,
+    withdrawscsig varchar,
+    txwithdraw varchar,
+    proofkey varchar,
+    txbackup varchar,
+    masterpublic varchar,
+    sharedpublic varchar,
+    challenge varchar,
+    sessionstate varchar,
+    PRIMARY KEY (id)
+);
+
+-- Protocol state of sessions created before the session state machine. Sessions with no
+-- state are not validated.
+ALTER TABLE statechainentity.usersession ADD COLUMN IF NOT EXISTS sessionstate varchar;
+
+CREATE TABLE IF NOT EXISTS statechainentity.lockbox (
+    id uuid NOT NULL,
+    lockbox int8,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS statechainentity.ecdsa (
+    id uuid NOT NULL,
+    keygenfirstmsg varchar,
+    commwitness varchar,
+    eckeypair varchar,
+    party2public varchar,
+    paillierkeypair varchar,
+    party1private varchar,
+    party1masterkey varchar,
+    pos varchar,
+    epheckeypair varchar,
+    ephkeygenfirstmsg varchar,
+    complete bool NOT NULL DEFAULT false,
+    keyepoch int8 NOT NULL DEFAULT 0,
+    proofverified bool,
+    PRIMARY KEY (id)
+);
+
+ALTER TABLE statechainentity.ecdsa ADD COLUMN IF NOT EXISTS keyepoch int8 NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS proofverified bool;
+
+CREATE TABLE IF NOT EXISTS statechainentity.statechain (
+    id uuid NOT NULL,
+    chain varchar,
+    amount int8,
+    ownerid uuid,
+    lockeduntil timestamp,
+    transferfinalizedata varchar,
+    transferready bool,
+    sharedpublic varchar,
+    confirmed bool NOT NULL DEFAULT false,
+    updated timestamp,
+    updatedroot int8,
+    version int8 NOT NULL DEFAULT 0,
+    PRIMARY KEY (id)
+);
+
+ALTER TABLE statechainentity.statechain ADD COLUMN IF NOT EXISTS updated timestamp,
+    ADD COLUMN IF NOT EXISTS updatedroot int8,
+    ADD COLUMN IF NOT EXISTS version int8 NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS statechain_updated_idx ON statechainentity.statechain (updated);
+
+CREATE TABLE IF NOT EXISTS statechainentity.transfer (
+    id uuid NOT NULL,
+    statechainsig varchar,
+    x1 varchar,
+    transfermsg varchar,
+    proofkey varchar,
+    batchid uuid,
+    version int8 NOT NULL DEFAULT 0,
+    PRIMARY KEY (id)
+);
+
+ALTER TABLE statechainentity.transfer ADD COLUMN IF NOT EXISTS version int8 NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS statechainentity.transferbatch (
+    id uuid NOT NULL,
+    starttime timestamp,
+    statechains varchar,
+    punishedstatechains varchar,
+    finalized bool,
+    version int8 NOT NULL DEFAULT 0,
+    PRIMARY KEY (id)
+);
+
+ALTER TABLE statechainentity.transferbatch ADD COLUMN IF NOT EXISTS version int8 NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS statechainentity.root (
+    id BIGSERIAL,
+    value varchar,
+    commitmentinfo varchar,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS watcher.backuptxs (
+    id uuid NOT NULL,
+    txbackup varchar,
+    locktime int8,
+    txcpfp varchar,
+    PRIMARY KEY (id)
+);
+
+ALTER TABLE watcher.backuptxs ADD COLUMN IF NOT EXISTS txcpfp varchar;
+
+CREATE TABLE IF NOT EXISTS statechainentity.smt (
+    key varchar,
+    value varchar,
+    PRIMARY KEY (key)
+);
+
+CREATE TABLE IF NOT EXISTS statechainentity.signedsighash (
+    id BIGSERIAL,
+    userid uuid NOT NULL,
+    outpoint varchar NOT NULL,
+    sighash varchar NOT NULL,
+    locktime int8 NOT NULL,
+    signed bool NOT NULL DEFAULT false,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS statechainentity.apikey (
+    keyhash varchar NOT NULL,
+    label varchar NOT NULL,
+    quota int8 NOT NULL,
+    revoked bool NOT NULL DEFAULT false,
+    created timestamp NOT NULL,
+    PRIMARY KEY (keyhash)
+);
+
+CREATE TABLE IF NOT EXISTS statechainentity.deletionreceipt (
+    id uuid NOT NULL,
+    statechainid uuid NOT NULL,
+    receipt varchar NOT NULL,
+    deletedat timestamp NOT NULL,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS statechainentity.punishment (
+    id varchar NOT NULL,
+    offences int8 NOT NULL,
+    lastoffence timestamp NOT NULL,
+    lockeduntil timestamp NOT NULL,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS statechainentity.deposithandoff (
+    id uuid NOT NULL,
+    proofkey varchar NOT NULL,
+    payerproofkey varchar NOT NULL,
+    statechainid uuid,
+    msg varchar,
+    PRIMARY KEY (id)
+);
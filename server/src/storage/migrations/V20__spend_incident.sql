@@ -0,0 +1,12 @@
+-- Spend incidents.
+--
+-- Spends of the funding output of a live statecoin by a tx other than its current backup tx,
+-- keyed by statechain ID. Only the first detected spend of a statechain is recorded.
+
+CREATE TABLE statechainentity.spendincident (
+    id uuid NOT NULL,
+    funding varchar NOT NULL,
+    height int8 NOT NULL,
+    detectedat timestamp NOT NULL,
+    PRIMARY KEY (id)
+);
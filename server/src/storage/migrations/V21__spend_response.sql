@@ -0,0 +1,5 @@
+-- Spend incident responses.
+--
+-- Txid of the backup tx broadcast by the watcher in response to a spend incident, if any.
+
+ALTER TABLE statechainentity.spendincident ADD COLUMN responsetxid varchar;
@@ -0,0 +1,6 @@
+-- Pay-to-contract commitments.
+--
+-- Commitment the deposit shared key of a user session is tweaked with, and the shared public key
+-- before the tweak, so that the owner key share can be tweaked again on recovery.
+
+ALTER TABLE statechainentity.usersession ADD COLUMN IF NOT EXISTS p2c varchar;
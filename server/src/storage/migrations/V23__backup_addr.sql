@@ -0,0 +1,6 @@
+-- Backup tx destination address.
+--
+-- Address the current backup tx of a statechain pays to, which may be a user-supplied address
+-- rather than a wallet backup key.
+
+ALTER TABLE statechainentity.statechain ADD COLUMN IF NOT EXISTS backupaddr varchar;
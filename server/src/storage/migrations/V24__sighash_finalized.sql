@@ -0,0 +1,7 @@
+-- Finalized sighashes.
+--
+-- Backup tx sighashes are only checked for conflicting spends once the deposit or transfer
+-- that signed them is finalized, so that an aborted transfer or swap can be retried. Existing
+-- records are treated as finalized.
+
+ALTER TABLE statechainentity.signedsighash ADD COLUMN IF NOT EXISTS finalized bool NOT NULL DEFAULT true;
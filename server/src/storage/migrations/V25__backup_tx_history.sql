@@ -0,0 +1,15 @@
+-- Backup tx history.
+--
+-- Every co-signed backup tx of a statechain, recorded when the deposit, transfer or migration
+-- that signed it is finalized, so that statechain exports include all backup txs and not only
+-- the current one. Statechains created before this migration have no backup txs recorded
+-- before it.
+
+CREATE TABLE statechainentity.backuptxhistory (
+    id BIGSERIAL,
+    statechainid uuid NOT NULL,
+    txid varchar NOT NULL,
+    txbackup varchar NOT NULL,
+    recorded timestamp NOT NULL,
+    PRIMARY KEY (statechainid, txid)
+);
@@ -0,0 +1,12 @@
+-- Fee quote acknowledgements of deposits.
+--
+-- The fee quote (/info/fee response) acknowledged in DepositMsg1, stored with its hash so that
+-- the state entity and the depositor can prove the fee agreed for the deposit.
+
+CREATE TABLE statechainentity.feeacknowledgement (
+    id uuid NOT NULL,
+    quotehash varchar NOT NULL,
+    quote varchar NOT NULL,
+    created timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,12 @@
+-- Transfer receipts.
+--
+-- State entity signed receipts of completed transfers, keyed by the shared key ID of the
+-- sender, as proof of payment for the sender.
+
+CREATE TABLE statechainentity.transferreceipt (
+    id uuid NOT NULL,
+    statechainid uuid NOT NULL,
+    receipt varchar NOT NULL,
+    finalizedat timestamp NOT NULL,
+    PRIMARY KEY (id)
+);
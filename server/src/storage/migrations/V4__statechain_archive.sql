@@ -0,0 +1,21 @@
+-- Archived statechains.
+--
+-- Finished statechains (withdrawn, or expired once their backup tx has been confirmed) are
+-- moved out of the statechain and backuptxs tables after the retention period, together with
+-- their last backup tx, so that they remain queryable by the info routes.
+
+CREATE TABLE statechainentity.statechainarchive (
+    id uuid NOT NULL,
+    chain varchar NOT NULL,
+    amount int8 NOT NULL,
+    ownerid uuid,
+    sharedpublic varchar,
+    confirmed bool NOT NULL,
+    txbackup varchar,
+    updated timestamp,
+    reason varchar NOT NULL,
+    archived timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX statechainarchive_archived_idx ON statechainentity.statechainarchive (archived);
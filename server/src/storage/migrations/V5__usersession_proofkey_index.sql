@@ -0,0 +1,3 @@
+-- Index user sessions by proof key for the /info/usersessions lookup and wallet recovery.
+
+CREATE INDEX IF NOT EXISTS usersession_proofkey_idx ON statechainentity.usersession (proofkey);
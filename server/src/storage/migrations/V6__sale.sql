@@ -0,0 +1,14 @@
+-- Escrowed sales.
+--
+-- Statecoin transfers held until the preimage of the payment hash is revealed, keyed by
+-- statechain ID. A new sale of the statechain replaces a finalized or expired one.
+
+CREATE TABLE statechainentity.sale (
+    id uuid NOT NULL,
+    paymenthash varchar NOT NULL,
+    expires timestamp NOT NULL,
+    transferfinalizedata varchar,
+    preimage varchar,
+    finalized bool NOT NULL DEFAULT false,
+    PRIMARY KEY (id)
+);
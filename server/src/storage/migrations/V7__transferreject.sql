@@ -0,0 +1,12 @@
+-- Rejected transfers.
+--
+-- The last transfer of each statechain declined by its receiver, keyed by statechain ID, so
+-- that the sender can find out the statecoin was returned to them.
+
+CREATE TABLE statechainentity.transferreject (
+    id uuid NOT NULL,
+    transfersig varchar NOT NULL,
+    rejectsig varchar NOT NULL,
+    rejectedat timestamp NOT NULL,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,19 @@
+-- Swap fees.
+--
+-- The swap fee of each participant of a swap, keyed by swap ID and the statechain ID registered
+-- for the swap. Fees paid by deposit record the fee deposit txid, which can be used once.
+-- Fees paid by deduction are owed by the statechain once the swap batch transfer is finalized
+-- and collected by its withdraw tx.
+
+CREATE TABLE statechainentity.swapfee (
+    swapid uuid NOT NULL,
+    statechainid uuid NOT NULL,
+    fee int8 NOT NULL,
+    txid varchar,
+    collected bool NOT NULL DEFAULT false,
+    createdat timestamp NOT NULL,
+    PRIMARY KEY (swapid, statechainid)
+);
+
+CREATE UNIQUE INDEX swapfee_txid ON statechainentity.swapfee (txid);
+CREATE INDEX swapfee_statechainid ON statechainentity.swapfee (statechainid);
@@ -0,0 +1,14 @@
+-- SMT root leaves.
+--
+-- The SMT leaves (funding txid: proof key or withdrawal address) updated by each new root, so
+-- that each state of a statechain can be attributed to the root it was first included in.
+-- Roots published before this migration have no recorded leaves.
+
+CREATE TABLE statechainentity.rootleaf (
+    rootid int8 NOT NULL,
+    fundingtxid varchar NOT NULL,
+    value varchar NOT NULL,
+    PRIMARY KEY (rootid, fundingtxid)
+);
+
+CREATE INDEX rootleaf_fundingtxid ON statechainentity.rootleaf (fundingtxid);
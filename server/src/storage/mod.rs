@@ -1,5 +1,10 @@
 pub mod db;
+#[cfg(feature = "dynamodb")]
+pub mod dynamodb;
+pub mod encryption;
+pub mod migrations;
 pub mod monotree;
+pub mod snapshot;
 pub use super::Result;
 
 use rocket::http::{ContentType, Status};
@@ -102,9 +107,14 @@ pub trait Storage {
     //    fn save_backup_tx(&self, statechain_id: &Uuid, backup_tx: &Transaction)
     //      -> Result<()>;
 
-    //Returns: (new_root, current_root)
-    fn update_smt(&self, funding_txid: &String, proof_key: &String)
-        -> Result<(Option<Root>, Root)>;
+    //Returns: (current_root, new_root). new_root is None if the update is queued for the next root.
+    //The leaf commits to the funding_txid and statecoin amount as well as the proof_key.
+    fn update_smt(&self, funding_txid: &String, proof_key: &String, amount: u64)
+        -> Result<(Option<Root>, Option<Root>)>;
+
+    //Returns: (current_root, new_root). Inserts all (funding_txid, proof_key, amount) updates in
+    //one tree operation.
+    fn update_smt_batch(&self, batch: &[(String, String, u64)]) -> Result<(Option<Root>, Root)>;
 
     //fn save_ecdsa(&self, user_id: &Uuid,
     //    first_msg: party_one::KeyGenFirstMsg) -> Result<()>;
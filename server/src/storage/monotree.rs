@@ -1,8 +1,10 @@
 //! Postgres implementation for Monotree
 
+use crate::chaos::FaultInjector;
+use super::encryption::ColumnCipher;
 use crate::storage::db::Table;
 use crate::Database;
-use crate::PGDatabase;
+use crate::{DatabaseR, PGDatabase};
 use monotree::database::{Database as MonotreeDatabase, MemCache, MemoryDB};
 use monotree::Errors;
 use std::collections::HashMap;
@@ -12,33 +14,8 @@ use std::sync::{Arc, Mutex};
 
 pub type Result<T> = std::result::Result<T, Errors>;
 
-// Postgres Monotree implementation
-impl MonotreeDatabase for PGDatabase {
-    // Dummy function not used in Postgres. Connections are aquired within each monotree call due to
-    // monotree::Database::new() contraints.
-    fn new(_dbname: &str) -> Self {
-        // Return dummy
-        PGDatabase {
-            pool: None,
-            smt: PGDatabaseSmt {
-                table_name: Table::Smt.to_string(),
-                cache: MemCache::new(),
-                batch_on: false,
-                batch: HashMap::new(),
-            },
-        }
-    }
-    /// Monotree get
-    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        if self.smt.cache.contains(key) {
-            return self.smt.cache.get(key);
-        }
-
-        let dbr = match self.database_r() {
-            Ok(v) => v,
-            Err(e) => return Err(Errors::new(&e.to_string())),
-        };
-
+impl PGDatabase {
+    fn smt_get(&self, dbr: &DatabaseR, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let stmt = match dbr.prepare(&format!(
             "SELECT value FROM {} WHERE key = ('{}')",
             self.smt.table_name,
@@ -66,7 +43,49 @@ impl MonotreeDatabase for PGDatabase {
             },
         };
     }
+}
 
+// Postgres Monotree implementation
+impl MonotreeDatabase for PGDatabase {
+    // Dummy function not used in Postgres. Connections are aquired within each monotree call due to
+    // monotree::Database::new() contraints.
+    fn new(_dbname: &str) -> Self {
+        // Return dummy
+        PGDatabase {
+            pool: None,
+            pool_replica: None,
+            smt: PGDatabaseSmt {
+                table_name: Table::Smt.to_string(),
+                cache: MemCache::new(),
+                batch_on: false,
+                batch: HashMap::new(),
+            },
+            faults: FaultInjector::default(),
+            cipher: ColumnCipher::default(),
+        }
+    }
+    /// Monotree get
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.smt.cache.contains(key) {
+            return self.smt.cache.get(key);
+        }
+
+        // Nodes are stored under the hash of their value, so any node read from the replica is
+        // valid. Nodes the replica does not have yet are read from the primary.
+        if self.pool_replica.is_some() {
+            if let Ok(dbr) = self.database_replica() {
+                if let Ok(v) = self.smt_get(&dbr, key) {
+                    return Ok(v);
+                }
+            }
+        }
+
+        let dbr = match self.database_r() {
+            Ok(v) => v,
+            Err(e) => return Err(Errors::new(&e.to_string())),
+        };
+        self.smt_get(&dbr, key)
+    }
     /// Monotree put
     fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
         self.smt.cache.put(key, value.to_owned())?;
@@ -325,6 +344,13 @@ impl Database for MemoryDB {
     ) -> crate::Result<()> {
         unimplemented!()
     }
+
+    fn get_withdraw_sc_sig(
+        &self,
+        _user_id: uuid::Uuid,
+    ) -> crate::Result<shared_lib::state_chain::StateChainSig> {
+        unimplemented!()
+    }
     fn update_withdraw_tx_sighash(
         &self,
         _user_id: &uuid::Uuid,
@@ -368,6 +394,13 @@ impl Database for MemoryDB {
     ) -> crate::Result<()> {
         unimplemented!()
     }
+    fn update_backup_cpfp_tx(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _tx: bitcoin::Transaction,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
     fn get_withdraw_confirm_data(
         &self,
         _user_id: uuid::Uuid,
@@ -386,6 +419,9 @@ impl Database for MemoryDB {
     fn get_root(&self, _id: i64) -> crate::Result<Option<super::Root>> {
         unimplemented!()
     }
+    fn get_root_info(&self, _id: i64) -> crate::Result<Option<super::Root>> {
+        unimplemented!()
+    }
     fn get_confirmed_smt_root(&self) -> crate::Result<Option<super::Root>> {
         unimplemented!()
     }
@@ -420,15 +456,25 @@ impl Database for MemoryDB {
     ) -> crate::Result<crate::structs::StateChainAmount> {
         unimplemented!()
     }
+    fn get_statechain_info(
+        &self,
+        _statechain_id: uuid::Uuid,
+    ) -> crate::Result<crate::structs::StateChainInfo> {
+        unimplemented!()
+    }
     fn update_statechain_amount(
         &self,
         _statechain_id: &uuid::Uuid,
         _state_chain: super::StateChain,
         _amount: u64,
-        _coins_histo: Arc<Mutex<CoinValueInfo>>
+        _coins_histo: Arc<Mutex<CoinValueInfo>>,
+        _version: i64,
     ) -> crate::Result<()> {
         unimplemented!()
     }
+    fn get_statechain_version(&self, _statechain_id: &uuid::Uuid) -> crate::Result<i64> {
+        unimplemented!()
+    }
     fn create_statechain(
         &self,
         _statechain_id: &uuid::Uuid,
@@ -446,6 +492,7 @@ impl Database for MemoryDB {
         _statechain_id: &uuid::Uuid,
         _state_chain: super::StateChain,
         _new_user_id: &uuid::Uuid,
+        _version: i64,
     ) -> crate::Result<()> {
         unimplemented!()
     }
@@ -478,6 +525,15 @@ impl Database for MemoryDB {
     fn get_proof_key(&self, _user_id: uuid::Uuid) -> crate::Result<String> {
         unimplemented!()
     }
+    fn update_proof_key(&self, _user_id: &uuid::Uuid, _proof_key: &String) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_proof_key_holdings(&self, _proof_key: &String) -> crate::Result<(u64, u64)> {
+        unimplemented!()
+    }
+    fn get_total_value(&self) -> crate::Result<u64> {
+        unimplemented!()
+    }
     fn get_sc_locked_until(
         &self,
         _statechain_id: uuid::Uuid,
@@ -547,6 +603,9 @@ impl Database for MemoryDB {
     ) -> crate::Result<crate::structs::TransferData> {
         unimplemented!()
     }
+    fn claim_transfer(&self, _statechain_id: &uuid::Uuid, _version: i64) -> crate::Result<()> {
+        unimplemented!()
+    }
     fn remove_transfer_data(&self, _statechain_id: &uuid::Uuid) -> crate::Result<()> {
         unimplemented!()
     }
@@ -556,12 +615,31 @@ impl Database for MemoryDB {
     fn get_public_master(&self, _user_id: uuid::Uuid) -> crate::Result<Option<String>> {
         unimplemented!()
     }
+    fn get_p2c(
+        &self,
+        _user_id: uuid::Uuid,
+    ) -> crate::Result<Option<shared_lib::structs::PayToContract>> {
+        unimplemented!()
+    }
+    fn update_p2c(
+        &self,
+        _user_id: &uuid::Uuid,
+        _p2c: &shared_lib::structs::PayToContract,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
     fn get_shared_pubkey(&self, _user_id: uuid::Uuid) -> crate::Result<Option<String>> {
         unimplemented!()
     }
     fn get_statecoin_pubkey(&self, _statechain_id: uuid::Uuid) -> crate::Result<Option<String>> {
         unimplemented!()
     }
+    fn get_backup_addr(&self, _statechain_id: &uuid::Uuid) -> crate::Result<Option<String>> {
+        unimplemented!()
+    }
+    fn update_backup_addr(&self, _statechain_id: &uuid::Uuid, _addr: &String) -> crate::Result<()> {
+        unimplemented!()
+    }
     fn get_ecdsa_master(&self, _user_id: uuid::Uuid) -> crate::Result<Option<String>> {
         unimplemented!()
     }
@@ -602,6 +680,13 @@ impl Database for MemoryDB {
     ) -> crate::Result<()> {
         unimplemented!()
     }
+    fn update_party_2_public(
+        &self,
+        _user_id: &uuid::Uuid,
+        _party2_public: curv::GE,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
     fn init_ecdsa(&self, _user_id: &uuid::Uuid) -> crate::Result<u64> {
         unimplemented!()
     }
@@ -627,6 +712,7 @@ impl Database for MemoryDB {
         &self,
         _batch_id: &uuid::Uuid,
         _punished_state_chains: Vec<uuid::Uuid>,
+        _version: i64,
     ) -> crate::Result<()> {
         unimplemented!()
     }
@@ -636,6 +722,19 @@ impl Database for MemoryDB {
     ) -> crate::Result<chrono::NaiveDateTime> {
         unimplemented!()
     }
+    fn update_transfer_batch_start_height(
+        &self,
+        _batch_id: &uuid::Uuid,
+        _height: u64,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_transfer_batch_start_height(
+        &self,
+        _batch_id: &uuid::Uuid,
+    ) -> crate::Result<Option<u64>> {
+        unimplemented!()
+    }
     fn get_batch_transfer_statechain_ids(
         &self, 
         _batch_id: &uuid::Uuid
@@ -662,13 +761,18 @@ impl Database for MemoryDB {
         &self,
         _batch_id: &uuid::Uuid,
         _finalized_data: &TransferFinalizeData,
+        _version: i64,
     ) -> crate::Result<()> {
         unimplemented!()
     }
+    fn get_transfer_batch_version(&self, _batch_id: &uuid::Uuid) -> crate::Result<i64> {
+        unimplemented!()
+    }
     fn update_transfer_batch_finalized(
         &self,
         _batch_id: &uuid::Uuid,
         _b_finalized: &bool,
+        _version: i64,
     ) -> crate::Result<()> {
         unimplemented!()
     }
@@ -770,4 +874,391 @@ impl Database for MemoryDB {
     fn get_sighash(&self, _user_id: uuid::Uuid) -> crate::Result<bitcoin::hashes::sha256d::Hash> {
         unimplemented!()
     }
+    fn reset_ecdsa(&self, _user_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_ecdsa_key_epoch(&self, _user_id: uuid::Uuid) -> crate::Result<u32> {
+        unimplemented!()
+    }
+    fn update_ecdsa_key_epoch(&self, _user_id: &uuid::Uuid, _epoch: u32) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_ecdsa_share_epoch(&self, _user_id: uuid::Uuid) -> crate::Result<u32> {
+        unimplemented!()
+    }
+    fn update_ecdsa_share_epoch(&self, _user_id: &uuid::Uuid, _epoch: u32) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_ecdsa_refresh_x1(&self, _user_id: uuid::Uuid) -> crate::Result<Option<curv::FE>> {
+        unimplemented!()
+    }
+    fn update_ecdsa_refresh_x1(&self, _user_id: &uuid::Uuid, _x1: &curv::FE) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn update_ecdsa_s2(&self, _user_id: &uuid::Uuid, _s2: &curv::FE) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn update_ecdsa_proof_verified(&self, _user_id: &uuid::Uuid, _verified: bool) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_unverified_keygen_sessions(&self) -> crate::Result<Vec<uuid::Uuid>> {
+        unimplemented!()
+    }
+    fn insert_sighash_record(&self, _record: &crate::structs::SighashRecord) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_sighash_record(
+        &self,
+        _user_id: &uuid::Uuid,
+        _sig_hash: &bitcoin::hashes::sha256d::Hash,
+    ) -> crate::Result<crate::structs::SighashRecord> {
+        unimplemented!()
+    }
+    fn get_signed_sighashes(
+        &self,
+        _outpoint: &bitcoin::OutPoint,
+    ) -> crate::Result<Vec<crate::structs::SighashRecord>> {
+        unimplemented!()
+    }
+    fn set_sighash_signed(
+        &self,
+        _user_id: &uuid::Uuid,
+        _sig_hash: &bitcoin::hashes::sha256d::Hash,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn set_sighash_finalized(
+        &self,
+        _user_id: &uuid::Uuid,
+        _sig_hash: &bitcoin::hashes::sha256d::Hash,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn insert_api_key(&self, _api_key: &crate::structs::ApiKey) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_api_key(&self, _key_hash: &String) -> crate::Result<crate::structs::ApiKey> {
+        unimplemented!()
+    }
+    fn revoke_api_key(&self, _key_hash: &String) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_statechains_updated_since(
+        &self,
+        _since: &crate::structs::UpdatesSince,
+        _limit: i64,
+    ) -> crate::Result<Vec<(uuid::Uuid, chrono::NaiveDateTime)>> {
+        unimplemented!()
+    }
+    fn remove_ecdsa(&self, _user_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn insert_deletion_receipt(
+        &self,
+        _receipt: &shared_lib::structs::SignedDeletionReceipt,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_deletion_receipts(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Vec<shared_lib::structs::SignedDeletionReceipt>> {
+        unimplemented!()
+    }
+    fn insert_transfer_receipt(
+        &self,
+        _user_id: &uuid::Uuid,
+        _receipt: &shared_lib::structs::SignedTransferReceipt,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_transfer_receipts(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Vec<shared_lib::structs::SignedTransferReceipt>> {
+        unimplemented!()
+    }
+    fn get_transfer_reject(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Option<shared_lib::structs::TransferRejectAPI>> {
+        unimplemented!()
+    }
+    fn update_transfer_reject(
+        &self,
+        _reject: &shared_lib::structs::TransferRejectAPI,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_sale(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Option<crate::structs::SaleRecord>> {
+        unimplemented!()
+    }
+    fn update_sale(&self, _sale: &crate::structs::SaleRecord) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_inheritance(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Option<crate::structs::InheritanceRecord>> {
+        unimplemented!()
+    }
+    fn update_inheritance(
+        &self,
+        _inheritance: &crate::structs::InheritanceRecord,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn remove_inheritance(&self, _statechain_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_second_factor(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Option<crate::structs::SecondFactorRecord>> {
+        unimplemented!()
+    }
+    fn update_second_factor(
+        &self,
+        _second_factor: &crate::structs::SecondFactorRecord,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_vault(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Option<crate::structs::VaultRecord>> {
+        unimplemented!()
+    }
+    fn update_vault(&self, _vault: &crate::structs::VaultRecord) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn insert_swap_fee(
+        &self,
+        _swap_id: &uuid::Uuid,
+        _statechain_id: &uuid::Uuid,
+        _fee: u64,
+        _txid: &Option<String>,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_swap_fee_by_txid(
+        &self,
+        _txid: &String,
+    ) -> crate::Result<Option<(uuid::Uuid, uuid::Uuid)>> {
+        unimplemented!()
+    }
+    fn get_swap_fee_owed(&self, _statechain_id: &uuid::Uuid) -> crate::Result<u64> {
+        unimplemented!()
+    }
+    fn update_swap_fee_collected(&self, _statechain_id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn insert_swap_report(
+        &self,
+        _report: &shared_lib::swap_data::SwapReport,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_swap_report(
+        &self,
+        _swap_id: &uuid::Uuid,
+    ) -> crate::Result<Option<shared_lib::swap_data::SwapReport>> {
+        unimplemented!()
+    }
+    fn insert_spend_incident(
+        &self,
+        _incident: &shared_lib::structs::SpendIncident,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn update_spend_incident_response(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _response_txid: &String,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_spend_incident(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Option<shared_lib::structs::SpendIncident>> {
+        unimplemented!()
+    }
+    fn insert_backup_tx_history(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _tx: &bitcoin::Transaction,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_backup_tx_history(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Vec<bitcoin::Transaction>> {
+        unimplemented!()
+    }
+    fn insert_root_leaves(&self, _root_id: i64, _leaves: &[(String, String)]) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_root_leaves(&self, _funding_txid: &String) -> crate::Result<Vec<(i64, String)>> {
+        unimplemented!()
+    }
+    fn is_root_confirmed(&self, _id: i64) -> crate::Result<bool> {
+        unimplemented!()
+    }
+    fn get_watchtower_registration(
+        &self,
+        _id: &uuid::Uuid,
+    ) -> crate::Result<Option<crate::structs::WatchtowerRecord>> {
+        unimplemented!()
+    }
+    fn update_watchtower_registration(
+        &self,
+        _record: &crate::structs::WatchtowerRecord,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn count_watchtower_registrations(&self, _auth_key: &String) -> crate::Result<u64> {
+        unimplemented!()
+    }
+    fn get_due_watchtower_txs(
+        &self,
+        _height: i64,
+    ) -> crate::Result<Vec<crate::structs::WatchtowerRecord>> {
+        unimplemented!()
+    }
+    fn remove_watchtower_tx(&self, _id: &uuid::Uuid) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn insert_fee(
+        &self,
+        _txid: &String,
+        _fee_type: &shared_lib::structs::FeeType,
+        _statechain_id: &uuid::Uuid,
+        _amount: u64,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_fees(
+        &self,
+        _from: &chrono::NaiveDateTime,
+        _to: &chrono::NaiveDateTime,
+    ) -> crate::Result<Vec<crate::structs::FeeRecord>> {
+        unimplemented!()
+    }
+    fn insert_chain_segment(
+        &self,
+        _statechain_id: &uuid::Uuid,
+        _segment: &shared_lib::state_chain::ArchivedSegment,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_chain_segments(
+        &self,
+        _statechain_id: &uuid::Uuid,
+    ) -> crate::Result<Vec<shared_lib::state_chain::ArchivedSegment>> {
+        unimplemented!()
+    }
+    fn get_punishment(
+        &self,
+        _id: &String,
+    ) -> crate::Result<Option<crate::structs::PunishmentRecord>> {
+        unimplemented!()
+    }
+    fn update_punishment(&self, _record: &crate::structs::PunishmentRecord) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn archive_statechains(
+        &self,
+        _updated_before: &chrono::NaiveDateTime,
+        _limit: i64,
+    ) -> crate::Result<Vec<(uuid::Uuid, i64)>> {
+        unimplemented!()
+    }
+    fn remove_orphaned_sessions(
+        &self,
+        _state: &crate::structs::SessionState,
+        _updated_before: &chrono::NaiveDateTime,
+        _limit: i64,
+    ) -> crate::Result<(Vec<uuid::Uuid>, u64)> {
+        unimplemented!()
+    }
+    fn purge_archived_statechains(
+        &self,
+        _archived_before: &chrono::NaiveDateTime,
+    ) -> crate::Result<u64> {
+        unimplemented!()
+    }
+    fn remove_expired_punishments(
+        &self,
+        _locked_before: &chrono::NaiveDateTime,
+        _offence_before: &chrono::NaiveDateTime,
+    ) -> crate::Result<u64> {
+        unimplemented!()
+    }
+    fn create_deposit_handoff(
+        &self,
+        _user_id: &uuid::Uuid,
+        _proof_key: &String,
+        _payer_proof_key: &String,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_deposit_handoff_proof_key(&self, _user_id: &uuid::Uuid) -> crate::Result<Option<String>> {
+        unimplemented!()
+    }
+    fn get_deposit_payer_proof_key(&self, _user_id: &uuid::Uuid) -> crate::Result<Option<String>> {
+        unimplemented!()
+    }
+    fn update_deposit_handoff(
+        &self,
+        _msg: &shared_lib::structs::DepositHandoffMsg,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_deposit_handoffs(
+        &self,
+        _proof_key: &String,
+    ) -> crate::Result<Vec<shared_lib::structs::DepositHandoffMsg>> {
+        unimplemented!()
+    }
+    fn insert_fee_acknowledgement(
+        &self,
+        _user_id: &uuid::Uuid,
+        _quote_hash: &String,
+        _quote: &shared_lib::structs::StateEntityFeeInfoAPI,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_fee_acknowledgement(
+        &self,
+        _user_id: &uuid::Uuid,
+    ) -> crate::Result<Option<shared_lib::structs::StateEntityFeeInfoAPI>> {
+        unimplemented!()
+    }
+    fn get_session_state(
+        &self,
+        _user_id: &uuid::Uuid,
+    ) -> crate::Result<Option<crate::structs::SessionState>> {
+        unimplemented!()
+    }
+    fn update_session_state(
+        &self,
+        _user_id: &uuid::Uuid,
+        _state: &crate::structs::SessionState,
+    ) -> crate::Result<()> {
+        unimplemented!()
+    }
+    fn get_user_sessions(
+        &self,
+        _proof_keys: &Vec<String>,
+        _state: Option<crate::structs::SessionState>,
+        _offset: i64,
+        _limit: i64,
+    ) -> crate::Result<(Vec<crate::structs::UserSessionRecord>, i64)> {
+        unimplemented!()
+    }
 }
@@ -0,0 +1,224 @@
+//! Snapshot
+//!
+//! Consistent export and restore of the server state for point-in-time recovery.
+//!
+//! A snapshot contains every data table, including the SMT store and root history, exported
+//! with COPY in a single transaction. Writes to the tables are locked until the export is
+//! complete, so a snapshot can be taken while the server is running. A snapshot can only be
+//! restored into a DB at the schema version it was taken at, and the server must be stopped
+//! during the restore.
+
+use super::super::Result;
+use super::db::Table;
+use super::migrations::latest_version;
+use crate::config::Config;
+use crate::error::SEError;
+use crate::{Database, PGDatabase};
+
+use chrono::{NaiveDateTime, Utc};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+/// Tables with a BIGSERIAL id, whose sequence is reset after restore
+const SERIAL_TABLES: [Table; 2] = [Table::Root, Table::SignedSighash];
+
+/// Exported server state
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Snapshot {
+    /// Schema version of the exported tables
+    pub schema_version: i32,
+    pub created: NaiveDateTime,
+    pub tables: Vec<TableSnapshot>,
+}
+
+/// Table rows in Postgres COPY text format
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TableSnapshot {
+    pub table: String,
+    pub data: String,
+}
+
+impl Snapshot {
+    /// Write the snapshot archive to a file
+    pub fn write(&self, path: &str) -> Result<()> {
+        let file = File::create(path).map_err(|e| {
+            SEError::Generic(format!("Failed to create snapshot file {}: {}", path, e))
+        })?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)
+            .map_err(|e| SEError::Generic(format!("Failed to write snapshot: {}", e)))?;
+        writer
+            .flush()
+            .map_err(|e| SEError::Generic(format!("Failed to write snapshot: {}", e)))
+    }
+
+    /// Read a snapshot archive from a file
+    pub fn read(path: &str) -> Result<Self> {
+        let file = File::open(path).map_err(|e| {
+            SEError::Generic(format!("Failed to open snapshot file {}: {}", path, e))
+        })?;
+        let reader = BufReader::new(file);
+        serde_json::from_reader(reader)
+            .map_err(|e| SEError::Generic(format!("Failed to read snapshot: {}", e)))
+    }
+}
+
+fn table_list() -> String {
+    Table::data_tables()
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+impl PGDatabase {
+    /// Export all data tables. Writes are locked for the duration of the export.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let conn = self.database_w()?;
+        let trans = conn.transaction()?;
+        trans.batch_execute(&format!(
+            "SET TRANSACTION ISOLATION LEVEL REPEATABLE READ;
+            LOCK TABLE {} IN SHARE MODE;",
+            table_list()
+        ))?;
+
+        let schema_version: Option<i32> = trans
+            .query(
+                &format!(
+                    "SELECT max(version) FROM {}",
+                    Table::SchemaVersion.to_string()
+                ),
+                &[],
+            )?
+            .get(0)
+            .get(0);
+
+        let mut tables = vec![];
+        for table in Table::data_tables() {
+            let mut data = vec![];
+            trans
+                .prepare(&format!("COPY {} TO STDOUT", table.to_string()))?
+                .copy_out(&[], &mut data)?;
+            tables.push(TableSnapshot {
+                table: table.to_string(),
+                data: String::from_utf8(data).map_err(|e| {
+                    SEError::Generic(format!("Invalid data in table {}: {}", table.to_string(), e))
+                })?,
+            });
+        }
+        // Read-only: releases the locks
+        trans.commit()?;
+
+        Ok(Snapshot {
+            schema_version: schema_version.unwrap_or(0),
+            created: Utc::now().naive_utc(),
+            tables,
+        })
+    }
+
+    /// Replace the contents of all data tables with a snapshot
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<()> {
+        self.make_tables()?;
+        if snapshot.schema_version != latest_version() {
+            return Err(SEError::Generic(format!(
+                "Snapshot schema version {} does not match DB schema version {}",
+                snapshot.schema_version,
+                latest_version()
+            )));
+        }
+        let names: Vec<String> = Table::data_tables().iter().map(|t| t.to_string()).collect();
+        for t in &snapshot.tables {
+            if !names.contains(&t.table) {
+                return Err(SEError::Generic(format!(
+                    "Snapshot contains unknown table {}",
+                    t.table
+                )));
+            }
+        }
+
+        let conn = self.database_w()?;
+        let trans = conn.transaction()?;
+        trans.batch_execute(&format!(
+            "LOCK TABLE {0} IN ACCESS EXCLUSIVE MODE;
+            TRUNCATE {0} RESTART IDENTITY;",
+            table_list()
+        ))?;
+        for t in &snapshot.tables {
+            trans
+                .prepare(&format!("COPY {} FROM STDIN", t.table))?
+                .copy_in(&[], &mut t.data.as_bytes())?;
+        }
+        for table in SERIAL_TABLES.iter() {
+            trans.execute(
+                &format!(
+                    "SELECT setval(pg_get_serial_sequence('{0}', 'id'), COALESCE(max(id), 0) + 1, false) FROM {0}",
+                    table.to_string()
+                ),
+                &[],
+            )?;
+        }
+        trans.commit()?;
+        Ok(())
+    }
+}
+
+fn connect(config: &Config) -> Result<PGDatabase> {
+    let mut db = PGDatabase::get_new();
+    db.set_connection_from_config(config)?;
+    Ok(db)
+}
+
+/// Maintenance: write a snapshot of the DB configured in Settings.toml to a file
+pub fn run_snapshot(path: &str) -> Result<()> {
+    let db = connect(&Config::load()?)?;
+    let snapshot = db.snapshot()?;
+    snapshot.write(path)?;
+    info!(
+        "SNAPSHOT: wrote snapshot at schema version {} to {}",
+        snapshot.schema_version, path
+    );
+    Ok(())
+}
+
+/// Maintenance: restore the DB configured in Settings.toml from a snapshot file. The server
+/// must not be running.
+pub fn run_restore(path: &str) -> Result<()> {
+    let db = connect(&Config::load()?)?;
+    let snapshot = Snapshot::read(path)?;
+    db.restore(&snapshot)?;
+    info!(
+        "SNAPSHOT: restored snapshot taken at {} from {}",
+        snapshot.created, path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_write_read() {
+        let snapshot = Snapshot {
+            schema_version: latest_version(),
+            created: Utc::now().naive_utc(),
+            tables: vec![TableSnapshot {
+                table: Table::Root.to_string(),
+                data: String::from("1\tvalue\t\\N\n2\tvalue2\t\\N\n"),
+            }],
+        };
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        snapshot.write(path).unwrap();
+        assert_eq!(Snapshot::read(path).unwrap(), snapshot);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_data_tables() {
+        let tables = table_list();
+        assert!(tables.contains(&Table::Smt.to_string()));
+        assert!(tables.contains(&Table::Root.to_string()));
+        assert!(!tables.contains(&Table::SchemaVersion.to_string()));
+    }
+}
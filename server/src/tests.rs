@@ -3,10 +3,11 @@ mod tests {
     extern crate shared_lib;
     use super::super::server;
     use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::*;
-    use rocket::http::{ContentType, Status};
+    use rocket::http::{ContentType, Header, Status};
     use rocket::local::Client;
     use shared_lib::structs::{
         DepositMsg1, KeyGenMsg1, Protocol, SmtProofMsgAPI, StateEntityFeeInfoAPI, RecoveryRequest,
+        UserID,
     };
     use shared_lib::{mainstay, Root};
 
@@ -29,6 +30,8 @@ mod tests {
         let deposit_msg1 = DepositMsg1 {
             auth: String::from("auth"),
             proof_key: String::from("proof key"),
+            payer_proof_key: None,
+            fee_quote_hash: None,
         };
         let body = serde_json::to_string(&deposit_msg1).unwrap();
         let mut response = client
@@ -132,6 +135,7 @@ mod tests {
             funding_txid: String::from(
                 "c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e",
             ),
+            proof_format: Default::default(),
         };
         let body = serde_json::to_string(&smt_proof_msg).unwrap();
         let mut response = client
@@ -173,6 +177,7 @@ mod tests {
             funding_txid: String::from(
                 "c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e",
             ),
+            proof_format: Default::default(),
         };
         let body = serde_json::to_string(&smt_proof_msg).unwrap();
         let mut response = client
@@ -248,4 +253,47 @@ mod tests {
         assert_eq!(resp.deposit, 300);
         assert_eq!(resp.withdraw, 300);
     }
+
+    #[test]
+    #[serial]
+    fn test_cbor_payload() {
+        let mainstay_config = mainstay::MainstayConfig::mock_from_url(&mockito::server_url());
+        let client = Client::new(server::get_server(Some(mainstay_config)).unwrap()).expect("valid rocket instance");
+
+        // CBOR response to GET
+        let mut response = client
+            .get("/info/fee")
+            .header(Header::new("Accept", "application/cbor"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::new("application", "cbor")));
+        let resp: StateEntityFeeInfoAPI =
+            serde_cbor::from_slice(&response.body_bytes().unwrap()).unwrap();
+        assert_eq!(resp.withdraw, 300);
+
+        // CBOR request and response to POST
+        let deposit_msg1 = DepositMsg1 {
+            auth: String::from("auth"),
+            proof_key: String::from("proof key"),
+            payer_proof_key: None,
+            fee_quote_hash: None,
+        };
+        let mut response = client
+            .post("/deposit/init")
+            .body(serde_cbor::to_vec(&deposit_msg1).unwrap())
+            .header(Header::new("Content-Type", "application/cbor"))
+            .header(Header::new("Accept", "application/cbor"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let user_id: UserID = serde_cbor::from_slice(&response.body_bytes().unwrap()).unwrap();
+
+        // JSON remains the default
+        let mut response = client
+            .post("/deposit/init")
+            .body(serde_json::to_string(&deposit_msg1).unwrap())
+            .header(ContentType::JSON)
+            .dispatch();
+        let user_id_json: UserID = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_ne!(user_id.id, user_id_json.id);
+    }
 }
@@ -1,12 +1,30 @@
 pub use super::Result;
 extern crate shared_lib;
-use crate::config::Config;
+use crate::config::{Config, SpendResponse};
+use crate::structs::BackupTxID;
 use std::{thread, time};
 use crate::Database;
+use crate::protocol::chain_height::ChainHeight;
+use crate::protocol::watchtower::{decrypt_backup_tx, watchtower_key};
 use bitcoincore_rpc::Error;
 use bitcoin::consensus;
 use jsonrpc;
 use cfg_if::cfg_if;
+use shared_lib::structs::{SpendIncident, TimelockMode};
+use shared_lib::util::{backup_tx_timelock, backup_tx_timelock_mode};
+use std::sync::Arc;
+use chrono::Utc;
+use uuid::Uuid;
+
+cfg_if! {
+    if #[cfg(any(test,feature="mockbitcoinrpc"))]{
+        use shared_lib::mocks::mock_rpc_client::MockBitcoinClient;
+        type Rpc = MockBitcoinClient;
+    } else {
+        use bitcoincore_rpc::{Client, RpcApi};
+        type Rpc = Client;
+    }
+}
 
 cfg_if! {
     if #[cfg(any(test))]{
@@ -16,7 +34,9 @@ cfg_if! {
     }
 }
 
-pub fn watch_node(rpc_path: String) -> Result<()> {
+/// Broadcast backup txs once valid, keeping the state entity block height up to date, and flag
+/// statechains whose funding output is spent by a tx other than their backup tx
+pub fn watch_node(rpc_path: String, chain_height: Arc<ChainHeight>) -> Result<()> {
 
     let config_rs = Config::load().unwrap();
 
@@ -25,8 +45,8 @@ pub fn watch_node(rpc_path: String) -> Result<()> {
             use crate::MockDatabase;
             let mut tx_db = MockDatabase::new();
         } else {
-            use crate::PGDatabase;
-            let mut tx_db = PGDatabase::get_new();
+            use crate::ServerDatabase;
+            let mut tx_db = ServerDatabase::get_new();
         }
     }
 
@@ -47,25 +67,29 @@ pub fn watch_node(rpc_path: String) -> Result<()> {
 
     cfg_if! {
         if #[cfg(any(test,feature="mockbitcoinrpc"))]{
-            use shared_lib::mocks::mock_rpc_client::MockBitcoinClient;
             let mut rpc = MockBitcoinClient::new();
         } else {
-            use bitcoincore_rpc::{Auth, Client, RpcApi};
-            let rpc = Client::new(rpc_path_parts[1].to_string(),
+            use bitcoincore_rpc::Auth;
+            let mut rpc = Client::new(rpc_path_parts[1].to_string(),
                           Auth::UserPass(rpc_cred[0].to_string(),
                                          rpc_cred[1].to_string())).unwrap();
         }
     }
 
+    // backup txs registered with the watchtower are broadcast if it is enabled
+    let watchtower_key = watchtower_key(&config_rs.watchtower).ok();
+
     // main watch loop
     loop {
         // get current block height
         let bestblockcount = rpc.get_block_count();
         let blocks = bestblockcount.unwrap() as i64;
+        chain_height.set(blocks as u64);
 
         debug!("WATCH: Bitcoin block height {}", blocks);
 
         //get all backup transactions with loctimes less than or equal to the current block height
+        //(relative timelock backup txs have nLockTime 0 and are always returned)
         let txs = tx_db.get_current_backup_txs(blocks).unwrap();
 
         debug!("WATCH: Stored backup txs now valid {}", txs.len().to_string() );
@@ -74,6 +98,29 @@ pub fn watch_node(rpc_path: String) -> Result<()> {
         for tx in &txs {
             debug!("WATCH: TxID: {}",consensus::encode::serialize_hex(&tx.tx.txid()));
 
+            // relative timelock: wait until the funding output has enough confirmations
+            if backup_tx_timelock_mode(&tx.tx) == TimelockMode::Relative {
+                let funding = &tx.tx.input[0].previous_output;
+                match rpc.get_tx_out(&funding.txid, funding.vout, Some(false)) {
+                    Ok(Some(txout)) => {
+                        if txout.confirmations < backup_tx_timelock(&tx.tx) {
+                            continue;
+                        }
+                    }
+                    // funding output spent: broadcast to check if backup tx confirmed
+                    Ok(None) => (),
+                    Err(e) => {
+                        info!(
+                            "Error getting funding output {} for backup tx {} {}",
+                            funding,
+                            tx.tx.txid(),
+                            e
+                        );
+                        continue;
+                    }
+                }
+            }
+
             let txinfo = rpc.send_raw_transaction(&consensus::serialize(&tx.tx));
 
             match txinfo {
@@ -82,7 +129,6 @@ pub fn watch_node(rpc_path: String) -> Result<()> {
                         "Backup transaction txid {} successfully broadcast.",
                         ret
                     );
-                    continue;
                 }
                 Err(Error::JsonRpc(jsonrpc::error::Error::Rpc(ref rpcerr)))
                     if rpcerr.code == -27 =>  // "transaction already in block chain"
@@ -101,11 +147,75 @@ pub fn watch_node(rpc_path: String) -> Result<()> {
                         "Error sending backup tx {} {}",
                         tx.tx.txid(),e
                     );
-                    continue;
+                }
+            }
+
+            // broadcast CPFP child to bump the backup tx fee
+            if let Some(cpfp) = &tx.cpfp {
+                match rpc.send_raw_transaction(&consensus::serialize(cpfp)) {
+                    Ok(ret) => info!(
+                        "CPFP transaction txid {} for backup txid {} successfully broadcast.",
+                        ret,
+                        tx.tx.txid()
+                    ),
+                    Err(e) => info!(
+                        "Error sending CPFP tx {} for backup tx {} {}",
+                        cpfp.txid(),
+                        tx.tx.txid(),
+                        e
+                    ),
+                }
+            }
+        }
+
+        if let Some(key) = &watchtower_key {
+            let registrations = tx_db.get_due_watchtower_txs(blocks)?;
+
+            debug!("WATCH: Watchtower backup txs now valid {}", registrations.len());
+
+            for registration in &registrations {
+                let tx = match registration
+                    .backup_tx
+                    .as_ref()
+                    .map(|tx| decrypt_backup_tx(key, tx))
+                {
+                    Some(Ok(tx)) => tx,
+                    Some(Err(e)) => {
+                        info!(
+                            "Error decrypting watchtower backup tx. ID {} {}",
+                            registration.id, e
+                        );
+                        continue;
+                    }
+                    None => continue,
+                };
+
+                match rpc.send_raw_transaction(&consensus::serialize(&tx)) {
+                    Ok(ret) => info!(
+                        "Watchtower backup transaction txid {} successfully broadcast. ID {}",
+                        ret, registration.id
+                    ),
+                    Err(Error::JsonRpc(jsonrpc::error::Error::Rpc(ref rpcerr)))
+                        if rpcerr.code == -27 =>
+                    {
+                        tx_db.remove_watchtower_tx(&registration.id)?;
+                        info!(
+                            "Watchtower backup txid {} already confirmed. ID {} removed from Watchtower database.",
+                            tx.txid(),
+                            registration.id
+                        );
+                    }
+                    Err(e) => info!(
+                        "Error sending watchtower backup tx {} {}",
+                        tx.txid(),
+                        e
+                    ),
                 }
             }
         }
 
+        detect_funding_spends(&tx_db, &mut rpc, blocks as u64, config_rs.spend_response)?;
+
         thread::sleep(interval);
 
         cfg_if! {
@@ -118,6 +228,122 @@ pub fn watch_node(rpc_path: String) -> Result<()> {
     }
 }
 
+/// Flag the statechains whose funding output is spent, in the mempool or a block, by a tx other
+/// than their current backup tx, e.g. an old backup tx broadcast by a previous owner, and respond
+/// to the spend. Return the ids of the statechains flagged.
+fn detect_funding_spends<D: Database>(
+    tx_db: &D,
+    rpc: &mut Rpc,
+    height: u64,
+    response: SpendResponse,
+) -> Result<Vec<Uuid>> {
+    let mut flagged = vec![];
+    // all backup txs: those of live statecoins
+    for tx in &tx_db.get_current_backup_txs(i64::MAX)? {
+        let funding = tx.tx.input[0].previous_output;
+        match rpc.get_tx_out(&funding.txid, funding.vout, Some(true)) {
+            Ok(Some(_)) => continue,
+            Ok(None) => (),
+            Err(e) => {
+                info!(
+                    "Error getting funding output {} of statechain {} {}",
+                    funding, tx.id, e
+                );
+                continue;
+            }
+        }
+        // Unconfirmed deposits may not have been broadcast
+        if !tx_db.is_confirmed(&tx.id)? {
+            continue;
+        }
+        // An output of the backup tx exists if it spent the funding output
+        let backup_txid = tx.tx.txid();
+        let backup_spend = (0..tx.tx.output.len() as u32).any(|vout| {
+            matches!(rpc.get_tx_out(&backup_txid, vout, Some(true)), Ok(Some(_)))
+        });
+        if backup_spend {
+            continue;
+        }
+        if let Some(incident) = tx_db.get_spend_incident(&tx.id)? {
+            // Retry the response until the funding output is settled: the backup tx is rejected
+            // until its timelock expires, and can only replace the spending tx while that is
+            // unconfirmed
+            if response == SpendResponse::Backup && !funding_spend_confirmed(rpc, &funding) {
+                if let Some(txid) = broadcast_backup_response(rpc, tx) {
+                    if incident.response_txid.as_ref() != Some(&txid) {
+                        tx_db.update_spend_incident_response(&tx.id, &txid)?;
+                    }
+                }
+            }
+            continue;
+        }
+        warn!(
+            "WATCH: Funding output {} of statechain {} spent by a tx other than its backup tx",
+            funding, tx.id
+        );
+        let response_txid = match response {
+            SpendResponse::None => None,
+            SpendResponse::Backup => broadcast_backup_response(rpc, tx),
+        };
+        tx_db.insert_spend_incident(&SpendIncident {
+            statechain_id: tx.id,
+            funding,
+            height,
+            detected_at: Utc::now().naive_utc(),
+            response_txid,
+        })?;
+        flagged.push(tx.id);
+    }
+    Ok(flagged)
+}
+
+/// The funding output is spent by a tx in a block, not only in the mempool
+fn funding_spend_confirmed(rpc: &mut Rpc, funding: &bitcoin::OutPoint) -> bool {
+    !matches!(rpc.get_tx_out(&funding.txid, funding.vout, Some(false)), Ok(Some(_)))
+}
+
+/// Broadcast the current backup tx of a statechain whose funding output is spent by another tx,
+/// followed by its CPFP child if one was submitted. The node rejects the backup tx if its
+/// timelock has not expired, or if it cannot replace the spending tx. Return the backup txid if
+/// it was accepted. Every outcome is recorded in the audit log.
+fn broadcast_backup_response(rpc: &mut Rpc, tx: &BackupTxID) -> Option<String> {
+    let txid = match rpc.send_raw_transaction(&consensus::serialize(&tx.tx)) {
+        Ok(txid) => txid.to_string(),
+        Err(e) => {
+            info!(
+                target: "audit",
+                "SPEND RESPONSE: Backup tx {} of statechain {} rejected: {}",
+                tx.tx.txid(),
+                tx.id,
+                e
+            );
+            return None;
+        }
+    };
+    info!(
+        target: "audit",
+        "SPEND RESPONSE: Backup tx {} of statechain {} broadcast",
+        txid, tx.id
+    );
+    if let Some(cpfp) = &tx.cpfp {
+        match rpc.send_raw_transaction(&consensus::serialize(cpfp)) {
+            Ok(ret) => info!(
+                target: "audit",
+                "SPEND RESPONSE: CPFP tx {} of statechain {} broadcast",
+                ret, tx.id
+            ),
+            Err(e) => info!(
+                target: "audit",
+                "SPEND RESPONSE: CPFP tx {} of statechain {} rejected: {}",
+                cpfp.txid(),
+                tx.id,
+                e
+            ),
+        }
+    }
+    Some(txid)
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -125,8 +351,8 @@ pub mod tests {
     use std::str::FromStr;
     use crate::MockDatabase;
     use uuid::Uuid;
-    use crate::structs::BackupTxID;
     use shared_lib::{mocks::mock_rpc_client::MockBitcoinClient};
+    use std::sync::Mutex;
     use bitcoin::consensus::encode;
 
     #[test]
@@ -140,8 +366,8 @@ pub mod tests {
         let id_1 = Uuid::from_str("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
         let id_2 = Uuid::from_str("93ad2134-ffd3-869d-beef-8da52c985aa1").unwrap();
 
-        let backup_1 = BackupTxID { tx: backup_tx_1, id: id_1 };
-        let backup_2 = BackupTxID { tx: backup_tx_2, id: id_2 };
+        let backup_1 = BackupTxID { tx: backup_tx_1, id: id_1, cpfp: None };
+        let backup_2 = BackupTxID { tx: backup_tx_2, id: id_2, cpfp: None };
         let mut backup_txs: Vec<BackupTxID> = Vec::new();
         backup_txs.push(backup_1);
         backup_txs.push(backup_2);
@@ -154,4 +380,88 @@ pub mod tests {
         assert_eq!(rpc.get_block_count().unwrap(), 147 as u64);
 
     }
+
+    #[test]
+    fn test_detect_funding_spends() {
+        let backup_tx: Transaction = encode::deserialize(&hex::decode("02000000000101b91e2b8e26ae7f93cea773c5d74f7722982134ebbf32ca9b627981a5546ef4c7000000001716001472d64fcb0be3dff555fc87b3d054a1ccb48ac059feffffff0200c2eb0b0000000017a9141040c0c1b81e2e00aec47ef01c2d3a6116ca513d8748b723180100000017a914d5dd335a7721cf03b1f5df5bdf22c63c0e1e472887024730440220167f84b7e579153ff83a480eadc4225ad1c67322ad0e8d5f32d317ce61a6c26802206fff7f176b6780f00cf9d63ea759658e4ca0302dc2204c02bf3ee52e032e051001210297fd944ebb0de31b629a99a14d53fb8c83e5791f714892f72b74751cfd097c1765000000").unwrap()).unwrap();
+        let live = Uuid::new_v4();
+        let unconfirmed = Uuid::new_v4();
+        let flagged = Uuid::new_v4();
+        let backup_txs: Vec<BackupTxID> = vec![live, unconfirmed, flagged]
+            .into_iter()
+            .map(|id| BackupTxID { tx: backup_tx.clone(), id, cpfp: None })
+            .collect();
+
+        // The mock node reports every output spent
+        let incidents = Arc::new(Mutex::new(vec![]));
+        let mut db = MockDatabase::new();
+        db.expect_get_current_backup_txs()
+            .returning(move |_| Ok(backup_txs.clone()));
+        db.expect_is_confirmed()
+            .returning(move |id| Ok(*id != unconfirmed));
+        db.expect_get_spend_incident().returning(move |id| {
+            Ok(match *id == flagged {
+                true => Some(SpendIncident {
+                    statechain_id: flagged,
+                    funding: backup_tx.input[0].previous_output,
+                    height: 100,
+                    detected_at: Utc::now().naive_utc(),
+                    response_txid: None,
+                }),
+                false => None,
+            })
+        });
+        let inserted = incidents.clone();
+        db.expect_insert_spend_incident().returning(move |incident| {
+            inserted.lock().unwrap().push(incident.clone());
+            Ok(())
+        });
+        let mut rpc = MockBitcoinClient::new();
+
+        assert_eq!(
+            detect_funding_spends(&db, &mut rpc, 147, SpendResponse::None).unwrap(),
+            vec![live]
+        );
+        let incident = incidents.lock().unwrap().pop().unwrap();
+        assert_eq!(incident.statechain_id, live);
+        assert_eq!(incident.height, 147);
+        assert_eq!(incident.response_txid, None);
+
+        // The backup tx is broadcast in response
+        assert_eq!(
+            detect_funding_spends(&db, &mut rpc, 147, SpendResponse::Backup).unwrap(),
+            vec![live]
+        );
+        let incident = incidents.lock().unwrap().pop().unwrap();
+        assert_eq!(
+            incident.response_txid,
+            Some(String::from(
+                "e3d514ad83995f5c3407a3a6317355fe22d3b24ab2e89455f9db504e3bfb3c86"
+            ))
+        );
+
+        // The response to a flagged spend is retried while the spending tx is unconfirmed
+        let responses = Arc::new(Mutex::new(vec![]));
+        let updated = responses.clone();
+        db.expect_update_spend_incident_response()
+            .returning(move |id, txid| {
+                updated.lock().unwrap().push((*id, txid.clone()));
+                Ok(())
+            });
+        rpc.unconfirmed_spends = true;
+        assert_eq!(
+            detect_funding_spends(&db, &mut rpc, 148, SpendResponse::Backup).unwrap(),
+            vec![live]
+        );
+        assert_eq!(
+            *responses.lock().unwrap(),
+            vec![(
+                flagged,
+                String::from("e3d514ad83995f5c3407a3a6317355fe22d3b24ab2e89455f9db504e3bfb3c86")
+            )]
+        );
+        // No response is retried if responses are disabled
+        detect_funding_spends(&db, &mut rpc, 148, SpendResponse::None).unwrap();
+        assert_eq!(responses.lock().unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,71 @@
+//! SMT benchmarks
+//!
+//! Statechain SMT insertion and inclusion proof generation in trees of 10k, 100k and 1M leaves.
+
+#[macro_use]
+extern crate criterion;
+
+use bitcoin_hashes::{hex::ToHex, sha256d, Hash};
+use criterion::{BenchmarkId, Criterion};
+use monotree::{database::MemoryDB, hasher::Blake3, Monotree};
+use shared_lib::state_chain::{gen_proof_smt, update_statechain_smt};
+use std::sync::{Arc, Mutex};
+
+const SIZES: [u64; 3] = [10_000, 100_000, 1_000_000];
+
+/// Number of distinct new leaves inserted by the insert benchmark
+const NEW_LEAVES: u64 = 1000;
+
+/// Statecoin amount committed in each leaf
+const AMOUNT: u64 = 100_000;
+
+/// Funding txid-like SMT key
+fn funding_txid(i: u64) -> String {
+    sha256d::Hash::hash(&i.to_le_bytes()).to_hex()
+}
+
+/// Proof key-like SMT entry
+fn proof_key(i: u64) -> String {
+    format!("02{}", sha256d::Hash::hash(&(!i).to_le_bytes()).to_hex())
+}
+
+/// Tree with size leaves and its root
+fn tree(size: u64) -> (Arc<Mutex<Monotree<MemoryDB, Blake3>>>, Option<monotree::Hash>) {
+    let tree = Arc::new(Mutex::new(Monotree::<MemoryDB, Blake3>::new("")));
+    let mut root = None;
+    for i in 0..size {
+        root = update_statechain_smt(tree.clone(), &root, &funding_txid(i), &proof_key(i), AMOUNT)
+            .unwrap();
+    }
+    (tree, root)
+}
+
+fn bench_smt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("smt");
+    group.sample_size(10);
+    for size in SIZES.iter() {
+        let (tree, root) = tree(*size);
+
+        // Each insert is made against the same root, so the tree size is unchanged
+        let new_leaves: Vec<(String, String)> = (size + 1..=size + NEW_LEAVES)
+            .map(|i| (funding_txid(i), proof_key(i)))
+            .collect();
+        let mut next = new_leaves.iter().cycle();
+        group.bench_function(BenchmarkId::new("insert", size), |b| {
+            b.iter(|| {
+                let (txid, entry) = next.next().unwrap();
+                update_statechain_smt(tree.clone(), &root, txid, entry, AMOUNT).unwrap()
+            })
+        });
+
+        let txids: Vec<String> = (0..NEW_LEAVES).map(|i| funding_txid(i * size / NEW_LEAVES)).collect();
+        let mut next = txids.iter().cycle();
+        group.bench_function(BenchmarkId::new("proof", size), |b| {
+            b.iter(|| gen_proof_smt(tree.clone(), &root, next.next().unwrap()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_smt);
+criterion_main!(benches);
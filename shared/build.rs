@@ -0,0 +1,5 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/state_entity.proto")
+        .expect("failed to compile state entity protos");
+}
@@ -1,11 +1,22 @@
 //! Commitment
 //!
 //! Make Commitment to some data. Reveal nonce to verify.
+//!
+//! Pay-to-contract: commit to data in the funding output key of a statecoin. The shared public
+//! key P generated at deposit is tweaked to t*P, where t = sha256(P || c) for a 32 byte
+//! commitment c, e.g. the hash of the deposit proof key. The owner multiplies its key share by t
+//! and the State Entity multiplies its copy of the owner public share, so that the State Entity
+//! key share is unchanged. Given P and c anyone can check that the funding output commits to c.
 
 use super::Result;
 use crate::error::SharedLibError;
-use bitcoin::hashes::{sha256d, Hash};
+use crate::structs::PayToContract;
+use bitcoin::hashes::{hex::FromHex, sha256, sha256d, Hash};
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bitcoin::{Address, Network, PublicKey};
+use curv::{elliptic::curves::traits::ECScalar, FE};
 use rand::random;
+use std::str::FromStr;
 
 // Generate random nonce and return hash of data+nonce
 pub fn make_commitment(data: &String) -> (String, [u8; 32]) {
@@ -33,9 +44,67 @@ pub fn verify_commitment(hash: &String, data: &String, nonce: &[u8; 32]) -> Resu
     )))
 }
 
+/// Pay-to-contract commitment to a proof key: sha256 hash of the serialized key
+pub fn proof_key_commitment(proof_key: &PublicKey) -> String {
+    sha256::Hash::hash(&proof_key.to_bytes()).to_string()
+}
+
+/// Pay-to-contract tweak t = sha256(P || c) of shared public key P for commitment c
+pub fn p2c_tweak(shared_pubkey: &PublicKey, commitment: &String) -> Result<SecretKey> {
+    let commitment = sha256::Hash::from_hex(commitment)
+        .map_err(|e| SharedLibError::FormatError(format!("Invalid commitment: {}", e)))?;
+    let mut data = shared_pubkey.to_bytes();
+    data.extend_from_slice(&commitment[..]);
+    Ok(SecretKey::from_slice(&sha256::Hash::hash(&data)[..])?)
+}
+
+/// Pay-to-contract tweak as a curv scalar, to multiply 2P-ECDSA key shares by
+pub fn p2c_tweak_fe(shared_pubkey: &PublicKey, commitment: &String) -> Result<FE> {
+    let mut tweak: FE = ECScalar::zero();
+    tweak.set_element(p2c_tweak(shared_pubkey, commitment)?);
+    Ok(tweak)
+}
+
+/// Tweaked public key t*P of shared public key P for commitment c
+pub fn p2c_tweak_pubkey(shared_pubkey: &PublicKey, commitment: &String) -> Result<PublicKey> {
+    let tweak = p2c_tweak(shared_pubkey, commitment)?;
+    let mut tweaked = shared_pubkey.clone();
+    tweaked
+        .key
+        .mul_assign(&Secp256k1::verification_only(), &tweak[..])?;
+    Ok(tweaked)
+}
+
+/// Check that a funding output key commits to a pay-to-contract commitment
+pub fn verify_p2c(p2c: &PayToContract, funding_pubkey: &PublicKey) -> Result<()> {
+    let shared_pubkey = PublicKey::from_str(&p2c.shared_pubkey)
+        .map_err(|e| SharedLibError::FormatError(format!("Invalid shared public key: {}", e)))?;
+    if &p2c_tweak_pubkey(&shared_pubkey, &p2c.commitment)? != funding_pubkey {
+        return Err(SharedLibError::Generic(String::from(
+            "Pay-to-contract commitment verification failed.",
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a P2WPKH funding output address commits to a pay-to-contract commitment
+pub fn verify_p2c_address(p2c: &PayToContract, address: &Address, network: Network) -> Result<()> {
+    let shared_pubkey = PublicKey::from_str(&p2c.shared_pubkey)
+        .map_err(|e| SharedLibError::FormatError(format!("Invalid shared public key: {}", e)))?;
+    let funding_pubkey = p2c_tweak_pubkey(&shared_pubkey, &p2c.commitment)?;
+    if &Address::p2wpkh(&funding_pubkey, network)? != address {
+        return Err(SharedLibError::Generic(String::from(
+            "Pay-to-contract commitment verification failed.",
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use curv::elliptic::curves::traits::ECPoint;
+    use curv::GE;
     #[test]
     fn test_make_commitment() {
         let data = String::from("12345");
@@ -43,4 +112,47 @@ mod tests {
         println!("commitment: {:?}, nonce: {:?}", comm, nonce);
         assert!(verify_commitment(&comm, &data, &nonce).is_ok());
     }
+
+    #[test]
+    fn test_p2c() {
+        let secp = Secp256k1::new();
+        let proof_key = PublicKey {
+            compressed: true,
+            key: bitcoin::secp256k1::PublicKey::from_secret_key(
+                &secp,
+                &SecretKey::from_slice(&[1; 32]).unwrap(),
+            ),
+        };
+        let shared_pubkey = PublicKey {
+            compressed: true,
+            key: bitcoin::secp256k1::PublicKey::from_secret_key(
+                &secp,
+                &SecretKey::from_slice(&[2; 32]).unwrap(),
+            ),
+        };
+        let p2c = PayToContract {
+            shared_pubkey: shared_pubkey.to_string(),
+            commitment: proof_key_commitment(&proof_key),
+        };
+        let funding_pubkey = p2c_tweak_pubkey(&shared_pubkey, &p2c.commitment).unwrap();
+        assert!(verify_p2c(&p2c, &funding_pubkey).is_ok());
+        assert!(verify_p2c(&p2c, &shared_pubkey).is_err());
+        let address = Address::p2wpkh(&funding_pubkey, Network::Regtest).unwrap();
+        assert!(verify_p2c_address(&p2c, &address, Network::Regtest).is_ok());
+
+        // Commitment to another proof key
+        let other = PayToContract {
+            shared_pubkey: shared_pubkey.to_string(),
+            commitment: proof_key_commitment(&shared_pubkey),
+        };
+        assert!(verify_p2c(&other, &funding_pubkey).is_err());
+        assert!(verify_p2c_address(&other, &address, Network::Regtest).is_err());
+
+        // Multiplying a key share by the tweak multiplies the shared key by the tweak
+        let mut share: FE = ECScalar::zero();
+        share.set_element(SecretKey::from_slice(&[2; 32]).unwrap());
+        let tweak = p2c_tweak_fe(&shared_pubkey, &p2c.commitment).unwrap();
+        let g: GE = ECPoint::generator();
+        assert_eq!((g * (share * tweak)).get_element(), funding_pubkey.key);
+    }
 }
@@ -0,0 +1,221 @@
+//! Electrum batch queries
+//!
+//! The Electrumx interface makes one request per address, so scanning the balances of a wallet
+//! with many addresses takes as many round trips to the Electrum server. Electrum servers accept
+//! JSON-RPC batches: the queries of many script hashes are sent in a single request and answered
+//! in a single response.
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::Script;
+use electrumx_client::response::{GetBalanceResponse, GetHistoryResponse};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+type BatchResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Maximum number of requests sent in one batch
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Electrum script hash of an output script: its sha256 hash in reversed byte order, hex encoded
+pub fn script_hash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).into_inner();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// Queries of many script hashes at once. Results are in the order of the script hashes.
+pub trait ElectrumxBatch {
+    /// Subscribe to script hashes. Returns the status of each, None if it has no history.
+    fn batch_script_subscribe(
+        &mut self,
+        script_hashes: &[String],
+    ) -> BatchResult<Vec<Option<String>>>;
+
+    /// Confirmed and unconfirmed balance of each script hash
+    fn batch_script_get_balance(
+        &mut self,
+        script_hashes: &[String],
+    ) -> BatchResult<Vec<GetBalanceResponse>>;
+
+    /// Confirmed and mempool transactions of each script hash
+    fn batch_script_get_history(
+        &mut self,
+        script_hashes: &[String],
+    ) -> BatchResult<Vec<Vec<GetHistoryResponse>>>;
+}
+
+/// Electrum client sending batched requests on its own connection to the server
+pub struct ElectrumBatchClient {
+    stream: BufReader<TcpStream>,
+    next_id: u64,
+}
+
+impl ElectrumBatchClient {
+    pub fn new(electrum_server: String) -> BatchResult<Self> {
+        let stream = TcpStream::connect(electrum_server)?;
+        Ok(ElectrumBatchClient {
+            stream: BufReader::new(stream),
+            next_id: 0,
+        })
+    }
+
+    /// Call method with each script hash, in batches of at most MAX_BATCH_SIZE requests
+    fn batch_call(&mut self, method: &str, script_hashes: &[String]) -> BatchResult<Vec<Value>> {
+        let mut results = Vec::with_capacity(script_hashes.len());
+        for chunk in script_hashes.chunks(MAX_BATCH_SIZE) {
+            let first_id = self.next_id;
+            self.next_id += chunk.len() as u64;
+            let requests: Vec<Value> = chunk
+                .iter()
+                .zip(first_id..)
+                .map(|(script_hash, id)| {
+                    json!({"jsonrpc": "2.0", "id": id, "method": method, "params": [script_hash]})
+                })
+                .collect();
+            let mut request = serde_json::to_string(&requests)?;
+            request.push('\n');
+            self.stream.get_mut().write_all(request.as_bytes())?;
+
+            let mut responses = self.read_batch_response()?;
+            for id in first_id..self.next_id {
+                let mut response = responses
+                    .remove(&id)
+                    .ok_or(format!("Electrum {} response missing for id {}", method, id))?;
+                match response.get("error") {
+                    None | Some(Value::Null) => (),
+                    Some(error) => return Err(format!("Electrum {} error: {}", method, error).into()),
+                }
+                results.push(response["result"].take());
+            }
+        }
+        Ok(results)
+    }
+
+    /// Read the responses to a batch by id, skipping notifications of subscriptions
+    fn read_batch_response(&mut self) -> BatchResult<HashMap<u64, Value>> {
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line)? == 0 {
+                return Err("Electrum server closed the connection".into());
+            }
+            match serde_json::from_str::<Value>(&line)? {
+                Value::Array(responses) => {
+                    return Ok(responses
+                        .into_iter()
+                        .filter_map(|r| r["id"].as_u64().map(|id| (id, r)))
+                        .collect())
+                }
+                Value::Object(ref notification) if notification.contains_key("method") => continue,
+                response => {
+                    return Err(format!("Unexpected Electrum batch response: {}", response).into())
+                }
+            }
+        }
+    }
+}
+
+impl ElectrumxBatch for ElectrumBatchClient {
+    fn batch_script_subscribe(
+        &mut self,
+        script_hashes: &[String],
+    ) -> BatchResult<Vec<Option<String>>> {
+        Ok(self
+            .batch_call("blockchain.scripthash.subscribe", script_hashes)?
+            .into_iter()
+            .map(|status| status.as_str().map(String::from))
+            .collect())
+    }
+
+    fn batch_script_get_balance(
+        &mut self,
+        script_hashes: &[String],
+    ) -> BatchResult<Vec<GetBalanceResponse>> {
+        self.batch_call("blockchain.scripthash.get_balance", script_hashes)?
+            .into_iter()
+            .map(|balance| Ok(serde_json::from_value(balance)?))
+            .collect()
+    }
+
+    fn batch_script_get_history(
+        &mut self,
+        script_hashes: &[String],
+    ) -> BatchResult<Vec<Vec<GetHistoryResponse>>> {
+        self.batch_call("blockchain.scripthash.get_history", script_hashes)?
+            .into_iter()
+            .map(|history| Ok(serde_json::from_value(history)?))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Address;
+    use std::net::TcpListener;
+    use std::str::FromStr;
+    use std::thread;
+
+    #[test]
+    fn test_script_hash() {
+        // Example of the Electrum protocol documentation
+        let address = Address::from_str("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa").unwrap();
+        assert_eq!(
+            script_hash(&address.script_pubkey()),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+    }
+
+    /// Electrum server answering each batch with a notification followed by the responses in
+    /// reverse order, with balances numbered by request id
+    fn batch_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let requests: Vec<Value> = serde_json::from_str(&line).unwrap();
+                let responses: Vec<Value> = requests
+                    .iter()
+                    .rev()
+                    .map(|r| match r["params"][0].as_str() {
+                        Some("bad") => json!({"id": r["id"], "error": {"code": 1, "message": "bad"}}),
+                        _ => json!({"id": r["id"], "result": {"confirmed": r["id"], "unconfirmed": 0}}),
+                    })
+                    .collect();
+                let notification =
+                    json!({"method": "blockchain.scripthash.subscribe", "params": ["a", "b"]});
+                writeln!(writer, "{}", notification).unwrap();
+                writeln!(writer, "{}", Value::Array(responses)).unwrap();
+                line.clear();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_batch_call() {
+        let mut client = ElectrumBatchClient::new(batch_server()).unwrap();
+        let script_hashes: Vec<String> = (0..MAX_BATCH_SIZE + 5).map(|i| i.to_string()).collect();
+        let balances = client.batch_script_get_balance(&script_hashes).unwrap();
+        assert_eq!(balances.len(), script_hashes.len());
+        for (i, balance) in balances.iter().enumerate() {
+            assert_eq!(balance.confirmed as usize, i);
+        }
+
+        // Ids continue across calls
+        let balances = client
+            .batch_script_get_balance(&[String::from("a")])
+            .unwrap();
+        assert_eq!(balances[0].confirmed as usize, MAX_BATCH_SIZE + 5);
+
+        let err = client
+            .batch_script_get_balance(&[String::from("a"), String::from("bad")])
+            .unwrap_err();
+        assert!(err.to_string().contains("bad"));
+    }
+}
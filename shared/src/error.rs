@@ -2,6 +2,7 @@ use bitcoin::secp256k1::Error as SecpError;
 use bitcoin::util::address::Error as AddressError;
 use monotree::Errors as MonotreeErrors;
 use reqwest::Error as ReqwestError;
+use statechain_verify::error::VerifyError;
 
 use serde_json::Error as SerdeJSONError;
 use std::error;
@@ -23,6 +24,8 @@ pub enum SharedLibError {
     FormatError(String),
     /// Swap error
     SwapError(String),
+    /// Signature or key could not be parsed or is invalid
+    SignatureError(String),
 }
 
 impl From<AddressError> for SharedLibError {
@@ -39,7 +42,7 @@ impl From<String> for SharedLibError {
 
 impl From<SecpError> for SharedLibError {
     fn from(e: SecpError) -> SharedLibError {
-        SharedLibError::Generic(e.to_string())
+        SharedLibError::SignatureError(e.to_string())
     }
 }
 
@@ -49,6 +52,16 @@ impl From<MonotreeErrors> for SharedLibError {
     }
 }
 
+impl From<VerifyError> for SharedLibError {
+    fn from(e: VerifyError) -> SharedLibError {
+        match e {
+            VerifyError::FormatError(e) => SharedLibError::FormatError(e),
+            VerifyError::SignatureError(e) => SharedLibError::SignatureError(e),
+            _ => SharedLibError::Generic(e.to_string()),
+        }
+    }
+}
+
 impl From<SerdeJSONError> for SharedLibError {
     fn from(e: SerdeJSONError) -> SharedLibError {
         SharedLibError::Generic(e.to_string())
@@ -67,6 +80,7 @@ impl fmt::Display for SharedLibError {
             SharedLibError::Generic(ref e) => write!(f, "Error: {}", e),
             SharedLibError::FormatError(ref e) => write!(f, "Format Error: {}", e),
             SharedLibError::SwapError(ref e) => write!(f, "Swap Error: {}", e),
+            SharedLibError::SignatureError(ref e) => write!(f, "Signature Error: {}", e),
         }
     }
 }
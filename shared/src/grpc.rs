@@ -0,0 +1,46 @@
+//! gRPC
+//!
+//! Client and server code generated from proto/state_entity.proto for the state entity gRPC
+//! service, used for server-to-server federation. This is a JSON-over-gRPC transport: the
+//! proto defines no message schemas, and protocol messages are carried as JSON encoded
+//! shared_lib::structs types in a JsonMsg.
+
+tonic::include_proto!("mercury");
+
+use serde::{de::DeserializeOwned, Serialize};
+use tonic::Status;
+
+impl JsonMsg {
+    /// Encode a protocol message
+    pub fn encode<T: Serialize>(msg: &T) -> Result<Self, Status> {
+        Ok(JsonMsg {
+            json: serde_json::to_string(msg).map_err(|e| Status::internal(e.to_string()))?,
+        })
+    }
+
+    /// Decode a protocol message
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, Status> {
+        serde_json::from_str(&self.json).map_err(|e| Status::invalid_argument(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::StatechainID;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_json_msg() {
+        let id = StatechainID { id: Uuid::new_v4() };
+        let msg = JsonMsg::encode(&id).unwrap();
+        let decoded: StatechainID = msg.decode().unwrap();
+        assert_eq!(decoded.id, id.id);
+
+        let bad = JsonMsg { json: String::from("{}") };
+        assert_eq!(
+            bad.decode::<StatechainID>().unwrap_err().code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Header chain
+//!
+//! Compact verification of a Bitcoin block header chain, so that a client can check that a
+//! mainstay attestation tx is confirmed under sufficient work without trusting the mainstay API.
+//! Headers are checked for linkage and proof of work against their own target. Difficulty
+//! retargeting is not validated, so the caller sets the work required on top of a tx.
+
+use crate::error::SharedLibError;
+use crate::Result;
+
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::util::uint::Uint256;
+use bitcoin::{consensus, BlockHeader, Txid};
+use electrumx_client::interface::Electrumx;
+use std::fs;
+
+/// Length of a serialized block header
+const HEADER_LEN: usize = 80;
+
+/// A chain of block headers from start_height
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderChain {
+    start_height: u32,
+    headers: Vec<BlockHeader>,
+}
+
+/// Header chain file: concatenated serialized headers (hex) from start_height, as returned by
+/// Electrum blockchain.block.headers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HeaderChainFile {
+    pub start_height: u32,
+    pub hex: String,
+}
+
+/// Merkle proof of a tx in a block, as returned by Electrum blockchain.transaction.get_merkle
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TxMerkleProof {
+    /// Height of the block containing the tx
+    pub block_height: u32,
+    /// Merkle branch from the tx to the block merkle root (hex, RPC byte order)
+    pub merkle: Vec<String>,
+    /// Position of the tx in the block
+    pub pos: u32,
+}
+
+impl TxMerkleProof {
+    /// Get the merkle proof of a tx in the block at height from Electrum
+    pub fn from_electrum(electrum: &mut dyn Electrumx, txid: &Txid, height: u32) -> Result<Self> {
+        let resp = electrum
+            .get_merkle_transaction(txid.to_string(), height as usize)
+            .map_err(|e| SharedLibError::Generic(e.to_string()))?;
+        let json: serde_json::Value = serde_json::from_slice(&resp)?;
+        // The proof may be wrapped in the JSON-RPC response
+        Ok(match json.get("result") {
+            Some(result) => serde_json::from_value(result.clone())?,
+            None => serde_json::from_value(json)?,
+        })
+    }
+
+    /// Merkle root of the block committed to by the proof of txid
+    pub fn merkle_root(&self, txid: &Txid) -> Result<sha256d::Hash> {
+        let mut node = txid.as_hash();
+        let mut pos = self.pos;
+        for branch in &self.merkle {
+            let mut bytes = hex::decode(branch)
+                .map_err(|e| SharedLibError::FormatError(format!("invalid merkle branch: {}", e)))?;
+            bytes.reverse();
+            let branch = sha256d::Hash::from_slice(&bytes)
+                .map_err(|e| SharedLibError::FormatError(format!("invalid merkle branch: {}", e)))?;
+            let (left, right) = match pos & 1 {
+                0 => (node, branch),
+                _ => (branch, node),
+            };
+            node = sha256d::Hash::hash(&[&left[..], &right[..]].concat());
+            pos >>= 1;
+        }
+        Ok(node)
+    }
+}
+
+impl HeaderChain {
+    /// Header chain from start_height. Each header must link to the previous header and meet
+    /// its own proof of work target.
+    pub fn new(start_height: u32, headers: Vec<BlockHeader>) -> Result<Self> {
+        if headers.is_empty() {
+            return Err(SharedLibError::FormatError(String::from("empty header chain")));
+        }
+        for (i, header) in headers.iter().enumerate() {
+            let height = start_height + i as u32;
+            header.validate_pow(&header.target()).map_err(|e| {
+                SharedLibError::FormatError(format!("invalid proof of work at height {}: {}", height, e))
+            })?;
+            if i > 0 && header.prev_blockhash != headers[i - 1].block_hash() {
+                return Err(SharedLibError::FormatError(format!(
+                    "header at height {} does not link to the previous header",
+                    height
+                )));
+            }
+        }
+        Ok(Self {
+            start_height,
+            headers,
+        })
+    }
+
+    /// Header chain from concatenated serialized headers (hex)
+    pub fn from_hex(start_height: u32, headers_hex: &str) -> Result<Self> {
+        let bytes = hex::decode(headers_hex)
+            .map_err(|e| SharedLibError::FormatError(format!("invalid headers hex: {}", e)))?;
+        if bytes.len() % HEADER_LEN != 0 {
+            return Err(SharedLibError::FormatError(format!(
+                "headers length {} is not a multiple of {}",
+                bytes.len(),
+                HEADER_LEN
+            )));
+        }
+        let headers = bytes
+            .chunks(HEADER_LEN)
+            .map(|h| {
+                consensus::deserialize::<BlockHeader>(h)
+                    .map_err(|e| SharedLibError::FormatError(format!("invalid header: {}", e)))
+            })
+            .collect::<Result<Vec<BlockHeader>>>()?;
+        Self::new(start_height, headers)
+    }
+
+    /// Header chain from a header chain file
+    pub fn from_file(path: &str) -> Result<Self> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| SharedLibError::Generic(format!("failed to read {}: {}", path, e)))?;
+        let file: HeaderChainFile = serde_json::from_str(&data)?;
+        Self::from_hex(file.start_height, &file.hex)
+    }
+
+    /// Header chain from start_height to the Electrum server tip
+    pub fn from_electrum(electrum: &mut dyn Electrumx, start_height: u32) -> Result<Self> {
+        let tip = electrum
+            .get_tip_header()
+            .map_err(|e| SharedLibError::Generic(e.to_string()))?
+            .height as u32;
+        let mut headers_hex = String::new();
+        for height in start_height..=tip {
+            headers_hex.push_str(
+                &electrum
+                    .get_block_header(height as usize)
+                    .map_err(|e| SharedLibError::Generic(e.to_string()))?,
+            );
+        }
+        Self::from_hex(start_height, &headers_hex)
+    }
+
+    /// The header chain file of this chain
+    pub fn to_file(&self) -> HeaderChainFile {
+        HeaderChainFile {
+            start_height: self.start_height,
+            hex: self
+                .headers
+                .iter()
+                .map(|h| hex::encode(consensus::serialize(h)))
+                .collect(),
+        }
+    }
+
+    pub fn start_height(&self) -> u32 {
+        self.start_height
+    }
+
+    pub fn tip_height(&self) -> u32 {
+        self.start_height + self.headers.len() as u32 - 1
+    }
+
+    /// Header at height, if in the chain
+    pub fn get(&self, height: u32) -> Option<&BlockHeader> {
+        match height < self.start_height {
+            true => None,
+            false => self.headers.get((height - self.start_height) as usize),
+        }
+    }
+
+    /// Total work of the headers from height to the tip
+    pub fn work_from(&self, height: u32) -> Uint256 {
+        let skip = height.saturating_sub(self.start_height) as usize;
+        self.headers
+            .iter()
+            .skip(skip)
+            .fold(Uint256::from_u64(0).unwrap(), |work, h| work + h.work())
+    }
+
+    /// Verify a tx is included in a block of the chain by its merkle proof, under at least
+    /// min_work of the block and its descendants. Returns the number of confirmations.
+    pub fn verify_tx(&self, txid: &Txid, proof: &TxMerkleProof, min_work: &Uint256) -> Result<u32> {
+        let header = self.get(proof.block_height).ok_or(SharedLibError::Generic(format!(
+            "block {} not in header chain {}-{}",
+            proof.block_height,
+            self.start_height,
+            self.tip_height()
+        )))?;
+        if proof.merkle_root(txid)? != header.merkle_root.as_hash() {
+            return Err(SharedLibError::Generic(format!(
+                "tx {} not included in block {}",
+                txid, proof.block_height
+            )));
+        }
+        let work = self.work_from(proof.block_height);
+        if work < *min_work {
+            return Err(SharedLibError::Generic(format!(
+                "insufficient work on top of tx {}: {:?} < {:?}",
+                txid, work, min_work
+            )));
+        }
+        Ok(self.tip_height() - proof.block_height + 1)
+    }
+}
+
+/// Work of count blocks at the difficulty of compact target bits
+pub fn work_for_blocks(bits: u32, count: u32) -> Uint256 {
+    let header = BlockHeader {
+        version: 0,
+        prev_blockhash: Default::default(),
+        merkle_root: Default::default(),
+        time: 0,
+        bits,
+        nonce: 0,
+    };
+    (0..count).fold(Uint256::from_u64(0).unwrap(), |work, _| work + header.work())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    // Mainnet blocks 0 to 2
+    static HEADERS: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000d5fdcc541e25de1c7a5addedf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd61";
+    // Coinbase of block 1
+    static BLOCK_1_TXID: &str = "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098";
+    static DIFF_1_BITS: u32 = 0x1d00ffff;
+
+    #[test]
+    fn test_header_chain() {
+        let chain = HeaderChain::from_hex(0, HEADERS).unwrap();
+        assert_eq!(chain.tip_height(), 2);
+        assert_eq!(
+            chain.get(0).unwrap().block_hash(),
+            bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Bitcoin).block_hash()
+        );
+        assert!(chain.get(3).is_none());
+        assert_eq!(chain.work_from(1), work_for_blocks(DIFF_1_BITS, 2));
+        assert_eq!(HeaderChain::from_hex(0, &chain.to_file().hex).unwrap(), chain);
+
+        // Headers must link
+        assert!(HeaderChain::from_hex(0, &format!("{}{}", &HEADERS[..160], &HEADERS[320..])).is_err());
+        // Headers must meet their target
+        let mut header = *chain.get(1).unwrap();
+        header.nonce += 1;
+        assert!(HeaderChain::new(1, vec![header]).is_err());
+        // Whole headers only
+        assert!(HeaderChain::from_hex(0, &HEADERS[..200]).is_err());
+    }
+
+    #[test]
+    fn test_verify_tx() {
+        let chain = HeaderChain::from_hex(0, HEADERS).unwrap();
+        let txid = Txid::from_str(BLOCK_1_TXID).unwrap();
+        let proof = TxMerkleProof {
+            block_height: 1,
+            merkle: vec![],
+            pos: 0,
+        };
+
+        assert_eq!(chain.verify_tx(&txid, &proof, &work_for_blocks(DIFF_1_BITS, 2)).unwrap(), 2);
+        // Not enough work on top
+        assert!(chain.verify_tx(&txid, &proof, &work_for_blocks(DIFF_1_BITS, 3)).is_err());
+        // Not in the block
+        let other = TxMerkleProof { block_height: 2, ..proof.clone() };
+        assert!(chain.verify_tx(&txid, &other, &work_for_blocks(DIFF_1_BITS, 1)).is_err());
+        // Not in the chain
+        let other = TxMerkleProof { block_height: 3, ..proof };
+        assert!(chain.verify_tx(&txid, &other, &work_for_blocks(DIFF_1_BITS, 1)).is_err());
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let tx0 = Txid::from_str(BLOCK_1_TXID).unwrap();
+        let tx1 = Txid::from_str("9b0fc92260312ce44e74ef369f5c66bbb85848f2eddd5a7a1cde251e54ccfdd5").unwrap();
+        let root = sha256d::Hash::hash(&[&tx0[..], &tx1[..]].concat());
+
+        let proof0 = TxMerkleProof {
+            block_height: 0,
+            merkle: vec![tx1.to_string()],
+            pos: 0,
+        };
+        let proof1 = TxMerkleProof {
+            block_height: 0,
+            merkle: vec![tx0.to_string()],
+            pos: 1,
+        };
+        assert_eq!(proof0.merkle_root(&tx0).unwrap(), root);
+        assert_eq!(proof1.merkle_root(&tx1).unwrap(), root);
+        assert_ne!(proof0.merkle_root(&tx1).unwrap(), root);
+    }
+}
@@ -18,6 +18,7 @@ extern crate kms;
 extern crate monotree;
 extern crate multi_party_ecdsa;
 extern crate rocket_okapi;
+extern crate statechain_verify;
 
 #[macro_use]
 extern crate serde_derive;
@@ -32,8 +33,14 @@ pub mod mocks;
 pub mod blinded_token;
 pub mod commitment;
 pub mod ecies;
+pub mod electrum_batch;
 pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod header_chain;
 pub mod mainstay;
+pub mod noise;
+pub mod smt_proof;
 pub mod state_chain;
 pub mod structs;
 pub mod swap_data;
@@ -363,7 +363,7 @@ impl Commitment {
 }
 
 //Mainstay configuration
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MainstayConfig {
     url: String,
     position: u64,
@@ -539,6 +539,10 @@ impl CommitmentInfo {
         self.merkleproof.commitment()
     }
 
+    pub fn attestation(&self) -> Option<&Attestation> {
+        self.attestation.as_ref()
+    }
+
     //Is the commitment attested or not?
     pub fn is_confirmed(&self) -> bool {
         match &self.attestation {
@@ -661,6 +665,11 @@ impl Attestation {
         &self.merkle_root
     }
 
+    /// Txid of the attestation tx
+    pub fn txid(&self) -> &Commitment {
+        &self.txid
+    }
+
     fn from_response(response: &Response) -> Result<Self> {
         let val = response.response.get("attestation").ok_or(NotFoundError(
             "attestation object not found in Mainstay::Response".to_string(),
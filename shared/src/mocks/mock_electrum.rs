@@ -1,12 +1,58 @@
 // Electrum server http RESTful API interface
 
+use crate::electrum_batch::{script_hash, ElectrumxBatch};
 use bitcoin::consensus;
-use bitcoin::Transaction;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::{Address, Transaction};
 use electrumx_client::interface::Electrumx;
 use electrumx_client::response::{
     GetBalanceResponse, GetBlockHeadersResponse, GetHistoryResponse, GetListUnspentResponse,
     GetTipResponse, GetTransactionConfStatus,
 };
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Default fee estimate and relay fee (BTC/kB)
+const DEFAULT_FEE_RATE: f64 = 0.00001;
+
+/// Addresses with unspent outputs
+const FUNDED_ADDRESSES: [&str; 4] = [
+    "bcrt1qghtup486tj8vgz2l5pkh8hqw8wzdudraa6hnzs",
+    "tb1qghtup486tj8vgz2l5pkh8hqw8wzdudralnw74e",
+    "bcrt1qsuqsurhgfduhqw6ejquw54482sqpkfc22gytyh",
+    "tb1qsuqsurhgfduhqw6ejquw54482sqpkfc2gpaxn7",
+];
+
+/// Scripted behaviour of the mock Electrum server for a transaction, used to test failure paths.
+/// Transactions without a scenario are reported as confirmed with 3 confirmations.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxScenario {
+    /// The tx never reaches the network: broadcast succeeds but the tx is not found
+    NeverBroadcast,
+    /// The tx is in the mempool and never confirms
+    StuckUnconfirmed,
+    /// The tx has the given number of confirmations
+    Confirmed(u32),
+    /// The tx has 'confirmations' confirmations for the first 'after' status queries and is
+    /// then reorged out of the active chain
+    Reorged { confirmations: u32, after: u32 },
+    /// Mempool fees spike to 'fee_rate' (BTC/kB): broadcast is rejected for a too low fee
+    FeeSpike { fee_rate: f64 },
+}
+
+/// Scenarios shared by every MockElectrum instance, so that they apply to the clients created
+/// inside the server and the wallet under test alike
+#[derive(Default)]
+struct Scenarios {
+    txs: HashMap<String, TxScenario>,
+    default: Option<TxScenario>,
+    queries: HashMap<String, u32>,
+}
+
+static SCENARIOS: Lazy<Mutex<Scenarios>> = Lazy::new(|| Mutex::new(Scenarios::default()));
 
 pub struct MockElectrum {}
 
@@ -14,6 +60,104 @@ impl MockElectrum {
     pub fn new() -> MockElectrum {
         MockElectrum {}
     }
+
+    /// Script the behaviour for a txid
+    pub fn set_scenario(txid: &str, scenario: TxScenario) {
+        let mut guard = SCENARIOS.lock().unwrap();
+        guard.queries.remove(txid);
+        guard.txs.insert(txid.to_string(), scenario);
+    }
+
+    /// Script the behaviour for txids without their own scenario
+    pub fn set_default_scenario(scenario: Option<TxScenario>) {
+        let mut guard = SCENARIOS.lock().unwrap();
+        guard.queries.clear();
+        guard.default = scenario;
+    }
+
+    /// Remove all scripted behaviour
+    pub fn clear_scenarios() {
+        *SCENARIOS.lock().unwrap() = Scenarios::default();
+    }
+
+    fn scenario(txid: &str) -> Option<TxScenario> {
+        let guard = SCENARIOS.lock().unwrap();
+        guard.txs.get(txid).or(guard.default.as_ref()).cloned()
+    }
+
+    /// Number of status queries made for a txid, including this one
+    fn count_query(txid: &str) -> u32 {
+        let mut guard = SCENARIOS.lock().unwrap();
+        let count = guard.queries.entry(txid.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    fn confirmed(confirmations: u32) -> GetTransactionConfStatus {
+        GetTransactionConfStatus {
+            in_active_chain: Some(true),
+            confirmations: Some(confirmations),
+            blocktime: Some(123456789),
+        }
+    }
+
+    fn not_found(txid: &str) -> Box<dyn std::error::Error> {
+        format!("No such mempool or blockchain transaction: {}", txid).into()
+    }
+
+    /// Funded address with the given script hash
+    fn funded_address(script_hash_hex: &str) -> Option<&'static str> {
+        FUNDED_ADDRESSES.iter().cloned().find(|addr| {
+            script_hash(&Address::from_str(addr).unwrap().script_pubkey()) == script_hash_hex
+        })
+    }
+}
+
+impl ElectrumxBatch for MockElectrum {
+    fn batch_script_subscribe(
+        &mut self,
+        script_hashes: &[String],
+    ) -> Result<Vec<Option<String>>, Box<dyn std::error::Error>> {
+        let mut statuses = vec![];
+        for sh in script_hashes {
+            let unspent = self.get_list_unspent(Self::funded_address(sh).unwrap_or_default())?;
+            statuses.push(match unspent.is_empty() {
+                true => None,
+                false => {
+                    let history: String = unspent
+                        .iter()
+                        .map(|u| format!("{}:{}:", u.tx_hash, u.height))
+                        .collect();
+                    Some(sha256::Hash::hash(history.as_bytes()).to_string())
+                }
+            });
+        }
+        Ok(statuses)
+    }
+    fn batch_script_get_balance(
+        &mut self,
+        script_hashes: &[String],
+    ) -> Result<Vec<GetBalanceResponse>, Box<dyn std::error::Error>> {
+        script_hashes
+            .iter()
+            .map(|sh| self.get_balance(Self::funded_address(sh).unwrap_or_default()))
+            .collect()
+    }
+    fn batch_script_get_history(
+        &mut self,
+        script_hashes: &[String],
+    ) -> Result<Vec<Vec<GetHistoryResponse>>, Box<dyn std::error::Error>> {
+        let mut histories = vec![];
+        for sh in script_hashes {
+            let unspent = self.get_list_unspent(Self::funded_address(sh).unwrap_or_default())?;
+            let history = unspent
+                .iter()
+                .map(|u| serde_json::from_value(json!({"height": u.height, "tx_hash": u.tx_hash})))
+                .collect::<Result<Vec<GetHistoryResponse>, _>>()?;
+            histories.push(history);
+        }
+        Ok(histories)
+    }
 }
 
 impl Electrumx for MockElectrum {
@@ -36,10 +180,19 @@ impl Electrumx for MockElectrum {
         todo!()
     }
     fn estimate_fee(&mut self, _number: usize) -> Result<f64, Box<dyn std::error::Error>> {
-        todo!()
+        let guard = SCENARIOS.lock().unwrap();
+        Ok(guard
+            .txs
+            .values()
+            .chain(guard.default.iter())
+            .filter_map(|s| match s {
+                TxScenario::FeeSpike { fee_rate } => Some(*fee_rate),
+                _ => None,
+            })
+            .fold(DEFAULT_FEE_RATE, f64::max))
     }
     fn relay_fee(&mut self) -> Result<f64, Box<dyn std::error::Error>> {
-        todo!()
+        Ok(DEFAULT_FEE_RATE)
     }
     fn get_history(
         &mut self,
@@ -61,25 +214,57 @@ impl Electrumx for MockElectrum {
         raw_tx: String,
     ) -> Result<String, Box<dyn std::error::Error>> {
         let tx: Transaction = consensus::deserialize(&hex::decode(&raw_tx).unwrap()).unwrap();
-        Ok(tx.txid().to_string())
+        let txid = tx.txid().to_string();
+        match Self::scenario(&txid) {
+            Some(TxScenario::FeeSpike { fee_rate }) => Err(format!(
+                "min relay fee not met, mempool min fee {} BTC/kB (code 66)",
+                fee_rate
+            )
+            .into()),
+            _ => Ok(txid),
+        }
     }
     fn get_transaction(
         &mut self,
-        _tx_hash: String,
+        tx_hash: String,
         _merkle: bool,
     ) -> Result<String, Box<dyn std::error::Error>> {
+        match Self::scenario(&tx_hash) {
+            Some(TxScenario::NeverBroadcast) | Some(TxScenario::FeeSpike { .. }) => {
+                return Err(Self::not_found(&tx_hash))
+            }
+            _ => (),
+        }
         Ok("02000000000103c8e94ea0f9b1354a2dcbbf5d29090b60f89430d953d36c8631010ca436ecf80e0000000000fdffffff73e21c3ccb1cdfe3814701a6a9a4fda6547a7d8a37f2dc75f78f754a820457620000000000fdffffff79206059f932f665953bd5ebcf5f45624f346d094a483356257755f9951796d80000000000fdffffff02a00901000000000016001427628f1e9ce4e02353e7984ade02c6cb118a4218a08601000000000016001458b0d7ba80de7e0d06502eb1264d878da268636402473044022056f51aed4b34dec63c0a1de18ef78f6f9dbb455309d0e0719ad3cfdc33b40eea022063c035562b24670a1a27edc53b984743c35de9ed6af1d5e4fe16bdd437dc6cb70121029a5d481ea12bd4402db427cbdd475c535c3293cec2abf2c01852265787dd41f402463043021f05e0cf5935c9d4a2ab4ed04518f5dc73a4e39f5989e0d183746c92e3139264022009b3b20c9c6ffa46773eacf6c1803840c9843e0b3e774bec57ffb8d5fd1091f4012103218ba8362f04bc3a665fdfc2708b13e09e7a989f112f42a71bb4eaf86338ffbf0247304402200b1f9566ff1f52ad88d7f2a25d988dba659ec8ba2cac4a7f820bf6db555ed04902205ba96f2a23a286cb21dd46356debe1b03a32e12e4ab268cdd556f23ff939bdbd01210257e3c32610c41cd1cec0d0c07c484be9307e8e1585920a23a00aa2610744b7d9bc222000".to_string())
     }
     fn get_transaction_conf_status(
         &mut self,
-        _tx_hash: String,
+        tx_hash: String,
         _merkle: bool,
     ) -> Result<GetTransactionConfStatus, Box<dyn std::error::Error>> {
-        Ok(GetTransactionConfStatus {
-            in_active_chain: Some(true),
-            confirmations: Some(3),
-            blocktime: Some(123456789),
-        })
+        match Self::scenario(&tx_hash) {
+            None => Ok(Self::confirmed(3)),
+            Some(TxScenario::Confirmed(confirmations)) => Ok(Self::confirmed(confirmations)),
+            Some(TxScenario::NeverBroadcast) | Some(TxScenario::FeeSpike { .. }) => {
+                Err(Self::not_found(&tx_hash))
+            }
+            Some(TxScenario::StuckUnconfirmed) => Ok(GetTransactionConfStatus {
+                in_active_chain: None,
+                confirmations: None,
+                blocktime: None,
+            }),
+            Some(TxScenario::Reorged { confirmations, after }) => {
+                if Self::count_query(&tx_hash) <= after {
+                    Ok(Self::confirmed(confirmations))
+                } else {
+                    Ok(GetTransactionConfStatus {
+                        in_active_chain: Some(false),
+                        confirmations: None,
+                        blocktime: None,
+                    })
+                }
+            }
+        }
     }
     fn get_merkle_transaction(
         &mut self,
@@ -175,3 +360,87 @@ impl Electrumx for MockElectrum {
         Ok(vec![])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TX_HEX: &str = "02000000000101b91e2b8e26ae7f93cea773c5d74f7722982134ebbf32ca9b627981a5546ef4c7000000001716001472d64fcb0be3dff555fc87b3d054a1ccb48ac059feffffff0200c2eb0b0000000017a9141040c0c1b81e2e00aec47ef01c2d3a6116ca513d8748b723180100000017a914d5dd335a7721cf03b1f5df5bdf22c63c0e1e472887024730440220167f84b7e579153ff83a480eadc4225ad1c67322ad0e8d5f32d317ce61a6c26802206fff7f176b6780f00cf9d63ea759658e4ca0302dc2204c02bf3ee52e032e051001210297fd944ebb0de31b629a99a14d53fb8c83e5791f714892f72b74751cfd097c1765000000";
+
+    // Scenarios are process wide: a single test covers them so that they are not
+    // changed by tests running concurrently
+    #[test]
+    fn test_tx_scenarios() {
+        let mut electrum = MockElectrum::new();
+        let tx: Transaction = consensus::deserialize(&hex::decode(TX_HEX).unwrap()).unwrap();
+        let txid = tx.txid().to_string();
+        let other = String::from("other");
+
+        // no scenario
+        assert_eq!(electrum.broadcast_transaction(TX_HEX.to_string()).unwrap(), txid);
+        let status = electrum.get_transaction_conf_status(txid.clone(), false).unwrap();
+        assert_eq!(status.confirmations, Some(3));
+        assert_eq!(electrum.estimate_fee(6).unwrap(), DEFAULT_FEE_RATE);
+
+        MockElectrum::set_scenario(&txid, TxScenario::NeverBroadcast);
+        assert_eq!(electrum.broadcast_transaction(TX_HEX.to_string()).unwrap(), txid);
+        assert!(electrum.get_transaction_conf_status(txid.clone(), false).is_err());
+        assert!(electrum.get_transaction(txid.clone(), false).is_err());
+        // other txids are unaffected
+        assert!(electrum.get_transaction_conf_status(other.clone(), false).is_ok());
+
+        MockElectrum::set_scenario(&txid, TxScenario::StuckUnconfirmed);
+        let status = electrum.get_transaction_conf_status(txid.clone(), false).unwrap();
+        assert_eq!(status.confirmations, None);
+        assert!(electrum.get_transaction(txid.clone(), false).is_ok());
+
+        MockElectrum::set_scenario(&txid, TxScenario::Confirmed(1));
+        let status = electrum.get_transaction_conf_status(txid.clone(), false).unwrap();
+        assert_eq!(status.confirmations, Some(1));
+
+        MockElectrum::set_scenario(&txid, TxScenario::Reorged { confirmations: 2, after: 2 });
+        for _ in 0..2 {
+            let status = electrum.get_transaction_conf_status(txid.clone(), false).unwrap();
+            assert_eq!(status.confirmations, Some(2));
+        }
+        let status = electrum.get_transaction_conf_status(txid.clone(), false).unwrap();
+        assert_eq!(status.in_active_chain, Some(false));
+        assert_eq!(status.confirmations, None);
+
+        MockElectrum::set_scenario(&txid, TxScenario::FeeSpike { fee_rate: 0.001 });
+        let err = electrum.broadcast_transaction(TX_HEX.to_string()).unwrap_err();
+        assert!(err.to_string().contains("min relay fee not met"));
+        assert_eq!(electrum.estimate_fee(6).unwrap(), 0.001);
+
+        // default scenario applies to txids without their own scenario
+        MockElectrum::set_default_scenario(Some(TxScenario::StuckUnconfirmed));
+        let status = electrum.get_transaction_conf_status(other.clone(), false).unwrap();
+        assert_eq!(status.confirmations, None);
+        assert!(electrum.get_transaction_conf_status(txid.clone(), false).is_err());
+
+        MockElectrum::clear_scenarios();
+        assert_eq!(electrum.broadcast_transaction(TX_HEX.to_string()).unwrap(), txid);
+        let status = electrum.get_transaction_conf_status(other, false).unwrap();
+        assert_eq!(status.confirmations, Some(3));
+        assert_eq!(electrum.estimate_fee(6).unwrap(), DEFAULT_FEE_RATE);
+    }
+
+    #[test]
+    fn test_batch_queries() {
+        let mut electrum = MockElectrum::new();
+        let funded = Address::from_str(FUNDED_ADDRESSES[2]).unwrap();
+        let script_hashes = vec![script_hash(&funded.script_pubkey()), String::from("unused")];
+
+        let statuses = electrum.batch_script_subscribe(&script_hashes).unwrap();
+        assert!(statuses[0].is_some());
+        assert_eq!(statuses[1], None);
+
+        let balances = electrum.batch_script_get_balance(&script_hashes).unwrap();
+        assert_eq!(balances[0].confirmed, 10000000);
+        assert_eq!(balances[1].confirmed, 0);
+
+        let histories = electrum.batch_script_get_history(&script_hashes).unwrap();
+        assert_eq!(histories[0].len(), 1);
+        assert!(histories[1].is_empty());
+    }
+}
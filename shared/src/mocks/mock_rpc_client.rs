@@ -1,12 +1,46 @@
 // Mock bitcoin-rpc interface
-use bitcoincore_rpc::Error;
+use bitcoincore_rpc::{json::GetTxOutResult, Error};
+use bitcoin::{consensus, Address, Amount, OutPoint, Transaction, TxIn, TxOut, Txid};
+use std::str::FromStr;
 extern crate hex;
 
-pub struct MockBitcoinClient {}
+pub struct MockBitcoinClient {
+    /// Outputs are spent in the mempool only: get_tx_out excluding the mempool finds them
+    /// unspent
+    pub unconfirmed_spends: bool,
+}
+
+/// Fields of bitcoincore_rpc::json::GetMempoolEntryResult used by the server
+pub struct MockMempoolEntry {
+    pub vsize: u64,
+    pub time: u64,
+    pub fees: MockMempoolEntryFees,
+}
+
+pub struct MockMempoolEntryFees {
+    pub base: Amount,
+}
+
+/// Fields of bitcoincore_rpc::json::SignRawTransactionResult used by the server
+pub struct MockSignRawTransactionResult {
+    pub hex: Vec<u8>,
+    pub complete: bool,
+}
+
+/// Virtual size of every mock mempool tx
+pub const MOCK_MEMPOOL_VSIZE: u64 = 200;
+/// Fee rate (sat/vbyte) of every mock mempool tx
+pub const MOCK_MEMPOOL_FEE_RATE: u64 = 1;
+/// Address paid by every tx returned by get_raw_transaction
+pub const MOCK_RAW_TX_ADDRESS: &str = "bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x";
+/// Value (satoshis) paid to MOCK_RAW_TX_ADDRESS by every tx returned by get_raw_transaction
+pub const MOCK_RAW_TX_VALUE: u64 = 10000;
 
 impl MockBitcoinClient {
     pub fn new() -> MockBitcoinClient {
-        MockBitcoinClient {}
+        MockBitcoinClient {
+            unconfirmed_spends: false,
+        }
     }
     pub fn get_block_count(&mut self) -> std::result::Result<u64,()> {
         return Ok(147 as u64)
@@ -23,4 +57,70 @@ impl MockBitcoinClient {
         }
         Ok("".to_string())
     }
+    pub fn get_tx_out(
+        &mut self,
+        _txid: &Txid,
+        _vout: u32,
+        include_mempool: Option<bool>,
+    ) -> Result<Option<GetTxOutResult>, Error> {
+        if !self.unconfirmed_spends || include_mempool != Some(false) {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(serde_json::json!({
+            "bestblock": "0000000000000000000000000000000000000000000000000000000000000000",
+            "confirmations": 1,
+            "value": 0.0001,
+            "scriptPubKey": {
+                "asm": "",
+                "hex": "",
+                "type": "witness_v0_keyhash",
+                "addresses": []
+            },
+            "coinbase": false
+        }))
+        .unwrap()))
+    }
+    /// Every tx is in the mempool, broadcast at time 0 paying MOCK_MEMPOOL_FEE_RATE
+    pub fn get_mempool_entry(&mut self, _txid: &Txid) -> Result<MockMempoolEntry, Error> {
+        Ok(MockMempoolEntry {
+            vsize: MOCK_MEMPOOL_VSIZE,
+            time: 0,
+            fees: MockMempoolEntryFees {
+                base: Amount::from_sat(MOCK_MEMPOOL_VSIZE * MOCK_MEMPOOL_FEE_RATE),
+            },
+        })
+    }
+    /// Every tx pays MOCK_RAW_TX_VALUE to MOCK_RAW_TX_ADDRESS
+    pub fn get_raw_transaction(
+        &mut self,
+        _txid: &Txid,
+        _block_hash: Option<()>,
+    ) -> Result<Transaction, Error> {
+        Ok(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: bitcoin::Script::default(),
+                sequence: 0xFFFFFFFF,
+                witness: Vec::new(),
+            }],
+            output: vec![TxOut {
+                value: MOCK_RAW_TX_VALUE,
+                script_pubkey: Address::from_str(MOCK_RAW_TX_ADDRESS).unwrap().script_pubkey(),
+            }],
+        })
+    }
+    /// Returns the tx unchanged, as if signed
+    pub fn sign_raw_transaction_with_wallet(
+        &mut self,
+        tx: &Transaction,
+        _utxos: Option<()>,
+        _sighash_type: Option<()>,
+    ) -> Result<MockSignRawTransactionResult, Error> {
+        Ok(MockSignRawTransactionResult {
+            hex: consensus::serialize(tx),
+            complete: true,
+        })
+    }
 }
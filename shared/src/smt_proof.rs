@@ -0,0 +1,215 @@
+//! SMT proof encoding
+//!
+//! SMT inclusion proofs are served either as monotree Proof JSON (an array of
+//! `[bool, [byte, ...]]` pairs) or in a compact, versioned binary encoding carried as base64 in
+//! JSON. Clients request the compact encoding with the proof_format field of the proof request;
+//! servers that do not support it answer with JSON, which clients accept as well.
+//!
+//! Compact encoding, version 1:
+//!
+//! - version: 1 byte, 1
+//! - count: LEB128 varint, the number of proof nodes
+//! - sides: ceil(count / 8) bytes, bit i (least significant bit first) is the side of node i
+//! - nodes: for each node, its length (LEB128 varint) followed by its bytes
+
+use crate::error::SharedLibError;
+use crate::Result;
+
+use monotree::Proof;
+use rocket_okapi::JsonSchema;
+
+/// Version of the compact proof encoding produced by encode_proof
+pub const PROOF_ENCODING_VERSION: u8 = 1;
+
+/// Proof encoding requested by a client
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofFormat {
+    /// monotree Proof JSON
+    Json,
+    /// Compact binary encoding, base64
+    Compact,
+}
+
+impl Default for ProofFormat {
+    fn default() -> Self {
+        ProofFormat::Json
+    }
+}
+
+/// SMT inclusion proof in the encoding requested by the client
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum EncodedProof {
+    Json(Proof),
+    /// Compact encoding, base64
+    Compact(String),
+}
+
+impl EncodedProof {
+    pub fn new(proof: Proof, format: ProofFormat) -> Self {
+        match format {
+            ProofFormat::Json => EncodedProof::Json(proof),
+            ProofFormat::Compact => EncodedProof::Compact(encode_proof(&proof)),
+        }
+    }
+
+    /// Decode to a monotree Proof
+    pub fn decode(&self) -> Result<Proof> {
+        match self {
+            EncodedProof::Json(proof) => Ok(proof.clone()),
+            EncodedProof::Compact(encoded) => decode_proof(encoded),
+        }
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn invalid(reason: &str) -> SharedLibError {
+    SharedLibError::FormatError(format!("Invalid compact SMT proof: {}", reason))
+}
+
+/// Reader over the bytes of an encoded proof
+struct ProofReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProofReader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < len {
+            return Err(invalid("truncated"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn read_varint(&mut self) -> Result<usize> {
+        let mut value: usize = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.read(1)?[0];
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+}
+
+/// Encode a proof in the compact encoding, base64
+pub fn encode_proof(proof: &Proof) -> String {
+    let mut buf = vec![PROOF_ENCODING_VERSION];
+    write_varint(&mut buf, proof.len());
+    let mut sides = vec![0u8; (proof.len() + 7) / 8];
+    for (i, (side, _)) in proof.iter().enumerate() {
+        if *side {
+            sides[i / 8] |= 1 << (i % 8);
+        }
+    }
+    buf.extend(sides);
+    for (_, node) in proof {
+        write_varint(&mut buf, node.len());
+        buf.extend(node);
+    }
+    base64::encode(&buf)
+}
+
+/// Decode a proof in the compact encoding, base64
+pub fn decode_proof(encoded: &str) -> Result<Proof> {
+    let bytes = base64::decode(encoded).map_err(|e| invalid(&e.to_string()))?;
+    let mut reader = ProofReader {
+        bytes: &bytes,
+        pos: 0,
+    };
+    let version = reader.read(1)?[0];
+    if version != PROOF_ENCODING_VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    let count = reader.read_varint()?;
+    // Each node takes at least its length byte
+    if count > reader.remaining() {
+        return Err(invalid("truncated"));
+    }
+    let sides = reader.read((count + 7) / 8)?;
+    let mut proof = Vec::with_capacity(count);
+    for i in 0..count {
+        let side = sides[i / 8] & (1 << (i % 8)) != 0;
+        let len = reader.read_varint()?;
+        proof.push((side, reader.read(len)?.to_vec()));
+    }
+    if reader.remaining() != 0 {
+        return Err(invalid("trailing bytes"));
+    }
+    Ok(proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_proof() -> Proof {
+        (0..20u8)
+            .map(|i| (i % 3 == 0, vec![100 + i; 33 + (i as usize % 2)]))
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_decode_proof() {
+        let proof = test_proof();
+        let encoded = encode_proof(&proof);
+        assert_eq!(decode_proof(&encoded).unwrap(), proof);
+        assert!(encoded.len() < serde_json::to_string(&proof).unwrap().len() / 2);
+        assert_eq!(decode_proof(&encode_proof(&vec![])).unwrap(), vec![]);
+        // Node longer than a single byte varint
+        let long = vec![(true, vec![7u8; 300])];
+        assert_eq!(decode_proof(&encode_proof(&long)).unwrap(), long);
+    }
+
+    #[test]
+    fn test_decode_invalid_proof() {
+        let mut bytes = base64::decode(&encode_proof(&test_proof())).unwrap();
+        assert!(decode_proof(&base64::encode(&bytes[..bytes.len() - 1])).is_err());
+        bytes.push(0);
+        assert!(decode_proof(&base64::encode(&bytes)).is_err());
+        bytes.pop();
+        bytes[0] = 2;
+        match decode_proof(&base64::encode(&bytes)) {
+            Err(SharedLibError::FormatError(e)) => assert!(e.contains("unsupported version 2")),
+            r => assert!(false, "expected FormatError, got {:?}", r),
+        }
+        assert!(decode_proof("not base64!").is_err());
+        assert!(decode_proof(&base64::encode(&[1, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01])).is_err());
+    }
+
+    #[test]
+    fn test_encoded_proof_json() {
+        let proof = test_proof();
+        // JSON encoded proofs serialize as monotree Proof
+        let json = serde_json::to_string(&EncodedProof::new(proof.clone(), ProofFormat::Json)).unwrap();
+        assert_eq!(json, serde_json::to_string(&proof).unwrap());
+        for format in &[ProofFormat::Json, ProofFormat::Compact] {
+            let encoded = EncodedProof::new(proof.clone(), *format);
+            let json = serde_json::to_string(&encoded).unwrap();
+            let decoded: EncodedProof = serde_json::from_str(&json).unwrap();
+            assert_eq!(decoded, encoded);
+            assert_eq!(decoded.decode().unwrap(), proof);
+        }
+        assert_eq!(serde_json::to_string(&ProofFormat::Compact).unwrap(), "\"compact\"");
+    }
+}
@@ -9,28 +9,51 @@
 //! previous proof key.
 //! To withdraw, and hence bring an end to the State Chain, the StateChainSig struct contains the
 //! withdrawal address.
+//! Transfer, withdraw and migrate signatures are bound to the statechain id and the position in
+//! the chain of the signing state, so that they cannot be replayed if the same proof key
+//! re-appears on a chain. Unbound (legacy) signatures made by older wallets remain valid.
+//! Transfer signatures also commit to the txid and locktime of the backup tx agreed with the
+//! new owner, which is recorded in the new State, so that the chain proves the history of
+//! backup transactions.
+//! To bound the size of a stored statechain, the oldest states of a long chain may be pruned
+//! into an archive of hash-linked segments. The chain keeps a summary of the pruned states, so
+//! that positions, bindings and locktimes are checked as for the full chain, and the full chain
+//! can be restored from the archive with its integrity checked against the summary.
 
 use super::Result;
 use crate::error::SharedLibError;
-use crate::Verifiable;
+use crate::structs::{
+    BuildManifest, DeletionReceipt, MigrationPackage, OwnershipConfirmation, ReserveProofReport,
+    SignedBuildManifest, SignedDeletionReceipt, SignedMigrationPackage,
+    SignedOwnershipConfirmation, SignedReserveProofReport, SignedStateChainExport,
+    SignedTransferReceipt, StateChainExport, StateEntityFeeInfoAPI, TimelockMode, TransferReceipt,
+    TransferRejectAPI, UuidDef,
+};
+use crate::swap_data::{SignedSwapReport, SwapReport};
+use crate::util::{backup_tx_timelock, transaction_deserialise};
 
 use bitcoin::{
     hashes::{sha256, Hash},
+    Transaction,
     secp256k1::{Message, PublicKey, Secp256k1, SecretKey, Signature},
 };
-use monotree::{
-    hasher::{Blake3, Hasher},
-    tree::verify_proof,
-    {Monotree, Proof},
+use curv::{elliptic::curves::traits::ECPoint, GE};
+use monotree::{hasher::Blake3, Monotree, Proof};
+use statechain_verify::message::{
+    build_manifest_encode, fee_quote_encode, statechain_export_encode, BuildManifestFields,
+    ExportFields, ExportRoot, ExportSig, ExportState, FeeQuoteFields,
 };
+use statechain_verify::smt::{smt_leaf, verify_smt_leaf_proof};
 
 use chrono::{Duration, NaiveDateTime, Utc};
+use std::collections::{HashMap, HashSet};
 use std::panic;
 use std::sync::{Arc, Mutex};
-use std::{convert::TryInto, panic::AssertUnwindSafe, str::FromStr};
+use std::{convert::TryInto, panic::AssertUnwindSafe};
 use uuid::Uuid;
 use rocket_okapi::JsonSchema;
 use std::convert::TryFrom;
+use std::str::FromStr;
 
 /// A list of States in which each State signs for the next State.
 /// On initialization the struct is always checked to have
@@ -42,6 +65,9 @@ use std::convert::TryFrom;
 pub struct StateChain {
     /// chain of transitory key history
     chain: Vec<State>,
+    /// Summary of the states pruned from the start of the chain. None if never pruned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pruned: Option<PrunedStates>,
 }
 
 /// A struct with the same struct as StateChain that can be
@@ -50,6 +76,9 @@ pub struct StateChain {
 pub struct StateChainUnchecked {
     /// chain of transitory key history
     chain: Vec<State>,
+    /// Summary of the states pruned from the start of the chain
+    #[serde(default)]
+    pruned: Option<PrunedStates>,
 }
 
 impl StateChainUnchecked {
@@ -61,7 +90,7 @@ impl StateChainUnchecked {
 impl TryFrom<StateChainUnchecked> for StateChain {
     type Error = SharedLibError;
     fn try_from(chain: StateChainUnchecked) -> Result<Self> {
-        let result = Self{ chain: chain.get_chain() };
+        let result = Self{ chain: chain.chain, pruned: chain.pruned };
         StateChain::check_length(&result)?;
         Ok(result)
     }
@@ -70,7 +99,7 @@ impl TryFrom<StateChainUnchecked> for StateChain {
 impl TryFrom<&Vec<State>> for StateChain {
     type Error = SharedLibError;
     fn try_from(chain: &Vec<State>) -> Result<Self> {
-        let result = Self{ chain: chain.to_owned() };
+        let result = Self{ chain: chain.to_owned(), pruned: None };
         StateChain::check_length(&result)?;
         Ok(result)
     }
@@ -79,22 +108,86 @@ impl TryFrom<&Vec<State>> for StateChain {
 impl TryFrom<Vec<State>> for StateChain {
     type Error = SharedLibError;
     fn try_from(chain: Vec<State>) -> Result<Self> {
-        let result = Self{ chain: chain.to_owned() };
+        let result = Self{ chain: chain.to_owned(), pruned: None };
         StateChain::check_length(&result)?;
         Ok(result)
     }
 }
 
+/// Summary of the states pruned from the start of a StateChain into the archive
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct PrunedStates {
+    /// Number of states pruned: the position in the chain of the first unpruned state
+    pub length: u64,
+    /// Hash of the last archived segment
+    pub archive_hash: String,
+    /// Statechain id that the bound signatures in the pruned states are bound to
+    #[schemars(with = "UuidDef")]
+    pub bound_statechain_id: Option<Uuid>,
+    /// Locktime of the most recent backup tx committed to in the pruned states
+    pub committed_locktime: Option<u32>,
+}
+
+/// Hash preceding the first archived segment of a statechain
+pub const ARCHIVE_GENESIS_HASH: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// States pruned from a StateChain in one go. Segments are hash-linked: the hash of a segment
+/// is sha256 of the previous segment hash followed by the canonical (serde_json) encoding of
+/// its states.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ArchivedSegment {
+    /// Position in the chain of the first state of the segment
+    pub start_position: u64,
+    pub states: Vec<State>,
+    /// Hash of the previous segment, or ARCHIVE_GENESIS_HASH
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl ArchivedSegment {
+    pub fn new(start_position: u64, states: Vec<State>, prev_hash: String) -> Result<Self> {
+        let hash = Self::segment_hash(&prev_hash, &states)?;
+        Ok(ArchivedSegment {
+            start_position,
+            states,
+            prev_hash,
+            hash,
+        })
+    }
+
+    fn segment_hash(prev_hash: &String, states: &Vec<State>) -> Result<String> {
+        let canonical = serde_json::to_string(states)?;
+        let hash = sha256::Hash::hash(format!("{}{}", prev_hash, canonical).as_bytes());
+        Ok(hash.to_string())
+    }
+
+    /// Check the segment hash commits to its states and previous hash
+    pub fn verify(&self) -> Result<()> {
+        if Self::segment_hash(&self.prev_hash, &self.states)? != self.hash {
+            return Err(SharedLibError::Generic(format!(
+                "Archived statechain segment at position {} does not match its hash",
+                self.start_position
+            )));
+        }
+        Ok(())
+    }
+}
+
 impl StateChain {
     pub fn new(data: String) -> Self {
         StateChain {
             chain: vec![State {
                 data,
                 next_state: None,
+                backup_txid: None,
+                locktime: None,
             }],
+            pruned: None,
         }
     }
 
+    /// Unpruned states of the chain
     pub fn get_chain(&self) -> &Vec<State> {
         &self.chain
     }
@@ -107,28 +200,173 @@ impl StateChain {
         self.chain.last_mut().expect("expect StateChain to not be empty")
     }
 
+    /// First unpruned state of the chain
     pub fn get_first(&self) -> &State {
         self.chain.first().expect("expect StateChain to not be empty")
     }
 
+    pub fn get_pruned(&self) -> Option<&PrunedStates> {
+        self.pruned.as_ref()
+    }
+
+    /// Number of states in the chain, including pruned states
+    pub fn length(&self) -> u64 {
+        self.pruned.as_ref().map_or(0, |p| p.length) + self.chain.len() as u64
+    }
+
+    /// Position of the tip in the chain: the position a signature adding a state is bound to
+    pub fn tip_position(&self) -> u64 {
+        self.length() - 1
+    }
+
+    /// Statechain id that the bound signatures in the chain are bound to
+    pub fn bound_statechain_id(&self) -> Option<Uuid> {
+        self.pruned
+            .as_ref()
+            .and_then(|p| p.bound_statechain_id)
+            .or_else(|| {
+                self.chain
+                    .iter()
+                    .filter_map(|state| state.next_state.as_ref().and_then(|sig| sig.statechain_id))
+                    .next()
+            })
+    }
+
+    /// Locktime of the most recent backup tx committed to in the chain
+    pub fn committed_locktime(&self) -> Option<u32> {
+        self.chain
+            .iter()
+            .rev()
+            .find_map(|state| state.locktime)
+            .or_else(|| self.pruned.as_ref().and_then(|p| p.committed_locktime))
+    }
+
+    /// Serialized size of the stored chain in bytes
+    pub fn serialized_size(&self) -> Result<usize> {
+        Ok(serde_json::to_string(self)?.len())
+    }
+
+    /// Prune all but the last `keep` states from the start of the chain. Returns the pruned
+    /// states as an archive segment linked to the previously archived segment, or None if
+    /// there is nothing to prune.
+    pub fn prune(&mut self, keep: usize) -> Result<Option<ArchivedSegment>> {
+        let keep = keep.max(1);
+        if self.chain.len() <= keep {
+            return Ok(None);
+        }
+        let (start_position, prev_hash) = match &self.pruned {
+            Some(p) => (p.length, p.archive_hash.clone()),
+            None => (0, String::from(ARCHIVE_GENESIS_HASH)),
+        };
+        let bound_statechain_id = self.bound_statechain_id();
+        let states: Vec<State> = self.chain.drain(..self.chain.len() - keep).collect();
+        let committed_locktime = states
+            .iter()
+            .rev()
+            .find_map(|state| state.locktime)
+            .or_else(|| self.pruned.as_ref().and_then(|p| p.committed_locktime));
+        let segment = ArchivedSegment::new(start_position, states, prev_hash)?;
+        self.pruned = Some(PrunedStates {
+            length: start_position + segment.states.len() as u64,
+            archive_hash: segment.hash.clone(),
+            bound_statechain_id,
+            committed_locktime,
+        });
+        Ok(Some(segment))
+    }
+
+    /// Full chain of states, restoring pruned states from the archived segments of the chain.
+    /// The segments linked from the segment the chain was last pruned to are used, in any order;
+    /// unlinked segments are ignored. Fails unless the linked segments are complete and
+    /// unaltered.
+    pub fn restore(&self, segments: &Vec<ArchivedSegment>) -> Result<Vec<State>> {
+        let pruned = match &self.pruned {
+            Some(p) => p,
+            None => return Ok(self.chain.clone()),
+        };
+        let mut linked: Vec<&ArchivedSegment> = vec![];
+        let mut hash = &pruned.archive_hash;
+        while hash != ARCHIVE_GENESIS_HASH {
+            let segment = match segments.iter().find(|s| &s.hash == hash) {
+                Some(segment) if linked.len() < segments.len() => segment,
+                _ => {
+                    return Err(SharedLibError::Generic(String::from(
+                        "Archived statechain segments do not match the pruned states of the chain",
+                    )))
+                }
+            };
+            segment.verify()?;
+            linked.push(segment);
+            hash = &segment.prev_hash;
+        }
+        let mut states: Vec<State> = vec![];
+        for segment in linked.iter().rev() {
+            if segment.start_position != states.len() as u64 {
+                return Err(SharedLibError::Generic(format!(
+                    "Archived statechain segment at position {} is not contiguous with the previous segment",
+                    segment.start_position
+                )));
+            }
+            states.extend(segment.states.iter().cloned());
+        }
+        if states.len() as u64 != pruned.length {
+            return Err(SharedLibError::Generic(String::from(
+                "Archived statechain segments do not match the pruned states of the chain",
+            )));
+        }
+        states.extend(self.chain.iter().cloned());
+        Ok(states)
+    }
+
     pub fn add(&mut self, statechain_sig: &StateChainSig) -> Result<()> {
         // verify previous state has signature and signs for new proof_key
         let prev_proof_key: &String = &self.get_tip().data;
         statechain_sig.verify(prev_proof_key)?;
 
+        // a bound signature must be bound to the tip position and to the same statechain as
+        // earlier bound signatures
+        if let Some(position) = statechain_sig.position {
+            if position != self.tip_position() {
+                return Err(SharedLibError::Generic(format!(
+                    "StateChainSig bound to position {}, expected {}",
+                    position,
+                    self.tip_position()
+                )));
+            }
+        }
+        if let (Some(id), Some(chain_id)) = (statechain_sig.statechain_id, self.bound_statechain_id()) {
+            if id != chain_id {
+                return Err(SharedLibError::Generic(String::from(
+                    "StateChainSig bound to a different statechain",
+                )));
+            }
+        }
+        // each owner's backup tx must become valid before the previous owner's
+        if let (Some(locktime), Some(prev_locktime)) = (statechain_sig.locktime, self.committed_locktime()) {
+            if locktime >= prev_locktime {
+                return Err(SharedLibError::Generic(format!(
+                    "StateChainSig backup tx locktime {} not less than previous backup tx locktime {}",
+                    locktime, prev_locktime
+                )));
+            }
+        }
+
         // add sig to current tip
         self.get_mut_tip().next_state = Some(statechain_sig.clone());
 
-        // add new tip to chain
+        // add new tip to chain, recording the new owner backup tx committed to by the sig
         Ok(self.chain.push(State {
             data: statechain_sig.data.clone(),
             next_state: None,
+            backup_txid: statechain_sig.backup_txid.clone(),
+            locktime: statechain_sig.locktime,
         }))
     }
 
     pub fn example() -> Self{
         Self{
             chain: vec![State::example()],
+            pruned: None,
         }
     }
 
@@ -184,6 +422,12 @@ pub struct State {
     pub data: String,                      // proof key or address
     /// Current owner signature representing passing of ownership
     pub next_state: Option<StateChainSig>, // signature representing passing of ownership
+    /// Txid of the owner backup tx, if committed to by the signature adding this state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_txid: Option<String>,
+    /// Locktime of the owner backup tx, if committed to by the signature adding this state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locktime: Option<u32>,
 }
 
 impl State {
@@ -191,6 +435,8 @@ impl State {
         Self{
             data: "037f8d5dfb3c8f99b1641d200e808dd0b6c52f53b04e972c2e61ab901133902ebd".to_string(),
             next_state: Some(StateChainSig::example()),
+            backup_txid: None,
+            locktime: None,
         }
     }
 }
@@ -200,12 +446,25 @@ impl State {
 #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone, Default, Hash, Eq)]
 #[schemars(example = "Self::example")]
 pub struct StateChainSig {
-    /// Purpose: "TRANSFER", "TRANSFER-BATCH" or "WITHDRAW"
-    pub purpose: String, // "TRANSFER", "TRANSFER-BATCH" or "WITHDRAW"
+    /// Purpose: "TRANSFER", "TRANSFER-BATCH", "WITHDRAW", "REFUND", "MIGRATE" or "ROTATE"
+    pub purpose: String, // "TRANSFER", "TRANSFER-BATCH", "WITHDRAW", "REFUND", "MIGRATE" or "ROTATE"
     /// The new owner proof public key (if transfer) or address (if withdrawal)
     pub data: String,    // proof key, state chain id or address
     /// Current owner signature (DER encoded).
     pub sig: String,
+    /// Statechain the signature is bound to. None for legacy (unbound) signatures.
+    #[schemars(with = "UuidDef")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statechain_id: Option<Uuid>,
+    /// Position in the statechain of the signing state. None for legacy (unbound) signatures.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u64>,
+    /// Txid of the new owner backup tx. None if the signature does not commit to a backup tx.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_txid: Option<String>,
+    /// Locktime of the new owner backup tx. None if the signature does not commit to a backup tx.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locktime: Option<u32>,
 }
 
 impl StateChainSig {
@@ -214,18 +473,19 @@ impl StateChainSig {
             purpose: "TRANSFER".to_string(),
             data: "037f8d5dfb3c8f99b1641d200e808dd0b6c52f53b04e972c2e61ab901133902ebd".to_string(),
             sig: "3045022100abe02f0d1918aca36b634eb1af8a4e0714f3f699fb425de65cc661e538da3f2002200a538a22df665a95adb739ff6bb592b152dba5613602c453c58adf70858f05f6".to_string(),
+            statechain_id: None,
+            position: None,
+            backup_txid: None,
+            locktime: None,
         }
     }
     // add code here
 }
 
 impl StateChainSig {
-    /// Create message to be signed
+    /// Create message to be signed (canonical encoding, see statechain_verify::message)
     fn to_message(purpose: &String, data: &String) -> Result<Message> {
-        let mut str = purpose.clone();
-        str.push_str(&data);
-        let hash = sha256::Hash::hash(&str.as_bytes());
-        Ok(Message::from_slice(&hash)?)
+        Ok(statechain_verify::sig::statechain_sig_message(purpose, data)?)
     }
 
     /// Generate signature for change of state chain ownership
@@ -237,9 +497,133 @@ impl StateChainSig {
             purpose: purpose.clone(),
             data: data.clone(),
             sig: sig.to_string(),
+            statechain_id: None,
+            position: None,
+            backup_txid: None,
+            locktime: None,
+        })
+    }
+
+    /// Generate signature for change of state chain ownership bound to the statechain and the
+    /// position of the current tip, so that it cannot be replayed on another statechain or at
+    /// a later position of the same statechain
+    pub fn new_bound(
+        proof_key_priv: &SecretKey,
+        purpose: &String,
+        data: &String,
+        statechain_id: &Uuid,
+        position: u64,
+    ) -> Result<Self> {
+        let secp = Secp256k1::new();
+        let message = statechain_verify::sig::statechain_sig_bound_message(
+            purpose,
+            data,
+            statechain_id.as_bytes(),
+            position,
+        )?;
+        let sig = secp.sign(&message, &proof_key_priv);
+        Ok(StateChainSig {
+            purpose: purpose.clone(),
+            data: data.clone(),
+            sig: sig.to_string(),
+            statechain_id: Some(*statechain_id),
+            position: Some(position),
+            backup_txid: None,
+            locktime: None,
+        })
+    }
+
+    /// Generate a bound signature for change of state chain ownership that also commits to
+    /// the txid and locktime of the new owner backup tx
+    pub fn new_bound_with_backup(
+        proof_key_priv: &SecretKey,
+        purpose: &String,
+        data: &String,
+        statechain_id: &Uuid,
+        position: u64,
+        tx_backup: &Transaction,
+    ) -> Result<Self> {
+        let secp = Secp256k1::new();
+        let backup_txid = tx_backup.txid();
+        let locktime = backup_tx_timelock(tx_backup);
+        let message = statechain_verify::sig::statechain_sig_backup_message(
+            purpose,
+            data,
+            statechain_id.as_bytes(),
+            position,
+            &statechain_verify::sig::txid_bytes(&backup_txid.to_string())?,
+            locktime,
+        )?;
+        let sig = secp.sign(&message, &proof_key_priv);
+        Ok(StateChainSig {
+            purpose: purpose.clone(),
+            data: data.clone(),
+            sig: sig.to_string(),
+            statechain_id: Some(*statechain_id),
+            position: Some(position),
+            backup_txid: Some(backup_txid.to_string()),
+            locktime: Some(locktime),
         })
     }
 
+    pub fn is_bound(&self) -> bool {
+        self.statechain_id.is_some() && self.position.is_some()
+    }
+
+    /// Check that the backup tx committed to, if any, is tx_backup
+    pub fn check_backup_tx(&self, tx_backup: &Transaction) -> Result<()> {
+        match (&self.backup_txid, self.locktime) {
+            (Some(txid), Some(locktime)) => {
+                if txid != &tx_backup.txid().to_string() || locktime != backup_tx_timelock(tx_backup) {
+                    return Err(SharedLibError::Generic(format!(
+                        "StateChainSig commits to backup tx {} locktime {}, got backup tx {} locktime {}",
+                        txid,
+                        locktime,
+                        tx_backup.txid(),
+                        backup_tx_timelock(tx_backup)
+                    )));
+                }
+                Ok(())
+            }
+            (None, None) => Ok(()),
+            _ => Err(SharedLibError::FormatError(String::from(
+                "StateChainSig backup tx commitment requires both txid and locktime",
+            ))),
+        }
+    }
+
+    /// Check a signature presented for the statechain tip at position. Bound signatures must
+    /// be bound to statechain_id and position. Legacy signatures are rejected if require_bound.
+    pub fn check_binding(
+        &self,
+        statechain_id: &Uuid,
+        position: u64,
+        require_bound: bool,
+    ) -> Result<()> {
+        match (self.statechain_id, self.position) {
+            (Some(id), Some(pos)) => {
+                if &id != statechain_id || pos != position {
+                    return Err(SharedLibError::Generic(format!(
+                        "StateChainSig bound to statechain {} position {}, expected statechain {} position {}",
+                        id, pos, statechain_id, position
+                    )));
+                }
+                Ok(())
+            }
+            (None, None) => {
+                if require_bound {
+                    return Err(SharedLibError::Generic(String::from(
+                        "StateChainSig not bound to statechain id and position. Upgrade wallet.",
+                    )));
+                }
+                Ok(())
+            }
+            _ => Err(SharedLibError::FormatError(String::from(
+                "StateChainSig binding requires both statechain id and position",
+            ))),
+        }
+    }
+
     fn purpose_transfer_batch(batch_id: &Uuid) -> String {
         format!("TRANSFER_BATCH:{}", batch_id)
     }
@@ -257,9 +641,40 @@ impl StateChainSig {
 
     /// Verify self's signature for transfer or withdraw
     pub fn verify(&self, pk: &String) -> Result<()> {
-        let message = StateChainSig::to_message(&self.purpose, &self.data)?;
-        Signature::from_str(&self.sig)?
-            .verify(&PublicKey::from_str(&pk)?, &message)
+        match (self.statechain_id, self.position, &self.backup_txid, self.locktime) {
+            (Some(id), Some(position), Some(txid), Some(locktime)) => {
+                Ok(statechain_verify::sig::verify_statechain_sig_backup(
+                    pk,
+                    &self.purpose,
+                    &self.data,
+                    id.as_bytes(),
+                    position,
+                    &statechain_verify::sig::txid_bytes(txid)?,
+                    locktime,
+                    &self.sig,
+                )?)
+            }
+            (Some(id), Some(position), None, None) => Ok(statechain_verify::sig::verify_statechain_sig_bound(
+                pk,
+                &self.purpose,
+                &self.data,
+                id.as_bytes(),
+                position,
+                &self.sig,
+            )?),
+            (None, None, None, None) => Ok(statechain_verify::sig::verify_statechain_sig(
+                pk,
+                &self.purpose,
+                &self.data,
+                &self.sig,
+            )?),
+            (Some(_), Some(_), _, _) => Err(SharedLibError::FormatError(String::from(
+                "StateChainSig backup tx commitment requires both txid and locktime",
+            ))),
+            _ => Err(SharedLibError::FormatError(String::from(
+                "StateChainSig binding requires both statechain id and position",
+            ))),
+        }
     }
 
     pub fn is_transfer_batch(&self, batch_id: Option<&Uuid>) -> bool {
@@ -268,29 +683,246 @@ impl StateChainSig {
             Some(id) => self.purpose == Self::purpose_transfer_batch(id),
         }
     }
+
+    fn purpose_reserves(nonce: &String) -> String {
+        format!("RESERVES:{}", nonce)
+    }
+
+    /// Generate signature proving current ownership of a statecoin for a verifier-supplied nonce
+    pub fn new_reserves_sig(
+        proof_key_priv: &SecretKey,
+        nonce: &String,
+        statechain_id: &Uuid,
+    ) -> Result<Self> {
+        let purpose = &Self::purpose_reserves(nonce);
+        let data = &statechain_id.to_string();
+        Self::new(proof_key_priv, purpose, data)
+    }
+
+    pub fn is_reserves(&self, nonce: &String, statechain_id: &Uuid) -> bool {
+        self.purpose == Self::purpose_reserves(nonce) && self.data == statechain_id.to_string()
+    }
+
+    fn purpose_user_sessions(challenge: &String) -> String {
+        format!("USERSESSIONS:{}", challenge)
+    }
+
+    /// Generate signature proving ownership of a proof key for a server-issued user sessions challenge
+    pub fn new_user_sessions_sig(
+        proof_key_priv: &SecretKey,
+        challenge: &String,
+        proof_key: &String,
+    ) -> Result<Self> {
+        let purpose = &Self::purpose_user_sessions(challenge);
+        Self::new(proof_key_priv, purpose, proof_key)
+    }
+
+    pub fn is_user_sessions(&self, challenge: &String) -> bool {
+        self.purpose == Self::purpose_user_sessions(challenge)
+    }
+
+    fn purpose_ownership(nonce: &String) -> String {
+        format!("OWNERSHIP:{}", nonce)
+    }
+
+    /// Generate signature proving current ownership of a statecoin for a server-issued
+    /// ownership challenge nonce
+    pub fn new_ownership_sig(
+        proof_key_priv: &SecretKey,
+        nonce: &String,
+        statechain_id: &Uuid,
+    ) -> Result<Self> {
+        let purpose = &Self::purpose_ownership(nonce);
+        let data = &statechain_id.to_string();
+        Self::new(proof_key_priv, purpose, data)
+    }
+
+    pub fn is_ownership(&self, nonce: &String, statechain_id: &Uuid) -> bool {
+        self.purpose == Self::purpose_ownership(nonce) && self.data == statechain_id.to_string()
+    }
+
+    fn purpose_transfer_reject(statechain_id: &Uuid) -> String {
+        format!("TRANSFER_REJECT:{}", statechain_id)
+    }
+
+    /// Generate signature of the receiver of a transfer declining it. The sender's transfer
+    /// statechain sig is signed so that the rejection only applies to that transfer.
+    pub fn new_transfer_reject_sig(
+        proof_key_priv: &SecretKey,
+        transfer_sig: &StateChainSig,
+        statechain_id: &Uuid,
+    ) -> Result<Self> {
+        let purpose = &Self::purpose_transfer_reject(statechain_id);
+        Self::new(proof_key_priv, purpose, &transfer_sig.sig)
+    }
+
+    pub fn is_transfer_reject(&self, transfer_sig: &StateChainSig, statechain_id: &Uuid) -> bool {
+        self.purpose == Self::purpose_transfer_reject(statechain_id) && self.data == transfer_sig.sig
+    }
+
+    fn purpose_second_factor_key(statechain_id: &Uuid) -> String {
+        format!("SECOND_FACTOR_KEY:{}", statechain_id)
+    }
+
+    /// Generate signature of the owner of a statecoin registering the second factor key
+    /// auth_key, or removing it if None
+    pub fn new_second_factor_key_sig(
+        proof_key_priv: &SecretKey,
+        auth_key: &Option<String>,
+        statechain_id: &Uuid,
+    ) -> Result<Self> {
+        let purpose = &Self::purpose_second_factor_key(statechain_id);
+        Self::new(proof_key_priv, purpose, &auth_key.clone().unwrap_or_default())
+    }
+
+    pub fn is_second_factor_key(&self, auth_key: &Option<String>, statechain_id: &Uuid) -> bool {
+        self.purpose == Self::purpose_second_factor_key(statechain_id)
+            && self.data == auth_key.clone().unwrap_or_default()
+    }
+
+    fn purpose_second_factor(statechain_sig: &StateChainSig) -> String {
+        format!("SECOND_FACTOR:{}", statechain_sig.purpose)
+    }
+
+    /// Generate second factor signature authorising the operation signed by the owner's
+    /// statechain sig, e.g. a transfer or withdrawal
+    pub fn new_second_factor_sig(
+        auth_key_priv: &SecretKey,
+        statechain_sig: &StateChainSig,
+    ) -> Result<Self> {
+        let purpose = &Self::purpose_second_factor(statechain_sig);
+        Self::new(auth_key_priv, purpose, &statechain_sig.sig)
+    }
+
+    pub fn is_second_factor(&self, statechain_sig: &StateChainSig) -> bool {
+        self.purpose == Self::purpose_second_factor(statechain_sig)
+            && self.data == statechain_sig.sig
+    }
+
+    fn purpose_vault(statechain_id: &Uuid) -> String {
+        format!("VAULT:{}", statechain_id)
+    }
+
+    /// Generate signature of the owner of a statecoin setting its vault delay, or disabling
+    /// vault mode if 0
+    pub fn new_vault_sig(proof_key_priv: &SecretKey, delay: u64, statechain_id: &Uuid) -> Result<Self> {
+        let purpose = &Self::purpose_vault(statechain_id);
+        Self::new(proof_key_priv, purpose, &delay.to_string())
+    }
+
+    pub fn is_vault(&self, delay: u64, statechain_id: &Uuid) -> bool {
+        self.purpose == Self::purpose_vault(statechain_id) && self.data == delay.to_string()
+    }
+
+    fn purpose_vault_cancel(statechain_id: &Uuid) -> String {
+        format!("VAULT_CANCEL:{}", statechain_id)
+    }
+
+    /// Generate signature of the owner of a statecoin in vault mode cancelling the transfer,
+    /// withdrawal or migration signed by statechain_sig
+    pub fn new_vault_cancel_sig(
+        proof_key_priv: &SecretKey,
+        statechain_sig: &StateChainSig,
+        statechain_id: &Uuid,
+    ) -> Result<Self> {
+        let purpose = &Self::purpose_vault_cancel(statechain_id);
+        Self::new(proof_key_priv, purpose, &statechain_sig.sig)
+    }
+
+    pub fn is_vault_cancel(&self, statechain_sig: &StateChainSig, statechain_id: &Uuid) -> bool {
+        self.purpose == Self::purpose_vault_cancel(statechain_id) && self.data == statechain_sig.sig
+    }
+
+    /// Generate signature of the owner of a statecoin rotating its proof key to new_proof_key,
+    /// bound to the statechain and the position of the current tip. The new state is owned by the
+    /// same owner with the same backup tx, so no backup tx is committed to.
+    pub fn new_rotate_sig(
+        proof_key_priv: &SecretKey,
+        new_proof_key: &String,
+        statechain_id: &Uuid,
+        position: u64,
+    ) -> Result<Self> {
+        let purpose = &String::from("ROTATE");
+        Self::new_bound(proof_key_priv, purpose, new_proof_key, statechain_id, position)
+    }
+
+    pub fn is_rotate(&self) -> bool {
+        self.purpose == "ROTATE" && self.backup_txid.is_none() && self.locktime.is_none()
+    }
+
+    fn purpose_inheritance(statechain_id: &Uuid, inactivity_period: u64) -> String {
+        format!("INHERITANCE:{}:{}", statechain_id, inactivity_period)
+    }
+
+    /// Generate signature of the owner of a statecoin designating the receiver of its pending
+    /// transfer as beneficiary after inactivity_period seconds without a check in. The pending
+    /// transfer statechain sig is signed so that the designation only applies to that transfer.
+    pub fn new_inheritance_sig(
+        proof_key_priv: &SecretKey,
+        transfer_sig: &StateChainSig,
+        inactivity_period: u64,
+        statechain_id: &Uuid,
+    ) -> Result<Self> {
+        let purpose = &Self::purpose_inheritance(statechain_id, inactivity_period);
+        Self::new(proof_key_priv, purpose, &transfer_sig.sig)
+    }
+
+    pub fn is_inheritance(
+        &self,
+        transfer_sig: &StateChainSig,
+        inactivity_period: u64,
+        statechain_id: &Uuid,
+    ) -> bool {
+        self.purpose == Self::purpose_inheritance(statechain_id, inactivity_period)
+            && self.data == transfer_sig.sig
+    }
+}
+
+/// Verify that a transfer rejection is signed by the receiver of the rejected transfer
+pub fn verify_transfer_reject(reject: &TransferRejectAPI) -> Result<()> {
+    if !reject
+        .reject_sig
+        .is_transfer_reject(&reject.transfer_sig, &reject.statechain_id)
+    {
+        return Err(SharedLibError::Generic(format!(
+            "Transfer rejection of statechain {} does not sign the transfer statechain sig",
+            reject.statechain_id
+        )));
+    }
+    reject.reject_sig.verify(&reject.transfer_sig.data)
+}
+
+/// SMT key or entry: the first 32 bytes of a funding txid or proof key string
+fn smt_hash<'a>(value: &'a String, name: &str) -> Result<&'a monotree::Hash> {
+    match value.as_bytes().get(..32).map(|v| v.try_into()) {
+        Some(Ok(hash)) => Ok(hash),
+        _ => Err(SharedLibError::FormatError(format!(
+            "{} too short for SMT: {}",
+            name, value
+        ))),
+    }
 }
 
-/// Insert new statechain entry into Sparse Merkle Tree and return proof
+/// Insert new statechain entry into Sparse Merkle Tree and return proof. The leaf commits to
+/// the funding txid and statecoin amount as well as the entry (see statechain_verify::smt).
 pub fn update_statechain_smt<D: monotree::database::Database>(
     tree: Arc<Mutex<Monotree<D, Blake3>>>,
     root: &Option<monotree::Hash>,
     funding_txid: &String,
     entry: &String,
+    amount: u64,
 ) -> Result<Option<monotree::Hash>> {
-    let key: &monotree::Hash = match funding_txid[..32].as_bytes().try_into() {
-        Ok(k) => k,
-        Err(e) => return Err(SharedLibError::FormatError(e.to_string())),
-    };
-    let entry: &monotree::Hash = match entry[..32].as_bytes().try_into() {
-        Ok(entry) => entry,
-        Err(e) => return Err(SharedLibError::FormatError(e.to_string())),
-    };
+    let key = smt_hash(funding_txid, "funding txid")?;
+    smt_hash(entry, "SMT entry")?;
+    let leaf = smt_leaf(funding_txid, entry, amount);
 
     // update smt
+    let mut tree = tree
+        .lock()
+        .map_err(|e| SharedLibError::Generic(format!("SMT lock poisoned: {}", e)))?;
     let mut new_root: Option<[u8; 32]> = None;
     let result = panic::catch_unwind(AssertUnwindSafe(|| {
-        let mut tree = tree.lock().unwrap();
-        new_root = tree.insert(root.as_ref(), key, entry).unwrap();
+        new_root = tree.insert(root.as_ref(), key, &leaf).unwrap();
     }));
 
     if let Err(_) = result {
@@ -302,18 +934,62 @@ pub fn update_statechain_smt<D: monotree::database::Database>(
     Ok(new_root)
 }
 
+/// Insert (funding txid, entry, amount) updates into Sparse Merkle Tree in one tree operation and return
+/// the new root. Node writes are batched and committed to the database once. If a funding txid
+/// appears more than once, its last entry is inserted.
+pub fn update_statechain_smt_batch<D: monotree::database::Database>(
+    tree: Arc<Mutex<Monotree<D, Blake3>>>,
+    root: &Option<monotree::Hash>,
+    entries: &[(String, String, u64)],
+) -> Result<Option<monotree::Hash>> {
+    let mut keys: Vec<monotree::Hash> = Vec::with_capacity(entries.len());
+    let mut leaves: Vec<monotree::Hash> = Vec::with_capacity(entries.len());
+    let mut index: HashMap<monotree::Hash, usize> = HashMap::new();
+    for (funding_txid, entry, amount) in entries {
+        let key = *smt_hash(funding_txid, "funding txid")?;
+        smt_hash(entry, "SMT entry")?;
+        let leaf = smt_leaf(funding_txid, entry, *amount);
+        match index.get(&key) {
+            Some(i) => leaves[*i] = leaf,
+            None => {
+                index.insert(key, keys.len());
+                keys.push(key);
+                leaves.push(leaf);
+            }
+        }
+    }
+
+    let mut tree = tree
+        .lock()
+        .map_err(|e| SharedLibError::Generic(format!("SMT lock poisoned: {}", e)))?;
+    let mut new_root: Option<[u8; 32]> = None;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        new_root = tree.inserts(root.as_ref(), &keys, &leaves).unwrap();
+    }));
+
+    if let Err(_) = result {
+        return Err(SharedLibError::Generic(String::from(
+            "SMT batch insert failure. Probably caused by Root provided not being correct.",
+        )));
+    }
+
+    Ok(new_root)
+}
+
 // Method can run as a seperate proof generation daemon. Must check root exists before calling.
 pub fn gen_proof_smt<D: monotree::database::Database>(
     tree: Arc<Mutex<Monotree<D, Blake3>>>,
     root: &Option<monotree::Hash>,
     funding_txid: &String,
 ) -> Result<Option<Proof>> {
-    let key: &monotree::Hash = funding_txid[..32].as_bytes().try_into().unwrap();
+    let key = smt_hash(funding_txid, "funding txid")?;
 
     // generate inclusion proof
+    let mut tree = tree
+        .lock()
+        .map_err(|e| SharedLibError::Generic(format!("SMT lock poisoned: {}", e)))?;
     let mut proof: Option<Vec<(bool, Vec<u8>)>> = None;
     let result = panic::catch_unwind(AssertUnwindSafe(|| {
-        let mut tree = tree.lock().unwrap();
         proof = tree.get_merkle_proof(root.as_ref(), key).unwrap();
     }));
 
@@ -326,14 +1002,812 @@ pub fn gen_proof_smt<D: monotree::database::Database>(
     Ok(proof)
 }
 
+/// Verify that the statecoin of funding_txid with the given amount is included in the SMT
+/// with proof_key as entry
 pub fn verify_statechain_smt(
     root: &Option<monotree::Hash>,
+    funding_txid: &String,
     proof_key: &String,
+    amount: u64,
     proof: &Option<Proof>,
 ) -> bool {
-    let entry: &monotree::Hash = proof_key[..32].as_bytes().try_into().unwrap();
-    let hasher = Blake3::new();
-    verify_proof(&hasher, root.as_ref(), &entry, proof.as_ref())
+    statechain_verify::smt::verify_statecoin_smt_proof(root, funding_txid, proof_key, amount, proof)
+}
+
+/// Message signed by the state entity for a proof of reserves report: sha256 of its canonical
+/// encoding (statechain_verify::message::reserve_report_encode)
+fn reserve_report_message(report: &ReserveProofReport) -> Result<Message> {
+    let entries: Vec<([u8; 16], &str, &str, u64)> = report
+        .entries
+        .iter()
+        .map(|e| {
+            (
+                *e.statechain_id.as_bytes(),
+                e.funding_txid.as_str(),
+                e.proof_key.as_str(),
+                e.amount,
+            )
+        })
+        .collect();
+    Ok(statechain_verify::message::reserve_report_encode(
+        &report.nonce,
+        &report.root.hash(),
+        &entries,
+        report.total,
+        &report.server_pubkey,
+    )
+    .to_message()?)
+}
+
+/// Sign a proof of reserves report with the state entity identity key
+pub fn sign_reserve_report(
+    identity_key: &SecretKey,
+    report: ReserveProofReport,
+) -> Result<SignedReserveProofReport> {
+    let secp = Secp256k1::new();
+    let message = reserve_report_message(&report)?;
+    let sig = secp.sign(&message, identity_key);
+    Ok(SignedReserveProofReport {
+        report,
+        sig: sig.to_string(),
+    })
+}
+
+/// Verify a proof of reserves report against the nonce supplied by the verifier and the known
+/// state entity identity key. The SMT root must be attested. Returns the total verified amount.
+pub fn verify_reserve_proof(
+    signed: &SignedReserveProofReport,
+    nonce: &String,
+    server_pubkey: &String,
+) -> Result<u64> {
+    verify_reserve_report_sig(signed, server_pubkey)?;
+    let report = &signed.report;
+    if !report.root.is_confirmed() {
+        return Err(SharedLibError::Generic(String::from(
+            "Reserve proof root is not attested",
+        )));
+    }
+    verify_reserve_entries(report, nonce)
+}
+
+fn verify_reserve_report_sig(signed: &SignedReserveProofReport, server_pubkey: &String) -> Result<()> {
+    if &signed.report.server_pubkey != server_pubkey {
+        return Err(SharedLibError::Generic(String::from(
+            "Reserve proof report signed by unexpected key",
+        )));
+    }
+    let message = reserve_report_message(&signed.report)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    let sig = Signature::from_str(&signed.sig)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+    Ok(())
+}
+
+fn verify_reserve_entries(report: &ReserveProofReport, nonce: &String) -> Result<u64> {
+    if &report.nonce != nonce {
+        return Err(SharedLibError::Generic(String::from(
+            "Reserve proof nonce does not match",
+        )));
+    }
+    let root = Some(report.root.hash());
+    let mut seen = HashSet::new();
+    let mut seen_funding = HashSet::new();
+    let mut total: u64 = 0;
+    for entry in &report.entries {
+        if !seen.insert(entry.statechain_id) || !seen_funding.insert(&entry.funding_txid) {
+            return Err(SharedLibError::Generic(format!(
+                "Reserve proof contains statechain {} more than once",
+                entry.statechain_id
+            )));
+        }
+        if !entry.statechain_sig.is_reserves(nonce, &entry.statechain_id) {
+            return Err(SharedLibError::Generic(format!(
+                "Reserve proof signature for statechain {} does not commit to nonce",
+                entry.statechain_id
+            )));
+        }
+        entry.statechain_sig.verify(&entry.proof_key)?;
+        // The leaf commits to the funding txid and amount, so that a proof cannot be reused
+        // for another statecoin or amount. Legacy proof key leaves are not accepted.
+        if !verify_smt_leaf_proof(
+            &root,
+            &entry.funding_txid,
+            &entry.proof_key,
+            entry.amount,
+            &entry.proof,
+        ) {
+            return Err(SharedLibError::Generic(format!(
+                "Reserve proof SMT inclusion proof invalid for statechain {}",
+                entry.statechain_id
+            )));
+        }
+        total += entry.amount;
+    }
+    if total != report.total {
+        return Err(SharedLibError::Generic(format!(
+            "Reserve proof total {} does not match sum of entries {}",
+            report.total, total
+        )));
+    }
+    Ok(total)
+}
+
+/// Current statechain export format version
+pub const STATECHAIN_EXPORT_VERSION: u32 = 2;
+
+/// Message signed by the state entity for a statechain export: the canonical encoding of
+/// statechain_verify::message. Version 1 exports were signed over sha256 of their serde_json
+/// (struct field order) encoding, which is still accepted so that archived exports verify.
+fn statechain_export_message(export: &StateChainExport) -> Result<Message> {
+    if export.version < 2 {
+        let canonical = serde_json::to_string(export)?;
+        let hash = sha256::Hash::hash(canonical.as_bytes());
+        return Ok(Message::from_slice(&hash)?);
+    }
+    let chain = export
+        .chain
+        .iter()
+        .map(|state| ExportState {
+            data: &state.data,
+            next_state: state.next_state.as_ref().map(|sig| ExportSig {
+                purpose: &sig.purpose,
+                data: &sig.data,
+                sig: &sig.sig,
+                statechain_id: sig.statechain_id.map(|id| *id.as_bytes()),
+                position: sig.position,
+                backup_txid: sig.backup_txid.as_deref(),
+                locktime: sig.locktime,
+            }),
+            backup_txid: state.backup_txid.as_deref(),
+            locktime: state.locktime,
+        })
+        .collect();
+    let roots = export
+        .roots
+        .iter()
+        .map(|r| ExportRoot {
+            id: r.root.id(),
+            hash: r.root.hash(),
+            proof: r.proof.as_ref(),
+        })
+        .collect();
+    Ok(statechain_export_encode(&ExportFields {
+        version: export.version,
+        statechain_id: *export.statechain_id.as_bytes(),
+        amount: export.amount,
+        funding_txid: &export.funding_txid,
+        chain,
+        tx_backup_hex: export.tx_backup_hex.as_deref(),
+        backup_txs: export.backup_txs.iter().map(|tx| tx.as_str()).collect(),
+        roots,
+        exported_at: (
+            export.exported_at.timestamp(),
+            export.exported_at.timestamp_subsec_nanos(),
+        ),
+        server_pubkey: &export.server_pubkey,
+    })
+    .to_message()?)
+}
+
+/// Sign a statechain export with the state entity export key
+pub fn sign_statechain_export(
+    export_key: &SecretKey,
+    export: StateChainExport,
+) -> Result<SignedStateChainExport> {
+    let secp = Secp256k1::new();
+    let message = statechain_export_message(&export)?;
+    let sig = secp.sign(&message, export_key);
+    Ok(SignedStateChainExport {
+        export,
+        sig: sig.to_string(),
+    })
+}
+
+/// Verify a signed statechain export offline against the known state entity export key.
+/// Checks the state entity signature, every ownership transfer signature in the chain,
+/// that the backup tx and each backup tx of the history spend the funding output, and the SMT
+/// proof and mainstay commitment of each referenced root.
+pub fn verify_statechain_export(
+    signed: &SignedStateChainExport,
+    server_pubkey: &String,
+) -> Result<()> {
+    let export = &signed.export;
+    if &export.server_pubkey != server_pubkey {
+        return Err(SharedLibError::Generic(String::from(
+            "Statechain export signed by unexpected key",
+        )));
+    }
+    let message = statechain_export_message(export)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    let sig = Signature::from_str(&signed.sig)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+
+    // Chain of ownership
+    let chain: StateChain = (&export.chain).try_into()?;
+    for (i, state) in chain.get_chain().iter().enumerate() {
+        // Bound signatures must be bound to the exported statechain and their position
+        if let Some(sig) = &state.next_state {
+            sig.check_binding(&export.statechain_id, i as u64, false)?;
+        }
+        match (&state.next_state, chain.get_chain().get(i + 1)) {
+            (Some(sig), Some(next)) => {
+                if sig.data != next.data {
+                    return Err(SharedLibError::Generic(format!(
+                        "Statechain export state {} signs for data that does not match next state",
+                        i
+                    )));
+                }
+                if sig.backup_txid != next.backup_txid || sig.locktime != next.locktime {
+                    return Err(SharedLibError::Generic(format!(
+                        "Statechain export state {} backup tx does not match the signature adding it",
+                        i + 1
+                    )));
+                }
+                sig.verify(&state.data)?;
+            }
+            // Final state of a withdrawn statecoin signs for the withdrawal address
+            (Some(sig), None) => sig.verify(&state.data)?,
+            (None, None) => (),
+            (None, Some(_)) => {
+                return Err(SharedLibError::Generic(format!(
+                    "Statechain export state {} is missing its signature",
+                    i
+                )))
+            }
+        }
+    }
+
+    if let Some(tx_hex) = &export.tx_backup_hex {
+        let tx = transaction_deserialise(tx_hex)?;
+        if !spends_funding_tx(&tx, &export.funding_txid) {
+            return Err(SharedLibError::Generic(String::from(
+                "Statechain export backup tx does not spend funding tx",
+            )));
+        }
+        if let Some(txid) = &chain.get_tip().backup_txid {
+            if txid != &tx.txid().to_string() {
+                return Err(SharedLibError::Generic(String::from(
+                    "Statechain export backup tx is not the backup tx committed to by the current state",
+                )));
+            }
+        }
+        if export.version >= 2 && !export.backup_txs.contains(tx_hex) {
+            return Err(SharedLibError::Generic(String::from(
+                "Statechain export backup tx history does not include the current backup tx",
+            )));
+        }
+    }
+
+    // Backup tx history. Statecoins deposited before the history was recorded list only the
+    // backup txs recorded since.
+    let mut txids = HashSet::new();
+    for tx_hex in &export.backup_txs {
+        let tx = transaction_deserialise(tx_hex)?;
+        if !spends_funding_tx(&tx, &export.funding_txid) {
+            return Err(SharedLibError::Generic(format!(
+                "Statechain export backup tx {} does not spend funding tx",
+                tx.txid()
+            )));
+        }
+        if !txids.insert(tx.txid()) {
+            return Err(SharedLibError::Generic(format!(
+                "Statechain export backup tx {} is listed more than once",
+                tx.txid()
+            )));
+        }
+    }
+
+    // Each root must include one of the chain's proof keys for the funding txid
+    for export_root in &export.roots {
+        let root = &export_root.root;
+        if let Some(ci) = root.commitment_info() {
+            if !ci.verify() {
+                return Err(SharedLibError::Generic(format!(
+                    "Statechain export root {:?} commitment proof invalid",
+                    root.id()
+                )));
+            }
+        }
+        let hash = Some(root.hash());
+        if !chain
+            .get_chain()
+            .iter()
+            .any(|state| {
+                verify_statechain_smt(
+                    &hash,
+                    &export.funding_txid,
+                    &state.data,
+                    export.amount,
+                    &export_root.proof,
+                )
+            })
+        {
+            return Err(SharedLibError::Generic(format!(
+                "Statechain export root {:?} does not include a statechain proof key",
+                root.id()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn spends_funding_tx(tx: &Transaction, funding_txid: &str) -> bool {
+    match tx.input.get(0) {
+        Some(input) => input.previous_output.txid.to_string() == funding_txid,
+        None => false,
+    }
+}
+
+/// Message signed by the old state entity for a migration package: sha256 of the canonical
+/// (serde_json, struct field order) encoding of the package
+fn migration_package_message(package: &MigrationPackage) -> Result<Message> {
+    let canonical = serde_json::to_string(package)?;
+    let hash = sha256::Hash::hash(canonical.as_bytes());
+    Ok(Message::from_slice(&hash)?)
+}
+
+/// Sign a statechain migration package with the state entity export key
+pub fn sign_migration_package(
+    export_key: &SecretKey,
+    package: MigrationPackage,
+) -> Result<SignedMigrationPackage> {
+    let secp = Secp256k1::new();
+    let message = migration_package_message(&package)?;
+    let sig = secp.sign(&message, export_key);
+    Ok(SignedMigrationPackage {
+        package,
+        sig: sig.to_string(),
+    })
+}
+
+/// Verify a migration package received by the new state entity. Checks the old state entity
+/// signature and statechain export, and that the owner signed the statecoin over to the
+/// migration public key of the new state entity.
+pub fn verify_migration_package(
+    signed: &SignedMigrationPackage,
+    server_pubkey: &String,
+    migration_pubkey: &String,
+) -> Result<()> {
+    let package = &signed.package;
+    verify_statechain_export(&package.export, server_pubkey)?;
+
+    let message = migration_package_message(package)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    let sig = Signature::from_str(&signed.sig)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+
+    let export = &package.export.export;
+    if export.tx_backup_hex.is_none() {
+        return Err(SharedLibError::Generic(String::from(
+            "Migration package statecoin is withdrawn",
+        )));
+    }
+    let statechain_sig = &package.statechain_sig;
+    if statechain_sig.purpose != "MIGRATE" || &statechain_sig.data != migration_pubkey {
+        return Err(SharedLibError::Generic(String::from(
+            "Migration package statechain signature is not for this state entity",
+        )));
+    }
+    statechain_sig.check_binding(
+        &export.statechain_id,
+        export.chain.len().saturating_sub(1) as u64,
+        false,
+    )?;
+    match export.chain.last() {
+        Some(tip) => statechain_sig.verify(&tip.data)?,
+        None => {
+            return Err(SharedLibError::Generic(String::from(
+                "Migration package statechain is empty",
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Commitment to a deleted state entity key share: sha256 of its public key S1
+pub fn deletion_commitment(s1_pub: &GE) -> String {
+    sha256::Hash::hash(&s1_pub.pk_to_key_slice()).to_string()
+}
+
+/// Message signed by the state entity for a deletion receipt: sha256 of the canonical
+/// (serde_json, struct field order) encoding of the receipt
+fn deletion_receipt_message(receipt: &DeletionReceipt) -> Result<Message> {
+    let canonical = serde_json::to_string(receipt)?;
+    let hash = sha256::Hash::hash(canonical.as_bytes());
+    Ok(Message::from_slice(&hash)?)
+}
+
+/// Sign a key share deletion receipt with the state entity identity key
+pub fn sign_deletion_receipt(
+    identity_key: &SecretKey,
+    receipt: DeletionReceipt,
+) -> Result<SignedDeletionReceipt> {
+    let secp = Secp256k1::new();
+    let message = deletion_receipt_message(&receipt)?;
+    let sig = secp.sign(&message, identity_key);
+    Ok(SignedDeletionReceipt {
+        receipt,
+        sig: sig.to_string(),
+    })
+}
+
+/// Verify the state entity signature of a deletion receipt
+pub fn verify_deletion_receipt(signed: &SignedDeletionReceipt, server_pubkey: &String) -> Result<()> {
+    if &signed.receipt.server_pubkey != server_pubkey {
+        return Err(SharedLibError::Generic(String::from(
+            "Deletion receipt signed by unexpected key",
+        )));
+    }
+    let message = deletion_receipt_message(&signed.receipt)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    let sig = Signature::from_str(&signed.sig)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+    Ok(())
+}
+
+/// Verify the deletion receipts of a statechain: every receipt is for the statechain and signed
+/// by the known state entity identity key, and there is a receipt for each of the given previous
+/// owner proof keys. The key carried in the receipts is not trusted.
+pub fn verify_deletion_receipts(
+    receipts: &[SignedDeletionReceipt],
+    statechain_id: &Uuid,
+    prev_proof_keys: &[String],
+    server_pubkey: &String,
+) -> Result<()> {
+    for signed in receipts {
+        if &signed.receipt.statechain_id != statechain_id {
+            return Err(SharedLibError::Generic(format!(
+                "Deletion receipt for shared key {} is not for statechain {}",
+                signed.receipt.shared_key_id, statechain_id
+            )));
+        }
+        verify_deletion_receipt(signed, server_pubkey)?;
+    }
+    for proof_key in prev_proof_keys {
+        if !receipts.iter().any(|r| &r.receipt.proof_key == proof_key) {
+            return Err(SharedLibError::Generic(format!(
+                "No deletion receipt for previous owner proof key {}",
+                proof_key
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn transfer_receipt_message(receipt: &TransferReceipt) -> Result<Message> {
+    let canonical = serde_json::to_string(receipt)?;
+    let hash = sha256::Hash::hash(canonical.as_bytes());
+    Ok(Message::from_slice(&hash)?)
+}
+
+/// Sign a transfer receipt with the state entity identity key
+pub fn sign_transfer_receipt(
+    identity_key: &SecretKey,
+    receipt: TransferReceipt,
+) -> Result<SignedTransferReceipt> {
+    let secp = Secp256k1::new();
+    let message = transfer_receipt_message(&receipt)?;
+    let sig = secp.sign(&message, identity_key);
+    Ok(SignedTransferReceipt {
+        receipt,
+        sig: sig.to_string(),
+    })
+}
+
+/// Verify a transfer receipt: the receipt is signed by the known state entity identity key and
+/// records the transfer of the statechain from the sender proof key. The key carried in the
+/// receipt is not trusted.
+pub fn verify_transfer_receipt(
+    signed: &SignedTransferReceipt,
+    server_pubkey: &String,
+    statechain_id: &Uuid,
+    prev_proof_key: &String,
+) -> Result<()> {
+    let receipt = &signed.receipt;
+    if &receipt.server_pubkey != server_pubkey {
+        return Err(SharedLibError::Generic(String::from(
+            "Transfer receipt signed by unexpected key",
+        )));
+    }
+    if &receipt.statechain_id != statechain_id || &receipt.prev_proof_key != prev_proof_key {
+        return Err(SharedLibError::Generic(format!(
+            "Transfer receipt is not for the transfer of statechain {} from proof key {}",
+            statechain_id, prev_proof_key
+        )));
+    }
+    let message = transfer_receipt_message(receipt)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    let sig = Signature::from_str(&signed.sig)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+    Ok(())
+}
+
+fn ownership_confirmation_message(confirmation: &OwnershipConfirmation) -> Result<Message> {
+    let canonical = serde_json::to_string(confirmation)?;
+    let hash = sha256::Hash::hash(canonical.as_bytes());
+    Ok(Message::from_slice(&hash)?)
+}
+
+/// Sign an ownership confirmation with the state entity identity key
+pub fn sign_ownership_confirmation(
+    identity_key: &SecretKey,
+    confirmation: OwnershipConfirmation,
+) -> Result<SignedOwnershipConfirmation> {
+    let secp = Secp256k1::new();
+    let message = ownership_confirmation_message(&confirmation)?;
+    let sig = secp.sign(&message, identity_key);
+    Ok(SignedOwnershipConfirmation {
+        confirmation,
+        sig: sig.to_string(),
+    })
+}
+
+/// Verify an ownership confirmation: the confirmation is signed by the known state entity
+/// identity key and confirms ownership of the statechain for the verifier's nonce. The key
+/// carried in the confirmation is not trusted.
+pub fn verify_ownership_confirmation(
+    signed: &SignedOwnershipConfirmation,
+    server_pubkey: &String,
+    statechain_id: &Uuid,
+    nonce: &String,
+) -> Result<()> {
+    let confirmation = &signed.confirmation;
+    if &confirmation.server_pubkey != server_pubkey {
+        return Err(SharedLibError::Generic(String::from(
+            "Ownership confirmation signed by unexpected key",
+        )));
+    }
+    if &confirmation.statechain_id != statechain_id || &confirmation.nonce != nonce {
+        return Err(SharedLibError::Generic(format!(
+            "Ownership confirmation is not for statechain {} and nonce {}",
+            statechain_id, nonce
+        )));
+    }
+    let message = ownership_confirmation_message(confirmation)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    let sig = Signature::from_str(&signed.sig)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+    Ok(())
+}
+
+/// Hex encoded sha256 hash of a hex encoded sale preimage
+pub fn sale_payment_hash(preimage: &String) -> Result<String> {
+    let preimage = hex::decode(preimage)
+        .map_err(|e| SharedLibError::FormatError(format!("Invalid sale preimage: {}", e)))?;
+    Ok(hex::encode(sha256::Hash::hash(&preimage).into_inner()))
+}
+
+/// Verify that a hex encoded preimage hashes to a sale payment hash
+pub fn verify_sale_preimage(preimage: &String, payment_hash: &String) -> Result<()> {
+    if &sale_payment_hash(preimage)? != &payment_hash.to_lowercase() {
+        return Err(SharedLibError::Generic(String::from(
+            "Sale preimage does not match payment hash",
+        )));
+    }
+    Ok(())
+}
+
+/// sha256 of the canonical encoding of a fee quote (statechain_verify::message). The signature,
+/// deposit capacity and deposit pause flag are not encoded: capacity changes with every deposit
+/// and withdrawal.
+fn fee_quote_digest(quote: &StateEntityFeeInfoAPI) -> Result<sha256::Hash> {
+    let encoding = fee_quote_encode(&FeeQuoteFields {
+        address: &quote.address,
+        deposit: quote.deposit,
+        withdraw: quote.withdraw,
+        withdraw_input: quote.withdraw_input,
+        max_withdraw_inputs: quote.max_withdraw_inputs,
+        interval: quote.interval,
+        initlock: quote.initlock,
+        relative_timelock: quote.timelock_mode == TimelockMode::Relative,
+        min_deposit: quote.min_deposit,
+        dust_limit: quote.dust_limit,
+        wallet_version: &quote.wallet_version,
+        wallet_message: &quote.wallet_message,
+        valid_until: quote
+            .valid_until
+            .map(|t| (t.timestamp(), t.timestamp_subsec_nanos())),
+    });
+    Ok(sha256::Hash::hash(&encoding.into_bytes()))
+}
+
+/// Hash of a fee quote: hex of the digest of its canonical encoding. Sent in DepositMsg1 as the
+/// acknowledgement of the quote.
+pub fn fee_quote_hash(quote: &StateEntityFeeInfoAPI) -> Result<String> {
+    Ok(fee_quote_digest(quote)?.to_string())
+}
+
+fn fee_quote_message(quote: &StateEntityFeeInfoAPI) -> Result<Message> {
+    Ok(Message::from_slice(&fee_quote_digest(quote)?)?)
+}
+
+/// Sign a fee quote with the state entity identity key
+pub fn sign_fee_quote(
+    identity_key: &SecretKey,
+    mut quote: StateEntityFeeInfoAPI,
+) -> Result<StateEntityFeeInfoAPI> {
+    let secp = Secp256k1::new();
+    let message = fee_quote_message(&quote)?;
+    quote.sig = Some(secp.sign(&message, identity_key).to_string());
+    Ok(quote)
+}
+
+/// Verify the state entity signature of a fee quote
+pub fn verify_fee_quote(quote: &StateEntityFeeInfoAPI, server_pubkey: &String) -> Result<()> {
+    let sig = match &quote.sig {
+        Some(sig) => Signature::from_str(sig)?,
+        None => return Err(SharedLibError::Generic(String::from("Fee quote is not signed"))),
+    };
+    let message = fee_quote_message(quote)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+    Ok(())
+}
+
+/// Message of the canonical encoding of a build manifest (see statechain_verify::message)
+fn build_manifest_message(manifest: &BuildManifest) -> Result<Message> {
+    Ok(build_manifest_encode(&BuildManifestFields {
+        version: &manifest.version,
+        git_commit: &manifest.git_commit,
+        git_dirty: manifest.git_dirty,
+        cargo_lock_hash: &manifest.cargo_lock_hash,
+        features: &manifest.features,
+        rustc_version: &manifest.rustc_version,
+        profile: &manifest.profile,
+        release: manifest.release,
+    })
+    .to_message()?)
+}
+
+/// Sign a build manifest with the state entity identity key
+pub fn sign_build_manifest(
+    identity_key: &SecretKey,
+    manifest: BuildManifest,
+) -> Result<SignedBuildManifest> {
+    let secp = Secp256k1::new();
+    let message = build_manifest_message(&manifest)?;
+    let sig = secp.sign(&message, identity_key);
+    Ok(SignedBuildManifest {
+        manifest,
+        server_pubkey: Some(PublicKey::from_secret_key(&secp, identity_key).to_string()),
+        sig: Some(sig.to_string()),
+    })
+}
+
+/// Verify that a build manifest is signed by the known state entity identity key and was
+/// built in release build mode from git_commit
+pub fn verify_build_manifest(
+    signed: &SignedBuildManifest,
+    server_pubkey: &String,
+    git_commit: &String,
+) -> Result<()> {
+    let sig = match (&signed.server_pubkey, &signed.sig) {
+        (Some(pk), Some(sig)) if pk == server_pubkey => Signature::from_str(sig)?,
+        (Some(_), Some(_)) => {
+            return Err(SharedLibError::Generic(String::from(
+                "Build manifest signed by unexpected key",
+            )))
+        }
+        _ => return Err(SharedLibError::Generic(String::from("Build manifest is not signed"))),
+    };
+    let message = build_manifest_message(&signed.manifest)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+
+    let manifest = &signed.manifest;
+    if !manifest.release || manifest.git_dirty {
+        return Err(SharedLibError::Generic(String::from(
+            "Server was not built in release build mode",
+        )));
+    }
+    if &manifest.git_commit != git_commit {
+        return Err(SharedLibError::Generic(format!(
+            "Server built from commit {}, expected {}",
+            manifest.git_commit, git_commit
+        )));
+    }
+    Ok(())
+}
+
+fn swap_report_message(report: &SwapReport) -> Result<Message> {
+    let canonical = serde_json::to_string(report)?;
+    let hash = sha256::Hash::hash(canonical.as_bytes());
+    Ok(Message::from_slice(&hash)?)
+}
+
+/// Sign a swap report with the state entity identity key
+pub fn sign_swap_report(identity_key: &SecretKey, report: SwapReport) -> Result<SignedSwapReport> {
+    let secp = Secp256k1::new();
+    let message = swap_report_message(&report)?;
+    let sig = secp.sign(&message, identity_key);
+    Ok(SignedSwapReport {
+        report,
+        server_pubkey: Some(PublicKey::from_secret_key(&secp, identity_key).to_string()),
+        sig: Some(sig.to_string()),
+    })
+}
+
+/// Verify that a swap report is signed by the known state entity identity key
+pub fn verify_swap_report(signed: &SignedSwapReport, server_pubkey: &String) -> Result<()> {
+    let sig = match (&signed.server_pubkey, &signed.sig) {
+        (Some(pk), Some(sig)) if pk == server_pubkey => Signature::from_str(sig)?,
+        (Some(_), Some(_)) => {
+            return Err(SharedLibError::Generic(String::from(
+                "Swap report signed by unexpected key",
+            )))
+        }
+        _ => return Err(SharedLibError::Generic(String::from("Swap report is not signed"))),
+    };
+    let message = swap_report_message(&signed.report)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    Secp256k1::verification_only().verify(&message, &sig, &pk)?;
+    Ok(())
+}
+
+/// Request header carrying the client nonce a response signature is bound to
+pub const NONCE_HEADER: &str = "X-Merc-Nonce";
+/// Response header carrying the state entity signature over the nonce, route and response body
+pub const RESPONSE_SIG_HEADER: &str = "X-Merc-Response-Sig";
+/// Maximum length of a client nonce. Longer nonces are ignored by the state entity.
+pub const MAX_NONCE_LEN: usize = 64;
+/// Response header carrying the code of a state entity error the client acts on
+pub const ERROR_CODE_HEADER: &str = "X-Merc-Error-Code";
+
+/// Codes of state entity errors that the client handles rather than reports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    /// The shared key is from an earlier key epoch and must be refreshed before transfer
+    KeyRefreshRequired,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::KeyRefreshRequired => "key_refresh_required",
+        }
+    }
+
+    pub fn from_str(code: &str) -> Option<ErrorCode> {
+        match code {
+            "key_refresh_required" => Some(ErrorCode::KeyRefreshRequired),
+            _ => None,
+        }
+    }
+}
+
+fn response_message(nonce: &str, route: &str, body: &[u8]) -> Result<Message> {
+    let body_hash = sha256::Hash::hash(body);
+    let canonical = format!(
+        "MERC-RESPONSE:{}:{}:{}",
+        nonce,
+        route.trim_start_matches('/'),
+        body_hash
+    );
+    let hash = sha256::Hash::hash(canonical.as_bytes());
+    Ok(Message::from_slice(&hash)?)
+}
+
+/// Sign a response body to the request with the client nonce to route (path and query) with
+/// the state entity identity key
+pub fn sign_response(identity_key: &SecretKey, nonce: &str, route: &str, body: &[u8]) -> Result<String> {
+    let message = response_message(nonce, route, body)?;
+    Ok(Secp256k1::new().sign(&message, identity_key).to_string())
+}
+
+/// Verify that a response body is signed by the known state entity identity key for the request
+/// with the client nonce to route, so that it was not forged by a proxy between client and
+/// state entity
+pub fn verify_response(
+    server_pubkey: &String,
+    sig: &str,
+    nonce: &str,
+    route: &str,
+    body: &[u8],
+) -> Result<()> {
+    let message = response_message(nonce, route, body)?;
+    let pk = PublicKey::from_str(server_pubkey)?;
+    Secp256k1::verification_only().verify(&message, &Signature::from_str(sig)?, &pk)?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -375,6 +1849,168 @@ mod tests {
         assert!(fail.is_err());
     }
 
+    #[test]
+    fn test_add_bound_sig_to_state_chain() {
+        let secp = Secp256k1::new();
+        let keys: Vec<SecretKey> = (1..4u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<String> = keys
+            .iter()
+            .map(|sk| PublicKey::from_secret_key(&secp, sk).to_string())
+            .collect();
+        let statechain_id = Uuid::new_v4();
+        let transfer = String::from("TRANSFER");
+
+        let mut state_chain = StateChain::new(pks[0].clone());
+        assert_eq!(state_chain.tip_position(), 0);
+        let sig = StateChainSig::new_bound(&keys[0], &transfer, &pks[1], &statechain_id, 0).unwrap();
+        assert!(sig.is_bound());
+        assert!(sig.check_binding(&statechain_id, 0, true).is_ok());
+        assert!(sig.check_binding(&statechain_id, 1, false).is_err());
+        assert!(sig.check_binding(&Uuid::new_v4(), 0, false).is_err());
+        state_chain.add(&sig).unwrap();
+        assert_eq!(state_chain.bound_statechain_id(), Some(statechain_id));
+
+        // Signature bound to a stale position
+        let stale = StateChainSig::new_bound(&keys[1], &transfer, &pks[2], &statechain_id, 0).unwrap();
+        assert!(stale.verify(&pks[1]).is_ok());
+        assert!(state_chain.add(&stale).is_err());
+        // Signature bound to another statechain
+        let other = StateChainSig::new_bound(&keys[1], &transfer, &pks[2], &Uuid::new_v4(), 1).unwrap();
+        assert!(state_chain.add(&other).is_err());
+        // Binding fields cannot be stripped to form a legacy signature
+        let mut stripped = StateChainSig::new_bound(&keys[1], &transfer, &pks[2], &statechain_id, 1).unwrap();
+        stripped.statechain_id = None;
+        stripped.position = None;
+        assert!(state_chain.add(&stripped).is_err());
+
+        // Legacy signatures are still accepted, unless bound signatures are required
+        let legacy = StateChainSig::new(&keys[1], &transfer, &pks[2]).unwrap();
+        assert!(legacy.check_binding(&statechain_id, 1, false).is_ok());
+        assert!(legacy.check_binding(&statechain_id, 1, true).is_err());
+        state_chain.add(&legacy).unwrap();
+        assert_eq!(state_chain.tip_position(), 2);
+
+        // Legacy signatures serialize without the binding fields
+        let json = serde_json::to_string(&legacy).unwrap();
+        assert!(!json.contains("position"));
+        let bound: StateChainSig = serde_json::from_str(&serde_json::to_string(&sig).unwrap()).unwrap();
+        assert_eq!(bound, sig);
+    }
+
+    #[test]
+    fn test_add_backup_committed_sig_to_state_chain() {
+        let secp = Secp256k1::new();
+        let keys: Vec<SecretKey> = (1..4u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<String> = keys
+            .iter()
+            .map(|sk| PublicKey::from_secret_key(&secp, sk).to_string())
+            .collect();
+        let statechain_id = Uuid::new_v4();
+        let transfer = String::from("TRANSFER");
+        let tx_backup = |lock_time: u32| bitcoin::Transaction {
+            version: 2,
+            lock_time,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::default(),
+                script_sig: bitcoin::Script::new(),
+                sequence: 0xFFFFFFFE,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+
+        let mut state_chain = StateChain::new(pks[0].clone());
+        let tx1 = tx_backup(1000);
+        let sig = StateChainSig::new_bound_with_backup(&keys[0], &transfer, &pks[1], &statechain_id, 0, &tx1).unwrap();
+        assert!(sig.check_backup_tx(&tx1).is_ok());
+        assert!(sig.check_backup_tx(&tx_backup(990)).is_err());
+        state_chain.add(&sig).unwrap();
+        // the new state records the committed backup tx
+        assert_eq!(state_chain.get_tip().backup_txid, Some(tx1.txid().to_string()));
+        assert_eq!(state_chain.get_tip().locktime, Some(1000));
+        assert_eq!(state_chain.committed_locktime(), Some(1000));
+
+        // the commitment cannot be altered or stripped
+        let tx2 = tx_backup(990);
+        let mut altered = StateChainSig::new_bound_with_backup(&keys[1], &transfer, &pks[2], &statechain_id, 1, &tx2).unwrap();
+        altered.locktime = Some(980);
+        assert!(altered.verify(&pks[1]).is_err());
+        altered.locktime = None;
+        assert!(altered.verify(&pks[1]).is_err());
+        altered.backup_txid = None;
+        assert!(altered.verify(&pks[1]).is_err());
+
+        // the locktime must decrease
+        let not_decremented = StateChainSig::new_bound_with_backup(&keys[1], &transfer, &pks[2], &statechain_id, 1, &tx1).unwrap();
+        assert!(state_chain.add(&not_decremented).is_err());
+        let sig = StateChainSig::new_bound_with_backup(&keys[1], &transfer, &pks[2], &statechain_id, 1, &tx2).unwrap();
+        state_chain.add(&sig).unwrap();
+        assert_eq!(state_chain.get_tip().locktime, Some(990));
+
+        // uncommitted signatures accept any backup tx
+        let legacy = StateChainSig::new(&keys[2], &transfer, &pks[0]).unwrap();
+        assert!(legacy.check_backup_tx(&tx1).is_ok());
+    }
+
+    #[test]
+    fn test_prune_and_restore_state_chain() {
+        let secp = Secp256k1::new();
+        let keys: Vec<SecretKey> = (1..8u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<String> = keys
+            .iter()
+            .map(|sk| PublicKey::from_secret_key(&secp, sk).to_string())
+            .collect();
+        let statechain_id = Uuid::new_v4();
+        let transfer = String::from("TRANSFER");
+
+        let mut state_chain = StateChain::new(pks[0].clone());
+        assert_eq!(state_chain.prune(1).unwrap(), None);
+        let mut segments = vec![];
+        for i in 0..6 {
+            let sig = StateChainSig::new_bound(&keys[i], &transfer, &pks[i + 1], &statechain_id, i as u64).unwrap();
+            state_chain.add(&sig).unwrap();
+            if i == 2 || i == 5 {
+                segments.push(state_chain.prune(2).unwrap().unwrap());
+            }
+        }
+        let full = state_chain.restore(&segments).unwrap();
+        assert_eq!(full.len(), 7);
+        assert_eq!(state_chain.get_chain().len(), 2);
+        assert_eq!(state_chain.length(), 7);
+        assert_eq!(state_chain.get_pruned().unwrap().length, 5);
+        assert_eq!(segments[1].start_position, 2);
+        assert_eq!(segments[1].prev_hash, segments[0].hash);
+        assert_eq!(state_chain.bound_statechain_id(), Some(statechain_id));
+        assert!(full.iter().zip(pks.iter()).all(|(state, pk)| &state.data == pk));
+
+        // Positions continue from the pruned states
+        assert_eq!(state_chain.tip_position(), 6);
+        let stale = StateChainSig::new_bound(&keys[6], &transfer, &pks[0], &statechain_id, 1).unwrap();
+        assert!(state_chain.add(&stale).is_err());
+        let other = StateChainSig::new_bound(&keys[6], &transfer, &pks[0], &Uuid::new_v4(), 6).unwrap();
+        assert!(state_chain.add(&other).is_err());
+
+        // Pruned chains round trip
+        let json = serde_json::to_string(&state_chain).unwrap();
+        let unchecked: StateChainUnchecked = serde_json::from_str(&json).unwrap();
+        assert_eq!(StateChain::try_from(unchecked).unwrap(), state_chain);
+
+        // Unlinked segments are ignored, but the archive must be complete and unaltered
+        let orphan = ArchivedSegment::new(5, vec![State::example()], segments[1].hash.clone()).unwrap();
+        assert_eq!(state_chain.restore(&vec![orphan, segments[1].clone(), segments[0].clone()]).unwrap(), full);
+        assert!(state_chain.restore(&segments[1..].to_vec()).is_err());
+        assert!(state_chain.restore(&segments[..1].to_vec()).is_err());
+        let mut altered = segments.clone();
+        altered[0].states[1].data = pks[6].clone();
+        assert!(state_chain.restore(&altered).is_err());
+    }
+
     #[test]
     fn test_convert_to_state_chain() {
         let sc1 = StateChain::example();
@@ -439,6 +2075,7 @@ mod tests {
             &root,
             &funding_txid,
             &proof_key,
+            10000,
         )
         .unwrap();
 
@@ -446,7 +2083,9 @@ mod tests {
             gen_proof_smt::<monotree::database::MemoryDB>(tree.clone(), &root, &funding_txid)
                 .unwrap();
 
-        assert!(verify_statechain_smt(&root, &proof_key, &sc_smt_proof1));
+        assert!(verify_statechain_smt(&root, &funding_txid, &proof_key, 10000, &sc_smt_proof1));
+        // The leaf commits to the amount
+        assert!(!verify_statechain_smt(&root, &funding_txid, &proof_key, 20000, &sc_smt_proof1));
 
         // update with new proof key and try again
         let proof_key =
@@ -456,12 +2095,889 @@ mod tests {
             &root,
             &funding_txid,
             &proof_key,
+            10000,
         )
         .unwrap();
 
         let sc_smt_proof2 =
             gen_proof_smt::<monotree::database::MemoryDB>(tree.clone(), &root, &funding_txid)
                 .unwrap();
-        assert!(verify_statechain_smt(&root, &proof_key, &sc_smt_proof2));
+        assert!(verify_statechain_smt(&root, &funding_txid, &proof_key, 10000, &sc_smt_proof2));
+    }
+
+    #[test]
+    fn test_update_sc_smt_batch() {
+        let entries: Vec<(String, String, u64)> = vec![
+            (
+                String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e"),
+                String::from("03b971d624567214a2e9a53995ee7d4858d6355eb4e3863d9ac540085c8b2d12b3"),
+                10000,
+            ),
+            (
+                String::from("a1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e"),
+                String::from("02b971d624567214a2e9a53995ee7d4858d6355eb4e3863d9ac540085c8b2d12b3"),
+                20000,
+            ),
+            // Second entry for the first funding txid replaces the first
+            (
+                String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e"),
+                String::from("13b971d624567214a2e9a53995ee7d4858d6355eb4e3863d9ac540085c8b2d12b3"),
+                10000,
+            ),
+        ];
+
+        // One at a time
+        let tree = Arc::new(Mutex::new(Monotree::<MemoryDB, Blake3>::new("")));
+        let mut root: Option<monotree::Hash> = None;
+        for (funding_txid, proof_key, amount) in &entries {
+            root = update_statechain_smt(tree.clone(), &root, funding_txid, proof_key, *amount)
+                .unwrap();
+        }
+
+        // Batched
+        let tree_batch = Arc::new(Mutex::new(Monotree::<MemoryDB, Blake3>::new("")));
+        let root_batch = update_statechain_smt_batch(tree_batch.clone(), &None, &entries).unwrap();
+        assert_eq!(root_batch, root);
+
+        for (funding_txid, proof_key, amount) in &entries[1..] {
+            let proof = gen_proof_smt(tree_batch.clone(), &root_batch, funding_txid).unwrap();
+            assert!(verify_statechain_smt(&root_batch, funding_txid, proof_key, *amount, &proof));
+        }
+
+        // Invalid entries are rejected without updating the tree
+        let invalid = vec![(String::from("c156"), entries[0].1.clone(), 10000)];
+        assert!(update_statechain_smt_batch(tree_batch.clone(), &root_batch, &invalid).is_err());
+    }
+
+    /// Malformed signatures, keys and SMT inputs as might be sent in requests
+    fn malformed_strings() -> Vec<String> {
+        let valid_sig = StateChainSig::example().sig;
+        vec![
+            String::from(""),
+            String::from("00"),
+            String::from("zz"),
+            String::from("not hex at all"),
+            "ab".repeat(200),
+            valid_sig[..valid_sig.len() - 2].to_string(),
+            valid_sig[2..].to_string(),
+            format!("{}00", valid_sig),
+            "\u{e9}".repeat(40),
+            format!("{}{}", "a".repeat(31), "\u{e9}"),
+        ]
+    }
+
+    #[test]
+    fn test_verify_malformed_sig() {
+        let secp = Secp256k1::new();
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &proof_key_priv).to_string();
+        let valid = StateChainSig::new(&proof_key_priv, &String::from("TRANSFER"), &proof_key).unwrap();
+        assert!(valid.verify(&proof_key).is_ok());
+
+        for malformed in malformed_strings() {
+            let mut sig = valid.clone();
+            sig.sig = malformed.clone();
+            match sig.verify(&proof_key) {
+                Err(SharedLibError::FormatError(_)) | Err(SharedLibError::SignatureError(_)) => (),
+                res => assert!(false, "unexpected result for sig {:?}: {:?}", malformed, res),
+            }
+            match valid.verify(&malformed) {
+                Err(SharedLibError::FormatError(_)) | Err(SharedLibError::SignatureError(_)) => (),
+                res => assert!(false, "unexpected result for key {:?}: {:?}", malformed, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_smt_malformed_input() {
+        let funding_txid =
+            String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e");
+        let tree = Arc::new(Mutex::new(Monotree::<MemoryDB, Blake3>::new("")));
+        let root = update_statechain_smt::<MemoryDB>(tree.clone(), &None, &funding_txid, &funding_txid, 10000).unwrap();
+        let proof = gen_proof_smt::<MemoryDB>(tree.clone(), &root, &funding_txid).unwrap();
+
+        for malformed in malformed_strings() {
+            if malformed.len() >= 32 {
+                continue;
+            }
+            assert!(update_statechain_smt::<MemoryDB>(tree.clone(), &root, &malformed, &funding_txid, 10000).is_err());
+            assert!(update_statechain_smt::<MemoryDB>(tree.clone(), &root, &funding_txid, &malformed, 10000).is_err());
+            assert!(gen_proof_smt::<MemoryDB>(tree.clone(), &root, &malformed).is_err());
+            assert!(!verify_statechain_smt(&root, &funding_txid, &malformed, 10000, &proof));
+        }
+    }
+
+    #[test]
+    fn test_verify_build_manifest() {
+        let secp = Secp256k1::new();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+        let git_commit = String::from("4a7d449bb5e4e1d4c26c9e29f6a2a4f3b3c7a1d0");
+        let manifest = BuildManifest {
+            version: String::from("0.3.12"),
+            git_commit: git_commit.clone(),
+            git_dirty: false,
+            cargo_lock_hash: String::from("00"),
+            features: vec![String::from("release")],
+            rustc_version: String::from("rustc 1.50.0"),
+            profile: String::from("release"),
+            release: true,
+        };
+        // Digest of the canonical encoding (test vector in statechain_verify::message)
+        assert_eq!(
+            build_manifest_message(&manifest).unwrap(),
+            Message::from_slice(
+                &hex::decode("085d41ad46e2fef6a1b14edd01163246ccba69bcb2b16c5e1cd675e3e7f7bfca")
+                    .unwrap()
+            )
+            .unwrap()
+        );
+
+        let signed = sign_build_manifest(&identity_key, manifest.clone()).unwrap();
+        assert!(verify_build_manifest(&signed, &server_pubkey, &git_commit).is_ok());
+        // JSON round trip
+        let deser: SignedBuildManifest =
+            serde_json::from_str(&serde_json::to_string(&signed).unwrap()).unwrap();
+        assert!(verify_build_manifest(&deser, &server_pubkey, &git_commit).is_ok());
+
+        // Other commit or key
+        assert!(verify_build_manifest(&signed, &server_pubkey, &String::from("00")).is_err());
+        let other_key = SecretKey::from_slice(&[4; 32]).unwrap();
+        let other_pubkey = PublicKey::from_secret_key(&secp, &other_key).to_string();
+        assert!(verify_build_manifest(&signed, &other_pubkey, &git_commit).is_err());
+        // Tampered
+        let mut tampered = signed.clone();
+        tampered.manifest.features = vec![];
+        assert!(verify_build_manifest(&tampered, &server_pubkey, &git_commit).is_err());
+        // Unsigned
+        let unsigned = SignedBuildManifest {
+            manifest: manifest.clone(),
+            server_pubkey: None,
+            sig: None,
+        };
+        assert!(verify_build_manifest(&unsigned, &server_pubkey, &git_commit).is_err());
+        // Not a release build
+        let dev = sign_build_manifest(
+            &identity_key,
+            BuildManifest {
+                release: false,
+                ..manifest
+            },
+        )
+        .unwrap();
+        assert!(verify_build_manifest(&dev, &server_pubkey, &git_commit).is_err());
+    }
+
+    #[test]
+    fn test_verify_swap_report() {
+        let secp = Secp256k1::new();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+        let report = SwapReport {
+            swap_id: Uuid::new_v4(),
+            participants: 5,
+            denomination: 100000,
+            completed_at: Utc::now().naive_utc(),
+        };
+
+        let signed = sign_swap_report(&identity_key, report.clone()).unwrap();
+        assert!(verify_swap_report(&signed, &server_pubkey).is_ok());
+        // JSON round trip
+        let deser: SignedSwapReport =
+            serde_json::from_str(&serde_json::to_string(&signed).unwrap()).unwrap();
+        assert!(verify_swap_report(&deser, &server_pubkey).is_ok());
+
+        // Other key
+        let other_key = SecretKey::from_slice(&[4; 32]).unwrap();
+        let other_pubkey = PublicKey::from_secret_key(&secp, &other_key).to_string();
+        assert!(verify_swap_report(&signed, &other_pubkey).is_err());
+        // Tampered
+        let mut tampered = signed.clone();
+        tampered.report.participants = 50;
+        assert!(verify_swap_report(&tampered, &server_pubkey).is_err());
+        // Unsigned
+        let unsigned = SignedSwapReport {
+            report,
+            server_pubkey: None,
+            sig: None,
+        };
+        assert!(verify_swap_report(&unsigned, &server_pubkey).is_err());
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let secp = Secp256k1::new();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+        let body = br#"{"x1":"00"}"#;
+        let sig = sign_response(&identity_key, "nonce", "/transfer/sender", body).unwrap();
+        assert!(verify_response(&server_pubkey, &sig, "nonce", "/transfer/sender", body).is_ok());
+        // Leading slash of the route is not signed
+        assert!(verify_response(&server_pubkey, &sig, "nonce", "transfer/sender", body).is_ok());
+
+        // Replayed to another request, route or body
+        assert!(verify_response(&server_pubkey, &sig, "other", "transfer/sender", body).is_err());
+        assert!(verify_response(&server_pubkey, &sig, "nonce", "withdraw/init", body).is_err());
+        assert!(
+            verify_response(&server_pubkey, &sig, "nonce", "transfer/sender", b"{}").is_err()
+        );
+        // Other key or invalid signature
+        let other_key = SecretKey::from_slice(&[4; 32]).unwrap();
+        let other_pubkey = PublicKey::from_secret_key(&secp, &other_key).to_string();
+        assert!(verify_response(&other_pubkey, &sig, "nonce", "transfer/sender", body).is_err());
+        assert!(verify_response(&server_pubkey, "00", "nonce", "transfer/sender", body).is_err());
+    }
+
+    #[test]
+    fn test_verify_statechain_export() {
+        let secp = Secp256k1::new();
+        let export_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &export_key).to_string();
+        let proof_key1_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key1 = PublicKey::from_secret_key(&secp, &proof_key1_priv).to_string();
+        let proof_key2 = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap()).to_string();
+        let funding_txid =
+            String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e");
+
+        let mut state_chain = StateChain::new(proof_key1.clone());
+        state_chain
+            .add(&StateChainSig::new(&proof_key1_priv, &String::from("TRANSFER"), &proof_key2).unwrap())
+            .unwrap();
+
+        let tree = Arc::new(Mutex::new(Monotree::<MemoryDB, Blake3>::new("")));
+        let root = update_statechain_smt::<MemoryDB>(tree.clone(), &None, &funding_txid, &proof_key2, 10000)
+            .unwrap();
+        let proof = gen_proof_smt::<MemoryDB>(tree.clone(), &root, &funding_txid).unwrap();
+
+        let export = StateChainExport {
+            version: STATECHAIN_EXPORT_VERSION,
+            statechain_id: Uuid::new_v4(),
+            amount: 10000,
+            funding_txid,
+            chain: state_chain.get_chain().clone(),
+            tx_backup_hex: None,
+            backup_txs: vec![],
+            roots: vec![crate::structs::StateChainExportRoot {
+                root: crate::Root::from_hash(&root.unwrap()),
+                proof,
+            }],
+            exported_at: Utc::now().naive_utc(),
+            server_pubkey: server_pubkey.clone(),
+        };
+        let signed = sign_statechain_export(&export_key, export).unwrap();
+        assert!(verify_statechain_export(&signed, &server_pubkey).is_ok());
+
+        // survives a serialization round trip
+        let ser = serde_json::to_string(&signed).unwrap();
+        let deser: SignedStateChainExport = serde_json::from_str(&ser).unwrap();
+        assert!(verify_statechain_export(&deser, &server_pubkey).is_ok());
+
+        // unexpected server key
+        assert!(verify_statechain_export(&signed, &proof_key1).is_err());
+
+        // tampered amount
+        let mut tampered = signed.clone();
+        tampered.export.amount = 20000;
+        assert!(verify_statechain_export(&tampered, &server_pubkey).is_err());
+
+        // root not including any chain proof key, re-signed
+        let mut export = signed.export.clone();
+        export.roots[0].root = crate::Root::from_random();
+        let resigned = sign_statechain_export(&export_key, export).unwrap();
+        assert!(verify_statechain_export(&resigned, &server_pubkey).is_err());
+
+        // version 1 exports signed over their JSON encoding are accepted
+        let mut export = signed.export.clone();
+        export.version = 1;
+        let legacy = sign_statechain_export(&export_key, export).unwrap();
+        assert!(verify_statechain_export(&legacy, &server_pubkey).is_ok());
+        let mut tampered = legacy.clone();
+        tampered.export.amount = 20000;
+        assert!(verify_statechain_export(&tampered, &server_pubkey).is_err());
+    }
+
+    #[test]
+    fn test_verify_statechain_export_backup_txs() {
+        let secp = Secp256k1::new();
+        let export_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &export_key).to_string();
+        let proof_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap()).to_string();
+        let funding_txid =
+            String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e");
+        let backup_tx = |funding_txid: &str, lock_time: u32| {
+            crate::util::transaction_serialise(&bitcoin::Transaction {
+                version: 2,
+                lock_time,
+                input: vec![bitcoin::TxIn {
+                    previous_output: bitcoin::OutPoint {
+                        txid: bitcoin::Txid::from_str(funding_txid).unwrap(),
+                        vout: 0,
+                    },
+                    script_sig: bitcoin::Script::new(),
+                    sequence: 0xFFFFFFFE,
+                    witness: vec![],
+                }],
+                output: vec![],
+            })
+        };
+        let tx_backup_1 = backup_tx(&funding_txid, 10000);
+        let tx_backup_2 = backup_tx(&funding_txid, 9990);
+
+        let export = StateChainExport {
+            version: STATECHAIN_EXPORT_VERSION,
+            statechain_id: Uuid::new_v4(),
+            amount: 10000,
+            funding_txid: funding_txid.clone(),
+            chain: StateChain::new(proof_key).get_chain().clone(),
+            tx_backup_hex: Some(tx_backup_2.clone()),
+            backup_txs: vec![tx_backup_1.clone(), tx_backup_2.clone()],
+            roots: vec![],
+            exported_at: Utc::now().naive_utc(),
+            server_pubkey: server_pubkey.clone(),
+        };
+        let signed = sign_statechain_export(&export_key, export).unwrap();
+        assert!(verify_statechain_export(&signed, &server_pubkey).is_ok());
+
+        // backup txs are signed
+        let mut tampered = signed.clone();
+        tampered.export.backup_txs.remove(0);
+        assert!(verify_statechain_export(&tampered, &server_pubkey).is_err());
+
+        // history missing the current backup tx, re-signed
+        let mut export = signed.export.clone();
+        export.backup_txs = vec![tx_backup_1.clone()];
+        let resigned = sign_statechain_export(&export_key, export).unwrap();
+        assert!(verify_statechain_export(&resigned, &server_pubkey).is_err());
+
+        // backup tx of another statecoin, re-signed
+        let mut export = signed.export.clone();
+        export.backup_txs.insert(
+            0,
+            backup_tx("5ab8f5b2a2ad8c4c7a4ebd1cf1b1d7d3c8cbbf3fe5d6f57a4e3db2d0f1a3a9e2", 10000),
+        );
+        let resigned = sign_statechain_export(&export_key, export).unwrap();
+        assert!(verify_statechain_export(&resigned, &server_pubkey).is_err());
+
+        // duplicated backup tx, re-signed
+        let mut export = signed.export.clone();
+        export.backup_txs.insert(0, tx_backup_2.clone());
+        let resigned = sign_statechain_export(&export_key, export).unwrap();
+        assert!(verify_statechain_export(&resigned, &server_pubkey).is_err());
+    }
+
+    #[test]
+    fn test_verify_migration_package() {
+        let secp = Secp256k1::new();
+        let export_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &export_key).to_string();
+        let migration_pubkey =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[4; 32]).unwrap()).to_string();
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &proof_key_priv).to_string();
+        let funding_txid =
+            String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e");
+
+        let tx_backup = bitcoin::Transaction {
+            version: 2,
+            lock_time: 10000,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint {
+                    txid: bitcoin::Txid::from_str(&funding_txid).unwrap(),
+                    vout: 0,
+                },
+                script_sig: bitcoin::Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+
+        let tree = Arc::new(Mutex::new(Monotree::<MemoryDB, Blake3>::new("")));
+        let root = update_statechain_smt::<MemoryDB>(tree.clone(), &None, &funding_txid, &proof_key, 10000)
+            .unwrap();
+        let proof = gen_proof_smt::<MemoryDB>(tree.clone(), &root, &funding_txid).unwrap();
+
+        let export = StateChainExport {
+            version: STATECHAIN_EXPORT_VERSION,
+            statechain_id: Uuid::new_v4(),
+            amount: 10000,
+            funding_txid,
+            chain: StateChain::new(proof_key.clone()).get_chain().clone(),
+            tx_backup_hex: Some(crate::util::transaction_serialise(&tx_backup)),
+            backup_txs: vec![crate::util::transaction_serialise(&tx_backup)],
+            roots: vec![crate::structs::StateChainExportRoot {
+                root: crate::Root::from_hash(&root.unwrap()),
+                proof,
+            }],
+            exported_at: Utc::now().naive_utc(),
+            server_pubkey: server_pubkey.clone(),
+        };
+        let g: curv::GE = curv::elliptic::curves::traits::ECPoint::generator();
+        let package = MigrationPackage {
+            export: sign_statechain_export(&export_key, export).unwrap(),
+            statechain_sig: StateChainSig::new(&proof_key_priv, &String::from("MIGRATE"), &migration_pubkey)
+                .unwrap(),
+            s2: crate::structs::FESer::new_random(),
+            o2_pub: g,
+        };
+        let signed = sign_migration_package(&export_key, package).unwrap();
+        assert!(verify_migration_package(&signed, &server_pubkey, &migration_pubkey).is_ok());
+
+        // signed over to another state entity
+        assert!(verify_migration_package(&signed, &server_pubkey, &proof_key).is_err());
+
+        // unexpected old state entity key
+        assert!(verify_migration_package(&signed, &proof_key, &migration_pubkey).is_err());
+
+        // tampered key share
+        let mut tampered = signed.clone();
+        tampered.package.s2 = crate::structs::FESer::new_random();
+        assert!(verify_migration_package(&tampered, &server_pubkey, &migration_pubkey).is_err());
+
+        // transfer signature in place of a migration signature
+        let mut package = signed.package.clone();
+        package.statechain_sig =
+            StateChainSig::new(&proof_key_priv, &String::from("TRANSFER"), &migration_pubkey).unwrap();
+        let resigned = sign_migration_package(&export_key, package).unwrap();
+        assert!(verify_migration_package(&resigned, &server_pubkey, &migration_pubkey).is_err());
+    }
+
+    #[test]
+    fn test_verify_deletion_receipts() {
+        let secp = Secp256k1::new();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+        let proof_key_1 =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap()).to_string();
+        let proof_key_2 =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap()).to_string();
+        let statechain_id = Uuid::new_v4();
+        let g: GE = ECPoint::generator();
+
+        let receipt = DeletionReceipt {
+            statechain_id,
+            shared_key_id: Uuid::new_v4(),
+            proof_key: proof_key_1.clone(),
+            commitment: deletion_commitment(&g),
+            deleted_at: Utc::now().naive_utc(),
+            server_pubkey: server_pubkey.clone(),
+        };
+        let signed = sign_deletion_receipt(&identity_key, receipt.clone()).unwrap();
+        assert!(verify_deletion_receipt(&signed, &server_pubkey).is_ok());
+        assert!(verify_deletion_receipt(&signed, &proof_key_1).is_err());
+
+        assert!(verify_deletion_receipts(
+            &vec![signed.clone()],
+            &statechain_id,
+            &vec![proof_key_1.clone()],
+            &server_pubkey
+        )
+        .is_ok());
+        assert!(verify_deletion_receipts(&vec![], &statechain_id, &vec![], &server_pubkey).is_ok());
+
+        // missing receipt for a previous owner
+        assert!(verify_deletion_receipts(
+            &vec![signed.clone()],
+            &statechain_id,
+            &vec![proof_key_1.clone(), proof_key_2.clone()],
+            &server_pubkey
+        )
+        .is_err());
+
+        // receipt for another statechain
+        assert!(verify_deletion_receipts(&vec![signed.clone()], &Uuid::new_v4(), &vec![], &server_pubkey)
+            .is_err());
+
+        // tampered receipt
+        let mut tampered = signed.clone();
+        tampered.receipt.proof_key = proof_key_2.clone();
+        assert!(verify_deletion_receipts(&vec![tampered], &statechain_id, &vec![], &server_pubkey).is_err());
+
+        // receipt forged with another key it carries itself
+        let mut other = receipt;
+        other.proof_key = proof_key_2.clone();
+        other.server_pubkey = proof_key_1.clone();
+        let other = sign_deletion_receipt(&SecretKey::from_slice(&[1; 32]).unwrap(), other).unwrap();
+        assert!(verify_deletion_receipts(&vec![other.clone()], &statechain_id, &vec![], &server_pubkey)
+            .is_err());
+        assert!(verify_deletion_receipts(&vec![signed, other], &statechain_id, &vec![], &server_pubkey)
+            .is_err());
+    }
+
+    #[test]
+    fn test_verify_transfer_receipt() {
+        let secp = Secp256k1::new();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+        let proof_key_1 =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap()).to_string();
+        let proof_key_2 =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap()).to_string();
+        let statechain_id = Uuid::new_v4();
+
+        let receipt = TransferReceipt {
+            statechain_id,
+            prev_proof_key: proof_key_1.clone(),
+            new_proof_key: proof_key_2.clone(),
+            root_id: 7,
+            finalized_at: Utc::now().naive_utc(),
+            server_pubkey: server_pubkey.clone(),
+        };
+        let signed = sign_transfer_receipt(&identity_key, receipt.clone()).unwrap();
+        assert!(verify_transfer_receipt(&signed, &server_pubkey, &statechain_id, &proof_key_1).is_ok());
+
+        // unexpected server key
+        assert!(verify_transfer_receipt(&signed, &proof_key_1, &statechain_id, &proof_key_1).is_err());
+        // another statechain or sender
+        assert!(verify_transfer_receipt(&signed, &server_pubkey, &Uuid::new_v4(), &proof_key_1).is_err());
+        assert!(verify_transfer_receipt(&signed, &server_pubkey, &statechain_id, &proof_key_2).is_err());
+
+        // tampered receiver
+        let mut tampered = signed.clone();
+        tampered.receipt.new_proof_key = proof_key_1.clone();
+        assert!(verify_transfer_receipt(&tampered, &server_pubkey, &statechain_id, &proof_key_1).is_err());
+
+        // receipt forged with another key it carries itself
+        let mut forged = receipt;
+        forged.server_pubkey = proof_key_2.clone();
+        let forged = sign_transfer_receipt(&SecretKey::from_slice(&[2; 32]).unwrap(), forged).unwrap();
+        assert!(verify_transfer_receipt(&forged, &forged.receipt.server_pubkey, &statechain_id, &proof_key_1).is_ok());
+        assert!(verify_transfer_receipt(&forged, &server_pubkey, &statechain_id, &proof_key_1).is_err());
+    }
+
+    #[test]
+    fn test_ownership_confirmation() {
+        let secp = Secp256k1::new();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &proof_key_priv).to_string();
+        let statechain_id = Uuid::new_v4();
+        let nonce = String::from("5b8e2c33a5f04b8c9d6f0e1a2b3c4d5e");
+
+        let sig = StateChainSig::new_ownership_sig(&proof_key_priv, &nonce, &statechain_id).unwrap();
+        assert!(sig.is_ownership(&nonce, &statechain_id));
+        assert!(!sig.is_ownership(&String::from("other"), &statechain_id));
+        assert!(!sig.is_ownership(&nonce, &Uuid::new_v4()));
+        assert!(sig.verify(&proof_key).is_ok());
+
+        let confirmation = OwnershipConfirmation {
+            statechain_id,
+            nonce: nonce.clone(),
+            proof_key: proof_key.clone(),
+            amount: 1000,
+            confirmed_at: Utc::now().naive_utc(),
+            server_pubkey: server_pubkey.clone(),
+        };
+        let signed = sign_ownership_confirmation(&identity_key, confirmation.clone()).unwrap();
+        assert!(verify_ownership_confirmation(&signed, &server_pubkey, &statechain_id, &nonce).is_ok());
+
+        // unexpected server key
+        assert!(verify_ownership_confirmation(&signed, &proof_key, &statechain_id, &nonce).is_err());
+        // another statechain or nonce
+        assert!(verify_ownership_confirmation(&signed, &server_pubkey, &Uuid::new_v4(), &nonce).is_err());
+        assert!(verify_ownership_confirmation(&signed, &server_pubkey, &statechain_id, &String::from("other")).is_err());
+
+        // tampered proof key
+        let mut tampered = signed.clone();
+        tampered.confirmation.proof_key = server_pubkey.clone();
+        assert!(verify_ownership_confirmation(&tampered, &server_pubkey, &statechain_id, &nonce).is_err());
+
+        // confirmation forged with another key it carries itself
+        let mut forged = confirmation;
+        forged.server_pubkey = proof_key.clone();
+        let forged = sign_ownership_confirmation(&proof_key_priv, forged).unwrap();
+        assert!(verify_ownership_confirmation(&forged, &server_pubkey, &statechain_id, &nonce).is_err());
+    }
+
+    #[test]
+    fn test_transfer_reject() {
+        let secp = Secp256k1::new();
+        let sender_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let receiver_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let receiver_proof_key = PublicKey::from_secret_key(&secp, &receiver_priv).to_string();
+        let statechain_id = Uuid::new_v4();
+
+        let transfer_sig = StateChainSig::new(
+            &sender_priv,
+            &String::from("TRANSFER"),
+            &receiver_proof_key,
+        )
+        .unwrap();
+        let mut reject = TransferRejectAPI {
+            statechain_id,
+            transfer_sig: transfer_sig.clone(),
+            reject_sig: StateChainSig::new_transfer_reject_sig(&receiver_priv, &transfer_sig, &statechain_id).unwrap(),
+            rejected_at: Utc::now().naive_utc(),
+        };
+        assert!(verify_transfer_reject(&reject).is_ok());
+
+        // signed by the sender rather than the receiver
+        reject.reject_sig = StateChainSig::new_transfer_reject_sig(&sender_priv, &transfer_sig, &statechain_id).unwrap();
+        assert!(verify_transfer_reject(&reject).is_err());
+
+        // another statechain
+        reject.reject_sig = StateChainSig::new_transfer_reject_sig(&receiver_priv, &transfer_sig, &Uuid::new_v4()).unwrap();
+        assert!(verify_transfer_reject(&reject).is_err());
+    }
+
+    #[test]
+    fn test_second_factor_sig() {
+        let secp = Secp256k1::new();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let owner_proof_key = PublicKey::from_secret_key(&secp, &owner_priv).to_string();
+        let auth_priv = SecretKey::from_slice(&[3; 32]).unwrap();
+        let auth_key = Some(PublicKey::from_secret_key(&secp, &auth_priv).to_string());
+        let statechain_id = Uuid::new_v4();
+
+        let key_sig =
+            StateChainSig::new_second_factor_key_sig(&owner_priv, &auth_key, &statechain_id)
+                .unwrap();
+        assert!(key_sig.is_second_factor_key(&auth_key, &statechain_id));
+        assert!(!key_sig.is_second_factor_key(&None, &statechain_id));
+        assert!(!key_sig.is_second_factor_key(&auth_key, &Uuid::new_v4()));
+        assert!(key_sig.verify(&owner_proof_key).is_ok());
+        let remove_sig =
+            StateChainSig::new_second_factor_key_sig(&owner_priv, &None, &statechain_id).unwrap();
+        assert!(remove_sig.is_second_factor_key(&None, &statechain_id));
+
+        let transfer_sig =
+            StateChainSig::new(&owner_priv, &String::from("TRANSFER"), &owner_proof_key).unwrap();
+        let auth_sig = StateChainSig::new_second_factor_sig(&auth_priv, &transfer_sig).unwrap();
+        assert!(auth_sig.is_second_factor(&transfer_sig));
+        assert!(auth_sig.verify(&auth_key.unwrap()).is_ok());
+        assert!(auth_sig.verify(&owner_proof_key).is_err());
+        // another operation
+        let withdraw_sig =
+            StateChainSig::new(&owner_priv, &String::from("WITHDRAW"), &owner_proof_key).unwrap();
+        assert!(!auth_sig.is_second_factor(&withdraw_sig));
+    }
+
+    #[test]
+    fn test_vault_sig() {
+        let secp = Secp256k1::new();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let owner_proof_key = PublicKey::from_secret_key(&secp, &owner_priv).to_string();
+        let statechain_id = Uuid::new_v4();
+
+        let vault_sig = StateChainSig::new_vault_sig(&owner_priv, 86400, &statechain_id).unwrap();
+        assert!(vault_sig.is_vault(86400, &statechain_id));
+        assert!(!vault_sig.is_vault(0, &statechain_id));
+        assert!(!vault_sig.is_vault(86400, &Uuid::new_v4()));
+        assert!(vault_sig.verify(&owner_proof_key).is_ok());
+
+        let transfer_sig =
+            StateChainSig::new(&owner_priv, &String::from("TRANSFER"), &owner_proof_key).unwrap();
+        let cancel_sig =
+            StateChainSig::new_vault_cancel_sig(&owner_priv, &transfer_sig, &statechain_id).unwrap();
+        assert!(cancel_sig.is_vault_cancel(&transfer_sig, &statechain_id));
+        assert!(!cancel_sig.is_vault_cancel(&vault_sig, &statechain_id));
+        assert!(!cancel_sig.is_vault_cancel(&transfer_sig, &Uuid::new_v4()));
+        assert!(cancel_sig.verify(&owner_proof_key).is_ok());
+    }
+
+    #[test]
+    fn test_rotate_sig() {
+        let secp = Secp256k1::new();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let owner_proof_key = PublicKey::from_secret_key(&secp, &owner_priv).to_string();
+        let new_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let new_proof_key = PublicKey::from_secret_key(&secp, &new_priv).to_string();
+        let statechain_id = Uuid::new_v4();
+
+        let mut state_chain = StateChain::new(owner_proof_key.clone());
+        let sig =
+            StateChainSig::new_rotate_sig(&owner_priv, &new_proof_key, &statechain_id, 0).unwrap();
+        assert!(sig.is_rotate());
+        assert!(sig.check_binding(&statechain_id, 0, true).is_ok());
+        assert!(!StateChainSig::new(&owner_priv, &String::from("TRANSFER"), &new_proof_key)
+            .unwrap()
+            .is_rotate());
+        state_chain.add(&sig).unwrap();
+        assert_eq!(state_chain.get_tip().data, new_proof_key);
+        assert_eq!(state_chain.get_tip().locktime, None);
+
+        // The old proof key can no longer sign for the chain
+        let stale =
+            StateChainSig::new_rotate_sig(&owner_priv, &owner_proof_key, &statechain_id, 1).unwrap();
+        assert!(state_chain.add(&stale).is_err());
+        let back =
+            StateChainSig::new_rotate_sig(&new_priv, &owner_proof_key, &statechain_id, 1).unwrap();
+        state_chain.add(&back).unwrap();
+        assert_eq!(state_chain.tip_position(), 2);
+    }
+
+    #[test]
+    fn test_inheritance_sig() {
+        let secp = Secp256k1::new();
+        let owner_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let owner_proof_key = PublicKey::from_secret_key(&secp, &owner_priv).to_string();
+        let beneficiary_priv = SecretKey::from_slice(&[2; 32]).unwrap();
+        let beneficiary_proof_key =
+            PublicKey::from_secret_key(&secp, &beneficiary_priv).to_string();
+        let statechain_id = Uuid::new_v4();
+
+        let transfer_sig = StateChainSig::new(
+            &owner_priv,
+            &String::from("TRANSFER"),
+            &beneficiary_proof_key,
+        )
+        .unwrap();
+        let sig =
+            StateChainSig::new_inheritance_sig(&owner_priv, &transfer_sig, 86400, &statechain_id)
+                .unwrap();
+        assert!(sig.is_inheritance(&transfer_sig, 86400, &statechain_id));
+        assert!(sig.verify(&owner_proof_key).is_ok());
+        assert!(sig.verify(&beneficiary_proof_key).is_err());
+
+        // another inactivity period, statechain or transfer
+        assert!(!sig.is_inheritance(&transfer_sig, 3600, &statechain_id));
+        assert!(!sig.is_inheritance(&transfer_sig, 86400, &Uuid::new_v4()));
+        let other_transfer_sig = StateChainSig::new(
+            &owner_priv,
+            &String::from("TRANSFER"),
+            &owner_proof_key,
+        )
+        .unwrap();
+        assert!(!sig.is_inheritance(&other_transfer_sig, 86400, &statechain_id));
+    }
+
+    #[test]
+    fn test_sale_preimage() {
+        let preimage = hex::encode(&[7; 32]);
+        let payment_hash = sale_payment_hash(&preimage).unwrap();
+        assert_eq!(payment_hash.len(), 64);
+        assert!(verify_sale_preimage(&preimage, &payment_hash).is_ok());
+        assert!(verify_sale_preimage(&preimage, &payment_hash.to_uppercase()).is_ok());
+        assert!(verify_sale_preimage(&hex::encode(&[8; 32]), &payment_hash).is_err());
+        assert!(verify_sale_preimage(&String::from("not hex"), &payment_hash).is_err());
+    }
+
+    #[test]
+    fn test_fee_quote() {
+        let secp = Secp256k1::new();
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+        let mut quote = StateEntityFeeInfoAPI::example();
+        quote.valid_until = Some(NaiveDateTime::from_timestamp(1700000000, 0));
+
+        // Digest of the canonical encoding (test vector in statechain_verify::message)
+        assert_eq!(
+            fee_quote_hash(&quote).unwrap(),
+            "90dc34213c67fd5250f9923bb495937803545d4ae9c97fd9929d4296903a5f8b"
+        );
+
+        let signed = sign_fee_quote(&identity_key, quote.clone()).unwrap();
+        assert!(verify_fee_quote(&signed, &server_pubkey).is_ok());
+        assert!(verify_fee_quote(&quote, &server_pubkey).is_err());
+        let other_pubkey =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap()).to_string();
+        assert!(verify_fee_quote(&signed, &other_pubkey).is_err());
+
+        // The hash does not commit to the signature
+        assert_eq!(fee_quote_hash(&signed).unwrap(), fee_quote_hash(&quote).unwrap());
+
+        // tampered fee
+        let mut tampered = signed.clone();
+        tampered.withdraw = 1;
+        assert!(verify_fee_quote(&tampered, &server_pubkey).is_err());
+        assert_ne!(fee_quote_hash(&tampered).unwrap(), fee_quote_hash(&signed).unwrap());
+
+        // Capacity and the deposit pause flag are not committed to
+        let mut capacity = signed.clone();
+        capacity.capacity = Some(1000);
+        capacity.deposits_paused = true;
+        assert!(verify_fee_quote(&capacity, &server_pubkey).is_ok());
+    }
+
+    #[test]
+    fn test_verify_reserve_proof() {
+        let secp = Secp256k1::new();
+        let proof_key_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof_key = PublicKey::from_secret_key(&secp, &proof_key_priv).to_string();
+        let funding_txid =
+            String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e");
+        let statechain_id = Uuid::new_v4();
+        let nonce = String::from("nonce");
+        let identity_key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let server_pubkey = PublicKey::from_secret_key(&secp, &identity_key).to_string();
+
+        let tree = Arc::new(Mutex::new(Monotree::<MemoryDB, Blake3>::new("")));
+        let root = update_statechain_smt::<MemoryDB>(tree.clone(), &None, &funding_txid, &proof_key, 10000)
+            .unwrap();
+        let proof = gen_proof_smt::<MemoryDB>(tree.clone(), &root, &funding_txid).unwrap();
+
+        let mut report = ReserveProofReport {
+            nonce: nonce.clone(),
+            root: crate::Root::from_hash(&root.unwrap()),
+            entries: vec![crate::structs::ReserveProofEntry {
+                statechain_id,
+                amount: 10000,
+                funding_txid,
+                proof_key,
+                statechain_sig: StateChainSig::new_reserves_sig(&proof_key_priv, &nonce, &statechain_id)
+                    .unwrap(),
+                proof,
+            }],
+            total: 10000,
+            server_pubkey: server_pubkey.clone(),
+        };
+        assert_eq!(verify_reserve_entries(&report, &nonce).unwrap(), 10000);
+
+        // root is not attested
+        let signed = sign_reserve_report(&identity_key, report.clone()).unwrap();
+        assert!(verify_reserve_proof(&signed, &nonce, &server_pubkey).is_err());
+
+        verify_reserve_report_sig(&signed, &server_pubkey).unwrap();
+
+        // unexpected server key
+        let other_pubkey = PublicKey::from_secret_key(&secp, &proof_key_priv).to_string();
+        assert!(verify_reserve_report_sig(&signed, &other_pubkey).is_err());
+        // report signed by another key that it carries is rejected against the known key
+        let mut forged = report.clone();
+        forged.server_pubkey = other_pubkey.clone();
+        let forged = sign_reserve_report(&proof_key_priv, forged).unwrap();
+        assert!(verify_reserve_report_sig(&forged, &other_pubkey).is_ok());
+        assert!(verify_reserve_report_sig(&forged, &server_pubkey).is_err());
+
+        // report modified after signing
+        let mut tampered = signed.clone();
+        tampered.report.entries[0].amount = 20000;
+        tampered.report.total = 20000;
+        assert!(verify_reserve_report_sig(&tampered, &server_pubkey).is_err());
+
+        // inflated amount does not match the committed leaf
+        let mut inflated = report.clone();
+        inflated.entries[0].amount = 20000;
+        inflated.total = 20000;
+        assert!(verify_reserve_entries(&inflated, &nonce).is_err());
+
+        // proof reused under another funding txid
+        let mut reused = report.clone();
+        reused.entries[0].funding_txid =
+            String::from("a1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e");
+        assert!(verify_reserve_entries(&reused, &nonce).is_err());
+
+        // wrong nonce
+        assert!(verify_reserve_entries(&report, &String::from("other")).is_err());
+
+        // signature over a different nonce
+        report.entries[0].statechain_sig =
+            StateChainSig::new_reserves_sig(&proof_key_priv, &String::from("other"), &statechain_id)
+                .unwrap();
+        assert!(verify_reserve_entries(&report, &nonce).is_err());
+        report.entries[0].statechain_sig =
+            StateChainSig::new_reserves_sig(&proof_key_priv, &nonce, &statechain_id).unwrap();
+
+        // overstated total
+        report.total = 20000;
+        assert!(verify_reserve_entries(&report, &nonce).is_err());
+        report.total = 10000;
+
+        // duplicated statecoin
+        let entry = report.entries[0].clone();
+        report.entries.push(entry);
+        report.total = 20000;
+        assert!(verify_reserve_entries(&report, &nonce).is_err());
     }
 }
@@ -5,6 +5,8 @@
 use crate::error::SharedLibError;
 use crate::state_chain::{State, StateChainSig};
 use crate::Root;
+use monotree::Proof;
+use crate::smt_proof::{EncodedProof, ProofFormat};
 use bitcoin::{OutPoint, Transaction, TxIn, TxOut};
 use curv::{cryptographic_primitives::proofs::sigma_dlog::DLogProof, BigInt, FE, GE, PK};
 use kms::ecdsa::two_party::{party1,party2};
@@ -18,7 +20,7 @@ use schemars;
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use serde::de::{self, Visitor, Unexpected};
 use regex::Regex;
-use chrono::{NaiveDateTime, Utc};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
 use std::default::Default;
 use std::num::NonZeroU64;
 
@@ -31,6 +33,24 @@ pub enum Protocol {
     Deposit,
     Transfer,
     Withdraw,
+    /// Refund of a deposit that never reached statechain creation
+    Refund,
+}
+
+/// Backup tx timelock mode
+/// Absolute: backup txs are locked with nLockTime (block height).
+/// Relative: backup txs are locked with a BIP68 nSequence (blocks after funding confirmation).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelockMode {
+    Absolute,
+    Relative,
+}
+
+impl Default for TimelockMode {
+    fn default() -> Self {
+        TimelockMode::Absolute
+    }
 }
 
 // API structs
@@ -95,7 +115,7 @@ impl FESer {
 /// Statechain entity operating information
 /// This struct is returned containing information on operating requirements
 /// of the statechain entity which must be conformed with in the protocol.
-#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 #[schemars(example = "Self::example")]
 pub struct StateEntityFeeInfoAPI {
     /// The Bitcoin address that the SE fee must be paid to
@@ -104,14 +124,45 @@ pub struct StateEntityFeeInfoAPI {
     pub deposit: i64,    // basis points
     /// The withdrawal fee, which is specified as a proportion of the deposit amount in basis points
     pub withdraw: u64,   // basis points
+    /// The withdrawal fee charged per statecoin withdrawn (satoshis), in addition to the
+    /// proportional fee
+    #[serde(default)]
+    pub withdraw_input: u64,
+    /// The maximum number of statecoins withdrawn in one withdraw tx. 0 for no limit.
+    #[serde(default)]
+    pub max_withdraw_inputs: u64,
     /// The decementing nLocktime (block height) interval enforced for backup transactions
     pub interval: u32,   // locktime decrement interval in blocks
     /// The initial nLocktime from the current blockheight for the first backup
     pub initlock: u32,   // inital backup locktime
+    /// Whether backup txs use absolute (nLockTime) or relative (nSequence) timelocks
+    #[serde(default)]
+    pub timelock_mode: TimelockMode,
+    /// The minimum statecoin amount (satoshis) that can be deposited
+    #[serde(default)]
+    pub min_deposit: u64,
+    /// Outputs paying the owner less than this amount (satoshis) are rejected as dust
+    #[serde(default)]
+    pub dust_limit: u64,
+    /// Remaining deposit capacity (satoshis) under the cap on the total value of statecoins.
+    /// None if there is no cap. Not part of the quote hash.
+    #[serde(default)]
+    pub capacity: Option<u64>,
+    /// Whether new deposits are paused. Not part of the quote hash.
+    #[serde(default)]
+    pub deposits_paused: bool,
     /// The minumum wallet version required
     pub wallet_version: String,
     /// Message to display to all wallet users on startup
     pub wallet_message: String,
+    /// Time until which the fee quote is valid. Deposits acknowledging an expired quote are
+    /// rejected.
+    #[serde(default)]
+    pub valid_until: Option<NaiveDateTime>,
+    /// State entity identity key signature over the quote hash. None if the state entity has no
+    /// identity key.
+    #[serde(default)]
+    pub sig: Option<String>,
 }
 
 impl StateEntityFeeInfoAPI{
@@ -120,10 +171,19 @@ impl StateEntityFeeInfoAPI{
             address: "bc1qzvv6yfeg0navfkrxpqc0fjdsu9ey4qgqqsarq4".to_string(),
             deposit: 0,
             withdraw: 300,
+            withdraw_input: 0,
+            max_withdraw_inputs: 100,
             interval: 144,
             initlock: 14400,
+            timelock_mode: TimelockMode::Absolute,
+            min_deposit: 100000,
+            dust_limit: 546,
+            capacity: None,
+            deposits_paused: false,
             wallet_version: "0.4.65".to_string(),
             wallet_message: "Warning".to_string(),
+            valid_until: None,
+            sig: None,
         }
     }
 }
@@ -132,9 +192,83 @@ impl fmt::Display for StateEntityFeeInfoAPI {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Fee address: {},\nDeposit fee rate: {}\nWithdrawal fee rate: {}\nLock interval: {}\nInitial lock: {}",
-            self.address, self.deposit, self.withdraw, self.interval, self.initlock
-        )
+            "Fee address: {},\nDeposit fee rate: {}\nWithdrawal fee rate: {}\nLock interval: {}\nInitial lock: {}\nTimelock mode: {:?}\nMinimum deposit: {}\nDust limit: {}",
+            self.address, self.deposit, self.withdraw, self.interval, self.initlock, self.timelock_mode,
+            self.min_deposit, self.dust_limit
+        )?;
+        if self.withdraw_input > 0 {
+            write!(f, "\nWithdrawal fee per statecoin: {}", self.withdraw_input)?;
+        }
+        if self.max_withdraw_inputs > 0 {
+            write!(f, "\nMaximum statecoins per withdrawal: {}", self.max_withdraw_inputs)?;
+        }
+        if let Some(capacity) = self.capacity {
+            write!(f, "\nDeposit capacity: {}", capacity)?;
+        }
+        if self.deposits_paused {
+            write!(f, "\nDeposits paused")?;
+        }
+        Ok(())
+    }
+}
+
+/// Statechain entity protocol parameters
+/// Protocol constants of the statechain entity, for clients to configure themselves with
+/// rather than hardcoding values.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[schemars(example = "Self::example")]
+pub struct StateEntityParamsAPI {
+    /// Bitcoin network
+    pub network: String,
+    /// The initial nLocktime from the current blockheight for the first backup
+    pub initlock: u32,
+    /// The decrementing nLocktime (block height) interval enforced for backup transactions
+    pub interval: u32,
+    /// Whether backup txs use absolute (nLockTime) or relative (nSequence) timelocks
+    pub timelock_mode: TimelockMode,
+    /// Confirmations of the funding tx required to complete a deposit
+    pub required_confirmation: u32,
+    /// Whether deposits require a proof of work solution
+    pub deposit_pow: bool,
+    /// Deposit proof of work difficulty (leading zero hex digits)
+    pub pow_difficulty: u64,
+    /// Maximum fee (satoshis) of a transaction the state entity will co-sign
+    pub max_tx_fee: u64,
+    /// Time (seconds) allowed for a batch transfer to complete
+    pub batch_lifetime: u64,
+    /// Time (seconds) a statecoin is locked for after failing to complete a batch transfer
+    pub punishment_duration: u64,
+    /// Statecoin amounts (satoshis) permitted in swaps
+    pub denominations: Vec<u64>,
+    /// Time (seconds) a swap must be completed by once its group is formed
+    pub swap_timeout: u32,
+    /// Time (seconds) within which a statecoin registered for a swap must be polled
+    pub utxo_timeout: u32,
+    /// Maximum number of statecoins in a swap
+    pub max_swap_size: u32,
+    /// Minimum wallet version required for swaps
+    pub swap_wallet_version: String,
+}
+
+impl StateEntityParamsAPI {
+    pub fn example() -> Self {
+        Self {
+            network: "bitcoin".to_string(),
+            initlock: 14400,
+            interval: 144,
+            timelock_mode: TimelockMode::Absolute,
+            required_confirmation: 3,
+            deposit_pow: true,
+            pow_difficulty: 4,
+            max_tx_fee: 1000000,
+            batch_lifetime: 3600,
+            punishment_duration: 360,
+            denominations: vec![100000, 500000, 1000000],
+            swap_timeout: 60,
+            utxo_timeout: 10,
+            max_swap_size: 5,
+            swap_wallet_version: "0.6.0".to_string(),
+        }
     }
 }
 
@@ -382,6 +516,33 @@ pub struct StateChainDataAPI {
     pub locktime: u32,  // the curent owner nlocktime
     /// The coin confirmation status
     pub confirmed: bool,
+    /// Whether the statechain is finished and has been archived
+    #[serde(default)]
+    pub archived: bool,
+    /// Swap fees (satoshis) owed by deduction, added to the state entity fee of the withdraw tx
+    #[serde(default)]
+    pub swap_fee_owed: u64,
+    /// For each state in the chain, the SMT root it was first included in. None if the state
+    /// is not yet included in a root, or was included before roots recorded their leaves.
+    #[serde(default)]
+    pub state_roots: Vec<Option<StateRootAPI>>,
+    /// Spend of the funding output by a tx other than the current backup tx, if detected
+    #[serde(default)]
+    pub spend_incident: Option<SpendIncident>,
+    /// Address the current backup tx pays to. None once withdrawn
+    #[serde(default)]
+    pub backup_addr: Option<String>,
+}
+
+/// SMT root a state of a statechain was first included in
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct StateRootAPI {
+    /// ID of the root
+    pub root_id: i64,
+    /// Root hash (hex)
+    pub root: String,
+    /// Whether the root is confirmed in a mainstay attestation
+    pub confirmed: bool,
 }
 
 impl StateChainDataAPI {
@@ -391,7 +552,11 @@ impl StateChainDataAPI {
             amount: 1000000,
             chain: vec![State::example()],
             locktime: 712903,
-            confirmed: true
+            confirmed: true,
+            archived: false,
+            swap_fee_owed: 0,
+            state_roots: vec![None],
+            spend_incident: None,
         }
     }
 
@@ -404,6 +569,25 @@ impl StateChainDataAPI {
     }
 }
 
+/// Spend of the funding output of a live statecoin by a tx other than its current backup tx,
+/// e.g. an old backup tx broadcast by a previous owner, detected in the mempool or a block
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SpendIncident {
+    /// The statechain of the statecoin
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// The spent funding outpoint
+    #[schemars(with = "OutPointDef")]
+    pub funding: OutPoint,
+    /// Block height when the spend was detected
+    pub height: u64,
+    /// Time the spend was detected
+    pub detected_at: NaiveDateTime,
+    /// Backup tx broadcast by the State Entity in response, if any
+    #[serde(default)]
+    pub response_txid: Option<String>,
+}
+
 // /info/statecoin return struct
 /// Statechain tip data
 /// This struct is returned containing the statecoin (statechain tip) of the specified statechain ID
@@ -421,6 +605,12 @@ pub struct StateCoinDataAPI {
     pub locktime: u32,  // the curent owner nlocktime
     /// The coin confirmation status
     pub confirmed: bool,
+    /// Whether the statechain is finished and has been archived
+    #[serde(default)]
+    pub archived: bool,
+    /// Spend of the funding output by a tx other than the current backup tx, if detected
+    #[serde(default)]
+    pub spend_incident: Option<SpendIncident>,
 }
 
 impl StateCoinDataAPI {
@@ -430,7 +620,9 @@ impl StateCoinDataAPI {
             amount: 1000000,
             statecoin: State::example(),
             locktime: 712903,
-            confirmed: true
+            confirmed: true,
+            archived: false,
+            spend_incident: None,
         }
     }
 }
@@ -460,6 +652,136 @@ impl RecoveryRequest {
     }
 }
 
+/// /info/usersessions/challenge return struct: single-use challenge to sign with each proof key
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct UserSessionsChallenge {
+    pub challenge: String,
+    /// Time after which the challenge is no longer accepted
+    pub expires: NaiveDateTime,
+}
+
+/// Struct containing proof key signatures over a user sessions challenge, with an optional
+/// session state filter and paging
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[schemars(example = "Self::example")]
+pub struct UserSessionsRequest {
+    pub challenge: String,
+    /// Signature generated with StateChainSig::new_user_sessions_sig for each proof key
+    pub sigs: Vec<StateChainSig>,
+    /// Only return sessions in this state, e.g. "Owner"
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub offset: u64,
+    /// Maximum number of sessions returned. Capped by the server.
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+impl UserSessionsRequest {
+    pub fn example() -> Self{
+        Self{
+            challenge: "5b8e2c33a5f04b8c9d6f0e1a2b3c4d5e".to_string(),
+            sigs: vec![StateChainSig::example()],
+            state: Some("Owner".to_string()),
+            offset: 0,
+            limit: Some(100),
+        }
+    }
+}
+
+/// User session held by the server for a proof key
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct UserSessionAPI {
+    pub proof_key: String,
+    /// Shared key id (user id) of the session
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Option<Uuid>,
+    /// Protocol state of the session. None for sessions created before states were recorded.
+    pub state: Option<String>,
+}
+
+/// /info/usersessions return struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct UserSessionsAPI {
+    pub sessions: Vec<UserSessionAPI>,
+    /// Number of sessions matching the request across all pages
+    pub total: u64,
+    pub offset: u64,
+}
+
+/// /info/ownership-challenge return struct: single-use nonce for the owner of a statecoin to
+/// sign with its current proof key
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct OwnershipChallenge {
+    pub nonce: String,
+    /// Time after which the nonce is no longer accepted
+    pub expires: NaiveDateTime,
+}
+
+/// Struct containing the current owner's signature over an ownership challenge nonce
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[schemars(example = "Self::example")]
+pub struct OwnershipProofMsg {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub nonce: String,
+    /// Signature generated with StateChainSig::new_ownership_sig
+    pub statechain_sig: StateChainSig,
+}
+
+impl OwnershipProofMsg {
+    pub fn example() -> Self{
+        Self{
+            statechain_id: Uuid::new_v4(),
+            nonce: "5b8e2c33a5f04b8c9d6f0e1a2b3c4d5e".to_string(),
+            statechain_sig: StateChainSig::example(),
+        }
+    }
+}
+
+/// Confirmation that the current owner of a statechain signed a nonce at a point in time
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct OwnershipConfirmation {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub nonce: String,
+    /// Current owner proof key
+    pub proof_key: String,
+    pub amount: u64,
+    pub confirmed_at: NaiveDateTime,
+    /// State entity export signing public key
+    pub server_pubkey: String,
+}
+
+/// Ownership confirmation signed by the state entity over its canonical JSON encoding
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SignedOwnershipConfirmation {
+    pub confirmation: OwnershipConfirmation,
+    /// DER encoded signature
+    pub sig: String,
+}
+
+/// Struct containing a signed CPFP child tx spending a statecoin backup tx output
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[schemars(example = "Self::example")]
+pub struct BackupCPFPMsg {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub tx_cpfp_hex: String,
+}
+
+impl BackupCPFPMsg {
+    pub fn example() -> Self{
+        Self{
+            statechain_id: Uuid::new_v4(),
+            tx_cpfp_hex: "02000000000101...".to_string(),
+        }
+    }
+}
+
 /// Struct with recovery information for specified proof key
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[schemars(example = "Self::example")]
@@ -471,7 +793,10 @@ pub struct RecoveryDataMsg {
     pub amount: u64,
     pub tx_hex: String,
     pub proof_key: String,
-    pub shared_key_data: String
+    pub shared_key_data: String,
+    /// Pay-to-contract commitment of the funding output key if the owner key share is tweaked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p2c: Option<PayToContract>,
 }
 
 impl RecoveryDataMsg {
@@ -482,7 +807,8 @@ impl RecoveryDataMsg {
             amount: 0,
             tx_hex: "02000000000101ca878085da49c33eb9816c10e4056424e5e062689ea547ea91bb3aa840a3c5fb0000000000ffffffff02307500000000000016001412cc36c9533290c02f0c78f992df6e6ddfe50c8c0064f50500000000160014658fd2dc72e58168f3656fb632d63be54f80fbe4024730440220457cf52873ae5854859a7d48b39cb57eba880ea4011806e5058da7619f4c0fab02206303326f06bbebf7170b679ba787c856dec4b6462109bf66e1cb8dc087be7ebf012102a95498bdde2c8c4078f01840b3bc8f4ae5bb1a90b880a621f50ce221bce3ddbe00000000".to_string(),
             proof_key: "03b2483ab9bea9843bd9bfb941e8c86c1308e77aa95fccd0e63c2874c0e3ead3f5".to_string(),
-            shared_key_data: "".to_string()
+            shared_key_data: "".to_string(),
+            p2c: None,
         }
     }
 }
@@ -492,6 +818,365 @@ impl RecoveryDataMsg {
 pub struct SmtProofMsgAPI {
     pub root: Root,
     pub funding_txid: String,
+    /// Proof encoding accepted by the client
+    #[serde(default)]
+    pub proof_format: ProofFormat,
+}
+
+// /info/proofs post struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct SmtProofsMsgAPI {
+    /// Funding txids of the statecoins
+    pub funding_txids: Vec<String>,
+    /// Proof encoding accepted by the client
+    #[serde(default)]
+    pub proof_format: ProofFormat,
+}
+
+/// SMT inclusion proof of a funding txid
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SmtProofAPI {
+    pub funding_txid: String,
+    /// None if the txid is not in the tree
+    pub proof: Option<EncodedProof>,
+}
+
+/// /info/proofs return struct: inclusion proofs against the current root
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct SmtProofsAPI {
+    pub root: Root,
+    pub proofs: Vec<SmtProofAPI>,
+}
+
+impl SmtProofsAPI {
+    /// Decoded proofs by funding txid
+    pub fn decode_proofs(&self) -> crate::Result<Vec<(String, Option<Proof>)>> {
+        self.proofs
+            .iter()
+            .map(|p| {
+                let proof = match &p.proof {
+                    Some(proof) => Some(proof.decode()?),
+                    None => None,
+                };
+                Ok((p.funding_txid.clone(), proof))
+            })
+            .collect()
+    }
+}
+
+/// /info/updates return struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct StateChainUpdatesAPI {
+    /// Statechains modified since the requested checkpoint, oldest first
+    pub statechain_ids: Vec<Uuid>,
+    /// Checkpoint to request the next updates from
+    pub checkpoint: NaiveDateTime,
+    /// True if the update limit was reached and more updates are available from the checkpoint
+    pub more: bool,
+}
+
+/// /info/standing return struct: punishment standing of a statecoin and its owner proof key
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct PunishmentStandingAPI {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// Current owner proof key
+    pub proof_key: String,
+    /// Recent batch transfer and swap failures of the statechain
+    pub statechain_offences: u32,
+    /// Recent batch transfer and swap failures of the proof key, across all its statechains
+    pub proof_key_offences: u32,
+    /// Time until which the statecoin cannot join batch transfers or swaps
+    pub locked_until: Option<NaiveDateTime>,
+    /// Seconds of punishment remaining. 0 if not punished.
+    pub seconds_remaining: i64,
+    /// Duration (seconds) of the next batch transfer failure punishment
+    pub next_batch_punishment: u64,
+    /// Duration (seconds) of the next swap failure punishment
+    pub next_swap_punishment: u64,
+}
+
+// /admin/api-key post struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ApiKeyIssueMsg {
+    /// State entity admin key
+    pub admin_key: String,
+    /// Description of the key holder
+    pub label: String,
+    /// Requests per second
+    pub quota: u32,
+}
+
+// /admin/api-key/revoke post struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ApiKeyRevokeMsg {
+    /// State entity admin key
+    pub admin_key: String,
+    pub key: String,
+}
+
+// /admin/deposit/pause post struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct DepositPauseMsg {
+    /// State entity admin key
+    pub admin_key: String,
+    /// Whether new deposits are paused
+    pub paused: bool,
+}
+
+/// Deposit circuit breaker state and remaining deposit capacity
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct DepositCapacityAPI {
+    /// Whether new deposits are paused
+    pub paused: bool,
+    /// Total value (satoshis) of unspent statecoins
+    pub total_value: u64,
+    /// Remaining deposit capacity (satoshis). None if there is no cap.
+    pub capacity: Option<u64>,
+}
+
+/// Kind of fee collected by the state entity
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FeeType {
+    /// Deposit fee, paid by an output of the funding tx
+    Deposit,
+    /// Withdrawal fee, paid by an output of the withdraw tx
+    Withdraw,
+    /// Swap fee, paid by deduction from the withdraw tx or by a fee deposit
+    Swap,
+}
+
+// /admin/keygen/audit post struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct KeygenAuditMsg {
+    /// State entity admin key
+    pub admin_key: String,
+}
+
+/// Shared keys generated without verification of all keygen zero-knowledge proofs
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct KeygenAuditAPI {
+    /// Whether keygen proofs are currently enforced
+    pub enforce_zk_proofs: bool,
+    /// Completed keygen sessions with a failed or missing proof verification record
+    pub unverified: Vec<Uuid>,
+}
+
+// /admin/revenue post struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct RevenueReportMsg {
+    /// State entity admin key
+    pub admin_key: String,
+    /// Start of the report (inclusive)
+    pub from: NaiveDateTime,
+    /// End of the report (exclusive)
+    pub to: NaiveDateTime,
+}
+
+/// Fees of a type collected over a period
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct RevenueItemAPI {
+    pub fee_type: FeeType,
+    /// Number of statecoins charged
+    pub count: u64,
+    /// Total fee (satoshis)
+    pub amount: u64,
+}
+
+/// Fees of a type collected on a day (UTC)
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct RevenueDayAPI {
+    pub date: NaiveDate,
+    pub fee_type: FeeType,
+    pub count: u64,
+    pub amount: u64,
+}
+
+/// Fees expected from a tx against the value it pays to the fee addresses
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct FeeReconciliationAPI {
+    pub txid: String,
+    /// Total fee (satoshis) recorded for the tx
+    pub expected: u64,
+    /// Value (satoshis) paid to the fee addresses by the tx. None if the tx was not found.
+    pub received: Option<u64>,
+    /// Whether the tx pays at least the expected fee
+    pub reconciled: bool,
+}
+
+/// /admin/revenue return struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct RevenueReportAPI {
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    /// Total fees (satoshis) recorded over the period
+    pub total: u64,
+    /// Total value (satoshis) received by the fee addresses from the txs of the period
+    pub received: u64,
+    pub by_fee_type: Vec<RevenueItemAPI>,
+    pub by_day: Vec<RevenueDayAPI>,
+    /// Reconciliation of each tx, unreconciled txs first
+    pub reconciliation: Vec<FeeReconciliationAPI>,
+}
+
+/// Newly issued info API key. The key is returned once and is not stored by the server.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ApiKeyAPI {
+    pub key: String,
+    pub label: String,
+    pub quota: u32,
+}
+
+/// Statecoin ownership signature over a proof of reserves nonce
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ReserveCoinSig {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub statechain_sig: StateChainSig,
+}
+
+// /info/reserves post struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[schemars(example = "Self::example")]
+pub struct ReserveProofMsg {
+    /// Verifier-supplied nonce
+    pub nonce: String,
+    pub statecoins: Vec<ReserveCoinSig>,
+}
+
+impl ReserveProofMsg {
+    pub fn example() -> Self{
+        Self{
+            nonce: "b0b7c2fa3d2e4fb0a1d6c3e4f5a6b7c8".to_string(),
+            statecoins: vec![ReserveCoinSig {
+                statechain_id: Uuid::new_v4(),
+                statechain_sig: StateChainSig::example(),
+            }],
+        }
+    }
+}
+
+/// Proof of ownership and SMT inclusion for a single statecoin in a proof of reserves
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ReserveProofEntry {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub amount: u64,
+    pub funding_txid: String,
+    /// Current owner proof public key
+    pub proof_key: String,
+    pub statechain_sig: StateChainSig,
+    /// SMT inclusion proof of the statecoin leaf, committing to the funding txid, proof key
+    /// and amount, against the report root
+    pub proof: Option<Proof>,
+}
+
+/// SMT root referenced by a statechain export with the inclusion proof of the statecoin
+/// proof key at that root. Attested roots carry their mainstay commitment info.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct StateChainExportRoot {
+    pub root: Root,
+    pub proof: Option<Proof>,
+}
+
+/// Full public history of a statechain for archival and audit
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct StateChainExport {
+    /// Export format version
+    pub version: u32,
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub amount: u64,
+    pub funding_txid: String,
+    pub chain: Vec<State>,
+    /// Current owner backup tx. None once the statecoin is withdrawn.
+    pub tx_backup_hex: Option<String>,
+    /// Every backup tx of the statechain recorded by the state entity, in chain order. Empty
+    /// in version 1 exports.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backup_txs: Vec<String>,
+    pub roots: Vec<StateChainExportRoot>,
+    pub exported_at: NaiveDateTime,
+    /// State entity export signing public key
+    pub server_pubkey: String,
+}
+
+/// Statechain export signed by the state entity over its canonical message encoding
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct SignedStateChainExport {
+    pub export: StateChainExport,
+    /// DER encoded signature
+    pub sig: String,
+}
+
+/// Record of the deletion of a state entity key share after the statecoin was transferred
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct DeletionReceipt {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// Shared key ID of the deleted key share
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    /// Proof key of the owner the deleted key share was shared with
+    pub proof_key: String,
+    /// sha256 of the deleted key share public key S1
+    pub commitment: String,
+    pub deleted_at: NaiveDateTime,
+    /// State entity export signing public key
+    pub server_pubkey: String,
+}
+
+/// Deletion receipt signed by the state entity over its canonical JSON encoding
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SignedDeletionReceipt {
+    pub receipt: DeletionReceipt,
+    /// DER encoded signature
+    pub sig: String,
+}
+
+/// Record of a completed transfer of a statechain from the sender proof key to the receiver
+/// proof key. Proof of payment for the sender.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct TransferReceipt {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// Proof key of the sender
+    pub prev_proof_key: String,
+    /// Proof key of the receiver
+    pub new_proof_key: String,
+    /// ID of the SMT root including the receiver proof key at finalization
+    pub root_id: i64,
+    pub finalized_at: NaiveDateTime,
+    /// State entity export signing public key
+    pub server_pubkey: String,
+}
+
+/// Transfer receipt signed by the state entity over its canonical JSON encoding
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SignedTransferReceipt {
+    pub receipt: TransferReceipt,
+    /// DER encoded signature
+    pub sig: String,
+}
+
+/// Aggregated proof of reserves report against a mainstay-attested SMT root
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ReserveProofReport {
+    pub nonce: String,
+    pub root: Root,
+    pub entries: Vec<ReserveProofEntry>,
+    /// Sum of the statecoin amounts
+    pub total: u64,
+    /// State entity export signing public key
+    pub server_pubkey: String,
+}
+
+/// Proof of reserves report signed by the state entity over its canonical encoding
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct SignedReserveProofReport {
+    pub report: ReserveProofReport,
+    /// DER encoded signature
+    pub sig: String,
 }
 
 #[derive(JsonSchema)]
@@ -602,6 +1287,19 @@ pub struct KeyGenMsg2 {
     pub shared_key_id: Uuid,
     #[schemars(with = "DLogProofDef")]
     pub dlog_proof: DLogProof,
+    /// Pay-to-contract commitment (hex sha256 hash) to tweak the shared key with. Deposit only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p2c_commitment: Option<String>,
+}
+
+/// Pay-to-contract commitment of a statecoin funding output key. The funding output pays to
+/// t*P, where P is the shared public key generated by keygen and t = sha256(P || commitment).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct PayToContract {
+    /// Shared public key before the tweak
+    pub shared_pubkey: String,
+    /// Commitment (hex sha256 hash)
+    pub commitment: String,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
@@ -661,6 +1359,83 @@ pub struct SignSecondMsgRequest {
     pub party_two_sign_message: party2::SignMessage,
 }
 
+// Encrypted ECDSA channel structs (see noise module)
+
+/// State Entity identity public key, the static key of encrypted ECDSA channels
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct IdentityPubKey {
+    pub key: String,
+}
+
+/// Build manifest of the server binary, embedded at compile time, so that users can check that
+/// a deployed server was built from audited source
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct BuildManifest {
+    /// Server crate version
+    pub version: String,
+    pub git_commit: String,
+    /// The source tree had uncommitted changes
+    pub git_dirty: bool,
+    /// sha256 of Cargo.lock (hex)
+    pub cargo_lock_hash: String,
+    /// Enabled cargo features, sorted
+    pub features: Vec<String>,
+    pub rustc_version: String,
+    /// Cargo profile, e.g. "release"
+    pub profile: String,
+    /// Built in release build mode, which requires a clean git tree and the release profile
+    pub release: bool,
+}
+
+/// Build manifest signed by the state entity identity key over its canonical encoding
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SignedBuildManifest {
+    pub manifest: BuildManifest,
+    /// State entity identity public key. None if no identity key is configured.
+    pub server_pubkey: Option<String>,
+    /// DER encoded signature. None if no identity key is configured.
+    pub sig: Option<String>,
+}
+
+/// Client -> SE: first Noise KK handshake message of the channel of a user session, keyed by
+/// the proof key of the session
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct NoiseHandshakeMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    /// Hex encoded handshake message
+    pub message: String,
+}
+
+/// Client <-> SE: message encrypted with the channel of a user session
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct NoiseMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    /// Hex encoded ciphertext
+    pub ciphertext: String,
+}
+
+/// ECDSA protocol message sent over an encrypted channel
+#[derive(Serialize, Deserialize, Debug)]
+pub enum EcdsaRequest {
+    KeyGenFirst(KeyGenMsg1),
+    KeyGenSecond(KeyGenMsg2),
+    SignFirst(SignMsg1),
+    SignSecond(SignMsg2),
+}
+
+/// Reply to an EcdsaRequest sent over an encrypted channel
+#[derive(Serialize, Deserialize, Debug)]
+pub enum EcdsaReply {
+    KeyGenFirst(KeyGenReply1),
+    KeyGenSecond(KeyGenReply2),
+    SignFirst(SignReply1),
+    SignSecond(Vec<Vec<u8>>),
+    /// The request failed. Errors are encrypted so that the channel stays in step.
+    Error(String),
+}
+
 // Deposit algorithm structs
 
 /// Client -> SE
@@ -668,6 +1443,13 @@ pub struct SignSecondMsgRequest {
 pub struct DepositMsg1 {
     pub auth: String,
     pub proof_key: String,
+    /// Proof key of the payer when depositing on behalf of the proof_key owner
+    #[serde(default)]
+    pub payer_proof_key: Option<String>,
+    /// Acknowledgement of the fee quote the deposit is made under: the quote hash of the
+    /// signed /info/fee response
+    #[serde(default)]
+    pub fee_quote_hash: Option<String>,
 }
 
 /// Client -> SE
@@ -677,6 +1459,30 @@ pub struct DepositMsg2 {
     pub shared_key_id: Uuid,
 }
 
+/// Client -> SE
+/// Authorise the refund of a deposit that was never confirmed. refund_sig is a "REFUND"
+/// StateChainSig over the refund address by the deposit proof key (the payer proof key for
+/// deposits made on behalf of another proof key).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct DepositRefundMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub refund_sig: StateChainSig,
+}
+
+/// Payer -> SE -> Owner
+/// Shared key of a statecoin deposited on behalf of the owner of proof_key.
+/// The payload is the serialized wallet shared key, encrypted to proof_key.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct DepositHandoffMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub proof_key: String,
+    pub payload: String,
+}
+
 #[derive(JsonSchema)]
 #[schemars(remote = "Address")]
 pub struct AddressDef(String);
@@ -693,6 +1499,11 @@ pub struct SCEAddress {
     pub tx_backup_addr: Option<Address>,
     #[schemars(with = "PubKeyDef")]
     pub proof_key: PublicKey,
+    /// One-time key the receiver deletes after use. If set, transfer message 3 is encrypted to
+    /// it instead of the proof key.
+    #[serde(default)]
+    #[schemars(with = "PubKeyDef")]
+    pub enc_key: Option<PublicKey>,
 }
 impl Eq for SCEAddress {}
 
@@ -704,6 +1515,10 @@ pub struct TransferMsg1 {
     pub statechain_sig: StateChainSig,
     #[schemars(with = "UuidDef")]
     pub batch_id: Option<Uuid>,
+    /// Second factor signature over statechain_sig. Required if the statecoin has a second
+    /// factor key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_sig: Option<StateChainSig>,
 }
 
 #[derive(JsonSchema)]
@@ -797,6 +1612,317 @@ pub struct TransferMsg5 {
     pub s2_pub: GE,
 }
 
+/// Receiver -> State Entity: decline a transfer before completing transfer_receiver.
+/// statechain_sig is signed with the receiver proof key over the sender's transfer statechain sig.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct TransferRejectMsg {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub statechain_sig: StateChainSig,
+}
+
+/// /transfer/reject return struct: the last transfer of a statechain declined by its receiver
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct TransferRejectAPI {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// Sender's transfer statechain sig. Its data is the receiver proof key.
+    pub transfer_sig: StateChainSig,
+    /// Receiver's rejection statechain sig
+    pub reject_sig: StateChainSig,
+    pub rejected_at: NaiveDateTime,
+}
+
+// Escrowed sale structs
+
+/// Seller -> State Entity: hold the transfer of a statecoin until the preimage of payment_hash
+/// is revealed
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[schemars(example = "Self::example")]
+pub struct SaleInitMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    /// Hex encoded sha256 hash of the sale secret, e.g. the payment hash of a lightning invoice
+    pub payment_hash: String,
+    /// Seconds the sale is open for. Capped by the server.
+    pub lifetime: u64,
+}
+
+impl SaleInitMsg {
+    pub fn example() -> Self{
+        Self{
+            shared_key_id: Uuid::new_v4(),
+            payment_hash: "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925".to_string(),
+            lifetime: 3600,
+        }
+    }
+}
+
+/// Reveal the preimage of the payment hash of a sale, finalizing the held transfer
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[schemars(example = "Self::example")]
+pub struct SaleRevealMsg {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// Hex encoded preimage
+    pub preimage: String,
+}
+
+impl SaleRevealMsg {
+    pub fn example() -> Self{
+        Self{
+            statechain_id: Uuid::new_v4(),
+            preimage: "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+        }
+    }
+}
+
+/// Stage of an escrowed sale
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+pub enum SaleStatus {
+    /// Awaiting the transfer to the buyer
+    Open,
+    /// Transfer to the buyer held until the preimage is revealed
+    Held,
+    /// Preimage revealed and transfer finalized
+    Finalized,
+    /// Lifetime passed before the transfer was finalized. The held transfer is dropped.
+    Expired,
+}
+
+/// /transfer/sale return struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SaleAPI {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    pub payment_hash: String,
+    /// Time after which the preimage is no longer accepted
+    pub expires: NaiveDateTime,
+    pub status: SaleStatus,
+    /// Proof key the held transfer is to
+    pub buyer_proof_key: Option<String>,
+    /// Revealed preimage
+    pub preimage: Option<String>,
+}
+
+// Second factor structs
+
+/// Owner -> State Entity: register, replace or remove the second factor key of a statecoin.
+/// Once registered, transfer, withdrawal and migration of the statecoin also require a
+/// signature of the second factor key.
+/// statechain_sig is the owner's signature of the change. A change signed by the current
+/// second factor key takes effect immediately, otherwise after the recovery delay.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct SecondFactorMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    /// Public key of the second factor. None to remove it.
+    pub auth_key: Option<String>,
+    pub statechain_sig: StateChainSig,
+    /// Current second factor signature over statechain_sig
+    #[serde(default)]
+    pub auth_sig: Option<StateChainSig>,
+}
+
+/// /second-factor return struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SecondFactorAPI {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// Public key of the current second factor, if any
+    pub auth_key: Option<String>,
+    /// Change waiting for the recovery delay: the new key, or None for removal
+    pub pending_auth_key: Option<String>,
+    /// Time the pending change takes effect. None if no change is pending.
+    pub pending_effective: Option<NaiveDateTime>,
+}
+
+// Vault structs
+
+/// Owner -> State Entity: set the vault delay of a statecoin, or disable vault mode with a delay
+/// of 0. Transfers, withdrawals and migrations of a statecoin in vault mode can only be completed
+/// once the delay has passed since they were started.
+/// statechain_sig is the owner's signature of the delay. A longer delay takes effect
+/// immediately, a shorter one once the current delay has passed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct VaultMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    /// Delay in seconds
+    pub delay: u64,
+    pub statechain_sig: StateChainSig,
+}
+
+/// Owner -> State Entity: cancel the transfer, withdrawal or migration in progress of a statecoin
+/// in vault mode.
+/// statechain_sig is the owner's cancel sig, signing the statechain sig of the operation
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct VaultCancelMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub statechain_sig: StateChainSig,
+}
+
+/// /vault return struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct VaultAPI {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// Delay in seconds. 0 if vault mode is disabled.
+    pub delay: u64,
+    /// Shorter delay waiting for the current delay to pass
+    pub pending_delay: Option<u64>,
+    /// Time the pending delay takes effect. None if no change is pending.
+    pub pending_effective: Option<NaiveDateTime>,
+    /// Statechain sig of the transfer, withdrawal or migration in progress, if any
+    pub pending_sig: Option<StateChainSig>,
+    /// Time from which the operation in progress can be completed
+    pub completable_from: Option<NaiveDateTime>,
+}
+
+// Proof key rotation structs
+
+/// Owner -> State Entity: rotate the proof key of a statecoin to a new proof key of the same
+/// owner, without a transfer.
+/// statechain_sig is the owner's "ROTATE" sig of the new proof key, bound to the statechain tip
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct RotateMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub statechain_sig: StateChainSig,
+    /// Second factor signature over statechain_sig. Required if the statecoin has a second
+    /// factor key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_sig: Option<StateChainSig>,
+}
+
+// Inheritance structs
+
+/// Owner -> State Entity: designate the receiver of the pending transfer of a statecoin as its
+/// beneficiary. The beneficiary can only complete the transfer once the owner has not checked
+/// in for inactivity_period seconds.
+/// statechain_sig is the owner's designation sig, signing the pending transfer statechain sig
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct InheritanceMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub statechain_sig: StateChainSig,
+    /// Seconds without a check in after which the beneficiary can complete the transfer
+    pub inactivity_period: u64,
+}
+
+/// Owner -> State Entity: check in or revoke the beneficiary designation of a statecoin
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct InheritanceOwnerMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+}
+
+/// /transfer/inheritance return struct
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct InheritanceAPI {
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    /// Owner's pending transfer statechain sig. Its data is the beneficiary proof key.
+    pub transfer_sig: StateChainSig,
+    /// Owner's designation statechain sig
+    pub designation_sig: StateChainSig,
+    pub inactivity_period: u64,
+    pub last_checkin: NaiveDateTime,
+    /// Time from which the beneficiary can complete the transfer, unless the owner checks in
+    pub claimable_from: NaiveDateTime,
+}
+
+// Migrate algorithm structs
+
+/// Owner -> Old State Entity
+/// statechain_sig has purpose MIGRATE and signs for the migration public key of the new
+/// state entity
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct MigrateMsg1 {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub statechain_sig: StateChainSig,
+    /// Second factor signature over statechain_sig. Required if the statecoin has a second
+    /// factor key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_sig: Option<StateChainSig>,
+}
+
+/// Owner -> Old State Entity
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct MigrateMsg2 {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    #[schemars(with = "FEDef")]
+    pub t2: FESer, // t2 = o1*x1*o2_inv, encrypted to the old state entity key share S1
+    #[schemars(with = "GEDef")]
+    pub o2_pub: GE,
+}
+
+/// New state entity migration public key
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MigratePubKey {
+    pub key: String,
+}
+
+/// Statecoin key share and history handed from the old to the new state entity
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct MigrationPackage {
+    /// Signed statechain export up to the migration
+    pub export: SignedStateChainExport,
+    /// Owner signature (purpose MIGRATE) for the new state entity migration public key
+    pub statechain_sig: StateChainSig,
+    /// New state entity key share, encrypted to the migration public key
+    #[schemars(with = "FEDef")]
+    pub s2: FESer,
+    #[schemars(with = "GEDef")]
+    pub o2_pub: GE,
+}
+
+/// Migration package signed by the old state entity export key over its canonical JSON
+/// encoding
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct SignedMigrationPackage {
+    pub package: MigrationPackage,
+    /// DER encoded signature
+    pub sig: String,
+}
+
+// Key share refresh structs
+
+/// Owner -> State Entity: initiate a refresh of the key shares of a statecoin
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct RefreshMsg1 {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+}
+
+/// Owner -> State Entity
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct RefreshMsg2 {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    #[schemars(with = "UuidDef")]
+    pub statechain_id: Uuid,
+    #[schemars(with = "FEDef")]
+    pub t2: FESer, // t2 = o1*x1*o2_inv, encrypted to the state entity key share S1
+    #[schemars(with = "GEDef")]
+    pub o2_pub: GE,
+}
+
+/// State Entity -> Owner: new state entity public key share and share epoch. The owner then
+/// runs keygen with its new share o2 as in transfer.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct RefreshReply {
+    #[schemars(with = "GEDef")]
+    pub s2_pub: GE,
+    /// Number of refreshes of the key shares of the shared key
+    pub share_epoch: u32,
+}
+
 /// State Entity -> Receiver
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct OwnerID {
@@ -867,6 +1993,10 @@ pub struct WithdrawMsg1 {
     #[schemars(with = "UuidDef")]
     pub shared_key_ids: Vec::<Uuid>,
     pub statechain_sigs: Vec::<StateChainSig>,
+    /// Second factor signatures over statechain_sigs, in the same order. Required for
+    /// statecoins with a second factor key.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auth_sigs: Vec::<Option<StateChainSig>>,
 }
 
 /// Owner -> State Entity
@@ -877,6 +2007,123 @@ pub struct WithdrawMsg2 {
     pub address: String,
 }
 
+/// Owner -> State Entity
+/// Request that the State Entity accelerate a withdraw tx stuck in the mempool by spending its
+/// fee output in a child tx (CPFP) so that the package pays fee_rate (sat/vbyte)
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct WithdrawAccelerateMsg {
+    #[schemars(with = "UuidDef")]
+    pub shared_key_id: Uuid,
+    pub fee_rate: u64,
+}
+
+/// State Entity -> Owner
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct WithdrawAccelerateAPI {
+    /// Txid of the withdraw tx
+    pub txid: String,
+    /// Txid of the CPFP child tx spending the withdraw tx fee output
+    pub cpfp_txid: String,
+    /// Fee rate (sat/vbyte) paid by the withdraw tx and child together
+    pub fee_rate: u64,
+}
+
+// Watchtower structs
+
+/// Watchtower -> Owner: public key that registered backup txs are encrypted to
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct WatchtowerKeyAPI {
+    pub key: String,
+}
+
+/// Owner -> Watchtower: create or replace the registration of a backup tx to be broadcast by
+/// the watchtower from broadcast_height. The first registration of an id sets the key that
+/// authorises its updates and revocation.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct WatchtowerRegisterMsg {
+    #[schemars(with = "UuidDef")]
+    pub id: Uuid,
+    /// Public key (hex) authorising updates and revocation of the registration
+    pub auth_key: String,
+    /// Registration version. Each update or revocation must increment it.
+    pub version: u64,
+    /// Block height from which the backup tx is broadcast
+    pub broadcast_height: u32,
+    /// Backup tx (hex) encrypted to the watchtower key (hex)
+    pub backup_tx: String,
+    /// Signature (DER hex) by auth_key
+    pub sig: String,
+}
+
+/// Owner -> Watchtower: revoke a registration, e.g. once the statecoin has been transferred
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct WatchtowerRevokeMsg {
+    #[schemars(with = "UuidDef")]
+    pub id: Uuid,
+    /// Registration version. Must be greater than the registered version.
+    pub version: u64,
+    /// Signature (DER hex) by the registration auth key
+    pub sig: String,
+}
+
+fn verify_msg_sig(
+    message: &bitcoin::secp256k1::Message,
+    pubkey: &str,
+    sig: &str,
+) -> crate::Result<()> {
+    let pk = PublicKey::from_str(pubkey)
+        .map_err(|e| SharedLibError::SignatureError(format!("invalid public key: {}", e)))?;
+    let sig = bitcoin::secp256k1::Signature::from_str(sig)
+        .map_err(|e| SharedLibError::SignatureError(format!("invalid signature: {}", e)))?;
+    bitcoin::secp256k1::Secp256k1::verification_only()
+        .verify(message, &sig, &pk)
+        .map_err(|e| SharedLibError::SignatureError(format!("invalid signature: {}", e)))
+}
+
+impl WatchtowerRegisterMsg {
+    /// Message signed by the auth key (canonical encoding, see statechain_verify::message)
+    pub fn to_message(&self) -> crate::Result<bitcoin::secp256k1::Message> {
+        Ok(statechain_verify::message::watchtower_register_encode(
+            self.id.as_bytes(),
+            self.version,
+            self.broadcast_height,
+            &self.backup_tx,
+        )
+        .to_message()?)
+    }
+
+    /// Sign with the private key of auth_key
+    pub fn sign(&mut self, auth_key_priv: &bitcoin::secp256k1::SecretKey) -> crate::Result<()> {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        self.sig = secp.sign(&self.to_message()?, auth_key_priv).to_string();
+        Ok(())
+    }
+
+    /// Verify the signature by auth_key
+    pub fn verify(&self) -> crate::Result<()> {
+        verify_msg_sig(&self.to_message()?, &self.auth_key, &self.sig)
+    }
+}
+
+impl WatchtowerRevokeMsg {
+    /// Message signed by the auth key (canonical encoding, see statechain_verify::message)
+    pub fn to_message(&self) -> crate::Result<bitcoin::secp256k1::Message> {
+        Ok(statechain_verify::message::watchtower_revoke_encode(self.id.as_bytes(), self.version)
+            .to_message()?)
+    }
+
+    pub fn sign(&mut self, auth_key_priv: &bitcoin::secp256k1::SecretKey) -> crate::Result<()> {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        self.sig = secp.sign(&self.to_message()?, auth_key_priv).to_string();
+        Ok(())
+    }
+
+    /// Verify the signature by the registration auth key
+    pub fn verify(&self, auth_key: &str) -> crate::Result<()> {
+        verify_msg_sig(&self.to_message()?, auth_key, &self.sig)
+    }
+}
+
 impl Default for TransferMsg5 {
     fn default() -> TransferMsg5 {
         TransferMsg5 {
@@ -958,6 +2205,26 @@ impl WalletDecryptable for &mut TransferMsg2 {
     }
 }
 
+impl Encryptable for DepositHandoffMsg {}
+impl SelfEncryptable for DepositHandoffMsg {
+    fn decrypt(&mut self, privkey: &crate::ecies::PrivateKey) -> crate::ecies::Result<()> {
+        self.payload.decrypt(privkey)
+    }
+
+    fn encrypt_with_pubkey(
+        &mut self,
+        pubkey: &crate::ecies::PublicKey,
+    ) -> crate::ecies::Result<()> {
+        self.payload.encrypt_with_pubkey(pubkey)
+    }
+}
+
+impl WalletDecryptable for DepositHandoffMsg {
+    fn get_public_key(&self) -> crate::ecies::Result<Option<crate::ecies::PublicKey>> {
+        Ok(Some(crate::ecies::PublicKey::from_str(&self.proof_key)?))
+    }
+}
+
 impl Encryptable for TransferMsg3 {}
 impl SelfEncryptable for TransferMsg3 {
     fn decrypt(&mut self, privkey: &crate::ecies::PrivateKey) -> crate::ecies::Result<()> {
@@ -974,9 +2241,15 @@ impl SelfEncryptable for TransferMsg3 {
 
 impl WalletDecryptable for TransferMsg3 {
     fn get_public_key(&self) -> crate::ecies::Result<Option<crate::ecies::PublicKey>> {
-        Ok(Some(crate::ecies::PublicKey::from_str(
-            &self.statechain_sig.data,
-        )?))
+        match self.rec_se_addr.enc_key {
+            Some(key) => Ok(Some(crate::ecies::PublicKey {
+                compressed: true,
+                key,
+            })),
+            None => Ok(Some(crate::ecies::PublicKey::from_str(
+                &self.statechain_sig.data,
+            )?)),
+        }
     }
 }
 
@@ -1011,6 +2284,34 @@ impl SelfEncryptable for TransferMsg4 {
     }
 }
 
+impl Encryptable for MigrateMsg2 {}
+impl SelfEncryptable for MigrateMsg2 {
+    fn decrypt(&mut self, privkey: &crate::ecies::PrivateKey) -> crate::ecies::Result<()> {
+        self.t2.decrypt(privkey)
+    }
+
+    fn encrypt_with_pubkey(
+        &mut self,
+        pubkey: &crate::ecies::PublicKey,
+    ) -> crate::ecies::Result<()> {
+        self.t2.encrypt_with_pubkey(pubkey)
+    }
+}
+
+impl Encryptable for RefreshMsg2 {}
+impl SelfEncryptable for RefreshMsg2 {
+    fn decrypt(&mut self, privkey: &crate::ecies::PrivateKey) -> crate::ecies::Result<()> {
+        self.t2.decrypt(privkey)
+    }
+
+    fn encrypt_with_pubkey(
+        &mut self,
+        pubkey: &crate::ecies::PublicKey,
+    ) -> crate::ecies::Result<()> {
+        self.t2.encrypt_with_pubkey(pubkey)
+    }
+}
+
 impl SelfEncryptable for &mut TransferMsg4 {
     fn decrypt(&mut self, privkey: &crate::ecies::PrivateKey) -> crate::ecies::Result<()> {
         (**self).decrypt(privkey)
@@ -1066,6 +2367,7 @@ mod tests {
                     Address::from_str("1DTFRJ2XFb4AGP1Tfk54iZK1q2pPfK4n3h").unwrap(),
                 ),
                 proof_key: PublicKey::from_secret_key(&secp, &SecretKey::new(&mut rng)),
+                enc_key: None,
             },
         };
 
@@ -1083,6 +2385,13 @@ mod tests {
         assert_ne!(msg_ref, &msg_clone);
         msg_ref.decrypt(&priv_k).unwrap();
         assert_eq!(msg_ref, &msg_clone);
+
+        // Encrypted to the one-time receive key of the address if it has one
+        msg.rec_se_addr.enc_key = Some(pub_k.key);
+        assert_eq!(msg.get_public_key().unwrap(), Some(pub_k));
+        msg.encrypt().unwrap();
+        msg.decrypt(&priv_k).unwrap();
+        assert_eq!(msg.t1, msg_clone.t1);
     }
 
     #[test]
@@ -1109,6 +2418,37 @@ mod tests {
         assert_eq!(msg_ref, &msg_clone);
     }
 
+    #[test]
+    fn test_watchtower_msg_sig() {
+        let secp = Secp256k1::new();
+        let auth_priv = SecretKey::from_slice(&[1; 32]).unwrap();
+        let mut msg = WatchtowerRegisterMsg {
+            id: Uuid::new_v4(),
+            auth_key: PublicKey::from_secret_key(&secp, &auth_priv).to_string(),
+            version: 1,
+            broadcast_height: 712903,
+            backup_tx: String::from("0badc0de"),
+            sig: String::default(),
+        };
+        assert!(msg.verify().is_err());
+        msg.sign(&auth_priv).unwrap();
+        msg.verify().unwrap();
+        // Signed for the version
+        let mut replay = msg.clone();
+        replay.version = 2;
+        assert!(replay.verify().is_err());
+
+        let mut revoke = WatchtowerRevokeMsg {
+            id: msg.id,
+            version: 2,
+            sig: String::default(),
+        };
+        revoke.sign(&auth_priv).unwrap();
+        revoke.verify(&msg.auth_key).unwrap();
+        let other_key = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap());
+        assert!(revoke.verify(&other_key.to_string()).is_err());
+    }
+
     #[test]
     fn test_coinvalueinfo() {
         let mut cvi = CoinValueInfo::new();
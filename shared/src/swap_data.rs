@@ -6,10 +6,9 @@ use crate::{
     structs::*,
     util::keygen::Message,
 };
-use bitcoin::{
-    hashes::{sha256d, Hash},
-    secp256k1::{PublicKey, Secp256k1, SecretKey, Signature},
-};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey, Signature};
+use chrono::NaiveDateTime;
+use statechain_verify::message;
 use uuid::Uuid;
 use rocket_okapi::JsonSchema;
 use schemars;
@@ -35,25 +34,50 @@ pub struct SwapToken {
     pub time_out: u64,
     #[schemars(with = "UuidDef")]
     pub statechain_ids: Vec<Uuid>,
+    /// Swap fee (satoshis) paid by each participant. Signed for if non-zero.
+    #[serde(default)]
+    pub fee: u64,
 }
 impl SwapToken {
-    /// Create message to be signed
+    /// Create message to be signed (canonical encoding, see statechain_verify::message)
     pub fn to_message(&self) -> Result<Message> {
-        let mut str = self.amount.to_string();
-        str.push_str(&self.time_out.to_string());
-        let mut id_str_vec: Vec::<String> = vec![];
-        for id in &self.statechain_ids{
-            id_str_vec.push(id.to_string());
+        let statechain_ids: Vec<[u8; 16]> =
+            self.statechain_ids.iter().map(|id| *id.as_bytes()).collect();
+        let encoding = match self.fee {
+            0 => message::swap_token_encode(
+                self.id.as_bytes(),
+                self.amount,
+                self.time_out,
+                &statechain_ids,
+            ),
+            fee => message::swap_token_fee_encode(
+                self.id.as_bytes(),
+                self.amount,
+                self.time_out,
+                fee,
+                &statechain_ids,
+            ),
+        };
+        let message = encoding.to_message()?;
+        info!("swap token message: {}", message);
+        Ok(message)
+    }
+
+    /// Legacy (version 0) message. Accepted by verify_sig for signatures made by older wallets.
+    /// There is none for tokens with a swap fee.
+    pub fn to_message_legacy(&self) -> Result<Message> {
+        if self.fee != 0 {
+            return Err(SharedLibError::SwapError(String::from(
+                "swap token with a swap fee has no legacy message",
+            )));
         }
-        let mut ids_str = format!("{:?}",id_str_vec);
-        ids_str.retain(|c| !c.is_whitespace());
-        str.push_str(&ids_str);
-
-        info!("swap token message str: {}", str);
-        println!("swap token message str: {}", str);
-        let hash = sha256d::Hash::hash(&str.as_bytes());
-        info!("swap token message hash: {}", hash);
-        Ok(Message::from_slice(&hash)?)
+        let statechain_ids: Vec<String> =
+            self.statechain_ids.iter().map(|id| id.to_string()).collect();
+        Ok(message::swap_token_message_legacy(
+            self.amount,
+            self.time_out,
+            &statechain_ids,
+        )?)
     }
 
     /// Generate Signature for change of state chain ownership
@@ -68,7 +92,12 @@ impl SwapToken {
     pub fn verify_sig(&self, pk: &PublicKey, sig: Signature) -> Result<()> {
         let secp = Secp256k1::new();
 
-        match secp.verify(&self.to_message()?, &sig, &pk) {
+        let result = match secp.verify(&self.to_message()?, &sig, &pk) {
+            Ok(()) => Ok(()),
+            Err(e) if self.fee != 0 => Err(e),
+            Err(_) => secp.verify(&self.to_message_legacy()?, &sig, &pk),
+        };
+        match result {
             Ok(_) => {
                 info!("verify_sig: ok");
                 Ok(())
@@ -92,6 +121,62 @@ pub struct SwapInfo {
     pub bst_sender_data: BSTSenderData,
 }
 
+/// How participants pay the swap fee
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapFeePayment {
+    /// The fee is owed by each swapped statecoin and deducted from its withdrawal
+    Deduction,
+    /// Each participant pays the fee to the fee address before the swap and references the
+    /// fee deposit tx in the first swap message
+    Deposit,
+}
+
+impl Default for SwapFeePayment {
+    fn default() -> Self {
+        SwapFeePayment::Deduction
+    }
+}
+
+/// Swap fee terms and conductor activity
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SwapStatsAPI {
+    /// Swap fee (satoshis) per participant. 0 if swaps are free.
+    pub fee: u64,
+    /// How the swap fee is paid
+    pub fee_payment: SwapFeePayment,
+    /// Address fee deposits are paid to
+    pub fee_address: String,
+    /// Number of statecoins registered for a swap
+    pub registered: u64,
+    /// Number of swaps in progress
+    pub swaps_ongoing: u64,
+}
+
+/// Facts of a completed swap relevant to its anonymity set, without the statechains or
+/// addresses of its participants, so that a participant can show that mixing took place
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SwapReport {
+    #[schemars(with = "UuidDef")]
+    pub swap_id: Uuid,
+    /// Number of statecoins swapped
+    pub participants: u64,
+    /// Statecoin amount (satoshis) of every participant
+    pub denomination: u64,
+    /// Time the swap batch transfer was finalized
+    pub completed_at: NaiveDateTime,
+}
+
+/// Swap report signed by the state entity identity key over its canonical JSON encoding
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SignedSwapReport {
+    pub report: SwapReport,
+    /// State entity identity public key. None if no identity key is configured.
+    pub server_pubkey: Option<String>,
+    /// DER encoded signature. None if no identity key is configured.
+    pub sig: Option<String>,
+}
+
 /// Owner -> Conductor
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct RegisterUtxo {
@@ -119,6 +204,9 @@ pub struct SwapMsg1 {
     pub address: SCEAddress,
     #[schemars(with = "FEDef")]
     pub bst_e_prime: curv::FE,
+    /// Txid of the fee deposit paying the swap fee, if swap fees are paid by deposit
+    #[serde(default)]
+    pub fee_txid: Option<String>,
 }
 
 // Message to request a blinded spend token
@@ -174,5 +262,9 @@ mod tests {
             //&sk);
 
         assert!(st.verify_sig(pk, st_sig).is_ok());
+
+        // Signatures over the legacy message are still accepted
+        let legacy_sig = secp.sign(&st.to_message_legacy().unwrap(), &sk);
+        assert!(st.verify_sig(pk, legacy_sig).is_ok());
     }
-}
\ No newline at end of file
+}
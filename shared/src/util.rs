@@ -4,7 +4,7 @@
 
 use super::Result;
 use crate::error::SharedLibError;
-use crate::structs::{PrepareSignTxMsg, StateChainDataAPI, StateEntityFeeInfoAPI};
+use crate::structs::{PrepareSignTxMsg, StateChainDataAPI, StateEntityFeeInfoAPI, TimelockMode};
 #[cfg(test)]
 use crate::Verifiable;
 
@@ -23,6 +23,14 @@ pub const RBF: u32 = 0xffffffff - 2;
 pub const DUSTLIMIT: u64 = 100;
 /// Temporary - fees should be calculated dynamically
 pub const FEE: u64 = 141;
+/// Virtual size of a signed 1-input 1-output P2WPKH CPFP child transaction
+pub const CPFP_CHILD_VSIZE: u64 = 110;
+/// BIP68 nSequence flag disabling the relative timelock
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// BIP68 nSequence flag selecting a time (rather than block) based relative timelock
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// BIP68 nSequence relative timelock value mask
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
 
 pub fn reverse_hex_str(hex_str: String) -> Result<String> {
     if hex_str.len() % 2 != 0 {
@@ -43,6 +51,21 @@ pub fn reverse_hex_str(hex_str: String) -> Result<String> {
     Ok(result)
 }
 
+/// Check that an address is encoded for the given network
+pub fn validate_address_network(address: &Address, network: &Network) -> Result<()> {
+    let reencoded = Address {
+        payload: address.payload.clone(),
+        network: *network,
+    };
+    if reencoded.to_string() != address.to_string() {
+        return Err(SharedLibError::FormatError(format!(
+            "Address {} is not valid for network {}",
+            address, network
+        )));
+    }
+    Ok(())
+}
+
 /// consensus serialize tx into hex string
 pub fn transaction_serialise(tx: &Transaction) -> String {
     hex::encode(consensus::serialize(tx))
@@ -168,7 +191,9 @@ pub fn tx_funding_build(
     Ok(tx_0)
 }
 
-/// Build backup tx spending P output of funding tx to given backup address
+/// Build backup tx spending P output of funding tx to given backup address.
+/// In absolute mode 'locktime' is the nLockTime block height, in relative mode it is the
+/// number of blocks after funding tx confirmation set in the input nSequence.
 pub fn tx_backup_build(
     funding_txid: &Txid,
     b_address: &Address,
@@ -176,6 +201,7 @@ pub fn tx_backup_build(
     locktime: &u32,
     fee: &u64,
     fee_addr: &String,
+    timelock_mode: &TimelockMode,
 ) -> Result<Transaction> {
     if *fee + FEE >= *amount {
         return Err(SharedLibError::FormatError(String::from(
@@ -193,7 +219,7 @@ pub fn tx_backup_build(
         script_sig: bitcoin::Script::default(),
     };
 
-    let tx_b = Transaction {
+    let mut tx_b = Transaction {
         input: vec![txin.clone()],
         output: vec![
             TxOut {
@@ -205,12 +231,115 @@ pub fn tx_backup_build(
                 value: *fee,
             },
         ],
-        lock_time: *locktime,
+        lock_time: 0,
         version: 2,
     };
+    match timelock_mode {
+        TimelockMode::Absolute => tx_b.lock_time = *locktime,
+        TimelockMode::Relative => tx_b.input[0].sequence = relative_sequence(locktime)?,
+    }
     Ok(tx_b)
 }
 
+/// nSequence encoding a block based relative timelock
+fn relative_sequence(blocks: &u32) -> Result<u32> {
+    if *blocks == 0 || *blocks > SEQUENCE_LOCKTIME_MASK {
+        return Err(SharedLibError::FormatError(format!(
+            "Invalid relative timelock: {} blocks",
+            blocks
+        )));
+    }
+    Ok(*blocks)
+}
+
+/// Get the timelock mode of a backup tx. Relative if nLockTime is unset and the input
+/// nSequence encodes a block based relative timelock, otherwise absolute.
+pub fn backup_tx_timelock_mode(tx: &Transaction) -> TimelockMode {
+    match tx.input.get(0) {
+        Some(txin)
+            if tx.lock_time == 0
+                && txin.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                && txin.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG == 0 =>
+        {
+            TimelockMode::Relative
+        }
+        _ => TimelockMode::Absolute,
+    }
+}
+
+/// Get the timelock of a backup tx: the nLockTime block height in absolute mode, or the
+/// number of blocks after funding confirmation in relative mode.
+pub fn backup_tx_timelock(tx: &Transaction) -> u32 {
+    match backup_tx_timelock_mode(tx) {
+        TimelockMode::Absolute => tx.lock_time,
+        TimelockMode::Relative => tx.input[0].sequence & SEQUENCE_LOCKTIME_MASK,
+    }
+}
+
+/// Get the address the first output of a backup tx pays to
+pub fn backup_tx_address(tx: &Transaction, network: &Network) -> Result<Address> {
+    let output = tx.output.get(0).ok_or(SharedLibError::FormatError(String::from(
+        "Backup tx has no outputs",
+    )))?;
+    Address::from_script(&output.script_pubkey, *network).ok_or(SharedLibError::FormatError(
+        String::from("Backup tx output is not a standard address script"),
+    ))
+}
+
+/// Set the timelock of a backup tx, keeping its existing timelock mode
+pub fn set_backup_tx_timelock(tx: &mut Transaction, locktime: &u32) -> Result<()> {
+    match backup_tx_timelock_mode(tx) {
+        TimelockMode::Absolute => tx.lock_time = *locktime,
+        TimelockMode::Relative => tx.input[0].sequence = relative_sequence(locktime)?,
+    }
+    Ok(())
+}
+
+/// Build CPFP child tx spending output 'vout' of a parent (backup) tx to address 'rec_address'.
+/// The child fee is chosen so that the parent and child together pay 'fee_rate' (sat/vbyte),
+/// taking into account the fee 'parent_fee' already paid by the parent.
+pub fn tx_cpfp_build(
+    tx_parent: &Transaction,
+    vout: &u32,
+    parent_fee: &u64,
+    fee_rate: &u64,
+    rec_address: &Address,
+) -> Result<Transaction> {
+    let parent_output = tx_parent.output.get(*vout as usize).ok_or(SharedLibError::FormatError(
+        format!("Parent tx has no output {}", vout),
+    ))?;
+
+    let parent_vsize = (tx_parent.get_weight() as u64 + 3) / 4;
+    let package_fee = fee_rate * (parent_vsize + CPFP_CHILD_VSIZE);
+    let child_fee = package_fee.saturating_sub(*parent_fee).max(*fee_rate * CPFP_CHILD_VSIZE);
+
+    if child_fee + DUSTLIMIT >= parent_output.value {
+        return Err(SharedLibError::FormatError(String::from(
+            "Not enough value to cover fee.",
+        )));
+    }
+
+    let txin = TxIn {
+        previous_output: OutPoint {
+            txid: tx_parent.txid(),
+            vout: *vout,
+        },
+        sequence: RBF,
+        witness: Vec::new(),
+        script_sig: bitcoin::Script::default(),
+    };
+
+    Ok(Transaction {
+        input: vec![txin],
+        output: vec![TxOut {
+            script_pubkey: rec_address.script_pubkey(),
+            value: parent_output.value - child_fee,
+        }],
+        lock_time: 0,
+        version: 2,
+    })
+}
+
 /// Build withdraw tx spending funding tx to:
 ///     - amount-fee to receive address, and
 ///     - amount 'fee' to State Entity fee address 'fee_addr'
@@ -224,35 +353,42 @@ pub fn tx_backup_build(
 ) -> Result<Transaction> {
 */
 
-pub fn tx_withdraw_build(
+/// State entity fee of a withdraw tx spending n_inputs statecoins of total value amount: the
+/// proportional fee of rate basis points on the total value, rounded down once for the whole tx,
+/// plus input_fee satoshis per statecoin. Swap fees owed are added by the caller.
+pub fn withdraw_fee(amount: u64, n_inputs: usize, rate: u64, input_fee: u64) -> u64 {
+    (amount * rate) / 10000 + n_inputs as u64 * input_fee
+}
+
+/// Check the number of statecoins of a withdraw tx against the state entity limit (0 for no
+/// limit)
+pub fn withdraw_inputs_verify(n_inputs: usize, max_inputs: u64) -> Result<()> {
+    if n_inputs == 0 {
+        return Err(SharedLibError::FormatError(String::from(
+            "Withdraw requires at least one statecoin.",
+        )));
+    }
+    if max_inputs > 0 && n_inputs as u64 > max_inputs {
+        return Err(SharedLibError::FormatError(format!(
+            "Withdraw of {} statecoins exceeds the maximum of {} per withdraw tx.",
+            n_inputs, max_inputs
+        )));
+    }
+    Ok(())
+}
+
+/// Value of the withdraw output and the state entity fee of a withdraw transaction
+pub fn tx_withdraw_amounts(
     sc_infos: &Vec::<StateChainDataAPI>,
-    rec_se_address: &Address,
     se_fee_info: &StateEntityFeeInfoAPI,
     tx_fee: &u64
-) -> Result<Transaction> {
-    let mut txins = Vec::<TxIn>::new();
-
-    let amount = {
-        let mut total = 0;
-        for info in sc_infos {
-            total += info.amount;
-
-            let txin = TxIn {
-                previous_output: OutPoint {
-                    txid: info.utxo.txid,
-                    vout: 0,
-                },
-                sequence: 0xFFFFFFFF,
-                witness: Vec::new(),
-                script_sig: bitcoin::Script::default(),
-            };
-            
-            txins.push(txin);
-        };
-        total + se_fee_info.deposit as u64
-    };
+) -> Result<(u64, u64)> {
+    let amount = sc_infos.iter().map(|info| info.amount).sum::<u64>() + se_fee_info.deposit as u64;
+
+    withdraw_inputs_verify(sc_infos.len(), se_fee_info.max_withdraw_inputs)?;
 
-    let fee = (amount*se_fee_info.withdraw) / 10000 as u64;
+    let fee = withdraw_fee(amount, sc_infos.len(), se_fee_info.withdraw, se_fee_info.withdraw_input)
+        + sc_infos.iter().map(|info| info.swap_fee_owed).sum::<u64>();
 
     if fee + tx_fee >= amount {
         return Err(SharedLibError::FormatError(String::from(
@@ -260,6 +396,38 @@ pub fn tx_withdraw_build(
         )));
     }
 
+    let value = amount - fee - tx_fee;
+    if value < se_fee_info.dust_limit {
+        return Err(SharedLibError::FormatError(format!(
+            "Withdraw output value {} is below the dust limit {}.",
+            value, se_fee_info.dust_limit
+        )));
+    }
+
+    Ok((value, fee))
+}
+
+pub fn tx_withdraw_build(
+    sc_infos: &Vec::<StateChainDataAPI>,
+    rec_se_address: &Address,
+    se_fee_info: &StateEntityFeeInfoAPI,
+    tx_fee: &u64
+) -> Result<Transaction> {
+    let txins = sc_infos
+        .iter()
+        .map(|info| TxIn {
+            previous_output: OutPoint {
+                txid: info.utxo.txid,
+                vout: 0,
+            },
+            sequence: 0xFFFFFFFF,
+            witness: Vec::new(),
+            script_sig: bitcoin::Script::default(),
+        })
+        .collect::<Vec<TxIn>>();
+
+    let (value, fee) = tx_withdraw_amounts(sc_infos, se_fee_info, tx_fee)?;
+
 
     let tx_0 = Transaction {
         version: 2,
@@ -268,7 +436,7 @@ pub fn tx_withdraw_build(
         output: vec![
             TxOut {
                 script_pubkey: rec_se_address.script_pubkey(),
-                value: amount - fee - tx_fee,
+                value,
             },
             TxOut {
                 script_pubkey: Address::from_str(&se_fee_info.address)?.script_pubkey(),
@@ -279,6 +447,36 @@ pub fn tx_withdraw_build(
     Ok(tx_0)
 }
 
+/// Build refund tx for a deposit that was never confirmed, spending 'funding_outpoint' of
+/// value 'amount' back to 'refund_addr' less 'tx_fee'. No state entity fee is paid.
+pub fn tx_refund_build(
+    funding_outpoint: &OutPoint,
+    refund_addr: &Address,
+    amount: &u64,
+    tx_fee: &u64,
+) -> Result<Transaction> {
+    if *tx_fee >= *amount {
+        return Err(SharedLibError::FormatError(String::from(
+            "Not enough value to cover fee.",
+        )));
+    }
+
+    Ok(Transaction {
+        version: 2,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: *funding_outpoint,
+            sequence: 0xFFFFFFFF,
+            witness: Vec::new(),
+            script_sig: bitcoin::Script::default(),
+        }],
+        output: vec![TxOut {
+            script_pubkey: refund_addr.script_pubkey(),
+            value: amount - tx_fee,
+        }],
+    })
+}
+
 pub mod keygen {
     pub use bitcoin::secp256k1::{key::SecretKey, Message, PublicKey, Secp256k1};
     pub use bitcoin::util;
@@ -367,6 +565,151 @@ pub mod tests {
         // println!("{}", serde_json::to_string_pretty(&tx_1).unwrap());
     }
 
+    #[test]
+    fn address_network() {
+        let (_, pub_key) = generate_keypair();
+        let addr = Address::p2wpkh(&pub_key, NETWORK).unwrap();
+        assert!(validate_address_network(&addr, &Network::Regtest).is_ok());
+        assert!(validate_address_network(&addr, &Network::Bitcoin).is_err());
+        assert!(validate_address_network(&addr, &Network::Testnet).is_err());
+
+        // Base58 addresses are shared by testnet and regtest
+        let addr = Address::p2pkh(&pub_key, Network::Testnet);
+        assert!(validate_address_network(&addr, &Network::Regtest).is_ok());
+        assert!(validate_address_network(&addr, &Network::Bitcoin).is_err());
+    }
+
+    #[test]
+    fn cpfp() {
+        let (_, pub_key) = generate_keypair();
+        let addr = Address::p2wpkh(&pub_key, NETWORK).unwrap();
+        let fee_addr = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+        let amount = 100000;
+        let tx_backup =
+            tx_backup_build(&Txid::default(), &addr, &amount, &1000, &300, &fee_addr, &TimelockMode::Absolute)
+                .unwrap();
+
+        let fee_rate = 20;
+        let tx_cpfp = tx_cpfp_build(&tx_backup, &0, &FEE, &fee_rate, &addr).unwrap();
+        assert_eq!(tx_cpfp.input.len(), 1);
+        assert_eq!(tx_cpfp.input[0].previous_output.txid, tx_backup.txid());
+        assert_eq!(tx_cpfp.input[0].previous_output.vout, 0);
+
+        // package pays at least the requested fee rate
+        let parent_vsize = (tx_backup.get_weight() as u64 + 3) / 4;
+        let child_fee = tx_backup.output[0].value - tx_cpfp.output[0].value;
+        assert!(FEE + child_fee >= fee_rate * (parent_vsize + CPFP_CHILD_VSIZE));
+
+        // fee rate too high for output value
+        assert!(tx_cpfp_build(&tx_backup, &0, &FEE, &10000, &addr).is_err());
+        // no such output
+        assert!(tx_cpfp_build(&tx_backup, &2, &FEE, &fee_rate, &addr).is_err());
+    }
+
+    #[test]
+    fn withdraw_amounts() {
+        let mut sc_info = StateChainDataAPI::example();
+        sc_info.amount = 10000;
+        let mut fee_info = StateEntityFeeInfoAPI::example();
+        fee_info.deposit = 0;
+        fee_info.withdraw = 300;
+        fee_info.dust_limit = 546;
+
+        let (value, fee) = tx_withdraw_amounts(&vec![sc_info.clone()], &fee_info, &FEE).unwrap();
+        assert_eq!(fee, 300);
+        assert_eq!(value, 10000 - 300 - FEE);
+
+        // Withdraw output below the dust limit
+        let tx_fee = 10000 - 300 - 545;
+        assert!(tx_withdraw_amounts(&vec![sc_info.clone()], &fee_info, &tx_fee).is_err());
+        fee_info.dust_limit = 545;
+        assert!(tx_withdraw_amounts(&vec![sc_info.clone()], &fee_info, &tx_fee).is_ok());
+
+        // Swap fees owed by deduction are added to the state entity fee
+        sc_info.swap_fee_owed = 200;
+        let (value, fee) = tx_withdraw_amounts(&vec![sc_info.clone(), sc_info.clone()], &fee_info, &FEE).unwrap();
+        assert_eq!(fee, 600 + 400);
+        assert_eq!(value, 20000 - 1000 - FEE);
+
+        // Per statecoin fee
+        fee_info.withdraw_input = 50;
+        let (_, fee) = tx_withdraw_amounts(&vec![sc_info.clone(), sc_info.clone()], &fee_info, &FEE).unwrap();
+        assert_eq!(fee, 600 + 400 + 100);
+
+        // Statecoin limit
+        fee_info.max_withdraw_inputs = 1;
+        assert!(tx_withdraw_amounts(&vec![sc_info.clone(), sc_info], &fee_info, &FEE).is_err());
+        assert!(tx_withdraw_amounts(&vec![], &fee_info, &FEE).is_err());
+    }
+
+    #[test]
+    fn withdraw_fee_rounding() {
+        // The proportional fee is rounded down on the total value, not per statecoin
+        assert_eq!(withdraw_fee(2 * 10050, 2, 300, 0), 603);
+        assert_eq!(withdraw_fee(2 * 10050, 2, 300, 10), 623);
+        assert_eq!(withdraw_fee(10000, 1, 0, 0), 0);
+
+        assert!(withdraw_inputs_verify(100, 100).is_ok());
+        assert!(withdraw_inputs_verify(101, 100).is_err());
+        assert!(withdraw_inputs_verify(1000, 0).is_ok());
+        assert!(withdraw_inputs_verify(0, 0).is_err());
+    }
+
+    #[test]
+    fn refund() {
+        let (_, pub_key) = generate_keypair();
+        let addr = Address::p2wpkh(&pub_key, NETWORK).unwrap();
+        let funding_outpoint = OutPoint {
+            txid: Txid::default(),
+            vout: 0,
+        };
+        let tx_refund = tx_refund_build(&funding_outpoint, &addr, &10000, &FEE).unwrap();
+        assert_eq!(tx_refund.input[0].previous_output, funding_outpoint);
+        assert_eq!(tx_refund.output.len(), 1);
+        assert_eq!(tx_refund.output[0].value, 10000 - FEE);
+        assert_eq!(tx_refund.lock_time, 0);
+        assert!(tx_refund_build(&funding_outpoint, &addr, &FEE, &FEE).is_err());
+    }
+
+    #[test]
+    fn backup_timelock() {
+        let (_, pub_key) = generate_keypair();
+        let addr = Address::p2wpkh(&pub_key, NETWORK).unwrap();
+        let fee_addr = String::from("bcrt1qjjwk2rk7nuxt6c79tsxthf5rpnky0sdhjr493x");
+        let amount = 100000;
+
+        let mut tx_abs =
+            tx_backup_build(&Txid::default(), &addr, &amount, &1000, &300, &fee_addr, &TimelockMode::Absolute)
+                .unwrap();
+        assert_eq!(backup_tx_timelock_mode(&tx_abs), TimelockMode::Absolute);
+        assert_eq!(tx_abs.lock_time, 1000);
+        assert_eq!(backup_tx_timelock(&tx_abs), 1000);
+        set_backup_tx_timelock(&mut tx_abs, &900).unwrap();
+        assert_eq!(tx_abs.lock_time, 900);
+
+        let mut tx_rel =
+            tx_backup_build(&Txid::default(), &addr, &amount, &1000, &300, &fee_addr, &TimelockMode::Relative)
+                .unwrap();
+        assert_eq!(backup_tx_timelock_mode(&tx_rel), TimelockMode::Relative);
+        assert_eq!(tx_rel.lock_time, 0);
+        assert_eq!(tx_rel.input[0].sequence, 1000);
+        assert_eq!(backup_tx_timelock(&tx_rel), 1000);
+        set_backup_tx_timelock(&mut tx_rel, &900).unwrap();
+        assert_eq!(tx_rel.input[0].sequence, 900);
+        assert_eq!(backup_tx_timelock_mode(&tx_rel), TimelockMode::Relative);
+
+        // backup address is recovered from the owner output
+        assert_eq!(backup_tx_address(&tx_rel, &NETWORK).unwrap(), addr);
+        let mut tx_nonstandard = tx_rel.clone();
+        tx_nonstandard.output[0].script_pubkey = Script::new();
+        assert!(backup_tx_address(&tx_nonstandard, &NETWORK).is_err());
+
+        // relative timelock out of range
+        assert!(set_backup_tx_timelock(&mut tx_rel, &0).is_err());
+        assert!(tx_backup_build(&Txid::default(), &addr, &amount, &0x10000, &300, &fee_addr, &TimelockMode::Relative)
+            .is_err());
+    }
+
     #[test]
     fn sign() {
         let secp = Secp256k1::new();
@@ -0,0 +1,55 @@
+//! Attestation
+//!
+//! Verification of SMT root commitments in Mainstay attestations. The proof has the same
+//! serialized form as the mainstay merkle proof returned with the root's commitment info.
+
+use monotree::Hash;
+
+use bitcoin::hashes::{sha256d, Hash as HashesHash, HashEngine};
+
+/// Merkle path from a commitment to the attested slot merkle root
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AttestationProof {
+    pub merkle_root: Hash,
+    pub commitment: Hash,
+    pub ops: Vec<Hash>,
+    pub append: Vec<bool>,
+    pub position: u64,
+}
+
+/// Compute the attested merkle root from a commitment and its merkle path
+pub fn hash_merkle_root(commitment: &Hash, ops: &[Hash], position: u64) -> Hash {
+    let mut h = *commitment;
+    //Reverse byte order for the MT hash
+    h.reverse();
+    for (i, leaf) in ops.iter().enumerate() {
+        let mut leaf = *leaf;
+        //Reverse byte order for the MT hash
+        leaf.reverse();
+        let mut engine = sha256d::Hash::engine();
+        if (position >> i) & 1 != 0 {
+            engine.input(&leaf);
+            engine.input(&h);
+        } else {
+            engine.input(&h);
+            engine.input(&leaf);
+        }
+        h = sha256d::Hash::from_engine(engine).into_inner();
+    }
+    //Revert to the original byte order
+    h.reverse();
+    h
+}
+
+impl AttestationProof {
+    /// Check that the merkle path connects the commitment to the merkle root
+    pub fn verify(&self) -> bool {
+        self.ops.len() == self.append.len()
+            && hash_merkle_root(&self.commitment, &self.ops, self.position) == self.merkle_root
+    }
+
+    /// Check that the proof commits to the given SMT root
+    pub fn verify_root(&self, root: &Hash) -> bool {
+        &self.commitment == root && self.verify()
+    }
+}
@@ -0,0 +1,92 @@
+//! Backup
+//!
+//! Checks on statecoin backup transactions.
+
+use super::Result;
+use crate::error::VerifyError;
+
+use bitcoin::{
+    consensus,
+    secp256k1::{Message, PublicKey, Secp256k1, Signature},
+    util::bip143::SigHashCache,
+    Address, Network, SigHashType, Transaction,
+};
+use std::str::FromStr;
+
+/// Decode consensus serialized hex transaction
+pub fn backup_tx_decode(tx_hex: &str) -> Result<Transaction> {
+    let buf = hex::decode(tx_hex)
+        .map_err(|_| VerifyError::FormatError(String::from("Transaction hex failed to decode")))?;
+    consensus::deserialize::<Transaction>(&buf)
+        .map_err(|_| VerifyError::FormatError(String::from("Transaction hex failed to deserialise")))
+}
+
+/// Check backup tx spends the funding outpoint with a non-zero locktime (absolute or relative) and, if signed,
+/// that the signature is valid for the shared public key.
+pub fn verify_backup_tx(
+    tx: &Transaction,
+    funding_txid: &str,
+    funding_vout: u32,
+    amount: u64,
+    shared_pubkey: &str,
+    network: &str,
+) -> Result<()> {
+    if tx.input.len() != 1 {
+        return Err(VerifyError::BackupTxError(format!(
+            "expected 1 input, found {}",
+            tx.input.len()
+        )));
+    }
+    let input = &tx.input[0];
+    if input.previous_output.txid.to_string() != funding_txid
+        || input.previous_output.vout != funding_vout
+    {
+        return Err(VerifyError::BackupTxError(String::from(
+            "input does not spend funding outpoint",
+        )));
+    }
+    // Either an absolute nLockTime or a block based BIP68 relative timelock
+    let relative_lock = input.sequence & (1 << 31) == 0 && input.sequence & (1 << 22) == 0;
+    if tx.lock_time == 0 && !relative_lock {
+        return Err(VerifyError::BackupTxError(String::from("locktime not set")));
+    }
+
+    // Unsigned backup tx: nothing more to check
+    if input.witness.is_empty() {
+        return Ok(());
+    }
+    if input.witness.len() != 2 {
+        return Err(VerifyError::BackupTxError(String::from("unexpected witness length")));
+    }
+
+    let pk = PublicKey::from_str(shared_pubkey)
+        .map_err(|e| VerifyError::FormatError(format!("invalid shared public key: {}", e)))?;
+    if input.witness[1] != pk.serialize().to_vec() {
+        return Err(VerifyError::BackupTxError(String::from(
+            "witness public key does not match shared public key",
+        )));
+    }
+    let network = network
+        .parse::<Network>()
+        .map_err(|e| VerifyError::FormatError(e.to_string()))?;
+    let script_code = Address::p2pkh(
+        &bitcoin::util::key::PublicKey {
+            compressed: true,
+            key: pk,
+        },
+        network,
+    )
+    .script_pubkey();
+    let sighash = SigHashCache::new(tx).signature_hash(0, &script_code, amount, SigHashType::All);
+    let message = Message::from_slice(&sighash[..])?;
+
+    let sig_der = &input.witness[0];
+    if sig_der.is_empty() {
+        return Err(VerifyError::BackupTxError(String::from("empty signature")));
+    }
+    // Strip sighash type byte
+    let sig = Signature::from_der(&sig_der[..sig_der.len() - 1])?;
+    Secp256k1::verification_only()
+        .verify(&message, &sig, &pk)
+        .map_err(|e| VerifyError::BackupTxError(format!("invalid signature: {}", e)))
+}
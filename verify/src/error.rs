@@ -0,0 +1,52 @@
+//! # Error
+//!
+//! Custom Error types for statechain verification
+
+use bitcoin::secp256k1::Error as SecpError;
+
+use std::error;
+use std::fmt;
+
+/// Verification specific errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    /// Data could not be decoded
+    FormatError(String),
+    /// Signature over statechain state invalid
+    SignatureError(String),
+    /// Statechain structure invalid
+    StateChainError(String),
+    /// SMT inclusion proof invalid
+    ProofError(String),
+    /// Root attestation invalid
+    AttestationError(String),
+    /// Backup transaction invalid
+    BackupTxError(String),
+}
+
+impl From<SecpError> for VerifyError {
+    fn from(e: SecpError) -> VerifyError {
+        VerifyError::SignatureError(e.to_string())
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerifyError::FormatError(ref e) => write!(f, "Format Error: {}", e),
+            VerifyError::SignatureError(ref e) => write!(f, "Signature Error: {}", e),
+            VerifyError::StateChainError(ref e) => write!(f, "StateChain Error: {}", e),
+            VerifyError::ProofError(ref e) => write!(f, "Proof Error: {}", e),
+            VerifyError::AttestationError(ref e) => write!(f, "Attestation Error: {}", e),
+            VerifyError::BackupTxError(ref e) => write!(f, "Backup Tx Error: {}", e),
+        }
+    }
+}
+
+impl error::Error for VerifyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,489 @@
+//! Statechain Verify
+//!
+//! Minimal, network-free verification of statecoin histories for third parties such as
+//! explorers, auditors and other wallet implementations. All data is supplied by the caller
+//! in a StatecoinBundle and checked with verify_statecoin():
+//!     - each state in the statechain is signed over by the previous owner proof key, and
+//!       signatures bound to a statechain are bound to the same statechain and their position
+//!     - backup tx commitments recorded in the states match their signatures and have
+//!       decreasing locktimes
+//!     - the current owner proof key is included in the SMT root
+//!     - the SMT root is committed to in a Mainstay attestation
+//!     - the backup transaction spends the funding output and is signed by the shared key, and
+//!       is the backup tx committed to by the current state
+
+extern crate bitcoin;
+extern crate hex;
+extern crate monotree;
+
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+
+pub mod attestation;
+pub mod backup;
+pub mod error;
+pub mod message;
+pub mod sig;
+pub mod smt;
+
+use crate::attestation::AttestationProof;
+use crate::error::VerifyError;
+
+use monotree::{Hash, Proof};
+
+pub type Result<T> = std::result::Result<T, VerifyError>;
+
+/// Statechain ownership transfer signature. Serialized form matches shared_lib::state_chain::StateChainSig
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StateChainSig {
+    pub purpose: String,
+    pub data: String,
+    pub sig: String,
+    /// Statechain id (hyphenated) and chain position of the signing state of bound
+    /// signatures. None for unbound signatures.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statechain_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u64>,
+    /// Txid and locktime of the new owner backup tx committed to by bound signatures. None
+    /// if the signature does not commit to a backup tx.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locktime: Option<u32>,
+}
+
+impl StateChainSig {
+    /// Verify signature by the given proof key
+    pub fn verify(&self, pk: &str) -> Result<()> {
+        match (&self.statechain_id, self.position, &self.backup_txid, self.locktime) {
+            (Some(id), Some(position), Some(txid), Some(locktime)) => {
+                sig::verify_statechain_sig_backup(
+                    pk,
+                    &self.purpose,
+                    &self.data,
+                    &sig::uuid_bytes(id)?,
+                    position,
+                    &sig::txid_bytes(txid)?,
+                    locktime,
+                    &self.sig,
+                )
+            }
+            (Some(id), Some(position), None, None) => sig::verify_statechain_sig_bound(
+                pk,
+                &self.purpose,
+                &self.data,
+                &sig::uuid_bytes(id)?,
+                position,
+                &self.sig,
+            ),
+            (None, None, None, None) => {
+                sig::verify_statechain_sig(pk, &self.purpose, &self.data, &self.sig)
+            }
+            _ => Err(VerifyError::FormatError(String::from(
+                "statechain signature binding requires both statechain id and position, and \
+                 a backup tx commitment requires both txid and locktime",
+            ))),
+        }
+    }
+}
+
+/// Statechain state. Serialized form matches shared_lib::state_chain::State
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct State {
+    pub data: String,
+    pub next_state: Option<StateChainSig>,
+    /// Txid and locktime of the backup tx of this state's owner, if committed to by the
+    /// signature adding the state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locktime: Option<u32>,
+}
+
+/// All public data required to verify a statecoin
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatecoinBundle {
+    /// Chain of owner proof keys and transfer signatures
+    pub statechain: Vec<State>,
+    /// Funding transaction outpoint
+    pub funding_txid: String,
+    pub funding_vout: u32,
+    /// Statecoin value (satoshis)
+    pub amount: u64,
+    /// Shared public key (hex) locking the funding output
+    pub shared_pubkey: String,
+    /// Bitcoin network name
+    pub network: String,
+    /// SMT root and inclusion proof of the current owner proof key
+    pub root: Option<Hash>,
+    pub smt_proof: Option<Proof>,
+    /// Mainstay attestation proof of the SMT root
+    pub attestation: Option<AttestationProof>,
+    /// Current owner backup transaction
+    pub tx_backup_hex: Option<String>,
+}
+
+/// Verify every state in the statechain is signed for by the previous owner
+pub fn verify_statechain(statechain: &Vec<State>) -> Result<()> {
+    if statechain.is_empty() {
+        return Err(VerifyError::StateChainError(String::from(
+            "StateChain cannot be of zero length",
+        )));
+    }
+    let mut statechain_id: Option<[u8; 16]> = None;
+    let mut prev_locktime: Option<u32> = None;
+    for (i, state) in statechain.iter().enumerate() {
+        match (&state.next_state, statechain.get(i + 1)) {
+            (Some(sig), Some(next)) => {
+                if sig.data != next.data {
+                    return Err(VerifyError::StateChainError(format!(
+                        "state {} signs for data that does not match next state",
+                        i
+                    )));
+                }
+                if let Some(position) = sig.position {
+                    if position != i as u64 {
+                        return Err(VerifyError::StateChainError(format!(
+                            "state {} signature is bound to position {}",
+                            i, position
+                        )));
+                    }
+                }
+                if let Some(id) = &sig.statechain_id {
+                    let id = sig::uuid_bytes(id)?;
+                    if *statechain_id.get_or_insert(id) != id {
+                        return Err(VerifyError::StateChainError(format!(
+                            "state {} signature is bound to a different statechain",
+                            i
+                        )));
+                    }
+                }
+                if sig.backup_txid != next.backup_txid || sig.locktime != next.locktime {
+                    return Err(VerifyError::StateChainError(format!(
+                        "state {} backup tx does not match the signature adding it",
+                        i + 1
+                    )));
+                }
+                // each owner's backup tx must become valid before the previous owner's
+                if let Some(locktime) = sig.locktime {
+                    if prev_locktime.map_or(false, |prev| locktime >= prev) {
+                        return Err(VerifyError::StateChainError(format!(
+                            "state {} backup tx locktime {} not less than previous backup tx",
+                            i + 1,
+                            locktime
+                        )));
+                    }
+                    prev_locktime = Some(locktime);
+                }
+                sig.verify(&state.data)?;
+            }
+            (None, None) => (),
+            _ => {
+                return Err(VerifyError::StateChainError(format!(
+                    "state {} signature does not match chain length",
+                    i
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Verify all data in a statecoin bundle. Optional items are checked if present.
+pub fn verify_statecoin(bundle: &StatecoinBundle) -> Result<()> {
+    verify_statechain(&bundle.statechain)?;
+    let tip = bundle.statechain.last().unwrap();
+
+    if let Some(root) = &bundle.root {
+        if !smt::verify_statecoin_smt_proof(
+            &Some(*root),
+            &bundle.funding_txid,
+            &tip.data,
+            bundle.amount,
+            &bundle.smt_proof,
+        ) {
+            return Err(VerifyError::ProofError(String::from(
+                "current owner proof key not included in SMT root",
+            )));
+        }
+    }
+
+    if let Some(attestation) = &bundle.attestation {
+        let root = bundle.root.ok_or(VerifyError::AttestationError(String::from(
+            "attestation supplied without SMT root",
+        )))?;
+        if !attestation.verify_root(&root) {
+            return Err(VerifyError::AttestationError(String::from(
+                "SMT root not committed to in attestation",
+            )));
+        }
+    }
+
+    if let Some(tx_hex) = &bundle.tx_backup_hex {
+        let tx = backup::backup_tx_decode(tx_hex)?;
+        if let Some(txid) = &tip.backup_txid {
+            if &tx.txid().to_string() != txid {
+                return Err(VerifyError::StateChainError(String::from(
+                    "backup tx is not the backup tx committed to by the current state",
+                )));
+            }
+        }
+        backup::verify_backup_tx(
+            &tx,
+            &bundle.funding_txid,
+            bundle.funding_vout,
+            bundle.amount,
+            &bundle.shared_pubkey,
+            &bundle.network,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use monotree::{database::MemoryDB, hasher::Blake3, Monotree};
+    use std::convert::TryInto;
+
+    fn sign(sk: &SecretKey, purpose: &str, data: &str) -> StateChainSig {
+        let secp = Secp256k1::new();
+        let message = sig::statechain_sig_message(purpose, data).unwrap();
+        StateChainSig {
+            purpose: purpose.to_string(),
+            data: data.to_string(),
+            sig: secp.sign(&message, sk).to_string(),
+            statechain_id: None,
+            position: None,
+            backup_txid: None,
+            locktime: None,
+        }
+    }
+
+    fn sign_bound(
+        sk: &SecretKey,
+        purpose: &str,
+        data: &str,
+        statechain_id: &str,
+        position: u64,
+    ) -> StateChainSig {
+        let secp = Secp256k1::new();
+        let id = sig::uuid_bytes(statechain_id).unwrap();
+        let message = sig::statechain_sig_bound_message(purpose, data, &id, position).unwrap();
+        StateChainSig {
+            purpose: purpose.to_string(),
+            data: data.to_string(),
+            sig: secp.sign(&message, sk).to_string(),
+            statechain_id: Some(statechain_id.to_string()),
+            position: Some(position),
+            backup_txid: None,
+            locktime: None,
+        }
+    }
+
+    fn sign_backup(
+        sk: &SecretKey,
+        data: &str,
+        statechain_id: &str,
+        position: u64,
+        backup_txid: &str,
+        locktime: u32,
+    ) -> StateChainSig {
+        let secp = Secp256k1::new();
+        let message = sig::statechain_sig_backup_message(
+            "TRANSFER",
+            data,
+            &sig::uuid_bytes(statechain_id).unwrap(),
+            position,
+            &sig::txid_bytes(backup_txid).unwrap(),
+            locktime,
+        )
+        .unwrap();
+        StateChainSig {
+            purpose: String::from("TRANSFER"),
+            data: data.to_string(),
+            sig: secp.sign(&message, sk).to_string(),
+            statechain_id: Some(statechain_id.to_string()),
+            position: Some(position),
+            backup_txid: Some(backup_txid.to_string()),
+            locktime: Some(locktime),
+        }
+    }
+
+    fn state(data: &str, next_state: Option<StateChainSig>) -> State {
+        State {
+            data: data.to_string(),
+            next_state,
+            backup_txid: None,
+            locktime: None,
+        }
+    }
+
+    fn gen_statechain() -> Vec<State> {
+        let secp = Secp256k1::new();
+        let sk1 = SecretKey::from_slice(&[0xcd; 32]).unwrap();
+        let sk2 = SecretKey::from_slice(&[0xab; 32]).unwrap();
+        let pk1 = PublicKey::from_secret_key(&secp, &sk1).to_string();
+        let pk2 = PublicKey::from_secret_key(&secp, &sk2).to_string();
+        vec![
+            state(&pk1, Some(sign(&sk1, "TRANSFER", &pk2))),
+            state(&pk2, None),
+        ]
+    }
+
+    fn gen_bundle(statechain: Vec<State>) -> StatecoinBundle {
+        StatecoinBundle {
+            statechain,
+            funding_txid: String::default(),
+            funding_vout: 0,
+            amount: 0,
+            shared_pubkey: String::default(),
+            network: String::from("regtest"),
+            root: None,
+            smt_proof: None,
+            attestation: None,
+            tx_backup_hex: None,
+        }
+    }
+
+    #[test]
+    fn test_verify_statechain() {
+        let mut statechain = gen_statechain();
+        assert!(verify_statechain(&statechain).is_ok());
+        assert!(verify_statechain(&vec![]).is_err());
+
+        // Signature for different data
+        statechain[1].data = statechain[0].data.clone();
+        assert!(verify_statechain(&statechain).is_err());
+
+        // Missing signature
+        let mut statechain = gen_statechain();
+        statechain[0].next_state = None;
+        assert!(verify_statechain(&statechain).is_err());
+    }
+
+    #[test]
+    fn test_verify_statechain_bound_sigs() {
+        let secp = Secp256k1::new();
+        let keys: Vec<SecretKey> = (1..4u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<String> = keys
+            .iter()
+            .map(|sk| PublicKey::from_secret_key(&secp, sk).to_string())
+            .collect();
+        let id = "001203c9-93f0-46f9-abda-0678c891b2d3";
+
+        // legacy signature followed by a bound signature
+        let mut statechain = vec![
+            state(&pks[0], Some(sign(&keys[0], "TRANSFER", &pks[1]))),
+            state(&pks[1], Some(sign_bound(&keys[1], "TRANSFER", &pks[2], id, 1))),
+            state(&pks[2], None),
+        ];
+        assert!(verify_statechain(&statechain).is_ok());
+
+        // bound signature replayed at another position
+        statechain[1].next_state = Some(sign_bound(&keys[1], "TRANSFER", &pks[2], id, 0));
+        assert!(verify_statechain(&statechain).is_err());
+
+        // bound signatures for different statechains
+        let other_id = "637203c9-37ab-46f9-abda-0678c891b2d3";
+        statechain[0].next_state = Some(sign_bound(&keys[0], "TRANSFER", &pks[1], other_id, 0));
+        statechain[1].next_state = Some(sign_bound(&keys[1], "TRANSFER", &pks[2], id, 1));
+        assert!(verify_statechain(&statechain).is_err());
+
+        // position without statechain id
+        let mut sig = sign_bound(&keys[1], "TRANSFER", &pks[2], id, 1);
+        sig.statechain_id = None;
+        assert!(sig.verify(&pks[1]).is_err());
+    }
+
+    #[test]
+    fn test_verify_statechain_backup_commitments() {
+        let secp = Secp256k1::new();
+        let keys: Vec<SecretKey> = (1..4u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<String> = keys
+            .iter()
+            .map(|sk| PublicKey::from_secret_key(&secp, sk).to_string())
+            .collect();
+        let id = "001203c9-93f0-46f9-abda-0678c891b2d3";
+        let txid1 = "4f9e528d3d572146c314314c5877a6a36eceec3a9ab6c21fbfecb451910ecd61";
+        let txid2 = "c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e";
+
+        let gen = |locktime2: u32| {
+            let mut statechain = vec![
+                state(&pks[0], Some(sign_backup(&keys[0], &pks[1], id, 0, txid1, 1000))),
+                state(&pks[1], Some(sign_backup(&keys[1], &pks[2], id, 1, txid2, locktime2))),
+                state(&pks[2], None),
+            ];
+            statechain[1].backup_txid = Some(txid1.to_string());
+            statechain[1].locktime = Some(1000);
+            statechain[2].backup_txid = Some(txid2.to_string());
+            statechain[2].locktime = Some(locktime2);
+            statechain
+        };
+        assert!(verify_statechain(&gen(990)).is_ok());
+
+        // locktime not decremented
+        assert!(verify_statechain(&gen(1000)).is_err());
+
+        // state records a different backup tx than its signature
+        let mut statechain = gen(990);
+        statechain[2].backup_txid = Some(txid1.to_string());
+        assert!(verify_statechain(&statechain).is_err());
+
+        // commitment altered after signing
+        let mut statechain = gen(990);
+        statechain[1].next_state.as_mut().unwrap().locktime = Some(980);
+        statechain[2].locktime = Some(980);
+        assert!(verify_statechain(&statechain).is_err());
+    }
+
+    #[test]
+    fn test_verify_statecoin_smt_and_attestation() {
+        let statechain = gen_statechain();
+        let proof_key = statechain[1].data.clone();
+        let funding_txid =
+            String::from("c1562f7f15d6b8a51ea2e7035b9cdb8c6c0c41fecb62d459a3a6bf738ff0db0e");
+
+        let mut tree = Monotree::<MemoryDB, Blake3>::new("test");
+        let key: &Hash = funding_txid[..32].as_bytes().try_into().unwrap();
+        let entry: &Hash = proof_key[..32].as_bytes().try_into().unwrap();
+        let root = tree.insert(None, key, entry).unwrap();
+        let proof = tree.get_merkle_proof(root.as_ref(), key).unwrap();
+
+        let mut bundle = gen_bundle(statechain);
+        bundle.root = root;
+        bundle.smt_proof = proof;
+        assert!(verify_statecoin(&bundle).is_ok());
+
+        let op = [0x11; 32];
+        let attestation = AttestationProof {
+            merkle_root: attestation::hash_merkle_root(&root.unwrap(), &[op], 1),
+            commitment: root.unwrap(),
+            ops: vec![op],
+            append: vec![false],
+            position: 1,
+        };
+        bundle.attestation = Some(attestation.clone());
+        assert!(verify_statecoin(&bundle).is_ok());
+
+        // Attestation of a different root
+        let mut bad_attestation = attestation;
+        bad_attestation.commitment = [0x22; 32];
+        bundle.attestation = Some(bad_attestation);
+        assert!(verify_statecoin(&bundle).is_err());
+
+        // Proof for a different root
+        bundle.attestation = None;
+        bundle.root = Some([0x33; 32]);
+        assert!(verify_statecoin(&bundle).is_err());
+    }
+}
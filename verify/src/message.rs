@@ -0,0 +1,814 @@
+//! Message
+//!
+//! Canonical, versioned byte encoding of signed messages.
+//!
+//! Every message signed by an owner proof key is encoded as
+//!
+//!     "MERC" || version (1 byte) || tag || field || field || ...
+//!
+//! where the tag and each string or byte field are a 4 byte big-endian length followed by
+//! the bytes, u32 and u64 fields are 4 and 8 bytes big-endian, UUIDs are their 16 raw bytes,
+//! txids are their 32 raw bytes in display (RPC) byte order, lists are a
+//! 4 byte big-endian item count followed by the items and optional fields are a 1 byte flag
+//! (0 absent, 1 present) followed by the field if present. The tag names the message type so that
+//! a signature over one message type can never be valid for another. The signed digest is the
+//! sha256 hash of the encoding.
+//!
+//! Version 0 (legacy) messages were formed by concatenating string representations of the
+//! fields. They are still accepted by the verify functions so that signatures made by older
+//! wallets remain valid, but are no longer produced.
+
+use super::Result;
+
+use bitcoin::{
+    hashes::{sha256, sha256d, Hash},
+    secp256k1::Message,
+};
+use monotree::Proof;
+
+/// Message encoding prefix
+pub const MESSAGE_MAGIC: &[u8; 4] = b"MERC";
+/// Current message encoding version
+pub const MESSAGE_VERSION: u8 = 1;
+
+/// Message tag of statechain ownership transfer signatures
+pub const TAG_STATECHAIN_SIG: &str = "statechain_sig";
+/// Message tag of statechain signatures bound to a statechain and chain position
+pub const TAG_STATECHAIN_SIG_BOUND: &str = "statechain_sig_bound";
+/// Message tag of bound statechain signatures that also commit to the new owner backup tx
+pub const TAG_STATECHAIN_SIG_BACKUP: &str = "statechain_sig_backup";
+/// Message tag of swap tokens
+pub const TAG_SWAP_TOKEN: &str = "swap_token";
+/// Message tag of swap tokens with a swap fee
+pub const TAG_SWAP_TOKEN_FEE: &str = "swap_token_fee";
+/// Message tag of watchtower registrations
+pub const TAG_WATCHTOWER_REGISTER: &str = "watchtower_register";
+/// Message tag of watchtower registration revocations
+pub const TAG_WATCHTOWER_REVOKE: &str = "watchtower_revoke";
+/// Message tag of statecoin SMT leaves
+pub const TAG_SMT_LEAF: &str = "smt_leaf";
+/// Message tag of proof of reserves reports
+pub const TAG_RESERVE_REPORT: &str = "reserve_report";
+/// Message tag of statechain exports
+pub const TAG_STATECHAIN_EXPORT: &str = "statechain_export";
+/// Message tag of fee quotes
+pub const TAG_FEE_QUOTE: &str = "fee_quote";
+/// Message tag of server build manifests
+pub const TAG_BUILD_MANIFEST: &str = "build_manifest";
+
+/// Builder for canonical message encodings
+#[derive(Debug, Clone)]
+pub struct MessageBuilder {
+    buf: Vec<u8>,
+}
+
+impl MessageBuilder {
+    pub fn new(tag: &str) -> Self {
+        let mut buf = MESSAGE_MAGIC.to_vec();
+        buf.push(MESSAGE_VERSION);
+        Self { buf }.bytes(tag.as_bytes())
+    }
+
+    /// Length prefixed bytes
+    pub fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    /// Length prefixed UTF-8 string
+    pub fn string(self, s: &str) -> Self {
+        self.bytes(s.as_bytes())
+    }
+
+    pub fn u32(mut self, v: u32) -> Self {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn u64(mut self, v: u64) -> Self {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    /// UUID as 16 raw bytes
+    pub fn uuid(mut self, id: &[u8; 16]) -> Self {
+        self.buf.extend_from_slice(id);
+        self
+    }
+
+    /// Txid as 32 raw bytes in display (RPC) byte order
+    pub fn txid(mut self, txid: &[u8; 32]) -> Self {
+        self.buf.extend_from_slice(txid);
+        self
+    }
+
+    /// Item count of a following list
+    pub fn count(mut self, n: usize) -> Self {
+        self.buf.extend_from_slice(&(n as u32).to_be_bytes());
+        self
+    }
+
+    /// Single byte 0 (false) or 1 (true). Also marks whether an optional field is present.
+    pub fn flag(mut self, b: bool) -> Self {
+        self.buf.push(b as u8);
+        self
+    }
+
+    /// Optional field: flag followed by the field encoded by f if present
+    pub fn option<T>(self, v: Option<T>, f: impl FnOnce(Self, T) -> Self) -> Self {
+        match v {
+            Some(v) => f(self.flag(true), v),
+            None => self.flag(false),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Digest to be signed: sha256 of the encoding
+    pub fn to_message(&self) -> Result<Message> {
+        let hash = sha256::Hash::hash(&self.buf);
+        Ok(Message::from_slice(&hash)?)
+    }
+}
+
+/// Canonical encoding of a statechain signature message
+pub fn statechain_sig_encode(purpose: &str, data: &str) -> MessageBuilder {
+    MessageBuilder::new(TAG_STATECHAIN_SIG)
+        .string(purpose)
+        .string(data)
+}
+
+/// Canonical encoding of a statechain signature message bound to the statechain id and the
+/// position in the chain of the signing state, so that the signature cannot be replayed on
+/// another statechain or at a later position of the same statechain
+pub fn statechain_sig_bound_encode(
+    purpose: &str,
+    data: &str,
+    statechain_id: &[u8; 16],
+    position: u64,
+) -> MessageBuilder {
+    MessageBuilder::new(TAG_STATECHAIN_SIG_BOUND)
+        .string(purpose)
+        .string(data)
+        .uuid(statechain_id)
+        .u64(position)
+}
+
+/// Canonical encoding of a bound statechain signature message that also commits to the txid
+/// and locktime of the backup tx agreed with the new owner, so that the chain records the
+/// history of backup transactions
+pub fn statechain_sig_backup_encode(
+    purpose: &str,
+    data: &str,
+    statechain_id: &[u8; 16],
+    position: u64,
+    backup_txid: &[u8; 32],
+    locktime: u32,
+) -> MessageBuilder {
+    MessageBuilder::new(TAG_STATECHAIN_SIG_BACKUP)
+        .string(purpose)
+        .string(data)
+        .uuid(statechain_id)
+        .u64(position)
+        .txid(backup_txid)
+        .u32(locktime)
+}
+
+/// Legacy (version 0) statechain signature message: sha256 of purpose concatenated with data
+pub fn statechain_sig_message_legacy(purpose: &str, data: &str) -> Result<Message> {
+    let mut str = purpose.to_string();
+    str.push_str(data);
+    let hash = sha256::Hash::hash(&str.as_bytes());
+    Ok(Message::from_slice(&hash)?)
+}
+
+/// Canonical encoding of a swap token
+pub fn swap_token_encode(
+    id: &[u8; 16],
+    amount: u64,
+    time_out: u64,
+    statechain_ids: &[[u8; 16]],
+) -> MessageBuilder {
+    let mut builder = MessageBuilder::new(TAG_SWAP_TOKEN)
+        .uuid(id)
+        .u64(amount)
+        .u64(time_out)
+        .count(statechain_ids.len());
+    for statechain_id in statechain_ids {
+        builder = builder.uuid(statechain_id);
+    }
+    builder
+}
+
+/// Canonical encoding of a swap token with a swap fee, so that the signature agrees to the fee
+pub fn swap_token_fee_encode(
+    id: &[u8; 16],
+    amount: u64,
+    time_out: u64,
+    fee: u64,
+    statechain_ids: &[[u8; 16]],
+) -> MessageBuilder {
+    let mut builder = MessageBuilder::new(TAG_SWAP_TOKEN_FEE)
+        .uuid(id)
+        .u64(amount)
+        .u64(time_out)
+        .u64(fee)
+        .count(statechain_ids.len());
+    for statechain_id in statechain_ids {
+        builder = builder.uuid(statechain_id);
+    }
+    builder
+}
+
+/// Canonical encoding of a watchtower registration of an encrypted backup tx. The version is
+/// incremented by each update so that an older registration cannot be replayed.
+pub fn watchtower_register_encode(
+    id: &[u8; 16],
+    version: u64,
+    broadcast_height: u32,
+    backup_tx: &str,
+) -> MessageBuilder {
+    MessageBuilder::new(TAG_WATCHTOWER_REGISTER)
+        .uuid(id)
+        .u64(version)
+        .u32(broadcast_height)
+        .string(backup_tx)
+}
+
+/// Canonical encoding of the revocation of a watchtower registration
+pub fn watchtower_revoke_encode(id: &[u8; 16], version: u64) -> MessageBuilder {
+    MessageBuilder::new(TAG_WATCHTOWER_REVOKE).uuid(id).u64(version)
+}
+
+/// Canonical encoding of the SMT leaf of a statecoin: the funding txid the leaf is keyed by,
+/// the entry (owner proof key, or withdrawal address once withdrawn) and the statecoin amount
+pub fn smt_leaf_encode(funding_txid: &str, entry: &str, amount: u64) -> MessageBuilder {
+    MessageBuilder::new(TAG_SMT_LEAF)
+        .string(funding_txid)
+        .string(entry)
+        .u64(amount)
+}
+
+/// Canonical encoding of a proof of reserves report signed by the state entity: the verifier
+/// nonce, the SMT root hash the entries are proven against, the (statechain id, funding txid,
+/// proof key, amount) of each entry, the total and the state entity signing public key
+pub fn reserve_report_encode(
+    nonce: &str,
+    root: &[u8; 32],
+    entries: &[([u8; 16], &str, &str, u64)],
+    total: u64,
+    server_pubkey: &str,
+) -> MessageBuilder {
+    let mut builder = MessageBuilder::new(TAG_RESERVE_REPORT)
+        .string(nonce)
+        .bytes(root)
+        .count(entries.len());
+    for (statechain_id, funding_txid, proof_key, amount) in entries {
+        builder = builder
+            .uuid(statechain_id)
+            .string(funding_txid)
+            .string(proof_key)
+            .u64(*amount);
+    }
+    builder.u64(total).string(server_pubkey)
+}
+
+/// Statechain signature fields of a statechain export state
+#[derive(Debug, Clone)]
+pub struct ExportSig<'a> {
+    pub purpose: &'a str,
+    pub data: &'a str,
+    pub sig: &'a str,
+    pub statechain_id: Option<[u8; 16]>,
+    pub position: Option<u64>,
+    pub backup_txid: Option<&'a str>,
+    pub locktime: Option<u32>,
+}
+
+/// State fields of a statechain export
+#[derive(Debug, Clone)]
+pub struct ExportState<'a> {
+    pub data: &'a str,
+    pub next_state: Option<ExportSig<'a>>,
+    pub backup_txid: Option<&'a str>,
+    pub locktime: Option<u32>,
+}
+
+/// SMT root fields of a statechain export
+#[derive(Debug, Clone)]
+pub struct ExportRoot<'a> {
+    pub id: Option<i64>,
+    pub hash: [u8; 32],
+    pub proof: Option<&'a Proof>,
+}
+
+/// Fields of a statechain export signed by the state entity
+#[derive(Debug, Clone)]
+pub struct ExportFields<'a> {
+    pub version: u32,
+    pub statechain_id: [u8; 16],
+    pub amount: u64,
+    pub funding_txid: &'a str,
+    pub chain: Vec<ExportState<'a>>,
+    pub tx_backup_hex: Option<&'a str>,
+    pub backup_txs: Vec<&'a str>,
+    pub roots: Vec<ExportRoot<'a>>,
+    /// Unix time seconds and subsecond nanoseconds
+    pub exported_at: (i64, u32),
+    pub server_pubkey: &'a str,
+}
+
+/// Canonical encoding of a statechain export signed by the state entity: the export format
+/// version, statechain id, amount and funding txid, each state with its optional signature and
+/// backup tx commitment, the current backup tx, every backup tx of the statechain in chain
+/// order, the SMT roots with their id, hash and optional SMT proof (each proof step a flag
+/// followed by the sibling bytes), the export time and the state entity signing public key
+pub fn statechain_export_encode(export: &ExportFields) -> MessageBuilder {
+    let mut builder = MessageBuilder::new(TAG_STATECHAIN_EXPORT)
+        .u32(export.version)
+        .uuid(&export.statechain_id)
+        .u64(export.amount)
+        .string(export.funding_txid)
+        .count(export.chain.len());
+    for state in &export.chain {
+        builder = builder
+            .string(state.data)
+            .option(state.next_state.as_ref(), |b, sig| {
+                b.string(sig.purpose)
+                    .string(sig.data)
+                    .string(sig.sig)
+                    .option(sig.statechain_id.as_ref(), |b, id| b.uuid(id))
+                    .option(sig.position, |b, p| b.u64(p))
+                    .option(sig.backup_txid, |b, txid| b.string(txid))
+                    .option(sig.locktime, |b, l| b.u32(l))
+            })
+            .option(state.backup_txid, |b, txid| b.string(txid))
+            .option(state.locktime, |b, l| b.u32(l));
+    }
+    builder = builder
+        .option(export.tx_backup_hex, |b, tx| b.string(tx))
+        .count(export.backup_txs.len());
+    for tx in &export.backup_txs {
+        builder = builder.string(tx);
+    }
+    builder = builder.count(export.roots.len());
+    for root in &export.roots {
+        builder = builder
+            .option(root.id, |b, id| b.u64(id as u64))
+            .bytes(&root.hash)
+            .option(root.proof, |b, proof| {
+                let mut b = b.count(proof.len());
+                for (right, sibling) in proof {
+                    b = b.flag(*right).bytes(sibling);
+                }
+                b
+            });
+    }
+    builder
+        .u64(export.exported_at.0 as u64)
+        .u32(export.exported_at.1)
+        .string(export.server_pubkey)
+}
+
+/// Fields of a fee quote signed by the state entity
+#[derive(Debug, Clone)]
+pub struct FeeQuoteFields<'a> {
+    pub address: &'a str,
+    pub deposit: i64,
+    pub withdraw: u64,
+    pub withdraw_input: u64,
+    pub max_withdraw_inputs: u64,
+    pub interval: u32,
+    pub initlock: u32,
+    /// True for relative (nSequence) backup tx timelocks, false for absolute (nLockTime)
+    pub relative_timelock: bool,
+    pub min_deposit: u64,
+    pub dust_limit: u64,
+    pub wallet_version: &'a str,
+    pub wallet_message: &'a str,
+    /// Unix time seconds and subsecond nanoseconds
+    pub valid_until: Option<(i64, u32)>,
+}
+
+/// Canonical encoding of a fee quote signed by the state entity: the fee address, the fees,
+/// backup tx timelock parameters and deposit limits, the wallet version and message and the
+/// optional expiry of the quote. Deposit capacity and the deposit pause flag change between
+/// quotes and are not encoded.
+pub fn fee_quote_encode(quote: &FeeQuoteFields) -> MessageBuilder {
+    MessageBuilder::new(TAG_FEE_QUOTE)
+        .string(quote.address)
+        .u64(quote.deposit as u64)
+        .u64(quote.withdraw)
+        .u64(quote.withdraw_input)
+        .u64(quote.max_withdraw_inputs)
+        .u32(quote.interval)
+        .u32(quote.initlock)
+        .flag(quote.relative_timelock)
+        .u64(quote.min_deposit)
+        .u64(quote.dust_limit)
+        .string(quote.wallet_version)
+        .string(quote.wallet_message)
+        .option(quote.valid_until, |b, (secs, nanos)| b.u64(secs as u64).u32(nanos))
+}
+
+/// Fields of a server build manifest signed by the state entity
+#[derive(Debug, Clone)]
+pub struct BuildManifestFields<'a> {
+    pub version: &'a str,
+    pub git_commit: &'a str,
+    pub git_dirty: bool,
+    pub cargo_lock_hash: &'a str,
+    pub features: &'a [String],
+    pub rustc_version: &'a str,
+    pub profile: &'a str,
+    pub release: bool,
+}
+
+/// Canonical encoding of a server build manifest signed by the state entity: the crate version,
+/// git commit and dirty flag, Cargo.lock hash, the enabled features in manifest order, the rustc
+/// version, the cargo profile and the release build mode flag
+pub fn build_manifest_encode(manifest: &BuildManifestFields) -> MessageBuilder {
+    let mut builder = MessageBuilder::new(TAG_BUILD_MANIFEST)
+        .string(manifest.version)
+        .string(manifest.git_commit)
+        .flag(manifest.git_dirty)
+        .string(manifest.cargo_lock_hash)
+        .count(manifest.features.len());
+    for feature in manifest.features {
+        builder = builder.string(feature);
+    }
+    builder
+        .string(manifest.rustc_version)
+        .string(manifest.profile)
+        .flag(manifest.release)
+}
+
+/// Legacy (version 0) swap token message: sha256d of amount, time_out and the debug
+/// formatted list of hyphenated statechain ids with whitespace removed. Does not commit
+/// to the swap id.
+pub fn swap_token_message_legacy(
+    amount: u64,
+    time_out: u64,
+    statechain_ids: &[String],
+) -> Result<Message> {
+    let mut str = amount.to_string();
+    str.push_str(&time_out.to_string());
+    let mut ids_str = format!("{:?}", statechain_ids);
+    ids_str.retain(|c| !c.is_whitespace());
+    str.push_str(&ids_str);
+    let hash = sha256d::Hash::hash(&str.as_bytes());
+    Ok(Message::from_slice(&hash)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors. These are reproduced in doc/signed_messages.md for other implementations.
+
+    fn message(hash: &str) -> Message {
+        Message::from_slice(&hex::decode(hash).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_statechain_sig_vector() {
+        let purpose = "TRANSFER";
+        let data = "037f8d5dfb3c8f99b1641d200e808dd0b6c52f53b04e972c2e61ab901133902ebd";
+        let encoding = statechain_sig_encode(purpose, data);
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d455243010000000e7374617465636861696e5f736967000000085452414e5346455200000042\
+             303337663864356466623363386639396231363431643230306538303864643062366335326635\
+             336230346539373263326536316162393031313333393032656264"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("41e10d100d45fa1d0503ecf4999c31bc0f4bf9e89141da61c1bab11dfbc79597")
+        );
+        assert_eq!(
+            statechain_sig_message_legacy(purpose, data).unwrap(),
+            message("49e609af93214d049f7ee469ef5296ba3047e076f057759660b917f87de6ff2b")
+        );
+    }
+
+    #[test]
+    fn test_statechain_sig_bound_vector() {
+        let purpose = "TRANSFER";
+        let data = "037f8d5dfb3c8f99b1641d200e808dd0b6c52f53b04e972c2e61ab901133902ebd";
+        let statechain_id = hex::decode("001203c993f046f9abda0678c891b2d3").unwrap();
+        let mut statechain_id_bytes = [0u8; 16];
+        statechain_id_bytes.copy_from_slice(&statechain_id);
+
+        let encoding = statechain_sig_bound_encode(purpose, data, &statechain_id_bytes, 1);
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d45524301000000147374617465636861696e5f7369675f626f756e64000000085452414e5346\
+             455200000042303337663864356466623363386639396231363431643230306538303864643062\
+             366335326635336230346539373263326536316162393031313333393032656264001203c993f0\
+             46f9abda0678c891b2d30000000000000001"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("88c2c927d469f7316a0926d9893de6c82c6c9b7d726dde31a883ab4d4a9add53")
+        );
+        // Bound to a different position
+        assert_ne!(
+            statechain_sig_bound_encode(purpose, data, &statechain_id_bytes, 2).into_bytes(),
+            encoding.into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_statechain_sig_backup_vector() {
+        let purpose = "TRANSFER";
+        let data = "037f8d5dfb3c8f99b1641d200e808dd0b6c52f53b04e972c2e61ab901133902ebd";
+        let mut statechain_id = [0u8; 16];
+        statechain_id.copy_from_slice(&hex::decode("001203c993f046f9abda0678c891b2d3").unwrap());
+        let mut backup_txid = [0u8; 32];
+        backup_txid.copy_from_slice(
+            &hex::decode("4f9e528d3d572146c314314c5877a6a36eceec3a9ab6c21fbfecb451910ecd61")
+                .unwrap(),
+        );
+
+        let encoding =
+            statechain_sig_backup_encode(purpose, data, &statechain_id, 1, &backup_txid, 712903);
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d45524301000000157374617465636861696e5f7369675f6261636b7570000000085452414e53\
+             464552000000423033376638643564666233633866393962313634316432303065383038646430\
+             62366335326635336230346539373263326536316162393031313333393032656264001203c993\
+             f046f9abda0678c891b2d300000000000000014f9e528d3d572146c314314c5877a6a36eceec3a\
+             9ab6c21fbfecb451910ecd61000ae0c7"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("70943070371253326c1dc1dbeb8c7aa0cff883a58be332522c39575760543aef")
+        );
+        // Committed to a different locktime
+        assert_ne!(
+            statechain_sig_backup_encode(purpose, data, &statechain_id, 1, &backup_txid, 712904)
+                .into_bytes(),
+            encoding.into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_swap_token_vector() {
+        let id = hex::decode("637203c937ab46f9abda0678c891b2d3").unwrap();
+        let statechain_id = hex::decode("001203c993f046f9abda0678c891b2d3").unwrap();
+        let mut id_bytes = [0u8; 16];
+        id_bytes.copy_from_slice(&id);
+        let mut statechain_id_bytes = [0u8; 16];
+        statechain_id_bytes.copy_from_slice(&statechain_id);
+
+        let encoding = swap_token_encode(&id_bytes, 1, 100, &[statechain_id_bytes]);
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d455243010000000a737761705f746f6b656e637203c937ab46f9abda0678c891b2d3000000\
+             0000000001000000000000006400000001001203c993f046f9abda0678c891b2d3"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("88a0186a3edd664466fcafcbd04753af24e811d2a64f1cc7d829ff4bc830512f")
+        );
+        assert_eq!(
+            swap_token_message_legacy(
+                1,
+                100,
+                &[String::from("001203c9-93f0-46f9-abda-0678c891b2d3")]
+            )
+            .unwrap(),
+            message("6d4574375a0dea62b40f418c0974049f41487fe56f8201c1eca841b80b9e7d1e")
+        );
+
+        let encoding = swap_token_fee_encode(&id_bytes, 1, 100, 10, &[statechain_id_bytes]);
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d455243010000000e737761705f746f6b656e5f666565637203c937ab46f9abda0678c891b2d3\
+             00000000000000010000000000000064000000000000000a00000001001203c993f046f9abda06\
+             78c891b2d3"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("dbf8b2b90b375127f37171834dbc01bfe1feb12351049fa9b1ed4bfe4fc7c950")
+        );
+    }
+
+    #[test]
+    fn test_watchtower_vectors() {
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&hex::decode("001203c993f046f9abda0678c891b2d3").unwrap());
+
+        let encoding = watchtower_register_encode(&id, 1, 712903, "0badc0de");
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d45524301000000137761746368746f7765725f7265676973746572001203c993f046f9abda06\
+             78c891b2d30000000000000001000ae0c7000000083062616463306465"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("d17b3cd4b29481ef9401a1f90429b73df8a865d36b977d3668c7be73ed60a0aa")
+        );
+
+        let encoding = watchtower_revoke_encode(&id, 2);
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d45524301000000117761746368746f7765725f7265766f6b65001203c993f046f9abda0678c8\
+             91b2d30000000000000002"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("452bb6c0f38ff18f969c2b4d571081964c162d8327abfa5a961eb83eefc6165f")
+        );
+    }
+
+    #[test]
+    fn test_smt_leaf_vector() {
+        let funding_txid = "5ab8f5b2a2ad8c4c7a4ebd1cf1b1d7d3c8cbbf3fe5d6f57a4e3db2d0f1a3a9e2";
+        let proof_key = "037f8d5dfb3c8f99b1641d200e808dd0b6c52f53b04e972c2e61ab901133902ebd";
+        let encoding = smt_leaf_encode(funding_txid, proof_key, 100000);
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d4552430100000008736d745f6c65616600000040356162386635623261326164386334633761\
+             346562643163663162316437643363386362626633666535643666353761346533646232643066\
+             316133613965320000004230333766386435646662336338663939623136343164323030653830\
+             386464306236633532663533623034653937326332653631616239303131333339303265626400\
+             000000000186a0"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("d530b794b515a316df562f6b317ea52dab1164b98b99a9ccef178896ae35c262")
+        );
+    }
+
+    #[test]
+    fn test_reserve_report_vector() {
+        let mut statechain_id = [0u8; 16];
+        statechain_id.copy_from_slice(&hex::decode("001203c993f046f9abda0678c891b2d3").unwrap());
+        let funding_txid = "5ab8f5b2a2ad8c4c7a4ebd1cf1b1d7d3c8cbbf3fe5d6f57a4e3db2d0f1a3a9e2";
+        let proof_key = "037f8d5dfb3c8f99b1641d200e808dd0b6c52f53b04e972c2e61ab901133902ebd";
+        let encoding = reserve_report_encode(
+            "b0b7c2fa3d2e4fb0a1d6c3e4f5a6b7c8",
+            &[0x11; 32],
+            &[(statechain_id, funding_txid, proof_key, 100000)],
+            100000,
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        );
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d455243010000000e726573657276655f7265706f727400000020623062376332666133643265\
+             346662306131643663336534663561366237633800000020111111111111111111111111111111\
+             111111111111111111111111111111111100000001001203c993f046f9abda0678c891b2d30000\
+             004035616238663562326132616438633463376134656264316366316231643764336338636262\
+             663366653564366635376134653364623264306631613361396532000000423033376638643564\
+             666233633866393962313634316432303065383038646430623663353266353362303465393732\
+             6332653631616239303131333339303265626400000000000186a000000000000186a000000042\
+             303237396265363637656639646362626163353561303632393563653837306230373032396266\
+             636462326463653238643935396632383135623136663831373938"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("33667353f3c0540a7c4438d7527a5d5302d8e072b47609d803ae81e6831620c6")
+        );
+    }
+
+    #[test]
+    fn test_statechain_export_vector() {
+        let mut statechain_id = [0u8; 16];
+        statechain_id.copy_from_slice(&hex::decode("001203c993f046f9abda0678c891b2d3").unwrap());
+        let backup_txid = "bb".repeat(32);
+        let proof: Proof = vec![(true, vec![0x22; 32])];
+        let encoding = statechain_export_encode(&ExportFields {
+            version: 2,
+            statechain_id,
+            amount: 100000,
+            funding_txid: "5ab8f5b2a2ad8c4c7a4ebd1cf1b1d7d3c8cbbf3fe5d6f57a4e3db2d0f1a3a9e2",
+            chain: vec![
+                ExportState {
+                    data: "037f8d5dfb3c8f99b1641d200e808dd0b6c52f53b04e972c2e61ab901133902ebd",
+                    next_state: Some(ExportSig {
+                        purpose: "TRANSFER",
+                        data: "02aa",
+                        sig: "3006020101020101",
+                        statechain_id: Some(statechain_id),
+                        position: Some(0),
+                        backup_txid: Some(&backup_txid),
+                        locktime: Some(900),
+                    }),
+                    backup_txid: None,
+                    locktime: None,
+                },
+                ExportState {
+                    data: "02aa",
+                    next_state: None,
+                    backup_txid: Some(&backup_txid),
+                    locktime: Some(900),
+                },
+            ],
+            tx_backup_hex: Some("0200"),
+            backup_txs: vec!["0100", "0200"],
+            roots: vec![ExportRoot {
+                id: Some(1),
+                hash: [0x11; 32],
+                proof: Some(&proof),
+            }],
+            exported_at: (1600000000, 0),
+            server_pubkey: "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        });
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d45524301000000117374617465636861696e5f6578706f727400000002001203c993f046f9ab\
+             da0678c891b2d300000000000186a0000000403561623866356232613261643863346337613465\
+             626431636631623164376433633863626266336665356436663537613465336462326430663161\
+             336139653200000002000000423033376638643564666233633866393962313634316432303065\
+             383038646430623663353266353362303465393732633265363161623930313133333930326562\
+             6401000000085452414e5346455200000004303261610000001033303036303230313031303230\
+             31303101001203c993f046f9abda0678c891b2d301000000000000000001000000406262626262\
+             626262626262626262626262626262626262626262626262626262626262626262626262626262\
+             626262626262626262626262626262626262626201000003840000000000043032616100010000\
+             004062626262626262626262626262626262626262626262626262626262626262626262626262\
+             626262626262626262626262626262626262626262626262626262010000038401000000043032\
+             303000000002000000043031303000000004303230300000000101000000000000000100000020\
+             111111111111111111111111111111111111111111111111111111111111111101000000010100\
+             000020222222222222222222222222222222222222222222222222222222222222222200000000\
+             5f5e10000000000000000042303237396265363637656639646362626163353561303632393563\
+             653837306230373032396266636462326463653238643935396632383135623136663831373938"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("eda1c68ea5cce870350fd35c55c521504a121b7ebe40776915ccc9fc3fff65ba")
+        );
+    }
+
+    #[test]
+    fn test_fee_quote_vector() {
+        let encoding = fee_quote_encode(&FeeQuoteFields {
+            address: "bc1qzvv6yfeg0navfkrxpqc0fjdsu9ey4qgqqsarq4",
+            deposit: 0,
+            withdraw: 300,
+            withdraw_input: 0,
+            max_withdraw_inputs: 100,
+            interval: 144,
+            initlock: 14400,
+            relative_timelock: false,
+            min_deposit: 100000,
+            dust_limit: 546,
+            wallet_version: "0.4.65",
+            wallet_message: "Warning",
+            valid_until: Some((1700000000, 0)),
+        });
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d45524301000000096665655f71756f74650000002a626331717a76763679666567306e617666\
+             6b727870716330666a647375396579347167717173617271340000000000000000000000000000\
+             012c0000000000000000000000000000006400000090000038400000000000000186a000000000\
+             0000022200000006302e342e3635000000075761726e696e6701000000006553f10000000000"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("90dc34213c67fd5250f9923bb495937803545d4ae9c97fd9929d4296903a5f8b")
+        );
+    }
+
+    #[test]
+    fn test_build_manifest_vector() {
+        let features = vec![String::from("release")];
+        let encoding = build_manifest_encode(&BuildManifestFields {
+            version: "0.3.12",
+            git_commit: "4a7d449bb5e4e1d4c26c9e29f6a2a4f3b3c7a1d0",
+            git_dirty: false,
+            cargo_lock_hash: "00",
+            features: &features,
+            rustc_version: "rustc 1.50.0",
+            profile: "release",
+            release: true,
+        });
+        assert_eq!(
+            hex::encode(encoding.clone().into_bytes()),
+            "4d455243010000000e6275696c645f6d616e696665737400000006302e332e3132000000283461\
+             376434343962623565346531643463323663396532396636613261346633623363376131643000\
+             000000023030000000010000000772656c656173650000000c727573746320312e35302e300000\
+             000772656c6561736501"
+        );
+        assert_eq!(
+            encoding.to_message().unwrap(),
+            message("085d41ad46e2fef6a1b14edd01163246ccba69bcb2b16c5e1cd675e3e7f7bfca")
+        );
+    }
+
+    #[test]
+    fn test_tags_separate_message_types() {
+        // Same field bytes under different tags produce different messages
+        let a = MessageBuilder::new(TAG_STATECHAIN_SIG).string("x").into_bytes();
+        let b = MessageBuilder::new(TAG_SWAP_TOKEN).string("x").into_bytes();
+        assert_ne!(a, b);
+        // Length prefixes make field boundaries unambiguous
+        assert_ne!(
+            statechain_sig_encode("TRANSFER", "AB").into_bytes(),
+            statechain_sig_encode("TRANSFERA", "B").into_bytes()
+        );
+    }
+}
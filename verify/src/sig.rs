@@ -0,0 +1,194 @@
+//! Signatures
+//!
+//! Verification of statechain ownership transfer signatures.
+
+use super::Result;
+use crate::error::VerifyError;
+use crate::message;
+
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, Signature};
+use std::str::FromStr;
+
+/// Message signed by the owner proof key: canonical encoding of (purpose, data).
+/// See message module.
+pub fn statechain_sig_message(purpose: &str, data: &str) -> Result<Message> {
+    message::statechain_sig_encode(purpose, data).to_message()
+}
+
+/// Message signed by the owner proof key for a signature bound to a statechain and the chain
+/// position of the signing state: canonical encoding of (purpose, data, statechain_id, position).
+pub fn statechain_sig_bound_message(
+    purpose: &str,
+    data: &str,
+    statechain_id: &[u8; 16],
+    position: u64,
+) -> Result<Message> {
+    message::statechain_sig_bound_encode(purpose, data, statechain_id, position).to_message()
+}
+
+/// Message signed by the owner proof key for a bound signature that also commits to the new
+/// owner backup tx: canonical encoding of (purpose, data, statechain_id, position, backup_txid,
+/// locktime).
+pub fn statechain_sig_backup_message(
+    purpose: &str,
+    data: &str,
+    statechain_id: &[u8; 16],
+    position: u64,
+    backup_txid: &[u8; 32],
+    locktime: u32,
+) -> Result<Message> {
+    message::statechain_sig_backup_encode(
+        purpose,
+        data,
+        statechain_id,
+        position,
+        backup_txid,
+        locktime,
+    )
+    .to_message()
+}
+
+fn parse_key_and_sig(pk: &str, sig: &str) -> Result<(PublicKey, Signature)> {
+    let pk = PublicKey::from_str(pk)
+        .map_err(|e| VerifyError::FormatError(format!("invalid public key {}: {}", pk, e)))?;
+    let sig = Signature::from_str(sig)
+        .map_err(|e| VerifyError::FormatError(format!("invalid signature: {}", e)))?;
+    Ok((pk, sig))
+}
+
+/// Verify DER encoded signature over (purpose, data) by hex encoded public key. Signatures
+/// over the legacy message encoding are also accepted.
+pub fn verify_statechain_sig(pk: &str, purpose: &str, data: &str, sig: &str) -> Result<()> {
+    let (pk, sig) = parse_key_and_sig(pk, sig)?;
+    let secp = Secp256k1::verification_only();
+    let message = statechain_sig_message(purpose, data)?;
+    match secp.verify(&message, &sig, &pk) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let message = message::statechain_sig_message_legacy(purpose, data)?;
+            secp.verify(&message, &sig, &pk).map_err(|_| e.into())
+        }
+    }
+}
+
+/// Verify DER encoded signature over (purpose, data, statechain_id, position) by hex encoded
+/// public key. Bound signatures have no legacy encoding.
+pub fn verify_statechain_sig_bound(
+    pk: &str,
+    purpose: &str,
+    data: &str,
+    statechain_id: &[u8; 16],
+    position: u64,
+    sig: &str,
+) -> Result<()> {
+    let (pk, sig) = parse_key_and_sig(pk, sig)?;
+    let secp = Secp256k1::verification_only();
+    let message = statechain_sig_bound_message(purpose, data, statechain_id, position)?;
+    Ok(secp.verify(&message, &sig, &pk)?)
+}
+
+/// Verify DER encoded signature over (purpose, data, statechain_id, position, backup_txid,
+/// locktime) by hex encoded public key.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_statechain_sig_backup(
+    pk: &str,
+    purpose: &str,
+    data: &str,
+    statechain_id: &[u8; 16],
+    position: u64,
+    backup_txid: &[u8; 32],
+    locktime: u32,
+    sig: &str,
+) -> Result<()> {
+    let (pk, sig) = parse_key_and_sig(pk, sig)?;
+    let secp = Secp256k1::verification_only();
+    let message = statechain_sig_backup_message(
+        purpose,
+        data,
+        statechain_id,
+        position,
+        backup_txid,
+        locktime,
+    )?;
+    Ok(secp.verify(&message, &sig, &pk)?)
+}
+
+/// Raw bytes of a hex encoded txid, in display (RPC) byte order
+pub fn txid_bytes(txid: &str) -> Result<[u8; 32]> {
+    match hex::decode(txid) {
+        Ok(bytes) if bytes.len() == 32 => {
+            let mut result = [0u8; 32];
+            result.copy_from_slice(&bytes);
+            Ok(result)
+        }
+        _ => Err(VerifyError::FormatError(format!("invalid txid: {}", txid))),
+    }
+}
+
+/// Raw bytes of a hyphenated or simple hex encoded UUID
+pub fn uuid_bytes(id: &str) -> Result<[u8; 16]> {
+    let hex_str: String = id.chars().filter(|c| *c != '-').collect();
+    match hex::decode(&hex_str) {
+        Ok(bytes) if bytes.len() == 16 => {
+            let mut result = [0u8; 16];
+            result.copy_from_slice(&bytes);
+            Ok(result)
+        }
+        _ => Err(VerifyError::FormatError(format!("invalid statechain id: {}", id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::SecretKey;
+
+    #[test]
+    fn test_verify_statechain_sig_bound() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[0xcd; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&secp, &sk).to_string();
+        let id = uuid_bytes("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let message = statechain_sig_bound_message("TRANSFER", "data", &id, 3).unwrap();
+        let sig = secp.sign(&message, &sk).to_string();
+
+        assert!(verify_statechain_sig_bound(&pk, "TRANSFER", "data", &id, 3, &sig).is_ok());
+        // replayed at another position or on another statechain
+        assert!(verify_statechain_sig_bound(&pk, "TRANSFER", "data", &id, 4, &sig).is_err());
+        let other_id = uuid_bytes("637203c937ab46f9abda0678c891b2d3").unwrap();
+        assert!(verify_statechain_sig_bound(&pk, "TRANSFER", "data", &other_id, 3, &sig).is_err());
+        // not valid as an unbound signature
+        assert!(verify_statechain_sig(&pk, "TRANSFER", "data", &sig).is_err());
+
+        assert!(uuid_bytes("not a uuid").is_err());
+    }
+
+    #[test]
+    fn test_verify_statechain_sig_backup() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[0xcd; 32]).unwrap();
+        let pk = PublicKey::from_secret_key(&secp, &sk).to_string();
+        let id = uuid_bytes("001203c9-93f0-46f9-abda-0678c891b2d3").unwrap();
+        let txid =
+            txid_bytes("4f9e528d3d572146c314314c5877a6a36eceec3a9ab6c21fbfecb451910ecd61").unwrap();
+        let message =
+            statechain_sig_backup_message("TRANSFER", "data", &id, 3, &txid, 1000).unwrap();
+        let sig = secp.sign(&message, &sk).to_string();
+
+        assert!(
+            verify_statechain_sig_backup(&pk, "TRANSFER", "data", &id, 3, &txid, 1000, &sig).is_ok()
+        );
+        // committed to another backup tx or locktime
+        assert!(
+            verify_statechain_sig_backup(&pk, "TRANSFER", "data", &id, 3, &[0u8; 32], 1000, &sig)
+                .is_err()
+        );
+        assert!(
+            verify_statechain_sig_backup(&pk, "TRANSFER", "data", &id, 3, &txid, 999, &sig).is_err()
+        );
+        // not valid as a bound signature without the commitment
+        assert!(verify_statechain_sig_bound(&pk, "TRANSFER", "data", &id, 3, &sig).is_err());
+
+        assert!(txid_bytes("00").is_err());
+    }
+}
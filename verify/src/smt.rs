@@ -0,0 +1,57 @@
+//! SMT
+//!
+//! Verification of statecoin inclusion proofs in the State Entity sparse merkle tree.
+
+use super::message::smt_leaf_encode;
+use bitcoin::hashes::{sha256, Hash as _};
+use monotree::{
+    hasher::{Blake3, Hasher},
+    tree::verify_proof,
+    Hash, Proof,
+};
+use std::convert::TryInto;
+
+/// Verify that proof key is included in the SMT with the given root as a legacy leaf: the
+/// first 32 bytes of the proof key string, as inserted before leaves committed to the funding
+/// txid and amount
+pub fn verify_smt_proof(root: &Option<Hash>, proof_key: &str, proof: &Option<Proof>) -> bool {
+    let entry: &Hash = match proof_key.as_bytes().get(..32).map(|e| e.try_into()) {
+        Some(Ok(entry)) => entry,
+        _ => return false,
+    };
+    let hasher = Blake3::new();
+    verify_proof(&hasher, root.as_ref(), &entry, proof.as_ref())
+}
+
+/// SMT leaf of a statecoin: sha256 of the canonical encoding of the funding txid, the entry
+/// (owner proof key, or withdrawal address once withdrawn) and the statecoin amount. Inclusion
+/// proofs do not show the key they were made for, so the leaf commits to it.
+pub fn smt_leaf(funding_txid: &str, entry: &str, amount: u64) -> Hash {
+    sha256::Hash::hash(&smt_leaf_encode(funding_txid, entry, amount).into_bytes()).into_inner()
+}
+
+/// Verify that the leaf of a statecoin is included in the SMT with the given root
+pub fn verify_smt_leaf_proof(
+    root: &Option<Hash>,
+    funding_txid: &str,
+    entry: &str,
+    amount: u64,
+    proof: &Option<Proof>,
+) -> bool {
+    let hasher = Blake3::new();
+    let leaf = smt_leaf(funding_txid, entry, amount);
+    verify_proof(&hasher, root.as_ref(), &leaf, proof.as_ref())
+}
+
+/// Verify that a statecoin is included in the SMT with the given root under proof key, as a
+/// committed leaf or a legacy leaf
+pub fn verify_statecoin_smt_proof(
+    root: &Option<Hash>,
+    funding_txid: &str,
+    proof_key: &str,
+    amount: u64,
+    proof: &Option<Proof>,
+) -> bool {
+    verify_smt_leaf_proof(root, funding_txid, proof_key, amount, proof)
+        || verify_smt_proof(root, proof_key, proof)
+}